@@ -0,0 +1,83 @@
+//! Counts the heap allocations made answering one query, with owned
+//! messages and with the arena. Run with `cargo bench --bench alloc`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use mairudns::arena::{Arena, MessageRef};
+use mairudns::message::Message;
+use mairudns::rr::{RData, Record, RecordType};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ITERATIONS: usize = 100_000;
+
+const ADDR: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+fn owned(query: &[u8]) -> usize {
+    let query = Message::from_wire(query).unwrap();
+    let mut resp = query.response();
+    let name = query.question().unwrap().name.clone();
+    resp.answers.push(Record::new(name, 300, RData::A(ADDR)));
+    resp.to_wire_limited(512).unwrap().len()
+}
+
+fn arena(arena: &mut Arena, query: &[u8]) -> usize {
+    let query = MessageRef::parse(query).unwrap();
+    let name = query.question().unwrap().name;
+    let mut resp = arena.response(&query, 512);
+    resp.answer(name, RecordType::A, 300, &ADDR.octets());
+    resp.finish().len()
+}
+
+fn measure<F: FnMut() -> usize>(what: &str, mut f: F) {
+    // Warm up, so that reusable buffers have grown.
+    f();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut bytes = 0;
+    for _ in 0..ITERATIONS {
+        bytes += f();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "{:<8} {:>6.2} allocations/query {:>8.0} ns/query ({} bytes)",
+        what,
+        allocations as f64 / ITERATIONS as f64,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64,
+        bytes / ITERATIONS,
+    );
+}
+
+fn main() {
+    let query = Message::query("www.example.com".parse().unwrap(), RecordType::A)
+        .to_wire()
+        .unwrap();
+    measure("message", || owned(&query));
+    let mut buf = Arena::new();
+    measure("arena", || arena(&mut buf, &query));
+}
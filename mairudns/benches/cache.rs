@@ -0,0 +1,148 @@
+//! The forwarding cache: hits and inserts from one thread, and hits while
+//! other threads insert, each with the cache in one shard, as the single
+//! lock it used to be, and in the default number of shards. Run with
+//! `cargo bench --bench cache`.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Question};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{CachePolicy, CachedResponse, Forwarder, Request, Route, Security};
+
+/// Distinct names in the cache.
+const NAMES: usize = 10_000;
+
+/// Threads inserting while another one reads.
+const WRITERS: usize = 3;
+
+/// The single-lock layout and the default one.
+const SHARDS: [usize; 2] = [1, 16];
+
+fn name(i: usize) -> DomainName {
+    format!("host{}.example.com.", i).parse().unwrap()
+}
+
+fn entry(i: usize) -> CachedResponse {
+    let qname = name(i);
+    let mut response = Message::query(qname.clone(), RecordType::A).response();
+    response.answers.push(Record::new(
+        qname.clone(),
+        300,
+        RData::A(Ipv4Addr::from(0xc633_0000 | i as u32)),
+    ));
+    CachedResponse {
+        question: Question::new(qname, RecordType::A),
+        dnssec_ok: false,
+        checking_disabled: false,
+        response,
+        security: Security::Unchecked,
+        expires_in: Duration::from_secs(300),
+    }
+}
+
+/// A forwarder whose only route has every name cached, in `shards`
+/// shards. Its upstream is never asked.
+fn forwarder(shards: usize) -> Arc<Forwarder> {
+    let upstream = Resolver::new(ResolverConfig {
+        servers: vec!["127.0.0.1:9".parse().unwrap()],
+        ..ResolverConfig::default()
+    });
+    let policy = CachePolicy {
+        capacity: NAMES * 2,
+        shards,
+        ..CachePolicy::default()
+    };
+    let route = Route::new(DomainName::root(), upstream).cache_policy(policy);
+    for i in 0..NAMES {
+        route.restore(entry(i));
+    }
+    Arc::new(Forwarder::new().route(route))
+}
+
+fn request(i: usize) -> Request {
+    let src: SocketAddr = "192.0.2.1:5300".parse().unwrap();
+    Request {
+        message: Message::query(name(i), RecordType::A),
+        src,
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache/get");
+    for shards in SHARDS {
+        let forwarder = forwarder(shards);
+        let requests: Vec<Request> = (0..NAMES).map(request).collect();
+        let mut i = 0;
+        group.bench_function(BenchmarkId::from_parameter(shards), |b| {
+            b.iter(|| {
+                i = (i + 1) % NAMES;
+                black_box(forwarder.answer_cached(&requests[i]).unwrap())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache/insert");
+    for shards in SHARDS {
+        let forwarder = forwarder(shards);
+        let route = &forwarder.routes()[0];
+        let entries: Vec<CachedResponse> = (0..NAMES).map(entry).collect();
+        let mut i = 0;
+        group.bench_function(BenchmarkId::from_parameter(shards), |b| {
+            b.iter(|| {
+                i = (i + 1) % NAMES;
+                route.restore(black_box(entries[i].clone()))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn contended(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cache/get-contended");
+    for shards in SHARDS {
+        let forwarder = forwarder(shards);
+        let stop = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|w| {
+                let (forwarder, stop) = (forwarder.clone(), stop.clone());
+                thread::spawn(move || {
+                    let entries: Vec<CachedResponse> = (0..NAMES).map(entry).collect();
+                    let mut i = w;
+                    while !stop.load(Ordering::Relaxed) {
+                        i = (i + WRITERS) % NAMES;
+                        forwarder.routes()[0].restore(entries[i].clone());
+                    }
+                })
+            })
+            .collect();
+        let requests: Vec<Request> = (0..NAMES).map(request).collect();
+        let mut i = 0;
+        group.bench_function(BenchmarkId::from_parameter(shards), |b| {
+            b.iter(|| {
+                i = (i + 1) % NAMES;
+                black_box(forwarder.answer_cached(&requests[i]).unwrap())
+            })
+        });
+        stop.store(true, Ordering::Relaxed);
+        for w in writers {
+            w.join().unwrap();
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, get, insert, contended);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+//! Name encoding and decoding with compression, and IPv6 address
+//! formatting. Run with `cargo bench --bench codec`; `cargo test --benches`
+//! runs each once, checking that it does what it says.
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mairudns::addr;
+use mairudns::name::DomainName;
+use mairudns::rr::RData;
+use mairudns::wire::{Decoder, Encoder};
+
+/// Names sharing suffixes, as the owners and targets of a typical answer.
+const NAMES: &[&str] = &[
+    "www.example.com.",
+    "example.com.",
+    "ns1.example.com.",
+    "ns2.example.com.",
+    "mail.example.com.",
+    "cdn.www.example.com.",
+    "example.net.",
+    "www.example.net.",
+];
+
+fn names() -> Vec<DomainName> {
+    NAMES.iter().map(|n| n.parse().unwrap()).collect()
+}
+
+fn encode(names: &[DomainName], compress: bool) -> Vec<u8> {
+    let mut enc = if compress {
+        Encoder::new()
+    } else {
+        Encoder::uncompressed()
+    };
+    for name in names {
+        enc.name(name, compress);
+    }
+    enc.into_bytes()
+}
+
+fn names_encode(c: &mut Criterion) {
+    let names = names();
+    // Compression must have something to do.
+    assert!(encode(&names, true).len() < encode(&names, false).len() / 2);
+    let mut group = c.benchmark_group("name/encode");
+    group.bench_function("compressed", |b| b.iter(|| encode(black_box(&names), true)));
+    group.bench_function("uncompressed", |b| {
+        b.iter(|| encode(black_box(&names), false))
+    });
+    group.finish();
+}
+
+fn names_decode(c: &mut Criterion) {
+    let names = names();
+    let mut group = c.benchmark_group("name/decode");
+    for &(label, compress) in &[("compressed", true), ("uncompressed", false)] {
+        let wire = encode(&names, compress);
+        let mut dec = Decoder::new(&wire);
+        for name in &names {
+            assert_eq!(&dec.name().unwrap(), name);
+        }
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                let mut dec = Decoder::new(black_box(&wire));
+                for _ in 0..names.len() {
+                    black_box(dec.name().unwrap());
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn names_parse(c: &mut Criterion) {
+    c.bench_function("name/parse", |b| {
+        b.iter(|| black_box(NAMES[5]).parse::<DomainName>().unwrap())
+    });
+}
+
+fn ipv6_format(c: &mut Criterion) {
+    let addrs: Vec<Ipv6Addr> = [
+        "2001:db8::1",
+        "2001:db8:0:1:1:1:1:1",
+        "fe80::1ff:fe23:4567:890a",
+    ]
+    .iter()
+    .map(|a| a.parse().unwrap())
+    .collect();
+    // Zeros are compressed, and a lone one is not.
+    assert_eq!(RData::Aaaa(addrs[0]).to_string(), "2001:db8::1");
+    assert_eq!(RData::Aaaa(addrs[1]).to_string(), "2001:db8:0:1:1:1:1:1");
+    assert_eq!(
+        addr::reverse_name(IpAddr::V6(addrs[0])).to_string(),
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+    );
+    let mut group = c.benchmark_group("ipv6");
+    group.bench_function("aaaa", |b| {
+        b.iter(|| {
+            for &addr in black_box(&addrs) {
+                black_box(RData::Aaaa(addr).to_string());
+            }
+        })
+    });
+    group.bench_function("reverse-name", |b| {
+        b.iter(|| {
+            for &addr in black_box(&addrs) {
+                black_box(addr::reverse_name(IpAddr::V6(addr)));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    names_encode,
+    names_decode,
+    names_parse,
+    ipv6_format
+);
+criterion_main!(benches);
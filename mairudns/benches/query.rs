@@ -0,0 +1,80 @@
+//! A query's round trip through a server answering from a zone, over
+//! loopback UDP and over a TCP connection kept open. Run with
+//! `cargo bench --bench query`; `cargo test --benches` runs each once,
+//! checking the answer.
+
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use mairudns::client::{read_framed, write_framed, Protocol};
+use mairudns::message::{Message, Rcode};
+use mairudns::rr::{RData, RecordType};
+use mairudns::server::{Authority, Server};
+use mairudns::zone::Zone;
+
+const ZONE: &str = "$TTL 3600\n\
+    @ IN SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+    @ IN NS ns1\n\
+    ns1 IN A 192.0.2.53\n\
+    www IN A 192.0.2.80\n\
+    www IN AAAA 2001:db8::80\n";
+
+/// Checks that `resp` answers the query with the zone's address.
+fn answered(resp: Message) -> Message {
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.header.aa);
+    assert_eq!(resp.answers[0].rdata, RData::A([192, 0, 2, 80].into()));
+    resp
+}
+
+fn round_trip(c: &mut Criterion) {
+    let authority = Authority::new();
+    authority.insert(Zone::from_master("example.com.".parse().unwrap(), ZONE).unwrap());
+    let mut server = Server::new(authority);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = |protocol| -> SocketAddr {
+        server
+            .local_addrs()
+            .into_iter()
+            .find(|&(p, _)| p == protocol)
+            .unwrap()
+            .1
+    };
+    let (udp_addr, tcp_addr) = (addr(Protocol::Udp), addr(Protocol::Tcp));
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+
+    let query = Message::query("www.example.com.".parse().unwrap(), RecordType::A)
+        .to_wire()
+        .unwrap();
+    let mut group = c.benchmark_group("query");
+
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.connect(udp_addr).unwrap();
+    let mut buf = [0u8; 512];
+    group.bench_function("udp", |b| {
+        b.iter(|| {
+            udp.send(&query).unwrap();
+            let n = udp.recv(&mut buf).unwrap();
+            answered(Message::from_wire(&buf[..n]).unwrap())
+        })
+    });
+
+    let mut tcp = TcpStream::connect(tcp_addr).unwrap();
+    tcp.set_nodelay(true).unwrap();
+    group.bench_function("tcp", |b| {
+        b.iter(|| {
+            write_framed(&mut tcp, &query).unwrap();
+            answered(Message::from_wire(&read_framed(&mut tcp).unwrap()).unwrap())
+        })
+    });
+    group.finish();
+
+    drop(tcp);
+    control.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+criterion_group!(benches, round_trip);
+criterion_main!(benches);
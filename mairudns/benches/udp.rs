@@ -0,0 +1,82 @@
+//! Queries per second answered over loopback UDP by each backend: threads
+//! blocking in `recvmmsg()`, and io_uring. Run with
+//! `cargo bench --bench udp --features uring`; without the feature only
+//! the threads are measured.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::message::Message;
+use mairudns::rr::RecordType;
+use mairudns::server::{Request, Server, UdpBackend};
+
+const CLIENTS: usize = 4;
+
+/// Queries each client keeps outstanding.
+const WINDOW: usize = 16;
+
+const DURATION: Duration = Duration::from_secs(3);
+
+fn client(server: SocketAddr, stop: &AtomicBool, answered: &AtomicUsize) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(server).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let query = Message::query("www.example.com".parse().unwrap(), RecordType::A)
+        .to_wire()
+        .unwrap();
+    let mut buf = [0u8; 512];
+    for _ in 0..WINDOW {
+        socket.send(&query).unwrap();
+    }
+    while !stop.load(Ordering::Relaxed) {
+        // A lost query is replaced, so that the window stays full.
+        if socket.recv(&mut buf).is_ok() {
+            answered.fetch_add(1, Ordering::Relaxed);
+        }
+        let _ = socket.send(&query);
+    }
+}
+
+fn measure(backend: UdpBackend) {
+    let mut server = Server::new(|r: &Request| Some(r.message.response()));
+    server.set_udp_backend(backend);
+    server.set_udp_threads(1);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server.local_addrs()[0].1;
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let answered = Arc::new(AtomicUsize::new(0));
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let (stop, answered) = (stop.clone(), answered.clone());
+            thread::spawn(move || client(addr, &stop, &answered))
+        })
+        .collect();
+    let start = Instant::now();
+    thread::sleep(DURATION);
+    stop.store(true, Ordering::Relaxed);
+    let elapsed = start.elapsed();
+    for c in clients {
+        c.join().unwrap();
+    }
+    control.shutdown();
+    running.join().unwrap().unwrap();
+    let qps = answered.load(Ordering::Relaxed) as f64 / elapsed.as_secs_f64();
+    println!("{:>8?}: {:>9.0} queries/s", backend, qps);
+}
+
+fn main() {
+    measure(UdpBackend::Threads);
+    if UdpBackend::IoUring.is_available() {
+        measure(UdpBackend::IoUring);
+    } else {
+        println!("IoUring: not available");
+    }
+}
@@ -0,0 +1,65 @@
+//! Zone lookups: an existing name, a name a wildcard answers for, and a
+//! name that does not exist, in a zone of a few thousand names. Run with
+//! `cargo bench --bench zone`; `cargo test --benches` runs each once,
+//! checking that it finds what it says.
+
+use std::fmt::Write;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mairudns::message::Rcode;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, RecordType};
+use mairudns::zone::Zone;
+
+const HOSTS: usize = 5000;
+
+fn zone() -> Zone {
+    let mut text = String::from(
+        "$TTL 3600\n\
+         @ IN SOA ns1 hostmaster 1 7200 900 1209600 300\n\
+         @ IN NS ns1\n\
+         ns1 IN A 192.0.2.53\n\
+         *.wild IN A 192.0.2.80\n",
+    );
+    for i in 0..HOSTS {
+        writeln!(text, "host{} IN A 198.51.{}.{}", i, i / 256 % 256, i % 256).unwrap();
+    }
+    Zone::from_master("example.com.".parse().unwrap(), &text).unwrap()
+}
+
+fn lookup(c: &mut Criterion) {
+    let zone = zone();
+    let cases: [(&str, DomainName, Rcode, Option<RData>); 3] = [
+        (
+            "exact",
+            "host2500.example.com.".parse().unwrap(),
+            Rcode::NOERROR,
+            Some(RData::A([198, 51, 9, 196].into())),
+        ),
+        (
+            "wildcard",
+            "a.b.wild.example.com.".parse().unwrap(),
+            Rcode::NOERROR,
+            Some(RData::A([192, 0, 2, 80].into())),
+        ),
+        (
+            "nxdomain",
+            "nowhere.example.com.".parse().unwrap(),
+            Rcode::NXDOMAIN,
+            None,
+        ),
+    ];
+    let mut group = c.benchmark_group("zone/lookup");
+    for (label, qname, rcode, rdata) in &cases {
+        let answer = zone.lookup(qname, RecordType::A);
+        assert_eq!((answer.rcode, answer.authoritative), (*rcode, true));
+        assert_eq!(answer.answers.first().map(|rr| &rr.rdata), rdata.as_ref());
+        group.bench_function(*label, |b| {
+            b.iter(|| zone.lookup(black_box(qname), RecordType::A))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup);
+criterion_main!(benches);
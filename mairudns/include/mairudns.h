@@ -0,0 +1,108 @@
+/*
+ * mairudns.h: the C ABI of the mairudns crate, built with the ffi feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Functions that can fail return MAIRU_OK or one of the negative
+ * MAIRU_ERR_* codes, which mairu_strerror() describes. Strings are
+ * NUL-terminated UTF-8. Text is written into caller-supplied buffers; when
+ * one is too small the call returns MAIRU_ERR_BUFFER and stores the size
+ * needed, terminator included, in *needed if that is not NULL.
+ */
+
+#ifndef MAIRUDNS_H
+#define MAIRUDNS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MAIRU_OK 0
+#define MAIRU_ERR_ARGUMENT (-1)
+#define MAIRU_ERR_PARSE (-2)
+#define MAIRU_ERR_BUFFER (-3)
+#define MAIRU_ERR_TIMEOUT (-4)
+#define MAIRU_ERR_RESOLVE (-5)
+
+/* A static description of a status code. */
+const char *mairu_strerror(int code);
+
+/* Domain names. */
+int mairu_name_format(const char *text, char *out, size_t out_len, size_t *needed);
+int mairu_name_to_wire(const char *text, uint8_t *out, size_t out_len, size_t *written);
+int mairu_name_from_wire(const uint8_t *wire, size_t len, char *out, size_t out_len,
+                         size_t *needed);
+
+/* IP addresses: family is 4 or 6; IPv4 takes the first four bytes. */
+typedef struct mairu_addr {
+    uint8_t family;
+    uint8_t bytes[16];
+} mairu_addr;
+
+int mairu_addr_parse(const char *text, mairu_addr *out);
+int mairu_addr_format(const mairu_addr *addr, char *out, size_t out_len, size_t *needed);
+int mairu_addr_reverse_name(const mairu_addr *addr, char *out, size_t out_len,
+                            size_t *needed);
+
+/* Decoded messages. Everything a message points to lives until it is
+ * freed with mairu_message_free(). */
+typedef struct mairu_question {
+    const char *name;
+    uint16_t qtype;
+    uint16_t qclass;
+} mairu_question;
+
+typedef struct mairu_record {
+    const char *name;
+    uint16_t rtype;
+    uint16_t rclass;
+    uint32_t ttl;
+    const uint8_t *rdata; /* uncompressed wire form */
+    size_t rdata_len;
+    const char *rdata_text; /* presentation form */
+} mairu_record;
+
+typedef struct mairu_message {
+    uint16_t id;
+    uint16_t flags; /* as on the wire, opcode and RCODE bits included */
+    uint8_t opcode;
+    uint16_t rcode; /* extended bits included */
+    uint8_t has_edns;
+    uint8_t edns_version;
+    uint8_t dnssec_ok;
+    uint16_t udp_size;
+    const mairu_question *questions;
+    size_t question_count;
+    const mairu_record *answers;
+    size_t answer_count;
+    const mairu_record *authority;
+    size_t authority_count;
+    const mairu_record *additional;
+    size_t additional_count;
+} mairu_message;
+
+int mairu_message_decode(const uint8_t *wire, size_t len, mairu_message **out);
+void mairu_message_free(mairu_message *message);
+
+/* Resolvers, which may be shared between threads. servers holds count
+ * addresses such as "192.0.2.1" or "[2001:db8::1]:5353"; with count 0 the
+ * system's servers are used. A timeout_ms of 0 keeps the default. */
+typedef struct mairu_resolver mairu_resolver;
+
+mairu_resolver *mairu_resolver_new(const char *const *servers, size_t count,
+                                   uint32_t timeout_ms);
+void mairu_resolver_free(mairu_resolver *resolver);
+
+/* Blocks until a server answers; a response with an error RCODE such as
+ * NXDOMAIN is a success. */
+int mairu_resolve(const mairu_resolver *resolver, const char *name, uint16_t qtype,
+                  mairu_message **out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,479 @@
+//! IP address helpers: prefixes, address match lists, prefix maps,
+//! IPv4-embedded IPv6 addresses (RFC 6052) and reverse-mapping names.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::iter::FromIterator;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::name::DomainName;
+
+/// Errors produced when building or parsing prefixes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The address part is not a valid IP address.
+    BadAddress,
+    /// The prefix length is missing, malformed or too long for the family.
+    BadPrefixLen,
+    /// A NAT64 prefix length other than 32, 40, 48, 56, 64 or 96.
+    BadNat64Len,
+    /// A character that has no place in an address or prefix, at byte
+    /// `index` of the text parsed.
+    IllegalChar { index: usize, ch: char },
+}
+
+impl Error {
+    /// The error with its index moved `by` bytes on, for text parsed as
+    /// part of a longer string.
+    fn offset(self, by: usize) -> Error {
+        match self {
+            Error::IllegalChar { index, ch } => Error::IllegalChar {
+                index: index + by,
+                ch,
+            },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::BadAddress => "invalid IP address",
+            Error::BadPrefixLen => "invalid prefix length",
+            Error::BadNat64Len => "NAT64 prefix length must be 32, 40, 48, 56, 64 or 96",
+            Error::IllegalChar { index, ch } => {
+                return write!(f, "illegal character {:?} in address at byte {}", ch, index)
+            }
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// An address prefix such as `192.0.2.0/24` or `2001:db8::/32`. Host bits
+/// are always zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Prefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+fn mask_v4(a: Ipv4Addr, len: u8) -> Ipv4Addr {
+    let bits = u32::from(a);
+    Ipv4Addr::from(if len == 0 {
+        0
+    } else {
+        bits & (!0u32 << (32 - len))
+    })
+}
+
+fn mask_v6(a: Ipv6Addr, len: u8) -> Ipv6Addr {
+    let bits = u128::from(a);
+    Ipv6Addr::from(if len == 0 {
+        0
+    } else {
+        bits & (!0u128 << (128 - len))
+    })
+}
+
+impl Prefix {
+    /// A prefix of `len` bits of `addr`; host bits are cleared.
+    pub fn new(addr: IpAddr, len: u8) -> Result<Prefix, Error> {
+        let addr = match addr {
+            IpAddr::V4(a) if len <= 32 => IpAddr::V4(mask_v4(a, len)),
+            IpAddr::V6(a) if len <= 128 => IpAddr::V6(mask_v6(a, len)),
+            _ => return Err(Error::BadPrefixLen),
+        };
+        Ok(Prefix { addr, len })
+    }
+
+    /// A prefix covering exactly one address.
+    pub fn host(addr: IpAddr) -> Prefix {
+        let len = if addr.is_ipv4() { 32 } else { 128 };
+        Prefix { addr, len }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    /// Whether the prefix has length zero, i.e. covers a whole family.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether `addr` lies within this prefix. IPv4 addresses never match
+    /// IPv6 prefixes and vice versa.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(p), IpAddr::V4(a)) => mask_v4(*a, self.len) == p,
+            (IpAddr::V6(p), IpAddr::V6(a)) => mask_v6(*a, self.len) == p,
+            _ => false,
+        }
+    }
+
+    /// Whether `other` is equal to or more specific than this prefix.
+    pub fn covers(&self, other: &Prefix) -> bool {
+        other.len >= self.len && self.contains(&other.addr)
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = Error;
+
+    /// Parses `addr/len`; a bare address is a host prefix.
+    fn from_str(s: &str) -> Result<Prefix, Error> {
+        if let Some((index, ch)) = s
+            .char_indices()
+            .find(|&(_, c)| !(c.is_ascii_hexdigit() || matches!(c, ':' | '.' | '/')))
+        {
+            return Err(Error::IllegalChar { index, ch });
+        }
+        match s.split_once('/') {
+            Some((a, l)) => {
+                let addr = a.parse().map_err(|_| Error::BadAddress)?;
+                let len = l.parse().map_err(|_| Error::BadPrefixLen)?;
+                Prefix::new(addr, len)
+            }
+            None => Ok(Prefix::host(s.parse().map_err(|_| Error::BadAddress)?)),
+        }
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// An address match list: prefixes, each possibly negated, checked in
+/// order with the first match deciding, as in BIND ACLs. IPv4-mapped IPv6
+/// addresses, as seen on dual-stack sockets, match as IPv4.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct IpSet {
+    entries: Vec<(Prefix, bool)>,
+}
+
+impl IpSet {
+    /// The set matching nothing.
+    pub fn new() -> IpSet {
+        IpSet::default()
+    }
+
+    /// The set matching every address.
+    pub fn any() -> IpSet {
+        let mut set = IpSet::new();
+        set.insert(Prefix::new(Ipv4Addr::UNSPECIFIED.into(), 0).unwrap());
+        set.insert(Prefix::new(Ipv6Addr::UNSPECIFIED.into(), 0).unwrap());
+        set
+    }
+
+    /// Appends a prefix whose addresses are in the set.
+    pub fn insert(&mut self, prefix: Prefix) {
+        self.entries.push((prefix, true));
+    }
+
+    /// Appends a prefix whose addresses are not in the set, unless an
+    /// earlier entry already matched them.
+    pub fn exclude(&mut self, prefix: Prefix) {
+        self.entries.push((prefix, false));
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(a) => a.to_ipv4_mapped().map_or(*addr, IpAddr::V4),
+            IpAddr::V4(_) => *addr,
+        };
+        self.entries
+            .iter()
+            .find(|(prefix, _)| prefix.contains(&addr))
+            .is_some_and(|&(_, included)| included)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries in order, with `false` marking negated ones.
+    pub fn entries(&self) -> &[(Prefix, bool)] {
+        &self.entries
+    }
+}
+
+impl FromIterator<Prefix> for IpSet {
+    fn from_iter<I: IntoIterator<Item = Prefix>>(iter: I) -> IpSet {
+        IpSet {
+            entries: iter.into_iter().map(|p| (p, true)).collect(),
+        }
+    }
+}
+
+impl FromStr for IpSet {
+    type Err = Error;
+
+    /// Parses prefixes separated by commas or whitespace, each optionally
+    /// preceded by `!`, e.g. `!192.0.2.1, 192.0.2.0/24`.
+    fn from_str(s: &str) -> Result<IpSet, Error> {
+        let mut set = IpSet::new();
+        for item in s.split([',', ' ', '\t', '\n']).filter(|i| !i.is_empty()) {
+            let at = item.as_ptr() as usize - s.as_ptr() as usize;
+            match item.strip_prefix('!') {
+                Some(p) => set.exclude(p.parse().map_err(|e: Error| e.offset(at + 1))?),
+                None => set.insert(item.parse().map_err(|e: Error| e.offset(at))?),
+            }
+        }
+        Ok(set)
+    }
+}
+
+impl fmt::Display for IpSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (prefix, included)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            if !included {
+                f.write_str("!")?;
+            }
+            write!(f, "{}", prefix)?;
+        }
+        Ok(())
+    }
+}
+
+/// Values keyed by prefix, looked up by the longest prefix containing an
+/// address. IPv4-mapped IPv6 addresses are looked up as IPv4.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrefixMap<T> {
+    entries: HashMap<Prefix, T>,
+    /// Prefix lengths in use, per family, so lookups probe only those.
+    lengths_v4: BTreeSet<u8>,
+    lengths_v6: BTreeSet<u8>,
+}
+
+impl<T> Default for PrefixMap<T> {
+    fn default() -> PrefixMap<T> {
+        PrefixMap {
+            entries: HashMap::new(),
+            lengths_v4: BTreeSet::new(),
+            lengths_v6: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T> PrefixMap<T> {
+    pub fn new() -> PrefixMap<T> {
+        PrefixMap::default()
+    }
+
+    /// Sets the value for `prefix`, returning the one it replaces.
+    pub fn insert(&mut self, prefix: Prefix, value: T) -> Option<T> {
+        match prefix.addr {
+            IpAddr::V4(_) => self.lengths_v4.insert(prefix.len),
+            IpAddr::V6(_) => self.lengths_v6.insert(prefix.len),
+        };
+        self.entries.insert(prefix, value)
+    }
+
+    pub fn remove(&mut self, prefix: &Prefix) -> Option<T> {
+        let value = self.entries.remove(prefix)?;
+        let in_use = self
+            .entries
+            .keys()
+            .any(|p| p.len == prefix.len && p.addr.is_ipv4() == prefix.addr.is_ipv4());
+        if !in_use {
+            match prefix.addr {
+                IpAddr::V4(_) => self.lengths_v4.remove(&prefix.len),
+                IpAddr::V6(_) => self.lengths_v6.remove(&prefix.len),
+            };
+        }
+        Some(value)
+    }
+
+    /// The value for exactly `prefix`.
+    pub fn get(&self, prefix: &Prefix) -> Option<&T> {
+        self.entries.get(prefix)
+    }
+
+    /// The longest prefix containing `addr`, with its value.
+    pub fn lookup_prefix(&self, addr: &IpAddr) -> Option<(Prefix, &T)> {
+        let addr = match addr {
+            IpAddr::V6(a) => a.to_ipv4_mapped().map_or(*addr, IpAddr::V4),
+            IpAddr::V4(_) => *addr,
+        };
+        let lengths = match addr {
+            IpAddr::V4(_) => &self.lengths_v4,
+            IpAddr::V6(_) => &self.lengths_v6,
+        };
+        lengths.iter().rev().find_map(|&len| {
+            let prefix = Prefix::new(addr, len).ok()?;
+            self.entries.get(&prefix).map(|value| (prefix, value))
+        })
+    }
+
+    /// The value of the longest prefix containing `addr`.
+    pub fn lookup(&self, addr: &IpAddr) -> Option<&T> {
+        self.lookup_prefix(addr).map(|(_, value)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Prefix, &T)> {
+        self.entries.iter()
+    }
+}
+
+impl<T> FromIterator<(Prefix, T)> for PrefixMap<T> {
+    fn from_iter<I: IntoIterator<Item = (Prefix, T)>>(iter: I) -> PrefixMap<T> {
+        let mut map = PrefixMap::new();
+        for (prefix, value) in iter {
+            map.insert(prefix, value);
+        }
+        map
+    }
+}
+
+/// An IPv6 prefix used to embed IPv4 addresses (RFC 6052 §2.2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Nat64Prefix {
+    prefix: Ipv6Addr,
+    len: u8,
+}
+
+impl Nat64Prefix {
+    /// The well-known prefix `64:ff9b::/96`.
+    pub const WELL_KNOWN: Nat64Prefix = Nat64Prefix {
+        prefix: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+        len: 96,
+    };
+
+    pub fn new(prefix: Ipv6Addr, len: u8) -> Result<Nat64Prefix, Error> {
+        match len {
+            32 | 40 | 48 | 56 | 64 | 96 => Ok(Nat64Prefix {
+                prefix: mask_v6(prefix, len),
+                len,
+            }),
+            _ => Err(Error::BadNat64Len),
+        }
+    }
+
+    pub fn prefix(&self) -> Prefix {
+        Prefix {
+            addr: IpAddr::V6(self.prefix),
+            len: self.len,
+        }
+    }
+
+    /// Byte positions that carry the IPv4 address, skipping the reserved
+    /// "u" octet (bits 64..71).
+    fn positions(&self) -> impl Iterator<Item = usize> {
+        (usize::from(self.len / 8)..16).filter(|&i| i != 8).take(4)
+    }
+
+    /// The prefixes `v6` could be `v4` embedded in, shortest first: those
+    /// that [`embed`](Nat64Prefix::embed) `v4` as exactly `v6`.
+    pub fn embedding(v6: Ipv6Addr, v4: Ipv4Addr) -> Vec<Nat64Prefix> {
+        [32, 40, 48, 56, 64, 96]
+            .iter()
+            .filter_map(|&len| {
+                let prefix = Nat64Prefix::new(v6, len).ok()?;
+                (prefix.embed(v4) == v6).then_some(prefix)
+            })
+            .collect()
+    }
+
+    /// Embeds `v4` into this prefix.
+    pub fn embed(&self, v4: Ipv4Addr) -> Ipv6Addr {
+        let mut out = self.prefix.octets();
+        for (i, b) in self.positions().zip(v4.octets().iter()) {
+            out[i] = *b;
+        }
+        Ipv6Addr::from(out)
+    }
+
+    /// Extracts the embedded IPv4 address if `v6` lies within the prefix.
+    pub fn extract(&self, v6: Ipv6Addr) -> Option<Ipv4Addr> {
+        if mask_v6(v6, self.len) != self.prefix {
+            return None;
+        }
+        let o = v6.octets();
+        let mut v4 = [0u8; 4];
+        for (b, i) in v4.iter_mut().zip(self.positions()) {
+            *b = o[i];
+        }
+        Some(Ipv4Addr::from(v4))
+    }
+}
+
+impl fmt::Display for Nat64Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.prefix, self.len)
+    }
+}
+
+/// The `in-addr.arpa.` or `ip6.arpa.` name for `addr`.
+pub fn reverse_name(addr: IpAddr) -> DomainName {
+    let labels: Vec<String> = match addr {
+        IpAddr::V4(a) => {
+            let mut l: Vec<String> = a.octets().iter().rev().map(|o| o.to_string()).collect();
+            l.extend(["in-addr", "arpa"].iter().map(|s| s.to_string()));
+            l
+        }
+        IpAddr::V6(a) => {
+            let mut l: Vec<String> = a
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|o| vec![format!("{:x}", o & 0xf), format!("{:x}", o >> 4)])
+                .collect();
+            l.extend(["ip6", "arpa"].iter().map(|s| s.to_string()));
+            l
+        }
+    };
+    DomainName::from_labels(labels).expect("reverse names are always valid")
+}
+
+/// The address a complete reverse-mapping name refers to.
+pub fn parse_reverse_name(name: &DomainName) -> Option<IpAddr> {
+    let labels = name.labels();
+    let text: Vec<&str> = labels
+        .iter()
+        .map(|l| std::str::from_utf8(l).ok())
+        .collect::<Option<_>>()?;
+    let n = text.len();
+    if n == 6 && text[4].eq_ignore_ascii_case("in-addr") && text[5].eq_ignore_ascii_case("arpa") {
+        let mut o = [0u8; 4];
+        for (i, t) in text[..4].iter().rev().enumerate() {
+            if t.len() > 1 && t.starts_with('0') {
+                return None;
+            }
+            o[i] = t.parse().ok()?;
+        }
+        return Some(IpAddr::V4(Ipv4Addr::from(o)));
+    }
+    if n == 34 && text[32].eq_ignore_ascii_case("ip6") && text[33].eq_ignore_ascii_case("arpa") {
+        let mut o = [0u8; 16];
+        for (i, t) in text[..32].iter().rev().enumerate() {
+            if t.len() != 1 {
+                return None;
+            }
+            let nibble = u8::from_str_radix(t, 16).ok()?;
+            o[i / 2] |= if i % 2 == 0 { nibble << 4 } else { nibble };
+        }
+        return Some(IpAddr::V6(Ipv6Addr::from(o)));
+    }
+    None
+}
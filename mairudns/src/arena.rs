@@ -0,0 +1,769 @@
+//! Allocation-free message handling for hot paths.
+//!
+//! A [`Message`] owns its names and records, so decoding a query and
+//! encoding its response allocate a dozen times over. [`MessageRef`]
+//! instead reads a message in place, borrowing names and record data from
+//! the buffer it arrived in, and [`ResponseBuilder`] writes a response
+//! straight into the buffer of an [`Arena`]. Handlers take and return
+//! owned messages, so the server's UDP and TCP workers do not use these;
+//! they serve paths that answer without a handler, such as the XDP
+//! listener. A caller that keeps one arena for every response it builds
+//! allocates nothing at all once the buffer has grown to the largest
+//! response:
+//!
+//! ```
+//! use mairudns::arena::{Arena, MessageRef};
+//! use mairudns::message::Message;
+//! use mairudns::rr::RecordType;
+//!
+//! let query = Message::query("www.example.com".parse().unwrap(), RecordType::A);
+//! let wire = query.to_wire().unwrap();
+//!
+//! let mut arena = Arena::new();
+//! let query = MessageRef::parse(&wire).unwrap();
+//! let q = query.question().unwrap();
+//! let mut resp = arena.response(&query, 512);
+//! resp.answer(&q.name, RecordType::A, 300, &[192, 0, 2, 1]);
+//! let resp = Message::from_wire(resp.finish()).unwrap();
+//! assert_eq!(resp.answers.len(), 1);
+//! ```
+//!
+//! The builder compresses names against everything written before them,
+//! and leaves out what does not fit in its size limit as
+//! [`Message::to_wire_limited`] does.
+//!
+//! [`MessageRef::parse_with`] reads a message under a [`Compliance`]
+//! profile, as [`Message::from_wire_with`] decodes one.
+
+use std::fmt;
+
+use crate::compliance::Compliance;
+use crate::message::{Edns, Header, Message, OptionCode, Question, Rcode};
+use crate::name::{self, DomainName};
+use crate::rr::{Record, RecordClass, RecordType};
+use crate::wire::{Decoder, Error};
+
+/// Names a response remembers the position of for compression.
+const MAX_COMPRESSION_TARGETS: usize = 64;
+
+/// Offsets beyond this cannot be the target of a compression pointer.
+const MAX_POINTER: usize = 0x3fff;
+
+/// Size of the OPT record a response without options ends with.
+const OPT_LEN: usize = 11;
+
+/// Reusable memory for building responses.
+#[derive(Debug, Default)]
+pub struct Arena {
+    buf: Vec<u8>,
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    /// An arena that can build responses of `capacity` bytes before it
+    /// first has to grow.
+    pub fn with_capacity(capacity: usize) -> Arena {
+        Arena {
+            buf: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Starts a response to `query` of at most `max` bytes, overwriting
+    /// the previous one. The response echoes the query's ID, opcode, RD
+    /// and CD bits and first question, and has EDNS if the query did.
+    pub fn response<'a>(&'a mut self, query: &MessageRef<'_>, max: usize) -> ResponseBuilder<'a> {
+        ResponseBuilder::new(&mut self.buf, query, max)
+    }
+}
+
+/// Reads the name at `pos` of `buf`, checking its labels, pointers and
+/// length. Returns the name and the position after it.
+fn read_name(buf: &[u8], pos: usize) -> Result<(NameRef<'_>, usize), Error> {
+    let start = pos;
+    let mut pos = pos;
+    let mut end = None;
+    let mut len = 1;
+    loop {
+        let b = *buf.get(pos).ok_or(Error::Truncated { offset: pos })?;
+        match b & 0xc0 {
+            0x00 if b == 0 => {
+                pos += 1;
+                break;
+            }
+            0x00 => {
+                let n = usize::from(b);
+                if buf.len() < pos + 1 + n {
+                    return Err(Error::Truncated { offset: pos });
+                }
+                len += n + 1;
+                if len > name::MAX_NAME_LEN {
+                    return Err(Error::BadName {
+                        offset: start,
+                        error: name::Error::NameTooLong { index: pos },
+                    });
+                }
+                pos += n + 1;
+            }
+            0xc0 => {
+                let lo = *buf.get(pos + 1).ok_or(Error::Truncated { offset: pos })?;
+                let target = (usize::from(b & 0x3f) << 8) | usize::from(lo);
+                // As in `Decoder::name`, pointers only go backwards.
+                if target >= pos {
+                    return Err(Error::BadPointer { offset: pos });
+                }
+                if end.is_none() {
+                    end = Some(pos + 2);
+                }
+                pos = target;
+            }
+            _ => return Err(Error::BadLabelType { offset: pos }),
+        }
+    }
+    Ok((NameRef { buf, pos: start }, end.unwrap_or(pos)))
+}
+
+/// A name inside a message buffer, possibly compressed.
+#[derive(Clone, Copy)]
+pub struct NameRef<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NameRef<'a> {
+    /// The labels from the leftmost to the rightmost, excluding the root.
+    pub fn labels(&self) -> Labels<'a> {
+        Labels {
+            buf: self.buf,
+            pos: self.pos,
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels().next().is_none()
+    }
+
+    /// The length of the name uncompressed.
+    pub fn wire_len(&self) -> usize {
+        self.labels().map(|l| l.len() + 1).sum::<usize>() + 1
+    }
+
+    pub fn to_name(&self) -> DomainName {
+        // The labels were checked when the message was parsed.
+        DomainName::from_labels(self.labels()).unwrap_or_default()
+    }
+}
+
+impl PartialEq for NameRef<'_> {
+    fn eq(&self, other: &NameRef<'_>) -> bool {
+        let mut a = self.labels();
+        let mut b = other.labels();
+        loop {
+            match (a.next(), b.next()) {
+                (Some(x), Some(y)) if x.eq_ignore_ascii_case(y) => {}
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl PartialEq<DomainName> for NameRef<'_> {
+    fn eq(&self, other: &DomainName) -> bool {
+        let mut labels = self.labels();
+        other
+            .labels()
+            .iter()
+            .all(|l| labels.next().is_some_and(|x| x.eq_ignore_ascii_case(l)))
+            && labels.next().is_none()
+    }
+}
+
+impl fmt::Display for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_name().fmt(f)
+    }
+}
+
+impl fmt::Debug for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NameRef({})", self)
+    }
+}
+
+/// The labels of a [`NameRef`].
+#[derive(Clone, Debug)]
+pub struct Labels<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            let b = *self.buf.get(self.pos)?;
+            if b & 0xc0 == 0xc0 {
+                let lo = *self.buf.get(self.pos + 1)?;
+                self.pos = (usize::from(b & 0x3f) << 8) | usize::from(lo);
+                continue;
+            }
+            if b == 0 {
+                return None;
+            }
+            let label = self.buf.get(self.pos + 1..self.pos + 1 + usize::from(b))?;
+            self.pos += 1 + usize::from(b);
+            return Some(label);
+        }
+    }
+}
+
+/// An entry of the question section, in place.
+#[derive(Clone, Copy, Debug)]
+pub struct QuestionRef<'a> {
+    pub name: NameRef<'a>,
+    pub qtype: RecordType,
+    pub qclass: RecordClass,
+}
+
+impl QuestionRef<'_> {
+    pub fn to_question(&self) -> Question {
+        Question {
+            name: self.name.to_name(),
+            qtype: self.qtype,
+            qclass: self.qclass,
+        }
+    }
+}
+
+/// A resource record, in place. Its data is only checked by
+/// [`to_record`](Self::to_record), and its TTL is as it was sent.
+#[derive(Clone, Copy, Debug)]
+pub struct RecordRef<'a> {
+    pub name: NameRef<'a>,
+    pub rtype: RecordType,
+    pub class: RecordClass,
+    pub ttl: u32,
+    pub rdata: &'a [u8],
+    pos: usize,
+    compliance: Compliance,
+}
+
+impl RecordRef<'_> {
+    /// Decodes the record, with names in its data decompressed, under the
+    /// profile its message was parsed with.
+    pub fn to_record(&self) -> Result<Record, Error> {
+        let mut dec = Decoder::with_compliance(self.name.buf, self.compliance);
+        dec.seek(self.pos)?;
+        Record::decode(&mut dec)
+    }
+}
+
+/// Checks that the name at `pos` is written without compression pointers,
+/// as the names in SRV and DNAME data must be.
+fn check_uncompressed(buf: &[u8], pos: usize) -> Result<(), Error> {
+    read_name(buf, pos)?;
+    let mut pos = pos;
+    while buf[pos] != 0 {
+        if buf[pos] & 0xc0 != 0 {
+            return Err(Error::BadPointer { offset: pos });
+        }
+        pos += 1 + usize::from(buf[pos]);
+    }
+    Ok(())
+}
+
+/// Reads the record at `pos`, returning it and the position after it.
+fn read_record(
+    buf: &[u8],
+    pos: usize,
+    compliance: Compliance,
+) -> Result<(RecordRef<'_>, usize), Error> {
+    let (name, end) = read_name(buf, pos)?;
+    let fixed = buf
+        .get(end..end + 10)
+        .ok_or(Error::Truncated { offset: end })?;
+    let field = |i: usize| u16::from_be_bytes([fixed[i], fixed[i + 1]]);
+    let len = usize::from(field(8));
+    let rdata = buf
+        .get(end + 10..end + 10 + len)
+        .ok_or(Error::Truncated { offset: end + 10 })?;
+    let record = RecordRef {
+        name,
+        rtype: RecordType(field(0)),
+        class: RecordClass(field(2)),
+        ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+        rdata,
+        pos,
+        compliance,
+    };
+    Ok((record, end + 10 + len))
+}
+
+/// The records of one section of a [`MessageRef`].
+#[derive(Clone, Debug)]
+pub struct Records<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    left: u16,
+    skip: Option<usize>,
+    compliance: Compliance,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = RecordRef<'a>;
+
+    fn next(&mut self) -> Option<RecordRef<'a>> {
+        while self.left > 0 {
+            self.left -= 1;
+            let pos = self.pos;
+            let (record, end) = read_record(self.buf, pos, self.compliance).ok()?;
+            self.pos = end;
+            if Some(pos) != self.skip {
+                return Some(record);
+            }
+        }
+        None
+    }
+}
+
+/// The OPT record of a [`MessageRef`], in place.
+#[derive(Clone, Copy, Debug)]
+pub struct EdnsRef<'a> {
+    pub udp_size: u16,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    options: &'a [u8],
+}
+
+impl<'a> EdnsRef<'a> {
+    /// The options, as codes and data.
+    pub fn options(&self) -> impl Iterator<Item = (OptionCode, &'a [u8])> + 'a {
+        let mut rest = self.options;
+        std::iter::from_fn(move || {
+            if rest.len() < 4 {
+                return None;
+            }
+            let code = OptionCode(u16::from_be_bytes([rest[0], rest[1]]));
+            let len = usize::from(u16::from_be_bytes([rest[2], rest[3]]));
+            let data = rest.get(4..4 + len)?;
+            rest = &rest[4 + len..];
+            Some((code, data))
+        })
+    }
+
+    /// The data of the first option with the given code.
+    pub fn option(&self, code: OptionCode) -> Option<&'a [u8]> {
+        self.options()
+            .find(|(c, _)| *c == code)
+            .map(|(_, data)| data)
+    }
+}
+
+/// A message read in place from its wire format.
+///
+/// Parsing walks the whole message once, checking its framing as
+/// [`Message::from_wire_with`] does under the same profile, and remembers
+/// where each section starts; nothing is copied.
+#[derive(Clone, Copy, Debug)]
+pub struct MessageRef<'a> {
+    buf: &'a [u8],
+    header: Header,
+    counts: [u16; 4],
+    sections: [usize; 4],
+    opt: Option<usize>,
+    compliance: Compliance,
+}
+
+impl<'a> MessageRef<'a> {
+    /// Reads the message in `buf`, rejecting trailing bytes.
+    pub fn parse(buf: &'a [u8]) -> Result<MessageRef<'a>, Error> {
+        MessageRef::parse_with(buf, &Compliance::default())
+    }
+
+    /// Reads the message in `buf`, putting up with what `compliance` does.
+    /// Several questions are left for the caller to judge, as servers
+    /// judge them by opcode.
+    pub fn parse_with(buf: &'a [u8], compliance: &Compliance) -> Result<MessageRef<'a>, Error> {
+        let mut dec = Decoder::new(buf);
+        let id = dec.u16()?;
+        let flags = dec.u16()?;
+        let mut counts = [0u16; 4];
+        for count in counts.iter_mut() {
+            *count = dec.u16()?;
+        }
+        let mut msg = MessageRef {
+            buf,
+            header: Header::from_flags(id, flags),
+            counts,
+            sections: [12; 4],
+            opt: None,
+            compliance: *compliance,
+        };
+        let mut pos = 12;
+        for _ in 0..counts[0] {
+            let (_, end) = read_name(buf, pos)?;
+            if buf.len() < end + 4 {
+                return Err(Error::Truncated { offset: end });
+            }
+            pos = end + 4;
+        }
+        for (section, &count) in counts.iter().enumerate().skip(1) {
+            msg.sections[section] = pos;
+            for _ in 0..count {
+                let (record, end) = read_record(buf, pos, *compliance)?;
+                let rdata = end - record.rdata.len();
+                let target = match record.rtype {
+                    RecordType::SRV if record.rdata.len() > 6 => Some(rdata + 6),
+                    RecordType::DNAME if !record.rdata.is_empty() => Some(rdata),
+                    _ => None,
+                };
+                if let (Some(target), false) = (target, compliance.compressed_rdata) {
+                    check_uncompressed(buf, target)?;
+                }
+                if section == 3 && record.rtype == RecordType::OPT {
+                    if msg.opt.is_some() || !record.name.is_root() {
+                        return Err(Error::BadRdata { offset: pos });
+                    }
+                    msg.opt = Some(pos);
+                    let extended = u16::from((record.ttl >> 24) as u8) << 4;
+                    msg.header.rcode = Rcode(msg.header.rcode.0 | extended);
+                }
+                pos = end;
+            }
+        }
+        if pos != buf.len() && !compliance.trailing_bytes {
+            return Err(Error::TrailingData { offset: pos });
+        }
+        Ok(msg)
+    }
+
+    /// The header, with the extended rcode bits of the OPT record.
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    pub fn questions(&self) -> impl Iterator<Item = QuestionRef<'a>> + 'a {
+        let buf = self.buf;
+        let mut pos = self.sections[0];
+        let mut left = self.counts[0];
+        std::iter::from_fn(move || {
+            if left == 0 {
+                return None;
+            }
+            left -= 1;
+            let (name, end) = read_name(buf, pos).ok()?;
+            let fixed = buf.get(end..end + 4)?;
+            pos = end + 4;
+            Some(QuestionRef {
+                name,
+                qtype: RecordType(u16::from_be_bytes([fixed[0], fixed[1]])),
+                qclass: RecordClass(u16::from_be_bytes([fixed[2], fixed[3]])),
+            })
+        })
+    }
+
+    /// The first question, if any.
+    pub fn question(&self) -> Option<QuestionRef<'a>> {
+        self.questions().next()
+    }
+
+    fn section(&self, i: usize) -> Records<'a> {
+        Records {
+            buf: self.buf,
+            pos: self.sections[i],
+            left: self.counts[i],
+            skip: self.opt,
+            compliance: self.compliance,
+        }
+    }
+
+    pub fn answers(&self) -> Records<'a> {
+        self.section(1)
+    }
+
+    pub fn authority(&self) -> Records<'a> {
+        self.section(2)
+    }
+
+    /// Additional records, excluding the OPT pseudo-record.
+    pub fn additional(&self) -> Records<'a> {
+        self.section(3)
+    }
+
+    pub fn edns(&self) -> Option<EdnsRef<'a>> {
+        let (record, _) = read_record(self.buf, self.opt?, self.compliance).ok()?;
+        Some(EdnsRef {
+            udp_size: record.class.0,
+            version: (record.ttl >> 16) as u8,
+            dnssec_ok: record.ttl & 0x8000 != 0,
+            options: record.rdata,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    /// Decodes the whole message, record data included, under the profile
+    /// it was parsed with.
+    pub fn to_message(&self) -> Result<Message, Error> {
+        Message::from_wire_with(self.buf, &self.compliance)
+    }
+}
+
+/// Names a [`ResponseBuilder`] can write.
+pub trait ToWireName {
+    /// Appends the name to `out`, uncompressed.
+    fn write_uncompressed(&self, out: &mut Vec<u8>);
+}
+
+impl ToWireName for DomainName {
+    fn write_uncompressed(&self, out: &mut Vec<u8>) {
+        for label in self.labels() {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label);
+        }
+        out.push(0);
+    }
+}
+
+impl ToWireName for NameRef<'_> {
+    fn write_uncompressed(&self, out: &mut Vec<u8>) {
+        for label in self.labels() {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label);
+        }
+        out.push(0);
+    }
+}
+
+impl<N: ToWireName + ?Sized> ToWireName for &N {
+    fn write_uncompressed(&self, out: &mut Vec<u8>) {
+        (**self).write_uncompressed(out)
+    }
+}
+
+/// Writes a response into an [`Arena`].
+///
+/// Records go in section by section: once an authority record is added,
+/// answers are refused, and so on. A record that would take the response
+/// past its size limit is refused too, and so is everything after it; if
+/// it was an answer or authority record, the response is marked truncated.
+#[derive(Debug)]
+pub struct ResponseBuilder<'a> {
+    buf: &'a mut Vec<u8>,
+    header: Header,
+    limit: usize,
+    counts: [u16; 4],
+    section: usize,
+    full: bool,
+    edns: Option<Edns>,
+    targets: [u16; MAX_COMPRESSION_TARGETS],
+    target_count: usize,
+}
+
+impl<'a> ResponseBuilder<'a> {
+    fn new(buf: &'a mut Vec<u8>, query: &MessageRef<'_>, max: usize) -> ResponseBuilder<'a> {
+        let max = max.clamp(12, usize::from(u16::MAX));
+        buf.clear();
+        buf.reserve(max);
+        buf.extend_from_slice(&[0; 12]);
+        let q = query.header();
+        let edns = query.edns().map(|e| Edns {
+            dnssec_ok: e.dnssec_ok,
+            ..Edns::default()
+        });
+        let mut builder = ResponseBuilder {
+            buf,
+            header: Header {
+                id: q.id,
+                qr: true,
+                opcode: q.opcode,
+                rd: q.rd,
+                cd: q.cd,
+                ..Header::default()
+            },
+            limit: max - if edns.is_some() { OPT_LEN } else { 0 },
+            counts: [0; 4],
+            section: 1,
+            full: false,
+            edns,
+            targets: [0; MAX_COMPRESSION_TARGETS],
+            target_count: 0,
+        };
+        if let Some(question) = query.question() {
+            builder.name(&question.name);
+            builder
+                .buf
+                .extend_from_slice(&question.qtype.0.to_be_bytes());
+            builder
+                .buf
+                .extend_from_slice(&question.qclass.0.to_be_bytes());
+            if builder.buf.len() > builder.limit {
+                builder.buf.truncate(12);
+                builder.header.tc = true;
+                builder.full = true;
+            } else {
+                builder.counts[0] = 1;
+            }
+        }
+        builder
+    }
+
+    pub fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    pub fn set_rcode(&mut self, rcode: Rcode) {
+        self.header.rcode = rcode;
+    }
+
+    /// The EDNS the response will carry, if the query had it.
+    pub fn edns_mut(&mut self) -> Option<&mut Edns> {
+        self.edns.as_mut()
+    }
+
+    /// Writes `name`, compressed against the names written before it.
+    fn name<N: ToWireName>(&mut self, name: &N) {
+        let start = self.buf.len();
+        name.write_uncompressed(self.buf);
+        // Find the longest suffix already in the message.
+        let mut pos = start;
+        while self.buf[pos] != 0 {
+            let buf: &[u8] = self.buf;
+            let suffix = NameRef { buf, pos };
+            let found = self.targets[..self.target_count].iter().find(|&&t| {
+                NameRef {
+                    buf,
+                    pos: usize::from(t),
+                } == suffix
+            });
+            if let Some(&target) = found {
+                self.buf.truncate(pos);
+                self.buf.extend_from_slice(&(0xc000 | target).to_be_bytes());
+                break;
+            }
+            pos += 1 + usize::from(self.buf[pos]);
+        }
+        // Remember where the labels written out in full start.
+        let mut label = start;
+        while label < pos && label <= MAX_POINTER && self.target_count < MAX_COMPRESSION_TARGETS {
+            self.targets[self.target_count] = label as u16;
+            self.target_count += 1;
+            label += 1 + usize::from(self.buf[label]);
+        }
+    }
+
+    fn record<N: ToWireName>(
+        &mut self,
+        section: usize,
+        name: N,
+        rtype: RecordType,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> bool {
+        if self.full || section < self.section || rdata.len() > usize::from(u16::MAX) {
+            return false;
+        }
+        let start = self.buf.len();
+        let targets = self.target_count;
+        self.name(&name);
+        self.buf.extend_from_slice(&rtype.0.to_be_bytes());
+        self.buf.extend_from_slice(&RecordClass::IN.0.to_be_bytes());
+        self.buf.extend_from_slice(&ttl.to_be_bytes());
+        self.buf
+            .extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(rdata);
+        if self.buf.len() > self.limit {
+            self.buf.truncate(start);
+            self.target_count = targets;
+            self.header.tc |= section < 3;
+            self.full = true;
+            return false;
+        }
+        self.section = section;
+        self.counts[section] += 1;
+        true
+    }
+
+    /// Adds an answer of class IN with data `rdata` in wire format. Names
+    /// in `rdata` are not compressed. Returns whether it was added.
+    pub fn answer<N: ToWireName>(
+        &mut self,
+        name: N,
+        rtype: RecordType,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> bool {
+        self.record(1, name, rtype, ttl, rdata)
+    }
+
+    /// Adds an authority record, as [`answer`](Self::answer) does.
+    pub fn authority<N: ToWireName>(
+        &mut self,
+        name: N,
+        rtype: RecordType,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> bool {
+        self.record(2, name, rtype, ttl, rdata)
+    }
+
+    /// Adds an additional record, as [`answer`](Self::answer) does.
+    pub fn additional<N: ToWireName>(
+        &mut self,
+        name: N,
+        rtype: RecordType,
+        ttl: u32,
+        rdata: &[u8],
+    ) -> bool {
+        self.record(3, name, rtype, ttl, rdata)
+    }
+
+    /// Completes the response: writes the header and the OPT record, and
+    /// returns the response in wire format. EDNS options that no longer
+    /// fit are left out.
+    pub fn finish(self) -> &'a [u8] {
+        let mut counts = self.counts;
+        if let Some(edns) = &self.edns {
+            let buf = &mut *self.buf;
+            buf.push(0);
+            buf.extend_from_slice(&RecordType::OPT.0.to_be_bytes());
+            buf.extend_from_slice(&edns.udp_size.to_be_bytes());
+            buf.push((self.header.rcode.0 >> 4) as u8);
+            buf.push(edns.version);
+            let flags: u16 = if edns.dnssec_ok { 0x8000 } else { 0 };
+            buf.extend_from_slice(&flags.to_be_bytes());
+            let len_pos = buf.len();
+            buf.extend_from_slice(&[0, 0]);
+            let room = (self.limit + OPT_LEN).saturating_sub(buf.len());
+            let mut len = 0;
+            for opt in &edns.options {
+                if len + 4 + opt.data.len() > room {
+                    continue;
+                }
+                buf.extend_from_slice(&opt.code.0.to_be_bytes());
+                buf.extend_from_slice(&(opt.data.len() as u16).to_be_bytes());
+                buf.extend_from_slice(&opt.data);
+                len += 4 + opt.data.len();
+            }
+            buf[len_pos..len_pos + 2].copy_from_slice(&(len as u16).to_be_bytes());
+            counts[3] += 1;
+        }
+        let buf: &'a mut Vec<u8> = self.buf;
+        buf[0..2].copy_from_slice(&self.header.id.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.header.flags().to_be_bytes());
+        for (i, count) in counts.iter().enumerate() {
+            buf[4 + 2 * i..6 + 2 * i].copy_from_slice(&count.to_be_bytes());
+        }
+        buf
+    }
+}
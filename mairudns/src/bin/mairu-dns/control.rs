@@ -0,0 +1,210 @@
+//! `mairu-dns control`: commands for a running server, and the server's
+//! side of them.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use mairudns::cache::NamePattern;
+use mairudns::config::{Config, ZoneConfig, ZoneKind};
+use mairudns::name::DomainName;
+use mairudns::server::remote::{self, Command, Operator};
+use mairudns::server::Control;
+
+use super::option_value;
+use super::serve::{load_zone, reconfigure, Built, Running};
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--config" | "-c" => path = Some(option_value(args, &mut i)?),
+            word => words.push(word),
+        }
+        i += 1;
+    }
+    let path = path.ok_or("control needs --config <file>")?;
+    let command: Command = words.join(" ").parse()?;
+    let config = Config::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    let control = config
+        .control
+        .as_ref()
+        .ok_or_else(|| format!("{}: no control channel configured", path))?;
+    let key = config
+        .find_key(&control.key)
+        .ok_or("control: no such key")?
+        .to_key()
+        .map_err(|e| format!("control: {}", e))?;
+    let answer = remote::send(&control.socket, &key.secret, &command)
+        .map_err(|e| format!("{}: {}", control.socket.display(), e))?;
+    if !answer.is_empty() {
+        println!("{}", answer.trim_end());
+    }
+    Ok(())
+}
+
+/// Carries out control commands on a server built by `serve`.
+pub struct Controller {
+    /// The configuration file, read again to reconfigure.
+    path: PathBuf,
+    running: Arc<Mutex<Running>>,
+    control: Control,
+    started: Instant,
+}
+
+impl Controller {
+    pub fn new<P: AsRef<Path>>(path: P, built: &Built) -> Controller {
+        Controller {
+            path: path.as_ref().to_path_buf(),
+            running: built.running.clone(),
+            control: built.server.control(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Reloads a primary zone from its file, or has a secondary check its
+    /// primaries now.
+    fn reload(running: &Running, zone: &ZoneConfig) -> Result<(), String> {
+        let origin: DomainName = zone.name.parse().map_err(|e| format!("{}", e))?;
+        let authority = match &zone.view {
+            Some(view) => &running.views[view],
+            None => &running.authority,
+        };
+        match (zone.kind, &zone.file) {
+            (ZoneKind::Primary, Some(file)) => {
+                let verify = zone.verify_zonemd == Some(true);
+                authority.insert(load_zone(&running.config, &origin, file, verify)?);
+            }
+            _ => {
+                let key = (zone.view.clone(), origin);
+                if let Some(secondary) = running.secondaries.get(&key) {
+                    secondary.notify();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the configuration file again and applies what changed.
+    fn reconfigure(&self) -> Result<String, String> {
+        let config =
+            Config::load(&self.path).map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let mut running = self.running.lock().unwrap();
+        reconfigure(&mut running, &self.control, config)
+    }
+
+    fn status(&self, running: &Running) -> String {
+        format!(
+            "version: mairu-dns {}\nuptime: {}s\nzones: {}\nforwarding routes: {}\nlisteners: {}\ndebug logging: {}",
+            env!("CARGO_PKG_VERSION"),
+            self.started.elapsed().as_secs(),
+            running.authority.origins().len()
+                + running
+                    .views
+                    .values()
+                    .map(|v| v.origins().len())
+                    .sum::<usize>(),
+            running.forwarder.routes().len(),
+            self.control.listening().len(),
+            if running.debug.load(Ordering::Relaxed) {
+                "on"
+            } else {
+                "off"
+            }
+        )
+    }
+}
+
+impl Operator for Controller {
+    fn execute(&self, command: &Command) -> Result<String, String> {
+        let running = self.running.lock().unwrap();
+        let zones = running.config.effective_zones();
+        match command {
+            Command::Status => Ok(self.status(&running)),
+            Command::Reload(None) => {
+                let errors: Vec<String> = zones
+                    .iter()
+                    .filter_map(|z| Controller::reload(&running, z).err())
+                    .collect();
+                if errors.is_empty() {
+                    Ok(format!("reloaded {} zones", zones.len()))
+                } else {
+                    Err(errors.join("\n"))
+                }
+            }
+            Command::Reload(Some(origin)) => {
+                // In every view that has it.
+                let zones: Vec<&ZoneConfig> = zones
+                    .iter()
+                    .filter(|z| z.name.parse().ok().as_ref() == Some(origin))
+                    .collect();
+                if zones.is_empty() {
+                    return Err(format!("no zone {}", origin));
+                }
+                for zone in zones {
+                    Controller::reload(&running, zone)?;
+                }
+                Ok(format!("reloaded {}", origin))
+            }
+            Command::Flush(None, _) => {
+                running.forwarder.flush();
+                Ok("flushed the cache".into())
+            }
+            Command::Flush(Some(pattern), rtype) => {
+                let dropped = running.forwarder.flush_matching(pattern, *rtype);
+                Ok(format!("flushed {} responses for {}", dropped, pattern))
+            }
+            Command::Cache(pattern) => {
+                let pattern = pattern.clone().unwrap_or_else(NamePattern::any);
+                let lines: Vec<String> = running
+                    .forwarder
+                    .inspect_cache(&pattern)
+                    .into_iter()
+                    .map(|(route, c)| {
+                        format!(
+                            "{} {} {} {}s {} {} via {}{}{}",
+                            c.question.name,
+                            c.question.qclass,
+                            c.question.qtype,
+                            c.expires_in.as_secs(),
+                            c.response.header.rcode,
+                            c.security,
+                            route,
+                            if c.dnssec_ok { " +dnssec" } else { "" },
+                            if c.checking_disabled { " +cd" } else { "" },
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            Command::Stats => Ok(running.metrics.to_prometheus()),
+            Command::Recent(count) => {
+                let recent = running
+                    .recent
+                    .as_ref()
+                    .ok_or("no telemetry configured to keep recent queries")?;
+                let lines: Vec<String> = recent
+                    .last(count.unwrap_or(recent.capacity()))
+                    .iter()
+                    .map(|q| q.to_string())
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            Command::Debug(on) => {
+                running.debug.store(*on, Ordering::Relaxed);
+                Ok(format!("debug logging {}", if *on { "on" } else { "off" }))
+            }
+            Command::Drain => {
+                self.control.shutdown();
+                Ok("draining".into())
+            }
+            Command::Reconfigure => {
+                drop(running);
+                self.reconfigure()
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! `mairu-dns`: runs a server from a configuration file, sends queries in
+//! the manner of `dig`, checks, converts, compares and signs zone files,
+//! and prints and replays packet captures.
+
+use std::env;
+use std::process;
+
+#[cfg(unix)]
+mod control;
+mod pcap;
+mod query;
+mod serve;
+mod zone;
+
+const USAGE: &str = "\
+usage: mairu-dns <command> [arguments]
+
+commands:
+    serve --config <file> [--check]
+        Serve as the configuration says, or with --check only validate it.
+    control --config <file> <command>
+        Send status, reload [<zone>], reconfigure, cache [<pattern>],
+        flush [<pattern> [<type>]], stats, debug on|off or drain to a
+        running server. A pattern is a name, or *.<name> for the name
+        and all below it.
+    query <name> [<type>] [<class>] [@<server>] [-p <port>] [+tcp] [+dnssec] [+norec] [+cd]
+        Send a query and print the response.
+    zone check <origin> <file> [--strict]
+    zone convert <origin> <file> [-o <output>]
+    zone compile <origin> <file> -o <output>
+    zone diff <origin> <old-file> <new-file>
+        Check a zone file, its ZONEMD digest included, with --strict
+        against every RFC rule, rewrite it in canonical form, compile it
+        for a mapped backend, or show what changed between two versions
+        of it.
+    zone sign <origin> <file> -k <key>... [-o <output>] [--nsec3]
+              [--iterations <n>] [--salt <hex>] [--opt-out] [--legacy-nsec3]
+              [--validity <time>] [--jitter <time>] [--zonemd sha384|sha512]
+              [--foreign <file>]...
+        Sign a zone with BIND-format key pairs, with an NSEC chain or
+        with --nsec3 an NSEC3 one, and with --zonemd add a ZONEMD digest.
+        --foreign publishes the DNSKEY records in a file, the keys of
+        another provider signing the zone too, without signing with them.
+        NSEC3 iterations and salts, which RFC 9276 advises against, need
+        --legacy-nsec3. Needs the dnssec feature.
+    zone ds <key>... [-d sha256|sha384]...
+        Print the DS records the parent zone should publish for the
+        key-signing keys among the given keys. Needs the dnssec feature.
+    zone sshfp <host> <public-key>...
+        Print the SSHFP records for the host keys in OpenSSH public key
+        files. Needs the dnssec feature.
+    pcap print <file>
+        Print the DNS messages in a packet capture.
+    pcap replay <file> @<server> [-p <port>] [--speed <factor>]
+                [--concurrency <n>] [--timeout <secs>]
+        Send the queries in a packet capture to a server, as fast as it
+        answers or with --speed at a multiple of the captured pace, and
+        report its answers, latencies and where they differ from the
+        captured responses.
+";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let rest = args.get(1..).unwrap_or(&[]);
+    let result = match args.first().map(String::as_str) {
+        Some("serve") => serve::run(rest),
+        Some("query") => query::run(rest),
+        Some("zone") => zone::run(rest),
+        Some("pcap") => pcap::run(rest),
+        #[cfg(unix)]
+        Some("control") => control::run(rest),
+        Some("version") | Some("--version") | Some("-V") => {
+            println!("mairu-dns {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        Some("help") | Some("--help") | Some("-h") => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => Err(format!("unknown command {:?}", other)),
+        None => {
+            eprint!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("mairu-dns: {}", e);
+        process::exit(1);
+    }
+}
+
+/// The value following the option at `args[*i]`, advancing past it.
+fn option_value<'a>(args: &'a [String], i: &mut usize) -> Result<&'a str, String> {
+    *i += 1;
+    args.get(*i)
+        .map(String::as_str)
+        .ok_or_else(|| format!("{} needs a value", args[*i - 1]))
+}
@@ -0,0 +1,146 @@
+//! `mairu-dns pcap`: printing the DNS messages in a packet capture and
+//! replaying its queries against a server.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::tools::pcap::{self, DnsMessage, Replay};
+
+use super::option_value;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let rest = args.get(1..).unwrap_or(&[]);
+    match args.first().map(String::as_str) {
+        Some("print") => print(rest),
+        Some("replay") => replay(rest),
+        Some(other) => Err(format!("unknown pcap command {:?}", other)),
+        None => Err("pcap needs a command: print or replay".into()),
+    }
+}
+
+fn read(path: &str) -> Result<Vec<DnsMessage>, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    pcap::read_messages(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn print(args: &[String]) -> Result<(), String> {
+    let path = match args {
+        [path] => path,
+        _ => return Err("usage: mairu-dns pcap print <file>".into()),
+    };
+    for message in read(path)? {
+        let transport = match message.protocol {
+            Protocol::Udp => "UDP",
+            Protocol::Tcp => "TCP",
+            Protocol::Tls => "TLS",
+        };
+        println!(
+            ";; {}.{:06} {} -> {} ({})",
+            message.timestamp.as_secs(),
+            message.timestamp.subsec_micros(),
+            message.src,
+            message.dst,
+            transport
+        );
+        match message.decode() {
+            Ok(decoded) => print!("{}", decoded),
+            Err(e) => println!(";; malformed: {}", e),
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn replay(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut server: Option<IpAddr> = None;
+    let mut port = 53;
+    let mut speed = None;
+    let mut concurrency = None;
+    let mut timeout = None;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if let Some(addr) = arg.strip_prefix('@') {
+            server = Some(
+                addr.parse()
+                    .map_err(|_| format!("invalid server address {:?}", addr))?,
+            );
+        } else if arg == "-p" {
+            let value = option_value(args, &mut i)?;
+            port = value
+                .parse()
+                .map_err(|_| format!("invalid port {:?}", value))?;
+        } else if arg == "--speed" {
+            let value = option_value(args, &mut i)?;
+            speed = Some(
+                value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|s| *s > 0.0)
+                    .ok_or_else(|| format!("invalid speed {:?}", value))?,
+            );
+        } else if arg == "--concurrency" {
+            let value = option_value(args, &mut i)?;
+            concurrency = Some(
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("invalid concurrency {:?}", value))?,
+            );
+        } else if arg == "--timeout" {
+            let value = option_value(args, &mut i)?;
+            let secs = value
+                .parse()
+                .map_err(|_| format!("invalid timeout {:?}", value))?;
+            timeout = Some(Duration::from_secs(secs));
+        } else if path.is_none() && !arg.starts_with('-') {
+            path = Some(arg);
+        } else {
+            return Err(format!("unexpected argument {:?}", arg));
+        }
+        i += 1;
+    }
+    let (path, server) = match (path, server) {
+        (Some(path), Some(server)) => (path, SocketAddr::new(server, port)),
+        _ => return Err("usage: mairu-dns pcap replay <file> @<server> [...]".into()),
+    };
+    let messages = read(path)?;
+    let mut replay = Replay::new(server);
+    replay.speed = speed;
+    if let Some(concurrency) = concurrency {
+        replay.concurrency = concurrency;
+    }
+    if let Some(timeout) = timeout {
+        replay.timeout = timeout;
+    }
+    let report = replay.run(&messages);
+
+    println!("sent:       {}", report.sent);
+    println!("answered:   {}", report.answered);
+    println!("timed out:  {}", report.timed_out);
+    println!("failed:     {}", report.failed);
+    println!("differing:  {}", report.differing);
+    for (rcode, count) in &report.rcodes {
+        println!("{:<11} {}", format!("{}:", rcode), count);
+    }
+    println!(
+        "elapsed:    {:.3} s, {:.1} queries/s",
+        report.elapsed.as_secs_f64(),
+        report.queries_per_second()
+    );
+    for p in &[0.5, 0.9, 0.99] {
+        if let Some(latency) = report.percentile(*p) {
+            println!(
+                "p{:<9} {:.3} ms",
+                format!("{}:", p * 100.0),
+                latency.as_secs_f64() * 1000.0
+            );
+        }
+    }
+    Ok(())
+}
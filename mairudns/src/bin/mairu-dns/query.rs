@@ -0,0 +1,134 @@
+//! `mairu-dns query`: one query, printed the way `dig` prints it.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use mairudns::client::{self, Protocol};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::ResolverConfig;
+use mairudns::rr::{RecordClass, RecordType};
+
+use super::option_value;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut name: Option<DomainName> = None;
+    let mut qtype = None;
+    let mut qclass = RecordClass::IN;
+    let mut server: Option<IpAddr> = None;
+    let mut port = 53;
+    let mut protocol = Protocol::Udp;
+    let mut dnssec = false;
+    let mut recurse = true;
+    let mut checking_disabled = false;
+    let mut timeout = Duration::from_secs(5);
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        if let Some(addr) = arg.strip_prefix('@') {
+            server = Some(
+                addr.parse()
+                    .map_err(|_| format!("invalid server address {:?}", addr))?,
+            );
+        } else if arg == "-p" {
+            let value = option_value(args, &mut i)?;
+            port = value
+                .parse()
+                .map_err(|_| format!("invalid port {:?}", value))?;
+        } else if let Some(option) = arg.strip_prefix('+') {
+            match option {
+                "tcp" | "vc" => protocol = Protocol::Tcp,
+                "dnssec" => dnssec = true,
+                "norec" | "norecurse" => recurse = false,
+                "cd" | "cdflag" => checking_disabled = true,
+                _ => match option.strip_prefix("timeout=") {
+                    Some(secs) => {
+                        let secs = secs
+                            .parse()
+                            .map_err(|_| format!("invalid timeout {:?}", secs))?;
+                        timeout = Duration::from_secs(secs);
+                    }
+                    None => return Err(format!("unknown option {:?}", arg)),
+                },
+            }
+        } else if name.is_none() {
+            name = Some(
+                arg.parse()
+                    .map_err(|e| format!("invalid name {:?}: {}", arg, e))?,
+            );
+        } else if let Some(class) = parse_class(arg) {
+            qclass = class;
+        } else if qtype.is_none() {
+            qtype = Some(
+                arg.parse::<RecordType>()
+                    .map_err(|_| format!("unknown record type {:?}", arg))?,
+            );
+        } else {
+            return Err(format!("unexpected argument {:?}", arg));
+        }
+        i += 1;
+    }
+
+    let name = name.unwrap_or_else(DomainName::root);
+    let qtype = qtype.unwrap_or(if name.is_root() {
+        RecordType::NS
+    } else {
+        RecordType::A
+    });
+    let server = match server {
+        Some(ip) => SocketAddr::new(ip, port),
+        None => {
+            let system = ResolverConfig::system()
+                .map_err(|e| format!("cannot read the system resolver configuration: {}", e))?;
+            let first = system
+                .servers
+                .first()
+                .ok_or("no name servers in the system configuration")?;
+            SocketAddr::new(first.ip(), port)
+        }
+    };
+
+    let mut query = Message::query(name, qtype);
+    query.questions[0].qclass = qclass;
+    query.header.rd = recurse;
+    query.header.cd = checking_disabled;
+    if let Some(edns) = &mut query.edns {
+        edns.dnssec_ok = dnssec;
+    }
+
+    let started = Instant::now();
+    let mut resp =
+        client::exchange(server, protocol, &query, timeout).map_err(|e| e.to_string())?;
+    if resp.header.tc && protocol == Protocol::Udp {
+        println!(";; Truncated, retrying in TCP mode.");
+        protocol = Protocol::Tcp;
+        resp = client::exchange(server, protocol, &query, timeout).map_err(|e| e.to_string())?;
+    }
+    let elapsed = started.elapsed();
+
+    print!("{}", resp);
+    println!();
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    let transport = match protocol {
+        Protocol::Udp => "UDP",
+        Protocol::Tcp => "TCP",
+        Protocol::Tls => "TLS",
+    };
+    println!(";; SERVER: {}({})", server, transport);
+    if let Ok(wire) = resp.to_wire() {
+        println!(";; MSG SIZE  rcvd: {}", wire.len());
+    }
+    Ok(())
+}
+
+/// A class given on the command line; `ANY` is taken as a type, as `dig`
+/// does.
+fn parse_class(arg: &str) -> Option<RecordClass> {
+    if arg.eq_ignore_ascii_case("CHAOS") {
+        return Some(RecordClass::CH);
+    }
+    arg.parse()
+        .ok()
+        .filter(|c| *c != RecordClass::ANY && *c != RecordClass::NONE)
+}
@@ -0,0 +1,1008 @@
+//! `mairu-dns serve`: a server assembled from a configuration file.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::compliance::Compliance;
+use mairudns::config::{
+    AnswerOrderConfig, BackendConfig, BackendKind, BlockAction, BlocklistFormat,
+    CachePersistenceConfig, Config, DiscoveryProxyConfig, ListenerConfig, LogFormat, Transport,
+    ZoneConfig, ZoneKind,
+};
+use mairudns::mdns::{DiscoveryProxy, ProxyLink};
+use mairudns::message::{Message, Opcode};
+use mairudns::metrics::Metrics;
+use mairudns::name::DomainName;
+use mairudns::policy::{Action, Filter, TtlPolicy, TtlRule};
+use mairudns::querylog::{self, Format, Logger};
+#[cfg(feature = "api")]
+use mairudns::server::api::Api;
+#[cfg(unix)]
+use mairudns::server::remote::RemoteControl;
+#[cfg(feature = "script")]
+use mairudns::server::ScriptPlugin;
+use mairudns::server::{
+    from_fn, Authority, Classify, Control, Forwarder, Handler, HandlerExt, Instrument, Limits,
+    Plugins, Pools, QueryLog, RecentQueries, Request, Server, SharedForwarder,
+};
+use mairudns::tsig::Keyring;
+#[cfg(feature = "sqlite")]
+use mairudns::zone::SqliteBackend;
+use mairudns::zone::{
+    Backend, CachedBackend, LeaseSync, MappedBackend, RedisBackend, Secondary, Zone,
+};
+
+use super::option_value;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut check_only = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--config" | "-c" => path = Some(option_value(args, &mut i)?),
+            "--check" => check_only = true,
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+        i += 1;
+    }
+    let path = path.ok_or("serve needs --config <file>")?;
+    let config = Config::load(path).map_err(|e| format!("{}: {}", path, e))?;
+    if check_only {
+        println!("{}: OK", path);
+        return Ok(());
+    }
+    let built = build(&config)?;
+    for (protocol, addr) in built.server.local_addrs() {
+        eprintln!("listening on {} ({:?})", addr, protocol);
+    }
+    #[cfg(unix)]
+    if let Some(control) = &config.control {
+        let key = config
+            .find_key(&control.key)
+            .ok_or("control: no such key")?
+            .to_key()
+            .map_err(|e| format!("control: {}", e))?;
+        let remote = RemoteControl::bind(&control.socket, &key.secret)
+            .map_err(|e| format!("{}: {}", control.socket.display(), e))?;
+        let operator = super::control::Controller::new(path, &built);
+        thread::spawn(move || remote.run(operator));
+    }
+    persist_cache(&config.cache_persistence, &built.running);
+    let result = built.server.run().map_err(|e| e.to_string());
+    if let Some(path) = &config.cache_persistence.snapshot {
+        save_cache(&built.running, path);
+    }
+    result
+}
+
+/// Restores the cache snapshot, then keeps writing it and warms the cache
+/// in the background.
+fn persist_cache(config: &CachePersistenceConfig, running: &Arc<Mutex<Running>>) {
+    let forwarder = running.lock().unwrap().forwarder.clone();
+    if let Some(path) = &config.snapshot {
+        match forwarder.load_cache(path) {
+            Ok(n) => eprintln!("restored {} cached responses", n),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+        if config.snapshot_interval_secs > 0 {
+            let running = running.clone();
+            let path = path.clone();
+            let interval = Duration::from_secs(config.snapshot_interval_secs);
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                save_cache(&running, &path);
+            });
+        }
+    }
+    let mut questions = Vec::new();
+    for path in &config.warm_from {
+        let read = File::open(path)
+            .and_then(|f| querylog::popular_questions(BufReader::new(f), config.warm_limit));
+        match read {
+            Ok(popular) => questions.extend(popular),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+    let mut seen = HashSet::new();
+    questions.retain(|q| seen.insert(q.clone()));
+    questions.truncate(config.warm_limit);
+    if !questions.is_empty() {
+        let forwarder = forwarder.clone();
+        thread::spawn(move || {
+            let answered = forwarder.warm(&questions);
+            eprintln!(
+                "warmed the cache with {} of {} names",
+                answered,
+                questions.len()
+            );
+        });
+    }
+}
+
+/// Writes the cache of the forwarder running now.
+fn save_cache(running: &Mutex<Running>, path: &Path) {
+    let forwarder = running.lock().unwrap().forwarder.clone();
+    if let Err(e) = forwarder.save_cache(path) {
+        eprintln!("{}: {}", path.display(), e);
+    }
+}
+
+/// A server built from a configuration, with the parts the control
+/// channel acts on.
+pub struct Built {
+    pub server: Server,
+    pub running: Arc<Mutex<Running>>,
+}
+
+/// A zone's view, if any, and origin.
+pub type ZoneKey = (Option<String>, DomainName);
+
+/// The configuration a server runs, and the parts built from it that
+/// reconfiguring keeps or replaces.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub struct Running {
+    pub config: Config,
+    /// The zones outside views.
+    pub authority: Arc<Authority>,
+    /// The zones of each view, by name.
+    pub views: HashMap<String, Arc<Authority>>,
+    pub forwarder: Arc<Forwarder>,
+    /// What the XDP listeners answer from, kept holding `forwarder`.
+    pub fast_path: SharedForwarder,
+    pub plugins: Plugins,
+    pub secondaries: HashMap<ZoneKey, Arc<Secondary>>,
+    /// The syncs keeping the records of DHCP leases in zones.
+    pub leases: HashMap<ZoneKey, Arc<LeaseSync>>,
+    pub pools: Option<Pools>,
+    pub discovery_proxy: Option<DiscoveryProxy>,
+    pub query_log: Option<QueryLog>,
+    pub metrics: Arc<Metrics>,
+    /// The last requests classified, if telemetry is configured.
+    pub recent: Option<Arc<RecentQueries>>,
+    /// Whether every request is logged to standard error.
+    pub debug: Arc<AtomicBool>,
+}
+
+/// Reads the zone file of a primary zone under the compliance profile of
+/// `config`, checking it against its ZONEMD records if `verify` is set.
+pub fn load_zone(
+    config: &Config,
+    origin: &DomainName,
+    file: &Path,
+    verify: bool,
+) -> Result<Zone, String> {
+    let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let compliance = config.compliance.to_compliance();
+    let zone = Zone::from_master_with(origin.clone(), &text, &compliance)
+        .map_err(|e| format!("{}: {}", file.display(), e))?;
+    if verify {
+        zone.verify_zonemd()
+            .map_err(|e| format!("{}: {}", file.display(), e))?;
+    }
+    Ok(zone)
+}
+
+/// Signs the answers of the zone at `origin` with its signing keys,
+/// publishing its foreign keys too.
+#[cfg(feature = "dnssec")]
+fn set_signer(authority: &Authority, origin: &DomainName, zone: &ZoneConfig) -> Result<(), String> {
+    use mairudns::dnssec::{foreign_keys, OnlineSigner, SigningPolicy};
+    use mairudns::rr::Record;
+
+    let keys = zone
+        .signing_keys
+        .iter()
+        .flatten()
+        .map(|path| super::zone::read_key(&path.to_string_lossy()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut foreign = Vec::new();
+    for path in zone.foreign_keys.iter().flatten() {
+        for key in super::zone::read_dnskeys(&path.to_string_lossy())? {
+            foreign.push(Record::new(origin.clone(), 0, key.to_rdata()));
+        }
+    }
+    let local: Vec<_> = keys.iter().map(|k| k.dnskey().clone()).collect();
+    let policy = SigningPolicy {
+        foreign: foreign_keys(&foreign, &local),
+        ..SigningPolicy::default()
+    };
+    authority
+        .set_signer(
+            origin.clone(),
+            Arc::new(OnlineSigner::new(keys).policy(policy)),
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "dnssec"))]
+fn set_signer(_: &Authority, _: &DomainName, _: &ZoneConfig) -> Result<(), String> {
+    Err("this binary has no DNSSEC support".into())
+}
+
+/// Opens the database of a backend zone, behind a cache unless it is
+/// turned off. Mapped zones are read in place and need none, and are held
+/// to `compliance` as zone files are.
+fn open_backend(
+    config: &BackendConfig,
+    compliance: &Compliance,
+) -> Result<Arc<dyn Backend>, String> {
+    fn cached<B: Backend + 'static>(backend: B, config: &BackendConfig) -> Arc<dyn Backend> {
+        if config.cache_size == 0 {
+            Arc::new(backend)
+        } else {
+            Arc::new(CachedBackend::new(backend, config.to_cache()))
+        }
+    }
+    match (config.kind, &config.path, config.address) {
+        #[cfg(feature = "sqlite")]
+        (BackendKind::Sqlite, Some(path), _) => {
+            let backend =
+                SqliteBackend::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(cached(backend, config))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        (BackendKind::Sqlite, Some(_), _) => {
+            Err("this binary was built without SQLite support".into())
+        }
+        (BackendKind::Redis, _, Some(addr)) => {
+            let mut backend = RedisBackend::new(addr);
+            if let Some(password) = &config.password {
+                backend = backend.password(password.clone());
+            }
+            if let Some(prefix) = &config.prefix {
+                backend = backend.prefix(prefix.clone());
+            }
+            Ok(cached(backend, config))
+        }
+        (BackendKind::Mapped, Some(path), _) => {
+            let backend = MappedBackend::open_with(path, compliance)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(Arc::new(backend))
+        }
+        _ => Err("incomplete backend".into()),
+    }
+}
+
+/// The plugins the configured scripts make.
+#[cfg(feature = "script")]
+fn plugins(config: &Config) -> Result<Plugins, String> {
+    let mut plugins = Plugins::new();
+    for path in &config.scripts {
+        let script = ScriptPlugin::load(path).map_err(|e| e.to_string())?;
+        plugins = plugins.plugin(Arc::new(script));
+    }
+    Ok(plugins)
+}
+
+#[cfg(not(feature = "script"))]
+fn plugins(config: &Config) -> Result<Plugins, String> {
+    match config.scripts.first() {
+        Some(path) => Err(format!(
+            "cannot run {}: this binary was built without scripting",
+            path.display()
+        )),
+        None => Ok(Plugins::new()),
+    }
+}
+
+fn keyring(config: &Config) -> Result<Keyring, String> {
+    let mut keys = Keyring::new();
+    for k in &config.keys {
+        keys.insert(k.to_key().map_err(|e| format!("keys: {}", e))?);
+    }
+    Ok(keys)
+}
+
+/// The forwarder of the configured routes.
+fn forwarder(
+    config: &Config,
+    metrics: &Arc<Metrics>,
+    plugins: &Plugins,
+) -> Result<Forwarder, String> {
+    let mut forwarder = Forwarder::new()
+        .metrics(metrics.clone())
+        .plugins(plugins.clone());
+    for r in &config.forwarders {
+        forwarder = forwarder.route(r.to_route().map_err(|e| format!("forwarders: {}", e))?);
+    }
+    Ok(forwarder)
+}
+
+/// Serves zone `z`, replacing what was served at its origin in its view.
+fn add_zone(running: &mut Running, config: &Config, z: &ZoneConfig) -> Result<(), String> {
+    let origin: DomainName = z.name.parse().map_err(|e| format!("{}: {}", z.name, e))?;
+    let authority = match &z.view {
+        Some(view) => running.views[view].clone(),
+        None => running.authority.clone(),
+    };
+    let key = (z.view.clone(), origin.clone());
+    let verify_zonemd = z.verify_zonemd == Some(true);
+    #[cfg(feature = "dnssec")]
+    authority.remove_signer(&origin);
+    match (z.kind, &z.file, &z.backend) {
+        (ZoneKind::Primary, Some(file), _) => {
+            let zone = load_zone(config, &origin, file, verify_zonemd)?;
+            authority.remove_backend(&origin);
+            authority.insert(zone);
+        }
+        (ZoneKind::Backend, _, Some(backend)) => {
+            let compliance = config.compliance.to_compliance();
+            let backend = open_backend(backend, &compliance)
+                .map_err(|e| format!("zone {}: {}", origin, e))?;
+            authority.insert_backend(origin.clone(), backend);
+        }
+        _ => {
+            let tsig = match z.primary_key.as_deref().and_then(|k| config.find_key(k)) {
+                Some(key) => Some(key.to_key().map_err(|e| e.to_string())?),
+                None => None,
+            };
+            // A zone already held keeps answering until the first transfer.
+            authority.remove_backend(&origin);
+            let shared = authority
+                .zone(&origin)
+                .unwrap_or_else(|| authority.insert(Zone::new(origin.clone())));
+            let mut secondary = Secondary::new(shared, z.primaries.clone());
+            secondary.set_verify_zonemd(verify_zonemd);
+            if let Some(tsig) = tsig {
+                secondary.set_key(tsig);
+            }
+            let secondary = Arc::new(secondary);
+            running.secondaries.insert(key.clone(), secondary.clone());
+            thread::spawn(move || secondary.run());
+        }
+    }
+    let field = |e| format!("zone {}: {}", origin, e);
+    authority.set_transfer_acl(origin.clone(), z.transfer_acl().map_err(field)?);
+    authority.set_update_policy(origin.clone(), z.update_policy().map_err(field)?);
+    authority.set_report_channel(origin.clone(), z.report_agent().map_err(field)?);
+    if z.is_signed() {
+        set_signer(&authority, &origin, z).map_err(|e| format!("zone {}: {}", origin, e))?;
+    }
+    if let Some(leases) = &z.leases {
+        let sync = Arc::new(leases.to_sync(authority, origin));
+        running.leases.insert(key, sync.clone());
+        thread::spawn(move || sync.run());
+    }
+    Ok(())
+}
+
+/// Stops the secondary and lease sync of the zone at `key`, if it has
+/// them.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn stop_zone(running: &mut Running, key: &ZoneKey) {
+    if let Some(secondary) = running.secondaries.remove(key) {
+        secondary.stop();
+    }
+    if let Some(sync) = running.leases.remove(key) {
+        sync.stop();
+    }
+}
+
+/// Stops serving zone `z`.
+#[cfg_attr(not(unix), allow(dead_code))]
+fn remove_zone(running: &mut Running, z: &ZoneConfig) {
+    let origin: DomainName = match z.name.parse() {
+        Ok(origin) => origin,
+        Err(_) => return,
+    };
+    stop_zone(running, &(z.view.clone(), origin.clone()));
+    let authority = match &z.view {
+        Some(view) => running.views.get(view),
+        None => Some(&running.authority),
+    };
+    if let Some(authority) = authority {
+        authority.remove(&origin);
+        authority.remove_backend(&origin);
+        #[cfg(feature = "dnssec")]
+        authority.remove_signer(&origin);
+    }
+}
+
+/// The handler answering requests with the running zones and forwarder,
+/// under the configured policies. Pools and the query log are started
+/// unless running already.
+fn handler(config: &Config, running: &mut Running) -> Result<Box<dyn Handler>, String> {
+    let mut ttl_rules: HashMap<Option<String>, Vec<TtlRule>> = HashMap::new();
+    for z in &config.effective_zones() {
+        let rules = z
+            .ttl_rules()
+            .map_err(|e| format!("zone {}: {}", z.name, e))?;
+        ttl_rules.entry(z.view.clone()).or_default().extend(rules);
+    }
+
+    // The zones of a view answer its clients in place of those outside
+    // views, each with the TTL rules of its zones.
+    let mut zone_handler = |zones: &Arc<Authority>, view: Option<&str>| -> Box<dyn Handler> {
+        let rules = ttl_rules
+            .remove(&view.map(str::to_string))
+            .unwrap_or_default();
+        if rules.is_empty() {
+            return Box::new(zones.clone());
+        }
+        Box::new(
+            zones
+                .clone()
+                .with(rules.into_iter().fold(TtlPolicy::new(), TtlPolicy::rule)),
+        )
+    };
+    let mut view_zones = Vec::new();
+    for v in &config.views {
+        let zones = running.views[&v.name].clone();
+        let view = v
+            .to_view(zone_handler(&zones, Some(&v.name)))
+            .map_err(|e| format!("view {}: {}", v.name, e))?;
+        view_zones.push((view, zones));
+    }
+    let authority = running.authority.clone();
+    let other_zones = (authority.clone(), zone_handler(&authority, None));
+
+    // Zones answer for what they hold, updates and transfers; everything
+    // else is forwarded if there is anywhere to forward it.
+    let upstream = running.forwarder.clone();
+    let mut handler: Box<dyn Handler> = Box::new(move |request: &Request| -> Option<Message> {
+        let msg = &request.message;
+        let qname = msg.question().map(|q| &q.name);
+        let holds = |zones: &Authority| qname.is_some_and(|n| zones.zone_origin(n).is_some());
+        let (zones, handler) = view_zones
+            .iter()
+            .find(|(view, zones)| view.matches(request) && holds(zones))
+            .map_or((&other_zones.0, &*other_zones.1), |(view, zones)| {
+                (zones, view.handler())
+            });
+        let local = msg.header.opcode != Opcode::QUERY
+            || request.is_transfer()
+            || upstream.routes().is_empty()
+            || holds(zones);
+        if local {
+            handler.handle(request)
+        } else {
+            upstream.handle(request)
+        }
+    });
+
+    if let Some(proxy) = &running.discovery_proxy {
+        handler = Box::new(handler.with(proxy.clone()));
+    }
+    if running.pools.is_none() && !config.pools.is_empty() {
+        let mut pools = Pools::new();
+        for p in &config.pools {
+            pools = pools.pool(p.to_pool().map_err(|e| format!("pools: {}", e))?);
+        }
+        let probed = pools.clone();
+        thread::spawn(move || probed.run());
+        running.pools = Some(pools);
+    }
+    if let Some(pools) = &running.pools {
+        handler = Box::new(handler.with(pools.clone()));
+    }
+    if !running.plugins.is_empty() {
+        handler = Box::new(handler.with(running.plugins.clone()));
+    }
+    if !config.blocklists.is_empty() {
+        handler = Box::new(handler.with(Arc::new(filter(config)?)));
+    }
+    if config.answer_order != AnswerOrderConfig::default() {
+        let ordering = config
+            .answer_order
+            .to_answer_ordering()
+            .map_err(|e| format!("answer-order: {}", e))?;
+        handler = Box::new(handler.with(ordering));
+    }
+    if !config.ttl_rules.is_empty() {
+        let policy = config.to_ttl_policy().map_err(|e| e.to_string())?;
+        handler = Box::new(handler.with(policy));
+    }
+    handler = Box::new(handler.with(config.identity.to_identity()));
+    let metrics = &running.metrics;
+    if let Some(rrl) = &config.rate_limit {
+        let mut rrl = rrl
+            .to_rate_limit()
+            .map_err(|e| format!("rate-limit: {}", e))?;
+        rrl.authority = Some(authority.clone());
+        rrl.metrics = Some(metrics.clone());
+        handler = Box::new(handler.with(rrl));
+    }
+    if let Some(quota) = &config.quota {
+        let mut quota = quota.to_quota().map_err(|e| format!("quota: {}", e))?;
+        quota.metrics = Some(metrics.clone());
+        handler = Box::new(handler.with(quota));
+    }
+    let acl = config
+        .acl
+        .to_access_control()
+        .map_err(|e| format!("acl: {}", e))?;
+    handler = Box::new(handler.with(acl));
+    if let (None, Some(log)) = (&running.query_log, &config.query_log) {
+        let logger = match log.format {
+            LogFormat::Json => Logger::create(&log.path, Format::JsonLines),
+            #[cfg(unix)]
+            LogFormat::Dnstap if log.socket => Logger::connect_dnstap(&log.path),
+            LogFormat::Dnstap => Logger::create(&log.path, Format::Dnstap),
+        }
+        .map_err(|e| format!("{}: {}", log.path.display(), e))?;
+        let mut query_log = QueryLog::new(Arc::new(logger));
+        query_log.sample_rate = log.sample_rate;
+        query_log.redaction = log.redaction();
+        running.query_log = Some(query_log);
+    }
+    if let Some(query_log) = &running.query_log {
+        handler = Box::new(handler.with(query_log.clone()));
+    }
+    if let (Some(telemetry), Some(recent)) = (&config.telemetry, &running.recent) {
+        let classify = Classify::new()
+            .sampler(recent.clone())
+            .sample_rate(telemetry.sample_rate);
+        handler = Box::new(handler.with(classify));
+    }
+    let mut instrument = Instrument::new(metrics.clone());
+    instrument.authority = Some(authority);
+    handler = Box::new(handler.with(instrument));
+    let enabled = running.debug.clone();
+    handler = Box::new(
+        handler.with(from_fn(move |request: &Request, inner: &dyn Handler| {
+            let resp = inner.handle(request);
+            if enabled.load(Ordering::Relaxed) {
+                let question = request.message.question().map(|q| q.to_string());
+                let rcode = resp.as_ref().map(|r| r.header.rcode.to_string());
+                eprintln!(
+                    "{} {:?} {} -> {}",
+                    request.src,
+                    request.protocol,
+                    question.as_deref().unwrap_or("-"),
+                    rcode.as_deref().unwrap_or("dropped")
+                );
+            }
+            resp
+        })),
+    );
+    Ok(handler)
+}
+
+/// The server a validated configuration describes.
+fn build(config: &Config) -> Result<Built, String> {
+    let keys = keyring(config)?;
+    let plugins = plugins(config)?;
+    let metrics = Arc::new(Metrics::new());
+    let forwarder = Arc::new(forwarder(config, &metrics, &plugins)?);
+    let discovery_proxy = match &config.discovery_proxy {
+        Some(proxy) => {
+            let proxy = discovery_proxy(proxy)?;
+            let running = proxy.clone();
+            thread::spawn(move || {
+                if let Err(e) = running.run() {
+                    eprintln!("discovery proxy stopped: {}", e);
+                }
+            });
+            Some(proxy)
+        }
+        None => None,
+    };
+    let mut running = Running {
+        config: config.clone(),
+        authority: Arc::new(Authority::new()),
+        views: config
+            .views
+            .iter()
+            .map(|v| (v.name.clone(), Arc::new(Authority::new())))
+            .collect(),
+        fast_path: Arc::new(RwLock::new(forwarder.clone())),
+        forwarder,
+        plugins,
+        secondaries: HashMap::new(),
+        leases: HashMap::new(),
+        pools: None,
+        discovery_proxy,
+        query_log: None,
+        recent: config
+            .telemetry
+            .as_ref()
+            .map(|t| Arc::new(RecentQueries::new(t.recent_queries))),
+        metrics,
+        debug: Arc::new(AtomicBool::new(false)),
+    };
+    for z in &config.effective_zones() {
+        add_zone(&mut running, config, z)?;
+    }
+    let handler = handler(config, &mut running)?;
+
+    let settings = &config.server;
+    let mut server = Server::new(handler);
+    server.set_keyring(keys);
+    server.set_tcp_idle_timeout(Duration::from_secs(settings.tcp_idle_timeout_secs));
+    server.set_max_tcp_connections(settings.max_tcp_connections);
+    server.set_max_transfers(settings.max_transfers);
+    server.set_udp_threads(settings.udp_threads);
+    let backend = settings.to_udp_backend();
+    if !backend.is_available() {
+        eprintln!("{:?} is not available; serving UDP with threads", backend);
+    }
+    server.set_udp_backend(backend);
+    server.set_drain_timeout(Duration::from_secs(settings.drain_timeout_secs));
+    server.set_response_policy(settings.to_response_policy());
+    server.set_compliance(config.compliance.to_compliance());
+    server.set_dso(settings.to_dso());
+    server.set_push(settings.to_push());
+    for l in config
+        .listeners
+        .iter()
+        .filter(|l| l.xdp_interface.is_none())
+    {
+        listen(&mut server, l, &running.metrics)
+            .map_err(|e| format!("cannot listen on {}: {}", l.address, e))?;
+    }
+    listen_xdp(&mut server, &config.listeners, &running.fast_path)?;
+    if let Some(api) = &config.api {
+        #[cfg(feature = "api")]
+        {
+            let mut handle = Api::new(api.token.clone(), running.authority.clone())
+                .forwarder(running.forwarder.clone());
+            // The API manages the zones outside views.
+            for ((view, origin), secondary) in &running.secondaries {
+                if view.is_none() {
+                    handle = handle.secondary(origin.clone(), secondary.clone());
+                }
+            }
+            server
+                .listen_api(api.address, handle)
+                .map_err(|e| format!("cannot listen on {}: {}", api.address, e))?;
+        }
+        #[cfg(not(feature = "api"))]
+        return Err(format!(
+            "cannot serve the API on {}: this binary was built without it",
+            api.address
+        ));
+    }
+    Ok(Built {
+        server,
+        running: Arc::new(Mutex::new(running)),
+    })
+}
+
+const NO_TLS: &str =
+    "this binary has no TLS implementation; terminate TLS in a proxy and use an http listener";
+
+fn listen(
+    server: &mut Server,
+    listener: &ListenerConfig,
+    metrics: &Arc<Metrics>,
+) -> Result<(), String> {
+    let addr = listener.address;
+    let mut limits = Limits::default();
+    if let Some(max) = listener.max_connections {
+        limits.max_connections = max;
+    }
+    limits.proxy_protocol = listener.proxy_protocol;
+    let result = match (listener.transport, listener.workers) {
+        (Transport::Dns, Some(workers)) => server.listen_workers(addr, workers).map(drop),
+        (Transport::Dns, None) if listener.proxy_protocol => server
+            .listen_udp(addr)
+            .and_then(|udp| server.listen_tcp_with(udp, limits))
+            .map(drop),
+        (Transport::Dns, None) => server.listen(addr),
+        (Transport::Udp, _) => server.listen_udp(addr).map(drop),
+        (Transport::Tcp, _) if listener.proxy_protocol => {
+            server.listen_tcp_with(addr, limits).map(drop)
+        }
+        (Transport::Tcp, _) => server.listen_tcp(addr).map(drop),
+        (Transport::Http, _) => server.listen_http(addr, limits).map(drop),
+        (Transport::Metrics, _) => server.listen_metrics(addr, metrics.clone()).map(drop),
+        (Transport::Tls, _) | (Transport::Https, _) => return Err(NO_TLS.into()),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// Serves the listeners with an XDP interface, one fast path for each
+/// interface.
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+fn listen_xdp(
+    server: &mut Server,
+    listeners: &[ListenerConfig],
+    forwarder: &SharedForwarder,
+) -> Result<(), String> {
+    let mut interfaces: Vec<&str> = listeners
+        .iter()
+        .filter_map(|l| l.xdp_interface.as_deref())
+        .collect();
+    interfaces.sort_unstable();
+    interfaces.dedup();
+    for interface in interfaces {
+        let group: Vec<&ListenerConfig> = listeners
+            .iter()
+            .filter(|l| l.xdp_interface.as_deref() == Some(interface))
+            .collect();
+        let addrs: Vec<_> = group.iter().map(|l| l.address).collect();
+        let queues = group.iter().filter_map(|l| l.xdp_queues).max().unwrap_or(1);
+        server
+            .listen_xdp(&addrs, interface, queues, forwarder.clone())
+            .map_err(|e| format!("cannot attach XDP to {}: {}", interface, e))?;
+        for l in group.iter().filter(|l| l.transport == Transport::Dns) {
+            server
+                .listen_tcp(l.address)
+                .map_err(|e| format!("cannot listen on {}: {}", l.address, e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(feature = "xdp", target_os = "linux")))]
+fn listen_xdp(
+    _: &mut Server,
+    listeners: &[ListenerConfig],
+    _: &SharedForwarder,
+) -> Result<(), String> {
+    match listeners.iter().find_map(|l| l.xdp_interface.as_deref()) {
+        Some(interface) => Err(format!(
+            "cannot attach XDP to {}: this binary has no XDP support",
+            interface
+        )),
+        None => Ok(()),
+    }
+}
+
+fn filter(config: &Config) -> Result<Filter, String> {
+    let mut filter = Filter::new();
+    for b in &config.blocklists {
+        let source = b.path.display().to_string();
+        let text = fs::read_to_string(&b.path).map_err(|e| format!("{}: {}", source, e))?;
+        let action = match b.action {
+            BlockAction::Nxdomain => Action::Nxdomain,
+            BlockAction::Nodata => Action::Nodata,
+            BlockAction::Sinkhole => Action::sinkhole(),
+            BlockAction::Drop => Action::Drop,
+        };
+        match b.format {
+            BlocklistFormat::Hosts => {
+                filter.load_hosts(&text, &action, &source);
+            }
+            BlocklistFormat::Domains => {
+                filter.load_domains(&text, &action, &source);
+            }
+            BlocklistFormat::Rpz => {
+                let origin: DomainName = b
+                    .origin
+                    .as_deref()
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|e| format!("{}: {}", source, e))?;
+                filter
+                    .load_rpz(&text, &origin, &source)
+                    .map_err(|e| format!("{}: {}", source, e))?;
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// A discovery proxy with a socket bound on each of its links.
+fn discovery_proxy(config: &DiscoveryProxyConfig) -> Result<DiscoveryProxy, String> {
+    let mut proxy = DiscoveryProxy::new()
+        .wait(Duration::from_millis(config.wait_ms))
+        .max_ttl(config.max_ttl);
+    for (i, l) in config.links.iter().enumerate() {
+        let (domain, interface) = l
+            .to_link()
+            .map_err(|e| format!("discovery-proxy.links[{}].{}", i, e))?;
+        let link = ProxyLink::bind(domain.clone(), interface)
+            .map_err(|e| format!("cannot listen for mDNS for {}: {}", domain, e))?;
+        proxy = proxy.link(link);
+    }
+    Ok(proxy)
+}
+
+/// Takes up `config` in place of the running configuration, touching only
+/// what differs: listeners added or removed are opened or closed, zones
+/// added, removed or changed are loaded or dropped, the forwarder is
+/// rebuilt only if its routes or scripts changed, keeping the caches of
+/// the routes that did not, and the handler is replaced. Rate limits and
+/// quotas start afresh. Sections only a restart takes up keep their
+/// running settings, and are reported.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn reconfigure(
+    running: &mut Running,
+    control: &Control,
+    mut config: Config,
+) -> Result<String, String> {
+    let diff = running.config.diff(&config);
+    if diff.is_empty() {
+        return Ok("no changes".into());
+    }
+    let old = running.config.clone();
+    let restart = diff.needs_restart();
+    config.server = old.server.clone();
+    config.cache_persistence = old.cache_persistence.clone();
+    config.discovery_proxy = old.discovery_proxy.clone();
+    config.telemetry = old.telemetry.clone();
+    config.compliance = old.compliance.clone();
+    config.control = old.control.clone();
+    config.api = old.api.clone();
+    config.listeners = old
+        .listeners
+        .iter()
+        .filter(|l| !l.is_reconfigurable())
+        .chain(config.listeners.iter().filter(|l| l.is_reconfigurable()))
+        .cloned()
+        .collect();
+
+    // Everything that can fail without touching the running server first.
+    let keys = if diff.changed("keys") {
+        Some(keyring(&config)?)
+    } else {
+        None
+    };
+    let rebuilt = if diff.changed("forwarders") || diff.changed("scripts") {
+        let plugins = plugins(&config)?;
+        let forwarder = forwarder(&config, &running.metrics, &plugins)?;
+        Some((forwarder, plugins))
+    } else {
+        None
+    };
+
+    let mut report = Vec::new();
+    let mut errors = Vec::new();
+    for l in diff
+        .listeners_removed
+        .iter()
+        .filter(|l| l.is_reconfigurable())
+    {
+        control.close(l.address);
+    }
+    for l in diff
+        .listeners_added
+        .iter()
+        .filter(|l| l.is_reconfigurable())
+    {
+        let opened = match l.transport {
+            Transport::Udp => control.listen_udp(l.address).map(drop),
+            Transport::Tcp => control.listen_tcp(l.address).map(drop),
+            _ => control.listen(l.address),
+        };
+        if let Err(e) = opened {
+            errors.push(format!("cannot listen on {}: {}", l.address, e));
+            config.listeners.retain(|kept| kept != l);
+        }
+    }
+    if !diff.listeners_added.is_empty() || !diff.listeners_removed.is_empty() {
+        report.push(format!(
+            "listeners: {} added, {} removed",
+            diff.listeners_added.len(),
+            diff.listeners_removed.len()
+        ));
+    }
+
+    for z in &diff.zones_removed {
+        remove_zone(running, z);
+    }
+    running
+        .views
+        .retain(|name, _| config.find_view(name).is_some());
+    for v in &config.views {
+        running
+            .views
+            .entry(v.name.clone())
+            .or_insert_with(|| Arc::new(Authority::new()));
+    }
+    for z in diff.zones_added.iter().chain(&diff.zones_changed) {
+        let old_zone = old.effective_zone(&z.name, z.view.as_deref());
+        if let Some(old_zone) = &old_zone {
+            if old_zone.kind != z.kind {
+                remove_zone(running, old_zone);
+            } else if let Ok(origin) = z.name.parse() {
+                stop_zone(running, &(z.view.clone(), origin));
+            }
+        }
+        if let Err(e) = add_zone(running, &config, z) {
+            errors.push(e);
+            // Left as it was, so that reconfiguring again retries it.
+            let at = config
+                .zones
+                .iter()
+                .position(|c| c.name == z.name && c.view == z.view);
+            let was = old
+                .zones
+                .iter()
+                .find(|c| c.name == z.name && c.view == z.view);
+            match (at, was) {
+                (Some(at), Some(was)) => config.zones[at] = was.clone(),
+                (Some(at), None) => {
+                    config.zones.remove(at);
+                }
+                _ => {}
+            }
+        }
+    }
+    if diff.changed("zones") || diff.changed("zone-defaults") || diff.changed("views") {
+        report.push(format!(
+            "zones: {} added, {} removed, {} changed",
+            diff.zones_added.len(),
+            diff.zones_removed.len(),
+            diff.zones_changed.len()
+        ));
+    }
+
+    if let Some((forwarder, plugins)) = rebuilt {
+        let mut kept = 0;
+        for route in forwarder.routes() {
+            let suffix = route.suffix();
+            let unchanged = !diff
+                .routes_added
+                .iter()
+                .chain(&diff.routes_changed)
+                .any(|r| r.suffix.parse().ok().as_ref() == Some(suffix));
+            let old_route = running
+                .forwarder
+                .routes()
+                .iter()
+                .find(|r| r.suffix() == suffix);
+            if let (true, Some(old_route)) = (unchanged, old_route) {
+                for entry in old_route.cache_entries(None) {
+                    route.restore(entry);
+                }
+                kept += 1;
+            }
+        }
+        report.push(format!(
+            "forwarders: rebuilt, kept the caches of {} routes",
+            kept
+        ));
+        running.forwarder = Arc::new(forwarder);
+        *running.fast_path.write().unwrap() = running.forwarder.clone();
+        running.plugins = plugins;
+    }
+    if diff.changed("pools") {
+        if let Some(pools) = running.pools.take() {
+            pools.stop();
+        }
+    }
+    if diff.changed("query-log") {
+        running.query_log = None;
+    }
+    match handler(&config, running) {
+        Ok(handler) => control.set_handler(handler),
+        Err(e) => {
+            // The policies in effect stay, and are retried next time.
+            errors.push(e);
+            config.blocklists = old.blocklists.clone();
+            config.answer_order = old.answer_order.clone();
+            config.ttl_rules = old.ttl_rules.clone();
+            config.identity = old.identity.clone();
+            config.rate_limit = old.rate_limit.clone();
+            config.quota = old.quota.clone();
+            config.acl = old.acl.clone();
+            config.query_log = old.query_log.clone();
+            config.pools = old.pools.clone();
+        }
+    }
+    if let Some(keys) = keys {
+        control.set_keyring(keys);
+    }
+    running.config = config;
+
+    let others: Vec<&str> = diff
+        .sections
+        .iter()
+        .copied()
+        .filter(|s| {
+            !restart.contains(s)
+                && !["listeners", "zones", "zone-defaults", "views", "forwarders"].contains(s)
+        })
+        .collect();
+    if !others.is_empty() {
+        report.push(format!("updated: {}", others.join(", ")));
+    }
+    if !restart.is_empty() {
+        report.push(format!("needs a restart: {}", restart.join(", ")));
+    }
+    if errors.is_empty() {
+        Ok(report.join("\n"))
+    } else {
+        Err(errors.join("\n"))
+    }
+}
@@ -0,0 +1,448 @@
+//! `mairu-dns zone`: checking, converting, comparing and signing zone
+//! files, and giving the DS records of a zone's keys and the SSHFP records
+//! of a host's.
+
+use std::collections::HashSet;
+use std::fs;
+
+use mairudns::compliance::Compliance;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::zone::{serial_cmp, Zone};
+
+use super::option_value;
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let rest = args.get(1..).unwrap_or(&[]);
+    match args.first().map(String::as_str) {
+        Some("check") => check(rest),
+        Some("convert") => convert(rest),
+        Some("compile") => compile(rest),
+        Some("diff") => diff(rest),
+        Some("sign") => sign(rest),
+        Some("ds") => ds(rest),
+        Some("sshfp") => sshfp(rest),
+        Some(other) => Err(format!("unknown zone command {:?}", other)),
+        None => {
+            Err("zone needs a command: check, convert, compile, diff, sign, ds or sshfp".into())
+        }
+    }
+}
+
+fn load(origin: &str, path: &str) -> Result<Zone, String> {
+    load_with(origin, path, &Compliance::default())
+}
+
+fn load_with(origin: &str, path: &str, compliance: &Compliance) -> Result<Zone, String> {
+    let origin: DomainName = origin
+        .parse()
+        .map_err(|e| format!("invalid origin {:?}: {}", origin, e))?;
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Zone::from_master_with(origin, &text, compliance).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn positional<'a>(args: &'a [String], count: usize, usage: &str) -> Result<&'a [String], String> {
+    match args.get(..count) {
+        Some(values) if values.iter().all(|v| !v.starts_with('-')) => Ok(values),
+        _ => Err(format!("usage: mairu-dns zone {}", usage)),
+    }
+}
+
+/// Problems that would keep the zone from being served properly.
+fn problems(zone: &Zone) -> Vec<String> {
+    let origin = zone.origin();
+    let mut problems = Vec::new();
+    if zone.soa().is_none() {
+        problems.push(format!("no SOA record at {}", origin));
+    }
+    let apex_ns = zone.rrset(origin, RecordType::NS).unwrap_or(&[]);
+    if apex_ns.is_empty() {
+        problems.push(format!("no NS records at {}", origin));
+    }
+    for ns in apex_ns {
+        let target = match &ns.rdata {
+            RData::Ns(target) => target,
+            _ => continue,
+        };
+        let has_address = zone.rrset(target, RecordType::A).is_some()
+            || zone.rrset(target, RecordType::AAAA).is_some();
+        if target.is_subdomain_of(origin) && !has_address {
+            problems.push(format!("name server {} has no address records", target));
+        }
+    }
+    if let Err(e) = zone.verify_zonemd() {
+        problems.push(e.to_string());
+    }
+    problems
+}
+
+fn check(args: &[String]) -> Result<(), String> {
+    let values = positional(args, 2, "check <origin> <file> [--strict]")?;
+    let mut compliance = Compliance::default();
+    for arg in &args[2..] {
+        match arg.as_str() {
+            "--strict" => compliance = Compliance::STRICT,
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+    }
+    let zone = load_with(&values[0], &values[1], &compliance)?;
+    let problems = problems(&zone);
+    for problem in &problems {
+        eprintln!("{}: {}", values[1], problem);
+    }
+    if !problems.is_empty() {
+        return Err(format!("{} has {} problems", values[1], problems.len()));
+    }
+    println!(
+        "zone {}/{}: loaded serial {}, {} records",
+        zone.origin(),
+        zone.class(),
+        zone.serial().unwrap_or(0),
+        zone.len()
+    );
+    println!("OK");
+    Ok(())
+}
+
+/// The zone in canonical master file form: absolute names, explicit TTLs
+/// and classes, the SOA first and the rest in canonical name order.
+fn canonical(zone: &Zone) -> String {
+    let mut out = format!("$ORIGIN {}\n", zone.origin());
+    let soa = zone.soa();
+    for rr in soa
+        .into_iter()
+        .chain(zone.records().filter(|rr| Some(*rr) != soa))
+    {
+        out.push_str(&rr.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+fn convert(args: &[String]) -> Result<(), String> {
+    let values = positional(args, 2, "convert <origin> <file> [-o <output>]")?;
+    let mut output = None;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-o" => output = Some(option_value(args, &mut i)?),
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+        i += 1;
+    }
+    let text = canonical(&load(&values[0], &values[1])?);
+    match output {
+        Some(path) => fs::write(path, text).map_err(|e| format!("{}: {}", path, e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+fn compile(args: &[String]) -> Result<(), String> {
+    let values = positional(args, 2, "compile <origin> <file> -o <output>")?;
+    let mut output = None;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-o" => output = Some(option_value(args, &mut i)?),
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+        i += 1;
+    }
+    let output = output.ok_or("usage: mairu-dns zone compile <origin> <file> -o <output>")?;
+    let zone = load(&values[0], &values[1])?;
+    mairudns::zone::compile(&zone, output).map_err(|e| format!("{}: {}", output, e))?;
+    println!(
+        "zone {}/{}: compiled {} records under {} names",
+        zone.origin(),
+        zone.class(),
+        zone.len(),
+        zone.names().count()
+    );
+    Ok(())
+}
+
+fn diff(args: &[String]) -> Result<(), String> {
+    let values = positional(args, 3, "diff <origin> <old-file> <new-file>")?;
+    let old = load(&values[0], &values[1])?;
+    let new = load(&values[0], &values[2])?;
+    let old_records: HashSet<&Record> = old.records().collect();
+    let new_records: HashSet<&Record> = new.records().collect();
+    let mut changed = false;
+    for rr in old.records().filter(|rr| !new_records.contains(rr)) {
+        println!("-{}", rr);
+        changed = true;
+    }
+    for rr in new.records().filter(|rr| !old_records.contains(rr)) {
+        println!("+{}", rr);
+        changed = true;
+    }
+    if let (true, Some(a), Some(b)) = (changed, old.serial(), new.serial()) {
+        if serial_cmp(b, a) != Some(std::cmp::Ordering::Greater) {
+            eprintln!(
+                "warning: the serial did not increase ({} to {}), so secondaries will not pick up the change",
+                a, b
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The owner and DNSKEY record of a BIND-format `.key` file.
+#[cfg(feature = "dnssec")]
+fn read_dnskey(path: &str) -> Result<(DomainName, mairudns::dnssec::Dnskey), String> {
+    let public = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    public
+        .lines()
+        .map(|line| line.split(';').next().unwrap_or(""))
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let at = fields
+                .iter()
+                .position(|f| f.eq_ignore_ascii_case("DNSKEY"))?;
+            let owner = fields.first()?.parse().ok()?;
+            Some((owner, fields[at + 1..].join(" ").parse().ok()?))
+        })
+        .ok_or_else(|| format!("{}: no DNSKEY record", path))
+}
+
+/// The DNSKEY records of a file of them, such as another signer's key set
+/// as `dig` prints it or a `.key` file.
+#[cfg(feature = "dnssec")]
+pub fn read_dnskeys(path: &str) -> Result<Vec<mairudns::dnssec::Dnskey>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut keys = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .split_whitespace()
+            .collect();
+        if let Some(at) = fields.iter().position(|f| f.eq_ignore_ascii_case("DNSKEY")) {
+            let key = fields[at + 1..]
+                .join(" ")
+                .parse()
+                .map_err(|_| format!("{}:{}: invalid DNSKEY record", path, n + 1))?;
+            keys.push(key);
+        }
+    }
+    if keys.is_empty() {
+        return Err(format!("{}: no DNSKEY record", path));
+    }
+    Ok(keys)
+}
+
+/// Reads the key pair `K<zone>+<alg>+<tag>` from its `.key` and `.private`
+/// files, given either path or the name they share.
+#[cfg(feature = "dnssec")]
+pub fn read_key(path: &str) -> Result<mairudns::dnssec::SigningKey, String> {
+    use mairudns::dnssec::SigningKey;
+
+    let base = path
+        .strip_suffix(".key")
+        .or_else(|| path.strip_suffix(".private"))
+        .unwrap_or(path);
+    let public_path = format!("{}.key", base);
+    let private_path = format!("{}.private", base);
+    let (_, dnskey) = read_dnskey(&public_path)?;
+    let private =
+        fs::read_to_string(&private_path).map_err(|e| format!("{}: {}", private_path, e))?;
+    let key = SigningKey::from_bind(dnskey.flags, &private)
+        .map_err(|e| format!("{}: {}", private_path, e))?;
+    if *key.dnskey() != dnskey {
+        return Err(format!(
+            "{} does not hold the private key of {}",
+            private_path, public_path
+        ));
+    }
+    Ok(key)
+}
+
+#[cfg(feature = "dnssec")]
+fn ds(args: &[String]) -> Result<(), String> {
+    use mairudns::dnssec::{ds_records, DigestType, Ds};
+
+    let usage = "ds <key>... [-d sha256|sha384]...";
+    let mut paths = Vec::new();
+    let mut digest_types = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-d" => {
+                let value = option_value(args, &mut i)?;
+                digest_types.push(match value.to_ascii_lowercase().as_str() {
+                    "sha256" | "2" => DigestType::SHA256,
+                    "sha384" | "4" => DigestType::SHA384,
+                    _ => return Err(format!("unsupported digest type {:?}", value)),
+                });
+            }
+            other if other.starts_with('-') => {
+                return Err(format!("unexpected argument {:?}", other))
+            }
+            path => paths.push(path),
+        }
+        i += 1;
+    }
+    if paths.is_empty() {
+        return Err(format!("usage: mairu-dns zone {}", usage));
+    }
+    if digest_types.is_empty() {
+        digest_types.push(DigestType::SHA256);
+    }
+    for path in paths {
+        let public_path = match path.strip_suffix(".private") {
+            Some(base) => format!("{}.key", base),
+            None if path.ends_with(".key") => path.to_string(),
+            None => format!("{}.key", path),
+        };
+        let (owner, dnskey) = read_dnskey(&public_path)?;
+        let records = ds_records(&owner, &[dnskey], &digest_types, 0);
+        if records.is_empty() {
+            return Err(format!("{}: not a key-signing key", public_path));
+        }
+        for rr in records {
+            if let Some(ds) = Ds::from_rdata(&rr.rdata) {
+                println!("{}\tIN\tDS\t{}", rr.name, ds);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "dnssec"))]
+fn ds(_: &[String]) -> Result<(), String> {
+    Err("this binary has no DNSSEC support".into())
+}
+
+#[cfg(feature = "dnssec")]
+fn sshfp(args: &[String]) -> Result<(), String> {
+    use mairudns::dnssec::sshfp_records;
+
+    let (host, paths) = match args.split_first() {
+        Some((host, paths)) if !paths.is_empty() => (host, paths),
+        _ => return Err("usage: mairu-dns zone sshfp <host> <public-key>...".into()),
+    };
+    let host: DomainName = host
+        .parse()
+        .map_err(|e| format!("invalid host {:?}: {}", host, e))?;
+    for path in paths {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        for line in text
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.starts_with('#'))
+        {
+            let records = sshfp_records(line)
+                .ok_or_else(|| format!("{}: not an OpenSSH public key", path))?;
+            for fp in records {
+                println!("{}\tIN\tSSHFP\t{}", host, fp);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "dnssec"))]
+fn sshfp(_: &[String]) -> Result<(), String> {
+    Err("this binary has no DNSSEC support".into())
+}
+
+#[cfg(feature = "dnssec")]
+fn sign(args: &[String]) -> Result<(), String> {
+    use std::time::Duration;
+
+    use mairudns::dnssec::{foreign_keys, sign_zone, Denial, SigningPolicy};
+    use mairudns::encoding;
+    use mairudns::rr::Record;
+    use mairudns::zone::{parse_ttl, Zonemd};
+
+    let usage = "sign <origin> <file> -k <key>... [-o <output>] [--nsec3] [--iterations <n>] \
+                 [--salt <hex>] [--opt-out] [--legacy-nsec3] [--validity <time>] \
+                 [--jitter <time>] [--zonemd sha384|sha512] [--foreign <file>]...";
+    let values = positional(args, 2, usage)?;
+    let mut keys = Vec::new();
+    let mut foreign = Vec::new();
+    let mut output = None;
+    let mut policy = SigningPolicy::default();
+    let (mut nsec3, mut iterations, mut salt, mut opt_out) = (false, 0, Vec::new(), false);
+    let duration = |value: &str| {
+        parse_ttl(value)
+            .map(|secs| Duration::from_secs(u64::from(secs)))
+            .ok_or_else(|| format!("invalid time {:?}", value))
+    };
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-k" => keys.push(read_key(option_value(args, &mut i)?)?),
+            "--foreign" => foreign.extend(read_dnskeys(option_value(args, &mut i)?)?),
+            "-o" => output = Some(option_value(args, &mut i)?),
+            "--nsec3" => nsec3 = true,
+            "--opt-out" => opt_out = true,
+            "--legacy-nsec3" => {
+                policy.max_nsec3_iterations = u16::MAX;
+                policy.nsec3_salt = true;
+            }
+            "--iterations" => {
+                let value = option_value(args, &mut i)?;
+                iterations = value
+                    .parse()
+                    .map_err(|_| format!("invalid iteration count {:?}", value))?;
+            }
+            "--salt" => {
+                let value = option_value(args, &mut i)?;
+                salt = match value {
+                    "-" => Vec::new(),
+                    hex => encoding::hex_decode(hex)
+                        .map_err(|_| format!("invalid salt {:?}", value))?,
+                };
+            }
+            "--zonemd" => {
+                let value = option_value(args, &mut i)?;
+                policy
+                    .zonemd
+                    .push(match value.to_ascii_lowercase().as_str() {
+                        "sha384" | "1" => Zonemd::SHA384,
+                        "sha512" | "2" => Zonemd::SHA512,
+                        _ => return Err(format!("unsupported ZONEMD hash {:?}", value)),
+                    });
+            }
+            "--validity" => policy.validity = duration(option_value(args, &mut i)?)?,
+            "--jitter" => policy.jitter = duration(option_value(args, &mut i)?)?,
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+        i += 1;
+    }
+    if keys.is_empty() {
+        return Err(format!("usage: mairu-dns zone {}", usage));
+    }
+    if nsec3 {
+        policy.denial = Denial::Nsec3 {
+            iterations,
+            salt,
+            opt_out,
+        };
+    }
+    let zone = load(&values[0], &values[1])?;
+    let foreign: Vec<Record> = foreign
+        .into_iter()
+        .map(|key| Record::new(zone.origin().clone(), 0, key.to_rdata()))
+        .collect();
+    let local: Vec<_> = keys.iter().map(|k| k.dnskey().clone()).collect();
+    policy.foreign = foreign_keys(&foreign, &local);
+    let signed = sign_zone(&zone, &keys, &policy).map_err(|e| format!("{}: {}", values[1], e))?;
+    let text = canonical(&signed);
+    match output {
+        Some(path) => fs::write(path, text).map_err(|e| format!("{}: {}", path, e)),
+        None => {
+            print!("{}", text);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "dnssec"))]
+fn sign(_: &[String]) -> Result<(), String> {
+    Err("this binary has no DNSSEC support".into())
+}
@@ -0,0 +1,261 @@
+//! Cached values that expire, and records whose TTLs count down.
+//!
+//! A record's TTL says how long it may be kept from when it was received
+//! (RFC 1035 §3.2.1), so one handed on from a cache carries what is left
+//! of it: the original TTL less the whole seconds it has been held, never
+//! below zero (RFC 2181 §8). [`Expiring`] keeps a value with when it was
+//! stored and when it expires, and gives its records counted down to any
+//! instant without touching what is stored:
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//! use mairudns::cache::{Expiring, RemainingTtl};
+//! use mairudns::rr::{RData, Record};
+//!
+//! let stored = Instant::now();
+//! let rr = Record::new("www.example.com".parse().unwrap(), 300, RData::A([192, 0, 2, 1].into()));
+//! let cached = vec![Expiring::for_ttl(rr, stored)];
+//!
+//! let later = stored + Duration::from_secs(120);
+//! let records: Vec<Record> = cached.iter().with_remaining_ttl(later).collect();
+//! assert_eq!(records[0].ttl, 180);
+//! assert_eq!(cached[0].value().ttl, 300);
+//! ```
+//!
+//! A [`NamePattern`] picks out the cached names to inspect or flush: one
+//! name, or a name and everything below it.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::message::Message;
+use crate::name::{self, DomainName};
+use crate::rr::Record;
+
+/// What is left at `now` of a TTL of `ttl` seconds that started counting
+/// at `stored`: less every whole second since, and zero once it has run
+/// out.
+pub fn remaining_ttl(ttl: u32, stored: Instant, now: Instant) -> u32 {
+    let held = now.saturating_duration_since(stored).as_secs();
+    ttl.saturating_sub(held.min(u64::from(u32::MAX)) as u32)
+}
+
+/// A value with when it was stored and when it expires.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expiring<V> {
+    value: V,
+    stored: Instant,
+    expires: Instant,
+}
+
+impl<V> Expiring<V> {
+    pub fn new(value: V, stored: Instant, expires: Instant) -> Expiring<V> {
+        Expiring {
+            value,
+            stored,
+            expires,
+        }
+    }
+
+    /// `value` stored at `stored` and kept for `ttl`.
+    pub fn kept_for(value: V, stored: Instant, ttl: Duration) -> Expiring<V> {
+        Expiring::new(value, stored, stored + ttl)
+    }
+
+    /// The value, expired or not.
+    pub fn value(&self) -> &V {
+        &self.value
+    }
+
+    pub fn into_value(self) -> V {
+        self.value
+    }
+
+    pub fn stored(&self) -> Instant {
+        self.stored
+    }
+
+    pub fn expires(&self) -> Instant {
+        self.expires
+    }
+
+    /// How long the value was to be kept when it was stored.
+    pub fn lifetime(&self) -> Duration {
+        self.expires.saturating_duration_since(self.stored)
+    }
+
+    /// How long the value has been held at `now`.
+    pub fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.stored)
+    }
+
+    /// How long the value is still good for at `now`.
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.expires.saturating_duration_since(now)
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.expires
+    }
+
+    /// The value, unless it has expired by `now`.
+    pub fn get(&self, now: Instant) -> Option<&V> {
+        (!self.is_expired(now)).then_some(&self.value)
+    }
+
+    /// The records of the value, with their TTLs counted down to `now`;
+    /// none once it has expired.
+    pub fn records<'a>(&'a self, now: Instant) -> impl Iterator<Item = Record> + 'a
+    where
+        &'a V: IntoIterator<Item = &'a Record>,
+    {
+        let stored = self.stored;
+        self.get(now)
+            .into_iter()
+            .flatten()
+            .map(move |rr| counted_down(rr, stored, now))
+    }
+}
+
+impl Expiring<Record> {
+    /// `record` stored at `stored`, expiring when its TTL runs out.
+    pub fn for_ttl(record: Record, stored: Instant) -> Expiring<Record> {
+        let ttl = Duration::from_secs(u64::from(record.ttl));
+        Expiring::kept_for(record, stored, ttl)
+    }
+
+    /// The record with its TTL counted down to `now`, unless it has
+    /// expired.
+    pub fn record(&self, now: Instant) -> Option<Record> {
+        self.get(now).map(|rr| counted_down(rr, self.stored, now))
+    }
+}
+
+impl Expiring<Message> {
+    /// The message with the TTL of every record counted down to `now`,
+    /// whether or not it has expired.
+    pub fn aged(&self, now: Instant) -> Message {
+        let held = self.age(now).as_secs();
+        let mut msg = self.value.clone();
+        age(&mut msg, held.min(u64::from(u32::MAX)) as u32);
+        msg
+    }
+}
+
+/// Takes `secs` off the TTL of every record of `msg`, as when it has been
+/// held that long some other way than in an [`Expiring`].
+pub fn age(msg: &mut Message, secs: u32) {
+    for rr in msg
+        .answers
+        .iter_mut()
+        .chain(&mut msg.authority)
+        .chain(&mut msg.additional)
+    {
+        rr.ttl = rr.ttl.saturating_sub(secs);
+    }
+}
+
+fn counted_down(rr: &Record, stored: Instant, now: Instant) -> Record {
+    Record {
+        ttl: remaining_ttl(rr.ttl, stored, now),
+        ..rr.clone()
+    }
+}
+
+/// Counts down the TTLs of cached records as they are iterated over.
+pub trait RemainingTtl<'a>: Iterator<Item = &'a Expiring<Record>> + Sized {
+    /// The records that have not expired by `now`, with their TTLs
+    /// counted down to it.
+    fn with_remaining_ttl(self, now: Instant) -> WithRemainingTtl<Self> {
+        WithRemainingTtl { inner: self, now }
+    }
+}
+
+impl<'a, I: Iterator<Item = &'a Expiring<Record>>> RemainingTtl<'a> for I {}
+
+/// The iterator of [`RemainingTtl::with_remaining_ttl`].
+#[derive(Clone, Debug)]
+pub struct WithRemainingTtl<I> {
+    inner: I,
+    now: Instant,
+}
+
+impl<'a, I: Iterator<Item = &'a Expiring<Record>>> Iterator for WithRemainingTtl<I> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        let now = self.now;
+        self.inner.by_ref().find_map(|e| e.record(now))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+/// Which names of a cache to inspect or flush.
+///
+/// Written as a name for that name only, or with a leading `*.` for the
+/// name and every name below it; `*` alone matches every name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NamePattern {
+    pub name: DomainName,
+    /// Whether the names below `name` match too.
+    pub subtree: bool,
+}
+
+impl NamePattern {
+    /// Matches `name` only.
+    pub fn exact(name: DomainName) -> NamePattern {
+        NamePattern {
+            name,
+            subtree: false,
+        }
+    }
+
+    /// Matches `name` and every name below it.
+    pub fn subtree(name: DomainName) -> NamePattern {
+        NamePattern {
+            name,
+            subtree: true,
+        }
+    }
+
+    /// Matches every name.
+    pub fn any() -> NamePattern {
+        NamePattern::subtree(DomainName::root())
+    }
+
+    pub fn matches(&self, name: &DomainName) -> bool {
+        if self.subtree {
+            name.is_subdomain_of(&self.name)
+        } else {
+            *name == self.name
+        }
+    }
+}
+
+impl FromStr for NamePattern {
+    type Err = name::Error;
+
+    fn from_str(s: &str) -> Result<NamePattern, name::Error> {
+        match s {
+            "*" => Ok(NamePattern::any()),
+            _ => match s.strip_prefix("*.") {
+                Some(name) => name.parse().map(NamePattern::subtree),
+                None => s.parse().map(NamePattern::exact),
+            },
+        }
+    }
+}
+
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.subtree, self.name.is_root()) {
+            (true, true) => f.write_str("*"),
+            (true, false) => write!(f, "*.{}", self.name),
+            (false, _) => write!(f, "{}", self.name),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use crate::dso::{self, DsoMessage, DsoType, Keepalive};
+use crate::message::{Message, Rcode};
+use crate::push::{self, Change, Subscription};
+use crate::rr::Record;
+use crate::server::{Stream, ALPN_DOT};
+
+use super::{
+    check_alpn, connect_tcp, is_response_to, read_framed, write_framed, Error, Proxy, TlsConnector,
+};
+
+/// The client side of a DSO session (RFC 8490) over TCP or TLS: one
+/// connection that queries share, kept open for as long as the server's
+/// timeouts allow.
+///
+/// The session does not keep itself alive. Callers check
+/// [`keepalive_due`](DsoSession::keepalive_due) and send
+/// [`keepalive`](DsoSession::keepalive) in time, and close the session
+/// once [`inactivity_expired`](DsoSession::inactivity_expired).
+///
+/// On servers that offer DNS Push Notifications (RFC 8765), the session
+/// can [`subscribe`](DsoSession::subscribe) to records and then wait for
+/// their changes with [`next_push`](DsoSession::next_push).
+pub struct DsoSession {
+    stream: Box<dyn Stream>,
+    /// The TCP connection under `stream`, for its timeouts.
+    socket: TcpStream,
+    timeouts: Keepalive,
+    last_sent: Instant,
+    last_activity: Instant,
+    retry_delay: Option<Duration>,
+    /// The IDs of the SUBSCRIBE requests of active subscriptions.
+    subscriptions: Vec<u16>,
+    /// Changes pushed but not yet taken.
+    pushed: Vec<Change>,
+}
+
+impl DsoSession {
+    /// Connects to `server` over TCP, through `proxy` if there is one, and
+    /// establishes a session asking for the timeouts in `requested`.
+    pub fn connect(
+        server: SocketAddr,
+        proxy: Option<&Proxy>,
+        requested: &Keepalive,
+        timeout: Duration,
+    ) -> Result<DsoSession, Error> {
+        let stream = connect_tcp(server, proxy, timeout)?;
+        let socket = stream.try_clone()?;
+        DsoSession::establish(Box::new(stream), socket, requested, timeout)
+    }
+
+    /// Connects to `server` over TLS (RFC 7858) and establishes a session
+    /// as [`connect`](DsoSession::connect) does.
+    pub fn connect_tls(
+        connector: &dyn TlsConnector,
+        server: SocketAddr,
+        proxy: Option<&Proxy>,
+        server_name: &str,
+        requested: &Keepalive,
+        timeout: Duration,
+    ) -> Result<DsoSession, Error> {
+        let stream = connect_tcp(server, proxy, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let socket = stream.try_clone()?;
+        let connected = connector.connect(stream, server_name, &[ALPN_DOT])?;
+        check_alpn(&connected, ALPN_DOT)?;
+        DsoSession::establish(connected.stream, socket, requested, timeout)
+    }
+
+    fn establish(
+        stream: Box<dyn Stream>,
+        socket: TcpStream,
+        requested: &Keepalive,
+        timeout: Duration,
+    ) -> Result<DsoSession, Error> {
+        socket.set_write_timeout(Some(timeout))?;
+        let now = Instant::now();
+        let mut session = DsoSession {
+            stream,
+            socket,
+            timeouts: *requested,
+            last_sent: now,
+            last_activity: now,
+            retry_delay: None,
+            subscriptions: Vec::new(),
+            pushed: Vec::new(),
+        };
+        session.keepalive_with(requested, timeout)?;
+        Ok(session)
+    }
+
+    /// The timeouts the server set for the session.
+    pub fn timeouts(&self) -> Keepalive {
+        self.timeouts
+    }
+
+    /// How long the server asked the client to wait before reconnecting,
+    /// once it has asked the client to go. The session is then over.
+    pub fn retry_delay(&self) -> Option<Duration> {
+        self.retry_delay
+    }
+
+    /// Whether the keepalive interval has passed since anything was last
+    /// sent.
+    pub fn keepalive_due(&self) -> bool {
+        self.timeouts
+            .keepalive_interval
+            .is_some_and(|interval| self.last_sent.elapsed() >= interval)
+    }
+
+    /// Whether the session has been idle for the inactivity timeout, so
+    /// that the client should close it (RFC 8490 §6.2). Sessions with
+    /// subscriptions are never idle.
+    pub fn inactivity_expired(&self) -> bool {
+        self.subscriptions.is_empty()
+            && self
+                .timeouts
+                .inactivity_timeout
+                .is_some_and(|timeout| self.last_activity.elapsed() >= timeout)
+    }
+
+    /// Sends a Keepalive request to show the session is still in use,
+    /// taking on whatever timeouts the server answers with.
+    pub fn keepalive(&mut self, timeout: Duration) -> Result<(), Error> {
+        let requested = self.timeouts;
+        self.keepalive_with(&requested, timeout)
+    }
+
+    fn keepalive_with(&mut self, requested: &Keepalive, timeout: Duration) -> Result<(), Error> {
+        let resp = self.request(&DsoMessage::keepalive(requested), timeout)?;
+        if resp.header.rcode != Rcode::NOERROR {
+            // NOTIMP or DSOTYPENI: the server does not do DSO sessions.
+            return Err(refused("the DSO session", resp.header.rcode));
+        }
+        // The server's timeouts are binding (RFC 8490 §7.1.1).
+        self.timeouts = resp
+            .keepalive_values()
+            // Without the TLV, what is missing is right after the header.
+            .ok_or(Error::Wire(crate::wire::Error::BadRdata { offset: 12 }))?;
+        Ok(())
+    }
+
+    /// Sends `query` on the session and waits for its response.
+    pub fn query(&mut self, query: &Message, timeout: Duration) -> Result<Message, Error> {
+        self.send(&query.to_wire()?)?;
+        let resp = self.receive(timeout, |wire| {
+            if dso::is_dso(wire) {
+                return Ok(None);
+            }
+            Ok(Some(Message::from_wire(wire)?).filter(|r| is_response_to(query, r)))
+        })?;
+        self.last_activity = Instant::now();
+        Ok(resp)
+    }
+
+    /// Subscribes to the records of `subscription`, returning the ID by
+    /// which to [`unsubscribe`](DsoSession::unsubscribe). The records there
+    /// are now arrive as the first push.
+    pub fn subscribe(
+        &mut self,
+        subscription: &Subscription,
+        timeout: Duration,
+    ) -> Result<u16, Error> {
+        let request = DsoMessage::request(subscription.to_tlv());
+        let resp = self.request(&request, timeout)?;
+        if resp.header.rcode != Rcode::NOERROR {
+            return Err(refused("the subscription", resp.header.rcode));
+        }
+        self.subscriptions.push(request.header.id);
+        self.last_activity = Instant::now();
+        Ok(request.header.id)
+    }
+
+    /// Ends the subscription made with the ID `id`. Changes the server
+    /// pushed before learning of it may still arrive.
+    pub fn unsubscribe(&mut self, id: u16) -> Result<(), Error> {
+        let msg = DsoMessage::unidirectional(push::unsubscribe_tlv(id));
+        self.send(&msg.to_wire()?)?;
+        self.subscriptions.retain(|&s| s != id);
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Asks the server to check that a pushed `record` still exists, as
+    /// when it failed to work; a removal is pushed if it does not.
+    pub fn reconfirm(&mut self, record: &Record) -> Result<(), Error> {
+        let msg = DsoMessage::unidirectional(push::reconfirm_tlv(record)?);
+        self.send(&msg.to_wire()?)
+    }
+
+    /// The changes pushed since last asked, waiting up to `timeout` for
+    /// some if there are none yet.
+    pub fn next_push(&mut self, timeout: Duration) -> Result<Vec<Change>, Error> {
+        let deadline = Instant::now() + timeout;
+        while self.pushed.is_empty() {
+            // Responses nobody waits for any more are dropped.
+            self.read_before(deadline)?;
+        }
+        Ok(std::mem::take(&mut self.pushed))
+    }
+
+    /// Sends a DSO `request` and waits for its response.
+    fn request(&mut self, request: &DsoMessage, timeout: Duration) -> Result<DsoMessage, Error> {
+        self.send(&request.to_wire()?)?;
+        self.receive(timeout, |wire| {
+            Ok(dso::is_dso(wire)
+                .then(|| DsoMessage::from_wire(wire))
+                .transpose()?
+                .filter(|m| m.header.qr && m.header.id == request.header.id))
+        })
+    }
+
+    fn send(&mut self, wire: &[u8]) -> Result<(), Error> {
+        if let Some(delay) = self.retry_delay {
+            return Err(gone(delay));
+        }
+        write_framed(&mut self.stream, wire)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Reads messages until `accept` takes one, handling the
+    /// unidirectional messages the server sends meanwhile.
+    fn receive<T>(
+        &mut self,
+        timeout: Duration,
+        mut accept: impl FnMut(&[u8]) -> Result<Option<T>, Error>,
+    ) -> Result<T, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(wire) = self.read_before(deadline)? {
+                if let Some(found) = accept(&wire)? {
+                    return Ok(found);
+                }
+            }
+        }
+    }
+
+    /// Reads one message arriving before `deadline`. Unidirectional
+    /// messages are handled here and give `None`.
+    fn read_before(&mut self, deadline: Instant) -> Result<Option<Vec<u8>>, Error> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return Err(Error::Timeout);
+        }
+        self.socket.set_read_timeout(Some(left))?;
+        let wire = read_framed(&mut self.stream)?;
+        if !dso::is_dso(&wire) {
+            return Ok(Some(wire));
+        }
+        let msg = DsoMessage::from_wire(&wire)?;
+        if !msg.is_unidirectional() {
+            return Ok(Some(wire));
+        }
+        if let Some(keepalive) = msg.keepalive_values() {
+            self.timeouts = keepalive;
+        }
+        if let Some(delay) = msg.retry_delay_value() {
+            self.retry_delay = Some(delay);
+            return Err(gone(delay));
+        }
+        for tlv in msg.tlvs.iter().filter(|t| t.dtype == DsoType::PUSH) {
+            self.pushed.extend(push::parse_push(tlv)?);
+        }
+        Ok(None)
+    }
+}
+
+/// The error for a request the server answered with `rcode`.
+fn refused(what: &str, rcode: Rcode) -> Error {
+    let kind = match rcode {
+        Rcode::NOTIMP | Rcode::DSOTYPENI => ErrorKind::Unsupported,
+        _ => ErrorKind::Other,
+    };
+    Error::Io(io::Error::new(
+        kind,
+        format!("server refused {}: {}", what, rcode),
+    ))
+}
+
+/// The error for a session the server has asked the client to leave.
+fn gone(delay: Duration) -> Error {
+    Error::Io(io::Error::new(
+        ErrorKind::ConnectionAborted,
+        format!(
+            "server ended the DSO session; retry after {} ms",
+            delay.as_millis()
+        ),
+    ))
+}
@@ -0,0 +1,92 @@
+//! DNS over HTTPS through the fetch API of a WebAssembly host.
+
+use std::io;
+use std::time::Duration;
+
+use js_sys::{Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AbortSignal, Request, RequestInit, Response};
+
+use super::{is_response_to, mismatch, zero_id, Error};
+use crate::message::Message;
+
+/// POSTs `query` to the DoH endpoint `url`, such as
+/// `https://dns.example/dns-query`, with the host's `fetch` (RFC 8484).
+/// It works wherever `fetch` is a global: in browser pages, extensions and
+/// workers, and in edge runtimes. The host picks the HTTP version and
+/// reuses connections; `timeout` aborts the request if it runs longer.
+pub async fn exchange_fetch(
+    url: &str,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    let sent = zero_id(query);
+    let body = Uint8Array::from(&sent.to_wire()?[..]);
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&body);
+    let millis = timeout.as_millis().min(u128::from(u32::MAX)) as u32;
+    init.set_signal(Some(&AbortSignal::timeout_with_u32(millis)));
+    let request = Request::new_with_str_and_init(url, &init).map_err(js_error)?;
+    let headers = request.headers();
+    headers
+        .set("Content-Type", "application/dns-message")
+        .map_err(js_error)?;
+    headers
+        .set("Accept", "application/dns-message")
+        .map_err(js_error)?;
+
+    let global = js_sys::global();
+    let fetch: Function = Reflect::get(&global, &JsValue::from_str("fetch"))
+        .ok()
+        .and_then(|f| f.dyn_into().ok())
+        .ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no fetch in this host",
+            ))
+        })?;
+    let promise: Promise = fetch
+        .call1(&global, &request)
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    let response: Response = JsFuture::from(promise)
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+    if response.status() != 200 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("HTTP status {}", response.status()),
+        )));
+    }
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    let mut resp = Message::from_wire(&Uint8Array::new(&buffer).to_vec())?;
+    if !is_response_to(&sent, &resp) {
+        return Err(mismatch());
+    }
+    resp.header.id = query.header.id;
+    Ok(resp)
+}
+
+/// The error for a rejected promise or a failed call: a timeout if the
+/// abort signal fired, otherwise the message of the JavaScript error.
+fn js_error(e: JsValue) -> Error {
+    let name = Reflect::get(&e, &JsValue::from_str("name"))
+        .ok()
+        .and_then(|n| n.as_string());
+    if name.as_deref() == Some("TimeoutError") {
+        return Error::Timeout;
+    }
+    let message = Reflect::get(&e, &JsValue::from_str("message"))
+        .ok()
+        .and_then(|m| m.as_string())
+        .or_else(|| e.as_string())
+        .unwrap_or_else(|| "fetch failed".to_string());
+    Error::Io(io::Error::other(message))
+}
@@ -0,0 +1,456 @@
+//! Blocking query transports.
+//!
+//! Each function sends a single query to one server and waits for the
+//! matching response. Retries, server rotation and TCP fallback on
+//! truncation are the caller's business (see [`crate::resolver`]). A
+//! [`TcpPool`] keeps TCP connections open across queries instead. TCP
+//! connections, plain or encrypted, may be opened through a [`Proxy`].
+//! A [`DsoSession`] holds one open under the timeouts a server negotiates
+//! with DNS Stateful Operations (RFC 8490).
+//!
+//! The encrypted transports, DNS over TLS (RFC 7858), HTTPS (RFC 8484,
+//! on HTTP/1.1) and QUIC (RFC 9250), run over a [`TlsConnector`] or
+//! [`QuicConnector`] the application supplies, as the server's listeners
+//! run over its [`TlsAcceptor`](crate::server::TlsAcceptor).
+//!
+//! WebAssembly hosts have no sockets to give these transports. In
+//! browsers and edge runtimes, on wasm32-unknown-unknown, the `wasm`
+//! feature adds `exchange_fetch` instead, an asynchronous DNS over HTTPS
+//! transport on the host's fetch API.
+
+mod dso;
+#[cfg(all(feature = "wasm", target_arch = "wasm32", target_os = "unknown"))]
+mod fetch;
+mod pool;
+mod proxy;
+
+pub use self::dso::DsoSession;
+#[cfg(all(feature = "wasm", target_arch = "wasm32", target_os = "unknown"))]
+pub use self::fetch::exchange_fetch;
+pub use self::pool::{PoolPolicy, TcpPool};
+pub use self::proxy::{connect_tcp, Proxy, ProxyKind};
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::message::Message;
+use crate::server::{Stream, ALPN_DOQ, ALPN_DOT, ALPN_HTTP1};
+use crate::wire;
+
+/// Errors produced by a single exchange.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Wire(wire::Error),
+    /// No matching response arrived in time.
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::Wire(e) => write!(f, "malformed message: {}", e),
+            Error::Timeout => f.write_str("timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<wire::Error> for Error {
+    fn from(e: wire::Error) -> Error {
+        Error::Wire(e)
+    }
+}
+
+/// The transport used to reach a server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+    /// DNS over TLS (RFC 7858), which queries reach through a
+    /// [`TlsConnector`]. Servers report requests on their encrypted
+    /// listeners as `Tcp`.
+    Tls,
+}
+
+/// Whether `response` answers `query`: same ID and same question.
+pub fn is_response_to(query: &Message, response: &Message) -> bool {
+    response.header.qr
+        && response.header.id == query.header.id
+        && response.questions == query.questions
+}
+
+/// Sends `query` over UDP and waits for a matching response, ignoring
+/// datagrams that do not match until the deadline passes.
+pub fn exchange_udp(
+    server: SocketAddr,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    let local: SocketAddr = if server.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    socket.send(&query.to_wire()?)?;
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0u8; 65535];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return Err(Error::Timeout);
+        }
+        socket.set_read_timeout(Some(left))?;
+        let n = socket.recv(&mut buf)?;
+        match Message::from_wire(&buf[..n]) {
+            Ok(resp) if is_response_to(query, &resp) => return Ok(resp),
+            _ => continue,
+        }
+    }
+}
+
+/// Writes one length-prefixed message to a stream (RFC 1035 §4.2.2).
+pub fn write_framed<W: Write>(stream: &mut W, msg: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(msg.len() + 2);
+    buf.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+    buf.extend_from_slice(msg);
+    stream.write_all(&buf)
+}
+
+/// Reads one length-prefixed message from a stream.
+pub fn read_framed<R: Read>(stream: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut buf = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Sends `query` over a fresh TCP connection and reads the response.
+pub fn exchange_tcp(
+    server: SocketAddr,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    exchange_tcp_via(server, None, query, timeout)
+}
+
+/// Sends `query` over a fresh TCP connection, opened through `proxy` if
+/// there is one, and reads the response.
+pub fn exchange_tcp_via(
+    server: SocketAddr,
+    proxy: Option<&Proxy>,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    let deadline = Instant::now() + timeout;
+    let mut stream = connect_tcp(server, proxy, timeout)?;
+    stream.set_write_timeout(Some(timeout))?;
+    write_framed(&mut stream, &query.to_wire()?)?;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return Err(Error::Timeout);
+        }
+        stream.set_read_timeout(Some(left))?;
+        let resp = Message::from_wire(&read_framed(&mut stream)?)?;
+        if is_response_to(query, &resp) {
+            return Ok(resp);
+        }
+    }
+}
+
+/// Exchanges `query` with `server` over the given protocol. TLS needs a
+/// connector, so it fails here; see [`exchange_tls`].
+pub fn exchange(
+    server: SocketAddr,
+    protocol: Protocol,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    match protocol {
+        Protocol::Udp => exchange_udp(server, query, timeout),
+        Protocol::Tcp => exchange_tcp(server, query, timeout),
+        Protocol::Tls => Err(Error::Io(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "DNS over TLS needs a TLS connector",
+        ))),
+    }
+}
+
+/// A connection to a server after a completed handshake.
+pub struct Connected {
+    pub stream: Box<dyn Stream>,
+    /// The protocol agreed on with ALPN, if the server picked one.
+    pub alpn: Option<Vec<u8>>,
+    /// The DER certificates the server presented, its own first.
+    pub certificates: Vec<Vec<u8>>,
+}
+
+/// The client side of TLS handshakes.
+pub trait TlsConnector: Send + Sync + 'static {
+    /// Completes a handshake on `stream`, offering the protocols in `alpn`.
+    /// The server's certificate must be checked for `server_name`, a host
+    /// name or an IP address, and the handshake fail if it does not hold.
+    ///
+    /// The read and write timeouts of `stream` are already set; they bound
+    /// the handshake and carry over to the returned stream.
+    fn connect(
+        &self,
+        stream: TcpStream,
+        server_name: &str,
+        alpn: &[&[u8]],
+    ) -> io::Result<Connected>;
+}
+
+impl<T: TlsConnector + ?Sized> TlsConnector for Arc<T> {
+    fn connect(
+        &self,
+        stream: TcpStream,
+        server_name: &str,
+        alpn: &[&[u8]],
+    ) -> io::Result<Connected> {
+        (**self).connect(stream, server_name, alpn)
+    }
+}
+
+/// The client side of QUIC.
+pub trait QuicConnector: Send + Sync + 'static {
+    /// Connects to `server`, checking its certificate as
+    /// [`TlsConnector::connect`] does, and opens a bidirectional stream.
+    /// `timeout` bounds the handshake and each read and write on the
+    /// stream; dropping the stream finishes its sending side.
+    fn open(
+        &self,
+        server: SocketAddr,
+        server_name: &str,
+        alpn: &[&[u8]],
+        timeout: Duration,
+    ) -> io::Result<Connected>;
+}
+
+impl<T: QuicConnector + ?Sized> QuicConnector for Arc<T> {
+    fn open(
+        &self,
+        server: SocketAddr,
+        server_name: &str,
+        alpn: &[&[u8]],
+        timeout: Duration,
+    ) -> io::Result<Connected> {
+        (**self).open(server, server_name, alpn, timeout)
+    }
+}
+
+/// Connects to `server` over TCP, through `proxy` if there is one, and
+/// completes a TLS handshake on it offering `alpn`, which the server must
+/// agree to.
+pub fn connect_tls(
+    connector: &dyn TlsConnector,
+    server: SocketAddr,
+    proxy: Option<&Proxy>,
+    server_name: &str,
+    alpn: &[u8],
+    timeout: Duration,
+) -> Result<Connected, Error> {
+    let stream = connect_tcp(server, proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let connected = connector.connect(stream, server_name, &[alpn])?;
+    check_alpn(&connected, alpn)?;
+    Ok(connected)
+}
+
+fn check_alpn(connected: &Connected, alpn: &[u8]) -> io::Result<()> {
+    match &connected.alpn {
+        Some(agreed) if agreed != alpn => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "server agreed to another protocol",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The error for the one response a stream carries not answering the
+/// query.
+fn mismatch() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "response does not match the query",
+    ))
+}
+
+/// `query` with the ID zero that the HTTPS and QUIC transports use, so
+/// that responses can be cached by their content (RFC 8484 §4.1, RFC 9250
+/// §4.2.1).
+fn zero_id(query: &Message) -> Message {
+    let mut query = query.clone();
+    query.header.id = 0;
+    query
+}
+
+/// Sends `query` over a fresh TLS connection and reads the response
+/// (RFC 7858).
+pub fn exchange_tls(
+    connector: &dyn TlsConnector,
+    server: SocketAddr,
+    proxy: Option<&Proxy>,
+    server_name: &str,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    let deadline = Instant::now() + timeout;
+    let mut connected = connect_tls(connector, server, proxy, server_name, ALPN_DOT, timeout)?;
+    write_framed(&mut connected.stream, &query.to_wire()?)?;
+    loop {
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout);
+        }
+        let resp = Message::from_wire(&read_framed(&mut connected.stream)?)?;
+        if is_response_to(query, &resp) {
+            return Ok(resp);
+        }
+    }
+}
+
+/// Sends `query` on a fresh QUIC stream and reads the response
+/// (RFC 9250).
+pub fn exchange_quic(
+    connector: &dyn QuicConnector,
+    server: SocketAddr,
+    server_name: &str,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    let sent = zero_id(query);
+    let mut connected = connector.open(server, server_name, &[ALPN_DOQ], timeout)?;
+    check_alpn(&connected, ALPN_DOQ)?;
+    write_framed(&mut connected.stream, &sent.to_wire()?)?;
+    let mut resp = Message::from_wire(&read_framed(&mut connected.stream)?)?;
+    if !is_response_to(&sent, &resp) {
+        return Err(mismatch());
+    }
+    resp.header.id = query.header.id;
+    Ok(resp)
+}
+
+/// The largest HTTP response read, head and body.
+const MAX_HTTP_RESPONSE: usize = 16 * 1024 + 65535;
+
+/// POSTs `query` to `path` over a fresh TLS connection and reads the
+/// response (RFC 8484). The request is HTTP/1.1, closing the connection
+/// after it.
+pub fn exchange_https(
+    connector: &dyn TlsConnector,
+    server: SocketAddr,
+    proxy: Option<&Proxy>,
+    server_name: &str,
+    path: &str,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, Error> {
+    let sent = zero_id(query);
+    let body = sent.to_wire()?;
+    let mut connected = connect_tls(connector, server, proxy, server_name, ALPN_HTTP1, timeout)?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/dns-message\r\n\
+         Accept: application/dns-message\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        server_name,
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(&body);
+    connected.stream.write_all(&request)?;
+    connected.stream.flush()?;
+    let mut raw = Vec::new();
+    (&mut connected.stream)
+        .take(MAX_HTTP_RESPONSE as u64 + 1)
+        .read_to_end(&mut raw)?;
+    if raw.len() > MAX_HTTP_RESPONSE {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "HTTP response too large",
+        )));
+    }
+    let mut resp = Message::from_wire(&http_body(&raw)?)?;
+    if !is_response_to(&sent, &resp) {
+        return Err(mismatch());
+    }
+    resp.header.id = query.header.id;
+    Ok(resp)
+}
+
+/// The body of `raw`, a whole HTTP/1.1 response, if its status is 200.
+fn http_body(raw: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| invalid("truncated HTTP response"))?;
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let body = &raw[end + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or_else(|| invalid("malformed HTTP response"))?;
+    if status != "200" {
+        return Err(invalid(&format!("HTTP status {}", status)));
+    }
+    let mut chunked = false;
+    let mut length = None;
+    for line in lines {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse::<usize>().ok();
+        }
+    }
+    if chunked {
+        return dechunk(body).ok_or_else(|| invalid("malformed chunked body"));
+    }
+    match length {
+        Some(n) => body
+            .get(..n)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| invalid("truncated HTTP response")),
+        None => Ok(body.to_vec()),
+    }
+}
+
+/// The content of a chunked HTTP/1.1 body (RFC 9112 §7.1).
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let line = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line]).ok()?;
+        let size = size.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[line + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
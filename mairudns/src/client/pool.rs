@@ -0,0 +1,363 @@
+//! Persistent TCP connections to servers (RFC 7766 §6.2.1).
+//!
+//! A [`TcpPool`] keeps connections open between queries and pipelines
+//! queries on them: each gets an ID unique on its connection, and
+//! responses are matched to queries in whatever order they arrive. Queries
+//! carry the edns-tcp-keepalive option (RFC 7828), and a connection is
+//! closed once it has been idle for as long as the server's responses
+//! allow, or at once when the server asks for that.
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::message::{Message, Question};
+use crate::random;
+
+use super::{connect_tcp, write_framed, Error, Proxy};
+
+/// How connections are shared and when they are closed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolPolicy {
+    /// Connections open to one server at most.
+    pub max_connections: usize,
+    /// Queries outstanding on one connection before another is opened.
+    /// Once `max_connections` are open, the least busy one takes further
+    /// queries anyway.
+    pub max_outstanding: usize,
+    /// How long a connection is kept idle when the server gives no
+    /// timeout of its own.
+    pub idle_timeout: Duration,
+    /// How long a connection is kept idle at most, whatever the server
+    /// allows.
+    pub max_idle_timeout: Duration,
+    /// Sends the edns-tcp-keepalive option in queries that have EDNS.
+    pub keepalive: bool,
+}
+
+impl Default for PoolPolicy {
+    fn default() -> PoolPolicy {
+        PoolPolicy {
+            max_connections: 2,
+            max_outstanding: 64,
+            idle_timeout: Duration::from_secs(10),
+            max_idle_timeout: Duration::from_secs(120),
+            keepalive: true,
+        }
+    }
+}
+
+/// A query waiting for its response.
+struct Pending {
+    questions: Vec<Question>,
+    tx: Sender<Message>,
+}
+
+struct State {
+    pending: HashMap<u16, Pending>,
+    idle_timeout: Duration,
+    /// When the last query was sent or response received.
+    last_active: Instant,
+    /// Takes no new queries: the server asked for the connection to be
+    /// closed, or it failed.
+    closing: bool,
+}
+
+/// One pooled connection; a thread of its own reads the responses.
+struct Conn {
+    stream: Mutex<TcpStream>,
+    state: Mutex<State>,
+}
+
+impl Conn {
+    fn outstanding(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    fn is_closing(&self) -> bool {
+        self.state.lock().unwrap().closing
+    }
+
+    /// Sends `query` and waits until `deadline` for its response.
+    fn exchange(&self, query: &Message, deadline: Instant) -> Result<Message, Error> {
+        let (tx, rx) = mpsc::channel();
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            if state.closing {
+                return Err(closed());
+            }
+            let mut id = random::u16();
+            while state.pending.contains_key(&id) {
+                id = random::u16();
+            }
+            state.pending.insert(
+                id,
+                Pending {
+                    questions: query.questions.clone(),
+                    tx,
+                },
+            );
+            state.last_active = Instant::now();
+            id
+        };
+        let mut sent = query.clone();
+        sent.header.id = id;
+        let written = sent.to_wire().map_err(Error::from).and_then(|wire| {
+            let mut stream = self.stream.lock().unwrap();
+            let left = deadline.saturating_duration_since(Instant::now());
+            stream.set_write_timeout(Some(left.max(Duration::from_millis(1))))?;
+            write_framed(&mut *stream, &wire).map_err(Error::from)
+        });
+        if let Err(e) = written {
+            let mut state = self.state.lock().unwrap();
+            state.pending.remove(&id);
+            state.closing = true;
+            return Err(e);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(left) {
+            Ok(mut resp) => {
+                resp.header.id = query.header.id;
+                Ok(resp)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                self.state.lock().unwrap().pending.remove(&id);
+                Err(Error::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(closed()),
+        }
+    }
+
+    /// Hands `resp` to the query it answers, and applies the idle timeout
+    /// it gives.
+    fn deliver(&self, resp: Message, policy: &PoolPolicy) {
+        let mut state = self.state.lock().unwrap();
+        state.last_active = Instant::now();
+        match state.pending.get(&resp.header.id) {
+            Some(p) if resp.header.qr && resp.questions == p.questions => {}
+            // An answer to a query given up on, or garbage.
+            _ => return,
+        }
+        match resp.tcp_keepalive() {
+            Some(Some(t)) if t == Duration::from_secs(0) => state.closing = true,
+            Some(Some(t)) => state.idle_timeout = t.min(policy.max_idle_timeout),
+            _ => {}
+        }
+        let pending = state.pending.remove(&resp.header.id).unwrap();
+        // The caller may have timed out meanwhile.
+        let _ = pending.tx.send(resp);
+    }
+
+    /// Whether the connection has nothing outstanding and may be closed:
+    /// the server asked for it, or it has been idle long enough.
+    fn is_done(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.pending.is_empty()
+            && (state.closing || state.last_active.elapsed() >= state.idle_timeout)
+    }
+}
+
+fn closed() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "connection closed",
+    ))
+}
+
+/// The connections to one server.
+type Conns = Arc<Mutex<Vec<Arc<Conn>>>>;
+
+struct Inner {
+    policy: PoolPolicy,
+    proxy: Option<Proxy>,
+    servers: Mutex<HashMap<SocketAddr, Conns>>,
+}
+
+/// Persistent TCP connections, shared by clones.
+#[derive(Clone)]
+pub struct TcpPool {
+    inner: Arc<Inner>,
+}
+
+impl TcpPool {
+    pub fn new(policy: PoolPolicy) -> TcpPool {
+        TcpPool::with_proxy(policy, None)
+    }
+
+    /// A pool whose connections are opened through `proxy`, if any.
+    pub fn with_proxy(policy: PoolPolicy, proxy: Option<Proxy>) -> TcpPool {
+        TcpPool {
+            inner: Arc::new(Inner {
+                policy,
+                proxy,
+                servers: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn policy(&self) -> &PoolPolicy {
+        &self.inner.policy
+    }
+
+    /// The number of connections open to `server`.
+    pub fn connections(&self, server: SocketAddr) -> usize {
+        let conns = self.inner.servers.lock().unwrap().get(&server).cloned();
+        conns.map_or(0, |c| c.lock().unwrap().len())
+    }
+
+    /// Sends `query` to `server` on a pooled connection and waits for the
+    /// response. A query that fails on a reused connection, which the
+    /// server may have just closed, is sent once more on a fresh one.
+    pub fn exchange(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        timeout: Duration,
+    ) -> Result<Message, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut query = query.clone();
+        if self.inner.policy.keepalive && query.tcp_keepalive().is_none() {
+            query.set_tcp_keepalive(None);
+        }
+        let (conn, fresh) = self.connection(server, timeout, false)?;
+        match conn.exchange(&query, deadline) {
+            Err(Error::Io(_)) if !fresh => {}
+            result => return result,
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return Err(Error::Timeout);
+        }
+        let (conn, _) = self.connection(server, left, true)?;
+        conn.exchange(&query, deadline)
+    }
+
+    /// A connection to `server` for a query, and whether it was just
+    /// opened: the least busy open one, unless it is busy enough that
+    /// another should be opened, or `fresh` asks for a new one anyway.
+    /// Queries to the server wait while a connection is being opened.
+    fn connection(
+        &self,
+        server: SocketAddr,
+        timeout: Duration,
+        fresh: bool,
+    ) -> Result<(Arc<Conn>, bool), Error> {
+        let policy = &self.inner.policy;
+        let conns = self
+            .inner
+            .servers
+            .lock()
+            .unwrap()
+            .entry(server)
+            .or_default()
+            .clone();
+        let mut list = conns.lock().unwrap();
+        list.retain(|c| !c.is_closing());
+        if !fresh {
+            if let Some(conn) = list.iter().min_by_key(|c| c.outstanding()) {
+                if conn.outstanding() < policy.max_outstanding
+                    || list.len() >= policy.max_connections
+                {
+                    return Ok((conn.clone(), false));
+                }
+            }
+        }
+        let conn = self.open(server, timeout)?;
+        list.push(conn.clone());
+        // Beyond the limit, the oldest connection takes no more queries
+        // and closes once done.
+        if list.len() > policy.max_connections.max(1) {
+            list.remove(0).state.lock().unwrap().closing = true;
+        }
+        Ok((conn, true))
+    }
+
+    /// Opens a connection to `server` and starts reading from it.
+    fn open(&self, server: SocketAddr, timeout: Duration) -> Result<Arc<Conn>, Error> {
+        let stream = connect_tcp(server, self.inner.proxy.as_ref(), timeout)?;
+        let reader = stream.try_clone()?;
+        let conn = Arc::new(Conn {
+            stream: Mutex::new(stream),
+            state: Mutex::new(State {
+                pending: HashMap::new(),
+                idle_timeout: self.inner.policy.idle_timeout,
+                last_active: Instant::now(),
+                closing: false,
+            }),
+        });
+        let pool = Arc::downgrade(&self.inner);
+        let read_conn = conn.clone();
+        thread::spawn(move || read_responses(reader, read_conn, server, pool));
+        Ok(conn)
+    }
+}
+
+impl std::fmt::Debug for TcpPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpPool")
+            .field("policy", &self.inner.policy)
+            .field("proxy", &self.inner.proxy)
+            .finish()
+    }
+}
+
+/// How often a reader wakes up to see whether its connection is done.
+const IDLE_CHECK: Duration = Duration::from_millis(500);
+
+/// Reads responses from `stream` and hands them out until the connection
+/// fails or is done, then closes it and drops it from the pool.
+fn read_responses(mut stream: TcpStream, conn: Arc<Conn>, server: SocketAddr, pool: Weak<Inner>) {
+    let policy = match pool.upgrade() {
+        Some(inner) => inner.policy,
+        None => return,
+    };
+    let mut buf = Vec::new();
+    let mut chunk = vec![0u8; 16 * 1024];
+    loop {
+        // Whole messages first; a read may have brought several.
+        while buf.len() >= 2 {
+            let len = usize::from(u16::from_be_bytes([buf[0], buf[1]]));
+            if buf.len() < 2 + len {
+                break;
+            }
+            if let Ok(resp) = Message::from_wire(&buf[2..2 + len]) {
+                conn.deliver(resp, &policy);
+            }
+            buf.drain(..2 + len);
+        }
+        if conn.is_done() {
+            break;
+        }
+        if stream.set_read_timeout(Some(IDLE_CHECK)).is_err() {
+            break;
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    // Waiting queries see their senders dropped and fail.
+    {
+        let mut state = conn.state.lock().unwrap();
+        state.closing = true;
+        state.pending.clear();
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+    if let Some(inner) = pool.upgrade() {
+        let conns = inner.servers.lock().unwrap().get(&server).cloned();
+        if let Some(conns) = conns {
+            conns.lock().unwrap().retain(|c| !Arc::ptr_eq(c, &conn));
+        }
+    }
+}
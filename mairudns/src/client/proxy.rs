@@ -0,0 +1,267 @@
+//! Connections to servers through a proxy.
+//!
+//! The TCP-based transports can reach servers through a SOCKS5 proxy
+//! (RFC 1928, with the username and password authentication of RFC 1929)
+//! or through the CONNECT method of an HTTP proxy (RFC 9110 §9.3.6).
+//! Neither carries UDP here, so queries that should go through a proxy
+//! have to be sent over TCP.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::encoding::base64_encode;
+
+/// The protocol spoken to a proxy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProxyKind {
+    Socks5,
+    /// An HTTP proxy, asked to CONNECT.
+    Http,
+}
+
+/// A proxy that TCP connections to servers are opened through.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub address: SocketAddr,
+    /// Username and password, sent in the clear.
+    pub credentials: Option<(String, String)>,
+}
+
+/// The largest HTTP response head read from a proxy.
+const MAX_HEAD: usize = 8 * 1024;
+
+impl Proxy {
+    pub fn socks5(address: SocketAddr) -> Proxy {
+        Proxy {
+            kind: ProxyKind::Socks5,
+            address,
+            credentials: None,
+        }
+    }
+
+    pub fn http(address: SocketAddr) -> Proxy {
+        Proxy {
+            kind: ProxyKind::Http,
+            address,
+            credentials: None,
+        }
+    }
+
+    pub fn credentials<U: Into<String>, P: Into<String>>(
+        mut self,
+        username: U,
+        password: P,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Opens a connection to `target` through the proxy. `timeout` bounds
+    /// connecting to the proxy and each read and write of the handshake;
+    /// the returned stream keeps those timeouts.
+    pub fn connect(&self, target: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect_timeout(&self.address, timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        match self.kind {
+            ProxyKind::Socks5 => self.socks5_connect(&mut stream, target)?,
+            ProxyKind::Http => self.http_connect(&mut stream, target)?,
+        }
+        Ok(stream)
+    }
+
+    fn socks5_connect(&self, stream: &mut TcpStream, target: SocketAddr) -> io::Result<()> {
+        // Methods: 0 is no authentication, 2 username and password.
+        let method = if self.credentials.is_some() { 2 } else { 0 };
+        stream.write_all(&[5, 1, method])?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != 5 {
+            return Err(refused("not a SOCKS5 proxy"));
+        }
+        if reply[1] != method {
+            return Err(refused("SOCKS5 proxy accepts no offered authentication"));
+        }
+        if let Some((username, password)) = &self.credentials {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SOCKS5 username or password too long",
+                ));
+            }
+            let mut auth = vec![1, username.len() as u8];
+            auth.extend_from_slice(username.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth)?;
+            stream.read_exact(&mut reply)?;
+            if reply[1] != 0 {
+                return Err(refused("SOCKS5 proxy rejected the credentials"));
+            }
+        }
+        let mut request = vec![5, 1, 0];
+        match target.ip() {
+            IpAddr::V4(ip) => {
+                request.push(1);
+                request.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                request.push(4);
+                request.extend_from_slice(&ip.octets());
+            }
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream.write_all(&request)?;
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head)?;
+        if head[0] != 5 {
+            return Err(refused("malformed SOCKS5 reply"));
+        }
+        if head[1] != 0 {
+            return Err(refused(socks5_reply(head[1])));
+        }
+        // The address the proxy bound, which is of no use here.
+        let len = match head[3] {
+            1 => 4,
+            4 => 16,
+            3 => {
+                let mut len = [0u8];
+                stream.read_exact(&mut len)?;
+                usize::from(len[0])
+            }
+            _ => return Err(refused("malformed SOCKS5 reply")),
+        };
+        let mut bound = vec![0u8; len + 2];
+        stream.read_exact(&mut bound)
+    }
+
+    fn http_connect(&self, stream: &mut TcpStream, target: SocketAddr) -> io::Result<()> {
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+        if let Some((username, password)) = &self.credentials {
+            let token = base64_encode(format!("{}:{}", username, password).as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        // Byte by byte, so that nothing after the head is consumed.
+        let mut head = Vec::new();
+        let mut byte = [0u8];
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_HEAD {
+                return Err(refused("HTTP proxy response too large"));
+            }
+            stream.read_exact(&mut byte)?;
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8_lossy(&head);
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .ok_or_else(|| refused("malformed HTTP proxy response"))?;
+        if !status.starts_with('2') {
+            return Err(refused(&format!("HTTP proxy responded {}", status)));
+        }
+        Ok(())
+    }
+}
+
+fn refused(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, what.to_string())
+}
+
+/// The meaning of a SOCKS5 reply code (RFC 1928 §6).
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        1 => "SOCKS5 proxy failed",
+        2 => "SOCKS5 proxy forbids the connection",
+        3 => "network unreachable from the SOCKS5 proxy",
+        4 => "host unreachable from the SOCKS5 proxy",
+        5 => "connection refused through the SOCKS5 proxy",
+        6 => "TTL expired through the SOCKS5 proxy",
+        7 => "SOCKS5 proxy does not support CONNECT",
+        8 => "SOCKS5 proxy does not support the address type",
+        _ => "SOCKS5 proxy refused the connection",
+    }
+}
+
+/// Opens a TCP connection to `server`, through `proxy` if there is one.
+pub fn connect_tcp(
+    server: SocketAddr,
+    proxy: Option<&Proxy>,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    match proxy {
+        Some(proxy) => proxy.connect(server, timeout),
+        None => {
+            let stream = TcpStream::connect_timeout(&server, timeout)?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        }
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("address", &self.address)
+            .field(
+                "username",
+                &self.credentials.as_ref().map(|(username, _)| username),
+            )
+            .finish()
+    }
+}
+
+impl fmt::Display for Proxy {
+    /// Writes the proxy as a URL, without the password.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        };
+        match &self.credentials {
+            Some((username, _)) => write!(f, "{}://{}@{}", scheme, username, self.address),
+            None => write!(f, "{}://{}", scheme, self.address),
+        }
+    }
+}
+
+impl FromStr for Proxy {
+    type Err = String;
+
+    /// Parses URLs such as `socks5://192.0.2.1:1080` or
+    /// `http://user:secret@[2001:db8::1]:3128`. The proxy is given by
+    /// address, not by name.
+    fn from_str(s: &str) -> Result<Proxy, String> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| format!("invalid proxy {:?}: expected scheme://address", s))?;
+        let kind = match scheme {
+            "socks5" => ProxyKind::Socks5,
+            "http" => ProxyKind::Http,
+            _ => return Err(format!("unsupported proxy scheme {:?}", scheme)),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (credentials, address) = match rest.rsplit_once('@') {
+            Some((userinfo, address)) => {
+                let (username, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((username.to_string(), password.to_string())), address)
+            }
+            None => (None, rest),
+        };
+        let address = address
+            .parse()
+            .map_err(|_| format!("invalid proxy address {:?}", address))?;
+        Ok(Proxy {
+            kind,
+            address,
+            credentials,
+        })
+    }
+}
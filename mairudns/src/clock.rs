@@ -0,0 +1,100 @@
+//! Sources of time.
+//!
+//! Time-dependent parts of the crate read the time from a [`Clock`]: the
+//! forwarder's cache ages and expires responses by it, the server checks
+//! TSIG signing times against it, and the DNSSEC validator checks RRSIG
+//! validity periods and expires the keys it has learned by it. They use
+//! the [`SystemClock`] unless given another, such as a [`MockClock`] that
+//! only moves when told to, so that expiry can be tested without waiting
+//! for it.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A source of monotonic and wall-clock time.
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    /// Monotonic time, for ages and expiry.
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for anything dated, such as signatures.
+    fn system_time(&self) -> SystemTime;
+
+    /// Seconds since the Unix epoch, zero before it.
+    fn unix_time(&self) -> u64 {
+        self.system_time()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for Arc<T> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        (**self).system_time()
+    }
+}
+
+/// The operating system's clocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The [`SystemClock`], shared.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A clock that stands still until it is advanced or set. Clones share
+/// the same time.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    time: Arc<Mutex<(Instant, SystemTime)>>,
+}
+
+impl MockClock {
+    /// A clock reading `unix_time` seconds since the epoch.
+    pub fn new(unix_time: u64) -> MockClock {
+        MockClock {
+            time: Arc::new(Mutex::new((
+                Instant::now(),
+                UNIX_EPOCH + Duration::from_secs(unix_time),
+            ))),
+        }
+    }
+
+    /// Moves both clocks forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        let mut time = self.time.lock().unwrap();
+        time.0 += by;
+        time.1 += by;
+    }
+
+    /// Steps the wall clock to `unix_time`, as an operator or NTP might,
+    /// leaving monotonic time where it is.
+    pub fn set_unix_time(&self, unix_time: u64) {
+        self.time.lock().unwrap().1 = UNIX_EPOCH + Duration::from_secs(unix_time);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.time.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.time.lock().unwrap().1
+    }
+}
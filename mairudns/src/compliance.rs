@@ -0,0 +1,153 @@
+//! How strictly the RFCs are held to where software in the wild strays
+//! from them.
+//!
+//! Plenty of what the RFCs forbid turns up in practice: host names with
+//! underscores (RFC 952 and RFC 1123 §2.1 allow letters, digits and
+//! hyphens only), TTLs with the top bit set (RFC 2181 §8), compression
+//! pointers in the data of records whose names must not be compressed
+//! (RFC 2782, RFC 6672 §2.5), bytes after the last record of a message,
+//! and queries with several questions (RFC 9619). A [`Compliance`]
+//! profile says which of these to put up with, so that choosing between
+//! interoperability and strictness is deliberate rather than inherited
+//! from whatever the parser happens to do.
+//!
+//! Messages are decoded under a profile with
+//! [`Message::from_wire_with`](crate::message::Message::from_wire_with),
+//! zone files read with
+//! [`parse_records_with`](crate::zone::parse_records_with), and servers
+//! judge requests with
+//! [`Server::set_compliance`](crate::server::Server::set_compliance).
+
+use std::fmt;
+
+use crate::name::DomainName;
+use crate::rr::{RData, Record};
+
+/// The largest TTL RFC 2181 §8 allows: 2^31 - 1 seconds.
+pub const MAX_TTL: u32 = 0x7fff_ffff;
+
+/// What to put up with that the RFCs forbid. Each field set tolerates one
+/// departure; [`Compliance::STRICT`] tolerates none and
+/// [`Compliance::LENIENT`] all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Compliance {
+    /// Accept `_` in host names: the owners of address records and the
+    /// names NS, MX, SRV and SOA records point at, in zone files.
+    pub underscores_in_hostnames: bool,
+    /// Keep TTLs above [`MAX_TTL`] as they are. Otherwise those received
+    /// count as zero, as RFC 2181 §8 says, and those in zone files are
+    /// refused.
+    pub large_ttls: bool,
+    /// Follow compression pointers in the names of SRV and DNAME data,
+    /// which must be written uncompressed.
+    pub compressed_rdata: bool,
+    /// Ignore bytes after the last record of a message instead of
+    /// refusing it.
+    pub trailing_bytes: bool,
+    /// Answer the first question of a query with several, instead of
+    /// refusing it with FORMERR.
+    pub multiple_questions: bool,
+    /// Accept TXT records without a single string, which RFC 1035 §3.3.14
+    /// requires.
+    pub empty_txt: bool,
+}
+
+impl Compliance {
+    /// Every departure from the RFCs refused.
+    pub const STRICT: Compliance = Compliance {
+        underscores_in_hostnames: false,
+        large_ttls: false,
+        compressed_rdata: false,
+        trailing_bytes: false,
+        multiple_questions: false,
+        empty_txt: false,
+    };
+
+    /// Every departure the profile knows of put up with.
+    pub const LENIENT: Compliance = Compliance {
+        underscores_in_hostnames: true,
+        large_ttls: true,
+        compressed_rdata: true,
+        trailing_bytes: true,
+        multiple_questions: true,
+        empty_txt: true,
+    };
+
+    /// Checks what a zone file holds beyond its syntax: the host names of
+    /// `record`, and its TTL.
+    pub fn check_record(&self, record: &Record) -> Result<(), Violation> {
+        if !self.large_ttls && record.ttl > MAX_TTL {
+            return Err(Violation::LargeTtl(record.ttl));
+        }
+        if !self.empty_txt && matches!(&record.rdata, RData::Txt(strings) if strings.is_empty()) {
+            return Err(Violation::EmptyTxt);
+        }
+        if self.underscores_in_hostnames {
+            return Ok(());
+        }
+        let hostnames: Vec<&DomainName> = match &record.rdata {
+            RData::A(_) | RData::Aaaa(_) => vec![&record.name],
+            RData::Ns(target) => vec![target],
+            RData::Mx { exchange, .. } => vec![exchange],
+            RData::Srv { target, .. } => vec![target],
+            RData::Soa(soa) => vec![&soa.mname],
+            _ => Vec::new(),
+        };
+        match hostnames.into_iter().find(|n| !is_hostname(n)) {
+            Some(name) => Err(Violation::Hostname(name.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lenient where refusing would break things in common use, such as
+/// underscores in host names and compressed SRV targets, but refusing
+/// bytes after a message, which more often mean a corrupt or misframed
+/// message than a quirk.
+impl Default for Compliance {
+    fn default() -> Compliance {
+        Compliance {
+            trailing_bytes: false,
+            ..Compliance::LENIENT
+        }
+    }
+}
+
+/// Whether `name` is a host name as RFC 952 and RFC 1123 §2.1 have it:
+/// labels of letters, digits and inner hyphens, with a leading `*` label
+/// allowed for wildcards.
+pub fn is_hostname(name: &DomainName) -> bool {
+    name.labels().iter().enumerate().all(|(i, label)| {
+        (i == 0 && label.as_slice() == b"*")
+            || (!label.is_empty()
+                && !label.starts_with(b"-")
+                && !label.ends_with(b"-")
+                && label
+                    .iter()
+                    .all(|&b| b.is_ascii_alphanumeric() || b == b'-'))
+    })
+}
+
+/// A departure from the RFCs a [`Compliance`] profile refuses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A host name with other characters than letters, digits and
+    /// hyphens.
+    Hostname(DomainName),
+    /// A TTL above [`MAX_TTL`].
+    LargeTtl(u32),
+    /// TXT data without a string.
+    EmptyTxt,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Hostname(name) => write!(f, "{} is not a valid host name", name),
+            Violation::LargeTtl(ttl) => write!(f, "TTL {} is above 2^31 - 1", ttl),
+            Violation::EmptyTxt => f.write_str("TXT record without a string"),
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
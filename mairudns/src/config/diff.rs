@@ -0,0 +1,205 @@
+//! What changed between two configurations, so that a running server can
+//! take up a reloaded one by touching only what differs.
+
+use super::{same_name, Config, ListenerConfig, RouteConfig, Transport, ZoneConfig};
+
+/// Sections a running server only takes up when restarted, by their names
+/// in a configuration file.
+pub const RESTART_SECTIONS: &[&str] = &[
+    "server",
+    "cache-persistence",
+    "discovery-proxy",
+    "telemetry",
+    "compliance",
+    "control",
+    "api",
+];
+
+/// The differences between a running configuration and a new one, from
+/// [`Config::diff`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    /// The top-level sections that differ, by their names in a
+    /// configuration file, such as `rate-limit`.
+    pub sections: Vec<&'static str>,
+    pub listeners_added: Vec<ListenerConfig>,
+    pub listeners_removed: Vec<ListenerConfig>,
+    /// Zones as [`Config::effective_zones`] gives them, matched by name
+    /// and view. A zone counts as changed when its settings differ or the
+    /// TSIG keys it names do.
+    pub zones_added: Vec<ZoneConfig>,
+    pub zones_removed: Vec<ZoneConfig>,
+    /// The new settings of the changed zones.
+    pub zones_changed: Vec<ZoneConfig>,
+    /// Names of the views added, removed or changed.
+    pub views_changed: Vec<String>,
+    /// Forwarding routes, matched by suffix.
+    pub routes_added: Vec<RouteConfig>,
+    pub routes_removed: Vec<RouteConfig>,
+    /// The new settings of the changed routes.
+    pub routes_changed: Vec<RouteConfig>,
+}
+
+impl ConfigDiff {
+    /// Whether the configurations are the same.
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Whether the section of the given name differs.
+    pub fn changed(&self, section: &str) -> bool {
+        self.sections.contains(&section)
+    }
+
+    /// Whether the zone of the given name and view is added, removed or
+    /// changed.
+    pub fn zone_changed(&self, name: &str, view: Option<&str>) -> bool {
+        self.zones_added
+            .iter()
+            .chain(&self.zones_removed)
+            .chain(&self.zones_changed)
+            .any(|z| same_name(&z.name, name) && z.view.as_deref() == view)
+    }
+
+    /// The changed sections that only take effect on a restart: those of
+    /// [`RESTART_SECTIONS`], and `listeners` if a listener added or
+    /// removed cannot be opened or closed while running.
+    pub fn needs_restart(&self) -> Vec<&'static str> {
+        let mut sections: Vec<_> = self
+            .sections
+            .iter()
+            .copied()
+            .filter(|s| RESTART_SECTIONS.contains(s))
+            .collect();
+        if self
+            .listeners_added
+            .iter()
+            .chain(&self.listeners_removed)
+            .any(|l| !l.is_reconfigurable())
+        {
+            sections.push("listeners");
+        }
+        sections
+    }
+}
+
+impl ListenerConfig {
+    /// Whether the listener can be opened and closed while the server
+    /// runs: plain DNS, UDP or TCP without workers, XDP or the PROXY
+    /// protocol.
+    pub fn is_reconfigurable(&self) -> bool {
+        matches!(
+            self.transport,
+            Transport::Dns | Transport::Udp | Transport::Tcp
+        ) && self.workers.is_none()
+            && self.xdp_interface.is_none()
+            && !self.proxy_protocol
+    }
+}
+
+impl Config {
+    /// Compares the configuration with a new one.
+    pub fn diff(&self, new: &Config) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        macro_rules! sections {
+            ($($field:ident => $name:literal),* $(,)?) => {
+                $(if self.$field != new.$field {
+                    diff.sections.push($name);
+                })*
+            };
+        }
+        sections! {
+            server => "server",
+            listeners => "listeners",
+            keys => "keys",
+            zone_defaults => "zone-defaults",
+            views => "views",
+            zones => "zones",
+            forwarders => "forwarders",
+            cache_persistence => "cache-persistence",
+            acl => "acl",
+            rate_limit => "rate-limit",
+            quota => "quota",
+            blocklists => "blocklists",
+            pools => "pools",
+            answer_order => "answer-order",
+            ttl_rules => "ttl-rules",
+            discovery_proxy => "discovery-proxy",
+            identity => "identity",
+            compliance => "compliance",
+            query_log => "query-log",
+            telemetry => "telemetry",
+            control => "control",
+            api => "api",
+            scripts => "scripts",
+        }
+        if diff.is_empty() {
+            return diff;
+        }
+
+        diff.listeners_added = missing(&new.listeners, &self.listeners, |a, b| a == b);
+        diff.listeners_removed = missing(&self.listeners, &new.listeners, |a, b| a == b);
+
+        let changed_keys: Vec<&str> = self
+            .keys
+            .iter()
+            .filter(|k| !new.keys.contains(k))
+            .map(|k| k.name.as_str())
+            .collect();
+        let uses_changed_key = |zone: &ZoneConfig| {
+            [&zone.primary_key, &zone.transfer_key]
+                .iter()
+                .filter_map(|k| k.as_deref())
+                .any(|k| changed_keys.iter().any(|c| same_name(c, k)))
+        };
+        let (old_zones, new_zones) = (self.effective_zones(), new.effective_zones());
+        let same_zone =
+            |a: &ZoneConfig, b: &ZoneConfig| same_name(&a.name, &b.name) && a.view == b.view;
+        diff.zones_added = missing(&new_zones, &old_zones, same_zone);
+        diff.zones_removed = missing(&old_zones, &new_zones, same_zone);
+        diff.zones_changed = new_zones
+            .iter()
+            .filter(|z| {
+                old_zones
+                    .iter()
+                    .any(|old| same_zone(old, z) && (old != *z || uses_changed_key(z)))
+            })
+            .cloned()
+            .collect();
+
+        for view in &self.views {
+            if !new.views.contains(view) {
+                diff.views_changed.push(view.name.clone());
+            }
+        }
+        for view in &new.views {
+            if !self.views.iter().any(|v| v.name == view.name) {
+                diff.views_changed.push(view.name.clone());
+            }
+        }
+
+        let same_route = |a: &RouteConfig, b: &RouteConfig| same_name(&a.suffix, &b.suffix);
+        diff.routes_added = missing(&new.forwarders, &self.forwarders, same_route);
+        diff.routes_removed = missing(&self.forwarders, &new.forwarders, same_route);
+        diff.routes_changed = new
+            .forwarders
+            .iter()
+            .filter(|r| {
+                self.forwarders
+                    .iter()
+                    .any(|old| same_route(old, r) && old != *r)
+            })
+            .cloned()
+            .collect();
+        diff
+    }
+}
+
+/// The items of `items` with no match in `others`.
+fn missing<T: Clone>(items: &[T], others: &[T], matches: impl Fn(&T, &T) -> bool) -> Vec<T> {
+    items
+        .iter()
+        .filter(|a| !others.iter().any(|b| matches(a, b)))
+        .cloned()
+        .collect()
+}
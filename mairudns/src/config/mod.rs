@@ -0,0 +1,2063 @@
+//! The configuration of a server: listeners, keys, zones, forwarding,
+//! access control and policies, as plain data.
+//!
+//! A [`Config`] is built in code with its builder methods or, with the
+//! `toml` or `yaml` features, read from a file. Every section has
+//! defaults, so a file only needs what differs from them. Names, prefixes
+//! and the like are kept as text; [`Config::validate`] checks that they
+//! parse and that what refers to what exists, such as the TSIG keys named
+//! by zones, and reports every problem at once.
+//!
+//! Zones inherit the settings they leave unset from their view and from
+//! the defaults of the whole configuration;
+//! [`Config::effective_zone`] gives a zone as it ends up.
+//!
+//! [`Config::diff`] compares a running configuration with a reloaded one,
+//! section by section and down to single listeners, zones and routes.
+
+mod diff;
+mod validate;
+
+pub use self::diff::{ConfigDiff, RESTART_SECTIONS};
+pub use self::validate::Problem;
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::name::DomainName;
+
+/// Errors produced when loading a configuration.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The text is not valid TOML or YAML, or does not fit the model.
+    Syntax(String),
+    /// The file extension names no supported format.
+    UnknownFormat(PathBuf),
+    /// The configuration parses but is inconsistent.
+    Invalid(Vec<Problem>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "cannot read configuration: {}", e),
+            Error::Syntax(e) => write!(f, "malformed configuration: {}", e),
+            Error::UnknownFormat(path) => {
+                write!(f, "unknown configuration format: {}", path.display())
+            }
+            Error::Invalid(problems) => {
+                f.write_str("invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// Whether two names in the configuration are the same, as names are
+/// compared: ignoring case and a final dot.
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// The whole configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct Config {
+    pub server: ServerSettings,
+    pub listeners: Vec<ListenerConfig>,
+    pub keys: Vec<KeyConfig>,
+    /// Settings every zone has unless its view or the zone itself says
+    /// otherwise.
+    pub zone_defaults: ZoneSettings,
+    pub views: Vec<ViewConfig>,
+    pub zones: Vec<ZoneConfig>,
+    /// Forwarding routes; queries outside every zone go to the most
+    /// specific route covering them.
+    pub forwarders: Vec<RouteConfig>,
+    /// Keeping the forwarding cache across restarts.
+    pub cache_persistence: CachePersistenceConfig,
+    pub acl: AclConfig,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Per-client quotas on recursive queries.
+    pub quota: Option<QuotaConfig>,
+    pub blocklists: Vec<BlocklistConfig>,
+    /// Health-checked answer pools.
+    pub pools: Vec<PoolConfig>,
+    /// The order of records in answers.
+    pub answer_order: AnswerOrderConfig,
+    /// Changes to the TTLs of answers sent to clients.
+    pub ttl_rules: Vec<TtlRuleConfig>,
+    /// Answering for mDNS names on local links under unicast domains.
+    pub discovery_proxy: Option<DiscoveryProxyConfig>,
+    pub identity: IdentityConfig,
+    /// What requests and zone files may get away with that the RFCs
+    /// forbid.
+    pub compliance: ComplianceConfig,
+    pub query_log: Option<QueryLogConfig>,
+    /// Classifying requests, for the `recent` control command.
+    pub telemetry: Option<TelemetryConfig>,
+    pub control: Option<ControlConfig>,
+    pub api: Option<ApiConfig>,
+    /// Rhai scripts run as plugins, in order, with the `script` feature; see
+    /// `server::ScriptPlugin`.
+    pub scripts: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    pub fn listener(mut self, listener: ListenerConfig) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    pub fn key(mut self, key: KeyConfig) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn zone_defaults(mut self, settings: ZoneSettings) -> Self {
+        self.zone_defaults = settings;
+        self
+    }
+
+    pub fn view(mut self, view: ViewConfig) -> Self {
+        self.views.push(view);
+        self
+    }
+
+    pub fn zone(mut self, zone: ZoneConfig) -> Self {
+        self.zones.push(zone);
+        self
+    }
+
+    pub fn forwarder(mut self, route: RouteConfig) -> Self {
+        self.forwarders.push(route);
+        self
+    }
+
+    pub fn cache_persistence(mut self, persistence: CachePersistenceConfig) -> Self {
+        self.cache_persistence = persistence;
+        self
+    }
+
+    pub fn acl(mut self, acl: AclConfig) -> Self {
+        self.acl = acl;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimitConfig) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    pub fn quota(mut self, quota: QuotaConfig) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    pub fn discovery_proxy(mut self, proxy: DiscoveryProxyConfig) -> Self {
+        self.discovery_proxy = Some(proxy);
+        self
+    }
+
+    pub fn blocklist(mut self, blocklist: BlocklistConfig) -> Self {
+        self.blocklists.push(blocklist);
+        self
+    }
+
+    pub fn pool(mut self, pool: PoolConfig) -> Self {
+        self.pools.push(pool);
+        self
+    }
+
+    pub fn answer_order(mut self, order: AnswerOrderConfig) -> Self {
+        self.answer_order = order;
+        self
+    }
+
+    pub fn ttl_rule(mut self, rule: TtlRuleConfig) -> Self {
+        self.ttl_rules.push(rule);
+        self
+    }
+
+    pub fn query_log(mut self, log: QueryLogConfig) -> Self {
+        self.query_log = Some(log);
+        self
+    }
+
+    pub fn telemetry(mut self, telemetry: TelemetryConfig) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    pub fn control(mut self, control: ControlConfig) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    pub fn api(mut self, api: ApiConfig) -> Self {
+        self.api = Some(api);
+        self
+    }
+
+    pub fn script<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.scripts.push(path.into());
+        self
+    }
+
+    /// The key named `name`, if configured.
+    pub fn find_key(&self, name: &str) -> Option<&KeyConfig> {
+        self.keys.iter().find(|k| same_name(&k.name, name))
+    }
+
+    /// The view named `name`, if configured.
+    pub fn find_view(&self, name: &str) -> Option<&ViewConfig> {
+        self.views.iter().find(|v| v.name == name)
+    }
+
+    /// The zone named `name` in the view `view`, or outside every view if
+    /// `view` is `None`, with the settings it inherits from its view and
+    /// from [`zone_defaults`](Config::zone_defaults) filled in. Nothing
+    /// is left to inherit: a setting still unset has its default, and
+    /// empty keys and report channels are unset.
+    pub fn effective_zone(&self, name: &str, view: Option<&str>) -> Option<ZoneConfig> {
+        self.zones
+            .iter()
+            .find(|z| same_name(&z.name, name) && z.view.as_deref() == view)
+            .map(|z| self.resolve(z))
+    }
+
+    /// Every zone as [`effective_zone`](Config::effective_zone) gives it.
+    pub fn effective_zones(&self) -> Vec<ZoneConfig> {
+        self.zones.iter().map(|z| self.resolve(z)).collect()
+    }
+
+    fn resolve(&self, zone: &ZoneConfig) -> ZoneConfig {
+        let mut settings = zone.settings();
+        if let Some(view) = zone.view.as_deref().and_then(|v| self.find_view(v)) {
+            settings = settings.inherit(&view.zone_defaults);
+        }
+        settings = settings.inherit(&self.zone_defaults);
+        let set = |s: Option<String>| s.filter(|s| !s.is_empty());
+        settings.primary_key = set(settings.primary_key);
+        settings.transfer_key = set(settings.transfer_key);
+        settings.report_channel = set(settings.report_channel);
+        settings.ttl_rules = settings.ttl_rules.map(|rules| {
+            rules
+                .into_iter()
+                .filter_map(|rule| rule.within_zone(&zone.name))
+                .collect()
+        });
+        zone.clone().with_settings(settings)
+    }
+
+    /// Parses TOML text and validates the result.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(text: &str) -> Result<Config, Error> {
+        let config: Config = toml::from_str(text).map_err(|e| Error::Syntax(e.to_string()))?;
+        config.validate().map_err(Error::Invalid)?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string_pretty(self).map_err(|e| Error::Syntax(e.to_string()))
+    }
+
+    /// Parses YAML text and validates the result.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(text: &str) -> Result<Config, Error> {
+        let config: Config =
+            serde_yaml::from_str(text).map_err(|e| Error::Syntax(e.to_string()))?;
+        config.validate().map_err(Error::Invalid)?;
+        Ok(config)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        serde_yaml::to_string(self).map_err(|e| Error::Syntax(e.to_string()))
+    }
+
+    /// Reads and validates the file at `path`, in the format its extension
+    /// names: `.toml`, or `.yaml` or `.yml`.
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Config, Error> {
+        let path = path.into();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension {
+            #[cfg(feature = "toml")]
+            "toml" => Config::from_toml(&std::fs::read_to_string(&path)?),
+            #[cfg(feature = "yaml")]
+            "yaml" | "yml" => Config::from_yaml(&std::fs::read_to_string(&path)?),
+            _ => Err(Error::UnknownFormat(path)),
+        }
+    }
+}
+
+/// Settings of the server frontend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ServerSettings {
+    pub tcp_idle_timeout_secs: u64,
+    pub max_tcp_connections: usize,
+    pub max_transfers: usize,
+    pub udp_threads: usize,
+    /// How UDP sockets are served.
+    pub udp_backend: UdpBackend,
+    /// How long shutdown waits for requests under way.
+    pub drain_timeout_secs: u64,
+    /// Padding block length for encrypted responses; zero disables it.
+    pub padding_block: usize,
+    /// The largest UDP response, whatever the client offers.
+    pub max_udp_size: usize,
+    /// Leave authority and additional records out of positive answers.
+    pub minimal_responses: bool,
+    /// Retry oversized UDP responses as minimal ones before truncating.
+    pub minimal_on_overflow: bool,
+    /// Send truncated responses without any records.
+    pub empty_truncated: bool,
+    /// Accept DNS Stateful Operations sessions on TCP and TLS.
+    pub dso: bool,
+    /// How long a DSO session may stay open with no queries; zero is no
+    /// limit.
+    pub dso_inactivity_timeout_secs: u64,
+    /// How often DSO clients must send something; zero is no limit.
+    pub dso_keepalive_interval_secs: u64,
+    /// Take DNS Push Notification subscriptions on DSO sessions.
+    pub push: bool,
+    /// How often subscribed records are looked up again for changes.
+    pub push_poll_interval_ms: u64,
+    /// Subscriptions one session may hold.
+    pub push_max_subscriptions: usize,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        ServerSettings {
+            tcp_idle_timeout_secs: 10,
+            max_tcp_connections: 1000,
+            max_transfers: 10,
+            udp_threads: 4,
+            udp_backend: UdpBackend::Threads,
+            drain_timeout_secs: 5,
+            padding_block: 468,
+            max_udp_size: 1232,
+            minimal_responses: false,
+            minimal_on_overflow: true,
+            empty_truncated: false,
+            dso: true,
+            dso_inactivity_timeout_secs: 15,
+            dso_keepalive_interval_secs: 15,
+            push: false,
+            push_poll_interval_ms: 1000,
+            push_max_subscriptions: 64,
+        }
+    }
+}
+
+/// How UDP sockets are served.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum UdpBackend {
+    #[default]
+    Threads,
+    /// io_uring on Linux, falling back to threads where it is missing.
+    IoUring,
+}
+
+/// What a listener serves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum Transport {
+    /// DNS over UDP and TCP on the same port.
+    Dns,
+    Udp,
+    Tcp,
+    Tls,
+    Https,
+    /// DNS over cleartext HTTP, behind a proxy terminating TLS.
+    Http,
+    /// Prometheus metrics.
+    Metrics,
+}
+
+impl Transport {
+    /// Whether a certificate and key are needed.
+    pub fn is_encrypted(self) -> bool {
+        matches!(self, Transport::Tls | Transport::Https)
+    }
+}
+
+/// An address to serve on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ListenerConfig {
+    pub transport: Transport,
+    pub address: SocketAddr,
+    /// Sockets bound with `SO_REUSEPORT`, each with a thread of its own;
+    /// for `dns` and `udp` only.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub workers: Option<usize>,
+    /// PEM certificate chain, for `tls` and `https`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub certificate: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub private_key: Option<PathBuf>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_connections: Option<usize>,
+    /// Interface to attach the experimental AF_XDP fast path to; for `dns`
+    /// and `udp` only. Listeners on the same interface share it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub xdp_interface: Option<String>,
+    /// Receive queues of the interface served, from queue 0; one by
+    /// default.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub xdp_queues: Option<u32>,
+    /// Connections open with a PROXY protocol v2 header from a load
+    /// balancer; for the TCP side of `dns`, and `tcp` and `http`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub proxy_protocol: bool,
+}
+
+impl ListenerConfig {
+    pub fn new(transport: Transport, address: SocketAddr) -> ListenerConfig {
+        ListenerConfig {
+            transport,
+            address,
+            workers: None,
+            certificate: None,
+            private_key: None,
+            max_connections: None,
+            xdp_interface: None,
+            xdp_queues: None,
+            proxy_protocol: false,
+        }
+    }
+
+    /// DNS over UDP and TCP.
+    pub fn dns(address: SocketAddr) -> ListenerConfig {
+        ListenerConfig::new(Transport::Dns, address)
+    }
+
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = Some(workers);
+        self
+    }
+
+    pub fn certificate<P: Into<PathBuf>, Q: Into<PathBuf>>(mut self, chain: P, key: Q) -> Self {
+        self.certificate = Some(chain.into());
+        self.private_key = Some(key.into());
+        self
+    }
+
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    pub fn xdp<S: Into<String>>(mut self, interface: S, queues: u32) -> Self {
+        self.xdp_interface = Some(interface.into());
+        self.xdp_queues = Some(queues);
+        self
+    }
+
+    pub fn proxy_protocol(mut self) -> Self {
+        self.proxy_protocol = true;
+        self
+    }
+}
+
+/// A TSIG key.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct KeyConfig {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "default_algorithm"))]
+    pub algorithm: String,
+    /// The secret in base64.
+    pub secret: String,
+}
+
+#[cfg(feature = "serde")]
+fn default_algorithm() -> String {
+    "hmac-sha256".into()
+}
+
+impl fmt::Debug for KeyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyConfig")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyConfig {
+    /// An HMAC-SHA256 key.
+    pub fn new(name: impl Into<String>, secret: impl Into<String>) -> KeyConfig {
+        KeyConfig {
+            name: name.into(),
+            algorithm: "hmac-sha256".into(),
+            secret: secret.into(),
+        }
+    }
+}
+
+/// Whether a zone is loaded from a file, transferred from primaries or
+/// served from a database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ZoneKind {
+    #[default]
+    Primary,
+    Secondary,
+    Backend,
+}
+
+/// The databases zones can be served from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum BackendKind {
+    /// An SQLite database in the PowerDNS layout.
+    #[default]
+    Sqlite,
+    Redis,
+    /// A zone compiled with `mairu-dns zone compile`, mapped into memory.
+    Mapped,
+}
+
+/// Where a backend zone is kept.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    /// The SQLite database file, or the compiled zone of a mapped
+    /// backend.
+    pub path: Option<PathBuf>,
+    /// The Redis server.
+    pub address: Option<SocketAddr>,
+    pub password: Option<String>,
+    /// The prefix of the Redis keys.
+    pub prefix: Option<String>,
+    /// How many answers to cache; zero turns the cache off.
+    pub cache_size: usize,
+    pub cache_ttl_secs: u64,
+}
+
+impl Default for BackendConfig {
+    fn default() -> BackendConfig {
+        BackendConfig {
+            kind: BackendKind::Sqlite,
+            path: None,
+            address: None,
+            password: None,
+            prefix: None,
+            cache_size: 10_000,
+            cache_ttl_secs: 5,
+        }
+    }
+}
+
+impl fmt::Debug for BackendConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackendConfig")
+            .field("kind", &self.kind)
+            .field("path", &self.path)
+            .field("address", &self.address)
+            .field("prefix", &self.prefix)
+            .field("cache_size", &self.cache_size)
+            .field("cache_ttl_secs", &self.cache_ttl_secs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BackendConfig {
+    pub fn sqlite(path: impl Into<PathBuf>) -> BackendConfig {
+        BackendConfig {
+            path: Some(path.into()),
+            ..BackendConfig::default()
+        }
+    }
+
+    pub fn redis(address: SocketAddr) -> BackendConfig {
+        BackendConfig {
+            kind: BackendKind::Redis,
+            address: Some(address),
+            ..BackendConfig::default()
+        }
+    }
+
+    pub fn mapped(path: impl Into<PathBuf>) -> BackendConfig {
+        BackendConfig {
+            kind: BackendKind::Mapped,
+            path: Some(path.into()),
+            ..BackendConfig::default()
+        }
+    }
+}
+
+/// An authoritative zone.
+///
+/// The settings a zone shares with the [`ZoneSettings`] of its view and
+/// of the whole configuration are inherited when unset; see
+/// [`Config::effective_zone`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ZoneConfig {
+    pub name: String,
+    pub kind: ZoneKind,
+    /// The view the zone is served in; outside every view if unset.
+    pub view: Option<String>,
+    /// The master file of a primary zone.
+    pub file: Option<PathBuf>,
+    /// Where a secondary zone is transferred from.
+    pub primaries: Vec<SocketAddr>,
+    /// The key signing transfers from the primaries.
+    pub primary_key: Option<String>,
+    /// Prefixes allowed to transfer the zone.
+    pub allow_transfer: Option<Vec<String>>,
+    /// A key transfers must also be signed with.
+    pub transfer_key: Option<String>,
+    pub update_grants: Option<Vec<UpdateGrant>>,
+    /// Keys to sign the answers of a primary zone with as they are given,
+    /// each the path its `.key` and `.private` files share.
+    pub signing_keys: Option<Vec<PathBuf>>,
+    /// Files of the DNSKEY records of other providers signing the zone
+    /// too (RFC 8901), published alongside the signing keys.
+    pub foreign_keys: Option<Vec<PathBuf>>,
+    /// Checks the zone against its ZONEMD records when it is loaded or
+    /// transferred, refusing a version that does not match.
+    pub verify_zonemd: Option<bool>,
+    /// The agent domain to advertise in answers, for resolvers to send
+    /// error reports to (RFC 9567).
+    pub report_channel: Option<String>,
+    /// Changes to the TTLs of the zone's answers.
+    pub ttl_rules: Option<Vec<TtlRuleConfig>>,
+    /// The database a backend zone is served from.
+    pub backend: Option<BackendConfig>,
+    /// A DHCP server's lease file whose clients get records in the zone.
+    pub leases: Option<LeasesConfig>,
+}
+
+impl ZoneConfig {
+    pub fn primary(name: impl Into<String>, file: impl Into<PathBuf>) -> ZoneConfig {
+        ZoneConfig {
+            name: name.into(),
+            file: Some(file.into()),
+            ..ZoneConfig::default()
+        }
+    }
+
+    pub fn secondary(name: impl Into<String>, primaries: Vec<SocketAddr>) -> ZoneConfig {
+        ZoneConfig {
+            name: name.into(),
+            kind: ZoneKind::Secondary,
+            primaries,
+            ..ZoneConfig::default()
+        }
+    }
+
+    pub fn backend(name: impl Into<String>, backend: BackendConfig) -> ZoneConfig {
+        ZoneConfig {
+            name: name.into(),
+            kind: ZoneKind::Backend,
+            backend: Some(backend),
+            ..ZoneConfig::default()
+        }
+    }
+
+    pub fn primary_key(mut self, key: impl Into<String>) -> Self {
+        self.primary_key = Some(key.into());
+        self
+    }
+
+    pub fn allow_transfer(mut self, prefix: impl Into<String>) -> Self {
+        self.allow_transfer
+            .get_or_insert_with(Vec::new)
+            .push(prefix.into());
+        self
+    }
+
+    pub fn transfer_key(mut self, key: impl Into<String>) -> Self {
+        self.transfer_key = Some(key.into());
+        self
+    }
+
+    pub fn grant(mut self, grant: UpdateGrant) -> Self {
+        self.update_grants.get_or_insert_with(Vec::new).push(grant);
+        self
+    }
+
+    pub fn signing_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.signing_keys
+            .get_or_insert_with(Vec::new)
+            .push(path.into());
+        self
+    }
+
+    pub fn foreign_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.foreign_keys
+            .get_or_insert_with(Vec::new)
+            .push(path.into());
+        self
+    }
+
+    pub fn verify_zonemd(mut self) -> Self {
+        self.verify_zonemd = Some(true);
+        self
+    }
+
+    pub fn ttl_rule(mut self, rule: TtlRuleConfig) -> Self {
+        self.ttl_rules.get_or_insert_with(Vec::new).push(rule);
+        self
+    }
+
+    pub fn view(mut self, view: impl Into<String>) -> Self {
+        self.view = Some(view.into());
+        self
+    }
+
+    pub fn report_channel(mut self, agent: impl Into<String>) -> Self {
+        self.report_channel = Some(agent.into());
+        self
+    }
+
+    pub fn leases(mut self, leases: LeasesConfig) -> Self {
+        self.leases = Some(leases);
+        self
+    }
+
+    /// The settings the zone sets itself, leaving the rest to inherit.
+    pub fn settings(&self) -> ZoneSettings {
+        ZoneSettings {
+            primary_key: self.primary_key.clone(),
+            allow_transfer: self.allow_transfer.clone(),
+            transfer_key: self.transfer_key.clone(),
+            update_grants: self.update_grants.clone(),
+            signing_keys: self.signing_keys.clone(),
+            foreign_keys: self.foreign_keys.clone(),
+            verify_zonemd: self.verify_zonemd,
+            report_channel: self.report_channel.clone(),
+            ttl_rules: self.ttl_rules.clone(),
+        }
+    }
+
+    /// The zone with `settings` in place of its own.
+    pub fn with_settings(mut self, settings: ZoneSettings) -> ZoneConfig {
+        self.primary_key = settings.primary_key;
+        self.allow_transfer = settings.allow_transfer;
+        self.transfer_key = settings.transfer_key;
+        self.update_grants = settings.update_grants;
+        self.signing_keys = settings.signing_keys;
+        self.foreign_keys = settings.foreign_keys;
+        self.verify_zonemd = settings.verify_zonemd;
+        self.report_channel = settings.report_channel;
+        self.ttl_rules = settings.ttl_rules;
+        self
+    }
+}
+
+/// The settings zones inherit: those of the whole configuration, then
+/// those of a zone's view, then the zone's own. A setting that is set
+/// overrides whatever it would inherit, lists included as a whole, so
+/// that an empty list clears an inherited one; an empty key or report
+/// channel likewise stands for none.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ZoneSettings {
+    pub primary_key: Option<String>,
+    pub allow_transfer: Option<Vec<String>>,
+    pub transfer_key: Option<String>,
+    pub update_grants: Option<Vec<UpdateGrant>>,
+    /// Used by primary zones only.
+    pub signing_keys: Option<Vec<PathBuf>>,
+    pub foreign_keys: Option<Vec<PathBuf>>,
+    pub verify_zonemd: Option<bool>,
+    pub report_channel: Option<String>,
+    /// Rules above a zone apply to the zone as a whole; rules outside it
+    /// do not apply to it.
+    pub ttl_rules: Option<Vec<TtlRuleConfig>>,
+}
+
+impl ZoneSettings {
+    pub fn new() -> ZoneSettings {
+        ZoneSettings::default()
+    }
+
+    pub fn primary_key(mut self, key: impl Into<String>) -> Self {
+        self.primary_key = Some(key.into());
+        self
+    }
+
+    pub fn allow_transfer(mut self, prefixes: Vec<String>) -> Self {
+        self.allow_transfer = Some(prefixes);
+        self
+    }
+
+    pub fn transfer_key(mut self, key: impl Into<String>) -> Self {
+        self.transfer_key = Some(key.into());
+        self
+    }
+
+    pub fn update_grants(mut self, grants: Vec<UpdateGrant>) -> Self {
+        self.update_grants = Some(grants);
+        self
+    }
+
+    pub fn signing_keys(mut self, paths: Vec<PathBuf>) -> Self {
+        self.signing_keys = Some(paths);
+        self
+    }
+
+    pub fn foreign_keys(mut self, paths: Vec<PathBuf>) -> Self {
+        self.foreign_keys = Some(paths);
+        self
+    }
+
+    pub fn verify_zonemd(mut self, verify: bool) -> Self {
+        self.verify_zonemd = Some(verify);
+        self
+    }
+
+    pub fn report_channel(mut self, agent: impl Into<String>) -> Self {
+        self.report_channel = Some(agent.into());
+        self
+    }
+
+    pub fn ttl_rules(mut self, rules: Vec<TtlRuleConfig>) -> Self {
+        self.ttl_rules = Some(rules);
+        self
+    }
+
+    /// These settings over `parent`: each one set here, or else as
+    /// `parent` has it.
+    pub fn inherit(&self, parent: &ZoneSettings) -> ZoneSettings {
+        fn or<T: Clone>(own: &Option<T>, parent: &Option<T>) -> Option<T> {
+            own.as_ref().or(parent.as_ref()).cloned()
+        }
+        ZoneSettings {
+            primary_key: or(&self.primary_key, &parent.primary_key),
+            allow_transfer: or(&self.allow_transfer, &parent.allow_transfer),
+            transfer_key: or(&self.transfer_key, &parent.transfer_key),
+            update_grants: or(&self.update_grants, &parent.update_grants),
+            signing_keys: or(&self.signing_keys, &parent.signing_keys),
+            foreign_keys: or(&self.foreign_keys, &parent.foreign_keys),
+            verify_zonemd: or(&self.verify_zonemd, &parent.verify_zonemd),
+            report_channel: or(&self.report_channel, &parent.report_channel),
+            ttl_rules: or(&self.ttl_rules, &parent.ttl_rules),
+        }
+    }
+}
+
+/// A split-horizon view: the clients it serves, and the defaults of the
+/// zones in it. The zones in a view are served to its clients in place of
+/// zones outside views with the same name; the first view a client
+/// matches is its view.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ViewConfig {
+    pub name: String,
+    /// Prefixes of the clients served; every client if empty.
+    pub clients: Vec<String>,
+    /// Keys of which requests must be signed with one, if any are given.
+    pub keys: Vec<String>,
+    pub zone_defaults: ZoneSettings,
+}
+
+impl ViewConfig {
+    pub fn new(name: impl Into<String>) -> ViewConfig {
+        ViewConfig {
+            name: name.into(),
+            ..ViewConfig::default()
+        }
+    }
+
+    pub fn client(mut self, prefix: impl Into<String>) -> Self {
+        self.clients.push(prefix.into());
+        self
+    }
+
+    pub fn key(mut self, key: impl Into<String>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    pub fn zone_defaults(mut self, settings: ZoneSettings) -> Self {
+        self.zone_defaults = settings;
+        self
+    }
+}
+
+/// The formats of DHCP lease files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum LeaseFormatConfig {
+    /// ISC dhcpd's `dhcpd.leases`.
+    #[default]
+    IscDhcpd,
+    /// Kea's memfile CSV.
+    KeaCsv,
+    /// The JSON of Kea's `lease4-get-all` and `lease6-get-all`.
+    KeaJson,
+}
+
+/// A lease file whose named clients get A, AAAA and PTR records.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct LeasesConfig {
+    pub file: PathBuf,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub format: LeaseFormatConfig,
+    /// Keeps PTR records too, in the reverse zones served here.
+    #[cfg_attr(feature = "serde", serde(default = "default_reverse"))]
+    pub reverse: bool,
+    /// The largest TTL of the records, which otherwise have half the
+    /// lease lifetime.
+    #[cfg_attr(feature = "serde", serde(default = "default_lease_max_ttl"))]
+    pub max_ttl: u32,
+    /// How often the file is checked for changes.
+    #[cfg_attr(feature = "serde", serde(default = "default_lease_interval_secs"))]
+    pub interval_secs: u64,
+}
+
+impl LeasesConfig {
+    pub fn new(file: impl Into<PathBuf>, format: LeaseFormatConfig) -> LeasesConfig {
+        LeasesConfig {
+            file: file.into(),
+            format,
+            reverse: default_reverse(),
+            max_ttl: default_lease_max_ttl(),
+            interval_secs: default_lease_interval_secs(),
+        }
+    }
+}
+
+fn default_reverse() -> bool {
+    true
+}
+
+fn default_lease_max_ttl() -> u32 {
+    300
+}
+
+fn default_lease_interval_secs() -> u64 {
+    30
+}
+
+/// Which names an [`UpdateGrant`] covers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum GrantScope {
+    /// Exactly the grant's name.
+    Name,
+    /// The grant's name and everything below it.
+    Subdomain,
+    /// The whole zone.
+    #[default]
+    Zone,
+    /// The name of the signing key.
+    KeyName,
+}
+
+/// Allows dynamic updates signed with a key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct UpdateGrant {
+    pub key: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub scope: GrantScope,
+    /// The name for the `name` and `subdomain` scopes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: Option<String>,
+    /// Record types allowed; empty allows any but SOA.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub types: Vec<String>,
+}
+
+impl UpdateGrant {
+    pub fn new(key: impl Into<String>, scope: GrantScope) -> UpdateGrant {
+        UpdateGrant {
+            key: key.into(),
+            scope,
+            name: None,
+            types: Vec::new(),
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn types<I: IntoIterator<Item = S>, S: Into<String>>(mut self, types: I) -> Self {
+        self.types = types.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+/// When a query moves on to the next matching route.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum FallthroughConfig {
+    #[default]
+    Never,
+    OnFailure,
+    OnNxdomain,
+}
+
+/// The [`Privacy`](crate::resolver::Privacy) policy of a forwarding
+/// route's upstreams, with that of some overridden.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct PrivacyConfig {
+    pub client_subnet: ClientSubnetConfig,
+    /// The longest prefixes Client Subnet addresses are cut to when
+    /// coarsened.
+    pub v4_prefix: u8,
+    pub v6_prefix: u8,
+    pub minimize_reverse: bool,
+    pub upstreams: Vec<UpstreamPrivacyConfig>,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> PrivacyConfig {
+        PrivacyConfig {
+            client_subnet: ClientSubnetConfig::default(),
+            v4_prefix: 24,
+            v6_prefix: 56,
+            minimize_reverse: false,
+            upstreams: Vec::new(),
+        }
+    }
+}
+
+/// What to do with the Client Subnet option of a query sent upstream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ClientSubnetConfig {
+    Forward,
+    Coarsen,
+    #[default]
+    Strip,
+    Suppress,
+}
+
+/// The privacy settings of one upstream that differ from its route's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct UpstreamPrivacyConfig {
+    pub address: SocketAddr,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub client_subnet: Option<ClientSubnetConfig>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub minimize_reverse: Option<bool>,
+}
+
+/// Which negative answers a forwarding route caches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum NegativeCachingConfig {
+    #[default]
+    Always,
+    /// Not those that send the query on to the next route.
+    FinalOnly,
+    Never,
+}
+
+/// Caching of a forwarding route.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct CacheConfig {
+    pub capacity: usize,
+    /// About how much memory cached responses may take; zero for no limit.
+    pub max_bytes: usize,
+    pub min_ttl: u32,
+    pub max_ttl: u32,
+    pub max_negative_ttl: u32,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            capacity: 10_000,
+            max_bytes: 0,
+            min_ttl: 0,
+            max_ttl: 86400,
+            max_negative_ttl: 3600,
+        }
+    }
+}
+
+/// Names at or below `suffix` are forwarded to `upstreams`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct RouteConfig {
+    pub suffix: String,
+    pub upstreams: Vec<SocketAddr>,
+    #[cfg_attr(feature = "serde", serde(default = "default_timeout_ms"))]
+    pub timeout_ms: u64,
+    #[cfg_attr(feature = "serde", serde(default = "default_attempts"))]
+    pub attempts: usize,
+    /// Queries each upstream may have pending at once; more go to the
+    /// next upstream, or fail if every one is at its limit.
+    #[cfg_attr(feature = "serde", serde(default = "default_max_pending"))]
+    pub max_pending: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fallthrough: FallthroughConfig,
+    /// Further sets of upstreams, asked in turn after `upstreams` when
+    /// `fallback-when` says, with the same timeouts and attempts.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fallbacks: Vec<Vec<SocketAddr>>,
+    #[cfg_attr(feature = "serde", serde(default = "default_fallback_when"))]
+    pub fallback_when: FallthroughConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cache: CacheConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub negative_caching: NegativeCachingConfig,
+    /// Validates upstream answers with DNSSEC, which needs the `dnssec`
+    /// feature; the upstreams must return DNSSEC records.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dnssec_validation: bool,
+    /// A file of trust anchors to validate with, in BIND `trust-anchors`
+    /// syntax, or in IANA's XML format if its name ends in `.xml`; the
+    /// root zone's keys if unset.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub trust_anchors: Option<PathBuf>,
+    /// Reports answers that fail validation to the agent their zone
+    /// advertises (RFC 9567).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub error_reporting: bool,
+    /// Keeps TCP connections to the upstreams open and pipelines queries
+    /// on them, instead of a connection per query.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub persistent_tcp: bool,
+    /// A SOCKS5 or HTTP CONNECT proxy to reach the upstreams through, as
+    /// a URL such as `socks5://192.0.2.1:1080`; queries then go over TCP.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub proxy: Option<String>,
+    /// What queries to the upstreams give away about their clients.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub privacy: PrivacyConfig,
+}
+
+#[cfg(feature = "serde")]
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+#[cfg(feature = "serde")]
+fn default_attempts() -> usize {
+    2
+}
+
+#[cfg(feature = "serde")]
+fn default_max_pending() -> usize {
+    256
+}
+
+#[cfg(feature = "serde")]
+fn default_fallback_when() -> FallthroughConfig {
+    FallthroughConfig::OnFailure
+}
+
+impl RouteConfig {
+    pub fn new(suffix: impl Into<String>, upstreams: Vec<SocketAddr>) -> RouteConfig {
+        RouteConfig {
+            suffix: suffix.into(),
+            upstreams,
+            timeout_ms: 5000,
+            attempts: 2,
+            max_pending: 256,
+            fallthrough: FallthroughConfig::Never,
+            fallbacks: Vec::new(),
+            fallback_when: FallthroughConfig::OnFailure,
+            cache: CacheConfig::default(),
+            negative_caching: NegativeCachingConfig::Always,
+            dnssec_validation: false,
+            trust_anchors: None,
+            error_reporting: false,
+            persistent_tcp: false,
+            proxy: None,
+            privacy: PrivacyConfig::default(),
+        }
+    }
+
+    pub fn fallthrough(mut self, fallthrough: FallthroughConfig) -> Self {
+        self.fallthrough = fallthrough;
+        self
+    }
+
+    /// Adds a set of upstreams to ask when `fallback-when` says.
+    pub fn fallback(mut self, upstreams: Vec<SocketAddr>) -> Self {
+        self.fallbacks.push(upstreams);
+        self
+    }
+
+    pub fn fallback_when(mut self, when: FallthroughConfig) -> Self {
+        self.fallback_when = when;
+        self
+    }
+
+    pub fn cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn negative_caching(mut self, negative_caching: NegativeCachingConfig) -> Self {
+        self.negative_caching = negative_caching;
+        self
+    }
+
+    /// Validates upstream answers, against the trust anchors in the file
+    /// at `anchors` if given.
+    pub fn dnssec_validation(mut self, anchors: Option<PathBuf>) -> Self {
+        self.dnssec_validation = true;
+        self.trust_anchors = anchors;
+        self
+    }
+
+    /// Reports answers that fail validation; only used with DNSSEC
+    /// validation.
+    pub fn error_reporting(mut self) -> Self {
+        self.error_reporting = true;
+        self
+    }
+
+    pub fn persistent_tcp(mut self) -> Self {
+        self.persistent_tcp = true;
+        self
+    }
+
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+}
+
+/// An ACL action.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum AclActionConfig {
+    #[default]
+    Allow,
+    Refuse,
+    Drop,
+    Truncate,
+}
+
+/// Rules for one kind of request, by longest matching prefix.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct AclRules {
+    pub default: AclActionConfig,
+    pub rules: Vec<AclRule>,
+}
+
+impl AclRules {
+    pub fn new(default: AclActionConfig) -> AclRules {
+        AclRules {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn rule(mut self, prefix: impl Into<String>, action: AclActionConfig) -> Self {
+        self.rules.push(AclRule {
+            prefix: prefix.into(),
+            action,
+        });
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct AclRule {
+    pub prefix: String,
+    pub action: AclActionConfig,
+}
+
+/// Address-based access control; everything is allowed by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct AclConfig {
+    pub queries: AclRules,
+    pub recursion: AclRules,
+    pub transfers: AclRules,
+    pub updates: AclRules,
+    pub refusal_text: Option<String>,
+}
+
+/// Response rate limiting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct RateLimitConfig {
+    pub responses_per_second: u32,
+    pub nxdomains_per_second: u32,
+    pub errors_per_second: u32,
+    pub window: u32,
+    pub slip: u32,
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    /// Prefixes never limited.
+    pub exempt: Vec<String>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            responses_per_second: 0,
+            nxdomains_per_second: 0,
+            errors_per_second: 0,
+            window: 15,
+            slip: 2,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+/// What is done with a query over quota.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum QuotaActionConfig {
+    #[default]
+    Servfail,
+    Drop,
+    Truncate,
+}
+
+/// Per-client quotas; zero leaves a limit off.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct QuotaConfig {
+    pub queries_per_second: u32,
+    /// Defaults to `queries-per-second`.
+    pub burst: Option<u32>,
+    pub max_concurrent: usize,
+    pub action: QuotaActionConfig,
+    /// Also count queries that do not ask for recursion.
+    pub all_queries: bool,
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    /// Prefixes never limited.
+    pub exempt: Vec<String>,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> QuotaConfig {
+        QuotaConfig {
+            queries_per_second: 0,
+            burst: None,
+            max_concurrent: 0,
+            action: QuotaActionConfig::Servfail,
+            all_queries: false,
+            ipv4_prefix_len: 32,
+            ipv6_prefix_len: 64,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+/// A discovery proxy answering for its links' mDNS names.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct DiscoveryProxyConfig {
+    pub links: Vec<ProxyLinkConfig>,
+    /// How long a query waits for mDNS answers when nothing is cached.
+    pub wait_ms: u64,
+    /// The largest TTL of the answers.
+    pub max_ttl: u32,
+}
+
+impl Default for DiscoveryProxyConfig {
+    fn default() -> DiscoveryProxyConfig {
+        DiscoveryProxyConfig {
+            links: Vec::new(),
+            wait_ms: 500,
+            max_ttl: 10,
+        }
+    }
+}
+
+/// One link of a discovery proxy: the unicast domain its mDNS names are
+/// given and the interface it is reached on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ProxyLinkConfig {
+    /// Such as `lab.home.arpa.`.
+    pub domain: String,
+    /// The IPv4 address of the interface; the default interface if
+    /// neither this nor `ipv6-interface` is set.
+    pub address: Option<String>,
+    /// The index of the interface, to use mDNS over IPv6 instead.
+    pub ipv6_interface: Option<u32>,
+}
+
+/// The format of a blocklist file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum BlocklistFormat {
+    /// `/etc/hosts` lines.
+    Hosts,
+    /// One domain per line.
+    Domains,
+    /// A Response Policy Zone; its origin is the list's `origin`.
+    Rpz,
+}
+
+/// What blocked names get.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum BlockAction {
+    #[default]
+    Nxdomain,
+    Nodata,
+    Sinkhole,
+    Drop,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct BlocklistConfig {
+    pub path: PathBuf,
+    pub format: BlocklistFormat,
+    /// Ignored for RPZ, whose records say what to do.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub action: BlockAction,
+    /// The origin of an RPZ.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub origin: Option<String>,
+}
+
+impl BlocklistConfig {
+    pub fn new(path: impl Into<PathBuf>, format: BlocklistFormat) -> BlocklistConfig {
+        BlocklistConfig {
+            path: path.into(),
+            format,
+            action: BlockAction::Nxdomain,
+            origin: None,
+        }
+    }
+}
+
+/// Where the forwarding cache is saved and what it is warmed with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct CachePersistenceConfig {
+    /// The snapshot read on start and written on shutdown.
+    pub snapshot: Option<PathBuf>,
+    /// How often the snapshot is also written while serving; zero writes
+    /// it on shutdown only.
+    pub snapshot_interval_secs: u64,
+    /// Query logs or lists of names whose most popular questions are
+    /// resolved on start.
+    pub warm_from: Vec<PathBuf>,
+    /// How many questions warming resolves.
+    pub warm_limit: usize,
+}
+
+impl Default for CachePersistenceConfig {
+    fn default() -> CachePersistenceConfig {
+        CachePersistenceConfig {
+            snapshot: None,
+            snapshot_interval_secs: 300,
+            warm_from: Vec::new(),
+            warm_limit: 1000,
+        }
+    }
+}
+
+/// How pool targets are probed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ProbeKind {
+    #[default]
+    Tcp,
+    Http,
+    Icmp,
+}
+
+/// How a pool that gives out fewer targets than it has chooses them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum SelectionKind {
+    /// Afresh for each query.
+    #[default]
+    Random,
+    /// The same for every client in a subnet.
+    ClientSubnet,
+}
+
+/// A pool target with a weight or failover tier.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct PoolTargetConfig {
+    pub address: IpAddr,
+    #[cfg_attr(feature = "serde", serde(default = "default_weight"))]
+    pub weight: u32,
+    /// Targets are only given out while no lower tier has a healthy one.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tier: u32,
+}
+
+#[cfg(feature = "serde")]
+fn default_weight() -> u32 {
+    1
+}
+
+/// A name whose A and AAAA answers only hold the targets that pass health
+/// checks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct PoolConfig {
+    pub name: String,
+    pub targets: Vec<IpAddr>,
+    pub probe: ProbeKind,
+    /// The port TCP and HTTP probes connect to; HTTP defaults to 80.
+    pub port: Option<u16>,
+    /// What HTTP probes fetch.
+    pub path: String,
+    /// The `Host` header of HTTP probes.
+    pub host: Option<String>,
+    pub interval_secs: u64,
+    pub timeout_secs: u64,
+    /// Successes in a row that bring a target back.
+    pub rise: u32,
+    /// Failures in a row that withdraw a target.
+    pub fall: u32,
+    /// Fewest addresses of each type an answer keeps, healthy or not.
+    pub min_answers: usize,
+    /// Targets with weights or tiers, besides those of weight 1 in tier
+    /// 0 in `targets`.
+    pub weighted_targets: Vec<PoolTargetConfig>,
+    /// Most targets of each type an answer holds; 0 for all.
+    pub answers: usize,
+    pub selection: SelectionKind,
+    /// The subnets that `client-subnet` selection gives the same targets.
+    pub subnet_prefix_v4: u8,
+    pub subnet_prefix_v6: u8,
+    /// When set, the pool answers A and AAAA queries for its name itself,
+    /// with this TTL.
+    pub ttl: Option<u32>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            name: String::new(),
+            targets: Vec::new(),
+            probe: ProbeKind::Tcp,
+            port: None,
+            path: "/".into(),
+            host: None,
+            interval_secs: 10,
+            timeout_secs: 2,
+            rise: 2,
+            fall: 3,
+            min_answers: 1,
+            weighted_targets: Vec::new(),
+            answers: 0,
+            selection: SelectionKind::Random,
+            subnet_prefix_v4: 24,
+            subnet_prefix_v6: 56,
+            ttl: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn new(name: impl Into<String>, targets: Vec<IpAddr>, probe: ProbeKind) -> PoolConfig {
+        PoolConfig {
+            name: name.into(),
+            targets,
+            probe,
+            ..PoolConfig::default()
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+}
+
+/// How the records of an RRset are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum OrderConfig {
+    #[default]
+    Fixed,
+    Cyclic,
+    Random,
+}
+
+/// The order of RRsets at and below a name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct NameOrderConfig {
+    pub name: String,
+    pub order: OrderConfig,
+}
+
+/// Address preferences for the clients in a prefix, most preferred
+/// first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct SortlistConfig {
+    pub clients: String,
+    pub prefer: Vec<String>,
+}
+
+/// Answer ordering; records are left as they are by default.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct AnswerOrderConfig {
+    pub order: OrderConfig,
+    pub names: Vec<NameOrderConfig>,
+    pub sortlist: Vec<SortlistConfig>,
+}
+
+impl AnswerOrderConfig {
+    pub fn new(order: OrderConfig) -> AnswerOrderConfig {
+        AnswerOrderConfig {
+            order,
+            ..AnswerOrderConfig::default()
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>, order: OrderConfig) -> Self {
+        self.names.push(NameOrderConfig {
+            name: name.into(),
+            order,
+        });
+        self
+    }
+
+    pub fn sortlist(mut self, clients: impl Into<String>, prefer: Vec<String>) -> Self {
+        self.sortlist.push(SortlistConfig {
+            clients: clients.into(),
+            prefer,
+        });
+        self
+    }
+}
+
+/// TTL changes for answers to queries for names at or below `name`; see
+/// [`TtlRule`](crate::policy::TtlRule).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct TtlRuleConfig {
+    pub name: String,
+    /// Query types the rule is for; all if empty.
+    pub types: Vec<String>,
+    pub floor: Option<u32>,
+    pub ceiling: Option<u32>,
+    pub multiplier: Option<f64>,
+    /// Bounds on how long NXDOMAIN and NODATA answers may be cached.
+    pub negative_floor: Option<u32>,
+    pub negative_ceiling: Option<u32>,
+}
+
+impl Default for TtlRuleConfig {
+    fn default() -> TtlRuleConfig {
+        TtlRuleConfig::new(".")
+    }
+}
+
+impl TtlRuleConfig {
+    /// The rule as it applies to the zone `origin`: narrowed to the zone
+    /// if it covers more, or `None` if it covers none of it. Names that
+    /// do not parse are left for validation to report.
+    fn within_zone(mut self, origin: &str) -> Option<TtlRuleConfig> {
+        let (name, origin) = match (
+            self.name.parse::<DomainName>(),
+            origin.parse::<DomainName>(),
+        ) {
+            (Ok(name), Ok(origin)) => (name, origin),
+            _ => return Some(self),
+        };
+        if origin.is_subdomain_of(&name) {
+            self.name = origin.to_string();
+        } else if !name.is_subdomain_of(&origin) {
+            return None;
+        }
+        Some(self)
+    }
+
+    pub fn new(name: impl Into<String>) -> TtlRuleConfig {
+        TtlRuleConfig {
+            name: name.into(),
+            types: Vec::new(),
+            floor: None,
+            ceiling: None,
+            multiplier: None,
+            negative_floor: None,
+            negative_ceiling: None,
+        }
+    }
+
+    pub fn floor(mut self, ttl: u32) -> Self {
+        self.floor = Some(ttl);
+        self
+    }
+
+    pub fn ceiling(mut self, ttl: u32) -> Self {
+        self.ceiling = Some(ttl);
+        self
+    }
+
+    pub fn multiplier(mut self, factor: f64) -> Self {
+        self.multiplier = Some(factor);
+        self
+    }
+
+    pub fn negative_floor(mut self, ttl: u32) -> Self {
+        self.negative_floor = Some(ttl);
+        self
+    }
+
+    pub fn negative_ceiling(mut self, ttl: u32) -> Self {
+        self.negative_ceiling = Some(ttl);
+        self
+    }
+}
+
+/// The CHAOS identity answers and NSID. Unset values are taken from the
+/// crate version and host name unless `hide` is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct IdentityConfig {
+    /// Refuses identity queries and sends no NSID.
+    pub hide: bool,
+    pub version: Option<String>,
+    pub hostname: Option<String>,
+    pub id: Option<String>,
+    pub nsid: Option<String>,
+}
+
+/// A [`Compliance`](crate::compliance::Compliance) profile, with any of
+/// its toggles overridden.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(default, rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ComplianceConfig {
+    pub profile: ComplianceProfile,
+    pub underscores_in_hostnames: Option<bool>,
+    pub large_ttls: Option<bool>,
+    pub compressed_rdata: Option<bool>,
+    pub trailing_bytes: Option<bool>,
+    pub multiple_questions: Option<bool>,
+    pub empty_txt: Option<bool>,
+}
+
+impl ComplianceConfig {
+    pub fn new(profile: ComplianceProfile) -> ComplianceConfig {
+        ComplianceConfig {
+            profile,
+            ..ComplianceConfig::default()
+        }
+    }
+}
+
+/// The profile toggles start from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum ComplianceProfile {
+    #[default]
+    Default,
+    Strict,
+    Lenient,
+}
+
+/// The format of the query log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
+pub enum LogFormat {
+    #[default]
+    Json,
+    Dnstap,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct QueryLogConfig {
+    /// A file, or for dnstap, a Unix socket if `socket` is set.
+    pub path: PathBuf,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub format: LogFormat,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub socket: bool,
+    #[cfg_attr(feature = "serde", serde(default = "default_sample_rate"))]
+    pub sample_rate: f64,
+    /// Logs client networks rather than addresses.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub anonymize: bool,
+}
+
+#[cfg(feature = "serde")]
+fn default_sample_rate() -> f64 {
+    1.0
+}
+
+impl QueryLogConfig {
+    pub fn new(path: impl Into<PathBuf>, format: LogFormat) -> QueryLogConfig {
+        QueryLogConfig {
+            path: path.into(),
+            format,
+            socket: false,
+            sample_rate: 1.0,
+            anonymize: false,
+        }
+    }
+}
+
+/// Classifying requests and keeping the last ones, which the control
+/// channel's `recent` command shows.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct TelemetryConfig {
+    /// How many of the last requests classified are kept.
+    #[cfg_attr(feature = "serde", serde(default = "default_recent_queries"))]
+    pub recent_queries: usize,
+    #[cfg_attr(feature = "serde", serde(default = "default_sample_rate"))]
+    pub sample_rate: f64,
+}
+
+#[cfg(feature = "serde")]
+fn default_recent_queries() -> usize {
+    1000
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> TelemetryConfig {
+        TelemetryConfig {
+            recent_queries: 1000,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+/// The control channel of a running server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ControlConfig {
+    /// The Unix socket commands are sent to.
+    pub socket: PathBuf,
+    /// The name of the key, among `keys`, commands are authenticated with.
+    pub key: String,
+}
+
+impl ControlConfig {
+    pub fn new(socket: impl Into<PathBuf>, key: impl Into<String>) -> ControlConfig {
+        ControlConfig {
+            socket: socket.into(),
+            key: key.into(),
+        }
+    }
+}
+
+/// The HTTP management API, served when the `api` feature is enabled.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(rename_all = "kebab-case", deny_unknown_fields)
+)]
+pub struct ApiConfig {
+    /// Where the API listens; it has no TLS of its own.
+    pub address: SocketAddr,
+    /// The bearer token every request must carry.
+    pub token: String,
+}
+
+impl fmt::Debug for ApiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiConfig")
+            .field("address", &self.address)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ApiConfig {
+    pub fn new(address: SocketAddr, token: impl Into<String>) -> ApiConfig {
+        ApiConfig {
+            address,
+            token: token.into(),
+        }
+    }
+}
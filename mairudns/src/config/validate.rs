@@ -0,0 +1,1119 @@
+//! Checking a [`Config`] and turning its sections into the types the
+//! server is built from.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "dnssec")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::addr::{IpSet, Prefix};
+use crate::client::{PoolPolicy, Proxy, TcpPool};
+use crate::compliance::Compliance;
+#[cfg(feature = "dnssec")]
+use crate::dnssec::TrustAnchors;
+use crate::dso::{self, Keepalive};
+use crate::mdns::{self, LinkInterface};
+use crate::name::DomainName;
+use crate::policy::{TtlPolicy, TtlRule};
+use crate::querylog::Redaction;
+#[cfg(feature = "dnssec")]
+use crate::resolver::ReportPolicy;
+use crate::resolver::{ClientSubnet, Privacy, Resolver, ResolverConfig, SendLimits};
+use crate::rr::RecordType;
+use crate::server::{
+    AccessControl, Acl, AclAction, AnswerOrder, AnswerOrdering, Authority, CachePolicy,
+    ClientQuota, Fallthrough, Handler, NegativeCaching, Pool, Probe, PushPolicy, QuotaAction,
+    RateLimit, ResponsePolicy, Route, Selection, ServerIdentity, SortlistEntry, Target,
+    TransferAcl, View,
+};
+use crate::tsig::{Algorithm, Key};
+use crate::zone::{BackendCache, LeaseFormat, LeaseSync, NameMatch, UpdatePolicy};
+
+use super::{
+    AclActionConfig, AclConfig, AclRules, AnswerOrderConfig, BackendConfig, BackendKind,
+    BlocklistFormat, ClientSubnetConfig, ComplianceConfig, ComplianceProfile, Config,
+    FallthroughConfig, GrantScope, IdentityConfig, KeyConfig, LeaseFormatConfig, LeasesConfig,
+    NegativeCachingConfig, OrderConfig, PoolConfig, PrivacyConfig, ProbeKind, ProxyLinkConfig,
+    QueryLogConfig, QuotaActionConfig, QuotaConfig, RateLimitConfig, RouteConfig, SelectionKind,
+    ServerSettings, Transport, TtlRuleConfig, UdpBackend, UpdateGrant, ViewConfig, ZoneConfig,
+    ZoneKind, ZoneSettings,
+};
+
+/// Something wrong with one field of a configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// Where the field is, as in `zones[2].primary-key`.
+    pub field: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Problem {
+    /// The problem with its field taken as relative to `field`.
+    fn within(mut self, field: &str) -> Problem {
+        self.field = format!("{}.{}", field, self.field);
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+fn name(field: &str, text: &str) -> Result<DomainName, Problem> {
+    text.parse()
+        .map_err(|e| Problem::new(field, format!("invalid name {:?}: {}", text, e)))
+}
+
+fn prefix(field: &str, text: &str) -> Result<Prefix, Problem> {
+    text.parse()
+        .map_err(|e| Problem::new(field, format!("invalid prefix {:?}: {}", text, e)))
+}
+
+fn proxy(field: &str, text: &str) -> Result<Proxy, Problem> {
+    text.parse().map_err(|e| Problem::new(field, e))
+}
+
+fn order(order: OrderConfig) -> AnswerOrder {
+    match order {
+        OrderConfig::Fixed => AnswerOrder::Fixed,
+        OrderConfig::Cyclic => AnswerOrder::Cyclic,
+        OrderConfig::Random => AnswerOrder::Random,
+    }
+}
+
+fn action(action: AclActionConfig) -> AclAction {
+    match action {
+        AclActionConfig::Allow => AclAction::Allow,
+        AclActionConfig::Refuse => AclAction::Refuse,
+        AclActionConfig::Drop => AclAction::Drop,
+        AclActionConfig::Truncate => AclAction::Truncate,
+    }
+}
+
+/// Collects problems from the checks of a whole configuration.
+#[derive(Default)]
+struct Checker {
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn check<T>(&mut self, result: Result<T, Problem>) -> Option<T> {
+        result.map_err(|p| self.problems.push(p)).ok()
+    }
+
+    fn check_in<T>(&mut self, field: &str, result: Result<T, Problem>) -> Option<T> {
+        self.check(result.map_err(|p| p.within(field)))
+    }
+
+    fn report(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.problems.push(Problem::new(field, message));
+    }
+
+    fn key_ref(&mut self, config: &Config, field: String, key: &str) {
+        if config.find_key(key).is_none() {
+            self.report(field, format!("no key named {:?}", key));
+        }
+    }
+
+    /// Checks zone settings at `field`, whether defaults or a zone's own.
+    /// Empty keys, which clear inherited ones, are fine.
+    fn check_settings(&mut self, config: &Config, field: &str, settings: &ZoneSettings) {
+        let keys = [
+            ("primary-key", &settings.primary_key),
+            ("transfer-key", &settings.transfer_key),
+        ];
+        for (name, key) in keys {
+            if let Some(key) = key.as_deref().filter(|k| !k.is_empty()) {
+                self.key_ref(config, format!("{}.{}", field, name), key);
+            }
+        }
+        for (i, g) in settings.update_grants.iter().flatten().enumerate() {
+            self.key_ref(
+                config,
+                format!("{}.update-grants[{}].key", field, i),
+                &g.key,
+            );
+        }
+        let zone = ZoneConfig::default().with_settings(settings.clone());
+        self.check_in(field, zone.transfer_acl());
+        self.check_in(field, zone.update_policy());
+        self.check_in(field, zone.report_agent());
+        self.check_in(field, zone.ttl_rules());
+    }
+}
+
+impl Config {
+    /// Checks that every name, prefix, key and record type parses, that
+    /// zones and keys are not defined twice, that the keys zones refer to
+    /// exist, and that each listener and zone has what its kind needs.
+    /// Returns every problem found.
+    pub fn validate(&self) -> Result<(), Vec<Problem>> {
+        let mut c = Checker::default();
+
+        if self.server.max_udp_size < 512 || self.server.max_udp_size > 65535 {
+            c.report("server.max-udp-size", "must be between 512 and 65535");
+        }
+        if self.server.dso_keepalive_interval_secs > 0
+            && self.server.dso_keepalive_interval_secs < dso::MIN_KEEPALIVE_INTERVAL.as_secs()
+        {
+            c.report(
+                "server.dso-keepalive-interval-secs",
+                "must be zero or at least 10",
+            );
+        }
+        if self.server.push && self.server.push_poll_interval_ms == 0 {
+            c.report("server.push-poll-interval-ms", "must be at least 1");
+        }
+
+        for (i, l) in self.listeners.iter().enumerate() {
+            let field = format!("listeners[{}]", i);
+            if l.transport.is_encrypted() && (l.certificate.is_none() || l.private_key.is_none()) {
+                c.report(&field, "TLS needs a certificate and a private key");
+            }
+            if l.workers == Some(0) {
+                c.report(format!("{}.workers", field), "must be at least 1");
+            }
+            if l.xdp_interface.is_some() {
+                let field = format!("{}.xdp-interface", field);
+                if !matches!(l.transport, Transport::Dns | Transport::Udp) {
+                    c.report(&field, "only for dns and udp listeners");
+                } else if l.workers.is_some() {
+                    c.report(&field, "cannot be combined with workers");
+                } else if l.address.port() == 0 {
+                    c.report(&field, "needs a port other than 0");
+                }
+                // Cache hits are answered before any of these would see
+                // them.
+                let bypassed = [
+                    (self.acl.queries != AclRules::default(), "acl.queries"),
+                    (self.acl.recursion != AclRules::default(), "acl.recursion"),
+                    (self.rate_limit.is_some(), "rate-limit"),
+                    (self.quota.is_some(), "quota"),
+                    (!self.views.is_empty(), "views"),
+                ];
+                for (set, section) in bypassed {
+                    if set {
+                        c.report(&field, format!("cannot be combined with {}", section));
+                    }
+                }
+            }
+            if l.xdp_queues == Some(0) {
+                c.report(format!("{}.xdp-queues", field), "must be at least 1");
+            }
+            if l.proxy_protocol {
+                let field = format!("{}.proxy-protocol", field);
+                if !matches!(
+                    l.transport,
+                    Transport::Dns | Transport::Tcp | Transport::Http
+                ) {
+                    c.report(&field, "only for dns, tcp and http listeners");
+                } else if l.workers.is_some() || l.xdp_interface.is_some() {
+                    c.report(&field, "cannot be combined with workers or XDP");
+                }
+            }
+        }
+
+        let mut keys = HashSet::new();
+        for (i, k) in self.keys.iter().enumerate() {
+            if let Some(key) = c.check_in(&format!("keys[{}]", i), k.to_key()) {
+                if !keys.insert(key.name) {
+                    c.report(format!("keys[{}].name", i), "defined twice");
+                }
+            }
+        }
+
+        c.check_settings(self, "zone-defaults", &self.zone_defaults);
+        let mut views = HashSet::new();
+        for (i, v) in self.views.iter().enumerate() {
+            let field = format!("views[{}]", i);
+            if v.name.is_empty() {
+                c.report(format!("{}.name", field), "must not be empty");
+            } else if !views.insert(v.name.as_str()) {
+                c.report(format!("{}.name", field), "defined twice");
+            }
+            for (j, p) in v.clients.iter().enumerate() {
+                c.check(prefix(&format!("{}.clients[{}]", field, j), p));
+            }
+            for (j, k) in v.keys.iter().enumerate() {
+                c.key_ref(self, format!("{}.keys[{}]", field, j), k);
+            }
+            c.check_settings(self, &format!("{}.zone-defaults", field), &v.zone_defaults);
+        }
+
+        let mut zones = HashSet::new();
+        for (i, (z, effective)) in self.zones.iter().zip(self.effective_zones()).enumerate() {
+            let field = format!("zones[{}]", i);
+            if let Some(origin) = c.check(name(&format!("{}.name", field), &z.name)) {
+                if !zones.insert((z.view.as_deref(), origin)) {
+                    c.report(format!("{}.name", field), "defined twice");
+                }
+            }
+            if let Some(view) = &z.view {
+                if self.find_view(view).is_none() {
+                    c.report(
+                        format!("{}.view", field),
+                        format!("no view named {:?}", view),
+                    );
+                }
+            }
+            match z.kind {
+                ZoneKind::Primary if z.file.is_none() => {
+                    c.report(&field, "a primary zone needs a file")
+                }
+                ZoneKind::Secondary if z.primaries.is_empty() => {
+                    c.report(&field, "a secondary zone needs primaries")
+                }
+                ZoneKind::Backend => match &z.backend {
+                    None => c.report(&field, "a backend zone needs a backend"),
+                    Some(b) if b.kind == BackendKind::Sqlite && b.path.is_none() => c.report(
+                        format!("{}.backend", field),
+                        "an SQLite backend needs a path",
+                    ),
+                    Some(b) if b.kind == BackendKind::Redis && b.address.is_none() => c.report(
+                        format!("{}.backend", field),
+                        "a Redis backend needs an address",
+                    ),
+                    Some(b) if b.kind == BackendKind::Mapped && b.path.is_none() => c.report(
+                        format!("{}.backend", field),
+                        "a mapped backend needs a path",
+                    ),
+                    Some(_) => {}
+                },
+                _ => {}
+            }
+            c.check_settings(self, &field, &z.settings());
+            for (j, t) in z.ttl_rules.iter().flatten().enumerate() {
+                if let (Ok(rule), Ok(origin)) = (t.name.parse::<DomainName>(), z.name.parse()) {
+                    if !rule.is_subdomain_of(&origin) && !origin.is_subdomain_of(&rule) {
+                        c.report(
+                            format!("{}.ttl-rules[{}].name", field, j),
+                            "outside the zone",
+                        );
+                    }
+                }
+            }
+            // Inherited settings a zone has no use for are ignored; only
+            // its own are held against it.
+            if z.signing_keys.as_ref().is_some_and(|k| !k.is_empty()) && z.kind != ZoneKind::Primary
+            {
+                c.report(
+                    format!("{}.signing-keys", field),
+                    "only primary zones are signed online",
+                );
+            }
+            if z.foreign_keys.as_ref().is_some_and(|k| !k.is_empty()) && !effective.is_signed() {
+                c.report(
+                    format!("{}.foreign-keys", field),
+                    "only used with signing-keys",
+                );
+            }
+            if z.verify_zonemd == Some(true) && z.kind == ZoneKind::Backend {
+                c.report(
+                    format!("{}.verify-zonemd", field),
+                    "backend zones are not verified",
+                );
+            }
+            if let Some(leases) = &z.leases {
+                if z.kind != ZoneKind::Primary {
+                    c.report(
+                        format!("{}.leases", field),
+                        "only primary zones take records from leases",
+                    );
+                }
+                if leases.interval_secs == 0 {
+                    c.report(
+                        format!("{}.leases.interval-secs", field),
+                        "must be at least 1",
+                    );
+                }
+                if leases.format == LeaseFormatConfig::KeaJson && !cfg!(feature = "kea") {
+                    c.report(
+                        format!("{}.leases.format", field),
+                        "this build cannot read Kea JSON",
+                    );
+                }
+            }
+        }
+
+        let mut suffixes = HashSet::new();
+        for (i, r) in self.forwarders.iter().enumerate() {
+            let field = format!("forwarders[{}]", i);
+            if let Some(suffix) = c.check(name(&format!("{}.suffix", field), &r.suffix)) {
+                if !suffixes.insert(suffix) {
+                    c.report(format!("{}.suffix", field), "defined twice");
+                }
+            }
+            if r.upstreams.is_empty() {
+                c.report(format!("{}.upstreams", field), "no upstreams");
+            }
+            if r.attempts == 0 {
+                c.report(format!("{}.attempts", field), "must be at least 1");
+            }
+            if r.max_pending == 0 {
+                c.report(format!("{}.max-pending", field), "must be at least 1");
+            }
+            if !r.fallbacks.is_empty() && r.fallback_when == FallthroughConfig::Never {
+                c.report(
+                    format!("{}.fallback-when", field),
+                    "fallbacks would never be asked",
+                );
+            }
+            for (j, _) in r.fallbacks.iter().enumerate().filter(|(_, f)| f.is_empty()) {
+                c.report(
+                    format!("{}.fallbacks[{}]", field, j),
+                    "a fallback needs upstreams",
+                );
+            }
+            if r.cache.min_ttl > r.cache.max_ttl {
+                c.report(format!("{}.cache", field), "min-ttl is above max-ttl");
+            }
+            if r.dnssec_validation && !cfg!(feature = "dnssec") {
+                c.report(
+                    format!("{}.dnssec-validation", field),
+                    "this build has no DNSSEC support",
+                );
+            }
+            if r.trust_anchors.is_some() && !r.dnssec_validation {
+                c.report(
+                    format!("{}.trust-anchors", field),
+                    "only used with dnssec-validation",
+                );
+            }
+            if r.error_reporting && !r.dnssec_validation {
+                c.report(
+                    format!("{}.error-reporting", field),
+                    "only used with dnssec-validation",
+                );
+            }
+            if let Some(url) = &r.proxy {
+                c.check_in(&field, proxy("proxy", url));
+            }
+            c.check_in(&field, r.privacy());
+        }
+
+        c.check_in("acl", self.acl.to_access_control());
+
+        if let Some(rrl) = &self.rate_limit {
+            c.check_in("rate-limit", rrl.to_rate_limit());
+        }
+
+        if let Some(quota) = &self.quota {
+            c.check_in("quota", quota.to_quota());
+        }
+
+        if let Some(proxy) = &self.discovery_proxy {
+            if proxy.links.is_empty() {
+                c.report("discovery-proxy.links", "no links");
+            }
+            for (i, l) in proxy.links.iter().enumerate() {
+                c.check_in(&format!("discovery-proxy.links[{}]", i), l.to_link());
+            }
+        }
+
+        c.check_in("answer-order", self.answer_order.to_answer_ordering());
+
+        for (i, t) in self.ttl_rules.iter().enumerate() {
+            c.check_in(&format!("ttl-rules[{}]", i), t.to_ttl_rule());
+        }
+
+        for (i, b) in self.blocklists.iter().enumerate() {
+            let field = format!("blocklists[{}].origin", i);
+            match (&b.origin, b.format) {
+                (Some(origin), _) => {
+                    c.check(name(&field, origin));
+                }
+                (None, BlocklistFormat::Rpz) => c.report(field, "an RPZ needs an origin"),
+                (None, _) => {}
+            }
+        }
+
+        let mut pools = HashSet::new();
+        for (i, p) in self.pools.iter().enumerate() {
+            let field = format!("pools[{}]", i);
+            if let Some(pool) = c.check_in(&field, p.to_pool()) {
+                if !pools.insert(pool.name.clone()) {
+                    c.report(format!("{}.name", field), "pool defined twice");
+                }
+            }
+        }
+
+        if let Some(log) = &self.query_log {
+            if !(0.0..=1.0).contains(&log.sample_rate) {
+                c.report("query-log.sample-rate", "must be between 0 and 1");
+            }
+        }
+
+        if let Some(telemetry) = &self.telemetry {
+            if !(0.0..=1.0).contains(&telemetry.sample_rate) {
+                c.report("telemetry.sample-rate", "must be between 0 and 1");
+            }
+            if telemetry.recent_queries == 0 {
+                c.report("telemetry.recent-queries", "must be at least 1");
+            }
+        }
+
+        if let Some(control) = &self.control {
+            c.key_ref(self, "control.key".into(), &control.key);
+        }
+
+        if let Some(api) = &self.api {
+            if api.token.trim().is_empty() {
+                c.report("api.token", "must not be empty");
+            }
+        }
+
+        if c.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(c.problems)
+        }
+    }
+
+    /// The policy of the TTL rules.
+    pub fn to_ttl_policy(&self) -> Result<TtlPolicy, Problem> {
+        let mut policy = TtlPolicy::new();
+        for (i, t) in self.ttl_rules.iter().enumerate() {
+            policy = policy.rule(
+                t.to_ttl_rule()
+                    .map_err(|p| p.within(&format!("ttl-rules[{}]", i)))?,
+            );
+        }
+        Ok(policy)
+    }
+}
+
+impl KeyConfig {
+    /// The TSIG key.
+    pub fn to_key(&self) -> Result<Key, Problem> {
+        let name = name("name", &self.name)?;
+        let algorithm: Algorithm = self.algorithm.parse().map_err(|_| {
+            Problem::new(
+                "algorithm",
+                format!("unknown algorithm {:?}", self.algorithm),
+            )
+        })?;
+        Key::from_base64(name, algorithm, &self.secret)
+            .map_err(|e| Problem::new("secret", format!("bad base64: {}", e)))
+    }
+}
+
+impl UpdateGrant {
+    fn to_rule(&self) -> Result<(DomainName, NameMatch, Vec<RecordType>), Problem> {
+        let key = name("key", &self.key)?;
+        let names = match (self.scope, &self.name) {
+            (GrantScope::Name, Some(n)) => NameMatch::Name(name("name", n)?),
+            (GrantScope::Subdomain, Some(n)) => NameMatch::Subdomain(name("name", n)?),
+            (GrantScope::Name, None) | (GrantScope::Subdomain, None) => {
+                return Err(Problem::new("name", "this scope needs a name"))
+            }
+            (GrantScope::Zone, _) => NameMatch::Zone,
+            (GrantScope::KeyName, _) => NameMatch::KeyName,
+        };
+        let types = self
+            .types
+            .iter()
+            .map(|t| {
+                t.parse()
+                    .map_err(|_| Problem::new("types", format!("unknown type {:?}", t)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((key, names, types))
+    }
+}
+
+impl ZoneConfig {
+    /// Who may update the zone.
+    pub fn update_policy(&self) -> Result<UpdatePolicy, Problem> {
+        let mut policy = UpdatePolicy::new();
+        for (i, g) in self.update_grants.iter().flatten().enumerate() {
+            let (key, names, types) = g
+                .to_rule()
+                .map_err(|p| p.within(&format!("update-grants[{}]", i)))?;
+            policy = policy.grant(key, names, types);
+        }
+        Ok(policy)
+    }
+
+    /// Who may transfer the zone. Without addresses or a transfer key,
+    /// nobody may.
+    pub fn transfer_acl(&self) -> Result<TransferAcl, Problem> {
+        let mut acl = TransferAcl::default();
+        for (i, p) in self.allow_transfer.iter().flatten().enumerate() {
+            acl.addresses
+                .push(prefix(&format!("allow-transfer[{}]", i), p)?);
+        }
+        if let Some(key) = self.transfer_key.as_deref().filter(|k| !k.is_empty()) {
+            acl.keys.push(name("transfer-key", key)?);
+        }
+        Ok(acl)
+    }
+
+    /// The agent domain the zone advertises, if any.
+    pub fn report_agent(&self) -> Result<Option<DomainName>, Problem> {
+        let agent = match self.report_channel.as_deref().filter(|a| !a.is_empty()) {
+            Some(agent) => name("report-channel", agent)?,
+            None => return Ok(None),
+        };
+        if agent.is_root() {
+            return Err(Problem::new(
+                "report-channel",
+                "the root cannot be an agent",
+            ));
+        }
+        Ok(Some(agent))
+    }
+
+    /// The rules changing the TTLs of the zone's answers.
+    pub fn ttl_rules(&self) -> Result<Vec<TtlRule>, Problem> {
+        self.ttl_rules
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, t)| {
+                t.to_ttl_rule()
+                    .map_err(|p| p.within(&format!("ttl-rules[{}]", i)))
+            })
+            .collect()
+    }
+
+    /// Whether the answers of the zone are signed online.
+    pub fn is_signed(&self) -> bool {
+        self.kind == ZoneKind::Primary && self.signing_keys.as_ref().is_some_and(|k| !k.is_empty())
+    }
+}
+
+impl ViewConfig {
+    /// The view serving its clients with `handler`.
+    pub fn to_view<H: Handler>(&self, handler: H) -> Result<View, Problem> {
+        let mut clients = IpSet::new();
+        for (i, p) in self.clients.iter().enumerate() {
+            clients.insert(prefix(&format!("clients[{}]", i), p)?);
+        }
+        if self.clients.is_empty() {
+            clients = IpSet::any();
+        }
+        let mut view = View::new(self.name.clone(), clients, handler);
+        for (i, k) in self.keys.iter().enumerate() {
+            view = view.key(name(&format!("keys[{}]", i), k)?);
+        }
+        Ok(view)
+    }
+}
+
+impl LeasesConfig {
+    /// The sync keeping the records of the leases in `zone` of
+    /// `authority`.
+    pub fn to_sync(&self, authority: Arc<Authority>, zone: DomainName) -> LeaseSync {
+        let format = match self.format {
+            LeaseFormatConfig::IscDhcpd => LeaseFormat::IscDhcpd,
+            LeaseFormatConfig::KeaCsv => LeaseFormat::KeaCsv,
+            LeaseFormatConfig::KeaJson => LeaseFormat::KeaJson,
+        };
+        LeaseSync::new(authority, zone, &self.file, format)
+            .reverse(self.reverse)
+            .max_ttl(self.max_ttl)
+            .interval(Duration::from_secs(self.interval_secs))
+    }
+}
+
+impl RouteConfig {
+    /// The forwarding route, with a resolver of its own.
+    pub fn to_route(&self) -> Result<Route, Problem> {
+        let suffix = name("suffix", &self.suffix)?;
+        let proxy = match &self.proxy {
+            Some(url) => Some(proxy("proxy", url)?),
+            None => None,
+        };
+        let (privacy, server_privacy) = self.privacy()?;
+        let resolver = |servers: &[SocketAddr]| {
+            Resolver::new(ResolverConfig {
+                servers: servers.to_vec(),
+                timeout: Duration::from_millis(self.timeout_ms),
+                attempts: self.attempts,
+                limits: SendLimits {
+                    max_pending_per_server: self.max_pending,
+                    ..SendLimits::default()
+                },
+                tcp_pool: if self.persistent_tcp {
+                    Some(TcpPool::with_proxy(PoolPolicy::default(), proxy.clone()))
+                } else {
+                    None
+                },
+                proxy: proxy.clone(),
+                privacy,
+                server_privacy: server_privacy.clone(),
+                ..ResolverConfig::default()
+            })
+        };
+        let fallthrough = |config: FallthroughConfig| match config {
+            FallthroughConfig::Never => Fallthrough::Never,
+            FallthroughConfig::OnFailure => Fallthrough::OnFailure,
+            FallthroughConfig::OnNxdomain => Fallthrough::OnNxdomain,
+        };
+        if !self.fallbacks.is_empty() && self.fallback_when == FallthroughConfig::Never {
+            return Err(Problem::new(
+                "fallback-when",
+                "fallbacks would never be asked",
+            ));
+        }
+        if let Some(i) = self.fallbacks.iter().position(Vec::is_empty) {
+            return Err(Problem::new(
+                format!("fallbacks[{}]", i),
+                "a fallback needs upstreams",
+            ));
+        }
+        let mut route = Route::new(suffix, resolver(&self.upstreams))
+            .fallthrough(fallthrough(self.fallthrough))
+            .fallback_when(fallthrough(self.fallback_when))
+            .negative_caching(match self.negative_caching {
+                NegativeCachingConfig::Always => NegativeCaching::Always,
+                NegativeCachingConfig::FinalOnly => NegativeCaching::FinalOnly,
+                NegativeCachingConfig::Never => NegativeCaching::Never,
+            })
+            .cache_policy(CachePolicy {
+                capacity: self.cache.capacity,
+                max_bytes: self.cache.max_bytes,
+                min_ttl: self.cache.min_ttl,
+                max_ttl: self.cache.max_ttl,
+                max_negative_ttl: self.cache.max_negative_ttl,
+                ..CachePolicy::default()
+            });
+        for servers in &self.fallbacks {
+            route = route.fallback(resolver(servers));
+        }
+        if !self.dnssec_validation {
+            return Ok(route);
+        }
+        #[cfg(feature = "dnssec")]
+        {
+            let route = route.validate(self.anchors()?);
+            if self.error_reporting {
+                return Ok(route.report_errors(ReportPolicy::default()));
+            }
+            Ok(route)
+        }
+        #[cfg(not(feature = "dnssec"))]
+        Err(Problem::new(
+            "dnssec-validation",
+            "this build has no DNSSEC support",
+        ))
+    }
+
+    /// The privacy policy of the route's upstreams, and those overridden
+    /// by address.
+    fn privacy(&self) -> Result<(Privacy, HashMap<SocketAddr, Privacy>), Problem> {
+        let config = &self.privacy;
+        if config.v4_prefix > 32 {
+            return Err(Problem::new("privacy.v4-prefix", "longer than 32 bits"));
+        }
+        if config.v6_prefix > 128 {
+            return Err(Problem::new("privacy.v6-prefix", "longer than 128 bits"));
+        }
+        let mut overridden = HashMap::new();
+        for (i, upstream) in config.upstreams.iter().enumerate() {
+            let field = format!("privacy.upstreams[{}].address", i);
+            if !self
+                .upstreams
+                .iter()
+                .chain(self.fallbacks.iter().flatten())
+                .any(|&a| a == upstream.address)
+            {
+                return Err(Problem::new(field, "not an upstream of the route"));
+            }
+            let privacy = Privacy {
+                client_subnet: config
+                    .client_subnet(upstream.client_subnet.unwrap_or(config.client_subnet)),
+                minimize_reverse: upstream.minimize_reverse.unwrap_or(config.minimize_reverse),
+            };
+            if overridden.insert(upstream.address, privacy).is_some() {
+                return Err(Problem::new(field, "overridden twice"));
+            }
+        }
+        Ok((config.to_privacy(), overridden))
+    }
+
+    /// The trust anchors the route validates with.
+    #[cfg(feature = "dnssec")]
+    fn anchors(&self) -> Result<TrustAnchors, Problem> {
+        let path = match &self.trust_anchors {
+            Some(path) => path,
+            None => return Ok(TrustAnchors::root()),
+        };
+        let problem = |message: String| Problem::new("trust-anchors", message);
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| problem(format!("{}: {}", path.display(), e)))?;
+        let anchors = if path.extension().is_some_and(|e| e == "xml") {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            TrustAnchors::from_iana_xml(&text, now)
+        } else {
+            TrustAnchors::from_bind(&text)
+        };
+        let anchors = anchors.map_err(|e| problem(format!("{}: {}", path.display(), e)))?;
+        if anchors.is_empty() {
+            return Err(problem(format!("{}: no trust anchors", path.display())));
+        }
+        Ok(anchors)
+    }
+}
+
+impl AclRules {
+    pub fn to_acl(&self) -> Result<Acl, Problem> {
+        let mut acl = Acl::new(action(self.default));
+        for (i, rule) in self.rules.iter().enumerate() {
+            let p = prefix(&format!("rules[{}].prefix", i), &rule.prefix)?;
+            acl = acl.rule(p, action(rule.action));
+        }
+        Ok(acl)
+    }
+}
+
+impl AclConfig {
+    pub fn to_access_control(&self) -> Result<AccessControl, Problem> {
+        let acl = |rules: &AclRules, field: &str| rules.to_acl().map_err(|p| p.within(field));
+        Ok(AccessControl {
+            queries: acl(&self.queries, "queries")?,
+            recursion: acl(&self.recursion, "recursion")?,
+            transfers: acl(&self.transfers, "transfers")?,
+            updates: acl(&self.updates, "updates")?,
+            refusal_text: self.refusal_text.clone(),
+        })
+    }
+}
+
+impl RateLimitConfig {
+    /// The rate limit; wildcard, referral and no-data responses share the
+    /// rate of plain answers.
+    pub fn to_rate_limit(&self) -> Result<RateLimit, Problem> {
+        if self.ipv4_prefix_len > 32 {
+            return Err(Problem::new("ipv4-prefix-len", "above 32"));
+        }
+        if self.ipv6_prefix_len > 128 {
+            return Err(Problem::new("ipv6-prefix-len", "above 128"));
+        }
+        let mut exempt = IpSet::new();
+        for (i, p) in self.exempt.iter().enumerate() {
+            exempt.insert(prefix(&format!("exempt[{}]", i), p)?);
+        }
+        Ok(RateLimit {
+            nxdomains_per_second: self.nxdomains_per_second,
+            errors_per_second: self.errors_per_second,
+            window: self.window,
+            slip: self.slip,
+            ipv4_prefix_len: self.ipv4_prefix_len,
+            ipv6_prefix_len: self.ipv6_prefix_len,
+            exempt,
+            ..RateLimit::new(self.responses_per_second)
+        })
+    }
+}
+
+impl QuotaConfig {
+    pub fn to_quota(&self) -> Result<ClientQuota, Problem> {
+        if self.ipv4_prefix_len > 32 {
+            return Err(Problem::new("ipv4-prefix-len", "above 32"));
+        }
+        if self.ipv6_prefix_len > 128 {
+            return Err(Problem::new("ipv6-prefix-len", "above 128"));
+        }
+        if self.burst == Some(0) {
+            return Err(Problem::new("burst", "must be at least 1"));
+        }
+        let mut exempt = IpSet::new();
+        for (i, p) in self.exempt.iter().enumerate() {
+            exempt.insert(prefix(&format!("exempt[{}]", i), p)?);
+        }
+        let action = match self.action {
+            QuotaActionConfig::Servfail => QuotaAction::ServFail,
+            QuotaActionConfig::Drop => QuotaAction::Drop,
+            QuotaActionConfig::Truncate => QuotaAction::Truncate,
+        };
+        Ok(ClientQuota {
+            burst: self.burst.unwrap_or(self.queries_per_second),
+            max_concurrent: self.max_concurrent,
+            action,
+            recursion_only: !self.all_queries,
+            ipv4_prefix_len: self.ipv4_prefix_len,
+            ipv6_prefix_len: self.ipv6_prefix_len,
+            exempt,
+            ..ClientQuota::new(self.queries_per_second)
+        })
+    }
+}
+
+impl PoolConfig {
+    pub fn to_pool(&self) -> Result<Pool, Problem> {
+        let name = name("name", &self.name)?;
+        if self.targets.is_empty() && self.weighted_targets.is_empty() {
+            return Err(Problem::new("targets", "a pool needs targets"));
+        }
+        if self.subnet_prefix_v4 > 32 {
+            return Err(Problem::new(
+                "subnet-prefix-v4",
+                "an IPv4 prefix is at most 32 bits",
+            ));
+        }
+        if self.subnet_prefix_v6 > 128 {
+            return Err(Problem::new(
+                "subnet-prefix-v6",
+                "an IPv6 prefix is at most 128 bits",
+            ));
+        }
+        let weighted = self
+            .weighted_targets
+            .iter()
+            .map(|t| Target::new(t.address).weight(t.weight).tier(t.tier));
+        let targets: Vec<Target> = self
+            .targets
+            .iter()
+            .map(|&addr| Target::new(addr))
+            .chain(weighted)
+            .collect();
+        let probe = match (self.probe, self.port) {
+            (ProbeKind::Tcp, Some(port)) => Probe::Tcp { port },
+            (ProbeKind::Tcp, None) => {
+                return Err(Problem::new("port", "TCP probes need a port"));
+            }
+            (ProbeKind::Http, port) => Probe::Http {
+                port: port.unwrap_or(80),
+                path: self.path.clone(),
+                host: self.host.clone(),
+            },
+            (ProbeKind::Icmp, _) => Probe::Icmp,
+        };
+        if self.interval_secs == 0 {
+            return Err(Problem::new("interval-secs", "must be at least 1"));
+        }
+        if self.timeout_secs == 0 || self.timeout_secs > self.interval_secs {
+            return Err(Problem::new(
+                "timeout-secs",
+                "must be at least 1 and at most interval-secs",
+            ));
+        }
+        if self.rise == 0 || self.fall == 0 {
+            return Err(Problem::new("rise", "rise and fall must be at least 1"));
+        }
+        Ok(Pool {
+            interval: Duration::from_secs(self.interval_secs),
+            timeout: Duration::from_secs(self.timeout_secs),
+            rise: self.rise,
+            fall: self.fall,
+            min_answers: self.min_answers,
+            answers: self.answers,
+            selection: match self.selection {
+                SelectionKind::Random => Selection::Random,
+                SelectionKind::ClientSubnet => Selection::ClientSubnet {
+                    v4_prefix: self.subnet_prefix_v4,
+                    v6_prefix: self.subnet_prefix_v6,
+                },
+            },
+            ttl: self.ttl,
+            ..Pool::new(name, targets, probe)
+        })
+    }
+}
+
+impl ServerSettings {
+    pub fn to_response_policy(&self) -> ResponsePolicy {
+        ResponsePolicy {
+            padding_block: self.padding_block,
+            max_udp_size: self.max_udp_size,
+            minimal_responses: self.minimal_responses,
+            minimal_on_overflow: self.minimal_on_overflow,
+            empty_truncated: self.empty_truncated,
+        }
+    }
+
+    /// The timeouts of DSO sessions, or `None` if they are not accepted.
+    pub fn to_dso(&self) -> Option<Keepalive> {
+        let secs = |s: u64| (s > 0).then(|| Duration::from_secs(s));
+        self.dso.then(|| Keepalive {
+            inactivity_timeout: secs(self.dso_inactivity_timeout_secs),
+            keepalive_interval: secs(self.dso_keepalive_interval_secs),
+        })
+    }
+
+    /// How push notifications are served, or `None` if they are not.
+    pub fn to_push(&self) -> Option<PushPolicy> {
+        self.push.then(|| PushPolicy {
+            poll_interval: Duration::from_millis(self.push_poll_interval_ms),
+            max_subscriptions: self.push_max_subscriptions,
+        })
+    }
+
+    pub fn to_udp_backend(&self) -> crate::server::UdpBackend {
+        match self.udp_backend {
+            UdpBackend::Threads => crate::server::UdpBackend::Threads,
+            UdpBackend::IoUring => crate::server::UdpBackend::IoUring,
+        }
+    }
+}
+
+impl ComplianceConfig {
+    pub fn to_compliance(&self) -> Compliance {
+        let base = match self.profile {
+            ComplianceProfile::Default => Compliance::default(),
+            ComplianceProfile::Strict => Compliance::STRICT,
+            ComplianceProfile::Lenient => Compliance::LENIENT,
+        };
+        Compliance {
+            underscores_in_hostnames: self
+                .underscores_in_hostnames
+                .unwrap_or(base.underscores_in_hostnames),
+            large_ttls: self.large_ttls.unwrap_or(base.large_ttls),
+            compressed_rdata: self.compressed_rdata.unwrap_or(base.compressed_rdata),
+            trailing_bytes: self.trailing_bytes.unwrap_or(base.trailing_bytes),
+            multiple_questions: self.multiple_questions.unwrap_or(base.multiple_questions),
+            empty_txt: self.empty_txt.unwrap_or(base.empty_txt),
+        }
+    }
+}
+
+impl PrivacyConfig {
+    /// The policy of the upstreams not overridden.
+    pub fn to_privacy(&self) -> Privacy {
+        Privacy {
+            client_subnet: self.client_subnet(self.client_subnet),
+            minimize_reverse: self.minimize_reverse,
+        }
+    }
+
+    fn client_subnet(&self, config: ClientSubnetConfig) -> ClientSubnet {
+        match config {
+            ClientSubnetConfig::Forward => ClientSubnet::Forward,
+            ClientSubnetConfig::Coarsen => ClientSubnet::Coarsen {
+                v4_prefix: self.v4_prefix,
+                v6_prefix: self.v6_prefix,
+            },
+            ClientSubnetConfig::Strip => ClientSubnet::Strip,
+            ClientSubnetConfig::Suppress => ClientSubnet::Suppress,
+        }
+    }
+}
+
+impl ProxyLinkConfig {
+    /// The domain of the link and the interface to bind it on.
+    pub fn to_link(&self) -> Result<(DomainName, LinkInterface), Problem> {
+        let domain = name("domain", &self.domain)?;
+        if domain.is_subdomain_of(&mdns::local()) {
+            return Err(Problem::new("domain", "must not be under local."));
+        }
+        let interface = match (&self.address, self.ipv6_interface) {
+            (Some(_), Some(_)) => {
+                return Err(Problem::new(
+                    "address",
+                    "cannot be set along with ipv6-interface",
+                ))
+            }
+            (None, Some(index)) => LinkInterface::V6(index),
+            (Some(a), None) => LinkInterface::V4(
+                a.parse()
+                    .map_err(|_| Problem::new("address", format!("bad IPv4 address {:?}", a)))?,
+            ),
+            (None, None) => LinkInterface::V4(Ipv4Addr::UNSPECIFIED),
+        };
+        Ok((domain, interface))
+    }
+}
+
+impl AnswerOrderConfig {
+    pub fn to_answer_ordering(&self) -> Result<AnswerOrdering, Problem> {
+        let mut ordering = AnswerOrdering::new(order(self.order));
+        for (i, n) in self.names.iter().enumerate() {
+            let field = format!("names[{}].name", i);
+            ordering = ordering.name(name(&field, &n.name)?, order(n.order));
+        }
+        for (i, s) in self.sortlist.iter().enumerate() {
+            let field = format!("sortlist[{}]", i);
+            let mut entry = SortlistEntry::new(prefix(&format!("{}.clients", field), &s.clients)?);
+            for (j, p) in s.prefer.iter().enumerate() {
+                entry = entry.prefer(prefix(&format!("{}.prefer[{}]", field, j), p)?);
+            }
+            ordering = ordering.sortlist(entry);
+        }
+        Ok(ordering)
+    }
+}
+
+impl TtlRuleConfig {
+    pub fn to_ttl_rule(&self) -> Result<TtlRule, Problem> {
+        let mut rule = TtlRule::new(name("name", &self.name)?);
+        for t in &self.types {
+            let rtype = t
+                .parse()
+                .map_err(|_| Problem::new("types", format!("unknown type {:?}", t)))?;
+            rule.types.push(rtype);
+        }
+        if let (Some(floor), Some(ceiling)) = (self.floor, self.ceiling) {
+            if floor > ceiling {
+                return Err(Problem::new("floor", "above the ceiling"));
+            }
+        }
+        if let (Some(floor), Some(ceiling)) = (self.negative_floor, self.negative_ceiling) {
+            if floor > ceiling {
+                return Err(Problem::new("negative-floor", "above the negative ceiling"));
+            }
+        }
+        if let Some(factor) = self.multiplier {
+            if !factor.is_finite() || factor < 0.0 {
+                return Err(Problem::new("multiplier", "must be a number of at least 0"));
+            }
+        }
+        rule.floor = self.floor;
+        rule.ceiling = self.ceiling;
+        rule.multiplier = self.multiplier;
+        rule.negative_floor = self.negative_floor;
+        rule.negative_ceiling = self.negative_ceiling;
+        Ok(rule)
+    }
+}
+
+impl IdentityConfig {
+    pub fn to_identity(&self) -> ServerIdentity {
+        if self.hide {
+            return ServerIdentity::hidden();
+        }
+        let defaults = ServerIdentity::new();
+        ServerIdentity {
+            version: self.version.clone().or(defaults.version),
+            hostname: self.hostname.clone().or(defaults.hostname),
+            id: self.id.clone().or(defaults.id),
+            nsid: self.nsid.clone().map(String::into_bytes).or(defaults.nsid),
+        }
+    }
+}
+
+impl QueryLogConfig {
+    pub fn redaction(&self) -> Redaction {
+        if self.anonymize {
+            Redaction::anonymize()
+        } else {
+            Redaction::default()
+        }
+    }
+}
+
+impl BackendConfig {
+    /// The limits of the cache in front of the backend.
+    pub fn to_cache(&self) -> BackendCache {
+        BackendCache {
+            capacity: self.cache_size,
+            ttl: Duration::from_secs(self.cache_ttl_secs),
+        }
+    }
+}
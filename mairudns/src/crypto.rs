@@ -0,0 +1,1164 @@
+//! Hash functions and HMAC for TSIG and record digests, the
+//! ChaCha20-Poly1305 cipher and PBKDF2 that protect stored keys, and the
+//! XSalsa20-Poly1305 box DNSCrypt encrypts with.
+//!
+//! Only what the protocol code needs, implemented here so the core crate
+//! keeps no dependencies. Apart from MAC comparison, none of this tries
+//! to resist timing side channels.
+
+/// A streaming hash function.
+pub trait Digest: Default {
+    /// Input block size in bytes, used by HMAC.
+    const BLOCK_LEN: usize;
+
+    fn update(&mut self, data: &[u8]);
+
+    fn finish(self) -> Vec<u8>;
+
+    /// Hashes `data` in one go.
+    fn digest(data: &[u8]) -> Vec<u8> {
+        let mut h = Self::default();
+        h.update(data);
+        h.finish()
+    }
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 (FIPS 180-4).
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    block: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Sha256 {
+    const BLOCK_LEN: usize = 64;
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 64 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let bits = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        self.state.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
+
+/// SHA-1 (FIPS 180-4), which DNSSEC still needs for NSEC3 hashes and
+/// old DS records.
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+#[derive(Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    block: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Sha1 {
+        Sha1 {
+            state: [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0],
+            block: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+impl Sha1 {
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Sha1 {
+    const BLOCK_LEN: usize = 64;
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 64 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let bits = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        self.state.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+/// SHA-512 (FIPS 180-4).
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+#[derive(Clone)]
+pub struct Sha512 {
+    state: [u64; 8],
+    block: Vec<u8>,
+    len: u128,
+}
+
+impl Default for Sha512 {
+    fn default() -> Sha512 {
+        Sha512::with_state([
+            0x6a09e667f3bcc908,
+            0xbb67ae8584caa73b,
+            0x3c6ef372fe94f82b,
+            0xa54ff53a5f1d36f1,
+            0x510e527fade682d1,
+            0x9b05688c2b3e6c1f,
+            0x1f83d9abfb41bd6b,
+            0x5be0cd19137e2179,
+        ])
+    }
+}
+
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+impl Sha512 {
+    fn with_state(state: [u64; 8]) -> Sha512 {
+        Sha512 {
+            state,
+            block: Vec::with_capacity(128),
+            len: 0,
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u64; 80];
+        for (i, word) in block.chunks(8).enumerate() {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(word);
+            w[i] = u64::from_be_bytes(b);
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA512_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+impl Digest for Sha512 {
+    const BLOCK_LEN: usize = 128;
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u128;
+        while !data.is_empty() {
+            let take = (128 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() == 128 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let bits = self.len.wrapping_mul(8);
+        self.update(&[0x80]);
+        while self.block.len() != 112 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        self.state.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
+
+/// SHA-384: SHA-512 from other initial values, truncated.
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+#[derive(Clone)]
+pub struct Sha384(Sha512);
+
+impl Default for Sha384 {
+    fn default() -> Sha384 {
+        Sha384(Sha512::with_state([
+            0xcbbb9d5dc1059ed8,
+            0x629a292a367cd507,
+            0x9159015a3070dd17,
+            0x152fecd8f70e5939,
+            0x67332667ffc00b31,
+            0x8eb44a8768581511,
+            0xdb0c2e0d64f98fa7,
+            0x47b5481dbefa4fa4,
+        ]))
+    }
+}
+
+impl Digest for Sha384 {
+    const BLOCK_LEN: usize = 128;
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut digest = self.0.finish();
+        digest.truncate(48);
+        digest
+    }
+}
+
+/// HMAC (RFC 2104) of `data` under `key`.
+pub fn hmac<D: Digest>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut block = if key.len() > D::BLOCK_LEN {
+        D::digest(key)
+    } else {
+        key.to_vec()
+    };
+    block.resize(D::BLOCK_LEN, 0);
+    let mut inner = D::default();
+    inner.update(&block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    inner.update(data);
+    let mut outer = D::default();
+    outer.update(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+/// Compares two MACs without stopping at the first difference.
+pub fn verify_mac(expected: &[u8], actual: &[u8]) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(actual)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// PBKDF2 (RFC 8018) with HMAC over `D`: `len` bytes of key derived
+/// from `password` and `salt`.
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+pub fn pbkdf2<D: Digest>(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut block = 1u32;
+    while out.len() < len {
+        let mut input = salt.to_vec();
+        input.extend_from_slice(&block.to_be_bytes());
+        let mut u = hmac::<D>(password, &input);
+        let mut t = u.clone();
+        for _ in 1..iterations {
+            u = hmac::<D>(password, &u);
+            t.iter_mut().zip(&u).for_each(|(t, u)| *t ^= u);
+        }
+        out.extend_from_slice(&t);
+        block += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// One 64-byte block of the ChaCha20 key stream (RFC 8439 §2.3).
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for i in 0..8 {
+        state[4 + i] = le32(&key[i * 4..]);
+    }
+    state[12] = counter;
+    for i in 0..3 {
+        state[13 + i] = le32(&nonce[i * 4..]);
+    }
+    let mut x = state;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+    let mut out = [0u8; 64];
+    for i in 0..16 {
+        out[i * 4..i * 4 + 4].copy_from_slice(&x[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    out
+}
+
+/// XORs `data` with the ChaCha20 key stream, starting at block `counter`.
+fn chacha20(key: &[u8; 32], counter: u32, nonce: &[u8; 12], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        chunk.iter_mut().zip(&stream[..]).for_each(|(b, k)| *b ^= k);
+    }
+}
+
+/// The Poly1305 one-time authenticator (RFC 8439 §2.5) of `data`.
+fn poly1305(key: &[u8; 32], data: &[u8]) -> [u8; 16] {
+    const MASK: u64 = 0x3ffffff;
+    let r = [
+        u64::from(le32(&key[0..])) & 0x3ffffff,
+        u64::from(le32(&key[3..]) >> 2) & 0x3ffff03,
+        u64::from(le32(&key[6..]) >> 4) & 0x3ffc0ff,
+        u64::from(le32(&key[9..]) >> 6) & 0x3f03fff,
+        u64::from(le32(&key[12..]) >> 8) & 0x00fffff,
+    ];
+    let s = [r[1] * 5, r[2] * 5, r[3] * 5, r[4] * 5];
+    let mut h = [0u64; 5];
+    for chunk in data.chunks(16) {
+        let mut block = [0u8; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()] = 1;
+        h[0] += u64::from(le32(&block[0..])) & MASK;
+        h[1] += u64::from(le32(&block[3..]) >> 2) & MASK;
+        h[2] += u64::from(le32(&block[6..]) >> 4) & MASK;
+        h[3] += u64::from(le32(&block[9..]) >> 6) & MASK;
+        h[4] += u64::from(le32(&block[12..]) >> 8) | (u64::from(block[16]) << 24);
+        let d = [
+            h[0] * r[0] + h[1] * s[3] + h[2] * s[2] + h[3] * s[1] + h[4] * s[0],
+            h[0] * r[1] + h[1] * r[0] + h[2] * s[3] + h[3] * s[2] + h[4] * s[1],
+            h[0] * r[2] + h[1] * r[1] + h[2] * r[0] + h[3] * s[3] + h[4] * s[2],
+            h[0] * r[3] + h[1] * r[2] + h[2] * r[1] + h[3] * r[0] + h[4] * s[3],
+            h[0] * r[4] + h[1] * r[3] + h[2] * r[2] + h[3] * r[1] + h[4] * r[0],
+        ];
+        let mut carry = 0;
+        for i in 0..5 {
+            let v = d[i] + carry;
+            h[i] = v & MASK;
+            carry = v >> 26;
+        }
+        h[0] += carry * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= MASK;
+    }
+    // Fully reduce h, then subtract p if h is at least p.
+    let mut carry = 0;
+    for _ in 0..2 {
+        for v in h.iter_mut() {
+            *v += carry;
+            carry = *v >> 26;
+            *v &= MASK;
+        }
+        h[0] += carry * 5;
+        carry = 0;
+    }
+    let mut g = [0u64; 5];
+    let mut carry = 5;
+    for i in 0..5 {
+        let v = h[i] + carry;
+        g[i] = v & MASK;
+        carry = v >> 26;
+    }
+    if carry != 0 {
+        h = g;
+    }
+    let value = u128::from(h[0])
+        | u128::from(h[1]) << 26
+        | u128::from(h[2]) << 52
+        | u128::from(h[3]) << 78
+        | u128::from(h[4]) << 104;
+    let mut pad = [0u8; 16];
+    pad.copy_from_slice(&key[16..]);
+    value.wrapping_add(u128::from_le_bytes(pad)).to_le_bytes()
+}
+
+/// The Poly1305 tag over `aad` and `ciphertext` as RFC 8439 §2.8 lays
+/// them out.
+fn aead_tag(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let mut otk = [0u8; 32];
+    otk.copy_from_slice(&chacha20_block(key, 0, nonce)[..32]);
+    let pad = |len: usize| vec![0u8; (16 - len % 16) % 16];
+    let mut mac_data = aad.to_vec();
+    mac_data.extend(pad(aad.len()));
+    mac_data.extend_from_slice(ciphertext);
+    mac_data.extend(pad(ciphertext.len()));
+    mac_data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    mac_data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(&otk, &mac_data)
+}
+
+/// Encrypts `plaintext` with ChaCha20-Poly1305 (RFC 8439), also
+/// authenticating `aad`. Returns the ciphertext with the tag appended.
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+pub fn seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut out = plaintext.to_vec();
+    chacha20(key, 1, nonce, &mut out);
+    let tag = aead_tag(key, nonce, aad, &out);
+    out.extend_from_slice(&tag);
+    out
+}
+
+/// Decrypts what [`seal`] made, or `None` if it or `aad` was tampered
+/// with or the key is wrong.
+#[cfg_attr(not(feature = "dnssec"), allow(dead_code))]
+pub fn open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < 16 {
+        return None;
+    }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - 16);
+    if !verify_mac(&aead_tag(key, nonce, aad, ciphertext), tag) {
+        return None;
+    }
+    let mut out = ciphertext.to_vec();
+    chacha20(key, 1, nonce, &mut out);
+    Some(out)
+}
+
+/// The Salsa20 double rounds (the Salsa20 specification, §4) applied ten
+/// times to `state`, without the final addition.
+fn salsa20_rounds(state: &[u32; 16]) -> [u32; 16] {
+    fn quarter(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    }
+    let mut x = *state;
+    for _ in 0..10 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    x
+}
+
+/// The Salsa20 state for `key` and the 16 bytes of nonce and counter.
+fn salsa20_state(key: &[u8; 32], input: &[u8; 16]) -> [u32; 16] {
+    let mut state = [0u32; 16];
+    for (i, &c) in [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]
+        .iter()
+        .enumerate()
+    {
+        state[i * 5] = c;
+    }
+    for i in 0..4 {
+        state[1 + i] = le32(&key[i * 4..]);
+        state[11 + i] = le32(&key[16 + i * 4..]);
+        state[6 + i] = le32(&input[i * 4..]);
+    }
+    state
+}
+
+/// HSalsa20: a key derived from `key` and `input` (the XSalsa20 paper,
+/// §2).
+fn hsalsa20(key: &[u8; 32], input: &[u8; 16]) -> [u8; 32] {
+    let x = salsa20_rounds(&salsa20_state(key, input));
+    let mut out = [0u8; 32];
+    for (i, &w) in [0, 5, 10, 15, 6, 7, 8, 9].iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&x[w].to_le_bytes());
+    }
+    out
+}
+
+/// XORs `data` with the XSalsa20 key stream for `nonce`, from its start.
+fn xsalsa20(key: &[u8; 32], nonce: &[u8; 24], data: &mut [u8]) {
+    let mut prefix = [0u8; 16];
+    prefix.copy_from_slice(&nonce[..16]);
+    let subkey = hsalsa20(key, &prefix);
+    let mut input = [0u8; 16];
+    input[..8].copy_from_slice(&nonce[16..]);
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        input[8..].copy_from_slice(&(i as u64).to_le_bytes());
+        let state = salsa20_state(&subkey, &input);
+        let x = salsa20_rounds(&state);
+        let mut stream = [0u8; 64];
+        for j in 0..16 {
+            stream[j * 4..j * 4 + 4].copy_from_slice(&x[j].wrapping_add(state[j]).to_le_bytes());
+        }
+        chunk.iter_mut().zip(&stream[..]).for_each(|(b, k)| *b ^= k);
+    }
+}
+
+/// The key a box between two parties uses, from their X25519 shared
+/// secret (NaCl's `crypto_box_beforenm`).
+#[cfg_attr(not(feature = "dnscrypt"), allow(dead_code))]
+pub fn box_key(shared_secret: &[u8; 32]) -> [u8; 32] {
+    hsalsa20(shared_secret, &[0; 16])
+}
+
+/// Encrypts `plaintext` with XSalsa20-Poly1305 (NaCl's
+/// `crypto_secretbox`). Returns the tag followed by the ciphertext.
+#[cfg_attr(not(feature = "dnscrypt"), allow(dead_code))]
+pub fn secretbox_seal(key: &[u8; 32], nonce: &[u8; 24], plaintext: &[u8]) -> Vec<u8> {
+    // The first 32 bytes of the key stream are the Poly1305 key.
+    let mut data = vec![0u8; 32];
+    data.extend_from_slice(plaintext);
+    xsalsa20(key, nonce, &mut data);
+    let mut otk = [0u8; 32];
+    otk.copy_from_slice(&data[..32]);
+    let tag = poly1305(&otk, &data[32..]);
+    data[16..32].copy_from_slice(&tag);
+    data.split_off(16)
+}
+
+/// Decrypts what [`secretbox_seal`] made, or `None` if it was tampered
+/// with or the key is wrong.
+#[cfg_attr(not(feature = "dnscrypt"), allow(dead_code))]
+pub fn secretbox_open(key: &[u8; 32], nonce: &[u8; 24], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < 16 {
+        return None;
+    }
+    let (tag, ciphertext) = sealed.split_at(16);
+    let mut otk = [0u8; 32];
+    xsalsa20(key, nonce, &mut otk);
+    if !verify_mac(&poly1305(&otk, ciphertext), tag) {
+        return None;
+    }
+    let mut data = vec![0u8; 32];
+    data.extend_from_slice(ciphertext);
+    xsalsa20(key, nonce, &mut data);
+    Some(data.split_off(32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn array<const N: usize>(s: &str) -> [u8; N] {
+        let mut out = [0u8; N];
+        out.copy_from_slice(&hex(s));
+        out
+    }
+
+    const SUNSCREEN: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+        only one tip for the future, sunscreen would be it.";
+
+    // RFC 8439 §2.3.2.
+    #[test]
+    fn chacha20_block_function() {
+        let key = array("000102030405060708090a0b0c0d0e0f 101112131415161718191a1b1c1d1e1f");
+        let nonce = array("000000090000004a00000000");
+        assert_eq!(
+            chacha20_block(&key, 1, &nonce)[..],
+            hex(
+                "10f1e7e4d13b5915500fdd1fa32071c4 c7d1f4c733c068030422aa9ac3d46c4e
+                 d2826446079faa0914c2d705d98b02a2 b5129cd1de164eb9cbd083e8a2503c4e"
+            )[..]
+        );
+    }
+
+    // RFC 8439 §2.4.2.
+    #[test]
+    fn chacha20_encryption() {
+        let key = array("000102030405060708090a0b0c0d0e0f 101112131415161718191a1b1c1d1e1f");
+        let nonce = array("000000000000004a00000000");
+        let mut data = SUNSCREEN.to_vec();
+        chacha20(&key, 1, &nonce, &mut data);
+        assert_eq!(
+            data,
+            hex(
+                "6e2e359a2568f98041ba0728dd0d6981 e97e7aec1d4360c20a27afccfd9fae0b
+                 f91b65c5524733ab8f593dabcd62b357 1639d624e65152ab8f530c359f0861d8
+                 07ca0dbf500d6a6156a38e088a22b65e 52bc514d16ccf806818ce91ab7793736
+                 5af90bbf74a35be6b40b8eedf2785e42 874d"
+            )
+        );
+        chacha20(&key, 1, &nonce, &mut data);
+        assert_eq!(data, SUNSCREEN);
+    }
+
+    // RFC 8439 §2.5.2 and Appendix A.3, and the test vector NaCl ships
+    // for crypto_onetimeauth.
+    #[test]
+    fn poly1305_tags() {
+        let key = array("85d6be7857556d337f4452fe42d506a8 0103808afb0db2fd4abff6af4149f51b");
+        assert_eq!(
+            poly1305(&key, b"Cryptographic Forum Research Group"),
+            array("a8061dc1305136c6c22b8baf0c0127a9")
+        );
+        assert_eq!(poly1305(&[0; 32], &[0; 64]), [0; 16]);
+
+        // Cases where h or h + s wraps past 2^130 - 5 or 2^128.
+        let two = array("02000000000000000000000000000000 00000000000000000000000000000000");
+        assert_eq!(
+            poly1305(&two, &[0xff; 16]),
+            array("03000000000000000000000000000000")
+        );
+        let two_minus = array("02000000000000000000000000000000 ffffffffffffffffffffffffffffffff");
+        assert_eq!(
+            poly1305(&two_minus, &hex("02000000000000000000000000000000")),
+            array("03000000000000000000000000000000")
+        );
+        let one = array("01000000000000000000000000000000 00000000000000000000000000000000");
+        assert_eq!(
+            poly1305(
+                &one,
+                &hex(
+                    "ffffffffffffffffffffffffffffffff fbfefefefefefefefefefefefefefefe
+                      01010101010101010101010101010101"
+                )
+            ),
+            [0; 16]
+        );
+
+        let nacl = array("eea6a7251c1e72916d11c2cb214d3c25 2539121d8e234e652d651fa4c8cff880");
+        let message = hex(
+            "8e993b9f48681273c29650ba32fc76ce 48332ea7164d96a4476fb8c531a1186a
+             c0dfc17c98dce87b4da7f011ec48c972 71d2c20f9b928fe2270d6fb863d51738
+             b48eeee314a7cc8ab932164548e526ae 90224368517acfeabd6bb3732bc0e9da
+             99832b61ca01b6de56244a9e88d5f9b3 7973f622a43d14a6599b1f654cb45a74
+             e355a5",
+        );
+        assert_eq!(
+            poly1305(&nacl, &message),
+            array("f3ffc7703f9400e52a7dfb4b3d3305d9")
+        );
+    }
+
+    // RFC 8439 §2.8.2.
+    #[test]
+    fn aead_seals_and_opens() {
+        let key = array("808182838485868788898a8b8c8d8e8f 909192939495969798999a9b9c9d9e9f");
+        let nonce = array("070000004041424344454647");
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let sealed = seal(&key, &nonce, &aad, SUNSCREEN);
+        assert_eq!(
+            sealed,
+            hex(
+                "d31a8d34648e60db7b86afbc53ef7ec2 a4aded51296e08fea9e2b5a736ee62d6
+                 3dbea45e8ca9671282fafb69da92728b 1a71de0a9e060b2905d6a5b67ecd3b36
+                 92ddbd7f2d778b8c9803aee328091b58 fab324e4fad675945585808b4831d7bc
+                 3ff4def08e4b7a9de576d26586cec64b 6116
+                 1ae10b594f09e26a7e902ecbd0600691"
+            )
+        );
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), SUNSCREEN);
+
+        // Any change to the ciphertext, the tag, the data or the key fails.
+        for i in [0, SUNSCREEN.len() - 1, SUNSCREEN.len(), sealed.len() - 1] {
+            let mut bad = sealed.clone();
+            bad[i] ^= 1;
+            assert_eq!(open(&key, &nonce, &aad, &bad), None);
+        }
+        assert_eq!(open(&key, &nonce, b"", &sealed), None);
+        assert_eq!(open(&[0; 32], &nonce, &aad, &sealed), None);
+        assert_eq!(open(&key, &nonce, &aad, &sealed[..15]), None);
+        assert_eq!(
+            open(&key, &nonce, b"", &seal(&key, &nonce, b"", b"")).unwrap(),
+            b""
+        );
+    }
+
+    // RFC 6070.
+    #[test]
+    fn pbkdf2_hmac_sha1() {
+        let cases: &[(&[u8], &[u8], u32, &str)] = &[
+            (
+                b"password",
+                b"salt",
+                1,
+                "0c60c80f961f0e71f3a9b524af6012062fe037a6",
+            ),
+            (
+                b"password",
+                b"salt",
+                2,
+                "ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957",
+            ),
+            (
+                b"password",
+                b"salt",
+                4096,
+                "4b007901b765489abead49d926f721d065a429c1",
+            ),
+            (
+                b"passwordPASSWORDpassword",
+                b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+                4096,
+                "3d2eec4fe41c849b80c8d83662c0e44a8b291a964cf2f07038",
+            ),
+            (
+                b"pass\0word",
+                b"sa\0lt",
+                4096,
+                "56fa6aa75548099dcc37d7f03425e0c3",
+            ),
+        ];
+        for &(password, salt, iterations, expected) in cases {
+            let expected = hex(expected);
+            assert_eq!(
+                pbkdf2::<Sha1>(password, salt, iterations, expected.len()),
+                expected
+            );
+        }
+    }
+
+    // FIPS 180-4 and its examples: "abc", the two-block messages, the
+    // empty message, and a million "a"s fed in uneven pieces.
+    #[test]
+    fn secure_hash_standard() {
+        fn check<D: Digest>(expected: [&str; 5]) {
+            let messages: [&[u8]; 4] = [
+                b"abc",
+                b"",
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+                  hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+            ];
+            for (message, expected) in messages.iter().zip(&expected) {
+                assert_eq!(D::digest(message), hex(expected));
+            }
+            let mut digest = D::default();
+            let a = [b'a'; 1000];
+            let mut left = 1_000_000;
+            for size in [1, 55, 56, 63, 64, 65, 127, 128, 129, 1000].iter().cycle() {
+                let size = (*size).min(left);
+                digest.update(&a[..size]);
+                left -= size;
+                if left == 0 {
+                    break;
+                }
+            }
+            assert_eq!(digest.finish(), hex(expected[4]));
+        }
+        check::<Sha1>([
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            "a49b2446a02c645bf419f995b67091253a04a259",
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+        ]);
+        check::<Sha256>([
+            "ba7816bf8f01cfea414140de5dae2223 b00361a396177a9cb410ff61f20015ad",
+            "e3b0c44298fc1c149afbf4c8996fb924 27ae41e4649b934ca495991b7852b855",
+            "248d6a61d20638b8e5c026930c3e6039 a33ce45964ff2167f6ecedd419db06c1",
+            "cf5b16a778af8380036ce59e7b049237 0b249b11e8f07a51afac45037afee9d1",
+            "cdc76e5c9914fb9281a1c7e284d73e67 f1809a48a497200e046d39ccc7112cd0",
+        ]);
+        check::<Sha384>([
+            "cb00753f45a35e8bb5a03d699ac65007 272c32ab0eded1631a8b605a43ff5bed
+             8086072ba1e7cc2358baeca134c825a7",
+            "38b060a751ac96384cd9327eb1b1e36a 21fdb71114be07434c0cc7bf63f6e1da
+             274edebfe76f65fbd51ad2f14898b95b",
+            "3391fdddfc8dc7393707a65b1b470939 7cf8b1d162af05abfe8f450de5f36bc6
+             b0455a8520bc4e6f5fe95b1fe3c8452b",
+            "09330c33f71147e83d192fc782cd1b47 53111b173b3b05d22fa08086e3b0f712
+             fcc7c71a557e2db966c3e9fa91746039",
+            "9d0e1809716474cb086e834e310a4a1c ed149e9c00f248527972cec5704c2a5b
+             07b8b3dc38ecc4ebae97ddd87f3d8985",
+        ]);
+        check::<Sha512>([
+            "ddaf35a193617abacc417349ae204131 12e6fa4e89a97ea20a9eeee64b55d39a
+             2192992a274fc1a836ba3c23a3feebbd 454d4423643ce80e2a9ac94fa54ca49f",
+            "cf83e1357eefb8bdf1542850d66d8007 d620e4050b5715dc83f4a921d36ce9ce
+             47d0d13c5d85f2b0ff8318d2877eec2f 63b931bd47417a81a538327af927da3e",
+            "204a8fc6dda82f0a0ced7beb8e08a416 57c16ef468b228a8279be331a703c335
+             96fd15c13b1b07f9aa1d3bea57789ca0 31ad85c7a71dd70354ec631238ca3445",
+            "8e959b75dae313da8cf4f72814fc143f 8f7779c6eb9f7fa17299aeadb6889018
+             501d289e4900f7e4331b99dec4b5433a c7d329eeb6dd26545e96e55b874be909",
+            "e718483d0ce769644e2e42c7bc15b463 8e1f98b13b2044285632a803afa973eb
+             de0ff244877ea60a4cb0432ce577c31b eb009c5c2c49aa2e4eadb217ad8cc09b",
+        ]);
+    }
+
+    // RFC 4231 §4.2 to §4.8; test case 5 is truncated to 128 bits.
+    #[test]
+    fn hmac_sha2() {
+        let long_key = [0xaa; 131];
+        let cases: [(&[u8], &[u8], [&str; 3]); 7] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                [
+                    "b0344c61d8db38535ca8afceaf0bf12b 881dc200c9833da726e9376c2e32cff7",
+                    "afd03944d84895626b0825f4ab46907f 15f9dadbe4101ec682aa034c7cebc59c
+                     faea9ea9076ede7f4af152e8b2fa9cb6",
+                    "87aa7cdea5ef619d4ff0b4241a1d6cb0 2379f4e2ce4ec2787ad0b30545e17cde
+                     daa833b7d6b8a702038b274eaea3f4e4 be9d914eeb61f1702e696c203a126854",
+                ],
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                [
+                    "5bdcc146bf60754e6a042426089575c7 5a003f089d2739839dec58b964ec3843",
+                    "af45d2e376484031617f78d2b58a6b1b 9c7ef464f5a01b47e42ec3736322445e
+                     8e2240ca5e69e2c78b3239ecfab21649",
+                    "164b7a7bfcf819e2e395fbe73b56e0a3 87bd64222e831fd610270cd7ea250554
+                     9758bf75c05a994a6d034f65f8f0e6fd caeab1a34d4a6b4b636e070a38bce737",
+                ],
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                [
+                    "773ea91e36800e46854db8ebd09181a7 2959098b3ef8c122d9635514ced565fe",
+                    "88062608d3e6ad8a0aa2ace014c8a86f 0aa635d947ac9febe83ef4e55966144b
+                     2a5ab39dc13814b94e3ab6e101a34f27",
+                    "fa73b0089d56a284efb0f0756c890be9 b1b5dbdd8ee81a3655f83e33b2279d39
+                     bf3e848279a722c806b485a47e67c807 b946a337bee8942674278859e13292fb",
+                ],
+            ),
+            (
+                &[
+                    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
+                    23, 24, 25,
+                ],
+                &[0xcd; 50],
+                [
+                    "82558a389a443c0ea4cc819899f2083a 85f0faa3e578f8077a2e3ff46729665b",
+                    "3e8a69b7783c25851933ab6290af6ca7 7a9981480850009cc5577c6e1f573b4e
+                     6801dd23c4a7d679ccf8a386c674cffb",
+                    "b0ba465637458c6990e5a8c5f61d4af7 e576d97ff94b872de76f8050361ee3db
+                     a91ca5c11aa25eb4d679275cc5788063 a5f19741120c4f2de2adebeb10a298dd",
+                ],
+            ),
+            (
+                &[0x0c; 20],
+                b"Test With Truncation",
+                [
+                    "a3b6167473100ee06e0c796c2955552b",
+                    "3abf34c3503b2a23a46efc619baef897",
+                    "415fad6271580a531d4179bc891d87a6",
+                ],
+            ),
+            (
+                &long_key,
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                [
+                    "60e431591ee0b67f0d8a26aacbf5b77f 8e0bc6213728c5140546040f0ee37f54",
+                    "4ece084485813e9088d2c63a041bc5b4 4f9ef1012a2b588f3cd11f05033ac4c6
+                     0c2ef6ab4030fe8296248df163f44952",
+                    "80b24263c7c1a3ebb71493c1dd7be8b4 9b46d1f41b4aeec1121b013783f8f352
+                     6b56d037e05f2598bd0fd2215d6a1e52 95e64f73f63f0aec8b915a985d786598",
+                ],
+            ),
+            (
+                &long_key,
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm.",
+                [
+                    "9b09ffa71b942fcb27635fbcd5b0e944 bfdc63644f0713938a7f51535c3a35e2",
+                    "6617178e941f020d351e2f254e8fd32c 602420feb0b8fb9adccebb82461e99c5
+                     a678cc31e799176d3860e6110c46523e",
+                    "e37b6a775dc87dbaa4dfa9f96e5e3ffd debd71f8867289865df5a32d20cdc944
+                     b6022cac3c4982b10d5eeb55c3e4de15 134676fb6de0446065c97440fa8c6a58",
+                ],
+            ),
+        ];
+        for (key, data, [sha256, sha384, sha512]) in cases.iter() {
+            for (mac, expected) in [
+                (hmac::<Sha256>(key, data), sha256),
+                (hmac::<Sha384>(key, data), sha384),
+                (hmac::<Sha512>(key, data), sha512),
+            ] {
+                let expected = hex(expected);
+                assert_eq!(mac[..expected.len()], expected[..]);
+                assert!(verify_mac(&expected, &mac[..expected.len()]));
+            }
+        }
+        let mac = hmac::<Sha256>(b"Jefe", b"what do ya want for nothing?");
+        let mut bad = mac.clone();
+        bad[31] ^= 1;
+        assert!(!verify_mac(&mac, &bad));
+        assert!(!verify_mac(&mac, &mac[..16]));
+    }
+
+    // NaCl's tests/core1.c, core2.c and secretbox.c: Alice's and Bob's
+    // X25519 shared secret, the box key and the HSalsa20 subkey derived
+    // from it, and a message sealed under that key.
+    #[test]
+    fn nacl_secretbox() {
+        let shared = array("4a5d9d5ba4ce2de1728e3bf480350f25 e07e21c947d19e3376f09b3c1e161742");
+        let key = box_key(&shared);
+        assert_eq!(
+            key,
+            array("1b27556473e985d462cd51197a9a46c7 6009549eac6474f206c4ee0844f68389")
+        );
+        assert_eq!(
+            hsalsa20(&key, &array("69696ee955b62b73cd62bda875fc73d6")),
+            array("dc908dda0b9344a953629b7338207788 80f3ceb421bb61b91cbd4c3e66256ce4")
+        );
+
+        let nonce = array("69696ee955b62b73cd62bda875fc73d6 8219e0036b7a0b37");
+        let message = hex(
+            "be075fc53c81f2d5cf141316ebeb0c7b 5228c52a4c62cbd44b66849b64244ffc
+             e5ecbaaf33bd751a1ac728d45e6c6129 6cdc3c01233561f41db66cce314adb31
+             0e3be8250c46f06dceea3a7fa1348057 e2f6556ad6b1318a024a838f21af1fde
+             048977eb48f59ffd4924ca1c60902e52 f0a089bc76897040e082f93776384864
+             5e0705",
+        );
+        let sealed = secretbox_seal(&key, &nonce, &message);
+        assert_eq!(
+            sealed,
+            hex(
+                "f3ffc7703f9400e52a7dfb4b3d3305d9 8e993b9f48681273c29650ba32fc76ce
+                 48332ea7164d96a4476fb8c531a1186a c0dfc17c98dce87b4da7f011ec48c972
+                 71d2c20f9b928fe2270d6fb863d51738 b48eeee314a7cc8ab932164548e526ae
+                 90224368517acfeabd6bb3732bc0e9da 99832b61ca01b6de56244a9e88d5f9b3
+                 7973f622a43d14a6599b1f654cb45a74 e355a5"
+            )
+        );
+        assert_eq!(secretbox_open(&key, &nonce, &sealed).unwrap(), message);
+
+        // Any change to the tag, the ciphertext, the nonce or the key fails.
+        for i in [0, 15, 16, sealed.len() - 1] {
+            let mut bad = sealed.clone();
+            bad[i] ^= 1;
+            assert_eq!(secretbox_open(&key, &nonce, &bad), None);
+        }
+        let mut other = nonce;
+        other[23] ^= 1;
+        assert_eq!(secretbox_open(&key, &other, &sealed), None);
+        assert_eq!(secretbox_open(&shared, &nonce, &sealed), None);
+        assert_eq!(secretbox_open(&key, &nonce, &sealed[..15]), None);
+        assert_eq!(
+            secretbox_open(&key, &nonce, &secretbox_seal(&key, &nonce, b"")).unwrap(),
+            b""
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Just enough DER (X.690) to pick fields out of X.509 certificates
+//! (RFC 5280): the public key DANE matches and the addresses a
+//! certificate is issued for.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const SEQUENCE: u8 = 0x30;
+const OID: u8 = 0x06;
+const OCTET_STRING: u8 = 0x04;
+/// The explicit tag of a certificate's version.
+#[cfg(feature = "dnssec")]
+const VERSION: u8 = 0xa0;
+/// The explicit tag of a certificate's extensions.
+const EXTENSIONS: u8 = 0xa3;
+/// The `iPAddress` choice of a GeneralName.
+const IP_ADDRESS: u8 = 0x87;
+
+/// The subjectAltName extension, 2.5.29.17.
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// One DER element at the start of `data`: its tag, and where its content
+/// starts and ends.
+fn element(data: &[u8]) -> Option<(u8, usize, usize)> {
+    let tag = *data.first()?;
+    let first = *data.get(1)?;
+    let (len, start) = if first < 0x80 {
+        (usize::from(first), 2)
+    } else {
+        let n = usize::from(first & 0x7f);
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let len = data
+            .get(2..2 + n)?
+            .iter()
+            .fold(0usize, |acc, &b| acc << 8 | usize::from(b));
+        (len, 2 + n)
+    };
+    let end = start.checked_add(len)?;
+    if end > data.len() {
+        return None;
+    }
+    Some((tag, start, end))
+}
+
+/// An element with its tag, whole and as its content.
+type Element<'a> = (u8, &'a [u8], &'a [u8]);
+
+/// The elements `data` holds one after another; `None` if one is
+/// malformed.
+fn elements(mut data: &[u8]) -> Option<Vec<Element<'_>>> {
+    let mut out = Vec::new();
+    while !data.is_empty() {
+        let (tag, start, end) = element(data)?;
+        out.push((tag, &data[..end], &data[start..end]));
+        data = &data[end..];
+    }
+    Some(out)
+}
+
+/// The content of the element `data` holds, if it has the tag `tag`.
+fn content(data: &[u8], tag: u8) -> Option<&[u8]> {
+    match element(data)? {
+        (t, start, end) if t == tag => Some(&data[start..end]),
+        _ => None,
+    }
+}
+
+/// The fields of the TBSCertificate of `cert`, a DER X.509 certificate,
+/// each with its tag and whole.
+fn tbs_fields(cert: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let tbs = content(content(cert, SEQUENCE)?, SEQUENCE)?;
+    Some(
+        elements(tbs)?
+            .into_iter()
+            .map(|(tag, whole, _)| (tag, whole))
+            .collect(),
+    )
+}
+
+/// The DER SubjectPublicKeyInfo of `cert`, a DER X.509 certificate
+/// (RFC 5280 §4.1).
+#[cfg(feature = "dnssec")]
+pub(crate) fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let fields = tbs_fields(cert)?;
+    // The version, serial number, signature algorithm, issuer, validity
+    // and subject come before it.
+    let skip = if fields.first()?.0 == VERSION { 6 } else { 5 };
+    match fields.get(skip)? {
+        &(SEQUENCE, spki) => Some(spki),
+        _ => None,
+    }
+}
+
+/// The IP addresses in the subjectAltName extension of `cert`, a DER
+/// X.509 certificate.
+pub(crate) fn subject_alt_addresses(cert: &[u8]) -> Vec<IpAddr> {
+    subject_alt_names(cert)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(tag, _)| *tag == IP_ADDRESS)
+        .filter_map(|(_, name)| match name.len() {
+            4 => Some(IpAddr::V4(Ipv4Addr::new(
+                name[0], name[1], name[2], name[3],
+            ))),
+            16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(name);
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The GeneralNames of the subjectAltName extension of `cert`.
+fn subject_alt_names(cert: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let fields = tbs_fields(cert)?;
+    let (_, extensions) = fields.iter().find(|(tag, _)| *tag == EXTENSIONS)?;
+    let extensions = content(content(extensions, EXTENSIONS)?, SEQUENCE)?;
+    for (_, _, extension) in elements(extensions)? {
+        let parts = elements(extension)?;
+        let id = match parts.first() {
+            Some((OID, _, id)) => *id,
+            _ => continue,
+        };
+        if id != SUBJECT_ALT_NAME {
+            continue;
+        }
+        let value = parts
+            .iter()
+            .find(|(tag, _, _)| *tag == OCTET_STRING)
+            .map(|(_, _, value)| *value)?;
+        let names = content(value, SEQUENCE)?;
+        return Some(
+            elements(names)?
+                .into_iter()
+                .map(|(tag, _, name)| (tag, name))
+                .collect(),
+        );
+    }
+    None
+}
@@ -0,0 +1,424 @@
+//! DNSCrypt version 2 client transport.
+//!
+//! A DNSCrypt resolver is known by its address, its provider name and the
+//! provider's Ed25519 public key, usually given together as an `sdns://`
+//! stamp. [`DnsCrypt`] fetches the resolver's certificates as TXT records
+//! of the provider name, keeps the newest valid one, and exchanges queries
+//! boxed with X25519-XSalsa20Poly1305 under its key, over UDP or TCP.
+//!
+//! Each query is sent from a fresh key pair, so the resolver cannot link
+//! queries by the client's key. Queries are padded as the protocol
+//! requires; UDP queries are at least [`MIN_QUERY_LEN`] bytes, growing
+//! each time a response comes back truncated.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::Verifier;
+use rand_core::{OsRng, RngCore};
+
+use crate::client::{self, is_response_to, read_framed, write_framed, Protocol};
+use crate::crypto;
+use crate::encoding;
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::rr::{RData, RecordType};
+
+/// The first bytes of a certificate.
+pub const CERT_MAGIC: &[u8; 4] = b"DNSC";
+
+/// The first bytes of an encrypted response.
+pub const RESOLVER_MAGIC: &[u8; 8] = b"r6fnvWj8";
+
+/// The encryption system of X25519-XSalsa20Poly1305, the only one
+/// supported.
+pub const ES_XSALSA20POLY1305: u16 = 1;
+
+/// The size UDP queries are padded to at first.
+pub const MIN_QUERY_LEN: usize = 256;
+
+/// The size padded UDP queries grow to at most.
+pub const MAX_QUERY_LEN: usize = 1472;
+
+/// The stamp protocol identifier of DNSCrypt.
+const STAMP_DNSCRYPT: u8 = 0x01;
+
+/// Errors reading stamps and certificates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The stamp is not a valid DNSCrypt stamp.
+    BadStamp(&'static str),
+    /// A certificate is malformed or uses an unsupported version.
+    BadCertificate(&'static str),
+    /// A certificate's signature does not verify under the provider key.
+    BadSignature,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BadStamp(why) => write!(f, "invalid DNSCrypt stamp: {}", why),
+            Error::BadCertificate(why) => write!(f, "invalid DNSCrypt certificate: {}", why),
+            Error::BadSignature => f.write_str("DNSCrypt certificate signature does not verify"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A DNSCrypt resolver, as a stamp describes it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Provider {
+    pub address: SocketAddr,
+    /// The name its certificates are published at, such as
+    /// `2.dnscrypt-cert.example.com`.
+    pub name: DomainName,
+    /// The Ed25519 key its certificates are signed with.
+    pub public_key: [u8; 32],
+}
+
+impl Provider {
+    /// Reads an `sdns://` stamp of a DNSCrypt resolver. An address without
+    /// a port is on port 443.
+    pub fn from_stamp(stamp: &str) -> Result<Provider, Error> {
+        let data = stamp
+            .strip_prefix("sdns://")
+            .ok_or(Error::BadStamp("not an sdns:// URL"))?;
+        let data = encoding::base64url_decode(data).map_err(|_| Error::BadStamp("bad base64"))?;
+        if data.first() != Some(&STAMP_DNSCRYPT) {
+            return Err(Error::BadStamp("not a DNSCrypt stamp"));
+        }
+        // The protocol, then eight bytes of properties.
+        let mut rest = data.get(9..).ok_or(Error::BadStamp("truncated"))?;
+        let mut field = || -> Result<&[u8], Error> {
+            let (&len, tail) = rest.split_first().ok_or(Error::BadStamp("truncated"))?;
+            let value = tail
+                .get(..usize::from(len))
+                .ok_or(Error::BadStamp("truncated"))?;
+            rest = &tail[usize::from(len)..];
+            Ok(value)
+        };
+        let address = std::str::from_utf8(field()?).map_err(|_| Error::BadStamp("bad address"))?;
+        let address = parse_address(address).ok_or(Error::BadStamp("bad address"))?;
+        let mut public_key = [0u8; 32];
+        let key = field()?;
+        if key.len() != 32 {
+            return Err(Error::BadStamp("bad public key"));
+        }
+        public_key.copy_from_slice(key);
+        let name =
+            std::str::from_utf8(field()?).map_err(|_| Error::BadStamp("bad provider name"))?;
+        let name = name
+            .parse()
+            .map_err(|_| Error::BadStamp("bad provider name"))?;
+        Ok(Provider {
+            address,
+            name,
+            public_key,
+        })
+    }
+
+    /// The `sdns://` stamp of this resolver, with no properties set.
+    pub fn to_stamp(&self) -> String {
+        let address = if self.address.port() == 443 {
+            match self.address.ip() {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("[{}]", ip),
+            }
+        } else {
+            self.address.to_string()
+        };
+        let name = self.name.to_string();
+        let name = name.trim_end_matches('.');
+        let mut data = vec![STAMP_DNSCRYPT];
+        data.extend_from_slice(&[0; 8]);
+        for field in [address.as_bytes(), &self.public_key[..], name.as_bytes()].iter() {
+            data.push(field.len() as u8);
+            data.extend_from_slice(field);
+        }
+        format!("sdns://{}", encoding::base64url_encode(&data))
+    }
+}
+
+/// `text` as a socket address, on port 443 if it has none.
+fn parse_address(text: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = text.parse() {
+        return Some(addr);
+    }
+    let ip = text.trim_start_matches('[').trim_end_matches(']');
+    Some(SocketAddr::new(ip.parse().ok()?, 443))
+}
+
+/// A resolver certificate, whose signature has been verified.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Certificate {
+    pub es_version: u16,
+    pub minor_version: u16,
+    /// The resolver's X25519 key queries are boxed for.
+    pub resolver_key: [u8; 32],
+    /// The first bytes of queries, telling the resolver which certificate
+    /// they use.
+    pub client_magic: [u8; 8],
+    pub serial: u32,
+    /// When the certificate is valid, as seconds since the Unix epoch.
+    pub valid_from: u32,
+    pub valid_until: u32,
+}
+
+impl Certificate {
+    /// Reads `data`, the content of a TXT record of the provider name, and
+    /// verifies its signature under `provider_key`.
+    pub fn parse(data: &[u8], provider_key: &[u8; 32]) -> Result<Certificate, Error> {
+        if data.len() < 124 {
+            return Err(Error::BadCertificate("truncated"));
+        }
+        if &data[..4] != CERT_MAGIC {
+            return Err(Error::BadCertificate("bad magic"));
+        }
+        let es_version = u16::from_be_bytes([data[4], data[5]]);
+        if es_version != ES_XSALSA20POLY1305 {
+            return Err(Error::BadCertificate("unsupported encryption system"));
+        }
+        let key = ed25519_dalek::VerifyingKey::from_bytes(provider_key)
+            .map_err(|_| Error::BadSignature)?;
+        let signature =
+            ed25519_dalek::Signature::from_slice(&data[8..72]).map_err(|_| Error::BadSignature)?;
+        let signed = &data[72..];
+        key.verify(signed, &signature)
+            .map_err(|_| Error::BadSignature)?;
+        let u32_at =
+            |i: usize| u32::from_be_bytes([signed[i], signed[i + 1], signed[i + 2], signed[i + 3]]);
+        let mut resolver_key = [0u8; 32];
+        resolver_key.copy_from_slice(&signed[..32]);
+        let mut client_magic = [0u8; 8];
+        client_magic.copy_from_slice(&signed[32..40]);
+        Ok(Certificate {
+            es_version,
+            minor_version: u16::from_be_bytes([data[6], data[7]]),
+            resolver_key,
+            client_magic,
+            serial: u32_at(40),
+            valid_from: u32_at(44),
+            valid_until: u32_at(48),
+        })
+    }
+
+    pub fn is_valid_at(&self, now: u32) -> bool {
+        self.valid_from <= now && now <= self.valid_until
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn unix_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+fn invalid(what: &str) -> client::Error {
+    client::Error::Io(io::Error::new(io::ErrorKind::InvalidData, what.to_string()))
+}
+
+#[derive(Debug)]
+struct State {
+    certificate: Option<Certificate>,
+    /// The size UDP queries are padded to.
+    min_query_len: usize,
+}
+
+/// A client of one DNSCrypt resolver, safe to share between threads.
+#[derive(Debug)]
+pub struct DnsCrypt {
+    provider: Provider,
+    state: Mutex<State>,
+}
+
+impl DnsCrypt {
+    pub fn new(provider: Provider) -> DnsCrypt {
+        DnsCrypt {
+            provider,
+            state: Mutex::new(State {
+                certificate: None,
+                min_query_len: MIN_QUERY_LEN,
+            }),
+        }
+    }
+
+    pub fn provider(&self) -> &Provider {
+        &self.provider
+    }
+
+    /// The certificate queries are boxed for: the one last fetched while
+    /// it is valid, otherwise the newest valid one the resolver serves.
+    pub fn certificate(&self, timeout: Duration) -> Result<Certificate, client::Error> {
+        let now = unix_now();
+        if let Some(cert) = &self.state.lock().unwrap().certificate {
+            if cert.is_valid_at(now) {
+                return Ok(cert.clone());
+            }
+        }
+        let cert = self
+            .fetch_certificates(timeout)?
+            .into_iter()
+            .filter(|c| c.is_valid_at(now))
+            .max_by_key(|c| c.serial)
+            .ok_or_else(|| invalid("no valid DNSCrypt certificate"))?;
+        self.state.lock().unwrap().certificate = Some(cert.clone());
+        Ok(cert)
+    }
+
+    /// The certificates the resolver serves whose signatures verify,
+    /// queried in the clear.
+    pub fn fetch_certificates(&self, timeout: Duration) -> Result<Vec<Certificate>, client::Error> {
+        let query = Message::query(self.provider.name.clone(), RecordType::TXT);
+        let mut resp = client::exchange_udp(self.provider.address, &query, timeout)?;
+        if resp.header.tc {
+            resp = client::exchange_tcp(self.provider.address, &query, timeout)?;
+        }
+        Ok(resp
+            .answers
+            .iter()
+            .filter(|rr| rr.name == self.provider.name)
+            .filter_map(|rr| match &rr.rdata {
+                RData::Txt(strings) => Some(strings.concat()),
+                _ => None,
+            })
+            .filter_map(|data| Certificate::parse(&data, &self.provider.public_key).ok())
+            .collect())
+    }
+
+    /// Exchanges `query` with the resolver over `protocol`; `None` means
+    /// UDP, then TCP if the response is truncated.
+    pub fn exchange(
+        &self,
+        query: &Message,
+        protocol: Option<Protocol>,
+        timeout: Duration,
+    ) -> Result<Message, client::Error> {
+        let cert = self.certificate(timeout)?;
+        if protocol == Some(Protocol::Tcp) {
+            return self.exchange_tcp(&cert, query, timeout);
+        }
+        let resp = self.exchange_udp(&cert, query, timeout)?;
+        if resp.header.tc {
+            {
+                let mut state = self.state.lock().unwrap();
+                state.min_query_len = (state.min_query_len + 64).min(MAX_QUERY_LEN);
+            }
+            if protocol.is_none() {
+                return self.exchange_tcp(&cert, query, timeout);
+            }
+        }
+        Ok(resp)
+    }
+
+    fn exchange_udp(
+        &self,
+        cert: &Certificate,
+        query: &Message,
+        timeout: Duration,
+    ) -> Result<Message, client::Error> {
+        let min_len = self.state.lock().unwrap().min_query_len;
+        let (packet, sealed) = Sealed::new(cert, &query.to_wire()?, min_len);
+        let server = self.provider.address;
+        let local: SocketAddr = if server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server)?;
+        socket.send(&packet)?;
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Err(client::Error::Timeout);
+            }
+            socket.set_read_timeout(Some(left))?;
+            let n = socket.recv(&mut buf)?;
+            match sealed.open(&buf[..n]).map(|wire| Message::from_wire(&wire)) {
+                Some(Ok(resp)) if is_response_to(query, &resp) => return Ok(resp),
+                _ => continue,
+            }
+        }
+    }
+
+    fn exchange_tcp(
+        &self,
+        cert: &Certificate,
+        query: &Message,
+        timeout: Duration,
+    ) -> Result<Message, client::Error> {
+        let (packet, sealed) = Sealed::new(cert, &query.to_wire()?, 0);
+        let mut stream = TcpStream::connect_timeout(&self.provider.address, timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write_framed(&mut stream, &packet)?;
+        let wire = sealed
+            .open(&read_framed(&mut stream)?)
+            .ok_or_else(|| invalid("response does not decrypt"))?;
+        let resp = Message::from_wire(&wire)?;
+        if !is_response_to(query, &resp) {
+            return Err(invalid("response does not match the query"));
+        }
+        Ok(resp)
+    }
+}
+
+/// What is needed to open the response to one query.
+struct Sealed {
+    key: [u8; 32],
+    client_nonce: [u8; 12],
+}
+
+impl Sealed {
+    /// The packet carrying `wire`, boxed for the resolver of `cert` from a
+    /// fresh key pair and padded to a multiple of 64 bytes of at least
+    /// `min_len`.
+    fn new(cert: &Certificate, wire: &[u8], min_len: usize) -> (Vec<u8>, Sealed) {
+        let secret = x25519_dalek::StaticSecret::random_from_rng(OsRng);
+        let public = x25519_dalek::PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(cert.resolver_key));
+        let key = crypto::box_key(shared.as_bytes());
+        let mut client_nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut client_nonce);
+        let mut nonce = [0u8; 24];
+        nonce[..12].copy_from_slice(&client_nonce);
+
+        let len = (wire.len() + 1).max(min_len);
+        let mut padded = wire.to_vec();
+        padded.push(0x80);
+        padded.resize(len.div_ceil(64) * 64, 0);
+
+        let mut packet = Vec::with_capacity(52 + 16 + padded.len());
+        packet.extend_from_slice(&cert.client_magic);
+        packet.extend_from_slice(public.as_bytes());
+        packet.extend_from_slice(&client_nonce);
+        packet.extend_from_slice(&crypto::secretbox_seal(&key, &nonce, &padded));
+        (packet, Sealed { key, client_nonce })
+    }
+
+    /// The response message in `packet`, or `None` if it is not a response
+    /// to this query.
+    fn open(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.len() < 32 || &packet[..8] != RESOLVER_MAGIC || packet[8..20] != self.client_nonce
+        {
+            return None;
+        }
+        let mut nonce = [0u8; 24];
+        nonce.copy_from_slice(&packet[8..32]);
+        let mut padded = crypto::secretbox_open(&self.key, &nonce, &packet[32..])?;
+        let end = padded.iter().rposition(|&b| b != 0)?;
+        if padded[end] != 0x80 {
+            return None;
+        }
+        padded.truncate(end);
+        Some(padded)
+    }
+}
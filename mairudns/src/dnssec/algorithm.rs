@@ -0,0 +1,223 @@
+//! Signature algorithms (RFC 8624) and DS digest types, and checking
+//! signatures made with them.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use ed25519_dalek::Verifier;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+
+use crate::crypto::{Digest, Sha1, Sha256, Sha384, Sha512};
+
+/// A DNSSEC signing algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Algorithm(pub u8);
+
+impl Algorithm {
+    pub const RSASHA1: Algorithm = Algorithm(5);
+    pub const RSASHA1_NSEC3_SHA1: Algorithm = Algorithm(7);
+    pub const RSASHA256: Algorithm = Algorithm(8);
+    pub const RSASHA512: Algorithm = Algorithm(10);
+    pub const ECDSAP256SHA256: Algorithm = Algorithm(13);
+    pub const ECDSAP384SHA384: Algorithm = Algorithm(14);
+    pub const ED25519: Algorithm = Algorithm(15);
+    pub const ED448: Algorithm = Algorithm(16);
+
+    const TABLE: &'static [(Algorithm, &'static str)] = &[
+        (Algorithm::RSASHA1, "RSASHA1"),
+        (Algorithm::RSASHA1_NSEC3_SHA1, "RSASHA1-NSEC3-SHA1"),
+        (Algorithm::RSASHA256, "RSASHA256"),
+        (Algorithm::RSASHA512, "RSASHA512"),
+        (Algorithm::ECDSAP256SHA256, "ECDSAP256SHA256"),
+        (Algorithm::ECDSAP384SHA384, "ECDSAP384SHA384"),
+        (Algorithm::ED25519, "ED25519"),
+        (Algorithm::ED448, "ED448"),
+    ];
+
+    /// The mnemonic for well-known values.
+    pub fn mnemonic(self) -> Option<&'static str> {
+        Algorithm::TABLE
+            .iter()
+            .find(|&&(a, _)| a == self)
+            .map(|&(_, s)| s)
+    }
+
+    /// Whether signatures made with this algorithm can be checked.
+    pub fn is_supported(self) -> bool {
+        matches!(
+            self,
+            Algorithm::RSASHA1
+                | Algorithm::RSASHA1_NSEC3_SHA1
+                | Algorithm::RSASHA256
+                | Algorithm::RSASHA512
+                | Algorithm::ECDSAP256SHA256
+                | Algorithm::ECDSAP384SHA384
+                | Algorithm::ED25519
+        )
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mnemonic() {
+            Some(s) => f.write_str(s),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Algorithm, ()> {
+        Algorithm::TABLE
+            .iter()
+            .find(|(_, m)| m.eq_ignore_ascii_case(s))
+            .map(|&(a, _)| a)
+            .or_else(|| s.parse().ok().map(Algorithm))
+            .ok_or(())
+    }
+}
+
+/// The digest type of a DS record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DigestType(pub u8);
+
+impl DigestType {
+    pub const SHA1: DigestType = DigestType(1);
+    pub const SHA256: DigestType = DigestType(2);
+    pub const SHA384: DigestType = DigestType(4);
+
+    pub fn is_supported(self) -> bool {
+        matches!(
+            self,
+            DigestType::SHA1 | DigestType::SHA256 | DigestType::SHA384
+        )
+    }
+
+    /// The digest of `data`, or `None` for an unsupported type.
+    pub fn digest(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            DigestType::SHA1 => Some(Sha1::digest(data)),
+            DigestType::SHA256 => Some(Sha256::digest(data)),
+            DigestType::SHA384 => Some(Sha384::digest(data)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DigestType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Why a signature was not accepted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum VerifyError {
+    Unsupported,
+    BadKey,
+    BadSignature,
+}
+
+/// The DER DigestInfo prefixes of PKCS #1 v1.5 signatures (RFC 8017 §9.2).
+const SHA1_PREFIX: &[u8] = &[
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+const SHA256_PREFIX: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+const SHA512_PREFIX: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05,
+    0x00, 0x04, 0x40,
+];
+
+/// Splits an RSA public key in the RFC 3110 format into its exponent and
+/// modulus.
+pub(crate) fn rsa_key(key: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = match *key.first()? {
+        0 => (
+            usize::from(u16::from_be_bytes([*key.get(1)?, *key.get(2)?])),
+            &key[3..],
+        ),
+        n => (usize::from(n), &key[1..]),
+    };
+    if len == 0 || rest.len() <= len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+/// The PKCS #1 v1.5 scheme of an RSA `algorithm`, with the hash of
+/// `data` it signs; `None` for other algorithms.
+pub(crate) fn rsa_hash(algorithm: Algorithm, data: &[u8]) -> Option<(Pkcs1v15Sign, Vec<u8>)> {
+    let (prefix, hash) = match algorithm {
+        Algorithm::RSASHA1 | Algorithm::RSASHA1_NSEC3_SHA1 => (SHA1_PREFIX, Sha1::digest(data)),
+        Algorithm::RSASHA256 => (SHA256_PREFIX, Sha256::digest(data)),
+        Algorithm::RSASHA512 => (SHA512_PREFIX, Sha512::digest(data)),
+        _ => return None,
+    };
+    let scheme = Pkcs1v15Sign {
+        hash_len: Some(hash.len()),
+        prefix: prefix.into(),
+    };
+    Some((scheme, hash))
+}
+
+/// Checks `signature` over `data` with a public key in the DNSKEY format
+/// of `algorithm`.
+pub(crate) fn verify(
+    algorithm: Algorithm,
+    key: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<(), VerifyError> {
+    if let Some((scheme, hash)) = rsa_hash(algorithm, data) {
+        let (e, n) = rsa_key(key).ok_or(VerifyError::BadKey)?;
+        let key = RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+            .map_err(|_| VerifyError::BadKey)?;
+        return key
+            .verify(scheme, &hash, signature)
+            .map_err(|_| VerifyError::BadSignature);
+    }
+    match algorithm {
+        Algorithm::ECDSAP256SHA256 => {
+            let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(&uncompressed_point(key))
+                .map_err(|_| VerifyError::BadKey)?;
+            let signature = p256::ecdsa::Signature::from_slice(signature)
+                .map_err(|_| VerifyError::BadSignature)?;
+            key.verify_prehash(&Sha256::digest(data), &signature)
+                .map_err(|_| VerifyError::BadSignature)
+        }
+        Algorithm::ECDSAP384SHA384 => {
+            let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(&uncompressed_point(key))
+                .map_err(|_| VerifyError::BadKey)?;
+            let signature = p384::ecdsa::Signature::from_slice(signature)
+                .map_err(|_| VerifyError::BadSignature)?;
+            key.verify_prehash(&Sha384::digest(data), &signature)
+                .map_err(|_| VerifyError::BadSignature)
+        }
+        Algorithm::ED25519 => {
+            let key: &[u8; 32] = key.try_into().map_err(|_| VerifyError::BadKey)?;
+            let key =
+                ed25519_dalek::VerifyingKey::from_bytes(key).map_err(|_| VerifyError::BadKey)?;
+            let signature = ed25519_dalek::Signature::from_slice(signature)
+                .map_err(|_| VerifyError::BadSignature)?;
+            key.verify(data, &signature)
+                .map_err(|_| VerifyError::BadSignature)
+        }
+        _ => Err(VerifyError::Unsupported),
+    }
+}
+
+/// The SEC1 encoding of an ECDSA public key, which DNSKEY records carry
+/// as bare coordinates (RFC 6605 §4).
+fn uncompressed_point(key: &[u8]) -> Vec<u8> {
+    let mut point = Vec::with_capacity(key.len() + 1);
+    point.push(0x04);
+    point.extend_from_slice(key);
+    point
+}
@@ -0,0 +1,331 @@
+//! Trust anchors, and keeping them up to date as their zones roll keys
+//! the RFC 5011 way; and negative trust anchors (RFC 7646), under which
+//! validation is turned off.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::encoding;
+use crate::name::DomainName;
+use crate::rr::Record;
+
+use super::{Algorithm, DigestType, Dnskey, Ds};
+
+/// How long a new key must be seen before it is trusted, and a revoked
+/// one is remembered (RFC 5011 §2.4.1, §2.4.2).
+pub const HOLD_DOWN: Duration = Duration::from_secs(30 * 86400);
+
+/// The root zone's key-signing keys, KSK-2017 and KSK-2024, as published
+/// by IANA.
+const ROOT_ANCHORS: &[(u16, &str)] = &[
+    (
+        20326,
+        "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D",
+    ),
+    (
+        38696,
+        "683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16",
+    ),
+];
+
+/// What an anchor trusts: a DS record, or the key itself.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AnchorKey {
+    Ds(Ds),
+    Dnskey(Dnskey),
+}
+
+impl AnchorKey {
+    /// Whether this anchor is `key`, owned by `zone`, disregarding the
+    /// REVOKE flag.
+    fn matches(&self, zone: &DomainName, key: &Dnskey) -> bool {
+        let unrevoked = Dnskey {
+            flags: key.flags & !Dnskey::REVOKE,
+            ..key.clone()
+        };
+        match self {
+            AnchorKey::Ds(ds) => ds.matches(zone, &unrevoked),
+            AnchorKey::Dnskey(k) => {
+                k.algorithm == key.algorithm
+                    && k.public_key == key.public_key
+                    && k.flags & !Dnskey::REVOKE == unrevoked.flags
+            }
+        }
+    }
+
+    fn is_supported(&self) -> bool {
+        match self {
+            AnchorKey::Ds(ds) => ds.is_supported(),
+            AnchorKey::Dnskey(k) => k.algorithm.is_supported(),
+        }
+    }
+}
+
+/// Where an anchor stands in the RFC 5011 life cycle (§4).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AnchorState {
+    /// A new key, seen for less than the hold-down time; not trusted yet.
+    AddPending,
+    Valid,
+    /// A trusted key no longer in the zone's key set; still trusted.
+    Missing,
+    /// Revoked by its zone; never trusted again.
+    Revoked,
+}
+
+/// A trust anchor.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Anchor {
+    pub key: AnchorKey,
+    pub state: AnchorState,
+    /// Seconds since the epoch when the anchor entered its state.
+    pub since: u64,
+    /// Whether the anchor follows the zone's key rollovers. Anchors
+    /// configured by hand stay as they are.
+    pub managed: bool,
+}
+
+impl Anchor {
+    pub fn is_trusted(&self) -> bool {
+        matches!(self.state, AnchorState::Valid | AnchorState::Missing)
+    }
+}
+
+/// The trust anchors of a validator, by zone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrustAnchors {
+    zones: BTreeMap<DomainName, Vec<Anchor>>,
+    /// Negative trust anchors, with when they lapse in seconds since the
+    /// epoch.
+    negative: BTreeMap<DomainName, Option<u64>>,
+}
+
+impl TrustAnchors {
+    /// No anchors: everything validates as insecure.
+    pub fn new() -> TrustAnchors {
+        TrustAnchors::default()
+    }
+
+    /// The root zone's published key-signing keys, managed.
+    pub fn root() -> TrustAnchors {
+        let mut anchors = TrustAnchors::new();
+        for &(key_tag, digest) in ROOT_ANCHORS {
+            let ds = Ds {
+                key_tag,
+                algorithm: Algorithm::RSASHA256,
+                digest_type: DigestType::SHA256,
+                digest: encoding::hex_decode(digest).expect("valid root anchor digest"),
+            };
+            anchors
+                .zones
+                .entry(DomainName::root())
+                .or_default()
+                .push(Anchor {
+                    key: AnchorKey::Ds(ds),
+                    state: AnchorState::Valid,
+                    since: 0,
+                    managed: true,
+                });
+        }
+        anchors
+    }
+
+    /// Adds a trusted anchor for `zone` that is left as configured.
+    pub fn add(&mut self, zone: DomainName, key: AnchorKey) {
+        let anchors = self.zones.entry(zone).or_default();
+        if !anchors.iter().any(|a| a.key == key) {
+            anchors.push(Anchor {
+                key,
+                state: AnchorState::Valid,
+                since: 0,
+                managed: false,
+            });
+        }
+    }
+
+    /// Unmanaged anchors for the DS and DNSKEY records among `records`,
+    /// each for the zone owning it: how tests trust zones they sign
+    /// themselves.
+    pub fn from_records(records: &[Record]) -> TrustAnchors {
+        let mut anchors = TrustAnchors::new();
+        for rr in records {
+            if let Some(ds) = Ds::from_rdata(&rr.rdata) {
+                anchors.add(rr.name.clone(), AnchorKey::Ds(ds));
+            } else if let Some(key) = Dnskey::from_rdata(&rr.rdata) {
+                if key.is_zone_key() && !key.is_revoked() {
+                    anchors.add(rr.name.clone(), AnchorKey::Dnskey(key));
+                }
+            }
+        }
+        anchors
+    }
+
+    /// Adds an anchor with its state, as read back from storage.
+    pub fn insert(&mut self, zone: DomainName, anchor: Anchor) {
+        self.zones.entry(zone).or_default().push(anchor);
+    }
+
+    /// Drops every anchor for `zone`.
+    pub fn remove(&mut self, zone: &DomainName) -> Vec<Anchor> {
+        self.zones.remove(zone).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    /// The anchors by zone.
+    pub fn iter(&self) -> impl Iterator<Item = (&DomainName, &[Anchor])> {
+        self.zones.iter().map(|(z, a)| (z, a.as_slice()))
+    }
+
+    pub fn get(&self, zone: &DomainName) -> Option<&[Anchor]> {
+        self.zones.get(zone).map(Vec::as_slice)
+    }
+
+    /// The closest zone at or above `name` with an anchor.
+    pub fn closest(&self, name: &DomainName) -> Option<&DomainName> {
+        self.zones
+            .keys()
+            .filter(|z| name.is_subdomain_of(z))
+            .max_by_key(|z| z.label_count())
+    }
+
+    /// Turns validation off for `zone` and everything below it until
+    /// `until`, in seconds since the epoch, or until removed: for a zone
+    /// whose operator broke its signatures, which RFC 7646 suggests be
+    /// trusted so for a week at most.
+    pub fn add_negative(&mut self, zone: DomainName, until: Option<u64>) {
+        self.negative.insert(zone, until);
+    }
+
+    /// Drops the negative trust anchor at `zone`; returns whether it had
+    /// one.
+    pub fn remove_negative(&mut self, zone: &DomainName) -> bool {
+        self.negative.remove(zone).is_some()
+    }
+
+    /// The negative trust anchors, with when they lapse.
+    pub fn negative(&self) -> impl Iterator<Item = (&DomainName, Option<u64>)> {
+        self.negative.iter().map(|(z, until)| (z, *until))
+    }
+
+    /// Whether a negative trust anchor at or above `name` is in force at
+    /// `now`.
+    pub fn is_negative(&self, name: &DomainName, now: u64) -> bool {
+        self.negative
+            .iter()
+            .any(|(zone, until)| name.is_subdomain_of(zone) && until.is_none_or(|t| now < t))
+    }
+
+    /// Drops the negative trust anchors that have lapsed by `now`.
+    pub fn expire_negative(&mut self, now: u64) -> Vec<DomainName> {
+        let lapsed: Vec<DomainName> = self
+            .negative
+            .iter()
+            .filter(|(_, until)| until.is_some_and(|t| now >= t))
+            .map(|(zone, _)| zone.clone())
+            .collect();
+        for zone in &lapsed {
+            self.negative.remove(zone);
+        }
+        lapsed
+    }
+
+    /// Whether `zone`'s anchors can be checked at all: a zone anchored
+    /// only with unsupported algorithms or digests validates as
+    /// insecure.
+    pub(crate) fn is_supported(&self, zone: &DomainName) -> bool {
+        self.zones.get(zone).is_some_and(|anchors| {
+            anchors
+                .iter()
+                .any(|a| a.is_trusted() && a.key.is_supported())
+        })
+    }
+
+    /// Whether `key` of `zone` is trusted, and not revoked.
+    pub fn trusts(&self, zone: &DomainName, key: &Dnskey) -> bool {
+        !key.is_revoked()
+            && self.zones.get(zone).is_some_and(|anchors| {
+                anchors
+                    .iter()
+                    .any(|a| a.is_trusted() && a.key.matches(zone, key))
+            })
+    }
+
+    /// Follows the managed anchors of `zone` through the RFC 5011 state
+    /// machine, given its key set validated at `now` and the key tags of
+    /// the keys whose signatures over the set were valid. Returns whether
+    /// anything changed.
+    pub fn observe(
+        &mut self,
+        zone: &DomainName,
+        keys: &[Dnskey],
+        signers: &[u16],
+        now: u64,
+    ) -> bool {
+        let anchors = match self.zones.get_mut(zone) {
+            Some(anchors) if anchors.iter().any(|a| a.managed) => anchors,
+            _ => return false,
+        };
+        let hold_down = HOLD_DOWN.as_secs();
+        let mut changed = false;
+        let set = |anchor: &mut Anchor, state| {
+            if anchor.state == state {
+                return false;
+            }
+            anchor.state = state;
+            anchor.since = now;
+            true
+        };
+        for anchor in anchors.iter_mut().filter(|a| a.managed) {
+            let present = keys.iter().find(|k| anchor.key.matches(zone, k));
+            match (anchor.state, present) {
+                (AnchorState::Revoked, _) => {}
+                // A revoked key counts only if it signed the set itself.
+                (_, Some(key)) if key.is_revoked() && signers.contains(&key.key_tag()) => {
+                    changed |= set(anchor, AnchorState::Revoked)
+                }
+                (_, Some(key)) if key.is_revoked() => {}
+                (AnchorState::AddPending, Some(_)) if now >= anchor.since + hold_down => {
+                    changed |= set(anchor, AnchorState::Valid)
+                }
+                (AnchorState::Missing, Some(_)) => changed |= set(anchor, AnchorState::Valid),
+                (AnchorState::Valid, None) => changed |= set(anchor, AnchorState::Missing),
+                _ => {}
+            }
+            // Once the key is known, hold on to it rather than its digest.
+            if let (AnchorKey::Ds(_), Some(key)) = (&anchor.key, present) {
+                if !key.is_revoked() {
+                    anchor.key = AnchorKey::Dnskey(key.clone());
+                    changed = true;
+                }
+            }
+        }
+        // New keys start their hold-down; pending keys that went away and
+        // revoked keys past theirs are forgotten.
+        let before = anchors.len();
+        anchors.retain(|a| match a.state {
+            AnchorState::AddPending => keys.iter().any(|k| a.key.matches(zone, k)),
+            AnchorState::Revoked => !a.managed || now < a.since + hold_down,
+            _ => true,
+        });
+        changed |= anchors.len() != before;
+        for key in keys {
+            if key.is_sep()
+                && key.is_zone_key()
+                && !key.is_revoked()
+                && !anchors.iter().any(|a| a.key.matches(zone, key))
+            {
+                anchors.push(Anchor {
+                    key: AnchorKey::Dnskey(key.clone()),
+                    state: AnchorState::AddPending,
+                    since: now,
+                    managed: true,
+                });
+                changed = true;
+            }
+        }
+        changed
+    }
+}
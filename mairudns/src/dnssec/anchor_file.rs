@@ -0,0 +1,501 @@
+//! Trust anchors on disk: BIND's `trust-anchors` statement, along with
+//! the `managed-keys` and `trusted-keys` statements it replaced, and the
+//! XML format IANA publishes the root zone's anchors in (RFC 9718).
+
+use std::fmt;
+use std::fmt::Write as _;
+
+use crate::encoding;
+use crate::name::DomainName;
+
+use super::{
+    format_time, parse_time, Algorithm, Anchor, AnchorKey, AnchorState, DigestType, Dnskey, Ds,
+    TrustAnchors,
+};
+
+/// Why a trust anchor file could not be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchorError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AnchorError {
+    fn new(line: usize, message: impl Into<String>) -> AnchorError {
+        AnchorError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AnchorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AnchorError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    Semicolon,
+}
+
+/// Splits BIND configuration text into tokens with their lines, dropping
+/// the three kinds of comments it allows.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, AnchorError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                let start = line;
+                chars.next();
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            last = c;
+                        }
+                        None => return Err(AnchorError::new(start, "unterminated comment")),
+                    }
+                }
+            }
+            '"' => {
+                let start = line;
+                let mut quoted = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => {
+                            line += usize::from(c == '\n');
+                            quoted.push(c);
+                        }
+                        None => return Err(AnchorError::new(start, "unterminated string")),
+                    }
+                }
+                tokens.push((Token::Quoted(quoted), start));
+            }
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            ';' => tokens.push((Token::Semicolon, line)),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{};\"#".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// The text of a word or a quoted string.
+fn text(token: &Token) -> Option<&str> {
+    match token {
+        Token::Word(s) | Token::Quoted(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// Reads one anchor, `name kind a b c "data"`, or with `trusted-keys` no
+/// kind, out of `fields`.
+fn anchor(
+    fields: &[(Token, usize)],
+    statement: &str,
+) -> Result<(DomainName, AnchorKey, bool), AnchorError> {
+    let line = fields[0].1;
+    let words: Vec<&str> = fields.iter().filter_map(|(t, _)| text(t)).collect();
+    if words.len() != fields.len() {
+        return Err(AnchorError::new(line, "unexpected brace"));
+    }
+    let (name, kind, rest) = match (statement, words.as_slice()) {
+        ("trusted-keys", [name, rest @ ..]) => (*name, "static-key", rest),
+        (_, [name, kind, rest @ ..]) => (*name, *kind, rest),
+        _ => return Err(AnchorError::new(line, "incomplete trust anchor")),
+    };
+    let zone: DomainName = name
+        .parse()
+        .map_err(|_| AnchorError::new(line, format!("bad zone name {:?}", name)))?;
+    let (a, b, c, data) = match rest {
+        [a, b, c, data] => (*a, *b, *c, *data),
+        _ => return Err(AnchorError::new(line, "trust anchors have four fields")),
+    };
+    let number = |field: &str, what: &str| {
+        field
+            .parse::<u16>()
+            .map_err(|_| AnchorError::new(line, format!("bad {} {:?}", what, field)))
+    };
+    let small = |field: &str, what: &str| {
+        field
+            .parse::<u8>()
+            .map_err(|_| AnchorError::new(line, format!("bad {} {:?}", what, field)))
+    };
+    // Base64 and hex are often broken over lines within their quotes.
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    let key = match kind {
+        "initial-key" | "static-key" => AnchorKey::Dnskey(Dnskey {
+            flags: number(a, "flags")?,
+            protocol: small(b, "protocol")?,
+            algorithm: Algorithm(small(c, "algorithm")?),
+            public_key: encoding::base64_decode(&data)
+                .map_err(|_| AnchorError::new(line, "bad base64 key"))?,
+        }),
+        "initial-ds" | "static-ds" => AnchorKey::Ds(Ds {
+            key_tag: number(a, "key tag")?,
+            algorithm: Algorithm(small(b, "algorithm")?),
+            digest_type: DigestType(small(c, "digest type")?),
+            digest: encoding::hex_decode(&data)
+                .map_err(|_| AnchorError::new(line, "bad hex digest"))?,
+        }),
+        _ => {
+            return Err(AnchorError::new(
+                line,
+                format!("unknown anchor type {:?}", kind),
+            ))
+        }
+    };
+    Ok((zone, key, kind.starts_with("initial-")))
+}
+
+/// A time in the ISO 8601 form IANA uses, such as
+/// `2017-02-02T00:00:00+00:00`, in seconds since the epoch.
+fn parse_iso_time(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (stamp, zone) = (text.get(..19)?, &text[19..]);
+    let digits: String = stamp.chars().filter(char::is_ascii_digit).collect();
+    if digits.len() != 14 {
+        return None;
+    }
+    let secs = i64::from(parse_time(&digits)?);
+    // Fractions of a second are dropped.
+    let zone = zone.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "" | "Z" => 0,
+        _ => {
+            let (sign, zone) = match (zone.strip_prefix('+'), zone.strip_prefix('-')) {
+                (Some(zone), _) => (1, zone),
+                (_, Some(zone)) => (-1, zone),
+                _ => return None,
+            };
+            let mut parts = zone.split(':');
+            let hours: i64 = parts.next()?.parse().ok()?;
+            let minutes: i64 = parts.next().unwrap_or("0").parse().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+    let secs = secs - offset;
+    if secs < 0 {
+        return None;
+    }
+    Some(secs as u64)
+}
+
+fn iso_time(secs: u64) -> String {
+    let t = format_time(secs.min(u64::from(u32::MAX)) as u32);
+    format!(
+        "{}-{}-{}T{}:{}:{}+00:00",
+        &t[0..4],
+        &t[4..6],
+        &t[6..8],
+        &t[8..10],
+        &t[10..12],
+        &t[12..14]
+    )
+}
+
+/// The elements named `tag` in `xml`, each as its attributes and content,
+/// with the line it starts on.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<(&'a str, &'a str, usize)> {
+    let mut found = Vec::new();
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut rest = 0;
+    while let Some(at) = xml[rest..].find(&open).map(|i| i + rest) {
+        let after = at + open.len();
+        rest = after;
+        if !xml[after..].starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let end = match xml[after..].find('>') {
+            Some(i) => after + i,
+            None => break,
+        };
+        let line = xml[..at].matches('\n').count() + 1;
+        if xml[..end].ends_with('/') {
+            found.push((&xml[after..end - 1], "", line));
+            rest = end;
+            continue;
+        }
+        let body_end = match xml[end..].find(&close) {
+            Some(i) => end + i,
+            None => break,
+        };
+        found.push((&xml[after..end], &xml[end + 1..body_end], line));
+        rest = body_end + close.len();
+    }
+    found
+}
+
+/// The content of the first element named `tag` in `xml`, trimmed.
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    elements(xml, tag).first().map(|(_, body, _)| body.trim())
+}
+
+/// The value of the attribute `name` among `attrs`.
+fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    while let Some(at) = rest.find(name) {
+        let before = rest[..at].chars().last();
+        let after = rest[at + name.len()..].trim_start();
+        rest = &rest[at + name.len()..];
+        if before.is_some_and(|c| !c.is_whitespace()) {
+            continue;
+        }
+        if let Some(value) = after.strip_prefix('=') {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote != '"' && quote != '\'' {
+                return None;
+            }
+            let value = &value[1..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
+    }
+    None
+}
+
+impl TrustAnchors {
+    /// Reads the `trust-anchors`, `managed-keys` and `trusted-keys`
+    /// statements of BIND configuration text, skipping any others. Anchors
+    /// given as `initial-key` or `initial-ds` are managed the RFC 5011 way;
+    /// `static-key`, `static-ds` and those of `trusted-keys` are not.
+    pub fn from_bind(text: &str) -> Result<TrustAnchors, AnchorError> {
+        let tokens = tokenize(text)?;
+        let mut anchors = TrustAnchors::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let (token, line) = &tokens[i];
+            let statement = match token {
+                Token::Word(w) => w.as_str(),
+                Token::Semicolon => {
+                    i += 1;
+                    continue;
+                }
+                _ => return Err(AnchorError::new(*line, "expected a statement")),
+            };
+            // The statement runs to the semicolon after its braces close.
+            let mut depth = 0;
+            let mut end = i;
+            while end < tokens.len() {
+                match tokens[end].0 {
+                    Token::Open => depth += 1,
+                    Token::Close if depth == 0 => {
+                        return Err(AnchorError::new(tokens[end].1, "unbalanced braces"))
+                    }
+                    Token::Close => depth -= 1,
+                    Token::Semicolon if depth == 0 => break,
+                    _ => {}
+                }
+                end += 1;
+            }
+            if depth != 0 {
+                return Err(AnchorError::new(*line, "unbalanced braces"));
+            }
+            if matches!(statement, "trust-anchors" | "managed-keys" | "trusted-keys") {
+                let body = &tokens[i + 1..end];
+                match (body.first(), body.last()) {
+                    (Some((Token::Open, _)), Some((Token::Close, _))) => {}
+                    _ => return Err(AnchorError::new(*line, "expected a block")),
+                }
+                for fields in body[1..body.len() - 1].split(|(t, _)| *t == Token::Semicolon) {
+                    if fields.is_empty() {
+                        continue;
+                    }
+                    let (zone, key, managed) = anchor(fields, statement)?;
+                    if managed {
+                        anchors.insert(
+                            zone,
+                            Anchor {
+                                key,
+                                state: AnchorState::Valid,
+                                since: 0,
+                                managed: true,
+                            },
+                        );
+                    } else {
+                        anchors.add(zone, key);
+                    }
+                }
+            }
+            i = end + 1;
+        }
+        Ok(anchors)
+    }
+
+    /// The trusted anchors as a BIND `trust-anchors` statement: managed
+    /// ones as initial keys or digests, the rest as static ones.
+    pub fn to_bind(&self) -> String {
+        let mut out = String::from("trust-anchors {\n");
+        for (zone, anchors) in self.iter() {
+            for anchor in anchors.iter().filter(|a| a.is_trusted()) {
+                let kind = if anchor.managed { "initial" } else { "static" };
+                let _ = match &anchor.key {
+                    AnchorKey::Dnskey(key) => writeln!(
+                        out,
+                        "\t\"{}\" {}-key {} {} {} \"{}\";",
+                        zone,
+                        kind,
+                        key.flags,
+                        key.protocol,
+                        key.algorithm.0,
+                        encoding::base64_encode(&key.public_key)
+                    ),
+                    AnchorKey::Ds(ds) => writeln!(
+                        out,
+                        "\t\"{}\" {}-ds {} {} {} \"{}\";",
+                        zone,
+                        kind,
+                        ds.key_tag,
+                        ds.algorithm.0,
+                        ds.digest_type.0,
+                        encoding::hex_encode(&ds.digest).to_ascii_uppercase()
+                    ),
+                };
+            }
+        }
+        out.push_str("};\n");
+        out
+    }
+
+    /// Reads IANA's XML trust anchor format, as in `root-anchors.xml`:
+    /// the key digests valid at `now`, in seconds since the epoch, become
+    /// managed anchors.
+    pub fn from_iana_xml(xml: &str, now: u64) -> Result<TrustAnchors, AnchorError> {
+        let (_, document, line) = elements(xml, "TrustAnchor")
+            .into_iter()
+            .next()
+            .ok_or_else(|| AnchorError::new(1, "no TrustAnchor element"))?;
+        let zone =
+            element(document, "Zone").ok_or_else(|| AnchorError::new(line, "no Zone element"))?;
+        let zone: DomainName = zone
+            .parse()
+            .map_err(|_| AnchorError::new(line, format!("bad zone name {:?}", zone)))?;
+        let mut anchors = TrustAnchors::new();
+        for (attrs, body, rel) in elements(document, "KeyDigest") {
+            let line = line + rel - 1;
+            let time = |name: &str| match attribute(attrs, name) {
+                Some(value) => parse_iso_time(value)
+                    .map(Some)
+                    .ok_or_else(|| AnchorError::new(line, format!("bad {} {:?}", name, value))),
+                None => Ok(None),
+            };
+            let from = time("validFrom")?
+                .ok_or_else(|| AnchorError::new(line, "KeyDigest without validFrom"))?;
+            let until = time("validUntil")?;
+            if now < from || until.is_some_and(|until| now >= until) {
+                continue;
+            }
+            let field = |tag: &str| {
+                element(body, tag)
+                    .ok_or_else(|| AnchorError::new(line, format!("KeyDigest without {}", tag)))
+            };
+            let number = |tag: &str| {
+                let value = field(tag)?;
+                value
+                    .parse::<u16>()
+                    .map_err(|_| AnchorError::new(line, format!("bad {} {:?}", tag, value)))
+            };
+            let ds = Ds {
+                key_tag: number("KeyTag")?,
+                algorithm: Algorithm(number("Algorithm")? as u8),
+                digest_type: DigestType(number("DigestType")? as u8),
+                digest: encoding::hex_decode(field("Digest")?)
+                    .map_err(|_| AnchorError::new(line, "bad hex digest"))?,
+            };
+            anchors.insert(
+                zone.clone(),
+                Anchor {
+                    key: AnchorKey::Ds(ds),
+                    state: AnchorState::Valid,
+                    since: from,
+                    managed: true,
+                },
+            );
+        }
+        Ok(anchors)
+    }
+
+    /// The trusted anchors of `zone` in IANA's XML format, each as the
+    /// SHA-256 digest of its key and, where the key is known, the key
+    /// itself.
+    pub fn to_iana_xml(&self, zone: &DomainName) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(out, "<TrustAnchor id=\"{}\">", zone);
+        let _ = writeln!(out, "<Zone>{}</Zone>", zone);
+        for anchor in self
+            .get(zone)
+            .unwrap_or(&[])
+            .iter()
+            .filter(|a| a.is_trusted())
+        {
+            let (ds, key) = match &anchor.key {
+                AnchorKey::Ds(ds) => (ds.clone(), None),
+                AnchorKey::Dnskey(key) => match key.ds(zone, DigestType::SHA256) {
+                    Some(ds) => (ds, Some(key)),
+                    None => continue,
+                },
+            };
+            let _ = writeln!(
+                out,
+                "<KeyDigest id=\"{}\" validFrom=\"{}\">",
+                ds.key_tag,
+                iso_time(anchor.since)
+            );
+            let _ = writeln!(out, "<KeyTag>{}</KeyTag>", ds.key_tag);
+            let _ = writeln!(out, "<Algorithm>{}</Algorithm>", ds.algorithm.0);
+            let _ = writeln!(out, "<DigestType>{}</DigestType>", ds.digest_type.0);
+            let _ = writeln!(
+                out,
+                "<Digest>{}</Digest>",
+                encoding::hex_encode(&ds.digest).to_ascii_uppercase()
+            );
+            if let Some(key) = key {
+                let _ = writeln!(
+                    out,
+                    "<PublicKey>{}</PublicKey>",
+                    encoding::base64_encode(&key.public_key)
+                );
+                let _ = writeln!(out, "<Flags>{}</Flags>", key.flags);
+            }
+            out.push_str("</KeyDigest>\n");
+        }
+        out.push_str("</TrustAnchor>\n");
+        out
+    }
+}
@@ -0,0 +1,49 @@
+//! The canonical form and order of records (RFC 4034 §6), over which
+//! signatures are computed.
+
+use crate::name::DomainName;
+use crate::rr::Record;
+use crate::wire::Encoder;
+
+use super::Rrsig;
+
+/// The owner name a signature covers: the record's own name, or for a
+/// wildcard expansion the wildcard it was expanded from.
+pub(crate) fn signed_owner(owner: &DomainName, labels: u8) -> DomainName {
+    let labels = usize::from(labels);
+    let count = owner.label_count() - usize::from(owner.is_wildcard());
+    if labels < count {
+        owner
+            .suffix(labels)
+            .prepend(b"*")
+            .unwrap_or_else(|_| owner.clone())
+    } else {
+        owner.clone()
+    }
+}
+
+/// The data `rrsig` signs over the RRset `records`: the signature's own
+/// fields, then each record in canonical form and order, with the
+/// original TTL (RFC 4035 §5.3.2).
+pub(crate) fn signed_data(rrsig: &Rrsig, records: &[&Record]) -> Vec<u8> {
+    let mut data = rrsig.signed_fields();
+    let first = match records.first() {
+        Some(rr) => rr,
+        None => return data,
+    };
+    let mut owner = Encoder::uncompressed();
+    owner.canonical_name(&signed_owner(&first.name, rrsig.labels));
+    let owner = owner.into_bytes();
+    let mut rdatas: Vec<Vec<u8>> = records.iter().map(|rr| rr.rdata.canonical()).collect();
+    rdatas.sort();
+    rdatas.dedup();
+    for rd in rdatas {
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&rrsig.type_covered.0.to_be_bytes());
+        data.extend_from_slice(&first.class.0.to_be_bytes());
+        data.extend_from_slice(&rrsig.original_ttl.to_be_bytes());
+        data.extend_from_slice(&(rd.len() as u16).to_be_bytes());
+        data.extend_from_slice(&rd);
+    }
+    data
+}
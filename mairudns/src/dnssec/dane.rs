@@ -0,0 +1,192 @@
+//! DANE (RFC 6698, RFC 7671): authenticating a TLS server by the TLSA
+//! records its zone publishes, which count only when DNSSEC-secure.
+
+use std::fmt;
+
+use crate::crypto::{Digest, Sha256, Sha512};
+use crate::der;
+use crate::encoding;
+use crate::name::DomainName;
+use crate::rr::{RData, RecordType};
+
+use super::{Status, Validated};
+
+/// A TLSA record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tlsa {
+    pub usage: u8,
+    pub selector: u8,
+    pub matching_type: u8,
+    pub data: Vec<u8>,
+}
+
+impl Tlsa {
+    /// The certificate must pass PKIX validation and be one of the chain's
+    /// CAs.
+    pub const PKIX_TA: u8 = 0;
+    /// The certificate must pass PKIX validation and be the server's.
+    pub const PKIX_EE: u8 = 1;
+    /// The certificate is the trust anchor the chain must lead to.
+    pub const DANE_TA: u8 = 2;
+    /// The certificate is the server's, with nothing else checked.
+    pub const DANE_EE: u8 = 3;
+
+    /// The whole certificate is matched.
+    pub const CERT: u8 = 0;
+    /// Only its SubjectPublicKeyInfo is.
+    pub const SPKI: u8 = 1;
+
+    pub const FULL: u8 = 0;
+    pub const SHA256: u8 = 1;
+    pub const SHA512: u8 = 2;
+
+    pub fn from_rdata(rdata: &RData) -> Option<Tlsa> {
+        let data = match rdata {
+            RData::Unknown { rtype, data } if *rtype == RecordType::TLSA => data,
+            _ => return None,
+        };
+        if data.len() < 3 {
+            return None;
+        }
+        Some(Tlsa {
+            usage: data[0],
+            selector: data[1],
+            matching_type: data[2],
+            data: data[3..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut data = Vec::with_capacity(3 + self.data.len());
+        data.extend_from_slice(&[self.usage, self.selector, self.matching_type]);
+        data.extend_from_slice(&self.data);
+        RData::Unknown {
+            rtype: RecordType::TLSA,
+            data,
+        }
+    }
+
+    /// Whether the record's usage, selector and matching type are all
+    /// known, so that it can be checked at all.
+    pub fn is_usable(&self) -> bool {
+        self.usage <= Tlsa::DANE_EE
+            && self.selector <= Tlsa::SPKI
+            && self.matching_type <= Tlsa::SHA512
+    }
+
+    /// Whether `cert`, a DER-encoded X.509 certificate, is the one this
+    /// record describes, whatever its usage.
+    pub fn matches(&self, cert: &[u8]) -> bool {
+        let selected = match self.selector {
+            Tlsa::CERT => Some(cert),
+            Tlsa::SPKI => der::spki(cert),
+            _ => None,
+        };
+        let selected = match selected {
+            Some(selected) => selected,
+            None => return false,
+        };
+        match self.matching_type {
+            Tlsa::FULL => selected == self.data.as_slice(),
+            Tlsa::SHA256 => Sha256::digest(selected) == self.data,
+            Tlsa::SHA512 => Sha512::digest(selected) == self.data,
+            _ => false,
+        }
+    }
+
+    /// Whether one of `chain`, the server's certificate first, is the one
+    /// this record's usage calls for: the server's own, or one of the CAs
+    /// after it.
+    fn matches_chain<C: AsRef<[u8]>>(&self, chain: &[C]) -> bool {
+        match self.usage {
+            Tlsa::PKIX_EE | Tlsa::DANE_EE => {
+                chain.first().is_some_and(|c| self.matches(c.as_ref()))
+            }
+            // A chain of one may be a self-signed certificate named as
+            // its own trust anchor.
+            Tlsa::DANE_TA if chain.len() == 1 => self.matches(chain[0].as_ref()),
+            Tlsa::PKIX_TA | Tlsa::DANE_TA => chain.iter().skip(1).any(|c| self.matches(c.as_ref())),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Tlsa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.usage,
+            self.selector,
+            self.matching_type,
+            encoding::hex_encode(&self.data).to_ascii_uppercase()
+        )
+    }
+}
+
+/// The owner of the TLSA records for a service on `port` of `host` over
+/// `protocol`, such as `_25._tcp.mail.example.`.
+pub fn tlsa_owner(host: &DomainName, port: u16, protocol: &str) -> Option<DomainName> {
+    host.prepend(format!("_{}", protocol).as_bytes())
+        .and_then(|name| name.prepend(format!("_{}", port).as_bytes()))
+        .ok()
+}
+
+/// What DANE makes of a server's certificates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Dane {
+    /// The TLSA records are matched by the certificate chain. With usage
+    /// PKIX-TA or PKIX-EE, the chain must still pass PKIX validation; with
+    /// DANE-TA, its signatures must lead to the certificate matched.
+    Matched(Tlsa),
+    /// Secure TLSA records were found but none matches: the server must
+    /// not be trusted.
+    Mismatch,
+    /// There are no secure, usable TLSA records: DANE has nothing to say,
+    /// and the server is authenticated, or not, as it would be without.
+    Insecure,
+    /// The TLSA records could not be validated, because they are bogus or
+    /// the keys could not be fetched: the server must not be trusted
+    /// (RFC 7672 §2.1.1).
+    Failed,
+}
+
+impl Dane {
+    /// Whether the server's certificates are authenticated by DANE alone,
+    /// without PKIX validation.
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self, Dane::Matched(tlsa) if tlsa.usage >= Tlsa::DANE_TA)
+    }
+}
+
+/// Checks `cert_chain`, the DER-encoded certificates a server presented,
+/// its own first, against the TLSA records of `tlsa`, a validated response
+/// to a TLSA query for the server (RFC 7671 §5). The records count only
+/// if the response is secure.
+pub fn verify_tlsa<C: AsRef<[u8]>>(cert_chain: &[C], tlsa: &Validated) -> Dane {
+    match tlsa.status {
+        Status::Secure => {}
+        Status::Insecure => return Dane::Insecure,
+        Status::Bogus | Status::Indeterminate => return Dane::Failed,
+    }
+    let records: Vec<Tlsa> = tlsa
+        .message
+        .answers
+        .iter()
+        .filter_map(|rr| Tlsa::from_rdata(&rr.rdata))
+        .filter(Tlsa::is_usable)
+        .collect();
+    if records.is_empty() {
+        return Dane::Insecure;
+    }
+    // Records that need no PKIX validation are the stronger match.
+    let mut matched: Vec<&Tlsa> = records
+        .iter()
+        .filter(|t| t.matches_chain(cert_chain))
+        .collect();
+    matched.sort_by_key(|t| std::cmp::Reverse(t.usage));
+    match matched.first() {
+        Some(tlsa) => Dane::Matched((*tlsa).clone()),
+        None => Dane::Mismatch,
+    }
+}
@@ -0,0 +1,328 @@
+//! Authenticated denial of existence: checking that NSEC (RFC 4035 §5.4)
+//! and NSEC3 (RFC 5155 §8) records prove a name or type absent.
+
+use std::cmp::Ordering;
+
+use crate::crypto::{Digest, Sha1};
+use crate::encoding;
+use crate::name::DomainName;
+use crate::rr::{Record, RecordType};
+use crate::wire::Encoder;
+
+use super::{Nsec, Nsec3};
+
+/// NSEC3 chains iterating the hash more often than this are treated as
+/// insecure rather than hashed (RFC 9276 §3.2).
+pub const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+/// The NSEC3 hash of `name` (RFC 5155 §5).
+pub fn nsec3_hash(name: &DomainName, salt: &[u8], iterations: u16) -> Vec<u8> {
+    let mut enc = Encoder::uncompressed();
+    enc.canonical_name(name);
+    let mut hash = enc.into_bytes();
+    for _ in 0..=iterations {
+        let mut h = Sha1::default();
+        h.update(&hash);
+        h.update(salt);
+        hash = h.finish();
+    }
+    hash
+}
+
+/// What a set of denial records proves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Proof {
+    Secure,
+    /// The records are sound but cannot prove anything: an opt-out span
+    /// or too many NSEC3 iterations.
+    Insecure,
+    Failed,
+}
+
+/// Whether a name without a DS record is a delegation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Cut {
+    /// A delegation proven to have no DS record: an insecure child.
+    Unsigned,
+    /// The name is part of the zone, or does not exist.
+    Inside,
+    Failed,
+}
+
+/// The longest name both `a` and `b` lie beneath.
+fn common_ancestor(a: &DomainName, b: &DomainName) -> DomainName {
+    let shared = a
+        .labels()
+        .iter()
+        .rev()
+        .zip(b.labels().iter().rev())
+        .take_while(|(x, y)| x.eq_ignore_ascii_case(y))
+        .count();
+    a.suffix(shared)
+}
+
+/// Whether `name` falls strictly between `owner` and `next` in canonical
+/// order, the last record of a chain wrapping round to the first.
+fn between<T: Ord + ?Sized>(owner: &T, next: &T, name: &T) -> bool {
+    match owner.cmp(next) {
+        Ordering::Less => owner < name && name < next,
+        _ => owner < name || name < next,
+    }
+}
+
+/// The validated NSEC and NSEC3 records of a response, from one zone.
+#[derive(Debug, Default)]
+pub(crate) struct Denials {
+    zone: DomainName,
+    nsec: Vec<(DomainName, Nsec)>,
+    /// Owners' hashes, decoded, with their records; only those of the
+    /// known hash algorithm.
+    nsec3: Vec<(Vec<u8>, Nsec3)>,
+    /// An NSEC3 record hashed too often to be used was present.
+    too_costly: bool,
+}
+
+impl Denials {
+    pub(crate) fn new<'a, I: IntoIterator<Item = &'a Record>>(
+        zone: &DomainName,
+        records: I,
+    ) -> Denials {
+        let mut denials = Denials {
+            zone: zone.clone(),
+            ..Denials::default()
+        };
+        for rr in records {
+            if !rr.name.is_subdomain_of(zone) {
+                continue;
+            }
+            if let Some(nsec) = Nsec::from_rdata(&rr.rdata) {
+                denials.nsec.push((rr.name.clone(), nsec));
+            } else if let Some(nsec3) = Nsec3::from_rdata(&rr.rdata) {
+                if nsec3.hash_algorithm != Nsec3::SHA1 || rr.name.parent().as_ref() != Some(zone) {
+                    continue;
+                }
+                if nsec3.iterations > MAX_NSEC3_ITERATIONS {
+                    denials.too_costly = true;
+                    continue;
+                }
+                let label = String::from_utf8_lossy(&rr.name.labels()[0]).into_owned();
+                if let Ok(hash) = encoding::base32hex_decode(&label) {
+                    denials.nsec3.push((hash, nsec3));
+                }
+            }
+        }
+        denials
+    }
+
+    /// What to conclude when no record proves anything.
+    fn unproven(&self) -> Proof {
+        if self.too_costly {
+            Proof::Insecure
+        } else {
+            Proof::Failed
+        }
+    }
+
+    /// The NSEC record owned by `name`.
+    fn nsec_at(&self, name: &DomainName) -> Option<&Nsec> {
+        self.nsec.iter().find(|(o, _)| o == name).map(|(_, n)| n)
+    }
+
+    /// The NSEC record proving `name` does not exist, if any. A record
+    /// owned by a delegation or DNAME above `name` proves nothing about
+    /// names beneath it (RFC 6840 §4.1).
+    fn nsec_covering(&self, name: &DomainName) -> Option<(&DomainName, &Nsec)> {
+        self.nsec
+            .iter()
+            .filter(|(owner, nsec)| {
+                name.is_subdomain_of(&self.zone) && between(owner, &nsec.next, name)
+            })
+            .find(|(owner, nsec)| {
+                let cut = nsec.has(RecordType::NS) && !nsec.has(RecordType::SOA);
+                !(name.is_subdomain_of(owner) && (cut || nsec.has(RecordType::DNAME)))
+            })
+            .map(|(o, n)| (o, n))
+    }
+
+    fn hash(&self, name: &DomainName) -> Option<Vec<u8>> {
+        let (_, first) = self.nsec3.first()?;
+        Some(nsec3_hash(name, &first.salt, first.iterations))
+    }
+
+    fn nsec3_matching(&self, name: &DomainName) -> Option<&Nsec3> {
+        let hash = self.hash(name)?;
+        self.nsec3.iter().find(|(h, _)| *h == hash).map(|(_, n)| n)
+    }
+
+    fn nsec3_covering(&self, name: &DomainName) -> Option<&Nsec3> {
+        let hash = self.hash(name)?;
+        self.nsec3
+            .iter()
+            .find(|(h, n)| between(h.as_slice(), n.next_hashed.as_slice(), &hash[..]))
+            .map(|(_, n)| n)
+    }
+
+    /// The closest encloser proof of RFC 5155 §7.2.1 for a name that does
+    /// not exist: the closest encloser, and the NSEC3 covering the next
+    /// closer name.
+    fn closest_encloser(&self, name: &DomainName) -> Option<(DomainName, &Nsec3)> {
+        let zone_labels = self.zone.label_count();
+        for labels in (zone_labels..name.label_count()).rev() {
+            let encloser = name.suffix(labels);
+            if let Some(nsec3) = self.nsec3_matching(&encloser) {
+                // An encloser that is a delegation or DNAME cannot be one
+                // (RFC 5155 §8.3).
+                let cut = nsec3.has(RecordType::NS) && !nsec3.has(RecordType::SOA);
+                if cut || nsec3.has(RecordType::DNAME) {
+                    return None;
+                }
+                let next_closer = name.suffix(labels + 1);
+                return self.nsec3_covering(&next_closer).map(|n| (encloser, n));
+            }
+        }
+        None
+    }
+
+    /// The closest encloser NSEC records show for a name they prove does
+    /// not exist.
+    fn nsec_encloser(&self, name: &DomainName, owner: &DomainName, nsec: &Nsec) -> DomainName {
+        let a = common_ancestor(name, owner);
+        let b = common_ancestor(name, &nsec.next);
+        if a.label_count() >= b.label_count() {
+            a
+        } else {
+            b
+        }
+    }
+
+    /// Proof that `name` does not exist, nor a wildcard that could have
+    /// answered for it.
+    pub(crate) fn name_error(&self, name: &DomainName) -> Proof {
+        if let Some((owner, nsec)) = self.nsec_covering(name) {
+            let encloser = self.nsec_encloser(name, owner, nsec);
+            return match encloser.prepend(b"*") {
+                Ok(wildcard) if self.nsec_covering(&wildcard).is_some() => Proof::Secure,
+                _ => Proof::Failed,
+            };
+        }
+        if let Some((encloser, next_closer)) = self.closest_encloser(name) {
+            let wildcard = match encloser.prepend(b"*") {
+                Ok(wildcard) => wildcard,
+                Err(_) => return Proof::Failed,
+            };
+            if self.nsec3_covering(&wildcard).is_none() {
+                return Proof::Failed;
+            }
+            return if next_closer.is_opt_out() {
+                Proof::Insecure
+            } else {
+                Proof::Secure
+            };
+        }
+        self.unproven()
+    }
+
+    /// Proof that `name` has no records of `rtype`, possibly through a
+    /// wildcard, nor a CNAME.
+    pub(crate) fn no_data(&self, name: &DomainName, rtype: RecordType) -> Proof {
+        let absent = |types: &[RecordType]| {
+            let has = |t| types.contains(&t);
+            if has(rtype) || has(RecordType::CNAME) {
+                return false;
+            }
+            // A DS answer comes from the parent side of a cut, anything
+            // else from the child side (RFC 4035 §5.4).
+            if rtype == RecordType::DS {
+                !has(RecordType::SOA) || name.is_root()
+            } else {
+                !has(RecordType::NS) || has(RecordType::SOA)
+            }
+        };
+        if let Some(nsec) = self.nsec_at(name) {
+            return if absent(&nsec.types) {
+                Proof::Secure
+            } else {
+                Proof::Failed
+            };
+        }
+        if let Some((owner, nsec)) = self.nsec_covering(name) {
+            // An empty non-terminal: the next name lies beneath.
+            if nsec.next.is_subdomain_of(name) && nsec.next != *name {
+                return Proof::Secure;
+            }
+            let encloser = self.nsec_encloser(name, owner, nsec);
+            return match encloser.prepend(b"*") {
+                Ok(wildcard) if self.nsec_at(&wildcard).is_some_and(|n| absent(&n.types)) => {
+                    Proof::Secure
+                }
+                _ => Proof::Failed,
+            };
+        }
+        if let Some(nsec3) = self.nsec3_matching(name) {
+            return if absent(&nsec3.types) {
+                Proof::Secure
+            } else {
+                Proof::Failed
+            };
+        }
+        if let Some((encloser, next_closer)) = self.closest_encloser(name) {
+            // No DS at an unsigned delegation in an opt-out span (RFC 5155
+            // §8.6).
+            if rtype == RecordType::DS && next_closer.is_opt_out() {
+                return Proof::Insecure;
+            }
+            return match encloser.prepend(b"*") {
+                Ok(wildcard)
+                    if self
+                        .nsec3_matching(&wildcard)
+                        .is_some_and(|n| absent(&n.types)) =>
+                {
+                    Proof::Secure
+                }
+                _ => Proof::Failed,
+            };
+        }
+        self.unproven()
+    }
+
+    /// Proof that `name`, answered from a wildcard whose signature covers
+    /// `labels` labels, does not exist itself (RFC 4035 §5.3.4, RFC 5155
+    /// §8.8).
+    pub(crate) fn wildcard_answer(&self, name: &DomainName, labels: u8) -> Proof {
+        if self.nsec_covering(name).is_some() {
+            return Proof::Secure;
+        }
+        let next_closer = name.suffix(usize::from(labels) + 1);
+        if self.nsec3_covering(&next_closer).is_some() {
+            return Proof::Secure;
+        }
+        self.unproven()
+    }
+
+    /// Whether `name`, which has no DS record, is a delegation.
+    pub(crate) fn cut(&self, name: &DomainName) -> Cut {
+        let types = self
+            .nsec_at(name)
+            .map(|n| &n.types)
+            .or_else(|| self.nsec3_matching(name).map(|n| &n.types));
+        if let Some(types) = types {
+            let has = |t| types.contains(&t);
+            return if has(RecordType::DS) || (has(RecordType::SOA) && !name.is_root()) {
+                Cut::Failed
+            } else if has(RecordType::NS) {
+                Cut::Unsigned
+            } else {
+                Cut::Inside
+            };
+        }
+        if self.nsec_covering(name).is_some() {
+            return Cut::Inside;
+        }
+        match self.closest_encloser(name) {
+            Some((_, next_closer)) if next_closer.is_opt_out() => Cut::Unsigned,
+            Some(_) => Cut::Inside,
+            None if self.too_costly => Cut::Unsigned,
+            None => Cut::Failed,
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! Private keys that sign zones, and the BIND private-key file format
+//! they are usually kept in.
+
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::sync::Arc;
+
+use ed25519_dalek::Signer;
+use p256::ecdsa::signature::hazmat::PrehashSigner;
+use rand_core::{OsRng, RngCore};
+use rsa::traits::{PrivateKeyParts, PublicKeyParts};
+use rsa::{BigUint, RsaPrivateKey};
+
+use crate::crypto::{Digest, Sha256, Sha384};
+use crate::encoding;
+
+use super::algorithm;
+use super::{Algorithm, Dnskey};
+
+/// Why a private key could not be used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyError {
+    /// The algorithm cannot sign.
+    Unsupported(Algorithm),
+    /// The key file is malformed; the text says how.
+    Format(String),
+    /// The key material is not a valid key of its algorithm.
+    Invalid,
+    /// A [`KeyBackend`] failed; the text says how.
+    Backend(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Unsupported(alg) => write!(f, "algorithm {} cannot sign", alg),
+            KeyError::Format(what) => write!(f, "malformed private key: {}", what),
+            KeyError::Invalid => f.write_str("invalid key material"),
+            KeyError::Backend(what) => write!(f, "key backend: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// Keeps private keys outside the process and signs with them, as a
+/// hardware security module or a cloud KMS does. Keys are named by label,
+/// like PKCS#11 objects; an implementation over a PKCS#11 token maps the
+/// methods onto `C_GenerateKeyPair`, `C_FindObjects`, `C_Sign` and
+/// `C_DestroyObject`.
+pub trait KeyBackend: Send + Sync {
+    /// Makes a key pair of `algorithm` under `label`, returning its public
+    /// key as a DNSKEY record carries it.
+    fn generate(&self, label: &str, algorithm: Algorithm) -> Result<Vec<u8>, KeyError>;
+
+    /// The public key under `label`, if there is one.
+    fn public_key(&self, label: &str) -> Result<Option<Vec<u8>>, KeyError>;
+
+    /// Signs `data` with the key under `label`, in the signature format
+    /// of RRSIG records.
+    fn sign(&self, label: &str, algorithm: Algorithm, data: &[u8]) -> Result<Vec<u8>, KeyError>;
+
+    /// Destroys the key under `label`.
+    fn destroy(&self, label: &str) -> Result<(), KeyError>;
+}
+
+#[derive(Clone)]
+enum Secret {
+    Rsa(RsaPrivateKey),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+    External(Arc<dyn KeyBackend>, String),
+}
+
+/// The modulus size of RSA keys [`SigningKey::generate`] makes.
+pub const DEFAULT_RSA_BITS: usize = 2048;
+
+/// A private key with the DNSKEY record that publishes it.
+#[derive(Clone)]
+pub struct SigningKey {
+    dnskey: Dnskey,
+    secret: Secret,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("dnskey", &self.dnskey)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The RFC 3110 encoding of an RSA public key.
+fn rsa_public(key: &RsaPrivateKey) -> Vec<u8> {
+    let e = key.e().to_bytes_be();
+    let mut out = Vec::with_capacity(3 + e.len() + key.size());
+    match u8::try_from(e.len()) {
+        Ok(len) => out.push(len),
+        Err(_) => {
+            out.push(0);
+            out.extend_from_slice(&(e.len() as u16).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(&e);
+    out.extend_from_slice(&key.n().to_bytes_be());
+    out
+}
+
+impl SigningKey {
+    fn new(flags: u16, algorithm: Algorithm, secret: Secret) -> SigningKey {
+        let public_key = match &secret {
+            Secret::Rsa(key) => rsa_public(key),
+            Secret::P256(key) => {
+                key.verifying_key().to_encoded_point(false).as_bytes()[1..].to_vec()
+            }
+            Secret::P384(key) => {
+                key.verifying_key().to_encoded_point(false).as_bytes()[1..].to_vec()
+            }
+            Secret::Ed25519(key) => key.verifying_key().to_bytes().to_vec(),
+            Secret::External(..) => unreachable!("external keys carry their public key"),
+        };
+        SigningKey {
+            dnskey: Dnskey::new(flags, algorithm, public_key),
+            secret,
+        }
+    }
+
+    /// A new key of `algorithm`, made with the operating system's random
+    /// number generator; RSA keys get a [`DEFAULT_RSA_BITS`] modulus.
+    pub fn generate(flags: u16, algorithm: Algorithm) -> Result<SigningKey, KeyError> {
+        match algorithm {
+            Algorithm::ED25519 => {
+                let mut seed = [0; 32];
+                OsRng.fill_bytes(&mut seed);
+                SigningKey::ed25519(flags, &seed)
+            }
+            Algorithm::ECDSAP256SHA256 => Ok(SigningKey::new(
+                flags,
+                algorithm,
+                Secret::P256(p256::ecdsa::SigningKey::random(&mut OsRng)),
+            )),
+            Algorithm::ECDSAP384SHA384 => Ok(SigningKey::new(
+                flags,
+                algorithm,
+                Secret::P384(p384::ecdsa::SigningKey::random(&mut OsRng)),
+            )),
+            _ => SigningKey::generate_rsa(flags, algorithm, DEFAULT_RSA_BITS),
+        }
+    }
+
+    /// A new RSA key of `algorithm` with a `bits`-bit modulus.
+    pub fn generate_rsa(
+        flags: u16,
+        algorithm: Algorithm,
+        bits: usize,
+    ) -> Result<SigningKey, KeyError> {
+        if algorithm::rsa_hash(algorithm, &[]).is_none() {
+            return Err(KeyError::Unsupported(algorithm));
+        }
+        let key = RsaPrivateKey::new(&mut OsRng, bits).map_err(|_| KeyError::Invalid)?;
+        Ok(SigningKey::new(flags, algorithm, Secret::Rsa(key)))
+    }
+
+    /// The key under `label` in `backend`, which signs with it.
+    pub fn external(
+        flags: u16,
+        algorithm: Algorithm,
+        backend: Arc<dyn KeyBackend>,
+        label: &str,
+    ) -> Result<SigningKey, KeyError> {
+        let public_key = backend
+            .public_key(label)?
+            .ok_or_else(|| KeyError::Backend(format!("no key labelled {}", label)))?;
+        Ok(SigningKey {
+            dnskey: Dnskey::new(flags, algorithm, public_key),
+            secret: Secret::External(backend, label.to_owned()),
+        })
+    }
+
+    /// An Ed25519 key from its 32-byte seed.
+    pub fn ed25519(flags: u16, seed: &[u8]) -> Result<SigningKey, KeyError> {
+        let seed: [u8; 32] = seed.try_into().map_err(|_| KeyError::Invalid)?;
+        let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+        Ok(SigningKey::new(
+            flags,
+            Algorithm::ED25519,
+            Secret::Ed25519(key),
+        ))
+    }
+
+    /// An ECDSA key of `algorithm` from its private scalar.
+    pub fn ecdsa(flags: u16, algorithm: Algorithm, scalar: &[u8]) -> Result<SigningKey, KeyError> {
+        let secret = match algorithm {
+            Algorithm::ECDSAP256SHA256 => Secret::P256(
+                p256::ecdsa::SigningKey::from_slice(scalar).map_err(|_| KeyError::Invalid)?,
+            ),
+            Algorithm::ECDSAP384SHA384 => Secret::P384(
+                p384::ecdsa::SigningKey::from_slice(scalar).map_err(|_| KeyError::Invalid)?,
+            ),
+            other => return Err(KeyError::Unsupported(other)),
+        };
+        Ok(SigningKey::new(flags, algorithm, secret))
+    }
+
+    /// An RSA key of `algorithm` from its modulus, exponents and primes,
+    /// big-endian.
+    pub fn rsa(
+        flags: u16,
+        algorithm: Algorithm,
+        modulus: &[u8],
+        public_exponent: &[u8],
+        private_exponent: &[u8],
+        primes: &[&[u8]],
+    ) -> Result<SigningKey, KeyError> {
+        if algorithm::rsa_hash(algorithm, &[]).is_none() {
+            return Err(KeyError::Unsupported(algorithm));
+        }
+        let key = RsaPrivateKey::from_components(
+            BigUint::from_bytes_be(modulus),
+            BigUint::from_bytes_be(public_exponent),
+            BigUint::from_bytes_be(private_exponent),
+            primes.iter().map(|p| BigUint::from_bytes_be(p)).collect(),
+        )
+        .map_err(|_| KeyError::Invalid)?;
+        key.validate().map_err(|_| KeyError::Invalid)?;
+        Ok(SigningKey::new(flags, algorithm, Secret::Rsa(key)))
+    }
+
+    /// Reads a key in the BIND private-key format (`Private-key-format:
+    /// v1.3`, as in `K<zone>+<alg>+<tag>.private` files). The format
+    /// leaves out the DNSKEY flags, hence `flags`.
+    pub fn from_bind(flags: u16, text: &str) -> Result<SigningKey, KeyError> {
+        let mut fields = Vec::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| KeyError::Format(format!("line {:?} has no field name", line)))?;
+            fields.push((name.trim(), value.trim()));
+        }
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|&(_, v)| v)
+                .ok_or_else(|| KeyError::Format(format!("no {} field", name)))
+        };
+        let bytes = |name: &str| {
+            encoding::base64_decode(field(name)?)
+                .map_err(|_| KeyError::Format(format!("{} is not base64", name)))
+        };
+        let format = field("Private-key-format")?;
+        if !format.starts_with("v1.") {
+            return Err(KeyError::Format(format!("unknown format {}", format)));
+        }
+        let algorithm = field("Algorithm")?
+            .split_whitespace()
+            .next()
+            .and_then(|a| a.parse().ok())
+            .map(Algorithm)
+            .ok_or_else(|| KeyError::Format("bad Algorithm field".into()))?;
+        match algorithm {
+            Algorithm::ED25519 => SigningKey::ed25519(flags, &bytes("PrivateKey")?),
+            Algorithm::ECDSAP256SHA256 | Algorithm::ECDSAP384SHA384 => {
+                SigningKey::ecdsa(flags, algorithm, &bytes("PrivateKey")?)
+            }
+            _ if algorithm::rsa_hash(algorithm, &[]).is_some() => SigningKey::rsa(
+                flags,
+                algorithm,
+                &bytes("Modulus")?,
+                &bytes("PublicExponent")?,
+                &bytes("PrivateExponent")?,
+                &[&bytes("Prime1")?, &bytes("Prime2")?],
+            ),
+            other => Err(KeyError::Unsupported(other)),
+        }
+    }
+
+    /// The key in the BIND private-key format. A key held in a
+    /// [`KeyBackend`] is written as its label alone.
+    pub fn to_bind(&self) -> String {
+        let alg = self.algorithm();
+        let mut out = format!("Private-key-format: v1.3\nAlgorithm: {} ({})\n", alg.0, alg);
+        if let Some(label) = self.label() {
+            out.push_str(&format!("Label: {}\n", label));
+            return out;
+        }
+        let mut field = |name: &str, value: &[u8]| {
+            out.push_str(&format!("{}: {}\n", name, encoding::base64_encode(value)));
+        };
+        match &self.secret {
+            Secret::Rsa(key) => {
+                field("Modulus", &key.n().to_bytes_be());
+                field("PublicExponent", &key.e().to_bytes_be());
+                field("PrivateExponent", &key.d().to_bytes_be());
+                let primes = key.primes();
+                field("Prime1", &primes[0].to_bytes_be());
+                field("Prime2", &primes[1].to_bytes_be());
+                if let (Some(dp), Some(dq), Some(qinv)) =
+                    (key.dp(), key.dq(), key.crt_coefficient())
+                {
+                    field("Exponent1", &dp.to_bytes_be());
+                    field("Exponent2", &dq.to_bytes_be());
+                    field("Coefficient", &qinv.to_bytes_be());
+                }
+            }
+            Secret::P256(key) => field("PrivateKey", &key.to_bytes()),
+            Secret::P384(key) => field("PrivateKey", &key.to_bytes()),
+            Secret::Ed25519(key) => field("PrivateKey", key.as_bytes()),
+            Secret::External(..) => {}
+        }
+        out
+    }
+
+    pub fn dnskey(&self) -> &Dnskey {
+        &self.dnskey
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.dnskey.algorithm
+    }
+
+    pub fn key_tag(&self) -> u16 {
+        self.dnskey.key_tag()
+    }
+
+    /// The label of a key held in a [`KeyBackend`].
+    pub fn label(&self) -> Option<&str> {
+        match &self.secret {
+            Secret::External(_, label) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// Whether the key signs the key set only, as a key-signing key does.
+    pub fn is_ksk(&self) -> bool {
+        self.dnskey.is_sep()
+    }
+
+    /// Signs `data`, in the signature format of RRSIG records. Only keys
+    /// held in a [`KeyBackend`] can fail to.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        Ok(match &self.secret {
+            Secret::Rsa(key) => {
+                let (scheme, hash) =
+                    algorithm::rsa_hash(self.algorithm(), data).expect("RSA key of RSA algorithm");
+                key.sign(scheme, &hash)
+                    .expect("hash fits the modulus of a validated key")
+            }
+            Secret::P256(key) => {
+                let sig: p256::ecdsa::Signature = key
+                    .sign_prehash(&Sha256::digest(data))
+                    .expect("SHA-256 is a valid prehash");
+                sig.to_bytes().to_vec()
+            }
+            Secret::P384(key) => {
+                let sig: p384::ecdsa::Signature = key
+                    .sign_prehash(&Sha384::digest(data))
+                    .expect("SHA-384 is a valid prehash");
+                sig.to_bytes().to_vec()
+            }
+            Secret::Ed25519(key) => key.sign(data).to_bytes().to_vec(),
+            Secret::External(backend, label) => backend.sign(label, self.algorithm(), data)?,
+        })
+    }
+}
@@ -0,0 +1,69 @@
+//! DNSSEC (RFC 4033–4035), with the `dnssec` feature.
+//!
+//! The record types DNSSEC adds are carried by [`RData::Unknown`] like any
+//! other type the codec has no variant for; [`Dnskey`], [`Ds`], [`Rrsig`],
+//! [`Nsec`], [`Nsec3`] and [`Nsec3Param`] parse them out of it and back.
+//!
+//! A [`Validator`] wraps a [`Resolver`] and checks what it returns against
+//! a chain of trust from its [`TrustAnchors`]: by default the root zone's
+//! keys, followed through rollovers as RFC 5011 describes, or anchors read
+//! from BIND configuration or IANA's XML format. Negative trust anchors
+//! turn validation off below zones known to be broken. Signatures made
+//! with RSA/SHA-1 and RSA/SHA-2, ECDSA P-256 and P-384, and Ed25519 are
+//! verified; data signed only with other algorithms validates as
+//! insecure.
+//!
+//! Zones are signed with [`SigningKey`]s either in advance, all at once,
+//! by [`sign_zone`], or answer by answer as they are served by an
+//! [`OnlineSigner`], which suits zones that change through dynamic updates.
+//! A zone signed in advance answers with the signatures and NSEC or NSEC3
+//! records [`add_proofs`] picks from it.
+//! A [`KeyStore`] makes the keys, rolls them over as its [`KeyPolicy`]
+//! schedules, and keeps them on disk, encrypted if wanted, or in an
+//! external [`KeyBackend`] such as an HSM. Its key-signing key rollovers
+//! can wait on a [`DsChecker`] seeing the parent publish the new DS
+//! records, which [`ds_records`] gives and the CDS and CDNSKEY records a
+//! signed zone carries ask for (RFC 8078). Changing a [`KeyPolicy`]'s
+//! algorithm rolls the keys over to it without breaking validation. A zone
+//! served by several providers, each signing with its own keys (RFC 8901),
+//! publishes the others' [`foreign_keys`] alongside its own.
+//!
+//! [`verify_tlsa`] checks a TLS server's certificates against the TLSA
+//! records a validator found for it, as DANE (RFC 6698) has it, and
+//! [`verify_sshfp`] an SSH server's host key against its SSHFP records.
+//!
+//! [`RData::Unknown`]: crate::rr::RData::Unknown
+//! [`Resolver`]: crate::resolver::Resolver
+
+mod algorithm;
+mod anchor;
+mod anchor_file;
+mod canonical;
+mod dane;
+mod denial;
+mod key;
+mod online;
+mod parent;
+mod proof;
+mod record;
+mod signer;
+mod sshfp;
+mod store;
+mod validator;
+
+pub use self::algorithm::{Algorithm, DigestType};
+pub use self::anchor::{Anchor, AnchorKey, AnchorState, TrustAnchors, HOLD_DOWN};
+pub use self::anchor_file::AnchorError;
+pub use self::dane::{tlsa_owner, verify_tlsa, Dane, Tlsa};
+pub use self::denial::{nsec3_hash, MAX_NSEC3_ITERATIONS};
+pub use self::key::{KeyBackend, KeyError, SigningKey, DEFAULT_RSA_BITS};
+pub use self::online::OnlineSigner;
+pub use self::parent::{
+    cdnskey_records, cds_records, delete_records, ds_records, DsChecker, ParentDs,
+};
+pub use self::proof::{add_proofs, denial_records};
+pub use self::record::{format_time, parse_time, Dnskey, Ds, Nsec, Nsec3, Nsec3Param, Rrsig};
+pub use self::signer::{foreign_keys, sign_zone, Denial, SignError, SigningPolicy};
+pub use self::sshfp::{parse_openssh_key, sshfp_records, verify_sshfp, HostKeyCheck, Sshfp};
+pub use self::store::{KeyEvent, KeyPolicy, KeyScheme, KeyStore, KeyTiming, StoreError, StoredKey};
+pub use self::validator::{Reason, Status, Validated, Validator};
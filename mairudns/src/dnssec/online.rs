@@ -0,0 +1,261 @@
+//! Signing answers as they are given (online signing), for zones that
+//! change too often to be signed in advance, such as those kept up to date
+//! through dynamic updates or the API.
+//!
+//! Nonexistence is proven with NSEC records made up for each answer that
+//! cover only the name asked about (RFC 4470's "white lies"), so no chain
+//! over the whole zone is ever computed.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+use crate::zone::{serial_cmp, Answer, Zone};
+
+use super::proof::{facts, rrsets, Fact};
+use super::signer::{
+    self, check_foreign, check_keys, publish_keys, replaced, rrsig, signers, unix_now,
+};
+use super::{Nsec, SignError, SigningKey, SigningPolicy};
+
+/// The signatures remembered before the cache is started over.
+const CACHE_LIMIT: usize = 65536;
+
+/// Signatures made over one RRset.
+#[derive(Debug)]
+struct Signed {
+    rrsigs: Vec<Record>,
+    /// When to make them anew, halfway through their validity.
+    refresh: u32,
+}
+
+/// Signs the answers of one zone with its keys.
+#[derive(Debug)]
+pub struct OnlineSigner {
+    keys: Vec<SigningKey>,
+    policy: SigningPolicy,
+    /// Signatures by the RRset and label count they cover.
+    cache: Mutex<HashMap<(Vec<Record>, u8), Signed>>,
+}
+
+/// The name just before `name` in canonical order, as near as can be
+/// told without looking at the zone: its first label with the last octet
+/// decremented and `\255` appended.
+fn predecessor(name: &DomainName) -> DomainName {
+    let parent = name.parent().unwrap_or_else(DomainName::root);
+    let mut label = match name.labels().first() {
+        Some(label) => label.to_ascii_lowercase(),
+        None => return parent,
+    };
+    match label.last_mut() {
+        // Whatever falls in the range of the capitals would compare as
+        // lowercase; step below them instead.
+        Some(b) if (b'A'..=b'[').contains(b) => *b = b'@',
+        Some(b) if *b > 0 => *b -= 1,
+        _ => {
+            label.pop();
+            if label.is_empty() {
+                return parent;
+            }
+            return parent.prepend(&label).unwrap_or(parent);
+        }
+    }
+    let mut longer = label.clone();
+    longer.push(0xff);
+    parent
+        .prepend(&longer)
+        .or_else(|_| parent.prepend(&label))
+        .unwrap_or(parent)
+}
+
+/// The name just after everything at and below `name`: its first label
+/// with a zero octet appended.
+fn past(name: &DomainName) -> DomainName {
+    let parent = name.parent().unwrap_or_else(DomainName::root);
+    let mut label = name
+        .labels()
+        .first()
+        .map(|l| l.to_ascii_lowercase())
+        .unwrap_or_default();
+    label.push(0);
+    parent
+        .prepend(&label)
+        .or_else(|_| name.prepend(b"\0"))
+        .unwrap_or_else(|_| name.clone())
+}
+
+impl OnlineSigner {
+    pub fn new(keys: Vec<SigningKey>) -> OnlineSigner {
+        OnlineSigner {
+            keys,
+            policy: SigningPolicy::default(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the validity, jitter and CDS/CDNSKEY publishing of the
+    /// signatures, and the keys of other signers to publish; its denial
+    /// setting is ignored, NSEC white lies being the only way to deny
+    /// online.
+    pub fn policy(mut self, policy: SigningPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn keys(&self) -> &[SigningKey] {
+        &self.keys
+    }
+
+    /// Readies `zone` for signing online: publishes the keys and drops any
+    /// signatures and NSEC or NSEC3 records it was signed with before.
+    pub fn publish(&self, zone: &mut Zone) -> Result<(), SignError> {
+        check_keys(&self.keys)?;
+        check_foreign(&self.keys, &self.policy)?;
+        let stale: Vec<Record> = zone
+            .records()
+            .filter(|rr| replaced(&self.policy, rr.rtype()))
+            .cloned()
+            .collect();
+        for rr in &stale {
+            zone.remove(rr);
+        }
+        publish_keys(zone, &self.keys, &self.policy)
+    }
+
+    /// The RRSIGs over `records`, one RRset of the zone `origin` whose
+    /// owner, if it was expanded from a wildcard, had `labels` labels.
+    pub fn sign_rrset(&self, origin: &DomainName, records: &[Record], labels: u8) -> Vec<Record> {
+        let rtype = match records.first() {
+            Some(rr) => rr.rtype(),
+            None => return Vec::new(),
+        };
+        let now = unix_now();
+        // White lies are made up for one answer; there is no use keeping
+        // their signatures.
+        let cached = rtype != RecordType::NSEC;
+        let key = (records.to_vec(), labels);
+        if cached {
+            if let Some(signed) = self.cache.lock().unwrap().get(&key) {
+                if serial_cmp(now, signed.refresh) == Some(Ordering::Less) {
+                    return signed.rrsigs.clone();
+                }
+            }
+        }
+        let inception = now.wrapping_sub(self.policy.backdate.as_secs() as u32);
+        let expiration = signer::expiration(&self.policy, now);
+        let sigs: Vec<Record> = signers(&self.keys, rtype)
+            .into_iter()
+            .filter_map(|k| rrsig(k, origin, records, labels, inception, expiration).ok())
+            .collect();
+        if cached {
+            let refresh = now.wrapping_add((self.policy.validity.as_secs() / 2) as u32);
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() >= CACHE_LIMIT {
+                cache.clear();
+            }
+            cache.insert(
+                key,
+                Signed {
+                    rrsigs: sigs.clone(),
+                    refresh,
+                },
+            );
+        }
+        sigs
+    }
+
+    /// Signs `answer`, what `zone` gave for `qname`/`qtype`, adding the
+    /// NSEC records that prove a negative answer, a wildcard expansion or
+    /// an unsigned delegation.
+    pub fn sign_answer(
+        &self,
+        zone: &Zone,
+        qname: &DomainName,
+        qtype: RecordType,
+        answer: &mut Answer,
+    ) {
+        let origin = zone.origin();
+        let ttl = match zone.soa().map(|rr| (rr.ttl, &rr.rdata)) {
+            Some((ttl, RData::Soa(soa))) => ttl.min(soa.minimum),
+            _ => return,
+        };
+        let nsec = |owner: DomainName, next: DomainName, mut types: Vec<RecordType>| {
+            types.extend([RecordType::RRSIG, RecordType::NSEC].iter());
+            types.sort_unstable();
+            types.dedup();
+            Record {
+                class: zone.class(),
+                ..Record::new(owner, ttl, Nsec { next, types }.to_rdata())
+            }
+        };
+        // The types an NSEC at `name` lists: only the parent side's at a
+        // delegation.
+        let types_at = |name: &DomainName| -> Vec<RecordType> {
+            let cut = zone.find_cut(name).is_some_and(|cut| cut == *name);
+            zone.types(name)
+                .into_iter()
+                .filter(|&t| !cut || t == RecordType::NS || t == RecordType::DS)
+                .collect()
+        };
+        // A white lie covering `name` and everything below it.
+        let covering = |name: &DomainName| nsec(predecessor(name), past(name), Vec::new());
+
+        let mut denial = Vec::new();
+        for fact in facts(zone, qname, qtype, answer) {
+            match fact {
+                Fact::NoName(name) => denial.push(covering(&name)),
+                Fact::NoType(name) => {
+                    denial.push(nsec(name.clone(), past_self(&name), types_at(&name)))
+                }
+                // A referral: the DS records, or proof there are none.
+                Fact::Referral(cut) => match zone.rrset(&cut, RecordType::DS) {
+                    Some(ds) => answer.authority.extend_from_slice(ds),
+                    None => denial.push(nsec(cut.clone(), past_self(&cut), types_at(&cut))),
+                },
+            }
+        }
+        denial.dedup_by(|a, b| a.name == b.name);
+        answer.authority.extend(denial);
+
+        let authoritative = answer.authoritative;
+        answer.answers = self.sign_section(zone, &answer.answers, |_| true);
+        answer.authority = self.sign_section(zone, &answer.authority, |rr| {
+            authoritative || rr.rtype() != RecordType::NS
+        });
+        answer.additional = self.sign_section(zone, &answer.additional, |rr| {
+            rr.name.is_subdomain_of(origin) && zone.find_cut(&rr.name).is_none()
+        });
+    }
+
+    /// `records` with the RRSIGs of the RRsets `signed` picks following
+    /// each.
+    fn sign_section<F>(&self, zone: &Zone, records: &[Record], signed: F) -> Vec<Record>
+    where
+        F: Fn(&Record) -> bool,
+    {
+        let mut out = Vec::with_capacity(records.len() * 2);
+        for set in rrsets(records) {
+            let first = &set[0];
+            if signed(first) && first.rtype() != RecordType::RRSIG {
+                let labels = match zone.wildcard_owner(&first.name) {
+                    Some(wildcard) => wildcard.label_count() - 1,
+                    None => first.name.label_count() - usize::from(first.name.is_wildcard()),
+                };
+                let sigs = self.sign_rrset(zone.origin(), &set, labels as u8);
+                out.extend(set);
+                out.extend(sigs);
+            } else {
+                out.extend(set);
+            }
+        }
+        out
+    }
+}
+
+/// The name just after `name` itself, for an NSEC that covers nothing
+/// but lists the types at its owner.
+fn past_self(name: &DomainName) -> DomainName {
+    name.prepend(b"\0").unwrap_or_else(|_| past(name))
+}
@@ -0,0 +1,213 @@
+//! Keeping the parent zone's DS records in step with a zone's keys: the
+//! DS records to hand to the parent, the CDS and CDNSKEY records that ask
+//! it to update them itself (RFC 7344, RFC 8078), and a check of what the
+//! parent's servers publish.
+
+use std::net::SocketAddr;
+
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::resolver::{Error, QueryOptions, Resolver, ResolverConfig};
+use crate::rr::{RData, Record, RecordType};
+
+use super::{Algorithm, DigestType, Dnskey, Ds};
+
+/// The keys whose digests go to the parent: the zone's key-signing keys,
+/// less any revoked.
+fn secure_entry_points(keys: &[Dnskey]) -> impl Iterator<Item = &Dnskey> {
+    keys.iter()
+        .filter(|k| k.is_zone_key() && k.is_sep() && !k.is_revoked())
+}
+
+/// `rdata` as a record of `rtype`, for the DNSSEC types that share
+/// another's format.
+fn retyped(mut rdata: RData, as_type: RecordType) -> RData {
+    if let RData::Unknown { rtype, .. } = &mut rdata {
+        *rtype = as_type;
+    }
+    rdata
+}
+
+/// The DS records the parent of `owner` should publish for its keys, one
+/// for each key-signing key and digest type.
+pub fn ds_records(
+    owner: &DomainName,
+    keys: &[Dnskey],
+    digest_types: &[DigestType],
+    ttl: u32,
+) -> Vec<Record> {
+    secure_entry_points(keys)
+        .flat_map(|key| digest_types.iter().filter_map(move |&t| key.ds(owner, t)))
+        .map(|ds| Record::new(owner.clone(), ttl, ds.to_rdata()))
+        .collect()
+}
+
+/// The CDS records `owner` publishes to have its parent's DS records
+/// match its keys.
+pub fn cds_records(
+    owner: &DomainName,
+    keys: &[Dnskey],
+    digest_types: &[DigestType],
+    ttl: u32,
+) -> Vec<Record> {
+    ds_records(owner, keys, digest_types, ttl)
+        .into_iter()
+        .map(|rr| Record {
+            rdata: retyped(rr.rdata, RecordType::CDS),
+            ..rr
+        })
+        .collect()
+}
+
+/// The CDNSKEY records `owner` publishes, for parents that compute the
+/// DS records themselves.
+pub fn cdnskey_records(owner: &DomainName, keys: &[Dnskey], ttl: u32) -> Vec<Record> {
+    secure_entry_points(keys)
+        .map(|key| {
+            let rdata = retyped(key.to_rdata(), RecordType::CDNSKEY);
+            Record::new(owner.clone(), ttl, rdata)
+        })
+        .collect()
+}
+
+/// The CDS and CDNSKEY records that ask the parent to remove the DS
+/// records of `owner` altogether, turning it insecure (RFC 8078 §4).
+pub fn delete_records(owner: &DomainName, ttl: u32) -> Vec<Record> {
+    let cds = Ds {
+        key_tag: 0,
+        algorithm: Algorithm(0),
+        digest_type: DigestType(0),
+        digest: vec![0],
+    };
+    let cdnskey = Dnskey::new(0, Algorithm(0), vec![0]);
+    vec![
+        Record::new(owner.clone(), ttl, retyped(cds.to_rdata(), RecordType::CDS)),
+        Record::new(
+            owner.clone(),
+            ttl,
+            retyped(cdnskey.to_rdata(), RecordType::CDNSKEY),
+        ),
+    ]
+}
+
+/// What the servers of a zone's parent publish for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParentDs {
+    /// Every server gives these DS records, sorted.
+    Agreed(Vec<Ds>),
+    /// The servers give different DS records, as while a change is on
+    /// its way to all of them.
+    Disagreed,
+}
+
+impl ParentDs {
+    /// Whether every server publishes DS records for all of `keys`'
+    /// key-signing keys and for no other key.
+    pub fn matches(&self, owner: &DomainName, keys: &[Dnskey]) -> bool {
+        let ds = match self {
+            ParentDs::Agreed(ds) => ds,
+            ParentDs::Disagreed => return false,
+        };
+        secure_entry_points(keys).all(|key| ds.iter().any(|ds| ds.matches(owner, key)))
+            && ds
+                .iter()
+                .all(|ds| secure_entry_points(keys).any(|key| ds.matches(owner, key)))
+    }
+}
+
+/// Asks each server of a zone's parent, directly, which DS records it has
+/// for the zone, to learn when a change at the parent is complete, such
+/// as the new DS records of a key-signing key rollover.
+#[derive(Clone, Debug)]
+pub struct DsChecker {
+    resolver: Resolver,
+    servers: Vec<SocketAddr>,
+}
+
+impl DsChecker {
+    /// A checker that finds the parent's servers through `resolver`.
+    pub fn new(resolver: Resolver) -> DsChecker {
+        DsChecker {
+            resolver,
+            servers: Vec::new(),
+        }
+    }
+
+    /// Asks these servers instead of looking up the parent's.
+    pub fn servers(mut self, servers: Vec<SocketAddr>) -> Self {
+        self.servers = servers;
+        self
+    }
+
+    /// The DS records the parent's servers have for `zone`.
+    pub fn check(&self, zone: &DomainName) -> Result<ParentDs, Error> {
+        let servers = if self.servers.is_empty() {
+            self.parent_servers(zone)?
+        } else {
+            self.servers.clone()
+        };
+        if servers.is_empty() {
+            return Err(Error::NoServers);
+        }
+        let options = QueryOptions {
+            recursion_desired: false,
+            ..QueryOptions::default()
+        };
+        let mut seen: Option<Vec<Ds>> = None;
+        for server in servers {
+            let resolver = Resolver::new(ResolverConfig {
+                servers: vec![server],
+                ..self.resolver.config().clone()
+            });
+            let response = resolver.query_with(zone, RecordType::DS, &options)?;
+            if response.header.rcode != Rcode::NOERROR {
+                return Err(Error::Rcode(response.header.rcode));
+            }
+            let mut ds: Vec<Ds> = response
+                .answers
+                .iter()
+                .filter(|rr| rr.rtype() == RecordType::DS && rr.name == *zone)
+                .filter_map(|rr| Ds::from_rdata(&rr.rdata))
+                .collect();
+            ds.sort_by(|a, b| {
+                let key = |ds: &Ds| (ds.key_tag, ds.algorithm.0, ds.digest_type.0);
+                key(a).cmp(&key(b)).then_with(|| a.digest.cmp(&b.digest))
+            });
+            ds.dedup();
+            match &seen {
+                Some(before) if *before != ds => return Ok(ParentDs::Disagreed),
+                _ => seen = Some(ds),
+            }
+        }
+        Ok(ParentDs::Agreed(seen.unwrap_or_default()))
+    }
+
+    /// The addresses of the name servers of the zone above `zone`.
+    fn parent_servers(&self, zone: &DomainName) -> Result<Vec<SocketAddr>, Error> {
+        let parent = match zone.parent() {
+            Some(parent) => parent,
+            None => return Ok(Vec::new()),
+        };
+        // The parent's apex owns the SOA record in the answer, or in the
+        // authority section of a negative one.
+        let response = self.resolver.query(&parent, RecordType::SOA)?;
+        let apex = response
+            .answers
+            .iter()
+            .chain(&response.authority)
+            .find(|rr| rr.rtype() == RecordType::SOA)
+            .map(|rr| rr.name.clone())
+            .unwrap_or(parent);
+        let mut servers = Vec::new();
+        for rr in self.resolver.lookup(&apex, RecordType::NS)? {
+            if let RData::Ns(host) = &rr.rdata {
+                servers.extend(
+                    self.resolver
+                        .lookup_ip(host)
+                        .map(|ip| SocketAddr::new(ip, 53)),
+                );
+            }
+        }
+        Ok(servers)
+    }
+}
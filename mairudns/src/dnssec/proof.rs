@@ -0,0 +1,278 @@
+//! Authenticated denial in answers: what an answer has to prove does not
+//! exist, and for zones signed in advance, the NSEC or NSEC3 records of
+//! their chain that prove it (RFC 4035 §3.1.3, RFC 5155 §7.2) and the
+//! RRSIGs that go with the records given.
+
+use crate::encoding;
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+use crate::zone::{Answer, Zone};
+
+use super::{nsec3_hash, Nsec3Param, Rrsig, MAX_NSEC3_ITERATIONS};
+
+/// Something an answer relies on not existing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) enum Fact {
+    /// Neither `name` nor anything below it exists: a name error, the
+    /// wildcard that could have answered instead, or the name a wildcard
+    /// was expanded for.
+    NoName(DomainName),
+    /// The name exists, as an owner or an empty non-terminal, without the
+    /// type asked for.
+    NoType(DomainName),
+    /// A referral to the delegation at the name: its DS records, or proof
+    /// it has none.
+    Referral(DomainName),
+}
+
+/// What `answer`, which `zone` gave for `qname`/`qtype`, relies on not
+/// existing.
+pub(super) fn facts(
+    zone: &Zone,
+    qname: &DomainName,
+    qtype: RecordType,
+    answer: &Answer,
+) -> Vec<Fact> {
+    let origin = zone.origin();
+    // The name below `encloser` on the way down to `name`.
+    let next_closer =
+        |name: &DomainName, encloser: &DomainName| name.suffix(encloser.label_count() + 1);
+    let mut facts = Vec::new();
+    if !answer.authoritative {
+        if let Some(cut) = answer
+            .authority
+            .iter()
+            .find(|rr| rr.rtype() == RecordType::NS)
+        {
+            facts.push(Fact::Referral(cut.name.clone()));
+        }
+        return facts;
+    }
+    let mut target = qname.clone();
+    for rr in &answer.answers {
+        if let (RData::Cname(next), true) = (&rr.rdata, rr.name == target) {
+            target = next.clone();
+        }
+    }
+    let answered = answer
+        .answers
+        .iter()
+        .any(|rr| rr.name == target && (rr.rtype() == qtype || qtype == RecordType::ANY));
+    if target.is_subdomain_of(origin) && answer.rcode == Rcode::NXDOMAIN {
+        let mut encloser = target.parent().unwrap_or_else(DomainName::root);
+        while !zone.name_exists(&encloser) && encloser != *origin {
+            encloser = encloser.parent().unwrap_or_else(DomainName::root);
+        }
+        facts.push(Fact::NoName(next_closer(&target, &encloser)));
+        if let Ok(wildcard) = encloser.prepend(b"*") {
+            facts.push(Fact::NoName(wildcard));
+        }
+    } else if target.is_subdomain_of(origin) && !answered {
+        if zone.name_exists(&target) {
+            facts.push(Fact::NoType(target));
+        } else if let Some(wildcard) = zone.wildcard_owner(&target) {
+            let encloser = wildcard.parent().unwrap_or_else(DomainName::root);
+            facts.push(Fact::NoName(next_closer(&target, &encloser)));
+            facts.push(Fact::NoType(wildcard));
+        }
+    }
+    // Wildcard expansions in the answer need the name itself proven
+    // absent.
+    for rr in &answer.answers {
+        if let Some(wildcard) = zone.wildcard_owner(&rr.name) {
+            let encloser = wildcard.parent().unwrap_or_else(DomainName::root);
+            let fact = Fact::NoName(next_closer(&rr.name, &encloser));
+            if !facts.contains(&fact) {
+                facts.push(fact);
+            }
+        }
+    }
+    facts
+}
+
+/// `records` grouped into RRsets, in the order they first appear.
+pub(super) fn rrsets(records: &[Record]) -> Vec<Vec<Record>> {
+    let mut rrsets: Vec<Vec<Record>> = Vec::new();
+    for rr in records {
+        match rrsets
+            .iter_mut()
+            .find(|set| set[0].name == rr.name && set[0].rtype() == rr.rtype())
+        {
+            Some(set) => set.push(rr.clone()),
+            None => rrsets.push(vec![rr.clone()]),
+        }
+    }
+    rrsets
+}
+
+/// The records of one NSEC3 chain.
+struct Nsec3Chain<'a> {
+    zone: &'a Zone,
+    param: Nsec3Param,
+}
+
+impl Nsec3Chain<'_> {
+    fn owner(&self, name: &DomainName) -> Option<DomainName> {
+        let hash = nsec3_hash(name, &self.param.salt, self.param.iterations);
+        let label = encoding::base32hex_encode(&hash).to_ascii_lowercase();
+        self.zone.origin().prepend(label.as_bytes()).ok()
+    }
+
+    fn matching(&self, name: &DomainName) -> Option<&[Record]> {
+        self.zone.rrset(&self.owner(name)?, RecordType::NSEC3)
+    }
+
+    fn covering(&self, name: &DomainName) -> Option<&[Record]> {
+        let owner = self.zone.preceding(&self.owner(name)?, RecordType::NSEC3)?;
+        self.zone.rrset(owner, RecordType::NSEC3)
+    }
+
+    /// The closest provable encloser proof for `name` (RFC 5155 §7.2.1):
+    /// the NSEC3 of its nearest ancestor in the chain, and the one covering
+    /// the next closer name, which at an opt-out span may be `name` itself.
+    fn closest_encloser(&self, name: &DomainName) -> Vec<Record> {
+        let zone_labels = self.zone.origin().label_count();
+        for labels in (zone_labels..name.label_count()).rev() {
+            if let Some(encloser) = self.matching(&name.suffix(labels)) {
+                let next_closer = name.suffix(labels + 1);
+                return encloser
+                    .iter()
+                    .chain(self.covering(&next_closer).unwrap_or(&[]))
+                    .cloned()
+                    .collect();
+            }
+        }
+        Vec::new()
+    }
+}
+
+/// The NSEC or NSEC3 records from the chain of `zone`, a zone signed in
+/// advance, that prove what `answer` for `qname`/`qtype` relies on not
+/// existing. A chain hashed more often than validators accept gives one of
+/// its records, which is enough for them to treat the zone as insecure.
+pub fn denial_records(
+    zone: &Zone,
+    qname: &DomainName,
+    qtype: RecordType,
+    answer: &Answer,
+) -> Vec<Record> {
+    let origin = zone.origin();
+    let param = zone
+        .rrset(origin, RecordType::NSEC3PARAM)
+        .into_iter()
+        .flatten()
+        .find_map(|rr| Nsec3Param::from_rdata(&rr.rdata));
+    let facts = facts(zone, qname, qtype, answer);
+    let mut out: Vec<Record> = Vec::new();
+    let mut add = |records: &[Record]| {
+        for rr in records {
+            if !out.contains(rr) {
+                out.push(rr.clone());
+            }
+        }
+    };
+    match param {
+        Some(param) if param.iterations > MAX_NSEC3_ITERATIONS => {
+            if !facts.is_empty() {
+                let any = zone
+                    .preceding(origin, RecordType::NSEC3)
+                    .and_then(|owner| zone.rrset(owner, RecordType::NSEC3));
+                add(any.unwrap_or(&[]));
+            }
+        }
+        Some(param) => {
+            let chain = Nsec3Chain { zone, param };
+            for fact in facts {
+                match fact {
+                    Fact::NoName(name) => add(&chain.closest_encloser(&name)),
+                    Fact::Referral(cut) if zone.rrset(&cut, RecordType::DS).is_some() => {}
+                    Fact::NoType(name) | Fact::Referral(name) => match chain.matching(&name) {
+                        Some(records) => add(records),
+                        None => add(&chain.closest_encloser(&name)),
+                    },
+                }
+            }
+        }
+        None => {
+            let covering = |name: &DomainName| {
+                zone.preceding(name, RecordType::NSEC)
+                    .and_then(|owner| zone.rrset(owner, RecordType::NSEC))
+            };
+            for fact in facts {
+                match fact {
+                    Fact::NoName(name) => add(covering(&name).unwrap_or(&[])),
+                    Fact::Referral(cut) if zone.rrset(&cut, RecordType::DS).is_some() => {}
+                    // An empty non-terminal has no NSEC of its own; the one
+                    // before it, whose next name lies below, proves it empty.
+                    Fact::NoType(name) | Fact::Referral(name) => add(zone
+                        .rrset(&name, RecordType::NSEC)
+                        .or_else(|| covering(&name))
+                        .unwrap_or(&[])),
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Completes `answer`, which `zone`, a zone signed in advance, gave for
+/// `qname`/`qtype`, for a querier that asked for DNSSEC records: the DS
+/// records of a referral, the records of [`denial_records`], and the
+/// zone's RRSIGs over each RRset it is authoritative for.
+pub fn add_proofs(zone: &Zone, qname: &DomainName, qtype: RecordType, answer: &mut Answer) {
+    let origin = zone.origin();
+    for fact in facts(zone, qname, qtype, answer) {
+        if let Fact::Referral(cut) = fact {
+            if let Some(ds) = zone.rrset(&cut, RecordType::DS) {
+                answer.authority.extend_from_slice(ds);
+            }
+        }
+    }
+    let denial = denial_records(zone, qname, qtype, answer);
+    answer.authority.extend(denial);
+
+    let authoritative = answer.authoritative;
+    answer.answers = with_signatures(zone, &answer.answers, |_| true);
+    answer.authority = with_signatures(zone, &answer.authority, |rr| {
+        authoritative || rr.rtype() != RecordType::NS
+    });
+    answer.additional = with_signatures(zone, &answer.additional, |rr| {
+        rr.name.is_subdomain_of(origin) && zone.find_cut(&rr.name).is_none()
+    });
+}
+
+/// `records` with the RRSIGs `zone` has over the RRsets `signed` picks
+/// following each; those of an RRset expanded from a wildcard are the
+/// wildcard's, given its name.
+fn with_signatures<F>(zone: &Zone, records: &[Record], signed: F) -> Vec<Record>
+where
+    F: Fn(&Record) -> bool,
+{
+    let mut out = Vec::with_capacity(records.len() * 2);
+    for set in rrsets(records) {
+        let first = &set[0];
+        let rtype = first.rtype();
+        let sigs: Vec<Record> = if signed(first) && rtype != RecordType::RRSIG {
+            let node = match zone.rrset(&first.name, rtype) {
+                Some(_) => Some(first.name.clone()),
+                None => zone.wildcard_owner(&first.name),
+            };
+            node.and_then(|node| zone.rrset(&node, RecordType::RRSIG))
+                .unwrap_or(&[])
+                .iter()
+                .filter(|rr| Rrsig::from_rdata(&rr.rdata).is_some_and(|s| s.type_covered == rtype))
+                .map(|rr| Record {
+                    name: first.name.clone(),
+                    ..rr.clone()
+                })
+                .filter(|rr| !records.contains(rr))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        out.extend(set);
+        out.extend(sigs);
+    }
+    out
+}
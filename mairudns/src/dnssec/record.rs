@@ -0,0 +1,586 @@
+//! The record data of the DNSSEC types (RFC 4034, RFC 5155). The core
+//! codec keeps these as [`RData::Unknown`]; the types here decode and
+//! encode that opaque data.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::encoding;
+use crate::name::DomainName;
+use crate::rr::{RData, RecordType};
+use crate::wire::{Decoder, Encoder};
+
+use super::{Algorithm, DigestType};
+
+/// The data of `rdata` if it is of one of `types`.
+fn data<'a>(rdata: &'a RData, types: &[RecordType]) -> Option<&'a [u8]> {
+    match rdata {
+        RData::Unknown { rtype, data } if types.contains(rtype) => Some(data),
+        _ => None,
+    }
+}
+
+/// A DNSKEY (or CDNSKEY) record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Dnskey {
+    pub flags: u16,
+    pub protocol: u8,
+    pub algorithm: Algorithm,
+    pub public_key: Vec<u8>,
+}
+
+impl Dnskey {
+    /// The key may sign the zone's data.
+    pub const ZONE: u16 = 0x0100;
+    /// The key is revoked (RFC 5011 §3).
+    pub const REVOKE: u16 = 0x0080;
+    /// The key is a secure entry point, as key-signing keys are.
+    pub const SEP: u16 = 0x0001;
+
+    pub fn new(flags: u16, algorithm: Algorithm, public_key: Vec<u8>) -> Dnskey {
+        Dnskey {
+            flags,
+            protocol: 3,
+            algorithm,
+            public_key,
+        }
+    }
+
+    /// Decodes DNSKEY or CDNSKEY data.
+    pub fn from_rdata(rdata: &RData) -> Option<Dnskey> {
+        let data = data(rdata, &[RecordType::DNSKEY, RecordType::CDNSKEY])?;
+        if data.len() < 4 {
+            return None;
+        }
+        Some(Dnskey {
+            flags: u16::from_be_bytes([data[0], data[1]]),
+            protocol: data[2],
+            algorithm: Algorithm(data[3]),
+            public_key: data[4..].to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(4 + self.public_key.len());
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        data.push(self.protocol);
+        data.push(self.algorithm.0);
+        data.extend_from_slice(&self.public_key);
+        data
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        RData::Unknown {
+            rtype: RecordType::DNSKEY,
+            data: self.to_bytes(),
+        }
+    }
+
+    pub fn is_zone_key(&self) -> bool {
+        self.flags & Dnskey::ZONE != 0 && self.protocol == 3
+    }
+
+    pub fn is_sep(&self) -> bool {
+        self.flags & Dnskey::SEP != 0
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.flags & Dnskey::REVOKE != 0
+    }
+
+    /// The key tag signatures and DS records refer to the key by (RFC 4034
+    /// Appendix B).
+    pub fn key_tag(&self) -> u16 {
+        let mut acc: u32 = 0;
+        for (i, &b) in self.to_bytes().iter().enumerate() {
+            acc += if i % 2 == 0 {
+                u32::from(b) << 8
+            } else {
+                u32::from(b)
+            };
+        }
+        acc += acc >> 16;
+        acc as u16
+    }
+
+    /// The digest of this key, owned by `owner`, that a DS record of
+    /// `digest_type` holds; `None` for an unsupported type.
+    pub fn digest(&self, owner: &DomainName, digest_type: DigestType) -> Option<Vec<u8>> {
+        let mut enc = Encoder::uncompressed();
+        enc.canonical_name(owner);
+        enc.bytes(&self.to_bytes());
+        digest_type.digest(enc.as_bytes())
+    }
+
+    /// The DS record for this key, owned by `owner`.
+    pub fn ds(&self, owner: &DomainName, digest_type: DigestType) -> Option<Ds> {
+        Some(Ds {
+            key_tag: self.key_tag(),
+            algorithm: self.algorithm,
+            digest_type,
+            digest: self.digest(owner, digest_type)?,
+        })
+    }
+}
+
+impl fmt::Display for Dnskey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.flags,
+            self.protocol,
+            self.algorithm.0,
+            encoding::base64_encode(&self.public_key)
+        )
+    }
+}
+
+impl FromStr for Dnskey {
+    type Err = ();
+
+    /// Parses the presentation form `flags protocol algorithm key`, where
+    /// the algorithm may be a mnemonic and the key may be split by spaces.
+    fn from_str(s: &str) -> Result<Dnskey, ()> {
+        let mut fields = s.split_whitespace();
+        let flags = fields.next().ok_or(())?.parse().map_err(|_| ())?;
+        let protocol = fields.next().ok_or(())?.parse().map_err(|_| ())?;
+        let algorithm = fields.next().ok_or(())?.parse()?;
+        let key: String = fields.collect();
+        Ok(Dnskey {
+            flags,
+            protocol,
+            algorithm,
+            public_key: encoding::base64_decode(&key).map_err(|_| ())?,
+        })
+    }
+}
+
+/// A DS (or CDS) record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Ds {
+    pub key_tag: u16,
+    pub algorithm: Algorithm,
+    pub digest_type: DigestType,
+    pub digest: Vec<u8>,
+}
+
+impl Ds {
+    /// Decodes DS or CDS data.
+    pub fn from_rdata(rdata: &RData) -> Option<Ds> {
+        let data = data(rdata, &[RecordType::DS, RecordType::CDS])?;
+        if data.len() < 4 {
+            return None;
+        }
+        Some(Ds {
+            key_tag: u16::from_be_bytes([data[0], data[1]]),
+            algorithm: Algorithm(data[2]),
+            digest_type: DigestType(data[3]),
+            digest: data[4..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut data = Vec::with_capacity(4 + self.digest.len());
+        data.extend_from_slice(&self.key_tag.to_be_bytes());
+        data.push(self.algorithm.0);
+        data.push(self.digest_type.0);
+        data.extend_from_slice(&self.digest);
+        RData::Unknown {
+            rtype: RecordType::DS,
+            data,
+        }
+    }
+
+    /// Whether this record refers to `key`, owned by `owner`.
+    pub fn matches(&self, owner: &DomainName, key: &Dnskey) -> bool {
+        self.algorithm == key.algorithm
+            && self.key_tag == key.key_tag()
+            && key
+                .digest(owner, self.digest_type)
+                .is_some_and(|d| d == self.digest)
+    }
+
+    /// Whether a validator can use this record: both its algorithm and
+    /// its digest type are supported.
+    pub fn is_supported(&self) -> bool {
+        self.algorithm.is_supported() && self.digest_type.is_supported()
+    }
+}
+
+impl fmt::Display for Ds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.key_tag,
+            self.algorithm.0,
+            self.digest_type.0,
+            encoding::hex_encode(&self.digest).to_ascii_uppercase()
+        )
+    }
+}
+
+/// An RRSIG record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rrsig {
+    pub type_covered: RecordType,
+    pub algorithm: Algorithm,
+    /// Labels in the owner name of the signed RRset, not counting a
+    /// leading `*`; fewer than the owner has means a wildcard expansion.
+    pub labels: u8,
+    pub original_ttl: u32,
+    /// Seconds since the epoch, in serial number arithmetic.
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: DomainName,
+    pub signature: Vec<u8>,
+}
+
+impl Rrsig {
+    pub fn from_rdata(rdata: &RData) -> Option<Rrsig> {
+        let data = data(rdata, &[RecordType::RRSIG])?;
+        let mut dec = Decoder::new(data);
+        let mut rrsig = Rrsig {
+            type_covered: RecordType(dec.u16().ok()?),
+            algorithm: Algorithm(dec.u8().ok()?),
+            labels: dec.u8().ok()?,
+            original_ttl: dec.u32().ok()?,
+            expiration: dec.u32().ok()?,
+            inception: dec.u32().ok()?,
+            key_tag: dec.u16().ok()?,
+            signer: dec.name().ok()?,
+            signature: Vec::new(),
+        };
+        rrsig.signature = dec.bytes(dec.remaining()).ok()?.to_vec();
+        Some(rrsig)
+    }
+
+    /// The data up to the signature, with the signer's name in canonical
+    /// form, as it is signed (RFC 4034 §3.1.8.1).
+    pub(crate) fn signed_fields(&self) -> Vec<u8> {
+        let mut enc = Encoder::uncompressed();
+        enc.u16(self.type_covered.0);
+        enc.u8(self.algorithm.0);
+        enc.u8(self.labels);
+        enc.u32(self.original_ttl);
+        enc.u32(self.expiration);
+        enc.u32(self.inception);
+        enc.u16(self.key_tag);
+        enc.canonical_name(&self.signer);
+        enc.into_bytes()
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut data = self.signed_fields();
+        data.extend_from_slice(&self.signature);
+        RData::Unknown {
+            rtype: RecordType::RRSIG,
+            data,
+        }
+    }
+
+    /// Whether `now`, in seconds since the epoch, lies in the validity
+    /// period, comparing the way RFC 4034 §3.1.5 asks.
+    pub fn is_current(&self, now: u32) -> bool {
+        !self.is_expired(now) && !self.is_premature(now)
+    }
+
+    pub fn is_expired(&self, now: u32) -> bool {
+        (self.expiration.wrapping_sub(now) as i32) < 0
+    }
+
+    pub fn is_premature(&self, now: u32) -> bool {
+        (now.wrapping_sub(self.inception) as i32) < 0
+    }
+}
+
+impl fmt::Display for Rrsig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {} {} {}",
+            self.type_covered,
+            self.algorithm.0,
+            self.labels,
+            self.original_ttl,
+            format_time(self.expiration),
+            format_time(self.inception),
+            self.key_tag,
+            self.signer,
+            encoding::base64_encode(&self.signature)
+        )
+    }
+}
+
+/// A signature time as `YYYYMMDDHHmmSS` (RFC 4034 §3.2).
+pub fn format_time(secs: u32) -> String {
+    let secs = u64::from(secs);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil from days, after Howard Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// Parses a signature time given as `YYYYMMDDHHmmSS` or as seconds since
+/// the epoch.
+pub fn parse_time(text: &str) -> Option<u32> {
+    if text.len() != 14 {
+        return text.parse().ok();
+    }
+    let field = |range: std::ops::Range<usize>| text.get(range)?.parse::<u64>().ok();
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if second > 60 || year < 1970 {
+        return None;
+    }
+    // Days from civil, the inverse of the above.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some((days * 86400 + hour * 3600 + minute * 60 + second) as u32)
+}
+
+/// Encodes a type bitmap (RFC 4034 §4.1.2).
+pub(crate) fn encode_types(types: &[RecordType], enc: &mut Encoder) {
+    let mut types = types.to_vec();
+    types.sort_unstable();
+    types.dedup();
+    let mut i = 0;
+    while i < types.len() {
+        let window = (types[i].0 >> 8) as u8;
+        let mut bitmap = [0u8; 32];
+        let mut len = 0;
+        while i < types.len() && (types[i].0 >> 8) as u8 == window {
+            let low = (types[i].0 & 0xff) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            len = low / 8 + 1;
+            i += 1;
+        }
+        enc.u8(window);
+        enc.u8(len as u8);
+        enc.bytes(&bitmap[..len]);
+    }
+}
+
+/// Decodes a type bitmap taking up the rest of `dec`.
+pub(crate) fn decode_types(dec: &mut Decoder<'_>) -> Option<Vec<RecordType>> {
+    let mut types = Vec::new();
+    let mut last = None;
+    while dec.remaining() > 0 {
+        let window = dec.u8().ok()?;
+        let len = usize::from(dec.u8().ok()?);
+        if len == 0 || len > 32 || last.is_some_and(|w| w >= window) {
+            return None;
+        }
+        last = Some(window);
+        for (byte, &bits) in dec.bytes(len).ok()?.iter().enumerate() {
+            for bit in 0..8 {
+                if bits & (0x80 >> bit) != 0 {
+                    types.push(RecordType(u16::from(window) << 8 | (byte * 8 + bit) as u16));
+                }
+            }
+        }
+    }
+    Some(types)
+}
+
+fn write_types(f: &mut fmt::Formatter<'_>, types: &[RecordType]) -> fmt::Result {
+    for t in types {
+        write!(f, " {}", t)?;
+    }
+    Ok(())
+}
+
+/// An NSEC record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Nsec {
+    pub next: DomainName,
+    pub types: Vec<RecordType>,
+}
+
+impl Nsec {
+    pub fn from_rdata(rdata: &RData) -> Option<Nsec> {
+        let data = data(rdata, &[RecordType::NSEC])?;
+        let mut dec = Decoder::new(data);
+        Some(Nsec {
+            next: dec.name().ok()?,
+            types: decode_types(&mut dec)?,
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut enc = Encoder::uncompressed();
+        enc.name(&self.next, false);
+        encode_types(&self.types, &mut enc);
+        RData::Unknown {
+            rtype: RecordType::NSEC,
+            data: enc.into_bytes(),
+        }
+    }
+
+    pub fn has(&self, rtype: RecordType) -> bool {
+        self.types.contains(&rtype)
+    }
+}
+
+impl fmt::Display for Nsec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.next)?;
+        write_types(f, &self.types)
+    }
+}
+
+/// An NSEC3 record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Nsec3 {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+    /// The hash of the next name in the chain, in binary.
+    pub next_hashed: Vec<u8>,
+    pub types: Vec<RecordType>,
+}
+
+impl Nsec3 {
+    /// The only hash algorithm defined, SHA-1.
+    pub const SHA1: u8 = 1;
+    /// The record may cover unsigned delegations (RFC 5155 §6).
+    pub const OPT_OUT: u8 = 0x01;
+
+    pub fn from_rdata(rdata: &RData) -> Option<Nsec3> {
+        let data = data(rdata, &[RecordType::NSEC3])?;
+        let mut dec = Decoder::new(data);
+        let hash_algorithm = dec.u8().ok()?;
+        let flags = dec.u8().ok()?;
+        let iterations = dec.u16().ok()?;
+        let salt = dec.character_string().ok()?.to_vec();
+        let next_hashed = dec.character_string().ok()?.to_vec();
+        Some(Nsec3 {
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+            next_hashed,
+            types: decode_types(&mut dec)?,
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut enc = Encoder::uncompressed();
+        enc.u8(self.hash_algorithm);
+        enc.u8(self.flags);
+        enc.u16(self.iterations);
+        enc.u8(self.salt.len() as u8);
+        enc.bytes(&self.salt);
+        enc.u8(self.next_hashed.len() as u8);
+        enc.bytes(&self.next_hashed);
+        encode_types(&self.types, &mut enc);
+        RData::Unknown {
+            rtype: RecordType::NSEC3,
+            data: enc.into_bytes(),
+        }
+    }
+
+    pub fn is_opt_out(&self) -> bool {
+        self.flags & Nsec3::OPT_OUT != 0
+    }
+
+    pub fn has(&self, rtype: RecordType) -> bool {
+        self.types.contains(&rtype)
+    }
+}
+
+fn write_salt(f: &mut fmt::Formatter<'_>, salt: &[u8]) -> fmt::Result {
+    if salt.is_empty() {
+        f.write_str("-")
+    } else {
+        f.write_str(&encoding::hex_encode(salt).to_ascii_uppercase())
+    }
+}
+
+impl fmt::Display for Nsec3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} ",
+            self.hash_algorithm, self.flags, self.iterations
+        )?;
+        write_salt(f, &self.salt)?;
+        write!(f, " {}", encoding::base32hex_encode(&self.next_hashed))?;
+        write_types(f, &self.types)
+    }
+}
+
+/// An NSEC3PARAM record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Nsec3Param {
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub iterations: u16,
+    pub salt: Vec<u8>,
+}
+
+impl Nsec3Param {
+    pub fn from_rdata(rdata: &RData) -> Option<Nsec3Param> {
+        let data = data(rdata, &[RecordType::NSEC3PARAM])?;
+        let mut dec = Decoder::new(data);
+        let param = Nsec3Param {
+            hash_algorithm: dec.u8().ok()?,
+            flags: dec.u8().ok()?,
+            iterations: dec.u16().ok()?,
+            salt: dec.character_string().ok()?.to_vec(),
+        };
+        if dec.remaining() != 0 {
+            return None;
+        }
+        Some(param)
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut data = vec![self.hash_algorithm, self.flags];
+        data.extend_from_slice(&self.iterations.to_be_bytes());
+        data.push(self.salt.len() as u8);
+        data.extend_from_slice(&self.salt);
+        RData::Unknown {
+            rtype: RecordType::NSEC3PARAM,
+            data,
+        }
+    }
+}
+
+impl fmt::Display for Nsec3Param {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} ",
+            self.hash_algorithm, self.flags, self.iterations
+        )?;
+        write_salt(f, &self.salt)
+    }
+}
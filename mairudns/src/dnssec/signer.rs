@@ -0,0 +1,587 @@
+//! Offline zone signing: the DNSKEY, CDS and CDNSKEY records, an NSEC or
+//! NSEC3 chain, an RRSIG over every authoritative RRset, and the ZONEMD
+//! digest over all of it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::encoding;
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{RData, Record, RecordType};
+use crate::zone::{self, Zone, Zonemd, ZonemdError};
+
+use super::canonical;
+use super::parent::{cdnskey_records, cds_records};
+use super::{
+    nsec3_hash, Algorithm, DigestType, Dnskey, KeyError, Nsec, Nsec3, Nsec3Param, Rrsig, SigningKey,
+};
+
+/// How a signed zone proves that names and types do not exist.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Denial {
+    /// An NSEC chain, which lets the zone's names be listed.
+    Nsec,
+    /// An NSEC3 chain over hashed names (RFC 5155).
+    Nsec3 {
+        iterations: u16,
+        salt: Vec<u8>,
+        /// Leaves unsigned delegations out of the chain, so that adding
+        /// or removing one does not touch it.
+        opt_out: bool,
+    },
+}
+
+/// How a zone is signed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SigningPolicy {
+    /// When signing happens, in seconds since the epoch; `None` for now.
+    pub now: Option<u32>,
+    /// How long before signing signatures become valid, for validators
+    /// whose clocks run behind.
+    pub backdate: Duration,
+    /// How long after signing signatures expire.
+    pub validity: Duration,
+    /// Each signature expires up to this much earlier, chosen at random,
+    /// so that they do not all need refreshing at once.
+    pub jitter: Duration,
+    pub denial: Denial,
+    /// The most NSEC3 iterations signing accepts; RFC 9276 §3.1 advises
+    /// none, extra iterations costing servers and validators more than
+    /// they cost anyone walking the zone.
+    pub max_nsec3_iterations: u16,
+    /// Accepts a salt for NSEC3 hashes, which RFC 9276 §3.1 also advises
+    /// against.
+    pub nsec3_salt: bool,
+    /// The digest types of the CDS records published for the key-signing
+    /// keys (RFC 7344); none leaves CDS records as they are.
+    pub cds: Vec<DigestType>,
+    /// Publishes CDNSKEY records for the key-signing keys.
+    pub cdnskey: bool,
+    /// Keys published in the key set that do not sign, such as those
+    /// introduced ahead of a rollover or kept after one.
+    pub published: Vec<Dnskey>,
+    /// Signing keys left out of the key set, as a new algorithm's are at
+    /// the start of an algorithm rollover: its signatures must be cached
+    /// everywhere before its keys are (RFC 6781 §4.1.4).
+    pub withheld: Vec<Dnskey>,
+    /// The keys of the zone's other signers when it is served by several
+    /// providers (RFC 8901), which sign their copies of the zone with
+    /// their own keys: published in the key set so that validators can
+    /// check either copy, and their key-signing keys in the CDS and
+    /// CDNSKEY records so that the parent lists them all. Each of their
+    /// algorithms must also be one of the local keys'.
+    pub foreign: Vec<Dnskey>,
+    /// The hash algorithms of the ZONEMD records (RFC 8976) the signed
+    /// zone carries; none keeps those of the zone up to date.
+    pub zonemd: Vec<u8>,
+}
+
+impl Default for SigningPolicy {
+    fn default() -> SigningPolicy {
+        SigningPolicy {
+            now: None,
+            backdate: Duration::from_secs(3600),
+            validity: Duration::from_secs(30 * 86400),
+            jitter: Duration::from_secs(86400),
+            denial: Denial::Nsec,
+            max_nsec3_iterations: 0,
+            nsec3_salt: false,
+            cds: vec![DigestType::SHA256],
+            cdnskey: true,
+            published: Vec::new(),
+            withheld: Vec::new(),
+            foreign: Vec::new(),
+            zonemd: Vec::new(),
+        }
+    }
+}
+
+/// Why a zone could not be signed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignError {
+    /// The zone has no SOA record at its origin.
+    NoSoa,
+    /// No signing keys were given.
+    NoKeys,
+    /// A key does not sign for the zone, as a revoked key.
+    BadKey(u16),
+    /// A key failed to sign.
+    Key(KeyError),
+    /// The ZONEMD records asked for could not be made.
+    Zonemd(ZonemdError),
+    /// The NSEC3 iterations asked for are more than the policy allows.
+    Nsec3Iterations(u16),
+    /// An NSEC3 salt was asked for and the policy allows none.
+    Nsec3Salt,
+    /// A foreign key has an algorithm no local key signs with, which
+    /// would leave the zone's RRsets without its signatures.
+    UnsignedAlgorithm(Algorithm),
+    Zone(zone::Error),
+}
+
+impl fmt::Display for SignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignError::NoSoa => f.write_str("zone has no SOA record"),
+            SignError::NoKeys => f.write_str("no signing keys"),
+            SignError::BadKey(tag) => write!(f, "key {} is not a usable zone key", tag),
+            SignError::Key(e) => e.fmt(f),
+            SignError::Zonemd(e) => e.fmt(f),
+            SignError::Nsec3Iterations(n) => {
+                write!(f, "{} NSEC3 iterations are more than the policy allows", n)
+            }
+            SignError::Nsec3Salt => f.write_str("the policy allows no NSEC3 salt"),
+            SignError::UnsignedAlgorithm(alg) => {
+                write!(
+                    f,
+                    "no local key signs with the foreign keys' algorithm {}",
+                    alg
+                )
+            }
+            SignError::Zone(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SignError {}
+
+impl From<KeyError> for SignError {
+    fn from(e: KeyError) -> SignError {
+        SignError::Key(e)
+    }
+}
+
+impl From<ZonemdError> for SignError {
+    fn from(e: ZonemdError) -> SignError {
+        SignError::Zonemd(e)
+    }
+}
+
+impl From<zone::Error> for SignError {
+    fn from(e: zone::Error) -> SignError {
+        SignError::Zone(e)
+    }
+}
+
+/// What signing does with the records at a name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Authoritative,
+    /// A delegation point: only its DS records are signed.
+    Delegation {
+        secure: bool,
+    },
+    /// Glue or other data below a delegation: left unsigned and out of the
+    /// chain.
+    Occluded,
+}
+
+fn kind(zone: &Zone, name: &DomainName) -> Kind {
+    match zone.find_cut(name) {
+        None => Kind::Authoritative,
+        Some(cut) if cut == *name => Kind::Delegation {
+            secure: zone.rrset(name, RecordType::DS).is_some(),
+        },
+        Some(_) => Kind::Occluded,
+    }
+}
+
+/// Signs `zone` with `keys`, returning the signed copy. Any signatures,
+/// NSEC, NSEC3 and NSEC3PARAM records in the zone are replaced; DNSKEY
+/// records already there, as for keys being rolled in or out, are kept.
+/// Only `keys` sign, the policy's foreign keys included in the key set
+/// notwithstanding.
+///
+/// Key-signing keys (those with the SEP flag) sign the DNSKEY, CDS and
+/// CDNSKEY RRsets and zone-signing keys the rest; an algorithm with keys
+/// of only one kind signs everything with them (a combined signing key).
+pub fn sign_zone(
+    zone: &Zone,
+    keys: &[SigningKey],
+    policy: &SigningPolicy,
+) -> Result<Zone, SignError> {
+    let soa = zone.soa().ok_or(SignError::NoSoa)?;
+    let minimum = match &soa.rdata {
+        RData::Soa(soa) => soa.minimum,
+        _ => 0,
+    };
+    check_keys(keys)?;
+    check_foreign(keys, policy)?;
+    if let Denial::Nsec3 {
+        iterations, salt, ..
+    } = &policy.denial
+    {
+        if *iterations > policy.max_nsec3_iterations {
+            return Err(SignError::Nsec3Iterations(*iterations));
+        }
+        if !salt.is_empty() && !policy.nsec3_salt {
+            return Err(SignError::Nsec3Salt);
+        }
+    }
+    let origin = zone.origin();
+    let class = zone.class();
+    let now = policy.now.unwrap_or_else(unix_now);
+
+    let mut out = Zone::with_class(origin.clone(), class);
+    for rr in zone.records().filter(|rr| !replaced(policy, rr.rtype())) {
+        out.insert(rr.clone())?;
+    }
+    publish_keys(&mut out, keys, policy)?;
+    // The ZONEMD records are there for the chain to list, and made over
+    // once everything else is signed.
+    let digests: Vec<u8> = if policy.zonemd.is_empty() {
+        out.rrset(origin, RecordType::ZONEMD)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|rr| Zonemd::from_rdata(&rr.rdata))
+            .filter(|z| z.scheme == Zonemd::SIMPLE)
+            .map(|z| z.hash_algorithm)
+            .collect()
+    } else {
+        policy.zonemd.clone()
+    };
+    if !digests.is_empty() {
+        out.set_zonemd(&digests)?;
+    }
+
+    // The denial of existence chain, signed along with the rest.
+    let denial_ttl = soa.ttl.min(minimum);
+    let mut names: BTreeMap<DomainName, (Kind, BTreeSet<RecordType>)> = BTreeMap::new();
+    for rr in out.records() {
+        let (kind, types) = names
+            .entry(rr.name.clone())
+            .or_insert_with(|| (kind(&out, &rr.name), BTreeSet::new()));
+        let listed = match kind {
+            Kind::Delegation { .. } => rr.rtype() == RecordType::NS || rr.rtype() == RecordType::DS,
+            _ => true,
+        };
+        if listed {
+            types.insert(rr.rtype());
+        }
+    }
+    names.retain(|_, (kind, _)| *kind != Kind::Occluded);
+    let chain = match &policy.denial {
+        Denial::Nsec => nsec_chain(origin, &names, denial_ttl),
+        Denial::Nsec3 {
+            iterations,
+            salt,
+            opt_out,
+        } => {
+            let param = Nsec3Param {
+                hash_algorithm: Nsec3::SHA1,
+                flags: 0,
+                iterations: *iterations,
+                salt: salt.clone(),
+            };
+            out.insert(Record::new(origin.clone(), 0, param.to_rdata()))?;
+            nsec3_chain(origin, &names, &param, *opt_out, denial_ttl)
+        }
+    };
+    for rr in chain {
+        out.insert(Record { class, ..rr })?;
+    }
+
+    // The signatures.
+    let mut rrsets: BTreeMap<(DomainName, RecordType), Vec<Record>> = BTreeMap::new();
+    for rr in out.records() {
+        rrsets
+            .entry((rr.name.clone(), rr.rtype()))
+            .or_default()
+            .push(rr.clone());
+    }
+    let inception = now.wrapping_sub(policy.backdate.as_secs() as u32);
+    for ((name, rtype), records) in rrsets {
+        if rtype == RecordType::ZONEMD && name == *origin {
+            continue;
+        }
+        let signed = match kind(&out, &name) {
+            Kind::Authoritative => true,
+            Kind::Delegation { .. } => rtype == RecordType::DS || rtype == RecordType::NSEC,
+            Kind::Occluded => false,
+        };
+        if !signed {
+            continue;
+        }
+        let expiration = expiration(policy, now);
+        let labels = (name.label_count() - usize::from(name.is_wildcard())) as u8;
+        for key in signers(keys, rtype) {
+            out.insert(rrsig(key, origin, &records, labels, inception, expiration)?)?;
+        }
+    }
+    if !digests.is_empty() {
+        out.set_zonemd(&digests)?;
+        let records = out
+            .rrset(origin, RecordType::ZONEMD)
+            .unwrap_or(&[])
+            .to_vec();
+        let labels = origin.label_count() as u8;
+        let expiration = expiration(policy, now);
+        for key in signers(keys, RecordType::ZONEMD) {
+            out.insert(rrsig(key, origin, &records, labels, inception, expiration)?)?;
+        }
+    }
+    Ok(out)
+}
+
+/// Checks that `keys` can sign a zone.
+pub(super) fn check_keys(keys: &[SigningKey]) -> Result<(), SignError> {
+    if keys.is_empty() {
+        return Err(SignError::NoKeys);
+    }
+    match keys
+        .iter()
+        .find(|k| !k.dnskey().is_zone_key() || (k.dnskey().is_revoked() && !k.is_ksk()))
+    {
+        Some(key) => Err(SignError::BadKey(key.key_tag())),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the local `keys` sign with every algorithm of the policy's
+/// foreign keys.
+pub(super) fn check_foreign(keys: &[SigningKey], policy: &SigningPolicy) -> Result<(), SignError> {
+    match policy
+        .foreign
+        .iter()
+        .find(|f| !keys.iter().any(|k| k.algorithm() == f.algorithm))
+    {
+        Some(key) => Err(SignError::UnsignedAlgorithm(key.algorithm)),
+        None => Ok(()),
+    }
+}
+
+/// The keys of another signer of the zone, from `dnskeys`, its DNSKEY
+/// RRset as that signer publishes it: the zone keys among them that are
+/// not `local`, for [`SigningPolicy::foreign`].
+pub fn foreign_keys(dnskeys: &[Record], local: &[Dnskey]) -> Vec<Dnskey> {
+    let mut keys: Vec<Dnskey> = Vec::new();
+    for key in dnskeys
+        .iter()
+        .filter_map(|rr| Dnskey::from_rdata(&rr.rdata))
+    {
+        if key.is_zone_key() && !local.contains(&key) && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+pub(super) fn unix_now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32)
+}
+
+/// Whether signing under `policy` replaces the records of `rtype` the
+/// zone already has.
+pub(super) fn replaced(policy: &SigningPolicy, rtype: RecordType) -> bool {
+    match rtype {
+        RecordType::RRSIG | RecordType::NSEC | RecordType::NSEC3 | RecordType::NSEC3PARAM => true,
+        RecordType::CDS => !policy.cds.is_empty(),
+        RecordType::CDNSKEY => policy.cdnskey,
+        _ => false,
+    }
+}
+
+/// Adds the DNSKEY records of `keys` but those withheld, and of the
+/// policy's published and foreign keys, to `zone`, with the CDS and
+/// CDNSKEY records `policy` asks for the key-signing keys among them.
+pub(super) fn publish_keys(
+    zone: &mut Zone,
+    keys: &[SigningKey],
+    policy: &SigningPolicy,
+) -> Result<(), SignError> {
+    let origin = zone.origin().clone();
+    let key_ttl = zone
+        .rrset(&origin, RecordType::DNSKEY)
+        .and_then(|set| set.first())
+        .or_else(|| zone.soa())
+        .ok_or(SignError::NoSoa)?
+        .ttl;
+    let dnskeys: Vec<Dnskey> = keys
+        .iter()
+        .map(|k| k.dnskey().clone())
+        .filter(|key| !policy.withheld.contains(key))
+        .chain(policy.foreign.iter().cloned())
+        .collect();
+    let mut records: Vec<Record> = dnskeys
+        .iter()
+        .chain(&policy.published)
+        .map(|key| Record::new(origin.clone(), key_ttl, key.to_rdata()))
+        .collect();
+    records.extend(cds_records(&origin, &dnskeys, &policy.cds, key_ttl));
+    if policy.cdnskey {
+        records.extend(cdnskey_records(&origin, &dnskeys, key_ttl));
+    }
+    let class = zone.class();
+    for rr in records {
+        zone.insert(Record { class, ..rr })?;
+    }
+    Ok(())
+}
+
+/// When a signature made at `now` expires: after the policy's validity,
+/// less a random part of its jitter.
+pub(super) fn expiration(policy: &SigningPolicy, now: u32) -> u32 {
+    let jitter = policy.jitter.as_secs();
+    let offset = if jitter == 0 {
+        0
+    } else {
+        random::u64() % (jitter + 1)
+    };
+    now.wrapping_add(policy.validity.as_secs().saturating_sub(offset) as u32)
+}
+
+/// The RRSIG `key` of the zone `origin` makes over the RRset `records`,
+/// whose owner has `labels` labels once any wildcard is taken off.
+pub(super) fn rrsig(
+    key: &SigningKey,
+    origin: &DomainName,
+    records: &[Record],
+    labels: u8,
+    inception: u32,
+    expiration: u32,
+) -> Result<Record, KeyError> {
+    let first = &records[0];
+    let mut rrsig = Rrsig {
+        type_covered: first.rtype(),
+        algorithm: key.algorithm(),
+        labels,
+        original_ttl: first.ttl,
+        expiration,
+        inception,
+        key_tag: key.key_tag(),
+        signer: origin.clone(),
+        signature: Vec::new(),
+    };
+    let refs: Vec<&Record> = records.iter().collect();
+    rrsig.signature = key.sign(&canonical::signed_data(&rrsig, &refs))?;
+    Ok(Record {
+        class: first.class,
+        ..Record::new(first.name.clone(), first.ttl, rrsig.to_rdata())
+    })
+}
+
+/// The keys that sign an RRset of `rtype`: for each algorithm, its
+/// key-signing keys for the key set and its other keys for the rest, or
+/// all of them if it only has one kind. Revoked keys sign only the key
+/// set, to announce their revocation.
+pub(super) fn signers(keys: &[SigningKey], rtype: RecordType) -> Vec<&SigningKey> {
+    let key_set = matches!(
+        rtype,
+        RecordType::DNSKEY | RecordType::CDS | RecordType::CDNSKEY
+    );
+    let mut algorithms: Vec<_> = keys.iter().map(SigningKey::algorithm).collect();
+    algorithms.sort_unstable();
+    algorithms.dedup();
+    let mut out = Vec::new();
+    for algorithm in algorithms {
+        let of_alg: Vec<&SigningKey> = keys
+            .iter()
+            .filter(|k| k.algorithm() == algorithm && (key_set || !k.dnskey().is_revoked()))
+            .collect();
+        let preferred: Vec<&SigningKey> = of_alg
+            .iter()
+            .copied()
+            .filter(|k| k.is_ksk() == key_set)
+            .collect();
+        out.extend(if preferred.is_empty() {
+            of_alg
+        } else if key_set {
+            // Revoked keys sign whatever the kinds of the others.
+            preferred
+                .into_iter()
+                .chain(
+                    of_alg
+                        .into_iter()
+                        .filter(|k| k.dnskey().is_revoked() && !k.is_ksk()),
+                )
+                .collect()
+        } else {
+            preferred
+        });
+    }
+    out
+}
+
+/// The NSEC records linking the zone's names in canonical order.
+fn nsec_chain(
+    origin: &DomainName,
+    names: &BTreeMap<DomainName, (Kind, BTreeSet<RecordType>)>,
+    ttl: u32,
+) -> Vec<Record> {
+    let owners: Vec<&DomainName> = names.keys().collect();
+    owners
+        .iter()
+        .enumerate()
+        .map(|(i, &owner)| {
+            let next = owners.get(i + 1).copied().unwrap_or(origin);
+            let mut types = names[owner].1.clone();
+            types.insert(RecordType::NSEC);
+            types.insert(RecordType::RRSIG);
+            let nsec = Nsec {
+                next: next.clone(),
+                types: types.into_iter().collect(),
+            };
+            Record::new(owner.clone(), ttl, nsec.to_rdata())
+        })
+        .collect()
+}
+
+/// The NSEC3 records linking the hashes of the zone's names and empty
+/// non-terminals, leaving out unsigned delegations if `opt_out`.
+fn nsec3_chain(
+    origin: &DomainName,
+    names: &BTreeMap<DomainName, (Kind, BTreeSet<RecordType>)>,
+    param: &Nsec3Param,
+    opt_out: bool,
+    ttl: u32,
+) -> Vec<Record> {
+    let mut hashed: BTreeMap<Vec<u8>, BTreeSet<RecordType>> = BTreeMap::new();
+    for (name, (kind, types)) in names {
+        let mut types = types.clone();
+        match kind {
+            Kind::Delegation { secure: false } if opt_out => continue,
+            Kind::Delegation { secure: false } => {}
+            _ => {
+                types.insert(RecordType::RRSIG);
+            }
+        }
+        if name == origin {
+            types.insert(RecordType::NSEC3PARAM);
+        }
+        hashed.insert(nsec3_hash(name, &param.salt, param.iterations), types);
+        // The empty non-terminals between the name and the origin.
+        let mut parent = name.parent();
+        while let Some(ancestor) = parent.filter(|a| a.label_count() > origin.label_count()) {
+            if names.contains_key(&ancestor) {
+                break;
+            }
+            hashed
+                .entry(nsec3_hash(&ancestor, &param.salt, param.iterations))
+                .or_default();
+            parent = ancestor.parent();
+        }
+    }
+    let hashes: Vec<&Vec<u8>> = hashed.keys().collect();
+    hashes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &hash)| {
+            let next = hashes.get(i + 1).copied().unwrap_or(hashes[0]);
+            let nsec3 = Nsec3 {
+                hash_algorithm: param.hash_algorithm,
+                flags: if opt_out { Nsec3::OPT_OUT } else { 0 },
+                iterations: param.iterations,
+                salt: param.salt.clone(),
+                next_hashed: next.clone(),
+                types: hashed[hash].iter().copied().collect(),
+            };
+            let label = encoding::base32hex_encode(hash).to_ascii_lowercase();
+            let owner = origin.prepend(label.as_bytes()).ok()?;
+            Some(Record::new(owner, ttl, nsec3.to_rdata()))
+        })
+        .collect()
+}
@@ -0,0 +1,189 @@
+//! SSH host key fingerprints in the DNS (RFC 4255, RFC 6594): the SSHFP
+//! records for an OpenSSH public key, and checking the key a server
+//! presents against the records of its name, which count only when
+//! DNSSEC-secure.
+
+use std::fmt;
+
+use crate::crypto::{Digest, Sha1, Sha256};
+use crate::encoding;
+use crate::rr::{RData, RecordType};
+
+use super::{Status, Validated};
+
+/// An SSHFP record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sshfp {
+    pub algorithm: u8,
+    pub fingerprint_type: u8,
+    pub fingerprint: Vec<u8>,
+}
+
+impl Sshfp {
+    pub const RSA: u8 = 1;
+    pub const DSA: u8 = 2;
+    pub const ECDSA: u8 = 3;
+    pub const ED25519: u8 = 4;
+    pub const ED448: u8 = 6;
+
+    pub const SHA1: u8 = 1;
+    pub const SHA256: u8 = 2;
+
+    /// The record for `key`, a host key in the SSH wire format, with a
+    /// fingerprint of `fingerprint_type`; `None` for a key of an unknown
+    /// type or an unsupported fingerprint type.
+    pub fn new(key: &[u8], fingerprint_type: u8) -> Option<Sshfp> {
+        let fingerprint = match fingerprint_type {
+            Sshfp::SHA1 => Sha1::digest(key),
+            Sshfp::SHA256 => Sha256::digest(key),
+            _ => return None,
+        };
+        Some(Sshfp {
+            algorithm: key_algorithm(key)?,
+            fingerprint_type,
+            fingerprint,
+        })
+    }
+
+    pub fn from_rdata(rdata: &RData) -> Option<Sshfp> {
+        let data = match rdata {
+            RData::Unknown { rtype, data } if *rtype == RecordType::SSHFP => data,
+            _ => return None,
+        };
+        if data.len() < 2 {
+            return None;
+        }
+        Some(Sshfp {
+            algorithm: data[0],
+            fingerprint_type: data[1],
+            fingerprint: data[2..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut data = Vec::with_capacity(2 + self.fingerprint.len());
+        data.extend_from_slice(&[self.algorithm, self.fingerprint_type]);
+        data.extend_from_slice(&self.fingerprint);
+        RData::Unknown {
+            rtype: RecordType::SSHFP,
+            data,
+        }
+    }
+
+    /// Whether this record is the fingerprint of `key`, a host key in the
+    /// SSH wire format.
+    pub fn matches(&self, key: &[u8]) -> bool {
+        Sshfp::new(key, self.fingerprint_type).is_some_and(|fp| fp == *self)
+    }
+}
+
+impl fmt::Display for Sshfp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.algorithm,
+            self.fingerprint_type,
+            encoding::hex_encode(&self.fingerprint)
+        )
+    }
+}
+
+/// The type a host key in the SSH wire format starts with, as a string.
+fn key_type(key: &[u8]) -> Option<&[u8]> {
+    let len = key.get(..4)?;
+    let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    key.get(4..4usize.checked_add(len)?)
+}
+
+/// The SSHFP algorithm of `key`, by its type.
+fn key_algorithm(key: &[u8]) -> Option<u8> {
+    Some(match key_type(key)? {
+        b"ssh-rsa" => Sshfp::RSA,
+        b"ssh-dss" => Sshfp::DSA,
+        b"ecdsa-sha2-nistp256" | b"ecdsa-sha2-nistp384" | b"ecdsa-sha2-nistp521" => Sshfp::ECDSA,
+        b"ssh-ed25519" => Sshfp::ED25519,
+        b"ssh-ed448" => Sshfp::ED448,
+        _ => return None,
+    })
+}
+
+/// The host key in the SSH wire format of `line`, an OpenSSH public key
+/// such as a line of `ssh_host_ed25519_key.pub`: its type, the key in
+/// base64, and an optional comment.
+pub fn parse_openssh_key(line: &str) -> Option<Vec<u8>> {
+    let mut fields = line.split_whitespace();
+    let kind = fields.next()?;
+    let key = encoding::base64_decode(fields.next()?).ok()?;
+    // The key repeats its type; the two must agree.
+    if key_type(&key)? != kind.as_bytes() {
+        return None;
+    }
+    key_algorithm(&key)?;
+    Some(key)
+}
+
+/// The SSHFP records for the OpenSSH public key `line`, with SHA-1 and
+/// SHA-256 fingerprints as `ssh-keygen -r` gives them.
+pub fn sshfp_records(line: &str) -> Option<Vec<Sshfp>> {
+    let key = parse_openssh_key(line)?;
+    [Sshfp::SHA1, Sshfp::SHA256]
+        .iter()
+        .map(|&fingerprint_type| Sshfp::new(&key, fingerprint_type))
+        .collect()
+}
+
+/// What the SSHFP records of a server's name make of its host key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostKeyCheck {
+    /// A secure record is the key's fingerprint: the key may be trusted
+    /// without asking the user.
+    Verified,
+    /// Secure records for the key's algorithm were found but none is its
+    /// fingerprint.
+    Mismatch,
+    /// There are no secure records for the key's algorithm: the DNS has
+    /// nothing to say about it.
+    Insecure,
+    /// The records could not be validated, because they are bogus or the
+    /// keys could not be fetched.
+    Failed,
+}
+
+/// Checks `host_key`, the key a server presented in the SSH wire format,
+/// against the SSHFP records of `sshfp`, a validated response to an SSHFP
+/// query for its name (RFC 4255 §2.1). The records count only if the
+/// response is secure, and a SHA-1 fingerprint only if there is no SHA-256
+/// one for the same algorithm (RFC 6594 §4.1).
+pub fn verify_sshfp(host_key: &[u8], sshfp: &Validated) -> HostKeyCheck {
+    match sshfp.status {
+        Status::Secure => {}
+        Status::Insecure => return HostKeyCheck::Insecure,
+        Status::Bogus | Status::Indeterminate => return HostKeyCheck::Failed,
+    }
+    let algorithm = match key_algorithm(host_key) {
+        Some(algorithm) => algorithm,
+        None => return HostKeyCheck::Insecure,
+    };
+    let records: Vec<Sshfp> = sshfp
+        .message
+        .answers
+        .iter()
+        .filter_map(|rr| Sshfp::from_rdata(&rr.rdata))
+        .filter(|fp| fp.algorithm == algorithm)
+        .filter(|fp| matches!(fp.fingerprint_type, Sshfp::SHA1 | Sshfp::SHA256))
+        .collect();
+    let strongest = match records.iter().map(|fp| fp.fingerprint_type).max() {
+        Some(strongest) => strongest,
+        None => return HostKeyCheck::Insecure,
+    };
+    if records
+        .iter()
+        .filter(|fp| fp.fingerprint_type == strongest)
+        .any(|fp| fp.matches(host_key))
+    {
+        HostKeyCheck::Verified
+    } else {
+        HostKeyCheck::Mismatch
+    }
+}
@@ -0,0 +1,785 @@
+//! Key management: making a zone's keys, rolling them over on a schedule,
+//! and keeping them in a directory of BIND-style key files, encrypted
+//! under a passphrase if wanted, or in an external [`KeyBackend`].
+
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand_core::{OsRng, RngCore};
+
+use crate::crypto::{self, Sha256};
+use crate::encoding;
+use crate::name::DomainName;
+use crate::random;
+use crate::zone::Zone;
+
+use super::key::DEFAULT_RSA_BITS;
+use super::signer::unix_now;
+use super::{
+    format_time, parse_time, sign_zone, Algorithm, Dnskey, KeyBackend, KeyError, ParentDs,
+    SignError, SigningKey, SigningPolicy,
+};
+
+/// The PBKDF2 iterations that turn a passphrase into the key encrypting
+/// private keys.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// How often a new key is made before giving up on one whose key tag
+/// clashes with a key already in the store.
+const TAG_ATTEMPTS: usize = 8;
+
+/// Which keys sign a zone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyScheme {
+    /// A key-signing key for the key set and a zone-signing key for the
+    /// rest, rolled separately.
+    #[default]
+    Split,
+    /// One combined signing key (CSK) for everything.
+    Combined,
+}
+
+/// When a store makes keys and rolls them over. Zone-signing keys roll by
+/// pre-publication and key-signing and combined keys by double signature
+/// (RFC 6781 §4.1).
+///
+/// Changing the algorithm rolls every key over to the new one the
+/// conservative way (RFC 6781 §4.1.4): the new keys sign the whole zone
+/// first and are published `publish_safety` later, once their signatures
+/// are cached everywhere; the old keys are then retired together like
+/// key-signing keys, `ds_safety` on or once the parent's DS records have
+/// changed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyPolicy {
+    pub algorithm: Algorithm,
+    pub scheme: KeyScheme,
+    /// The modulus size of RSA keys, in bits.
+    pub rsa_bits: usize,
+    /// How long a key-signing or combined key is used before it is
+    /// replaced; `None` keeps it until it is rolled by hand.
+    pub ksk_lifetime: Option<Duration>,
+    /// How long a zone-signing key is used before it is replaced.
+    pub zsk_lifetime: Option<Duration>,
+    /// How long a new zone-signing key is published before it signs: at
+    /// least the DNSKEY TTL, plus time for secondaries to catch up.
+    pub publish_safety: Duration,
+    /// How long a retired key stays published: at least the longest TTL
+    /// of the records it signed, plus time for secondaries to catch up.
+    pub retire_safety: Duration,
+    /// How long a new key-signing key signs alongside the old one, for
+    /// the parent to swap the DS records and the old ones to expire from
+    /// caches.
+    pub ds_safety: Duration,
+    /// Keeps an old key-signing key in use until [`KeyStore::confirm_ds`]
+    /// sees the parent publish the new one's DS records only, instead of
+    /// retiring it after `ds_safety` regardless.
+    pub wait_for_ds: bool,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> KeyPolicy {
+        KeyPolicy {
+            algorithm: Algorithm::ECDSAP256SHA256,
+            scheme: KeyScheme::Split,
+            rsa_bits: DEFAULT_RSA_BITS,
+            ksk_lifetime: Some(Duration::from_secs(365 * 86400)),
+            zsk_lifetime: Some(Duration::from_secs(90 * 86400)),
+            publish_safety: Duration::from_secs(86400),
+            retire_safety: Duration::from_secs(2 * 86400),
+            ds_safety: Duration::from_secs(7 * 86400),
+            wait_for_ds: false,
+        }
+    }
+}
+
+/// When a key enters and leaves use, in seconds since the epoch, as the
+/// timing fields of BIND's key files give it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyTiming {
+    pub created: u32,
+    /// When the key is added to the key set.
+    pub publish: Option<u32>,
+    /// When the key starts signing.
+    pub activate: Option<u32>,
+    /// When the key stops signing.
+    pub inactive: Option<u32>,
+    /// When the key is taken out of the key set.
+    pub delete: Option<u32>,
+}
+
+impl KeyTiming {
+    /// A key published and signing from `now` on.
+    pub fn active_from(now: u32) -> KeyTiming {
+        KeyTiming {
+            created: now,
+            publish: Some(now),
+            activate: Some(now),
+            ..KeyTiming::default()
+        }
+    }
+
+    pub fn is_published(&self, now: u32) -> bool {
+        self.publish.is_some_and(|t| t <= now) && self.delete.is_none_or(|t| now < t)
+    }
+
+    pub fn is_active(&self, now: u32) -> bool {
+        self.activate.is_some_and(|t| t <= now)
+            && self.inactive.is_none_or(|t| now < t)
+            && self.delete.is_none_or(|t| now < t)
+    }
+}
+
+/// A key in a [`KeyStore`] and its timing.
+#[derive(Clone, Debug)]
+pub struct StoredKey {
+    key: SigningKey,
+    timing: KeyTiming,
+}
+
+impl StoredKey {
+    pub fn key(&self) -> &SigningKey {
+        &self.key
+    }
+
+    pub fn timing(&self) -> &KeyTiming {
+        &self.timing
+    }
+}
+
+/// What [`KeyStore::maintain`] did, by key tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// A key was made, to start signing at the given time.
+    Generated(u16, u32),
+    /// A key was given a successor and stops signing at the given time.
+    Retired(u16, u32),
+    /// A key was deleted.
+    Removed(u16),
+}
+
+/// Why a key store could not be read or written.
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Key(KeyError),
+    /// A private key is encrypted and the store has no passphrase, or the
+    /// wrong one; the text names the file.
+    Locked(String),
+    /// A private key is held in a backend and the store has none.
+    NoBackend(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "{}", e),
+            StoreError::Key(e) => write!(f, "{}", e),
+            StoreError::Locked(file) => write!(f, "{}: cannot decrypt with this passphrase", file),
+            StoreError::NoBackend(file) => write!(f, "{}: key is held in a backend", file),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> StoreError {
+        StoreError::Io(e)
+    }
+}
+
+impl From<KeyError> for StoreError {
+    fn from(e: KeyError) -> StoreError {
+        StoreError::Key(e)
+    }
+}
+
+/// The keys of one zone, kept in a directory as `K<zone>+<alg>+<tag>.key`
+/// and `.private` files the way BIND keeps them, timing included.
+///
+/// With a passphrase, private keys are written encrypted with
+/// ChaCha20-Poly1305 under a key derived by PBKDF2; files that are not
+/// encrypted are still read. With a [`KeyBackend`], new keys are made in
+/// the backend and the files only hold their labels.
+pub struct KeyStore {
+    origin: DomainName,
+    dir: PathBuf,
+    passphrase: Option<Vec<u8>>,
+    backend: Option<Arc<dyn KeyBackend>>,
+    keys: Vec<StoredKey>,
+}
+
+impl fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyStore")
+            .field("origin", &self.origin)
+            .field("dir", &self.dir)
+            .field("keys", &self.keys)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyStore {
+    /// An empty store for the zone `origin`, kept in `dir`; see
+    /// [`load`](KeyStore::load).
+    pub fn new(origin: DomainName, dir: impl Into<PathBuf>) -> KeyStore {
+        KeyStore {
+            origin: origin.to_lowercase(),
+            dir: dir.into(),
+            passphrase: None,
+            backend: None,
+            keys: Vec::new(),
+        }
+    }
+
+    pub fn passphrase(mut self, passphrase: impl Into<Vec<u8>>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    pub fn backend(mut self, backend: Arc<dyn KeyBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Reads the zone's keys from the directory, replacing those held.
+    pub fn load(&mut self) -> Result<(), StoreError> {
+        let prefix = format!("K{}+", self.origin);
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let file = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(base) = file
+                .strip_suffix(".key")
+                .filter(|base| base.starts_with(&prefix))
+            {
+                keys.push(self.read(base)?);
+            }
+        }
+        keys.sort_by_key(|k| (k.timing.created, k.key.key_tag()));
+        self.keys = keys;
+        Ok(())
+    }
+
+    pub fn origin(&self) -> &DomainName {
+        &self.origin
+    }
+
+    /// The keys, oldest first.
+    pub fn keys(&self) -> &[StoredKey] {
+        &self.keys
+    }
+
+    /// The keys that sign at `now`.
+    pub fn signing_keys(&self, now: u32) -> Vec<SigningKey> {
+        self.keys
+            .iter()
+            .filter(|k| k.timing.is_active(now))
+            .map(|k| k.key.clone())
+            .collect()
+    }
+
+    /// The keys that sign at `now` but are not published yet, for
+    /// [`SigningPolicy::withheld`].
+    pub fn withheld_keys(&self, now: u32) -> Vec<Dnskey> {
+        self.keys
+            .iter()
+            .filter(|k| k.timing.is_active(now) && !k.timing.is_published(now))
+            .map(|k| k.key.dnskey().clone())
+            .collect()
+    }
+
+    /// The keys published at `now` that do not sign, for
+    /// [`SigningPolicy::published`].
+    pub fn published_keys(&self, now: u32) -> Vec<Dnskey> {
+        self.keys
+            .iter()
+            .filter(|k| k.timing.is_published(now) && !k.timing.is_active(now))
+            .map(|k| k.key.dnskey().clone())
+            .collect()
+    }
+
+    /// Signs `zone` with the keys in use at the policy's signing time,
+    /// publishing those that are introduced or retiring as well, and not
+    /// those yet to be published.
+    pub fn sign(&self, zone: &Zone, policy: &SigningPolicy) -> Result<Zone, SignError> {
+        let now = policy.now.unwrap_or_else(unix_now);
+        let mut policy = policy.clone();
+        policy.published.extend(self.published_keys(now));
+        policy.withheld.extend(self.withheld_keys(now));
+        sign_zone(zone, &self.signing_keys(now), &policy)
+    }
+
+    /// Adds `key` with `timing` and writes its files.
+    pub fn add(&mut self, key: SigningKey, timing: KeyTiming) -> Result<&StoredKey, StoreError> {
+        let stored = StoredKey { key, timing };
+        self.write(&stored)?;
+        let order = |k: &StoredKey| (k.timing.created, k.key.key_tag());
+        let i = self.keys.partition_point(|k| order(k) <= order(&stored));
+        self.keys.insert(i, stored);
+        Ok(&self.keys[i])
+    }
+
+    /// Makes a key of `algorithm`, in the backend if the store has one,
+    /// and adds it with `timing`. RSA keys get `policy`'s modulus size.
+    pub fn generate(
+        &mut self,
+        policy: &KeyPolicy,
+        ksk: bool,
+        timing: KeyTiming,
+    ) -> Result<&StoredKey, StoreError> {
+        let flags = if ksk { 257 } else { 256 };
+        let algorithm = policy.algorithm;
+        for _ in 0..TAG_ATTEMPTS {
+            let key = match &self.backend {
+                Some(backend) => {
+                    let label =
+                        format!("K{}+{:03}+{:016x}", self.origin, algorithm.0, random::u64());
+                    backend.generate(&label, algorithm)?;
+                    SigningKey::external(flags, algorithm, backend.clone(), &label)?
+                }
+                None => match algorithm {
+                    Algorithm::ED25519
+                    | Algorithm::ECDSAP256SHA256
+                    | Algorithm::ECDSAP384SHA384 => SigningKey::generate(flags, algorithm)?,
+                    _ => SigningKey::generate_rsa(flags, algorithm, policy.rsa_bits)?,
+                },
+            };
+            if self.find(key.algorithm(), key.key_tag()).is_none() {
+                return self.add(key, timing);
+            }
+            if let (Some(backend), Some(label)) = (&self.backend, key.label()) {
+                backend.destroy(label)?;
+            }
+        }
+        Err(KeyError::Backend("no key with a fresh key tag".into()).into())
+    }
+
+    /// Deletes the key with `tag` and its files, destroying it in the
+    /// backend if it is held there.
+    pub fn remove(&mut self, algorithm: Algorithm, tag: u16) -> Result<(), StoreError> {
+        let i = match self.find(algorithm, tag) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let stored = self.keys.remove(i);
+        let base = self.base(&stored.key);
+        fs::remove_file(self.dir.join(format!("{}.key", base)))?;
+        fs::remove_file(self.dir.join(format!("{}.private", base)))?;
+        if let (Some(backend), Some(label)) = (&self.backend, stored.key.label()) {
+            backend.destroy(label)?;
+        }
+        Ok(())
+    }
+
+    /// Changes the timing of the key with `tag` and rewrites its files.
+    pub fn set_timing(
+        &mut self,
+        algorithm: Algorithm,
+        tag: u16,
+        timing: KeyTiming,
+    ) -> Result<(), StoreError> {
+        if let Some(i) = self.find(algorithm, tag) {
+            self.keys[i].timing = timing;
+            self.write(&self.keys[i])?;
+        }
+        Ok(())
+    }
+
+    /// Brings the keys in line with `policy` at `now`: makes the first
+    /// keys of a zone that has none, schedules a successor for each key
+    /// near the end of its lifetime, and deletes keys past their deletion
+    /// time. Keys of other algorithms than the policy's are rolled over
+    /// to it.
+    pub fn maintain(&mut self, policy: &KeyPolicy, now: u32) -> Result<Vec<KeyEvent>, StoreError> {
+        let mut events = Vec::new();
+        let secs = |d: Duration| d.as_secs().min(u64::from(u32::MAX)) as u32;
+        let roles: &[bool] = match policy.scheme {
+            KeyScheme::Split => &[true, false],
+            KeyScheme::Combined => &[true],
+        };
+        let rolling = self.rolling_algorithm(policy, now);
+        for &ksk in roles {
+            let lifetime = if ksk {
+                policy.ksk_lifetime
+            } else {
+                policy.zsk_lifetime
+            };
+            let of_role: Vec<&StoredKey> = self
+                .keys
+                .iter()
+                .filter(|k| {
+                    k.key.algorithm() == policy.algorithm
+                        && k.key.is_ksk() == ksk
+                        && k.timing.delete.is_none_or(|t| now < t)
+                })
+                .collect();
+            let current = of_role
+                .iter()
+                .filter(|k| k.timing.activate.is_some() && k.timing.inactive.is_none())
+                .max_by_key(|k| k.timing.activate)
+                .map(|k| (k.key.key_tag(), k.timing));
+            let (tag, timing) = match current {
+                Some(current) => current,
+                None if of_role.iter().any(|k| k.timing.is_active(now)) => continue,
+                None => {
+                    let timing = if rolling {
+                        // The new algorithm's signatures go out ahead of
+                        // its keys.
+                        KeyTiming {
+                            publish: Some(now.saturating_add(secs(policy.publish_safety))),
+                            ..KeyTiming::active_from(now)
+                        }
+                    } else {
+                        KeyTiming::active_from(now)
+                    };
+                    let key = self.generate(policy, ksk, timing)?;
+                    events.push(KeyEvent::Generated(key.key.key_tag(), now));
+                    continue;
+                }
+            };
+            let (activated, lifetime) = match (timing.activate, lifetime) {
+                (Some(activated), Some(lifetime)) => (activated, secs(lifetime)),
+                _ => continue,
+            };
+            let end = activated.saturating_add(lifetime);
+            let mut old = timing;
+            let new = if !ksk {
+                // Pre-publication: the successor goes into the key set
+                // early enough to be cached everywhere when it takes over.
+                if now.saturating_add(secs(policy.publish_safety)) < end {
+                    continue;
+                }
+                let start = end.max(now.saturating_add(secs(policy.publish_safety)));
+                old.inactive = Some(start);
+                old.delete = Some(start.saturating_add(secs(policy.retire_safety)));
+                KeyTiming {
+                    created: now,
+                    publish: Some(now),
+                    activate: Some(start),
+                    ..KeyTiming::default()
+                }
+            } else {
+                // Double signature: both keys sign until the parent has
+                // swapped the DS records.
+                if now < end {
+                    continue;
+                }
+                if !policy.wait_for_ds {
+                    retire_ksk(&mut old, policy, now);
+                }
+                KeyTiming::active_from(now)
+            };
+            let start = new.activate.unwrap_or(now);
+            let key = self.generate(policy, ksk, new)?;
+            events.push(KeyEvent::Generated(key.key.key_tag(), start));
+            if let Some(inactive) = old.inactive {
+                self.set_timing(policy.algorithm, tag, old)?;
+                events.push(KeyEvent::Retired(tag, inactive));
+            }
+        }
+        if rolling && !policy.wait_for_ds && self.rolled_in(policy, now) {
+            events.extend(self.retire_algorithms(policy, now)?);
+        }
+        let expired: Vec<(Algorithm, u16)> = self
+            .keys
+            .iter()
+            .filter(|k| k.timing.delete.is_some_and(|t| t <= now))
+            .map(|k| (k.key.algorithm(), k.key.key_tag()))
+            .collect();
+        for (algorithm, tag) in expired {
+            self.remove(algorithm, tag)?;
+            events.push(KeyEvent::Removed(tag));
+        }
+        Ok(events)
+    }
+
+    /// Completes the key-signing key and algorithm rollovers of a policy
+    /// with `wait_for_ds` once `parent`, as a [`DsChecker`](super::DsChecker)
+    /// found it, lists the DS records of the newest key-signing key and no
+    /// others: the keys it replaces, and all keys of other algorithms, are
+    /// retired `ds_safety` from `now`, for the old DS records to expire
+    /// from caches.
+    pub fn confirm_ds(
+        &mut self,
+        parent: &ParentDs,
+        policy: &KeyPolicy,
+        now: u32,
+    ) -> Result<Vec<KeyEvent>, StoreError> {
+        let ksks: Vec<&StoredKey> = self
+            .keys
+            .iter()
+            .filter(|k| {
+                k.key.algorithm() == policy.algorithm
+                    && k.key.is_ksk()
+                    && k.timing.activate.is_some()
+                    && k.timing.inactive.is_none()
+            })
+            .collect();
+        let newest = match ksks.iter().max_by_key(|k| k.timing.activate) {
+            Some(newest) => newest.key.dnskey().clone(),
+            None => return Ok(Vec::new()),
+        };
+        if !parent.matches(&self.origin, std::slice::from_ref(&newest)) {
+            return Ok(Vec::new());
+        }
+        let replaced: Vec<(u16, KeyTiming)> = ksks
+            .iter()
+            .filter(|k| k.key.dnskey() != &newest)
+            .map(|k| (k.key.key_tag(), k.timing))
+            .collect();
+        let mut events = Vec::new();
+        for (tag, mut timing) in replaced {
+            retire_ksk(&mut timing, policy, now);
+            self.set_timing(policy.algorithm, tag, timing)?;
+            events.push(KeyEvent::Retired(tag, timing.inactive.unwrap_or(now)));
+        }
+        if self.rolling_algorithm(policy, now) && self.rolled_in(policy, now) {
+            events.extend(self.retire_algorithms(policy, now)?);
+        }
+        Ok(events)
+    }
+
+    /// Whether keys of another algorithm than the policy's are in use and
+    /// not yet retired, so that the policy's algorithm is being rolled to.
+    fn rolling_algorithm(&self, policy: &KeyPolicy, now: u32) -> bool {
+        self.keys.iter().any(|k| {
+            k.key.algorithm() != policy.algorithm
+                && k.timing.is_active(now)
+                && k.timing.inactive.is_none()
+        })
+    }
+
+    /// Whether keys of the policy's algorithm are published and signing
+    /// at `now` in every role the policy's scheme has.
+    fn rolled_in(&self, policy: &KeyPolicy, now: u32) -> bool {
+        let roles: &[bool] = match policy.scheme {
+            KeyScheme::Split => &[true, false],
+            KeyScheme::Combined => &[true],
+        };
+        roles.iter().all(|&ksk| {
+            self.keys.iter().any(|k| {
+                k.key.algorithm() == policy.algorithm
+                    && k.key.is_ksk() == ksk
+                    && k.timing.is_published(now)
+                    && k.timing.is_active(now)
+            })
+        })
+    }
+
+    /// Ends an algorithm rollover: every key of another algorithm than the
+    /// policy's stops signing and leaves the key set `ds_safety` from
+    /// `now`. The new algorithm has signed everything for long enough
+    /// that its signatures and the old ones need not overlap any longer.
+    fn retire_algorithms(
+        &mut self,
+        policy: &KeyPolicy,
+        now: u32,
+    ) -> Result<Vec<KeyEvent>, StoreError> {
+        let retire = now.saturating_add(policy.ds_safety.as_secs().min(u64::from(u32::MAX)) as u32);
+        let old: Vec<(Algorithm, u16, KeyTiming)> = self
+            .keys
+            .iter()
+            .filter(|k| k.key.algorithm() != policy.algorithm && k.timing.inactive.is_none())
+            .filter(|k| k.timing.delete.is_none_or(|t| now < t))
+            .map(|k| (k.key.algorithm(), k.key.key_tag(), k.timing))
+            .collect();
+        let mut events = Vec::new();
+        for (algorithm, tag, mut timing) in old {
+            timing.inactive = Some(retire);
+            timing.delete = Some(retire);
+            self.set_timing(algorithm, tag, timing)?;
+            events.push(KeyEvent::Retired(tag, retire));
+        }
+        Ok(events)
+    }
+
+    fn find(&self, algorithm: Algorithm, tag: u16) -> Option<usize> {
+        self.keys
+            .iter()
+            .position(|k| k.key.algorithm() == algorithm && k.key.key_tag() == tag)
+    }
+
+    /// The name the files of `key` share, without extension.
+    fn base(&self, key: &SigningKey) -> String {
+        format!(
+            "K{}+{:03}+{:05}",
+            self.origin,
+            key.algorithm().0,
+            key.key_tag()
+        )
+    }
+
+    fn write(&self, stored: &StoredKey) -> Result<(), StoreError> {
+        let key = &stored.key;
+        let base = self.base(key);
+        let role = if key.is_ksk() { "key" } else { "zone" };
+        let public = format!(
+            "; This is a {}-signing key, keyid {}, for {}\n{} IN DNSKEY {}\n",
+            role,
+            key.key_tag(),
+            self.origin,
+            self.origin,
+            key.dnskey()
+        );
+        let secret = key.to_bind();
+        let mut private = match &self.passphrase {
+            Some(passphrase) => {
+                let mut salt = [0; 16];
+                let mut nonce = [0; 12];
+                OsRng.fill_bytes(&mut salt);
+                OsRng.fill_bytes(&mut nonce);
+                let sealed = crypto::seal(
+                    &cipher_key(passphrase, &salt, PBKDF2_ITERATIONS),
+                    &nonce,
+                    base.as_bytes(),
+                    secret.as_bytes(),
+                );
+                let alg = key.algorithm();
+                format!(
+                    "Private-key-format: v1.3\nAlgorithm: {} ({})\n\
+                     Encryption: PBKDF2-SHA256 ChaCha20-Poly1305\nIterations: {}\n\
+                     Salt: {}\nNonce: {}\nData: {}\n",
+                    alg.0,
+                    alg,
+                    PBKDF2_ITERATIONS,
+                    encoding::base64_encode(&salt),
+                    encoding::base64_encode(&nonce),
+                    encoding::base64_encode(&sealed)
+                )
+            }
+            None => secret,
+        };
+        let t = &stored.timing;
+        private.push_str(&format!("Created: {}\n", format_time(t.created)));
+        for (name, time) in [
+            ("Publish", t.publish),
+            ("Activate", t.activate),
+            ("Inactive", t.inactive),
+            ("Delete", t.delete),
+        ] {
+            if let Some(time) = time {
+                private.push_str(&format!("{}: {}\n", name, format_time(time)));
+            }
+        }
+        write_private(&self.dir.join(format!("{}.private", base)), &private)?;
+        fs::write(self.dir.join(format!("{}.key", base)), public)?;
+        Ok(())
+    }
+
+    fn read(&self, base: &str) -> Result<StoredKey, StoreError> {
+        let key_file = format!("{}.key", base);
+        let private_file = format!("{}.private", base);
+        let format_error = |file: &str, what: &str| {
+            StoreError::Key(KeyError::Format(format!("{}: {}", file, what)))
+        };
+        let dnskey: Dnskey = fs::read_to_string(self.dir.join(&key_file))?
+            .lines()
+            .map(|line| line.split(';').next().unwrap_or(""))
+            .find_map(|line| {
+                let mut fields = line.split_whitespace();
+                fields.find(|f| f.eq_ignore_ascii_case("DNSKEY"))?;
+                fields.collect::<Vec<_>>().join(" ").parse().ok()
+            })
+            .ok_or_else(|| format_error(&key_file, "no DNSKEY record"))?;
+        let text = fs::read_to_string(self.dir.join(&private_file))?;
+        let field = |name: &str| {
+            text.lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(n, _)| n.trim().eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.trim())
+        };
+        let time = |name: &str| field(name).and_then(parse_time);
+        let timing = KeyTiming {
+            created: time("Created").unwrap_or(0),
+            publish: time("Publish"),
+            activate: time("Activate"),
+            inactive: time("Inactive"),
+            delete: time("Delete"),
+        };
+        let secret = match field("Encryption") {
+            Some(_) => {
+                let passphrase = self
+                    .passphrase
+                    .as_ref()
+                    .ok_or_else(|| StoreError::Locked(private_file.clone()))?;
+                let bytes = |name: &str| {
+                    field(name)
+                        .and_then(|v| encoding::base64_decode(v).ok())
+                        .ok_or_else(|| format_error(&private_file, &format!("bad {} field", name)))
+                };
+                let iterations = field("Iterations")
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| format_error(&private_file, "bad Iterations field"))?;
+                let nonce: [u8; 12] = bytes("Nonce")?
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| format_error(&private_file, "bad Nonce field"))?;
+                let key = cipher_key(passphrase, &bytes("Salt")?, iterations);
+                crypto::open(&key, &nonce, base.as_bytes(), &bytes("Data")?)
+                    .and_then(|plain| String::from_utf8(plain).ok())
+                    .ok_or_else(|| StoreError::Locked(private_file.clone()))?
+            }
+            None => text.clone(),
+        };
+        let label = secret
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(n, _)| n.trim().eq_ignore_ascii_case("Label"))
+            .map(|(_, v)| v.trim().to_owned());
+        let key = match label {
+            Some(label) => {
+                let backend = self
+                    .backend
+                    .clone()
+                    .ok_or_else(|| StoreError::NoBackend(private_file.clone()))?;
+                SigningKey::external(dnskey.flags, dnskey.algorithm, backend, &label)?
+            }
+            None => SigningKey::from_bind(dnskey.flags, &secret)?,
+        };
+        if *key.dnskey() != dnskey {
+            return Err(format_error(
+                &private_file,
+                &format!("does not hold the private key of {}", key_file),
+            ));
+        }
+        Ok(StoredKey { key, timing })
+    }
+}
+
+/// Schedules the end of a key-signing or combined key replaced at `now`
+/// by double signature: a combined key stays published while the
+/// signatures it made over the rest of the zone expire from caches.
+fn retire_ksk(timing: &mut KeyTiming, policy: &KeyPolicy, now: u32) {
+    let secs = |d: Duration| d.as_secs().min(u64::from(u32::MAX)) as u32;
+    let retire = now.saturating_add(secs(policy.ds_safety));
+    timing.inactive = Some(retire);
+    timing.delete = Some(match policy.scheme {
+        KeyScheme::Split => retire,
+        KeyScheme::Combined => retire.saturating_add(secs(policy.retire_safety)),
+    });
+}
+
+/// The ChaCha20-Poly1305 key `passphrase` and `salt` derive.
+fn cipher_key(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0; 32];
+    key.copy_from_slice(&crypto::pbkdf2::<Sha256>(passphrase, salt, iterations, 32));
+    key
+}
+
+/// Writes a private key file readable by its owner only, where the
+/// platform has such permissions.
+fn write_private(path: &std::path::Path, text: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    io::Write::write_all(&mut options.open(path)?, text.as_bytes())
+}
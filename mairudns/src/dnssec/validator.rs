@@ -0,0 +1,787 @@
+//! The validator: checks the records of a response against the chain of
+//! trust from an anchor down to the zone that signed them (RFC 4035 §5).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::message::{ExtendedError, Message, Rcode};
+use crate::name::DomainName;
+use crate::resolver::{Error, ErrorReporter, QueryOptions, ReportPolicy, Resolver};
+use crate::rr::{RData, Record, RecordType};
+
+use super::algorithm::{self, VerifyError};
+use super::canonical;
+use super::denial::{Cut, Denials, Proof};
+use super::{Dnskey, Ds, Rrsig, TrustAnchors};
+
+/// How long a zone found bogus is remembered before its keys are fetched
+/// again.
+const BOGUS_TTL: u32 = 60;
+
+/// The longest a zone's keys or insecurity are remembered.
+const MAX_ZONE_TTL: u32 = 86400;
+
+/// The security status of data (RFC 4033 §5).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Status {
+    /// A chain of signatures leads from a trust anchor to the data.
+    Secure,
+    /// The data is proven to lie in an unsigned zone, or none of the
+    /// algorithms it is signed with is supported.
+    Insecure,
+    /// The validator could not find out, as when the keys could not be
+    /// fetched.
+    Indeterminate,
+    /// The data should be signed but its signatures are missing, expired
+    /// or wrong.
+    Bogus,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Secure => "secure",
+            Status::Insecure => "insecure",
+            Status::Indeterminate => "indeterminate",
+            Status::Bogus => "bogus",
+        })
+    }
+}
+
+/// Why data is not secure when it should be.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Reason {
+    /// Every usable signature has expired.
+    SignatureExpired,
+    /// Every usable signature's validity starts in the future.
+    SignatureNotYetValid,
+    /// No key matches the anchor, the DS records or the signatures.
+    DnskeyMissing,
+    /// The records carry no signatures.
+    RrsigsMissing,
+    /// The NSEC or NSEC3 records needed to deny existence are missing or
+    /// prove nothing.
+    NsecMissing,
+    /// A signature does not verify.
+    BadSignature,
+    /// A signer is not the apex of a zone.
+    NotAZone,
+    /// The keys or DS records could not be fetched.
+    Unreachable,
+}
+
+impl Reason {
+    /// The Extended DNS Error (RFC 8914) to report the failure with.
+    pub fn extended_error(self) -> ExtendedError {
+        let code = match self {
+            Reason::SignatureExpired => ExtendedError::SIGNATURE_EXPIRED,
+            Reason::SignatureNotYetValid => ExtendedError::SIGNATURE_NOT_YET_VALID,
+            Reason::DnskeyMissing => ExtendedError::DNSKEY_MISSING,
+            Reason::RrsigsMissing => ExtendedError::RRSIGS_MISSING,
+            Reason::NsecMissing => ExtendedError::NSEC_MISSING,
+            Reason::BadSignature | Reason::NotAZone => ExtendedError::DNSSEC_BOGUS,
+            Reason::Unreachable => ExtendedError::DNSSEC_INDETERMINATE,
+        };
+        ExtendedError::new(code, self.to_string())
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Reason::SignatureExpired => "signature expired",
+            Reason::SignatureNotYetValid => "signature not yet valid",
+            Reason::DnskeyMissing => "no matching DNSKEY",
+            Reason::RrsigsMissing => "RRSIGs missing",
+            Reason::NsecMissing => "denial of existence not proven",
+            Reason::BadSignature => "signature verification failed",
+            Reason::NotAZone => "signer is not a zone apex",
+            Reason::Unreachable => "keys could not be fetched",
+        })
+    }
+}
+
+/// A response with its security status.
+#[derive(Clone, Debug)]
+pub struct Validated {
+    pub status: Status,
+    /// Why the response is bogus or indeterminate.
+    pub reason: Option<Reason>,
+    /// The response, with the AD bit set only if it is secure and the
+    /// TTLs of secure records capped by their signatures' expiration.
+    pub message: Message,
+}
+
+impl Validated {
+    fn new(message: Message) -> Validated {
+        Validated {
+            status: Status::Secure,
+            reason: None,
+            message,
+        }
+    }
+
+    /// Lowers the status to `status` if that is worse.
+    fn downgrade(&mut self, status: Status, reason: Option<Reason>) {
+        if status > self.status {
+            self.status = status;
+            self.reason = reason;
+        }
+    }
+
+    fn apply(&mut self, state: &ZoneState) {
+        match state {
+            ZoneState::Secure(_) => {}
+            ZoneState::Insecure => self.downgrade(Status::Insecure, None),
+            ZoneState::Bogus(reason) => self.downgrade(Status::Bogus, Some(*reason)),
+            ZoneState::Indeterminate => {
+                self.downgrade(Status::Indeterminate, Some(Reason::Unreachable))
+            }
+        }
+    }
+
+    /// The Extended DNS Error to attach when answering with this status.
+    pub fn extended_error(&self) -> Option<ExtendedError> {
+        self.reason.map(Reason::extended_error)
+    }
+}
+
+/// What is known about a zone.
+#[derive(Clone, Debug)]
+enum ZoneState {
+    /// Its key set is validated; these are its zone keys.
+    Secure(Arc<[Dnskey]>),
+    Insecure,
+    Bogus(Reason),
+    Indeterminate,
+}
+
+/// An RRset of a response, with the signatures covering it.
+struct RRset<'a> {
+    name: &'a DomainName,
+    rtype: RecordType,
+    records: Vec<&'a Record>,
+    sigs: Vec<Rrsig>,
+}
+
+/// Groups `records` into RRsets, attaching the RRSIGs among them.
+fn rrsets(records: &[Record]) -> Vec<RRset<'_>> {
+    let mut sets: Vec<RRset<'_>> = Vec::new();
+    for rr in records.iter().filter(|rr| rr.rtype() != RecordType::RRSIG) {
+        match sets
+            .iter_mut()
+            .find(|s| s.rtype == rr.rtype() && *s.name == rr.name)
+        {
+            Some(set) => set.records.push(rr),
+            None => sets.push(RRset {
+                name: &rr.name,
+                rtype: rr.rtype(),
+                records: vec![rr],
+                sigs: Vec::new(),
+            }),
+        }
+    }
+    for rr in records {
+        if let Some(sig) = Rrsig::from_rdata(&rr.rdata) {
+            if let Some(set) = sets
+                .iter_mut()
+                .find(|s| s.rtype == sig.type_covered && *s.name == rr.name)
+            {
+                set.sigs.push(sig);
+            }
+        }
+    }
+    sets
+}
+
+/// Checks the signatures over `set` made by `zone` with its `keys`, and
+/// returns the signature that verified.
+fn verify_rrset<'a>(
+    set: &'a RRset<'_>,
+    zone: &DomainName,
+    keys: &[Dnskey],
+    now: u32,
+) -> Result<&'a Rrsig, Reason> {
+    let mut reason = Reason::RrsigsMissing;
+    let owner_labels = set.name.label_count() - usize::from(set.name.is_wildcard());
+    for sig in &set.sigs {
+        if sig.signer != *zone || usize::from(sig.labels) > owner_labels {
+            continue;
+        }
+        if sig.is_expired(now) {
+            reason = Reason::SignatureExpired;
+            continue;
+        }
+        if sig.is_premature(now) {
+            reason = Reason::SignatureNotYetValid;
+            continue;
+        }
+        let data = canonical::signed_data(sig, &set.records);
+        let mut verified = false;
+        let mut keyed = false;
+        for key in keys
+            .iter()
+            .filter(|k| k.algorithm == sig.algorithm && k.key_tag() == sig.key_tag)
+        {
+            keyed = true;
+            match algorithm::verify(sig.algorithm, &key.public_key, &data, &sig.signature) {
+                Ok(()) => {
+                    verified = true;
+                    break;
+                }
+                Err(VerifyError::Unsupported) => {}
+                Err(_) => reason = Reason::BadSignature,
+            }
+        }
+        if verified {
+            return Ok(sig);
+        }
+        if !keyed && reason == Reason::RrsigsMissing {
+            reason = Reason::DnskeyMissing;
+        }
+    }
+    Err(reason)
+}
+
+/// Caps the TTLs of `records` in `section` owned by `name` and of
+/// `rtype`, and of the RRSIGs covering them, at `ttl`.
+fn cap_ttl(section: &mut [Record], name: &DomainName, rtype: RecordType, ttl: u32) {
+    for rr in section.iter_mut().filter(|rr| rr.name == *name) {
+        let covered = match Rrsig::from_rdata(&rr.rdata) {
+            Some(sig) => sig.type_covered,
+            None => rr.rtype(),
+        };
+        if covered == rtype {
+            rr.ttl = rr.ttl.min(ttl);
+        }
+    }
+}
+
+/// A validating front for a [`Resolver`]: it asks with the DO and CD bits
+/// set and checks the answers itself, fetching the DNSKEY and DS records
+/// of the chain of trust through the same resolver. Validated zone keys
+/// are remembered for as long as their TTLs and signatures allow.
+///
+/// Validation failures can be reported to the agent a response advertises
+/// in its Report-Channel option (RFC 9567), as [`Validator::report_errors`]
+/// enables.
+#[derive(Debug)]
+pub struct Validator {
+    resolver: Resolver,
+    anchors: RwLock<TrustAnchors>,
+    zones: Mutex<HashMap<DomainName, (ZoneState, Instant)>>,
+    reporter: Option<ErrorReporter>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Validator {
+    /// A validator anchored at the root zone's keys.
+    pub fn new(resolver: Resolver) -> Validator {
+        Validator {
+            resolver,
+            anchors: RwLock::new(TrustAnchors::root()),
+            zones: Mutex::new(HashMap::new()),
+            reporter: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Checks signature validity periods, expires what it learns of zone
+    /// keys, and limits the error reports it sends, by `clock` rather than
+    /// the system's.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.reporter = self.reporter.map(|r| r.clock(clock.clone()));
+        self.clock = clock;
+        self
+    }
+
+    /// Replaces the trust anchors.
+    pub fn anchors(self, anchors: TrustAnchors) -> Self {
+        *self.anchors.write().unwrap() = anchors;
+        self.flush();
+        self
+    }
+
+    /// Reports responses that fail validation to the agent they advertise,
+    /// through the same resolver, within the limits of `policy`.
+    pub fn report_errors(mut self, policy: ReportPolicy) -> Self {
+        let reporter = ErrorReporter::new(self.resolver.clone(), policy);
+        self.reporter = Some(reporter.clock(self.clock.clone()));
+        self
+    }
+
+    /// Replaces the trust anchors of a validator in use.
+    pub fn set_trust_anchors(&self, anchors: TrustAnchors) {
+        *self.anchors.write().unwrap() = anchors;
+        self.flush();
+    }
+
+    /// Turns validation off for `zone` and everything below it, for
+    /// `lifetime` or until removed; see [`TrustAnchors::add_negative`].
+    pub fn add_negative_anchor(&self, zone: DomainName, lifetime: Option<Duration>) {
+        let until = lifetime.map(|d| self.clock.unix_time().saturating_add(d.as_secs()));
+        self.anchors.write().unwrap().add_negative(zone, until);
+    }
+
+    /// Drops the negative trust anchor at `zone`, so that it validates
+    /// again; returns whether it had one.
+    pub fn remove_negative_anchor(&self, zone: &DomainName) -> bool {
+        let removed = self.anchors.write().unwrap().remove_negative(zone);
+        if removed {
+            self.flush();
+        }
+        removed
+    }
+
+    /// The trust anchors, including what RFC 5011 tracking has learned;
+    /// worth saving so that rollovers seen are not forgotten.
+    pub fn trust_anchors(&self) -> TrustAnchors {
+        self.anchors.read().unwrap().clone()
+    }
+
+    pub fn resolver(&self) -> &Resolver {
+        &self.resolver
+    }
+
+    /// Forgets what is known about every zone's keys.
+    pub fn flush(&self) {
+        self.zones.lock().unwrap().clear();
+    }
+
+    /// Queries `name`/`qtype` and validates the response.
+    pub fn query(&self, name: &DomainName, qtype: RecordType) -> Result<Validated, Error> {
+        Ok(self.validate(&self.fetch(name, qtype)?))
+    }
+
+    /// Validates `response`, which should have been asked for with the DO
+    /// bit set.
+    pub fn validate(&self, response: &Message) -> Validated {
+        let validated = self.validate_at(response, self.clock.unix_time());
+        if let (Some(reporter), Some(error)) = (&self.reporter, validated.extended_error()) {
+            reporter.report_response(response, error.code);
+        }
+        validated
+    }
+
+    fn fetch(&self, name: &DomainName, qtype: RecordType) -> Result<Message, Error> {
+        let options = QueryOptions {
+            dnssec_ok: true,
+            checking_disabled: true,
+            ..QueryOptions::default()
+        };
+        self.resolver.query_with(name, qtype, &options)
+    }
+
+    fn validate_at(&self, response: &Message, now: u64) -> Validated {
+        let sig_now = now as u32;
+        let mut v = Validated::new(response.clone());
+        v.message.header.ad = false;
+        let q = match response.question() {
+            Some(q) => q,
+            None => {
+                v.downgrade(Status::Indeterminate, None);
+                return v;
+            }
+        };
+        let rcode = response.header.rcode;
+        if rcode != Rcode::NOERROR && rcode != Rcode::NXDOMAIN {
+            v.downgrade(Status::Indeterminate, None);
+            return v;
+        }
+
+        // The answer, RRset by RRset, following the CNAME chain.
+        let mut target = q.name.clone();
+        let mut answered = false;
+        let answers = rrsets(&response.answers);
+        let mut wildcards = Vec::new();
+        for set in &answers {
+            if set.rtype == RecordType::CNAME && *set.name == target {
+                if let Some(RData::Cname(next)) = set.records.first().map(|rr| &rr.rdata) {
+                    target = next.clone();
+                }
+            }
+            if *set.name == target && (set.rtype == q.qtype || q.qtype == RecordType::ANY) {
+                answered = true;
+            }
+            if set.rtype == RecordType::CNAME && self.synthesized(set, &answers) {
+                continue;
+            }
+            if set.sigs.is_empty() {
+                match self.name_state(set.name, sig_now) {
+                    ZoneState::Secure(_) => v.downgrade(Status::Bogus, Some(Reason::RrsigsMissing)),
+                    state => v.apply(&state),
+                }
+                continue;
+            }
+            let signer = set.sigs[0].signer.clone();
+            match self.zone_state(&signer, sig_now) {
+                ZoneState::Secure(keys) if set.name.is_subdomain_of(&signer) => {
+                    match verify_rrset(set, &signer, &keys, sig_now) {
+                        Ok(sig) => {
+                            let ttl = sig.original_ttl.min(sig.expiration.wrapping_sub(sig_now));
+                            cap_ttl(&mut v.message.answers, set.name, set.rtype, ttl);
+                            let owner_labels =
+                                set.name.label_count() - usize::from(set.name.is_wildcard());
+                            if usize::from(sig.labels) < owner_labels {
+                                wildcards.push((set.name.clone(), sig.labels, signer));
+                            }
+                        }
+                        Err(reason) => v.downgrade(Status::Bogus, Some(reason)),
+                    }
+                }
+                ZoneState::Secure(_) => v.downgrade(Status::Bogus, Some(Reason::NotAZone)),
+                state => v.apply(&state),
+            }
+        }
+
+        // The authority section proves wildcard expansions and negative
+        // answers.
+        let negative = rcode == Rcode::NXDOMAIN || !answered;
+        if negative || !wildcards.is_empty() {
+            let proof_target = if negative { Some(&target) } else { None };
+            match self.denials(response, proof_target, sig_now) {
+                Ok(Some((zone, denials))) => {
+                    for (name, labels, signer) in &wildcards {
+                        let proof = if *signer == zone {
+                            denials.wildcard_answer(name, *labels)
+                        } else {
+                            Proof::Failed
+                        };
+                        match proof {
+                            Proof::Secure => {}
+                            Proof::Insecure => v.downgrade(Status::Insecure, None),
+                            Proof::Failed => v.downgrade(Status::Bogus, Some(Reason::NsecMissing)),
+                        }
+                    }
+                    if negative {
+                        let proof = if rcode == Rcode::NXDOMAIN {
+                            denials.name_error(&target)
+                        } else {
+                            denials.no_data(&target, q.qtype)
+                        };
+                        match proof {
+                            Proof::Secure => {}
+                            Proof::Insecure => v.downgrade(Status::Insecure, None),
+                            Proof::Failed => v.downgrade(Status::Bogus, Some(Reason::NsecMissing)),
+                        }
+                    }
+                }
+                Ok(None) if !wildcards.is_empty() => {
+                    v.downgrade(Status::Bogus, Some(Reason::NsecMissing))
+                }
+                Ok(None) => {}
+                Err(state) => v.apply(&state),
+            }
+        }
+        v.message.header.ad = v.status == Status::Secure;
+        v
+    }
+
+    /// Whether `set` is a CNAME synthesized from a DNAME elsewhere in the
+    /// answer (RFC 6672 §5.3.1), which carries no signature of its own.
+    fn synthesized(&self, set: &RRset<'_>, answers: &[RRset<'_>]) -> bool {
+        let target = match set.records.first().map(|rr| &rr.rdata) {
+            Some(RData::Cname(target)) => target,
+            _ => return false,
+        };
+        answers.iter().any(|dname| {
+            let to = match dname.records.first().map(|rr| &rr.rdata) {
+                Some(RData::Dname(to)) if dname.rtype == RecordType::DNAME => to,
+                _ => return false,
+            };
+            if !set.name.is_subdomain_of(dname.name) || set.name == dname.name {
+                return false;
+            }
+            let prefix = &set.name.labels()[..set.name.label_count() - dname.name.label_count()];
+            DomainName::from_labels(prefix.iter().chain(to.labels().iter()))
+                .is_ok_and(|expected| expected == *target)
+        })
+    }
+
+    /// Validates the NSEC, NSEC3 and SOA records of `response`'s authority
+    /// section. Returns the zone that signed them with what they prove,
+    /// `None` if there are none to check, or the state that makes
+    /// checking them pointless. `target` is the name a negative answer is
+    /// about, for finding its zone if nothing is signed.
+    fn denials(
+        &self,
+        response: &Message,
+        target: Option<&DomainName>,
+        now: u32,
+    ) -> Result<Option<(DomainName, Denials)>, ZoneState> {
+        let sets: Vec<RRset<'_>> = rrsets(&response.authority)
+            .into_iter()
+            .filter(|s| {
+                matches!(
+                    s.rtype,
+                    RecordType::NSEC | RecordType::NSEC3 | RecordType::SOA
+                )
+            })
+            .collect();
+        let signer = sets
+            .iter()
+            .flat_map(|s| s.sigs.iter())
+            .map(|sig| sig.signer.clone())
+            .next();
+        let signer = match (signer, target) {
+            (Some(signer), _) => signer,
+            (None, None) => return Ok(None),
+            (None, Some(target)) => {
+                // Nothing signed: fine only if the zone is insecure. DS
+                // records lie on the parent's side of a cut, so an answer
+                // about them is judged by the parent, which also keeps
+                // this from asking the same question again.
+                let soa = sets.iter().find(|s| s.rtype == RecordType::SOA);
+                let zone = match soa.map(|s| s.name) {
+                    Some(zone) if target.is_subdomain_of(zone) => zone,
+                    _ => target,
+                };
+                let about_ds = response
+                    .question()
+                    .is_some_and(|q| q.qtype == RecordType::DS && q.name == *zone);
+                let state = match zone.parent() {
+                    Some(parent) if about_ds => self.name_state(&parent, now),
+                    _ => self.name_state(zone, now),
+                };
+                return Err(match state {
+                    ZoneState::Secure(_) => ZoneState::Bogus(Reason::NsecMissing),
+                    state => state,
+                });
+            }
+        };
+        if target.is_some_and(|t| !t.is_subdomain_of(&signer)) {
+            return Err(ZoneState::Bogus(Reason::NotAZone));
+        }
+        let keys = match self.zone_state(&signer, now) {
+            ZoneState::Secure(keys) => keys,
+            state => return Err(state),
+        };
+        let mut records = Vec::new();
+        for set in &sets {
+            match verify_rrset(set, &signer, &keys, now) {
+                Ok(_) => records.extend(set.records.iter().copied()),
+                Err(reason) => return Err(ZoneState::Bogus(reason)),
+            }
+        }
+        Ok(Some((signer.clone(), Denials::new(&signer, records))))
+    }
+
+    /// The state of the zone `zone`, whose apex it must be, from the cache
+    /// or else worked out.
+    fn zone_state(&self, zone: &DomainName, now: u32) -> ZoneState {
+        if self
+            .anchors
+            .read()
+            .unwrap()
+            .is_negative(zone, u64::from(now))
+        {
+            return ZoneState::Insecure;
+        }
+        if let Some((state, expires)) = self.zones.lock().unwrap().get(zone) {
+            if *expires > self.clock.now() {
+                return state.clone();
+            }
+        }
+        let (state, ttl) = self.fetch_zone(zone, now);
+        if !matches!(state, ZoneState::Indeterminate) {
+            let ttl = match state {
+                ZoneState::Bogus(_) => BOGUS_TTL,
+                _ => ttl.min(MAX_ZONE_TTL),
+            };
+            let expires = self.clock.now() + Duration::from_secs(u64::from(ttl));
+            self.zones
+                .lock()
+                .unwrap()
+                .insert(zone.clone(), (state.clone(), expires));
+        }
+        state
+    }
+
+    /// Works out the state of `zone` from its anchor, or from the DS
+    /// records its parent has for it. Returns it with how long it holds.
+    fn fetch_zone(&self, zone: &DomainName, now: u32) -> (ZoneState, u32) {
+        {
+            let anchors = self.anchors.read().unwrap();
+            if anchors.get(zone).is_some() {
+                if !anchors.is_supported(zone) {
+                    return (ZoneState::Insecure, MAX_ZONE_TTL);
+                }
+                drop(anchors);
+                return self.fetch_keys(zone, None, now);
+            }
+            if anchors.closest(zone).is_none() {
+                return (ZoneState::Insecure, MAX_ZONE_TTL);
+            }
+        }
+        let parent = match zone.parent() {
+            Some(parent) => parent,
+            None => return (ZoneState::Insecure, MAX_ZONE_TTL),
+        };
+        let response = match self.fetch(zone, RecordType::DS) {
+            Ok(response) => response,
+            Err(_) => return (ZoneState::Indeterminate, 0),
+        };
+        let sets = rrsets(&response.answers);
+        if let Some(set) = sets
+            .iter()
+            .find(|s| s.rtype == RecordType::DS && s.name == zone)
+        {
+            let signer = match set.sigs.first() {
+                Some(sig) => sig.signer.clone(),
+                None => {
+                    return match self.zone_state(&parent, now) {
+                        ZoneState::Secure(_) => (ZoneState::Bogus(Reason::RrsigsMissing), 0),
+                        state => (state, MAX_ZONE_TTL),
+                    }
+                }
+            };
+            if !zone.is_subdomain_of(&signer) || signer == *zone {
+                return (ZoneState::Bogus(Reason::NotAZone), 0);
+            }
+            let keys = match self.zone_state(&signer, now) {
+                ZoneState::Secure(keys) => keys,
+                state => return (state, MAX_ZONE_TTL),
+            };
+            if let Err(reason) = verify_rrset(set, &signer, &keys, now) {
+                return (ZoneState::Bogus(reason), 0);
+            }
+            let ds: Vec<Ds> = set
+                .records
+                .iter()
+                .filter_map(|rr| Ds::from_rdata(&rr.rdata))
+                .collect();
+            return self.fetch_keys(zone, Some(&ds), now);
+        }
+        // No DS: an unsigned delegation, or not a zone at all.
+        match self.denials(&response, Some(zone), now) {
+            Ok(Some((signer, denials))) if signer != *zone => match denials.cut(zone) {
+                Cut::Unsigned => (ZoneState::Insecure, min_ttl(&response.authority)),
+                Cut::Inside => (ZoneState::Bogus(Reason::NotAZone), 0),
+                Cut::Failed => (ZoneState::Bogus(Reason::NsecMissing), 0),
+            },
+            Ok(Some(_)) => (ZoneState::Bogus(Reason::NotAZone), 0),
+            Ok(None) => (ZoneState::Bogus(Reason::NsecMissing), 0),
+            Err(state) => (state, MAX_ZONE_TTL),
+        }
+    }
+
+    /// The state of the zone `name` lies in, for checking data that came
+    /// without signatures: secure means the data is bogus.
+    fn name_state(&self, name: &DomainName, now: u32) -> ZoneState {
+        {
+            let anchors = self.anchors.read().unwrap();
+            if anchors.is_negative(name, u64::from(now)) {
+                return ZoneState::Insecure;
+            }
+            if anchors.get(name).is_some() {
+                drop(anchors);
+                return self.zone_state(name, now);
+            }
+            if anchors.closest(name).is_none() {
+                return ZoneState::Insecure;
+            }
+        }
+        let response = match self.fetch(name, RecordType::DS) {
+            Ok(response) => response,
+            Err(_) => return ZoneState::Indeterminate,
+        };
+        if response
+            .answers
+            .iter()
+            .any(|rr| rr.rtype() == RecordType::DS && rr.name == *name)
+        {
+            return self.zone_state(name, now);
+        }
+        match self.denials(&response, Some(name), now) {
+            Ok(Some((zone, denials))) => match denials.cut(name) {
+                Cut::Unsigned => ZoneState::Insecure,
+                Cut::Inside => self.zone_state(&zone, now),
+                Cut::Failed => ZoneState::Bogus(Reason::NsecMissing),
+            },
+            Ok(None) => ZoneState::Bogus(Reason::NsecMissing),
+            Err(state) => state,
+        }
+    }
+
+    /// Fetches and validates the key set of `zone` against its DS records,
+    /// or against its trust anchors if `ds` is `None`.
+    fn fetch_keys(&self, zone: &DomainName, ds: Option<&[Ds]>, now: u32) -> (ZoneState, u32) {
+        if let Some(ds) = ds {
+            if !ds.iter().any(Ds::is_supported) {
+                return (ZoneState::Insecure, MAX_ZONE_TTL);
+            }
+        }
+        let response = match self.fetch(zone, RecordType::DNSKEY) {
+            Ok(response) => response,
+            Err(_) => return (ZoneState::Indeterminate, 0),
+        };
+        let sets = rrsets(&response.answers);
+        let set = match sets
+            .iter()
+            .find(|s| s.rtype == RecordType::DNSKEY && s.name == zone)
+        {
+            Some(set) => set,
+            None => return (ZoneState::Bogus(Reason::DnskeyMissing), 0),
+        };
+        let keys: Vec<Dnskey> = set
+            .records
+            .iter()
+            .filter_map(|rr| Dnskey::from_rdata(&rr.rdata))
+            .collect();
+        let trusted: Vec<Dnskey> = {
+            let anchors = self.anchors.read().unwrap();
+            keys.iter()
+                .filter(|k| k.is_zone_key() && !k.is_revoked())
+                .filter(|k| match ds {
+                    Some(ds) => ds.iter().any(|d| d.is_supported() && d.matches(zone, k)),
+                    None => anchors.trusts(zone, k),
+                })
+                .cloned()
+                .collect()
+        };
+        if trusted.is_empty() {
+            return (ZoneState::Bogus(Reason::DnskeyMissing), 0);
+        }
+        let sig = match verify_rrset(set, zone, &trusted, now) {
+            Ok(sig) => sig,
+            Err(reason) => return (ZoneState::Bogus(reason), 0),
+        };
+        let ttl = set
+            .records
+            .iter()
+            .map(|rr| rr.ttl)
+            .min()
+            .unwrap_or(0)
+            .min(sig.original_ttl)
+            .min(sig.expiration.wrapping_sub(now));
+        if ds.is_none() {
+            // Tags of the keys whose signatures over the set hold, as
+            // RFC 5011 needs for revocations.
+            let signers: Vec<u16> = keys
+                .iter()
+                .filter(|k| {
+                    let one = [(*k).clone()];
+                    verify_rrset(set, zone, &one, now).is_ok()
+                })
+                .map(Dnskey::key_tag)
+                .collect();
+            self.anchors
+                .write()
+                .unwrap()
+                .observe(zone, &keys, &signers, u64::from(now));
+        }
+        let zone_keys: Vec<Dnskey> = keys
+            .into_iter()
+            .filter(|k| k.is_zone_key() && !k.is_revoked())
+            .collect();
+        (ZoneState::Secure(zone_keys.into()), ttl)
+    }
+}
+
+/// The smallest TTL among `records`, for how long a proof holds.
+fn min_ttl(records: &[Record]) -> u32 {
+    records.iter().map(|rr| rr.ttl).min().unwrap_or(0)
+}
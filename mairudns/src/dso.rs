@@ -0,0 +1,279 @@
+//! DNS Stateful Operations (DSO, RFC 8490).
+//!
+//! DSO messages share the header of other DNS messages, with the DSO
+//! opcode and all four section counts zero, but carry a list of TLVs in
+//! place of the sections. The first TLV of a request is its primary TLV
+//! and says what the message is about; the rest modify it. Requests have
+//! a nonzero ID and are answered; unidirectional messages have the ID
+//! zero and are not.
+//!
+//! A session is established on a TCP or TLS connection when the client
+//! sends a [`Keepalive`] request and the server answers it. The server's
+//! answer sets how long the client may keep the connection open without
+//! queries in progress and how often it must send something to show it
+//! is still there. A server that wants the client gone sends a Retry
+//! Delay message saying how long to wait before reconnecting.
+//!
+//! [`DsoMessage`] encodes and decodes the messages; the client side of a
+//! session is [`DsoSession`](crate::client::DsoSession) and the server
+//! side is part of the server's TCP and TLS listeners. The TLVs of DNS
+//! Push Notifications, which run on DSO sessions, are in [`push`](crate::push).
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::message::{Header, Opcode, Rcode};
+use crate::random;
+use crate::wire::{self, Decoder, Encoder};
+
+/// The inactivity timeout and keepalive interval servers give unless
+/// configured otherwise (RFC 8490 §6.2).
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The shortest keepalive interval a server may ask for (RFC 8490 §6.5.2).
+pub const MIN_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The type of a DSO TLV.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DsoType(pub u16);
+
+impl DsoType {
+    pub const KEEPALIVE: DsoType = DsoType(1);
+    pub const RETRY_DELAY: DsoType = DsoType(2);
+    pub const ENCRYPTION_PADDING: DsoType = DsoType(3);
+    pub const SUBSCRIBE: DsoType = DsoType(0x40);
+    pub const PUSH: DsoType = DsoType(0x41);
+    pub const UNSUBSCRIBE: DsoType = DsoType(0x42);
+    pub const RECONFIRM: DsoType = DsoType(0x43);
+}
+
+impl fmt::Display for DsoType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DsoType::KEEPALIVE => f.write_str("KEEPALIVE"),
+            DsoType::RETRY_DELAY => f.write_str("RETRY-DELAY"),
+            DsoType::ENCRYPTION_PADDING => f.write_str("ENCRYPTION-PADDING"),
+            DsoType::SUBSCRIBE => f.write_str("SUBSCRIBE"),
+            DsoType::PUSH => f.write_str("PUSH"),
+            DsoType::UNSUBSCRIBE => f.write_str("UNSUBSCRIBE"),
+            DsoType::RECONFIRM => f.write_str("RECONFIRM"),
+            DsoType(n) => write!(f, "TYPE{}", n),
+        }
+    }
+}
+
+/// One type-length-value item of a DSO message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tlv {
+    pub dtype: DsoType,
+    pub data: Vec<u8>,
+}
+
+impl Tlv {
+    pub fn new(dtype: DsoType, data: Vec<u8>) -> Tlv {
+        Tlv { dtype, data }
+    }
+}
+
+/// The timeouts of a session, as carried by a Keepalive TLV. `None`
+/// stands for infinity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long the client may keep the session open with no queries
+    /// in progress.
+    pub inactivity_timeout: Option<Duration>,
+    /// How often the client must send something to keep the session.
+    pub keepalive_interval: Option<Duration>,
+}
+
+impl Default for Keepalive {
+    fn default() -> Keepalive {
+        Keepalive {
+            inactivity_timeout: Some(DEFAULT_TIMEOUT),
+            keepalive_interval: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+impl Keepalive {
+    pub fn to_tlv(&self) -> Tlv {
+        let mut data = Vec::with_capacity(8);
+        data.extend_from_slice(&millis(self.inactivity_timeout).to_be_bytes());
+        data.extend_from_slice(&millis(self.keepalive_interval).to_be_bytes());
+        Tlv::new(DsoType::KEEPALIVE, data)
+    }
+
+    pub fn from_tlv(tlv: &Tlv) -> Result<Keepalive, wire::Error> {
+        if tlv.dtype != DsoType::KEEPALIVE || tlv.data.len() != 8 {
+            return Err(wire::Error::BadRdata { offset: 0 });
+        }
+        let mut dec = Decoder::new(&tlv.data);
+        Ok(Keepalive {
+            inactivity_timeout: duration(dec.u32()?),
+            keepalive_interval: duration(dec.u32()?),
+        })
+    }
+}
+
+/// Milliseconds on the wire, all ones for infinity.
+fn millis(d: Option<Duration>) -> u32 {
+    d.map_or(u32::MAX, |d| {
+        d.as_millis().min(u128::from(u32::MAX - 1)) as u32
+    })
+}
+
+fn duration(ms: u32) -> Option<Duration> {
+    (ms != u32::MAX).then(|| Duration::from_millis(u64::from(ms)))
+}
+
+/// A Retry Delay TLV asking the client to wait `delay` before
+/// reconnecting.
+pub fn retry_delay_tlv(delay: Duration) -> Tlv {
+    let ms = delay.as_millis().min(u128::from(u32::MAX)) as u32;
+    Tlv::new(DsoType::RETRY_DELAY, ms.to_be_bytes().to_vec())
+}
+
+/// Whether `buf` starts with the header of a DSO message.
+pub fn is_dso(buf: &[u8]) -> bool {
+    buf.len() >= 12 && (buf[2] >> 3) & 0xf == Opcode::DSO.0
+}
+
+/// A DSO message: a header and its TLVs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DsoMessage {
+    pub header: Header,
+    /// The primary TLV first, if the message has one.
+    pub tlvs: Vec<Tlv>,
+}
+
+impl DsoMessage {
+    /// A request with a random nonzero ID.
+    pub fn request(primary: Tlv) -> DsoMessage {
+        let id = loop {
+            match random::u16() {
+                0 => continue,
+                id => break id,
+            }
+        };
+        DsoMessage {
+            header: Header {
+                id,
+                opcode: Opcode::DSO,
+                ..Header::default()
+            },
+            tlvs: vec![primary],
+        }
+    }
+
+    /// A message that is not answered, with the ID zero.
+    pub fn unidirectional(primary: Tlv) -> DsoMessage {
+        DsoMessage {
+            header: Header {
+                id: 0,
+                opcode: Opcode::DSO,
+                ..Header::default()
+            },
+            tlvs: vec![primary],
+        }
+    }
+
+    /// A request to establish a session, or keep one, with the timeouts
+    /// the client would like.
+    pub fn keepalive(keepalive: &Keepalive) -> DsoMessage {
+        DsoMessage::request(keepalive.to_tlv())
+    }
+
+    /// A unidirectional message from a server asking the client to close
+    /// the session and wait `delay` before reconnecting.
+    pub fn retry_delay(delay: Duration) -> DsoMessage {
+        DsoMessage::unidirectional(retry_delay_tlv(delay))
+    }
+
+    /// A response to this request with `rcode` and no TLVs.
+    pub fn response(&self, rcode: Rcode) -> DsoMessage {
+        DsoMessage {
+            header: Header {
+                id: self.header.id,
+                qr: true,
+                opcode: Opcode::DSO,
+                rcode,
+                ..Header::default()
+            },
+            tlvs: Vec::new(),
+        }
+    }
+
+    pub fn is_unidirectional(&self) -> bool {
+        self.header.id == 0
+    }
+
+    pub fn primary(&self) -> Option<&Tlv> {
+        self.tlvs.first()
+    }
+
+    /// The TLV of type `dtype`, primary or not.
+    pub fn tlv(&self, dtype: DsoType) -> Option<&Tlv> {
+        self.tlvs.iter().find(|t| t.dtype == dtype)
+    }
+
+    /// The timeouts of a Keepalive TLV in the message.
+    pub fn keepalive_values(&self) -> Option<Keepalive> {
+        self.tlv(DsoType::KEEPALIVE)
+            .and_then(|t| Keepalive::from_tlv(t).ok())
+    }
+
+    /// The delay of a Retry Delay TLV in the message.
+    pub fn retry_delay_value(&self) -> Option<Duration> {
+        let tlv = self.tlv(DsoType::RETRY_DELAY)?;
+        if tlv.data.len() != 4 {
+            return None;
+        }
+        let ms = Decoder::new(&tlv.data).u32().ok()?;
+        Some(Duration::from_millis(u64::from(ms)))
+    }
+
+    pub fn to_wire(&self) -> Result<Vec<u8>, wire::Error> {
+        let mut enc = Encoder::new();
+        enc.u16(self.header.id);
+        enc.u16(self.header.flags());
+        for _ in 0..4 {
+            enc.u16(0);
+        }
+        for tlv in &self.tlvs {
+            if tlv.data.len() > usize::from(u16::MAX) {
+                return Err(wire::Error::TooLong { offset: enc.len() });
+            }
+            enc.u16(tlv.dtype.0);
+            enc.u16(tlv.data.len() as u16);
+            enc.bytes(&tlv.data);
+        }
+        if enc.len() > usize::from(u16::MAX) {
+            return Err(wire::Error::TooLong { offset: enc.len() });
+        }
+        Ok(enc.into_bytes())
+    }
+
+    /// Decodes a DSO message; section counts other than zero are an
+    /// error (RFC 8490 §5.4).
+    pub fn from_wire(buf: &[u8]) -> Result<DsoMessage, wire::Error> {
+        let mut dec = Decoder::new(buf);
+        let id = dec.u16()?;
+        let header = Header::from_flags(id, dec.u16()?);
+        if header.opcode != Opcode::DSO {
+            return Err(wire::Error::BadRdata { offset: 2 });
+        }
+        for _ in 0..4 {
+            let at = dec.pos();
+            if dec.u16()? != 0 {
+                return Err(wire::Error::BadRdata { offset: at });
+            }
+        }
+        let mut tlvs = Vec::new();
+        while dec.remaining() > 0 {
+            let dtype = DsoType(dec.u16()?);
+            let len = dec.u16()?;
+            tlvs.push(Tlv::new(dtype, dec.bytes(usize::from(len))?.to_vec()));
+        }
+        Ok(DsoMessage { header, tlvs })
+    }
+}
@@ -0,0 +1,234 @@
+//! Base64 (RFC 4648 §4) and hexadecimal, as used in key files and the
+//! presentation format of DNSSEC and TSIG data, the URL-safe base64 of
+//! DNS over HTTPS (RFC 4648 §5), and the base32hex of NSEC3 hashed names
+//! (RFC 4648 §7, RFC 5155 §3.3).
+//!
+//! The bulk of long inputs goes through vector paths chosen at run time
+//! for the CPU: AVX2 or SSSE3 on x86-64, NEON on AArch64. Scalar loops
+//! handle the rest and agree with them byte for byte.
+
+use std::fmt;
+
+use crate::simd;
+
+/// Errors produced when decoding text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A character outside the alphabet.
+    BadChar,
+    /// Input that does not end on a whole number of bytes, or misplaced
+    /// padding.
+    BadLength,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::BadChar => "invalid character",
+            Error::BadLength => "invalid length or padding",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub(crate) const BASE64: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `data` as padded base64.
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    let done = simd::base64_encode(data, &mut out);
+    for chunk in data[done..].chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from(b[0]) << 16 | u32::from(b[1]) << 8 | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+    ascii(out)
+}
+
+/// The string of bytes an encoder produced, all ASCII.
+fn ascii(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).expect("encoders produce ASCII")
+}
+
+/// Decodes base64, ignoring whitespace so that multi-line presentation
+/// format can be passed as is.
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let text = text.as_bytes();
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+    let mut i = 0;
+    while i < text.len() {
+        // Between groups of four, whole blocks may go the fast way.
+        if bits == 0 && padding == 0 {
+            i += simd::base64_decode(&text[i..], &mut out);
+            if i == text.len() {
+                break;
+            }
+        }
+        let c = text[i];
+        i += 1;
+        if c.is_ascii_whitespace() {
+            continue;
+        }
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            _ => return Err(Error::BadChar),
+        };
+        if padding > 0 {
+            return Err(Error::BadLength);
+        }
+        acc = acc << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 6 || padding > 2 || acc & ((1 << bits) - 1) != 0 {
+        return Err(Error::BadLength);
+    }
+    Ok(out)
+}
+
+/// Encodes `data` as unpadded URL-safe base64.
+pub fn base64url_encode(data: &[u8]) -> String {
+    base64_encode(data)
+        .trim_end_matches('=')
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '/' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Decodes URL-safe base64, padded or not.
+pub fn base64url_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let standard = text
+        .chars()
+        .map(|c| match c {
+            '-' => Ok('+'),
+            '_' => Ok('/'),
+            '+' | '/' => Err(Error::BadChar),
+            c if c.is_ascii_whitespace() => Err(Error::BadChar),
+            c => Ok(c),
+        })
+        .collect::<Result<String, Error>>()?;
+    base64_decode(&standard)
+}
+
+/// Encodes `data` as lowercase hex.
+pub fn hex_encode(data: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let done = simd::hex_encode(data, &mut out);
+    for &b in &data[done..] {
+        out.push(DIGITS[usize::from(b >> 4)]);
+        out.push(DIGITS[usize::from(b & 0xf)]);
+    }
+    ascii(out)
+}
+
+/// Decodes hex in either case, ignoring whitespace.
+pub fn hex_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let text = text.as_bytes();
+    let mut out = Vec::with_capacity(text.len() / 2);
+    let mut high = None;
+    let mut i = 0;
+    while i < text.len() {
+        if high.is_none() {
+            i += simd::hex_decode(&text[i..], &mut out);
+            if i == text.len() {
+                break;
+            }
+        }
+        let c = text[i];
+        i += 1;
+        let v = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            c if c.is_ascii_whitespace() => continue,
+            _ => return Err(Error::BadChar),
+        };
+        match high.take() {
+            Some(h) => out.push(h << 4 | v),
+            None => high = Some(v),
+        }
+    }
+    if high.is_some() {
+        return Err(Error::BadLength);
+    }
+    Ok(out)
+}
+
+const BASE32HEX: &[u8; 32] = b"0123456789abcdefghijklmnopqrstuv";
+
+/// Encodes `data` as unpadded lowercase base32hex.
+pub fn base32hex_encode(data: &[u8]) -> String {
+    let mut out = Vec::with_capacity(data.len().div_ceil(5) * 8);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &b in data {
+        acc = acc << 8 | u32::from(b);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32HEX[(acc >> bits & 0x1f) as usize]);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32HEX[(acc << (5 - bits) & 0x1f) as usize]);
+    }
+    ascii(out)
+}
+
+/// Decodes unpadded base32hex in either case.
+pub fn base32hex_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let text = text.as_bytes();
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let done = simd::base32hex_decode(text, &mut out);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in &text[done..] {
+        let v = match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'v' => c - b'a' + 10,
+            b'A'..=b'V' => c - b'A' + 10,
+            _ => return Err(Error::BadChar),
+        };
+        acc = acc << 5 | u32::from(v);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || acc & ((1 << bits) - 1) != 0 {
+        return Err(Error::BadLength);
+    }
+    Ok(out)
+}
@@ -0,0 +1,542 @@
+//! A C ABI over the parser and the resolver, declared in
+//! `include/mairudns.h`.
+//!
+//! Build the shared library with
+//! `cargo rustc --release --lib --features ffi --crate-type cdylib`, or a
+//! static one with `--crate-type staticlib`. Every function is prefixed
+//! `mairu_`, and those that can fail return one of the `MAIRU_*` status
+//! codes, which [`mairu_strerror`] describes.
+//!
+//! Strings cross the boundary as NUL-terminated UTF-8. Text is written
+//! into buffers the caller supplies; when one is too small the call fails
+//! with [`MAIRU_ERR_BUFFER`] and stores the size needed, terminator
+//! included, where the caller asked for it. Decoded messages are trees of
+//! C structs allocated here, freed with [`mairu_message_free`]; everything
+//! they point to lives as long as they do. A resolver is an opaque handle
+//! that may be shared between threads. A panic aborts the process rather
+//! than unwind into C.
+
+use std::ffi::{CStr, CString};
+use std::net::{IpAddr, SocketAddr};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+use std::time::Duration;
+
+use crate::addr;
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::resolver::{self, Resolver, ResolverConfig};
+use crate::rr::{Record, RecordType};
+use crate::wire::{Decoder, Encoder};
+
+/// The call succeeded.
+pub const MAIRU_OK: c_int = 0;
+/// A required pointer is null or a string is not UTF-8.
+pub const MAIRU_ERR_ARGUMENT: c_int = -1;
+/// The input does not parse.
+pub const MAIRU_ERR_PARSE: c_int = -2;
+/// The output buffer is too small.
+pub const MAIRU_ERR_BUFFER: c_int = -3;
+/// No server answered in time.
+pub const MAIRU_ERR_TIMEOUT: c_int = -4;
+/// The query failed otherwise.
+pub const MAIRU_ERR_RESOLVE: c_int = -5;
+
+/// Describes status code `code` in a static string.
+#[no_mangle]
+pub extern "C" fn mairu_strerror(code: c_int) -> *const c_char {
+    let text: &'static [u8] = match code {
+        MAIRU_OK => b"success\0",
+        MAIRU_ERR_ARGUMENT => b"invalid argument\0",
+        MAIRU_ERR_PARSE => b"parse error\0",
+        MAIRU_ERR_BUFFER => b"buffer too small\0",
+        MAIRU_ERR_TIMEOUT => b"timed out\0",
+        MAIRU_ERR_RESOLVE => b"resolution failed\0",
+        _ => b"unknown error\0",
+    };
+    text.as_ptr() as *const c_char
+}
+
+/// The UTF-8 string at `text`, if it is one.
+unsafe fn str_arg<'a>(text: *const c_char) -> Option<&'a str> {
+    if text.is_null() {
+        return None;
+    }
+    CStr::from_ptr(text).to_str().ok()
+}
+
+/// Copies `text` and a terminator into `out`, or stores the size needed
+/// in `needed` if it does not fit.
+unsafe fn write_str(text: &str, out: *mut c_char, out_len: usize, needed: *mut usize) -> c_int {
+    let len = text.len() + 1;
+    if !needed.is_null() {
+        *needed = len;
+    }
+    if out.is_null() || out_len < len {
+        return MAIRU_ERR_BUFFER;
+    }
+    ptr::copy_nonoverlapping(text.as_ptr(), out as *mut u8, text.len());
+    *out.add(text.len()) = 0;
+    MAIRU_OK
+}
+
+/// Parses the domain name `text` and writes it back in canonical
+/// presentation form, fully qualified and with escapes where needed.
+///
+/// # Safety
+///
+/// `text` must be null or a NUL-terminated string, `out` null or valid for
+/// `out_len` bytes, and `needed` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_name_format(
+    text: *const c_char,
+    out: *mut c_char,
+    out_len: usize,
+    needed: *mut usize,
+) -> c_int {
+    let text = match str_arg(text) {
+        Some(text) => text,
+        None => return MAIRU_ERR_ARGUMENT,
+    };
+    match text.parse::<DomainName>() {
+        Ok(name) => write_str(&name.to_string(), out, out_len, needed),
+        Err(_) => MAIRU_ERR_PARSE,
+    }
+}
+
+/// Encodes the domain name `text` in uncompressed wire form into `out`,
+/// storing its length in `written`.
+///
+/// # Safety
+///
+/// `text` must be null or a NUL-terminated string, `out` null or valid for
+/// `out_len` bytes, and `written` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_name_to_wire(
+    text: *const c_char,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> c_int {
+    let name = match str_arg(text).map(str::parse::<DomainName>) {
+        Some(Ok(name)) => name,
+        Some(Err(_)) => return MAIRU_ERR_PARSE,
+        None => return MAIRU_ERR_ARGUMENT,
+    };
+    let mut enc = Encoder::uncompressed();
+    enc.name(&name, false);
+    let wire = enc.into_bytes();
+    if !written.is_null() {
+        *written = wire.len();
+    }
+    if out.is_null() || out_len < wire.len() {
+        return MAIRU_ERR_BUFFER;
+    }
+    ptr::copy_nonoverlapping(wire.as_ptr(), out, wire.len());
+    MAIRU_OK
+}
+
+/// Decodes the uncompressed wire-form name in the `len` bytes at `wire`
+/// and writes it in presentation form.
+///
+/// # Safety
+///
+/// `wire` must be null or valid for `len` bytes, `out` null or valid for
+/// `out_len` bytes, and `needed` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_name_from_wire(
+    wire: *const u8,
+    len: usize,
+    out: *mut c_char,
+    out_len: usize,
+    needed: *mut usize,
+) -> c_int {
+    if wire.is_null() {
+        return MAIRU_ERR_ARGUMENT;
+    }
+    let mut dec = Decoder::new(slice::from_raw_parts(wire, len));
+    match dec.name() {
+        Ok(name) if dec.remaining() == 0 => write_str(&name.to_string(), out, out_len, needed),
+        _ => MAIRU_ERR_PARSE,
+    }
+}
+
+/// An IP address: `family` is 4 or 6, and an IPv4 address takes the
+/// first four bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MairuAddr {
+    pub family: u8,
+    pub bytes: [u8; 16],
+}
+
+impl MairuAddr {
+    fn new(ip: IpAddr) -> MairuAddr {
+        let mut addr = MairuAddr::default();
+        match ip {
+            IpAddr::V4(v4) => {
+                addr.family = 4;
+                addr.bytes[..4].copy_from_slice(&v4.octets());
+            }
+            IpAddr::V6(v6) => {
+                addr.family = 6;
+                addr.bytes = v6.octets();
+            }
+        }
+        addr
+    }
+
+    fn ip(&self) -> Option<IpAddr> {
+        match self.family {
+            4 => Some(IpAddr::from([
+                self.bytes[0],
+                self.bytes[1],
+                self.bytes[2],
+                self.bytes[3],
+            ])),
+            6 => Some(IpAddr::from(self.bytes)),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the IPv4 or IPv6 address `text` into `out`.
+///
+/// # Safety
+///
+/// `text` must be null or a NUL-terminated string and `out` null or valid
+/// for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_addr_parse(text: *const c_char, out: *mut MairuAddr) -> c_int {
+    let text = match str_arg(text) {
+        Some(text) if !out.is_null() => text,
+        _ => return MAIRU_ERR_ARGUMENT,
+    };
+    match text.parse::<IpAddr>() {
+        Ok(ip) => {
+            *out = MairuAddr::new(ip);
+            MAIRU_OK
+        }
+        Err(_) => MAIRU_ERR_PARSE,
+    }
+}
+
+/// Writes the address at `addr` in text form, IPv6 as RFC 5952 gives it.
+///
+/// # Safety
+///
+/// `addr` must be null or valid for a read, `out` null or valid for
+/// `out_len` bytes, and `needed` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_addr_format(
+    addr: *const MairuAddr,
+    out: *mut c_char,
+    out_len: usize,
+    needed: *mut usize,
+) -> c_int {
+    match addr.as_ref().and_then(MairuAddr::ip) {
+        Some(ip) => write_str(&ip.to_string(), out, out_len, needed),
+        None => MAIRU_ERR_ARGUMENT,
+    }
+}
+
+/// Writes the reverse-mapping name of the address at `addr`, under
+/// in-addr.arpa or ip6.arpa.
+///
+/// # Safety
+///
+/// As for [`mairu_addr_format`].
+#[no_mangle]
+pub unsafe extern "C" fn mairu_addr_reverse_name(
+    addr: *const MairuAddr,
+    out: *mut c_char,
+    out_len: usize,
+    needed: *mut usize,
+) -> c_int {
+    match addr.as_ref().and_then(MairuAddr::ip) {
+        Some(ip) => write_str(&addr::reverse_name(ip).to_string(), out, out_len, needed),
+        None => MAIRU_ERR_ARGUMENT,
+    }
+}
+
+/// A question of a decoded message.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MairuQuestion {
+    pub name: *const c_char,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// A record of a decoded message. The data is given both in uncompressed
+/// wire form and in presentation form.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MairuRecord {
+    pub name: *const c_char,
+    pub rtype: u16,
+    pub rclass: u16,
+    pub ttl: u32,
+    pub rdata: *const u8,
+    pub rdata_len: usize,
+    pub rdata_text: *const c_char,
+}
+
+/// A decoded message. `flags` holds the header flags as on the wire, and
+/// `rcode` the full RCODE, extended bits included. The EDNS fields are
+/// meaningful only when `has_edns` is set.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MairuMessage {
+    pub id: u16,
+    pub flags: u16,
+    pub opcode: u8,
+    pub rcode: u16,
+    pub has_edns: u8,
+    pub edns_version: u8,
+    pub dnssec_ok: u8,
+    pub udp_size: u16,
+    pub questions: *const MairuQuestion,
+    pub question_count: usize,
+    pub answers: *const MairuRecord,
+    pub answer_count: usize,
+    pub authority: *const MairuRecord,
+    pub authority_count: usize,
+    pub additional: *const MairuRecord,
+    pub additional_count: usize,
+}
+
+/// A [`MairuMessage`] with the storage it points into. The message comes
+/// first so that a pointer to it is a pointer to the whole.
+#[repr(C)]
+struct OwnedMessage {
+    message: MairuMessage,
+    questions: Vec<MairuQuestion>,
+    sections: [Vec<MairuRecord>; 3],
+    strings: Vec<CString>,
+    rdata: Vec<Vec<u8>>,
+}
+
+impl OwnedMessage {
+    fn new(msg: &Message) -> Box<OwnedMessage> {
+        let mut owned = OwnedMessage {
+            message: MairuMessage {
+                id: msg.header.id,
+                flags: msg.header.flags(),
+                opcode: msg.header.opcode.0,
+                rcode: msg.header.rcode.0,
+                has_edns: msg.edns.is_some() as u8,
+                edns_version: msg.edns.as_ref().map_or(0, |e| e.version),
+                dnssec_ok: msg.edns.as_ref().map_or(0, |e| e.dnssec_ok as u8),
+                udp_size: msg.edns.as_ref().map_or(0, |e| e.udp_size),
+                questions: ptr::null(),
+                question_count: 0,
+                answers: ptr::null(),
+                answer_count: 0,
+                authority: ptr::null(),
+                authority_count: 0,
+                additional: ptr::null(),
+                additional_count: 0,
+            },
+            questions: Vec::new(),
+            sections: [Vec::new(), Vec::new(), Vec::new()],
+            strings: Vec::new(),
+            rdata: Vec::new(),
+        };
+        for q in &msg.questions {
+            let name = owned.string(q.name.to_string());
+            owned.questions.push(MairuQuestion {
+                name,
+                qtype: q.qtype.0,
+                qclass: q.qclass.0,
+            });
+        }
+        let sections = [&msg.answers, &msg.authority, &msg.additional];
+        for (i, records) in sections.iter().enumerate() {
+            for rr in records.iter() {
+                let record = owned.record(rr);
+                owned.sections[i].push(record);
+            }
+        }
+        let m = &mut owned.message;
+        m.questions = owned.questions.as_ptr();
+        m.question_count = owned.questions.len();
+        m.answers = owned.sections[0].as_ptr();
+        m.answer_count = owned.sections[0].len();
+        m.authority = owned.sections[1].as_ptr();
+        m.authority_count = owned.sections[1].len();
+        m.additional = owned.sections[2].as_ptr();
+        m.additional_count = owned.sections[2].len();
+        Box::new(owned)
+    }
+
+    /// Keeps `text` for the life of the message. Presentation forms escape
+    /// NUL bytes, so none is ever cut short.
+    fn string(&mut self, text: String) -> *const c_char {
+        let text = CString::new(text).unwrap_or_default();
+        let p = text.as_ptr();
+        self.strings.push(text);
+        p
+    }
+
+    fn record(&mut self, rr: &Record) -> MairuRecord {
+        let mut enc = Encoder::uncompressed();
+        // Data that decoded always encodes again.
+        let _ = rr.rdata.encode(&mut enc);
+        let data = enc.into_bytes();
+        let record = MairuRecord {
+            name: self.string(rr.name.to_string()),
+            rtype: rr.rtype().0,
+            rclass: rr.class.0,
+            ttl: rr.ttl,
+            rdata: data.as_ptr(),
+            rdata_len: data.len(),
+            rdata_text: self.string(rr.rdata.to_string()),
+        };
+        self.rdata.push(data);
+        record
+    }
+
+    fn into_raw(self: Box<OwnedMessage>) -> *mut MairuMessage {
+        Box::into_raw(self) as *mut MairuMessage
+    }
+}
+
+/// Decodes the `len`-byte message at `wire` and stores it in `out`, to be
+/// freed with [`mairu_message_free`].
+///
+/// # Safety
+///
+/// `wire` must be null or valid for `len` bytes and `out` null or valid
+/// for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_message_decode(
+    wire: *const u8,
+    len: usize,
+    out: *mut *mut MairuMessage,
+) -> c_int {
+    if wire.is_null() || out.is_null() {
+        return MAIRU_ERR_ARGUMENT;
+    }
+    match Message::from_wire(slice::from_raw_parts(wire, len)) {
+        Ok(msg) => {
+            *out = OwnedMessage::new(&msg).into_raw();
+            MAIRU_OK
+        }
+        Err(_) => MAIRU_ERR_PARSE,
+    }
+}
+
+/// Frees a message from [`mairu_message_decode`] or [`mairu_resolve`].
+///
+/// # Safety
+///
+/// `message` must be null or a message from this library not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_message_free(message: *mut MairuMessage) {
+    if !message.is_null() {
+        drop(Box::from_raw(message as *mut OwnedMessage));
+    }
+}
+
+/// An opaque resolver handle.
+pub struct MairuResolver {
+    resolver: Resolver,
+}
+
+/// Creates a resolver querying the `count` servers in `servers`, each an
+/// IP address with an optional port (`192.0.2.1`, `[2001:db8::1]:5353`),
+/// or the system's configured servers if `count` is zero. `timeout_ms`
+/// bounds each exchange with one server; zero keeps the default. Returns
+/// null if a server does not parse or the system configuration cannot be
+/// read.
+///
+/// # Safety
+///
+/// `servers` must be valid for `count` pointers to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_resolver_new(
+    servers: *const *const c_char,
+    count: usize,
+    timeout_ms: u32,
+) -> *mut MairuResolver {
+    let mut config = if count == 0 {
+        match ResolverConfig::system() {
+            Ok(config) => config,
+            Err(_) => return ptr::null_mut(),
+        }
+    } else if servers.is_null() {
+        return ptr::null_mut();
+    } else {
+        let mut config = ResolverConfig::default();
+        for &server in slice::from_raw_parts(servers, count) {
+            let text = match str_arg(server) {
+                Some(text) => text,
+                None => return ptr::null_mut(),
+            };
+            let addr = match text.parse::<SocketAddr>() {
+                Ok(addr) => addr,
+                Err(_) => match text.parse::<IpAddr>() {
+                    Ok(ip) => SocketAddr::new(ip, 53),
+                    Err(_) => return ptr::null_mut(),
+                },
+            };
+            config.servers.push(addr);
+        }
+        config
+    };
+    if timeout_ms > 0 {
+        config.timeout = Duration::from_millis(u64::from(timeout_ms));
+    }
+    Box::into_raw(Box::new(MairuResolver {
+        resolver: Resolver::new(config),
+    }))
+}
+
+/// Frees a resolver.
+///
+/// # Safety
+///
+/// `resolver` must be null or a resolver from [`mairu_resolver_new`] not
+/// yet freed, and no call may be using it.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_resolver_free(resolver: *mut MairuResolver) {
+    if !resolver.is_null() {
+        drop(Box::from_raw(resolver));
+    }
+}
+
+/// Queries `name` for records of type `qtype`, blocking until a server
+/// answers, and stores the response in `out`, to be freed with
+/// [`mairu_message_free`]. A response with an error RCODE such as
+/// NXDOMAIN is a success; its `rcode` tells.
+///
+/// # Safety
+///
+/// `resolver` must be null or a live resolver, `name` null or a
+/// NUL-terminated string, and `out` null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn mairu_resolve(
+    resolver: *const MairuResolver,
+    name: *const c_char,
+    qtype: u16,
+    out: *mut *mut MairuMessage,
+) -> c_int {
+    let (resolver, name) = match (resolver.as_ref(), str_arg(name)) {
+        (Some(resolver), Some(name)) if !out.is_null() => (resolver, name),
+        _ => return MAIRU_ERR_ARGUMENT,
+    };
+    let name = match name.parse::<DomainName>() {
+        Ok(name) => name,
+        Err(_) => return MAIRU_ERR_PARSE,
+    };
+    match resolver.resolver.query(&name, RecordType(qtype)) {
+        Ok(resp) => {
+            *out = OwnedMessage::new(&resp).into_raw();
+            MAIRU_OK
+        }
+        Err(resolver::Error::Client(crate::client::Error::Timeout)) => MAIRU_ERR_TIMEOUT,
+        Err(_) => MAIRU_ERR_RESOLVE,
+    }
+}
@@ -0,0 +1,388 @@
+//! Internationalized domain names for display.
+//!
+//! Names travel in the DNS as A-labels, the `xn--` Punycode form of
+//! RFC 3492 and RFC 5891. [`to_unicode`] turns them back into what a user
+//! would type, for showing in a user interface or a log. Labels that are
+//! not valid A-labels, or that would decode to something invisible or
+//! unprintable, are shown as they are.
+//!
+//! A name shown in Unicode can look like another: `аpple` with a
+//! Cyrillic `а` is not `apple`. [`to_unicode_checked`] also rates how
+//! likely that is, after the security mechanisms of Unicode Technical
+//! Standard #39: a label mixing scripts that are not written together is
+//! a [`DisplayRisk::MixedScript`], and one every character of which looks
+//! like a Latin letter or digit but is not is a
+//! [`DisplayRisk::Confusable`]. The characters it knows to look like ASCII
+//! are the most common ones of UTS #39's `confusables.txt`, in the
+//! Cyrillic, Greek, Armenian and Latin blocks and the fullwidth forms;
+//! consumers that must be safe from every lookalike should show risky
+//! names as A-labels.
+
+use std::fmt;
+
+use crate::name::DomainName;
+
+/// The ACE prefix of A-labels (RFC 5890 §2.3.2.5).
+const ACE_PREFIX: &str = "xn--";
+
+// Punycode parameters (RFC 3492 §5).
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+/// Characters that look like an ASCII letter or digit, from UTS #39's
+/// `confusables.txt`, and what they look like. The fullwidth forms are
+/// handled apart.
+const CONFUSABLES: &[(char, char)] = &[
+    // Cyrillic.
+    ('\u{0430}', 'a'),
+    ('\u{0435}', 'e'),
+    ('\u{043e}', 'o'),
+    ('\u{0440}', 'p'),
+    ('\u{0441}', 'c'),
+    ('\u{0443}', 'y'),
+    ('\u{0445}', 'x'),
+    ('\u{0455}', 's'),
+    ('\u{0456}', 'i'),
+    ('\u{0458}', 'j'),
+    ('\u{04bb}', 'h'),
+    ('\u{04cf}', 'l'),
+    ('\u{04af}', 'y'),
+    ('\u{0501}', 'd'),
+    ('\u{051b}', 'q'),
+    ('\u{051d}', 'w'),
+    ('\u{0410}', 'a'),
+    ('\u{0412}', 'b'),
+    ('\u{0415}', 'e'),
+    ('\u{041a}', 'k'),
+    ('\u{041c}', 'm'),
+    ('\u{041d}', 'h'),
+    ('\u{041e}', 'o'),
+    ('\u{0420}', 'p'),
+    ('\u{0421}', 'c'),
+    ('\u{0422}', 't'),
+    ('\u{0425}', 'x'),
+    ('\u{0405}', 's'),
+    ('\u{0406}', 'i'),
+    ('\u{0408}', 'j'),
+    // Greek.
+    ('\u{03b1}', 'a'),
+    ('\u{03b9}', 'i'),
+    ('\u{03ba}', 'k'),
+    ('\u{03bd}', 'v'),
+    ('\u{03bf}', 'o'),
+    ('\u{03c1}', 'p'),
+    ('\u{03c5}', 'u'),
+    ('\u{03c7}', 'x'),
+    ('\u{03f2}', 'c'),
+    ('\u{03f3}', 'j'),
+    ('\u{0391}', 'a'),
+    ('\u{0392}', 'b'),
+    ('\u{0395}', 'e'),
+    ('\u{0396}', 'z'),
+    ('\u{0397}', 'h'),
+    ('\u{0399}', 'i'),
+    ('\u{039a}', 'k'),
+    ('\u{039c}', 'm'),
+    ('\u{039d}', 'n'),
+    ('\u{039f}', 'o'),
+    ('\u{03a1}', 'p'),
+    ('\u{03a4}', 't'),
+    ('\u{03a5}', 'y'),
+    ('\u{03a7}', 'x'),
+    // Armenian.
+    ('\u{0566}', 'q'),
+    ('\u{0570}', 'h'),
+    ('\u{0578}', 'n'),
+    ('\u{057d}', 'u'),
+    ('\u{0581}', 'g'),
+    ('\u{0585}', 'o'),
+    // Latin letters that are not the ASCII ones.
+    ('\u{0131}', 'i'),
+    ('\u{01c0}', 'l'),
+    ('\u{0237}', 'j'),
+    ('\u{0251}', 'a'),
+    ('\u{0261}', 'g'),
+    ('\u{0269}', 'i'),
+];
+
+/// How likely a name shown in Unicode is to be mistaken for another,
+/// least first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum DisplayRisk {
+    /// Nothing found to be wary of.
+    #[default]
+    Safe,
+    /// A label mixes scripts that are not written together, such as
+    /// Latin and Cyrillic.
+    MixedScript,
+    /// A label looks like an ASCII label it is not.
+    Confusable,
+}
+
+impl fmt::Display for DisplayRisk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisplayRisk::Safe => "safe",
+            DisplayRisk::MixedScript => "mixed-script",
+            DisplayRisk::Confusable => "confusable",
+        })
+    }
+}
+
+/// A name as shown to users, and how far it may be trusted to be the
+/// name it looks like.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnicodeName {
+    pub text: String,
+    /// The highest risk of any of its labels.
+    pub risk: DisplayRisk,
+}
+
+impl fmt::Display for UnicodeName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// `name` with its A-labels decoded, in presentation format otherwise.
+pub fn to_unicode(name: &DomainName) -> String {
+    to_unicode_checked(name).text
+}
+
+/// `name` with its A-labels decoded, and how likely it is to be mistaken
+/// for another name.
+pub fn to_unicode_checked(name: &DomainName) -> UnicodeName {
+    if name.is_root() {
+        return UnicodeName {
+            text: ".".into(),
+            risk: DisplayRisk::Safe,
+        };
+    }
+    let mut text = String::new();
+    let mut risk = DisplayRisk::Safe;
+    for label in name.labels() {
+        match decode_label(label) {
+            Some(unicode) => {
+                risk = risk.max(label_risk(&unicode));
+                text.push_str(&unicode);
+            }
+            None => {
+                let ascii = DomainName::from_labels([label.as_slice()])
+                    .map(|n| n.to_string())
+                    .unwrap_or_default();
+                text.push_str(ascii.trim_end_matches('.'));
+            }
+        }
+        text.push('.');
+    }
+    UnicodeName { text, risk }
+}
+
+/// The U-label `label` encodes, if it is an A-label that decodes to
+/// something printable and not all ASCII.
+fn decode_label(label: &[u8]) -> Option<String> {
+    let label = std::str::from_utf8(label).ok()?;
+    let prefix = label.get(..ACE_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(ACE_PREFIX) {
+        return None;
+    }
+    let decoded = punycode_decode(&label[ACE_PREFIX.len()..])?;
+    let printable = decoded.chars().all(|c| !c.is_control() && !is_invisible(c));
+    if decoded.is_ascii() || !printable {
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Format and other characters that show as nothing, so that a name
+/// holding one looks like one that does not.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00ad}'
+            | '\u{034f}'
+            | '\u{061c}'
+            | '\u{115f}'
+            | '\u{1160}'
+            | '\u{180e}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{3164}'
+            | '\u{feff}'
+            | '\u{ffa0}'
+    ) || c.is_whitespace()
+}
+
+/// Decodes Punycode (RFC 3492 §6.2), without the ACE prefix.
+pub fn punycode_decode(input: &str) -> Option<String> {
+    if !input.is_ascii() {
+        return None;
+    }
+    let (basic, extended) = match input.rfind('-') {
+        Some(at) => (&input[..at], &input[at + 1..]),
+        None => ("", input),
+    };
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.bytes().peekable();
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = if k <= bias {
+                TMIN
+            } else if k >= bias + TMAX {
+                TMAX
+            } else {
+                k - bias
+            };
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+fn decode_digit(c: u8) -> Option<u32> {
+    match c {
+        b'a'..=b'z' => Some(u32::from(c - b'a')),
+        b'A'..=b'Z' => Some(u32::from(c - b'A')),
+        b'0'..=b'9' => Some(u32::from(c - b'0') + 26),
+        _ => None,
+    }
+}
+
+/// The bias adaptation function (RFC 3492 §6.1).
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+/// What `text` looks like with every character this module knows to look
+/// like an ASCII letter or digit replaced by it, and ASCII letters
+/// lowercased: two labels with the same skeleton are hard to tell apart.
+pub fn skeleton(text: &str) -> String {
+    text.chars()
+        .map(|c| match confusable(c) {
+            Some(ascii) => ascii,
+            None => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// The ASCII character `c` looks like, if it is not one itself.
+fn confusable(c: char) -> Option<char> {
+    match c {
+        // Fullwidth digits and letters.
+        '\u{ff10}'..='\u{ff19}' => char::from_u32(c as u32 - 0xff10 + u32::from(b'0')),
+        '\u{ff21}'..='\u{ff3a}' => char::from_u32(c as u32 - 0xff21 + u32::from(b'a')),
+        '\u{ff41}'..='\u{ff5a}' => char::from_u32(c as u32 - 0xff41 + u32::from(b'a')),
+        _ => CONFUSABLES
+            .iter()
+            .find(|&&(from, _)| from == c)
+            .map(|&(_, to)| to),
+    }
+}
+
+/// The scripts this module tells apart (UAX #24), by block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Script {
+    /// Digits, punctuation and combining marks, at home in any script.
+    Common,
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Hangul,
+    Hiragana,
+    Katakana,
+    Han,
+    Other,
+}
+
+fn script(c: char) -> Script {
+    match c {
+        'a'..='z' | 'A'..='Z' => Script::Latin,
+        '\u{0000}'..='\u{007f}' => Script::Common,
+        '\u{00c0}'..='\u{024f}' | '\u{1e00}'..='\u{1eff}' => Script::Latin,
+        '\u{ff21}'..='\u{ff3a}' | '\u{ff41}'..='\u{ff5a}' => Script::Latin,
+        '\u{0250}'..='\u{02af}' => Script::Latin,
+        '\u{0300}'..='\u{036f}' | '\u{ff10}'..='\u{ff19}' => Script::Common,
+        '\u{0370}'..='\u{03ff}' | '\u{1f00}'..='\u{1fff}' => Script::Greek,
+        '\u{0400}'..='\u{052f}' => Script::Cyrillic,
+        '\u{0530}'..='\u{058f}' => Script::Armenian,
+        '\u{0590}'..='\u{05ff}' => Script::Hebrew,
+        '\u{0600}'..='\u{06ff}' | '\u{0750}'..='\u{077f}' => Script::Arabic,
+        '\u{0900}'..='\u{097f}' => Script::Devanagari,
+        '\u{0e00}'..='\u{0e7f}' => Script::Thai,
+        '\u{1100}'..='\u{11ff}' | '\u{3130}'..='\u{318f}' | '\u{ac00}'..='\u{d7af}' => {
+            Script::Hangul
+        }
+        '\u{3040}'..='\u{309f}' => Script::Hiragana,
+        '\u{30a0}'..='\u{30ff}' => Script::Katakana,
+        '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => Script::Han,
+        '\u{3000}'..='\u{303f}' => Script::Common,
+        _ => Script::Other,
+    }
+}
+
+/// The risk of showing the U-label `label`.
+fn label_risk(label: &str) -> DisplayRisk {
+    let skeleton = skeleton(label);
+    if skeleton.is_ascii() && skeleton != label.to_ascii_lowercase() {
+        return DisplayRisk::Confusable;
+    }
+    let mut scripts: Vec<Script> = Vec::new();
+    for script in label.chars().map(script) {
+        if script != Script::Common && !scripts.contains(&script) {
+            scripts.push(script);
+        }
+    }
+    // The script combinations UTS #39 §5.2 allows a highly restrictive
+    // label: one script, or Latin with the scripts of Chinese, Japanese
+    // or Korean.
+    let within = |allowed: &[Script]| scripts.iter().all(|s| allowed.contains(s));
+    let restricted = scripts.len() <= 1
+        || within(&[
+            Script::Latin,
+            Script::Han,
+            Script::Hiragana,
+            Script::Katakana,
+        ])
+        || within(&[Script::Latin, Script::Han, Script::Hangul]);
+    if restricted {
+        DisplayRisk::Safe
+    } else {
+        DisplayRisk::MixedScript
+    }
+}
@@ -0,0 +1,165 @@
+//! Conversions to and from NLnet Labs' `domain` crate.
+//!
+//! `domain` keeps names and messages in wire format over an octets
+//! buffer, so the conversions here use `Vec<u8>` as that buffer. Records
+//! convert to `Record<Name<Vec<u8>>, UnknownRecordData<Vec<u8>>>`, which
+//! holds any type's data uncompressed; parse it into a typed record with
+//! `domain`'s own machinery where needed.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use domain::base::iana::{Class, Rtype};
+use domain::base::{self, Name, Ttl, UnknownRecordData};
+
+use crate::message::Message;
+use crate::name::{self, DomainName};
+use crate::rr::{RData, Record, RecordClass, RecordType};
+use crate::wire::{self, Decoder, Encoder};
+
+/// A `domain` record carrying uncompressed data of any type.
+pub type DomainRecord = base::Record<Name<Vec<u8>>, UnknownRecordData<Vec<u8>>>;
+
+/// Errors converting between the two crates' types.
+#[derive(Debug)]
+pub enum Error {
+    /// This crate could not encode or decode the value.
+    Wire(wire::Error),
+    /// `domain` rejected the value.
+    Domain(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Wire(e) => e.fmt(f),
+            Error::Domain(e) => write!(f, "domain: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<wire::Error> for Error {
+    fn from(e: wire::Error) -> Error {
+        Error::Wire(e)
+    }
+}
+
+impl From<name::Error> for Error {
+    fn from(e: name::Error) -> Error {
+        // Names converted stand alone, so the error is in the name.
+        Error::Wire(wire::Error::BadName {
+            offset: 0,
+            error: e,
+        })
+    }
+}
+
+fn domain_err<E: fmt::Display>(e: E) -> Error {
+    Error::Domain(e.to_string())
+}
+
+impl From<&DomainName> for Name<Vec<u8>> {
+    fn from(name: &DomainName) -> Name<Vec<u8>> {
+        let mut enc = Encoder::uncompressed();
+        enc.name(name, false);
+        Name::from_octets(enc.into_bytes()).expect("valid names convert")
+    }
+}
+
+impl From<DomainName> for Name<Vec<u8>> {
+    fn from(name: DomainName) -> Name<Vec<u8>> {
+        Name::from(&name)
+    }
+}
+
+impl<O: AsRef<[u8]>> TryFrom<&Name<O>> for DomainName {
+    type Error = name::Error;
+
+    fn try_from(name: &Name<O>) -> Result<DomainName, name::Error> {
+        DomainName::from_labels(name.iter().filter(|l| !l.is_root()).map(|l| l.as_slice()))
+    }
+}
+
+impl TryFrom<&Record> for DomainRecord {
+    type Error = Error;
+
+    fn try_from(record: &Record) -> Result<DomainRecord, Error> {
+        let mut enc = Encoder::uncompressed();
+        record.rdata.encode(&mut enc)?;
+        let rtype = Rtype::from_int(record.rtype().0);
+        let data = UnknownRecordData::from_octets(rtype, enc.into_bytes()).map_err(domain_err)?;
+        Ok(base::Record::new(
+            Name::from(&record.name),
+            Class::from_int(record.class.0),
+            Ttl::from_secs(record.ttl),
+            data,
+        ))
+    }
+}
+
+impl TryFrom<Record> for DomainRecord {
+    type Error = Error;
+
+    fn try_from(record: Record) -> Result<DomainRecord, Error> {
+        <DomainRecord as TryFrom<&Record>>::try_from(&record)
+    }
+}
+
+impl TryFrom<&DomainRecord> for Record {
+    type Error = Error;
+
+    fn try_from(record: &DomainRecord) -> Result<Record, Error> {
+        let data = record.data().data();
+        let mut dec = Decoder::new(data);
+        let rtype = RecordType(record.rtype().to_int());
+        let rdata = RData::decode(rtype, &mut dec, data.len())?;
+        Ok(Record {
+            name: DomainName::try_from(record.owner())?,
+            class: RecordClass(record.class().to_int()),
+            ttl: record.ttl().as_secs(),
+            rdata,
+        })
+    }
+}
+
+impl TryFrom<DomainRecord> for Record {
+    type Error = Error;
+
+    fn try_from(record: DomainRecord) -> Result<Record, Error> {
+        Record::try_from(&record)
+    }
+}
+
+impl TryFrom<&Message> for base::Message<Vec<u8>> {
+    type Error = Error;
+
+    fn try_from(msg: &Message) -> Result<base::Message<Vec<u8>>, Error> {
+        base::Message::from_octets(msg.to_wire()?).map_err(domain_err)
+    }
+}
+
+impl TryFrom<Message> for base::Message<Vec<u8>> {
+    type Error = Error;
+
+    fn try_from(msg: Message) -> Result<base::Message<Vec<u8>>, Error> {
+        <base::Message<Vec<u8>> as TryFrom<&Message>>::try_from(&msg)
+    }
+}
+
+impl<O: AsRef<[u8]>> TryFrom<&base::Message<O>> for Message {
+    type Error = Error;
+
+    fn try_from(msg: &base::Message<O>) -> Result<Message, Error> {
+        Ok(Message::from_wire(msg.as_slice())?)
+    }
+}
+
+impl<O: AsRef<[u8]>> TryFrom<base::Message<O>> for Message {
+    type Error = Error;
+
+    fn try_from(msg: base::Message<O>) -> Result<Message, Error> {
+        Message::try_from(&msg)
+    }
+}
@@ -0,0 +1,152 @@
+//! Conversions to and from `hickory-proto`.
+//!
+//! `hickory_proto::rr::Record` has an inherent `try_from` that shadows the
+//! trait method; convert records with `.try_into()` or
+//! `TryFrom::try_from` instead.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use hickory_proto::error::ProtoError;
+use hickory_proto::op;
+use hickory_proto::rr::{self, Name};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+use crate::message::Message;
+use crate::name::{self, DomainName};
+use crate::rr::Record;
+use crate::wire::{self, Decoder, Encoder};
+
+/// Errors converting between the two crates' types.
+#[derive(Debug)]
+pub enum Error {
+    /// This crate could not encode or decode the value.
+    Wire(wire::Error),
+    /// `hickory-proto` could not encode or decode the value.
+    Proto(ProtoError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Wire(e) => e.fmt(f),
+            Error::Proto(e) => write!(f, "hickory-proto: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<wire::Error> for Error {
+    fn from(e: wire::Error) -> Error {
+        Error::Wire(e)
+    }
+}
+
+impl From<ProtoError> for Error {
+    fn from(e: ProtoError) -> Error {
+        Error::Proto(e)
+    }
+}
+
+impl From<&DomainName> for Name {
+    fn from(name: &DomainName) -> Name {
+        // Both sides enforce the same label and name length limits.
+        Name::from_labels(name.labels().iter().map(|l| l.as_slice())).expect("valid names convert")
+    }
+}
+
+impl From<DomainName> for Name {
+    fn from(name: DomainName) -> Name {
+        Name::from(&name)
+    }
+}
+
+impl TryFrom<&Name> for DomainName {
+    type Error = name::Error;
+
+    /// Relative hickory names are taken to be relative to the root.
+    fn try_from(name: &Name) -> Result<DomainName, name::Error> {
+        DomainName::from_labels(name.iter())
+    }
+}
+
+impl TryFrom<Name> for DomainName {
+    type Error = name::Error;
+
+    fn try_from(name: Name) -> Result<DomainName, name::Error> {
+        DomainName::try_from(&name)
+    }
+}
+
+impl TryFrom<&Record> for rr::Record {
+    type Error = Error;
+
+    fn try_from(record: &Record) -> Result<rr::Record, Error> {
+        let mut enc = Encoder::uncompressed();
+        record.encode(&mut enc)?;
+        Ok(rr::Record::from_bytes(enc.as_bytes())?)
+    }
+}
+
+impl TryFrom<Record> for rr::Record {
+    type Error = Error;
+
+    fn try_from(record: Record) -> Result<rr::Record, Error> {
+        <rr::Record as TryFrom<&Record>>::try_from(&record)
+    }
+}
+
+impl TryFrom<&rr::Record> for Record {
+    type Error = Error;
+
+    fn try_from(record: &rr::Record) -> Result<Record, Error> {
+        let wire = record.to_bytes()?;
+        let mut dec = Decoder::new(&wire);
+        let record = Record::decode(&mut dec)?;
+        if dec.remaining() != 0 {
+            return Err(Error::Wire(wire::Error::TrailingData { offset: dec.pos() }));
+        }
+        Ok(record)
+    }
+}
+
+impl TryFrom<rr::Record> for Record {
+    type Error = Error;
+
+    fn try_from(record: rr::Record) -> Result<Record, Error> {
+        Record::try_from(&record)
+    }
+}
+
+impl TryFrom<&Message> for op::Message {
+    type Error = Error;
+
+    fn try_from(msg: &Message) -> Result<op::Message, Error> {
+        Ok(op::Message::from_vec(&msg.to_wire()?)?)
+    }
+}
+
+impl TryFrom<Message> for op::Message {
+    type Error = Error;
+
+    fn try_from(msg: Message) -> Result<op::Message, Error> {
+        <op::Message as TryFrom<&Message>>::try_from(&msg)
+    }
+}
+
+impl TryFrom<&op::Message> for Message {
+    type Error = Error;
+
+    fn try_from(msg: &op::Message) -> Result<Message, Error> {
+        Ok(Message::from_wire(&msg.to_vec()?)?)
+    }
+}
+
+impl TryFrom<op::Message> for Message {
+    type Error = Error;
+
+    fn try_from(msg: op::Message) -> Result<Message, Error> {
+        Message::try_from(&msg)
+    }
+}
@@ -0,0 +1,19 @@
+//! Conversions between this crate's types and those of other DNS crates.
+//!
+//! Each submodule is behind a feature of the same name: `hickory` for
+//! `hickory-proto` and `domain` for NLnet Labs' `domain`. Names convert
+//! label by label; records and messages go through the wire format, so
+//! anything either side can encode survives the trip. With `serde`,
+//! names serialize in presentation format, and records, RRsets and zones
+//! as maps whose record data has a field for each part, described by the
+//! JSON Schema [`json_schema`] gives.
+
+#[cfg(feature = "domain")]
+pub mod domain;
+#[cfg(feature = "hickory")]
+pub mod hickory;
+#[cfg(feature = "serde")]
+mod serde;
+
+#[cfg(feature = "serde")]
+pub use self::serde::json_schema;
@@ -0,0 +1,641 @@
+//! Serialization of names, types, classes, records, RRsets and zones with
+//! serde.
+//!
+//! Names, types and classes are strings in presentation format. A record
+//! is a map of `name`, `ttl`, `class`, `type` and `data`, where `data` is
+//! an object whose fields the `type` decides:
+//!
+//! ```json
+//! {"name": "example.com.", "ttl": 300, "class": "IN", "type": "MX",
+//!  "data": {"preference": 10, "exchange": "mail.example.com."}}
+//! ```
+//!
+//! | Type                         | Fields of `data`                                            |
+//! |------------------------------|-------------------------------------------------------------|
+//! | `A`, `AAAA`                  | `address`                                                   |
+//! | `NS`, `CNAME`, `PTR`, `DNAME`| `target`                                                    |
+//! | `SOA`                        | `mname`, `rname`, `serial`, `refresh`, `retry`, `expire`, `minimum` |
+//! | `MX`                         | `preference`, `exchange`                                    |
+//! | `TXT`                        | `strings`, each in zone file escaping without the quotes    |
+//! | `SRV`                        | `priority`, `weight`, `port`, `target`                      |
+//! | any other                    | `hex`, the data in hexadecimal as RFC 3597 gives it          |
+//!
+//! An RRset is the same map with `data` a list, one object for each
+//! record, and a zone is a map of `origin`, `class` and `rrsets`. The
+//! class may be left out when deserializing and defaults to `IN`, and
+//! `data` may also be given as a string in zone file syntax, as it was
+//! before it had fields. [`json_schema`] describes all of this as a JSON
+//! Schema.
+
+use std::fmt::{self, Write};
+use std::net::IpAddr;
+
+use ::serde::de::{self, value::MapAccessDeserializer, Deserializer, MapAccess, Visitor};
+use ::serde::ser::{SerializeStruct, Serializer};
+use ::serde::{Deserialize, Serialize};
+
+use crate::encoding::{hex_decode, hex_encode};
+use crate::name::DomainName;
+use crate::rr::{RData, RRset, Record, RecordClass, RecordType, Soa};
+use crate::zone::{parse_records, Zone};
+
+/// Serializes with `Display` and deserializes with `FromStr`.
+macro_rules! as_string {
+    ($ty:ty, $what:expr) => {
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$ty, D::Error> {
+                let text = String::deserialize(deserializer)?;
+                text.parse().map_err(|_| {
+                    de::Error::custom(format!(concat!("invalid ", $what, " {:?}"), text))
+                })
+            }
+        }
+    };
+}
+
+as_string!(DomainName, "name");
+as_string!(RecordType, "record type");
+as_string!(RecordClass, "class");
+
+/// The fields record data may have; which ones it has depends on the
+/// type.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DataFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    address: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mname: Option<DomainName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rname: Option<DomainName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    serial: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expire: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    minimum: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    preference: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exchange: Option<DomainName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    strings: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weight: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<DomainName>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hex: Option<String>,
+}
+
+impl DataFields {
+    fn of(rdata: &RData) -> DataFields {
+        match rdata {
+            RData::A(a) => DataFields {
+                address: Some(IpAddr::V4(*a)),
+                ..DataFields::default()
+            },
+            RData::Aaaa(a) => DataFields {
+                address: Some(IpAddr::V6(*a)),
+                ..DataFields::default()
+            },
+            RData::Ns(n) | RData::Cname(n) | RData::Ptr(n) | RData::Dname(n) => DataFields {
+                target: Some(n.clone()),
+                ..DataFields::default()
+            },
+            RData::Soa(soa) => DataFields {
+                mname: Some(soa.mname.clone()),
+                rname: Some(soa.rname.clone()),
+                serial: Some(soa.serial),
+                refresh: Some(soa.refresh),
+                retry: Some(soa.retry),
+                expire: Some(soa.expire),
+                minimum: Some(soa.minimum),
+                ..DataFields::default()
+            },
+            RData::Mx {
+                preference,
+                exchange,
+            } => DataFields {
+                preference: Some(*preference),
+                exchange: Some(exchange.clone()),
+                ..DataFields::default()
+            },
+            RData::Txt(strings) => DataFields {
+                strings: Some(strings.iter().map(|s| escape(s)).collect()),
+                ..DataFields::default()
+            },
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => DataFields {
+                priority: Some(*priority),
+                weight: Some(*weight),
+                port: Some(*port),
+                target: Some(target.clone()),
+                ..DataFields::default()
+            },
+            RData::Unknown { data, .. } => DataFields {
+                hex: Some(hex_encode(data)),
+                ..DataFields::default()
+            },
+        }
+    }
+
+    /// The names of the fields given.
+    fn present(&self) -> Vec<&'static str> {
+        let fields = [
+            ("address", self.address.is_some()),
+            ("mname", self.mname.is_some()),
+            ("rname", self.rname.is_some()),
+            ("serial", self.serial.is_some()),
+            ("refresh", self.refresh.is_some()),
+            ("retry", self.retry.is_some()),
+            ("expire", self.expire.is_some()),
+            ("minimum", self.minimum.is_some()),
+            ("preference", self.preference.is_some()),
+            ("exchange", self.exchange.is_some()),
+            ("strings", self.strings.is_some()),
+            ("priority", self.priority.is_some()),
+            ("weight", self.weight.is_some()),
+            ("port", self.port.is_some()),
+            ("target", self.target.is_some()),
+            ("hex", self.hex.is_some()),
+        ];
+        fields
+            .iter()
+            .filter(|(_, given)| *given)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    /// The data of a record of type `rtype` with these fields.
+    fn into_rdata(self, rtype: RecordType) -> Result<RData, String> {
+        let given = self.present();
+        let rdata = self.build(rtype)?;
+        let expected = DataFields::of(&rdata).present();
+        match given.iter().find(|f| !expected.contains(f)) {
+            Some(field) => Err(format!("{} data has no field `{}`", rtype, field)),
+            None => Ok(rdata),
+        }
+    }
+
+    fn build(self, rtype: RecordType) -> Result<RData, String> {
+        fn need<T>(field: Option<T>, name: &str, rtype: RecordType) -> Result<T, String> {
+            field.ok_or_else(|| format!("{} data needs field `{}`", rtype, name))
+        }
+        Ok(match rtype {
+            RecordType::A => match need(self.address, "address", rtype)? {
+                IpAddr::V4(a) => RData::A(a),
+                IpAddr::V6(a) => return Err(format!("{} is not an IPv4 address", a)),
+            },
+            RecordType::AAAA => match need(self.address, "address", rtype)? {
+                IpAddr::V6(a) => RData::Aaaa(a),
+                IpAddr::V4(a) => return Err(format!("{} is not an IPv6 address", a)),
+            },
+            RecordType::NS => RData::Ns(need(self.target, "target", rtype)?),
+            RecordType::CNAME => RData::Cname(need(self.target, "target", rtype)?),
+            RecordType::PTR => RData::Ptr(need(self.target, "target", rtype)?),
+            RecordType::DNAME => RData::Dname(need(self.target, "target", rtype)?),
+            RecordType::SOA => RData::Soa(Soa {
+                mname: need(self.mname, "mname", rtype)?,
+                rname: need(self.rname, "rname", rtype)?,
+                serial: need(self.serial, "serial", rtype)?,
+                refresh: need(self.refresh, "refresh", rtype)?,
+                retry: need(self.retry, "retry", rtype)?,
+                expire: need(self.expire, "expire", rtype)?,
+                minimum: need(self.minimum, "minimum", rtype)?,
+            }),
+            RecordType::MX => RData::Mx {
+                preference: need(self.preference, "preference", rtype)?,
+                exchange: need(self.exchange, "exchange", rtype)?,
+            },
+            RecordType::TXT => RData::Txt(
+                need(self.strings, "strings", rtype)?
+                    .iter()
+                    .map(|s| unescape(s))
+                    .collect::<Result<_, _>>()?,
+            ),
+            RecordType::SRV => RData::Srv {
+                priority: need(self.priority, "priority", rtype)?,
+                weight: need(self.weight, "weight", rtype)?,
+                port: need(self.port, "port", rtype)?,
+                target: need(self.target, "target", rtype)?,
+            },
+            _ => {
+                let hex = need(self.hex, "hex", rtype)?;
+                RData::Unknown {
+                    rtype,
+                    data: hex_decode(&hex).map_err(|e| format!("hex {:?}: {}", hex, e))?,
+                }
+            }
+        })
+    }
+}
+
+/// A character-string in zone file escaping, without the quotes.
+fn escape(s: &[u8]) -> String {
+    let mut text = String::with_capacity(s.len());
+    for &c in s {
+        match c {
+            b'"' | b'\\' => {
+                text.push('\\');
+                text.push(c as char);
+            }
+            c if !(b' '..0x7f).contains(&c) => {
+                let _ = write!(text, "\\{:03}", c);
+            }
+            c => text.push(c as char),
+        }
+    }
+    text
+}
+
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let digits = bytes
+            .get(i + 1..i + 4)
+            .filter(|d| d.iter().all(u8::is_ascii_digit));
+        match (digits, bytes.get(i + 1)) {
+            (Some(d), _) => {
+                let value = d.iter().fold(0u32, |v, c| v * 10 + u32::from(c - b'0'));
+                if value > 255 {
+                    return Err(format!("bad escape in {:?}", text));
+                }
+                out.push(value as u8);
+                i += 4;
+            }
+            (None, Some(&c)) => {
+                out.push(c);
+                i += 2;
+            }
+            (None, None) => return Err(format!("dangling backslash in {:?}", text)),
+        }
+    }
+    if out.len() > 255 {
+        return Err(format!("{:?} is longer than 255 bytes", text));
+    }
+    Ok(out)
+}
+
+/// Record data as given: fields, or zone file text.
+enum Data {
+    Fields(DataFields),
+    Text(String),
+}
+
+impl<'de> Deserialize<'de> for Data {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Data, D::Error> {
+        struct DataVisitor;
+
+        impl<'de> Visitor<'de> for DataVisitor {
+            type Value = Data;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("record data as an object or a string")
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<Data, E> {
+                Ok(Data::Text(text.to_string()))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<Data, M::Error> {
+                DataFields::deserialize(MapAccessDeserializer::new(map)).map(Data::Fields)
+            }
+        }
+
+        deserializer.deserialize_any(DataVisitor)
+    }
+}
+
+impl Data {
+    fn into_rdata(
+        self,
+        name: &DomainName,
+        class: RecordClass,
+        rtype: RecordType,
+    ) -> Result<RData, String> {
+        let text = match self {
+            Data::Fields(fields) => return fields.into_rdata(rtype),
+            Data::Text(text) => text,
+        };
+        // The text is read by the zone file parser, as one line.
+        let line = format!("{} 0 {} {} {}", name, class, rtype, text);
+        let mut records = parse_records(&line, &DomainName::root()).map_err(|e| e.to_string())?;
+        match (records.pop(), records.is_empty()) {
+            (Some(rr), true) if rr.rtype() == rtype => Ok(rr.rdata),
+            _ => Err(format!("invalid record data {:?}", text)),
+        }
+    }
+}
+
+fn default_class() -> RecordClass {
+    RecordClass::IN
+}
+
+impl Serialize for Record {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Record", 5)?;
+        s.serialize_field("name", &self.name)?;
+        s.serialize_field("ttl", &self.ttl)?;
+        s.serialize_field("class", &self.class)?;
+        s.serialize_field("type", &self.rtype())?;
+        s.serialize_field("data", &DataFields::of(&self.rdata))?;
+        s.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordFields {
+    name: DomainName,
+    ttl: u32,
+    #[serde(default = "default_class")]
+    class: RecordClass,
+    #[serde(rename = "type")]
+    rtype: RecordType,
+    data: Data,
+}
+
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Record, D::Error> {
+        let f = RecordFields::deserialize(deserializer)?;
+        let rdata = f
+            .data
+            .into_rdata(&f.name, f.class, f.rtype)
+            .map_err(de::Error::custom)?;
+        Ok(Record {
+            name: f.name,
+            class: f.class,
+            ttl: f.ttl,
+            rdata,
+        })
+    }
+}
+
+impl Serialize for RRset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let data: Vec<DataFields> = self.rdata.iter().map(DataFields::of).collect();
+        let mut s = serializer.serialize_struct("RRset", 5)?;
+        s.serialize_field("name", &self.name)?;
+        s.serialize_field("ttl", &self.ttl)?;
+        s.serialize_field("class", &self.class)?;
+        s.serialize_field("type", &self.rtype)?;
+        s.serialize_field("data", &data)?;
+        s.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RRsetFields {
+    name: DomainName,
+    ttl: u32,
+    #[serde(default = "default_class")]
+    class: RecordClass,
+    #[serde(rename = "type")]
+    rtype: RecordType,
+    data: Vec<Data>,
+}
+
+impl<'de> Deserialize<'de> for RRset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RRset, D::Error> {
+        let RRsetFields {
+            name,
+            ttl,
+            class,
+            rtype,
+            data,
+        } = RRsetFields::deserialize(deserializer)?;
+        let rdata = data
+            .into_iter()
+            .map(|data| data.into_rdata(&name, class, rtype))
+            .collect::<Result<_, _>>()
+            .map_err(de::Error::custom)?;
+        Ok(RRset {
+            name,
+            class,
+            rtype,
+            ttl,
+            rdata,
+        })
+    }
+}
+
+impl Serialize for Zone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut records: Vec<Record> = self.records().cloned().collect();
+        // A stable order, so that equal zones serialize the same.
+        records.sort_by(|a, b| (&a.name, a.rtype()).cmp(&(&b.name, b.rtype())));
+        let mut s = serializer.serialize_struct("Zone", 3)?;
+        s.serialize_field("origin", self.origin())?;
+        s.serialize_field("class", &self.class())?;
+        s.serialize_field("rrsets", &RRset::group(&records))?;
+        s.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ZoneFields {
+    origin: DomainName,
+    #[serde(default = "default_class")]
+    class: RecordClass,
+    rrsets: Vec<RRset>,
+}
+
+impl<'de> Deserialize<'de> for Zone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Zone, D::Error> {
+        let f = ZoneFields::deserialize(deserializer)?;
+        let mut zone = Zone::with_class(f.origin, f.class);
+        for rr in f.rrsets.iter().flat_map(RRset::records) {
+            zone.insert(rr.clone())
+                .map_err(|e| de::Error::custom(format!("{}: {}", rr, e)))?;
+        }
+        Ok(zone)
+    }
+}
+
+const NAME: &str = r##"{"$ref": "#/$defs/name"}"##;
+const U16: &str = r#"{"type": "integer", "minimum": 0, "maximum": 65535}"#;
+const U32: &str = r##"{"$ref": "#/$defs/u32"}"##;
+
+/// The fields of each type's data, as JSON Schema, for the types with
+/// fields of their own.
+const DATA_SCHEMAS: &[(RecordType, &[(&str, &str)])] = &[
+    (
+        RecordType::A,
+        &[("address", r#"{"type": "string", "format": "ipv4"}"#)],
+    ),
+    (
+        RecordType::AAAA,
+        &[("address", r#"{"type": "string", "format": "ipv6"}"#)],
+    ),
+    (RecordType::NS, &[("target", NAME)]),
+    (RecordType::CNAME, &[("target", NAME)]),
+    (RecordType::PTR, &[("target", NAME)]),
+    (RecordType::DNAME, &[("target", NAME)]),
+    (
+        RecordType::SOA,
+        &[
+            ("mname", NAME),
+            ("rname", NAME),
+            ("serial", U32),
+            ("refresh", U32),
+            ("retry", U32),
+            ("expire", U32),
+            ("minimum", U32),
+        ],
+    ),
+    (RecordType::MX, &[("preference", U16), ("exchange", NAME)]),
+    (
+        RecordType::TXT,
+        &[(
+            "strings",
+            r#"{"type": "array", "items": {"type": "string"}, "minItems": 1}"#,
+        )],
+    ),
+    (
+        RecordType::SRV,
+        &[
+            ("priority", U16),
+            ("weight", U16),
+            ("port", U16),
+            ("target", NAME),
+        ],
+    ),
+];
+
+/// A JSON Schema (draft 2020-12) for the serde form of records, RRsets
+/// and zones, for checking documents against, or generating code from.
+/// The document itself describes a zone; records and RRsets are under
+/// `$defs`, as `#/$defs/record` and `#/$defs/rrset`.
+pub fn json_schema() -> String {
+    let mut defs = String::new();
+    let mut data_types = Vec::new();
+    for (rtype, fields) in DATA_SCHEMAS {
+        let properties: Vec<String> = fields
+            .iter()
+            .map(|(name, schema)| format!("\"{}\": {}", name, schema))
+            .collect();
+        let required: Vec<String> = fields
+            .iter()
+            .map(|(name, _)| format!("\"{}\"", name))
+            .collect();
+        let _ = writeln!(
+            defs,
+            "    \"data-{}\": {{\"type\": \"object\", \"properties\": {{{}}}, \"required\": [{}], \"additionalProperties\": false}},",
+            rtype,
+            properties.join(", "),
+            required.join(", ")
+        );
+        data_types.push(format!("\"{}\"", rtype));
+    }
+    format!(
+        r##"{{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "Zone",
+  "$ref": "#/$defs/zone",
+  "$defs": {{
+    "name": {{"type": "string", "description": "An absolute domain name in presentation format."}},
+    "type": {{"type": "string", "description": "A record type mnemonic, or TYPEn."}},
+    "class": {{"type": "string", "description": "A class mnemonic, or CLASSn.", "default": "IN"}},
+    "u32": {{"type": "integer", "minimum": 0, "maximum": 4294967295}},
+{defs}    "data-other": {{"type": "object", "description": "Data of a type without fields of its own (RFC 3597).", "properties": {{"hex": {{"type": "string", "pattern": "^([0-9A-Fa-f]{{2}})*$"}}}}, "required": ["hex"], "additionalProperties": false}},
+    "data": {{"description": "Record data; the fields depend on the type it is given with.", "type": "object"}},
+    "record": {{
+      "type": "object",
+      "properties": {{
+        "name": {{"$ref": "#/$defs/name"}},
+        "ttl": {{"$ref": "#/$defs/u32"}},
+        "class": {{"$ref": "#/$defs/class"}},
+        "type": {{"$ref": "#/$defs/type"}},
+        "data": {{"$ref": "#/$defs/data"}}
+      }},
+      "required": ["name", "ttl", "type", "data"],
+      "additionalProperties": false,
+      "allOf": [{record_choices}]
+    }},
+    "rrset": {{
+      "type": "object",
+      "properties": {{
+        "name": {{"$ref": "#/$defs/name"}},
+        "ttl": {{"$ref": "#/$defs/u32"}},
+        "class": {{"$ref": "#/$defs/class"}},
+        "type": {{"$ref": "#/$defs/type"}},
+        "data": {{"type": "array", "items": {{"$ref": "#/$defs/data"}}}}
+      }},
+      "required": ["name", "ttl", "type", "data"],
+      "additionalProperties": false,
+      "allOf": [{rrset_choices}]
+    }},
+    "zone": {{
+      "type": "object",
+      "properties": {{
+        "origin": {{"$ref": "#/$defs/name"}},
+        "class": {{"$ref": "#/$defs/class"}},
+        "rrsets": {{"type": "array", "items": {{"$ref": "#/$defs/rrset"}}}}
+      }},
+      "required": ["origin", "rrsets"],
+      "additionalProperties": false
+    }}
+  }}
+}}
+"##,
+        defs = defs,
+        record_choices = data_choices(&data_types, false),
+        rrset_choices = data_choices(&data_types, true),
+    )
+}
+
+/// The schemas tying `data` to the type beside it, one for each of
+/// `types` and one for the rest; `data` is a list of them in an RRset.
+fn data_choices(types: &[String], rrset: bool) -> String {
+    let data = |def: &str| {
+        let one = format!("{{\"$ref\": \"#/$defs/{}\"}}", def);
+        match rrset {
+            true => format!("{{\"type\": \"array\", \"items\": {}}}", one),
+            false => one,
+        }
+    };
+    let mut choices = String::new();
+    for rtype in types {
+        let _ = write!(
+            choices,
+            "{{\"if\": {{\"properties\": {{\"type\": {{\"const\": {}}}}}}}, \"then\": {{\"properties\": {{\"data\": {}}}}}}}, ",
+            rtype,
+            data(&format!("data-{}", rtype.trim_matches('"')))
+        );
+    }
+    let _ = write!(
+        choices,
+        "{{\"if\": {{\"properties\": {{\"type\": {{\"not\": {{\"enum\": [{}]}}}}}}}}, \"then\": {{\"properties\": {{\"data\": {}}}}}}}",
+        types.join(", "),
+        data("data-other")
+    );
+    choices
+}
@@ -0,0 +1,45 @@
+//! A DNS library: wire codec, resolver and server building blocks.
+
+pub mod addr;
+pub mod arena;
+pub mod cache;
+pub mod client;
+pub mod clock;
+pub mod compliance;
+pub mod config;
+#[cfg(feature = "dnscrypt")]
+pub mod dnscrypt;
+#[cfg(feature = "dnssec")]
+pub mod dnssec;
+pub mod dso;
+pub mod encoding;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod idn;
+pub mod interop;
+pub mod llmnr;
+pub mod mail;
+pub mod mdns;
+pub mod message;
+pub mod metrics;
+pub mod name;
+pub mod netbios;
+pub mod policy;
+pub mod push;
+pub mod querylog;
+pub mod recursor;
+pub mod resolver;
+pub mod rr;
+pub mod server;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tools;
+pub mod tsig;
+pub mod wire;
+pub mod zone;
+
+mod crypto;
+mod der;
+mod random;
+mod simd;
+mod sys;
@@ -0,0 +1,91 @@
+//! Link-Local Multicast Name Resolution (RFC 4795), sender side.
+//!
+//! LLMNR uses the DNS message format, sent to a link-local multicast group
+//! on port 5355. Responders answer by unicast, so a single ephemeral socket
+//! per address family is enough to query.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::client::is_response_to;
+use crate::message::{Header, Message, Question, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{Record, RecordType};
+
+/// The LLMNR port.
+pub const PORT: u16 = 5355;
+
+/// The IPv4 LLMNR group.
+pub const GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 252);
+
+/// The IPv6 LLMNR group.
+pub const GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 1, 3);
+
+/// How long to wait for responses (`LLMNR_TIMEOUT`, §7).
+pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Builds an LLMNR query. Unlike DNS, LLMNR queries carry no RD bit and
+/// no EDNS.
+pub fn query_message(name: &DomainName, qtype: RecordType) -> Message {
+    Message {
+        header: Header {
+            id: random::u16(),
+            ..Header::default()
+        },
+        questions: vec![Question::new(name.clone(), qtype)],
+        ..Message::default()
+    }
+}
+
+/// Multicasts a query for `name`/`qtype` on IPv4 and, where available,
+/// IPv6, returning the answers of the first positive response. Responses
+/// flagged with the conflict bit are ignored.
+pub fn query(name: &DomainName, qtype: RecordType, timeout: Duration) -> io::Result<Vec<Record>> {
+    let query = query_message(name, qtype);
+    let wire = query
+        .to_wire()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut sockets = Vec::new();
+    let v4 = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    v4.set_multicast_ttl_v4(1)?;
+    v4.send_to(&wire, (GROUP_V4, PORT))?;
+    sockets.push(v4);
+    // IPv6 is best-effort: hosts without it still resolve over IPv4.
+    if let Ok(v6) = UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)) {
+        if v6
+            .send_to(&wire, SocketAddr::from((GROUP_V6, PORT)))
+            .is_ok()
+        {
+            sockets.push(v6);
+        }
+    }
+    for socket in &sockets {
+        socket.set_nonblocking(true)?;
+    }
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 9000];
+    while Instant::now() < deadline {
+        for socket in &sockets {
+            match socket.recv_from(&mut buf) {
+                Ok((n, _)) => {
+                    if let Ok(resp) = Message::from_wire(&buf[..n]) {
+                        // The AA bit position is the conflict bit in LLMNR.
+                        if is_response_to(&query, &resp)
+                            && !resp.header.aa
+                            && resp.header.rcode == Rcode::NOERROR
+                            && !resp.answers.is_empty()
+                        {
+                            return Ok(resp.answers);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    Ok(Vec::new())
+}
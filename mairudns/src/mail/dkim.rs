@@ -0,0 +1,99 @@
+//! DKIM public key records (RFC 6376 §3.6.1).
+
+use std::str::FromStr;
+
+use crate::encoding;
+use crate::name::DomainName;
+use crate::resolver::Resolver;
+
+use super::{single_txt, split_list, syntax, tag_list, MailError};
+
+/// A DKIM key record, as published at `<selector>._domainkey.<domain>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DkimKey {
+    /// The hash algorithms signatures may use, all if empty.
+    pub hash_algorithms: Vec<String>,
+    /// `rsa` unless the record says otherwise, as for `ed25519` keys.
+    pub key_type: String,
+    pub notes: Option<String>,
+    /// The public key, empty once it has been revoked.
+    pub public_key: Vec<u8>,
+    /// The services the key is for, `*` for all.
+    pub service_types: Vec<String>,
+    /// The `t=` flags: `y` while the domain is testing DKIM, `s` if
+    /// signing identities must be the domain itself, not subdomains.
+    pub flags: Vec<String>,
+}
+
+impl DkimKey {
+    pub fn is_revoked(&self) -> bool {
+        self.public_key.is_empty()
+    }
+
+    pub fn is_testing(&self) -> bool {
+        self.flags.iter().any(|f| f == "y")
+    }
+
+    /// Whether the key may sign for subdomains of the domain.
+    pub fn allows_subdomains(&self) -> bool {
+        !self.flags.iter().any(|f| f == "s")
+    }
+}
+
+impl FromStr for DkimKey {
+    type Err = MailError;
+
+    fn from_str(text: &str) -> Result<DkimKey, MailError> {
+        let tags = tag_list(text)?;
+        let mut key = DkimKey {
+            hash_algorithms: Vec::new(),
+            key_type: "rsa".to_string(),
+            notes: None,
+            public_key: Vec::new(),
+            service_types: vec!["*".to_string()],
+            flags: Vec::new(),
+        };
+        let mut has_key = false;
+        for (i, (tag, value)) in tags.into_iter().enumerate() {
+            match tag.as_str() {
+                "v" if i > 0 => return syntax("v= must come first"),
+                "v" if value != "DKIM1" => return syntax(format!("unknown version {:?}", value)),
+                "v" => {}
+                "h" => key.hash_algorithms = split_list(&value, ':'),
+                "k" => key.key_type = value.to_ascii_lowercase(),
+                "n" => key.notes = Some(value),
+                "p" => {
+                    // Long keys are often folded with whitespace.
+                    let value: String = value.split_whitespace().collect();
+                    key.public_key = match encoding::base64_decode(&value) {
+                        Ok(bytes) => bytes,
+                        Err(e) => return syntax(format!("public key: {}", e)),
+                    };
+                    has_key = true;
+                }
+                "s" => key.service_types = split_list(&value, ':'),
+                "t" => key.flags = split_list(&value, ':'),
+                // Unknown tags are ignored (RFC 6376 §3.6.1).
+                _ => {}
+            }
+        }
+        if !has_key {
+            return syntax("no p= tag");
+        }
+        Ok(key)
+    }
+}
+
+/// Looks up the key `selector` names for `domain`.
+pub fn lookup_dkim(
+    resolver: &Resolver,
+    selector: &str,
+    domain: &DomainName,
+) -> Result<DkimKey, MailError> {
+    let name = match DomainName::parse_relative(&format!("{}._domainkey", selector), domain) {
+        Ok(name) => name,
+        Err(e) => return syntax(format!("selector {:?}: {}", selector, e)),
+    };
+    // Records are matched on any text: the v= tag is optional.
+    single_txt(resolver, &name, "")?.parse()
+}
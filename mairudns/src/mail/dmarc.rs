@@ -0,0 +1,171 @@
+//! DMARC policy records (RFC 7489 §6.3).
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::name::DomainName;
+use crate::resolver::Resolver;
+
+use super::{single_txt, split_list, syntax, tag_list, MailError};
+
+/// What receivers are asked to do with mail that fails DMARC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disposition {
+    None,
+    Quarantine,
+    Reject,
+}
+
+impl FromStr for Disposition {
+    type Err = MailError;
+
+    fn from_str(s: &str) -> Result<Disposition, MailError> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Disposition::None),
+            "quarantine" => Ok(Disposition::Quarantine),
+            "reject" => Ok(Disposition::Reject),
+            _ => syntax(format!("unknown policy {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Disposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Disposition::None => "none",
+            Disposition::Quarantine => "quarantine",
+            Disposition::Reject => "reject",
+        })
+    }
+}
+
+/// How closely the DKIM or SPF domain must match the From domain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    /// The organizational domains must match.
+    Relaxed,
+    /// The domains must be identical.
+    Strict,
+}
+
+impl FromStr for Alignment {
+    type Err = MailError;
+
+    fn from_str(s: &str) -> Result<Alignment, MailError> {
+        match s.to_ascii_lowercase().as_str() {
+            "r" => Ok(Alignment::Relaxed),
+            "s" => Ok(Alignment::Strict),
+            _ => syntax(format!("unknown alignment {:?}", s)),
+        }
+    }
+}
+
+/// A DMARC policy, as published at `_dmarc.<domain>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DmarcPolicy {
+    pub policy: Disposition,
+    /// The policy for subdomains; the same as `policy` unless given.
+    pub subdomain_policy: Disposition,
+    /// The percentage of failing mail the policy applies to.
+    pub percent: u8,
+    /// Where aggregate reports go.
+    pub rua: Vec<String>,
+    /// Where failure reports go.
+    pub ruf: Vec<String>,
+    pub adkim: Alignment,
+    pub aspf: Alignment,
+    /// The `fo=` options: when failure reports are wanted.
+    pub failure_options: Vec<String>,
+    /// Seconds between aggregate reports.
+    pub report_interval: u32,
+    pub report_formats: Vec<String>,
+}
+
+impl FromStr for DmarcPolicy {
+    type Err = MailError;
+
+    fn from_str(text: &str) -> Result<DmarcPolicy, MailError> {
+        let tags = tag_list(text)?;
+        match tags.first() {
+            Some((tag, value)) if tag == "v" && value == "DMARC1" => {}
+            _ => return syntax("v=DMARC1 must come first"),
+        }
+        let mut policy = None;
+        let mut subdomain_policy = None;
+        let mut parsed = DmarcPolicy {
+            policy: Disposition::None,
+            subdomain_policy: Disposition::None,
+            percent: 100,
+            rua: Vec::new(),
+            ruf: Vec::new(),
+            adkim: Alignment::Relaxed,
+            aspf: Alignment::Relaxed,
+            failure_options: vec!["0".to_string()],
+            report_interval: 86400,
+            report_formats: vec!["afrf".to_string()],
+        };
+        for (tag, value) in tags.into_iter().skip(1) {
+            match tag.as_str() {
+                "p" => policy = Some(value.parse()?),
+                "sp" => subdomain_policy = Some(value.parse()?),
+                "pct" => {
+                    parsed.percent = match value.parse() {
+                        Ok(pct) if pct <= 100 => pct,
+                        _ => return syntax(format!("pct={} is not 0-100", value)),
+                    }
+                }
+                "rua" => parsed.rua = split_list(&value, ','),
+                "ruf" => parsed.ruf = split_list(&value, ','),
+                "adkim" => parsed.adkim = value.parse()?,
+                "aspf" => parsed.aspf = value.parse()?,
+                "fo" => parsed.failure_options = split_list(&value, ':'),
+                "ri" => {
+                    parsed.report_interval = match value.parse() {
+                        Ok(ri) => ri,
+                        Err(_) => return syntax(format!("ri={} is not a number", value)),
+                    }
+                }
+                "rf" => parsed.report_formats = split_list(&value, ':'),
+                "v" => return syntax("v= must come first"),
+                // Unknown tags are ignored (RFC 7489 §6.3).
+                _ => {}
+            }
+        }
+        parsed.policy = match policy {
+            Some(p) => p,
+            None => return syntax("no p= tag"),
+        };
+        parsed.subdomain_policy = subdomain_policy.unwrap_or(parsed.policy);
+        Ok(parsed)
+    }
+}
+
+/// Finds the DMARC policy that applies to mail from `domain`, with the
+/// name it was found at.
+///
+/// Without a public suffix list the organizational domain is not known,
+/// so `_dmarc` is tried at `domain` and then at each of its ancestors
+/// short of the top-level domain, the first record found winning. This
+/// is the tree walk later DMARC drafts replace the suffix list with.
+pub fn lookup_dmarc(
+    resolver: &Resolver,
+    domain: &DomainName,
+) -> Result<(DomainName, DmarcPolicy), MailError> {
+    let mut at = domain.clone();
+    while at.label_count() >= 2 {
+        let name = match at.prepend(b"_dmarc") {
+            Ok(name) => name,
+            Err(e) => return syntax(format!("{}: {}", at, e)),
+        };
+        match single_txt(resolver, &name, "v=DMARC1") {
+            Ok(text) => return Ok((at, text.parse()?)),
+            Err(MailError::Missing) => {}
+            Err(e) => return Err(e),
+        }
+        at = match at.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    Err(MailError::Missing)
+}
@@ -0,0 +1,134 @@
+//! Mail authentication policies published in the DNS.
+//!
+//! [`SpfRecord`] parses sender policies (RFC 7208) and [`check_spf`]
+//! follows their includes and redirects, counting the DNS lookups an
+//! evaluation would take against the limits. [`DkimKey`] parses DKIM key
+//! records (RFC 6376), [`DmarcPolicy`] DMARC policies (RFC 7489), and
+//! [`MtaStsRecord`] and [`MtaStsPolicy`] the two halves of MTA-STS
+//! (RFC 8461): the TXT record that announces a policy and the policy file
+//! served over HTTPS, which is left to the caller to fetch.
+
+mod dkim;
+mod dmarc;
+mod mta_sts;
+mod spf;
+
+pub use self::dkim::{lookup_dkim, DkimKey};
+pub use self::dmarc::{lookup_dmarc, Alignment, Disposition, DmarcPolicy};
+pub use self::mta_sts::{
+    lookup_mta_sts, policy_url, MtaStsMode, MtaStsPolicy, MtaStsRecord, POLICY_HOST,
+};
+pub use self::spf::{
+    check_spf, Directive, Mechanism, Qualifier, SpfCheck, SpfProblem, SpfRecord, MAX_SPF_LOOKUPS,
+    MAX_VOID_LOOKUPS,
+};
+
+use std::fmt;
+
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::resolver::{self, Resolver};
+use crate::rr::{RData, RecordType};
+
+/// Errors from looking up or parsing a mail policy record.
+#[derive(Debug)]
+pub enum MailError {
+    Resolver(resolver::Error),
+    /// There is no record of the kind at the name.
+    Missing,
+    /// There is more than one record where only one is allowed.
+    Multiple,
+    /// The record does not parse; holds why.
+    Syntax(String),
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailError::Resolver(e) => write!(f, "{}", e),
+            MailError::Missing => f.write_str("no record"),
+            MailError::Multiple => f.write_str("more than one record"),
+            MailError::Syntax(why) => write!(f, "invalid record: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<resolver::Error> for MailError {
+    fn from(e: resolver::Error) -> MailError {
+        MailError::Resolver(e)
+    }
+}
+
+fn syntax<T>(why: impl Into<String>) -> Result<T, MailError> {
+    Err(MailError::Syntax(why.into()))
+}
+
+/// The TXT records at `name` whose text, its strings joined, starts with
+/// `prefix`, ignoring case. A name that does not exist has none.
+fn txt_records(
+    resolver: &Resolver,
+    name: &DomainName,
+    prefix: &str,
+) -> Result<Vec<String>, MailError> {
+    let records = match resolver.lookup(name, RecordType::TXT) {
+        Ok(records) => records,
+        Err(resolver::Error::Rcode(Rcode::NXDOMAIN)) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(records
+        .into_iter()
+        .filter_map(|rr| match rr.rdata {
+            RData::Txt(strings) => Some(String::from_utf8_lossy(&strings.concat()).into_owned()),
+            _ => None,
+        })
+        .filter(|text| {
+            text.get(..prefix.len())
+                .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+        })
+        .collect())
+}
+
+/// The one record of [`txt_records`].
+fn single_txt(resolver: &Resolver, name: &DomainName, prefix: &str) -> Result<String, MailError> {
+    let mut records = txt_records(resolver, name, prefix)?;
+    match records.len() {
+        0 => Err(MailError::Missing),
+        1 => Ok(records.remove(0)),
+        _ => Err(MailError::Multiple),
+    }
+}
+
+/// Splits a `tag=value; tag=value` list, as DKIM, DMARC and MTA-STS
+/// records use, into lowercased tags and trimmed values. A tag may not
+/// appear twice.
+fn tag_list(text: &str) -> Result<Vec<(String, String)>, MailError> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for item in text.split(';') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let (tag, value) = match item.split_once('=') {
+            Some(split) => split,
+            None => return syntax(format!("{:?} is not tag=value", item)),
+        };
+        let tag = tag.trim().to_ascii_lowercase();
+        if tags.iter().any(|(t, _)| *t == tag) {
+            return syntax(format!("tag {} appears twice", tag));
+        }
+        tags.push((tag, value.trim().to_string()));
+    }
+    Ok(tags)
+}
+
+/// The items of a list split at `separator`, trimmed, without empty ones.
+fn split_list(value: &str, separator: char) -> Vec<String> {
+    value
+        .split(separator)
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,167 @@
+//! MTA-STS (RFC 8461): the `_mta-sts` TXT record and the policy file it
+//! announces.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::name::DomainName;
+use crate::resolver::Resolver;
+
+use super::{single_txt, syntax, tag_list, MailError};
+
+/// The host the policy file is served from, under the policy domain.
+pub const POLICY_HOST: &str = "mta-sts";
+
+/// The largest `max_age` a policy may have (RFC 8461 §3.2).
+const MAX_AGE_LIMIT: u64 = 31_557_600;
+
+/// The TXT record at `_mta-sts.<domain>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtaStsRecord {
+    /// Changes whenever the policy does, so senders know to refetch it.
+    pub id: String,
+}
+
+impl FromStr for MtaStsRecord {
+    type Err = MailError;
+
+    fn from_str(text: &str) -> Result<MtaStsRecord, MailError> {
+        let tags = tag_list(text)?;
+        match tags.first() {
+            Some((tag, value)) if tag == "v" && value == "STSv1" => {}
+            _ => return syntax("v=STSv1 must come first"),
+        }
+        let id = match tags.iter().find(|(tag, _)| tag == "id") {
+            Some((_, id)) => id,
+            None => return syntax("no id= tag"),
+        };
+        if id.is_empty() || id.len() > 32 || !id.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return syntax(format!("id {:?} is not 1-32 letters and digits", id));
+        }
+        Ok(MtaStsRecord { id: id.clone() })
+    }
+}
+
+/// Looks up the MTA-STS record of `domain`.
+pub fn lookup_mta_sts(resolver: &Resolver, domain: &DomainName) -> Result<MtaStsRecord, MailError> {
+    let name = match domain.prepend(b"_mta-sts") {
+        Ok(name) => name,
+        Err(e) => return syntax(format!("{}: {}", domain, e)),
+    };
+    single_txt(resolver, &name, "v=STSv1")?.parse()
+}
+
+/// Where the policy of `domain` is served.
+pub fn policy_url(domain: &DomainName) -> String {
+    let domain = domain.to_string();
+    format!(
+        "https://{}.{}/.well-known/mta-sts.txt",
+        POLICY_HOST,
+        domain.trim_end_matches('.')
+    )
+}
+
+/// How senders are to treat delivery that fails policy checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MtaStsMode {
+    /// Do not deliver.
+    Enforce,
+    /// Deliver, but report the failure.
+    Testing,
+    /// There is no policy.
+    None,
+}
+
+impl fmt::Display for MtaStsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MtaStsMode::Enforce => "enforce",
+            MtaStsMode::Testing => "testing",
+            MtaStsMode::None => "none",
+        })
+    }
+}
+
+/// A policy file, as fetched from [`policy_url`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtaStsPolicy {
+    pub mode: MtaStsMode,
+    /// The MX host patterns, possibly with a leading `*.` wildcard.
+    pub mx: Vec<String>,
+    pub max_age: Duration,
+}
+
+impl MtaStsPolicy {
+    /// Whether `host` matches one of the policy's MX patterns; a `*.`
+    /// wildcard stands for exactly one label.
+    pub fn matches_mx(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        self.mx
+            .iter()
+            .any(|pattern| match pattern.strip_prefix("*.") {
+                Some(suffix) => host.split_once('.').is_some_and(|(label, rest)| {
+                    !label.is_empty() && rest.eq_ignore_ascii_case(suffix)
+                }),
+                None => host.eq_ignore_ascii_case(pattern),
+            })
+    }
+}
+
+impl FromStr for MtaStsPolicy {
+    type Err = MailError;
+
+    /// Parses the `key: value` lines of a policy file.
+    fn from_str(text: &str) -> Result<MtaStsPolicy, MailError> {
+        let mut version = false;
+        let mut mode = None;
+        let mut mx = Vec::new();
+        let mut max_age = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => return syntax(format!("{:?} is not key: value", line)),
+            };
+            match key {
+                "version" if value == "STSv1" => version = true,
+                "version" => return syntax(format!("unknown version {:?}", value)),
+                "mode" => {
+                    mode = Some(match value {
+                        "enforce" => MtaStsMode::Enforce,
+                        "testing" => MtaStsMode::Testing,
+                        "none" => MtaStsMode::None,
+                        _ => return syntax(format!("unknown mode {:?}", value)),
+                    })
+                }
+                "mx" => mx.push(value.to_string()),
+                "max_age" => {
+                    max_age = match value.parse::<u64>() {
+                        Ok(secs) if secs <= MAX_AGE_LIMIT => Some(Duration::from_secs(secs)),
+                        _ => return syntax(format!("max_age {:?} is out of range", value)),
+                    }
+                }
+                // Unknown keys are ignored (RFC 8461 §3.2).
+                _ => {}
+            }
+        }
+        if !version {
+            return syntax("no version: STSv1");
+        }
+        let mode = match mode {
+            Some(mode) => mode,
+            None => return syntax("no mode"),
+        };
+        let max_age = match max_age {
+            Some(max_age) => max_age,
+            None => return syntax("no max_age"),
+        };
+        if mx.is_empty() && mode != MtaStsMode::None {
+            return syntax("no mx");
+        }
+        Ok(MtaStsPolicy { mode, mx, max_age })
+    }
+}
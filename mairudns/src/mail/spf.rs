@@ -0,0 +1,476 @@
+//! Sender Policy Framework records (RFC 7208).
+
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::name::DomainName;
+use crate::resolver::Resolver;
+
+use super::{syntax, txt_records, MailError};
+
+/// Terms that cause DNS lookups an evaluation may reach at most, across
+/// includes and redirects (RFC 7208 §4.6.4).
+pub const MAX_SPF_LOOKUPS: usize = 10;
+
+/// Lookups an evaluation may make that find nothing (RFC 7208 §4.6.4).
+pub const MAX_VOID_LOOKUPS: usize = 2;
+
+/// What a matching mechanism makes of the sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Qualifier {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+}
+
+impl Qualifier {
+    fn symbol(self) -> char {
+        match self {
+            Qualifier::Pass => '+',
+            Qualifier::Fail => '-',
+            Qualifier::SoftFail => '~',
+            Qualifier::Neutral => '?',
+        }
+    }
+}
+
+/// A mechanism: what a sender is matched against. Domain specifications
+/// are kept as written, as they may hold macros that only expand when a
+/// message is checked.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Mechanism {
+    All,
+    Include(String),
+    A {
+        domain: Option<String>,
+        v4_prefix: Option<u8>,
+        v6_prefix: Option<u8>,
+    },
+    Mx {
+        domain: Option<String>,
+        v4_prefix: Option<u8>,
+        v6_prefix: Option<u8>,
+    },
+    Ptr(Option<String>),
+    Ip4 {
+        addr: Ipv4Addr,
+        prefix: u8,
+    },
+    Ip6 {
+        addr: Ipv6Addr,
+        prefix: u8,
+    },
+    Exists(String),
+}
+
+impl Mechanism {
+    /// Whether evaluating the mechanism takes a DNS lookup.
+    pub fn needs_lookup(&self) -> bool {
+        !matches!(
+            self,
+            Mechanism::All | Mechanism::Ip4 { .. } | Mechanism::Ip6 { .. }
+        )
+    }
+}
+
+fn write_cidr(f: &mut fmt::Formatter<'_>, v4: Option<u8>, v6: Option<u8>) -> fmt::Result {
+    if let Some(len) = v4 {
+        write!(f, "/{}", len)?;
+    }
+    if let Some(len) = v6 {
+        write!(f, "//{}", len)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Mechanism {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mechanism::All => f.write_str("all"),
+            Mechanism::Include(domain) => write!(f, "include:{}", domain),
+            Mechanism::A {
+                domain,
+                v4_prefix,
+                v6_prefix,
+            }
+            | Mechanism::Mx {
+                domain,
+                v4_prefix,
+                v6_prefix,
+            } => {
+                f.write_str(match self {
+                    Mechanism::A { .. } => "a",
+                    _ => "mx",
+                })?;
+                if let Some(domain) = domain {
+                    write!(f, ":{}", domain)?;
+                }
+                write_cidr(f, *v4_prefix, *v6_prefix)
+            }
+            Mechanism::Ptr(None) => f.write_str("ptr"),
+            Mechanism::Ptr(Some(domain)) => write!(f, "ptr:{}", domain),
+            Mechanism::Ip4 { addr, prefix } => write!(f, "ip4:{}/{}", addr, prefix),
+            Mechanism::Ip6 { addr, prefix } => write!(f, "ip6:{}/{}", addr, prefix),
+            Mechanism::Exists(domain) => write!(f, "exists:{}", domain),
+        }
+    }
+}
+
+/// A mechanism with its qualifier.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Directive {
+    pub qualifier: Qualifier,
+    pub mechanism: Mechanism,
+}
+
+impl fmt::Display for Directive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.qualifier != Qualifier::Pass {
+            write!(f, "{}", self.qualifier.symbol())?;
+        }
+        write!(f, "{}", self.mechanism)
+    }
+}
+
+/// A parsed SPF record.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpfRecord {
+    pub directives: Vec<Directive>,
+    /// The domain whose record applies if no directive matches.
+    pub redirect: Option<String>,
+    /// The domain whose TXT record explains a failure.
+    pub explanation: Option<String>,
+    /// Modifiers this crate does not know, kept as name and value.
+    pub other_modifiers: Vec<(String, String)>,
+}
+
+impl SpfRecord {
+    /// The redirect, unless an `all` mechanism makes it unreachable
+    /// (RFC 7208 §6.1).
+    pub fn effective_redirect(&self) -> Option<&str> {
+        let has_all = self
+            .directives
+            .iter()
+            .any(|d| d.mechanism == Mechanism::All);
+        self.redirect.as_deref().filter(|_| !has_all)
+    }
+
+    /// Terms of this record alone that take a DNS lookup.
+    pub fn lookups(&self) -> usize {
+        let mechanisms = self
+            .directives
+            .iter()
+            .filter(|d| d.mechanism.needs_lookup())
+            .count();
+        mechanisms + usize::from(self.effective_redirect().is_some())
+    }
+}
+
+/// A target domain with its IPv4 and IPv6 prefix lengths.
+type DomainSpec = (Option<String>, Option<u8>, Option<u8>);
+
+/// The optional `:domain` and `/len//len` after `a` and `mx`.
+fn domain_and_cidr(rest: &str) -> Result<DomainSpec, MailError> {
+    let (domain, cidr) = match rest.strip_prefix(':') {
+        Some(spec) => match spec.find('/') {
+            Some(at) => (Some(spec[..at].to_string()), &spec[at..]),
+            None => (Some(spec.to_string()), ""),
+        },
+        None => (None, rest),
+    };
+    if domain.as_deref() == Some("") {
+        return syntax("empty domain");
+    }
+    let prefix = |text: &str, max: u8| match text.parse::<u8>() {
+        Ok(len) if len <= max => Ok(len),
+        _ => syntax(format!("invalid prefix length {:?}", text)),
+    };
+    let (v4, v6) = match cidr {
+        "" => (None, None),
+        _ => match cidr.strip_prefix("//") {
+            Some(v6) => (None, Some(prefix(v6, 128)?)),
+            None => {
+                let cidr = &cidr[1..];
+                match cidr.split_once("//") {
+                    Some((v4, v6)) => (Some(prefix(v4, 32)?), Some(prefix(v6, 128)?)),
+                    None => (Some(prefix(cidr, 32)?), None),
+                }
+            }
+        },
+    };
+    Ok((domain, v4, v6))
+}
+
+/// An address with an optional prefix length, the full length by default.
+fn network<A: FromStr>(text: &str, max: u8) -> Result<(A, u8), MailError> {
+    let (addr, len) = match text.split_once('/') {
+        Some((addr, len)) => (addr, Some(len)),
+        None => (text, None),
+    };
+    let addr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(_) => return syntax(format!("invalid address {:?}", addr)),
+    };
+    let prefix = match len.map(str::parse::<u8>) {
+        None => max,
+        Some(Ok(len)) if len <= max => len,
+        Some(_) => return syntax(format!("invalid network {:?}", text)),
+    };
+    Ok((addr, prefix))
+}
+
+fn mechanism(term: &str) -> Result<Mechanism, MailError> {
+    let split = term.find([':', '/']).unwrap_or(term.len());
+    let (name, rest) = term.split_at(split);
+    let spec = || match rest.strip_prefix(':') {
+        Some(spec) if !spec.is_empty() => Ok(spec.to_string()),
+        _ => syntax(format!("{} needs a domain", name)),
+    };
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "all" if rest.is_empty() => Mechanism::All,
+        "include" => Mechanism::Include(spec()?),
+        "exists" => Mechanism::Exists(spec()?),
+        "ptr" if rest.is_empty() => Mechanism::Ptr(None),
+        "ptr" => Mechanism::Ptr(Some(spec()?)),
+        "a" => {
+            let (domain, v4_prefix, v6_prefix) = domain_and_cidr(rest)?;
+            Mechanism::A {
+                domain,
+                v4_prefix,
+                v6_prefix,
+            }
+        }
+        "mx" => {
+            let (domain, v4_prefix, v6_prefix) = domain_and_cidr(rest)?;
+            Mechanism::Mx {
+                domain,
+                v4_prefix,
+                v6_prefix,
+            }
+        }
+        "ip4" => {
+            let (addr, prefix) = network(&spec()?, 32)?;
+            Mechanism::Ip4 { addr, prefix }
+        }
+        "ip6" => {
+            let (addr, prefix) = network(&spec()?, 128)?;
+            Mechanism::Ip6 { addr, prefix }
+        }
+        _ => return syntax(format!("unknown mechanism {:?}", term)),
+    })
+}
+
+impl FromStr for SpfRecord {
+    type Err = MailError;
+
+    fn from_str(text: &str) -> Result<SpfRecord, MailError> {
+        let mut terms = text.split_ascii_whitespace();
+        if !terms
+            .next()
+            .is_some_and(|v| v.eq_ignore_ascii_case("v=spf1"))
+        {
+            return syntax("not an SPF record");
+        }
+        let mut record = SpfRecord::default();
+        for term in terms {
+            let modifier = term
+                .split_once('=')
+                .filter(|(name, _)| !name.contains([':', '/']));
+            if let Some((name, value)) = modifier {
+                let slot = match name.to_ascii_lowercase().as_str() {
+                    "redirect" => &mut record.redirect,
+                    "exp" => &mut record.explanation,
+                    _ => {
+                        record
+                            .other_modifiers
+                            .push((name.to_string(), value.to_string()));
+                        continue;
+                    }
+                };
+                if slot.is_some() {
+                    return syntax(format!("{} appears twice", name));
+                }
+                if value.is_empty() {
+                    return syntax(format!("{} needs a domain", name));
+                }
+                *slot = Some(value.to_string());
+                continue;
+            }
+            let (qualifier, rest) = match term.chars().next() {
+                Some('+') => (Qualifier::Pass, &term[1..]),
+                Some('-') => (Qualifier::Fail, &term[1..]),
+                Some('~') => (Qualifier::SoftFail, &term[1..]),
+                Some('?') => (Qualifier::Neutral, &term[1..]),
+                _ => (Qualifier::Pass, term),
+            };
+            record.directives.push(Directive {
+                qualifier,
+                mechanism: mechanism(rest)?,
+            });
+        }
+        Ok(record)
+    }
+}
+
+impl fmt::Display for SpfRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("v=spf1")?;
+        for directive in &self.directives {
+            write!(f, " {}", directive)?;
+        }
+        if let Some(redirect) = &self.redirect {
+            write!(f, " redirect={}", redirect)?;
+        }
+        if let Some(exp) = &self.explanation {
+            write!(f, " exp={}", exp)?;
+        }
+        for (name, value) in &self.other_modifiers {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Something [`check_spf`] found wrong beneath the record it was asked
+/// about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpfProblem {
+    /// An include or redirect leads to a domain without an SPF record.
+    Missing(DomainName),
+    /// A domain has more than one SPF record.
+    Multiple(DomainName),
+    /// A domain's record does not parse.
+    Invalid(DomainName, String),
+    /// An include or redirect leads back to a domain already on the path.
+    Loop(DomainName),
+    /// An include or redirect target holds macros, so it depends on the
+    /// message and cannot be followed.
+    Macro(String),
+    /// A target that is not a valid name.
+    BadTarget(String),
+    /// A domain's record could not be looked up.
+    Lookup(DomainName, String),
+}
+
+impl fmt::Display for SpfProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpfProblem::Missing(domain) => write!(f, "{} has no SPF record", domain),
+            SpfProblem::Multiple(domain) => write!(f, "{} has more than one SPF record", domain),
+            SpfProblem::Invalid(domain, why) => write!(f, "{}: {}", domain, why),
+            SpfProblem::Loop(domain) => write!(f, "{} includes itself", domain),
+            SpfProblem::Macro(spec) => write!(f, "{} depends on the message", spec),
+            SpfProblem::BadTarget(spec) => write!(f, "{:?} is not a domain", spec),
+            SpfProblem::Lookup(domain, why) => write!(f, "{}: {}", domain, why),
+        }
+    }
+}
+
+/// The SPF record of a domain with every record its includes and
+/// redirects reach, as [`check_spf`] found them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpfCheck {
+    /// The records reached, in evaluation order, the domain's own first.
+    pub records: Vec<(DomainName, SpfRecord)>,
+    /// Terms that take a DNS lookup, across every record reached.
+    pub lookups: usize,
+    /// Includes and redirects that found no record.
+    pub void_lookups: usize,
+    pub problems: Vec<SpfProblem>,
+}
+
+impl SpfCheck {
+    /// Whether an evaluation stays within [`MAX_SPF_LOOKUPS`] and
+    /// [`MAX_VOID_LOOKUPS`], short of which it fails with a permanent
+    /// error however the sender matches.
+    pub fn within_limits(&self) -> bool {
+        self.lookups <= MAX_SPF_LOOKUPS && self.void_lookups <= MAX_VOID_LOOKUPS
+    }
+}
+
+/// The one SPF record at `domain`.
+fn fetch(resolver: &Resolver, domain: &DomainName) -> Result<SpfRecord, MailError> {
+    let mut records: Vec<String> = txt_records(resolver, domain, "v=spf1")?
+        .into_iter()
+        .filter(|text| text.len() == 6 || text.as_bytes()[6] == b' ')
+        .collect();
+    match records.len() {
+        0 => Err(MailError::Missing),
+        1 => records.remove(0).parse(),
+        _ => Err(MailError::Multiple),
+    }
+}
+
+/// Looks up the SPF record of `domain` and follows every include and
+/// redirect it leads to, counting the lookups an evaluation would make.
+/// Only a problem with the domain's own record is an error; those further
+/// down are reported in [`SpfCheck::problems`].
+pub fn check_spf(resolver: &Resolver, domain: &DomainName) -> Result<SpfCheck, MailError> {
+    let record = fetch(resolver, domain)?;
+    let mut check = SpfCheck {
+        records: Vec::new(),
+        lookups: 0,
+        void_lookups: 0,
+        problems: Vec::new(),
+    };
+    let mut path = HashSet::new();
+    path.insert(domain.to_lowercase());
+    follow(resolver, domain.clone(), record, &mut path, &mut check);
+    Ok(check)
+}
+
+fn follow(
+    resolver: &Resolver,
+    domain: DomainName,
+    record: SpfRecord,
+    path: &mut HashSet<DomainName>,
+    check: &mut SpfCheck,
+) {
+    check.lookups += record.lookups();
+    let targets: Vec<String> = record
+        .directives
+        .iter()
+        .filter_map(|d| match &d.mechanism {
+            Mechanism::Include(spec) => Some(spec.clone()),
+            _ => None,
+        })
+        .chain(record.effective_redirect().map(str::to_string))
+        .collect();
+    check.records.push((domain, record));
+    for spec in targets {
+        if spec.contains('%') {
+            check.problems.push(SpfProblem::Macro(spec));
+            continue;
+        }
+        let target = match spec.parse::<DomainName>() {
+            Ok(target) => target.to_lowercase(),
+            Err(_) => {
+                check.problems.push(SpfProblem::BadTarget(spec));
+                continue;
+            }
+        };
+        if path.contains(&target) {
+            check.problems.push(SpfProblem::Loop(target));
+            continue;
+        }
+        match fetch(resolver, &target) {
+            Ok(record) => {
+                path.insert(target.clone());
+                follow(resolver, target.clone(), record, path, check);
+                path.remove(&target);
+            }
+            Err(MailError::Missing) => {
+                check.void_lookups += 1;
+                check.problems.push(SpfProblem::Missing(target));
+            }
+            Err(MailError::Multiple) => check.problems.push(SpfProblem::Multiple(target)),
+            Err(MailError::Syntax(why)) => check.problems.push(SpfProblem::Invalid(target, why)),
+            Err(e) => check
+                .problems
+                .push(SpfProblem::Lookup(target, e.to_string())),
+        }
+    }
+}
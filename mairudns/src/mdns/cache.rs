@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::cache::{Expiring, RemainingTtl};
+use crate::name::DomainName;
+use crate::rr::{Record, RecordType};
+
+use super::{base_class, CACHE_FLUSH};
+
+/// Records learned from mDNS responses.
+///
+/// Records carrying the cache-flush bit replace the rest of their RRset
+/// once the old members are more than a second old (RFC 6762 §10.2), and
+/// TTL-zero "goodbye" records expire one second after receipt (§10.1).
+#[derive(Default)]
+pub struct Cache {
+    entries: HashMap<(DomainName, RecordType), Vec<Expiring<Record>>>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Adds a record received at `now`.
+    pub fn insert(&mut self, mut record: Record, now: Instant) {
+        let flush = record.class.0 & CACHE_FLUSH != 0;
+        record.class = base_class(record.class);
+        let ttl = if record.ttl == 0 {
+            Duration::from_secs(1)
+        } else {
+            Duration::from_secs(u64::from(record.ttl))
+        };
+        let key = (record.name.clone(), record.rtype());
+        let set = self.entries.entry(key).or_default();
+        if flush {
+            let cutoff = now.checked_sub(Duration::from_secs(1)).unwrap_or(now);
+            set.retain(|e| e.stored() > cutoff);
+        }
+        set.retain(|e| e.value().rdata != record.rdata || e.value().class != record.class);
+        set.push(Expiring::kept_for(record, now, ttl));
+    }
+
+    /// Unexpired records for `name`/`qtype` (any type for `ANY`), with TTLs
+    /// reduced to the time remaining.
+    pub fn lookup(&self, name: &DomainName, qtype: RecordType, now: Instant) -> Vec<Record> {
+        self.entries
+            .iter()
+            .filter(|((n, t), _)| n == name && (qtype == RecordType::ANY || *t == qtype))
+            .flat_map(|(_, set)| set.iter())
+            .with_remaining_ttl(now)
+            .collect()
+    }
+
+    /// Cached answers still fresh enough to suppress duplicates: more than
+    /// half of their original TTL remains.
+    pub fn known_answers(&self, name: &DomainName, qtype: RecordType, now: Instant) -> Vec<Record> {
+        self.entries
+            .iter()
+            .filter(|((n, t), _)| n == name && (qtype == RecordType::ANY || *t == qtype))
+            .flat_map(|(_, set)| set.iter())
+            .filter(|e| e.remaining(now) > e.lifetime() / 2)
+            .with_remaining_ttl(now)
+            .collect()
+    }
+
+    /// Every unexpired record, for browsing and diagnostics.
+    pub fn records(&self, now: Instant) -> Vec<Record> {
+        self.entries
+            .values()
+            .flat_map(|set| set.iter())
+            .filter_map(|e| e.get(now).cloned())
+            .collect()
+    }
+
+    /// Drops expired records.
+    pub fn purge(&mut self, now: Instant) {
+        for set in self.entries.values_mut() {
+            set.retain(|e| !e.is_expired(now));
+        }
+        self.entries.retain(|_, set| !set.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+//! Multicast DNS (RFC 6762) and DNS-based service discovery (RFC 6763).
+//!
+//! [`Socket`] wraps a UDP socket joined to the mDNS group of one address
+//! family. [`Querier`] sends one-shot and continuous-style queries and keeps
+//! a [`Cache`] with cache-flush semantics; [`Responder`] probes, announces
+//! and answers for a set of owned records. The [`sd`] module layers DNS-SD
+//! browsing and registration on top. A [`DiscoveryProxy`] answers unicast
+//! queries from what mDNS finds on each of its links.
+
+mod cache;
+mod proxy;
+mod responder;
+pub mod sd;
+
+pub use self::cache::Cache;
+pub use self::proxy::{DiscoveryProxy, LinkInterface, Proxied, ProxyLink};
+pub use self::responder::{Conflict, Responder, State};
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::message::{Header, Message, Question};
+use crate::name::DomainName;
+use crate::rr::{Record, RecordClass, RecordType};
+use crate::sys::{self, Reuse};
+
+/// The mDNS port.
+pub const PORT: u16 = 5353;
+
+/// The IPv4 mDNS group.
+pub const GROUP_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// The IPv6 link-local mDNS group.
+pub const GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Class bit requesting that caches flush other records of the RRset.
+pub const CACHE_FLUSH: u16 = 0x8000;
+
+/// Question class bit requesting a unicast response ("QU" question).
+pub const UNICAST_RESPONSE: u16 = 0x8000;
+
+/// The `local.` domain.
+pub fn local() -> DomainName {
+    DomainName::from_labels(["local"]).unwrap()
+}
+
+/// The class with the cache-flush / unicast-response bit cleared.
+pub fn base_class(class: RecordClass) -> RecordClass {
+    RecordClass(class.0 & !CACHE_FLUSH)
+}
+
+/// A UDP socket attached to the mDNS group of one address family.
+pub struct Socket {
+    socket: UdpSocket,
+    group: SocketAddr,
+}
+
+impl Socket {
+    /// Binds port 5353 (sharing it with other responders) and joins the
+    /// IPv4 group on the interface with address `interface`, or the
+    /// default interface when unspecified.
+    pub fn v4(interface: Ipv4Addr) -> io::Result<Socket> {
+        let reuse = Reuse {
+            addr: true,
+            port: true,
+        };
+        let socket = sys::bind_udp((Ipv4Addr::UNSPECIFIED, PORT).into(), reuse)?;
+        socket.join_multicast_v4(&GROUP_V4, &interface)?;
+        socket.set_multicast_ttl_v4(255)?;
+        socket.set_multicast_loop_v4(true)?;
+        Ok(Socket {
+            socket,
+            group: (GROUP_V4, PORT).into(),
+        })
+    }
+
+    /// Binds port 5353 and joins the IPv6 group on interface `index`.
+    pub fn v6(index: u32) -> io::Result<Socket> {
+        let reuse = Reuse {
+            addr: true,
+            port: true,
+        };
+        let socket = sys::bind_udp((Ipv6Addr::UNSPECIFIED, PORT).into(), reuse)?;
+        socket.join_multicast_v6(&GROUP_V6, index)?;
+        socket.set_multicast_loop_v6(true)?;
+        Ok(Socket {
+            socket,
+            group: SocketAddrV6::new(GROUP_V6, PORT, 0, index).into(),
+        })
+    }
+
+    /// An ephemeral-port IPv4 socket for one-shot queries (RFC 6762 §5.1).
+    /// Responders answer such queries by unicast.
+    pub fn oneshot() -> io::Result<Socket> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_multicast_ttl_v4(255)?;
+        Ok(Socket {
+            socket,
+            group: (GROUP_V4, PORT).into(),
+        })
+    }
+
+    /// The group address messages are multicast to.
+    pub fn group(&self) -> SocketAddr {
+        self.group
+    }
+
+    /// Multicasts `msg` to the group.
+    pub fn send(&self, msg: &Message) -> io::Result<()> {
+        self.send_to(msg, self.group)
+    }
+
+    /// Sends `msg` to a specific address.
+    pub fn send_to(&self, msg: &Message, addr: SocketAddr) -> io::Result<()> {
+        let wire = msg
+            .to_wire()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.socket.send_to(&wire, addr).map(drop)
+    }
+
+    /// Waits up to `timeout` for a well-formed message, skipping garbage.
+    pub fn recv(&self, timeout: Duration) -> io::Result<Option<(Message, SocketAddr)>> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 9000];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(left))?;
+            match self.socket.recv_from(&mut buf) {
+                Ok((n, from)) => {
+                    if let Ok(msg) = Message::from_wire(&buf[..n]) {
+                        return Ok(Some((msg, from)));
+                    }
+                }
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// An mDNS query message: ID zero, no recursion, no EDNS.
+pub fn query_message(questions: Vec<Question>) -> Message {
+    Message {
+        header: Header::default(),
+        questions,
+        ..Message::default()
+    }
+}
+
+/// Sends queries and caches whatever answers come back.
+pub struct Querier {
+    socket: Socket,
+    cache: Cache,
+}
+
+impl Querier {
+    pub fn new(socket: Socket) -> Querier {
+        Querier {
+            socket,
+            cache: Cache::new(),
+        }
+    }
+
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    /// Multicasts a query for `name`/`qtype`, listening for `timeout`, and
+    /// returns the matching cached records. Unexpired cached answers with
+    /// more than half their TTL left are listed as known answers (§7.1).
+    pub fn query(
+        &mut self,
+        name: &DomainName,
+        qtype: RecordType,
+        timeout: Duration,
+    ) -> io::Result<Vec<Record>> {
+        let mut query = query_message(vec![Question::new(name.clone(), qtype)]);
+        let now = Instant::now();
+        query.answers = self.cache.known_answers(name, qtype, now);
+        self.socket.send(&query)?;
+        self.listen(timeout)?;
+        Ok(self.cache.lookup(name, qtype, Instant::now()))
+    }
+
+    /// Receives responses for `timeout`, caching all records in them.
+    pub fn listen(&mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.socket.recv(left)? {
+                Some((msg, from)) => {
+                    // Genuine responses always come from port 5353 (§6).
+                    if msg.header.qr && from.port() == PORT && msg.header.rcode.0 == 0 {
+                        let now = Instant::now();
+                        for rr in msg.answers.iter().chain(&msg.additional) {
+                            self.cache.insert(rr.clone(), now);
+                        }
+                    }
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! A discovery proxy (RFC 8766): unicast DNS answers for what mDNS finds
+//! on a link.
+//!
+//! Each [`ProxyLink`] ties a unicast domain, such as `lab.home.arpa.`,
+//! to an mDNS socket on one interface. A query for a name under that
+//! domain is answered from what has been heard on the link under
+//! `local.`, multicasting a query and waiting briefly when nothing is
+//! cached, with `local.` in names replaced by the link's domain. Devices
+//! that only speak mDNS thereby become resolvable from other links.
+
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::message::{Message, Opcode, Question, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType, Soa};
+use crate::server::{Handler, Layer, Request};
+
+use super::{local, query_message, Cache, Socket, PORT};
+
+/// The interface a link's mDNS socket is attached to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkInterface {
+    /// IPv4, on the interface with this address, or the default one for
+    /// the unspecified address.
+    V4(Ipv4Addr),
+    /// IPv6, on the interface with this index.
+    V6(u32),
+}
+
+/// One link a [`DiscoveryProxy`] serves: a unicast domain and the mDNS
+/// socket of the interface on the link.
+pub struct ProxyLink {
+    domain: DomainName,
+    socket: Socket,
+    cache: Mutex<Cache>,
+}
+
+impl ProxyLink {
+    pub fn new(domain: DomainName, socket: Socket) -> ProxyLink {
+        ProxyLink {
+            domain,
+            socket,
+            cache: Mutex::new(Cache::new()),
+        }
+    }
+
+    /// A link for `domain` with a socket of its own on `interface`.
+    pub fn bind(domain: DomainName, interface: LinkInterface) -> io::Result<ProxyLink> {
+        let socket = match interface {
+            LinkInterface::V4(addr) => Socket::v4(addr)?,
+            LinkInterface::V6(index) => Socket::v6(index)?,
+        };
+        Ok(ProxyLink::new(domain, socket))
+    }
+
+    pub fn domain(&self) -> &DomainName {
+        &self.domain
+    }
+
+    /// Receives responses on the link for `timeout`, caching their
+    /// records.
+    pub fn listen(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let (msg, from) = match self.socket.recv(left)? {
+                Some(received) => received,
+                None => return Ok(()),
+            };
+            // Genuine responses always come from port 5353 (RFC 6762 §6).
+            if msg.header.qr && from.port() == PORT && msg.header.rcode == Rcode::NOERROR {
+                let now = Instant::now();
+                let mut cache = self.cache.lock().unwrap();
+                for rr in msg.answers.into_iter().chain(msg.additional) {
+                    cache.insert(rr, now);
+                }
+            }
+        }
+    }
+
+    /// The records of `name` under `local.` heard on the link. With
+    /// nothing cached, a query is multicast and answers are awaited for
+    /// up to `wait`; browsing waits for all of it, so that every instance
+    /// has a chance to answer.
+    fn lookup(
+        &self,
+        name: &DomainName,
+        qtype: RecordType,
+        wait: Duration,
+    ) -> io::Result<Vec<Record>> {
+        let cached = self.cached(name, qtype);
+        if !cached.is_empty() || wait == Duration::from_secs(0) {
+            return Ok(cached);
+        }
+        self.socket
+            .send(&query_message(vec![Question::new(name.clone(), qtype)]))?;
+        let deadline = Instant::now() + wait;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            thread::sleep(left.min(Duration::from_millis(20)));
+            let found = self.cached(name, qtype);
+            let browsing = qtype == RecordType::PTR;
+            if Instant::now() >= deadline || (!found.is_empty() && !browsing) {
+                return Ok(found);
+            }
+        }
+    }
+
+    fn cached(&self, name: &DomainName, qtype: RecordType) -> Vec<Record> {
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.purge(now);
+        cache.lookup(name, qtype, now)
+    }
+
+    /// `name` under the link's domain, moved to `local.`.
+    fn local_name(&self, name: &DomainName) -> Option<DomainName> {
+        let keep = name.label_count().checked_sub(self.domain.label_count())?;
+        DomainName::from_labels(&name.labels()[..keep])
+            .ok()?
+            .append(&local())
+            .ok()
+    }
+
+    /// `name` under `local.`, moved to the link's domain. Other names are
+    /// left alone.
+    fn unicast_name(&self, name: &DomainName) -> DomainName {
+        if !name.is_subdomain_of(&local()) {
+            return name.clone();
+        }
+        let keep = name.label_count() - 1;
+        DomainName::from_labels(&name.labels()[..keep])
+            .and_then(|n| n.append(&self.domain))
+            .unwrap_or_else(|_| name.clone())
+    }
+}
+
+/// Answers unicast queries under the domains of its links from mDNS, and
+/// passes everything else on.
+///
+/// A query for a name under a [`ProxyLink`]'s domain is answered from
+/// what has been heard on the link under `local.`, multicasting a query
+/// and waiting briefly when nothing is cached, with `local.` in names
+/// replaced by the link's domain.
+///
+/// The links only learn what they hear while [`run`](DiscoveryProxy::run)
+/// is running.
+#[derive(Clone)]
+pub struct DiscoveryProxy {
+    links: Vec<Arc<ProxyLink>>,
+    wait: Duration,
+    max_ttl: u32,
+}
+
+impl Default for DiscoveryProxy {
+    fn default() -> DiscoveryProxy {
+        DiscoveryProxy::new()
+    }
+}
+
+impl DiscoveryProxy {
+    pub fn new() -> DiscoveryProxy {
+        DiscoveryProxy {
+            links: Vec::new(),
+            wait: Duration::from_millis(500),
+            max_ttl: 10,
+        }
+    }
+
+    pub fn link(mut self, link: ProxyLink) -> Self {
+        self.links.push(Arc::new(link));
+        self
+    }
+
+    /// How long a query nothing is cached for waits for mDNS answers.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// The largest TTL given in unicast answers, since what mDNS caches
+    /// may change at any moment (RFC 8766 §5.5.1).
+    pub fn max_ttl(mut self, ttl: u32) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    pub fn links(&self) -> impl Iterator<Item = &ProxyLink> {
+        self.links.iter().map(|l| &**l)
+    }
+
+    /// Listens on every link, caching what is heard. Does not return but
+    /// on an error.
+    pub fn run(&self) -> io::Result<()> {
+        thread::scope(|s| {
+            let threads: Vec<_> = self
+                .links
+                .iter()
+                .map(|link| {
+                    s.spawn(move || loop {
+                        link.listen(Duration::from_secs(1))?
+                    })
+                })
+                .collect();
+            for t in threads {
+                t.join()
+                    .unwrap_or_else(|_| Err(io::Error::other("proxy thread panicked")))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// The link whose domain `name` is under, the most specific if several
+    /// are.
+    fn link_for(&self, name: &DomainName) -> Option<&ProxyLink> {
+        self.links()
+            .filter(|l| name.is_subdomain_of(&l.domain))
+            .max_by_key(|l| l.domain.label_count())
+    }
+
+    /// The response to `request` if its question is under the domain of a
+    /// link.
+    pub fn answer(&self, request: &Request) -> Option<Message> {
+        let msg = &request.message;
+        if msg.header.opcode != Opcode::QUERY || msg.questions.len() != 1 {
+            return None;
+        }
+        let q = &msg.questions[0];
+        let link = self.link_for(&q.name)?;
+        let mut resp = msg.response();
+        resp.header.aa = true;
+        if q.qclass != RecordClass::IN && q.qclass != RecordClass::ANY {
+            resp.header.rcode = Rcode::REFUSED;
+            return Some(resp);
+        }
+        if q.name == link.domain {
+            if q.qtype == RecordType::SOA || q.qtype == RecordType::ANY {
+                resp.answers.push(self.soa(link));
+            } else {
+                resp.authority.push(self.soa(link));
+            }
+            return Some(resp);
+        }
+        let name = match link.local_name(&q.name) {
+            Some(name) => name,
+            None => {
+                resp.header.rcode = Rcode::SERVFAIL;
+                return Some(resp);
+            }
+        };
+        let records = match link.lookup(&name, q.qtype, self.wait) {
+            Ok(records) => records,
+            Err(_) => {
+                resp.header.rcode = Rcode::SERVFAIL;
+                return Some(resp);
+            }
+        };
+        resp.answers = self.translate(link, records);
+        resp.additional
+            .extend(self.translate(link, self.additional(link, &resp.answers)));
+        if resp.answers.is_empty() {
+            // mDNS cannot tell that a name does not exist.
+            resp.authority.push(self.soa(link));
+        }
+        Some(resp)
+    }
+
+    /// The cached records that go with `answers`: the SRV and TXT records
+    /// of browsed instances and the addresses of SRV targets.
+    fn additional(&self, link: &ProxyLink, answers: &[Record]) -> Vec<Record> {
+        let mut names = Vec::new();
+        for rr in answers {
+            match &rr.rdata {
+                RData::Ptr(target) => {
+                    if let Some(instance) = link.local_name(target) {
+                        names.push((instance.clone(), RecordType::SRV));
+                        names.push((instance, RecordType::TXT));
+                    }
+                }
+                RData::Srv { target, .. } => {
+                    if let Some(host) = link.local_name(target) {
+                        names.push((host.clone(), RecordType::A));
+                        names.push((host, RecordType::AAAA));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut additional = Vec::new();
+        for (name, rtype) in names {
+            let found = link.cached(&name, rtype);
+            if rtype == RecordType::SRV {
+                for rr in &found {
+                    if let RData::Srv { target, .. } = &rr.rdata {
+                        additional.extend(link.cached(target, RecordType::A));
+                        additional.extend(link.cached(target, RecordType::AAAA));
+                    }
+                }
+            }
+            additional.extend(found);
+        }
+        additional
+    }
+
+    /// `records` as unicast answers: moved to the link's domain, with
+    /// TTLs capped and link-local addresses, useless off the link, left
+    /// out (RFC 8766 §5.5.2).
+    fn translate(&self, link: &ProxyLink, records: Vec<Record>) -> Vec<Record> {
+        let mut out: Vec<Record> = Vec::new();
+        for rr in records {
+            let link_local = match rr.rdata {
+                RData::A(a) => a.is_link_local(),
+                RData::Aaaa(a) => a.segments()[0] & 0xffc0 == 0xfe80,
+                _ => false,
+            };
+            if link_local {
+                continue;
+            }
+            let rdata = match rr.rdata {
+                RData::Ptr(n) => RData::Ptr(link.unicast_name(&n)),
+                RData::Cname(n) => RData::Cname(link.unicast_name(&n)),
+                RData::Srv {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                } => RData::Srv {
+                    priority,
+                    weight,
+                    port,
+                    target: link.unicast_name(&target),
+                },
+                rdata => rdata,
+            };
+            let translated = Record {
+                name: link.unicast_name(&rr.name),
+                class: RecordClass::IN,
+                ttl: rr.ttl.min(self.max_ttl),
+                rdata,
+            };
+            if !out.contains(&translated) {
+                out.push(translated);
+            }
+        }
+        out
+    }
+
+    /// The SOA record the proxy gives for a link's domain.
+    fn soa(&self, link: &ProxyLink) -> Record {
+        let domain = &link.domain;
+        let soa = Soa {
+            mname: domain.clone(),
+            rname: domain
+                .prepend(b"hostmaster")
+                .unwrap_or_else(|_| domain.clone()),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 86400,
+            minimum: self.max_ttl,
+        };
+        Record::new(domain.clone(), self.max_ttl, RData::Soa(soa))
+    }
+}
+
+impl<H: Handler> Layer<H> for DiscoveryProxy {
+    type Handler = Proxied<H>;
+
+    fn layer(&self, inner: H) -> Proxied<H> {
+        Proxied {
+            proxy: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler answering for the links of a [`DiscoveryProxy`].
+pub struct Proxied<H> {
+    proxy: DiscoveryProxy,
+    inner: H,
+}
+
+impl<H> Proxied<H> {
+    pub fn proxy(&self) -> &DiscoveryProxy {
+        &self.proxy
+    }
+}
+
+impl<H: Handler> Handler for Proxied<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        self.proxy
+            .answer(request)
+            .or_else(|| self.inner.handle(request))
+    }
+}
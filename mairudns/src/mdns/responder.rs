@@ -0,0 +1,345 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::message::{Header, Message, Question};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+
+use super::{base_class, query_message, Socket, CACHE_FLUSH, PORT, UNICAST_RESPONSE};
+
+/// Where a responder is in its lifecycle (RFC 6762 §8).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    /// Unique records have not been verified yet.
+    Probing,
+    /// Probing succeeded; announcements are being sent.
+    Announcing,
+    /// Answering queries.
+    Running,
+}
+
+/// Errors from starting a responder.
+#[derive(Debug)]
+pub enum Conflict {
+    Io(io::Error),
+    /// Another host answered for one of our unique names while probing.
+    Name(DomainName),
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::Io(e) => write!(f, "i/o error: {}", e),
+            Conflict::Name(n) => write!(f, "name {} is already in use", n),
+        }
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+impl From<io::Error> for Conflict {
+    fn from(e: io::Error) -> Conflict {
+        Conflict::Io(e)
+    }
+}
+
+struct Owned {
+    record: Record,
+    unique: bool,
+}
+
+/// Answers mDNS queries for a set of records this host owns.
+pub struct Responder {
+    socket: Socket,
+    records: Vec<Owned>,
+    state: State,
+}
+
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const LEGACY_TTL: u32 = 10;
+
+impl Responder {
+    pub fn new(socket: Socket) -> Responder {
+        Responder {
+            socket,
+            records: Vec::new(),
+            state: State::Probing,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Adds a record. Unique records (host addresses, SRV, TXT) must be
+    /// probed before they are announced; shared ones (PTR) need not be.
+    pub fn add(&mut self, record: Record, unique: bool) {
+        if unique {
+            self.state = State::Probing;
+        }
+        self.records.push(Owned { record, unique });
+    }
+
+    /// Withdraws all records owned for `name`, sending goodbyes.
+    pub fn remove(&mut self, name: &DomainName) -> io::Result<()> {
+        let (gone, kept) = self.records.drain(..).partition(|o| &o.record.name == name);
+        self.records = kept;
+        self.send_goodbye(&gone)
+    }
+
+    /// Probes every unique name three times, 250ms apart (§8.1), failing
+    /// if another host already answers for one of them.
+    pub fn probe(&mut self) -> Result<(), Conflict> {
+        let mut names: Vec<DomainName> = self
+            .records
+            .iter()
+            .filter(|o| o.unique)
+            .map(|o| o.record.name.clone())
+            .collect();
+        names.sort();
+        names.dedup();
+        if !names.is_empty() {
+            for i in 0..3 {
+                let mut probe = query_message(
+                    names
+                        .iter()
+                        .map(|n| Question {
+                            name: n.clone(),
+                            qtype: RecordType::ANY,
+                            qclass: RecordClass(
+                                RecordClass::IN.0 | if i == 0 { UNICAST_RESPONSE } else { 0 },
+                            ),
+                        })
+                        .collect(),
+                );
+                probe.authority = self
+                    .records
+                    .iter()
+                    .filter(|o| o.unique)
+                    .map(|o| o.record.clone())
+                    .collect();
+                self.socket.send(&probe)?;
+                let deadline = Instant::now() + PROBE_INTERVAL;
+                loop {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    match self.socket.recv(left)? {
+                        Some((msg, _)) if msg.header.qr => {
+                            if let Some(name) = self.conflicting_name(&msg, &names) {
+                                return Err(Conflict::Name(name));
+                            }
+                        }
+                        Some(_) => {}
+                        None => break,
+                    }
+                }
+            }
+        }
+        self.state = State::Announcing;
+        Ok(())
+    }
+
+    fn conflicting_name(&self, msg: &Message, names: &[DomainName]) -> Option<DomainName> {
+        msg.answers
+            .iter()
+            .chain(&msg.additional)
+            .find(|rr| {
+                names.contains(&rr.name)
+                    && !self
+                        .records
+                        .iter()
+                        .any(|o| o.record.name == rr.name && o.record.rdata == rr.rdata)
+            })
+            .map(|rr| rr.name.clone())
+    }
+
+    /// Multicasts all records twice, one second apart (§8.3), with the
+    /// cache-flush bit on unique records.
+    pub fn announce(&mut self) -> io::Result<()> {
+        let mut msg = response_message(0);
+        msg.answers = self.records.iter().map(|o| flagged(o, true)).collect();
+        for i in 0..2 {
+            if i > 0 {
+                thread::sleep(ANNOUNCE_INTERVAL);
+            }
+            self.socket.send(&msg)?;
+        }
+        self.state = State::Running;
+        Ok(())
+    }
+
+    /// Probes and announces.
+    pub fn start(&mut self) -> Result<(), Conflict> {
+        self.probe()?;
+        self.announce()?;
+        Ok(())
+    }
+
+    /// Builds the response to `query` received from `from`, and where to
+    /// send it: `None` to multicast, or the querier's address for QU
+    /// questions and legacy unicast queries from ports other than 5353.
+    pub fn respond(
+        &self,
+        query: &Message,
+        from: SocketAddr,
+    ) -> Option<(Message, Option<SocketAddr>)> {
+        if query.header.qr || query.header.opcode.0 != 0 {
+            return None;
+        }
+        let legacy = from.port() != PORT;
+        let mut unicast = legacy;
+        let mut answers: Vec<&Owned> = Vec::new();
+        for q in &query.questions {
+            let class = base_class(q.qclass);
+            if q.qclass.0 & UNICAST_RESPONSE != 0 {
+                unicast = true;
+            }
+            for o in &self.records {
+                let rr = &o.record;
+                if rr.name == q.name
+                    && (q.qtype == RecordType::ANY || q.qtype == rr.rtype())
+                    && (class == RecordClass::ANY || class == rr.class)
+                    && !known(query, rr)
+                    && !answers.iter().any(|a| std::ptr::eq(*a, o))
+                {
+                    answers.push(o);
+                }
+            }
+        }
+        if answers.is_empty() {
+            return None;
+        }
+        let mut msg = response_message(if legacy { query.header.id } else { 0 });
+        if legacy {
+            msg.questions = query.questions.clone();
+        }
+        let fix = |o: &Owned| {
+            let mut rr = flagged(o, !legacy);
+            if legacy {
+                rr.ttl = rr.ttl.min(LEGACY_TTL);
+            }
+            rr
+        };
+        msg.answers = answers.iter().map(|o| fix(o)).collect();
+        msg.additional = self.additional_for(&answers).into_iter().map(fix).collect();
+        Some((msg, if unicast { Some(from) } else { None }))
+    }
+
+    /// Records that make answers immediately usable (RFC 6763 §12).
+    fn additional_for(&self, answers: &[&Owned]) -> Vec<&Owned> {
+        let mut wanted: Vec<(DomainName, RecordType)> = Vec::new();
+        for o in answers {
+            match &o.record.rdata {
+                RData::Ptr(target) => {
+                    wanted.push((target.clone(), RecordType::SRV));
+                    wanted.push((target.clone(), RecordType::TXT));
+                    for s in self.records.iter().filter(|s| &s.record.name == target) {
+                        if let RData::Srv { target, .. } = &s.record.rdata {
+                            wanted.push((target.clone(), RecordType::A));
+                            wanted.push((target.clone(), RecordType::AAAA));
+                        }
+                    }
+                }
+                RData::Srv { target, .. } => {
+                    wanted.push((target.clone(), RecordType::A));
+                    wanted.push((target.clone(), RecordType::AAAA));
+                }
+                _ => {}
+            }
+        }
+        self.records
+            .iter()
+            .filter(|o| {
+                wanted
+                    .iter()
+                    .any(|(n, t)| &o.record.name == n && o.record.rtype() == *t)
+                    && !answers.iter().any(|a| std::ptr::eq(*a, *o))
+            })
+            .collect()
+    }
+
+    /// Handles incoming queries for up to `timeout`.
+    pub fn serve_for(&mut self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.socket.recv(left)? {
+                Some((query, from)) => {
+                    if let Some((resp, dest)) = self.respond(&query, from) {
+                        match dest {
+                            Some(addr) => self.socket.send_to(&resp, addr)?,
+                            None => self.socket.send(&resp)?,
+                        }
+                    }
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Handles incoming queries forever.
+    pub fn serve(&mut self) -> io::Result<()> {
+        loop {
+            self.serve_for(Duration::from_secs(60))?;
+        }
+    }
+
+    /// Sends TTL-zero goodbyes for all records (§10.1), e.g. on shutdown.
+    pub fn goodbye(&self) -> io::Result<()> {
+        let all: Vec<&Owned> = self.records.iter().collect();
+        self.send_goodbye_refs(&all)
+    }
+
+    fn send_goodbye(&self, records: &[Owned]) -> io::Result<()> {
+        let refs: Vec<&Owned> = records.iter().collect();
+        self.send_goodbye_refs(&refs)
+    }
+
+    fn send_goodbye_refs(&self, records: &[&Owned]) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut msg = response_message(0);
+        msg.answers = records
+            .iter()
+            .map(|o| {
+                let mut rr = o.record.clone();
+                rr.ttl = 0;
+                rr
+            })
+            .collect();
+        self.socket.send(&msg)
+    }
+}
+
+fn response_message(id: u16) -> Message {
+    Message {
+        header: Header {
+            id,
+            qr: true,
+            aa: true,
+            ..Header::default()
+        },
+        ..Message::default()
+    }
+}
+
+fn flagged(o: &Owned, flush: bool) -> Record {
+    let mut rr = o.record.clone();
+    if o.unique && flush {
+        rr.class = RecordClass(rr.class.0 | CACHE_FLUSH);
+    }
+    rr
+}
+
+/// Whether the querier listed `rr` as a known answer with at least half
+/// our TTL remaining (§7.1).
+fn known(query: &Message, rr: &Record) -> bool {
+    query
+        .answers
+        .iter()
+        .any(|k| k.name == rr.name && k.rdata == rr.rdata && k.ttl >= rr.ttl / 2)
+}
@@ -0,0 +1,198 @@
+//! DNS-based service discovery (RFC 6763) over mDNS.
+
+use std::io;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::name::{self, DomainName};
+use crate::rr::{RData, Record, RecordType};
+
+use super::{local, Querier, Responder};
+
+/// TTL for host-dependent records (SRV, A/AAAA), per RFC 6762 §10.
+pub const HOST_TTL: u32 = 120;
+
+/// TTL for other records (PTR, TXT).
+pub const OTHER_TTL: u32 = 4500;
+
+/// A discovered service instance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceInstance {
+    /// The full instance name, e.g. `My Printer._ipp._tcp.local.`.
+    pub name: DomainName,
+    /// The user-visible instance label.
+    pub instance: String,
+    pub host: DomainName,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+    /// TXT key/value pairs; `None` values are boolean attributes.
+    pub txt: Vec<(String, Option<Vec<u8>>)>,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// A service to advertise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceInfo {
+    /// The user-visible instance label, may contain spaces and dots.
+    pub instance: String,
+    /// The service type, e.g. `_http._tcp`.
+    pub service: String,
+    /// The host name the service runs on, e.g. `myhost.local.`.
+    pub host: DomainName,
+    pub port: u16,
+    /// TXT entries as `key=value` or bare `key`.
+    pub txt: Vec<String>,
+    pub addrs: Vec<IpAddr>,
+}
+
+/// Expands `_http._tcp` (or `_http._tcp.local.`) to a name under `local.`.
+pub fn service_name(service: &str) -> Result<DomainName, name::Error> {
+    DomainName::parse_relative(service, &local())
+}
+
+/// Splits a TXT record into key/value pairs (RFC 6763 §6.3).
+pub fn parse_txt(strings: &[Vec<u8>]) -> Vec<(String, Option<Vec<u8>>)> {
+    strings
+        .iter()
+        .filter(|s| !s.is_empty() && s[0] != b'=')
+        .map(|s| match s.iter().position(|&c| c == b'=') {
+            Some(i) => (
+                String::from_utf8_lossy(&s[..i]).into_owned(),
+                Some(s[i + 1..].to_vec()),
+            ),
+            None => (String::from_utf8_lossy(s).into_owned(), None),
+        })
+        .collect()
+}
+
+/// Browses for instances of `service` (e.g. `_http._tcp`), listening for
+/// `timeout`, then resolves each instance from the answers gathered.
+/// Instances whose SRV record did not arrive are queried once more.
+pub fn browse(
+    querier: &mut Querier,
+    service: &str,
+    timeout: Duration,
+) -> io::Result<Vec<ServiceInstance>> {
+    let service =
+        service_name(service).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let ptrs = querier.query(&service, RecordType::PTR, timeout)?;
+    let mut found = Vec::new();
+    for ptr in ptrs {
+        let name = match ptr.rdata {
+            RData::Ptr(name) => name,
+            _ => continue,
+        };
+        if !querier
+            .cache()
+            .lookup(&name, RecordType::SRV, Instant::now())
+            .is_empty()
+            || !querier.query(&name, RecordType::SRV, timeout)?.is_empty()
+        {
+            if let Some(instance) = resolve(querier, &name, &service) {
+                found.push(instance);
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn resolve(querier: &Querier, name: &DomainName, service: &DomainName) -> Option<ServiceInstance> {
+    let now = Instant::now();
+    let cache = querier.cache();
+    let (priority, weight, port, host) = cache
+        .lookup(name, RecordType::SRV, now)
+        .into_iter()
+        .find_map(|rr| match rr.rdata {
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => Some((priority, weight, port, target)),
+            _ => None,
+        })?;
+    let txt = cache
+        .lookup(name, RecordType::TXT, now)
+        .into_iter()
+        .find_map(|rr| match rr.rdata {
+            RData::Txt(strings) => Some(parse_txt(&strings)),
+            _ => None,
+        })
+        .unwrap_or_default();
+    let addrs = cache
+        .lookup(&host, RecordType::ANY, now)
+        .into_iter()
+        .filter_map(|rr| match rr.rdata {
+            RData::A(a) => Some(IpAddr::V4(a)),
+            RData::Aaaa(a) => Some(IpAddr::V6(a)),
+            _ => None,
+        })
+        .collect();
+    let instance = if name.label_count() > service.label_count() {
+        String::from_utf8_lossy(&name.labels()[0]).into_owned()
+    } else {
+        String::new()
+    };
+    Some(ServiceInstance {
+        name: name.clone(),
+        instance,
+        host,
+        port,
+        priority,
+        weight,
+        txt,
+        addrs,
+    })
+}
+
+/// Adds the PTR, SRV, TXT and address records advertising `info` to a
+/// responder. The caller then (re)starts the responder to probe and
+/// announce them.
+pub fn register_service(
+    responder: &mut Responder,
+    info: &ServiceInfo,
+) -> Result<DomainName, name::Error> {
+    let service = service_name(&info.service)?;
+    let instance = service.prepend(info.instance.as_bytes())?;
+    responder.add(
+        Record::new(service.clone(), OTHER_TTL, RData::Ptr(instance.clone())),
+        false,
+    );
+    let enumeration = DomainName::parse_relative("_services._dns-sd._udp", &local())?;
+    responder.add(
+        Record::new(enumeration, OTHER_TTL, RData::Ptr(service)),
+        false,
+    );
+    responder.add(
+        Record::new(
+            instance.clone(),
+            HOST_TTL,
+            RData::Srv {
+                priority: 0,
+                weight: 0,
+                port: info.port,
+                target: info.host.clone(),
+            },
+        ),
+        true,
+    );
+    // An empty TXT record is a single empty string (RFC 6763 §6.1).
+    let txt = if info.txt.is_empty() {
+        vec![Vec::new()]
+    } else {
+        info.txt.iter().map(|s| s.as_bytes().to_vec()).collect()
+    };
+    responder.add(
+        Record::new(instance.clone(), OTHER_TTL, RData::Txt(txt)),
+        true,
+    );
+    for addr in &info.addrs {
+        let rdata = match addr {
+            IpAddr::V4(a) => RData::A(*a),
+            IpAddr::V6(a) => RData::Aaaa(*a),
+        };
+        responder.add(Record::new(info.host.clone(), HOST_TTL, rdata), true);
+    }
+    Ok(instance)
+}
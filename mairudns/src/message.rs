@@ -0,0 +1,703 @@
+//! DNS messages (RFC 1035 §4) with EDNS(0) support (RFC 6891).
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::compliance::Compliance;
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{Record, RecordClass, RecordType};
+use crate::wire::{self, Decoder, Encoder};
+
+/// A message opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Opcode(pub u8);
+
+impl Opcode {
+    pub const QUERY: Opcode = Opcode(0);
+    pub const STATUS: Opcode = Opcode(2);
+    pub const NOTIFY: Opcode = Opcode(4);
+    pub const UPDATE: Opcode = Opcode(5);
+    pub const DSO: Opcode = Opcode(6);
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Opcode::QUERY => f.write_str("QUERY"),
+            Opcode::STATUS => f.write_str("STATUS"),
+            Opcode::NOTIFY => f.write_str("NOTIFY"),
+            Opcode::UPDATE => f.write_str("UPDATE"),
+            Opcode::DSO => f.write_str("DSO"),
+            Opcode(n) => write!(f, "OPCODE{}", n),
+        }
+    }
+}
+
+/// A response code, including the extended bits carried in OPT.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Rcode(pub u16);
+
+impl Rcode {
+    pub const NOERROR: Rcode = Rcode(0);
+    pub const FORMERR: Rcode = Rcode(1);
+    pub const SERVFAIL: Rcode = Rcode(2);
+    pub const NXDOMAIN: Rcode = Rcode(3);
+    pub const NOTIMP: Rcode = Rcode(4);
+    pub const REFUSED: Rcode = Rcode(5);
+    pub const YXDOMAIN: Rcode = Rcode(6);
+    pub const YXRRSET: Rcode = Rcode(7);
+    pub const NXRRSET: Rcode = Rcode(8);
+    pub const NOTAUTH: Rcode = Rcode(9);
+    pub const NOTZONE: Rcode = Rcode(10);
+    pub const DSOTYPENI: Rcode = Rcode(11);
+    pub const BADVERS: Rcode = Rcode(16);
+    /// TSIG signature failure; shares its value with BADVERS.
+    pub const BADSIG: Rcode = Rcode(16);
+    pub const BADKEY: Rcode = Rcode(17);
+    pub const BADTIME: Rcode = Rcode(18);
+    pub const BADCOOKIE: Rcode = Rcode(23);
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            Rcode::NOERROR => "NOERROR",
+            Rcode::FORMERR => "FORMERR",
+            Rcode::SERVFAIL => "SERVFAIL",
+            Rcode::NXDOMAIN => "NXDOMAIN",
+            Rcode::NOTIMP => "NOTIMP",
+            Rcode::REFUSED => "REFUSED",
+            Rcode::YXDOMAIN => "YXDOMAIN",
+            Rcode::YXRRSET => "YXRRSET",
+            Rcode::NXRRSET => "NXRRSET",
+            Rcode::NOTAUTH => "NOTAUTH",
+            Rcode::NOTZONE => "NOTZONE",
+            Rcode::DSOTYPENI => "DSOTYPENI",
+            Rcode::BADVERS => "BADVERS",
+            Rcode::BADKEY => "BADKEY",
+            Rcode::BADTIME => "BADTIME",
+            Rcode::BADCOOKIE => "BADCOOKIE",
+            Rcode(n) => return write!(f, "RCODE{}", n),
+        };
+        f.write_str(s)
+    }
+}
+
+/// The fixed message header, minus section counts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub id: u16,
+    pub qr: bool,
+    pub opcode: Opcode,
+    pub aa: bool,
+    pub tc: bool,
+    pub rd: bool,
+    pub ra: bool,
+    pub ad: bool,
+    pub cd: bool,
+    pub rcode: Rcode,
+}
+
+impl Default for Header {
+    fn default() -> Header {
+        Header {
+            id: 0,
+            qr: false,
+            opcode: Opcode::QUERY,
+            aa: false,
+            tc: false,
+            rd: false,
+            ra: false,
+            ad: false,
+            cd: false,
+            rcode: Rcode::NOERROR,
+        }
+    }
+}
+
+impl Header {
+    /// The header with the given ID and flags word; only the low four bits
+    /// of the rcode fit in it.
+    pub(crate) fn from_flags(id: u16, flags: u16) -> Header {
+        let bit = |n: u16| flags & (1 << n) != 0;
+        Header {
+            id,
+            qr: bit(15),
+            opcode: Opcode(((flags >> 11) & 0xf) as u8),
+            aa: bit(10),
+            tc: bit(9),
+            rd: bit(8),
+            ra: bit(7),
+            ad: bit(5),
+            cd: bit(4),
+            rcode: Rcode(flags & 0xf),
+        }
+    }
+
+    /// The flags word following the ID on the wire.
+    pub(crate) fn flags(&self) -> u16 {
+        let mut flags = u16::from(self.opcode.0 & 0xf) << 11 | (self.rcode.0 & 0xf);
+        for (bit, set) in [
+            (15, self.qr),
+            (10, self.aa),
+            (9, self.tc),
+            (8, self.rd),
+            (7, self.ra),
+            (5, self.ad),
+            (4, self.cd),
+        ]
+        .iter()
+        {
+            if *set {
+                flags |= 1 << bit;
+            }
+        }
+        flags
+    }
+}
+
+/// An entry of the question section.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Question {
+    pub name: DomainName,
+    pub qtype: RecordType,
+    pub qclass: RecordClass,
+}
+
+impl Question {
+    pub fn new(name: DomainName, qtype: RecordType) -> Question {
+        Question {
+            name,
+            qtype,
+            qclass: RecordClass::IN,
+        }
+    }
+}
+
+impl fmt::Display for Question {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\t{}\t{}", self.name, self.qclass, self.qtype)
+    }
+}
+
+/// An EDNS option code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct OptionCode(pub u16);
+
+impl OptionCode {
+    pub const NSID: OptionCode = OptionCode(3);
+    pub const CLIENT_SUBNET: OptionCode = OptionCode(8);
+    pub const COOKIE: OptionCode = OptionCode(10);
+    pub const TCP_KEEPALIVE: OptionCode = OptionCode(11);
+    pub const PADDING: OptionCode = OptionCode(12);
+    pub const EXTENDED_ERROR: OptionCode = OptionCode(15);
+    pub const REPORT_CHANNEL: OptionCode = OptionCode(18);
+}
+
+/// A single EDNS option.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EdnsOption {
+    pub code: OptionCode,
+    pub data: Vec<u8>,
+}
+
+/// The contents of an OPT pseudo-record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edns {
+    pub udp_size: u16,
+    pub version: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Default for Edns {
+    fn default() -> Edns {
+        Edns {
+            udp_size: 1232,
+            version: 0,
+            dnssec_ok: false,
+            options: Vec::new(),
+        }
+    }
+}
+
+impl Edns {
+    /// The first option with the given code.
+    pub fn option(&self, code: OptionCode) -> Option<&EdnsOption> {
+        self.options.iter().find(|o| o.code == code)
+    }
+}
+
+/// An Extended DNS Error (RFC 8914): an info code saying why a response
+/// failed or was filtered, with optional text for humans.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ExtendedError {
+    pub code: u16,
+    pub text: String,
+}
+
+impl ExtendedError {
+    pub const OTHER: u16 = 0;
+    pub const UNSUPPORTED_DNSKEY_ALGORITHM: u16 = 1;
+    pub const UNSUPPORTED_DS_DIGEST_TYPE: u16 = 2;
+    pub const STALE_ANSWER: u16 = 3;
+    pub const FORGED_ANSWER: u16 = 4;
+    pub const DNSSEC_INDETERMINATE: u16 = 5;
+    pub const DNSSEC_BOGUS: u16 = 6;
+    pub const SIGNATURE_EXPIRED: u16 = 7;
+    pub const SIGNATURE_NOT_YET_VALID: u16 = 8;
+    pub const DNSKEY_MISSING: u16 = 9;
+    pub const RRSIGS_MISSING: u16 = 10;
+    pub const NO_ZONE_KEY_BIT_SET: u16 = 11;
+    pub const NSEC_MISSING: u16 = 12;
+    pub const CACHED_ERROR: u16 = 13;
+    pub const NOT_READY: u16 = 14;
+    pub const BLOCKED: u16 = 15;
+    pub const CENSORED: u16 = 16;
+    pub const FILTERED: u16 = 17;
+    pub const PROHIBITED: u16 = 18;
+    pub const STALE_NXDOMAIN_ANSWER: u16 = 19;
+    pub const NOT_AUTHORITATIVE: u16 = 20;
+    pub const NOT_SUPPORTED: u16 = 21;
+    pub const NO_REACHABLE_AUTHORITY: u16 = 22;
+    pub const NETWORK_ERROR: u16 = 23;
+    pub const INVALID_DATA: u16 = 24;
+
+    pub fn new(code: u16, text: impl Into<String>) -> ExtendedError {
+        ExtendedError {
+            code,
+            text: text.into(),
+        }
+    }
+
+    pub fn to_option(&self) -> EdnsOption {
+        let mut data = self.code.to_be_bytes().to_vec();
+        data.extend_from_slice(self.text.as_bytes());
+        EdnsOption {
+            code: OptionCode::EXTENDED_ERROR,
+            data,
+        }
+    }
+
+    /// Decodes an EXTENDED_ERROR option; text that is not UTF-8 is
+    /// replaced lossily.
+    pub fn from_option(opt: &EdnsOption) -> Option<ExtendedError> {
+        if opt.code != OptionCode::EXTENDED_ERROR || opt.data.len() < 2 {
+            return None;
+        }
+        Some(ExtendedError {
+            code: u16::from_be_bytes([opt.data[0], opt.data[1]]),
+            text: String::from_utf8_lossy(&opt.data[2..]).into_owned(),
+        })
+    }
+}
+
+/// A complete DNS message.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Message {
+    pub header: Header,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    /// Additional records, excluding the OPT pseudo-record.
+    pub additional: Vec<Record>,
+    pub edns: Option<Edns>,
+}
+
+impl Message {
+    /// A recursive query for `name`/`qtype` with a random ID and EDNS.
+    pub fn query(name: DomainName, qtype: RecordType) -> Message {
+        Message {
+            header: Header {
+                id: random::u16(),
+                rd: true,
+                ..Header::default()
+            },
+            questions: vec![Question::new(name, qtype)],
+            edns: Some(Edns::default()),
+            ..Message::default()
+        }
+    }
+
+    /// A response skeleton echoing this message's ID, opcode, RD/CD bits
+    /// and question, with EDNS if the request carried it.
+    pub fn response(&self) -> Message {
+        Message {
+            header: Header {
+                id: self.header.id,
+                qr: true,
+                opcode: self.header.opcode,
+                rd: self.header.rd,
+                cd: self.header.cd,
+                ..Header::default()
+            },
+            questions: self.questions.clone(),
+            edns: self.edns.as_ref().map(|e| Edns {
+                dnssec_ok: e.dnssec_ok,
+                ..Edns::default()
+            }),
+            ..Message::default()
+        }
+    }
+
+    /// The first question, if any.
+    pub fn question(&self) -> Option<&Question> {
+        self.questions.first()
+    }
+
+    /// Encodes the message.
+    pub fn to_wire(&self) -> Result<Vec<u8>, wire::Error> {
+        let mut enc = Encoder::new();
+        self.encode_header(
+            &mut enc,
+            [
+                self.questions.len(),
+                self.answers.len(),
+                self.authority.len(),
+                self.additional.len() + self.edns.is_some() as usize,
+            ],
+        )?;
+        for q in &self.questions {
+            enc.name(&q.name, true);
+            enc.u16(q.qtype.0);
+            enc.u16(q.qclass.0);
+        }
+        for rr in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            rr.encode(&mut enc)?;
+        }
+        if let Some(edns) = &self.edns {
+            self.encode_opt(&mut enc, edns);
+        }
+        if enc.len() > usize::from(u16::MAX) {
+            return Err(wire::Error::TooLong { offset: enc.len() });
+        }
+        Ok(enc.into_bytes())
+    }
+
+    /// Encodes the message in at most `max` bytes, as for a UDP response.
+    /// Records that do not fit are left out: additional records silently,
+    /// answer and authority records by setting TC (RFC 2181 §9). The OPT
+    /// record is always kept.
+    pub fn to_wire_limited(&self, max: usize) -> Result<Vec<u8>, wire::Error> {
+        let full = self.to_wire()?;
+        if full.len() <= max {
+            return Ok(full);
+        }
+        let mut opt = Encoder::new();
+        if let Some(edns) = &self.edns {
+            self.encode_opt(&mut opt, edns);
+        }
+        let budget = max.saturating_sub(opt.len());
+
+        // The header goes in last, once the counts are known.
+        let mut enc = Encoder::new();
+        enc.bytes(&[0; 12]);
+        for q in &self.questions {
+            enc.name(&q.name, true);
+            enc.u16(q.qtype.0);
+            enc.u16(q.qclass.0);
+        }
+        let mut counts = [self.questions.len(), 0, 0, 0];
+        let mut truncated = false;
+        let sections = [&self.answers, &self.authority, &self.additional];
+        'sections: for (i, records) in sections.iter().enumerate() {
+            for rr in records.iter() {
+                let len = enc.len();
+                rr.encode(&mut enc)?;
+                if enc.len() > budget {
+                    enc.truncate(len);
+                    truncated = i < 2;
+                    break 'sections;
+                }
+                counts[i + 1] += 1;
+            }
+        }
+        if enc.len() > budget {
+            // Not even the question fits.
+            enc.truncate(12);
+            counts = [0; 4];
+            truncated = true;
+        }
+        enc.bytes(opt.as_bytes());
+        counts[3] += self.edns.is_some() as usize;
+
+        let mut header = Encoder::new();
+        let mut msg = Message {
+            header: self.header,
+            ..Message::default()
+        };
+        msg.header.tc |= truncated;
+        msg.encode_header(&mut header, counts)?;
+        let mut out = enc.into_bytes();
+        out[..12].copy_from_slice(header.as_bytes());
+        Ok(out)
+    }
+
+    fn encode_header(&self, enc: &mut Encoder, counts: [usize; 4]) -> Result<(), wire::Error> {
+        enc.u16(self.header.id);
+        enc.u16(self.header.flags());
+        for &count in counts.iter() {
+            if count > usize::from(u16::MAX) {
+                return Err(wire::Error::TooLong { offset: enc.len() });
+            }
+            enc.u16(count as u16);
+        }
+        Ok(())
+    }
+
+    fn encode_opt(&self, enc: &mut Encoder, edns: &Edns) {
+        enc.u8(0);
+        enc.u16(RecordType::OPT.0);
+        enc.u16(edns.udp_size);
+        enc.u8((self.header.rcode.0 >> 4) as u8);
+        enc.u8(edns.version);
+        enc.u16(if edns.dnssec_ok { 0x8000 } else { 0 });
+        let len_pos = enc.len();
+        enc.u16(0);
+        for opt in &edns.options {
+            enc.u16(opt.code.0);
+            enc.u16(opt.data.len() as u16);
+            enc.bytes(&opt.data);
+        }
+        let len = enc.len() - len_pos - 2;
+        enc.set_u16(len_pos, len as u16);
+    }
+
+    /// Decodes a message, rejecting trailing bytes.
+    pub fn from_wire(buf: &[u8]) -> Result<Message, wire::Error> {
+        Message::from_wire_with(buf, &Compliance::default())
+    }
+
+    /// Decodes a message, putting up with what `compliance` does.
+    pub fn from_wire_with(buf: &[u8], compliance: &Compliance) -> Result<Message, wire::Error> {
+        let mut dec = Decoder::with_compliance(buf, *compliance);
+        let id = dec.u16()?;
+        let flags = dec.u16()?;
+        let qdcount = dec.u16()?;
+        let ancount = dec.u16()?;
+        let nscount = dec.u16()?;
+        let arcount = dec.u16()?;
+        let mut msg = Message {
+            header: Header::from_flags(id, flags),
+            ..Message::default()
+        };
+        for _ in 0..qdcount {
+            msg.questions.push(Question {
+                name: dec.name()?,
+                qtype: RecordType(dec.u16()?),
+                qclass: RecordClass(dec.u16()?),
+            });
+        }
+        for _ in 0..ancount {
+            msg.answers.push(Record::decode(&mut dec)?);
+        }
+        for _ in 0..nscount {
+            msg.authority.push(Record::decode(&mut dec)?);
+        }
+        for _ in 0..arcount {
+            let at = dec.pos();
+            let rr = Record::decode(&mut dec)?;
+            if rr.rtype() == RecordType::OPT {
+                if msg.edns.is_some() || !rr.name.is_root() {
+                    return Err(wire::Error::BadRdata { offset: at });
+                }
+                msg.edns = Some(decode_opt(&mut msg.header, &rr, &dec)?);
+            } else {
+                msg.additional.push(rr);
+            }
+        }
+        if dec.remaining() != 0 && !compliance.trailing_bytes {
+            return Err(wire::Error::TrailingData { offset: dec.pos() });
+        }
+        Ok(msg)
+    }
+
+    /// Attaches an Extended DNS Error if the message has EDNS; clients
+    /// that did not send EDNS cannot receive one.
+    pub fn add_extended_error(&mut self, error: &ExtendedError) {
+        if let Some(edns) = &mut self.edns {
+            edns.options.push(error.to_option());
+        }
+    }
+
+    /// The Extended DNS Errors the message carries.
+    pub fn extended_errors(&self) -> impl Iterator<Item = ExtendedError> + '_ {
+        self.edns
+            .iter()
+            .flat_map(|e| &e.options)
+            .filter_map(ExtendedError::from_option)
+    }
+
+    /// Advertises `agent` as the domain to send DNS error reports to (RFC
+    /// 9567 §5) if the message has EDNS, replacing any agent already set.
+    /// The root cannot be an agent and is ignored.
+    pub fn set_report_channel(&mut self, agent: &DomainName) {
+        if agent.is_root() {
+            return;
+        }
+        if let Some(edns) = &mut self.edns {
+            edns.options
+                .retain(|o| o.code != OptionCode::REPORT_CHANNEL);
+            let mut enc = Encoder::uncompressed();
+            enc.name(agent, false);
+            edns.options.push(EdnsOption {
+                code: OptionCode::REPORT_CHANNEL,
+                data: enc.into_bytes(),
+            });
+        }
+    }
+
+    /// The agent domain of the message's Report-Channel option, if it has
+    /// a well-formed one.
+    pub fn report_channel(&self) -> Option<DomainName> {
+        let opt = self.edns.as_ref()?.option(OptionCode::REPORT_CHANNEL)?;
+        let mut dec = Decoder::new(&opt.data);
+        let agent = dec.name().ok()?;
+        if dec.remaining() != 0 || agent.is_root() {
+            return None;
+        }
+        Some(agent)
+    }
+
+    /// Sets the edns-tcp-keepalive option (RFC 7828) if the message has
+    /// EDNS: empty in a query, with the idle timeout in a response.
+    /// Timeouts are sent in units of 100 milliseconds, rounded down.
+    pub fn set_tcp_keepalive(&mut self, timeout: Option<Duration>) {
+        if let Some(edns) = &mut self.edns {
+            edns.options.retain(|o| o.code != OptionCode::TCP_KEEPALIVE);
+            let data = match timeout {
+                Some(t) => ((t.as_millis() / 100).min(u128::from(u16::MAX)) as u16)
+                    .to_be_bytes()
+                    .to_vec(),
+                None => Vec::new(),
+            };
+            edns.options.push(EdnsOption {
+                code: OptionCode::TCP_KEEPALIVE,
+                data,
+            });
+        }
+    }
+
+    /// The edns-tcp-keepalive option, if the message has a well-formed
+    /// one: `Some(None)` if it is empty, as in queries, otherwise the idle
+    /// timeout it gives.
+    pub fn tcp_keepalive(&self) -> Option<Option<Duration>> {
+        let opt = self.edns.as_ref()?.option(OptionCode::TCP_KEEPALIVE)?;
+        match opt.data[..] {
+            [] => Some(None),
+            [hi, lo] => Some(Some(Duration::from_millis(
+                u64::from(u16::from_be_bytes([hi, lo])) * 100,
+            ))),
+            _ => None,
+        }
+    }
+
+    /// All records of all three record sections.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+    }
+}
+
+/// The EDNS settings of `rr`, an OPT record `dec` has just read.
+fn decode_opt(header: &mut Header, rr: &Record, dec: &Decoder<'_>) -> Result<Edns, wire::Error> {
+    let end = dec.pos();
+    let data = match &rr.rdata {
+        crate::rr::RData::Unknown { data, .. } => data,
+        _ => return Err(wire::Error::BadRdata { offset: end }),
+    };
+    header.rcode = Rcode(header.rcode.0 | u16::from((rr.ttl >> 24) as u8) << 4);
+    // Options are read from the message itself, so that errors are at
+    // their offsets in it, and no further than the record's data.
+    let mut dec = Decoder::new(&dec.buffer()[..end]);
+    dec.seek(end - data.len())?;
+    let mut options = Vec::new();
+    while dec.remaining() > 0 {
+        let code = OptionCode(dec.u16()?);
+        let len = dec.u16()? as usize;
+        options.push(EdnsOption {
+            code,
+            data: dec.bytes(len)?.to_vec(),
+        });
+    }
+    Ok(Edns {
+        udp_size: rr.class.0,
+        version: (rr.ttl >> 16) as u8,
+        dnssec_ok: rr.ttl & 0x8000 != 0,
+        options,
+    })
+}
+
+impl fmt::Display for Message {
+    /// Renders the message in the familiar `dig` layout.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let h = &self.header;
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            h.opcode, h.rcode, h.id
+        )?;
+        let mut flags = Vec::new();
+        for (name, set) in [
+            ("qr", h.qr),
+            ("aa", h.aa),
+            ("tc", h.tc),
+            ("rd", h.rd),
+            ("ra", h.ra),
+            ("ad", h.ad),
+            ("cd", h.cd),
+        ]
+        .iter()
+        {
+            if *set {
+                flags.push(*name);
+            }
+        }
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            self.questions.len(),
+            self.answers.len(),
+            self.authority.len(),
+            self.additional.len() + self.edns.is_some() as usize
+        )?;
+        if let Some(edns) = &self.edns {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:")?;
+            writeln!(
+                f,
+                "; EDNS: version: {}, flags:{}; udp: {}",
+                edns.version,
+                if edns.dnssec_ok { " do" } else { "" },
+                edns.udp_size
+            )?;
+        }
+        writeln!(f, "\n;; QUESTION SECTION:")?;
+        for q in &self.questions {
+            writeln!(f, ";{}", q)?;
+        }
+        for (title, records) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authority),
+            ("ADDITIONAL", &self.additional),
+        ]
+        .iter()
+        {
+            if !records.is_empty() {
+                writeln!(f, "\n;; {} SECTION:", title)?;
+                for rr in records.iter() {
+                    writeln!(f, "{}", rr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
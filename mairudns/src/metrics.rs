@@ -0,0 +1,445 @@
+//! Counters and histograms describing what a server does, read through
+//! [`Metrics::snapshot`] or rendered in the Prometheus text format.
+//!
+//! One [`Metrics`] is shared by the parts recording into it: the
+//! [`Instrument`](crate::server::Instrument) layer counts requests by
+//! transport, type and rcode, per client and per zone, as well as zone
+//! transfers; a [`Forwarder`](crate::server::Forwarder) counts cache hits
+//! and times its upstreams; [`RateLimit`](crate::server::RateLimit) counts
+//! the responses it drops or truncates. A server can expose them to
+//! Prometheus with [`Server::listen_metrics`](crate::server::Server::listen_metrics).
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::client::Protocol;
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::rr::RecordType;
+
+/// The path the Prometheus text format is served at.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Upper bounds in seconds of the round-trip time histogram buckets; a
+/// last bucket takes everything slower.
+pub const RTT_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Default limit on the number of clients counted individually.
+const MAX_CLIENTS: usize = 10_000;
+
+/// What requests are counted by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct QueryKey {
+    pub protocol: Protocol,
+    pub qtype: RecordType,
+    /// `None` for requests that got no response.
+    pub rcode: Option<Rcode>,
+}
+
+/// A distribution of durations over [`RTT_BUCKETS`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative; one more than there are
+    /// bounds.
+    pub counts: Vec<u64>,
+    /// Sum of the observations in seconds.
+    pub sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if self.counts.is_empty() {
+            self.counts = vec![0; RTT_BUCKETS.len() + 1];
+        }
+        let bucket = RTT_BUCKETS
+            .iter()
+            .position(|&b| secs <= b)
+            .unwrap_or(RTT_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += secs;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// The values of every metric at one point in time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub queries: HashMap<QueryKey, u64>,
+    /// Requests per client address.
+    pub clients: HashMap<IpAddr, u64>,
+    /// Requests from clients beyond the limit of those counted
+    /// individually.
+    pub other_clients: u64,
+    /// Requests per zone of the authority they fell in.
+    pub zones: HashMap<DomainName, u64>,
+    /// Zone transfers served per zone and type, AXFR or IXFR.
+    pub transfers: HashMap<(DomainName, RecordType), u64>,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Round-trip times of upstream queries per forwarding route.
+    pub upstream_rtt: HashMap<DomainName, Histogram>,
+    /// Upstream queries that failed per forwarding route.
+    pub upstream_failures: HashMap<DomainName, u64>,
+    /// Responses rate limiting dropped.
+    pub rate_limit_drops: u64,
+    /// Responses rate limiting sent truncated instead.
+    pub rate_limit_slips: u64,
+    /// Queries over a client's quota of queries per second.
+    pub quota_rate_exceeded: u64,
+    /// Queries over a client's quota of queries in progress.
+    pub quota_concurrency_exceeded: u64,
+}
+
+impl Snapshot {
+    /// The share of cacheable queries answered from the cache, if there
+    /// were any.
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        let total = self.cache_hits + self.cache_misses;
+        (total > 0).then(|| self.cache_hits as f64 / total as f64)
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // Samples are sorted for stable output; the buckets of histograms
+        // must stay in order, so those come sorted by route.
+        let mut family = |name: &str, kind: &str, help: &str, mut samples: Vec<String>| {
+            if kind != "histogram" {
+                samples.sort();
+            }
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for sample in samples {
+                out.push_str(&sample);
+                out.push('\n');
+            }
+        };
+        family(
+            "dns_queries_total",
+            "counter",
+            "Requests by transport, type and response code.",
+            self.queries
+                .iter()
+                .map(|(k, n)| {
+                    let protocol = match k.protocol {
+                        Protocol::Udp => "udp",
+                        Protocol::Tcp => "tcp",
+                        Protocol::Tls => "tls",
+                    };
+                    let rcode = k
+                        .rcode
+                        .map_or_else(|| "none".to_string(), |r| r.to_string());
+                    format!(
+                        "dns_queries_total{{protocol=\"{}\",qtype=\"{}\",rcode=\"{}\"}} {}",
+                        protocol,
+                        escape(&k.qtype.to_string()),
+                        escape(&rcode),
+                        n
+                    )
+                })
+                .collect(),
+        );
+        let mut clients: Vec<String> = self
+            .clients
+            .iter()
+            .map(|(ip, n)| format!("dns_client_queries_total{{client=\"{}\"}} {}", ip, n))
+            .collect();
+        if self.other_clients > 0 {
+            clients.push(format!(
+                "dns_client_queries_total{{client=\"other\"}} {}",
+                self.other_clients
+            ));
+        }
+        family(
+            "dns_client_queries_total",
+            "counter",
+            "Requests by client address.",
+            clients,
+        );
+        family(
+            "dns_zone_queries_total",
+            "counter",
+            "Requests by authoritative zone.",
+            self.zones
+                .iter()
+                .map(|(zone, n)| {
+                    format!(
+                        "dns_zone_queries_total{{zone=\"{}\"}} {}",
+                        escape(&zone.to_string()),
+                        n
+                    )
+                })
+                .collect(),
+        );
+        family(
+            "dns_zone_transfers_total",
+            "counter",
+            "Zone transfers served by zone and type.",
+            self.transfers
+                .iter()
+                .map(|((zone, qtype), n)| {
+                    format!(
+                        "dns_zone_transfers_total{{zone=\"{}\",type=\"{}\"}} {}",
+                        escape(&zone.to_string()),
+                        qtype,
+                        n
+                    )
+                })
+                .collect(),
+        );
+        family(
+            "dns_cache_lookups_total",
+            "counter",
+            "Forwarder cache lookups by result.",
+            vec![
+                format!(
+                    "dns_cache_lookups_total{{result=\"hit\"}} {}",
+                    self.cache_hits
+                ),
+                format!(
+                    "dns_cache_lookups_total{{result=\"miss\"}} {}",
+                    self.cache_misses
+                ),
+            ],
+        );
+        let mut rtt = Vec::new();
+        let mut routes: Vec<_> = self.upstream_rtt.iter().collect();
+        routes.sort_by(|a, b| a.0.cmp(b.0));
+        for (route, h) in routes {
+            let route = escape(&route.to_string());
+            let mut cumulative = 0;
+            for (i, count) in h.counts.iter().enumerate() {
+                cumulative += count;
+                let le = RTT_BUCKETS
+                    .get(i)
+                    .map_or_else(|| "+Inf".to_string(), f64::to_string);
+                rtt.push(format!(
+                    "dns_upstream_rtt_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, le, cumulative
+                ));
+            }
+            rtt.push(format!(
+                "dns_upstream_rtt_seconds_sum{{route=\"{}\"}} {}",
+                route, h.sum
+            ));
+            rtt.push(format!(
+                "dns_upstream_rtt_seconds_count{{route=\"{}\"}} {}",
+                route,
+                h.count()
+            ));
+        }
+        family(
+            "dns_upstream_rtt_seconds",
+            "histogram",
+            "Round-trip time of upstream queries by forwarding route.",
+            rtt,
+        );
+        family(
+            "dns_upstream_failures_total",
+            "counter",
+            "Upstream queries that failed by forwarding route.",
+            self.upstream_failures
+                .iter()
+                .map(|(route, n)| {
+                    format!(
+                        "dns_upstream_failures_total{{route=\"{}\"}} {}",
+                        escape(&route.to_string()),
+                        n
+                    )
+                })
+                .collect(),
+        );
+        family(
+            "dns_rate_limited_total",
+            "counter",
+            "Responses withheld by rate limiting by action.",
+            vec![
+                format!(
+                    "dns_rate_limited_total{{action=\"drop\"}} {}",
+                    self.rate_limit_drops
+                ),
+                format!(
+                    "dns_rate_limited_total{{action=\"slip\"}} {}",
+                    self.rate_limit_slips
+                ),
+            ],
+        );
+        family(
+            "dns_client_quota_exceeded_total",
+            "counter",
+            "Queries over a client quota by limit.",
+            vec![
+                format!(
+                    "dns_client_quota_exceeded_total{{limit=\"rate\"}} {}",
+                    self.quota_rate_exceeded
+                ),
+                format!(
+                    "dns_client_quota_exceeded_total{{limit=\"concurrency\"}} {}",
+                    self.quota_concurrency_exceeded
+                ),
+            ],
+        );
+        out
+    }
+}
+
+/// Escapes a label value (backslash, double quote and line feed).
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A set of metrics shared by the parts of a server recording into it.
+#[derive(Debug)]
+pub struct Metrics {
+    values: Mutex<Snapshot>,
+    max_clients: usize,
+}
+
+impl Default for Metrics {
+    fn default() -> Metrics {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::with_max_clients(MAX_CLIENTS)
+    }
+
+    /// Metrics counting at most `max` clients individually, so that
+    /// clients with random addresses cannot exhaust memory. The others
+    /// are counted together.
+    pub fn with_max_clients(max: usize) -> Metrics {
+        Metrics {
+            values: Mutex::new(Snapshot::default()),
+            max_clients: max,
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.values.lock().unwrap().clone()
+    }
+
+    /// Sets every metric back to zero.
+    pub fn reset(&self) {
+        *self.values.lock().unwrap() = Snapshot::default();
+    }
+
+    pub fn to_prometheus(&self) -> String {
+        self.values.lock().unwrap().to_prometheus()
+    }
+
+    /// Counts a request from `client`, and for `zone` if it fell in one.
+    pub fn record_query(&self, key: QueryKey, client: IpAddr, zone: Option<&DomainName>) {
+        let mut values = self.values.lock().unwrap();
+        *values.queries.entry(key).or_insert(0) += 1;
+        if values.clients.len() < self.max_clients || values.clients.contains_key(&client) {
+            *values.clients.entry(client).or_insert(0) += 1;
+        } else {
+            values.other_clients += 1;
+        }
+        if let Some(zone) = zone {
+            *values.zones.entry(zone.clone()).or_insert(0) += 1;
+        }
+    }
+
+    pub fn record_transfer(&self, zone: &DomainName, qtype: RecordType) {
+        let mut values = self.values.lock().unwrap();
+        *values.transfers.entry((zone.clone(), qtype)).or_insert(0) += 1;
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        let mut values = self.values.lock().unwrap();
+        if hit {
+            values.cache_hits += 1;
+        } else {
+            values.cache_misses += 1;
+        }
+    }
+
+    /// Records an upstream query of `route` that took `rtt`, or that
+    /// failed if it is `None`.
+    pub fn record_upstream(&self, route: &DomainName, rtt: Option<Duration>) {
+        let mut values = self.values.lock().unwrap();
+        match rtt {
+            Some(rtt) => values
+                .upstream_rtt
+                .entry(route.clone())
+                .or_default()
+                .observe(rtt),
+            None => *values.upstream_failures.entry(route.clone()).or_insert(0) += 1,
+        }
+    }
+
+    /// Records a response rate limiting dropped, or sent truncated if
+    /// `slipped`.
+    pub fn record_rate_limited(&self, slipped: bool) {
+        let mut values = self.values.lock().unwrap();
+        if slipped {
+            values.rate_limit_slips += 1;
+        } else {
+            values.rate_limit_drops += 1;
+        }
+    }
+
+    /// Records a query over a client's quota of queries per second, or of
+    /// queries in progress if `concurrency`.
+    pub fn record_quota_exceeded(&self, concurrency: bool) {
+        let mut values = self.values.lock().unwrap();
+        if concurrency {
+            values.quota_concurrency_exceeded += 1;
+        } else {
+            values.quota_rate_exceeded += 1;
+        }
+    }
+}
+
+/// Answers one HTTP/1.1 request for [`METRICS_PATH`] on `stream`, then
+/// closes the connection.
+pub(crate) fn serve_http<S: Read + Write>(stream: &mut S, metrics: &Metrics) -> io::Result<()> {
+    const MAX_HEAD: usize = 8 * 1024;
+    let mut head = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD {
+            return Ok(());
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Ok(()),
+            n => head.extend_from_slice(&chunk[..n]),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.split("\r\n").next().unwrap_or("").split(' ');
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) if target.split('?').next() == Some(METRICS_PATH) => {
+            ("200 OK", metrics.to_prometheus())
+        }
+        (Some(_), Some(target)) if target.split('?').next() == Some(METRICS_PATH) => {
+            ("405 Method Not Allowed", String::new())
+        }
+        _ => ("404 Not Found", String::new()),
+    };
+    let resp = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(resp.as_bytes())?;
+    stream.flush()
+}
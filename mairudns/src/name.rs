@@ -0,0 +1,444 @@
+//! Domain names.
+//!
+//! A [`DomainName`] is always absolute and stores its labels in wire form
+//! (raw bytes, no length prefixes, no root label). Comparison and hashing
+//! are ASCII case-insensitive; ordering is the canonical DNS order of
+//! RFC 4034 §6.1.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::simd;
+
+/// Maximum length of a single label in bytes.
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Maximum length of an encoded name in bytes, including length octets.
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Errors produced when building or parsing a domain name, with where in
+/// the name they are: a byte index into the text parsed, into the message
+/// a name is decoded from, or for names built from labels, where the
+/// label would start in the name written out without escapes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// An empty label appeared somewhere other than the root.
+    EmptyLabel { index: usize },
+    /// A label of `len` bytes, more than 63.
+    LabelTooLong { index: usize, len: usize },
+    /// The encoded name exceeds 255 bytes with the label at `index`.
+    NameTooLong { index: usize },
+    /// A character that cannot appear unescaped in a name.
+    IllegalChar { index: usize, ch: char },
+    /// A malformed `\` escape sequence, starting at `index`.
+    BadEscape { index: usize },
+}
+
+impl Error {
+    /// Where in the name the error is.
+    pub fn index(&self) -> usize {
+        match *self {
+            Error::EmptyLabel { index }
+            | Error::LabelTooLong { index, .. }
+            | Error::NameTooLong { index }
+            | Error::IllegalChar { index, .. }
+            | Error::BadEscape { index } => index,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::EmptyLabel { index } => write!(f, "empty label at byte {}", index),
+            Error::LabelTooLong { index, len } => write!(
+                f,
+                "label of {} bytes at byte {} is longer than 63",
+                len, index
+            ),
+            Error::NameTooLong { index } => {
+                write!(f, "name longer than 255 bytes from byte {}", index)
+            }
+            Error::IllegalChar { index, ch } => {
+                write!(f, "illegal character {:?} in name at byte {}", ch, index)
+            }
+            Error::BadEscape { index } => {
+                write!(f, "malformed escape sequence at byte {}", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// An absolute domain name.
+#[derive(Clone, Default)]
+pub struct DomainName {
+    labels: Vec<Vec<u8>>,
+}
+
+impl DomainName {
+    /// The root name `.`.
+    pub fn root() -> DomainName {
+        DomainName { labels: Vec::new() }
+    }
+
+    /// Builds a name from labels ordered from the leftmost label to the
+    /// rightmost, excluding the root.
+    pub fn from_labels<I, L>(labels: I) -> Result<DomainName, Error>
+    where
+        I: IntoIterator<Item = L>,
+        L: AsRef<[u8]>,
+    {
+        let mut name = DomainName::root();
+        let mut len = 1;
+        for label in labels {
+            let label = label.as_ref();
+            let index = len - 1;
+            if label.is_empty() {
+                return Err(Error::EmptyLabel { index });
+            }
+            if label.len() > MAX_LABEL_LEN {
+                return Err(Error::LabelTooLong {
+                    index,
+                    len: label.len(),
+                });
+            }
+            len += label.len() + 1;
+            if len > MAX_NAME_LEN {
+                return Err(Error::NameTooLong { index });
+            }
+            name.labels.push(label.to_vec());
+        }
+        Ok(name)
+    }
+
+    /// Makes this name `labels`, already checked, reusing the memory of
+    /// the labels it had so that a name kept between calls stops
+    /// allocating.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    pub(crate) fn assign<'a, I: IntoIterator<Item = &'a [u8]>>(&mut self, labels: I) {
+        let mut n = 0;
+        for label in labels {
+            match self.labels.get_mut(n) {
+                Some(kept) => {
+                    kept.clear();
+                    kept.extend_from_slice(label);
+                }
+                None => self.labels.push(label.to_vec()),
+            }
+            n += 1;
+        }
+        self.labels.truncate(n);
+    }
+
+    /// Returns the labels from leftmost to rightmost, excluding the root.
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    /// Number of labels, excluding the root.
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Whether this is the root name.
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Whether the leftmost label is `*`.
+    pub fn is_wildcard(&self) -> bool {
+        self.labels.first().is_some_and(|l| l == b"*")
+    }
+
+    /// Length of the uncompressed wire encoding.
+    pub fn wire_len(&self) -> usize {
+        self.labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1
+    }
+
+    /// The name with its leftmost label removed, or `None` for the root.
+    pub fn parent(&self) -> Option<DomainName> {
+        if self.is_root() {
+            None
+        } else {
+            Some(DomainName {
+                labels: self.labels[1..].to_vec(),
+            })
+        }
+    }
+
+    /// The rightmost `n` labels of this name.
+    pub fn suffix(&self, n: usize) -> DomainName {
+        let n = n.min(self.labels.len());
+        DomainName {
+            labels: self.labels[self.labels.len() - n..].to_vec(),
+        }
+    }
+
+    /// Prepends a label, e.g. `*` to form a wildcard.
+    pub fn prepend(&self, label: &[u8]) -> Result<DomainName, Error> {
+        let mut labels = Vec::with_capacity(self.labels.len() + 1);
+        labels.push(label);
+        labels.extend(self.labels.iter().map(Vec::as_slice));
+        DomainName::from_labels(labels)
+    }
+
+    /// Concatenates a relative name (this one's labels) with `origin`.
+    pub fn append(&self, origin: &DomainName) -> Result<DomainName, Error> {
+        DomainName::from_labels(self.labels.iter().chain(origin.labels.iter()))
+    }
+
+    /// Whether this name equals `other` or lies beneath it.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        if other.labels.len() > self.labels.len() {
+            return false;
+        }
+        let skip = self.labels.len() - other.labels.len();
+        self.labels[skip..]
+            .iter()
+            .zip(&other.labels)
+            .all(|(a, b)| simd::eq_ignore_case(a, b))
+    }
+
+    /// Returns a copy with all ASCII letters lowercased.
+    pub fn to_lowercase(&self) -> DomainName {
+        DomainName {
+            labels: self
+                .labels
+                .iter()
+                .map(|l| {
+                    let mut l = l.clone();
+                    simd::lowercase(&mut l);
+                    l
+                })
+                .collect(),
+        }
+    }
+
+    /// Parses `s` relative to `origin` when it does not end in a dot. The
+    /// lone string `@` denotes the origin itself.
+    pub fn parse_relative(s: &str, origin: &DomainName) -> Result<DomainName, Error> {
+        if s == "@" {
+            return Ok(origin.clone());
+        }
+        let mut parsed = parse_labels(s)?;
+        if !parsed.absolute {
+            // The origin's labels come where the text ends.
+            let len = parsed.labels.len() + origin.labels.len();
+            parsed.starts.resize(len, s.len());
+            parsed.labels.extend(origin.labels.iter().cloned());
+        }
+        from_parsed(parsed)
+    }
+}
+
+/// A name in presentation format, as [`parse_labels`] takes it apart.
+struct Parsed {
+    labels: Vec<Vec<u8>>,
+    /// The byte index each label starts at.
+    starts: Vec<usize>,
+    /// Whether the name ends in a dot.
+    absolute: bool,
+}
+
+fn parse_labels(s: &str) -> Result<Parsed, Error> {
+    if s == "." {
+        return Ok(Parsed {
+            labels: Vec::new(),
+            starts: Vec::new(),
+            absolute: true,
+        });
+    }
+    if s.is_empty() {
+        return Err(Error::EmptyLabel { index: 0 });
+    }
+    let bytes = s.as_bytes();
+    let mut labels = Vec::new();
+    let mut starts = Vec::new();
+    let mut label = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    let mut absolute = false;
+    let mut push = |label: &mut Vec<u8>, start: usize| {
+        if label.len() > MAX_LABEL_LEN {
+            return Err(Error::LabelTooLong {
+                index: start,
+                len: label.len(),
+            });
+        }
+        labels.push(std::mem::take(label));
+        starts.push(start);
+        Ok(())
+    };
+    while i < bytes.len() {
+        let plain = simd::plain_prefix(&bytes[i..]);
+        if plain > 0 {
+            label.extend_from_slice(&bytes[i..i + plain]);
+            i += plain;
+            continue;
+        }
+        match bytes[i] {
+            b'.' => {
+                if label.is_empty() {
+                    return Err(Error::EmptyLabel { index: i });
+                }
+                push(&mut label, start)?;
+                start = i + 1;
+                if i + 1 == bytes.len() {
+                    absolute = true;
+                }
+            }
+            b'\\' => {
+                let bad = Error::BadEscape { index: i };
+                let rest = &bytes[i + 1..];
+                match rest.first() {
+                    Some(d) if d.is_ascii_digit() => {
+                        if rest.len() < 3 || !rest[..3].iter().all(u8::is_ascii_digit) {
+                            return Err(bad);
+                        }
+                        let v = rest[..3]
+                            .iter()
+                            .fold(0u32, |acc, d| acc * 10 + u32::from(d - b'0'));
+                        if v > 255 {
+                            return Err(bad);
+                        }
+                        label.push(v as u8);
+                        i += 3;
+                    }
+                    Some(&d) => {
+                        label.push(d);
+                        i += 1;
+                    }
+                    None => return Err(bad),
+                }
+            }
+            _ => {
+                // An escape may have taken the first byte of a character.
+                let ch = s
+                    .get(i..)
+                    .and_then(|rest| rest.chars().next())
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                return Err(Error::IllegalChar { index: i, ch });
+            }
+        }
+        i += 1;
+    }
+    if !label.is_empty() {
+        push(&mut label, start)?;
+    }
+    Ok(Parsed {
+        labels,
+        starts,
+        absolute,
+    })
+}
+
+/// The name of `parsed`, with errors at the byte indices of its text.
+fn from_parsed(parsed: Parsed) -> Result<DomainName, Error> {
+    let Parsed { labels, starts, .. } = parsed;
+    DomainName::from_labels(&labels).map_err(|e| match e {
+        Error::NameTooLong { index } => {
+            // The label the error is at, by its index in unescaped text.
+            let mut offset = 0;
+            let n = labels
+                .iter()
+                .position(|l| {
+                    let at = offset;
+                    offset += l.len() + 1;
+                    at == index
+                })
+                .unwrap_or(0);
+            Error::NameTooLong {
+                index: starts.get(n).copied().unwrap_or(0),
+            }
+        }
+        e => e,
+    })
+}
+
+impl FromStr for DomainName {
+    type Err = Error;
+
+    /// Parses a name in presentation format. A trailing dot is optional;
+    /// the name is always treated as absolute.
+    fn from_str(s: &str) -> Result<DomainName, Error> {
+        from_parsed(parse_labels(s)?)
+    }
+}
+
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str(".");
+        }
+        for label in &self.labels {
+            for &c in label {
+                match c {
+                    b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                        write!(f, "\\{}", c as char)?
+                    }
+                    c if c <= b' ' || c >= 0x7f => write!(f, "\\{:03}", c)?,
+                    c => write!(f, "{}", c as char)?,
+                }
+            }
+            f.write_str(".")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DomainName({})", self)
+    }
+}
+
+impl PartialEq for DomainName {
+    fn eq(&self, other: &DomainName) -> bool {
+        self.labels.len() == other.labels.len()
+            && self
+                .labels
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| simd::eq_ignore_case(a, b))
+    }
+}
+
+impl Eq for DomainName {}
+
+impl Hash for DomainName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.labels.len());
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for c in label {
+                state.write_u8(c.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+impl Ord for DomainName {
+    fn cmp(&self, other: &DomainName) -> Ordering {
+        let a = self.labels.iter().rev();
+        let b = other.labels.iter().rev();
+        for (x, y) in a.zip(b) {
+            let x = x.iter().map(u8::to_ascii_lowercase);
+            let y = y.iter().map(u8::to_ascii_lowercase);
+            match x.cmp(y) {
+                Ordering::Equal => {}
+                ord => return ord,
+            }
+        }
+        self.labels.len().cmp(&other.labels.len())
+    }
+}
+
+impl PartialOrd for DomainName {
+    fn partial_cmp(&self, other: &DomainName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
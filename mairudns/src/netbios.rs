@@ -0,0 +1,106 @@
+//! Minimal NetBIOS name queries (RFC 1002 §4.2.12), broadcast only.
+//!
+//! Only the NB query for a workstation name is implemented: enough to find
+//! the IPv4 address of a Windows host on the local segment when neither
+//! DNS nor LLMNR knows it.
+
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::message::{Header, Message, Question};
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{RData, RecordClass, RecordType};
+
+/// The NetBIOS name service port.
+pub const PORT: u16 = 137;
+
+/// The NB (general name service) record type.
+pub const NB: RecordType = RecordType(0x20);
+
+/// How long to wait for a response to a broadcast query.
+pub const TIMEOUT: Duration = Duration::from_millis(750);
+
+/// Encodes `name` with the suffix byte `suffix` in first-level encoding:
+/// uppercased, space padded to 15 bytes, each nibble mapped to `A`..`P`.
+pub fn encode_name(name: &str, suffix: u8) -> Option<DomainName> {
+    if name.is_empty() || name.len() > 15 {
+        return None;
+    }
+    let mut raw = [b' '; 16];
+    raw[..name.len()].copy_from_slice(name.to_ascii_uppercase().as_bytes());
+    raw[15] = suffix;
+    let label: Vec<u8> = raw
+        .iter()
+        .flat_map(|&b| vec![b'A' + (b >> 4), b'A' + (b & 0xf)])
+        .collect();
+    DomainName::from_labels([label]).ok()
+}
+
+/// Broadcasts a name query for the workstation `name` and returns the
+/// addresses from the first positive response.
+pub fn query(name: &str, timeout: Duration) -> io::Result<Vec<Ipv4Addr>> {
+    let qname = encode_name(name, 0x00)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid NetBIOS name"))?;
+    let query = Message {
+        header: Header {
+            id: random::u16(),
+            rd: true,
+            // NetBIOS puts its broadcast flag where DNS has the CD bit.
+            cd: true,
+            ..Header::default()
+        },
+        questions: vec![Question {
+            name: qname.clone(),
+            qtype: NB,
+            qclass: RecordClass::IN,
+        }],
+        ..Message::default()
+    };
+    let wire = query
+        .to_wire()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.send_to(&wire, (Ipv4Addr::BROADCAST, PORT))?;
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            return Ok(Vec::new());
+        }
+        socket.set_read_timeout(Some(left))?;
+        let n = match socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e),
+        };
+        let resp = match Message::from_wire(&buf[..n]) {
+            Ok(resp) => resp,
+            Err(_) => continue,
+        };
+        if !resp.header.qr || resp.header.id != query.header.id || resp.header.rcode.0 != 0 {
+            continue;
+        }
+        let addrs: Vec<Ipv4Addr> = resp
+            .answers
+            .iter()
+            .filter(|rr| rr.name == qname)
+            .filter_map(|rr| match &rr.rdata {
+                RData::Unknown { rtype, data } if *rtype == NB => Some(data),
+                _ => None,
+            })
+            .flat_map(|data| data.chunks_exact(6))
+            .map(|entry| Ipv4Addr::new(entry[2], entry[3], entry[4], entry[5]))
+            .collect();
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use crate::message::{ExtendedError, Message, Opcode, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+use crate::server::{Handler, Layer, Request};
+use crate::zone::{parse_records, ParseError};
+
+/// Which names a rule applies to, relative to the name it is set on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatchKind {
+    /// The name itself.
+    Exact,
+    /// The name and everything below it.
+    Suffix,
+    /// Everything below the name, but not the name itself (`*.name`).
+    Wildcard,
+}
+
+/// What happens to a query a rule matches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Nxdomain,
+    /// An empty NOERROR answer.
+    Nodata,
+    /// Answer with this data instead, owned by the query name: a CNAME, or
+    /// the records of the query type, such as sinkhole addresses.
+    Redirect(Vec<RData>),
+    /// Answer normally; the match is still reported to the logger.
+    Passthru,
+    /// Send nothing.
+    Drop,
+}
+
+impl Action {
+    /// Redirects to the unspecified addresses, which clients cannot
+    /// connect to.
+    pub fn sinkhole() -> Action {
+        Action::Redirect(vec![
+            RData::A(Ipv4Addr::UNSPECIFIED),
+            RData::Aaaa(Ipv6Addr::UNSPECIFIED),
+        ])
+    }
+}
+
+/// An action and the list it came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub source: Arc<str>,
+}
+
+/// A rule matching a query name.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit<'a> {
+    /// The name the rule is set on.
+    pub trigger: &'a DomainName,
+    pub kind: MatchKind,
+    pub rule: &'a Rule,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    /// The rule for the name itself.
+    exact: Option<(MatchKind, Rule)>,
+    /// The rule for names below.
+    below: Option<(MatchKind, Rule)>,
+}
+
+type Logger = dyn Fn(&Request, &Hit<'_>) + Send + Sync;
+
+/// Blocks, redirects or lets through queries by name.
+///
+/// Rules come from hosts files, domain lists and Response Policy Zones, or
+/// are added one by one. A rule on a name itself beats one covering it
+/// from above, and among those the closest wins; later rules replace
+/// earlier ones for the same name and kind. Only the query name is
+/// checked: names reached through CNAMEs, and RPZ address and name server
+/// triggers, are not.
+pub struct Filter {
+    nodes: HashMap<DomainName, Node>,
+    ttl: u32,
+    logger: Option<Arc<Logger>>,
+}
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("rules", &self.nodes.len())
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Names hosts files map to themselves rather than block.
+const HOSTS_RESERVED: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+];
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter {
+            nodes: HashMap::new(),
+            ttl: 60,
+            logger: None,
+        }
+    }
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    /// TTL of the records in redirected answers.
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = ttl;
+    }
+
+    /// Calls `logger` for every query a rule matches, including passthru
+    /// rules.
+    pub fn set_logger<F>(&mut self, logger: F)
+    where
+        F: Fn(&Request, &Hit<'_>) + Send + Sync + 'static,
+    {
+        self.logger = Some(Arc::new(logger));
+    }
+
+    pub fn insert(&mut self, name: DomainName, kind: MatchKind, rule: Rule) {
+        let node = self.nodes.entry(name).or_default();
+        match kind {
+            MatchKind::Exact => node.exact = Some((kind, rule)),
+            MatchKind::Wildcard => node.below = Some((kind, rule)),
+            MatchKind::Suffix => {
+                node.exact = Some((kind, rule.clone()));
+                node.below = Some((kind, rule));
+            }
+        }
+    }
+
+    /// Number of names with rules.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+
+    /// The rule for `name`, if any.
+    pub fn lookup<'a>(&'a self, name: &DomainName) -> Option<Hit<'a>> {
+        let hit = |(trigger, node): (&'a DomainName, &'a Node), below: bool| {
+            let (kind, rule) = if below { &node.below } else { &node.exact }.as_ref()?;
+            Some(Hit {
+                trigger,
+                kind: *kind,
+                rule,
+            })
+        };
+        if let Some(found) = self.nodes.get_key_value(name).and_then(|n| hit(n, false)) {
+            return Some(found);
+        }
+        (0..name.label_count())
+            .rev()
+            .filter_map(|n| self.nodes.get_key_value(&name.suffix(n)))
+            .find_map(|n| hit(n, true))
+    }
+
+    /// Loads a hosts file. Names mapped to an unspecified or loopback
+    /// address get `action`; names mapped to other addresses are
+    /// redirected there. Lines that cannot be parsed are skipped. Returns
+    /// the number of names loaded.
+    pub fn load_hosts(&mut self, text: &str, action: &Action, source: &str) -> usize {
+        let source: Arc<str> = source.into();
+        let mut redirects: HashMap<DomainName, Vec<RData>> = HashMap::new();
+        let mut count = 0;
+        for line in text.lines() {
+            let mut words = line.split('#').next().unwrap_or("").split_whitespace();
+            let addr = match words.next().and_then(|w| w.parse::<IpAddr>().ok()) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            for word in words {
+                if HOSTS_RESERVED.iter().any(|r| r.eq_ignore_ascii_case(word)) {
+                    continue;
+                }
+                let name = match parse_list_name(word) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                if addr.is_unspecified() || addr.is_loopback() {
+                    let rule = Rule {
+                        action: action.clone(),
+                        source: source.clone(),
+                    };
+                    self.insert(name, MatchKind::Exact, rule);
+                    count += 1;
+                } else {
+                    redirects.entry(name).or_default().push(match addr {
+                        IpAddr::V4(a) => RData::A(a),
+                        IpAddr::V6(a) => RData::Aaaa(a),
+                    });
+                }
+            }
+        }
+        for (name, data) in redirects {
+            let rule = Rule {
+                action: Action::Redirect(data),
+                source: source.clone(),
+            };
+            self.insert(name, MatchKind::Exact, rule);
+            count += 1;
+        }
+        count
+    }
+
+    /// Loads a list of domains, one per line: `name` matches exactly,
+    /// `*.name` matches names below, and the adblock form `||name^`
+    /// matches the name and everything below. `#` and `!` start comments;
+    /// lines that cannot be parsed are skipped. Returns the number of
+    /// rules loaded.
+    pub fn load_domains(&mut self, text: &str, action: &Action, source: &str) -> usize {
+        let source: Arc<str> = source.into();
+        let mut count = 0;
+        for line in text.lines() {
+            let line = line.split(['#', '!']).next().unwrap_or("").trim();
+            let (word, kind) = if let Some(rest) = line.strip_prefix("||") {
+                match rest.strip_suffix('^') {
+                    Some(word) => (word, MatchKind::Suffix),
+                    None => continue,
+                }
+            } else if let Some(word) = line.strip_prefix("*.") {
+                (word, MatchKind::Wildcard)
+            } else {
+                (line, MatchKind::Exact)
+            };
+            if let Some(name) = parse_list_name(word) {
+                let rule = Rule {
+                    action: action.clone(),
+                    source: source.clone(),
+                };
+                self.insert(name, kind, rule);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Loads the QNAME triggers of a Response Policy Zone whose origin is
+    /// `origin`. CNAMEs to `.`, `*.`, `rpz-passthru.` and `rpz-drop.` give
+    /// NXDOMAIN, NODATA, passthru and drop; other data is answered in
+    /// place of the real records. Address and name server triggers are
+    /// skipped. Returns the number of rules loaded.
+    pub fn load_rpz(
+        &mut self,
+        text: &str,
+        origin: &DomainName,
+        source: &str,
+    ) -> Result<usize, ParseError> {
+        let source: Arc<str> = source.into();
+        let mut triggers: HashMap<(DomainName, MatchKind), Action> = HashMap::new();
+        for rr in parse_records(text, origin)? {
+            let labels = rr.name.labels();
+            let depth = match labels.len().checked_sub(origin.label_count()) {
+                Some(depth) if depth > 0 && rr.name.is_subdomain_of(origin) => depth,
+                _ => continue,
+            };
+            let special = labels[depth - 1].to_ascii_lowercase();
+            if special.starts_with(b"rpz-") {
+                // rpz-ip, rpz-nsdname and the like.
+                continue;
+            }
+            let (kind, start) = if labels[0] == b"*" {
+                (MatchKind::Wildcard, 1)
+            } else {
+                (MatchKind::Exact, 0)
+            };
+            let trigger = match DomainName::from_labels(&labels[start..depth]) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let action = match &rr.rdata {
+                RData::Cname(target) if target.is_root() => Action::Nxdomain,
+                RData::Cname(target) if target.is_wildcard() && target.label_count() == 1 => {
+                    Action::Nodata
+                }
+                RData::Cname(target) if is_special(target, b"rpz-passthru") => Action::Passthru,
+                RData::Cname(target) if is_special(target, b"rpz-drop") => Action::Drop,
+                rdata => Action::Redirect(vec![rdata.clone()]),
+            };
+            // Several records at one trigger make up its local data.
+            match (triggers.get_mut(&(trigger.clone(), kind)), action) {
+                (Some(Action::Redirect(data)), Action::Redirect(more)) => data.extend(more),
+                (_, action) => {
+                    triggers.insert((trigger, kind), action);
+                }
+            }
+        }
+        let count = triggers.len();
+        for ((name, kind), action) in triggers {
+            let rule = Rule {
+                action,
+                source: source.clone(),
+            };
+            self.insert(name, kind, rule);
+        }
+        Ok(count)
+    }
+
+    /// The response for a query `hit` matched, or `None` to send nothing.
+    /// Passthru hits are left to the caller.
+    fn respond(&self, request: &Request, hit: &Hit<'_>) -> Option<Message> {
+        let mut resp = request.message.response();
+        resp.header.ra = true;
+        match &hit.rule.action {
+            Action::Nxdomain => {
+                resp.header.rcode = Rcode::NXDOMAIN;
+                resp.add_extended_error(&ExtendedError::new(ExtendedError::BLOCKED, ""));
+            }
+            Action::Nodata => {
+                resp.add_extended_error(&ExtendedError::new(ExtendedError::BLOCKED, ""));
+            }
+            Action::Redirect(data) => {
+                let q = request.message.question()?;
+                let cname = data.iter().find(|d| d.rtype() == RecordType::CNAME);
+                let answers = match cname {
+                    Some(cname) => vec![cname],
+                    None => data
+                        .iter()
+                        .filter(|d| q.qtype == RecordType::ANY || d.rtype() == q.qtype)
+                        .collect(),
+                };
+                resp.answers = answers
+                    .into_iter()
+                    .map(|d| Record::new(q.name.clone(), self.ttl, d.clone()))
+                    .collect();
+                resp.add_extended_error(&ExtendedError::new(ExtendedError::FORGED_ANSWER, ""));
+            }
+            Action::Passthru | Action::Drop => return None,
+        }
+        Some(resp)
+    }
+}
+
+/// Parses a name from a blocklist, accepting only hostname characters.
+fn parse_list_name(word: &str) -> Option<DomainName> {
+    let valid = !word.is_empty()
+        && word
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_' | b'.'));
+    if !valid {
+        return None;
+    }
+    word.parse().ok()
+}
+
+fn is_special(name: &DomainName, label: &[u8]) -> bool {
+    name.label_count() == 1 && name.labels()[0].eq_ignore_ascii_case(label)
+}
+
+impl<H: Handler> Layer<H> for Arc<Filter> {
+    type Handler = Filtered<H>;
+
+    fn layer(&self, inner: H) -> Filtered<H> {
+        Filtered {
+            filter: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler behind a [`Filter`].
+pub struct Filtered<H> {
+    filter: Arc<Filter>,
+    inner: H,
+}
+
+impl<H> Filtered<H> {
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+}
+
+impl<H: Handler> Handler for Filtered<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let msg = &request.message;
+        let hit = match msg.question() {
+            Some(q) if msg.header.opcode == Opcode::QUERY && q.qclass == RecordClass::IN => {
+                self.filter.lookup(&q.name)
+            }
+            _ => None,
+        };
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return self.inner.handle(request),
+        };
+        if let Some(logger) = &self.filter.logger {
+            logger(request, &hit);
+        }
+        match hit.rule.action {
+            Action::Passthru => self.inner.handle(request),
+            _ => self.filter.respond(request, &hit),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Policies that change answers on their way to clients.
+//!
+//! [`Filter`] blocks or redirects names from blocklists and Response Policy
+//! Zones, the way Pi-hole style resolvers do. [`Rewrite`] overrides and
+//! rewrites answers: local host addresses, CNAME flattening, address
+//! substitution for hairpin NAT and suppression of record types.
+//! [`TtlPolicy`] raises, lowers or scales the TTLs clients are given.
+
+mod filter;
+mod rewrite;
+mod ttl;
+
+pub use self::filter::{Action, Filter, Filtered, Hit, MatchKind, Rule};
+pub use self::rewrite::{Rewrite, Rewritten};
+pub use self::ttl::{TtlPolicy, TtlRewritten, TtlRule};
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::addr::{IpSet, Prefix};
+use crate::message::{Message, Opcode, Question, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+use crate::server::{Handler, Layer, Request};
+
+/// Rules that change answers: static addresses for names, CNAME
+/// flattening, address substitution and suppression of record types.
+///
+/// Static overrides and suppression answer without asking the wrapped
+/// handler. Flattening and substitution apply to the answers it gives; to
+/// have them cached in rewritten form, give the rules to the forwarder's
+/// routes with [`Route::rewrite`](crate::server::Route::rewrite) as well.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rewrite {
+    overrides: HashMap<DomainName, Vec<IpAddr>>,
+    flatten: Vec<DomainName>,
+    address_maps: Vec<(Prefix, Prefix)>,
+    suppress: Vec<(DomainName, RecordType, IpSet)>,
+    ttl: u32,
+}
+
+impl Default for Rewrite {
+    fn default() -> Rewrite {
+        Rewrite {
+            overrides: HashMap::new(),
+            flatten: Vec::new(),
+            address_maps: Vec::new(),
+            suppress: Vec::new(),
+            ttl: 60,
+        }
+    }
+}
+
+impl Rewrite {
+    pub fn new() -> Rewrite {
+        Rewrite::default()
+    }
+
+    /// TTL of the records of static overrides.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Answers A and AAAA queries for `name` with `addrs`; a family
+    /// without addresses gets no data.
+    pub fn host(mut self, name: DomainName, addrs: Vec<IpAddr>) -> Self {
+        self.overrides.insert(name, addrs);
+        self
+    }
+
+    /// Replaces CNAME chains in answers for names at or below `suffix`
+    /// with the records they lead to, owned by the query name.
+    pub fn flatten_cnames(mut self, suffix: DomainName) -> Self {
+        self.flatten.push(suffix);
+        self
+    }
+
+    /// Replaces addresses within `from` in answers by the address with the
+    /// same host part within `to`, as for hairpin NAT. Prefixes of
+    /// different families or lengths are ignored.
+    pub fn map_addresses(mut self, from: Prefix, to: Prefix) -> Self {
+        if from.len() == to.len() && from.addr().is_ipv4() == to.addr().is_ipv4() {
+            self.address_maps.push((from, to));
+        }
+        self
+    }
+
+    /// Answers queries of `qtype` for names at or below `suffix` from
+    /// `clients` with no data, e.g. AAAA for clients with broken IPv6.
+    pub fn suppress(mut self, suffix: DomainName, qtype: RecordType, clients: IpSet) -> Self {
+        self.suppress.push((suffix, qtype, clients));
+        self
+    }
+
+    /// The response for `request` if a static override or suppression
+    /// rule decides it.
+    pub fn answer(&self, request: &Request) -> Option<Message> {
+        let q = request.message.question()?;
+        let mut resp = request.message.response();
+        resp.header.ra = true;
+        let suppressed = self.suppress.iter().any(|(suffix, qtype, clients)| {
+            *qtype == q.qtype
+                && q.name.is_subdomain_of(suffix)
+                && clients.contains(&request.src.ip())
+        });
+        if suppressed {
+            return Some(resp);
+        }
+        let addrs = self.overrides.get(&q.name)?;
+        if q.qtype != RecordType::A && q.qtype != RecordType::AAAA && q.qtype != RecordType::ANY {
+            return None;
+        }
+        resp.header.aa = true;
+        resp.answers = addrs
+            .iter()
+            .filter_map(|addr| match addr {
+                IpAddr::V4(a) if q.qtype != RecordType::AAAA => Some(RData::A(*a)),
+                IpAddr::V6(a) if q.qtype != RecordType::A => Some(RData::Aaaa(*a)),
+                _ => None,
+            })
+            .map(|rdata| Record::new(q.name.clone(), self.ttl, rdata))
+            .collect();
+        Some(resp)
+    }
+
+    /// Rewrites the answers of `resp` to `question`.
+    pub fn apply(&self, question: &Question, resp: &mut Message) {
+        if resp.header.rcode != Rcode::NOERROR {
+            return;
+        }
+        if self
+            .flatten
+            .iter()
+            .any(|s| question.name.is_subdomain_of(s))
+        {
+            flatten(question, &mut resp.answers);
+        }
+        if !self.address_maps.is_empty() {
+            for rr in resp.answers.iter_mut().chain(&mut resp.additional) {
+                match &mut rr.rdata {
+                    RData::A(a) => {
+                        if let IpAddr::V4(mapped) = self.map_address(IpAddr::V4(*a)) {
+                            *a = mapped;
+                        }
+                    }
+                    RData::Aaaa(a) => {
+                        if let IpAddr::V6(mapped) = self.map_address(IpAddr::V6(*a)) {
+                            *a = mapped;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn map_address(&self, addr: IpAddr) -> IpAddr {
+        let (from, to) = match self
+            .address_maps
+            .iter()
+            .find(|(from, _)| from.contains(&addr))
+        {
+            Some(map) => map,
+            None => return addr,
+        };
+        match (addr, to.addr()) {
+            (IpAddr::V4(a), IpAddr::V4(base)) => {
+                let host = u32::from(a)
+                    & !u32::MAX
+                        .checked_shl(32 - u32::from(from.len()))
+                        .unwrap_or(0);
+                IpAddr::V4((u32::from(base) | host).into())
+            }
+            (IpAddr::V6(a), IpAddr::V6(base)) => {
+                let host = u128::from(a)
+                    & !u128::MAX
+                        .checked_shl(128 - u32::from(from.len()))
+                        .unwrap_or(0);
+                IpAddr::V6((u128::from(base) | host).into())
+            }
+            _ => addr,
+        }
+    }
+}
+
+/// Replaces the CNAME chain starting at the query name by the records of
+/// the query type it ends at, renamed to the query name. The TTL is the
+/// smallest along the chain. Chains that end without such records are
+/// left alone.
+fn flatten(question: &Question, answers: &mut Vec<Record>) {
+    if question.qtype == RecordType::CNAME {
+        return;
+    }
+    let mut name = &question.name;
+    let mut ttl = u32::MAX;
+    let mut steps = 0;
+    while let Some(cname) = answers
+        .iter()
+        .find(|rr| rr.name == *name && rr.rtype() == RecordType::CNAME)
+    {
+        match &cname.rdata {
+            RData::Cname(target) if steps < 16 => {
+                ttl = ttl.min(cname.ttl);
+                name = target;
+                steps += 1;
+            }
+            _ => return,
+        }
+    }
+    if steps == 0 {
+        return;
+    }
+    let flattened: Vec<Record> = answers
+        .iter()
+        .filter(|rr| {
+            rr.name == *name && (rr.rtype() == question.qtype || question.qtype == RecordType::ANY)
+        })
+        .map(|rr| Record {
+            name: question.name.clone(),
+            ttl: rr.ttl.min(ttl),
+            ..rr.clone()
+        })
+        .collect();
+    if !flattened.is_empty() {
+        *answers = flattened;
+    }
+}
+
+impl<H: Handler> Layer<H> for Rewrite {
+    type Handler = Rewritten<H>;
+
+    fn layer(&self, inner: H) -> Rewritten<H> {
+        Rewritten {
+            rewrite: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler whose answers are rewritten; see [`Rewrite`].
+pub struct Rewritten<H> {
+    rewrite: Rewrite,
+    inner: H,
+}
+
+impl<H> Rewritten<H> {
+    pub fn rewrite(&self) -> &Rewrite {
+        &self.rewrite
+    }
+}
+
+impl<H: Handler> Handler for Rewritten<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let msg = &request.message;
+        let q = match msg.question() {
+            Some(q) if msg.header.opcode == Opcode::QUERY && q.qclass == RecordClass::IN => q,
+            _ => return self.inner.handle(request),
+        };
+        if let Some(resp) = self.rewrite.answer(request) {
+            return Some(resp);
+        }
+        let mut resp = self.inner.handle(request)?;
+        self.rewrite.apply(q, &mut resp);
+        Some(resp)
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::clock::{self, Clock};
+use crate::message::{Message, Opcode, Question, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+use crate::server::{Handler, Layer, Request};
+
+/// How the TTLs of answers for names at or below `suffix` are changed:
+/// multiplied first, then raised to the floor, then lowered to the
+/// ceiling, which wins if the two cross.
+///
+/// The negative TTL of NXDOMAIN and NODATA answers, the lesser of the
+/// SOA's TTL and its minimum, is kept between `negative_floor` and
+/// `negative_ceiling` instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TtlRule {
+    pub suffix: DomainName,
+    /// The query types the rule is for; all if empty.
+    pub types: Vec<RecordType>,
+    pub floor: Option<u32>,
+    pub ceiling: Option<u32>,
+    pub multiplier: Option<f64>,
+    pub negative_floor: Option<u32>,
+    pub negative_ceiling: Option<u32>,
+}
+
+impl TtlRule {
+    /// A rule for names at or below `suffix` that changes nothing yet.
+    pub fn new(suffix: DomainName) -> TtlRule {
+        TtlRule {
+            suffix,
+            types: Vec::new(),
+            floor: None,
+            ceiling: None,
+            multiplier: None,
+            negative_floor: None,
+            negative_ceiling: None,
+        }
+    }
+
+    pub fn types(mut self, types: Vec<RecordType>) -> Self {
+        self.types = types;
+        self
+    }
+
+    pub fn floor(mut self, ttl: u32) -> Self {
+        self.floor = Some(ttl);
+        self
+    }
+
+    pub fn ceiling(mut self, ttl: u32) -> Self {
+        self.ceiling = Some(ttl);
+        self
+    }
+
+    pub fn multiplier(mut self, factor: f64) -> Self {
+        self.multiplier = Some(factor);
+        self
+    }
+
+    pub fn negative_floor(mut self, ttl: u32) -> Self {
+        self.negative_floor = Some(ttl);
+        self
+    }
+
+    pub fn negative_ceiling(mut self, ttl: u32) -> Self {
+        self.negative_ceiling = Some(ttl);
+        self
+    }
+
+    fn covers(&self, question: &Question) -> bool {
+        question.name.is_subdomain_of(&self.suffix)
+            && (self.types.is_empty() || self.types.contains(&question.qtype))
+    }
+
+    /// `ttl` as the rule changes it.
+    pub fn rewrite(&self, ttl: u32) -> u32 {
+        let mut ttl = match self.multiplier {
+            // The cast saturates, and takes NaN to zero.
+            Some(factor) => (f64::from(ttl) * factor).round() as u32,
+            None => ttl,
+        };
+        if let Some(floor) = self.floor {
+            ttl = ttl.max(floor);
+        }
+        if let Some(ceiling) = self.ceiling {
+            ttl = ttl.min(ceiling);
+        }
+        ttl
+    }
+
+    /// The negative TTL `ttl` as the rule changes it.
+    pub fn rewrite_negative(&self, mut ttl: u32) -> u32 {
+        if let Some(floor) = self.negative_floor {
+            ttl = ttl.max(floor);
+        }
+        if let Some(ceiling) = self.negative_ceiling {
+            ttl = ttl.min(ceiling);
+        }
+        ttl
+    }
+}
+
+/// Rules that change the TTLs of answers on their way to clients, to
+/// steer how long clients cache them. What the forwarder caches, and how
+/// long for, is left alone; see [`CachePolicy`](crate::server::CachePolicy)
+/// for that.
+///
+/// The most specific rule covering the query name and type applies to
+/// every record of the response but its RRSIGs, which are never changed.
+/// In NXDOMAIN and NODATA answers the authority section, which says how
+/// long the name or type is known to be missing, follows the rule's
+/// negative bounds rather than its positive ones. No RRset is given a TTL
+/// longer than an RRSIG covering it allows: the signature's original TTL,
+/// or the time left until it expires. Zone transfers are passed through
+/// unchanged.
+#[derive(Clone)]
+pub struct TtlPolicy {
+    rules: Vec<TtlRule>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for TtlPolicy {
+    fn default() -> TtlPolicy {
+        TtlPolicy {
+            rules: Vec::new(),
+            clock: clock::system(),
+        }
+    }
+}
+
+impl fmt::Debug for TtlPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TtlPolicy")
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+impl PartialEq for TtlPolicy {
+    fn eq(&self, other: &TtlPolicy) -> bool {
+        self.rules == other.rules
+    }
+}
+
+impl TtlPolicy {
+    pub fn new() -> TtlPolicy {
+        TtlPolicy::default()
+    }
+
+    pub fn rule(mut self, rule: TtlRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Judges how long signatures have left by `clock` rather than the
+    /// system's.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn rules(&self) -> &[TtlRule] {
+        &self.rules
+    }
+
+    /// The rule for answers to `question`, if any. Of rules with the same
+    /// suffix, one for particular types wins over one for all.
+    pub fn rule_for(&self, question: &Question) -> Option<&TtlRule> {
+        self.rules
+            .iter()
+            .filter(|r| r.covers(question))
+            .max_by_key(|r| (r.suffix.label_count(), !r.types.is_empty()))
+    }
+
+    /// Rewrites the TTLs of `resp`, an answer to `question`.
+    pub fn apply(&self, question: &Question, resp: &mut Message) {
+        let rule = match self.rule_for(question) {
+            Some(rule) => rule,
+            None => return,
+        };
+        let now = self.clock.unix_time();
+        let negative = is_negative(resp);
+        let positive = |ttl| rule.rewrite(ttl);
+        rewrite_section(&mut resp.answers, now, positive);
+        if negative {
+            let signed_soa = is_signed(&resp.authority, RecordType::SOA);
+            let ttl = resp.authority.iter().find_map(|rr| match &rr.rdata {
+                RData::Soa(soa) => Some(rule.rewrite_negative(rr.ttl.min(soa.minimum))),
+                _ => None,
+            });
+            if let Some(ttl) = ttl {
+                rewrite_section(&mut resp.authority, now, |_| ttl);
+                // Resolvers cache for the lesser of the SOA's TTL and its
+                // minimum, so both have to move; a signed SOA cannot.
+                for rr in &mut resp.authority {
+                    if let RData::Soa(soa) = &mut rr.rdata {
+                        if !signed_soa {
+                            soa.minimum = rr.ttl;
+                        }
+                    }
+                }
+            }
+        } else {
+            rewrite_section(&mut resp.authority, now, positive);
+        }
+        rewrite_section(&mut resp.additional, now, positive);
+    }
+}
+
+/// Whether `resp` says the name, or the type at it, does not exist.
+fn is_negative(resp: &Message) -> bool {
+    let has_soa = resp
+        .authority
+        .iter()
+        .any(|rr| rr.rtype() == RecordType::SOA);
+    has_soa && (resp.header.rcode == Rcode::NXDOMAIN || resp.answers.is_empty())
+}
+
+/// The type an RRSIG covers, its original TTL and its expiration, read
+/// straight from the record data so that no DNSSEC support is needed.
+fn signature(rr: &Record) -> Option<(RecordType, u32, u32)> {
+    match &rr.rdata {
+        RData::Unknown { rtype, data } if *rtype == RecordType::RRSIG && data.len() >= 12 => {
+            let u32_at =
+                |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+            Some((
+                RecordType(u16::from_be_bytes([data[0], data[1]])),
+                u32_at(4),
+                u32_at(8),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn is_signed(section: &[Record], rtype: RecordType) -> bool {
+    section
+        .iter()
+        .filter_map(signature)
+        .any(|(covered, _, _)| covered == rtype)
+}
+
+/// Sets the TTL of every record in `section` but the RRSIGs to `rewrite`
+/// of it, no higher than the signatures over its RRset allow at `now`.
+fn rewrite_section(section: &mut [Record], now: u64, rewrite: impl Fn(u32) -> u32) {
+    // Expirations are in serial number arithmetic, so the time left is
+    // the difference taken modulo 2^32; past ones come out negative.
+    let left = |expiration: u32| expiration.wrapping_sub(now as u32) as i32;
+    let limits: Vec<(DomainName, RecordType, u32)> = section
+        .iter()
+        .filter_map(|rr| {
+            let (covered, original_ttl, expiration) = signature(rr)?;
+            let limit = original_ttl.min(left(expiration).max(0) as u32);
+            Some((rr.name.clone(), covered, limit))
+        })
+        .collect();
+    for rr in section.iter_mut() {
+        if rr.rtype() == RecordType::RRSIG {
+            continue;
+        }
+        let mut ttl = rewrite(rr.ttl);
+        for (owner, covered, limit) in &limits {
+            if *covered == rr.rtype() && *owner == rr.name {
+                ttl = ttl.min(*limit);
+            }
+        }
+        rr.ttl = ttl;
+    }
+}
+
+impl<H: Handler> Layer<H> for TtlPolicy {
+    type Handler = TtlRewritten<H>;
+
+    fn layer(&self, inner: H) -> TtlRewritten<H> {
+        TtlRewritten {
+            policy: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler whose answers have their TTLs rewritten; see [`TtlPolicy`].
+pub struct TtlRewritten<H> {
+    policy: TtlPolicy,
+    inner: H,
+}
+
+impl<H> TtlRewritten<H> {
+    pub fn policy(&self) -> &TtlPolicy {
+        &self.policy
+    }
+}
+
+impl<H: Handler> Handler for TtlRewritten<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let mut resp = self.inner.handle(request)?;
+        let msg = &request.message;
+        if msg.header.opcode == Opcode::QUERY && !request.is_transfer() {
+            if let Some(q) = msg.question() {
+                self.policy.apply(q, &mut resp);
+            }
+        }
+        Some(resp)
+    }
+}
@@ -0,0 +1,220 @@
+//! DNS Push Notifications (RFC 8765).
+//!
+//! Instead of polling, a client subscribes on a DSO session to a name,
+//! type and class, and the server pushes every change to the matching
+//! records until the client unsubscribes or the session ends. A client
+//! that finds a pushed record no longer works may ask the server to
+//! reconfirm it.
+//!
+//! This module encodes and decodes the four TLVs; the client side is
+//! [`DsoSession::subscribe`](crate::client::DsoSession::subscribe) and
+//! the server side is enabled by
+//! [`Server::set_push`](crate::server::Server::set_push).
+
+use crate::dso::{DsoType, Tlv};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+use crate::wire::{self, Decoder, Encoder};
+
+/// The TTL of a pushed record that was removed.
+pub const REMOVE_RECORD: u32 = 0xFFFF_FFFF;
+
+/// The TTL of a pushed removal of every record of a name matching a type
+/// and class, either of which may be ANY.
+pub const REMOVE_ALL: u32 = 0xFFFF_FFFE;
+
+/// What a subscription is to: the records of one name, type and class.
+/// The type and class may be ANY.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Subscription {
+    pub name: DomainName,
+    pub rtype: RecordType,
+    pub class: RecordClass,
+}
+
+impl Subscription {
+    pub fn new(name: DomainName, rtype: RecordType) -> Subscription {
+        Subscription {
+            name,
+            rtype,
+            class: RecordClass::IN,
+        }
+    }
+
+    /// Whether a change to `record` concerns this subscription.
+    pub fn matches(&self, record: &Record) -> bool {
+        record.name == self.name
+            && (self.rtype == RecordType::ANY || record.rtype() == self.rtype)
+            && (self.class == RecordClass::ANY || record.class == self.class)
+    }
+
+    pub fn to_tlv(&self) -> Tlv {
+        let mut enc = Encoder::uncompressed();
+        enc.name(&self.name, false);
+        enc.u16(self.rtype.0);
+        enc.u16(self.class.0);
+        Tlv::new(DsoType::SUBSCRIBE, enc.into_bytes())
+    }
+
+    pub fn from_tlv(tlv: &Tlv) -> Result<Subscription, wire::Error> {
+        if tlv.dtype != DsoType::SUBSCRIBE {
+            return Err(wire::Error::BadRdata { offset: 0 });
+        }
+        let mut dec = Decoder::new(&tlv.data);
+        let sub = Subscription {
+            name: dec.name()?,
+            rtype: RecordType(dec.u16()?),
+            class: RecordClass(dec.u16()?),
+        };
+        if dec.remaining() > 0 {
+            return Err(dec.bad_rdata());
+        }
+        Ok(sub)
+    }
+}
+
+/// One change carried by a PUSH TLV.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// A record was added, or is there when the subscription starts.
+    Add(Record),
+    /// A record was removed; its TTL is not meaningful.
+    Remove(Record),
+    /// Every record of `name` matching `rtype` and `class`, either of
+    /// which may be ANY, was removed.
+    RemoveAll {
+        name: DomainName,
+        rtype: RecordType,
+        class: RecordClass,
+    },
+}
+
+impl Change {
+    fn encode(&self, enc: &mut Encoder) -> Result<(), wire::Error> {
+        match self {
+            Change::Add(record) => record.encode(enc),
+            Change::Remove(record) => Record {
+                ttl: REMOVE_RECORD,
+                ..record.clone()
+            }
+            .encode(enc),
+            Change::RemoveAll { name, rtype, class } => {
+                enc.name(name, false);
+                enc.u16(rtype.0);
+                enc.u16(class.0);
+                enc.u32(REMOVE_ALL);
+                enc.u16(0);
+                Ok(())
+            }
+        }
+    }
+
+    fn decode(dec: &mut Decoder<'_>) -> Result<Change, wire::Error> {
+        let name = dec.name()?;
+        let rtype = RecordType(dec.u16()?);
+        let class = RecordClass(dec.u16()?);
+        let at = dec.pos();
+        match dec.u32()? {
+            REMOVE_ALL => {
+                if dec.u16()? != 0 {
+                    return Err(wire::Error::BadRdata { offset: at + 4 });
+                }
+                Ok(Change::RemoveAll { name, rtype, class })
+            }
+            ttl => {
+                let len = usize::from(dec.u16()?);
+                let rdata = RData::decode(rtype, dec, len)?;
+                let record = Record {
+                    name,
+                    class,
+                    ttl,
+                    rdata,
+                };
+                if ttl == REMOVE_RECORD {
+                    Ok(Change::Remove(record))
+                } else if ttl > 0x7FFF_FFFF {
+                    // Reserved for future use (RFC 8765 §6.3.1).
+                    Err(wire::Error::BadRdata { offset: at })
+                } else {
+                    Ok(Change::Add(record))
+                }
+            }
+        }
+    }
+}
+
+/// PUSH TLVs carrying `changes`, as many as it takes to keep each within
+/// `max_len` bytes of data.
+pub fn push_tlvs(changes: &[Change], max_len: usize) -> Result<Vec<Tlv>, wire::Error> {
+    let mut tlvs = Vec::new();
+    let mut enc = Encoder::uncompressed();
+    for change in changes {
+        let mut one = Encoder::uncompressed();
+        change.encode(&mut one)?;
+        if !enc.is_empty() && enc.len() + one.len() > max_len {
+            tlvs.push(Tlv::new(DsoType::PUSH, enc.into_bytes()));
+            enc = Encoder::uncompressed();
+        }
+        enc.bytes(one.as_bytes());
+    }
+    if !enc.is_empty() {
+        tlvs.push(Tlv::new(DsoType::PUSH, enc.into_bytes()));
+    }
+    Ok(tlvs)
+}
+
+/// The changes of a PUSH TLV, of which there must be at least one.
+pub fn parse_push(tlv: &Tlv) -> Result<Vec<Change>, wire::Error> {
+    if tlv.dtype != DsoType::PUSH || tlv.data.is_empty() {
+        return Err(wire::Error::BadRdata { offset: 0 });
+    }
+    let mut dec = Decoder::new(&tlv.data);
+    let mut changes = Vec::new();
+    while dec.remaining() > 0 {
+        changes.push(Change::decode(&mut dec)?);
+    }
+    Ok(changes)
+}
+
+/// An UNSUBSCRIBE TLV ending the subscription made by the SUBSCRIBE
+/// request with the ID `id`.
+pub fn unsubscribe_tlv(id: u16) -> Tlv {
+    Tlv::new(DsoType::UNSUBSCRIBE, id.to_be_bytes().to_vec())
+}
+
+/// The subscription ID of an UNSUBSCRIBE TLV.
+pub fn parse_unsubscribe(tlv: &Tlv) -> Result<u16, wire::Error> {
+    if tlv.dtype != DsoType::UNSUBSCRIBE || tlv.data.len() != 2 {
+        return Err(wire::Error::BadRdata { offset: 0 });
+    }
+    Decoder::new(&tlv.data).u16()
+}
+
+/// A RECONFIRM TLV asking the server to check that `record` still
+/// exists.
+pub fn reconfirm_tlv(record: &Record) -> Result<Tlv, wire::Error> {
+    let mut enc = Encoder::uncompressed();
+    enc.name(&record.name, false);
+    enc.u16(record.rtype().0);
+    enc.u16(record.class.0);
+    record.rdata.encode(&mut enc)?;
+    Ok(Tlv::new(DsoType::RECONFIRM, enc.into_bytes()))
+}
+
+/// The record of a RECONFIRM TLV, with a TTL of zero.
+pub fn parse_reconfirm(tlv: &Tlv) -> Result<Record, wire::Error> {
+    if tlv.dtype != DsoType::RECONFIRM {
+        return Err(wire::Error::BadRdata { offset: 0 });
+    }
+    let mut dec = Decoder::new(&tlv.data);
+    let name = dec.name()?;
+    let rtype = RecordType(dec.u16()?);
+    let class = RecordClass(dec.u16()?);
+    let len = dec.remaining();
+    Ok(Record {
+        name,
+        class,
+        ttl: 0,
+        rdata: RData::decode(rtype, &mut dec, len)?,
+    })
+}
@@ -0,0 +1,537 @@
+//! Logging queries and their responses, as JSON lines or dnstap.
+//!
+//! A [`Logger`] owns a writer thread fed through a bounded queue, so that
+//! a slow disk or collector never holds up a request: when the queue is
+//! full, entries are dropped and counted instead. Entries reach it from the
+//! [`QueryLog`](crate::server::QueryLog) layer, which also samples them and
+//! redacts client addresses.
+//!
+//! dnstap frames are Frame Streams data frames holding `dnstap.Dnstap`
+//! protobuf messages, one `CLIENT_QUERY` and one `CLIENT_RESPONSE` per
+//! request. Over a Unix socket the bidirectional handshake is done with
+//! the collector; files get the unidirectional form.
+//!
+//! [`popular_questions`] reads a JSON lines log back, to find the names
+//! worth having in a cache before clients ask for them.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::addr::Prefix;
+use crate::client::Protocol;
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::rr::RecordType;
+
+/// Entries queued for the writer thread before new ones are dropped.
+const QUEUE: usize = 4096;
+
+/// The Frame Streams content type of dnstap.
+const DNSTAP_CONTENT_TYPE: &[u8] = b"protobuf:dnstap.Dnstap";
+
+/// How entries are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line.
+    JsonLines,
+    /// dnstap over Frame Streams.
+    Dnstap,
+}
+
+/// One request and its response.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// When the request arrived.
+    pub time: SystemTime,
+    /// How long the handler took to answer.
+    pub duration: Duration,
+    pub client: SocketAddr,
+    pub protocol: Protocol,
+    pub query: Message,
+    /// `None` if the handler sent nothing.
+    pub response: Option<Message>,
+}
+
+/// What is kept of client addresses. The default keeps them whole.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Redaction {
+    /// Leading bits of IPv4 addresses kept; the others are zeroed.
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    /// Whether the port is zeroed.
+    pub hide_port: bool,
+}
+
+impl Default for Redaction {
+    fn default() -> Redaction {
+        Redaction {
+            ipv4_prefix_len: 32,
+            ipv6_prefix_len: 128,
+            hide_port: false,
+        }
+    }
+}
+
+impl Redaction {
+    /// Keeps a /24 or /48 network and no port, as commonly done to log
+    /// without identifying individual clients.
+    pub fn anonymize() -> Redaction {
+        Redaction {
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 48,
+            hide_port: true,
+        }
+    }
+
+    pub fn apply(&self, addr: SocketAddr) -> SocketAddr {
+        let len = match addr.ip() {
+            IpAddr::V4(_) => self.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.ipv6_prefix_len.min(128),
+        };
+        let ip = Prefix::new(addr.ip(), len).map_or(addr.ip(), |p| p.addr());
+        let port = if self.hide_port { 0 } else { addr.port() };
+        SocketAddr::new(ip, port)
+    }
+}
+
+/// A sink of entries with a writer thread of its own. Dropping it writes
+/// what is queued, ends the stream properly and waits for the thread.
+pub struct Logger {
+    sender: Option<SyncSender<Entry>>,
+    dropped: AtomicU64,
+    writer: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Logger {
+    /// Writes entries to `writer`, which is buffered here.
+    pub fn new<W: Write + Send + 'static>(writer: W, format: Format) -> io::Result<Logger> {
+        Logger::spawn(Box::new(writer), None, format)
+    }
+
+    /// Appends entries to the file at `path`, creating it if needed. A
+    /// dnstap file must be new, as each holds a single stream.
+    pub fn create<P: AsRef<Path>>(path: P, format: Format) -> io::Result<Logger> {
+        let file = match format {
+            Format::JsonLines => OpenOptions::new().create(true).append(true).open(path)?,
+            Format::Dnstap => File::create(path)?,
+        };
+        Logger::new(file, format)
+    }
+
+    /// Sends dnstap to the collector listening on the Unix socket at
+    /// `path`, such as `fstrm_capture` or a resolver's dnstap input.
+    #[cfg(unix)]
+    pub fn connect_dnstap<P: AsRef<Path>>(path: P) -> io::Result<Logger> {
+        let mut stream = std::os::unix::net::UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        write_control(&mut stream, CONTROL_READY, true)?;
+        if read_control(&mut stream)? != CONTROL_ACCEPT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "collector did not accept the content type",
+            ));
+        }
+        let reader = stream.try_clone()?;
+        Logger::spawn(Box::new(stream), Some(Box::new(reader)), Format::Dnstap)
+    }
+
+    /// `finish` is the reading side of a bidirectional stream.
+    fn spawn(
+        writer: Box<dyn Write + Send>,
+        finish: Option<Box<dyn Read + Send>>,
+        format: Format,
+    ) -> io::Result<Logger> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE);
+        let writer = thread::Builder::new()
+            .name("querylog".into())
+            .spawn(move || write_entries(writer, finish, format, receiver))?;
+        Ok(Logger {
+            sender: Some(sender),
+            dropped: AtomicU64::new(0),
+            writer: Some(writer),
+        })
+    }
+
+    /// Queues `entry`, or drops it if the writer is behind or has failed.
+    pub fn log(&self, entry: Entry) {
+        let sender = self.sender.as_ref().expect("logger is open");
+        match sender.try_send(entry) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Entries dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger")
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+fn write_entries(
+    writer: Box<dyn Write + Send>,
+    finish: Option<Box<dyn Read + Send>>,
+    format: Format,
+    receiver: Receiver<Entry>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(writer);
+    if format == Format::Dnstap {
+        write_control(&mut out, CONTROL_START, true)?;
+    }
+    loop {
+        // Flush only once the queue is empty, so that a busy server
+        // writes in large chunks.
+        let entry = match receiver.try_recv() {
+            Ok(entry) => entry,
+            Err(TryRecvError::Empty) => {
+                out.flush()?;
+                match receiver.recv() {
+                    Ok(entry) => entry,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        match format {
+            Format::JsonLines => {
+                out.write_all(json_line(&entry).as_bytes())?;
+            }
+            Format::Dnstap => {
+                for frame in dnstap_frames(&entry) {
+                    out.write_all(&(frame.len() as u32).to_be_bytes())?;
+                    out.write_all(&frame)?;
+                }
+            }
+        }
+    }
+    if format == Format::Dnstap {
+        write_control(&mut out, CONTROL_STOP, false)?;
+    }
+    out.flush()?;
+    if let Some(mut reader) = finish {
+        read_control(&mut reader)?;
+    }
+    Ok(())
+}
+
+/// Renders an entry as a line of JSON.
+pub fn json_line(entry: &Entry) -> String {
+    let mut line = String::from("{");
+    let _ = write!(line, "\"time\":\"{}\"", rfc3339(entry.time));
+    let _ = write!(line, ",\"client\":\"{}\"", entry.client.ip());
+    if entry.client.port() != 0 {
+        let _ = write!(line, ",\"port\":{}", entry.client.port());
+    }
+    let protocol = match entry.protocol {
+        Protocol::Udp => "udp",
+        Protocol::Tcp => "tcp",
+        Protocol::Tls => "tls",
+    };
+    let _ = write!(line, ",\"protocol\":\"{}\"", protocol);
+    let _ = write!(line, ",\"id\":{}", entry.query.header.id);
+    let _ = write!(line, ",\"opcode\":\"{}\"", entry.query.header.opcode);
+    if let Some(q) = entry.query.question() {
+        let _ = write!(
+            line,
+            ",\"qname\":{},\"qtype\":\"{}\",\"qclass\":\"{}\"",
+            json_string(&q.name.to_string()),
+            q.qtype,
+            q.qclass
+        );
+    }
+    match &entry.response {
+        Some(resp) => {
+            let _ = write!(
+                line,
+                ",\"rcode\":\"{}\",\"answers\":{},\"truncated\":{}",
+                resp.header.rcode,
+                resp.answers.len(),
+                resp.header.tc
+            );
+        }
+        None => line.push_str(",\"rcode\":null"),
+    }
+    let _ = write!(line, ",\"duration_us\":{}}}", entry.duration.as_micros());
+    line.push('\n');
+    line
+}
+
+/// The `limit` questions asked most often in `input`, most popular first.
+/// Lines are either entries of a JSON lines log, of which `qname` and
+/// `qtype` are used, or a name and optionally a type (A if none), as in a
+/// hand-written list; blank lines, `#` comments and lines that are
+/// neither are skipped.
+pub fn popular_questions<R: BufRead>(
+    input: R,
+    limit: usize,
+) -> io::Result<Vec<(DomainName, RecordType)>> {
+    let mut counts: HashMap<(DomainName, RecordType), u64> = HashMap::new();
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        let question = if line.starts_with('{') {
+            json_field(line, "qname").zip(json_field(line, "qtype"))
+        } else if line.is_empty() || line.starts_with('#') {
+            None
+        } else {
+            let mut words = line.split_whitespace();
+            let name = words.next().map(str::to_string);
+            name.zip(Some(words.next().unwrap_or("A").to_string()))
+        };
+        let parsed = question.and_then(|(name, rtype)| {
+            let name: DomainName = name.parse().ok()?;
+            Some((name.to_lowercase(), rtype.parse().ok()?))
+        });
+        if let Some(question) = parsed {
+            *counts.entry(question).or_default() += 1;
+        }
+    }
+    let mut ranked: Vec<_> = counts.into_iter().collect();
+    // Ties are broken by name, so the result does not depend on hashing.
+    ranked.sort_by(|(a, m), (b, n)| {
+        n.cmp(m)
+            .then_with(|| a.0.cmp(&b.0))
+            .then_with(|| a.1 .0.cmp(&b.1 .0))
+    });
+    ranked.truncate(limit);
+    Ok(ranked.into_iter().map(|(q, _)| q).collect())
+}
+
+/// The string value of `field` in a line [`json_line`] wrote.
+fn json_field(line: &str, field: &str) -> Option<String> {
+    let start = line.find(&format!("\"{}\":\"", field))? + field.len() + 4;
+    let mut value = String::new();
+    let mut chars = line[start..].chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Formats a time as RFC 3339 in UTC with microseconds.
+fn rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since the epoch, after Howard Hinnant.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since.subsec_micros()
+    )
+}
+
+const CONTROL_ACCEPT: u32 = 1;
+const CONTROL_START: u32 = 2;
+const CONTROL_STOP: u32 = 3;
+#[cfg_attr(not(unix), allow(dead_code))]
+const CONTROL_READY: u32 = 4;
+const CONTROL_FINISH: u32 = 5;
+const FIELD_CONTENT_TYPE: u32 = 1;
+
+/// Writes a Frame Streams control frame, with the dnstap content type if
+/// `content_type` is set.
+fn write_control<W: Write>(out: &mut W, kind: u32, content_type: bool) -> io::Result<()> {
+    let mut frame = kind.to_be_bytes().to_vec();
+    if content_type {
+        frame.extend_from_slice(&FIELD_CONTENT_TYPE.to_be_bytes());
+        frame.extend_from_slice(&(DNSTAP_CONTENT_TYPE.len() as u32).to_be_bytes());
+        frame.extend_from_slice(DNSTAP_CONTENT_TYPE);
+    }
+    out.write_all(&0u32.to_be_bytes())?;
+    out.write_all(&(frame.len() as u32).to_be_bytes())?;
+    out.write_all(&frame)?;
+    out.flush()
+}
+
+/// Reads a control frame and returns its type.
+fn read_control<R: Read + ?Sized>(input: &mut R) -> io::Result<u32> {
+    let mut word = [0u8; 4];
+    input.read_exact(&mut word)?;
+    if u32::from_be_bytes(word) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "expected a control frame",
+        ));
+    }
+    input.read_exact(&mut word)?;
+    let len = u32::from_be_bytes(word) as usize;
+    if !(4..=512).contains(&len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad control frame length",
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    input.read_exact(&mut frame)?;
+    let kind = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]);
+    match kind {
+        CONTROL_ACCEPT | CONTROL_FINISH => Ok(kind),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected control frame",
+        )),
+    }
+}
+
+/// The `dnstap.Message` types used here.
+const CLIENT_QUERY: u64 = 5;
+const CLIENT_RESPONSE: u64 = 6;
+
+/// The dnstap messages of an entry: its query, then its response.
+pub fn dnstap_frames(entry: &Entry) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let query = entry.query.to_wire().ok();
+    frames.push(dnstap_message(
+        entry,
+        CLIENT_QUERY,
+        query.as_deref(),
+        None,
+        entry.time,
+    ));
+    if let Some(resp) = &entry.response {
+        let wire = resp.to_wire().ok();
+        frames.push(dnstap_message(
+            entry,
+            CLIENT_RESPONSE,
+            query.as_deref(),
+            wire.as_deref(),
+            entry.time + entry.duration,
+        ));
+    }
+    frames
+}
+
+fn dnstap_message(
+    entry: &Entry,
+    kind: u64,
+    query: Option<&[u8]>,
+    response: Option<&[u8]>,
+    time: SystemTime,
+) -> Vec<u8> {
+    let mut msg = Vec::new();
+    proto_varint_field(&mut msg, 1, kind);
+    let (family, addr) = match entry.client.ip() {
+        IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+    };
+    proto_varint_field(&mut msg, 2, family);
+    let protocol = match entry.protocol {
+        Protocol::Udp => 1,
+        Protocol::Tcp => 2,
+        Protocol::Tls => 3,
+    };
+    proto_varint_field(&mut msg, 3, protocol);
+    proto_bytes_field(&mut msg, 4, &addr);
+    proto_varint_field(&mut msg, 6, u64::from(entry.client.port()));
+    let since = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let query_time = since(entry.time);
+    proto_varint_field(&mut msg, 8, query_time.as_secs());
+    proto_fixed32_field(&mut msg, 9, query_time.subsec_nanos());
+    if let Some(query) = query.filter(|_| response.is_none()) {
+        proto_bytes_field(&mut msg, 10, query);
+    }
+    if let Some(response) = response {
+        let time = since(time);
+        proto_varint_field(&mut msg, 12, time.as_secs());
+        proto_fixed32_field(&mut msg, 13, time.subsec_nanos());
+        proto_bytes_field(&mut msg, 14, response);
+    }
+
+    let mut dnstap = Vec::new();
+    let version = format!("mairudns {}", env!("CARGO_PKG_VERSION"));
+    proto_bytes_field(&mut dnstap, 2, version.as_bytes());
+    proto_bytes_field(&mut dnstap, 14, &msg);
+    // Type MESSAGE.
+    proto_varint_field(&mut dnstap, 15, 1);
+    dnstap
+}
+
+fn proto_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn proto_varint_field(out: &mut Vec<u8>, field: u64, value: u64) {
+    proto_varint(out, field << 3);
+    proto_varint(out, value);
+}
+
+fn proto_fixed32_field(out: &mut Vec<u8>, field: u64, value: u32) {
+    proto_varint(out, field << 3 | 5);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn proto_bytes_field(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    proto_varint(out, field << 3 | 2);
+    proto_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
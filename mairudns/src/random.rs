@@ -0,0 +1,25 @@
+//! Non-cryptographic randomness for query IDs, ports and jitter.
+//!
+//! Each call draws fresh keys from the standard library's randomly seeded
+//! `RandomState` and mixes in a process-wide counter, which is unpredictable
+//! enough for transaction IDs without pulling in a dependency. On
+//! wasm32-unknown-unknown, where `RandomState` has no entropy to draw on,
+//! the `wasm` feature mixes in the host's `Math.random()`.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub fn u64() -> u64 {
+    let mut h = RandomState::new().build_hasher();
+    h.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    #[cfg(all(feature = "wasm", target_arch = "wasm32", target_os = "unknown"))]
+    h.write_u64(js_sys::Math::random().to_bits());
+    h.finish()
+}
+
+pub fn u16() -> u16 {
+    u64() as u16
+}
@@ -0,0 +1,460 @@
+//! The root servers resolution starts from: hints built in or read from
+//! `named.root`, and kept current by the priming query.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::client;
+use crate::clock::{self, Clock};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::resolver::scrub;
+use crate::rr::{RData, Record, RecordType};
+use crate::zone::{parse_records, ParseError};
+
+/// The root servers as IANA's `named.root` of December 2023 lists them:
+/// name, IPv4 and IPv6 address.
+const IANA_ROOT_SERVERS: &[(&str, &str, &str)] = &[
+    ("a.root-servers.net.", "198.41.0.4", "2001:503:ba3e::2:30"),
+    ("b.root-servers.net.", "170.247.170.2", "2801:1b8:10::b"),
+    ("c.root-servers.net.", "192.33.4.12", "2001:500:2::c"),
+    ("d.root-servers.net.", "199.7.91.13", "2001:500:2d::d"),
+    ("e.root-servers.net.", "192.203.230.10", "2001:500:a8::e"),
+    ("f.root-servers.net.", "192.5.5.241", "2001:500:2f::f"),
+    ("g.root-servers.net.", "192.112.36.4", "2001:500:12::d0d"),
+    ("h.root-servers.net.", "198.97.190.53", "2001:500:1::53"),
+    ("i.root-servers.net.", "192.36.148.17", "2001:7fe::53"),
+    ("j.root-servers.net.", "192.58.128.30", "2001:503:c27::2:30"),
+    ("k.root-servers.net.", "193.0.14.129", "2001:7fd::1"),
+    ("l.root-servers.net.", "199.7.83.42", "2001:500:9f::42"),
+    ("m.root-servers.net.", "202.12.27.33", "2001:dc3::35"),
+];
+
+/// The TTL `named.root` gives its records.
+const HINTS_TTL: u32 = 3_600_000;
+
+/// How long to wait before priming again after it failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The shortest time between two primings, whatever the TTL says.
+const MIN_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How often a wait for the next priming looks at the clock again, which
+/// may be moved further than the time waited.
+const CLOCK_CHECK: Duration = Duration::from_millis(100);
+
+/// Errors of reading root hints.
+#[derive(Debug)]
+pub enum HintsError {
+    Io(io::Error),
+    Parse(ParseError),
+    /// No root server with an address.
+    NoServers,
+}
+
+impl fmt::Display for HintsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HintsError::Io(e) => write!(f, "{}", e),
+            HintsError::Parse(e) => write!(f, "{}", e),
+            HintsError::NoServers => f.write_str("no root server with an address"),
+        }
+    }
+}
+
+impl std::error::Error for HintsError {}
+
+impl From<io::Error> for HintsError {
+    fn from(e: io::Error) -> HintsError {
+        HintsError::Io(e)
+    }
+}
+
+impl From<ParseError> for HintsError {
+    fn from(e: ParseError) -> HintsError {
+        HintsError::Parse(e)
+    }
+}
+
+/// Errors of the priming query.
+#[derive(Debug)]
+pub enum PrimeError {
+    /// No root server answered; holds the last failure.
+    Unreachable(client::Error),
+    /// The servers answered, but not with the root's name servers.
+    BadResponse(String),
+}
+
+impl fmt::Display for PrimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrimeError::Unreachable(e) => write!(f, "no root server answered: {}", e),
+            PrimeError::BadResponse(why) => write!(f, "bad priming response: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for PrimeError {}
+
+/// A root server: its name and addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootServer {
+    pub name: DomainName,
+    pub addresses: Vec<IpAddr>,
+}
+
+/// The names and addresses of the root servers, and how long they may be
+/// relied on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootHints {
+    servers: Vec<RootServer>,
+    ttl: u32,
+}
+
+impl Default for RootHints {
+    fn default() -> RootHints {
+        RootHints::iana()
+    }
+}
+
+impl RootHints {
+    /// The IANA root hints built into the crate.
+    pub fn iana() -> RootHints {
+        let servers = IANA_ROOT_SERVERS
+            .iter()
+            .map(|&(name, v4, v6)| RootServer {
+                name: name.parse().expect("valid root server name"),
+                addresses: vec![
+                    v4.parse().expect("valid root server address"),
+                    v6.parse().expect("valid root server address"),
+                ],
+            })
+            .collect();
+        RootHints {
+            servers,
+            ttl: HINTS_TTL,
+        }
+    }
+
+    /// Hints from the records of a `named.root` file: NS records of the
+    /// root and the addresses of the servers they name.
+    pub fn parse(text: &str) -> Result<RootHints, HintsError> {
+        RootHints::from_records(&parse_records(text, &DomainName::root())?)
+    }
+
+    /// Reads a `named.root` file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<RootHints, HintsError> {
+        RootHints::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Hints from the root's NS records among `records` and the addresses
+    /// there of the servers they name. The TTL is the NS records' least.
+    pub fn from_records(records: &[Record]) -> Result<RootHints, HintsError> {
+        let root = DomainName::root();
+        let mut servers: Vec<RootServer> = Vec::new();
+        let mut ttl = u32::MAX;
+        for rr in records.iter().filter(|rr| rr.name == root) {
+            if let RData::Ns(name) = &rr.rdata {
+                ttl = ttl.min(rr.ttl);
+                if !servers.iter().any(|s| s.name == *name) {
+                    servers.push(RootServer {
+                        name: name.clone(),
+                        addresses: Vec::new(),
+                    });
+                }
+            }
+        }
+        for rr in records {
+            let addr = match rr.rdata {
+                RData::A(a) => IpAddr::V4(a),
+                RData::Aaaa(a) => IpAddr::V6(a),
+                _ => continue,
+            };
+            if let Some(server) = servers.iter_mut().find(|s| s.name == rr.name) {
+                if !server.addresses.contains(&addr) {
+                    server.addresses.push(addr);
+                }
+            }
+        }
+        servers.retain(|s| !s.addresses.is_empty());
+        if servers.is_empty() {
+            return Err(HintsError::NoServers);
+        }
+        Ok(RootHints { servers, ttl })
+    }
+
+    pub fn servers(&self) -> &[RootServer] {
+        &self.servers
+    }
+
+    /// How long, in seconds, the list may be relied on.
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// Every address of every root server, on port 53.
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.servers
+            .iter()
+            .flat_map(|s| s.addresses.iter().map(|&ip| SocketAddr::new(ip, 53)))
+            .collect()
+    }
+
+    /// The hints as records: the root's NS records and the servers'
+    /// addresses, as in `named.root`.
+    pub fn to_records(&self) -> Vec<Record> {
+        let root = DomainName::root();
+        let ns = self
+            .servers
+            .iter()
+            .map(|s| Record::new(root.clone(), self.ttl, RData::Ns(s.name.clone())));
+        let addresses = self.servers.iter().flat_map(|s| {
+            s.addresses.iter().map(move |&ip| {
+                let rdata = match ip {
+                    IpAddr::V4(a) => RData::A(a),
+                    IpAddr::V6(a) => RData::Aaaa(a),
+                };
+                Record::new(s.name.clone(), self.ttl, rdata)
+            })
+        });
+        ns.chain(addresses).collect()
+    }
+}
+
+/// Sends the priming query (RFC 8109) to the servers of `hints`, in a
+/// random order until one answers, and returns the root servers it names.
+///
+/// The response must be an answer for the root's NS records. Servers it
+/// gives no address for keep those of `hints`, if it has any.
+pub fn prime(hints: &RootHints, timeout: Duration) -> Result<RootHints, PrimeError> {
+    let mut query = Message::query(DomainName::root(), RecordType::NS);
+    query.header.rd = false;
+    let mut servers = hints.addresses();
+    // Spreads priming across the root servers (RFC 8109 §3.2).
+    for i in (1..servers.len()).rev() {
+        servers.swap(i, (random::u64() % (i as u64 + 1)) as usize);
+    }
+    let mut last = None;
+    for server in servers {
+        let resp = match exchange(server, &query, timeout) {
+            Ok(resp) => resp,
+            Err(e) => {
+                last = Some(PrimeError::Unreachable(e));
+                continue;
+            }
+        };
+        match primed(hints, resp) {
+            Ok(primed) => return Ok(primed),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last.unwrap_or(PrimeError::Unreachable(client::Error::Timeout)))
+}
+
+/// Sends `query` over UDP, and again over TCP if the response was
+/// truncated.
+fn exchange(
+    server: SocketAddr,
+    query: &Message,
+    timeout: Duration,
+) -> Result<Message, client::Error> {
+    let resp = client::exchange_udp(server, query, timeout)?;
+    if resp.header.tc {
+        return client::exchange_tcp(server, query, timeout);
+    }
+    Ok(resp)
+}
+
+/// The root servers a priming response names.
+fn primed(hints: &RootHints, mut resp: Message) -> Result<RootHints, PrimeError> {
+    if resp.header.rcode != Rcode::NOERROR {
+        return Err(PrimeError::BadResponse(format!(
+            "server responded {}",
+            resp.header.rcode
+        )));
+    }
+    if !resp.header.aa {
+        return Err(PrimeError::BadResponse("not authoritative".into()));
+    }
+    scrub(&mut resp, &DomainName::root());
+    let mut records: Vec<Record> = resp.answers.clone();
+    records.extend(resp.additional.iter().cloned());
+    let names: Vec<&DomainName> = resp
+        .answers
+        .iter()
+        .filter_map(|rr| match &rr.rdata {
+            RData::Ns(name) if rr.name.is_root() => Some(name),
+            _ => None,
+        })
+        .collect();
+    if names.is_empty() {
+        return Err(PrimeError::BadResponse("no NS records for the root".into()));
+    }
+    for server in hints.servers() {
+        let known = records.iter().any(|rr| {
+            rr.name == server.name && matches!(rr.rtype(), RecordType::A | RecordType::AAAA)
+        });
+        if names.contains(&&server.name) && !known {
+            records.extend(
+                RootHints {
+                    servers: vec![server.clone()],
+                    ttl: hints.ttl,
+                }
+                .to_records()
+                .into_iter()
+                .filter(|rr| !rr.name.is_root()),
+            );
+        }
+    }
+    RootHints::from_records(&records)
+        .map_err(|_| PrimeError::BadResponse("no root server with an address".into()))
+}
+
+/// Keeps the list of root servers current by priming: on start, and
+/// again once the list's TTL has run out.
+///
+/// Until the first priming succeeds, and whenever no root server
+/// answers, the hints it was made with are served.
+pub struct RootPrimer {
+    hints: RootHints,
+    current: RwLock<Arc<RootHints>>,
+    primed: RwLock<Option<u64>>,
+    last_error: Mutex<Option<String>>,
+    timeout: Duration,
+    interval: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    /// Set by [`RootPrimer::stop`] to cut the current wait short.
+    wake: (Mutex<bool>, Condvar),
+    stopped: AtomicBool,
+}
+
+impl RootPrimer {
+    pub fn new(hints: RootHints) -> RootPrimer {
+        RootPrimer {
+            current: RwLock::new(Arc::new(hints.clone())),
+            hints,
+            primed: RwLock::new(None),
+            last_error: Mutex::new(None),
+            timeout: Duration::from_secs(2),
+            interval: None,
+            clock: clock::system(),
+            wake: (Mutex::new(false), Condvar::new()),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// How long to wait for each root server.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Primes this often instead of when the TTL runs out.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// The hints the primer was made with.
+    pub fn hints(&self) -> &RootHints {
+        &self.hints
+    }
+
+    /// The root servers as last primed, or the hints before that.
+    pub fn current(&self) -> Arc<RootHints> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// When priming last succeeded, in seconds since the epoch.
+    pub fn primed_at(&self) -> Option<u64> {
+        *self.primed.read().unwrap()
+    }
+
+    /// Why the last priming [`run`](RootPrimer::run) made failed, if it
+    /// did.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Primes now, from the current list and failing that from the hints,
+    /// which may be all that still works after a long outage.
+    pub fn prime(&self) -> Result<Arc<RootHints>, PrimeError> {
+        let current = self.current();
+        let primed = match prime(&current, self.timeout) {
+            Ok(primed) => primed,
+            Err(_) if *current != self.hints => prime(&self.hints, self.timeout)?,
+            Err(e) => return Err(e),
+        };
+        let primed = Arc::new(primed);
+        *self.current.write().unwrap() = primed.clone();
+        *self.primed.write().unwrap() = Some(self.clock.unix_time());
+        Ok(primed)
+    }
+
+    /// How long until priming is next due after one that gave `hints`.
+    fn next_in(&self, hints: &RootHints) -> Duration {
+        let ttl = Duration::from_secs(u64::from(hints.ttl()));
+        self.interval.unwrap_or(ttl).max(MIN_INTERVAL)
+    }
+
+    /// Has [`run`](RootPrimer::run) return; a priming under way is
+    /// finished first.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let (flag, cvar) = &self.wake;
+        *flag.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+
+    /// Primes now and whenever it is due, until stopped. Run it on a
+    /// thread of its own.
+    pub fn run(&self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            let result = self.prime();
+            let wait = match &result {
+                Ok(primed) => self.next_in(primed),
+                Err(_) => RETRY_INTERVAL,
+            };
+            *self.last_error.lock().unwrap() = result.err().map(|e| e.to_string());
+            self.wait(wait);
+        }
+    }
+
+    /// Waits until `wait` has passed by the clock, or until stopped.
+    fn wait(&self, wait: Duration) {
+        let deadline = self.clock.now() + wait;
+        let (flag, cvar) = &self.wake;
+        let mut woken = flag.lock().unwrap();
+        while !*woken {
+            let left = deadline.saturating_duration_since(self.clock.now());
+            if left.is_zero() {
+                break;
+            }
+            woken = cvar.wait_timeout(woken, left.min(CLOCK_CHECK)).unwrap().0;
+        }
+        *woken = false;
+    }
+}
+
+impl fmt::Debug for RootPrimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RootPrimer")
+            .field("servers", &self.current().servers().len())
+            .field("primed", &self.primed_at())
+            .field("timeout", &self.timeout)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
@@ -0,0 +1,301 @@
+//! What a recursor learns of the name servers it queries, by address.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::name::DomainName;
+use crate::random;
+
+/// The round-trip time assumed of a server never asked, low enough that
+/// a new server gets tried but above what most servers answer in.
+pub const UNKNOWN_RTT: Duration = Duration::from_millis(376);
+
+/// The longest a query waits for a server, however slow it has been.
+const MAX_TIMEOUT: Duration = Duration::from_secs(12);
+
+/// The shortest a query waits for a server, however quick it has been.
+const MIN_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Servers whose expected round trip is this close to the best one's are
+/// chosen among at random, so that each gets a share of the queries and
+/// its RTT stays known.
+const RTT_BAND: Duration = Duration::from_millis(400);
+
+/// How long it takes for half of a server's timeout history to be
+/// forgiven.
+const TIMEOUT_HALF_LIFE: Duration = Duration::from_secs(60);
+
+/// Whether a server is known to handle something.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Capability {
+    #[default]
+    Unknown,
+    Supported,
+    Unsupported,
+}
+
+/// Settings of an [`InfraCache`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InfraOptions {
+    /// How long what was learned of a server is kept once nothing new
+    /// has been.
+    pub ttl: Duration,
+    /// How long a server stays lame for a zone.
+    pub lame_ttl: Duration,
+    /// Servers held at most; the least recently heard of go first.
+    pub capacity: usize,
+}
+
+impl Default for InfraOptions {
+    fn default() -> InfraOptions {
+        InfraOptions {
+            ttl: Duration::from_secs(900),
+            lame_ttl: Duration::from_secs(900),
+            capacity: 10_000,
+        }
+    }
+}
+
+/// What has been learned of one name server address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    /// Smoothed round-trip time, if the server has answered (RFC 6298).
+    pub srtt: Option<Duration>,
+    /// Variation of the round-trip time.
+    pub rttvar: Duration,
+    pub edns: Capability,
+    pub tcp: Capability,
+    /// Timeouts in its history, each counting less the older it is.
+    pub timeouts: f64,
+    /// Timeouts since it last answered.
+    pub timeouts_in_row: u32,
+    /// The zones it has answered for without authority, and until when
+    /// it is not to be asked about them.
+    pub lame: HashMap<DomainName, Instant>,
+    /// When something was last learned of it.
+    pub updated: Instant,
+}
+
+impl ServerInfo {
+    fn new(now: Instant) -> ServerInfo {
+        ServerInfo {
+            srtt: None,
+            rttvar: Duration::from_secs(0),
+            edns: Capability::Unknown,
+            tcp: Capability::Unknown,
+            timeouts: 0.0,
+            timeouts_in_row: 0,
+            lame: HashMap::new(),
+            updated: now,
+        }
+    }
+
+    /// Forgets timeouts by how long ago they were, up to `now`.
+    fn decay(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let halvings = elapsed.as_secs_f64() / TIMEOUT_HALF_LIFE.as_secs_f64();
+        self.timeouts *= 0.5f64.powf(halvings);
+        self.updated = now;
+    }
+
+    /// How long to wait for it: the RTO of RFC 6298, doubled for each
+    /// timeout in a row.
+    pub fn timeout(&self) -> Duration {
+        let rto = match self.srtt {
+            Some(srtt) => srtt + self.rttvar * 4,
+            None => UNKNOWN_RTT * 2,
+        };
+        let backoff = 1u32 << self.timeouts_in_row.min(8);
+        (rto * backoff).clamp(MIN_TIMEOUT, MAX_TIMEOUT)
+    }
+
+    /// The round trip expected of it, for choosing between servers: its
+    /// smoothed RTT, weighed down by the timeouts in its history.
+    pub fn expected_rtt(&self) -> Duration {
+        self.srtt
+            .unwrap_or(UNKNOWN_RTT)
+            .mul_f64(1.0 + self.timeouts)
+            .min(MAX_TIMEOUT)
+    }
+
+    /// Whether it is lame for `zone` at `now`.
+    pub fn is_lame(&self, zone: &DomainName, now: Instant) -> bool {
+        self.lame.get(zone).is_some_and(|&until| now < until)
+    }
+}
+
+/// What a recursor has learned of the name servers it queries, by
+/// address: how quickly they answer, whether they handle EDNS and TCP,
+/// which zones they are lame for and how often they have not answered.
+/// It is kept apart from the cache of answers, since it describes
+/// servers rather than names, and it is what the recursor orders a
+/// zone's servers by, so that it keeps away from the slow and the broken.
+#[derive(Debug, Default)]
+pub struct InfraCache {
+    options: InfraOptions,
+    servers: Mutex<HashMap<IpAddr, ServerInfo>>,
+}
+
+impl InfraCache {
+    pub fn new(options: InfraOptions) -> InfraCache {
+        InfraCache {
+            options,
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn options(&self) -> &InfraOptions {
+        &self.options
+    }
+
+    /// What is known of `server` at `now`, if anything.
+    pub fn get(&self, server: IpAddr, now: Instant) -> Option<ServerInfo> {
+        let servers = self.servers.lock().unwrap();
+        let info = servers.get(&server)?;
+        if self.expired(info, now) {
+            return None;
+        }
+        let mut info = info.clone();
+        info.decay(now);
+        Some(info)
+    }
+
+    /// Updates what is known of `server` with `f`.
+    fn update(&self, server: IpAddr, now: Instant, f: impl FnOnce(&mut ServerInfo)) {
+        let mut servers = self.servers.lock().unwrap();
+        if !servers.contains_key(&server) && servers.len() >= self.options.capacity {
+            let oldest = servers
+                .iter()
+                .min_by_key(|(_, info)| info.updated)
+                .map(|(&addr, _)| addr);
+            if let Some(oldest) = oldest {
+                servers.remove(&oldest);
+            }
+        }
+        let info = servers
+            .entry(server)
+            .or_insert_with(|| ServerInfo::new(now));
+        if self.expired(info, now) {
+            *info = ServerInfo::new(now);
+        }
+        info.decay(now);
+        f(info);
+    }
+
+    fn expired(&self, info: &ServerInfo, now: Instant) -> bool {
+        now.saturating_duration_since(info.updated) >= self.options.ttl
+    }
+
+    /// Notes that `server` answered in `rtt`.
+    pub fn answered(&self, server: IpAddr, rtt: Duration, now: Instant) {
+        self.update(server, now, |info| {
+            match info.srtt {
+                Some(srtt) => {
+                    let delta = srtt.max(rtt) - srtt.min(rtt);
+                    info.rttvar = info.rttvar.mul_f64(0.75) + delta.mul_f64(0.25);
+                    info.srtt = Some(srtt.mul_f64(0.875) + rtt.mul_f64(0.125));
+                }
+                None => {
+                    info.srtt = Some(rtt);
+                    info.rttvar = rtt / 2;
+                }
+            }
+            info.timeouts_in_row = 0;
+        });
+    }
+
+    /// Notes that `server` did not answer in time.
+    pub fn timed_out(&self, server: IpAddr, now: Instant) {
+        self.update(server, now, |info| {
+            info.timeouts += 1.0;
+            info.timeouts_in_row = info.timeouts_in_row.saturating_add(1);
+        });
+    }
+
+    /// Notes whether `server` handled a query with EDNS.
+    pub fn edns(&self, server: IpAddr, supported: bool, now: Instant) {
+        self.update(server, now, |info| info.edns = capability(supported));
+    }
+
+    /// Notes whether `server` could be reached over TCP.
+    pub fn tcp(&self, server: IpAddr, supported: bool, now: Instant) {
+        self.update(server, now, |info| info.tcp = capability(supported));
+    }
+
+    /// Notes that `server` answered for `zone` without authority or with
+    /// a referral upwards, so it is not asked about the zone for a while.
+    pub fn lame(&self, server: IpAddr, zone: &DomainName, now: Instant) {
+        let until = now + self.options.lame_ttl;
+        self.update(server, now, |info| {
+            info.lame.retain(|_, &mut until| now < until);
+            info.lame.insert(zone.clone(), until);
+        });
+    }
+
+    /// Whether `server` is lame for `zone` at `now`.
+    pub fn is_lame(&self, server: IpAddr, zone: &DomainName, now: Instant) -> bool {
+        self.get(server, now)
+            .is_some_and(|info| info.is_lame(zone, now))
+    }
+
+    /// How long to wait for `server`.
+    pub fn timeout(&self, server: IpAddr, now: Instant) -> Duration {
+        self.get(server, now)
+            .unwrap_or_else(|| ServerInfo::new(now))
+            .timeout()
+    }
+
+    /// `servers` of `zone` in the order to try them: those lame for it
+    /// last, the rest by expected round trip, except that the first is
+    /// chosen at random among those within a band of the best.
+    pub fn select(&self, servers: &[IpAddr], zone: &DomainName, now: Instant) -> Vec<IpAddr> {
+        let mut ranked: Vec<(bool, Duration, IpAddr)> = servers
+            .iter()
+            .map(|&addr| match self.get(addr, now) {
+                Some(info) => (info.is_lame(zone, now), info.expected_rtt(), addr),
+                None => (false, UNKNOWN_RTT, addr),
+            })
+            .collect();
+        ranked.sort_by_key(|&(lame, rtt, _)| (lame, rtt));
+        if let Some(&(false, best, _)) = ranked.first() {
+            let band = ranked
+                .iter()
+                .take_while(|&&(lame, rtt, _)| !lame && rtt <= best + RTT_BAND)
+                .count();
+            ranked.swap(0, (random::u64() % band as u64) as usize);
+        }
+        ranked.into_iter().map(|(_, _, addr)| addr).collect()
+    }
+
+    /// Forgets servers nothing has been learned of for the TTL.
+    pub fn expire(&self, now: Instant) {
+        let ttl = self.options.ttl;
+        self.servers
+            .lock()
+            .unwrap()
+            .retain(|_, info| now.saturating_duration_since(info.updated) < ttl);
+    }
+
+    /// How many servers are held.
+    pub fn len(&self) -> usize {
+        self.servers.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.servers.lock().unwrap().clear();
+    }
+}
+
+fn capability(supported: bool) -> Capability {
+    if supported {
+        Capability::Supported
+    } else {
+        Capability::Unsupported
+    }
+}
@@ -0,0 +1,29 @@
+//! Building blocks of a recursive resolver, which answers from the root
+//! down rather than asking another resolver.
+//!
+//! Resolution starts from the root servers. [`RootHints`] holds their
+//! names and addresses: the IANA hints built into the crate, or a
+//! `named.root` file an operator supplies. The hints only need to be
+//! right enough to reach one root server: a [`RootPrimer`] sends the
+//! priming query (RFC 8109) on start and again whenever the answer's TTL
+//! runs out, and serves the current list from that, so that addresses
+//! that have moved since the crate was built are never used for long.
+//!
+//! Below the root, the recursor picks among a zone's servers by what its
+//! [`InfraCache`] has learned of each address: smoothed round-trip times
+//! as RFC 6298 computes them, whether EDNS and TCP work, which zones the
+//! server is lame for, and how often it has not answered lately.
+//!
+//! The referrals followed are kept in a [`ReferralCache`] by zone cut, NS
+//! records and glue together, so that resolution resumes from the
+//! closest cut known. [`find_zone_cut`] is how anything else that needs
+//! a name's servers, such as QNAME minimisation (RFC 9156), forwarding an
+//! update to the primary or fetching a DS RRset, looks them up.
+
+mod hints;
+mod infra;
+mod referral;
+
+pub use self::hints::{prime, HintsError, PrimeError, RootHints, RootPrimer, RootServer};
+pub use self::infra::{Capability, InfraCache, InfraOptions, ServerInfo, UNKNOWN_RTT};
+pub use self::referral::{find_zone_cut, Delegation, ReferralCache, ReferralOptions};
@@ -0,0 +1,268 @@
+//! The zone cuts a recursor has been referred through, and the lookup of
+//! the closest one to a name.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::cache::{remaining_ttl, Expiring};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::resolver::scrub;
+use crate::rr::{RData, Record};
+
+use super::RootHints;
+
+/// Settings of a [`ReferralCache`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReferralOptions {
+    /// Delegations held at most; those closest to expiring go first.
+    pub capacity: usize,
+    /// The longest a delegation is kept, whatever its TTL.
+    pub max_ttl: u32,
+}
+
+impl Default for ReferralOptions {
+    fn default() -> ReferralOptions {
+        ReferralOptions {
+            capacity: 100_000,
+            max_ttl: 86_400,
+        }
+    }
+}
+
+/// A zone cut: the zone below it, the names of its servers and the
+/// addresses the parent gave for them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Delegation {
+    pub zone: DomainName,
+    pub ns: Vec<DomainName>,
+    /// Address records for names in `ns`.
+    pub glue: Vec<Record>,
+    /// Seconds it may still be relied on.
+    pub ttl: u32,
+}
+
+impl Delegation {
+    /// The root zone, as `hints` have it.
+    pub fn root(hints: &RootHints) -> Delegation {
+        let records = hints.to_records();
+        Delegation {
+            zone: DomainName::root(),
+            ns: hints.servers().iter().map(|s| s.name.clone()).collect(),
+            glue: records
+                .into_iter()
+                .filter(|rr| !rr.name.is_root())
+                .collect(),
+            ttl: hints.ttl(),
+        }
+    }
+
+    /// The delegation a referral gives, if `resp` is one from the servers
+    /// of `zone`: no answer, and the NS records of a zone below `zone`
+    /// that the question lies in. Records the servers of `zone` have no
+    /// authority to give are left out.
+    pub fn from_referral(resp: &Message, zone: &DomainName) -> Option<Delegation> {
+        if resp.header.rcode != Rcode::NOERROR || !resp.answers.is_empty() {
+            return None;
+        }
+        let qname = &resp.question()?.name;
+        let mut resp = resp.clone();
+        scrub(&mut resp, zone);
+        let mut cut: Option<DomainName> = None;
+        let mut ns = Vec::new();
+        let mut ttl = u32::MAX;
+        for rr in &resp.authority {
+            let name = match &rr.rdata {
+                RData::Ns(name) => name,
+                _ => continue,
+            };
+            if rr.name == *zone
+                || !rr.name.is_subdomain_of(zone)
+                || !qname.is_subdomain_of(&rr.name)
+            {
+                continue;
+            }
+            // Only one cut per referral; the first one named wins.
+            match &cut {
+                Some(cut) if *cut != rr.name => continue,
+                Some(_) => {}
+                None => cut = Some(rr.name.clone()),
+            }
+            if !ns.contains(name) {
+                ns.push(name.clone());
+            }
+            ttl = ttl.min(rr.ttl);
+        }
+        let glue = resp
+            .additional
+            .iter()
+            .filter(|rr| matches!(rr.rdata, RData::A(_) | RData::Aaaa(_)))
+            .filter(|rr| ns.contains(&rr.name))
+            .cloned()
+            .collect();
+        Some(Delegation {
+            zone: cut?,
+            ns,
+            glue,
+            ttl,
+        })
+    }
+
+    /// The addresses of the servers, as glue gives them.
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        self.glue
+            .iter()
+            .filter_map(|rr| match rr.rdata {
+                RData::A(a) => Some(IpAddr::V4(a)),
+                RData::Aaaa(a) => Some(IpAddr::V6(a)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The servers with no glue, whose addresses must be looked up.
+    pub fn unresolved(&self) -> Vec<&DomainName> {
+        self.ns
+            .iter()
+            .filter(|name| !self.glue.iter().any(|rr| rr.name == **name))
+            .collect()
+    }
+
+    /// The delegation as records: the NS RRset and the glue.
+    pub fn to_records(&self) -> Vec<Record> {
+        let ns = self
+            .ns
+            .iter()
+            .map(|name| Record::new(self.zone.clone(), self.ttl, RData::Ns(name.clone())));
+        ns.chain(self.glue.iter().cloned()).collect()
+    }
+}
+
+/// The delegations a recursor has followed, by zone cut, for as long as
+/// their NS records may be cached.
+///
+/// It is what lets resolution start at the closest known cut instead of
+/// the root, and what anything else that needs to know which servers a
+/// name's zone is delegated to should ask: see [`find_zone_cut`].
+#[derive(Debug, Default)]
+pub struct ReferralCache {
+    options: ReferralOptions,
+    cuts: Mutex<HashMap<DomainName, Expiring<Delegation>>>,
+}
+
+impl ReferralCache {
+    pub fn new(options: ReferralOptions) -> ReferralCache {
+        ReferralCache {
+            options,
+            cuts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn options(&self) -> &ReferralOptions {
+        &self.options
+    }
+
+    /// Keeps `delegation` for its TTL, replacing what was held for its
+    /// zone.
+    pub fn insert(&self, mut delegation: Delegation, now: Instant) {
+        delegation.ttl = delegation.ttl.min(self.options.max_ttl);
+        let ttl = Duration::from_secs(u64::from(delegation.ttl));
+        let mut cuts = self.cuts.lock().unwrap();
+        if !cuts.contains_key(&delegation.zone) && cuts.len() >= self.options.capacity {
+            cuts.retain(|_, cut| !cut.is_expired(now));
+            if cuts.len() >= self.options.capacity {
+                let soonest = cuts
+                    .iter()
+                    .min_by_key(|(_, cut)| cut.expires())
+                    .map(|(zone, _)| zone.clone());
+                if let Some(soonest) = soonest {
+                    cuts.remove(&soonest);
+                }
+            }
+        }
+        cuts.insert(
+            delegation.zone.clone(),
+            Expiring::kept_for(delegation, now, ttl),
+        );
+    }
+
+    /// Keeps the delegation `resp` gives, if it is a referral from the
+    /// servers of `zone`, and returns its zone.
+    pub fn learn(&self, resp: &Message, zone: &DomainName, now: Instant) -> Option<DomainName> {
+        let delegation = Delegation::from_referral(resp, zone)?;
+        let cut = delegation.zone.clone();
+        self.insert(delegation, now);
+        Some(cut)
+    }
+
+    /// The delegation held for `zone`, with the TTL it has left.
+    pub fn get(&self, zone: &DomainName, now: Instant) -> Option<Delegation> {
+        let cuts = self.cuts.lock().unwrap();
+        let cut = cuts.get(zone)?;
+        cut.get(now)?;
+        let mut delegation = cut.value().clone();
+        delegation.ttl = remaining_ttl(delegation.ttl, cut.stored(), now);
+        Some(delegation)
+    }
+
+    /// The delegation held for the zone closest above or at `name`.
+    pub fn closest(&self, name: &DomainName, now: Instant) -> Option<Delegation> {
+        let mut zone = Some(name.clone());
+        while let Some(z) = zone {
+            if let Some(delegation) = self.get(&z, now) {
+                return Some(delegation);
+            }
+            zone = z.parent();
+        }
+        None
+    }
+
+    pub fn remove(&self, zone: &DomainName) -> Option<Delegation> {
+        self.cuts
+            .lock()
+            .unwrap()
+            .remove(zone)
+            .map(Expiring::into_value)
+    }
+
+    /// Drops the delegations whose TTL has run out.
+    pub fn expire(&self, now: Instant) {
+        self.cuts
+            .lock()
+            .unwrap()
+            .retain(|_, cut| !cut.is_expired(now));
+    }
+
+    /// How many delegations are held, expired or not.
+    pub fn len(&self) -> usize {
+        self.cuts.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.cuts.lock().unwrap().clear();
+    }
+}
+
+/// The closest known zone cut above or at `name`: the delegation
+/// `referrals` hold for it, or the root as `roots` have it when they hold
+/// none.
+///
+/// The zone found is the one whose servers answer for `name`. A DS
+/// RRset is served from the parent side of a cut, so to find the servers
+/// for the DS of `name`, look up the cut above `name.parent()`.
+pub fn find_zone_cut(
+    referrals: &ReferralCache,
+    roots: &RootHints,
+    name: &DomainName,
+    now: Instant,
+) -> Delegation {
+    referrals
+        .closest(name, now)
+        .unwrap_or_else(|| Delegation::root(roots))
+}
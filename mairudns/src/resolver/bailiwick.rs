@@ -0,0 +1,128 @@
+//! Response scrubbing against cache poisoning.
+//!
+//! A server is only believed about the zone it was asked as a server for,
+//! and only about the names the question leads to: the answer must follow
+//! the CNAME and DNAME chain from the question name, the authority section
+//! may only name the zones above it, and address records in the additional
+//! section must be for name servers, mail exchangers and service targets
+//! the rest of the response names. Glue for a delegation must lie inside
+//! the delegated zone. What is left is ranked as RFC 2181 §5.4.1 ranks
+//! data, so that a cache can refuse to replace what it holds with
+//! something it should believe less.
+
+use std::collections::HashSet;
+
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+
+/// How far the data of a response may be believed, lowest first, after
+/// RFC 2181 §5.4.1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Trust {
+    /// The additional section of any response.
+    Additional,
+    /// The authority section of a non-authoritative response, such as a
+    /// referral or a negative answer relayed by a recursive resolver.
+    Authority,
+    /// The answer section of a non-authoritative response.
+    Answer,
+    /// The authority section of an authoritative response: the SOA of a
+    /// negative answer.
+    AuthoritativeAuthority,
+    /// The answer section of an authoritative response.
+    AuthoritativeAnswer,
+}
+
+impl Trust {
+    /// The rank of what a cache keeps from `resp`: its answer, or its
+    /// authority section when it has no answer.
+    pub fn of(resp: &Message) -> Trust {
+        match (resp.header.aa, resp.answers.is_empty()) {
+            (true, false) => Trust::AuthoritativeAnswer,
+            (true, true) => Trust::AuthoritativeAuthority,
+            (false, false) => Trust::Answer,
+            (false, true) => Trust::Authority,
+        }
+    }
+}
+
+/// Drops the records of `resp` that the servers for `zone` have no
+/// authority to give, or that the question does not lead to. Returns how
+/// many were dropped. A recursive resolver answers for the whole tree, so
+/// its responses are scrubbed with the root as `zone`.
+pub fn scrub(resp: &mut Message, zone: &DomainName) -> usize {
+    let before = resp.answers.len() + resp.authority.len() + resp.additional.len();
+    let qname = match resp.question() {
+        Some(q) => q.name.clone(),
+        None => return 0,
+    };
+
+    let in_zone = |rr: &Record| rr.name.is_subdomain_of(zone);
+    resp.answers.retain(in_zone);
+    resp.authority.retain(in_zone);
+    resp.additional.retain(in_zone);
+
+    // The names the question leads to through the aliases in the answer.
+    let mut chain = vec![qname];
+    loop {
+        let next = resp.answers.iter().find_map(|rr| match &rr.rdata {
+            RData::Cname(target) if chain.contains(&rr.name) && !chain.contains(target) => {
+                Some(target.clone())
+            }
+            _ => None,
+        });
+        match next {
+            Some(target) => chain.push(target),
+            None => break,
+        }
+    }
+    resp.answers.retain(|rr| match rr.rdata {
+        RData::Dname(_) => chain
+            .iter()
+            .any(|n| n != &rr.name && n.is_subdomain_of(&rr.name)),
+        _ => chain.contains(&rr.name),
+    });
+
+    // Zone data may only come from a zone above a name in the chain;
+    // denial records and signatures from anywhere within the zone.
+    let above_chain = |name: &DomainName| chain.iter().any(|n| n.is_subdomain_of(name));
+    resp.authority.retain(|rr| match rr.rtype() {
+        RecordType::NS | RecordType::SOA | RecordType::DS => above_chain(&rr.name),
+        _ => true,
+    });
+
+    // The names addresses are wanted for. Glue for the name servers of a
+    // delegation must lie inside the delegated zone.
+    let mut wanted = HashSet::new();
+    for rr in resp.answers.iter().chain(&resp.authority) {
+        match &rr.rdata {
+            RData::Ns(ns) if &rr.name != zone && ns.is_subdomain_of(&rr.name) => {
+                wanted.insert(ns.clone());
+            }
+            RData::Ns(_) if &rr.name != zone => {}
+            RData::Ns(target)
+            | RData::Mx {
+                exchange: target, ..
+            }
+            | RData::Srv { target, .. } => {
+                wanted.insert(target.clone());
+            }
+            _ => {}
+        }
+    }
+    resp.additional.retain(|rr| {
+        matches!(rr.rdata, RData::A(_) | RData::Aaaa(_)) && wanted.contains(&rr.name)
+            || rr.rtype() == RecordType::RRSIG
+    });
+    let kept: HashSet<DomainName> = resp
+        .additional
+        .iter()
+        .filter(|rr| rr.rtype() != RecordType::RRSIG)
+        .map(|rr| rr.name.clone())
+        .collect();
+    resp.additional
+        .retain(|rr| rr.rtype() != RecordType::RRSIG || kept.contains(&rr.name));
+
+    before - resp.answers.len() - resp.authority.len() - resp.additional.len()
+}
@@ -0,0 +1,234 @@
+//! Resolving many names at once.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::rr::{Record, RecordType};
+
+use super::{Error, QueryOptions, Resolver};
+
+/// Settings for [`Resolver::resolve_many`].
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// Names being resolved at once at most.
+    pub concurrency: usize,
+    /// Lookups a second started across the batch at most, retries
+    /// included, or as fast as they complete if `None`.
+    pub rate: Option<f64>,
+    /// Tries for each name after the first, for failures that may pass:
+    /// timeouts, transport errors and SERVFAIL.
+    pub retries: usize,
+    /// Retries across the whole batch at most, so that an outage does not
+    /// multiply the load; once spent, failures are final.
+    pub retry_budget: Option<usize>,
+    pub query: QueryOptions,
+}
+
+impl Default for BatchOptions {
+    fn default() -> BatchOptions {
+        BatchOptions {
+            concurrency: 64,
+            rate: None,
+            retries: 2,
+            retry_budget: None,
+            query: QueryOptions::default(),
+        }
+    }
+}
+
+/// How far a batch has got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Names taken from the input.
+    pub started: usize,
+    /// Names whose outcome is known, failures included.
+    pub completed: usize,
+    pub failed: usize,
+    pub retries: usize,
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: AtomicBool,
+    started: AtomicUsize,
+    completed: AtomicUsize,
+    failed: AtomicUsize,
+    retries: AtomicUsize,
+    /// When the next lookup may start, under a rate limit.
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl State {
+    /// Waits until the next lookup may start at `rate` lookups a second.
+    fn wait_for_slot(&self, rate: Option<f64>) {
+        let interval = match rate.filter(|r| *r > 0.0) {
+            Some(rate) => Duration::from_secs_f64(1.0 / rate),
+            None => return,
+        };
+        let slot = {
+            let mut next = self.next_slot.lock().unwrap();
+            let slot = next.map_or_else(Instant::now, |n| n.max(Instant::now()));
+            *next = Some(slot + interval);
+            slot
+        };
+        // Sleeps in short steps so that a cancelled batch ends promptly.
+        while !self.cancelled.load(Ordering::SeqCst) {
+            let left = slot.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(Duration::from_millis(20)));
+        }
+    }
+
+    /// Takes a retry from the budget, if any is left.
+    fn take_retry(&self, budget: Option<usize>) -> bool {
+        let taken = self.retries.fetch_add(1, Ordering::SeqCst);
+        if budget.is_some_and(|b| taken >= b) {
+            self.retries.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+}
+
+/// Stops a batch from another thread.
+#[derive(Clone)]
+pub struct CancelHandle {
+    state: Arc<State>,
+}
+
+impl CancelHandle {
+    /// Stops the batch starting lookups. Those under way complete and are
+    /// still yielded.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The outcomes of a batch, produced by [`Resolver::resolve_many`], in the
+/// order the lookups complete.
+///
+/// The input is read as the lookups proceed and only a few outcomes are
+/// buffered ahead of the consumer, so a batch runs in bounded memory
+/// however many names it is given. Dropping it cancels the rest.
+pub struct Batch {
+    rx: Receiver<(DomainName, Result<Vec<Record>, Error>)>,
+    state: Arc<State>,
+}
+
+impl Batch {
+    pub fn progress(&self) -> Progress {
+        Progress {
+            started: self.state.started.load(Ordering::SeqCst),
+            completed: self.state.completed.load(Ordering::SeqCst),
+            failed: self.state.failed.load(Ordering::SeqCst),
+            retries: self.state.retries.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel_handle().cancel();
+    }
+}
+
+impl Iterator for Batch {
+    type Item = (DomainName, Result<Vec<Record>, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rx.recv().ok()
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Whether a failed lookup may succeed if tried again.
+fn transient(e: &Error) -> bool {
+    matches!(e, Error::Client(_) | Error::Rcode(Rcode::SERVFAIL))
+}
+
+impl Resolver {
+    /// Looks up `qtype` for each of `names`, as [`Resolver::lookup_with`]
+    /// does, with up to [`BatchOptions::concurrency`] lookups under way.
+    pub fn resolve_many<I>(&self, names: I, qtype: RecordType, options: &BatchOptions) -> Batch
+    where
+        I: IntoIterator<Item = DomainName>,
+        I::IntoIter: Send + 'static,
+    {
+        let concurrency = options.concurrency.max(1);
+        let (tx, rx) = mpsc::sync_channel(concurrency);
+        let state = Arc::new(State::default());
+        let names = Arc::new(Mutex::new(names.into_iter()));
+        for _ in 0..concurrency {
+            let resolver = self.clone();
+            let options = options.clone();
+            let state = state.clone();
+            let names = names.clone();
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                // The name is only taken once its slot has come, so that
+                // cancelling while waiting leaves it unstarted.
+                state.wait_for_slot(options.rate);
+                if state.cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                let name = match names.lock().unwrap().next() {
+                    Some(name) => name,
+                    None => return,
+                };
+                state.started.fetch_add(1, Ordering::SeqCst);
+                let mut tries = 0;
+                let result = loop {
+                    let result = resolver.lookup_with(&name, qtype, &options.query);
+                    match &result {
+                        Err(e)
+                            if transient(e)
+                                && tries < options.retries
+                                && !state.cancelled.load(Ordering::SeqCst)
+                                && state.take_retry(options.retry_budget) =>
+                        {
+                            tries += 1;
+                            state.wait_for_slot(options.rate);
+                        }
+                        _ => break result,
+                    }
+                };
+                if result.is_err() {
+                    state.failed.fetch_add(1, Ordering::SeqCst);
+                }
+                state.completed.fetch_add(1, Ordering::SeqCst);
+                // The receiver is gone if the batch was dropped.
+                if tx.send((name, result)).is_err() {
+                    return;
+                }
+            });
+        }
+        Batch { rx, state }
+    }
+}
+
+/// Resolves `names` with the system resolver; see
+/// [`Resolver::resolve_many`].
+pub fn resolve_many<I>(names: I, qtype: RecordType, options: &BatchOptions) -> io::Result<Batch>
+where
+    I: IntoIterator<Item = DomainName>,
+    I::IntoIter: Send + 'static,
+{
+    Ok(Resolver::system()?.resolve_many(names, qtype, options))
+}
@@ -0,0 +1,560 @@
+//! Discovery of Designated Resolvers (RFC 9462): asking a resolver known
+//! only by its address for the encrypted transports it offers, published
+//! as SVCB records at `_dns.resolver.arpa` (RFC 9461), and moving queries
+//! to one of them once its certificate shows it speaks for that address.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::client::{self, Proxy, QuicConnector, TlsConnector};
+use crate::der;
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, RecordType};
+use crate::server::{ALPN_DOQ, ALPN_DOT, ALPN_H2, ALPN_HTTP1};
+use crate::wire::{Decoder, Encoder};
+
+use super::{Error, Resolver, ResolverConfig};
+
+/// The name resolvers publish their designations at.
+pub const DESIGNATION_NAME: &str = "_dns.resolver.arpa.";
+
+/// An SVCB record (RFC 9460).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Svcb {
+    /// Zero for an alias, otherwise the order the service is tried in.
+    pub priority: u16,
+    pub target: DomainName,
+    /// The SvcParams, each as its key and value, in ascending key order.
+    pub params: Vec<(u16, Vec<u8>)>,
+}
+
+impl Svcb {
+    pub const ALPN: u16 = 1;
+    pub const NO_DEFAULT_ALPN: u16 = 2;
+    pub const PORT: u16 = 3;
+    pub const IPV4HINT: u16 = 4;
+    pub const IPV6HINT: u16 = 6;
+    pub const DOHPATH: u16 = 7;
+
+    pub fn from_rdata(rdata: &RData) -> Option<Svcb> {
+        let data = match rdata {
+            RData::Unknown { rtype, data } if *rtype == RecordType::SVCB => data,
+            _ => return None,
+        };
+        let mut dec = Decoder::new(data);
+        let priority = dec.u16().ok()?;
+        let target = dec.name().ok()?;
+        let mut params: Vec<(u16, Vec<u8>)> = Vec::new();
+        while dec.remaining() > 0 {
+            let key = dec.u16().ok()?;
+            let len = dec.u16().ok()?;
+            // Keys must be unique and in ascending order (RFC 9460 §2.2).
+            if params.last().is_some_and(|&(last, _)| last >= key) {
+                return None;
+            }
+            params.push((key, dec.bytes(usize::from(len)).ok()?.to_vec()));
+        }
+        Some(Svcb {
+            priority,
+            target,
+            params,
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut enc = Encoder::uncompressed();
+        enc.u16(self.priority);
+        enc.name(&self.target, false);
+        for (key, value) in &self.params {
+            enc.u16(*key);
+            enc.u16(value.len() as u16);
+            enc.bytes(value);
+        }
+        RData::Unknown {
+            rtype: RecordType::SVCB,
+            data: enc.into_bytes(),
+        }
+    }
+
+    pub fn param(&self, key: u16) -> Option<&[u8]> {
+        self.params
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The protocols of the `alpn` parameter; empty if it is missing or
+    /// malformed.
+    pub fn alpn(&self) -> Vec<Vec<u8>> {
+        let mut value = self.param(Svcb::ALPN).unwrap_or_default();
+        let mut ids = Vec::new();
+        while let Some((&len, rest)) = value.split_first() {
+            match rest.get(..usize::from(len)) {
+                Some(id) if len > 0 => ids.push(id.to_vec()),
+                _ => return Vec::new(),
+            }
+            value = &rest[usize::from(len)..];
+        }
+        ids
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self.param(Svcb::PORT)? {
+            &[hi, lo] => Some(u16::from_be_bytes([hi, lo])),
+            _ => None,
+        }
+    }
+
+    /// The addresses of the `ipv4hint` and `ipv6hint` parameters.
+    pub fn hints(&self) -> Vec<IpAddr> {
+        let v4 = self.param(Svcb::IPV4HINT).unwrap_or_default();
+        let v6 = self.param(Svcb::IPV6HINT).unwrap_or_default();
+        let mut addrs: Vec<IpAddr> = Vec::new();
+        if v4.len().is_multiple_of(4) {
+            addrs.extend(
+                v4.chunks(4)
+                    .map(|a| IpAddr::V4(Ipv4Addr::new(a[0], a[1], a[2], a[3]))),
+            );
+        }
+        if v6.len().is_multiple_of(16) {
+            addrs.extend(v6.chunks(16).map(|a| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(a);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }));
+        }
+        addrs
+    }
+
+    /// The URI template of the `dohpath` parameter (RFC 9461 §5).
+    pub fn dohpath(&self) -> Option<&str> {
+        std::str::from_utf8(self.param(Svcb::DOHPATH)?).ok()
+    }
+}
+
+/// An encrypted transport a resolver may designate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EncryptedTransport {
+    /// DNS over TLS (RFC 7858).
+    Tls,
+    /// DNS over HTTPS (RFC 8484), spoken here on HTTP/1.1.
+    Https,
+    /// DNS over QUIC (RFC 9250).
+    Quic,
+}
+
+impl EncryptedTransport {
+    /// The transport an ALPN protocol ID stands for.
+    pub fn from_alpn(alpn: &[u8]) -> Option<EncryptedTransport> {
+        match alpn {
+            ALPN_DOT => Some(EncryptedTransport::Tls),
+            ALPN_H2 | ALPN_HTTP1 => Some(EncryptedTransport::Https),
+            ALPN_DOQ => Some(EncryptedTransport::Quic),
+            _ => None,
+        }
+    }
+
+    /// The port the transport is offered on when a designation has none.
+    pub fn default_port(self) -> u16 {
+        match self {
+            EncryptedTransport::Tls | EncryptedTransport::Quic => 853,
+            EncryptedTransport::Https => 443,
+        }
+    }
+}
+
+impl fmt::Display for EncryptedTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EncryptedTransport::Tls => "DoT",
+            EncryptedTransport::Https => "DoH",
+            EncryptedTransport::Quic => "DoQ",
+        })
+    }
+}
+
+/// An encrypted resolver designated by an unencrypted one, at one of its
+/// addresses.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Designated {
+    pub transport: EncryptedTransport,
+    /// The Authentication Domain Name, which its certificate must be
+    /// issued for.
+    pub server_name: DomainName,
+    pub address: SocketAddr,
+    /// The path DoH queries are sent to, from the `dohpath` template
+    /// without its variables.
+    pub path: Option<String>,
+    pub priority: u16,
+}
+
+impl Designated {
+    /// The designations `svcb`, a record at [`DESIGNATION_NAME`], makes at
+    /// `addresses`, one per transport and address in the order of its
+    /// `alpn` parameter. Aliases and records naming no target designate
+    /// nothing (RFC 9461 §3), nor does DoH without a `dohpath`.
+    pub fn from_svcb(svcb: &Svcb, addresses: &[IpAddr]) -> Vec<Designated> {
+        if svcb.priority == 0 || svcb.target.is_root() {
+            return Vec::new();
+        }
+        let path = svcb
+            .dohpath()
+            .filter(|t| t.starts_with('/'))
+            .map(strip_variables);
+        let mut transports: Vec<EncryptedTransport> = Vec::new();
+        for id in svcb.alpn() {
+            match EncryptedTransport::from_alpn(&id) {
+                Some(t) if !transports.contains(&t) => transports.push(t),
+                _ => {}
+            }
+        }
+        let mut designated = Vec::new();
+        for transport in transports {
+            if transport == EncryptedTransport::Https && path.is_none() {
+                continue;
+            }
+            let port = svcb.port().unwrap_or_else(|| transport.default_port());
+            designated.extend(addresses.iter().map(|&addr| {
+                Designated {
+                    transport,
+                    server_name: svcb.target.clone(),
+                    address: SocketAddr::new(addr, port),
+                    path: path
+                        .clone()
+                        .filter(|_| transport == EncryptedTransport::Https),
+                    priority: svcb.priority,
+                }
+            }));
+        }
+        designated
+    }
+
+    /// The server name as TLS expects it, without the final dot.
+    fn host(&self) -> String {
+        let name = self.server_name.to_string();
+        name.trim_end_matches('.').to_string()
+    }
+}
+
+/// A URI template with its `{...}` expressions removed, as used for a
+/// POST request that carries the query as its body.
+fn strip_variables(template: &str) -> String {
+    let mut path = String::with_capacity(template.len());
+    let mut depth = 0;
+    for c in template.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth > 0 => depth -= 1,
+            c if depth == 0 => path.push(c),
+            _ => {}
+        }
+    }
+    path
+}
+
+/// Whether `addr` is a private or local address, for which a resolver may
+/// be designated without its certificate naming it (RFC 9462 §4.3).
+fn is_private(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(a) => a.is_private() || a.is_loopback() || a.is_link_local(),
+        IpAddr::V6(a) => {
+            let first = a.segments()[0];
+            a.is_loopback() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Errors returned by discovery.
+#[derive(Debug)]
+pub enum DiscoveryError {
+    /// The designations could not be queried.
+    Query(Error),
+    /// The resolver designates no encrypted resolver this client can use.
+    NoDesignation,
+    /// The designated resolver could not be reached.
+    Connect(client::Error),
+    /// The designated resolver's certificate is not issued for the address
+    /// of the resolver that designated it.
+    Unverified,
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiscoveryError::Query(e) => write!(f, "querying designations failed: {}", e),
+            DiscoveryError::NoDesignation => f.write_str("no usable designated resolver"),
+            DiscoveryError::Connect(e) => {
+                write!(f, "connecting to the designated resolver failed: {}", e)
+            }
+            DiscoveryError::Unverified => {
+                f.write_str("designated resolver's certificate does not name the resolver")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DiscoveryError {}
+
+impl From<Error> for DiscoveryError {
+    fn from(e: Error) -> DiscoveryError {
+        DiscoveryError::Query(e)
+    }
+}
+
+#[derive(Clone)]
+enum Connector {
+    Tls(Arc<dyn TlsConnector>),
+    Quic(Arc<dyn QuicConnector>),
+}
+
+/// A verified designated resolver that queries to its designating one are
+/// sent to instead.
+#[derive(Clone)]
+pub struct Upgrade {
+    designated: Designated,
+    connector: Connector,
+    proxy: Option<Proxy>,
+}
+
+impl Upgrade {
+    pub fn designated(&self) -> &Designated {
+        &self.designated
+    }
+
+    /// Exchanges `query` with the designated resolver over a fresh
+    /// connection.
+    pub fn exchange(&self, query: &Message, timeout: Duration) -> Result<Message, client::Error> {
+        let d = &self.designated;
+        let proxy = self.proxy.as_ref();
+        match (&self.connector, d.transport) {
+            (Connector::Quic(quic), _) => {
+                client::exchange_quic(&**quic, d.address, &d.host(), query, timeout)
+            }
+            (Connector::Tls(tls), EncryptedTransport::Https) => {
+                let path = d.path.as_deref().unwrap_or("/");
+                client::exchange_https(&**tls, d.address, proxy, &d.host(), path, query, timeout)
+            }
+            (Connector::Tls(tls), _) => {
+                client::exchange_tls(&**tls, d.address, proxy, &d.host(), query, timeout)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade")
+            .field("designated", &self.designated)
+            .finish()
+    }
+}
+
+/// Discovers and verifies the encrypted resolvers that a resolver's
+/// servers designate, over the TLS and QUIC implementations it is given;
+/// transports it has none for are passed over.
+///
+/// A designated resolver is used only if its certificate, which the
+/// connector checks for its name, also lists the address of the resolver
+/// that designated it (RFC 9462 §4.2). With
+/// [`opportunistic`](Discovery::opportunistic), a resolver at a private
+/// address may instead designate itself at that same address without
+/// being named (RFC 9462 §4.3).
+///
+/// With a [`proxy`](Discovery::proxy), designated resolvers are reached
+/// through it, and those over QUIC, which it cannot carry, are passed
+/// over.
+#[derive(Clone, Default)]
+pub struct Discovery {
+    tls: Option<Arc<dyn TlsConnector>>,
+    quic: Option<Arc<dyn QuicConnector>>,
+    opportunistic: bool,
+    proxy: Option<Proxy>,
+}
+
+impl Discovery {
+    pub fn new() -> Discovery {
+        Discovery::default()
+    }
+
+    /// Enables DoT and DoH over `connector`.
+    pub fn tls(mut self, connector: impl TlsConnector) -> Self {
+        self.tls = Some(Arc::new(connector));
+        self
+    }
+
+    /// Enables DoQ over `connector`.
+    pub fn quic(mut self, connector: impl QuicConnector) -> Self {
+        self.quic = Some(Arc::new(connector));
+        self
+    }
+
+    pub fn opportunistic(mut self, opportunistic: bool) -> Self {
+        self.opportunistic = opportunistic;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    fn connector(&self, transport: EncryptedTransport) -> Option<Connector> {
+        match transport {
+            EncryptedTransport::Tls | EncryptedTransport::Https => {
+                self.tls.clone().map(Connector::Tls)
+            }
+            EncryptedTransport::Quic if self.proxy.is_some() => None,
+            EncryptedTransport::Quic => self.quic.clone().map(Connector::Quic),
+        }
+    }
+
+    /// The designations `server` makes over the transports enabled here,
+    /// best first, as queried through `resolver`'s settings. Designated
+    /// resolvers without address hints are looked up at `server` too.
+    pub fn designations(
+        &self,
+        resolver: &Resolver,
+        server: SocketAddr,
+    ) -> Result<Vec<Designated>, DiscoveryError> {
+        let resolver = Resolver::new(ResolverConfig {
+            servers: vec![server],
+            upgrades: HashMap::new(),
+            ..resolver.config().clone()
+        });
+        let name: DomainName = DESIGNATION_NAME.parse().unwrap();
+        let response = resolver.query(&name, RecordType::SVCB)?;
+        if response.header.rcode == Rcode::NXDOMAIN {
+            return Ok(Vec::new());
+        }
+        if response.header.rcode != Rcode::NOERROR {
+            return Err(Error::Rcode(response.header.rcode).into());
+        }
+        let mut designated = Vec::new();
+        for svcb in response
+            .answers
+            .iter()
+            .filter(|rr| rr.name == name)
+            .filter_map(|rr| Svcb::from_rdata(&rr.rdata))
+        {
+            let mut addresses = svcb.hints();
+            if addresses.is_empty() && svcb.priority != 0 {
+                addresses = target_addresses(&resolver, &svcb.target);
+            }
+            designated.extend(
+                Designated::from_svcb(&svcb, &addresses)
+                    .into_iter()
+                    .filter(|d| self.connector(d.transport).is_some()),
+            );
+        }
+        designated.sort_by_key(|d| d.priority);
+        Ok(designated)
+    }
+
+    /// Connects to `designated`, a resolver that `server` designated, and
+    /// checks that it may be used in its place.
+    pub fn verify(
+        &self,
+        server: IpAddr,
+        designated: &Designated,
+        timeout: Duration,
+    ) -> Result<Upgrade, DiscoveryError> {
+        let connector = self
+            .connector(designated.transport)
+            .ok_or(DiscoveryError::NoDesignation)?;
+        let host = designated.host();
+        let connected = match (&connector, designated.transport) {
+            (Connector::Quic(quic), _) => quic
+                .open(designated.address, &host, &[ALPN_DOQ], timeout)
+                .map_err(client::Error::from),
+            (Connector::Tls(tls), transport) => {
+                let alpn = match transport {
+                    EncryptedTransport::Https => ALPN_HTTP1,
+                    _ => ALPN_DOT,
+                };
+                client::connect_tls(
+                    &**tls,
+                    designated.address,
+                    self.proxy.as_ref(),
+                    &host,
+                    alpn,
+                    timeout,
+                )
+            }
+        }
+        .map_err(DiscoveryError::Connect)?;
+        let named = connected
+            .certificates
+            .first()
+            .is_some_and(|cert| der::subject_alt_addresses(cert).contains(&server));
+        let same_private =
+            self.opportunistic && is_private(server) && designated.address.ip() == server;
+        if !named && !same_private {
+            return Err(DiscoveryError::Unverified);
+        }
+        Ok(Upgrade {
+            designated: designated.clone(),
+            connector,
+            proxy: self.proxy.clone(),
+        })
+    }
+
+    /// The best designated resolver of `server` that verifies.
+    pub fn discover(
+        &self,
+        resolver: &Resolver,
+        server: SocketAddr,
+    ) -> Result<Upgrade, DiscoveryError> {
+        let timeout = resolver.config().timeout;
+        let mut last = DiscoveryError::NoDesignation;
+        for designated in self.designations(resolver, server)? {
+            match self.verify(server.ip(), &designated, timeout) {
+                Ok(upgrade) => return Ok(upgrade),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    /// `resolver` with each of its servers that designates a verified
+    /// encrypted resolver moved to it; the others stay as they are. The
+    /// designations are looked up once; call again to follow changes.
+    pub fn upgrade(&self, resolver: &Resolver) -> Resolver {
+        let mut config = resolver.config().clone();
+        for &server in &resolver.config().servers {
+            if let Ok(upgrade) = self.discover(resolver, server) {
+                config.upgrades.insert(server, upgrade);
+            }
+        }
+        Resolver::new(config)
+    }
+}
+
+impl fmt::Debug for Discovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Discovery")
+            .field("tls", &self.tls.is_some())
+            .field("quic", &self.quic.is_some())
+            .field("opportunistic", &self.opportunistic)
+            .field("proxy", &self.proxy)
+            .finish()
+    }
+}
+
+/// The addresses of `target`, looked up through `resolver`.
+fn target_addresses(resolver: &Resolver, target: &DomainName) -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    for &qtype in [RecordType::AAAA, RecordType::A].iter() {
+        for rr in resolver.lookup(target, qtype).unwrap_or_default() {
+            match rr.rdata {
+                RData::A(a) => addrs.push(IpAddr::V4(a)),
+                RData::Aaaa(a) => addrs.push(IpAddr::V6(a)),
+                _ => {}
+            }
+        }
+    }
+    addrs
+}
@@ -0,0 +1,242 @@
+//! DNS64 synthesis of AAAA records from A records (RFC 6147), and
+//! discovery of the NAT64 prefix a network's DNS64 synthesizes with
+//! (RFC 7050).
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::addr::{self, Nat64Prefix, Prefix};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+
+use super::{Error, QueryOptions, Resolver};
+
+/// TTL used for synthesized data when the upstream gave no better bound.
+const DEFAULT_NEGATIVE_TTL: u32 = 600;
+
+/// The name a DNS64 synthesizes AAAA records for from well-known A
+/// records, revealing its NAT64 prefix (RFC 7050 §2.2).
+pub const IPV4ONLY_ARPA: &str = "ipv4only.arpa.";
+
+/// The addresses of the A records of [`IPV4ONLY_ARPA`].
+pub const IPV4ONLY_ADDRS: [Ipv4Addr; 2] =
+    [Ipv4Addr::new(192, 0, 0, 170), Ipv4Addr::new(192, 0, 0, 171)];
+
+/// The NAT64 prefixes a network's DNS64 synthesizes with, as
+/// [`discover_nat64`] finds them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nat64Discovery {
+    /// In the order the DNS64 gave them; the first is the one to use.
+    pub prefixes: Vec<Nat64Prefix>,
+    /// How long the prefixes may be relied on, in seconds: the lowest TTL
+    /// of the records they were found in. Discovery is to be run again
+    /// once it is up (§3).
+    pub ttl: u32,
+}
+
+impl Nat64Discovery {
+    /// DNS64 settings synthesizing with the first prefix.
+    pub fn dns64(&self) -> Option<Dns64> {
+        self.prefixes.first().map(|&prefix| Dns64::new(prefix))
+    }
+}
+
+/// Asks the servers of `resolver` for the AAAA records of
+/// [`IPV4ONLY_ARPA`] and finds the NAT64 prefixes they embed its addresses
+/// in (RFC 7050 §3). `None` if the servers synthesize none, so that there
+/// is no DNS64 on the way to them.
+///
+/// An address the well-known addresses could be embedded in at several
+/// prefix lengths is taken at the one both are found at across the
+/// records, and left out if that does not settle it. The records are
+/// taken as the servers give them: the DNSSEC check of §3.1, which needs
+/// a name for the NAT64 that the network's operator publishes, is left to
+/// applications.
+pub fn discover_nat64(resolver: &Resolver) -> Result<Option<Nat64Discovery>, Error> {
+    let name: DomainName = IPV4ONLY_ARPA.parse().expect("ipv4only.arpa. is valid");
+    let resp = resolver.send(&Message::query(name, RecordType::AAAA))?;
+    if resp.header.rcode != Rcode::NOERROR {
+        return Ok(None);
+    }
+    let synthesized: Vec<(Ipv6Addr, u32)> = resp
+        .answers
+        .iter()
+        .filter_map(|rr| match rr.rdata {
+            RData::Aaaa(v6) => Some((v6, rr.ttl)),
+            _ => None,
+        })
+        .collect();
+    // Every prefix each address could have either address embedded at.
+    let candidates: Vec<(Vec<Nat64Prefix>, u32)> = synthesized
+        .iter()
+        .map(|&(v6, ttl)| {
+            let prefixes: Vec<Nat64Prefix> = IPV4ONLY_ADDRS
+                .iter()
+                .flat_map(|&v4| Nat64Prefix::embedding(v6, v4))
+                .collect();
+            (prefixes, ttl)
+        })
+        .collect();
+    let embeds_both = |prefix: &Nat64Prefix| {
+        IPV4ONLY_ADDRS
+            .iter()
+            .all(|&v4| synthesized.iter().any(|&(v6, _)| v6 == prefix.embed(v4)))
+    };
+    let mut found = Nat64Discovery {
+        prefixes: Vec::new(),
+        ttl: u32::MAX,
+    };
+    for (mut prefixes, ttl) in candidates {
+        if prefixes.len() > 1 {
+            prefixes.retain(embeds_both);
+        }
+        if let [prefix] = prefixes.as_slice() {
+            if !found.prefixes.contains(prefix) {
+                found.prefixes.push(*prefix);
+            }
+            found.ttl = found.ttl.min(ttl);
+        }
+    }
+    Ok(Some(found).filter(|f| !f.prefixes.is_empty()))
+}
+
+/// DNS64 settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dns64 {
+    /// The prefix synthesized addresses are embedded in.
+    pub prefix: Nat64Prefix,
+    /// AAAA records within these prefixes count as absent (§5.1.4).
+    pub exclude_v6: Vec<Prefix>,
+    /// A records within these prefixes are never used for synthesis.
+    pub exclude_v4: Vec<Prefix>,
+    /// Answer PTR queries for addresses inside the prefix with a CNAME to
+    /// the corresponding `in-addr.arpa.` name (§5.3.1).
+    pub reverse: bool,
+}
+
+impl Dns64 {
+    /// Settings for `prefix` with the default exclusion of IPv4-mapped
+    /// addresses and reverse synthesis enabled.
+    pub fn new(prefix: Nat64Prefix) -> Dns64 {
+        Dns64 {
+            prefix,
+            exclude_v6: vec!["::ffff:0:0/96".parse().unwrap()],
+            exclude_v4: Vec::new(),
+            reverse: true,
+        }
+    }
+
+    /// Settings for the prefix [`discover_nat64`] finds first through
+    /// `resolver`; `None` if it finds none.
+    pub fn discover(resolver: &Resolver) -> Result<Option<Dns64>, Error> {
+        Ok(discover_nat64(resolver)?.and_then(|found| found.dns64()))
+    }
+
+    fn excluded(&self, addr: IpAddr) -> bool {
+        let list = if addr.is_ipv4() {
+            &self.exclude_v4
+        } else {
+            &self.exclude_v6
+        };
+        list.iter().any(|p| p.contains(&addr))
+    }
+
+    /// The IPv4 address a PTR query for `name` should be redirected to.
+    pub(super) fn reverse_target(&self, name: &DomainName) -> Option<DomainName> {
+        if !self.reverse {
+            return None;
+        }
+        match addr::parse_reverse_name(name)? {
+            IpAddr::V6(v6) => Some(addr::reverse_name(IpAddr::V4(self.prefix.extract(v6)?))),
+            IpAddr::V4(_) => None,
+        }
+    }
+
+    /// Post-processes the response to a AAAA query, synthesizing from A
+    /// records when there is no usable AAAA data. A validating client
+    /// (DO and CD both set) gets the unmodified response (§5.5).
+    pub(super) fn synthesize_aaaa(
+        &self,
+        resolver: &Resolver,
+        name: &DomainName,
+        options: &QueryOptions,
+        mut resp: Message,
+    ) -> Result<Message, Error> {
+        if options.dnssec_ok && options.checking_disabled {
+            return Ok(resp);
+        }
+        let is_usable = |rr: &Record| match rr.rdata {
+            RData::Aaaa(a) => !self.excluded(IpAddr::V6(a)),
+            _ => false,
+        };
+        if resp.answers.iter().any(is_usable) {
+            resp.answers
+                .retain(|rr| rr.rtype() != RecordType::AAAA || is_usable(rr));
+            return Ok(resp);
+        }
+        // NXDOMAIN is authoritative for every type; other failures are
+        // treated like an empty answer (§5.1.2, §5.1.3).
+        if resp.header.rcode == Rcode::NXDOMAIN {
+            return Ok(resp);
+        }
+        let negative_ttl = resp
+            .authority
+            .iter()
+            .find_map(|rr| match &rr.rdata {
+                RData::Soa(soa) => Some(rr.ttl.min(soa.minimum)),
+                _ => None,
+            })
+            .unwrap_or(DEFAULT_NEGATIVE_TTL);
+        let a = resolver.send_with(&options.message(name.clone(), RecordType::A), options)?;
+        if a.header.rcode != Rcode::NOERROR {
+            return Ok(resp);
+        }
+        let answers: Vec<Record> = a
+            .answers
+            .into_iter()
+            .filter_map(|rr| match rr.rdata {
+                RData::A(v4) if !self.excluded(IpAddr::V4(v4)) => Some(Record {
+                    rdata: RData::Aaaa(self.prefix.embed(v4)),
+                    ttl: rr.ttl.min(negative_ttl),
+                    ..rr
+                }),
+                RData::Cname(_) | RData::Dname(_) => Some(rr),
+                _ => None,
+            })
+            .collect();
+        if !answers.iter().any(|rr| rr.rtype() == RecordType::AAAA) {
+            return Ok(resp);
+        }
+        resp.header.rcode = Rcode::NOERROR;
+        // Synthesized data cannot be signed, so it is never authenticated.
+        resp.header.ad = false;
+        resp.answers = answers;
+        resp.authority.clear();
+        resp.additional.clear();
+        Ok(resp)
+    }
+
+    /// Answers a PTR query inside the NAT64 prefix by following a
+    /// synthesized CNAME to `target`.
+    pub(super) fn synthesize_ptr(
+        &self,
+        resolver: &Resolver,
+        name: &DomainName,
+        target: DomainName,
+        options: &QueryOptions,
+    ) -> Result<Message, Error> {
+        let mut resp =
+            resolver.send_with(&options.message(target.clone(), RecordType::PTR), options)?;
+        let ttl = resp
+            .answers
+            .iter()
+            .map(|rr| rr.ttl)
+            .min()
+            .unwrap_or(DEFAULT_NEGATIVE_TTL);
+        resp.questions = options.message(name.clone(), RecordType::PTR).questions;
+        resp.header.ad = false;
+        resp.answers
+            .insert(0, Record::new(name.clone(), ttl, RData::Cname(target)));
+        Ok(resp)
+    }
+}
@@ -0,0 +1,236 @@
+//! The queries a resolver has under way, and the limits on sending more.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::message::{Message, Question};
+
+/// Limits on the queries a [`Resolver`](super::Resolver) sends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SendLimits {
+    /// Queries one server may have pending at once. A query finding a
+    /// server at its limit goes to the next one, and fails with
+    /// [`Error::Busy`](super::Error::Busy) if every server is.
+    pub max_pending_per_server: usize,
+    /// Retries across every query of the resolver, so that an outage does
+    /// not multiply the load on the servers still up.
+    pub retry_budget: RetryBudget,
+}
+
+impl Default for SendLimits {
+    fn default() -> SendLimits {
+        SendLimits {
+            max_pending_per_server: 256,
+            retry_budget: RetryBudget::default(),
+        }
+    }
+}
+
+/// How many retries a resolver may send: a share of its first tries, and
+/// a few a second whatever the traffic, saved up to a limit. Once spent,
+/// a failure is final until the budget fills again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryBudget {
+    /// Retries earned by each first try.
+    pub ratio: f64,
+    /// Retries earned each second regardless.
+    pub min_per_sec: f64,
+    /// Retries saved up at most.
+    pub burst: f64,
+}
+
+impl Default for RetryBudget {
+    fn default() -> RetryBudget {
+        RetryBudget {
+            ratio: 0.2,
+            min_per_sec: 10.0,
+            burst: 100.0,
+        }
+    }
+}
+
+impl RetryBudget {
+    /// A budget that never runs out, for resolvers that must keep trying
+    /// whatever the cost.
+    pub fn unlimited() -> RetryBudget {
+        RetryBudget {
+            ratio: 0.0,
+            min_per_sec: f64::INFINITY,
+            burst: f64::INFINITY,
+        }
+    }
+}
+
+/// A query under way, as [`InFlight::outstanding`] lists it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outstanding {
+    pub server: SocketAddr,
+    pub id: u16,
+    pub question: Option<Question>,
+    pub started: Instant,
+    /// Whether it is a retry of a query another server failed.
+    pub retry: bool,
+}
+
+/// Why a query could not be sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Refusal {
+    /// The server has as many queries pending as it may.
+    Busy,
+    /// No retries are left in the budget.
+    NoRetries,
+}
+
+/// The queries a resolver has under way, shared by all its clones.
+#[derive(Debug, Default)]
+pub struct InFlight {
+    table: Mutex<Table>,
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    next: u64,
+    queries: HashMap<u64, Outstanding>,
+    pending: HashMap<SocketAddr, usize>,
+    retries: Tokens,
+    retries_refused: u64,
+}
+
+/// The retry budget's balance.
+#[derive(Debug, Default)]
+struct Tokens {
+    balance: f64,
+    updated: Option<Instant>,
+}
+
+impl Tokens {
+    /// Adds what `budget` earns by `now`, and `earned` more.
+    fn fill(&mut self, budget: &RetryBudget, earned: f64, now: Instant) {
+        match self.updated {
+            Some(at) => {
+                let elapsed = now.saturating_duration_since(at);
+                if !elapsed.is_zero() {
+                    self.balance += budget.min_per_sec * elapsed.as_secs_f64();
+                }
+            }
+            // A budget starts full.
+            None => self.balance = budget.burst,
+        }
+        self.balance = (self.balance + earned).min(budget.burst);
+        self.updated = Some(now);
+    }
+}
+
+impl InFlight {
+    pub fn new() -> InFlight {
+        InFlight::default()
+    }
+
+    /// Notes `query` as sent to `server`, unless `limits` forbid it. The
+    /// query stays in the table until the [`Pending`] returned is dropped.
+    pub(super) fn start(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        retry: bool,
+        limits: &SendLimits,
+        now: Instant,
+    ) -> Result<Pending<'_>, Refusal> {
+        let mut table = self.table.lock().unwrap();
+        let pending = table.pending.get(&server).copied().unwrap_or(0);
+        if pending >= limits.max_pending_per_server {
+            return Err(Refusal::Busy);
+        }
+        let budget = &limits.retry_budget;
+        if retry {
+            table.retries.fill(budget, 0.0, now);
+            if table.retries.balance < 1.0 {
+                table.retries_refused += 1;
+                return Err(Refusal::NoRetries);
+            }
+            table.retries.balance -= 1.0;
+        } else {
+            table.retries.fill(budget, budget.ratio, now);
+        }
+        let key = table.next;
+        table.next += 1;
+        table.queries.insert(
+            key,
+            Outstanding {
+                server,
+                id: query.header.id,
+                question: query.question().cloned(),
+                started: now,
+                retry,
+            },
+        );
+        *table.pending.entry(server).or_insert(0) += 1;
+        Ok(Pending {
+            table: &self.table,
+            key,
+            server,
+        })
+    }
+
+    /// The queries under way, oldest first.
+    pub fn outstanding(&self) -> Vec<Outstanding> {
+        let mut queries: Vec<Outstanding> = self
+            .table
+            .lock()
+            .unwrap()
+            .queries
+            .values()
+            .cloned()
+            .collect();
+        queries.sort_by_key(|q| q.started);
+        queries
+    }
+
+    /// How many queries `server` has pending.
+    pub fn pending(&self, server: SocketAddr) -> usize {
+        self.table
+            .lock()
+            .unwrap()
+            .pending
+            .get(&server)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// How many queries are under way in all.
+    pub fn len(&self) -> usize {
+        self.table.lock().unwrap().queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Retries not sent for want of budget, since the resolver was made.
+    pub fn retries_refused(&self) -> u64 {
+        self.table.lock().unwrap().retries_refused
+    }
+}
+
+/// A query's place in the [`InFlight`] table, given up when dropped.
+#[derive(Debug)]
+pub(super) struct Pending<'a> {
+    table: &'a Mutex<Table>,
+    key: u64,
+    server: SocketAddr,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        let mut table = self.table.lock().unwrap();
+        table.queries.remove(&self.key);
+        if let Some(n) = table.pending.get_mut(&self.server) {
+            *n -= 1;
+            if *n == 0 {
+                table.pending.remove(&self.server);
+            }
+        }
+    }
+}
@@ -0,0 +1,848 @@
+//! Stub resolver.
+//!
+//! [`Resolver`] sends recursive queries to a list of configured servers,
+//! retrying across servers and falling back to TCP on truncation. It is
+//! cheap to clone and safe to share between threads.
+//!
+//! Servers may also be DNSCrypt resolvers, with the `dnscrypt` feature,
+//! and may be reached over TCP through a SOCKS5 or HTTP proxy.
+//!
+//! [`Discovery`] finds the encrypted resolvers the configured servers
+//! designate (RFC 9462) and moves queries to them.
+//!
+//! An [`ErrorReporter`] sends DNS error reports (RFC 9567) through one to
+//! the agents that domains advertise.
+//!
+//! [`transfer`] runs an AXFR or IXFR as a [`Transfer`], an iterator that
+//! reads the response one message at a time, so that zones of any size
+//! can be walked without holding them in memory.
+//!
+//! [`scrub`] drops what a response has no authority to say before it is
+//! acted on or cached, and [`Trust`] ranks what remains.
+//!
+//! Every query sent is noted in the resolver's [`InFlight`] table until
+//! it completes. [`SendLimits`] cap the queries each server may have
+//! pending, turning queries away with [`Error::Busy`] once every server is
+//! at its cap, and hold retries to a budget shared by all queries, so that
+//! an outage does not set off a storm of them.
+//!
+//! The EDNS UDP payload size advertised to each server follows what is
+//! observed of the path to it, as [`UdpSizing`] describes, starting at the
+//! DNS Flag Day 2020 size of 1232 bytes.
+//!
+//! Each server's round-trip time is estimated as RFC 6298 does for TCP,
+//! or by an [`RttEstimator`] of the application's own, and kept with how
+//! its queries fared in [`ServerStats`], for applications to show.
+//!
+//! A [`Dns64`] synthesizes AAAA records for IPv4-only names, with a
+//! NAT64 prefix of its own or the one [`discover_nat64`] finds the
+//! network's DNS64 using (RFC 7050).
+//!
+//! What queries give away about their clients is up to a [`Privacy`]
+//! policy, for all servers or for each: Client Subnet options are
+//! dropped by default, and may be passed on, coarsened or suppressed, and
+//! reverse lookups may be minimised.
+//!
+//! [`Resolver::resolve_many`] looks up a stream of names as a [`Batch`],
+//! with a bound on the lookups under way, a rate limit and a budget for
+//! retries, yielding the outcomes as they complete.
+
+mod bailiwick;
+mod batch;
+mod ddr;
+mod dns64;
+mod inflight;
+mod privacy;
+mod report;
+mod sizing;
+mod stats;
+mod trace;
+mod transfer;
+
+pub use self::bailiwick::{scrub, Trust};
+pub use self::batch::{resolve_many, Batch, BatchOptions, CancelHandle, Progress};
+pub use self::ddr::{
+    Designated, Discovery, DiscoveryError, EncryptedTransport, Svcb, Upgrade, DESIGNATION_NAME,
+};
+pub use self::dns64::{discover_nat64, Dns64, Nat64Discovery, IPV4ONLY_ADDRS, IPV4ONLY_ARPA};
+pub use self::inflight::{InFlight, Outstanding, RetryBudget, SendLimits};
+pub use self::privacy::{ClientSubnet, Privacy};
+pub use self::report::{ErrorReport, ErrorReporter, ReportPolicy};
+pub use self::sizing::{PathStatus, UdpSizing, FLAG_DAY_UDP_SIZE, MIN_UDP_SIZE};
+pub use self::stats::{RttEstimator, ServerStats, SmoothedRtt, UpstreamStats};
+pub use self::trace::{trace, Attempt, ResolutionTrace, Step, StepKind};
+pub use self::transfer::{transfer, Transfer, TransferOptions};
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{self, Protocol, Proxy, TcpPool};
+#[cfg(feature = "dnscrypt")]
+use crate::dnscrypt::{DnsCrypt, Provider};
+use crate::llmnr;
+use crate::message::{EdnsOption, Message, Rcode};
+use crate::name::DomainName;
+use crate::netbios;
+use crate::rr::{RData, Record, RecordType};
+
+use self::inflight::Refusal;
+
+/// Errors returned by resolver lookups.
+#[derive(Debug)]
+pub enum Error {
+    /// Every server failed at the transport level; holds the last failure.
+    Client(client::Error),
+    /// The query completed with an error rcode.
+    Rcode(Rcode),
+    /// No servers are configured.
+    NoServers,
+    /// Every server has as many queries pending as it may; the caller
+    /// should slow down.
+    Busy,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Client(e) => write!(f, "query failed: {}", e),
+            Error::Rcode(rcode) => write!(f, "server responded {}", rcode),
+            Error::NoServers => f.write_str("no name servers configured"),
+            Error::Busy => f.write_str("every name server has too many queries pending"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<client::Error> for Error {
+    fn from(e: client::Error) -> Error {
+        Error::Client(e)
+    }
+}
+
+/// Resolver settings.
+#[derive(Clone, Debug)]
+pub struct ResolverConfig {
+    pub servers: Vec<SocketAddr>,
+    /// Timeout for a single exchange with a single server.
+    pub timeout: Duration,
+    /// Number of passes over the server list.
+    pub attempts: usize,
+    /// Caps on pending queries and retries.
+    pub limits: SendLimits,
+    /// Link-local protocols tried for single-label names that unicast DNS
+    /// could not resolve.
+    pub fallback: LocalFallback,
+    /// Synthesize AAAA records for IPv4-only names when set.
+    pub dns64: Option<Dns64>,
+    /// Encrypted resolvers that queries to servers are sent to instead,
+    /// as [`Discovery::upgrade`] finds them. Queries that pin UDP or TCP
+    /// in [`QueryOptions::protocol`] still go to the server itself; those
+    /// that pin TLS only go to a DNS over TLS upgrade.
+    pub upgrades: HashMap<SocketAddr, Upgrade>,
+    /// Keeps TCP connections to the servers open across queries when set;
+    /// otherwise each TCP query has a connection of its own.
+    pub tcp_pool: Option<TcpPool>,
+    /// Reaches the servers through this proxy when set. A proxy carries
+    /// TCP only, so every query is then sent over TCP, even one that pins
+    /// UDP. A [`tcp_pool`](ResolverConfig::tcp_pool) opens its connections
+    /// through the proxy it was built with instead.
+    pub proxy: Option<Proxy>,
+    /// DNSCrypt resolvers among the servers, by address; queries to them
+    /// are encrypted over whichever of UDP and TCP they are sent on.
+    #[cfg(feature = "dnscrypt")]
+    pub dnscrypt: HashMap<SocketAddr, Arc<DnsCrypt>>,
+    /// The estimator the round-trip time to each server is tracked with,
+    /// as a fresh copy per server; [`SmoothedRtt`] by default.
+    pub rtt_estimator: Arc<dyn RttEstimator>,
+    /// How queries to the servers are stripped of what identifies their
+    /// clients, unless [`server_privacy`](ResolverConfig::server_privacy)
+    /// says otherwise for a server.
+    pub privacy: Privacy,
+    /// Privacy policies of servers that differ from
+    /// [`privacy`](ResolverConfig::privacy), by address.
+    pub server_privacy: HashMap<SocketAddr, Privacy>,
+}
+
+/// Link-local name resolution used as a last resort, the way desktop
+/// operating systems resolve bare host names on a LAN. Off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LocalFallback {
+    /// Try LLMNR for any record type.
+    pub llmnr: bool,
+    /// Try a NetBIOS broadcast name query for A lookups.
+    pub netbios: bool,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> ResolverConfig {
+        ResolverConfig {
+            servers: Vec::new(),
+            timeout: Duration::from_secs(5),
+            attempts: 2,
+            limits: SendLimits::default(),
+            fallback: LocalFallback::default(),
+            dns64: None,
+            upgrades: HashMap::new(),
+            tcp_pool: None,
+            proxy: None,
+            #[cfg(feature = "dnscrypt")]
+            dnscrypt: HashMap::new(),
+            rtt_estimator: Arc::new(SmoothedRtt::new()),
+            privacy: Privacy::default(),
+            server_privacy: HashMap::new(),
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// The privacy policy of queries to `server`.
+    pub fn privacy_for(&self, server: SocketAddr) -> Privacy {
+        self.server_privacy
+            .get(&server)
+            .copied()
+            .unwrap_or(self.privacy)
+    }
+
+    /// Reads `nameserver`, `options timeout:` and `options attempts:` from
+    /// resolv.conf-formatted text.
+    pub fn from_resolv_conf(text: &str) -> ResolverConfig {
+        let mut config = ResolverConfig::default();
+        for line in text.lines() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(addr) = words.next().and_then(|w| w.parse::<IpAddr>().ok()) {
+                        config.servers.push(SocketAddr::new(addr, 53));
+                    }
+                }
+                Some("options") => {
+                    for opt in words {
+                        if let Some(v) = opt.strip_prefix("timeout:").and_then(|v| v.parse().ok()) {
+                            config.timeout = Duration::from_secs(v);
+                        } else if let Some(v) =
+                            opt.strip_prefix("attempts:").and_then(|v| v.parse().ok())
+                        {
+                            config.attempts = v;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+
+    /// Adds the DNSCrypt resolver `provider` as a server.
+    #[cfg(feature = "dnscrypt")]
+    pub fn add_dnscrypt(&mut self, provider: Provider) {
+        let address = provider.address;
+        if !self.servers.contains(&address) {
+            self.servers.push(address);
+        }
+        self.dnscrypt
+            .insert(address, Arc::new(DnsCrypt::new(provider)));
+    }
+
+    /// Reads `/etc/resolv.conf`.
+    pub fn system() -> io::Result<ResolverConfig> {
+        Ok(ResolverConfig::from_resolv_conf(&fs::read_to_string(
+            "/etc/resolv.conf",
+        )?))
+    }
+}
+
+/// Per-query settings that override or extend the resolver configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QueryOptions {
+    /// Ask for DNSSEC records by setting the DO bit.
+    pub dnssec_ok: bool,
+    /// Ask the upstream not to validate by setting the CD bit.
+    pub checking_disabled: bool,
+    /// Set the RD bit; on by default.
+    pub recursion_desired: bool,
+    /// Advertised EDNS UDP payload size; `None` leaves it to the resolver
+    /// to choose for each server.
+    pub udp_size: Option<u16>,
+    /// Per-exchange timeout; `None` uses [`ResolverConfig::timeout`].
+    pub timeout: Option<Duration>,
+    /// Pins the transport. `None` means the server's encrypted upgrade if
+    /// it has one, and otherwise UDP with TCP fallback on truncation.
+    /// [`Protocol::Tls`] means the server's DNS over TLS upgrade, and
+    /// fails for servers without one.
+    pub protocol: Option<Protocol>,
+    /// Extra EDNS options attached to the query. A Client Subnet option
+    /// among them is sent as the resolver's [`Privacy`] policy says.
+    pub edns_options: Vec<EdnsOption>,
+}
+
+impl Default for QueryOptions {
+    fn default() -> QueryOptions {
+        QueryOptions {
+            dnssec_ok: false,
+            checking_disabled: false,
+            recursion_desired: true,
+            udp_size: None,
+            timeout: None,
+            protocol: None,
+            edns_options: Vec::new(),
+        }
+    }
+}
+
+impl QueryOptions {
+    /// Builds the query message these options describe.
+    pub fn message(&self, name: DomainName, qtype: RecordType) -> Message {
+        let mut query = Message::query(name, qtype);
+        query.header.rd = self.recursion_desired;
+        query.header.cd = self.checking_disabled;
+        if let Some(edns) = &mut query.edns {
+            edns.dnssec_ok = self.dnssec_ok;
+            if let Some(size) = self.udp_size {
+                edns.udp_size = size;
+            }
+            edns.options.extend(self.edns_options.iter().cloned());
+        }
+        query
+    }
+}
+
+/// A stub resolver.
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    config: Arc<ResolverConfig>,
+    in_flight: Arc<InFlight>,
+    sizing: Arc<UdpSizing>,
+    stats: Arc<ServerStats>,
+}
+
+impl Resolver {
+    pub fn new(config: ResolverConfig) -> Resolver {
+        Resolver {
+            stats: Arc::new(ServerStats::new(config.rtt_estimator.clone())),
+            config: Arc::new(config),
+            in_flight: Arc::new(InFlight::new()),
+            sizing: Arc::new(UdpSizing::new()),
+        }
+    }
+
+    /// A resolver using the system configuration.
+    pub fn system() -> io::Result<Resolver> {
+        Ok(Resolver::new(ResolverConfig::system()?))
+    }
+
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// The queries the resolver and its clones have under way.
+    pub fn in_flight(&self) -> &InFlight {
+        &self.in_flight
+    }
+
+    /// Whether every server has as many queries pending as it may, so
+    /// that a query now would fail with [`Error::Busy`].
+    pub fn is_saturated(&self) -> bool {
+        let cap = self.config.limits.max_pending_per_server;
+        !self.config.servers.is_empty()
+            && self
+                .config
+                .servers
+                .iter()
+                .all(|&s| self.in_flight.pending(s) >= cap)
+    }
+
+    /// What the resolver has learned of the UDP paths to its servers.
+    pub fn udp_sizing(&self) -> &UdpSizing {
+        &self.sizing
+    }
+
+    /// How each server has fared with the resolver and its clones.
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// Sends `query` to the configured servers until one gives a usable
+    /// response. SERVFAIL, REFUSED and NOTIMP move on to the next server;
+    /// the last such response is returned if no server does better.
+    pub fn send(&self, query: &Message) -> Result<Message, Error> {
+        self.send_with(query, &QueryOptions::default())
+    }
+
+    /// Like [`Resolver::send`], using the timeout and transport from
+    /// `options`. The message itself is sent as is, but for the EDNS UDP
+    /// payload size of messages sent over UDP, which the resolver chooses
+    /// for each server unless `options` pins it, and for what the server's
+    /// [`Privacy`] policy takes out.
+    pub fn send_with(&self, query: &Message, options: &QueryOptions) -> Result<Message, Error> {
+        let limits = &self.config.limits;
+        let mut last_err = None;
+        let mut last_resp = None;
+        let mut tried = false;
+        let mut busy = false;
+        'passes: for _ in 0..self.config.attempts.max(1) {
+            for &server in &self.config.servers {
+                // Anything after the first exchange is a retry.
+                let pending =
+                    match self
+                        .in_flight
+                        .start(server, query, tried, limits, Instant::now())
+                    {
+                        Ok(pending) => pending,
+                        Err(Refusal::Busy) => {
+                            busy = true;
+                            continue;
+                        }
+                        Err(Refusal::NoRetries) => break 'passes,
+                    };
+                tried = true;
+                let (result, rtt) = self.exchange_private(server, query, options);
+                drop(pending);
+                let now = Instant::now();
+                match &result {
+                    Ok(resp) => self.stats.answered(server, query, resp, rtt, now),
+                    Err(client::Error::Timeout) => self.stats.timed_out(server),
+                    Err(_) => self.stats.failed(server),
+                }
+                match result {
+                    Ok(resp) => match resp.header.rcode {
+                        Rcode::SERVFAIL | Rcode::REFUSED | Rcode::NOTIMP => last_resp = Some(resp),
+                        _ => return Ok(resp),
+                    },
+                    Err(e) => last_err = Some(e),
+                }
+            }
+        }
+        match (last_resp, last_err) {
+            (Some(resp), _) => Ok(resp),
+            (None, Some(e)) => Err(e.into()),
+            (None, None) if busy => Err(Error::Busy),
+            (None, None) => Err(Error::NoServers),
+        }
+    }
+
+    /// Exchanges `query` with `server` under the server's privacy policy,
+    /// with the round-trip time of the last exchange.
+    fn exchange_private(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        options: &QueryOptions,
+    ) -> (Result<Message, client::Error>, Duration) {
+        let privacy = self.config.privacy_for(server);
+        let ancestors = match query.questions.as_slice() {
+            [q] if privacy.minimize_reverse => privacy::reverse_ancestors(&q.name),
+            _ => Vec::new(),
+        };
+        for ancestor in ancestors {
+            let mut probe = Message::query(ancestor, RecordType::NS);
+            probe.header.rd = query.header.rd;
+            probe.header.cd = query.header.cd;
+            if let (Some(edns), Some(asked)) = (&mut probe.edns, &query.edns) {
+                edns.dnssec_ok = asked.dnssec_ok;
+            }
+            let started = Instant::now();
+            match self.exchange(server, &probe, options) {
+                // Nothing exists below a name that does not (RFC 8020).
+                Ok(mut resp) if resp.header.rcode == Rcode::NXDOMAIN => {
+                    resp.header.id = query.header.id;
+                    resp.questions = query.questions.clone();
+                    resp.answers.clear();
+                    return (Ok(resp), started.elapsed());
+                }
+                Ok(_) => {}
+                Err(e) => return (Err(e), started.elapsed()),
+            }
+        }
+        let outgoing = privacy.outgoing(query);
+        let started = Instant::now();
+        let result = self.exchange(server, &outgoing, options);
+        (result, started.elapsed())
+    }
+
+    fn exchange(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        options: &QueryOptions,
+    ) -> Result<Message, client::Error> {
+        let timeout = options.timeout.unwrap_or(self.config.timeout);
+        if options.protocol == Some(Protocol::Tls) {
+            return match self.config.upgrades.get(&server) {
+                Some(upgrade) if upgrade.designated().transport == EncryptedTransport::Tls => {
+                    upgrade.exchange(query, timeout)
+                }
+                _ => Err(client::Error::Io(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{} has no DNS over TLS upgrade", server),
+                ))),
+            };
+        }
+        #[cfg(feature = "dnscrypt")]
+        if let Some(dnscrypt) = self.config.dnscrypt.get(&server) {
+            return dnscrypt.exchange(query, options.protocol, timeout);
+        }
+        let proxied = self.config.proxy.is_some();
+        match options.protocol {
+            Some(Protocol::Udp) if !proxied => {
+                return self.exchange_udp(server, query, options, timeout)
+            }
+            Some(_) => return self.exchange_tcp(server, query, timeout),
+            None => {}
+        }
+        if let Some(upgrade) = self.config.upgrades.get(&server) {
+            return upgrade.exchange(query, timeout);
+        }
+        if proxied {
+            return self.exchange_tcp(server, query, timeout);
+        }
+        if self.sizing.prefers_tcp(server, Instant::now()) {
+            let resp = self.exchange_tcp(server, query, timeout);
+            if resp.is_err() {
+                self.sizing.tcp_failed(server);
+            }
+            return resp;
+        }
+        let resp = self.exchange_udp(server, query, options, timeout)?;
+        if resp.header.tc {
+            return self.exchange_tcp(server, query, timeout);
+        }
+        Ok(resp)
+    }
+
+    /// Sends `query` over UDP, advertising the payload size chosen for
+    /// `server`, and notes how the path behaved.
+    fn exchange_udp(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        options: &QueryOptions,
+        timeout: Duration,
+    ) -> Result<Message, client::Error> {
+        let size = match (&query.edns, options.udp_size) {
+            (Some(_), None) => self.sizing.udp_size(server, Instant::now()),
+            _ => return client::exchange_udp(server, query, timeout),
+        };
+        let mut sized;
+        let query = match &query.edns {
+            Some(edns) if edns.udp_size != size => {
+                sized = query.clone();
+                if let Some(edns) = &mut sized.edns {
+                    edns.udp_size = size;
+                }
+                &sized
+            }
+            _ => query,
+        };
+        match client::exchange_udp(server, query, timeout) {
+            Ok(resp) => {
+                self.sizing.answered(server, size, &resp, Instant::now());
+                Ok(resp)
+            }
+            Err(client::Error::Timeout) => {
+                self.sizing.timed_out(server, size, Instant::now());
+                Err(client::Error::Timeout)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn exchange_tcp(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        timeout: Duration,
+    ) -> Result<Message, client::Error> {
+        match &self.config.tcp_pool {
+            Some(pool) => pool.exchange(server, query, timeout),
+            None => client::exchange_tcp_via(server, self.config.proxy.as_ref(), query, timeout),
+        }
+    }
+
+    /// Queries `name`/`qtype` and returns the full response.
+    pub fn query(&self, name: &DomainName, qtype: RecordType) -> Result<Message, Error> {
+        self.query_with(name, qtype, &QueryOptions::default())
+    }
+
+    /// Queries `name`/`qtype` with per-query options.
+    pub fn query_with(
+        &self,
+        name: &DomainName,
+        qtype: RecordType,
+        options: &QueryOptions,
+    ) -> Result<Message, Error> {
+        let dns64 = self.config.dns64.as_ref();
+        if let Some(target) = dns64
+            .filter(|_| qtype == RecordType::PTR)
+            .and_then(|d| d.reverse_target(name))
+        {
+            return dns64.unwrap().synthesize_ptr(self, name, target, options);
+        }
+        let resp = self.send_with(&options.message(name.clone(), qtype), options)?;
+        match dns64 {
+            Some(dns64) if qtype == RecordType::AAAA => {
+                dns64.synthesize_aaaa(self, name, options, resp)
+            }
+            _ => Ok(resp),
+        }
+    }
+
+    /// Queries `name`/`qtype` and returns the answer records of that type,
+    /// including those at the end of a CNAME chain. Error rcodes, including
+    /// NXDOMAIN, are returned as [`Error::Rcode`].
+    pub fn lookup(&self, name: &DomainName, qtype: RecordType) -> Result<Vec<Record>, Error> {
+        self.lookup_with(name, qtype, &QueryOptions::default())
+    }
+
+    /// Like [`Resolver::lookup`], with per-query options. When DNSSEC
+    /// records are requested, the RRSIGs covering the answer are not
+    /// filtered out.
+    pub fn lookup_with(
+        &self,
+        name: &DomainName,
+        qtype: RecordType,
+        options: &QueryOptions,
+    ) -> Result<Vec<Record>, Error> {
+        let result = self.query_with(name, qtype, options).and_then(|resp| {
+            if resp.header.rcode != Rcode::NOERROR {
+                return Err(Error::Rcode(resp.header.rcode));
+            }
+            Ok(resp
+                .answers
+                .into_iter()
+                .filter(|rr| {
+                    rr.rtype() == qtype || (options.dnssec_ok && rr.rtype() == RecordType::RRSIG)
+                })
+                .collect::<Vec<_>>())
+        });
+        match result {
+            Ok(records) if !records.is_empty() => Ok(records),
+            result if name.label_count() == 1 => match self.lookup_local(name, qtype) {
+                Some(records) => Ok(records),
+                None => result,
+            },
+            result => result,
+        }
+    }
+
+    /// Resolves a single-label name over the enabled link-local protocols.
+    fn lookup_local(&self, name: &DomainName, qtype: RecordType) -> Option<Vec<Record>> {
+        let fallback = self.config.fallback;
+        if fallback.llmnr {
+            match llmnr::query(name, qtype, llmnr::TIMEOUT) {
+                Ok(records) if !records.is_empty() => return Some(records),
+                _ => {}
+            }
+        }
+        if fallback.netbios && qtype == RecordType::A {
+            let label = String::from_utf8_lossy(&name.labels()[0]).into_owned();
+            match netbios::query(&label, netbios::TIMEOUT) {
+                Ok(addrs) if !addrs.is_empty() => {
+                    return Some(
+                        addrs
+                            .into_iter()
+                            .map(|a| Record::new(name.clone(), 0, RData::A(a)))
+                            .collect(),
+                    )
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Resolves the addresses of `name` the Happy Eyeballs way (RFC 8305).
+    ///
+    /// AAAA and A queries are sent concurrently. The returned iterator
+    /// yields addresses in connection-attempt order as soon as they are
+    /// usable: IPv6 first, alternating families, each family sorted by
+    /// RFC 6724 precedence. If the A answer arrives first, the iterator
+    /// waits up to [`RESOLUTION_DELAY`] for AAAA before starting with IPv4.
+    pub fn lookup_ip(&self, name: &DomainName) -> LookupIp {
+        self.lookup_ip_with(name, &QueryOptions::default())
+    }
+
+    /// Like [`Resolver::lookup_ip`], with options applied to both queries.
+    pub fn lookup_ip_with(&self, name: &DomainName, options: &QueryOptions) -> LookupIp {
+        let (tx, rx) = mpsc::channel();
+        for &qtype in [RecordType::AAAA, RecordType::A].iter() {
+            let resolver = self.clone();
+            let name = name.clone();
+            let options = options.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let result = resolver.lookup_with(&name, qtype, &options).map(|records| {
+                    records
+                        .into_iter()
+                        .filter_map(|rr| match rr.rdata {
+                            RData::A(a) => Some(IpAddr::V4(a)),
+                            RData::Aaaa(a) => Some(IpAddr::V6(a)),
+                            _ => None,
+                        })
+                        .collect()
+                });
+                // The receiver may be gone if the caller stopped early.
+                let _ = tx.send((qtype, result));
+            });
+        }
+        LookupIp {
+            rx,
+            v6: VecDeque::new(),
+            v4: VecDeque::new(),
+            pending: 2,
+            v6_done: false,
+            delay_until: None,
+            started: false,
+            last_v6: false,
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// Resolves `name` with the system resolver; see [`Resolver::lookup_ip`].
+pub fn lookup_ip(name: &DomainName) -> io::Result<LookupIp> {
+    Ok(Resolver::system()?.lookup_ip(name))
+}
+
+/// How long to wait for AAAA after A has arrived (RFC 8305 §3).
+pub const RESOLUTION_DELAY: Duration = Duration::from_millis(50);
+
+/// Addresses in connection-attempt order, produced by
+/// [`Resolver::lookup_ip`].
+pub struct LookupIp {
+    rx: Receiver<(RecordType, Result<Vec<IpAddr>, Error>)>,
+    v6: VecDeque<IpAddr>,
+    v4: VecDeque<IpAddr>,
+    pending: usize,
+    v6_done: bool,
+    delay_until: Option<Instant>,
+    started: bool,
+    last_v6: bool,
+    errors: Vec<Error>,
+}
+
+impl LookupIp {
+    /// Errors from the individual queries, available once they completed.
+    /// A lookup that yields no addresses at all has at least one.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    fn accept(&mut self, qtype: RecordType, result: Result<Vec<IpAddr>, Error>) {
+        self.pending -= 1;
+        match result {
+            Ok(mut addrs) => {
+                sort_by_precedence(&mut addrs);
+                if qtype == RecordType::AAAA {
+                    self.v6.extend(addrs);
+                } else {
+                    self.v4.extend(addrs);
+                }
+            }
+            Err(e) => self.errors.push(e),
+        }
+        if qtype == RecordType::AAAA {
+            self.v6_done = true;
+        } else if !self.v6_done {
+            self.delay_until = Some(Instant::now() + RESOLUTION_DELAY);
+        }
+    }
+
+    /// Blocks until it is time to start handing out addresses.
+    fn wait_for_start(&mut self) {
+        while !self.started {
+            if self.v6_done || self.pending == 0 {
+                self.started = true;
+                break;
+            }
+            let received = match self.delay_until {
+                Some(until) => {
+                    let left = until.saturating_duration_since(Instant::now());
+                    match self.rx.recv_timeout(left) {
+                        Ok(r) => Some(r),
+                        Err(RecvTimeoutError::Timeout) => {
+                            self.started = true;
+                            None
+                        }
+                        Err(RecvTimeoutError::Disconnected) => None,
+                    }
+                }
+                None => self.rx.recv().ok(),
+            };
+            match received {
+                Some((qtype, result)) => self.accept(qtype, result),
+                None => self.started = true,
+            }
+        }
+    }
+}
+
+impl Iterator for LookupIp {
+    type Item = IpAddr;
+
+    fn next(&mut self) -> Option<IpAddr> {
+        self.wait_for_start();
+        loop {
+            while let Ok((qtype, result)) = self.rx.try_recv() {
+                self.accept(qtype, result);
+            }
+            let prefer_v6 = !self.last_v6;
+            let addr = if prefer_v6 {
+                self.v6.pop_front().or_else(|| self.v4.pop_front())
+            } else {
+                self.v4.pop_front().or_else(|| self.v6.pop_front())
+            };
+            if let Some(addr) = addr {
+                self.last_v6 = addr.is_ipv6();
+                return Some(addr);
+            }
+            if self.pending == 0 {
+                return None;
+            }
+            match self.rx.recv() {
+                Ok((qtype, result)) => self.accept(qtype, result),
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+/// The RFC 6724 §2.1 default policy table precedence for `addr`, with
+/// IPv4 treated as its IPv4-mapped form.
+pub fn precedence(addr: &IpAddr) -> u8 {
+    let v6 = match addr {
+        IpAddr::V4(a) => a.to_ipv6_mapped(),
+        IpAddr::V6(a) => *a,
+    };
+    let s = v6.segments();
+    if v6 == Ipv6Addr::LOCALHOST {
+        50
+    } else if s[..5] == [0; 5] && s[5] == 0xffff {
+        35
+    } else if s[0] == 0x2002 {
+        30
+    } else if s[0] == 0x2001 && s[1] == 0 {
+        5
+    } else if s[0] & 0xfe00 == 0xfc00 {
+        3
+    } else if s[..6] == [0; 6] || s[0] & 0xffc0 == 0xfec0 || s[0] == 0x3ffe {
+        1
+    } else {
+        40
+    }
+}
+
+/// Stable-sorts addresses by descending RFC 6724 precedence. Without
+/// source address selection this is the part of destination address
+/// ordering that can be applied to a single family.
+pub fn sort_by_precedence(addrs: &mut [IpAddr]) {
+    addrs.sort_by_key(|a| std::cmp::Reverse(precedence(a)));
+}
@@ -0,0 +1,164 @@
+//! What queries sent upstream give away about the clients behind them.
+//!
+//! A Client Subnet option (RFC 7871) tells the servers a query goes to
+//! where its client is. [`ClientSubnet`] says whether to pass one on as
+//! it is, cut its address to a shorter prefix, drop it, or put in its
+//! place one with a source prefix length of 0, which asks the servers not
+//! to add one of their own (§7.1.2).
+//!
+//! Reverse lookups give away the addresses they are for. With
+//! [`Privacy::minimize_reverse`], a query for a name under `in-addr.arpa.`
+//! or `ip6.arpa.` goes after NS queries for its ancestors, a few labels
+//! longer each time, as QNAME minimisation has it (RFC 9156). An ancestor
+//! that does not exist means that the name does not either (RFC 8020), so
+//! an address whose reverse zone is not delegated is never sent in full.
+
+use std::borrow::Cow;
+
+use crate::message::{EdnsOption, Message, OptionCode};
+use crate::name::DomainName;
+
+/// Labels of `ip6.arpa.` names added per minimised query: 16 bits.
+const IP6_STEP: usize = 4;
+
+/// How queries sent to a server are stripped of what identifies their
+/// clients.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Privacy {
+    pub client_subnet: ClientSubnet,
+    /// Look up names under `in-addr.arpa.` and `ip6.arpa.` one ancestor at
+    /// a time, giving up at the first that does not exist.
+    pub minimize_reverse: bool,
+}
+
+impl Privacy {
+    /// Queries sent as they are.
+    pub const OPEN: Privacy = Privacy {
+        client_subnet: ClientSubnet::Forward,
+        minimize_reverse: false,
+    };
+
+    /// As little given away as the protocol allows.
+    pub const STRICT: Privacy = Privacy {
+        client_subnet: ClientSubnet::Suppress,
+        minimize_reverse: true,
+    };
+
+    /// `query` as it is to be sent: borrowed if the policy leaves it be.
+    pub fn outgoing<'a>(&self, query: &'a Message) -> Cow<'a, Message> {
+        let edns = match &query.edns {
+            Some(edns) => edns,
+            None => return Cow::Borrowed(query),
+        };
+        let has_subnet = edns.option(OptionCode::CLIENT_SUBNET).is_some();
+        let options = match self.client_subnet {
+            ClientSubnet::Forward => return Cow::Borrowed(query),
+            ClientSubnet::Strip if !has_subnet => return Cow::Borrowed(query),
+            ClientSubnet::Coarsen { .. } if !has_subnet => return Cow::Borrowed(query),
+            policy => subnet_options(&edns.options, policy),
+        };
+        let mut query = query.clone();
+        if let Some(edns) = &mut query.edns {
+            edns.options = options;
+        }
+        Cow::Owned(query)
+    }
+}
+
+/// What to do with the Client Subnet option of a query.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ClientSubnet {
+    /// Send it as it is.
+    Forward,
+    /// Cut its address to at most these prefix lengths; RFC 7871 §11.1
+    /// recommends 24 and 56.
+    Coarsen { v4_prefix: u8, v6_prefix: u8 },
+    /// Leave it out.
+    #[default]
+    Strip,
+    /// Leave it out, and ask the server not to send one of its own by
+    /// sending one with a source prefix length of 0.
+    Suppress,
+}
+
+/// `options` with their Client Subnet options treated as `policy` says.
+fn subnet_options(options: &[EdnsOption], policy: ClientSubnet) -> Vec<EdnsOption> {
+    let mut out: Vec<EdnsOption> = options
+        .iter()
+        .filter_map(|o| {
+            if o.code != OptionCode::CLIENT_SUBNET {
+                return Some(o.clone());
+            }
+            match policy {
+                ClientSubnet::Forward => Some(o.clone()),
+                ClientSubnet::Coarsen {
+                    v4_prefix,
+                    v6_prefix,
+                } => coarsen(&o.data, v4_prefix, v6_prefix).map(|data| EdnsOption {
+                    code: OptionCode::CLIENT_SUBNET,
+                    data,
+                }),
+                ClientSubnet::Strip | ClientSubnet::Suppress => None,
+            }
+        })
+        .collect();
+    if policy == ClientSubnet::Suppress {
+        // Family 1, source and scope prefix lengths 0, no address.
+        out.push(EdnsOption {
+            code: OptionCode::CLIENT_SUBNET,
+            data: vec![0, 1, 0, 0],
+        });
+    }
+    out
+}
+
+/// Client Subnet option data with its source prefix cut to the longest
+/// allowed for its family, and its address to the bytes the prefix takes
+/// (§6); `None` if the data is not of IPv4 or IPv6.
+fn coarsen(data: &[u8], v4_prefix: u8, v6_prefix: u8) -> Option<Vec<u8>> {
+    // Family, source prefix length, scope prefix length, then the address.
+    if data.len() < 4 {
+        return None;
+    }
+    let (family, prefix) = (u16::from_be_bytes([data[0], data[1]]), data[2]);
+    let (bits, most) = match family {
+        1 => (32, v4_prefix),
+        2 => (128, v6_prefix),
+        _ => return None,
+    };
+    let prefix = prefix.min(most).min(bits);
+    let len = usize::from(prefix).div_ceil(8);
+    let mut addr = data[4..].to_vec();
+    addr.resize(len, 0);
+    if prefix % 8 != 0 {
+        addr[len - 1] &= !(0xffu8 >> (prefix % 8));
+    }
+    let mut out = vec![data[0], data[1], prefix, 0];
+    out.extend(addr);
+    Some(out)
+}
+
+/// The ancestors of `name` to ask for before it when reverse lookups are
+/// minimised, shortest first; none if it is not a reverse-mapping name.
+pub(super) fn reverse_ancestors(name: &DomainName) -> Vec<DomainName> {
+    let labels = name.labels();
+    let n = labels.len();
+    let under = |zone: &[&[u8]]| {
+        n > zone.len()
+            && labels[n - zone.len()..]
+                .iter()
+                .zip(zone)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    };
+    let (base, step) = if under(&[b"in-addr", b"arpa"]) {
+        (2, 1)
+    } else if under(&[b"ip6", b"arpa"]) {
+        (2, IP6_STEP)
+    } else {
+        return Vec::new();
+    };
+    (base + step..n)
+        .step_by(step)
+        .map(|count| name.suffix(count))
+        .collect()
+}
@@ -0,0 +1,215 @@
+//! DNS error reporting (RFC 9567): telling the operator of a domain about
+//! failures to resolve it, by querying a name that encodes the failure
+//! under the agent domain its authoritative servers advertise in the
+//! Report-Channel option.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::clock::{self, Clock};
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::rr::RecordType;
+
+use super::Resolver;
+
+/// The label that opens and closes a report query's failure description.
+const REPORT_LABEL: &[u8] = b"_er";
+
+/// A failure to resolve a query, as a report query names it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ErrorReport {
+    pub qname: DomainName,
+    pub qtype: RecordType,
+    /// The Extended DNS Error info code (RFC 8914) saying what failed.
+    pub info_code: u16,
+}
+
+impl ErrorReport {
+    pub fn new(qname: DomainName, qtype: RecordType, info_code: u16) -> ErrorReport {
+        ErrorReport {
+            qname,
+            qtype,
+            info_code,
+        }
+    }
+
+    /// The name to query to send the report to `agent`:
+    /// `_er.<qtype>.<qname>.<info code>._er.<agent>` (RFC 9567 §6.1.1);
+    /// `None` if it would be too long, in which case no report is sent.
+    pub fn to_name(&self, agent: &DomainName) -> Option<DomainName> {
+        let qtype = self.qtype.0.to_string();
+        let info_code = self.info_code.to_string();
+        let labels = std::iter::once(REPORT_LABEL)
+            .chain(std::iter::once(qtype.as_bytes()))
+            .chain(self.qname.labels().iter().map(Vec::as_slice))
+            .chain(std::iter::once(info_code.as_bytes()))
+            .chain(std::iter::once(REPORT_LABEL))
+            .chain(agent.labels().iter().map(Vec::as_slice));
+        DomainName::from_labels(labels).ok()
+    }
+
+    /// The report `name` sends to `agent`, as the agent's authoritative
+    /// server receives it; `None` if it is not a report query for `agent`.
+    pub fn from_name(name: &DomainName, agent: &DomainName) -> Option<ErrorReport> {
+        if !name.is_subdomain_of(agent) {
+            return None;
+        }
+        let labels = name.labels();
+        let labels = &labels[..labels.len() - agent.label_count()];
+        // At least `_er`, the type, the code and `_er`; the name reported
+        // on may be the root.
+        if labels.len() < 4
+            || !labels[0].eq_ignore_ascii_case(REPORT_LABEL)
+            || !labels[labels.len() - 1].eq_ignore_ascii_case(REPORT_LABEL)
+        {
+            return None;
+        }
+        let number = |label: &[u8]| std::str::from_utf8(label).ok()?.parse::<u16>().ok();
+        Some(ErrorReport {
+            qname: DomainName::from_labels(&labels[2..labels.len() - 2]).ok()?,
+            qtype: RecordType(number(&labels[1])?),
+            info_code: number(&labels[labels.len() - 2])?,
+        })
+    }
+}
+
+/// Whether `name` is itself a report query, on whose failures nothing
+/// may be reported (RFC 9567 §6.1.1).
+fn is_report_query(name: &DomainName) -> bool {
+    name.labels()
+        .first()
+        .is_some_and(|l| l.eq_ignore_ascii_case(REPORT_LABEL))
+}
+
+/// Limits on the reports an [`ErrorReporter`] sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportPolicy {
+    /// Reports sent per second at most, to any agent; zero sends none.
+    pub reports_per_second: u32,
+    /// How long the same report is not sent again.
+    pub repeat_after: Duration,
+    /// Number of reports remembered as sent; beyond it, new reports are
+    /// dropped until old ones are forgotten.
+    pub max_entries: usize,
+}
+
+impl Default for ReportPolicy {
+    fn default() -> ReportPolicy {
+        ReportPolicy {
+            reports_per_second: 10,
+            repeat_after: Duration::from_secs(3600),
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Sent {
+    /// When each report name was last sent.
+    names: HashMap<DomainName, Instant>,
+    /// The start of the current second and the reports sent in it.
+    second: Instant,
+    in_second: u32,
+}
+
+/// Sends error reports as TXT queries through a resolver, in the
+/// background, each report at most once per [`ReportPolicy::repeat_after`]
+/// and no more than [`ReportPolicy::reports_per_second`] in all. Their
+/// responses are not looked at.
+#[derive(Debug)]
+pub struct ErrorReporter {
+    resolver: Resolver,
+    policy: ReportPolicy,
+    sent: Mutex<Sent>,
+    clock: Arc<dyn Clock>,
+}
+
+impl ErrorReporter {
+    pub fn new(resolver: Resolver, policy: ReportPolicy) -> ErrorReporter {
+        let clock = clock::system();
+        ErrorReporter {
+            resolver,
+            policy,
+            sent: Mutex::new(Sent {
+                names: HashMap::new(),
+                second: clock.now(),
+                in_second: 0,
+            }),
+            clock,
+        }
+    }
+
+    /// Applies the limits by `clock` rather than the system's.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.sent.get_mut().unwrap().second = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    pub fn policy(&self) -> &ReportPolicy {
+        &self.policy
+    }
+
+    /// Sends `report` to `agent` unless the limits hold it back, or it is
+    /// about a report query itself. Returns whether it was sent.
+    pub fn report(&self, report: &ErrorReport, agent: &DomainName) -> bool {
+        if is_report_query(&report.qname) {
+            return false;
+        }
+        let name = match report.to_name(agent) {
+            Some(name) => name,
+            None => return false,
+        };
+        if !self.admit(&name, self.clock.now()) {
+            return false;
+        }
+        let resolver = self.resolver.clone();
+        thread::spawn(move || {
+            let _ = resolver.query(&name, RecordType::TXT);
+        });
+        true
+    }
+
+    /// Reports `info_code` for the question of `response`, a response that
+    /// could not be used, to the agent it advertises, if any.
+    pub fn report_response(&self, response: &Message, info_code: u16) -> bool {
+        let (q, agent) = match (response.question(), response.report_channel()) {
+            (Some(q), Some(agent)) => (q, agent),
+            _ => return false,
+        };
+        let report = ErrorReport::new(q.name.to_lowercase(), q.qtype, info_code);
+        self.report(&report, &agent)
+    }
+
+    /// Whether a report may be sent to `name` at `now`, noting it as sent
+    /// if so.
+    fn admit(&self, name: &DomainName, now: Instant) -> bool {
+        let mut sent = self.sent.lock().unwrap();
+        if now.duration_since(sent.second) >= Duration::from_secs(1) {
+            sent.second = now;
+            sent.in_second = 0;
+        }
+        if sent.in_second >= self.policy.reports_per_second {
+            return false;
+        }
+        let repeat_after = self.policy.repeat_after;
+        if let Some(&last) = sent.names.get(name) {
+            if now.duration_since(last) < repeat_after {
+                return false;
+            }
+        }
+        if sent.names.len() >= self.policy.max_entries {
+            sent.names
+                .retain(|_, &mut last| now.duration_since(last) < repeat_after);
+            if sent.names.len() >= self.policy.max_entries {
+                return false;
+            }
+        }
+        sent.names.insert(name.clone(), now);
+        sent.in_second += 1;
+        true
+    }
+}
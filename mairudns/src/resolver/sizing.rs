@@ -0,0 +1,162 @@
+//! The EDNS UDP payload size advertised to each server, following what
+//! is observed of the path to it.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::message::Message;
+
+/// The EDNS UDP payload size advertised to a server not yet known to need
+/// less, the value DNS Flag Day 2020 settled on: small enough not to be
+/// fragmented on any common path.
+pub const FLAG_DAY_UDP_SIZE: u16 = 1232;
+
+/// The size every server can deliver over UDP, EDNS or not.
+pub const MIN_UDP_SIZE: u16 = 512;
+
+/// How long a server stays at the smaller size before the larger one is
+/// tried again, in case the path has changed.
+const PROBE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How long queries go straight to TCP once a server is found unable to
+/// deliver what it is asked for over UDP.
+const TCP_HOLD: Duration = Duration::from_secs(300);
+
+/// Truncated responses in a row, at the smallest size, after which a
+/// server is asked over TCP from the start.
+const TRUNCATIONS_FOR_TCP: u32 = 3;
+
+/// What has been learned of the UDP path to one server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathStatus {
+    /// The payload size advertised to it now.
+    pub udp_size: u16,
+    /// The largest UDP response it has delivered.
+    pub largest_response: usize,
+    /// Queries at a size above the smallest that went unanswered in a
+    /// row.
+    pub timeouts: u32,
+    /// Responses at the smallest size that came back truncated in a row.
+    pub truncations: u32,
+    /// Until when queries go to it over TCP first, if they do.
+    pub tcp_until: Option<Instant>,
+    /// When the larger size was last given up on, if it has been.
+    pub lowered: Option<Instant>,
+}
+
+impl Default for PathStatus {
+    fn default() -> PathStatus {
+        PathStatus {
+            udp_size: FLAG_DAY_UDP_SIZE,
+            largest_response: 0,
+            timeouts: 0,
+            truncations: 0,
+            tcp_until: None,
+            lowered: None,
+        }
+    }
+}
+
+/// The payload size a resolver advertises to each server, adjusted to
+/// what it observes, so that nobody has to tune it.
+///
+/// Every server starts at [`FLAG_DAY_UDP_SIZE`]. A query at that size
+/// going unanswered suggests the response was fragmented and the
+/// fragments dropped on the way, so the server drops to [`MIN_UDP_SIZE`],
+/// where nothing is fragmented, and the larger size is tried again after
+/// a while. A server that even then keeps answering truncated, so that
+/// every query ends up on TCP anyway, is asked over TCP from the start
+/// for a while.
+#[derive(Debug, Default)]
+pub struct UdpSizing {
+    paths: Mutex<HashMap<SocketAddr, PathStatus>>,
+}
+
+impl UdpSizing {
+    pub fn new() -> UdpSizing {
+        UdpSizing::default()
+    }
+
+    /// What has been learned of the path to `server`.
+    pub fn status(&self, server: SocketAddr) -> PathStatus {
+        self.paths
+            .lock()
+            .unwrap()
+            .get(&server)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The payload size to advertise to `server` at `now`.
+    pub fn udp_size(&self, server: SocketAddr, now: Instant) -> u16 {
+        let mut paths = self.paths.lock().unwrap();
+        let path = match paths.get_mut(&server) {
+            Some(path) => path,
+            None => return FLAG_DAY_UDP_SIZE,
+        };
+        if path
+            .lowered
+            .is_some_and(|at| now.saturating_duration_since(at) >= PROBE_INTERVAL)
+        {
+            path.udp_size = FLAG_DAY_UDP_SIZE;
+            path.lowered = None;
+        }
+        path.udp_size
+    }
+
+    /// Whether to ask `server` over TCP without trying UDP first.
+    pub fn prefers_tcp(&self, server: SocketAddr, now: Instant) -> bool {
+        self.paths
+            .lock()
+            .unwrap()
+            .get(&server)
+            .and_then(|p| p.tcp_until)
+            .is_some_and(|until| now < until)
+    }
+
+    /// Notes that a UDP query advertising `size` went unanswered.
+    pub fn timed_out(&self, server: SocketAddr, size: u16, now: Instant) {
+        if size <= MIN_UDP_SIZE {
+            return;
+        }
+        let mut paths = self.paths.lock().unwrap();
+        let path = paths.entry(server).or_default();
+        path.timeouts += 1;
+        if path.udp_size > MIN_UDP_SIZE {
+            path.udp_size = MIN_UDP_SIZE;
+            path.lowered = Some(now);
+            path.truncations = 0;
+        }
+    }
+
+    /// Notes `resp`, received over UDP from `server` for a query
+    /// advertising `size`.
+    pub fn answered(&self, server: SocketAddr, size: u16, resp: &Message, now: Instant) {
+        let len = resp.to_wire().map_or(0, |w| w.len());
+        let mut paths = self.paths.lock().unwrap();
+        let path = paths.entry(server).or_default();
+        path.largest_response = path.largest_response.max(len);
+        if size > MIN_UDP_SIZE {
+            path.timeouts = 0;
+        }
+        if !resp.header.tc || size > MIN_UDP_SIZE {
+            path.truncations = 0;
+            return;
+        }
+        path.truncations += 1;
+        if path.truncations >= TRUNCATIONS_FOR_TCP {
+            path.tcp_until = Some(now + TCP_HOLD);
+            path.truncations = 0;
+        }
+    }
+
+    /// Notes that a TCP query to `server` failed, so that UDP is tried
+    /// first again.
+    pub fn tcp_failed(&self, server: SocketAddr) {
+        if let Some(path) = self.paths.lock().unwrap().get_mut(&server) {
+            path.tcp_until = None;
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! What a resolver has seen of each of its servers: round-trip time
+//! estimates, and how its queries to them fared.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::message::{Message, Rcode};
+
+/// The smallest retransmission timeout [`RttEstimator::rto`] gives.
+const MIN_RTO: Duration = Duration::from_millis(50);
+
+/// The largest retransmission timeout, however often queries time out.
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Estimates the round-trip time to one server from the exchanges with
+/// it. A resolver keeps one for each of its servers, made with
+/// [`fresh`](RttEstimator::fresh) from the one in its
+/// [`ResolverConfig`](super::ResolverConfig); tests can put one there that
+/// reports what they need.
+pub trait RttEstimator: fmt::Debug + Send + Sync {
+    /// An estimator of the same kind and settings with no samples, for
+    /// another server.
+    fn fresh(&self) -> Box<dyn RttEstimator>;
+
+    /// Takes in the round-trip time of an exchange.
+    fn sample(&mut self, rtt: Duration);
+
+    /// Notes a query that went unanswered.
+    fn timed_out(&mut self) {}
+
+    /// The smoothed round-trip time, once there is a sample.
+    fn srtt(&self) -> Option<Duration>;
+
+    /// The variation of the round-trip time, once there is a sample.
+    fn rttvar(&self) -> Option<Duration>;
+
+    /// How long to wait for the server before giving up on a query.
+    fn rto(&self) -> Option<Duration> {
+        let rto = self.srtt()? + 4 * self.rttvar()?;
+        Some(rto.clamp(MIN_RTO, MAX_RTO))
+    }
+}
+
+/// The estimator of RFC 6298: an exponentially weighted average of the
+/// samples, with gain 1/8, and of their deviation from it, with gain 1/4.
+/// Each timeout in a row doubles the retransmission timeout, until the
+/// next sample.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SmoothedRtt {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    backoff: u32,
+}
+
+impl SmoothedRtt {
+    pub fn new() -> SmoothedRtt {
+        SmoothedRtt::default()
+    }
+}
+
+impl RttEstimator for SmoothedRtt {
+    fn fresh(&self) -> Box<dyn RttEstimator> {
+        Box::new(SmoothedRtt::new())
+    }
+
+    fn sample(&mut self, rtt: Duration) {
+        self.backoff = 0;
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let deviation = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + deviation) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    fn timed_out(&mut self) {
+        self.backoff = (self.backoff + 1).min(16);
+    }
+
+    fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    fn rttvar(&self) -> Option<Duration> {
+        self.srtt.map(|_| self.rttvar)
+    }
+
+    fn rto(&self) -> Option<Duration> {
+        let rto = self.srtt? + 4 * self.rttvar;
+        Some(
+            rto.saturating_mul(1 << self.backoff)
+                .clamp(MIN_RTO, MAX_RTO),
+        )
+    }
+}
+
+/// What a resolver has seen of one server.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpstreamStats {
+    /// The smoothed round-trip time, once the server has answered.
+    pub srtt: Option<Duration>,
+    /// The variation of the round-trip time.
+    pub rttvar: Option<Duration>,
+    /// The retransmission timeout the estimator suggests.
+    pub rto: Option<Duration>,
+    /// Queries sent.
+    pub queries: u64,
+    /// Responses that ended the query: any but SERVFAIL, REFUSED and
+    /// NOTIMP.
+    pub successes: u64,
+    /// Responses with SERVFAIL, REFUSED or NOTIMP.
+    pub failures: u64,
+    /// Queries that went unanswered in time.
+    pub timeouts: u64,
+    /// Queries that failed otherwise: refused connections, malformed
+    /// responses and the like.
+    pub errors: u64,
+    /// Queries with EDNS that came back without it, or with FORMERR,
+    /// NOTIMP or BADVERS as if EDNS were not understood.
+    pub edns_failures: u64,
+    /// Failures, timeouts and errors in a row, since the last success.
+    pub consecutive_failures: u64,
+    /// When the server last gave a successful response.
+    pub last_success: Option<Instant>,
+}
+
+impl UpstreamStats {
+    /// The share of queries that got a successful response, once any
+    /// were sent.
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.queries == 0 {
+            None
+        } else {
+            Some(self.successes as f64 / self.queries as f64)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Upstream {
+    estimator: Box<dyn RttEstimator>,
+    stats: UpstreamStats,
+}
+
+/// Statistics of the servers a resolver queries, shared by its clones,
+/// for applications to show how their upstreams are doing.
+pub struct ServerStats {
+    estimator: Arc<dyn RttEstimator>,
+    servers: Mutex<HashMap<SocketAddr, Upstream>>,
+}
+
+impl fmt::Debug for ServerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerStats")
+            .field("estimator", &self.estimator)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for ServerStats {
+    fn default() -> ServerStats {
+        ServerStats::new(Arc::new(SmoothedRtt::new()))
+    }
+}
+
+impl ServerStats {
+    /// Statistics with round-trip times estimated by estimators made from
+    /// `estimator`.
+    pub fn new(estimator: Arc<dyn RttEstimator>) -> ServerStats {
+        ServerStats {
+            estimator,
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// What has been seen of `server`; all zero if it was never queried.
+    pub fn get(&self, server: SocketAddr) -> UpstreamStats {
+        self.servers
+            .lock()
+            .unwrap()
+            .get(&server)
+            .map(|u| u.stats)
+            .unwrap_or_default()
+    }
+
+    /// Every server queried, by address.
+    pub fn all(&self) -> Vec<(SocketAddr, UpstreamStats)> {
+        let mut all: Vec<_> = self
+            .servers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&addr, u)| (addr, u.stats))
+            .collect();
+        all.sort_by_key(|&(addr, _)| addr);
+        all
+    }
+
+    /// Forgets every server.
+    pub fn clear(&self) {
+        self.servers.lock().unwrap().clear();
+    }
+
+    fn update(&self, server: SocketAddr, f: impl FnOnce(&mut Upstream)) {
+        let mut servers = self.servers.lock().unwrap();
+        let upstream = servers.entry(server).or_insert_with(|| Upstream {
+            estimator: self.estimator.fresh(),
+            stats: UpstreamStats::default(),
+        });
+        upstream.stats.queries += 1;
+        f(upstream);
+        let estimator = &upstream.estimator;
+        upstream.stats.srtt = estimator.srtt();
+        upstream.stats.rttvar = estimator.rttvar();
+        upstream.stats.rto = estimator.rto();
+    }
+
+    /// Notes `resp`, received from `server` `rtt` after `query` was sent.
+    pub fn answered(
+        &self,
+        server: SocketAddr,
+        query: &Message,
+        resp: &Message,
+        rtt: Duration,
+        now: Instant,
+    ) {
+        self.update(server, |u| {
+            u.estimator.sample(rtt);
+            let stats = &mut u.stats;
+            let rcode = resp.header.rcode;
+            if query.edns.is_some()
+                && (resp.edns.is_none()
+                    || rcode == Rcode::FORMERR
+                    || rcode == Rcode::NOTIMP
+                    || rcode == Rcode::BADVERS)
+            {
+                stats.edns_failures += 1;
+            }
+            match rcode {
+                Rcode::SERVFAIL | Rcode::REFUSED | Rcode::NOTIMP => {
+                    stats.failures += 1;
+                    stats.consecutive_failures += 1;
+                }
+                _ => {
+                    stats.successes += 1;
+                    stats.consecutive_failures = 0;
+                    stats.last_success = Some(now);
+                }
+            }
+        });
+    }
+
+    /// Notes a query to `server` that went unanswered.
+    pub fn timed_out(&self, server: SocketAddr) {
+        self.update(server, |u| {
+            u.estimator.timed_out();
+            u.stats.timeouts += 1;
+            u.stats.consecutive_failures += 1;
+        });
+    }
+
+    /// Notes a query to `server` that failed other than by timing out.
+    pub fn failed(&self, server: SocketAddr) {
+        self.update(server, |u| {
+            u.stats.errors += 1;
+            u.stats.consecutive_failures += 1;
+        });
+    }
+}
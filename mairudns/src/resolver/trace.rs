@@ -0,0 +1,378 @@
+//! Iterative resolution with a full record of every step, the way
+//! `dig +trace` shows it.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::client::{self, Protocol};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, RecordType};
+
+use super::{scrub, Error, QueryOptions, Resolver};
+
+/// Upper bound on queries sent during one trace.
+const MAX_STEPS: usize = 64;
+
+/// Upper bound on CNAME restarts during one trace.
+const MAX_CNAMES: usize = 8;
+
+/// One transmission of a query to a server.
+#[derive(Debug)]
+pub struct Attempt {
+    pub server: SocketAddr,
+    pub protocol: Protocol,
+    pub rtt: Duration,
+    /// `None` if a response was received.
+    pub error: Option<String>,
+}
+
+/// What a step's response meant for the resolution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    /// The starting point: root name servers learned from the stub resolver.
+    Priming,
+    /// A referral to the servers for `zone`.
+    Referral { zone: DomainName },
+    /// An alias; resolution restarts at `target`.
+    Cname { target: DomainName },
+    /// The final answer (possibly negative).
+    Answer,
+    /// No server for the zone gave a usable response.
+    Failure,
+}
+
+/// A query sent while tracing, with every (re)transmission and the
+/// response that was acted on.
+#[derive(Debug)]
+pub struct Step {
+    /// The zone whose servers were asked.
+    pub zone: DomainName,
+    pub query: Message,
+    pub attempts: Vec<Attempt>,
+    pub response: Option<Message>,
+    /// Wire size of the response.
+    pub response_size: usize,
+    /// Name of the server that answered, when known.
+    pub server_name: Option<DomainName>,
+    /// Records dropped from the response as out of bailiwick; see
+    /// [`scrub`](super::scrub).
+    pub scrubbed: usize,
+    pub kind: StepKind,
+}
+
+/// The full resolution path of a name, returned by [`Resolver::trace`].
+#[derive(Debug, Default)]
+pub struct ResolutionTrace {
+    pub steps: Vec<Step>,
+}
+
+impl ResolutionTrace {
+    /// The final response, if resolution completed.
+    pub fn answer(&self) -> Option<&Message> {
+        self.steps
+            .last()
+            .filter(|s| s.kind == StepKind::Answer)
+            .and_then(|s| s.response.as_ref())
+    }
+
+    /// Every server contacted, in order, including retransmissions.
+    pub fn servers(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.steps
+            .iter()
+            .flat_map(|s| s.attempts.iter().map(|a| a.server))
+    }
+}
+
+impl fmt::Display for ResolutionTrace {
+    /// Renders the trace like `dig +trace`: the records of each response
+    /// followed by where they came from.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            for a in step.attempts.iter().filter(|a| a.error.is_some()) {
+                writeln!(
+                    f,
+                    ";; {} to {} over {:?}: {}",
+                    step.query.questions[0],
+                    a.server,
+                    a.protocol,
+                    a.error.as_deref().unwrap_or("")
+                )?;
+            }
+            let resp = match &step.response {
+                Some(r) => r,
+                None => {
+                    writeln!(f, ";; no usable response for zone {}\n", step.zone)?;
+                    continue;
+                }
+            };
+            if resp.header.rcode != Rcode::NOERROR {
+                writeln!(f, ";; status: {}", resp.header.rcode)?;
+            }
+            if step.scrubbed > 0 {
+                writeln!(f, ";; dropped {} out-of-bailiwick records", step.scrubbed)?;
+            }
+            for rr in resp.answers.iter().chain(&resp.authority) {
+                writeln!(f, "{}", rr)?;
+            }
+            if let Some(a) = step.attempts.iter().find(|a| a.error.is_none()) {
+                let name = step
+                    .server_name
+                    .as_ref()
+                    .map_or_else(|| a.server.ip().to_string(), |n| n.to_string());
+                writeln!(
+                    f,
+                    ";; Received {} bytes from {}#{}({}) in {} ms\n",
+                    step.response_size,
+                    a.server.ip(),
+                    a.server.port(),
+                    name,
+                    a.rtt.as_millis()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A server to try: its name (if known) and address.
+type Server = (Option<DomainName>, SocketAddr);
+
+impl Resolver {
+    /// Resolves `name`/`qtype` iteratively from the root, recording every
+    /// server contacted, each retransmission with its round-trip time,
+    /// every referral and CNAME restart, and the messages received.
+    ///
+    /// The root server list is learned by asking the configured servers for
+    /// `. NS`, like `dig +trace`. Each response is scrubbed of what its
+    /// servers have no authority for before it is followed, so that glue
+    /// outside a delegation is never used; name server addresses missing
+    /// from glue are looked up through the configured servers.
+    pub fn trace(&self, name: &DomainName, qtype: RecordType) -> Result<ResolutionTrace, Error> {
+        let mut trace = ResolutionTrace::default();
+        let timeout = self.config.timeout;
+
+        let priming_query = QueryOptions::default().message(DomainName::root(), RecordType::NS);
+        let mut attempts = Vec::new();
+        let mut priming = Err(Error::NoServers);
+        for &server in &self.config.servers {
+            priming = timed(
+                server,
+                Protocol::Udp,
+                &priming_query,
+                timeout,
+                &mut attempts,
+            )
+            .map_err(Error::Client);
+            if priming.is_ok() {
+                break;
+            }
+        }
+        let mut priming = priming?;
+        let size = wire_size(&priming);
+        let scrubbed = scrub(&mut priming, &DomainName::root());
+        let mut servers = self.servers_from(&priming, &DomainName::root());
+        trace.steps.push(Step {
+            zone: DomainName::root(),
+            query: priming_query,
+            attempts,
+            response_size: size,
+            response: Some(priming),
+            server_name: None,
+            scrubbed,
+            kind: StepKind::Priming,
+        });
+
+        let mut qname = name.clone();
+        let mut zone = DomainName::root();
+        let mut cnames = 0;
+        while trace.steps.len() < MAX_STEPS {
+            let options = QueryOptions {
+                recursion_desired: false,
+                ..QueryOptions::default()
+            };
+            let query = options.message(qname.clone(), qtype);
+            let mut attempts = Vec::new();
+            let mut answered = None;
+            for (server_name, addr) in &servers {
+                if let Ok(resp) = timed(*addr, Protocol::Udp, &query, timeout, &mut attempts) {
+                    let resp = if resp.header.tc {
+                        match timed(*addr, Protocol::Tcp, &query, timeout, &mut attempts) {
+                            Ok(resp) => resp,
+                            Err(_) => continue,
+                        }
+                    } else {
+                        resp
+                    };
+                    if resp.header.rcode == Rcode::SERVFAIL || resp.header.rcode == Rcode::REFUSED {
+                        continue;
+                    }
+                    answered = Some((server_name.clone(), resp));
+                    break;
+                }
+            }
+            let (server_name, mut resp) = match answered {
+                Some(a) => a,
+                None => {
+                    trace.steps.push(Step {
+                        zone,
+                        query,
+                        attempts,
+                        response: None,
+                        response_size: 0,
+                        server_name: None,
+                        scrubbed: 0,
+                        kind: StepKind::Failure,
+                    });
+                    return Ok(trace);
+                }
+            };
+            // Only what the servers of `zone` may speak for is acted on.
+            let size = wire_size(&resp);
+            let scrubbed = scrub(&mut resp, &zone);
+            let kind = classify(&resp, &qname, qtype, &zone);
+            let next_servers = match &kind {
+                StepKind::Referral { zone } => self.servers_from(&resp, zone),
+                _ => Vec::new(),
+            };
+            let step_zone = zone.clone();
+            match &kind {
+                StepKind::Referral { zone: child } => {
+                    zone = child.clone();
+                    servers = next_servers;
+                }
+                StepKind::Cname { target } => {
+                    cnames += 1;
+                    qname = target.clone();
+                    zone = DomainName::root();
+                    servers = self.servers_from(trace.steps[0].response.as_ref().unwrap(), &zone);
+                }
+                _ => {}
+            }
+            let done = kind == StepKind::Answer || kind == StepKind::Failure || cnames > MAX_CNAMES;
+            trace.steps.push(Step {
+                zone: step_zone,
+                query,
+                attempts,
+                response_size: size,
+                response: Some(resp),
+                server_name,
+                scrubbed,
+                kind,
+            });
+            if done || servers.is_empty() {
+                break;
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Server addresses for the NS records of `zone` in `resp`, using glue
+    /// when present and the stub resolver otherwise.
+    fn servers_from(&self, resp: &Message, zone: &DomainName) -> Vec<Server> {
+        let mut servers = Vec::new();
+        for rr in resp.answers.iter().chain(&resp.authority) {
+            let ns = match &rr.rdata {
+                RData::Ns(ns) if &rr.name == zone => ns,
+                _ => continue,
+            };
+            let glue: Vec<IpAddr> = resp
+                .additional
+                .iter()
+                .filter(|g| &g.name == ns)
+                .filter_map(|g| match g.rdata {
+                    RData::A(a) => Some(IpAddr::V4(a)),
+                    RData::Aaaa(a) => Some(IpAddr::V6(a)),
+                    _ => None,
+                })
+                .collect();
+            let addrs = if glue.is_empty() {
+                self.lookup(ns, RecordType::A)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|rr| match rr.rdata {
+                        RData::A(a) => Some(IpAddr::V4(a)),
+                        _ => None,
+                    })
+                    .collect()
+            } else {
+                glue
+            };
+            servers.extend(
+                addrs
+                    .into_iter()
+                    .map(|a| (Some(ns.clone()), SocketAddr::new(a, 53))),
+            );
+        }
+        servers
+    }
+}
+
+/// Resolves `name`/`qtype` with [`Resolver::trace`] using the system
+/// configuration.
+pub fn trace(name: &DomainName, qtype: RecordType) -> Result<ResolutionTrace, Error> {
+    let resolver = Resolver::system().map_err(|e| Error::Client(client::Error::Io(e)))?;
+    resolver.trace(name, qtype)
+}
+
+fn wire_size(msg: &Message) -> usize {
+    msg.to_wire().map(|w| w.len()).unwrap_or(0)
+}
+
+/// Sends one query, recording the attempt.
+fn timed(
+    server: SocketAddr,
+    protocol: Protocol,
+    query: &Message,
+    timeout: Duration,
+    attempts: &mut Vec<Attempt>,
+) -> Result<Message, client::Error> {
+    let start = Instant::now();
+    let result = client::exchange(server, protocol, query, timeout);
+    attempts.push(Attempt {
+        server,
+        protocol,
+        rtt: start.elapsed(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+    });
+    result
+}
+
+/// Decides what a response from the servers of `zone` means.
+fn classify(resp: &Message, qname: &DomainName, qtype: RecordType, zone: &DomainName) -> StepKind {
+    if resp.header.rcode != Rcode::NOERROR {
+        return StepKind::Answer;
+    }
+    let at_name = |t: RecordType| {
+        resp.answers
+            .iter()
+            .any(|rr| &rr.name == qname && rr.rtype() == t)
+    };
+    if at_name(qtype) || (!resp.answers.is_empty() && qtype == RecordType::ANY) {
+        return StepKind::Answer;
+    }
+    if let Some(target) = resp.answers.iter().find_map(|rr| match &rr.rdata {
+        RData::Cname(target) if &rr.name == qname => Some(target.clone()),
+        _ => None,
+    }) {
+        return StepKind::Cname { target };
+    }
+    let referral = resp.authority.iter().find_map(|rr| match rr.rdata {
+        RData::Ns(_)
+            if qname.is_subdomain_of(&rr.name)
+                && rr.name.is_subdomain_of(zone)
+                && &rr.name != zone =>
+        {
+            Some(rr.name.clone())
+        }
+        _ => None,
+    });
+    match referral {
+        Some(child) if !resp.header.aa => StepKind::Referral { zone: child },
+        // An authoritative response without the data is NODATA; a
+        // non-authoritative one pointing sideways or upwards is lame.
+        _ if resp.header.aa => StepKind::Answer,
+        _ => StepKind::Failure,
+    }
+}
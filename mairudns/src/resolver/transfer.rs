@@ -0,0 +1,160 @@
+//! Zone transfers read record by record.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+use crate::tsig::{self, StreamVerifier};
+use crate::zone::{transfer_request, Connection, TransferEnd, TransferError};
+
+use super::Resolver;
+
+/// Settings for one zone transfer.
+#[derive(Clone, Debug)]
+pub struct TransferOptions {
+    /// Bounds the connection and each read and write on it.
+    pub timeout: Duration,
+    /// Signs the request and checks the response with TSIG when set.
+    pub key: Option<tsig::Key>,
+    /// The zone's SOA as already held; asks for an IXFR from its serial
+    /// instead of a full AXFR.
+    pub since: Option<Record>,
+    /// Fails the transfer once it has yielded this many records; `None`
+    /// lets it run to its end however long that is.
+    pub max_records: Option<usize>,
+}
+
+impl Default for TransferOptions {
+    fn default() -> TransferOptions {
+        TransferOptions {
+            timeout: Duration::from_secs(30),
+            key: None,
+            since: None,
+            max_records: None,
+        }
+    }
+}
+
+/// The records of a running AXFR or IXFR, from the opening SOA to the
+/// closing one, in the order the server sends them.
+///
+/// Only the response message being read is held in memory: the next
+/// message is read from the connection when the records of the last one
+/// have been taken, so a consumer that stops pulling stops the transfer
+/// too, and TCP flow control holds the server back. The iterator ends
+/// after the closing SOA, or after the first error.
+pub struct Transfer {
+    conn: Connection,
+    query: Message,
+    verifier: Option<StreamVerifier>,
+    end: TransferEnd,
+    pending: std::vec::IntoIter<Record>,
+    count: usize,
+    max_records: Option<usize>,
+    done: bool,
+}
+
+impl Transfer {
+    /// Sends `query` on `conn`. An IXFR response holding only an SOA no
+    /// newer than `held` ends after that SOA.
+    pub(crate) fn start(
+        mut conn: Connection,
+        query: Message,
+        held: Option<u32>,
+        max_records: Option<usize>,
+    ) -> Result<Transfer, TransferError> {
+        let verifier = conn.send(&query)?;
+        Ok(Transfer {
+            conn,
+            query,
+            verifier,
+            end: TransferEnd::new(held),
+            pending: Vec::new().into_iter(),
+            count: 0,
+            max_records,
+            done: false,
+        })
+    }
+
+    /// How many records have been yielded so far.
+    pub fn yielded(&self) -> usize {
+        self.count
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>, TransferError> {
+        let rr = loop {
+            if let Some(rr) = self.pending.next() {
+                break rr;
+            }
+            let resp = self.conn.recv(&self.query, &mut self.verifier)?;
+            self.pending = resp.answers.into_iter();
+        };
+        if self.max_records.is_some_and(|max| self.count >= max) {
+            return Err(TransferError::TooManyRecords(self.count));
+        }
+        self.count += 1;
+        if self.end.push(&rr)? {
+            self.done = true;
+            if self.verifier.as_ref().is_some_and(|v| !v.is_complete()) {
+                return Err(TransferError::Tsig(tsig::Error::Unsigned));
+            }
+        }
+        Ok(Some(rr))
+    }
+}
+
+impl Iterator for Transfer {
+    type Item = Result<Record, TransferError>;
+
+    fn next(&mut self) -> Option<Result<Record, TransferError>> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_record();
+        if next.is_err() {
+            self.done = true;
+        }
+        next.transpose()
+    }
+}
+
+/// Starts a transfer of `zone` from `server`: an AXFR, or an IXFR if
+/// [`TransferOptions::since`] is set.
+pub fn transfer(
+    server: SocketAddr,
+    zone: &DomainName,
+    options: &TransferOptions,
+) -> Result<Transfer, TransferError> {
+    let conn = Connection::open(server, options.timeout, options.key.clone())?;
+    let held = options.since.as_ref().and_then(|rr| match &rr.rdata {
+        RData::Soa(soa) => Some(soa.serial),
+        _ => None,
+    });
+    let qtype = match held {
+        Some(_) => RecordType::IXFR,
+        None => RecordType::AXFR,
+    };
+    let query = transfer_request(zone, qtype, options.since.clone());
+    Transfer::start(conn, query, held, options.max_records)
+}
+
+impl Resolver {
+    /// Starts a transfer of `zone` from the first configured server that
+    /// accepts the connection.
+    pub fn transfer(
+        &self,
+        zone: &DomainName,
+        options: &TransferOptions,
+    ) -> Result<Transfer, TransferError> {
+        let mut last_err = TransferError::NoPrimaries;
+        for &server in &self.config.servers {
+            match transfer(server, zone, options) {
+                Ok(transfer) => return Ok(transfer),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+}
@@ -0,0 +1,515 @@
+//! Resource records: types, classes and record data.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::compliance::MAX_TTL;
+use crate::name::DomainName;
+use crate::wire::{self, Decoder, Encoder};
+
+macro_rules! code_table {
+    ($ty:ident, $prefix:expr, { $($konst:ident = $val:expr, $text:expr;)* }) => {
+        impl $ty {
+            $(pub const $konst: $ty = $ty($val);)*
+
+            /// The mnemonic for well-known values.
+            pub fn mnemonic(self) -> Option<&'static str> {
+                match self.0 {
+                    $($val => Some($text),)*
+                    _ => None,
+                }
+            }
+        }
+
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self.mnemonic() {
+                    Some(s) => f.write_str(s),
+                    None => write!(f, concat!($prefix, "{}"), self.0),
+                }
+            }
+        }
+
+        impl FromStr for $ty {
+            type Err = ();
+
+            fn from_str(s: &str) -> Result<$ty, ()> {
+                let upper = s.to_ascii_uppercase();
+                match upper.as_str() {
+                    $($text => Ok($ty($val)),)*
+                    _ => upper
+                        .strip_prefix($prefix)
+                        .and_then(|n| n.parse().ok())
+                        .map($ty)
+                        .ok_or(()),
+                }
+            }
+        }
+    };
+}
+
+/// A record type (`TYPE` in RFC 1035 terms), including query-only types.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordType(pub u16);
+
+code_table!(RecordType, "TYPE", {
+    A = 1, "A";
+    NS = 2, "NS";
+    CNAME = 5, "CNAME";
+    SOA = 6, "SOA";
+    PTR = 12, "PTR";
+    HINFO = 13, "HINFO";
+    MX = 15, "MX";
+    TXT = 16, "TXT";
+    AAAA = 28, "AAAA";
+    SRV = 33, "SRV";
+    NAPTR = 35, "NAPTR";
+    DNAME = 39, "DNAME";
+    OPT = 41, "OPT";
+    DS = 43, "DS";
+    SSHFP = 44, "SSHFP";
+    RRSIG = 46, "RRSIG";
+    NSEC = 47, "NSEC";
+    DNSKEY = 48, "DNSKEY";
+    NSEC3 = 50, "NSEC3";
+    NSEC3PARAM = 51, "NSEC3PARAM";
+    TLSA = 52, "TLSA";
+    CDS = 59, "CDS";
+    CDNSKEY = 60, "CDNSKEY";
+    ZONEMD = 63, "ZONEMD";
+    SVCB = 64, "SVCB";
+    HTTPS = 65, "HTTPS";
+    TSIG = 250, "TSIG";
+    IXFR = 251, "IXFR";
+    AXFR = 252, "AXFR";
+    ANY = 255, "ANY";
+    CAA = 257, "CAA";
+});
+
+impl RecordType {
+    /// Whether this type may only appear in questions or as a pseudo-record.
+    pub fn is_meta(self) -> bool {
+        self == RecordType::OPT || (128..=255).contains(&self.0)
+    }
+}
+
+/// A record class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordClass(pub u16);
+
+code_table!(RecordClass, "CLASS", {
+    IN = 1, "IN";
+    CH = 3, "CH";
+    HS = 4, "HS";
+    NONE = 254, "NONE";
+    ANY = 255, "ANY";
+});
+
+/// The SOA record data.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Soa {
+    pub mname: DomainName,
+    pub rname: DomainName,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+/// Record data, decoded for the types this crate understands.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ns(DomainName),
+    Cname(DomainName),
+    Ptr(DomainName),
+    Dname(DomainName),
+    Soa(Soa),
+    Mx {
+        preference: u16,
+        exchange: DomainName,
+    },
+    Txt(Vec<Vec<u8>>),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName,
+    },
+    /// Data of a type without a dedicated variant, kept as opaque bytes.
+    Unknown {
+        rtype: RecordType,
+        data: Vec<u8>,
+    },
+}
+
+impl RData {
+    /// The record type this data belongs to.
+    pub fn rtype(&self) -> RecordType {
+        match self {
+            RData::A(_) => RecordType::A,
+            RData::Aaaa(_) => RecordType::AAAA,
+            RData::Ns(_) => RecordType::NS,
+            RData::Cname(_) => RecordType::CNAME,
+            RData::Ptr(_) => RecordType::PTR,
+            RData::Dname(_) => RecordType::DNAME,
+            RData::Soa(_) => RecordType::SOA,
+            RData::Mx { .. } => RecordType::MX,
+            RData::Txt(_) => RecordType::TXT,
+            RData::Srv { .. } => RecordType::SRV,
+            RData::Unknown { rtype, .. } => *rtype,
+        }
+    }
+
+    /// The domain name embedded in this data that may need additional
+    /// section processing or glue, if any.
+    pub fn target(&self) -> Option<&DomainName> {
+        match self {
+            RData::Ns(n) | RData::Cname(n) | RData::Ptr(n) | RData::Dname(n) => Some(n),
+            RData::Mx { exchange, .. } => Some(exchange),
+            RData::Srv { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// The record data in canonical form: uncompressed, with the embedded
+    /// names of the types RFC 4034 §6.2 lists in lowercase.
+    pub(crate) fn canonical(&self) -> Vec<u8> {
+        let lower = match self {
+            RData::Ns(n) => RData::Ns(n.to_lowercase()),
+            RData::Cname(n) => RData::Cname(n.to_lowercase()),
+            RData::Ptr(n) => RData::Ptr(n.to_lowercase()),
+            RData::Dname(n) => RData::Dname(n.to_lowercase()),
+            RData::Soa(soa) => RData::Soa(Soa {
+                mname: soa.mname.to_lowercase(),
+                rname: soa.rname.to_lowercase(),
+                ..soa.clone()
+            }),
+            RData::Mx {
+                preference,
+                exchange,
+            } => RData::Mx {
+                preference: *preference,
+                exchange: exchange.to_lowercase(),
+            },
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => RData::Srv {
+                priority: *priority,
+                weight: *weight,
+                port: *port,
+                target: target.to_lowercase(),
+            },
+            other => other.clone(),
+        };
+        let mut enc = Encoder::uncompressed();
+        // Only character strings over 255 bytes fail, and those never decode.
+        let _ = lower.encode(&mut enc);
+        enc.into_bytes()
+    }
+
+    /// Encodes the data (without the length prefix).
+    pub fn encode(&self, enc: &mut Encoder) -> Result<(), wire::Error> {
+        match self {
+            RData::A(a) => enc.bytes(&a.octets()),
+            RData::Aaaa(a) => enc.bytes(&a.octets()),
+            RData::Ns(n) | RData::Cname(n) | RData::Ptr(n) => enc.name(n, true),
+            RData::Dname(n) => enc.name(n, false),
+            RData::Soa(soa) => {
+                enc.name(&soa.mname, true);
+                enc.name(&soa.rname, true);
+                enc.u32(soa.serial);
+                enc.u32(soa.refresh);
+                enc.u32(soa.retry);
+                enc.u32(soa.expire);
+                enc.u32(soa.minimum);
+            }
+            RData::Mx {
+                preference,
+                exchange,
+            } => {
+                enc.u16(*preference);
+                enc.name(exchange, true);
+            }
+            RData::Txt(strings) => {
+                for s in strings {
+                    enc.character_string(s)?;
+                }
+            }
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                enc.u16(*priority);
+                enc.u16(*weight);
+                enc.u16(*port);
+                enc.name(target, false);
+            }
+            RData::Unknown { data, .. } => enc.bytes(data),
+        }
+        Ok(())
+    }
+
+    /// Decodes `len` bytes of data for `rtype` at the decoder's position.
+    pub fn decode(
+        rtype: RecordType,
+        dec: &mut Decoder<'_>,
+        len: usize,
+    ) -> Result<RData, wire::Error> {
+        let start = dec.pos();
+        let end = start + len;
+        if dec.remaining() < len {
+            return Err(dec.truncated());
+        }
+        let rdata = match rtype {
+            RecordType::A if len == 4 => {
+                let b = dec.bytes(4)?;
+                RData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            RecordType::AAAA if len == 16 => {
+                let mut o = [0u8; 16];
+                o.copy_from_slice(dec.bytes(16)?);
+                RData::Aaaa(Ipv6Addr::from(o))
+            }
+            RecordType::A | RecordType::AAAA => return Err(dec.bad_rdata()),
+            RecordType::NS => RData::Ns(dec.name()?),
+            RecordType::CNAME => RData::Cname(dec.name()?),
+            RecordType::PTR => RData::Ptr(dec.name()?),
+            RecordType::DNAME => RData::Dname(dec.uncompressed_name()?),
+            RecordType::SOA => RData::Soa(Soa {
+                mname: dec.name()?,
+                rname: dec.name()?,
+                serial: dec.u32()?,
+                refresh: dec.u32()?,
+                retry: dec.u32()?,
+                expire: dec.u32()?,
+                minimum: dec.u32()?,
+            }),
+            RecordType::MX => RData::Mx {
+                preference: dec.u16()?,
+                exchange: dec.name()?,
+            },
+            RecordType::TXT => {
+                let mut strings = Vec::new();
+                while dec.pos() < end {
+                    strings.push(dec.character_string()?.to_vec());
+                }
+                if strings.is_empty() && !dec.compliance().empty_txt {
+                    return Err(wire::Error::BadRdata { offset: start });
+                }
+                RData::Txt(strings)
+            }
+            RecordType::SRV => RData::Srv {
+                priority: dec.u16()?,
+                weight: dec.u16()?,
+                port: dec.u16()?,
+                target: dec.uncompressed_name()?,
+            },
+            _ => RData::Unknown {
+                rtype,
+                data: dec.bytes(len)?.to_vec(),
+            },
+        };
+        if dec.pos() != end {
+            return Err(wire::Error::BadRdata { offset: start });
+        }
+        Ok(rdata)
+    }
+}
+
+fn write_character_string(f: &mut fmt::Formatter<'_>, s: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &c in s {
+        match c {
+            b'"' | b'\\' => write!(f, "\\{}", c as char)?,
+            c if !(b' '..0x7f).contains(&c) => write!(f, "\\{:03}", c)?,
+            c => write!(f, "{}", c as char)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RData::A(a) => write!(f, "{}", a),
+            RData::Aaaa(a) => write!(f, "{}", a),
+            RData::Ns(n) | RData::Cname(n) | RData::Ptr(n) | RData::Dname(n) => write!(f, "{}", n),
+            RData::Soa(s) => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                s.mname, s.rname, s.serial, s.refresh, s.retry, s.expire, s.minimum
+            ),
+            RData::Mx {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            RData::Txt(strings) => {
+                for (i, s) in strings.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write_character_string(f, s)?;
+                }
+                Ok(())
+            }
+            RData::Srv {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            RData::Unknown { data, .. } => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    f.write_str(" ")?;
+                    for b in data {
+                        write!(f, "{:02x}", b)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A resource record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Record {
+    pub name: DomainName,
+    pub class: RecordClass,
+    pub ttl: u32,
+    pub rdata: RData,
+}
+
+impl Record {
+    pub fn new(name: DomainName, ttl: u32, rdata: RData) -> Record {
+        Record {
+            name,
+            class: RecordClass::IN,
+            ttl,
+            rdata,
+        }
+    }
+
+    pub fn rtype(&self) -> RecordType {
+        self.rdata.rtype()
+    }
+
+    /// Encodes the full record including owner name and rdata length.
+    pub fn encode(&self, enc: &mut Encoder) -> Result<(), wire::Error> {
+        enc.name(&self.name, true);
+        enc.u16(self.rtype().0);
+        enc.u16(self.class.0);
+        enc.u32(self.ttl);
+        let len_pos = enc.len();
+        enc.u16(0);
+        self.rdata.encode(enc)?;
+        let len = enc.len() - len_pos - 2;
+        if len > usize::from(u16::MAX) {
+            return Err(wire::Error::TooLong { offset: enc.len() });
+        }
+        enc.set_u16(len_pos, len as u16);
+        Ok(())
+    }
+
+    /// Decodes a full record.
+    pub fn decode(dec: &mut Decoder<'_>) -> Result<Record, wire::Error> {
+        let name = dec.name()?;
+        let rtype = RecordType(dec.u16()?);
+        let class = RecordClass(dec.u16()?);
+        let mut ttl = dec.u32()?;
+        // The TTL field of OPT carries flags instead.
+        if ttl > MAX_TTL && rtype != RecordType::OPT && !dec.compliance().large_ttls {
+            ttl = 0;
+        }
+        let len = dec.u16()? as usize;
+        let rdata = if len == 0 && (class == RecordClass::ANY || class == RecordClass::NONE) {
+            // The empty data of dynamic update prerequisites and
+            // deletions (RFC 2136 §2.4, §2.5).
+            RData::Unknown {
+                rtype,
+                data: Vec::new(),
+            }
+        } else {
+            RData::decode(rtype, dec, len)?
+        };
+        Ok(Record {
+            name,
+            class,
+            ttl,
+            rdata,
+        })
+    }
+}
+
+/// The records of one name, class and type, with the TTL they share.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RRset {
+    pub name: DomainName,
+    pub class: RecordClass,
+    pub rtype: RecordType,
+    pub ttl: u32,
+    /// The data of each record, all of type `rtype`.
+    pub rdata: Vec<RData>,
+}
+
+impl RRset {
+    /// `records` grouped into RRsets, in the order each first appears.
+    /// Records of one RRset that disagree on the TTL get the least
+    /// (RFC 2181 §5.2).
+    pub fn group(records: &[Record]) -> Vec<RRset> {
+        let mut sets: Vec<RRset> = Vec::new();
+        for rr in records {
+            let found = sets
+                .iter_mut()
+                .find(|s| s.name == rr.name && s.class == rr.class && s.rtype == rr.rtype());
+            match found {
+                Some(set) => {
+                    set.ttl = set.ttl.min(rr.ttl);
+                    set.rdata.push(rr.rdata.clone());
+                }
+                None => sets.push(RRset {
+                    name: rr.name.clone(),
+                    class: rr.class,
+                    rtype: rr.rtype(),
+                    ttl: rr.ttl,
+                    rdata: vec![rr.rdata.clone()],
+                }),
+            }
+        }
+        sets
+    }
+
+    /// The records of the set.
+    pub fn records(&self) -> impl Iterator<Item = Record> + '_ {
+        self.rdata.iter().map(move |rdata| Record {
+            name: self.name.clone(),
+            class: self.class,
+            ttl: self.ttl,
+            rdata: rdata.clone(),
+        })
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            self.name,
+            self.ttl,
+            self.class,
+            self.rtype(),
+            self.rdata
+        )
+    }
+}
@@ -0,0 +1,174 @@
+//! Address-based access control for queries, recursion, transfers and
+//! updates.
+
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+use crate::addr::{Prefix, PrefixMap};
+use crate::client::Protocol;
+use crate::message::{ExtendedError, Message, Opcode, Rcode};
+
+use super::{Handler, Layer, Request};
+
+/// What to do with a request from a client an [`Acl`] matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AclAction {
+    Allow,
+    /// Answer REFUSED.
+    Refuse,
+    /// Send nothing.
+    Drop,
+    /// Answer UDP requests with an empty truncated response, so that only
+    /// clients able to retry over TCP get through; TCP requests are
+    /// allowed.
+    Truncate,
+}
+
+/// Rules for one kind of request: the action of the longest prefix
+/// containing the client's address, or the default if none does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Acl {
+    rules: PrefixMap<AclAction>,
+    default: AclAction,
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        Acl::new(AclAction::Allow)
+    }
+}
+
+impl Acl {
+    /// An ACL taking `default` for every client until rules are added.
+    pub fn new(default: AclAction) -> Acl {
+        Acl {
+            rules: PrefixMap::new(),
+            default,
+        }
+    }
+
+    /// Adds a rule, replacing any for the same prefix.
+    pub fn rule(mut self, prefix: Prefix, action: AclAction) -> Self {
+        self.rules.insert(prefix, action);
+        self
+    }
+
+    pub fn default_action(&self) -> AclAction {
+        self.default
+    }
+
+    pub fn rules(&self) -> &PrefixMap<AclAction> {
+        &self.rules
+    }
+
+    pub fn action(&self, addr: &IpAddr) -> AclAction {
+        self.rules.lookup(addr).copied().unwrap_or(self.default)
+    }
+}
+
+/// Settings of the [`AccessControlled`] handler. Every ACL allows
+/// everyone by default.
+///
+/// Queries with RD set from clients `recursion` refuses are passed on with
+/// RD cleared, so that they get authoritative and cached data only;
+/// `Drop` and `Truncate` act on them as usual.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessControl {
+    pub queries: Acl,
+    pub recursion: Acl,
+    pub transfers: Acl,
+    pub updates: Acl,
+    /// Text of a Prohibited Extended DNS Error attached to refusals; `None`
+    /// sends none.
+    pub refusal_text: Option<String>,
+}
+
+impl AccessControl {
+    pub fn new() -> AccessControl {
+        AccessControl::default()
+    }
+
+    /// The ACL that applies to `request`, other than `recursion`.
+    pub fn acl_for(&self, request: &Request) -> &Acl {
+        if request.message.header.opcode == Opcode::UPDATE {
+            &self.updates
+        } else if request.is_transfer() {
+            &self.transfers
+        } else {
+            &self.queries
+        }
+    }
+
+    fn refusal(&self, request: &Request) -> Message {
+        let mut resp = request.message.response();
+        resp.header.rcode = Rcode::REFUSED;
+        if let Some(text) = &self.refusal_text {
+            resp.add_extended_error(&ExtendedError::new(ExtendedError::PROHIBITED, text.clone()));
+        }
+        resp
+    }
+}
+
+impl<H: Handler> Layer<H> for AccessControl {
+    type Handler = AccessControlled<H>;
+
+    fn layer(&self, inner: H) -> AccessControlled<H> {
+        AccessControlled {
+            config: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler behind address-based ACLs; see [`AccessControl`].
+pub struct AccessControlled<H> {
+    config: AccessControl,
+    inner: H,
+}
+
+impl<H> AccessControlled<H> {
+    pub fn config(&self) -> &AccessControl {
+        &self.config
+    }
+}
+
+impl<H: Handler> Handler for AccessControlled<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let src = request.src.ip();
+        let rd = request.message.header.rd;
+        let mut request = Cow::Borrowed(request);
+        let tcp = request.protocol == Protocol::Tcp;
+        let over_tcp = |action| match action {
+            AclAction::Truncate if tcp => AclAction::Allow,
+            action => action,
+        };
+        let mut action = over_tcp(self.config.acl_for(&request).action(&src));
+        let recursive = request.message.header.opcode == Opcode::QUERY
+            && request.message.header.rd
+            && !request.is_transfer();
+        if action == AclAction::Allow && recursive {
+            action = match over_tcp(self.config.recursion.action(&src)) {
+                AclAction::Refuse => {
+                    request.to_mut().message.header.rd = false;
+                    AclAction::Allow
+                }
+                other => other,
+            };
+        }
+        match action {
+            AclAction::Allow => {
+                let mut resp = self.inner.handle(&request)?;
+                // Echo the RD bit the client sent, not the one cleared.
+                resp.header.rd = rd;
+                Some(resp)
+            }
+            AclAction::Truncate => {
+                let mut resp = request.message.response();
+                resp.header.tc = true;
+                Some(resp)
+            }
+            AclAction::Refuse => Some(self.config.refusal(&request)),
+            AclAction::Drop => None,
+        }
+    }
+}
@@ -0,0 +1,460 @@
+//! An HTTP management API for zones, their records and the forwarding
+//! cache, with JSON bodies. Records take the form described in the serde
+//! support of [`interop`](crate::interop).
+//!
+//! Every request must carry the API token as `Authorization: Bearer
+//! <token>`. The endpoints, under [`API_PATH`]:
+//!
+//! | Method   | Path                                  |                                    |
+//! |----------|---------------------------------------|------------------------------------|
+//! | `GET`    | `/zones`                              | every zone, with serial and size   |
+//! | `GET`    | `/zones/{zone}`                       | a zone and all its records         |
+//! | `GET`    | `/zones/{zone}/rrsets/{name}/{type}`  | one RRset                          |
+//! | `PUT`    | `/zones/{zone}/rrsets/{name}/{type}`  | replace an RRset with the body's   |
+//! | `DELETE` | `/zones/{zone}/rrsets/{name}/{type}`  | remove an RRset                    |
+//! | `POST`   | `/zones/{zone}/transfer`              | refresh a secondary zone now       |
+//! | `GET`    | `/cache?name={pattern}`               | cached responses, all or for names |
+//! | `DELETE` | `/cache?name={pattern}&type={type}`   | flush the cache, all or in part    |
+//! | `GET`    | `/schema`                             | the JSON Schema of zones/records   |
+//!
+//! A cache pattern is a name, or `*.` and a name for the name and every
+//! name below it, as [`NamePattern`] has it; a flush for a pattern can
+//! be narrowed to one type.
+//!
+//! Changes to RRsets bump the zone's serial and are journaled like dynamic
+//! updates, so secondaries pick them up with IXFR. The SOA is left to the
+//! server.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::cache::NamePattern;
+use crate::crypto;
+use crate::interop::json_schema;
+use crate::name::DomainName;
+use crate::rr::{Record, RecordClass, RecordType};
+use crate::zone::{Refresh, Secondary};
+
+use super::{Authority, Forwarder};
+
+/// Where the API is served.
+pub const API_PATH: &str = "/api/v1";
+
+/// Longest request body accepted.
+const MAX_BODY: usize = 1 << 20;
+
+/// The state the API reads and changes.
+#[derive(Clone)]
+pub struct Api {
+    token: String,
+    authority: Arc<Authority>,
+    forwarder: Option<Arc<Forwarder>>,
+    secondaries: HashMap<DomainName, Arc<Secondary>>,
+}
+
+impl fmt::Debug for Api {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Api")
+            .field("zones", &self.authority.origins().len())
+            .field("forwarder", &self.forwarder.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The status and JSON body of an API response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Reply {
+    fn json<T: Serialize>(status: u16, value: &T) -> Reply {
+        Reply {
+            status,
+            body: serde_json::to_string(value).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: impl fmt::Display) -> Reply {
+        Reply::json(status, &json!({ "error": message.to_string() }))
+    }
+}
+
+#[derive(Serialize)]
+struct ZoneSummary {
+    name: DomainName,
+    class: RecordClass,
+    serial: Option<u32>,
+    records: usize,
+    secondary: bool,
+}
+
+#[derive(Serialize)]
+struct CacheEntry {
+    /// The suffix of the route whose cache holds the response.
+    route: DomainName,
+    name: DomainName,
+    #[serde(rename = "type")]
+    qtype: RecordType,
+    class: RecordClass,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+    security: String,
+    rcode: String,
+    expires_in: u64,
+    answers: Vec<Record>,
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                out.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+impl Api {
+    /// An API for the zones of `authority`, open to requests bearing
+    /// `token`.
+    pub fn new(token: impl Into<String>, authority: Arc<Authority>) -> Api {
+        Api {
+            token: token.into(),
+            authority,
+            forwarder: None,
+            secondaries: HashMap::new(),
+        }
+    }
+
+    /// Whose cache the cache endpoints inspect and flush.
+    pub fn forwarder(mut self, forwarder: Arc<Forwarder>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    /// The secondary keeping the zone at `origin` up to date, for the
+    /// transfer endpoint.
+    pub fn secondary(mut self, origin: DomainName, secondary: Arc<Secondary>) -> Self {
+        self.secondaries.insert(origin, secondary);
+        self
+    }
+
+    /// Answers one request. `target` is the path and query, and
+    /// `authorization` the value of the header of that name.
+    pub fn handle(
+        &self,
+        method: &str,
+        target: &str,
+        authorization: Option<&str>,
+        body: &[u8],
+    ) -> Reply {
+        let token = authorization.and_then(|a| a.strip_prefix("Bearer "));
+        if !token.is_some_and(|t| crypto::verify_mac(self.token.as_bytes(), t.trim().as_bytes())) {
+            return Reply::error(401, "missing or wrong token");
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = match path.strip_prefix(API_PATH) {
+            Some(path) => path,
+            None => return Reply::error(404, "not found"),
+        };
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(percent_decode)
+            .collect();
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let param = |key: &str| {
+            query
+                .split('&')
+                .filter_map(|p| p.split_once('='))
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| percent_decode(v))
+        };
+        match (method, segments.as_slice()) {
+            ("GET", ["zones"]) => self.zones(),
+            ("GET", ["zones", zone]) => self.zone(zone),
+            (_, ["zones", zone, "rrsets", name, rtype]) => {
+                self.rrset(method, zone, name, rtype, body)
+            }
+            ("POST", ["zones", zone, "transfer"]) => self.transfer(zone),
+            ("GET", ["cache"]) | ("DELETE", ["cache"]) => {
+                self.cache(method, param("name"), param("type"))
+            }
+            ("GET", ["schema"]) => Reply {
+                status: 200,
+                body: json_schema(),
+            },
+            (_, ["zones"])
+            | (_, ["zones", _])
+            | (_, ["zones", _, "transfer"])
+            | (_, ["cache"])
+            | (_, ["schema"]) => Reply::error(405, "method not allowed"),
+            _ => Reply::error(404, "not found"),
+        }
+    }
+
+    fn zones(&self) -> Reply {
+        let zones: Vec<ZoneSummary> = self
+            .authority
+            .origins()
+            .into_iter()
+            .filter_map(|origin| {
+                let zone = self.authority.zone(&origin)?;
+                let zone = zone.read().unwrap();
+                Some(ZoneSummary {
+                    secondary: self.secondaries.contains_key(&origin),
+                    name: origin,
+                    class: zone.class(),
+                    serial: zone.serial(),
+                    records: zone.len(),
+                })
+            })
+            .collect();
+        Reply::json(200, &zones)
+    }
+
+    fn zone(&self, zone: &str) -> Reply {
+        let origin = match zone.parse::<DomainName>() {
+            Ok(origin) => origin,
+            Err(e) => return Reply::error(400, e),
+        };
+        let zone = match self.authority.zone(&origin) {
+            Some(zone) => zone,
+            None => return Reply::error(404, format!("no zone {}", origin)),
+        };
+        let zone = zone.read().unwrap();
+        let records: Vec<&Record> = zone.records().collect();
+        Reply::json(
+            200,
+            &json!({
+                "name": origin,
+                "class": zone.class(),
+                "serial": zone.serial(),
+                "records": records,
+            }),
+        )
+    }
+
+    fn rrset(&self, method: &str, zone: &str, name: &str, rtype: &str, body: &[u8]) -> Reply {
+        let origin = match zone.parse::<DomainName>() {
+            Ok(origin) => origin,
+            Err(e) => return Reply::error(400, e),
+        };
+        let name = match name.parse::<DomainName>() {
+            Ok(name) if name.is_subdomain_of(&origin) => name,
+            Ok(name) => return Reply::error(400, format!("{} is outside {}", name, origin)),
+            Err(e) => return Reply::error(400, e),
+        };
+        let rtype = match rtype.parse::<RecordType>() {
+            Ok(rtype) => rtype,
+            Err(()) => return Reply::error(400, format!("unknown type {:?}", rtype)),
+        };
+        let shared = match self.authority.zone(&origin) {
+            Some(zone) => zone,
+            None => return Reply::error(404, format!("no zone {}", origin)),
+        };
+        if method == "GET" {
+            let zone = shared.read().unwrap();
+            return match zone.rrset(&name, rtype) {
+                Some(records) => Reply::json(200, &records),
+                None => Reply::error(404, format!("no {} records at {}", rtype, name)),
+            };
+        }
+        if method != "PUT" && method != "DELETE" {
+            return Reply::error(405, "method not allowed");
+        }
+        if rtype == RecordType::SOA {
+            return Reply::error(409, "the SOA is maintained by the server");
+        }
+        let records: Vec<Record> = if method == "PUT" {
+            match serde_json::from_slice(body) {
+                Ok(records) => records,
+                Err(e) => return Reply::error(400, e),
+            }
+        } else {
+            Vec::new()
+        };
+        let mut zone = shared.write().unwrap();
+        if let Some(rr) = records
+            .iter()
+            .find(|rr| rr.name != name || rr.rtype() != rtype || rr.class != zone.class())
+        {
+            return Reply::error(
+                400,
+                format!(
+                    "{} {} {} does not belong in this RRset",
+                    rr.name,
+                    rr.class,
+                    rr.rtype()
+                ),
+            );
+        }
+        match zone.replace_rrset(&name, rtype, records) {
+            Ok(changed) => {
+                Reply::json(200, &json!({ "changed": changed, "serial": zone.serial() }))
+            }
+            Err(e) => Reply::error(409, e),
+        }
+    }
+
+    fn transfer(&self, zone: &str) -> Reply {
+        let origin = match zone.parse::<DomainName>() {
+            Ok(origin) => origin,
+            Err(e) => return Reply::error(400, e),
+        };
+        let secondary = match self.secondaries.get(&origin) {
+            Some(secondary) => secondary,
+            None => return Reply::error(404, format!("{} is not a secondary zone", origin)),
+        };
+        match secondary.refresh() {
+            Ok(Refresh::UpToDate { serial }) => {
+                Reply::json(200, &json!({ "transferred": false, "serial": serial }))
+            }
+            Ok(Refresh::Transferred {
+                serial,
+                incremental,
+            }) => Reply::json(
+                200,
+                &json!({ "transferred": true, "serial": serial, "incremental": incremental }),
+            ),
+            Err(e) => Reply::error(502, e),
+        }
+    }
+
+    fn cache(&self, method: &str, pattern: Option<String>, rtype: Option<String>) -> Reply {
+        let forwarder = match &self.forwarder {
+            Some(forwarder) => forwarder,
+            None => return Reply::error(404, "no cache"),
+        };
+        let pattern = match pattern.map(|p| p.parse::<NamePattern>()).transpose() {
+            Ok(pattern) => pattern,
+            Err(e) => return Reply::error(400, e),
+        };
+        let rtype = match rtype.map(|t| t.parse::<RecordType>()).transpose() {
+            Ok(rtype) => rtype,
+            Err(_) => return Reply::error(400, "invalid type"),
+        };
+        if method == "DELETE" {
+            if pattern.is_none() && rtype.is_none() {
+                forwarder.flush();
+                return Reply::json(200, &json!({ "flushed": true }));
+            }
+            let pattern = pattern.unwrap_or_else(NamePattern::any);
+            let dropped = forwarder.flush_matching(&pattern, rtype);
+            return Reply::json(200, &json!({ "flushed": true, "responses": dropped }));
+        }
+        let entries: Vec<CacheEntry> = forwarder
+            .inspect_cache(&pattern.unwrap_or_else(NamePattern::any))
+            .into_iter()
+            .map(|(route, c)| CacheEntry {
+                route,
+                name: c.question.name,
+                qtype: c.question.qtype,
+                class: c.question.qclass,
+                dnssec_ok: c.dnssec_ok,
+                checking_disabled: c.checking_disabled,
+                security: c.security.to_string(),
+                rcode: c.response.header.rcode.to_string(),
+                expires_in: c.expires_in.as_secs(),
+                answers: c.response.answers,
+            })
+            .collect();
+        Reply::json(200, &entries)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        502 => "Bad Gateway",
+        _ => "Error",
+    }
+}
+
+/// Answers one HTTP/1.1 request on `stream`, then closes the connection.
+pub(crate) fn serve_http<S: Read + Write>(stream: &mut S, api: &Api) -> io::Result<()> {
+    const MAX_HEAD: usize = 8 * 1024;
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buf.len() > MAX_HEAD {
+            return Ok(());
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Ok(()),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("");
+    let target = request_line.next().unwrap_or("");
+    let mut content_length = 0;
+    let mut authorization = None;
+    for line in lines {
+        if let Some((field, value)) = line.split_once(':') {
+            if field.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(usize::MAX);
+            } else if field.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim());
+            }
+        }
+    }
+    let reply = if content_length > MAX_BODY {
+        Reply::error(413, "body too large")
+    } else {
+        let mut body = buf[head_end..].to_vec();
+        while body.len() < content_length {
+            match stream.read(&mut chunk)? {
+                0 => return Ok(()),
+                n => body.extend_from_slice(&chunk[..n]),
+            }
+        }
+        body.truncate(content_length);
+        api.handle(method, target, authorization, &body)
+    };
+    let resp = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status,
+        reason(reply.status),
+        reply.body.len(),
+        reply.body
+    );
+    stream.write_all(resp.as_bytes())?;
+    stream.flush()
+}
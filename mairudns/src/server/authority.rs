@@ -0,0 +1,491 @@
+//! A handler answering authoritatively from a set of zones.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::addr::Prefix;
+use crate::client::Protocol;
+use crate::message::{Message, Opcode, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, RecordClass, RecordType};
+use crate::zone::{serial_cmp, Backend, BackendError, UpdatePolicy, Zone};
+
+#[cfg(feature = "dnssec")]
+use crate::dnssec::{add_proofs, OnlineSigner, SignError};
+#[cfg(feature = "dnssec")]
+use crate::zone::Answer;
+
+use super::{Handler, Request};
+
+/// A zone shared between the handler and whatever keeps it up to date.
+pub type SharedZone = Arc<RwLock<Zone>>;
+
+/// Who may transfer a zone. A request must come from one of `addresses`
+/// (any address if empty) and, if `keys` is not empty, be signed with one
+/// of them. An ACL with both lists empty allows nobody.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransferAcl {
+    pub addresses: Vec<Prefix>,
+    pub keys: Vec<DomainName>,
+}
+
+impl TransferAcl {
+    pub fn allows(&self, src: IpAddr, key: Option<&DomainName>) -> bool {
+        if self.addresses.is_empty() && self.keys.is_empty() {
+            return false;
+        }
+        let addr_ok = self.addresses.is_empty() || self.addresses.iter().any(|p| p.contains(&src));
+        let key_ok = self.keys.is_empty() || key.is_some_and(|k| self.keys.contains(k));
+        addr_ok && key_ok
+    }
+}
+
+/// Serves queries from the zones it holds, choosing the closest enclosing
+/// zone for each query. Queries outside every zone are refused; zones
+/// without an SOA get SERVFAIL.
+///
+/// Zone transfers are served over TCP to clients a zone's [`TransferAcl`]
+/// allows: IXFR from the zone's journal when it reaches back to the
+/// client's serial, otherwise the whole zone.
+///
+/// Dynamic updates (RFC 2136) must be signed with TSIG and are applied
+/// when the zone's [`UpdatePolicy`] allows every change; each accepted
+/// update bumps the serial and is journaled. SIG(0) is not supported, so
+/// updates signed that way are refused like unsigned ones.
+///
+/// A zone given a report channel advertises its agent domain in the
+/// answers to queries with EDNS, so that resolvers failing to resolve it
+/// can report why (RFC 9567).
+///
+/// Zones served from a [`Backend`] answer queries and transfers alike,
+/// though IXFR requests get the whole zone; they cannot be updated
+/// dynamically.
+///
+/// With the `dnssec` feature, zones held in memory can be given a
+/// `dnssec::OnlineSigner`, which signs their answers to queries with the
+/// DO bit set as they are given, so updates need no re-signing.
+#[derive(Debug, Default)]
+pub struct Authority {
+    zones: RwLock<BTreeMap<DomainName, SharedZone>>,
+    backends: RwLock<BTreeMap<DomainName, Arc<dyn Backend>>>,
+    transfer_acls: RwLock<HashMap<DomainName, TransferAcl>>,
+    update_policies: RwLock<HashMap<DomainName, UpdatePolicy>>,
+    report_channels: RwLock<HashMap<DomainName, DomainName>>,
+    #[cfg(feature = "dnssec")]
+    signers: RwLock<HashMap<DomainName, Arc<OnlineSigner>>>,
+}
+
+impl Authority {
+    pub fn new() -> Authority {
+        Authority::default()
+    }
+
+    /// Adds `zone`, replacing any zone with the same origin.
+    pub fn insert(&self, zone: Zone) -> SharedZone {
+        let origin = zone.origin().clone();
+        #[cfg(feature = "dnssec")]
+        let zone = self.published(zone);
+        let shared = Arc::new(RwLock::new(zone));
+        self.zones.write().unwrap().insert(origin, shared.clone());
+        shared
+    }
+
+    pub fn remove(&self, origin: &DomainName) -> Option<SharedZone> {
+        self.zones.write().unwrap().remove(origin)
+    }
+
+    /// Serves the zone at `origin` from `backend`, in place of any zone
+    /// with the same origin.
+    pub fn insert_backend(&self, origin: DomainName, backend: Arc<dyn Backend>) {
+        self.zones.write().unwrap().remove(&origin);
+        self.backends.write().unwrap().insert(origin, backend);
+    }
+
+    pub fn remove_backend(&self, origin: &DomainName) -> Option<Arc<dyn Backend>> {
+        self.backends.write().unwrap().remove(origin)
+    }
+
+    /// The backend serving the zone with exactly this origin.
+    pub fn backend(&self, origin: &DomainName) -> Option<Arc<dyn Backend>> {
+        self.backends.read().unwrap().get(origin).cloned()
+    }
+
+    /// The zone with exactly this origin.
+    pub fn zone(&self, origin: &DomainName) -> Option<SharedZone> {
+        self.zones.read().unwrap().get(origin).cloned()
+    }
+
+    /// The closest zone containing `name`, unless that zone is served
+    /// from a backend.
+    pub fn find(&self, name: &DomainName) -> Option<SharedZone> {
+        match self.source(name)? {
+            Source::Memory(zone) => Some(zone),
+            Source::Backend(..) => None,
+        }
+    }
+
+    /// The origin of the closest zone containing `name`, whether it is
+    /// held in memory or served from a backend.
+    pub fn zone_origin(&self, name: &DomainName) -> Option<DomainName> {
+        match self.source(name)? {
+            Source::Memory(zone) => Some(zone.read().unwrap().origin().clone()),
+            Source::Backend(origin, _) => Some(origin),
+        }
+    }
+
+    /// The closest zone containing `name`, wherever it is kept.
+    fn source(&self, name: &DomainName) -> Option<Source> {
+        let zones = self.zones.read().unwrap();
+        let backends = self.backends.read().unwrap();
+        (0..=name.label_count()).rev().find_map(|n| {
+            let origin = name.suffix(n);
+            match (zones.get(&origin), backends.get(&origin)) {
+                (Some(zone), _) => Some(Source::Memory(zone.clone())),
+                (None, Some(backend)) => Some(Source::Backend(origin, backend.clone())),
+                (None, None) => None,
+            }
+        })
+    }
+
+    /// The owner of the wildcard answering for `name` in the closest zone
+    /// containing it, if any.
+    pub fn wildcard_owner(&self, name: &DomainName) -> Option<DomainName> {
+        match self.source(name)? {
+            Source::Memory(zone) => zone.read().unwrap().wildcard_owner(name),
+            Source::Backend(origin, backend) => {
+                backend.wildcard_owner(&origin, name).ok().flatten()
+            }
+        }
+    }
+
+    /// Sets who may transfer the zone at `origin`. Without an ACL, nobody
+    /// may.
+    pub fn set_transfer_acl(&self, origin: DomainName, acl: TransferAcl) {
+        self.transfer_acls.write().unwrap().insert(origin, acl);
+    }
+
+    /// Sets which keys may update the zone at `origin`. Without a policy,
+    /// updates are refused.
+    pub fn set_update_policy(&self, origin: DomainName, policy: UpdatePolicy) {
+        self.update_policies.write().unwrap().insert(origin, policy);
+    }
+
+    /// Advertises `agent` in the answers of the zone at `origin` as the
+    /// domain to send error reports to, or stops advertising one.
+    pub fn set_report_channel(&self, origin: DomainName, agent: Option<DomainName>) {
+        let mut channels = self.report_channels.write().unwrap();
+        match agent {
+            Some(agent) => channels.insert(origin, agent),
+            None => channels.remove(&origin),
+        };
+    }
+
+    /// Signs the answers of the zone at `origin` with `signer` from now on,
+    /// publishing its keys in the zone if it is already held.
+    #[cfg(feature = "dnssec")]
+    pub fn set_signer(
+        &self,
+        origin: DomainName,
+        signer: Arc<OnlineSigner>,
+    ) -> Result<(), SignError> {
+        if let Some(zone) = self.zone(&origin) {
+            signer.publish(&mut zone.write().unwrap())?;
+        }
+        self.signers.write().unwrap().insert(origin, signer);
+        Ok(())
+    }
+
+    /// Stops signing the answers of the zone at `origin`.
+    #[cfg(feature = "dnssec")]
+    pub fn remove_signer(&self, origin: &DomainName) -> Option<Arc<OnlineSigner>> {
+        self.signers.write().unwrap().remove(origin)
+    }
+
+    /// `zone` with the keys of its online signer published, if it has one;
+    /// should that fail, its answers go unsigned.
+    #[cfg(feature = "dnssec")]
+    fn published(&self, mut zone: Zone) -> Zone {
+        if let Some(signer) = self.signers.read().unwrap().get(zone.origin()) {
+            let _ = signer.publish(&mut zone);
+        }
+        zone
+    }
+
+    /// `answer` from `zone` to `query`, with its signatures and denial
+    /// records if the querier asked for DNSSEC records: made by the zone's
+    /// online signer if it has one, or taken from the zone if it was
+    /// signed in advance.
+    #[cfg(feature = "dnssec")]
+    fn signed(&self, zone: &Zone, query: &Message, mut answer: Answer) -> Answer {
+        let q = &query.questions[0];
+        if !query.edns.as_ref().is_some_and(|e| e.dnssec_ok) {
+            return answer;
+        }
+        if let Some(signer) = self.signers.read().unwrap().get(zone.origin()) {
+            signer.sign_answer(zone, &q.name, q.qtype, &mut answer);
+        } else if zone.rrset(zone.origin(), RecordType::RRSIG).is_some() {
+            add_proofs(zone, &q.name, q.qtype, &mut answer);
+        }
+        answer
+    }
+
+    /// Origins of every zone, in canonical order.
+    pub fn origins(&self) -> Vec<DomainName> {
+        let mut origins: Vec<DomainName> = self.zones.read().unwrap().keys().cloned().collect();
+        origins.extend(self.backends.read().unwrap().keys().cloned());
+        origins.sort();
+        origins
+    }
+}
+
+/// Where the zone answering a request is kept.
+enum Source {
+    Memory(SharedZone),
+    Backend(DomainName, Arc<dyn Backend>),
+}
+
+impl Handler for Authority {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let query = &request.message;
+        let mut resp = query.response();
+        if query.header.opcode == Opcode::UPDATE {
+            return Some(self.update(request, resp));
+        }
+        if query.header.opcode != Opcode::QUERY {
+            resp.header.rcode = Rcode::NOTIMP;
+            return Some(resp);
+        }
+        let q = match query.questions.as_slice() {
+            [q] => q,
+            _ => {
+                resp.header.rcode = Rcode::FORMERR;
+                return Some(resp);
+            }
+        };
+        if request.is_transfer() {
+            return Some(self.transfer(request, resp));
+        }
+        let (origin, answer) = match self.source(&q.name) {
+            Some(Source::Memory(zone)) => {
+                let zone = zone.read().unwrap();
+                if q.qclass != zone.class() && q.qclass != RecordClass::ANY {
+                    resp.header.rcode = Rcode::REFUSED;
+                    return Some(resp);
+                }
+                if zone.soa().is_none() {
+                    // Not loaded yet, or expired on a secondary.
+                    resp.header.rcode = Rcode::SERVFAIL;
+                    return Some(resp);
+                }
+                let answer = zone.lookup(&q.name, q.qtype);
+                #[cfg(feature = "dnssec")]
+                let answer = self.signed(&zone, query, answer);
+                (zone.origin().clone(), answer)
+            }
+            Some(Source::Backend(origin, backend)) => {
+                if q.qclass != RecordClass::IN && q.qclass != RecordClass::ANY {
+                    resp.header.rcode = Rcode::REFUSED;
+                    return Some(resp);
+                }
+                let answer = match backend.serial(&origin) {
+                    Ok(Some(_)) => backend.lookup(&origin, &q.name, q.qtype),
+                    Ok(None) => Err(BackendError::NoZone(origin.clone())),
+                    Err(e) => Err(e),
+                };
+                match answer {
+                    Ok(answer) => (origin, answer),
+                    // Without an SOA, or out of reach.
+                    Err(_) => {
+                        resp.header.rcode = Rcode::SERVFAIL;
+                        return Some(resp);
+                    }
+                }
+            }
+            None => {
+                resp.header.rcode = Rcode::REFUSED;
+                return Some(resp);
+            }
+        };
+        resp.header.aa = answer.authoritative;
+        resp.header.rcode = answer.rcode;
+        resp.answers = answer.answers;
+        resp.authority = answer.authority;
+        resp.additional = answer.additional;
+        if let Some(agent) = self.report_channels.read().unwrap().get(&origin) {
+            resp.set_report_channel(agent);
+        }
+        Some(resp)
+    }
+}
+
+impl Authority {
+    /// Answers an AXFR or IXFR request with the whole response in one
+    /// message; the server frontend splits it for sending.
+    fn transfer(&self, request: &Request, mut resp: Message) -> Message {
+        let q = &request.message.questions[0];
+        let allowed = self
+            .transfer_acls
+            .read()
+            .unwrap()
+            .get(&q.name)
+            .is_some_and(|acl| acl.allows(request.src.ip(), request.key.as_ref()));
+        let zone = match (self.zone(&q.name), self.backend(&q.name)) {
+            (Some(zone), _) if allowed => zone,
+            (None, Some(backend)) if allowed => {
+                return self.transfer_backend(request, resp, &*backend)
+            }
+            _ => {
+                resp.header.rcode = Rcode::REFUSED;
+                return resp;
+            }
+        };
+        let zone = zone.read().unwrap();
+        let soa = match zone.soa() {
+            Some(soa) => soa.clone(),
+            None => {
+                resp.header.rcode = Rcode::SERVFAIL;
+                return resp;
+            }
+        };
+        resp.header.aa = true;
+        if q.qtype == RecordType::AXFR {
+            if request.protocol != Protocol::Tcp {
+                // AXFR is only defined over TCP (RFC 5936 §4.2).
+                resp.header.rcode = Rcode::FORMERR;
+                return resp;
+            }
+            resp.answers = zone.transfer_records();
+            return resp;
+        }
+        let client_serial = match client_serial(request) {
+            Some(serial) => serial,
+            None => {
+                resp.header.rcode = Rcode::FORMERR;
+                return resp;
+            }
+        };
+        let newer = zone
+            .serial()
+            .is_some_and(|s| serial_cmp(s, client_serial) == Some(Ordering::Greater));
+        resp.answers = if !newer || request.protocol != Protocol::Tcp {
+            // Up to date, or over UDP where a lone SOA tells the client to
+            // retry over TCP (RFC 1995 §2).
+            vec![soa]
+        } else {
+            zone.incremental_records(client_serial)
+                .unwrap_or_else(|| zone.transfer_records())
+        };
+        resp
+    }
+
+    /// Answers a transfer request for a zone served from a backend, which
+    /// keeps no journal: IXFR is answered with the whole zone.
+    fn transfer_backend(
+        &self,
+        request: &Request,
+        mut resp: Message,
+        backend: &dyn Backend,
+    ) -> Message {
+        let q = &request.message.questions[0];
+        let soa = match backend.rrset(&q.name, &q.name, RecordType::SOA) {
+            Ok(soa) if !soa.is_empty() => soa[0].clone(),
+            _ => {
+                resp.header.rcode = Rcode::SERVFAIL;
+                return resp;
+            }
+        };
+        resp.header.aa = true;
+        let tcp = request.protocol == Protocol::Tcp;
+        let whole = if q.qtype == RecordType::AXFR {
+            if !tcp {
+                resp.header.rcode = Rcode::FORMERR;
+                return resp;
+            }
+            true
+        } else {
+            let client_serial = match client_serial(request) {
+                Some(serial) => serial,
+                None => {
+                    resp.header.rcode = Rcode::FORMERR;
+                    return resp;
+                }
+            };
+            tcp && matches!(&soa.rdata, RData::Soa(data)
+                if serial_cmp(data.serial, client_serial) == Some(Ordering::Greater))
+        };
+        resp.answers = if whole {
+            match backend.transfer_records(&q.name) {
+                Ok(records) => records,
+                Err(_) => {
+                    resp.header.rcode = Rcode::SERVFAIL;
+                    return resp;
+                }
+            }
+        } else {
+            vec![soa]
+        };
+        resp
+    }
+}
+
+/// The serial an IXFR request says the client has.
+fn client_serial(request: &Request) -> Option<u32> {
+    let q = &request.message.questions[0];
+    request
+        .message
+        .authority
+        .iter()
+        .find_map(|rr| match &rr.rdata {
+            RData::Soa(soa) if rr.name == q.name => Some(soa.serial),
+            _ => None,
+        })
+}
+
+impl Authority {
+    /// Processes a dynamic update: the zone section names the zone, the
+    /// answer section holds prerequisites and the authority section the
+    /// changes (RFC 2136 §3).
+    fn update(&self, request: &Request, mut resp: Message) -> Message {
+        let msg = &request.message;
+        let zone_name = match msg.questions.as_slice() {
+            [q] if q.qtype == RecordType::SOA => &q.name,
+            _ => {
+                resp.header.rcode = Rcode::FORMERR;
+                return resp;
+            }
+        };
+        let zone = match self.zone(zone_name) {
+            Some(zone) => zone,
+            None if self.backend(zone_name).is_some() => {
+                resp.header.rcode = Rcode::NOTIMP;
+                return resp;
+            }
+            None => {
+                resp.header.rcode = Rcode::NOTAUTH;
+                return resp;
+            }
+        };
+        let allowed = request.key.as_ref().is_some_and(|key| {
+            self.update_policies
+                .read()
+                .unwrap()
+                .get(zone_name)
+                .is_some_and(|policy| policy.allows(key, &msg.authority))
+        });
+        if !allowed {
+            resp.header.rcode = Rcode::REFUSED;
+            return resp;
+        }
+        let mut zone = zone.write().unwrap();
+        if msg.questions[0].qclass != zone.class() {
+            resp.header.rcode = Rcode::NOTAUTH;
+            return resp;
+        }
+        if let Err(rcode) = zone.update(&msg.answers, &msg.authority) {
+            resp.header.rcode = rcode;
+        }
+        resp
+    }
+}
@@ -0,0 +1,465 @@
+//! The message cache of a forwarding route.
+//!
+//! The cache is split into shards by a hash of the key, each behind a
+//! lock of its own, so that queries for different names seldom wait on one
+//! another; a hit only takes its shard's lock for reading. Each shard
+//! keeps a clock hand over its entries: a hit marks its entry, and the
+//! hand spares a marked entry once, clearing the mark, so entries no one
+//! asks for again are the first to go (the CLOCK approximation of least
+//! recently used).
+//!
+//! Queries never evict. Once a cache holds more entries or bytes than it
+//! may, it wakes a background thread, started the first time it is
+//! needed, which drops what has expired and then moves the clock hands
+//! until the cache is back under its limits with some room to spare. While
+//! a cache is more than a quarter over a limit, new entries are turned
+//! away, so that a burst the evictor cannot keep up with does not run away
+//! with memory.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Once, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cache::Expiring;
+use crate::clock::Clock;
+use crate::message::{Message, Question};
+use crate::resolver::Trust;
+
+/// How long the evictor sleeps between sweeps for expired entries when
+/// nothing wakes it.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Entries the evictor drops from a shard before moving on to the next,
+/// so that no shard is locked for long.
+const EVICT_BATCH: usize = 64;
+
+/// What a cached response was asked for, including the bits that change
+/// what an upstream returns.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct CacheKey {
+    pub(super) question: Question,
+    pub(super) dnssec_ok: bool,
+    pub(super) checking_disabled: bool,
+}
+
+impl CacheKey {
+    pub(super) fn new(query: &Message) -> Option<CacheKey> {
+        let q = query.question()?;
+        Some(CacheKey {
+            question: q.clone(),
+            dnssec_ok: query.edns.as_ref().is_some_and(|e| e.dnssec_ok),
+            checking_disabled: query.header.cd,
+        })
+    }
+}
+
+/// What DNSSEC validation made of a cached response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Security {
+    /// Not validated, as by a route that does not validate.
+    #[default]
+    Unchecked,
+    Secure,
+    Insecure,
+    /// Failed validation, or could not be validated: served only to
+    /// clients that set the CD bit.
+    Bogus,
+}
+
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Security::Unchecked => "unchecked",
+            Security::Secure => "secure",
+            Security::Insecure => "insecure",
+            Security::Bogus => "bogus",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct CacheEntry {
+    pub(super) response: Expiring<Message>,
+    pub(super) security: Security,
+}
+
+impl CacheEntry {
+    /// About how much memory the entry takes: its response in wire format
+    /// and the bookkeeping around it.
+    fn size(&self) -> usize {
+        let wire = self.response.value().to_wire().map_or(512, |w| w.len());
+        wire + mem::size_of::<Slot>()
+    }
+}
+
+/// How full a route's cache is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheUsage {
+    /// Responses held, including expired ones not yet swept.
+    pub entries: usize,
+    /// About how much memory they take.
+    pub bytes: usize,
+    /// Responses dropped to make room, since the cache was made.
+    pub evictions: u64,
+}
+
+struct Slot {
+    key: CacheKey,
+    entry: CacheEntry,
+    size: usize,
+    referenced: AtomicBool,
+}
+
+#[derive(Default)]
+struct Shard {
+    index: HashMap<CacheKey, usize>,
+    slots: Vec<Slot>,
+    hand: usize,
+}
+
+impl Shard {
+    /// Removes the slot at `i`, moving the last one into its place.
+    fn remove_at(&mut self, i: usize) -> Slot {
+        let slot = self.slots.swap_remove(i);
+        self.index.remove(&slot.key);
+        if let Some(moved) = self.slots.get(i) {
+            if let Some(at) = self.index.get_mut(&moved.key) {
+                *at = i;
+            }
+        }
+        slot
+    }
+
+    /// Moves the hand to the first unmarked slot and removes it.
+    fn evict(&mut self) -> Option<Slot> {
+        // Every slot is unmarked after one turn of the hand.
+        for _ in 0..=self.slots.len() {
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
+            let slot = self.slots.get(self.hand)?;
+            if slot.referenced.swap(false, Ordering::Relaxed) {
+                self.hand += 1;
+            } else {
+                return Some(self.remove_at(self.hand));
+            }
+        }
+        None
+    }
+}
+
+struct Inner {
+    shards: Box<[RwLock<Shard>]>,
+    hasher: RandomState,
+    capacity: usize,
+    max_bytes: usize,
+    len: AtomicUsize,
+    bytes: AtomicUsize,
+    evictions: AtomicU64,
+    /// The shard the evictor starts from on its next sweep.
+    next_shard: AtomicUsize,
+    evictor: Once,
+    woken: Mutex<bool>,
+    wakeup: Condvar,
+    /// What expiry is judged by when sweeping.
+    clock: Arc<dyn Clock>,
+}
+
+/// A limit with the room a cache may go over it while the evictor
+/// catches up.
+fn headroom(limit: usize) -> usize {
+    limit.saturating_add((limit / 4).max(1))
+}
+
+/// What the evictor brings a limit down to, so that it is not woken again
+/// by the next insertion.
+fn low_water(limit: usize) -> usize {
+    limit - limit / 16
+}
+
+impl Inner {
+    fn shard(&self, key: &CacheKey) -> &RwLock<Shard> {
+        let i = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[i]
+    }
+
+    fn over(&self, len: usize, bytes: usize) -> bool {
+        len > self.capacity || (self.max_bytes > 0 && bytes > self.max_bytes)
+    }
+
+    fn added(&self, size: usize) {
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn removed(&self, slot: &Slot) {
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        self.bytes.fetch_sub(slot.size, Ordering::Relaxed);
+    }
+
+    fn retain<F: FnMut(&CacheKey, &CacheEntry) -> bool>(&self, mut keep: F) {
+        for shard in self.shards.iter() {
+            let mut shard = shard.write().unwrap();
+            for i in (0..shard.slots.len()).rev() {
+                let slot = &shard.slots[i];
+                if !keep(&slot.key, &slot.entry) {
+                    let slot = shard.remove_at(i);
+                    self.removed(&slot);
+                }
+            }
+        }
+    }
+
+    /// Drops expired entries, then evicts until the cache is under its
+    /// low-water marks.
+    fn sweep(&self, now: Instant) {
+        self.retain(|_, entry| !entry.response.is_expired(now));
+        let len_target = low_water(self.capacity);
+        let bytes_target = low_water(self.max_bytes);
+        let under = || {
+            self.len.load(Ordering::Relaxed) <= len_target
+                && (self.max_bytes == 0 || self.bytes.load(Ordering::Relaxed) <= bytes_target)
+        };
+        // Each shard gives up its share of the excess in turn, starting
+        // where the last sweep stopped, so that neither the first shards
+        // nor the hand of a small shard bear every eviction and take the
+        // entries the hand has just spared.
+        let shards = self.shards.len();
+        let mut next = self.next_shard.load(Ordering::Relaxed);
+        while !under() {
+            let excess = self.len.load(Ordering::Relaxed).saturating_sub(len_target);
+            let share = (excess / shards).clamp(1, EVICT_BATCH);
+            let mut evicted = 0;
+            for _ in 0..shards {
+                if under() {
+                    break;
+                }
+                let mut shard = self.shards[next].write().unwrap();
+                next = (next + 1) % shards;
+                for _ in 0..share {
+                    if under() {
+                        break;
+                    }
+                    match shard.evict() {
+                        Some(slot) => {
+                            self.removed(&slot);
+                            evicted += 1;
+                        }
+                        None => break,
+                    }
+                }
+            }
+            self.evictions.fetch_add(evicted, Ordering::Relaxed);
+            if evicted == 0 {
+                break;
+            }
+        }
+        self.next_shard.store(next, Ordering::Relaxed);
+    }
+
+    fn wake(self: &Arc<Self>) {
+        self.evictor.call_once(|| {
+            let weak = Arc::downgrade(self);
+            let _ = thread::Builder::new()
+                .name("cache-evictor".into())
+                .spawn(move || evictor(weak));
+        });
+        *self.woken.lock().unwrap() = true;
+        self.wakeup.notify_one();
+    }
+}
+
+/// Sweeps the cache whenever it is woken, and every [`SWEEP_INTERVAL`]
+/// otherwise, until the cache is dropped.
+fn evictor(cache: Weak<Inner>) {
+    while let Some(inner) = cache.upgrade() {
+        let woken = inner.woken.lock().unwrap();
+        let (mut woken, _) = inner
+            .wakeup
+            .wait_timeout_while(woken, SWEEP_INTERVAL, |woken| !*woken)
+            .unwrap();
+        *woken = false;
+        drop(woken);
+        let now = inner.clock.now();
+        inner.sweep(now);
+    }
+}
+
+/// A route's cache of upstream responses, split into shards.
+pub(super) struct MessageCache {
+    inner: Arc<Inner>,
+}
+
+impl MessageCache {
+    /// A cache of at most `capacity` responses and, unless it is zero,
+    /// about `max_bytes` of memory, in `shards` shards, swept of expired
+    /// responses by `clock`.
+    pub(super) fn new(
+        capacity: usize,
+        max_bytes: usize,
+        shards: usize,
+        clock: Arc<dyn Clock>,
+    ) -> MessageCache {
+        let shards = (0..shards.max(1))
+            .map(|_| RwLock::new(Shard::default()))
+            .collect();
+        MessageCache {
+            inner: Arc::new(Inner {
+                shards,
+                hasher: RandomState::new(),
+                capacity,
+                max_bytes,
+                len: AtomicUsize::new(0),
+                bytes: AtomicUsize::new(0),
+                evictions: AtomicU64::new(0),
+                next_shard: AtomicUsize::new(0),
+                evictor: Once::new(),
+                woken: Mutex::new(false),
+                wakeup: Condvar::new(),
+                clock,
+            }),
+        }
+    }
+
+    /// The response cached for `key`, aged to `now`, with what validation
+    /// made of it, unless it has expired.
+    pub(super) fn get(&self, key: &CacheKey, now: Instant) -> Option<(Message, Security)> {
+        let shard = self.inner.shard(key).read().unwrap();
+        let slot = &shard.slots[*shard.index.get(key)?];
+        if slot.entry.response.is_expired(now) {
+            return None;
+        }
+        slot.referenced.store(true, Ordering::Relaxed);
+        Some((slot.entry.response.aged(now), slot.entry.security))
+    }
+
+    /// Calls `f` with the response cached for `key`, how many seconds it
+    /// has been held at `now`, and what validation made of it, unless it
+    /// has expired; unlike [`get`](Self::get), nothing is copied.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    pub(super) fn visit<R>(
+        &self,
+        key: &CacheKey,
+        now: Instant,
+        f: impl FnOnce(&Message, u32, Security) -> R,
+    ) -> Option<R> {
+        let shard = self.inner.shard(key).read().unwrap();
+        let slot = &shard.slots[*shard.index.get(key)?];
+        let response = &slot.entry.response;
+        if response.is_expired(now) {
+            return None;
+        }
+        slot.referenced.store(true, Ordering::Relaxed);
+        let held = response.age(now).as_secs().min(u64::from(u32::MAX)) as u32;
+        Some(f(response.value(), held, slot.entry.security))
+    }
+
+    /// Whether the cache holds as much as it may.
+    pub(super) fn is_full(&self) -> bool {
+        let inner = &self.inner;
+        let len = inner.len.load(Ordering::Relaxed);
+        let bytes = inner.bytes.load(Ordering::Relaxed);
+        len >= inner.capacity || (inner.max_bytes > 0 && bytes >= inner.max_bytes)
+    }
+
+    /// Caches `entry` under `key`, replacing what was there unless that
+    /// has yet to expire and is more trustworthy (RFC 2181 §5.4.1). Returns
+    /// whether it was cached; it is not while the cache is well over its
+    /// limits.
+    pub(super) fn insert(&self, key: CacheKey, entry: CacheEntry) -> bool {
+        let inner = &self.inner;
+        if inner.capacity == 0 {
+            return false;
+        }
+        let size = entry.size();
+        let len = inner.len.load(Ordering::Relaxed);
+        let bytes = inner.bytes.load(Ordering::Relaxed);
+        if len >= headroom(inner.capacity)
+            || (inner.max_bytes > 0 && bytes.saturating_add(size) > headroom(inner.max_bytes))
+        {
+            inner.wake();
+            return false;
+        }
+        {
+            let mut shard = inner.shard(&key).write().unwrap();
+            match shard.index.get(&key) {
+                Some(&i) => {
+                    let slot = &mut shard.slots[i];
+                    let current = &slot.entry.response;
+                    if !current.is_expired(entry.response.stored())
+                        && Trust::of(current.value()) > Trust::of(entry.response.value())
+                    {
+                        return false;
+                    }
+                    inner.bytes.fetch_add(size, Ordering::Relaxed);
+                    inner.bytes.fetch_sub(slot.size, Ordering::Relaxed);
+                    slot.entry = entry;
+                    slot.size = size;
+                }
+                None => {
+                    let i = shard.slots.len();
+                    shard.index.insert(key.clone(), i);
+                    shard.slots.push(Slot {
+                        key,
+                        entry,
+                        size,
+                        referenced: AtomicBool::new(false),
+                    });
+                    inner.added(size);
+                }
+            }
+        }
+        if inner.over(
+            inner.len.load(Ordering::Relaxed),
+            inner.bytes.load(Ordering::Relaxed),
+        ) {
+            inner.wake();
+        }
+        true
+    }
+
+    /// Keeps only the entries `keep` returns true for.
+    pub(super) fn retain<F: FnMut(&CacheKey, &CacheEntry) -> bool>(&self, keep: F) {
+        self.inner.retain(keep);
+    }
+
+    /// Calls `f` with every entry, one shard at a time.
+    pub(super) fn for_each<F: FnMut(&CacheKey, &CacheEntry)>(&self, mut f: F) {
+        for shard in self.inner.shards.iter() {
+            let shard = shard.read().unwrap();
+            for slot in &shard.slots {
+                f(&slot.key, &slot.entry);
+            }
+        }
+    }
+
+    pub(super) fn clear(&self) {
+        self.retain(|_, _| false);
+    }
+
+    pub(super) fn usage(&self) -> CacheUsage {
+        let inner = &self.inner;
+        CacheUsage {
+            entries: inner.len.load(Ordering::Relaxed),
+            bytes: inner.bytes.load(Ordering::Relaxed),
+            evictions: inner.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Debug for MessageCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageCache")
+            .field("shards", &self.inner.shards.len())
+            .field("capacity", &self.inner.capacity)
+            .field("max_bytes", &self.inner.max_bytes)
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
@@ -0,0 +1,334 @@
+//! Classifying requests for custom analytics, and keeping the most recent
+//! ones.
+//!
+//! The [`Classify`] layer describes every request it samples as a
+//! [`QueryClass`]: what was asked, how it was answered and at what cost,
+//! and hands it to each of its [`Sampler`]s. Handlers further in tell it
+//! what it cannot see in the response: a [`Forwarder`](super::Forwarder)
+//! notes whether its cache held the answer, how many upstream queries it
+//! sent and what DNSSEC validation made of the answer.
+//!
+//! [`RecentQueries`] is a sampler keeping the last requests in a ring
+//! buffer, as the `recent` command of the control channel shows them.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::client::Protocol;
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::RecordType;
+
+use super::{Handler, Layer, Request, Security};
+
+/// Where an answer came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topology {
+    /// From a zone the server is authoritative for.
+    Authoritative,
+    /// From a forwarding cache.
+    CacheHit,
+    /// Not in a forwarding cache, so from an upstream if from anywhere.
+    CacheMiss,
+    /// Made up without a zone or a cache, as a refusal or a blocked name.
+    Local,
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Topology::Authoritative => "authoritative",
+            Topology::CacheHit => "cache-hit",
+            Topology::CacheMiss => "cache-miss",
+            Topology::Local => "local",
+        })
+    }
+}
+
+/// What a request was and how it was answered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryClass {
+    /// When the request arrived.
+    pub time: SystemTime,
+    pub client: SocketAddr,
+    pub protocol: Protocol,
+    /// The question, if there was one.
+    pub qname: Option<DomainName>,
+    pub qtype: Option<RecordType>,
+    /// `None` if the handler sent nothing.
+    pub rcode: Option<Rcode>,
+    pub topology: Topology,
+    /// Upstream queries sent to answer it.
+    pub recursion_depth: u32,
+    /// The size of the response in wire format before any truncation;
+    /// zero if there was none.
+    pub response_size: usize,
+    pub security: Security,
+    /// How long the handler took to answer.
+    pub duration: Duration,
+}
+
+impl fmt::Display for QueryClass {
+    /// One line: time, client, protocol, question, outcome and cost.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "{}.{:03} {} {} ",
+            since.as_secs(),
+            since.subsec_millis(),
+            self.client,
+            match self.protocol {
+                Protocol::Udp => "udp",
+                Protocol::Tcp => "tcp",
+                Protocol::Tls => "tls",
+            }
+        )?;
+        match (&self.qname, self.qtype) {
+            (Some(name), Some(qtype)) => write!(f, "{} {}", name, qtype)?,
+            _ => f.write_str("- -")?,
+        }
+        match self.rcode {
+            Some(rcode) => write!(f, " {}", rcode)?,
+            None => f.write_str(" dropped")?,
+        }
+        write!(
+            f,
+            " {} upstream={} size={} {} {}ms",
+            self.topology,
+            self.recursion_depth,
+            self.response_size,
+            self.security,
+            self.duration.as_millis()
+        )
+    }
+}
+
+/// Takes the classification of sampled requests, as to feed an
+/// analytics pipeline. Samplers are called on the thread answering the
+/// request, so they should be quick.
+pub trait Sampler: Send + Sync + 'static {
+    fn sample(&self, class: &QueryClass);
+}
+
+impl<F> Sampler for F
+where
+    F: Fn(&QueryClass) + Send + Sync + 'static,
+{
+    fn sample(&self, class: &QueryClass) {
+        self(class)
+    }
+}
+
+/// The classifications of the last requests sampled, the oldest dropped
+/// once it holds as many as it may.
+#[derive(Debug)]
+pub struct RecentQueries {
+    capacity: usize,
+    ring: Mutex<VecDeque<QueryClass>>,
+}
+
+impl RecentQueries {
+    pub fn new(capacity: usize) -> RecentQueries {
+        RecentQueries {
+            capacity,
+            ring: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The last `count` requests, oldest first.
+    pub fn last(&self, count: usize) -> Vec<QueryClass> {
+        let ring = self.ring.lock().unwrap();
+        let skip = ring.len().saturating_sub(count);
+        ring.iter().skip(skip).cloned().collect()
+    }
+
+    /// Every request held, oldest first.
+    pub fn all(&self) -> Vec<QueryClass> {
+        self.last(self.capacity)
+    }
+
+    pub fn clear(&self) {
+        self.ring.lock().unwrap().clear();
+    }
+}
+
+impl Sampler for RecentQueries {
+    fn sample(&self, class: &QueryClass) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut ring = self.ring.lock().unwrap();
+        if ring.len() == self.capacity {
+            ring.pop_front();
+        }
+        ring.push_back(class.clone());
+    }
+}
+
+/// What handlers further in noted about the request being classified.
+#[derive(Clone, Copy, Debug, Default)]
+struct Trail {
+    /// Whether the last cache lookup found the answer.
+    cache_hit: Option<bool>,
+    upstream_queries: u32,
+    security: Option<Security>,
+}
+
+thread_local! {
+    static TRAIL: RefCell<Option<Trail>> = const { RefCell::new(None) };
+}
+
+/// Notes a lookup in a forwarding cache, if the request is classified.
+pub(super) fn note_cache_lookup(hit: bool) {
+    note(|t| t.cache_hit = Some(hit));
+}
+
+/// Notes an upstream query sent, if the request is classified.
+pub(super) fn note_upstream_query() {
+    note(|t| t.upstream_queries += 1);
+}
+
+/// Notes what validation made of the answer, if the request is
+/// classified.
+pub(super) fn note_security(security: Security) {
+    note(|t| t.security = Some(security));
+}
+
+fn note(f: impl FnOnce(&mut Trail)) {
+    TRAIL.with(|trail| {
+        if let Some(trail) = trail.borrow_mut().as_mut() {
+            f(trail);
+        }
+    });
+}
+
+/// Settings of the [`Classified`] handler.
+#[derive(Clone)]
+pub struct Classify {
+    pub samplers: Vec<Arc<dyn Sampler>>,
+    /// Share of requests classified, from 0 to 1.
+    pub sample_rate: f64,
+}
+
+impl Default for Classify {
+    fn default() -> Classify {
+        Classify::new()
+    }
+}
+
+impl fmt::Debug for Classify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Classify")
+            .field("samplers", &self.samplers.len())
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
+
+impl Classify {
+    /// Classifies every request, for no sampler yet.
+    pub fn new() -> Classify {
+        Classify {
+            samplers: Vec::new(),
+            sample_rate: 1.0,
+        }
+    }
+
+    pub fn sampler(mut self, sampler: Arc<dyn Sampler>) -> Self {
+        self.samplers.push(sampler);
+        self
+    }
+
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = rate;
+        self
+    }
+
+    fn sampled(&self) -> bool {
+        if self.samplers.is_empty() {
+            return false;
+        }
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        let draw = (random::u64() >> 11) as f64 / (1u64 << 53) as f64;
+        draw < self.sample_rate
+    }
+}
+
+impl<H: Handler> Layer<H> for Classify {
+    type Handler = Classified<H>;
+
+    fn layer(&self, inner: H) -> Classified<H> {
+        Classified {
+            config: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler whose requests are classified; see [`Classify`].
+pub struct Classified<H> {
+    config: Classify,
+    inner: H,
+}
+
+impl<H> Classified<H> {
+    pub fn config(&self) -> &Classify {
+        &self.config
+    }
+}
+
+impl<H: Handler> Handler for Classified<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        if !self.config.sampled() {
+            return self.inner.handle(request);
+        }
+        let time = SystemTime::now();
+        let started = Instant::now();
+        // Layers may be nested; the outer one gets its trail back after.
+        let outer = TRAIL.with(|t| t.replace(Some(Trail::default())));
+        let resp = self.inner.handle(request);
+        let duration = started.elapsed();
+        let trail = TRAIL.with(|t| t.replace(outer)).unwrap_or_default();
+
+        let topology = match trail.cache_hit {
+            Some(true) if trail.upstream_queries == 0 => Topology::CacheHit,
+            Some(_) => Topology::CacheMiss,
+            None if trail.upstream_queries > 0 => Topology::CacheMiss,
+            None if resp.as_ref().is_some_and(|r| r.header.aa) => Topology::Authoritative,
+            None => Topology::Local,
+        };
+        let question = request.message.question();
+        let class = QueryClass {
+            time,
+            client: request.src,
+            protocol: request.protocol,
+            qname: question.map(|q| q.name.clone()),
+            qtype: question.map(|q| q.qtype),
+            rcode: resp.as_ref().map(|r| r.header.rcode),
+            topology,
+            recursion_depth: trail.upstream_queries,
+            response_size: resp
+                .as_ref()
+                .and_then(|r| r.to_wire().ok())
+                .map_or(0, |w| w.len()),
+            security: trail.security.unwrap_or_default(),
+            duration,
+        };
+        for sampler in &self.config.samplers {
+            sampler.sample(&class);
+        }
+        resp
+    }
+}
@@ -0,0 +1,372 @@
+//! The server side of DSO sessions (RFC 8490) and DNS Push Notifications
+//! (RFC 8765) on TCP and TLS connections.
+//!
+//! Subscriptions are served by asking the handler for the subscribed
+//! records at an interval and pushing what changed since, so that push
+//! works over any handler: zones, a forwarder or an mDNS relay alike.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::dso::{DsoMessage, DsoType, Keepalive};
+use crate::message::{Header, Message, Rcode};
+use crate::push::{self, Change, Subscription};
+use crate::rr::{Record, RecordType};
+
+use super::{Request, TcpFrontend, Transport};
+
+/// The most data one PUSH TLV carries, leaving room for the header and
+/// TLV framing within a 64 KiB message.
+const MAX_PUSH_LEN: usize = 65_000;
+
+/// How DNS Push Notifications are served.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PushPolicy {
+    /// How often the handler is asked again for subscribed records.
+    pub poll_interval: Duration,
+    /// Subscriptions one session may hold; more are refused.
+    pub max_subscriptions: usize,
+}
+
+impl Default for PushPolicy {
+    fn default() -> PushPolicy {
+        PushPolicy {
+            poll_interval: Duration::from_secs(1),
+            max_subscriptions: 64,
+        }
+    }
+}
+
+/// The server side of an established DSO session.
+pub(super) struct Session {
+    timeouts: Keepalive,
+    /// When the client last sent anything.
+    last_message: Instant,
+    /// When the client last sent something other than a keepalive.
+    last_activity: Instant,
+    subscriptions: Vec<Subscribed>,
+    next_poll: Instant,
+}
+
+/// A subscription with the records last pushed for it.
+struct Subscribed {
+    /// The ID of the SUBSCRIBE request, by which it is unsubscribed.
+    id: u16,
+    subscription: Subscription,
+    records: Vec<Record>,
+}
+
+impl Session {
+    fn new(timeouts: Keepalive, now: Instant) -> Session {
+        Session {
+            timeouts,
+            last_message: now,
+            last_activity: now,
+            subscriptions: Vec::new(),
+            next_poll: now,
+        }
+    }
+
+    /// Notes a message other than a DSO one.
+    pub(super) fn touch(&mut self, now: Instant) {
+        self.last_message = now;
+        self.last_activity = now;
+    }
+
+    /// When a client that has sent nothing more is delinquent: after
+    /// twice the keepalive interval, or after twice the inactivity
+    /// timeout and at least five seconds (RFC 8490 §6.2, §6.4). Active
+    /// subscriptions keep a session from being inactive.
+    fn deadline(&self) -> Option<Instant> {
+        let keepalive = self
+            .timeouts
+            .keepalive_interval
+            .map(|i| self.last_message + i * 2);
+        let inactivity = self
+            .timeouts
+            .inactivity_timeout
+            .filter(|_| self.subscriptions.is_empty())
+            .map(|t| self.last_activity + (t * 2).max(Duration::from_secs(5)));
+        match (keepalive, inactivity) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub(super) fn is_delinquent(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|d| now >= d)
+    }
+
+    pub(super) fn poll_due(&self, now: Instant) -> bool {
+        !self.subscriptions.is_empty() && now >= self.next_poll
+    }
+
+    /// How long to wait for the client before the session needs
+    /// attention, or `None` to wait for ever.
+    pub(super) fn wait(&self, now: Instant) -> Option<Duration> {
+        let poll = (!self.subscriptions.is_empty()).then_some(self.next_poll);
+        let wake = match (self.deadline(), poll) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        // A zero read timeout is refused.
+        wake.map(|t| {
+            t.saturating_duration_since(now)
+                .max(Duration::from_millis(1))
+        })
+    }
+}
+
+/// What to send for a DSO message and whether to close the connection
+/// after.
+pub(super) struct DsoReply {
+    pub(super) messages: Vec<Vec<u8>>,
+    pub(super) close: bool,
+}
+
+impl DsoReply {
+    fn send(msg: &DsoMessage) -> DsoReply {
+        DsoReply {
+            messages: msg.to_wire().into_iter().collect(),
+            close: false,
+        }
+    }
+
+    fn nothing() -> DsoReply {
+        DsoReply {
+            messages: Vec::new(),
+            close: false,
+        }
+    }
+
+    fn close(mut self) -> DsoReply {
+        self.close = true;
+        self
+    }
+}
+
+impl TcpFrontend {
+    /// Answers a DSO message, establishing or keeping `session`.
+    pub(super) fn serve_dso(
+        &self,
+        buf: &[u8],
+        session: &mut Option<Session>,
+        src: SocketAddr,
+        transport: Transport,
+        now: Instant,
+    ) -> DsoReply {
+        let msg = match DsoMessage::from_wire(buf) {
+            Ok(msg) => msg,
+            Err(_) => {
+                let id = u16::from_be_bytes([buf[0], buf[1]]);
+                let mut header = Header::from_flags(id, u16::from_be_bytes([buf[2], buf[3]]));
+                header.qr = true;
+                header.rcode = Rcode::FORMERR;
+                let resp = DsoMessage {
+                    header,
+                    tlvs: Vec::new(),
+                };
+                return DsoReply::send(&resp).close();
+            }
+        };
+        // The server sends no requests, so there are no responses to
+        // take.
+        if msg.header.qr {
+            return DsoReply::nothing();
+        }
+        if msg.is_unidirectional() {
+            return self.serve_unidirectional(&msg, session, now);
+        }
+        let timeouts = match self.dso {
+            Some(timeouts) => timeouts,
+            None => return DsoReply::send(&msg.response(Rcode::NOTIMP)),
+        };
+        if let Some(session) = session.as_mut() {
+            session.last_message = now;
+        }
+        match msg.primary().map(|t| t.dtype) {
+            Some(DsoType::KEEPALIVE) if msg.keepalive_values().is_some() => {
+                session.get_or_insert_with(|| Session::new(timeouts, now));
+                let mut resp = msg.response(Rcode::NOERROR);
+                resp.tlvs.push(timeouts.to_tlv());
+                DsoReply::send(&resp)
+            }
+            Some(DsoType::SUBSCRIBE) if self.push.is_some() => {
+                let session = session.get_or_insert_with(|| Session::new(timeouts, now));
+                self.subscribe(&msg, session, src, transport, now)
+            }
+            // Only servers send these, and a malformed Keepalive is no
+            // better (RFC 8490 §7.1).
+            Some(DsoType::KEEPALIVE)
+            | Some(DsoType::RETRY_DELAY)
+            | Some(DsoType::ENCRYPTION_PADDING)
+            | None => DsoReply::send(&msg.response(Rcode::FORMERR)).close(),
+            Some(_) => DsoReply::send(&msg.response(Rcode::DSOTYPENI)),
+        }
+    }
+
+    /// Handles a unidirectional message from the client. Those other than
+    /// UNSUBSCRIBE and RECONFIRM within a session end it (RFC 8490 §5.4.5).
+    fn serve_unidirectional(
+        &self,
+        msg: &DsoMessage,
+        session: &mut Option<Session>,
+        now: Instant,
+    ) -> DsoReply {
+        let session = match session {
+            Some(session) if self.push.is_some() => session,
+            _ => return DsoReply::nothing().close(),
+        };
+        session.last_message = now;
+        let primary = match msg.primary() {
+            Some(primary) => primary,
+            None => return DsoReply::nothing().close(),
+        };
+        match primary.dtype {
+            DsoType::UNSUBSCRIBE => match push::parse_unsubscribe(primary) {
+                Ok(id) => {
+                    session.subscriptions.retain(|s| s.id != id);
+                    session.last_activity = now;
+                    DsoReply::nothing()
+                }
+                Err(_) => DsoReply::nothing().close(),
+            },
+            DsoType::RECONFIRM => match push::parse_reconfirm(primary) {
+                // The next poll finds out whether the record is gone.
+                Ok(_) => {
+                    session.next_poll = now;
+                    DsoReply::nothing()
+                }
+                Err(_) => DsoReply::nothing().close(),
+            },
+            _ => DsoReply::nothing().close(),
+        }
+    }
+
+    fn subscribe(
+        &self,
+        msg: &DsoMessage,
+        session: &mut Session,
+        src: SocketAddr,
+        transport: Transport,
+        now: Instant,
+    ) -> DsoReply {
+        let policy = self.push.unwrap_or_default();
+        let subscription = match msg.primary().map(Subscription::from_tlv) {
+            Some(Ok(s)) => s,
+            _ => return DsoReply::send(&msg.response(Rcode::FORMERR)).close(),
+        };
+        let duplicate = session
+            .subscriptions
+            .iter()
+            .any(|s| s.subscription == subscription || s.id == msg.header.id);
+        if duplicate || (subscription.rtype.is_meta() && subscription.rtype != RecordType::ANY) {
+            return DsoReply::send(&msg.response(Rcode::FORMERR));
+        }
+        if session.subscriptions.len() >= policy.max_subscriptions {
+            return DsoReply::send(&msg.response(Rcode::REFUSED));
+        }
+        let records = match self.lookup(&subscription, src, transport) {
+            Ok(records) => records,
+            Err(rcode) => return DsoReply::send(&msg.response(rcode)),
+        };
+        session.last_activity = now;
+        let mut reply = DsoReply::send(&msg.response(Rcode::NOERROR));
+        let changes: Vec<Change> = records.iter().cloned().map(Change::Add).collect();
+        reply.messages.extend(pushes(&changes));
+        session.subscriptions.push(Subscribed {
+            id: msg.header.id,
+            subscription,
+            records,
+        });
+        if session.subscriptions.len() == 1 {
+            session.next_poll = now + policy.poll_interval;
+        }
+        reply
+    }
+
+    /// Asks the handler again for every subscribed record, returning the
+    /// PUSH messages for what changed.
+    pub(super) fn poll(
+        &self,
+        session: &mut Session,
+        src: SocketAddr,
+        transport: Transport,
+        now: Instant,
+    ) -> Vec<Vec<u8>> {
+        let policy = self.push.unwrap_or_default();
+        session.next_poll = now + policy.poll_interval;
+        let mut changes = Vec::new();
+        for sub in &mut session.subscriptions {
+            // A failed lookup says nothing about the records.
+            let current = match self.lookup(&sub.subscription, src, transport) {
+                Ok(current) => current,
+                Err(_) => continue,
+            };
+            for old in &sub.records {
+                if !current.iter().any(|r| same_record(r, old)) {
+                    changes.push(Change::Remove(old.clone()));
+                }
+            }
+            for new in &current {
+                if !sub.records.iter().any(|r| same_record(r, new)) {
+                    changes.push(Change::Add(new.clone()));
+                }
+            }
+            sub.records = current;
+        }
+        pushes(&changes)
+    }
+
+    /// The records of `subscription` as the handler answers them now, or
+    /// the RCODE of a failure.
+    fn lookup(
+        &self,
+        subscription: &Subscription,
+        src: SocketAddr,
+        transport: Transport,
+    ) -> Result<Vec<Record>, Rcode> {
+        let mut message = Message::query(subscription.name.clone(), subscription.rtype);
+        message.header.rd = false;
+        message.questions[0].qclass = subscription.class;
+        let request = Request {
+            message,
+            src,
+            protocol: transport.protocol(),
+            key: None,
+        };
+        let resp = self
+            .frontend
+            .state
+            .handler()
+            .handle(&request)
+            .ok_or(Rcode::SERVFAIL)?;
+        match resp.header.rcode {
+            Rcode::NOERROR | Rcode::NXDOMAIN => Ok(resp
+                .answers
+                .into_iter()
+                .filter(|r| subscription.matches(r))
+                .collect()),
+            rcode => Err(rcode),
+        }
+    }
+}
+
+/// Whether two records are the same but for their TTLs.
+fn same_record(a: &Record, b: &Record) -> bool {
+    a.name == b.name && a.class == b.class && a.rdata == b.rdata
+}
+
+/// Unidirectional PUSH messages carrying `changes`.
+fn pushes(changes: &[Change]) -> Vec<Vec<u8>> {
+    if changes.is_empty() {
+        return Vec::new();
+    }
+    let tlvs = match push::push_tlvs(changes, MAX_PUSH_LEN) {
+        Ok(tlvs) => tlvs,
+        Err(_) => return Vec::new(),
+    };
+    tlvs.into_iter()
+        .filter_map(|tlv| DsoMessage::unidirectional(tlv).to_wire().ok())
+        .collect()
+}
@@ -0,0 +1,1101 @@
+//! A handler forwarding queries to upstream resolvers chosen by domain.
+//!
+//! A route with the `dnssec` feature may validate what its upstreams
+//! return. It then always asks with the DO and CD bits set, and caches the
+//! answers with their signatures and what validation made of them, so that
+//! each is validated once: clients that set CD get them as they came,
+//! bogus or not, and other clients get SERVFAIL for bogus answers and the
+//! signatures only if they set DO.
+//!
+//! For split DNS, a query goes first to the route with the longest suffix
+//! covering its name, and within the route to its upstreams, then to each
+//! of its fallbacks in turn while the answer calls for one: an internal
+//! resolver that answers NXDOMAIN can be followed by public ones. What
+//! the route finally answers may in turn send the query on to the next
+//! most specific route. No set of upstreams is asked twice for one query,
+//! and a query that comes back from an upstream while it is being
+//! forwarded, as when two forwarders point at each other, gets SERVFAIL
+//! instead of going round again.
+//!
+//! The Client Subnet option of a query (RFC 7871) is sent on to each
+//! upstream as its resolver's [`Privacy`](crate::resolver::Privacy) policy
+//! says, which by default is not at all. Answers meant for one subnet
+//! only are not cached.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::iter;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+use crate::arena::{MessageRef, ResponseBuilder};
+use crate::cache::{Expiring, NamePattern};
+use crate::client::Protocol;
+use crate::clock::{self, Clock};
+#[cfg(feature = "dnssec")]
+use crate::dnssec::{Status, TrustAnchors, Validator};
+use crate::message::{ExtendedError, Message, Opcode, OptionCode, Question, Rcode};
+use crate::metrics::Metrics;
+use crate::name::DomainName;
+use crate::policy::Rewrite;
+#[cfg(feature = "dnssec")]
+use crate::resolver::ReportPolicy;
+use crate::resolver::{scrub, Resolver};
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+use crate::rr::Record;
+use crate::rr::{RData, RecordClass, RecordType};
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+use crate::wire::Encoder;
+
+use super::cache::{CacheEntry, CacheKey, CacheUsage, MessageCache, Security};
+use super::{classify, snapshot, Handler, Plugins, Request};
+
+/// Queries a cache warm-up has in flight at once.
+const WARM_CONCURRENCY: usize = 16;
+
+/// The longest a response that failed validation is cached, so that a
+/// zone whose signatures are fixed is soon trusted again.
+const BOGUS_TTL: u32 = 60;
+
+/// The most sets of upstreams one query is sent to, across fallbacks and
+/// routes.
+const MAX_UPSTREAMS: usize = 8;
+
+/// How a route caches the responses it forwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// Maximum number of cached responses; zero disables the cache.
+    pub capacity: usize,
+    /// About how much memory the cached responses may take; zero for no
+    /// limit beyond `capacity`.
+    pub max_bytes: usize,
+    /// How many independently locked parts the cache is split into.
+    pub shards: usize,
+    /// Bounds applied to the TTL of positive responses.
+    pub min_ttl: u32,
+    pub max_ttl: u32,
+    /// Upper bound on how long NXDOMAIN and no-data responses are kept;
+    /// otherwise they are kept as long as their SOA says (RFC 2308 §5).
+    pub max_negative_ttl: u32,
+}
+
+impl Default for CachePolicy {
+    fn default() -> CachePolicy {
+        CachePolicy {
+            capacity: 10_000,
+            max_bytes: 0,
+            shards: 16,
+            min_ttl: 0,
+            max_ttl: 86400,
+            max_negative_ttl: 3600,
+        }
+    }
+}
+
+impl CachePolicy {
+    /// A policy that caches nothing.
+    pub fn disabled() -> CachePolicy {
+        CachePolicy {
+            capacity: 0,
+            ..CachePolicy::default()
+        }
+    }
+}
+
+/// A response held in a route's cache.
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    pub question: Question,
+    pub dnssec_ok: bool,
+    pub checking_disabled: bool,
+    pub response: Message,
+    pub security: Security,
+    /// Time left before the response expires.
+    pub expires_in: Duration,
+}
+
+/// When a query moves on from a route to the next matching one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fallthrough {
+    /// The route's answer is final, whatever it is.
+    #[default]
+    Never,
+    /// Move on if the upstreams cannot be reached or answer SERVFAIL,
+    /// REFUSED or NOTIMP.
+    OnFailure,
+    /// Also move on if the upstreams answer NXDOMAIN, as when an internal
+    /// resolver only knows part of a domain.
+    OnNxdomain,
+}
+
+impl Fallthrough {
+    /// Whether a query whose answer had `rcode`, or that got none, moves
+    /// on.
+    fn applies(self, rcode: Option<Rcode>) -> bool {
+        let failed = matches!(
+            rcode,
+            None | Some(Rcode::SERVFAIL) | Some(Rcode::REFUSED) | Some(Rcode::NOTIMP)
+        );
+        match self {
+            Fallthrough::Never => false,
+            Fallthrough::OnFailure => failed,
+            Fallthrough::OnNxdomain => failed || rcode == Some(Rcode::NXDOMAIN),
+        }
+    }
+}
+
+/// Which NXDOMAIN and no-data answers a route caches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NegativeCaching {
+    /// All of them, for as long as their SOA and the cache policy allow.
+    #[default]
+    Always,
+    /// Only those that are final: not those that send the query on to the
+    /// next route, so that a name an internal resolver starts to know, as
+    /// when a VPN comes up, is found at once.
+    FinalOnly,
+    Never,
+}
+
+/// The upstreams a query has been sent to.
+#[derive(Debug, Default)]
+struct Tried {
+    servers: Vec<SocketAddr>,
+    asked: usize,
+}
+
+impl Tried {
+    /// Whether `upstream` may be asked, noting that it is if so.
+    fn admit(&mut self, upstream: &Resolver) -> bool {
+        let servers = &upstream.config().servers;
+        if self.asked >= MAX_UPSTREAMS
+            || (!servers.is_empty() && servers.iter().all(|s| self.servers.contains(s)))
+        {
+            return false;
+        }
+        self.asked += 1;
+        self.servers.extend(servers);
+        true
+    }
+}
+
+/// Names at or below `suffix` are sent to `upstream`.
+#[derive(Debug)]
+pub struct Route {
+    suffix: DomainName,
+    upstream: Resolver,
+    fallbacks: Vec<Resolver>,
+    fallback_when: Fallthrough,
+    fallthrough: Fallthrough,
+    negative_caching: NegativeCaching,
+    policy: CachePolicy,
+    rewrite: Option<Rewrite>,
+    cache: MessageCache,
+    clock: Arc<dyn Clock>,
+    #[cfg(feature = "dnssec")]
+    validator: Option<Validator>,
+}
+
+impl Route {
+    /// A route with the default cache policy that never falls through,
+    /// and has no fallbacks.
+    pub fn new(suffix: DomainName, upstream: Resolver) -> Route {
+        let clock = clock::system();
+        Route {
+            suffix,
+            upstream,
+            fallbacks: Vec::new(),
+            fallback_when: Fallthrough::OnFailure,
+            fallthrough: Fallthrough::Never,
+            negative_caching: NegativeCaching::Always,
+            policy: CachePolicy::default(),
+            rewrite: None,
+            cache: cache_for(&CachePolicy::default(), &clock),
+            clock,
+            #[cfg(feature = "dnssec")]
+            validator: None,
+        }
+    }
+
+    /// Replaces the cache, and whatever it held, with one under `policy`.
+    pub fn cache_policy(mut self, policy: CachePolicy) -> Self {
+        self.policy = policy;
+        self.cache = cache_for(&policy, &self.clock);
+        self
+    }
+
+    /// Ages and expires cached responses, and checks signature validity,
+    /// by `clock` rather than the system's. Replaces the cache, as
+    /// [`cache_policy`](Route::cache_policy) does.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.cache = cache_for(&self.policy, &clock);
+        #[cfg(feature = "dnssec")]
+        {
+            self.validator = self.validator.map(|v| v.clock(clock.clone()));
+        }
+        self.clock = clock;
+        self
+    }
+
+    pub fn fallthrough(mut self, fallthrough: Fallthrough) -> Self {
+        self.fallthrough = fallthrough;
+        self
+    }
+
+    /// Adds upstreams to ask after those already added, when their answer
+    /// calls for it; see [`fallback_when`](Route::fallback_when).
+    pub fn fallback(mut self, upstream: Resolver) -> Self {
+        self.fallbacks.push(upstream);
+        self
+    }
+
+    /// When a query moves on to the route's next fallback: by default,
+    /// when the upstreams fail.
+    pub fn fallback_when(mut self, when: Fallthrough) -> Self {
+        self.fallback_when = when;
+        self
+    }
+
+    pub fn negative_caching(mut self, negative_caching: NegativeCaching) -> Self {
+        self.negative_caching = negative_caching;
+        self
+    }
+
+    /// Rewrites upstream answers before they are cached. Only flattening
+    /// and address substitution apply here.
+    pub fn rewrite(mut self, rewrite: Rewrite) -> Self {
+        self.rewrite = Some(rewrite);
+        self
+    }
+
+    /// Validates upstream answers against `anchors`, fetching the keys of
+    /// the chain of trust through the same upstreams, which must return
+    /// DNSSEC records.
+    #[cfg(feature = "dnssec")]
+    pub fn validate(mut self, anchors: TrustAnchors) -> Self {
+        let validator = Validator::new(self.upstream.clone()).clock(self.clock.clone());
+        self.validator = Some(validator.anchors(anchors));
+        self
+    }
+
+    /// Reports answers that fail validation to the agent they advertise
+    /// (RFC 9567), within the limits of `policy`. Only takes effect on a
+    /// route that already validates.
+    #[cfg(feature = "dnssec")]
+    pub fn report_errors(mut self, policy: ReportPolicy) -> Self {
+        self.validator = self.validator.map(|v| v.report_errors(policy));
+        self
+    }
+
+    /// Whether the route validates upstream answers.
+    pub fn is_validating(&self) -> bool {
+        #[cfg(feature = "dnssec")]
+        return self.validator.is_some();
+        #[cfg(not(feature = "dnssec"))]
+        return false;
+    }
+
+    pub fn suffix(&self) -> &DomainName {
+        &self.suffix
+    }
+
+    pub fn upstream(&self) -> &Resolver {
+        &self.upstream
+    }
+
+    pub fn fallbacks(&self) -> &[Resolver] {
+        &self.fallbacks
+    }
+
+    /// The route's upstreams then its fallbacks, in the order they are
+    /// asked.
+    pub fn upstreams(&self) -> impl Iterator<Item = &Resolver> {
+        iter::once(&self.upstream).chain(&self.fallbacks)
+    }
+
+    /// Drops every cached response.
+    pub fn flush(&self) {
+        self.cache.clear();
+    }
+
+    /// How full the cache is.
+    pub fn cache_usage(&self) -> CacheUsage {
+        self.cache.usage()
+    }
+
+    /// The unexpired responses in the cache, for `name` only if given,
+    /// with their TTLs counted down as when they are served.
+    pub fn cache_entries(&self, name: Option<&DomainName>) -> Vec<CachedResponse> {
+        self.entries_where(|q| name.is_none_or(|n| q.name == *n))
+    }
+
+    /// The unexpired responses in the cache for the names `pattern`
+    /// matches, with their TTLs counted down as when they are served.
+    pub fn inspect_cache(&self, pattern: &NamePattern) -> Vec<CachedResponse> {
+        self.entries_where(|q| pattern.matches(&q.name))
+    }
+
+    fn entries_where(&self, wanted: impl Fn(&Question) -> bool) -> Vec<CachedResponse> {
+        let now = self.clock.now();
+        let mut entries = Vec::new();
+        self.cache.for_each(|key, entry| {
+            if !entry.response.is_expired(now) && wanted(&key.question) {
+                entries.push(CachedResponse {
+                    question: key.question.clone(),
+                    dnssec_ok: key.dnssec_ok,
+                    checking_disabled: key.checking_disabled,
+                    response: entry.response.aged(now),
+                    security: entry.security,
+                    expires_in: entry.response.remaining(now),
+                });
+            }
+        });
+        entries
+    }
+
+    /// Puts a response back in the cache, as from a snapshot, unless it
+    /// has expired, the cache is full, or the route validates and the
+    /// response was not validated. Returns whether it was added.
+    pub fn restore(&self, entry: CachedResponse) -> bool {
+        if self.policy.capacity == 0 || entry.expires_in.is_zero() {
+            return false;
+        }
+        if self.is_validating() && entry.security == Security::Unchecked {
+            return false;
+        }
+        if self.cache.is_full() {
+            return false;
+        }
+        let now = self.clock.now();
+        let key = CacheKey {
+            question: entry.question,
+            dnssec_ok: entry.dnssec_ok,
+            checking_disabled: entry.checking_disabled,
+        };
+        self.cache.insert(
+            key,
+            CacheEntry {
+                response: Expiring::kept_for(entry.response, now, entry.expires_in),
+                security: entry.security,
+            },
+        )
+    }
+
+    /// Drops the cached responses for `name`, of every type.
+    pub fn flush_name(&self, name: &DomainName) {
+        self.flush_matching(&NamePattern::exact(name.clone()), None);
+    }
+
+    /// Drops the cached responses for the names `pattern` matches, only
+    /// those of `qtype` if given. Returns how many were dropped.
+    pub fn flush_matching(&self, pattern: &NamePattern, qtype: Option<RecordType>) -> usize {
+        let mut dropped = 0;
+        self.cache.retain(|key, _| {
+            let q = &key.question;
+            let hit = pattern.matches(&q.name) && qtype.is_none_or(|t| q.qtype == t);
+            dropped += usize::from(hit);
+            !hit
+        });
+        dropped
+    }
+
+    fn falls_through(&self, rcode: Option<Rcode>) -> bool {
+        self.fallthrough.applies(rcode)
+    }
+
+    /// The response to `query` of the upstreams, then of each fallback in
+    /// turn while the answer calls for one. Upstreams `tried` rules out
+    /// are skipped; the last response wins.
+    fn ask(
+        &self,
+        query: &Message,
+        metrics: Option<&Metrics>,
+        tried: &mut Tried,
+    ) -> Option<Message> {
+        let mut last: Option<Message> = None;
+        for (i, upstream) in self.upstreams().enumerate() {
+            if i > 0
+                && !self
+                    .fallback_when
+                    .applies(last.as_ref().map(|r| r.header.rcode))
+            {
+                break;
+            }
+            if !tried.admit(upstream) {
+                continue;
+            }
+            classify::note_upstream_query();
+            let sent = Instant::now();
+            let resp = upstream.send(query).ok();
+            if let Some(metrics) = metrics {
+                let rtt = resp.as_ref().map(|_| sent.elapsed());
+                metrics.record_upstream(&self.suffix, rtt);
+            }
+            if resp.is_some() {
+                last = resp;
+            }
+        }
+        last
+    }
+
+    /// The upstream response to `query`, from the cache if possible, with
+    /// what validation made of it. The upstreams are only asked if
+    /// `recurse` is set, and only those `tried` does not rule out.
+    #[allow(clippy::too_many_arguments)]
+    fn resolve(
+        &self,
+        query: &Message,
+        recurse: bool,
+        now: Instant,
+        metrics: Option<&Metrics>,
+        plugins: &Plugins,
+        request: &Request,
+        tried: &mut Tried,
+    ) -> Option<(Message, Security)> {
+        // Validating takes the signatures, whatever the client asked for.
+        let mut checked;
+        let query = if self.is_validating() {
+            checked = query.clone();
+            checked.header.cd = true;
+            if let Some(edns) = &mut checked.edns {
+                edns.dnssec_ok = true;
+            }
+            &checked
+        } else {
+            query
+        };
+        let key = CacheKey::new(query).filter(|_| self.policy.capacity > 0);
+        if let Some(key) = &key {
+            let cached = self.cache.get(key, now);
+            if let Some(metrics) = metrics {
+                metrics.record_cache_lookup(cached.is_some());
+            }
+            classify::note_cache_lookup(cached.is_some());
+            if let Some((_, security)) = &cached {
+                classify::note_security(*security);
+                return cached;
+            }
+        }
+        if !recurse {
+            return None;
+        }
+        let mut rewritten = None;
+        if !plugins.is_empty() {
+            let mut outgoing = query.clone();
+            if let Some(answer) = plugins.pre_upstream(request, &mut outgoing) {
+                return Some((answer, Security::Unchecked));
+            }
+            rewritten = Some(outgoing);
+        }
+        // An upstream resolver speaks for the whole tree, but only for the
+        // names the question leads to.
+        let mut resp = self.ask(rewritten.as_ref().unwrap_or(query), metrics, tried)?;
+        scrub(&mut resp, &DomainName::root());
+        let (mut resp, security) = self.check(resp);
+        classify::note_security(security);
+        if let (Some(rewrite), Some(q)) = (&self.rewrite, query.question()) {
+            rewrite.apply(q, &mut resp);
+        }
+        if let Some(key) = key {
+            self.store(key, &resp, security, now);
+        }
+        Some((resp, security))
+    }
+
+    /// Validates `resp` if the route validates, noting why it failed in an
+    /// Extended DNS Error.
+    #[cfg(feature = "dnssec")]
+    fn check(&self, resp: Message) -> (Message, Security) {
+        let validator = match &self.validator {
+            Some(validator) => validator,
+            None => return (resp, Security::Unchecked),
+        };
+        let validated = validator.validate(&resp);
+        let security = match validated.status {
+            Status::Secure => Security::Secure,
+            Status::Insecure => Security::Insecure,
+            Status::Bogus | Status::Indeterminate => Security::Bogus,
+        };
+        let error = validated.extended_error();
+        let mut message = validated.message;
+        if let Some(error) = error {
+            message.add_extended_error(&error);
+        }
+        (message, security)
+    }
+
+    #[cfg(not(feature = "dnssec"))]
+    fn check(&self, resp: Message) -> (Message, Security) {
+        (resp, Security::Unchecked)
+    }
+
+    fn store(&self, key: CacheKey, resp: &Message, security: Security, now: Instant) {
+        if self.policy.capacity == 0 || resp.header.tc {
+            return;
+        }
+        let negative = resp.header.rcode == Rcode::NXDOMAIN
+            || (resp.header.rcode == Rcode::NOERROR && resp.answers.is_empty());
+        let keep_negative = match self.negative_caching {
+            NegativeCaching::Always => true,
+            NegativeCaching::FinalOnly => !self.falls_through(Some(resp.header.rcode)),
+            NegativeCaching::Never => false,
+        };
+        if negative && !keep_negative {
+            return;
+        }
+        // An answer tailored to a client's subnet is only for that subnet
+        // (RFC 7871 §7.3.1), and the cache does not tell subnets apart.
+        let scope = resp
+            .edns
+            .as_ref()
+            .and_then(|e| e.option(OptionCode::CLIENT_SUBNET))
+            .and_then(|o| o.data.get(3).copied());
+        if scope.is_some_and(|scope| scope > 0) {
+            return;
+        }
+        let ttl = match self.ttl(resp) {
+            Some(ttl) if security == Security::Bogus => ttl.min(BOGUS_TTL),
+            Some(ttl) => ttl,
+            None => return,
+        };
+        if ttl == 0 {
+            return;
+        }
+        self.cache.insert(
+            key,
+            CacheEntry {
+                response: Expiring::kept_for(
+                    resp.clone(),
+                    now,
+                    Duration::from_secs(u64::from(ttl)),
+                ),
+                security,
+            },
+        );
+    }
+
+    /// How long `resp` may be cached under this route's policy, or `None`
+    /// if it must not be. No longer, in any case, than its signatures
+    /// last.
+    fn ttl(&self, resp: &Message) -> Option<u32> {
+        let ttl = self.record_ttl(resp)?;
+        Some(
+            match signatures_expire(resp, self.clock.unix_time() as u32) {
+                Some(left) => ttl.min(left),
+                None => ttl,
+            },
+        )
+    }
+
+    fn record_ttl(&self, resp: &Message) -> Option<u32> {
+        let negative = match resp.header.rcode {
+            Rcode::NXDOMAIN => true,
+            Rcode::NOERROR => resp.answers.is_empty(),
+            _ => return None,
+        };
+        if negative {
+            let soa = resp
+                .authority
+                .iter()
+                .find(|rr| rr.rtype() == RecordType::SOA)?;
+            let minimum = match &soa.rdata {
+                RData::Soa(data) => data.minimum,
+                _ => return None,
+            };
+            return Some(soa.ttl.min(minimum).min(self.policy.max_negative_ttl));
+        }
+        let ttl = resp
+            .answers
+            .iter()
+            .chain(&resp.authority)
+            .map(|rr| rr.ttl)
+            .min()?;
+        Some(ttl.clamp(
+            self.policy.min_ttl,
+            self.policy.max_ttl.max(self.policy.min_ttl),
+        ))
+    }
+}
+
+/// Seconds from `now` until the first of the RRSIGs in `resp` expires.
+fn signatures_expire(resp: &Message, now: u32) -> Option<u32> {
+    resp.records()
+        .filter_map(|rr| match &rr.rdata {
+            // The expiration follows the covered type, algorithm, labels
+            // and original TTL (RFC 4034 §3.1).
+            RData::Unknown { rtype, data } if *rtype == RecordType::RRSIG => data
+                .get(8..12)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+            _ => None,
+        })
+        // Serial number arithmetic, as signature times wrap.
+        .map(|expiration| (expiration.wrapping_sub(now) as i32).max(0) as u32)
+        .min()
+}
+
+/// Whether `rr` is a DNSSEC record a client that did not set DO gets only
+/// if it asked for that type (RFC 4035 §3.2.1).
+fn is_dnssec_record(rr: &crate::rr::Record, qtype: RecordType) -> bool {
+    let rtype = rr.rtype();
+    rtype != qtype
+        && matches!(
+            rtype,
+            RecordType::RRSIG | RecordType::NSEC | RecordType::NSEC3
+        )
+}
+
+/// Writes the data of `rdata` to `out`, uncompressed, allocating only
+/// for types other than addresses and aliases. Returns whether it could
+/// be encoded.
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+fn write_rdata(rdata: &RData, out: &mut Vec<u8>) -> bool {
+    out.clear();
+    match rdata {
+        RData::A(addr) => out.extend_from_slice(&addr.octets()),
+        RData::Aaaa(addr) => out.extend_from_slice(&addr.octets()),
+        RData::Cname(target) => {
+            for label in target.labels() {
+                out.push(label.len() as u8);
+                out.extend_from_slice(label);
+            }
+            out.push(0);
+        }
+        rdata => {
+            let mut enc = Encoder::uncompressed();
+            if rdata.encode(&mut enc).is_err() {
+                return false;
+            }
+            out.extend_from_slice(enc.as_bytes());
+        }
+    }
+    true
+}
+
+fn cache_for(policy: &CachePolicy, clock: &Arc<dyn Clock>) -> MessageCache {
+    MessageCache::new(
+        policy.capacity,
+        policy.max_bytes,
+        policy.shards,
+        clock.clone(),
+    )
+}
+
+/// A forwarder shared with what answers from it outside the handler,
+/// such as an XDP fast path, and replaced in place when routes change.
+pub type SharedForwarder = Arc<RwLock<Arc<Forwarder>>>;
+
+/// Answers recursive queries by forwarding them to upstream resolvers.
+///
+/// Each query goes to the route with the longest suffix covering its name,
+/// so a route for `corp.example` can send internal names to internal
+/// resolvers while a route for the root takes everything else. A route
+/// that falls through passes the query on to the next most specific route;
+/// if none is left, the last response (or SERVFAIL) is returned. Names no
+/// route covers are refused, and so are queries without RD set that the
+/// cache cannot answer.
+///
+/// Within a route, a query goes to its upstreams and then to each of its
+/// [fallbacks](Route::fallback) in turn while the answer calls for one.
+/// No set of upstreams is asked twice for one query, and a query that
+/// comes back from an upstream while it is being forwarded, as when two
+/// forwarders point at each other, gets SERVFAIL instead of going round
+/// again.
+#[derive(Debug, Default)]
+pub struct Forwarder {
+    /// Most specific first; routes with equal suffixes keep the order they
+    /// were added in.
+    routes: Vec<Route>,
+    metrics: Option<Arc<Metrics>>,
+    plugins: Plugins,
+    /// Questions being forwarded, with how many times each is.
+    forwarding: Mutex<HashMap<Question, usize>>,
+}
+
+impl Forwarder {
+    pub fn new() -> Forwarder {
+        Forwarder::default()
+    }
+
+    /// Records cache lookups and upstream round trips into `metrics`.
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Calls the pre-upstream hook of `plugins` before each query sent
+    /// upstream.
+    pub fn plugins(mut self, plugins: Plugins) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    /// Adds a route.
+    pub fn route(mut self, route: Route) -> Self {
+        let at = self
+            .routes
+            .iter()
+            .position(|r| r.suffix.label_count() < route.suffix.label_count())
+            .unwrap_or(self.routes.len());
+        self.routes.insert(at, route);
+        self
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// The routes covering `name`, in the order they are tried.
+    pub fn matching<'a>(&'a self, name: &'a DomainName) -> impl Iterator<Item = &'a Route> + 'a {
+        self.routes
+            .iter()
+            .filter(move |r| name.is_subdomain_of(&r.suffix))
+    }
+
+    /// The unexpired responses cached by every route, for `name` only if
+    /// given.
+    pub fn cache_entries(&self, name: Option<&DomainName>) -> Vec<CachedResponse> {
+        self.routes
+            .iter()
+            .flat_map(|r| r.cache_entries(name))
+            .collect()
+    }
+
+    /// The unexpired responses cached by every route for the names
+    /// `pattern` matches, each with the suffix of the route holding it.
+    pub fn inspect_cache(&self, pattern: &NamePattern) -> Vec<(DomainName, CachedResponse)> {
+        self.routes
+            .iter()
+            .flat_map(|r| {
+                r.inspect_cache(pattern)
+                    .into_iter()
+                    .map(move |c| (r.suffix.clone(), c))
+            })
+            .collect()
+    }
+
+    /// Drops the cached responses of every route.
+    pub fn flush(&self) {
+        for route in &self.routes {
+            route.flush();
+        }
+    }
+
+    /// Drops the cached responses for `name` from every route.
+    pub fn flush_name(&self, name: &DomainName) {
+        for route in &self.routes {
+            route.flush_name(name);
+        }
+    }
+
+    /// Drops the cached responses for the names `pattern` matches from
+    /// every route, only those of `qtype` if given. Returns how many were
+    /// dropped.
+    pub fn flush_matching(&self, pattern: &NamePattern, qtype: Option<RecordType>) -> usize {
+        self.routes
+            .iter()
+            .map(|r| r.flush_matching(pattern, qtype))
+            .sum()
+    }
+
+    /// Writes the caches of every route to `path`, replacing it only once
+    /// the snapshot is complete. Returns how many responses were written.
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".tmp");
+        let written = snapshot::write(self, BufWriter::new(File::create(&partial)?))?;
+        fs::rename(&partial, path)?;
+        Ok(written)
+    }
+
+    /// Fills the caches from a snapshot [`save_cache`](Self::save_cache)
+    /// wrote, minus what has expired since. Responses of routes that no
+    /// longer exist are skipped. Returns how many were restored.
+    pub fn load_cache<P: AsRef<Path>>(&self, path: P) -> io::Result<usize> {
+        snapshot::read(self, BufReader::new(File::open(path)?))
+    }
+
+    /// Resolves `questions`, such as the most popular names of a query
+    /// log, so that their answers are cached before clients ask. Returns
+    /// how many got an answer.
+    pub fn warm(&self, questions: &[(DomainName, RecordType)]) -> usize {
+        let next = AtomicUsize::new(0);
+        let answered = AtomicUsize::new(0);
+        let src = SocketAddr::from(([0, 0, 0, 0], 0));
+        thread::scope(|s| {
+            for _ in 0..WARM_CONCURRENCY.min(questions.len()) {
+                s.spawn(|| {
+                    while let Some((name, rtype)) =
+                        questions.get(next.fetch_add(1, Ordering::SeqCst))
+                    {
+                        let request = Request {
+                            message: Message::query(name.clone(), *rtype),
+                            src,
+                            protocol: Protocol::Udp,
+                            key: None,
+                        };
+                        let resp = self.handle(&request);
+                        if resp.is_some_and(|r| {
+                            r.header.rcode != Rcode::SERVFAIL && r.header.rcode != Rcode::REFUSED
+                        }) {
+                            answered.fetch_add(1, Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+        answered.into_inner()
+    }
+}
+
+impl Forwarder {
+    /// The response to `request` if the cache holds everything needed,
+    /// without asking any upstream; `None` otherwise.
+    pub fn answer_cached(&self, request: &Request) -> Option<Message> {
+        self.answer(request, true)
+    }
+
+    /// Writes the response [`answer_cached`](Self::answer_cached) would
+    /// give `query` into `resp`, for the XDP fast path. `key` and `rdata`
+    /// are scratch space kept between calls, so that once they and the
+    /// arena of `resp` have grown, answers holding only address and alias
+    /// records allocate nothing. Returns `false`, with `resp` to be thrown
+    /// away, when the answer is not cached or needs the full path: it
+    /// failed validation, its route falls through, or it does not fit.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    pub(super) fn write_cached(
+        &self,
+        query: &MessageRef<'_>,
+        resp: &mut ResponseBuilder<'_>,
+        key: &mut CacheKey,
+        rdata: &mut Vec<u8>,
+        minimal: bool,
+    ) -> bool {
+        let header = query.header();
+        let q = match query.question() {
+            Some(q) if q.qclass == RecordClass::IN && !q.qtype.is_meta() => q,
+            _ => return false,
+        };
+        let dnssec_ok = query.edns().is_some_and(|e| e.dnssec_ok);
+        key.question.name.assign(q.name.labels());
+        key.question.qtype = q.qtype;
+        key.question.qclass = RecordClass::IN;
+        let route = match self.matching(&key.question.name).next() {
+            Some(route) if route.policy.capacity > 0 => route,
+            _ => return false,
+        };
+        // As resolve() asks: validating routes take the signatures.
+        key.dnssec_ok = dnssec_ok || route.is_validating();
+        key.checking_disabled = header.cd || route.is_validating();
+        let written = route
+            .cache
+            .visit(key, route.clock.now(), |answer, held, security| {
+                if security == Security::Bogus && !header.cd
+                    || route.falls_through(Some(answer.header.rcode))
+                {
+                    return false;
+                }
+                resp.set_rcode(answer.header.rcode);
+                let flags = resp.header_mut();
+                flags.ra = true;
+                flags.ad = answer.header.ad && (header.ad || dnssec_ok);
+                // What the response policy keeps of positive answers.
+                let minimal =
+                    minimal && answer.header.rcode == Rcode::NOERROR && !answer.answers.is_empty();
+                let kept = |rr: &Record, section: usize| {
+                    if !dnssec_ok && is_dnssec_record(rr, q.qtype) {
+                        return false;
+                    }
+                    match section {
+                        _ if !minimal => true,
+                        2 => matches!(
+                            rr.rtype(),
+                            RecordType::NSEC | RecordType::NSEC3 | RecordType::RRSIG
+                        ),
+                        3 => false,
+                        _ => true,
+                    }
+                };
+                let sections = [
+                    (&answer.answers, 1),
+                    (&answer.authority, 2),
+                    (&answer.additional, 3),
+                ];
+                for (records, section) in sections {
+                    for rr in records.iter().filter(|rr| kept(rr, section)) {
+                        let rtype = rr.rtype();
+                        let ttl = rr.ttl.saturating_sub(held);
+                        let added = write_rdata(&rr.rdata, rdata)
+                            && match section {
+                                1 => resp.answer(&rr.name, rtype, ttl, rdata),
+                                2 => resp.authority(&rr.name, rtype, ttl, rdata),
+                                _ => resp.additional(&rr.name, rtype, ttl, rdata),
+                            };
+                        if !added {
+                            return false;
+                        }
+                    }
+                }
+                true
+            });
+        if let Some(metrics) = &self.metrics {
+            metrics.record_cache_lookup(written.is_some());
+        }
+        written.unwrap_or(false)
+    }
+
+    fn answer(&self, request: &Request, cached_only: bool) -> Option<Message> {
+        let query = &request.message;
+        let mut resp = query.response();
+        resp.header.ra = true;
+        if query.header.opcode != Opcode::QUERY {
+            resp.header.rcode = Rcode::NOTIMP;
+            return Some(resp);
+        }
+        let q = match query.questions.as_slice() {
+            [q] if !q.qtype.is_meta() || q.qtype == RecordType::ANY => q,
+            _ => {
+                resp.header.rcode = Rcode::FORMERR;
+                return Some(resp);
+            }
+        };
+        if q.qclass != RecordClass::IN {
+            resp.header.rcode = Rcode::REFUSED;
+            return Some(resp);
+        }
+
+        let mut upstream_query = Message::query(q.name.clone(), q.qtype);
+        upstream_query.header.cd = query.header.cd;
+        if let Some(edns) = &mut upstream_query.edns {
+            edns.dnssec_ok = query.edns.as_ref().is_some_and(|e| e.dnssec_ok);
+            // The upstreams' privacy policies say whether the client's
+            // subnet goes any further.
+            let subnet = query
+                .edns
+                .as_ref()
+                .and_then(|e| e.option(OptionCode::CLIENT_SUBNET));
+            edns.options.extend(subnet.cloned());
+        }
+
+        let question = Question::new(q.name.clone(), q.qtype);
+        if !cached_only && self.is_looping(request, &question) {
+            resp.header.rcode = Rcode::SERVFAIL;
+            resp.add_extended_error(&ExtendedError::new(ExtendedError::OTHER, "forwarding loop"));
+            return Some(resp);
+        }
+        let _forwarding = Forwarding::start(&self.forwarding, question);
+
+        let mut last = None;
+        let mut routed = false;
+        let mut tried = Tried::default();
+        for route in self.matching(&q.name) {
+            routed = true;
+            let answer = route.resolve(
+                &upstream_query,
+                query.header.rd && !cached_only,
+                route.clock.now(),
+                self.metrics.as_deref(),
+                &self.plugins,
+                request,
+                &mut tried,
+            );
+            if answer.is_none() && cached_only {
+                // Upstreams this route would ask come before later routes.
+                return None;
+            }
+            let falls_through = route.falls_through(answer.as_ref().map(|(a, _)| a.header.rcode));
+            if answer.is_some() {
+                last = answer;
+            }
+            if !falls_through {
+                break;
+            }
+        }
+        match last {
+            Some((answer, Security::Bogus)) if !query.header.cd => {
+                resp.header.rcode = Rcode::SERVFAIL;
+                for error in answer.extended_errors() {
+                    resp.add_extended_error(&error);
+                }
+            }
+            Some((answer, _)) => {
+                let dnssec_ok = upstream_query.edns.as_ref().is_some_and(|e| e.dnssec_ok);
+                resp.header.rcode = answer.header.rcode;
+                // Authenticated data only goes to clients that can tell
+                // (RFC 6840 §5.8).
+                resp.header.ad = answer.header.ad && (query.header.ad || dnssec_ok);
+                resp.answers = answer.answers;
+                resp.authority = answer.authority;
+                resp.additional = answer.additional;
+                if !dnssec_ok {
+                    for section in [&mut resp.answers, &mut resp.authority, &mut resp.additional] {
+                        section.retain(|rr| !is_dnssec_record(rr, q.qtype));
+                    }
+                }
+            }
+            None if cached_only => return None,
+            None if routed && query.header.rd => resp.header.rcode = Rcode::SERVFAIL,
+            None => resp.header.rcode = Rcode::REFUSED,
+        }
+        Some(resp)
+    }
+}
+
+impl Forwarder {
+    /// Whether `request` is one of ours come back: `question` is being
+    /// forwarded, and `request` comes from an upstream's address.
+    fn is_looping(&self, request: &Request, question: &Question) -> bool {
+        if !self.forwarding.lock().unwrap().contains_key(question) {
+            return false;
+        }
+        let src = request.src.ip();
+        self.routes
+            .iter()
+            .flat_map(Route::upstreams)
+            .any(|r| r.config().servers.iter().any(|s| s.ip() == src))
+    }
+}
+
+/// Marks a question as being forwarded while it lives.
+struct Forwarding<'a> {
+    questions: &'a Mutex<HashMap<Question, usize>>,
+    question: Question,
+}
+
+impl<'a> Forwarding<'a> {
+    fn start(questions: &'a Mutex<HashMap<Question, usize>>, question: Question) -> Forwarding<'a> {
+        *questions
+            .lock()
+            .unwrap()
+            .entry(question.clone())
+            .or_insert(0) += 1;
+        Forwarding {
+            questions,
+            question,
+        }
+    }
+}
+
+impl Drop for Forwarding<'_> {
+    fn drop(&mut self) {
+        let mut questions = self.questions.lock().unwrap();
+        if let Some(count) = questions.get_mut(&self.question) {
+            *count -= 1;
+            if *count == 0 {
+                questions.remove(&self.question);
+            }
+        }
+    }
+}
+
+impl Handler for Forwarder {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        self.answer(request, false)
+    }
+}
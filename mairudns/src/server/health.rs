@@ -0,0 +1,683 @@
+//! Health-checked answer pools, which make an authoritative server a
+//! simple global load balancer.
+//!
+//! A [`Pool`] names the addresses a name may resolve to and how each is
+//! probed. [`Pools::run`] probes them continually, and as a layer
+//! [`Pools`] removes the addresses found unhealthy from the A and AAAA
+//! records of answers, so clients are only sent to targets that work.
+//!
+//! A target changes state only after `rise` successes or `fall` failures
+//! in a row, so one lost probe does not withdraw it and one lucky probe
+//! does not bring back a flapping one. Each answer keeps at least
+//! `min_answers` addresses: when too few targets are healthy the
+//! unhealthy ones make up the difference, on the grounds that an address
+//! which might work beats an empty answer. Targets are taken to be
+//! healthy until probed.
+//!
+//! Targets can also be weighted and put in failover tiers. Only the
+//! lowest tier with a healthy target is given out, and a pool with
+//! `answers` set gives out no more addresses than that, chosen by weight
+//! with rendezvous hashing: at random for each query, or for each client
+//! subnet (the EDNS Client Subnet of RFC 7871 if the query has one, its
+//! source otherwise) so that a client keeps getting the same targets
+//! while they stay healthy. Answers chosen by a query's Client Subnet
+//! carry the prefix length they were chosen by as their scope, so that
+//! caches keep them for that subnet only. A pool with a `ttl` answers A
+//! and AAAA queries for its name itself, as if its targets were records
+//! of the zone; a zone that does not have the name still says so.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::crypto::{Digest, Sha256};
+use crate::message::{EdnsOption, Message, OptionCode, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{RData, Record, RecordType};
+use crate::sys;
+
+use super::{Handler, Layer, Request};
+
+/// How a target is probed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// A TCP connection to `port` is accepted.
+    Tcp { port: u16 },
+    /// A `GET` of `path` on `port` gets a 2xx or 3xx status. `host` is
+    /// sent as the `Host` header, the address if there is none.
+    Http {
+        port: u16,
+        path: String,
+        host: Option<String>,
+    },
+    /// An ICMP echo request is answered. This needs unprivileged ICMP
+    /// sockets, which Linux only allows to the groups in
+    /// `net.ipv4.ping_group_range`.
+    Icmp,
+}
+
+impl Probe {
+    /// Whether `addr` passes the probe within `timeout`.
+    pub fn check(&self, addr: IpAddr, timeout: Duration) -> bool {
+        match self {
+            Probe::Tcp { port } => {
+                TcpStream::connect_timeout(&SocketAddr::new(addr, *port), timeout).is_ok()
+            }
+            Probe::Http { port, path, host } => {
+                http_check(SocketAddr::new(addr, *port), path, host.as_deref(), timeout)
+            }
+            Probe::Icmp => icmp_check(addr, timeout),
+        }
+    }
+}
+
+fn http_check(addr: SocketAddr, path: &str, host: Option<&str>, timeout: Duration) -> bool {
+    let run = || -> std::io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_write_timeout(Some(timeout))?;
+        let host = match host {
+            Some(host) => host.to_string(),
+            None if addr.is_ipv6() => format!("[{}]", addr.ip()),
+            None => addr.ip().to_string(),
+        };
+        write!(
+            stream,
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: mairu-dns\r\nConnection: close\r\n\r\n",
+            path, host
+        )?;
+        // Only the status line matters.
+        let mut head = Vec::new();
+        let mut buf = [0u8; 256];
+        while !head.contains(&b'\n') && head.len() < 1024 {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(false);
+            }
+            stream.set_read_timeout(Some(left))?;
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+        let line = String::from_utf8_lossy(&head);
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok());
+        Ok(line.starts_with("HTTP/") && status.is_some_and(|s| (200..400).contains(&s)))
+    };
+    run().unwrap_or(false)
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|c| u32::from(u16::from_be_bytes([c[0], c.get(1).copied().unwrap_or(0)])))
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn icmp_check(addr: IpAddr, timeout: Duration) -> bool {
+    let run = || -> std::io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let socket = sys::icmp_socket(addr.is_ipv6())?;
+        // Type, code, checksum, identifier (set by the kernel), sequence,
+        // then a random payload to tell the reply apart.
+        let (request, reply) = if addr.is_ipv6() { (128, 129) } else { (8, 0) };
+        let mut echo = vec![request, 0, 0, 0, 0, 0];
+        echo.extend_from_slice(&random::u16().to_be_bytes());
+        echo.extend_from_slice(&random::u64().to_be_bytes());
+        if addr.is_ipv4() {
+            // The kernel fills in ICMPv6 checksums itself.
+            let sum = checksum(&echo);
+            echo[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        socket.send_to(&echo, SocketAddr::new(addr, 0))?;
+        let mut buf = [0u8; 1500];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(false);
+            }
+            socket.set_read_timeout(Some(left))?;
+            let (n, src) = socket.recv_from(&mut buf)?;
+            // Some systems include the IPv4 header in what is received.
+            if n < echo.len() {
+                continue;
+            }
+            let body = &buf[n - echo.len()..n];
+            if src.ip() == addr && body[0] == reply && body[6..] == echo[6..] {
+                return Ok(true);
+            }
+        }
+    };
+    run().unwrap_or(false)
+}
+
+/// A pool target and how it is preferred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub addr: IpAddr,
+    /// Share of the answers relative to the other targets of its tier.
+    /// A target of weight 0 is only given out when no other will do.
+    pub weight: u32,
+    /// Failover tier: targets are only given out while no lower tier has
+    /// a healthy target.
+    pub tier: u32,
+}
+
+impl Target {
+    /// A target of weight 1 in tier 0.
+    pub fn new(addr: IpAddr) -> Target {
+        Target {
+            addr,
+            weight: 1,
+            tier: 0,
+        }
+    }
+
+    pub fn weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn tier(mut self, tier: u32) -> Self {
+        self.tier = tier;
+        self
+    }
+}
+
+impl From<IpAddr> for Target {
+    fn from(addr: IpAddr) -> Target {
+        Target::new(addr)
+    }
+}
+
+/// How the targets of an answer are chosen when a pool gives out fewer
+/// than it has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Selection {
+    /// Afresh for each query.
+    #[default]
+    Random,
+    /// The same for every client in a subnet of these prefix lengths.
+    ClientSubnet { v4_prefix: u8, v6_prefix: u8 },
+}
+
+/// The addresses a name resolves to and how they are checked.
+#[derive(Clone, Debug)]
+pub struct Pool {
+    /// The owner of the A and AAAA records balanced.
+    pub name: DomainName,
+    pub targets: Vec<Target>,
+    pub probe: Probe,
+    /// Time between probes of each target.
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Successes in a row that bring an unhealthy target back.
+    pub rise: u32,
+    /// Failures in a row that withdraw a healthy target.
+    pub fall: u32,
+    /// Fewest addresses of each type an answer keeps.
+    pub min_answers: usize,
+    /// Most pool addresses of each type an answer holds; 0 for all.
+    pub answers: usize,
+    pub selection: Selection,
+    /// When set, A and AAAA queries for `name` are answered with the
+    /// targets, with this TTL, whatever records the zone has for it.
+    pub ttl: Option<u32>,
+}
+
+impl Pool {
+    /// A pool probing every 10 seconds, with a 2 second timeout, that
+    /// withdraws targets after 3 failures and restores them after 2
+    /// successes, and keeps at least one address in each answer.
+    pub fn new<T: Into<Target>>(
+        name: DomainName,
+        targets: impl IntoIterator<Item = T>,
+        probe: Probe,
+    ) -> Pool {
+        Pool {
+            name,
+            targets: targets.into_iter().map(Into::into).collect(),
+            probe,
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            rise: 2,
+            fall: 3,
+            min_answers: 1,
+            answers: 0,
+            selection: Selection::Random,
+            ttl: None,
+        }
+    }
+
+    fn target(&self, addr: IpAddr) -> Option<&Target> {
+        self.targets.iter().find(|t| t.addr == addr)
+    }
+}
+
+/// The health of one target, as last probed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetStatus {
+    pub addr: IpAddr,
+    pub healthy: bool,
+    /// Probes in a row that disagreed with `healthy`; the target changes
+    /// state when they reach the pool's `rise` or `fall`.
+    pub streak: u32,
+    pub last_checked: Option<SystemTime>,
+}
+
+impl TargetStatus {
+    fn record(&mut self, ok: bool, rise: u32, fall: u32) {
+        self.last_checked = Some(SystemTime::now());
+        if ok == self.healthy {
+            self.streak = 0;
+            return;
+        }
+        self.streak += 1;
+        if self.streak >= if ok { rise } else { fall } {
+            self.healthy = ok;
+            self.streak = 0;
+        }
+    }
+}
+
+struct PoolState {
+    pool: Pool,
+    targets: Mutex<Vec<TargetStatus>>,
+}
+
+impl PoolState {
+    fn probe_all(&self) {
+        let pool = &self.pool;
+        let results: Vec<bool> = thread::scope(|s| {
+            let probes: Vec<_> = pool
+                .targets
+                .iter()
+                .map(|t| s.spawn(move || pool.probe.check(t.addr, pool.timeout)))
+                .collect();
+            probes
+                .into_iter()
+                .map(|p| p.join().unwrap_or(false))
+                .collect()
+        });
+        let mut targets = self.targets.lock().unwrap();
+        for (target, ok) in targets.iter_mut().zip(results) {
+            target.record(ok, pool.rise, pool.fall);
+        }
+    }
+}
+
+/// Health-checked pools. [`run`](Pools::run) probes their targets
+/// continually, and as a [`Layer`] the pools remove the targets found
+/// unhealthy from the A and AAAA records of answers, keeping at least
+/// each pool's `min_answers`, and pick among the healthy ones by tier and
+/// weight.
+#[derive(Clone, Default)]
+pub struct Pools {
+    pools: Vec<Arc<PoolState>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl fmt::Debug for Pools {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.pools.iter().map(|p| &p.pool.name))
+            .finish()
+    }
+}
+
+impl Pools {
+    pub fn new() -> Pools {
+        Pools::default()
+    }
+
+    pub fn pool(mut self, pool: Pool) -> Self {
+        let targets = pool
+            .targets
+            .iter()
+            .map(|t| TargetStatus {
+                addr: t.addr,
+                healthy: true,
+                streak: 0,
+                last_checked: None,
+            })
+            .collect();
+        self.pools.push(Arc::new(PoolState {
+            pool,
+            targets: Mutex::new(targets),
+        }));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pools.is_empty()
+    }
+
+    /// Probes every target of every pool, each pool at its own interval,
+    /// until stopped.
+    pub fn run(&self) {
+        thread::scope(|s| {
+            for state in &self.pools {
+                s.spawn(move || {
+                    while !self.stopped.load(Ordering::SeqCst) {
+                        let started = Instant::now();
+                        state.probe_all();
+                        thread::sleep(state.pool.interval.saturating_sub(started.elapsed()));
+                    }
+                });
+            }
+        });
+    }
+
+    /// Has [`run`](Pools::run) return once each pool's current interval
+    /// has passed. Clones share the flag.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Probes every target once, now.
+    pub fn probe_all(&self) {
+        for state in &self.pools {
+            state.probe_all();
+        }
+    }
+
+    /// Records the outcome of a probe made elsewhere, such as by an
+    /// external monitor, as if the pool had made it.
+    pub fn report(&self, name: &DomainName, addr: IpAddr, healthy: bool) {
+        for state in self.pools.iter().filter(|p| p.pool.name == *name) {
+            let mut targets = state.targets.lock().unwrap();
+            for target in targets.iter_mut().filter(|t| t.addr == addr) {
+                target.record(healthy, state.pool.rise, state.pool.fall);
+            }
+        }
+    }
+
+    /// The targets of the pool for `name`.
+    pub fn status(&self, name: &DomainName) -> Option<Vec<TargetStatus>> {
+        self.pools
+            .iter()
+            .find(|p| p.pool.name == *name)
+            .map(|p| p.targets.lock().unwrap().clone())
+    }
+
+    /// Removes the unhealthy targets from the answers of `resp`, and
+    /// those of tiers failed over from or not chosen, choosing at random.
+    pub fn filter(&self, resp: &mut Message) {
+        for state in &self.pools {
+            state.filter(resp, &random::u64().to_be_bytes());
+        }
+    }
+
+    /// Answers for the pools with a TTL that `request` asks about, then
+    /// filters `resp` as [`filter`](Pools::filter) does, choosing targets
+    /// as each pool's [`Selection`] has it.
+    pub fn steer(&self, request: &Request, resp: &mut Message) {
+        for state in &self.pools {
+            state.synthesize(request, resp);
+            let (seed, scope) = match state.pool.selection {
+                Selection::Random => (random::u64().to_be_bytes().to_vec(), None),
+                Selection::ClientSubnet {
+                    v4_prefix,
+                    v6_prefix,
+                } => client_subnet(request, v4_prefix, v6_prefix),
+            };
+            if let (true, Some(scope)) = (state.filter(resp, &seed), scope) {
+                set_scope(request, resp, scope);
+            }
+        }
+    }
+}
+
+impl PoolState {
+    /// Puts the targets of the type `request` asks for in the answer, if
+    /// the pool has a TTL, `request` asks about its name and the zone has
+    /// the name.
+    fn synthesize(&self, request: &Request, resp: &mut Message) {
+        let pool = &self.pool;
+        let ttl = match pool.ttl {
+            Some(ttl) => ttl,
+            None => return,
+        };
+        let question = match request.message.question() {
+            Some(q) if q.name == pool.name => q,
+            _ => return,
+        };
+        // An NXDOMAIN stands, with the SOA that proves it: the pool's
+        // name is one the zone must have.
+        if resp.header.rcode != Rcode::NOERROR {
+            return;
+        }
+        let records: Vec<Record> = pool
+            .targets
+            .iter()
+            .filter_map(|t| match (question.qtype, t.addr) {
+                (RecordType::A, IpAddr::V4(a)) => Some(RData::A(a)),
+                (RecordType::AAAA, IpAddr::V6(a)) => Some(RData::Aaaa(a)),
+                _ => None,
+            })
+            .map(|rdata| Record::new(question.name.clone(), ttl, rdata))
+            .collect();
+        if records.is_empty() {
+            return;
+        }
+        resp.answers
+            .retain(|rr| rr.name != pool.name || rr.rtype() != question.qtype);
+        if resp.answers.is_empty() {
+            // The SOA of a no-data answer that no longer is one.
+            resp.authority.retain(|rr| rr.rtype() != RecordType::SOA);
+        }
+        resp.answers.extend(records);
+    }
+
+    /// Filters the pool's addresses in `resp`, hashing `seed` to choose
+    /// among them. Returns whether `seed` made a difference: whether there
+    /// were more targets to give out than the pool gives.
+    fn filter(&self, resp: &mut Message, seed: &[u8]) -> bool {
+        let pool = &self.pool;
+        let name = &pool.name;
+        if !resp.answers.iter().any(|rr| rr.name == *name) {
+            return false;
+        }
+        let mut chosen = false;
+        let statuses = self.targets.lock().unwrap();
+        let healthy = |addr: IpAddr| {
+            statuses
+                .iter()
+                .find(|t| t.addr == addr)
+                .is_none_or(|t| t.healthy)
+        };
+        for rtype in [RecordType::A, RecordType::AAAA] {
+            // Addresses outside the pool are left alone.
+            let mut outside = 0;
+            let mut pooled = Vec::new();
+            for (i, rr) in resp.answers.iter().enumerate() {
+                if rr.name != *name || rr.rtype() != rtype {
+                    continue;
+                }
+                let addr = match rr.rdata {
+                    RData::A(a) => IpAddr::V4(a),
+                    RData::Aaaa(a) => IpAddr::V6(a),
+                    _ => continue,
+                };
+                match pool.target(addr) {
+                    Some(target) => pooled.push((i, *target, healthy(addr))),
+                    None => outside += 1,
+                }
+            }
+            if pooled.is_empty() {
+                continue;
+            }
+
+            let tier = pooled.iter().filter(|p| p.2).map(|p| p.1.tier).min();
+            let (mut keep, mut rest): (Vec<_>, Vec<_>) = pooled
+                .into_iter()
+                .partition(|p| p.2 && Some(p.1.tier) == tier);
+            // The healthy targets of later tiers, then the unhealthy ones,
+            // make up an answer too short.
+            rest.sort_by_key(|p| (!p.2, p.1.tier));
+            let spare = pool.min_answers.saturating_sub(outside + keep.len());
+            keep.extend(rest.drain(..spare.min(rest.len())));
+            let most = pool.answers.max(pool.min_answers.saturating_sub(outside));
+            if pool.answers > 0 && keep.len() > most {
+                chosen = true;
+                keep.sort_by(|a, b| score(seed, &b.1).total_cmp(&score(seed, &a.1)));
+                rest.extend(keep.drain(most..));
+            }
+
+            let drop: Vec<usize> = rest.iter().map(|p| p.0).collect();
+            let mut i = 0;
+            resp.answers.retain(|_| {
+                i += 1;
+                !drop.contains(&(i - 1))
+            });
+        }
+        chosen
+    }
+}
+
+/// The weighted rendezvous hashing score of `target` for `seed`: the
+/// targets with the highest scores are given out.
+fn score(seed: &[u8], target: &Target) -> f64 {
+    if target.weight == 0 {
+        return 0.0;
+    }
+    let mut h = Sha256::default();
+    h.update(seed);
+    match target.addr {
+        IpAddr::V4(a) => h.update(&a.octets()),
+        IpAddr::V6(a) => h.update(&a.octets()),
+    }
+    let hash = h.finish();
+    let mut bits = [0u8; 8];
+    bits.copy_from_slice(&hash[..8]);
+    // Uniform in (0, 1).
+    let u = ((u64::from_be_bytes(bits) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    f64::from(target.weight) / -u.ln()
+}
+
+/// The Client Subnet option of `request`, if it has one.
+fn client_subnet_option(request: &Request) -> Option<&[u8]> {
+    request
+        .message
+        .edns
+        .as_ref()
+        .and_then(|e| e.option(OptionCode::CLIENT_SUBNET))
+        .map(|o| o.data.as_slice())
+}
+
+/// The subnet `request` comes from, as bytes: its Client Subnet option's
+/// if it has one, or its source address cut to the prefix lengths given.
+/// With the option comes the prefix length the subnet was cut to, the
+/// scope of an answer chosen by it.
+fn client_subnet(request: &Request, v4_prefix: u8, v6_prefix: u8) -> (Vec<u8>, Option<u8>) {
+    let ecs = client_subnet_option(request);
+    // Family, source prefix length, scope prefix length, then the address.
+    if let Some(data) = ecs.filter(|d| d.len() >= 4) {
+        let (family, prefix) = (u16::from_be_bytes([data[0], data[1]]), data[2]);
+        let (len, most) = match family {
+            1 => (4, v4_prefix),
+            2 => (16, v6_prefix),
+            _ => (0, 0),
+        };
+        if len > 0 {
+            let mut addr = data[4..].to_vec();
+            addr.resize(len, 0);
+            let prefix = prefix.min(most);
+            return (masked(&addr, prefix), Some(prefix));
+        }
+    }
+    let subnet = match request.src.ip() {
+        IpAddr::V4(a) => masked(&a.octets(), v4_prefix),
+        IpAddr::V6(a) => match a.to_ipv4_mapped() {
+            Some(a) => masked(&a.octets(), v4_prefix),
+            None => masked(&a.octets(), v6_prefix),
+        },
+    };
+    (subnet, None)
+}
+
+/// Echoes the Client Subnet option of `request` in `resp` with a scope
+/// prefix length of at least `scope` (RFC 7871 §7.2.1).
+fn set_scope(request: &Request, resp: &mut Message, scope: u8) {
+    let (ecs, edns) = match (client_subnet_option(request), resp.edns.as_mut()) {
+        (Some(ecs), Some(edns)) => (ecs, edns),
+        _ => return,
+    };
+    match edns
+        .options
+        .iter_mut()
+        .find(|o| o.code == OptionCode::CLIENT_SUBNET)
+    {
+        Some(option) if option.data.len() >= 4 => option.data[3] = option.data[3].max(scope),
+        Some(_) => {}
+        None => {
+            let mut data = ecs.to_vec();
+            data[3] = scope;
+            edns.options.push(EdnsOption {
+                code: OptionCode::CLIENT_SUBNET,
+                data,
+            });
+        }
+    }
+}
+
+/// `addr` with the bits after the first `prefix` cleared, then `prefix`.
+fn masked(addr: &[u8], prefix: u8) -> Vec<u8> {
+    let mut out: Vec<u8> = addr
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            let bits = usize::from(prefix).saturating_sub(i * 8).min(8);
+            b & !(0xffu16 >> bits) as u8
+        })
+        .collect();
+    out.push(prefix);
+    out
+}
+
+impl<H: Handler> Layer<H> for Pools {
+    type Handler = Balanced<H>;
+
+    fn layer(&self, inner: H) -> Balanced<H> {
+        Balanced {
+            pools: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler whose answers only hold healthy pool targets.
+#[derive(Debug)]
+pub struct Balanced<H> {
+    pools: Pools,
+    inner: H,
+}
+
+impl<H> Balanced<H> {
+    pub fn pools(&self) -> &Pools {
+        &self.pools
+    }
+}
+
+impl<H: Handler> Handler for Balanced<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let mut resp = self.inner.handle(request)?;
+        if resp.header.rcode == Rcode::NOERROR {
+            self.pools.steer(request, &mut resp);
+        }
+        Some(resp)
+    }
+}
@@ -0,0 +1,633 @@
+//! HPACK header compression for HTTP/2 (RFC 7541).
+//!
+//! The decoder is complete, since every header block a client sends must
+//! be decoded to keep the dynamic table in step. The encoder only emits
+//! literals that are not indexed, which needs no state.
+
+use std::collections::VecDeque;
+
+/// Headers as name and value.
+pub(super) type Headers = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// A malformed header block; the connection cannot go on after one
+/// (RFC 9113 §4.3).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct CompressionError;
+
+/// The static table (RFC 7541 Appendix A); index 1 is the first entry.
+const STATIC: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Code lengths of the Huffman code (RFC 7541 Appendix B) for each symbol,
+/// the last being EOS. The code is canonical, so the lengths determine it.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6, 15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6,
+    6, 5, 6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22,
+    23, 23, 23, 23, 23, 24, 23, 24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22,
+    21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20,
+    22, 22, 22, 23, 22, 22, 23, 26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19,
+    21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22,
+    22, 25, 25, 24, 24, 26, 23, 26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+];
+
+const EOS: u16 = 256;
+
+/// Overhead counted for each dynamic table entry (RFC 7541 §4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Decodes the header blocks of one connection.
+pub(super) struct Decoder {
+    table: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+    /// The largest size the peer may set, as advertised in SETTINGS.
+    limit: usize,
+    /// The largest header list a block may decode to, counted as table
+    /// entries are. Indexed fields make a block expand, so this bounds
+    /// what a small block can allocate.
+    max_list: usize,
+}
+
+impl Decoder {
+    pub(super) fn new(limit: usize, max_list: usize) -> Decoder {
+        Decoder {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: limit,
+            limit,
+            max_list,
+        }
+    }
+
+    pub(super) fn decode(&mut self, mut block: &[u8]) -> Result<Headers, CompressionError> {
+        let mut headers = Vec::new();
+        let mut list = 0;
+        let max_list = self.max_list;
+        let mut push = |headers: &mut Headers, header: (Vec<u8>, Vec<u8>)| {
+            list += header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+            if list > max_list {
+                return Err(CompressionError);
+            }
+            headers.push(header);
+            Ok(())
+        };
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let index = integer(&mut block, 7)?;
+                push(&mut headers, self.entry(index)?)?;
+            } else if first & 0x40 != 0 {
+                let header = self.literal(&mut block, 6)?;
+                self.insert(header.clone());
+                push(&mut headers, header)?;
+            } else if first & 0x20 != 0 {
+                let size = integer(&mut block, 5)?;
+                if size > self.limit {
+                    return Err(CompressionError);
+                }
+                self.max_size = size;
+                self.evict(0);
+            } else {
+                // Without indexing or never indexed: both only matter to
+                // intermediaries.
+                push(&mut headers, self.literal(&mut block, 4)?)?;
+            }
+        }
+        Ok(headers)
+    }
+
+    fn entry(&self, index: usize) -> Result<(Vec<u8>, Vec<u8>), CompressionError> {
+        match index {
+            0 => Err(CompressionError),
+            1..=61 => {
+                let (name, value) = STATIC[index - 1];
+                Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            }
+            _ => self.table.get(index - 62).cloned().ok_or(CompressionError),
+        }
+    }
+
+    fn literal(
+        &self,
+        block: &mut &[u8],
+        prefix: u32,
+    ) -> Result<(Vec<u8>, Vec<u8>), CompressionError> {
+        let index = integer(block, prefix)?;
+        let name = if index == 0 {
+            string(block)?
+        } else {
+            self.entry(index)?.0
+        };
+        let value = string(block)?;
+        Ok((name, value))
+    }
+
+    fn insert(&mut self, header: (Vec<u8>, Vec<u8>)) {
+        let size = header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry larger than the table empties it and is not added.
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    /// Evicts the oldest entries until `room` more bytes fit.
+    fn evict(&mut self, room: usize) {
+        while self.size + room > self.max_size {
+            match self.table.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Reads an integer with an N-bit prefix (RFC 7541 §5.1).
+fn integer(block: &mut &[u8], prefix: u32) -> Result<usize, CompressionError> {
+    let (&first, rest) = block.split_first().ok_or(CompressionError)?;
+    *block = rest;
+    let max = (1usize << prefix) - 1;
+    let mut value = usize::from(first) & max;
+    if value < max {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = block.split_first().ok_or(CompressionError)?;
+        *block = rest;
+        if shift > 21 {
+            return Err(CompressionError);
+        }
+        value += usize::from(b & 0x7f) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Reads a string literal, Huffman coded or not (RFC 7541 §5.2).
+fn string(block: &mut &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let huffman = block.first().ok_or(CompressionError)? & 0x80 != 0;
+    let len = integer(block, 7)?;
+    if len > block.len() {
+        return Err(CompressionError);
+    }
+    let (data, rest) = block.split_at(len);
+    *block = rest;
+    if huffman {
+        huffman_decode(data)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    // Symbols ordered by code length, then value, as canonical codes are
+    // assigned.
+    let mut counts = [0u32; 31];
+    for &len in HUFFMAN_LENGTHS.iter() {
+        counts[usize::from(len)] += 1;
+    }
+    let mut offsets = [0usize; 31];
+    for len in 1..offsets.len() {
+        offsets[len] = offsets[len - 1] + counts[len - 1] as usize;
+    }
+    let mut symbols = [0u16; 257];
+    for (symbol, &len) in HUFFMAN_LENGTHS.iter().enumerate() {
+        symbols[offsets[usize::from(len)]] = symbol as u16;
+        offsets[usize::from(len)] += 1;
+    }
+
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let (mut code, mut first, mut index, mut len) = (0u32, 0u32, 0usize, 0usize);
+    let mut all_ones = true;
+    for bit in data
+        .iter()
+        .flat_map(|b| (0..8).rev().map(move |i| u32::from(b >> i & 1)))
+    {
+        code |= bit;
+        all_ones &= bit == 1;
+        len += 1;
+        let count = counts[len];
+        if code < first + count {
+            let symbol = symbols[index + (code - first) as usize];
+            if symbol == EOS {
+                return Err(CompressionError);
+            }
+            out.push(symbol as u8);
+            code = 0;
+            first = 0;
+            index = 0;
+            len = 0;
+            all_ones = true;
+            continue;
+        }
+        if len == 30 {
+            return Err(CompressionError);
+        }
+        index += count as usize;
+        first = (first + count) << 1;
+        code <<= 1;
+    }
+    // Padding is the shortest prefix of EOS that fills the last octet.
+    if len > 7 || !all_ones {
+        return Err(CompressionError);
+    }
+    Ok(out)
+}
+
+/// Encodes headers as literals without indexing, with the name taken from
+/// the static table where it is there.
+pub(super) fn encode(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &(name, value) in headers {
+        if let Some(i) = STATIC.iter().position(|&h| h == (name, value)) {
+            put_integer(&mut out, 0x80, 7, i + 1);
+            continue;
+        }
+        match STATIC.iter().position(|&(n, _)| n == name) {
+            Some(i) => put_integer(&mut out, 0, 4, i + 1),
+            None => {
+                out.push(0);
+                put_integer(&mut out, 0, 7, name.len());
+                out.extend_from_slice(name.as_bytes());
+            }
+        }
+        put_integer(&mut out, 0, 7, value.len());
+        out.extend_from_slice(value.as_bytes());
+    }
+    out
+}
+
+fn put_integer(out: &mut Vec<u8>, flags: u8, prefix: u32, mut value: usize) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Headers {
+        list.iter()
+            .map(|&(n, v)| (n.as_bytes().to_vec(), v.as_bytes().to_vec()))
+            .collect()
+    }
+
+    /// A header block in hex, the headers it decodes to and the table size
+    /// after it.
+    type Step<'a> = (&'a str, &'a [(&'a str, &'a str)], usize);
+
+    /// Decodes `blocks` in turn with a table of `limit` bytes.
+    fn sequence(limit: usize, blocks: &[Step<'_>]) {
+        let mut decoder = Decoder::new(limit, 16 * 1024);
+        for &(block, expected, size) in blocks {
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), headers(expected));
+            assert_eq!(decoder.size, size);
+        }
+    }
+
+    // RFC 7541 Appendix C.1.
+    #[test]
+    fn integers() {
+        assert_eq!(integer(&mut &hex("0a")[..], 5), Ok(10));
+        assert_eq!(integer(&mut &hex("1f9a0a")[..], 5), Ok(1337));
+        assert_eq!(integer(&mut &hex("2a")[..], 8), Ok(42));
+        for &(prefix, value) in &[(5, 10), (5, 1337), (7, 127), (4, 1 << 20)] {
+            let mut out = Vec::new();
+            put_integer(&mut out, 0, prefix, value);
+            assert_eq!(integer(&mut &out[..], prefix), Ok(value));
+        }
+    }
+
+    // RFC 7541 Appendix C.2.
+    #[test]
+    fn header_field_representations() {
+        sequence(
+            4096,
+            &[(
+                "400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572",
+                &[("custom-key", "custom-header")],
+                55,
+            )],
+        );
+        sequence(
+            4096,
+            &[(
+                "040c 2f73 616d 706c 652f 7061 7468",
+                &[(":path", "/sample/path")],
+                0,
+            )],
+        );
+        sequence(
+            4096,
+            &[(
+                "1008 7061 7373 776f 7264 0673 6563 7265 74",
+                &[("password", "secret")],
+                0,
+            )],
+        );
+        sequence(4096, &[("82", &[(":method", "GET")], 0)]);
+    }
+
+    const REQUEST_1: &[(&str, &str)] = &[
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/"),
+        (":authority", "www.example.com"),
+    ];
+    const REQUEST_2: &[(&str, &str)] = &[
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", "/"),
+        (":authority", "www.example.com"),
+        ("cache-control", "no-cache"),
+    ];
+    const REQUEST_3: &[(&str, &str)] = &[
+        (":method", "GET"),
+        (":scheme", "https"),
+        (":path", "/index.html"),
+        (":authority", "www.example.com"),
+        ("custom-key", "custom-value"),
+    ];
+
+    // RFC 7541 Appendix C.3.
+    #[test]
+    fn requests_without_huffman_coding() {
+        sequence(
+            4096,
+            &[
+                (
+                    "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                    REQUEST_1,
+                    57,
+                ),
+                ("8286 84be 5808 6e6f 2d63 6163 6865", REQUEST_2, 110),
+                (
+                    "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+                    REQUEST_3,
+                    164,
+                ),
+            ],
+        );
+    }
+
+    // RFC 7541 Appendix C.4.
+    #[test]
+    fn requests_with_huffman_coding() {
+        sequence(
+            4096,
+            &[
+                ("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff", REQUEST_1, 57),
+                ("8286 84be 5886 a8eb 1064 9cbf", REQUEST_2, 110),
+                (
+                    "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+                    REQUEST_3,
+                    164,
+                ),
+            ],
+        );
+    }
+
+    const RESPONSE_1: &[(&str, &str)] = &[
+        (":status", "302"),
+        ("cache-control", "private"),
+        ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+        ("location", "https://www.example.com"),
+    ];
+    const RESPONSE_2: &[(&str, &str)] = &[
+        (":status", "307"),
+        ("cache-control", "private"),
+        ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+        ("location", "https://www.example.com"),
+    ];
+    const RESPONSE_3: &[(&str, &str)] = &[
+        (":status", "200"),
+        ("cache-control", "private"),
+        ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+        ("location", "https://www.example.com"),
+        ("content-encoding", "gzip"),
+        (
+            "set-cookie",
+            "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+        ),
+    ];
+
+    // RFC 7541 Appendix C.5, where a 256-byte table evicts entries.
+    #[test]
+    fn responses_without_huffman_coding() {
+        sequence(
+            256,
+            &[
+                (
+                    "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 \
+                     3230 3133 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 \
+                     7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                    RESPONSE_1,
+                    222,
+                ),
+                ("4803 3330 37c1 c0bf", RESPONSE_2, 222),
+                (
+                    "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 \
+                     3220 474d 54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a \
+                     584f 5157 454f 5049 5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 \
+                     3630 303b 2076 6572 7369 6f6e 3d31",
+                    RESPONSE_3,
+                    215,
+                ),
+            ],
+        );
+    }
+
+    // RFC 7541 Appendix C.6.
+    #[test]
+    fn responses_with_huffman_coding() {
+        sequence(
+            256,
+            &[
+                (
+                    "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 \
+                     66e0 82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+                    RESPONSE_1,
+                    222,
+                ),
+                ("4883 640e ffc1 c0bf", RESPONSE_2, 222),
+                (
+                    "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a \
+                     839b d9ab 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 \
+                     72c1 ab27 0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+                    RESPONSE_3,
+                    215,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn encodes_what_the_decoder_reads_back() {
+        let list = [
+            (":status", "200"),
+            ("content-type", "application/dns-message"),
+            ("x-custom", "value"),
+        ];
+        assert_eq!(encode(&list[..1]), vec![0x88]);
+        let mut decoder = Decoder::new(4096, 16 * 1024);
+        assert_eq!(decoder.decode(&encode(&list)).unwrap(), headers(&list));
+        assert_eq!(decoder.size, 0);
+    }
+
+    #[test]
+    fn rejects_malformed_integers() {
+        // Truncated, and too long to fit.
+        assert!(integer(&mut &hex("")[..], 7).is_err());
+        assert!(integer(&mut &hex("7f")[..], 7).is_err());
+        assert!(integer(&mut &hex("7f80")[..], 7).is_err());
+        assert!(integer(&mut &hex("7fffffffffffffffffff7f")[..], 7).is_err());
+        let mut decoder = Decoder::new(4096, 16 * 1024);
+        assert!(decoder.decode(&hex("ffffffffff7f")).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_indexes_and_strings() {
+        let mut decoder = Decoder::new(4096, 16 * 1024);
+        // Index 0, and a dynamic index past the table.
+        assert!(decoder.decode(&hex("80")).is_err());
+        assert!(decoder.decode(&hex("be")).is_err());
+        // A string longer than the block, and a missing value.
+        assert!(decoder.decode(&hex("4005 61")).is_err());
+        assert!(decoder.decode(&hex("4001 61")).is_err());
+        // A table size above the advertised limit: 4097.
+        assert!(decoder.decode(&hex("3fe2 1f")).is_err());
+        assert!(decoder.decode(&hex("3fe1 1f")).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_huffman_strings() {
+        // 'a' is 00011, padded with ones.
+        assert_eq!(huffman_decode(&hex("1f")), Ok(b"a".to_vec()));
+        // Padded with zeros, padded past an octet, and EOS itself.
+        assert!(huffman_decode(&hex("18")).is_err());
+        assert!(huffman_decode(&hex("1fff")).is_err());
+        assert!(huffman_decode(&hex("ffff fffc")).is_err());
+        assert!(huffman_decode(&hex("ffff ffff")).is_err());
+    }
+
+    #[test]
+    fn evicts_entries_to_stay_within_the_table_size() {
+        let mut decoder = Decoder::new(100, 16 * 1024);
+        // Entries of 34 bytes; the third evicts the first.
+        decoder.decode(&hex("4001 6101 61")).unwrap();
+        decoder.decode(&hex("4001 6201 62")).unwrap();
+        decoder.decode(&hex("4001 6301 63")).unwrap();
+        assert_eq!(decoder.size, 68);
+        assert_eq!(decoder.decode(&hex("be")).unwrap(), headers(&[("c", "c")]));
+        assert_eq!(decoder.decode(&hex("bf")).unwrap(), headers(&[("b", "b")]));
+        assert!(decoder.decode(&hex("c0")).is_err());
+        // An entry larger than the table empties it.
+        let mut block = hex("4046");
+        block.extend_from_slice(&[b'x'; 70]);
+        block.push(0);
+        decoder.decode(&block).unwrap();
+        assert_eq!(decoder.size, 0);
+        assert!(decoder.decode(&hex("be")).is_err());
+        // Shrinking the table evicts too.
+        decoder.decode(&hex("4001 6101 61")).unwrap();
+        decoder.decode(&hex("20")).unwrap();
+        assert_eq!(decoder.size, 0);
+    }
+
+    #[test]
+    fn bounds_the_header_list_of_indexed_fields() {
+        let mut decoder = Decoder::new(4096, 16 * 1024);
+        // A 4000-byte entry, then a block of one-byte references to it.
+        let mut block = hex("4001 61");
+        put_integer(&mut block, 0, 7, 4000);
+        block.extend_from_slice(&[b'x'; 4000]);
+        decoder.decode(&block).unwrap();
+        assert!(decoder.decode(&[0xbe; 3]).is_ok());
+        assert!(decoder.decode(&[0xbe; 1000]).is_err());
+    }
+}
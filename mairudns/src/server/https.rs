@@ -0,0 +1,724 @@
+//! DNS over HTTPS (RFC 8484) on HTTP/1.1 and HTTP/2.
+//!
+//! Queries are accepted at [`DOH_PATH`] with GET, the message in the `dns`
+//! parameter as unpadded URL-safe base64, or with POST, the message as the
+//! body. Requests on one connection are answered in the order they
+//! complete; HTTP/2 streams are multiplexed but handled one at a time.
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+
+use crate::encoding::base64url_decode;
+use crate::message::Message;
+
+use super::hpack::{self, Decoder, Headers};
+use super::tls::Stream;
+use super::{Frontend, Transport};
+
+/// The path DNS queries are accepted at.
+pub const DOH_PATH: &str = "/dns-query";
+
+const CONTENT_TYPE: &str = "application/dns-message";
+
+/// The client connection preface of HTTP/2 (RFC 9113 §3.4).
+pub(super) const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The largest HTTP/1.1 request head accepted.
+const MAX_HEAD: usize = 8 * 1024;
+
+/// The largest DNS message, and so request body, accepted.
+const MAX_BODY: usize = 65535;
+
+/// An HTTP response to a DoH request.
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+    /// Freshness lifetime of the answer (RFC 8484 §5.1).
+    max_age: Option<u32>,
+}
+
+impl Response {
+    fn status(status: u16) -> Response {
+        Response {
+            status,
+            body: Vec::new(),
+            max_age: None,
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        501 => "Not Implemented",
+        _ => "Error",
+    }
+}
+
+/// Answers one request. `None` means the handler chose to send nothing,
+/// which HTTP cannot express; the caller drops the request instead.
+fn answer(
+    frontend: &Frontend,
+    src: SocketAddr,
+    transport: Transport,
+    method: &[u8],
+    target: &[u8],
+    content_type: Option<&[u8]>,
+    body: Vec<u8>,
+) -> Option<Response> {
+    let target = String::from_utf8_lossy(target);
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (&*target, None),
+    };
+    if path != DOH_PATH {
+        return Some(Response::status(404));
+    }
+    let wire = match method {
+        b"GET" => {
+            let param = query
+                .into_iter()
+                .flat_map(|q| q.split('&'))
+                .find_map(|p| p.strip_prefix("dns="));
+            match param.map(base64url_decode) {
+                Some(Ok(wire)) => wire,
+                _ => return Some(Response::status(400)),
+            }
+        }
+        b"POST" => {
+            // Parameters such as a charset may follow the media type.
+            let media = content_type
+                .and_then(|t| t.split(|&b| b == b';').next())
+                .map(|t| t.trim_ascii());
+            if !media.is_some_and(|t| t.eq_ignore_ascii_case(CONTENT_TYPE.as_bytes())) {
+                return Some(Response::status(415));
+            }
+            body
+        }
+        _ => return Some(Response::status(405)),
+    };
+    if wire.len() > MAX_BODY {
+        return Some(Response::status(413));
+    }
+    if wire.len() < 12 || wire[2] & 0x80 != 0 {
+        return Some(Response::status(400));
+    }
+    let mut reply = frontend.dispatch(&wire, src, transport);
+    let body = match reply.messages.len() {
+        0 => return None,
+        1 => reply.messages.pop().unwrap(),
+        // A zone transfer takes several messages; an HTTP response holds
+        // one.
+        _ => return Some(Response::status(501)),
+    };
+    let max_age = Message::from_wire(&body)
+        .ok()
+        .and_then(|m| m.answers.iter().chain(&m.authority).map(|rr| rr.ttl).min());
+    Some(Response {
+        status: 200,
+        body,
+        max_age,
+    })
+}
+
+/// Serves HTTP/1.1 requests until the client closes the connection or
+/// asks for it to be closed. `buf` holds bytes already read.
+pub(super) fn serve_http1(
+    frontend: &Frontend,
+    stream: &mut dyn Stream,
+    src: SocketAddr,
+    transport: Transport,
+    mut buf: Vec<u8>,
+) -> io::Result<()> {
+    loop {
+        let end = loop {
+            if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+            if buf.len() > MAX_HEAD {
+                return write_http1(stream, &Response::status(400), false);
+            }
+            if !fill(stream, &mut buf)? {
+                return Ok(());
+            }
+        };
+        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+        buf.drain(..end);
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or("").split(' ');
+        let (method, target, version) = match (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) {
+            (Some(m), Some(t), Some(v)) if v.starts_with("HTTP/1.") => (m, t, v),
+            _ => return write_http1(stream, &Response::status(400), false),
+        };
+        let mut keep_alive = version != "HTTP/1.0";
+        let mut content_length = None;
+        let mut content_type = None;
+        let mut chunked = false;
+        for line in lines.filter(|l| !l.is_empty()) {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => return write_http1(stream, &Response::status(400), false),
+            };
+            if name.eq_ignore_ascii_case("content-length") {
+                match value.parse::<usize>() {
+                    Ok(len) => content_length = Some(len),
+                    Err(_) => return write_http1(stream, &Response::status(400), false),
+                }
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = true;
+            } else if name.eq_ignore_ascii_case("connection") {
+                let close = value.eq_ignore_ascii_case("close");
+                keep_alive = !close && (keep_alive || value.eq_ignore_ascii_case("keep-alive"));
+            }
+        }
+        // Bodies are only read with a known length; after a request with
+        // any other body the connection cannot be reused.
+        if chunked || (method == "POST" && content_length.is_none()) {
+            return write_http1(stream, &Response::status(411), false);
+        }
+        let len = content_length.unwrap_or(0);
+        if len > MAX_BODY {
+            return write_http1(stream, &Response::status(413), false);
+        }
+        while buf.len() < len {
+            if !fill(stream, &mut buf)? {
+                return Ok(());
+            }
+        }
+        let body: Vec<u8> = buf.drain(..len).collect();
+        let resp = answer(
+            frontend,
+            src,
+            transport,
+            method.as_bytes(),
+            target.as_bytes(),
+            content_type.as_deref().map(str::as_bytes),
+            body,
+        );
+        match resp {
+            Some(resp) => write_http1(stream, &resp, keep_alive)?,
+            None => return Ok(()),
+        }
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads more of the stream into `buf`; false at the end of the stream.
+fn fill(stream: &mut dyn Stream, buf: &mut Vec<u8>) -> io::Result<bool> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(false),
+            Ok(n) => {
+                buf.extend_from_slice(&chunk[..n]);
+                return Ok(true);
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn write_http1(stream: &mut dyn Stream, resp: &Response, keep_alive: bool) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", resp.status, reason(resp.status));
+    if resp.status == 200 {
+        head.push_str(&format!("Content-Type: {}\r\n", CONTENT_TYPE));
+    }
+    if let Some(age) = resp.max_age {
+        head.push_str(&format!("Cache-Control: max-age={}\r\n", age));
+    }
+    if resp.status == 405 {
+        head.push_str("Allow: GET, POST\r\n");
+    }
+    if !keep_alive {
+        head.push_str("Connection: close\r\n");
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", resp.body.len()));
+    let mut out = head.into_bytes();
+    out.extend_from_slice(&resp.body);
+    stream.write_all(&out)?;
+    stream.flush()
+}
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+
+/// Frame payloads up to this size are accepted; it is the least a peer
+/// must support, so it is not advertised.
+const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+const DEFAULT_WINDOW: i64 = 65535;
+
+/// Streams a client may have open at once.
+const MAX_STREAMS: usize = 100;
+
+/// Size of the HPACK dynamic table for header blocks from the client.
+const HEADER_TABLE_SIZE: usize = 4096;
+
+/// The largest header list accepted in a request, counted as HPACK
+/// counts table entries.
+const MAX_HEADER_LIST: usize = 16 * 1024;
+
+/// A request stream, from its first HEADERS frame until its response has
+/// been sent.
+struct H2Stream {
+    headers: Headers,
+    body: Vec<u8>,
+    /// Whether the client has sent all of the request.
+    complete: bool,
+    /// The response body still to be sent.
+    response: Option<Vec<u8>>,
+    send_window: i64,
+}
+
+/// Why an HTTP/2 connection ends early.
+enum ConnectionError {
+    Io(io::Error),
+    /// An error code reported in GOAWAY before the connection closes.
+    Protocol(u32),
+}
+
+impl From<io::Error> for ConnectionError {
+    fn from(e: io::Error) -> ConnectionError {
+        ConnectionError::Io(e)
+    }
+}
+
+struct H2<'a> {
+    frontend: &'a Frontend,
+    stream: &'a mut dyn Stream,
+    src: SocketAddr,
+    transport: Transport,
+    decoder: Decoder,
+    streams: HashMap<u32, H2Stream>,
+    last_stream: u32,
+    send_window: i64,
+    initial_window: i64,
+    max_frame_size: usize,
+    /// A header block awaiting CONTINUATION frames: stream, block so far
+    /// and whether it ends the stream.
+    continuation: Option<(u32, Vec<u8>, bool)>,
+}
+
+/// Serves HTTP/2 on a connection whose client preface, up to `read`
+/// bytes of it, has already been consumed.
+pub(super) fn serve_h2(
+    frontend: &Frontend,
+    stream: &mut dyn Stream,
+    src: SocketAddr,
+    transport: Transport,
+    read: usize,
+) -> io::Result<()> {
+    let mut preface = vec![0u8; H2_PREFACE.len() - read];
+    stream.read_exact(&mut preface)?;
+    if preface != H2_PREFACE[read..] {
+        return Ok(());
+    }
+    let mut conn = H2 {
+        frontend,
+        stream,
+        src,
+        transport,
+        decoder: Decoder::new(HEADER_TABLE_SIZE, MAX_HEADER_LIST),
+        streams: HashMap::new(),
+        last_stream: 0,
+        send_window: DEFAULT_WINDOW,
+        initial_window: DEFAULT_WINDOW,
+        max_frame_size: MAX_FRAME_SIZE,
+        continuation: None,
+    };
+    let mut settings = Vec::new();
+    settings.extend_from_slice(&SETTINGS_MAX_CONCURRENT_STREAMS.to_be_bytes());
+    settings.extend_from_slice(&(MAX_STREAMS as u32).to_be_bytes());
+    settings.extend_from_slice(&SETTINGS_MAX_HEADER_LIST_SIZE.to_be_bytes());
+    settings.extend_from_slice(&(MAX_HEADER_LIST as u32).to_be_bytes());
+    conn.write_frame(SETTINGS, 0, 0, &settings)?;
+    match conn.run() {
+        Ok(()) => Ok(()),
+        Err(ConnectionError::Io(e)) => Err(e),
+        Err(ConnectionError::Protocol(code)) => {
+            let mut payload = conn.last_stream.to_be_bytes().to_vec();
+            payload.extend_from_slice(&code.to_be_bytes());
+            conn.write_frame(GOAWAY, 0, 0, &payload)
+        }
+    }
+}
+
+impl H2<'_> {
+    fn run(&mut self) -> Result<(), ConnectionError> {
+        loop {
+            let mut header = [0u8; 9];
+            match self.stream.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let (kind, flags) = (header[3], header[4]);
+            let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+            if len > MAX_FRAME_SIZE {
+                return Err(ConnectionError::Protocol(FRAME_SIZE_ERROR));
+            }
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload)?;
+            if self.continuation.is_some() && kind != CONTINUATION {
+                return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+            }
+            match kind {
+                DATA => self.data(id, flags, payload)?,
+                HEADERS => self.headers(id, flags, payload)?,
+                CONTINUATION => self.continuation(id, flags, payload)?,
+                RST_STREAM => {
+                    self.streams.remove(&id);
+                }
+                SETTINGS if flags & ACK == 0 => {
+                    self.settings(&payload)?;
+                    self.write_frame(SETTINGS, ACK, 0, &[])?;
+                }
+                PING if flags & ACK == 0 => {
+                    if payload.len() != 8 {
+                        return Err(ConnectionError::Protocol(FRAME_SIZE_ERROR));
+                    }
+                    self.write_frame(PING, ACK, 0, &payload)?;
+                }
+                GOAWAY => return Ok(()),
+                WINDOW_UPDATE => {
+                    if payload.len() != 4 {
+                        return Err(ConnectionError::Protocol(FRAME_SIZE_ERROR));
+                    }
+                    let increment = i64::from(
+                        u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]])
+                            & 0x7fff_ffff,
+                    );
+                    let window = if id == 0 {
+                        Some(&mut self.send_window)
+                    } else {
+                        self.streams.get_mut(&id).map(|s| &mut s.send_window)
+                    };
+                    if let Some(window) = window {
+                        *window += increment;
+                        if *window > i64::from(i32::MAX) {
+                            return Err(ConnectionError::Protocol(FLOW_CONTROL_ERROR));
+                        }
+                    }
+                }
+                PUSH_PROMISE => return Err(ConnectionError::Protocol(PROTOCOL_ERROR)),
+                // PRIORITY, acknowledgements and unknown frame types.
+                _ => {}
+            }
+            self.flush()?;
+        }
+    }
+
+    fn settings(&mut self, payload: &[u8]) -> Result<(), ConnectionError> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(ConnectionError::Protocol(FRAME_SIZE_ERROR));
+        }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value > i32::MAX as u32 {
+                        return Err(ConnectionError::Protocol(FLOW_CONTROL_ERROR));
+                    }
+                    let delta = i64::from(value) - self.initial_window;
+                    for s in self.streams.values_mut() {
+                        s.send_window += delta;
+                    }
+                    self.initial_window = i64::from(value);
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(16384..=16_777_215).contains(&value) {
+                        return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                // Responses are encoded without the dynamic table, so its
+                // size does not matter.
+                SETTINGS_HEADER_TABLE_SIZE => {}
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn headers(&mut self, id: u32, flags: u8, payload: Vec<u8>) -> Result<(), ConnectionError> {
+        if id == 0 {
+            return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+        }
+        let mut block = unpad(flags, payload)?;
+        if flags & PRIORITY != 0 {
+            if block.len() < 5 {
+                return Err(ConnectionError::Protocol(FRAME_SIZE_ERROR));
+            }
+            block.drain(..5);
+        }
+        let end_stream = flags & END_STREAM != 0;
+        if flags & END_HEADERS == 0 {
+            self.continuation = Some((id, block, end_stream));
+            return Ok(());
+        }
+        self.header_block(id, &block, end_stream)
+    }
+
+    fn continuation(
+        &mut self,
+        id: u32,
+        flags: u8,
+        payload: Vec<u8>,
+    ) -> Result<(), ConnectionError> {
+        let (stream, mut block, end_stream) = match self.continuation.take() {
+            Some(c) if c.0 == id => c,
+            _ => return Err(ConnectionError::Protocol(PROTOCOL_ERROR)),
+        };
+        block.extend_from_slice(&payload);
+        // Encoded, a header list within the limit takes less room than
+        // the limit counts.
+        if block.len() > MAX_HEADER_LIST {
+            return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+        }
+        if flags & END_HEADERS == 0 {
+            self.continuation = Some((stream, block, end_stream));
+            return Ok(());
+        }
+        self.header_block(id, &block, end_stream)
+    }
+
+    fn header_block(
+        &mut self,
+        id: u32,
+        block: &[u8],
+        end_stream: bool,
+    ) -> Result<(), ConnectionError> {
+        // Decoded even when the stream is refused, to keep the table in
+        // step.
+        let headers = self
+            .decoder
+            .decode(block)
+            .map_err(|_| ConnectionError::Protocol(COMPRESSION_ERROR))?;
+        if let Some(s) = self.streams.get_mut(&id) {
+            // Trailers; only the end of the stream matters.
+            if s.complete || !end_stream {
+                return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+            }
+            s.complete = true;
+            return self.respond(id);
+        }
+        if id.is_multiple_of(2) || id <= self.last_stream {
+            return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+        }
+        self.last_stream = id;
+        if self.streams.len() >= MAX_STREAMS {
+            return self.reset(id, REFUSED_STREAM);
+        }
+        self.streams.insert(
+            id,
+            H2Stream {
+                headers,
+                body: Vec::new(),
+                complete: end_stream,
+                response: None,
+                send_window: self.initial_window,
+            },
+        );
+        if end_stream {
+            self.respond(id)?;
+        }
+        Ok(())
+    }
+
+    fn data(&mut self, id: u32, flags: u8, payload: Vec<u8>) -> Result<(), ConnectionError> {
+        // Flow control counts padding too; the window is restored at once,
+        // since bodies are bounded anyway.
+        let len = payload.len() as u32;
+        if len > 0 {
+            self.write_frame(WINDOW_UPDATE, 0, 0, &len.to_be_bytes())?;
+        }
+        let data = unpad(flags, payload)?;
+        let s = match self.streams.get_mut(&id) {
+            Some(s) if !s.complete => s,
+            Some(_) => return Err(ConnectionError::Protocol(PROTOCOL_ERROR)),
+            None if id == 0 || id > self.last_stream => {
+                return Err(ConnectionError::Protocol(PROTOCOL_ERROR));
+            }
+            // A stream that was reset or refused.
+            None => return Ok(()),
+        };
+        s.body.extend_from_slice(&data);
+        if s.body.len() > MAX_BODY {
+            s.complete = true;
+            s.body.clear();
+            return self.send_response(id, Response::status(413));
+        }
+        if flags & END_STREAM != 0 {
+            s.complete = true;
+            return self.respond(id);
+        }
+        if len > 0 {
+            self.write_frame(WINDOW_UPDATE, 0, id, &len.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Answers the complete request on stream `id`.
+    fn respond(&mut self, id: u32) -> Result<(), ConnectionError> {
+        let s = &self.streams[&id];
+        let header = |name: &str| {
+            s.headers
+                .iter()
+                .find(|(n, _)| n == name.as_bytes())
+                .map(|(_, v)| v.as_slice())
+        };
+        let method = header(":method").unwrap_or_default();
+        let path = header(":path").unwrap_or_default();
+        let resp = answer(
+            self.frontend,
+            self.src,
+            self.transport,
+            method,
+            path,
+            header("content-type"),
+            s.body.clone(),
+        );
+        match resp {
+            Some(resp) => self.send_response(id, resp),
+            None => self.reset(id, REFUSED_STREAM),
+        }
+    }
+
+    fn send_response(&mut self, id: u32, resp: Response) -> Result<(), ConnectionError> {
+        let status = resp.status.to_string();
+        let length = resp.body.len().to_string();
+        let age = resp.max_age.map(|age| format!("max-age={}", age));
+        let mut headers = vec![(":status", status.as_str())];
+        if resp.status == 200 {
+            headers.push(("content-type", CONTENT_TYPE));
+        }
+        if let Some(age) = &age {
+            headers.push(("cache-control", age));
+        }
+        headers.push(("content-length", &length));
+        let block = hpack::encode(&headers);
+        let flags = if resp.body.is_empty() {
+            END_HEADERS | END_STREAM
+        } else {
+            END_HEADERS
+        };
+        // Blocks this small always fit one frame.
+        self.write_frame(HEADERS, flags, id, &block)?;
+        if resp.body.is_empty() {
+            self.streams.remove(&id);
+        } else if let Some(s) = self.streams.get_mut(&id) {
+            s.response = Some(resp.body);
+        }
+        Ok(())
+    }
+
+    /// Sends as much of the pending response bodies as flow control
+    /// allows.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut ids: Vec<u32> = self
+            .streams
+            .iter()
+            .filter(|(_, s)| s.response.is_some())
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        for id in ids {
+            loop {
+                let s = self.streams.get_mut(&id).unwrap();
+                let body = s.response.as_mut().unwrap();
+                let window = self.send_window.min(s.send_window);
+                if window <= 0 {
+                    break;
+                }
+                let n = body.len().min(window as usize).min(self.max_frame_size);
+                let chunk: Vec<u8> = body.drain(..n).collect();
+                let last = body.is_empty();
+                s.send_window -= n as i64;
+                self.send_window -= n as i64;
+                if last {
+                    self.streams.remove(&id);
+                }
+                self.write_frame(DATA, if last { END_STREAM } else { 0 }, id, &chunk)?;
+                if last {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, id: u32, code: u32) -> Result<(), ConnectionError> {
+        self.streams.remove(&id);
+        self.write_frame(RST_STREAM, 0, id, &code.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn write_frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) -> io::Result<()> {
+        let len = (payload.len() as u32).to_be_bytes();
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&len[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+/// Strips the padding of a DATA or HEADERS frame.
+fn unpad(flags: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, ConnectionError> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let pad = match payload.first() {
+        Some(&pad) if usize::from(pad) < payload.len() => usize::from(pad),
+        _ => return Err(ConnectionError::Protocol(PROTOCOL_ERROR)),
+    };
+    payload.truncate(payload.len() - pad);
+    payload.remove(0);
+    Ok(payload)
+}
@@ -0,0 +1,164 @@
+//! Built-in answers identifying the server: the CHAOS-class TXT names
+//! `version.bind`, `hostname.bind`, `id.server` and `version.server`, and
+//! the NSID EDNS option (RFC 5001). Monitoring of anycast services relies
+//! on them to tell which instance answered.
+
+use crate::message::{EdnsOption, Message, Opcode, OptionCode, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+use crate::sys;
+
+use super::{Handler, Layer, Request};
+
+/// Settings of the [`Identified`] handler. A value of `None` refuses
+/// queries for the names it answers.
+///
+/// Other CHAOS-class queries are passed on to the inner handler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerIdentity {
+    /// Answer to `version.bind` and `version.server`.
+    pub version: Option<String>,
+    /// Answer to `hostname.bind`.
+    pub hostname: Option<String>,
+    /// Answer to `id.server` (RFC 4892).
+    pub id: Option<String>,
+    /// Sent in an NSID option to clients asking for one; `None` sends
+    /// none.
+    pub nsid: Option<Vec<u8>>,
+}
+
+impl Default for ServerIdentity {
+    fn default() -> ServerIdentity {
+        ServerIdentity::new()
+    }
+}
+
+impl ServerIdentity {
+    /// The crate version, and the host name for the other names and NSID.
+    pub fn new() -> ServerIdentity {
+        let hostname = sys::hostname();
+        ServerIdentity {
+            version: Some(format!("mairudns {}", env!("CARGO_PKG_VERSION"))),
+            id: hostname.clone(),
+            nsid: hostname.clone().map(String::into_bytes),
+            hostname,
+        }
+    }
+
+    /// Refuses every name and sends no NSID, for operators who would
+    /// rather not disclose anything.
+    pub fn hidden() -> ServerIdentity {
+        ServerIdentity {
+            version: None,
+            hostname: None,
+            id: None,
+            nsid: None,
+        }
+    }
+
+    /// The answer for a CHAOS-class `name`, or `None` if it is not one of
+    /// the names served here. The inner option is `None` for names that
+    /// are refused.
+    pub fn lookup(&self, name: &DomainName) -> Option<Option<&str>> {
+        let value = if is(name, "version.bind") || is(name, "version.server") {
+            &self.version
+        } else if is(name, "hostname.bind") {
+            &self.hostname
+        } else if is(name, "id.server") {
+            &self.id
+        } else {
+            return None;
+        };
+        Some(value.as_deref())
+    }
+
+    fn answer(&self, request: &Request) -> Option<Message> {
+        let msg = &request.message;
+        if msg.header.opcode != Opcode::QUERY || msg.questions.len() != 1 {
+            return None;
+        }
+        let q = &msg.questions[0];
+        if q.qclass != RecordClass::CH {
+            return None;
+        }
+        let value = self.lookup(&q.name)?;
+        let mut resp = msg.response();
+        match value {
+            Some(text) => {
+                resp.header.aa = true;
+                if q.qtype == RecordType::TXT || q.qtype == RecordType::ANY {
+                    let strings = text.as_bytes().chunks(255).map(<[u8]>::to_vec).collect();
+                    resp.answers.push(Record {
+                        name: q.name.clone(),
+                        class: RecordClass::CH,
+                        ttl: 0,
+                        rdata: RData::Txt(strings),
+                    });
+                }
+            }
+            None => resp.header.rcode = Rcode::REFUSED,
+        }
+        Some(resp)
+    }
+
+    /// Adds the NSID option to `resp` if the request asked for it (RFC
+    /// 5001 §2.1) and the response carries EDNS.
+    pub fn add_nsid(&self, request: &Request, resp: &mut Message) {
+        let nsid = match &self.nsid {
+            Some(nsid) => nsid,
+            None => return,
+        };
+        let asked = request
+            .message
+            .edns
+            .as_ref()
+            .and_then(|e| e.option(OptionCode::NSID))
+            .is_some_and(|o| o.data.is_empty());
+        if let Some(edns) = resp.edns.as_mut().filter(|_| asked) {
+            if edns.option(OptionCode::NSID).is_none() {
+                edns.options.push(EdnsOption {
+                    code: OptionCode::NSID,
+                    data: nsid.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn is(name: &DomainName, expected: &str) -> bool {
+    expected.parse::<DomainName>().is_ok_and(|e| *name == e)
+}
+
+impl<H: Handler> Layer<H> for ServerIdentity {
+    type Handler = Identified<H>;
+
+    fn layer(&self, inner: H) -> Identified<H> {
+        Identified {
+            config: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler answering identity queries itself; see [`ServerIdentity`].
+pub struct Identified<H> {
+    config: ServerIdentity,
+    inner: H,
+}
+
+impl<H> Identified<H> {
+    pub fn config(&self) -> &ServerIdentity {
+        &self.config
+    }
+}
+
+impl<H: Handler> Handler for Identified<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let mut resp = match self.config.answer(request) {
+            Some(resp) => resp,
+            None => self.inner.handle(request)?,
+        };
+        self.config.add_nsid(request, &mut resp);
+        Some(resp)
+    }
+}
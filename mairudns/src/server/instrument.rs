@@ -0,0 +1,73 @@
+//! Recording requests and zone transfers into [`Metrics`].
+
+use std::sync::Arc;
+
+use crate::message::{Message, Rcode};
+use crate::metrics::{Metrics, QueryKey};
+use crate::rr::RecordType;
+
+use super::{Authority, Handler, Layer, Request};
+
+/// Settings of the [`Instrumented`] handler.
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    pub metrics: Arc<Metrics>,
+    /// Used to count requests per zone; without it they are not.
+    pub authority: Option<Arc<Authority>>,
+}
+
+impl Instrument {
+    pub fn new(metrics: Arc<Metrics>) -> Instrument {
+        Instrument {
+            metrics,
+            authority: None,
+        }
+    }
+}
+
+impl<H: Handler> Layer<H> for Instrument {
+    type Handler = Instrumented<H>;
+
+    fn layer(&self, inner: H) -> Instrumented<H> {
+        Instrumented {
+            config: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler whose requests are counted; see [`Instrument`].
+pub struct Instrumented<H> {
+    config: Instrument,
+    inner: H,
+}
+
+impl<H> Instrumented<H> {
+    pub fn config(&self) -> &Instrument {
+        &self.config
+    }
+}
+
+impl<H: Handler> Handler for Instrumented<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let resp = self.inner.handle(request);
+        let question = request.message.question();
+        let zone = match (&self.config.authority, question) {
+            (Some(authority), Some(q)) => authority.zone_origin(&q.name),
+            _ => None,
+        };
+        let key = QueryKey {
+            protocol: request.protocol,
+            qtype: question.map_or(RecordType(0), |q| q.qtype),
+            rcode: resp.as_ref().map(|r| r.header.rcode),
+        };
+        let metrics = &self.config.metrics;
+        metrics.record_query(key, request.src.ip(), zone.as_ref());
+        if request.is_transfer() && key.rcode == Some(Rcode::NOERROR) {
+            if let Some(q) = question {
+                metrics.record_transfer(&q.name, q.qtype);
+            }
+        }
+        resp
+    }
+}
@@ -0,0 +1,179 @@
+//! Middleware: wrapping one [`Handler`] in another.
+//!
+//! A [`Layer`] turns a handler into a handler that does something around
+//! it — logging, access control, rate limiting, caching — so that these
+//! concerns compose instead of growing inside the server loop:
+//!
+//! ```no_run
+//! use mairudns::server::{from_fn, Builder, Handler, Request, Server};
+//!
+//! let inner = |req: &Request| Some(req.message.response());
+//! let handler = Builder::new()
+//!     .layer(from_fn(|req: &Request, next: &dyn Handler| {
+//!         eprintln!("{} asked {}", req.src, req.message.questions[0]);
+//!         next.handle(req)
+//!     }))
+//!     .handler(inner);
+//! let mut server = Server::new(handler);
+//! ```
+
+use std::sync::Arc;
+
+use crate::message::Message;
+
+use super::{Handler, Request};
+
+/// Wraps a handler of type `H`.
+pub trait Layer<H: Handler> {
+    type Handler: Handler;
+
+    fn layer(&self, inner: H) -> Self::Handler;
+}
+
+impl<H: Handler + ?Sized> Handler for Arc<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        (**self).handle(request)
+    }
+}
+
+impl Handler for Box<dyn Handler> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        (**self).handle(request)
+    }
+}
+
+/// Wraps a handler with `layer`, method style.
+pub trait HandlerExt: Handler + Sized {
+    fn with<L: Layer<Self>>(self, layer: L) -> L::Handler {
+        layer.layer(self)
+    }
+}
+
+impl<H: Handler> HandlerExt for H {}
+
+/// The layer that does nothing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Identity;
+
+impl<H: Handler> Layer<H> for Identity {
+    type Handler = H;
+
+    fn layer(&self, inner: H) -> H {
+        inner
+    }
+}
+
+/// Two layers applied in turn: `inner` first, then `outer` around it.
+#[derive(Clone, Debug)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<Inner, Outer> Stack<Inner, Outer> {
+    pub fn new(inner: Inner, outer: Outer) -> Stack<Inner, Outer> {
+        Stack { inner, outer }
+    }
+}
+
+impl<H, Inner, Outer> Layer<H> for Stack<Inner, Outer>
+where
+    H: Handler,
+    Inner: Layer<H>,
+    Outer: Layer<Inner::Handler>,
+{
+    type Handler = Outer::Handler;
+
+    fn layer(&self, inner: H) -> Outer::Handler {
+        self.outer.layer(self.inner.layer(inner))
+    }
+}
+
+/// Collects layers to wrap a handler in. Layers added first see requests
+/// first.
+#[derive(Clone, Debug)]
+pub struct Builder<L> {
+    layer: L,
+}
+
+impl Builder<Identity> {
+    pub fn new() -> Builder<Identity> {
+        Builder { layer: Identity }
+    }
+}
+
+impl Default for Builder<Identity> {
+    fn default() -> Builder<Identity> {
+        Builder::new()
+    }
+}
+
+impl<L> Builder<L> {
+    /// Adds a layer inside those already added.
+    pub fn layer<T>(self, layer: T) -> Builder<Stack<T, L>> {
+        Builder {
+            layer: Stack::new(layer, self.layer),
+        }
+    }
+
+    /// Wraps `handler` in every layer.
+    pub fn handler<H>(&self, handler: H) -> L::Handler
+    where
+        H: Handler,
+        L: Layer<H>,
+    {
+        self.layer.layer(handler)
+    }
+}
+
+/// A layer from a closure that receives each request along with the
+/// handler it wraps; see [`from_fn`].
+pub struct FnLayer<F> {
+    f: Arc<F>,
+}
+
+impl<F> Clone for FnLayer<F> {
+    fn clone(&self) -> FnLayer<F> {
+        FnLayer { f: self.f.clone() }
+    }
+}
+
+/// Middleware from a closure. The closure decides whether and how to call
+/// the wrapped handler, and may change the request or the response.
+pub fn from_fn<F>(f: F) -> FnLayer<F>
+where
+    F: Fn(&Request, &dyn Handler) -> Option<Message> + Send + Sync + 'static,
+{
+    FnLayer { f: Arc::new(f) }
+}
+
+impl<H, F> Layer<H> for FnLayer<F>
+where
+    H: Handler,
+    F: Fn(&Request, &dyn Handler) -> Option<Message> + Send + Sync + 'static,
+{
+    type Handler = FnHandler<F, H>;
+
+    fn layer(&self, inner: H) -> FnHandler<F, H> {
+        FnHandler {
+            f: self.f.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler wrapped by an [`FnLayer`].
+pub struct FnHandler<F, H> {
+    f: Arc<F>,
+    inner: H,
+}
+
+impl<F, H> Handler for FnHandler<F, H>
+where
+    H: Handler,
+    F: Fn(&Request, &dyn Handler) -> Option<Message> + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Option<Message> {
+        (self.f)(request, &self.inner)
+    }
+}
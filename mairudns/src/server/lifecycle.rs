@@ -0,0 +1,374 @@
+//! Controlling a running server: replacing its handler and keys, opening
+//! and closing listeners, shutting it down, and taking over sockets from a
+//! service manager.
+
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, UdpSocket,
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::client::Protocol;
+use crate::sys::{self, Adopted, Reuse};
+use crate::tsig::Keyring;
+
+use super::{Handler, QuicEndpoint};
+
+/// Starts serving a socket bound while the server runs, until the flag
+/// given with it is cleared.
+pub(super) type Spawn = Arc<dyn Fn(Socket, Arc<AtomicBool>) -> io::Result<()> + Send + Sync>;
+
+/// A socket being served, which [`Control::close`] can stop.
+struct Served {
+    protocol: Protocol,
+    addr: SocketAddr,
+    open: Arc<AtomicBool>,
+}
+
+/// State shared between a server's threads and its [`Control`] handles.
+pub(super) struct State {
+    handler: RwLock<Arc<dyn Handler>>,
+    keys: RwLock<Keyring>,
+    stopping: AtomicBool,
+    /// The TCP listeners, whose threads shutdown wakes from `accept()`.
+    listeners: Mutex<Vec<TcpListener>>,
+    served: Mutex<Vec<Served>>,
+    /// Set once the server runs.
+    spawn: Mutex<Option<Spawn>>,
+    endpoints: Mutex<Vec<Arc<dyn QuicEndpoint>>>,
+    connections: Mutex<HashMap<usize, TcpStream>>,
+    next_connection: AtomicUsize,
+    /// Requests being handled.
+    active: AtomicUsize,
+}
+
+impl State {
+    pub(super) fn new(handler: Arc<dyn Handler>) -> State {
+        State {
+            handler: RwLock::new(handler),
+            keys: RwLock::new(Keyring::new()),
+            stopping: AtomicBool::new(false),
+            listeners: Mutex::new(Vec::new()),
+            served: Mutex::new(Vec::new()),
+            spawn: Mutex::new(None),
+            endpoints: Mutex::new(Vec::new()),
+            connections: Mutex::new(HashMap::new()),
+            next_connection: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn handler(&self) -> Arc<dyn Handler> {
+        self.handler.read().unwrap().clone()
+    }
+
+    pub(super) fn keys(&self) -> &RwLock<Keyring> {
+        &self.keys
+    }
+
+    pub(super) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn add_listener(&self, listener: &TcpListener) -> io::Result<()> {
+        let clone = listener.try_clone()?;
+        self.listeners.lock().unwrap().push(clone);
+        Ok(())
+    }
+
+    /// Notes that the socket bound to `addr` is served, returning the flag
+    /// its threads stop at once cleared.
+    pub(super) fn serve(&self, protocol: Protocol, addr: SocketAddr) -> Arc<AtomicBool> {
+        let open = Arc::new(AtomicBool::new(true));
+        self.served.lock().unwrap().push(Served {
+            protocol,
+            addr,
+            open: open.clone(),
+        });
+        open
+    }
+
+    pub(super) fn set_spawn(&self, spawn: Spawn) {
+        *self.spawn.lock().unwrap() = Some(spawn);
+    }
+
+    /// Serves `socket` as the sockets bound before the server ran are.
+    fn start(&self, socket: Socket) -> io::Result<SocketAddr> {
+        let spawn = self.spawn.lock().unwrap().clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "the server is not running")
+        })?;
+        let (protocol, addr) = match &socket {
+            Socket::Udp(s) => (Protocol::Udp, s.local_addr()?),
+            Socket::Tcp(l) => (Protocol::Tcp, l.local_addr()?),
+        };
+        spawn(socket, self.serve(protocol, addr))?;
+        Ok(addr)
+    }
+
+    /// Stops serving the sockets bound to `addr`, returning how many there
+    /// were.
+    fn close(&self, addr: SocketAddr) -> usize {
+        let mut served = self.served.lock().unwrap();
+        let before = served.len();
+        served.retain(|s| {
+            if s.addr != addr {
+                return true;
+            }
+            s.open.store(false, Ordering::SeqCst);
+            false
+        });
+        let closed = before - served.len();
+        drop(served);
+        self.listeners.lock().unwrap().retain(|l| {
+            if l.local_addr().ok() != Some(addr) {
+                return true;
+            }
+            wake(l);
+            false
+        });
+        closed
+    }
+
+    pub(super) fn add_endpoint(&self, endpoint: Arc<dyn QuicEndpoint>) {
+        self.endpoints.lock().unwrap().push(endpoint);
+    }
+
+    /// Marks a request as being handled until the guard is dropped.
+    pub(super) fn begin_request(self: &Arc<State>) -> ActiveRequest {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ActiveRequest(self.clone())
+    }
+
+    /// Tracks an open connection until the guard is dropped, so that
+    /// shutdown can stop it from reading further queries.
+    pub(super) fn track(self: &Arc<State>, stream: &TcpStream) -> TrackedConnection {
+        let id = self.next_connection.fetch_add(1, Ordering::SeqCst);
+        if let Ok(clone) = stream.try_clone() {
+            self.connections.lock().unwrap().insert(id, clone);
+        }
+        // Shutdown may have swept the connections before this one was
+        // added.
+        if self.is_stopping() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        TrackedConnection {
+            state: self.clone(),
+            id,
+        }
+    }
+
+    /// Whether requests are still being handled or connections are still
+    /// open.
+    pub(super) fn is_busy(&self) -> bool {
+        self.active.load(Ordering::SeqCst) > 0 || !self.connections.lock().unwrap().is_empty()
+    }
+
+    /// Closes the connections still open after the drain deadline.
+    pub(super) fn abort_connections(&self) {
+        for stream in self.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn shutdown(&self) {
+        if self.stopping.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for listener in self.listeners.lock().unwrap().iter() {
+            wake(listener);
+        }
+        for endpoint in self.endpoints.lock().unwrap().iter() {
+            endpoint.close();
+        }
+        // Requests being read or answered complete; no more are read.
+        for stream in self.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+}
+
+/// Wakes the thread of `listener` from `accept()`.
+fn wake(listener: &TcpListener) {
+    if sys::interrupt_accept(listener).is_ok() {
+        return;
+    }
+    // Connecting wakes one thread, and may reach another listener sharing
+    // the port.
+    if let Ok(addr) = listener.local_addr() {
+        let _ = TcpStream::connect_timeout(&wake_addr(addr), Duration::from_secs(1));
+    }
+}
+
+/// The address to connect to to reach a listener bound to `addr`.
+fn wake_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, addr.port()).into(),
+        _ => addr,
+    }
+}
+
+pub(super) struct ActiveRequest(Arc<State>);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(super) struct TrackedConnection {
+    state: Arc<State>,
+    id: usize,
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        self.state.connections.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// A handle on a server, usable from any thread while it runs.
+///
+/// Replacing the handler or keys applies to requests that arrive
+/// afterwards; sockets stay open throughout, so reloading configuration
+/// or zones this way loses no queries. Listeners can be opened and closed
+/// the same way, leaving the others alone.
+#[derive(Clone)]
+pub struct Control {
+    state: Arc<State>,
+}
+
+impl Control {
+    pub(super) fn new(state: Arc<State>) -> Control {
+        Control { state }
+    }
+
+    pub fn set_handler<H: Handler>(&self, handler: H) {
+        *self.state.handler.write().unwrap() = Arc::new(handler);
+    }
+
+    pub fn set_keyring(&self, keys: Keyring) {
+        *self.state.keys.write().unwrap() = keys;
+    }
+
+    /// Listens on `addr` over both UDP and TCP, as
+    /// [`Server::listen`](super::Server::listen) does before the server
+    /// runs. Fails with [`NotConnected`](io::ErrorKind::NotConnected) until
+    /// [`Server::run`](super::Server::run) is called.
+    pub fn listen(&self, addr: SocketAddr) -> io::Result<()> {
+        let udp = self.listen_udp(addr)?;
+        if let Err(e) = self.listen_tcp(udp) {
+            self.close(udp);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Listens on `addr` over UDP, returning the bound address.
+    pub fn listen_udp(&self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let socket = sys::bind_udp(addr, Reuse::default())?;
+        self.state.start(Socket::Udp(socket))
+    }
+
+    /// Listens on `addr` over TCP, returning the bound address.
+    pub fn listen_tcp(&self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let reuse = Reuse {
+            addr: true,
+            ..Reuse::default()
+        };
+        self.state.start(Socket::Tcp(sys::bind_tcp(addr, reuse)?))
+    }
+
+    /// Stops listening on `addr`, over UDP and TCP alike, and returns how
+    /// many sockets were closed. UDP sockets close when their threads next
+    /// poll, within a fraction of a second; connections accepted before
+    /// are served until they end. XDP and QUIC listeners are left alone.
+    pub fn close(&self, addr: SocketAddr) -> usize {
+        self.state.close(addr)
+    }
+
+    /// The addresses listened on, with their protocols, apart from XDP and
+    /// QUIC listeners.
+    pub fn listening(&self) -> Vec<(Protocol, SocketAddr)> {
+        self.state
+            .served
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| (s.protocol, s.addr))
+            .collect()
+    }
+
+    /// Stops the server: listeners stop accepting, connections stop
+    /// reading, and [`Server::run`](super::Server::run) returns once the
+    /// requests under way are answered or the drain timeout has passed.
+    pub fn shutdown(&self) {
+        self.state.shutdown();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.is_stopping()
+    }
+}
+
+/// A socket passed by a service manager.
+#[derive(Debug)]
+pub enum Socket {
+    Udp(UdpSocket),
+    /// A listening TCP socket.
+    Tcp(TcpListener),
+}
+
+/// A socket passed by a service manager, with the name it was given.
+#[derive(Debug)]
+pub struct Activated {
+    /// The name from `LISTEN_FDNAMES`, e.g. `FileDescriptorName=` of a
+    /// systemd socket unit.
+    pub name: Option<String>,
+    pub socket: Socket,
+}
+
+/// The sockets passed with systemd's socket activation protocol
+/// (`LISTEN_PID`, `LISTEN_FDS`, `LISTEN_FDNAMES`), starting at descriptor
+/// 3. The variables are removed so that child processes do not take the
+/// sockets too. Descriptors that are neither UDP sockets nor listening TCP
+/// sockets are an error.
+///
+/// Without the variables, or if they are meant for another process, there
+/// are no sockets.
+pub fn activated_sockets() -> io::Result<Vec<Activated>> {
+    const FIRST_FD: i32 = 3;
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|c| c.parse::<i32>().ok());
+    let names = env::var("LISTEN_FDNAMES").ok();
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+    let count = match (pid, count) {
+        (Some(pid), Some(count)) if pid == std::process::id() && count > 0 => count,
+        _ => return Ok(Vec::new()),
+    };
+    let mut names = names
+        .as_deref()
+        .map(|n| n.split(':').map(str::to_string).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter();
+    let mut sockets = Vec::new();
+    for fd in FIRST_FD..FIRST_FD + count {
+        let name = names.next().filter(|n| !n.is_empty());
+        let socket = match sys::adopt(fd)? {
+            Adopted::Udp(s) => Socket::Udp(s),
+            Adopted::Tcp(l) => Socket::Tcp(l),
+        };
+        sockets.push(Activated { name, socket });
+    }
+    Ok(sockets)
+}
@@ -0,0 +1,1356 @@
+//! A DNS server frontend: UDP and TCP listeners that decode queries and
+//! pass them to a [`Handler`].
+//!
+//! Each UDP socket is served by a few threads sharing it, or, with
+//! [`Server::listen_workers`], by one thread per `SO_REUSEPORT` socket;
+//! each TCP connection gets a thread of its own. TCP follows RFC 7766:
+//! messages are length-prefixed, several queries may be sent on one
+//! connection, and idle connections are closed after a timeout.
+//!
+//! Requests signed with TSIG are verified against the server's
+//! [`Keyring`] before they reach the handler, and their responses are
+//! signed with the same key. Zone transfer responses are sent over TCP as
+//! a stream of messages.
+//!
+//! With the `uring` feature on Linux, UDP sockets can instead be served
+//! through io_uring, as the [`UdpBackend`] chosen says.
+//!
+//! With the `xdp` feature on Linux, `Server::listen_xdp` adds an
+//! experimental AF_XDP fast path answering queries from the cache of a
+//! [`Forwarder`] without going through the kernel's network stack.
+//!
+//! An [`AnswerOrdering`] rotates or shuffles the records of answers and
+//! sorts addresses by the client's preferences.
+//!
+//! A [`ClientQuota`] holds each client network of a resolver to a rate
+//! of queries and a number in progress at once.
+//!
+//! TCP and TLS connections may become DNS Stateful Operations sessions
+//! (RFC 8490): a client's Keepalive request is answered with the timeouts
+//! set by [`Server::set_dso`], which then replace the idle timeout of the
+//! connection. With a [`PushPolicy`], sessions also take DNS Push
+//! Notification subscriptions (RFC 8765).
+//!
+//! A [`Classify`] layer describes each request it samples, including
+//! whether a cache answered it and how many upstream queries it took, to
+//! [`Sampler`]s such as [`RecentQueries`].
+//!
+//! A [`ResponsePolicy`] decides how large UDP responses may be, what is
+//! left out when they do not fit, and how encrypted responses are padded.
+//!
+//! TCP-based listeners behind a load balancer can take the PROXY protocol
+//! header it sends to learn the real client addresses.
+//!
+//! Encrypted listeners serve the same handler over DNS over TLS, DNS over
+//! HTTPS and DNS over QUIC. The crate implements neither TLS nor QUIC:
+//! the application supplies a [`TlsAcceptor`] or [`QuicEndpoint`] built on
+//! a library of its choice.
+//!
+//! A running server is managed through its [`Control`] handle: the handler
+//! and keys can be replaced without closing any socket, and shutdown lets
+//! requests under way finish within a deadline. Sockets inherited through
+//! systemd's socket activation can be served in place of bound ones. On
+//! Unix, operators reach a running server through the [`remote`] control
+//! channel; with the `api` feature, zones, records and the cache can also
+//! be managed over HTTP through the [`api`].
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{read_framed, write_framed, Protocol};
+use crate::clock::{self, Clock};
+use crate::compliance::Compliance;
+use crate::dso::{is_dso, Keepalive, MIN_KEEPALIVE_INTERVAL};
+use crate::message::{Header, Message, Opcode, OptionCode, Rcode};
+use crate::metrics::{self, Metrics};
+use crate::name::DomainName;
+use crate::rr::{Record, RecordType};
+use crate::sys::{self, Reuse};
+use crate::tsig::{self, Keyring};
+
+use self::dso::Session;
+use self::lifecycle::{Spawn, State};
+use crate::wire::Encoder;
+
+mod acl;
+#[cfg(feature = "api")]
+pub mod api;
+mod authority;
+mod cache;
+mod classify;
+mod dso;
+mod forward;
+mod health;
+mod hpack;
+mod https;
+mod identity;
+mod instrument;
+mod layer;
+mod lifecycle;
+mod order;
+mod plugin;
+mod proxy;
+mod querylog;
+mod quic;
+mod quota;
+#[cfg(unix)]
+pub mod remote;
+mod rrl;
+#[cfg(feature = "script")]
+mod script;
+mod size;
+mod snapshot;
+mod table;
+mod tls;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+mod views;
+#[cfg(all(feature = "xdp", target_os = "linux"))]
+mod xdp;
+pub use self::acl::{AccessControl, AccessControlled, Acl, AclAction};
+pub use self::authority::{Authority, SharedZone, TransferAcl};
+pub use self::cache::{CacheUsage, Security};
+pub use self::classify::{Classified, Classify, QueryClass, RecentQueries, Sampler, Topology};
+pub use self::dso::PushPolicy;
+pub use self::forward::{
+    CachePolicy, CachedResponse, Fallthrough, Forwarder, NegativeCaching, Route, SharedForwarder,
+};
+pub use self::health::{Balanced, Pool, Pools, Probe, Selection, Target, TargetStatus};
+pub use self::https::DOH_PATH;
+pub use self::identity::{Identified, ServerIdentity};
+pub use self::instrument::{Instrument, Instrumented};
+pub use self::layer::{from_fn, Builder, FnHandler, FnLayer, HandlerExt, Identity, Layer, Stack};
+pub use self::lifecycle::{activated_sockets, Activated, Control, Socket};
+pub use self::order::{AnswerOrder, AnswerOrdering, Ordered, SortlistEntry};
+pub use self::plugin::{Plugin, Plugins, WithPlugins};
+pub use self::querylog::{Logged, QueryLog};
+pub use self::quic::{
+    QuicConnection, QuicEndpoint, DOQ_EXCESSIVE_LOAD, DOQ_INTERNAL_ERROR, DOQ_NO_ERROR,
+    DOQ_PROTOCOL_ERROR,
+};
+pub use self::quota::{ClientQuota, QuotaAction, QuotaLimited};
+pub use self::rrl::{RateLimit, RateLimited, ResponseKind};
+#[cfg(feature = "script")]
+pub use self::script::{ScriptError, ScriptPlugin};
+pub use self::size::{ResponsePolicy, MAX_UDP_SIZE, PADDING_BLOCK};
+pub use self::tls::{
+    Accepted, CertifiedKey, KeyFormat, PemError, Reloadable, Stream, TlsAcceptor, ALPN_DOQ,
+    ALPN_DOT, ALPN_H2, ALPN_HTTP1,
+};
+pub use self::views::{View, Views};
+
+/// The largest UDP response sent to clients without EDNS (RFC 1035 §4.2.1).
+pub const MIN_UDP_SIZE: usize = 512;
+
+/// How long an idle TCP connection is kept open (RFC 7766 §6.2.3).
+pub const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default limit on concurrently open TCP connections.
+pub const MAX_TCP_CONNECTIONS: usize = 256;
+
+/// Default number of threads serving each UDP socket.
+pub const UDP_THREADS: usize = 4;
+
+/// Default time allowed on shutdown for requests under way to be answered.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often UDP threads check whether the server is shutting down.
+const UDP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Datagrams received and answered per system call where batching is
+/// available.
+const UDP_BATCH: usize = 8;
+
+/// Default limit on zone transfers sent at the same time.
+pub const MAX_TRANSFERS: usize = 10;
+
+/// Approximate size of each message of a zone transfer.
+const TRANSFER_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// How UDP sockets are served.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UdpBackend {
+    /// Blocking threads, receiving and sending in batches where the
+    /// system allows.
+    #[default]
+    Threads,
+    /// io_uring, with multishot receive into buffers registered with the
+    /// kernel. Needs Linux 6.0 and the `uring` feature; elsewhere the
+    /// server falls back to threads.
+    IoUring,
+}
+
+impl UdpBackend {
+    /// Whether the backend can be used here; for io_uring, this tries it
+    /// on a loopback socket the first time.
+    pub fn is_available(self) -> bool {
+        match self {
+            UdpBackend::Threads => true,
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            UdpBackend::IoUring => uring::supported(),
+            #[cfg(not(all(feature = "uring", target_os = "linux")))]
+            UdpBackend::IoUring => false,
+        }
+    }
+}
+
+/// A decoded query and where it came from.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub message: Message,
+    pub src: SocketAddr,
+    pub protocol: Protocol,
+    /// The TSIG key the request was signed with, set only once the
+    /// signature has been verified.
+    pub key: Option<DomainName>,
+}
+
+impl Request {
+    /// The largest response the client can receive: the EDNS UDP payload
+    /// size (at least 512) over UDP, the frame limit over TCP and TLS.
+    pub fn max_response_size(&self) -> usize {
+        match self.protocol {
+            Protocol::Tcp | Protocol::Tls => usize::from(u16::MAX),
+            Protocol::Udp => self
+                .message
+                .edns
+                .as_ref()
+                .map_or(MIN_UDP_SIZE, |e| usize::from(e.udp_size).max(MIN_UDP_SIZE)),
+        }
+    }
+
+    /// Whether this is an AXFR or IXFR request.
+    pub fn is_transfer(&self) -> bool {
+        self.message
+            .question()
+            .is_some_and(|q| q.qtype == RecordType::AXFR || q.qtype == RecordType::IXFR)
+    }
+}
+
+/// Answers requests. Handlers are shared between all server threads and
+/// can be wrapped in middleware with [`Layer`]s.
+pub trait Handler: Send + Sync + 'static {
+    /// The response to `request`, or `None` to send nothing.
+    fn handle(&self, request: &Request) -> Option<Message>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Option<Message> + Send + Sync + 'static,
+{
+    fn handle(&self, request: &Request) -> Option<Message> {
+        self(request)
+    }
+}
+
+/// Limits of one encrypted listener, or of a TCP listener given its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Connections beyond this many are closed as soon as they are
+    /// accepted.
+    pub max_connections: usize,
+    /// How long a connection may stay idle, or take to complete its
+    /// handshake.
+    pub idle_timeout: Duration,
+    /// Connections open with a PROXY protocol v2 header, whose client
+    /// address stands in for the peer's; those without one are closed.
+    /// For listeners behind a load balancer.
+    pub proxy_protocol: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_connections: MAX_TCP_CONNECTIONS,
+            idle_timeout: TCP_IDLE_TIMEOUT,
+            proxy_protocol: false,
+        }
+    }
+}
+
+/// What runs on the connections of a TCP listener.
+#[derive(Clone)]
+enum Service {
+    /// DNS over TCP.
+    Dns,
+    /// DNS over TLS.
+    Tls(Arc<dyn TlsAcceptor>),
+    /// DNS over HTTPS, HTTP/2 or HTTP/1.1 as negotiated with ALPN.
+    Https(Arc<dyn TlsAcceptor>),
+    /// DNS over cleartext HTTP, HTTP/2 with prior knowledge or HTTP/1.1.
+    Http,
+    /// Metrics in the Prometheus text format over HTTP/1.1.
+    Metrics(Arc<Metrics>),
+    /// The management API over HTTP/1.1.
+    #[cfg(feature = "api")]
+    Api(Arc<api::Api>),
+}
+
+/// UDP, TCP and encrypted listeners sharing one handler.
+pub struct Server {
+    state: Arc<State>,
+    udp: Vec<UdpSocket>,
+    /// `SO_REUSEPORT` sockets, each served by one thread.
+    workers: Vec<UdpSocket>,
+    pin_workers: bool,
+    tcp: Vec<TcpListener>,
+    streams: Vec<(TcpListener, Service, Limits)>,
+    quic: Vec<(Arc<dyn QuicEndpoint>, Limits)>,
+    tcp_idle_timeout: Duration,
+    max_tcp_connections: usize,
+    max_transfers: usize,
+    udp_threads: usize,
+    udp_backend: UdpBackend,
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    xdp: Vec<xdp::Listener>,
+    drain_timeout: Duration,
+    response_policy: ResponsePolicy,
+    compliance: Compliance,
+    clock: Arc<dyn Clock>,
+    dso: Option<Keepalive>,
+    push: Option<PushPolicy>,
+}
+
+impl Server {
+    pub fn new<H: Handler>(handler: H) -> Server {
+        Server {
+            state: Arc::new(State::new(Arc::new(handler))),
+            udp: Vec::new(),
+            workers: Vec::new(),
+            pin_workers: false,
+            tcp: Vec::new(),
+            streams: Vec::new(),
+            quic: Vec::new(),
+            tcp_idle_timeout: TCP_IDLE_TIMEOUT,
+            max_tcp_connections: MAX_TCP_CONNECTIONS,
+            max_transfers: MAX_TRANSFERS,
+            udp_threads: UDP_THREADS,
+            udp_backend: UdpBackend::Threads,
+            #[cfg(all(feature = "xdp", target_os = "linux"))]
+            xdp: Vec::new(),
+            drain_timeout: DRAIN_TIMEOUT,
+            response_policy: ResponsePolicy::default(),
+            compliance: Compliance::default(),
+            clock: clock::system(),
+            dso: Some(Keepalive::default()),
+            push: None,
+        }
+    }
+
+    /// The TSIG keys requests may be signed with. Requests signed with
+    /// any other key are answered with NOTAUTH/BADKEY.
+    pub fn set_keyring(&mut self, keys: Keyring) {
+        self.control().set_keyring(keys);
+    }
+
+    /// A handle to reload or stop the server once it runs.
+    pub fn control(&self) -> Control {
+        Control::new(self.state.clone())
+    }
+
+    /// Listens on `addr` over both UDP and TCP. IPv6 sockets are IPv6-only,
+    /// so `0.0.0.0:53` and `[::]:53` can both be bound.
+    ///
+    /// With port 0, TCP takes the port UDP was given; should something
+    /// already hold that port over TCP, both are bound again on another.
+    pub fn listen(&mut self, addr: SocketAddr) -> io::Result<()> {
+        let mut attempts = if addr.port() == 0 { 8 } else { 1 };
+        loop {
+            let udp = self.listen_udp(addr)?;
+            match self.listen_tcp(udp) {
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempts > 1 => {
+                    self.udp.pop();
+                    attempts -= 1;
+                }
+                result => return result.map(drop),
+            }
+        }
+    }
+
+    /// Listens on `addr` over UDP, returning the bound address.
+    pub fn listen_udp(&mut self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let socket = sys::bind_udp(addr, Reuse::default())?;
+        let local = socket.local_addr()?;
+        self.udp.push(socket);
+        Ok(local)
+    }
+
+    /// Listens on each of `addrs` over UDP as
+    /// [`listen_udp`](Server::listen_udp) does, with an experimental
+    /// AF_XDP fast path on receive queues `0..queues` of `interface`: A and
+    /// AAAA queries the cache of the forwarder `forwarder` holds at the
+    /// time answers are answered straight from the frames they arrived in,
+    /// bypassing the kernel's network stack and the handler. Other queries
+    /// are handled as on any UDP listener. An interface takes one such
+    /// listener, and no address may have port 0. Needs the `xdp` feature,
+    /// Linux 5.9, and `CAP_NET_ADMIN` with `CAP_BPF`.
+    #[cfg(all(feature = "xdp", target_os = "linux"))]
+    pub fn listen_xdp(
+        &mut self,
+        addrs: &[SocketAddr],
+        interface: &str,
+        queues: u32,
+        forwarder: SharedForwarder,
+    ) -> io::Result<()> {
+        if addrs.iter().any(|a| a.port() == 0) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "an XDP listener needs a port",
+            ));
+        }
+        let udp = addrs
+            .iter()
+            .map(|&addr| sys::bind_udp(addr, Reuse::default()))
+            .collect::<io::Result<Vec<_>>>()?;
+        let clones = udp
+            .iter()
+            .map(UdpSocket::try_clone)
+            .collect::<io::Result<Vec<_>>>()?;
+        self.xdp
+            .push(xdp::Listener::new(clones, interface, queues, forwarder)?);
+        self.udp.extend(udp);
+        Ok(())
+    }
+
+    /// Listens on `addr` over TCP, returning the bound address.
+    pub fn listen_tcp(&mut self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let reuse = Reuse {
+            addr: true,
+            ..Reuse::default()
+        };
+        let listener = sys::bind_tcp(addr, reuse)?;
+        let local = listener.local_addr()?;
+        self.tcp.push(listener);
+        Ok(local)
+    }
+
+    /// Listens on `addr` over TCP with limits of its own instead of the
+    /// server's, returning the bound address; e.g. to take PROXY protocol
+    /// headers from a load balancer.
+    pub fn listen_tcp_with(&mut self, addr: SocketAddr, limits: Limits) -> io::Result<SocketAddr> {
+        self.listen_stream(addr, Service::Dns, limits)
+    }
+
+    /// Listens on `addr` with `workers` UDP sockets and as many TCP
+    /// listeners, all bound with `SO_REUSEPORT` so that the kernel spreads
+    /// clients over them. Each UDP socket is served by a thread of its
+    /// own, which avoids contention on a single socket. Returns the bound
+    /// address.
+    pub fn listen_workers(&mut self, addr: SocketAddr, workers: usize) -> io::Result<SocketAddr> {
+        let reuse = Reuse {
+            addr: true,
+            port: true,
+        };
+        let first = sys::bind_udp(addr, reuse)?;
+        // With port 0, the others take the port the first was given.
+        let local = first.local_addr()?;
+        let mut udp = vec![first];
+        let mut tcp = Vec::new();
+        for _ in 1..workers.max(1) {
+            udp.push(sys::bind_udp(local, reuse)?);
+        }
+        for _ in 0..workers.max(1) {
+            tcp.push(sys::bind_tcp(local, reuse)?);
+        }
+        self.workers.extend(udp);
+        self.tcp.extend(tcp);
+        Ok(local)
+    }
+
+    /// Pins the thread of each [`listen_workers`](Server::listen_workers)
+    /// socket to a CPU of its own, in turn. Only supported on Linux.
+    pub fn set_pin_workers(&mut self, pin: bool) {
+        self.pin_workers = pin;
+    }
+
+    /// Serves DNS on an already bound UDP socket.
+    pub fn adopt_udp(&mut self, socket: UdpSocket) {
+        self.udp.push(socket);
+    }
+
+    /// Serves DNS on an already listening TCP socket.
+    pub fn adopt_tcp(&mut self, listener: TcpListener) {
+        self.tcp.push(listener);
+    }
+
+    /// Serves DNS on every socket from [`activated_sockets`], returning
+    /// their addresses. Callers that need some of them for encrypted
+    /// listeners pass those to the `adopt_*` methods instead.
+    pub fn listen_activated(&mut self) -> io::Result<Vec<(Protocol, SocketAddr)>> {
+        let mut addrs = Vec::new();
+        for activated in activated_sockets()? {
+            match activated.socket {
+                Socket::Udp(socket) => {
+                    addrs.push((Protocol::Udp, socket.local_addr()?));
+                    self.adopt_udp(socket);
+                }
+                Socket::Tcp(listener) => {
+                    addrs.push((Protocol::Tcp, listener.local_addr()?));
+                    self.adopt_tcp(listener);
+                }
+            }
+        }
+        Ok(addrs)
+    }
+
+    /// Listens on `addr` for DNS over TLS (RFC 7858), usually on port 853.
+    pub fn listen_tls<A: TlsAcceptor>(
+        &mut self,
+        addr: SocketAddr,
+        acceptor: A,
+        limits: Limits,
+    ) -> io::Result<SocketAddr> {
+        self.listen_stream(addr, Service::Tls(Arc::new(acceptor)), limits)
+    }
+
+    /// Listens on `addr` for DNS over HTTPS (RFC 8484) at [`DOH_PATH`],
+    /// usually on port 443. Clients choose HTTP/2 or HTTP/1.1 with ALPN;
+    /// those that offer neither get HTTP/1.1.
+    pub fn listen_https<A: TlsAcceptor>(
+        &mut self,
+        addr: SocketAddr,
+        acceptor: A,
+        limits: Limits,
+    ) -> io::Result<SocketAddr> {
+        self.listen_stream(addr, Service::Https(Arc::new(acceptor)), limits)
+    }
+
+    /// Listens on `addr` for DNS over cleartext HTTP at [`DOH_PATH`], for
+    /// use behind a reverse proxy that terminates TLS. HTTP/2 is spoken to
+    /// clients that open with its preface, HTTP/1.1 to the others.
+    pub fn listen_http(&mut self, addr: SocketAddr, limits: Limits) -> io::Result<SocketAddr> {
+        self.listen_stream(addr, Service::Http, limits)
+    }
+
+    /// Listens on `addr` for Prometheus scrapes of `metrics` at
+    /// [`METRICS_PATH`](crate::metrics::METRICS_PATH), one request per
+    /// connection.
+    pub fn listen_metrics(
+        &mut self,
+        addr: SocketAddr,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<SocketAddr> {
+        self.listen_stream(addr, Service::Metrics(metrics), Limits::default())
+    }
+
+    /// Listens on `addr` for management API requests at [`api::API_PATH`],
+    /// one request per connection. The API has no TLS of its own: bind it
+    /// to a loopback address or put it behind a proxy.
+    #[cfg(feature = "api")]
+    pub fn listen_api(&mut self, addr: SocketAddr, api: api::Api) -> io::Result<SocketAddr> {
+        self.listen_stream(addr, Service::Api(Arc::new(api)), Limits::default())
+    }
+
+    /// Serves DNS over QUIC (RFC 9250) on connections from `endpoint`,
+    /// returning its address.
+    pub fn listen_quic<E: QuicEndpoint>(
+        &mut self,
+        endpoint: E,
+        limits: Limits,
+    ) -> io::Result<SocketAddr> {
+        let local = endpoint.local_addr()?;
+        self.quic.push((Arc::new(endpoint), limits));
+        Ok(local)
+    }
+
+    /// Serves DNS over TLS on an already listening TCP socket.
+    pub fn adopt_tls<A: TlsAcceptor>(
+        &mut self,
+        listener: TcpListener,
+        acceptor: A,
+        limits: Limits,
+    ) {
+        self.streams
+            .push((listener, Service::Tls(Arc::new(acceptor)), limits));
+    }
+
+    /// Serves DNS over HTTPS on an already listening TCP socket.
+    pub fn adopt_https<A: TlsAcceptor>(
+        &mut self,
+        listener: TcpListener,
+        acceptor: A,
+        limits: Limits,
+    ) {
+        self.streams
+            .push((listener, Service::Https(Arc::new(acceptor)), limits));
+    }
+
+    /// Serves DNS over cleartext HTTP on an already listening TCP socket.
+    pub fn adopt_http(&mut self, listener: TcpListener, limits: Limits) {
+        self.streams.push((listener, Service::Http, limits));
+    }
+
+    fn listen_stream(
+        &mut self,
+        addr: SocketAddr,
+        service: Service,
+        limits: Limits,
+    ) -> io::Result<SocketAddr> {
+        let reuse = Reuse {
+            addr: true,
+            ..Reuse::default()
+        };
+        let listener = sys::bind_tcp(addr, reuse)?;
+        let local = listener.local_addr()?;
+        self.streams.push((listener, service, limits));
+        Ok(local)
+    }
+
+    pub fn set_tcp_idle_timeout(&mut self, timeout: Duration) {
+        self.tcp_idle_timeout = timeout;
+    }
+
+    /// Connections beyond this limit are closed as soon as they are
+    /// accepted.
+    pub fn set_max_tcp_connections(&mut self, max: usize) {
+        self.max_tcp_connections = max;
+    }
+
+    /// Transfer requests beyond this many at a time are refused.
+    pub fn set_max_transfers(&mut self, max: usize) {
+        self.max_transfers = max;
+    }
+
+    pub fn set_udp_threads(&mut self, threads: usize) {
+        self.udp_threads = threads.max(1);
+    }
+
+    /// How UDP sockets are served. A backend that is not available falls
+    /// back to threads.
+    pub fn set_udp_backend(&mut self, backend: UdpBackend) {
+        self.udp_backend = backend;
+    }
+
+    /// How long [`run`](Server::run) waits after a shutdown for requests
+    /// under way; connections still open then are closed.
+    pub fn set_drain_timeout(&mut self, timeout: Duration) {
+        self.drain_timeout = timeout;
+    }
+
+    /// How responses are padded, limited and truncated.
+    pub fn set_response_policy(&mut self, policy: ResponsePolicy) {
+        self.response_policy = policy;
+    }
+
+    /// What requests may get away with that the RFCs forbid; see
+    /// [`Compliance`].
+    pub fn set_compliance(&mut self, compliance: Compliance) {
+        self.compliance = compliance;
+    }
+
+    /// The clock TSIG signing times are checked against and signed with,
+    /// instead of the system's.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// The timeouts DSO sessions on TCP and TLS connections are given, or
+    /// `None` to answer DSO requests with NOTIMP. Keepalive intervals
+    /// below [`MIN_KEEPALIVE_INTERVAL`] are raised to it.
+    pub fn set_dso(&mut self, timeouts: Option<Keepalive>) {
+        self.dso = timeouts.map(|t| Keepalive {
+            keepalive_interval: t.keepalive_interval.map(|i| i.max(MIN_KEEPALIVE_INTERVAL)),
+            ..t
+        });
+    }
+
+    /// Serves DNS Push Notifications on DSO sessions as `policy` says, or
+    /// not at all with `None`, the default.
+    pub fn set_push(&mut self, policy: Option<PushPolicy>) {
+        self.push = policy;
+    }
+
+    /// Every bound address with its protocol. Encrypted listeners over
+    /// TCP are included; QUIC endpoints are not.
+    pub fn local_addrs(&self) -> Vec<(Protocol, SocketAddr)> {
+        let udp = self
+            .udp
+            .iter()
+            .chain(&self.workers)
+            .filter_map(|s| s.local_addr().ok())
+            .map(|a| (Protocol::Udp, a));
+        let tcp = self
+            .tcp
+            .iter()
+            .chain(self.streams.iter().map(|(l, _, _)| l))
+            .filter_map(|s| s.local_addr().ok())
+            .map(|a| (Protocol::Tcp, a));
+        udp.chain(tcp).collect()
+    }
+
+    /// Serves requests until a socket fails or the server is shut down
+    /// through its [`Control`].
+    pub fn run(self) -> io::Result<()> {
+        let state = self.state;
+        let frontend = Arc::new(Frontend {
+            state: state.clone(),
+            max_transfers: self.max_transfers,
+            transfers: Arc::new(AtomicUsize::new(0)),
+            policy: self.response_policy,
+            compliance: self.compliance,
+            clock: self.clock,
+        });
+        for listener in self
+            .tcp
+            .iter()
+            .chain(self.streams.iter().map(|(l, _, _)| l))
+        {
+            state.add_listener(listener)?;
+        }
+        for (endpoint, _) in &self.quic {
+            state.add_endpoint(endpoint.clone());
+        }
+        let backend = match self.udp_backend {
+            b if b.is_available() => b,
+            _ => UdpBackend::Threads,
+        };
+        let mut threads = Vec::new();
+        let udp_threads = self.udp_threads;
+        let udp_frontend = frontend.clone();
+        let serve_udp_socket = move |socket: UdpSocket, open: Arc<AtomicBool>| {
+            let frontend = &udp_frontend;
+            socket.set_read_timeout(Some(UDP_POLL_INTERVAL))?;
+            let mut spawned = Vec::new();
+            for _ in 1..udp_threads {
+                let socket = socket.try_clone()?;
+                let frontend = frontend.clone();
+                let open = open.clone();
+                spawned.push(thread::spawn(move || {
+                    serve_datagrams(&socket, &frontend, backend, &open)
+                }));
+            }
+            let frontend = frontend.clone();
+            spawned.push(thread::spawn(move || {
+                serve_datagrams(&socket, &frontend, backend, &open)
+            }));
+            io::Result::Ok(spawned)
+        };
+        for socket in self.udp {
+            let open = state.serve(Protocol::Udp, socket.local_addr()?);
+            threads.extend(serve_udp_socket(socket, open)?);
+        }
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        for (i, socket) in self.workers.into_iter().enumerate() {
+            socket.set_read_timeout(Some(UDP_POLL_INTERVAL))?;
+            let open = state.serve(Protocol::Udp, socket.local_addr()?);
+            let frontend = frontend.clone();
+            let pin = self.pin_workers;
+            threads.push(thread::spawn(move || {
+                if pin {
+                    // Unpinned, the worker still works.
+                    let _ = sys::pin_thread(i % cpus);
+                }
+                serve_datagrams(&socket, &frontend, backend, &open)
+            }));
+        }
+        #[cfg(all(feature = "xdp", target_os = "linux"))]
+        for listener in self.xdp {
+            threads.extend(listener.spawn(&frontend, self.udp_threads)?);
+        }
+        let connections = Arc::new(AtomicUsize::new(0));
+        let dns_tcp = TcpFrontend {
+            frontend: frontend.clone(),
+            service: Service::Dns,
+            idle_timeout: self.tcp_idle_timeout,
+            max_connections: self.max_tcp_connections,
+            proxy_protocol: false,
+            connections: connections.clone(),
+            dso: self.dso,
+            push: self.push,
+        };
+        for listener in self.tcp {
+            let open = state.serve(Protocol::Tcp, listener.local_addr()?);
+            let tcp = dns_tcp.clone();
+            threads.push(thread::spawn(move || tcp.accept(&listener, &open)));
+        }
+        for (listener, service, limits) in self.streams {
+            let open = state.serve(Protocol::Tcp, listener.local_addr()?);
+            let tcp = TcpFrontend {
+                frontend: frontend.clone(),
+                service,
+                idle_timeout: limits.idle_timeout,
+                max_connections: limits.max_connections,
+                proxy_protocol: limits.proxy_protocol,
+                connections: Arc::new(AtomicUsize::new(0)),
+                dso: self.dso,
+                push: self.push,
+            };
+            threads.push(thread::spawn(move || tcp.accept(&listener, &open)));
+        }
+        // Sockets bound through a Control from now on are served as plain
+        // DNS over UDP and TCP are, sharing their limits.
+        let spawned_state = state.clone();
+        let spawn: Spawn = Arc::new(move |socket, open| match socket {
+            Socket::Udp(socket) => serve_udp_socket(socket, open).map(drop),
+            Socket::Tcp(listener) => {
+                spawned_state.add_listener(&listener)?;
+                let tcp = dns_tcp.clone();
+                thread::spawn(move || tcp.accept(&listener, &open));
+                Ok(())
+            }
+        });
+        state.set_spawn(spawn);
+        for (endpoint, limits) in self.quic {
+            let frontend = frontend.clone();
+            threads.push(thread::spawn(move || {
+                quic::serve(&*endpoint, frontend, limits)
+            }));
+        }
+        let mut result = Ok(());
+        for t in threads {
+            let r = t
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("server thread panicked")));
+            if result.is_ok() {
+                result = r;
+            }
+        }
+        // Listeners have stopped; connections finish what they were doing.
+        let deadline = Instant::now() + self.drain_timeout;
+        while state.is_busy() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        state.abort_connections();
+        result
+    }
+}
+
+/// Request decoding, TSIG and response encoding shared by the UDP and
+/// TCP paths.
+struct Frontend {
+    state: Arc<State>,
+    max_transfers: usize,
+    transfers: Arc<AtomicUsize>,
+    policy: ResponsePolicy,
+    compliance: Compliance,
+    clock: Arc<dyn Clock>,
+}
+
+/// How a request reached the server, as far as its response cares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+    /// TLS, HTTPS or QUIC.
+    Encrypted,
+}
+
+impl Transport {
+    fn protocol(self) -> Protocol {
+        match self {
+            Transport::Udp => Protocol::Udp,
+            Transport::Tcp | Transport::Encrypted => Protocol::Tcp,
+        }
+    }
+}
+
+/// The encoded messages answering one request.
+#[derive(Default)]
+struct Reply {
+    messages: Vec<Vec<u8>>,
+    /// Held while a zone transfer is being sent.
+    _slot: Option<TransferSlot>,
+}
+
+impl Reply {
+    fn one(wire: Vec<u8>) -> Reply {
+        Reply {
+            messages: vec![wire],
+            _slot: None,
+        }
+    }
+}
+
+/// One of the limited number of concurrent transfers.
+struct TransferSlot(Arc<AtomicUsize>);
+
+impl Drop for TransferSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How the messages of a reply are signed.
+struct Signer {
+    key: tsig::Key,
+    request_mac: Vec<u8>,
+}
+
+impl Frontend {
+    /// Decodes a query, checks its signature and passes it to the
+    /// handler. Malformed queries get FORMERR when at least a header could
+    /// be read, as do queries with several questions unless the compliance
+    /// profile puts up with them; responses are ignored.
+    fn dispatch(&self, buf: &[u8], src: SocketAddr, transport: Transport) -> Reply {
+        let protocol = transport.protocol();
+        if buf.len() < 12 || buf[2] & 0x80 != 0 {
+            return Reply::default();
+        }
+        let message = match Message::from_wire_with(buf, &self.compliance) {
+            Ok(m) => m,
+            Err(_) => return Reply::one(formerr(buf)),
+        };
+        // RFC 9619.
+        if message.questions.len() > 1
+            && message.header.opcode == Opcode::QUERY
+            && !self.compliance.multiple_questions
+        {
+            return Reply::one(formerr(buf));
+        }
+        let _active = self.state.begin_request();
+        let mut request = Request {
+            message,
+            src,
+            protocol,
+            key: None,
+        };
+        let mut signer = None;
+        if request
+            .message
+            .additional
+            .last()
+            .is_some_and(|rr| rr.rtype() == RecordType::TSIG)
+        {
+            match self.verify(buf, &mut request) {
+                Ok(s) => signer = Some(s),
+                Err(reply) => return reply,
+            }
+        }
+        let mut slot = None;
+        if protocol == Protocol::Tcp && request.is_transfer() {
+            if self.transfers.fetch_add(1, Ordering::SeqCst) >= self.max_transfers {
+                self.transfers.fetch_sub(1, Ordering::SeqCst);
+                let mut resp = request.message.response();
+                resp.header.rcode = Rcode::REFUSED;
+                return self.encode(vec![resp], &request, transport, signer.as_ref(), None);
+            }
+            slot = Some(TransferSlot(self.transfers.clone()));
+        }
+        let resp = match self.state.handler().handle(&request) {
+            Some(resp) => resp,
+            None => return Reply::default(),
+        };
+        let messages = if protocol == Protocol::Tcp && request.is_transfer() {
+            split_transfer(resp)
+        } else {
+            vec![resp]
+        };
+        self.encode(messages, &request, transport, signer.as_ref(), slot)
+    }
+
+    /// Verifies the TSIG record of `buf`, stripping it from the request.
+    /// Failures produce the reply to send instead (RFC 8945 §5.2).
+    fn verify(&self, buf: &[u8], request: &mut Request) -> Result<Signer, Reply> {
+        let signed = match tsig::Signed::parse(buf) {
+            Ok(Some(signed)) => signed,
+            _ => return Err(Reply::one(formerr(buf))),
+        };
+        let now = self.clock.unix_time();
+        let mut resp = signed.message.response();
+        let keys = self.state.keys().read().unwrap();
+        let key = match keys.get(&signed.key_name) {
+            Some(key) => key,
+            None => {
+                let wire = tsig::error_response(
+                    &resp,
+                    &signed.key_name,
+                    &signed.tsig.algorithm,
+                    Rcode::BADKEY,
+                    now,
+                );
+                return Err(Reply::one(wire.unwrap_or_default()));
+            }
+        };
+        match signed.verify(key, None, now) {
+            Ok(()) => {}
+            Err(tsig::Error::BadTime) => {
+                // The response is signed, reporting the server's time.
+                resp.header.rcode = Rcode::NOTAUTH;
+                let wire = resp.to_wire().and_then(|wire| {
+                    let mac = Some(signed.tsig.mac.as_slice());
+                    tsig::sign_wire(wire, key, mac, now, Rcode::BADTIME)
+                });
+                return Err(Reply::one(wire.map(|(w, _)| w).unwrap_or_default()));
+            }
+            Err(e) => {
+                let error = e.rcode().unwrap_or(Rcode::BADSIG);
+                let wire = tsig::error_response(
+                    &resp,
+                    &signed.key_name,
+                    &signed.tsig.algorithm,
+                    error,
+                    now,
+                );
+                return Err(Reply::one(wire.unwrap_or_default()));
+            }
+        }
+        request.message = signed.message;
+        request.key = Some(signed.key_name);
+        Ok(Signer {
+            key: key.clone(),
+            request_mac: signed.tsig.mac,
+        })
+    }
+
+    /// Encodes the response messages, each within the size the client
+    /// accepts and as the response policy says, and signs them if the
+    /// request was signed.
+    fn encode(
+        &self,
+        messages: Vec<Message>,
+        request: &Request,
+        transport: Transport,
+        signer: Option<&Signer>,
+        slot: Option<TransferSlot>,
+    ) -> Reply {
+        let mut max = request.max_response_size();
+        if transport == Transport::Udp {
+            max = self.policy.udp_limit(max);
+        }
+        let padded = transport == Transport::Encrypted
+            && request
+                .message
+                .edns
+                .as_ref()
+                .is_some_and(|e| e.option(OptionCode::PADDING).is_some());
+        let messages = match signer {
+            None if messages.len() == 1 => vec![self.policy.encode(&messages[0], max, padded)],
+            None => messages
+                .iter()
+                .map(|m| encode(m, max))
+                .filter(|w| !w.is_empty())
+                .collect(),
+            Some(signer) if messages.len() == 1 => {
+                let max = max.saturating_sub(tsig::overhead(&signer.key));
+                let mac = Some(signer.request_mac.as_slice());
+                let wire = self.policy.encode(&messages[0], max, padded);
+                tsig::sign_wire(
+                    wire,
+                    &signer.key,
+                    mac,
+                    self.clock.unix_time(),
+                    Rcode::NOERROR,
+                )
+                .map(|(w, _)| w)
+                .into_iter()
+                .collect()
+            }
+            Some(signer) => {
+                let mut stream =
+                    tsig::StreamSigner::new(signer.key.clone(), signer.request_mac.clone());
+                let now = self.clock.unix_time();
+                messages
+                    .iter()
+                    .map_while(|m| stream.sign(m, now).ok())
+                    .collect()
+            }
+        };
+        Reply {
+            messages,
+            _slot: slot,
+        }
+    }
+}
+
+/// A FORMERR response to a message of which only the header could be
+/// read.
+fn formerr(buf: &[u8]) -> Vec<u8> {
+    let resp = Message {
+        header: Header {
+            id: u16::from_be_bytes([buf[0], buf[1]]),
+            qr: true,
+            opcode: Opcode((buf[2] >> 3) & 0xf),
+            rcode: Rcode::FORMERR,
+            ..Header::default()
+        },
+        ..Message::default()
+    };
+    encode(&resp, MIN_UDP_SIZE)
+}
+
+/// Encodes a response to fit `max` bytes, falling back to a bare
+/// SERVFAIL if it cannot be encoded at all.
+fn encode(resp: &Message, max: usize) -> Vec<u8> {
+    resp.to_wire_limited(max).unwrap_or_else(|_| {
+        let mut fail = Message {
+            header: resp.header,
+            questions: resp.questions.clone(),
+            ..Message::default()
+        };
+        fail.header.rcode = Rcode::SERVFAIL;
+        fail.to_wire_limited(max).unwrap_or_default()
+    })
+}
+
+/// Splits a zone transfer response into messages of moderate size. Only
+/// the first repeats the question (RFC 5936 §2.2).
+fn split_transfer(resp: Message) -> Vec<Message> {
+    let mut chunks: Vec<Vec<Record>> = vec![Vec::new()];
+    let mut size = 0;
+    for rr in resp.answers.iter() {
+        let mut enc = Encoder::uncompressed();
+        // Cannot fail for records that came out of a zone; an oversized
+        // one fails again when its message is encoded.
+        let _ = rr.encode(&mut enc);
+        if size + enc.len() > TRANSFER_MESSAGE_SIZE && size > 0 {
+            chunks.push(Vec::new());
+            size = 0;
+        }
+        size += enc.len();
+        chunks.last_mut().unwrap().push(rr.clone());
+    }
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, answers)| Message {
+            header: resp.header,
+            questions: if i == 0 {
+                resp.questions.clone()
+            } else {
+                Vec::new()
+            },
+            answers,
+            // Anything else goes with the last message.
+            authority: if i + 1 == count {
+                resp.authority.clone()
+            } else {
+                Vec::new()
+            },
+            additional: if i + 1 == count {
+                resp.additional.clone()
+            } else {
+                Vec::new()
+            },
+            edns: resp.edns.clone(),
+        })
+        .collect()
+}
+
+/// Answers datagrams on `socket` through `backend`, or with
+/// [`serve_udp`] if its setup fails, until the server stops or `open` is
+/// cleared.
+fn serve_datagrams(
+    socket: &UdpSocket,
+    frontend: &Frontend,
+    backend: UdpBackend,
+    open: &AtomicBool,
+) -> io::Result<()> {
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    if backend == UdpBackend::IoUring {
+        if let Ok(ring) = uring::Ring::new(socket) {
+            return ring.serve(frontend, open);
+        }
+    }
+    let _ = backend;
+    serve_udp(socket, frontend, open)
+}
+
+fn serve_udp(socket: &UdpSocket, frontend: &Frontend, open: &AtomicBool) -> io::Result<()> {
+    let mut bufs = vec![vec![0u8; 65535]; UDP_BATCH];
+    let mut received = Vec::with_capacity(UDP_BATCH);
+    while !frontend.state.is_stopping() && open.load(Ordering::SeqCst) {
+        received.clear();
+        match sys::recv_batch(socket, &mut bufs, &mut received) {
+            Ok(()) => {}
+            // ICMP errors from earlier sends surface here on some systems;
+            // timeouts are only there to notice shutdown.
+            Err(e)
+                if e.kind() == ErrorKind::ConnectionReset
+                    || e.kind() == ErrorKind::ConnectionRefused
+                    || e.kind() == ErrorKind::Interrupted
+                    || e.kind() == ErrorKind::WouldBlock
+                    || e.kind() == ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        }
+        let replies: Vec<(Vec<u8>, SocketAddr)> = received
+            .iter()
+            .zip(&bufs)
+            .filter_map(|(&(n, src), buf)| {
+                let reply = frontend.dispatch(&buf[..n], src, Transport::Udp);
+                let wire = reply.messages.into_iter().next()?;
+                Some((wire, src)).filter(|(w, _)| !w.is_empty())
+            })
+            .collect();
+        let datagrams: Vec<(&[u8], SocketAddr)> = replies
+            .iter()
+            .map(|(w, src)| (w.as_slice(), *src))
+            .collect();
+        // A failed send concerns only its client.
+        sys::send_batch(socket, &datagrams);
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
+struct TcpFrontend {
+    frontend: Arc<Frontend>,
+    service: Service,
+    idle_timeout: Duration,
+    max_connections: usize,
+    proxy_protocol: bool,
+    connections: Arc<AtomicUsize>,
+    /// The timeouts of DSO sessions, if they are offered.
+    dso: Option<Keepalive>,
+    push: Option<PushPolicy>,
+}
+
+impl TcpFrontend {
+    /// Accepts connections on `listener` until the server stops or `open`
+    /// is cleared.
+    fn accept(self, listener: &TcpListener, open: &AtomicBool) -> io::Result<()> {
+        let this = Arc::new(self);
+        let state = this.frontend.state.clone();
+        loop {
+            let accepted = listener.accept();
+            // Shutdown wakes the listener, failing this or connecting.
+            if state.is_stopping() || !open.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let (stream, src) = match accepted {
+                Ok(c) => c,
+                // Per-connection failures and descriptor exhaustion are
+                // transient; keep accepting.
+                Err(_) => {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            };
+            if this.connections.fetch_add(1, Ordering::SeqCst) >= this.max_connections {
+                this.connections.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            let this = this.clone();
+            thread::spawn(move || {
+                let _ = this.serve(stream, src);
+                this.connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    fn serve(&self, mut stream: TcpStream, mut src: SocketAddr) -> io::Result<()> {
+        let _tracked = self.frontend.state.track(&stream);
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.idle_timeout))?;
+        stream.set_write_timeout(Some(self.idle_timeout))?;
+        if self.proxy_protocol {
+            if let Some(client) = proxy::read_header(&mut stream)? {
+                src = client;
+            }
+        }
+        match &self.service {
+            Service::Dns => {
+                let socket = stream.try_clone()?;
+                self.serve_dns(&mut stream, &socket, src, Transport::Tcp)
+            }
+            Service::Tls(acceptor) => {
+                let socket = stream.try_clone()?;
+                let mut accepted = acceptor.accept(stream, &[tls::ALPN_DOT])?;
+                self.serve_dns(&mut accepted.stream, &socket, src, Transport::Encrypted)
+            }
+            Service::Https(acceptor) => {
+                let mut accepted = acceptor.accept(stream, &[tls::ALPN_H2, tls::ALPN_HTTP1])?;
+                let frontend = &self.frontend;
+                let transport = Transport::Encrypted;
+                if accepted.alpn.as_deref() == Some(tls::ALPN_H2) {
+                    https::serve_h2(frontend, &mut accepted.stream, src, transport, 0)
+                } else {
+                    https::serve_http1(frontend, &mut accepted.stream, src, transport, Vec::new())
+                }
+            }
+            Service::Http => {
+                // The preface of HTTP/2 differs from any HTTP/1.1 request
+                // within its first bytes.
+                let mut read = Vec::new();
+                let mut byte = [0u8];
+                while read.len() < https::H2_PREFACE.len() && https::H2_PREFACE.starts_with(&read) {
+                    if stream.read(&mut byte)? == 0 {
+                        return Ok(());
+                    }
+                    read.push(byte[0]);
+                }
+                // Whoever terminates TLS in front decides about padding.
+                let transport = Transport::Tcp;
+                if read == https::H2_PREFACE {
+                    https::serve_h2(&self.frontend, &mut stream, src, transport, read.len())
+                } else {
+                    https::serve_http1(&self.frontend, &mut stream, src, transport, read)
+                }
+            }
+            Service::Metrics(metrics) => metrics::serve_http(&mut stream, metrics),
+            #[cfg(feature = "api")]
+            Service::Api(api) => api::serve_http(&mut stream, api),
+        }
+    }
+
+    /// Answers length-prefixed queries on one connection, in order, until
+    /// the client closes it or stays idle for too long. `socket` is the
+    /// TCP connection under `stream`, whose read timeout a DSO session
+    /// changes.
+    fn serve_dns<S: Read + Write>(
+        &self,
+        stream: &mut S,
+        socket: &TcpStream,
+        src: SocketAddr,
+        transport: Transport,
+    ) -> io::Result<()> {
+        let mut session: Option<Session> = None;
+        loop {
+            if let Some(session) = &mut session {
+                let now = Instant::now();
+                if session.is_delinquent(now) {
+                    // RFC 8490 §6.4.1.
+                    return Ok(());
+                }
+                if session.poll_due(now) {
+                    for wire in self.poll(session, src, transport, now) {
+                        write_framed(stream, &wire)?;
+                    }
+                }
+                socket.set_read_timeout(session.wait(Instant::now()))?;
+            }
+            let buf = match read_framed(stream) {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                // A session wakes up to push changes and to check on the
+                // client.
+                Err(e)
+                    if session.is_some()
+                        && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            // Garbage that is not even a header ends the connection.
+            if buf.len() < 12 {
+                return Ok(());
+            }
+            let now = Instant::now();
+            if is_dso(&buf) {
+                let reply = self.serve_dso(&buf, &mut session, src, transport, now);
+                for wire in &reply.messages {
+                    write_framed(stream, wire)?;
+                }
+                if reply.close {
+                    return Ok(());
+                }
+                continue;
+            }
+            if let Some(session) = &mut session {
+                session.touch(now);
+            }
+            let reply = self.frontend.dispatch(&buf, src, transport);
+            for wire in reply.messages.iter().filter(|w| !w.is_empty()) {
+                write_framed(stream, wire)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! The order records of an RRset are given in.
+//!
+//! Resolvers and stub clients that always use the first address of an
+//! answer depend on the server to spread them across addresses, or to
+//! put the one nearest to them first. An [`AnswerOrdering`] rotates,
+//! shuffles or keeps the order of each RRset in the answer section, and
+//! its sortlist then moves the addresses that suit the client to the
+//! front, in the manner of BIND's `sortlist` and the address selection
+//! rules of RFC 3484.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::addr::Prefix;
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{RData, Record, RecordType};
+
+use super::{Handler, Layer, Request};
+
+/// How the records of an RRset are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnswerOrder {
+    /// As the zone or upstream server gave them.
+    #[default]
+    Fixed,
+    /// Rotated by one more place with each response (round robin).
+    Cyclic,
+    /// Shuffled for each response.
+    Random,
+}
+
+/// Address preferences for the clients in `clients`: addresses in the
+/// first of `preferred` come first, then those in the second, and so on,
+/// with addresses in none of them last.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SortlistEntry {
+    pub clients: Prefix,
+    pub preferred: Vec<Prefix>,
+}
+
+impl SortlistEntry {
+    pub fn new(clients: Prefix) -> SortlistEntry {
+        SortlistEntry {
+            clients,
+            preferred: Vec::new(),
+        }
+    }
+
+    pub fn prefer(mut self, prefix: Prefix) -> Self {
+        self.preferred.push(prefix);
+        self
+    }
+
+    /// The place of `addr` among the preferences.
+    fn rank(&self, addr: &IpAddr) -> usize {
+        self.preferred
+            .iter()
+            .position(|p| p.contains(addr))
+            .unwrap_or(self.preferred.len())
+    }
+}
+
+/// Settings of the [`Ordered`] handler.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnswerOrdering {
+    /// The order of RRsets no entry of `names` covers.
+    pub order: AnswerOrder,
+    /// Orders for RRsets at and below a name; the most specific name
+    /// covering an owner applies.
+    pub names: Vec<(DomainName, AnswerOrder)>,
+    /// Applied to A and AAAA RRsets after `order`; the first entry whose
+    /// `clients` holds the client's address applies.
+    pub sortlist: Vec<SortlistEntry>,
+}
+
+impl AnswerOrdering {
+    pub fn new(order: AnswerOrder) -> AnswerOrdering {
+        AnswerOrdering {
+            order,
+            ..AnswerOrdering::default()
+        }
+    }
+
+    /// Orders RRsets at and below `name` by `order`.
+    pub fn name(mut self, name: DomainName, order: AnswerOrder) -> Self {
+        self.names.push((name, order));
+        self
+    }
+
+    pub fn sortlist(mut self, entry: SortlistEntry) -> Self {
+        self.sortlist.push(entry);
+        self
+    }
+
+    /// The order of RRsets owned by `owner`.
+    pub fn order_for(&self, owner: &DomainName) -> AnswerOrder {
+        self.names
+            .iter()
+            .filter(|(name, _)| owner.is_subdomain_of(name))
+            .max_by_key(|(name, _)| name.label_count())
+            .map_or(self.order, |&(_, order)| order)
+    }
+
+    /// The sortlist entry for `client`, if any.
+    pub fn sortlist_for(&self, client: &IpAddr) -> Option<&SortlistEntry> {
+        self.sortlist.iter().find(|e| e.clients.contains(client))
+    }
+
+    /// Reorders every RRset of more than one record in the answer section
+    /// of `resp` for `client`. `turn` counts responses, and says how far
+    /// cyclic RRsets are rotated.
+    pub fn apply(&self, resp: &mut Message, client: &IpAddr, turn: usize) {
+        let sortlist = self.sortlist_for(client);
+        // Records of an RRset keep the places the set had between them,
+        // so signatures and other RRsets stay where they were.
+        let mut sets: Vec<(DomainName, RecordType, Vec<usize>)> = Vec::new();
+        for (i, rr) in resp.answers.iter().enumerate() {
+            let rtype = rr.rtype();
+            match sets
+                .iter_mut()
+                .find(|(n, t, _)| *t == rtype && *n == rr.name)
+            {
+                Some((_, _, places)) => places.push(i),
+                None => sets.push((rr.name.clone(), rtype, vec![i])),
+            }
+        }
+        for (owner, rtype, places) in sets {
+            if places.len() < 2 || rtype == RecordType::RRSIG {
+                continue;
+            }
+            let mut records: Vec<Record> =
+                places.iter().map(|&i| resp.answers[i].clone()).collect();
+            match self.order_for(&owner) {
+                AnswerOrder::Fixed => {}
+                AnswerOrder::Cyclic => records.rotate_left(turn % places.len()),
+                AnswerOrder::Random => shuffle(&mut records),
+            }
+            if let Some(entry) = sortlist {
+                // A stable sort keeps the order above among equals.
+                records.sort_by_key(|rr| match rr.rdata {
+                    RData::A(a) => entry.rank(&IpAddr::V4(a)),
+                    RData::Aaaa(a) => entry.rank(&IpAddr::V6(a)),
+                    _ => 0,
+                });
+            }
+            for (i, rr) in places.into_iter().zip(records) {
+                resp.answers[i] = rr;
+            }
+        }
+    }
+}
+
+fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let j = (random::u64() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+impl<H: Handler> Layer<H> for AnswerOrdering {
+    type Handler = Ordered<H>;
+
+    fn layer(&self, inner: H) -> Ordered<H> {
+        Ordered {
+            config: self.clone(),
+            turn: AtomicUsize::new(0),
+            inner,
+        }
+    }
+}
+
+/// A handler reordering the answers of another; see [`AnswerOrdering`].
+pub struct Ordered<H> {
+    config: AnswerOrdering,
+    turn: AtomicUsize,
+    inner: H,
+}
+
+impl<H> Ordered<H> {
+    pub fn config(&self) -> &AnswerOrdering {
+        &self.config
+    }
+}
+
+impl<H: Handler> Handler for Ordered<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let mut resp = self.inner.handle(request)?;
+        if resp.answers.len() > 1 {
+            let turn = self.turn.fetch_add(1, Ordering::Relaxed);
+            self.config.apply(&mut resp, &request.src.ip(), turn);
+        }
+        Some(resp)
+    }
+}
@@ -0,0 +1,131 @@
+//! Plugins: custom logic called at fixed points while a request is
+//! answered, able to answer in the server's place or change its answer.
+//!
+//! There are three hook points:
+//!
+//! - *pre-cache*, before the handler sees the request, and with it any
+//!   cache the handler keeps;
+//! - *pre-upstream*, when a [`Forwarder`](super::Forwarder) is about to
+//!   send a query upstream after a cache miss;
+//! - *post-answer*, once the response is ready to be sent.
+//!
+//! [`Plugins`] is a layer running the first and last around any handler;
+//! hand the same list to [`Forwarder::plugins`](super::Forwarder::plugins)
+//! for the second.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::message::Message;
+
+use super::{Handler, Layer, Request};
+
+/// Custom logic called at the hook points. Every method does nothing by
+/// default.
+pub trait Plugin: Send + Sync {
+    /// Called before the handler sees `request`; an answer returned here
+    /// is sent in place of the handler's.
+    fn pre_cache(&self, request: &Request) -> Option<Message> {
+        let _ = request;
+        None
+    }
+
+    /// Called before `query`, sent on behalf of `request`, goes upstream.
+    /// The plugin may change the query, or return an answer to use in
+    /// place of the upstreams'; such answers are not cached.
+    fn pre_upstream(&self, request: &Request, query: &mut Message) -> Option<Message> {
+        let _ = (request, query);
+        None
+    }
+
+    /// Called with every response before it is sent, including those the
+    /// plugins made.
+    fn post_answer(&self, request: &Request, response: &mut Message) {
+        let _ = (request, response);
+    }
+}
+
+/// Plugins called in the order they were added: the first to answer at a
+/// hook point wins, and every plugin sees the answer after it.
+#[derive(Clone, Default)]
+pub struct Plugins {
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugins")
+            .field("len", &self.plugins.len())
+            .finish()
+    }
+}
+
+impl Plugins {
+    pub fn new() -> Plugins {
+        Plugins::default()
+    }
+
+    pub fn plugin(mut self, plugin: Arc<dyn Plugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.plugins.len()
+    }
+
+    pub fn pre_cache(&self, request: &Request) -> Option<Message> {
+        self.plugins.iter().find_map(|p| p.pre_cache(request))
+    }
+
+    pub fn pre_upstream(&self, request: &Request, query: &mut Message) -> Option<Message> {
+        self.plugins
+            .iter()
+            .find_map(|p| p.pre_upstream(request, query))
+    }
+
+    pub fn post_answer(&self, request: &Request, response: &mut Message) {
+        for plugin in &self.plugins {
+            plugin.post_answer(request, response);
+        }
+    }
+}
+
+impl<H: Handler> Layer<H> for Plugins {
+    type Handler = WithPlugins<H>;
+
+    fn layer(&self, inner: H) -> WithPlugins<H> {
+        WithPlugins {
+            plugins: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler with [`Plugins`] called before and after it.
+#[derive(Debug)]
+pub struct WithPlugins<H> {
+    plugins: Plugins,
+    inner: H,
+}
+
+impl<H> WithPlugins<H> {
+    pub fn plugins(&self) -> &Plugins {
+        &self.plugins
+    }
+}
+
+impl<H: Handler> Handler for WithPlugins<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let mut resp = self
+            .plugins
+            .pre_cache(request)
+            .or_else(|| self.inner.handle(request))?;
+        self.plugins.post_answer(request, &mut resp);
+        Some(resp)
+    }
+}
@@ -0,0 +1,55 @@
+//! The PROXY protocol, version 2: the header a load balancer sends ahead
+//! of a connection it relays, carrying the address of the client it
+//! accepted the connection from.
+//!
+//! Listeners that expect the header require it; a connection that does
+//! not open with one is closed, as anyone could otherwise claim any
+//! address. Only the binary version 2 is understood.
+
+use std::io::{self, Read};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The 12 bytes a version 2 header starts with.
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Reads the header from `stream`, returning the client address it
+/// carries. `None` means the connection is the load balancer's own, such
+/// as a health check, or is relayed from an address that is not IP; the
+/// peer address stands then.
+pub(super) fn read_header<R: Read>(stream: &mut R) -> io::Result<Option<SocketAddr>> {
+    let mut head = [0u8; 16];
+    stream.read_exact(&mut head)?;
+    if head[..12] != SIGNATURE || head[12] >> 4 != 2 {
+        return Err(invalid("connection does not start with a PROXY v2 header"));
+    }
+    let mut rest = vec![0u8; usize::from(u16::from_be_bytes([head[14], head[15]]))];
+    stream.read_exact(&mut rest)?;
+    match head[12] & 0x0f {
+        // LOCAL: sent by the load balancer itself.
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unknown PROXY command")),
+    }
+    // Address family in the high nibble; the addresses that follow are
+    // the source's, then the destination's, then their ports. TLVs after
+    // them are not used.
+    match head[13] >> 4 {
+        1 if rest.len() >= 12 => {
+            let ip = Ipv4Addr::new(rest[0], rest[1], rest[2], rest[3]);
+            let port = u16::from_be_bytes([rest[8], rest[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        2 if rest.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&rest[..16]);
+            let port = u16::from_be_bytes([rest[32], rest[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(octets).into(), port)))
+        }
+        1 | 2 => Err(invalid("truncated PROXY addresses")),
+        _ => Ok(None),
+    }
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what.to_string())
+}
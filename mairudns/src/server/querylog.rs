@@ -0,0 +1,81 @@
+//! Passing requests and their responses to a query [`Logger`].
+
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+use crate::message::Message;
+use crate::querylog::{Entry, Logger, Redaction};
+use crate::random;
+
+use super::{Handler, Layer, Request};
+
+/// Settings of the [`Logged`] handler.
+#[derive(Clone, Debug)]
+pub struct QueryLog {
+    pub logger: Arc<Logger>,
+    /// Share of requests logged, from 0 to 1.
+    pub sample_rate: f64,
+    pub redaction: Redaction,
+}
+
+impl QueryLog {
+    /// Logs every request with client addresses whole.
+    pub fn new(logger: Arc<Logger>) -> QueryLog {
+        QueryLog {
+            logger,
+            sample_rate: 1.0,
+            redaction: Redaction::default(),
+        }
+    }
+
+    fn sampled(&self) -> bool {
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        let draw = (random::u64() >> 11) as f64 / (1u64 << 53) as f64;
+        draw < self.sample_rate
+    }
+}
+
+impl<H: Handler> Layer<H> for QueryLog {
+    type Handler = Logged<H>;
+
+    fn layer(&self, inner: H) -> Logged<H> {
+        Logged {
+            config: self.clone(),
+            inner,
+        }
+    }
+}
+
+/// A handler whose requests are logged; see [`QueryLog`].
+pub struct Logged<H> {
+    config: QueryLog,
+    inner: H,
+}
+
+impl<H> Logged<H> {
+    pub fn config(&self) -> &QueryLog {
+        &self.config
+    }
+}
+
+impl<H: Handler> Handler for Logged<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        if !self.config.sampled() {
+            return self.inner.handle(request);
+        }
+        let time = SystemTime::now();
+        let started = Instant::now();
+        let resp = self.inner.handle(request);
+        self.config.logger.log(Entry {
+            time,
+            duration: started.elapsed(),
+            client: self.config.redaction.apply(request.src),
+            protocol: request.protocol,
+            query: request.message.clone(),
+            response: resp.clone(),
+        });
+        resp
+    }
+}
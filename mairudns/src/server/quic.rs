@@ -0,0 +1,125 @@
+//! Pluggable QUIC for DNS over QUIC (RFC 9250).
+//!
+//! As with TLS, the crate has no QUIC implementation; applications supply
+//! a [`QuicEndpoint`] that completes handshakes with the ALPN protocol
+//! [`ALPN_DOQ`](super::ALPN_DOQ) and hands over the streams clients open.
+//! Each stream carries one query, length-prefixed as over TCP, and its
+//! response.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use crate::client::{read_framed, write_framed};
+
+use super::tls::Stream;
+use super::{Frontend, Limits, Transport};
+
+/// No error; used when closing idle connections (RFC 9250 §4.3).
+pub const DOQ_NO_ERROR: u64 = 0x0;
+
+/// The server failed to handle a query.
+pub const DOQ_INTERNAL_ERROR: u64 = 0x1;
+
+/// The client broke the protocol, e.g. with a nonzero message ID.
+pub const DOQ_PROTOCOL_ERROR: u64 = 0x2;
+
+/// The server is overloaded.
+pub const DOQ_EXCESSIVE_LOAD: u64 = 0x4;
+
+/// A bound QUIC endpoint accepting connections.
+pub trait QuicEndpoint: Send + Sync + 'static {
+    /// Waits for the next connection whose handshake has completed.
+    fn accept(&self) -> io::Result<Arc<dyn QuicConnection>>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Stops accepting connections when the server shuts down; a blocked
+    /// [`accept`](QuicEndpoint::accept) should fail.
+    fn close(&self) {}
+}
+
+/// An established QUIC connection.
+pub trait QuicConnection: Send + Sync {
+    fn remote_addr(&self) -> SocketAddr;
+
+    /// Waits for the next bidirectional stream the client opens, or `None`
+    /// once the connection is closed. Dropping a stream finishes its
+    /// sending side.
+    fn accept_stream(&self) -> io::Result<Option<Box<dyn Stream>>>;
+
+    /// Closes the connection with an application error code.
+    fn close(&self, code: u64);
+}
+
+/// Accepts connections from `endpoint` until it fails.
+pub(super) fn serve(
+    endpoint: &dyn QuicEndpoint,
+    frontend: Arc<Frontend>,
+    limits: Limits,
+) -> io::Result<()> {
+    let connections = Arc::new(AtomicUsize::new(0));
+    loop {
+        let conn = match endpoint.accept() {
+            Ok(conn) => conn,
+            Err(_) if frontend.state.is_stopping() => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if frontend.state.is_stopping() {
+            conn.close(DOQ_NO_ERROR);
+            return Ok(());
+        }
+        if connections.fetch_add(1, Ordering::SeqCst) >= limits.max_connections {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            conn.close(DOQ_EXCESSIVE_LOAD);
+            continue;
+        }
+        let frontend = frontend.clone();
+        let connections = connections.clone();
+        thread::spawn(move || {
+            let _ = serve_connection(&conn, &frontend);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Answers the streams of one connection, each on a thread of its own.
+fn serve_connection(conn: &Arc<dyn QuicConnection>, frontend: &Arc<Frontend>) -> io::Result<()> {
+    while let Some(stream) = conn.accept_stream()? {
+        let conn = conn.clone();
+        let frontend = frontend.clone();
+        thread::spawn(move || {
+            if let Err(code) = serve_stream(stream, conn.remote_addr(), &frontend) {
+                conn.close(code);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answers the one query of a stream. Errors are the code to close the
+/// connection with.
+fn serve_stream(
+    mut stream: Box<dyn Stream>,
+    src: SocketAddr,
+    frontend: &Frontend,
+) -> Result<(), u64> {
+    let buf = match read_framed(&mut stream) {
+        Ok(buf) => buf,
+        // Streams the client gives up on are its own business.
+        Err(_) => return Ok(()),
+    };
+    // The message ID must be 0 (RFC 9250 §4.2.1).
+    if buf.len() < 12 || buf[..2] != [0, 0] {
+        return Err(DOQ_PROTOCOL_ERROR);
+    }
+    let reply = frontend.dispatch(&buf, src, Transport::Encrypted);
+    for wire in reply.messages.iter().filter(|w| !w.is_empty()) {
+        if write_framed(&mut stream, wire).is_err() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,321 @@
+//! Per-client query quotas for recursive service.
+//!
+//! Where [`RateLimit`](super::RateLimit) bounds identical responses to
+//! stop reflection attacks, a [`ClientQuota`] bounds how much of a
+//! resolver each client network may use: how many queries per second,
+//! with a burst allowance, and how many may be in progress at once. This
+//! keeps one busy or misbehaving client from starving the others of a
+//! public resolver.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::addr::{IpSet, Prefix};
+use crate::client::Protocol;
+use crate::message::{ExtendedError, Message, Rcode};
+use crate::metrics::Metrics;
+
+use super::table::{Entry, Table};
+use super::{Handler, Layer, Request};
+
+/// What is done with a query beyond a client's quota.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuotaAction {
+    /// Answer SERVFAIL with an extended error saying why.
+    #[default]
+    ServFail,
+    /// Send nothing.
+    Drop,
+    /// Send an empty truncated response so the client retries over TCP,
+    /// where the address is known not to be spoofed. TCP queries get
+    /// SERVFAIL instead.
+    Truncate,
+}
+
+/// Which limit a query ran into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum QuotaLimit {
+    /// Queries per second.
+    Rate,
+    /// Queries in progress at once.
+    Concurrency,
+}
+
+/// Settings of the [`QuotaLimited`] handler. A limit of zero is no limit.
+#[derive(Clone, Debug)]
+pub struct ClientQuota {
+    /// Queries per second each client network may send on average.
+    pub queries_per_second: u32,
+    /// Queries a client network may send at once after being idle; at
+    /// least one.
+    pub burst: u32,
+    /// Queries of a client network that may be in progress at once.
+    pub max_concurrent: usize,
+    pub action: QuotaAction,
+    /// Only queries asking for recursion count, so that authoritative
+    /// service is left to response rate limiting.
+    pub recursion_only: bool,
+    /// Client addresses are grouped into networks of these lengths.
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    /// Clients that are never limited.
+    pub exempt: IpSet,
+    /// Number of client networks tracked. Beyond it, a new network takes
+    /// the place of one that has gone unused the longest, roughly, and
+    /// starts with a full quota; networks with queries in progress are
+    /// kept.
+    pub max_entries: usize,
+    /// Where queries over quota are counted.
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for ClientQuota {
+    fn default() -> ClientQuota {
+        ClientQuota::new(0)
+    }
+}
+
+impl ClientQuota {
+    /// Allows each client network `per_second` queries per second, with a
+    /// burst of as many.
+    pub fn new(per_second: u32) -> ClientQuota {
+        ClientQuota {
+            queries_per_second: per_second,
+            burst: per_second,
+            max_concurrent: 0,
+            action: QuotaAction::ServFail,
+            recursion_only: true,
+            ipv4_prefix_len: 32,
+            ipv6_prefix_len: 64,
+            exempt: IpSet::new(),
+            max_entries: 100_000,
+            metrics: None,
+        }
+    }
+
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    pub fn max_concurrent(mut self, max: usize) -> Self {
+        self.max_concurrent = max;
+        self
+    }
+
+    pub fn action(mut self, action: QuotaAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    fn network(&self, addr: IpAddr) -> Prefix {
+        let addr = match addr {
+            IpAddr::V6(a) => a.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            IpAddr::V4(_) => addr,
+        };
+        let len = match addr {
+            IpAddr::V4(_) => self.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.ipv6_prefix_len.min(128),
+        };
+        Prefix::new(addr, len).unwrap_or_else(|_| Prefix::host(addr))
+    }
+
+    /// The response for a query of `request` over quota.
+    fn refusal(&self, request: &Request, limit: QuotaLimit) -> Option<Message> {
+        let mut resp = request.message.response();
+        match self.action {
+            QuotaAction::Drop => return None,
+            QuotaAction::Truncate if request.protocol == Protocol::Udp => resp.header.tc = true,
+            QuotaAction::ServFail | QuotaAction::Truncate => {
+                resp.header.rcode = Rcode::SERVFAIL;
+                let text = match limit {
+                    QuotaLimit::Rate => "client query rate exceeded",
+                    QuotaLimit::Concurrency => "too many client queries in progress",
+                };
+                resp.add_extended_error(&ExtendedError::new(ExtendedError::OTHER, text));
+            }
+        }
+        Some(resp)
+    }
+}
+
+impl<H: Handler> Layer<H> for ClientQuota {
+    type Handler = QuotaLimited<H>;
+
+    fn layer(&self, inner: H) -> QuotaLimited<H> {
+        QuotaLimited {
+            config: self.clone(),
+            clients: Table::new(self.max_entries),
+            inner,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Usage {
+    /// Queries that may still be sent at once.
+    tokens: f64,
+    updated: Instant,
+    in_flight: usize,
+}
+
+impl Entry for Usage {
+    fn pinned(&self) -> bool {
+        self.in_flight > 0
+    }
+}
+
+/// A handler whose clients are held to a quota; see [`ClientQuota`].
+pub struct QuotaLimited<H> {
+    config: ClientQuota,
+    clients: Table<Prefix, Usage>,
+    inner: H,
+}
+
+impl<H> QuotaLimited<H> {
+    pub fn config(&self) -> &ClientQuota {
+        &self.config
+    }
+
+    /// Number of client networks tracked.
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Takes a query of `network` into account, or says which limit it
+    /// is over.
+    fn admit(&self, network: Prefix, now: Instant) -> Result<(), QuotaLimit> {
+        let rate = f64::from(self.config.queries_per_second);
+        let burst = f64::from(self.config.burst.max(1));
+        let new = || Usage {
+            tokens: burst,
+            updated: now,
+            in_flight: 0,
+        };
+        self.clients.update(network, new, |usage| {
+            if self.config.max_concurrent > 0 && usage.in_flight >= self.config.max_concurrent {
+                return Err(QuotaLimit::Concurrency);
+            }
+            if rate > 0.0 {
+                let elapsed = now.saturating_duration_since(usage.updated).as_secs_f64();
+                usage.tokens = (usage.tokens + elapsed * rate).min(burst);
+                usage.updated = now;
+                if usage.tokens < 1.0 {
+                    return Err(QuotaLimit::Rate);
+                }
+                usage.tokens -= 1.0;
+            }
+            usage.in_flight += 1;
+            Ok(())
+        })
+    }
+
+    fn release(&self, network: &Prefix) {
+        self.clients.get_mut(network, |usage| {
+            usage.in_flight = usage.in_flight.saturating_sub(1);
+        });
+    }
+}
+
+impl<H: Handler> Handler for QuotaLimited<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let src = request.src.ip();
+        if (self.config.recursion_only && !request.message.header.rd)
+            || self.config.exempt.contains(&src)
+        {
+            return self.inner.handle(request);
+        }
+        let network = self.config.network(src);
+        if let Err(limit) = self.admit(network, Instant::now()) {
+            if let Some(metrics) = &self.config.metrics {
+                metrics.record_quota_exceeded(limit == QuotaLimit::Concurrency);
+            }
+            return self.config.refusal(request, limit);
+        }
+        let resp = self.inner.handle(request);
+        self.release(&network);
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn network(addr: &str) -> Prefix {
+        Prefix::host(addr.parse().unwrap())
+    }
+
+    fn limited(config: ClientQuota) -> QuotaLimited<()> {
+        QuotaLimited {
+            clients: Table::new(config.max_entries),
+            config,
+            inner: (),
+        }
+    }
+
+    #[test]
+    fn tokens_refill_at_the_rate_up_to_the_burst() {
+        let quota = limited(ClientQuota::new(10).burst(3));
+        let client = network("192.0.2.1");
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(quota.admit(client, start), Ok(()));
+            quota.release(&client);
+        }
+        assert_eq!(quota.admit(client, start), Err(QuotaLimit::Rate));
+        // A tenth of a second buys one more query.
+        let later = start + Duration::from_millis(100);
+        assert_eq!(quota.admit(client, later), Ok(()));
+        quota.release(&client);
+        assert_eq!(quota.admit(client, later), Err(QuotaLimit::Rate));
+        // However long the wait, no more than the burst.
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(quota.admit(client, much_later), Ok(()));
+            quota.release(&client);
+        }
+        assert_eq!(quota.admit(client, much_later), Err(QuotaLimit::Rate));
+        // Other networks have their own.
+        assert_eq!(quota.admit(network("192.0.2.2"), much_later), Ok(()));
+    }
+
+    #[test]
+    fn queries_in_progress_are_bounded_until_released() {
+        let quota = limited(ClientQuota::new(0).max_concurrent(2));
+        let client = network("192.0.2.1");
+        let now = Instant::now();
+        assert_eq!(quota.admit(client, now), Ok(()));
+        assert_eq!(quota.admit(client, now), Ok(()));
+        assert_eq!(quota.admit(client, now), Err(QuotaLimit::Concurrency));
+        quota.release(&client);
+        assert_eq!(quota.admit(client, now), Ok(()));
+        // Releasing what the table no longer holds is harmless.
+        quota.release(&network("192.0.2.9"));
+    }
+
+    #[test]
+    fn a_full_table_replaces_idle_networks_and_keeps_busy_ones() {
+        let mut config = ClientQuota::new(1).max_concurrent(1);
+        config.max_entries = 4;
+        let quota = limited(config);
+        let now = Instant::now();
+        let busy = network("192.0.2.1");
+        assert_eq!(quota.admit(busy, now), Ok(()));
+        // Many more networks than the table holds: each is counted, and
+        // the table stays within its size but for the busy one.
+        for i in 2..100 {
+            let client = network(&format!("192.0.2.{}", i));
+            assert_eq!(quota.admit(client, now), Ok(()));
+            quota.release(&client);
+            assert_eq!(quota.admit(client, now), Err(QuotaLimit::Rate));
+        }
+        assert!(quota.clients() <= 5, "{} networks held", quota.clients());
+        // The busy network was never forgotten, so it is still limited.
+        assert_eq!(quota.admit(busy, now), Err(QuotaLimit::Concurrency));
+        quota.release(&busy);
+        assert_eq!(quota.admit(busy, now), Err(QuotaLimit::Rate));
+    }
+}
@@ -0,0 +1,334 @@
+//! A control channel for a running server, in the manner of `rndc`:
+//! commands sent over a Unix socket to reload zones or the whole
+//! configuration, inspect or flush cached names, dump statistics, show
+//! recent requests, toggle debug logging or drain the server.
+//!
+//! Only the socket's owner can connect to it, and every command must also
+//! carry an HMAC-SHA256 of a fresh challenge under a shared key, so that a
+//! socket left with looser permissions does not open the server to other
+//! local users. The exchange is one command per connection:
+//!
+//! 1. the server sends a 32-byte challenge;
+//! 2. the client sends the MAC of the challenge followed by the command,
+//!    then the command text;
+//! 3. the server answers with a status byte, zero for success, and text.
+//!
+//! Each message is preceded by its length as a 32-bit big-endian integer.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::cache::NamePattern;
+use crate::crypto::{self, Sha256};
+use crate::name::DomainName;
+use crate::rr::RecordType;
+
+/// Longest message accepted; statistics dumps are the largest.
+const MAX_MESSAGE: usize = 16 << 20;
+
+/// How long a client has to send its command.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors of the control channel.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The server refused the command or failed to carry it out.
+    Failed(String),
+    /// A malformed message, or a command that does not parse.
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Failed(e) => write!(f, "command failed: {}", e),
+            Error::Protocol(e) => write!(f, "protocol error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+/// A command sent over the control channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// A summary of the server's state.
+    Status,
+    /// Reload one zone from its file, or refresh it if it is a secondary;
+    /// without a name, every zone.
+    Reload(Option<DomainName>),
+    /// Drop the cached responses for the names a pattern matches, only
+    /// those of one type if given, or the whole cache.
+    Flush(Option<NamePattern>, Option<RecordType>),
+    /// The cached responses for the names a pattern matches, or all of
+    /// them.
+    Cache(Option<NamePattern>),
+    /// The server's statistics.
+    Stats,
+    /// The last requests classified, as many as given or all those kept.
+    Recent(Option<usize>),
+    /// Turn logging of every request on or off.
+    Debug(bool),
+    /// Stop accepting requests and exit once those under way are
+    /// answered.
+    Drain,
+    /// Read the configuration again and apply what changed, leaving the
+    /// rest running.
+    Reconfigure,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    /// Parses commands such as `reload example.com` or `debug on`.
+    fn from_str(s: &str) -> Result<Command, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let name = |text: &str| {
+            text.parse::<DomainName>()
+                .map_err(|e| format!("invalid name {:?}: {}", text, e))
+        };
+        let pattern = |text: &str| {
+            text.parse::<NamePattern>()
+                .map_err(|e| format!("invalid name {:?}: {}", text, e))
+        };
+        match words.as_slice() {
+            ["status"] => Ok(Command::Status),
+            ["reload"] => Ok(Command::Reload(None)),
+            ["reload", zone] => Ok(Command::Reload(Some(name(zone)?))),
+            ["flush"] => Ok(Command::Flush(None, None)),
+            ["flush", p] => Ok(Command::Flush(Some(pattern(p)?), None)),
+            ["flush", p, t] => {
+                let rtype = t
+                    .parse::<RecordType>()
+                    .map_err(|_| format!("invalid type {:?}", t))?;
+                Ok(Command::Flush(Some(pattern(p)?), Some(rtype)))
+            }
+            ["cache"] => Ok(Command::Cache(None)),
+            ["cache", p] => Ok(Command::Cache(Some(pattern(p)?))),
+            ["stats"] => Ok(Command::Stats),
+            ["recent"] => Ok(Command::Recent(None)),
+            ["recent", n] => n
+                .parse()
+                .map(|n| Command::Recent(Some(n)))
+                .map_err(|_| format!("invalid count {:?}", n)),
+            ["debug", "on"] => Ok(Command::Debug(true)),
+            ["debug", "off"] => Ok(Command::Debug(false)),
+            ["drain"] => Ok(Command::Drain),
+            ["reconfigure"] => Ok(Command::Reconfigure),
+            _ => Err(format!("unknown command {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Status => f.write_str("status"),
+            Command::Reload(None) => f.write_str("reload"),
+            Command::Reload(Some(zone)) => write!(f, "reload {}", zone),
+            Command::Flush(None, _) => f.write_str("flush"),
+            Command::Flush(Some(pattern), None) => write!(f, "flush {}", pattern),
+            Command::Flush(Some(pattern), Some(rtype)) => {
+                write!(f, "flush {} {}", pattern, rtype)
+            }
+            Command::Cache(None) => f.write_str("cache"),
+            Command::Cache(Some(pattern)) => write!(f, "cache {}", pattern),
+            Command::Stats => f.write_str("stats"),
+            Command::Recent(None) => f.write_str("recent"),
+            Command::Recent(Some(n)) => write!(f, "recent {}", n),
+            Command::Debug(on) => write!(f, "debug {}", if *on { "on" } else { "off" }),
+            Command::Drain => f.write_str("drain"),
+            Command::Reconfigure => f.write_str("reconfigure"),
+        }
+    }
+}
+
+/// Carries out control commands, returning the text to send back or why
+/// the command failed.
+pub trait Operator: Send + Sync + 'static {
+    fn execute(&self, command: &Command) -> Result<String, String>;
+}
+
+impl<F> Operator for F
+where
+    F: Fn(&Command) -> Result<String, String> + Send + Sync + 'static,
+{
+    fn execute(&self, command: &Command) -> Result<String, String> {
+        self(command)
+    }
+}
+
+fn write_message<W: Write>(stream: &mut W, msg: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(msg.len() + 4);
+    buf.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+    buf.extend_from_slice(msg);
+    stream.write_all(&buf)
+}
+
+fn read_message<R: Read>(stream: &mut R) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE {
+        return Err(Error::Protocol(format!("message of {} bytes", len)));
+    }
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// A challenge from the operating system's CSPRNG: one a local user could
+/// predict would let them replay a MAC they had seen. Without the CSPRNG
+/// the connection fails rather than falling back to weaker randomness.
+fn challenge() -> io::Result<[u8; 32]> {
+    let mut challenge = [0u8; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut challenge)?;
+    Ok(challenge)
+}
+
+fn mac(key: &[u8], challenge: &[u8], command: &[u8]) -> Vec<u8> {
+    let mut data = challenge.to_vec();
+    data.extend_from_slice(command);
+    crypto::hmac::<Sha256>(key, &data)
+}
+
+/// The listening end of the control channel.
+pub struct RemoteControl {
+    listener: UnixListener,
+    path: PathBuf,
+    key: Arc<[u8]>,
+}
+
+impl fmt::Debug for RemoteControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteControl")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RemoteControl {
+    /// Listens on a Unix socket at `path`, replacing any socket left there
+    /// by an earlier run, readable and writable by its owner only.
+    pub fn bind<P: AsRef<Path>>(path: P, key: &[u8]) -> io::Result<RemoteControl> {
+        let path = path.as_ref().to_path_buf();
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_socket() => fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            }
+            Err(_) => {}
+        }
+        let listener = UnixListener::bind(&path)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        Ok(RemoteControl {
+            listener,
+            path,
+            key: key.into(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accepts connections forever, each on a thread of its own, passing
+    /// authenticated commands to `operator`. Run it on a thread of its
+    /// own.
+    pub fn run<O: Operator>(&self, operator: O) {
+        let operator = Arc::new(operator);
+        for stream in self.listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let operator = operator.clone();
+            let key = self.key.clone();
+            thread::spawn(move || {
+                let _ = serve(&mut stream, &key, &*operator);
+            });
+        }
+    }
+}
+
+impl Drop for RemoteControl {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve<O: Operator + ?Sized>(
+    stream: &mut UnixStream,
+    key: &[u8],
+    operator: &O,
+) -> Result<(), Error> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let challenge = challenge()?;
+    write_message(stream, &challenge)?;
+    let request = read_message(stream)?;
+    let (given, command) = match request.get(..32) {
+        Some(given) => (given, &request[32..]),
+        None => return Err(Error::Protocol("short request".into())),
+    };
+    let result = if !crypto::verify_mac(&mac(key, &challenge, command), given) {
+        Err("bad key".to_string())
+    } else {
+        std::str::from_utf8(command)
+            .map_err(|_| "command is not UTF-8".to_string())
+            .and_then(str::parse)
+            .and_then(|command| operator.execute(&command))
+    };
+    let mut response = Vec::new();
+    match result {
+        Ok(text) => {
+            response.push(0);
+            response.extend_from_slice(text.as_bytes());
+        }
+        Err(text) => {
+            response.push(1);
+            response.extend_from_slice(text.as_bytes());
+        }
+    }
+    write_message(stream, &response)?;
+    Ok(())
+}
+
+/// Sends `command` to the server listening at `path` and returns its
+/// answer.
+pub fn send<P: AsRef<Path>>(path: P, key: &[u8], command: &Command) -> Result<String, Error> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let challenge = read_message(&mut stream)?;
+    let text = command.to_string();
+    let mut request = mac(key, &challenge, text.as_bytes());
+    request.extend_from_slice(text.as_bytes());
+    write_message(&mut stream, &request)?;
+    let response = read_message(&mut stream)?;
+    let text = String::from_utf8_lossy(response.get(1..).unwrap_or(&[])).into_owned();
+    match response.first() {
+        Some(0) => Ok(text),
+        Some(_) => Err(Error::Failed(text)),
+        None => Err(Error::Protocol("empty response".into())),
+    }
+}
@@ -0,0 +1,286 @@
+//! Response rate limiting, as in BIND's RRL: bounding how often the same
+//! answer is sent to the same network, so that a server cannot be used to
+//! reflect and amplify traffic at a spoofed victim.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::addr::{IpSet, Prefix};
+use crate::client::Protocol;
+use crate::message::{Message, Rcode};
+use crate::metrics::Metrics;
+use crate::name::DomainName;
+use crate::rr::RecordType;
+
+use super::table::{Entry, Table};
+use super::{Authority, Handler, Layer, Request};
+
+/// What kind of response is being limited; each kind has its own rate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResponseKind {
+    /// A positive answer, accounted per name and type.
+    Answer,
+    /// A positive answer synthesized from a wildcard, accounted per
+    /// wildcard so that random names below it share a bucket.
+    Wildcard,
+    /// A referral, accounted per delegation.
+    Referral,
+    /// A name without records of the asked type, accounted per zone.
+    Nodata,
+    /// A name that does not exist, accounted per zone.
+    Nxdomain,
+    /// Any other rcode, accounted per client network alone.
+    Error,
+}
+
+/// Settings of the [`RateLimited`] handler. Rates are responses per second
+/// per client network; zero leaves a kind of response unlimited.
+///
+/// Only UDP responses are limited: TCP clients cannot spoof their address.
+/// A client that exceeds a rate has responses dropped, except that every
+/// `slip`-th one is sent truncated so that a real client behind a busy
+/// network can retry over TCP.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    pub responses_per_second: u32,
+    pub wildcards_per_second: u32,
+    pub referrals_per_second: u32,
+    pub nodata_per_second: u32,
+    pub nxdomains_per_second: u32,
+    pub errors_per_second: u32,
+    /// How many seconds of excess a client can build up, and so how long
+    /// it stays limited after a burst.
+    pub window: u32,
+    /// Send every `slip`-th limited response truncated instead of dropping
+    /// it; zero drops them all.
+    pub slip: u32,
+    /// Client addresses are grouped into networks of these lengths.
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    /// Clients that are never limited.
+    pub exempt: IpSet,
+    /// Number of buckets kept. Beyond it, a new bucket takes the place of
+    /// one that has gone unused the longest, roughly; its responses are
+    /// limited like any other's.
+    pub max_entries: usize,
+    /// Used to recognize wildcard answers; without it they count as plain
+    /// answers.
+    pub authority: Option<Arc<Authority>>,
+    /// Where dropped and truncated responses are counted.
+    pub metrics: Option<Arc<Metrics>>,
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit::new(0)
+    }
+}
+
+impl RateLimit {
+    /// Limits every kind of response to `per_second`.
+    pub fn new(per_second: u32) -> RateLimit {
+        RateLimit {
+            responses_per_second: per_second,
+            wildcards_per_second: per_second,
+            referrals_per_second: per_second,
+            nodata_per_second: per_second,
+            nxdomains_per_second: per_second,
+            errors_per_second: per_second,
+            window: 15,
+            slip: 2,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            exempt: IpSet::new(),
+            max_entries: 100_000,
+            authority: None,
+            metrics: None,
+        }
+    }
+
+    pub fn rate(&self, kind: ResponseKind) -> u32 {
+        match kind {
+            ResponseKind::Answer => self.responses_per_second,
+            ResponseKind::Wildcard => self.wildcards_per_second,
+            ResponseKind::Referral => self.referrals_per_second,
+            ResponseKind::Nodata => self.nodata_per_second,
+            ResponseKind::Nxdomain => self.nxdomains_per_second,
+            ResponseKind::Error => self.errors_per_second,
+        }
+    }
+
+    /// The kind of `resp` and the name it is accounted under.
+    pub fn classify(&self, resp: &Message) -> (ResponseKind, DomainName) {
+        let qname = resp
+            .question()
+            .map_or_else(DomainName::root, |q| q.name.clone());
+        let owner_of = |rtype: RecordType| {
+            resp.authority
+                .iter()
+                .find(|rr| rr.rtype() == rtype)
+                .map(|rr| rr.name.clone())
+        };
+        match resp.header.rcode {
+            Rcode::NOERROR => {}
+            Rcode::NXDOMAIN => {
+                let zone = owner_of(RecordType::SOA).unwrap_or(qname);
+                return (ResponseKind::Nxdomain, zone);
+            }
+            _ => return (ResponseKind::Error, DomainName::root()),
+        }
+        if resp.answers.is_empty() {
+            return match owner_of(RecordType::NS) {
+                Some(cut) if !resp.header.aa => (ResponseKind::Referral, cut),
+                _ => (
+                    ResponseKind::Nodata,
+                    owner_of(RecordType::SOA).unwrap_or(qname),
+                ),
+            };
+        }
+        match self
+            .authority
+            .as_ref()
+            .and_then(|a| a.wildcard_owner(&qname))
+        {
+            Some(wildcard) => (ResponseKind::Wildcard, wildcard),
+            None => (ResponseKind::Answer, qname),
+        }
+    }
+
+    fn network(&self, addr: IpAddr) -> Prefix {
+        let addr = match addr {
+            IpAddr::V6(a) => a.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            IpAddr::V4(_) => addr,
+        };
+        let len = match addr {
+            IpAddr::V4(_) => self.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.ipv6_prefix_len.min(128),
+        };
+        Prefix::new(addr, len).unwrap_or_else(|_| Prefix::host(addr))
+    }
+}
+
+impl<H: Handler> Layer<H> for RateLimit {
+    type Handler = RateLimited<H>;
+
+    fn layer(&self, inner: H) -> RateLimited<H> {
+        RateLimited {
+            config: self.clone(),
+            buckets: Table::new(self.max_entries),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct BucketKey {
+    network: Prefix,
+    kind: ResponseKind,
+    name: DomainName,
+    qtype: RecordType,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Responses that may still be sent; negative while limited.
+    balance: f64,
+    updated: Instant,
+    limited: u32,
+}
+
+impl Entry for Bucket {}
+
+enum Verdict {
+    Send,
+    Slip,
+    Drop,
+}
+
+/// A handler whose UDP responses are rate limited; see [`RateLimit`].
+pub struct RateLimited<H> {
+    config: RateLimit,
+    buckets: Table<BucketKey, Bucket>,
+    inner: H,
+}
+
+impl<H> RateLimited<H> {
+    pub fn config(&self) -> &RateLimit {
+        &self.config
+    }
+
+    /// Number of buckets held.
+    pub fn buckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    fn account(&self, key: BucketKey, rate: u32, now: Instant) -> Verdict {
+        let slip = self.config.slip;
+        let rate = f64::from(rate);
+        let window = f64::from(self.config.window.max(1));
+        let new = || Bucket {
+            balance: rate,
+            updated: now,
+            limited: 0,
+        };
+        self.buckets.update(key, new, |bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.balance = (bucket.balance + elapsed * rate).min(rate) - 1.0;
+            bucket.balance = bucket.balance.max(-rate * window);
+            bucket.updated = now;
+            if bucket.balance >= 0.0 {
+                bucket.limited = 0;
+                return Verdict::Send;
+            }
+            bucket.limited = bucket.limited.wrapping_add(1);
+            if slip > 0 && bucket.limited.is_multiple_of(slip) {
+                Verdict::Slip
+            } else {
+                Verdict::Drop
+            }
+        })
+    }
+}
+
+impl<H: Handler> Handler for RateLimited<H> {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let resp = self.inner.handle(request)?;
+        let src = request.src.ip();
+        if request.protocol != Protocol::Udp || self.config.exempt.contains(&src) {
+            return Some(resp);
+        }
+        let (kind, name) = self.config.classify(&resp);
+        let rate = self.config.rate(kind);
+        if rate == 0 {
+            return Some(resp);
+        }
+        let qtype = match kind {
+            ResponseKind::Answer | ResponseKind::Wildcard => {
+                resp.question().map_or(RecordType::ANY, |q| q.qtype)
+            }
+            _ => RecordType::ANY,
+        };
+        let key = BucketKey {
+            network: self.config.network(src),
+            kind,
+            name,
+            qtype,
+        };
+        let verdict = self.account(key, rate, Instant::now());
+        if let Some(metrics) = &self.config.metrics {
+            match verdict {
+                Verdict::Send => {}
+                Verdict::Slip => metrics.record_rate_limited(true),
+                Verdict::Drop => metrics.record_rate_limited(false),
+            }
+        }
+        match verdict {
+            Verdict::Send => Some(resp),
+            Verdict::Slip => {
+                let mut slip = request.message.response();
+                slip.header.tc = true;
+                Some(slip)
+            }
+            Verdict::Drop => None,
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! A [`Plugin`] written in Rhai, for custom answers such as weighted
+//! choices or records that change with the time of day, without building
+//! a server of one's own.
+//!
+//! A script defines any of the hook functions:
+//!
+//! ```text
+//! fn pre_cache(request) { ... }
+//! fn pre_upstream(request) { ... }
+//! fn post_answer(request, response) { ... }
+//! ```
+//!
+//! `request` is a map of `name`, `type`, `class`, `client` (the address),
+//! `protocol` (`"udp"` or `"tcp"`), `recursion_desired` and `dnssec_ok`.
+//! A response is a map of `rcode` and the `answers`, `authority` and
+//! `additional` sections, each an array of records in zone file syntax
+//! with absolute names: `"www.example.com. 60 IN A 192.0.2.1"`.
+//!
+//! `pre_cache` and `pre_upstream` return `()` to let the request go on, or
+//! a response map to answer with; missing fields are empty, and `rcode`
+//! is NOERROR. `post_answer` returns `()` to leave the response alone, or
+//! a map whose fields replace the response's. For example, to give half
+//! of the clients each address:
+//!
+//! ```text
+//! fn pre_cache(request) {
+//!     if request.name == "www.example.com." && request.type == "A" {
+//!         let addr = if random(2) == 0 { "192.0.2.1" } else { "192.0.2.2" };
+//!         return #{ answers: [`www.example.com. 30 IN A ${addr}`] };
+//!     }
+//! }
+//! ```
+//!
+//! Scripts may also call `random(n)`, a number below `n`, and
+//! `unix_time()`, `hour_utc()` and `weekday_utc()` (0 for Sunday). A hook
+//! that fails, or runs for too long, is skipped and counted in
+//! [`errors`](ScriptPlugin::errors).
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::Record;
+use crate::zone::parse_records;
+
+use super::{Plugin, Request};
+
+/// Most operations one hook call may take.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A script that cannot be read or does not compile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError(pub String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// A plugin running the hooks a Rhai script defines.
+pub struct ScriptPlugin {
+    engine: Engine,
+    ast: AST,
+    pre_cache: bool,
+    pre_upstream: bool,
+    post_answer: bool,
+    errors: AtomicU64,
+}
+
+impl fmt::Debug for ScriptPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptPlugin")
+            .field("pre_cache", &self.pre_cache)
+            .field("pre_upstream", &self.pre_upstream)
+            .field("post_answer", &self.post_answer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScriptPlugin {
+    /// Compiles the script `source`.
+    pub fn new(source: &str) -> Result<ScriptPlugin, ScriptError> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 64);
+        engine.register_fn("random", |n: i64| -> i64 {
+            if n <= 0 {
+                0
+            } else {
+                (random::u64() % n as u64) as i64
+            }
+        });
+        engine.register_fn("unix_time", unix_time);
+        engine.register_fn("hour_utc", || unix_time() / 3600 % 24);
+        engine.register_fn("weekday_utc", || (unix_time() / 86400 + 4) % 7);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| ScriptError(e.to_string()))?;
+        let defines = |name: &str, params: usize| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == params)
+        };
+        Ok(ScriptPlugin {
+            pre_cache: defines("pre_cache", 1),
+            pre_upstream: defines("pre_upstream", 1),
+            post_answer: defines("post_answer", 2),
+            engine,
+            ast,
+            errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Reads and compiles the script at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ScriptPlugin, ScriptError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|e| ScriptError(format!("{}: {}", path.display(), e)))?;
+        ScriptPlugin::new(&source).map_err(|e| ScriptError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// How many hook calls have failed.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    fn call(&self, name: &str, args: Vec<Dynamic>) -> Option<Map> {
+        let result = self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, args)
+            .map_err(|e| e.to_string())
+            .and_then(|value| {
+                if value.is_unit() {
+                    Ok(None)
+                } else {
+                    value
+                        .try_cast::<Map>()
+                        .map(Some)
+                        .ok_or_else(|| format!("{} returned neither () nor a map", name))
+                }
+            });
+        match result {
+            Ok(map) => map,
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// A response to `request` made from a map a hook returned.
+    fn answer(&self, request: &Request, map: Map) -> Option<Message> {
+        let mut resp = request.message.response();
+        match apply(&mut resp, map) {
+            Ok(()) => Some(resp),
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+fn request_map(request: &Request) -> Dynamic {
+    let msg = &request.message;
+    let mut map = Map::new();
+    if let Some(q) = msg.question() {
+        map.insert("name".into(), q.name.to_string().into());
+        map.insert("type".into(), q.qtype.to_string().into());
+        map.insert("class".into(), q.qclass.to_string().into());
+    }
+    map.insert("client".into(), request.src.ip().to_string().into());
+    let protocol = format!("{:?}", request.protocol).to_lowercase();
+    map.insert("protocol".into(), protocol.into());
+    map.insert("recursion_desired".into(), msg.header.rd.into());
+    let dnssec_ok = msg.edns.as_ref().is_some_and(|e| e.dnssec_ok);
+    map.insert("dnssec_ok".into(), dnssec_ok.into());
+    map.into()
+}
+
+fn section(records: &[Record]) -> Dynamic {
+    let array: Array = records.iter().map(|rr| rr.to_string().into()).collect();
+    array.into()
+}
+
+fn response_map(resp: &Message) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("rcode".into(), resp.header.rcode.to_string().into());
+    map.insert("answers".into(), section(&resp.answers));
+    map.insert("authority".into(), section(&resp.authority));
+    map.insert("additional".into(), section(&resp.additional));
+    map.into()
+}
+
+fn parse_rcode(text: &str) -> Result<Rcode, String> {
+    (0..=23)
+        .map(Rcode)
+        .find(|r| r.to_string().eq_ignore_ascii_case(text))
+        .ok_or_else(|| format!("unknown rcode {:?}", text))
+}
+
+fn parse_section(value: Dynamic) -> Result<Vec<Record>, String> {
+    let array = value
+        .try_cast::<Array>()
+        .ok_or("a section must be an array of records")?;
+    let mut records = Vec::new();
+    for item in array {
+        let line = item
+            .into_string()
+            .map_err(|_| "a record must be a string".to_string())?;
+        records.extend(parse_records(&line, &DomainName::root()).map_err(|e| e.to_string())?);
+    }
+    Ok(records)
+}
+
+/// Replaces the fields of `resp` that `map` has.
+fn apply(resp: &mut Message, map: Map) -> Result<(), String> {
+    for (key, value) in map {
+        match key.as_str() {
+            "rcode" => {
+                let text = value
+                    .into_string()
+                    .map_err(|_| "rcode must be a string".to_string())?;
+                resp.header.rcode = parse_rcode(&text)?;
+            }
+            "answers" => resp.answers = parse_section(value)?,
+            "authority" => resp.authority = parse_section(value)?,
+            "additional" => resp.additional = parse_section(value)?,
+            other => return Err(format!("unknown field {:?}", other)),
+        }
+    }
+    Ok(())
+}
+
+impl Plugin for ScriptPlugin {
+    fn pre_cache(&self, request: &Request) -> Option<Message> {
+        if !self.pre_cache {
+            return None;
+        }
+        let map = self.call("pre_cache", vec![request_map(request)])?;
+        self.answer(request, map)
+    }
+
+    fn pre_upstream(&self, request: &Request, _query: &mut Message) -> Option<Message> {
+        if !self.pre_upstream {
+            return None;
+        }
+        let map = self.call("pre_upstream", vec![request_map(request)])?;
+        self.answer(request, map)
+    }
+
+    fn post_answer(&self, request: &Request, response: &mut Message) {
+        if !self.post_answer {
+            return;
+        }
+        let args = vec![request_map(request), response_map(response)];
+        if let Some(map) = self.call("post_answer", args) {
+            let mut changed = response.clone();
+            match apply(&mut changed, map) {
+                Ok(()) => *response = changed,
+                Err(_) => {
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
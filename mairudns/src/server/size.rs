@@ -0,0 +1,143 @@
+//! Response sizing: EDNS padding (RFC 7830) on encrypted transports, a
+//! cap on UDP responses, minimal responses, and what is given up first
+//! when a response does not fit.
+//!
+//! Padding follows the block-length policy of RFC 8467 §4.1: responses to
+//! queries that carry a Padding option are padded to a multiple of the
+//! block length, so that their size says little about what they hold.
+//! Unencrypted responses are never padded, as padding them would only
+//! make them larger.
+
+use crate::message::{EdnsOption, Message, OptionCode, Rcode};
+use crate::rr::RecordType;
+
+use super::MIN_UDP_SIZE;
+
+/// Block length RFC 8467 recommends for responses.
+pub const PADDING_BLOCK: usize = 468;
+
+/// Default cap on UDP responses, the size DNS Flag Day 2020 settled on to
+/// avoid IP fragmentation.
+pub const MAX_UDP_SIZE: usize = 1232;
+
+/// How the server sizes its responses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponsePolicy {
+    /// Responses to padded queries over TLS, HTTPS and QUIC are padded to
+    /// a multiple of this many bytes; zero disables padding.
+    pub padding_block: usize,
+    /// The largest UDP response sent, whatever size the client offers;
+    /// never below 512.
+    pub max_udp_size: usize,
+    /// Leaves the authority and additional sections out of positive
+    /// answers, keeping only the records that prove a wildcard answer.
+    pub minimal_responses: bool,
+    /// Before truncating a UDP response that does not fit, tries it again
+    /// as a minimal response, so that the answer itself gets through.
+    pub minimal_on_overflow: bool,
+    /// Truncated responses carry only the question, so that clients retry
+    /// over TCP rather than make do with part of an answer.
+    pub empty_truncated: bool,
+}
+
+impl Default for ResponsePolicy {
+    fn default() -> ResponsePolicy {
+        ResponsePolicy {
+            padding_block: PADDING_BLOCK,
+            max_udp_size: MAX_UDP_SIZE,
+            minimal_responses: false,
+            minimal_on_overflow: true,
+            empty_truncated: false,
+        }
+    }
+}
+
+impl ResponsePolicy {
+    /// The size limit for a UDP client that offers `offered` bytes.
+    pub(super) fn udp_limit(&self, offered: usize) -> usize {
+        offered.min(self.max_udp_size.max(MIN_UDP_SIZE))
+    }
+
+    /// Encodes `resp` within `max` bytes as the policy says. `padded` is
+    /// whether the query asked for padding over an encrypted transport.
+    pub(super) fn encode(&self, resp: &Message, max: usize, padded: bool) -> Vec<u8> {
+        let mut resp = resp.clone();
+        if self.minimal_responses {
+            minimize(&mut resp);
+        }
+        if padded && self.padding_block > 0 {
+            pad(&mut resp, self.padding_block, max);
+        }
+        let wire = super::encode(&resp, max);
+        if !is_truncated(&wire) {
+            return wire;
+        }
+        if self.minimal_on_overflow && !self.minimal_responses && minimize(&mut resp) {
+            let wire = super::encode(&resp, max);
+            if !is_truncated(&wire) {
+                return wire;
+            }
+        }
+        if self.empty_truncated {
+            let mut empty = Message {
+                header: resp.header,
+                questions: resp.questions.clone(),
+                edns: resp.edns.clone(),
+                ..Message::default()
+            };
+            empty.header.tc = true;
+            return super::encode(&empty, max);
+        }
+        wire
+    }
+}
+
+/// Whether an encoded message has TC set.
+fn is_truncated(wire: &[u8]) -> bool {
+    wire.len() > 2 && wire[2] & 0x02 != 0
+}
+
+/// Drops what a positive answer does not need; returns whether anything
+/// was dropped.
+fn minimize(resp: &mut Message) -> bool {
+    if resp.header.rcode != Rcode::NOERROR || resp.answers.is_empty() {
+        // Negative answers need their SOA, referrals their NS and glue.
+        return false;
+    }
+    let before = resp.authority.len() + resp.additional.len();
+    // Denial of existence records prove that a wildcard answer is right.
+    resp.authority.retain(|rr| {
+        matches!(
+            rr.rtype(),
+            RecordType::NSEC | RecordType::NSEC3 | RecordType::RRSIG
+        )
+    });
+    resp.additional.clear();
+    resp.authority.len() + resp.additional.len() != before
+}
+
+/// Adds a Padding option bringing the encoded size of `resp` to a
+/// multiple of `block`, or to `max` if that is smaller. Responses without
+/// EDNS are left alone.
+fn pad(resp: &mut Message, block: usize, max: usize) {
+    match &mut resp.edns {
+        Some(edns) => edns.options.retain(|o| o.code != OptionCode::PADDING),
+        None => return,
+    }
+    let len = match resp.to_wire() {
+        Ok(wire) => wire.len(),
+        Err(_) => return,
+    };
+    // The option's code and length take four bytes.
+    if len + 4 > max {
+        return;
+    }
+    let padded = (len + 4).div_ceil(block) * block;
+    let size = padded.min(max) - (len + 4);
+    if let Some(edns) = &mut resp.edns {
+        edns.options.push(EdnsOption {
+            code: OptionCode::PADDING,
+            data: vec![0; size],
+        });
+    }
+}
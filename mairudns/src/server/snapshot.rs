@@ -0,0 +1,180 @@
+//! Cache snapshots, so that a restarted forwarder does not start cold.
+//!
+//! A snapshot starts with a magic string, a version byte and the time it
+//! was written, in seconds since the Unix epoch as a big-endian `u64`.
+//! Each cached response follows:
+//!
+//! - the suffix of its route, as a length-prefixed (`u16`) name in text;
+//! - a flags byte: 1 for DNSSEC OK, 2 for checking disabled, and 4, 8 or
+//!   16 for a response validated as secure, insecure or bogus;
+//! - the seconds it had left, as a `u32`;
+//! - the response in wire format, prefixed by its length as a `u32` of
+//!   at most 65535, with the question it was cached under.
+//!
+//! When a snapshot is read, the time since it was written is taken off
+//! what each response had left and off the TTLs of its records, and
+//! responses that would have expired meanwhile are dropped.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache::age;
+use crate::message::Message;
+use crate::name::DomainName;
+
+use super::cache::Security;
+use super::{CachedResponse, Forwarder};
+
+const MAGIC: &[u8] = b"MAIRUDNS-CACHE";
+
+const VERSION: u8 = 1;
+
+/// The longest response a snapshot may hold, the most a DNS message can
+/// be.
+const MAX_RESPONSE: u32 = 65535;
+
+const DNSSEC_OK: u8 = 1;
+const CHECKING_DISABLED: u8 = 2;
+const SECURE: u8 = 4;
+const INSECURE: u8 = 8;
+const BOGUS: u8 = 16;
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("bad cache snapshot: {}", what),
+    )
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Writes the cache of every route of `forwarder`. Returns how many
+/// responses were written.
+pub(super) fn write<W: Write>(forwarder: &Forwarder, mut out: W) -> io::Result<usize> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    out.write_all(&unix_now().to_be_bytes())?;
+    let mut written = 0;
+    for route in forwarder.routes() {
+        let suffix = route.suffix().to_string();
+        for entry in route.cache_entries(None) {
+            let mut response = entry.response;
+            response.questions = vec![entry.question];
+            let wire = match response.to_wire() {
+                Ok(wire) => wire,
+                Err(_) => continue,
+            };
+            let mut flags = 0;
+            if entry.dnssec_ok {
+                flags |= DNSSEC_OK;
+            }
+            if entry.checking_disabled {
+                flags |= CHECKING_DISABLED;
+            }
+            flags |= match entry.security {
+                Security::Unchecked => 0,
+                Security::Secure => SECURE,
+                Security::Insecure => INSECURE,
+                Security::Bogus => BOGUS,
+            };
+            out.write_all(&(suffix.len() as u16).to_be_bytes())?;
+            out.write_all(suffix.as_bytes())?;
+            out.write_all(&[flags])?;
+            let left = entry.expires_in.as_secs().min(u64::from(u32::MAX)) as u32;
+            out.write_all(&left.to_be_bytes())?;
+            out.write_all(&(wire.len() as u32).to_be_bytes())?;
+            out.write_all(&wire)?;
+            written += 1;
+        }
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Reads exactly `buf.len()` bytes, or nothing at the end of the input.
+fn read_or_end<R: Read>(input: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match input.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("cut short")),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// Restores the responses of a snapshot into the routes of `forwarder`
+/// with the same suffixes. Returns how many were restored.
+pub(super) fn read<R: Read>(forwarder: &Forwarder, mut input: R) -> io::Result<usize> {
+    let mut head = [0u8; MAGIC.len() + 9];
+    input.read_exact(&mut head)?;
+    if &head[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a snapshot"));
+    }
+    if head[MAGIC.len()] != VERSION {
+        return Err(invalid("unknown version"));
+    }
+    let mut saved = [0u8; 8];
+    saved.copy_from_slice(&head[MAGIC.len() + 1..]);
+    let elapsed = unix_now().saturating_sub(u64::from_be_bytes(saved));
+    let elapsed = elapsed.min(u64::from(u32::MAX)) as u32;
+
+    let mut restored = 0;
+    let mut len = [0u8; 2];
+    while read_or_end(&mut input, &mut len)? {
+        let mut suffix = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        input.read_exact(&mut suffix)?;
+        let suffix: DomainName = String::from_utf8(suffix)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("route suffix"))?;
+        let mut fixed = [0u8; 9];
+        input.read_exact(&mut fixed)?;
+        let flags = fixed[0];
+        let left = u32::from_be_bytes([fixed[1], fixed[2], fixed[3], fixed[4]]);
+        let size = u32::from_be_bytes([fixed[5], fixed[6], fixed[7], fixed[8]]);
+        if size > MAX_RESPONSE {
+            return Err(invalid("response"));
+        }
+        let mut wire = vec![0u8; size as usize];
+        input.read_exact(&mut wire)?;
+        if left <= elapsed {
+            continue;
+        }
+        let mut response = Message::from_wire(&wire).map_err(|_| invalid("response"))?;
+        let question = match response.questions.first() {
+            Some(q) => q.clone(),
+            None => return Err(invalid("response without a question")),
+        };
+        age(&mut response, elapsed);
+        let security = if flags & SECURE != 0 {
+            Security::Secure
+        } else if flags & INSECURE != 0 {
+            Security::Insecure
+        } else if flags & BOGUS != 0 {
+            Security::Bogus
+        } else {
+            Security::Unchecked
+        };
+        let entry = CachedResponse {
+            question,
+            dnssec_ok: flags & DNSSEC_OK != 0,
+            checking_disabled: flags & CHECKING_DISABLED != 0,
+            response,
+            security,
+            expires_in: Duration::from_secs(u64::from(left - elapsed)),
+        };
+        let route = forwarder.routes().iter().find(|r| *r.suffix() == suffix);
+        if route.is_some_and(|r| r.restore(entry)) {
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
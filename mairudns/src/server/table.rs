@@ -0,0 +1,149 @@
+//! The bounded table of per-client state behind response rate limiting
+//! and client quotas.
+//!
+//! Like the message cache, the table is split into shards by a hash of
+//! the key, each behind a lock of its own, and each shard keeps a clock
+//! hand over its entries: a hit marks its entry, and the hand spares a
+//! marked entry once, clearing the mark. A new key arriving at a full
+//! shard takes the place of the first entry the hand does not spare, so
+//! that the table stays within its size in constant time per query and
+//! every key is accounted for: one that is not held starts afresh, it is
+//! never let through unaccounted.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Mutex;
+
+/// Shards of a table large enough to have this many; smaller tables have
+/// one per entry.
+const SHARDS: usize = 16;
+
+/// State kept per key.
+pub(super) trait Entry {
+    /// Whether the entry must be kept whatever the hand says, as while
+    /// queries it counts are in progress.
+    fn pinned(&self) -> bool {
+        false
+    }
+}
+
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    referenced: bool,
+}
+
+struct Shard<K, V> {
+    index: HashMap<K, usize>,
+    slots: Vec<Slot<K, V>>,
+    hand: usize,
+}
+
+impl<K: Eq + Hash + Clone, V: Entry> Shard<K, V> {
+    /// Moves the hand to the first slot it does not spare and removes
+    /// it, or gives up if every slot is pinned.
+    fn evict(&mut self) -> bool {
+        // Every unpinned slot is unmarked after one turn of the hand.
+        for _ in 0..=2 * self.slots.len() {
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
+            let slot = match self.slots.get_mut(self.hand) {
+                Some(slot) => slot,
+                None => return false,
+            };
+            if slot.value.pinned() || std::mem::take(&mut slot.referenced) {
+                self.hand += 1;
+                continue;
+            }
+            let slot = self.slots.swap_remove(self.hand);
+            self.index.remove(&slot.key);
+            if let Some(moved) = self.slots.get(self.hand) {
+                self.index.insert(moved.key.clone(), self.hand);
+            }
+            return true;
+        }
+        false
+    }
+}
+
+/// Up to about `capacity` entries, each found by its key.
+pub(super) struct Table<K, V> {
+    shards: Box<[Mutex<Shard<K, V>>]>,
+    hasher: RandomState,
+    per_shard: usize,
+}
+
+impl<K: Eq + Hash + Clone, V: Entry> Table<K, V> {
+    pub(super) fn new(capacity: usize) -> Table<K, V> {
+        let capacity = capacity.max(1);
+        let shards = SHARDS.min(capacity);
+        Table {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        index: HashMap::new(),
+                        slots: Vec::new(),
+                        hand: 0,
+                    })
+                })
+                .collect(),
+            hasher: RandomState::new(),
+            per_shard: capacity.div_ceil(shards),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K, V>> {
+        &self.shards[self.hasher.hash_one(key) as usize % self.shards.len()]
+    }
+
+    /// Runs `f` on the entry for `key`, made by `new` if the table has
+    /// none, in place of one the clock hand picks if the table is full.
+    /// Only when every entry of the shard is pinned does it grow past its
+    /// size.
+    pub(super) fn update<R>(
+        &self,
+        key: K,
+        new: impl FnOnce() -> V,
+        f: impl FnOnce(&mut V) -> R,
+    ) -> R {
+        let mut shard = self.shard(&key).lock().unwrap();
+        let i = match shard.index.get(&key) {
+            Some(&i) => {
+                shard.slots[i].referenced = true;
+                i
+            }
+            None => {
+                if shard.slots.len() >= self.per_shard {
+                    shard.evict();
+                }
+                let i = shard.slots.len();
+                shard.index.insert(key.clone(), i);
+                shard.slots.push(Slot {
+                    key,
+                    value: new(),
+                    referenced: false,
+                });
+                i
+            }
+        };
+        f(&mut shard.slots[i].value)
+    }
+
+    /// Runs `f` on the entry for `key`, if the table holds one, without
+    /// counting it as a hit.
+    pub(super) fn get_mut<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        let mut shard = self.shard(key).lock().unwrap();
+        let i = *shard.index.get(key)?;
+        Some(f(&mut shard.slots[i].value))
+    }
+
+    /// Entries held, across all shards.
+    pub(super) fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().slots.len())
+            .sum()
+    }
+}
@@ -0,0 +1,209 @@
+//! Pluggable TLS for the encrypted listeners.
+//!
+//! The crate implements no TLS itself. Applications supply a
+//! [`TlsAcceptor`] over the library of their choice, and the server runs
+//! DNS over TLS (RFC 7858) or DNS over HTTPS (RFC 8484) on the streams it
+//! hands back. [`CertifiedKey`] loads certificates and keys from PEM files
+//! for such an acceptor, and [`Reloadable`] swaps acceptors while the
+//! server runs, e.g. after a certificate is renewed.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use crate::encoding::{self, base64_decode};
+
+/// ALPN protocol identifier of DNS over TLS (RFC 7858 §3.2).
+pub const ALPN_DOT: &[u8] = b"dot";
+
+/// ALPN protocol identifier of HTTP/2 (RFC 9113 §3.2).
+pub const ALPN_H2: &[u8] = b"h2";
+
+/// ALPN protocol identifier of HTTP/1.1 (RFC 7301 §6).
+pub const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// ALPN protocol identifier of DNS over QUIC (RFC 9250 §4.1.1).
+pub const ALPN_DOQ: &[u8] = b"doq";
+
+/// A bidirectional byte stream, such as a TLS session over a TCP
+/// connection.
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send + ?Sized> Stream for T {}
+
+/// A connection after a completed handshake.
+pub struct Accepted {
+    pub stream: Box<dyn Stream>,
+    /// The protocol agreed on with ALPN, if the client offered any.
+    pub alpn: Option<Vec<u8>>,
+}
+
+/// The server side of TLS handshakes.
+pub trait TlsAcceptor: Send + Sync + 'static {
+    /// Completes a handshake on `stream`, offering the protocols in `alpn`
+    /// in order of preference. Clients offering none of them should be
+    /// rejected (RFC 7301 §3.2).
+    ///
+    /// The read and write timeouts of `stream` are already set; they bound
+    /// the handshake and carry over to the returned stream.
+    fn accept(&self, stream: TcpStream, alpn: &[&[u8]]) -> io::Result<Accepted>;
+}
+
+impl<T: TlsAcceptor + ?Sized> TlsAcceptor for Arc<T> {
+    fn accept(&self, stream: TcpStream, alpn: &[&[u8]]) -> io::Result<Accepted> {
+        (**self).accept(stream, alpn)
+    }
+}
+
+/// An acceptor that can be replaced while the server runs. Handshakes
+/// already under way finish with the acceptor they started with.
+pub struct Reloadable {
+    current: RwLock<Arc<dyn TlsAcceptor>>,
+}
+
+impl Reloadable {
+    pub fn new<A: TlsAcceptor>(acceptor: A) -> Reloadable {
+        Reloadable {
+            current: RwLock::new(Arc::new(acceptor)),
+        }
+    }
+
+    pub fn replace<A: TlsAcceptor>(&self, acceptor: A) {
+        *self.current.write().unwrap() = Arc::new(acceptor);
+    }
+
+    pub fn current(&self) -> Arc<dyn TlsAcceptor> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+impl TlsAcceptor for Reloadable {
+    fn accept(&self, stream: TcpStream, alpn: &[&[u8]]) -> io::Result<Accepted> {
+        self.current().accept(stream, alpn)
+    }
+}
+
+/// How a private key is encoded, as told by its PEM label.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyFormat {
+    /// `PRIVATE KEY` (RFC 5208).
+    Pkcs8,
+    /// `RSA PRIVATE KEY` (RFC 8017).
+    Pkcs1,
+    /// `EC PRIVATE KEY` (RFC 5915).
+    Sec1,
+}
+
+/// Errors produced when reading PEM files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PemError {
+    /// A block whose base64 content is malformed.
+    Base64(encoding::Error),
+    /// A `BEGIN` line without its `END` line.
+    Unterminated,
+    NoCertificate,
+    NoKey,
+}
+
+impl fmt::Display for PemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PemError::Base64(e) => write!(f, "malformed base64: {}", e),
+            PemError::Unterminated => f.write_str("unterminated PEM block"),
+            PemError::NoCertificate => f.write_str("no certificate found"),
+            PemError::NoKey => f.write_str("no private key found"),
+        }
+    }
+}
+
+impl std::error::Error for PemError {}
+
+/// A certificate chain and its private key, DER encoded.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CertifiedKey {
+    /// The end-entity certificate first, then the intermediates.
+    pub chain: Vec<Vec<u8>>,
+    pub key: Vec<u8>,
+    pub key_format: KeyFormat,
+}
+
+impl fmt::Debug for CertifiedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertifiedKey")
+            .field("chain", &self.chain.len())
+            .field("key_format", &self.key_format)
+            .finish()
+    }
+}
+
+impl CertifiedKey {
+    /// Reads the `CERTIFICATE` blocks of `chain` and the first private key
+    /// of `key`. Other blocks are skipped, so both may be the same text.
+    pub fn from_pem(chain: &str, key: &str) -> Result<CertifiedKey, PemError> {
+        let chain: Vec<Vec<u8>> = pem_blocks(chain)?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| der)
+            .collect();
+        if chain.is_empty() {
+            return Err(PemError::NoCertificate);
+        }
+        let (key_format, key) = pem_blocks(key)?
+            .into_iter()
+            .find_map(|(label, der)| {
+                let format = match label.as_str() {
+                    "PRIVATE KEY" => KeyFormat::Pkcs8,
+                    "RSA PRIVATE KEY" => KeyFormat::Pkcs1,
+                    "EC PRIVATE KEY" => KeyFormat::Sec1,
+                    _ => return None,
+                };
+                Some((format, der))
+            })
+            .ok_or(PemError::NoKey)?;
+        Ok(CertifiedKey {
+            chain,
+            key,
+            key_format,
+        })
+    }
+
+    /// Reads a certificate chain and key from PEM files.
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(chain: P, key: Q) -> io::Result<CertifiedKey> {
+        let chain = fs::read_to_string(chain)?;
+        let key = fs::read_to_string(key)?;
+        CertifiedKey::from_pem(&chain, &key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// The label and decoded content of every PEM block in `text`; anything
+/// outside blocks is ignored (RFC 7468 §2).
+fn pem_blocks(text: &str) -> Result<Vec<(String, Vec<u8>)>, PemError> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let label = match line
+            .strip_prefix("-----BEGIN ")
+            .and_then(|l| l.strip_suffix("-----"))
+        {
+            Some(label) => label,
+            None => continue,
+        };
+        let end = format!("-----END {}-----", label);
+        let mut content = String::new();
+        loop {
+            match lines.next() {
+                Some(line) if line == end => break,
+                // Encapsulated headers of legacy PEM are not supported.
+                Some(line) => content.push_str(line),
+                None => return Err(PemError::Unterminated),
+            }
+        }
+        let der = base64_decode(&content).map_err(PemError::Base64)?;
+        blocks.push((label.to_string(), der));
+    }
+    Ok(blocks)
+}
@@ -0,0 +1,463 @@
+//! Serving UDP through io_uring on Linux.
+//!
+//! One multishot `recvmsg` stays armed on the socket, and the kernel picks
+//! a buffer for each datagram from a ring of buffers registered with it,
+//! so receiving takes no system call per datagram. A buffer goes back to
+//! the kernel once its datagram is answered. Responses are sent with
+//! `sendmsg` operations, submitted in the same call that waits for the
+//! next completions.
+//!
+//! Multishot `recvmsg` needs Linux 6.0. [`supported`] tries it once on a
+//! loopback socket, and where it fails the server serves UDP with threads
+//! instead.
+
+use std::cell::Cell;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+
+use crate::sys;
+
+use super::{Frontend, Transport, UDP_POLL_INTERVAL};
+
+/// Submission queue entries; the completion queue has twice as many.
+const ENTRIES: u32 = 256;
+
+/// Buffers registered with the kernel, a power of two.
+const BUFFERS: u16 = 256;
+
+/// Size of each buffer. Datagrams too large for one, which no query
+/// needs, are dropped.
+const BUFFER_SIZE: usize = 4096;
+
+/// The buffer group the buffers are registered as.
+const GROUP: u16 = 0;
+
+/// Room left for the source address of each datagram, enough for a
+/// `sockaddr_in6`.
+const NAME_LEN: usize = 28;
+
+/// Size of the `io_uring_recvmsg_out` header before each datagram.
+const HEADER_LEN: usize = 16;
+
+/// Responses being sent at a time; beyond that, responses are dropped.
+const MAX_SENDS: usize = 1024;
+
+/// `user_data` of the receive operation; sends carry their slot.
+const RECV: u64 = u64::MAX;
+const CANCEL: u64 = u64::MAX - 1;
+
+/// How long a stopping ring waits for its operations to finish.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const EINTR: i32 = 4;
+const EBUSY: i32 = 16;
+const ETIME: i32 = 62;
+const ENOBUFS: i32 = 105;
+const ECONNRESET: i32 = 104;
+const ECONNREFUSED: i32 = 111;
+const MSG_TRUNC: u32 = 0x20;
+
+#[repr(C)]
+struct IoVec {
+    base: *mut c_void,
+    len: usize,
+}
+
+#[repr(C)]
+struct MsgHdr {
+    name: *mut c_void,
+    name_len: u32,
+    iov: *mut IoVec,
+    iov_len: usize,
+    control: *mut c_void,
+    control_len: usize,
+    flags: c_int,
+}
+
+impl MsgHdr {
+    fn empty() -> MsgHdr {
+        MsgHdr {
+            name: ptr::null_mut(),
+            name_len: 0,
+            iov: ptr::null_mut(),
+            iov_len: 0,
+            control: ptr::null_mut(),
+            control_len: 0,
+            flags: 0,
+        }
+    }
+}
+
+/// An `io_uring_buf`; the tail of the ring overlays the last field of the
+/// first one.
+#[repr(C)]
+struct BufEntry {
+    addr: u64,
+    len: u32,
+    bid: u16,
+    resv: u16,
+}
+
+/// The buffers the kernel receives into, and the ring it takes them from.
+struct Buffers {
+    ring: ptr::NonNull<BufEntry>,
+    tail: u16,
+    memory: Vec<u8>,
+}
+
+impl Buffers {
+    fn layout() -> std::alloc::Layout {
+        let size = usize::from(BUFFERS) * std::mem::size_of::<BufEntry>();
+        // The kernel wants the ring page-aligned.
+        std::alloc::Layout::from_size_align(size, 4096).expect("buffer ring layout")
+    }
+
+    fn new() -> Buffers {
+        let layout = Buffers::layout();
+        let ring = unsafe { std::alloc::alloc_zeroed(layout) } as *mut BufEntry;
+        let ring =
+            ptr::NonNull::new(ring).unwrap_or_else(|| std::alloc::handle_alloc_error(layout));
+        let mut buffers = Buffers {
+            ring,
+            tail: 0,
+            memory: vec![0; usize::from(BUFFERS) * BUFFER_SIZE],
+        };
+        for bid in 0..BUFFERS {
+            buffers.put(bid);
+        }
+        buffers.publish();
+        buffers
+    }
+
+    /// Hands buffer `bid` back; the kernel sees it after
+    /// [`publish`](Buffers::publish).
+    fn put(&mut self, bid: u16) {
+        let addr = self.memory[usize::from(bid) * BUFFER_SIZE..].as_mut_ptr();
+        let slot = usize::from(self.tail & (BUFFERS - 1));
+        unsafe {
+            let entry = self.ring.as_ptr().add(slot);
+            (*entry).addr = addr as u64;
+            (*entry).len = BUFFER_SIZE as u32;
+            (*entry).bid = bid;
+        }
+        self.tail = self.tail.wrapping_add(1);
+    }
+
+    fn publish(&mut self) {
+        unsafe {
+            let tail = ptr::addr_of_mut!((*self.ring.as_ptr()).resv) as *const AtomicU16;
+            (*tail).store(self.tail, Ordering::Release);
+        }
+    }
+
+    fn get(&self, bid: u16, len: usize) -> &[u8] {
+        let start = usize::from(bid) * BUFFER_SIZE;
+        &self.memory[start..start + len.min(BUFFER_SIZE)]
+    }
+}
+
+impl Drop for Buffers {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.ring.as_ptr() as *mut u8, Buffers::layout()) }
+    }
+}
+
+/// A response being sent, with what `sendmsg` points to.
+struct Outgoing {
+    wire: Vec<u8>,
+    name: [u8; 28],
+    iov: IoVec,
+    hdr: MsgHdr,
+}
+
+/// An io_uring instance serving one UDP socket.
+pub(super) struct Ring {
+    // Dropped first, so that the kernel lets go of the buffers before
+    // they are freed.
+    ring: IoUring,
+    buffers: Buffers,
+    recv: Box<MsgHdr>,
+    armed: bool,
+    // Boxed, as the kernel holds pointers into them while the vector
+    // grows.
+    #[allow(clippy::vec_box)]
+    sends: Vec<Box<Outgoing>>,
+    free: Vec<usize>,
+    fd: types::Fd,
+}
+
+impl Ring {
+    /// Sets up a ring for `socket` and registers its buffers.
+    pub(super) fn new(socket: &UdpSocket) -> io::Result<Ring> {
+        let ring = IoUring::new(ENTRIES)?;
+        let buffers = Buffers::new();
+        unsafe {
+            ring.submitter().register_buf_ring_with_flags(
+                buffers.ring.as_ptr() as u64,
+                BUFFERS,
+                GROUP,
+                0,
+            )?;
+        }
+        let mut recv = Box::new(MsgHdr::empty());
+        recv.name_len = NAME_LEN as u32;
+        Ok(Ring {
+            ring,
+            buffers,
+            recv,
+            armed: false,
+            sends: Vec::new(),
+            free: Vec::new(),
+            fd: types::Fd(socket.as_raw_fd()),
+        })
+    }
+
+    /// Answers datagrams until the server is shut down or `open` is
+    /// cleared.
+    pub(super) fn serve(mut self, frontend: &Frontend, open: &AtomicBool) -> io::Result<()> {
+        let result = self.run(
+            |data, src| {
+                let reply = frontend.dispatch(data, src, Transport::Udp);
+                reply.messages.into_iter().next().filter(|w| !w.is_empty())
+            },
+            || frontend.state.is_stopping() || !open.load(Ordering::SeqCst),
+        );
+        self.drain();
+        result
+    }
+
+    fn run<F, S>(&mut self, mut answer: F, mut stopping: S) -> io::Result<()>
+    where
+        F: FnMut(&[u8], SocketAddr) -> Option<Vec<u8>>,
+        S: FnMut() -> bool,
+    {
+        let timeout = types::Timespec::from(UDP_POLL_INTERVAL);
+        let args = types::SubmitArgs::new().timespec(&timeout);
+        let mut completed = Vec::with_capacity(2 * ENTRIES as usize);
+        while !stopping() {
+            if !self.armed {
+                self.arm()?;
+            }
+            wait(&self.ring, &args)?;
+            completed.clear();
+            completed.extend(
+                self.ring
+                    .completion()
+                    .map(|c| (c.user_data(), c.result(), c.flags())),
+            );
+            for &(data, result, flags) in &completed {
+                match data {
+                    RECV => self.received(result, flags, &mut answer)?,
+                    CANCEL => {}
+                    slot => self.sent(slot as usize),
+                }
+            }
+            self.buffers.publish();
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, entry: &squeue::Entry) -> io::Result<()> {
+        loop {
+            if unsafe { self.ring.submission().push(entry) }.is_ok() {
+                return Ok(());
+            }
+            self.ring.submit()?;
+        }
+    }
+
+    fn arm(&mut self) -> io::Result<()> {
+        let entry =
+            opcode::RecvMsgMulti::new(self.fd, &*self.recv as *const MsgHdr as *const _, GROUP)
+                .build()
+                .user_data(RECV);
+        self.push(&entry)?;
+        self.armed = true;
+        Ok(())
+    }
+
+    fn received<F>(&mut self, result: i32, flags: u32, answer: &mut F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], SocketAddr) -> Option<Vec<u8>>,
+    {
+        if !cqueue::more(flags) {
+            self.armed = false;
+        }
+        let bid = match cqueue::buffer_select(flags) {
+            Some(bid) => bid,
+            // Running out of buffers ends the receive; it is armed again.
+            None if result >= 0 || recoverable(-result) => return Ok(()),
+            None => return Err(io::Error::from_raw_os_error(-result)),
+        };
+        let reply = if result > 0 {
+            let buf = self.buffers.get(bid, result as usize);
+            datagram(buf).and_then(|(data, src)| Some((answer(data, src)?, src)))
+        } else {
+            None
+        };
+        self.buffers.put(bid);
+        if let Some((wire, dst)) = reply {
+            self.send(wire, dst)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, wire: Vec<u8>, dst: SocketAddr) -> io::Result<()> {
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None if self.sends.len() < MAX_SENDS => {
+                self.sends.push(Box::new(Outgoing {
+                    wire: Vec::new(),
+                    name: [0; 28],
+                    iov: IoVec {
+                        base: ptr::null_mut(),
+                        len: 0,
+                    },
+                    hdr: MsgHdr::empty(),
+                }));
+                self.sends.len() - 1
+            }
+            // As with a send that fails, only this client is let down.
+            None => return Ok(()),
+        };
+        let send = &mut *self.sends[slot];
+        let (name, name_len) = sys::encode_sockaddr(&dst);
+        send.wire = wire;
+        send.name = name;
+        send.iov = IoVec {
+            base: send.wire.as_mut_ptr() as *mut c_void,
+            len: send.wire.len(),
+        };
+        send.hdr = MsgHdr {
+            name: send.name.as_mut_ptr() as *mut c_void,
+            name_len,
+            iov: &mut send.iov,
+            iov_len: 1,
+            ..MsgHdr::empty()
+        };
+        let entry = opcode::SendMsg::new(self.fd, &send.hdr as *const MsgHdr as *const _)
+            .build()
+            .user_data(slot as u64);
+        self.push(&entry)
+    }
+
+    fn sent(&mut self, slot: usize) {
+        if let Some(send) = self.sends.get_mut(slot) {
+            send.wire = Vec::new();
+            self.free.push(slot);
+        }
+    }
+
+    /// Cancels the receive and waits a while for sends under way.
+    fn drain(&mut self) {
+        if self.armed {
+            let entry = opcode::AsyncCancel::new(RECV).build().user_data(CANCEL);
+            if self.push(&entry).is_err() {
+                return;
+            }
+        }
+        let timeout = types::Timespec::from(UDP_POLL_INTERVAL);
+        let args = types::SubmitArgs::new().timespec(&timeout);
+        let deadline = std::time::Instant::now() + DRAIN_TIMEOUT;
+        while (self.armed || self.free.len() < self.sends.len())
+            && std::time::Instant::now() < deadline
+        {
+            if wait(&self.ring, &args).is_err() {
+                return;
+            }
+            let completed: Vec<_> = self
+                .ring
+                .completion()
+                .map(|c| (c.user_data(), c.flags()))
+                .collect();
+            for (data, flags) in completed {
+                match data {
+                    RECV if !cqueue::more(flags) => self.armed = false,
+                    RECV | CANCEL => {}
+                    slot => self.sent(slot as usize),
+                }
+            }
+        }
+    }
+}
+
+/// Submits what is queued and waits for a completion or the timeout.
+fn wait(ring: &IoUring, args: &types::SubmitArgs<'_, '_>) -> io::Result<()> {
+    match ring.submitter().submit_with_args(1, args) {
+        Ok(_) => Ok(()),
+        Err(e) if e.raw_os_error().is_some_and(recoverable) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Errors after which the ring goes on: timeouts, signals, a full
+/// completion queue, no free buffer, and ICMP errors from earlier sends.
+fn recoverable(errno: i32) -> bool {
+    matches!(
+        errno,
+        EINTR | EBUSY | ETIME | ENOBUFS | ECONNRESET | ECONNREFUSED
+    )
+}
+
+/// The datagram and its source in what multishot `recvmsg` wrote to a
+/// buffer. Truncated datagrams are left out.
+fn datagram(buf: &[u8]) -> Option<(&[u8], SocketAddr)> {
+    let word = |at: usize| -> Option<u32> {
+        let bytes = buf.get(at..at + 4)?;
+        Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let name_len = word(0)? as usize;
+    let payload_len = word(8)? as usize;
+    if word(12)? & MSG_TRUNC != 0 {
+        return None;
+    }
+    // The name takes the room the header asked for, whatever its length;
+    // no control data was asked for.
+    let name = buf.get(HEADER_LEN..HEADER_LEN + name_len.min(NAME_LEN))?;
+    let start = HEADER_LEN + NAME_LEN;
+    let payload = buf.get(start..start + payload_len)?;
+    Some((payload, sys::decode_sockaddr(name).ok()?))
+}
+
+/// Whether io_uring can serve UDP here, found out once by echoing a
+/// datagram over loopback.
+pub(super) fn supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| probe().is_ok())
+}
+
+fn probe() -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let mut ring = Ring::new(&socket)?;
+    socket.send_to(&[0; 12], socket.local_addr()?)?;
+    let seen = Cell::new(false);
+    let mut polls = 0;
+    let result = ring.run(
+        |_, _| {
+            seen.set(true);
+            None
+        },
+        || {
+            polls += 1;
+            seen.get() || polls > 5
+        },
+    );
+    ring.drain();
+    result?;
+    if seen.get() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "multishot recvmsg is not supported",
+        ))
+    }
+}
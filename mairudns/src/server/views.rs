@@ -0,0 +1,114 @@
+//! Split-horizon DNS: different answers depending on who is asking.
+
+use std::fmt;
+
+use crate::addr::IpSet;
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+
+use super::{Handler, Request};
+
+/// A handler chain serving the clients in `clients` and, if any keys are
+/// given, only requests signed with one of them.
+pub struct View {
+    name: String,
+    clients: IpSet,
+    keys: Vec<DomainName>,
+    handler: Box<dyn Handler>,
+}
+
+impl View {
+    pub fn new<H: Handler>(name: impl Into<String>, clients: IpSet, handler: H) -> View {
+        View {
+            name: name.into(),
+            clients,
+            keys: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Also requires requests to be signed with `key` or one of the other
+    /// keys added.
+    pub fn key(mut self, key: DomainName) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn clients(&self) -> &IpSet {
+        &self.clients
+    }
+
+    pub fn keys(&self) -> &[DomainName] {
+        &self.keys
+    }
+
+    pub fn handler(&self) -> &dyn Handler {
+        &*self.handler
+    }
+
+    pub fn matches(&self, request: &Request) -> bool {
+        self.clients.contains(&request.src.ip())
+            && (self.keys.is_empty() || request.key.as_ref().is_some_and(|k| self.keys.contains(k)))
+    }
+}
+
+impl fmt::Debug for View {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("View")
+            .field("name", &self.name)
+            .field("clients", &self.clients)
+            .field("keys", &self.keys)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends each request to the first [`View`] matching its client, so that
+/// internal clients can see internal records. Requests no view matches are
+/// refused; a last view for [`IpSet::any`] serves everyone else.
+///
+/// Each view has a handler chain of its own, so a cache inside one view —
+/// such as a [`Forwarder`](super::Forwarder)'s — never answers another
+/// view's clients. Layers wrapped around `Views` itself that keep state
+/// per answer should key it by [`Views::select`] as well as the question.
+#[derive(Debug, Default)]
+pub struct Views {
+    views: Vec<View>,
+}
+
+impl Views {
+    pub fn new() -> Views {
+        Views::default()
+    }
+
+    /// Adds a view after those already added.
+    pub fn view(mut self, view: View) -> Self {
+        self.views.push(view);
+        self
+    }
+
+    pub fn views(&self) -> &[View] {
+        &self.views
+    }
+
+    /// The view serving `request`.
+    pub fn select(&self, request: &Request) -> Option<&View> {
+        self.views.iter().find(|v| v.matches(request))
+    }
+}
+
+impl Handler for Views {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        match self.select(request) {
+            Some(view) => view.handler.handle(request),
+            None => {
+                let mut resp = request.message.response();
+                resp.header.rcode = Rcode::REFUSED;
+                Some(resp)
+            }
+        }
+    }
+}
@@ -0,0 +1,1440 @@
+//! An experimental AF_XDP fast path for queries the forwarding cache can
+//! answer.
+//!
+//! An XDP program attached to the interface hands UDP datagrams for the
+//! listener's port, and its address unless that is a wildcard, to AF_XDP
+//! sockets, one per receive queue; everything else goes on to the kernel.
+//! Datagrams arrive in memory shared with the kernel, the UMEM. A query
+//! for A or AAAA records that the [`Forwarder`] cache answers is read in
+//! place, its response built in an [`Arena`] kept by the socket's thread
+//! and copied over the query, and the frame sent back out through the
+//! same socket, so that neither the query nor its response crosses the
+//! kernel's network stack and answering allocates nothing. Other
+//! datagrams, and answers the response policy would have to cut down, are
+//! copied out and punted to threads that answer them as the UDP listener
+//! would, through the whole handler, with replies sent from the
+//! listener's own socket.
+//!
+//! Cache hits bypass the handler, so the configuration refuses XDP
+//! alongside query or recursion access lists, rate limits, quotas and
+//! views; query logs and plugins do not see hits either. Only untagged
+//! Ethernet frames carrying IPv4 without options or IPv6 without
+//! extension headers take the fast path. The kernel uses zero-copy mode
+//! where the driver supports it, and copies frames into the UMEM
+//! elsewhere.
+
+use std::ffi::CString;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_void};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::arena::{Arena, MessageRef};
+use crate::message::{Opcode, Question};
+use crate::name::DomainName;
+use crate::rr::RecordType;
+
+use super::cache::CacheKey;
+use super::{Frontend, SharedForwarder, Transport, MIN_UDP_SIZE, UDP_POLL_INTERVAL};
+
+/// Size of each frame of the UMEM; a frame holds one packet.
+const FRAME_SIZE: usize = 4096;
+
+/// Frames in the UMEM of each socket.
+const FRAMES: usize = 4096;
+
+/// Entries in each of the four rings of a socket, a power of two.
+const RING_SIZE: u32 = 2048;
+
+/// Packets taken from the receive ring at a time.
+const BATCH: u32 = 64;
+
+/// Datagrams waiting for a punt thread; beyond that, they are dropped.
+const PUNT_QUEUE: usize = 1024;
+
+const ETH_LEN: usize = 14;
+const IPV4_LEN: usize = 20;
+const IPV6_LEN: usize = 40;
+const UDP_LEN: usize = 8;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const IPPROTO_UDP: u8 = 17;
+
+/// Hop limit of responses sent from the fast path.
+const HOP_LIMIT: u8 = 64;
+
+const AF_XDP: c_int = 44;
+const SOCK_RAW: c_int = 3;
+const SOL_XDP: c_int = 283;
+const XDP_MMAP_OFFSETS: c_int = 1;
+const XDP_RX_RING: c_int = 2;
+const XDP_TX_RING: c_int = 3;
+const XDP_UMEM_REG: c_int = 4;
+const XDP_UMEM_FILL_RING: c_int = 5;
+const XDP_UMEM_COMPLETION_RING: c_int = 6;
+const XDP_USE_NEED_WAKEUP: u16 = 1 << 3;
+const XDP_RING_NEED_WAKEUP: u32 = 1;
+
+const XDP_PGOFF_RX_RING: i64 = 0;
+const XDP_PGOFF_TX_RING: i64 = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: i64 = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: i64 = 0x1_8000_0000;
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+const MAP_PRIVATE: c_int = 2;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_POPULATE: c_int = 0x8000;
+
+const POLLIN: i16 = 1;
+const MSG_DONTWAIT: c_int = 0x40;
+
+#[cfg(target_arch = "x86_64")]
+const SYS_BPF: c_long = 321;
+#[cfg(not(target_arch = "x86_64"))]
+const SYS_BPF: c_long = 280;
+
+const BPF_MAP_CREATE: c_int = 0;
+const BPF_MAP_UPDATE_ELEM: c_int = 2;
+const BPF_PROG_LOAD: c_int = 5;
+const BPF_LINK_CREATE: c_int = 28;
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const XDP_PASS: i32 = 2;
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: i16,
+    revents: i16,
+}
+
+extern "C" {
+    fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+    fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+    fn getsockopt(fd: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32)
+        -> c_int;
+    fn bind(fd: c_int, addr: *const u8, len: u32) -> c_int;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn poll(fds: *mut PollFd, n: u64, timeout: c_int) -> c_int;
+    fn sendto(
+        fd: c_int,
+        buf: *const c_void,
+        len: usize,
+        flags: c_int,
+        addr: *const c_void,
+        addr_len: u32,
+    ) -> isize;
+    fn syscall(number: c_long, ...) -> c_long;
+    fn if_nametoindex(name: *const c_char) -> c_uint;
+}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Memory mapped with `mmap()`, unmapped when dropped.
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(len: usize, flags: c_int, fd: c_int, offset: i64) -> io::Result<Mapping> {
+        let prot = PROT_READ | PROT_WRITE;
+        let addr = unsafe { mmap(ptr::null_mut(), len, prot, flags, fd, offset) };
+        if addr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            addr: addr as *mut u8,
+            len,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { munmap(self.addr as *mut c_void, self.len) };
+    }
+}
+
+/// Where the fields of a ring are in its mapping, as `XDP_MMAP_OFFSETS`
+/// reports them.
+#[derive(Clone, Copy)]
+struct RingOffsets {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+/// One of the four single-producer, single-consumer rings of a socket.
+/// The fill and transmit rings are produced here, the receive and
+/// completion rings consumed.
+struct Ring {
+    map: Mapping,
+    offsets: RingOffsets,
+    entry: usize,
+    /// The next entry to produce or consume.
+    head: u32,
+}
+
+impl Ring {
+    fn new(fd: c_int, offsets: RingOffsets, entry: usize, page: i64) -> io::Result<Ring> {
+        let len = offsets.desc as usize + RING_SIZE as usize * entry;
+        let map = Mapping::new(len, MAP_SHARED | MAP_POPULATE, fd, page)?;
+        let mut ring = Ring {
+            map,
+            offsets,
+            entry,
+            head: 0,
+        };
+        ring.head = ring.producer().load(Ordering::Acquire);
+        Ok(ring)
+    }
+
+    fn field(&self, offset: u64) -> &AtomicU32 {
+        unsafe { &*(self.map.addr.add(offset as usize) as *const AtomicU32) }
+    }
+
+    fn producer(&self) -> &AtomicU32 {
+        self.field(self.offsets.producer)
+    }
+
+    fn consumer(&self) -> &AtomicU32 {
+        self.field(self.offsets.consumer)
+    }
+
+    fn needs_wakeup(&self) -> bool {
+        self.field(self.offsets.flags).load(Ordering::Relaxed) & XDP_RING_NEED_WAKEUP != 0
+    }
+
+    fn slot(&self, index: u32) -> *mut u8 {
+        let i = (index & (RING_SIZE - 1)) as usize;
+        unsafe {
+            self.map
+                .addr
+                .add(self.offsets.desc as usize + i * self.entry)
+        }
+    }
+
+    /// Entries free for the producer.
+    fn free(&self) -> u32 {
+        let consumed = self.consumer().load(Ordering::Acquire);
+        RING_SIZE - self.head.wrapping_sub(consumed)
+    }
+
+    /// Entries ready for the consumer.
+    fn ready(&self) -> u32 {
+        self.producer()
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.head)
+    }
+
+    /// Writes a frame address, on the fill ring.
+    fn put_addr(&mut self, addr: u64) {
+        unsafe { (self.slot(self.head) as *mut u64).write(addr) };
+        self.head = self.head.wrapping_add(1);
+    }
+
+    /// Writes a packet descriptor, on the transmit ring.
+    fn put_desc(&mut self, addr: u64, len: u32) {
+        let slot = self.slot(self.head);
+        unsafe {
+            (slot as *mut u64).write(addr);
+            (slot.add(8) as *mut u32).write(len);
+            (slot.add(12) as *mut u32).write(0);
+        }
+        self.head = self.head.wrapping_add(1);
+    }
+
+    /// Makes what was written visible to the kernel.
+    fn publish(&self) {
+        self.producer().store(self.head, Ordering::Release);
+    }
+
+    /// Reads a frame address, from the completion ring.
+    fn take_addr(&mut self) -> u64 {
+        let addr = unsafe { (self.slot(self.head) as *const u64).read() };
+        self.head = self.head.wrapping_add(1);
+        addr
+    }
+
+    /// Reads a packet descriptor, from the receive ring.
+    fn take_desc(&mut self) -> (u64, u32) {
+        let slot = self.slot(self.head);
+        let desc = unsafe {
+            (
+                (slot as *const u64).read(),
+                (slot.add(8) as *const u32).read(),
+            )
+        };
+        self.head = self.head.wrapping_add(1);
+        desc
+    }
+
+    /// Gives what was read back to the kernel.
+    fn release(&self) {
+        self.consumer().store(self.head, Ordering::Release);
+    }
+}
+
+/// An AF_XDP socket bound to one receive queue, with its UMEM and rings.
+pub(super) struct Socket {
+    fd: OwnedFd,
+    umem: Mapping,
+    fill: Ring,
+    completion: Ring,
+    rx: Ring,
+    tx: Ring,
+    /// Frames neither the kernel nor a ring holds.
+    spare: Vec<u64>,
+}
+
+// The rings and the UMEM belong to the socket alone.
+unsafe impl Send for Socket {}
+
+impl Socket {
+    fn new(ifindex: u32, queue: u32) -> io::Result<Socket> {
+        let fd = check(unsafe { socket(AF_XDP, SOCK_RAW, 0) })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let raw = fd.as_raw_fd();
+        let umem = Mapping::new(
+            FRAMES * FRAME_SIZE,
+            MAP_PRIVATE | MAP_ANONYMOUS | MAP_POPULATE,
+            -1,
+            0,
+        )?;
+        // struct xdp_umem_reg: address, length, chunk size, headroom,
+        // flags and the length of TX metadata.
+        let mut reg = [0u8; 32];
+        reg[..8].copy_from_slice(&(umem.addr as u64).to_ne_bytes());
+        reg[8..16].copy_from_slice(&(umem.len as u64).to_ne_bytes());
+        reg[16..20].copy_from_slice(&(FRAME_SIZE as u32).to_ne_bytes());
+        set(raw, XDP_UMEM_REG, &reg)?;
+        for option in [
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            set(raw, option, &RING_SIZE.to_ne_bytes())?;
+        }
+        let mut offsets = [0u64; 16];
+        let mut len = std::mem::size_of_val(&offsets) as u32;
+        check(unsafe {
+            getsockopt(
+                raw,
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                offsets.as_mut_ptr() as *mut c_void,
+                &mut len,
+            )
+        })?;
+        if len as usize != std::mem::size_of_val(&offsets) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "AF_XDP rings without flags",
+            ));
+        }
+        let ring = |i: usize| RingOffsets {
+            producer: offsets[4 * i],
+            consumer: offsets[4 * i + 1],
+            desc: offsets[4 * i + 2],
+            flags: offsets[4 * i + 3],
+        };
+        let rx = Ring::new(raw, ring(0), 16, XDP_PGOFF_RX_RING)?;
+        let tx = Ring::new(raw, ring(1), 16, XDP_PGOFF_TX_RING)?;
+        let fill = Ring::new(raw, ring(2), 8, XDP_UMEM_PGOFF_FILL_RING)?;
+        let completion = Ring::new(raw, ring(3), 8, XDP_UMEM_PGOFF_COMPLETION_RING)?;
+
+        // struct sockaddr_xdp: family, flags, interface, queue and the
+        // socket whose UMEM is shared.
+        let mut addr = [0u8; 16];
+        addr[..2].copy_from_slice(&(AF_XDP as u16).to_ne_bytes());
+        addr[2..4].copy_from_slice(&XDP_USE_NEED_WAKEUP.to_ne_bytes());
+        addr[4..8].copy_from_slice(&ifindex.to_ne_bytes());
+        addr[8..12].copy_from_slice(&queue.to_ne_bytes());
+        check(unsafe { bind(raw, addr.as_ptr(), addr.len() as u32) })?;
+
+        let mut socket = Socket {
+            fd,
+            umem,
+            fill,
+            completion,
+            rx,
+            tx,
+            spare: (0..FRAMES).map(|i| (i * FRAME_SIZE) as u64).rev().collect(),
+        };
+        socket.refill();
+        Ok(socket)
+    }
+
+    /// Hands spare frames to the kernel to receive into.
+    fn refill(&mut self) {
+        let n = (self.fill.free() as usize).min(self.spare.len());
+        for _ in 0..n {
+            let addr = self.spare.pop().unwrap_or_default();
+            self.fill.put_addr(addr);
+        }
+        if n > 0 {
+            self.fill.publish();
+        }
+    }
+
+    /// Takes back the frames of responses sent.
+    fn reclaim(&mut self) {
+        let n = self.completion.ready();
+        for _ in 0..n {
+            let addr = self.completion.take_addr();
+            self.spare.push(addr - addr % FRAME_SIZE as u64);
+        }
+        if n > 0 {
+            self.completion.release();
+        }
+    }
+
+    /// Waits for packets, or for the poll interval to pass.
+    fn wait(&self) {
+        let mut fds = PollFd {
+            fd: self.fd.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+        let timeout = UDP_POLL_INTERVAL.as_millis() as c_int;
+        unsafe { poll(&mut fds, 1, timeout) };
+    }
+
+    /// Tells the kernel there is something to send.
+    fn kick(&self) {
+        unsafe {
+            sendto(
+                self.fd.as_raw_fd(),
+                ptr::null(),
+                0,
+                MSG_DONTWAIT,
+                ptr::null(),
+                0,
+            )
+        };
+    }
+
+    fn serve(mut self, fast: &mut FastPath) -> io::Result<()> {
+        while !fast.frontend.state.is_stopping() {
+            self.reclaim();
+            self.refill();
+            if self.rx.ready() == 0 {
+                // Polling also wakes the kernel up to use the fill ring.
+                self.wait();
+                continue;
+            }
+            let n = self.rx.ready().min(BATCH);
+            let mut sent = 0;
+            for _ in 0..n {
+                let (addr, len) = self.rx.take_desc();
+                let base = addr - addr % FRAME_SIZE as u64;
+                let start = addr as usize;
+                let end = base as usize + FRAME_SIZE;
+                let frame = unsafe {
+                    std::slice::from_raw_parts_mut(self.umem.addr.add(start), end - start)
+                };
+                let answered = match fast.handle(frame, len as usize) {
+                    Some(len) if self.tx.free() > 0 => Some(len),
+                    _ => None,
+                };
+                match answered {
+                    Some(len) => {
+                        self.tx.put_desc(addr, len as u32);
+                        sent += 1;
+                    }
+                    None => self.spare.push(base),
+                }
+            }
+            self.rx.release();
+            if sent > 0 {
+                self.tx.publish();
+                if self.tx.needs_wakeup() {
+                    self.kick();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set(fd: c_int, option: c_int, value: &[u8]) -> io::Result<()> {
+    let ptr = value.as_ptr() as *const c_void;
+    check(unsafe { setsockopt(fd, SOL_XDP, option, ptr, value.len() as u32) }).map(drop)
+}
+
+/// Arguments of a `bpf()` call, a `union bpf_attr`.
+struct Attr([u64; 16]);
+
+impl Attr {
+    fn new() -> Attr {
+        Attr([0; 16])
+    }
+
+    fn u32(mut self, offset: usize, value: u32) -> Attr {
+        let word = &mut self.0[offset / 8];
+        let mut bytes = word.to_ne_bytes();
+        bytes[offset % 8..offset % 8 + 4].copy_from_slice(&value.to_ne_bytes());
+        *word = u64::from_ne_bytes(bytes);
+        self
+    }
+
+    fn u64(mut self, offset: usize, value: u64) -> Attr {
+        self.0[offset / 8] = value;
+        self
+    }
+
+    fn call(mut self, cmd: c_int) -> io::Result<OwnedFd> {
+        let size = std::mem::size_of_val(&self.0);
+        let ret = unsafe { syscall(SYS_BPF, cmd, self.0.as_mut_ptr(), size) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(ret as c_int) })
+    }
+}
+
+/// A jump target in the program.
+type Label = usize;
+
+/// Assembles eBPF instructions.
+#[derive(Default)]
+struct Asm {
+    insns: Vec<[u8; 8]>,
+    /// Where each label is, once placed.
+    labels: Vec<usize>,
+    jumps: Vec<(usize, Label)>,
+}
+
+const R0: u8 = 0;
+const R1: u8 = 1;
+const R2: u8 = 2;
+const R3: u8 = 3;
+const R4: u8 = 4;
+const R5: u8 = 5;
+const R6: u8 = 6;
+const R7: u8 = 7;
+
+impl Asm {
+    fn insn(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) {
+        let mut insn = [0u8; 8];
+        insn[0] = code;
+        insn[1] = dst | src << 4;
+        insn[2..4].copy_from_slice(&off.to_ne_bytes());
+        insn[4..].copy_from_slice(&imm.to_ne_bytes());
+        self.insns.push(insn);
+    }
+
+    fn new_label(&mut self) -> Label {
+        self.labels.push(usize::MAX);
+        self.labels.len() - 1
+    }
+
+    fn label(&mut self, label: Label) {
+        self.labels[label] = self.insns.len();
+    }
+
+    /// `dst = src`
+    fn mov(&mut self, dst: u8, src: u8) {
+        self.insn(0xbf, dst, src, 0, 0);
+    }
+
+    /// `dst = imm`
+    fn mov_imm(&mut self, dst: u8, imm: i32) {
+        self.insn(0xb7, dst, 0, 0, imm);
+    }
+
+    /// `dst += imm`
+    fn add_imm(&mut self, dst: u8, imm: i32) {
+        self.insn(0x07, dst, 0, 0, imm);
+    }
+
+    /// `dst &= imm`
+    fn and_imm(&mut self, dst: u8, imm: i32) {
+        self.insn(0x57, dst, 0, 0, imm);
+    }
+
+    /// Loads `size` bytes (1, 2 or 4) at `src + off` into `dst`.
+    fn load(&mut self, size: u8, dst: u8, src: u8, off: i16) {
+        let code = match size {
+            1 => 0x71,
+            2 => 0x69,
+            _ => 0x61,
+        };
+        self.insn(code, dst, src, off, 0);
+    }
+
+    /// Loads the descriptor of map `fd` into `dst`.
+    fn load_map(&mut self, dst: u8, fd: c_int) {
+        // BPF_PSEUDO_MAP_FD in the source register.
+        self.insn(0x18, dst, 1, 0, fd);
+        self.insn(0, 0, 0, 0, 0);
+    }
+
+    fn jump(&mut self, code: u8, dst: u8, src: u8, imm: i32, to: Label) {
+        self.jumps.push((self.insns.len(), to));
+        self.insn(code, dst, src, 0, imm);
+    }
+
+    /// Jumps if the low 32 bits of `dst` differ from `imm`.
+    fn jne(&mut self, dst: u8, imm: u32, to: Label) {
+        self.jump(0x56, dst, 0, imm as i32, to);
+    }
+
+    /// Jumps if the low 32 bits of `dst` equal `imm`.
+    fn jeq(&mut self, dst: u8, imm: u32, to: Label) {
+        self.jump(0x16, dst, 0, imm as i32, to);
+    }
+
+    /// Jumps if `dst > src`, unsigned.
+    fn jgt(&mut self, dst: u8, src: u8, to: Label) {
+        self.jump(0x2d, dst, src, 0, to);
+    }
+
+    fn ja(&mut self, to: Label) {
+        self.jump(0x05, 0, 0, 0, to);
+    }
+
+    fn call(&mut self, helper: i32) {
+        self.insn(0x85, 0, 0, 0, helper);
+    }
+
+    fn exit(&mut self) {
+        self.insn(0x95, 0, 0, 0, 0);
+    }
+
+    fn finish(mut self) -> Vec<[u8; 8]> {
+        for &(at, to) in &self.jumps {
+            let target = self.labels[to];
+            let off = (target as isize - at as isize - 1) as i16;
+            self.insns[at][2..4].copy_from_slice(&off.to_ne_bytes());
+        }
+        self.insns
+    }
+}
+
+/// A network-order field as a load on this host reads it.
+fn be16(v: u16) -> u32 {
+    u32::from(u16::from_ne_bytes(v.to_be_bytes()))
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// The XDP program: UDP to any of `addrs` goes to the socket of its
+/// receive queue, anything else to the kernel.
+fn program(addrs: &[SocketAddr], map: c_int) -> Vec<[u8; 8]> {
+    let mut a = Asm::default();
+    let (ipv4, ipv6) = (a.new_label(), a.new_label());
+    let (redirect, pass) = (a.new_label(), a.new_label());
+    // r6 = ctx, r2 = data, r3 = data_end.
+    a.mov(R6, R1);
+    a.load(4, R2, R1, 0);
+    a.load(4, R3, R1, 4);
+    a.mov(R4, R2);
+    a.add_imm(R4, ETH_LEN as i32);
+    a.jgt(R4, R3, pass);
+    a.load(2, R5, R2, 12);
+    a.jeq(R5, be16(ETHERTYPE_IPV4), ipv4);
+    a.jeq(R5, be16(ETHERTYPE_IPV6), ipv6);
+    a.ja(pass);
+
+    a.label(ipv4);
+    a.mov(R4, R2);
+    a.add_imm(R4, (ETH_LEN + IPV4_LEN + UDP_LEN) as i32);
+    a.jgt(R4, R3, pass);
+    // Version 4 without options.
+    a.load(1, R5, R2, 14);
+    a.jne(R5, 0x45, pass);
+    a.load(1, R5, R2, 23);
+    a.jne(R5, u32::from(IPPROTO_UDP), pass);
+    // Fragments are for the kernel to reassemble.
+    a.load(2, R5, R2, 20);
+    a.and_imm(R5, be16(0x3fff) as i32);
+    a.jne(R5, 0, pass);
+    a.load(2, R7, R2, (ETH_LEN + IPV4_LEN + 2) as i16);
+    for addr in addrs {
+        let ip = match addr.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => continue,
+        };
+        let next = a.new_label();
+        a.jne(R7, be16(addr.port()), next);
+        if !ip.is_unspecified() {
+            a.load(4, R5, R2, 30);
+            a.jne(R5, be32(&ip.octets()), next);
+        }
+        a.ja(redirect);
+        a.label(next);
+    }
+    a.ja(pass);
+
+    a.label(ipv6);
+    a.mov(R4, R2);
+    a.add_imm(R4, (ETH_LEN + IPV6_LEN + UDP_LEN) as i32);
+    a.jgt(R4, R3, pass);
+    a.load(1, R5, R2, 20);
+    a.jne(R5, u32::from(IPPROTO_UDP), pass);
+    a.load(2, R7, R2, (ETH_LEN + IPV6_LEN + 2) as i16);
+    for addr in addrs {
+        let ip = match addr.ip() {
+            IpAddr::V6(ip) => ip,
+            IpAddr::V4(_) => continue,
+        };
+        let next = a.new_label();
+        a.jne(R7, be16(addr.port()), next);
+        if !ip.is_unspecified() {
+            for (i, word) in ip.octets().chunks(4).enumerate() {
+                a.load(4, R5, R2, 38 + 4 * i as i16);
+                a.jne(R5, be32(word), next);
+            }
+        }
+        a.ja(redirect);
+        a.label(next);
+    }
+    a.ja(pass);
+
+    a.label(redirect);
+    // bpf_redirect_map(map, rx_queue_index, XDP_PASS)
+    a.load(4, R2, R6, 16);
+    a.load_map(R1, map);
+    a.mov_imm(R3, XDP_PASS);
+    a.call(BPF_FUNC_REDIRECT_MAP);
+    a.exit();
+
+    a.label(pass);
+    a.mov_imm(R0, XDP_PASS);
+    a.exit();
+    a.finish()
+}
+
+/// The program attached to an interface and the map of its sockets; the
+/// program is detached when this is dropped.
+struct Program {
+    _link: OwnedFd,
+    _prog: OwnedFd,
+    _map: OwnedFd,
+}
+
+impl Program {
+    fn attach(addrs: &[SocketAddr], ifindex: u32, sockets: &[Socket]) -> io::Result<Program> {
+        let map = Attr::new()
+            .u32(0, BPF_MAP_TYPE_XSKMAP)
+            .u32(4, 4)
+            .u32(8, 4)
+            .u32(12, sockets.len() as u32)
+            .call(BPF_MAP_CREATE)?;
+        for (queue, socket) in sockets.iter().enumerate() {
+            let key = queue as u32;
+            let value = socket.fd.as_raw_fd() as u32;
+            Attr::new()
+                .u32(0, map.as_raw_fd() as u32)
+                .u64(8, &key as *const u32 as u64)
+                .u64(16, &value as *const u32 as u64)
+                .call(BPF_MAP_UPDATE_ELEM)?;
+        }
+        let insns = program(addrs, map.as_raw_fd());
+        let license = b"Dual MIT/GPL\0";
+        let load = |log: &mut [u8]| {
+            // Without a log, its buffer must be null.
+            let buf = match log.is_empty() {
+                true => 0,
+                false => log.as_mut_ptr() as u64,
+            };
+            Attr::new()
+                .u32(0, BPF_PROG_TYPE_XDP)
+                .u32(4, insns.len() as u32)
+                .u64(8, insns.as_ptr() as u64)
+                .u64(16, license.as_ptr() as u64)
+                .u32(24, u32::from(!log.is_empty()))
+                .u32(28, log.len() as u32)
+                .u64(32, buf)
+                .u32(68, BPF_XDP)
+                .call(BPF_PROG_LOAD)
+        };
+        let prog = match load(&mut []) {
+            Ok(prog) => prog,
+            Err(e) => {
+                // Load again to learn what the verifier objected to.
+                let mut log = vec![0u8; 64 * 1024];
+                let _ = load(&mut log);
+                let end = log.iter().position(|&b| b == 0).unwrap_or(0);
+                let log = String::from_utf8_lossy(&log[..end]);
+                let reason = log.lines().rev().find(|l| !l.is_empty());
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("XDP program rejected: {}", reason.unwrap_or("unknown")),
+                ));
+            }
+        };
+        let link = Attr::new()
+            .u32(0, prog.as_raw_fd() as u32)
+            .u32(4, ifindex)
+            .u32(8, BPF_XDP)
+            .call(BPF_LINK_CREATE)?;
+        Ok(Program {
+            _link: link,
+            _prog: prog,
+            _map: map,
+        })
+    }
+}
+
+/// A punted query, its source, and which of the listener's addresses it
+/// was sent to.
+type Punted = (Vec<u8>, SocketAddr, usize);
+
+/// An AF_XDP listener, set up and waiting for the server to run.
+pub(super) struct Listener {
+    program: Arc<Program>,
+    sockets: Vec<Socket>,
+    /// The ordinary UDP socket of each address, which punted queries are
+    /// answered from.
+    udp: Vec<UdpSocket>,
+    addrs: Vec<SocketAddr>,
+    forwarder: SharedForwarder,
+}
+
+impl Listener {
+    /// Attaches to receive queues `0..queues` of `interface`, taking the
+    /// datagrams for the addresses of `udp`.
+    pub(super) fn new(
+        udp: Vec<UdpSocket>,
+        interface: &str,
+        queues: u32,
+        forwarder: SharedForwarder,
+    ) -> io::Result<Listener> {
+        let name = CString::new(interface)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "bad interface name"))?;
+        let ifindex = unsafe { if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let addrs = udp
+            .iter()
+            .map(|s| s.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        let sockets = (0..queues.max(1))
+            .map(|queue| Socket::new(ifindex, queue))
+            .collect::<io::Result<Vec<_>>>()?;
+        let program = Program::attach(&addrs, ifindex, &sockets)?;
+        Ok(Listener {
+            program: Arc::new(program),
+            sockets,
+            udp,
+            addrs,
+            forwarder,
+        })
+    }
+
+    /// Starts a thread for each queue and `punt_threads` threads for the
+    /// queries the cache cannot answer.
+    pub(super) fn spawn(
+        self,
+        frontend: &Arc<Frontend>,
+        punt_threads: usize,
+    ) -> io::Result<Vec<JoinHandle<io::Result<()>>>> {
+        let (punt, punted) = mpsc::sync_channel(PUNT_QUEUE);
+        let punted = Arc::new(Mutex::new(punted));
+        let mut threads = Vec::new();
+        for _ in 0..punt_threads.max(1) {
+            let udp = self
+                .udp
+                .iter()
+                .map(UdpSocket::try_clone)
+                .collect::<io::Result<Vec<_>>>()?;
+            let frontend = frontend.clone();
+            let punted = punted.clone();
+            threads.push(thread::spawn(move || {
+                answer_punted(&udp, &frontend, &punted)
+            }));
+        }
+        for socket in self.sockets {
+            let mut fast = FastPath {
+                frontend: frontend.clone(),
+                forwarder: self.forwarder.clone(),
+                addrs: self.addrs.clone(),
+                punt: punt.clone(),
+                arena: Arena::with_capacity(FRAME_SIZE),
+                key: CacheKey {
+                    question: Question::new(DomainName::root(), RecordType::A),
+                    dnssec_ok: false,
+                    checking_disabled: false,
+                },
+                rdata: Vec::new(),
+            };
+            let program = self.program.clone();
+            threads.push(thread::spawn(move || {
+                // The program stays attached while any socket is served.
+                let _program = program;
+                socket.serve(&mut fast)
+            }));
+        }
+        Ok(threads)
+    }
+}
+
+/// Answers punted queries until every socket thread has stopped.
+fn answer_punted(
+    udp: &[UdpSocket],
+    frontend: &Frontend,
+    punted: &Mutex<Receiver<Punted>>,
+) -> io::Result<()> {
+    loop {
+        let next = punted.lock().unwrap().recv_timeout(UDP_POLL_INTERVAL);
+        let (query, src, to) = match next {
+            Ok(punted) => punted,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        let reply = frontend.dispatch(&query, src, Transport::Udp);
+        if let Some(wire) = reply.messages.first().filter(|w| !w.is_empty()) {
+            // A failed send concerns only its client.
+            let _ = udp[to].send_to(wire, src);
+        }
+    }
+}
+
+/// What the thread of a socket answers with, and the space it builds
+/// responses in, kept so that answering allocates nothing.
+struct FastPath {
+    frontend: Arc<Frontend>,
+    /// Read for each query, so that a forwarder replaced while serving
+    /// answers from then on.
+    forwarder: SharedForwarder,
+    addrs: Vec<SocketAddr>,
+    punt: SyncSender<Punted>,
+    arena: Arena,
+    key: CacheKey,
+    rdata: Vec<u8>,
+}
+
+impl FastPath {
+    /// Handles the `len`-byte packet at the start of `frame`, returning the
+    /// length of the response written over it, if any.
+    fn handle(&mut self, frame: &mut [u8], len: usize) -> Option<usize> {
+        let len = len.min(frame.len());
+        let (ip_len, src, dst) = parse(&frame[..len])?;
+        let to = self.addrs.iter().position(|a| {
+            a.port() == dst.port()
+                && (a.ip() == dst.ip() || a.ip().is_unspecified() && a.is_ipv4() == dst.is_ipv4())
+        })?;
+        let udp = ETH_LEN + ip_len;
+        let end = (udp + usize::from(u16::from_be_bytes([frame[udp + 4], frame[udp + 5]])))
+            .min(len)
+            .max(udp + UDP_LEN);
+        let payload = &mut frame[udp + UDP_LEN..];
+        let query = &payload[..end - udp - UDP_LEN];
+        match self.answer(query, payload.len()) {
+            Some(wire) => {
+                payload[..wire.len()].copy_from_slice(wire);
+                let len = wire.len();
+                Some(turn_around(frame, ip_len, len))
+            }
+            None => {
+                // With the queue full, the query is dropped, as it would be
+                // with the socket buffer full.
+                let _ = self.punt.try_send((query.to_vec(), src, to));
+                None
+            }
+        }
+    }
+
+    /// The response to `query` from the cache, built in the arena, if it
+    /// is a plain A or AAAA query the cache answers within `room` bytes.
+    /// What the compliance profile refuses is left to the handler path,
+    /// which answers it with FORMERR.
+    fn answer(&mut self, query: &[u8], room: usize) -> Option<&[u8]> {
+        let compliance = &self.frontend.compliance;
+        let parsed = MessageRef::parse_with(query, compliance).ok()?;
+        let header = parsed.header();
+        let q = parsed.question()?;
+        if header.qr
+            || parsed.questions().nth(1).is_some() && !compliance.multiple_questions
+            || header.opcode != Opcode::QUERY
+            || !(q.qtype == RecordType::A || q.qtype == RecordType::AAAA)
+            || parsed.additional().any(|rr| rr.rtype != RecordType::OPT)
+        {
+            return None;
+        }
+        let _active = self.frontend.state.begin_request();
+        let policy = &self.frontend.policy;
+        let offered = parsed
+            .edns()
+            .map_or(MIN_UDP_SIZE, |e| usize::from(e.udp_size).max(MIN_UDP_SIZE));
+        let mut resp = self
+            .arena
+            .response(&parsed, policy.udp_limit(offered).min(room));
+        // Anything left out goes through the handler, where the response
+        // policy decides what to drop.
+        let forwarder = self.forwarder.read().unwrap().clone();
+        forwarder
+            .write_cached(
+                &parsed,
+                &mut resp,
+                &mut self.key,
+                &mut self.rdata,
+                policy.minimal_responses,
+            )
+            .then(|| resp.finish())
+    }
+}
+
+/// The length of the IP header of a UDP packet, where it came from and
+/// where it went.
+fn parse(packet: &[u8]) -> Option<(usize, SocketAddr, SocketAddr)> {
+    let ethertype = u16::from_be_bytes([*packet.get(12)?, *packet.get(13)?]);
+    let ip = packet.get(ETH_LEN..)?;
+    let (ip_len, src, dst) = match ethertype {
+        ETHERTYPE_IPV4 if ip.len() >= IPV4_LEN + UDP_LEN && ip[0] == 0x45 => {
+            let src = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+            let dst = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+            (IPV4_LEN, IpAddr::V4(src), IpAddr::V4(dst))
+        }
+        ETHERTYPE_IPV6 if ip.len() >= IPV6_LEN + UDP_LEN && ip[0] >> 4 == 6 => {
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&ip[8..24]);
+            dst.copy_from_slice(&ip[24..40]);
+            (
+                IPV6_LEN,
+                Ipv6Addr::from(src).into(),
+                Ipv6Addr::from(dst).into(),
+            )
+        }
+        _ => return None,
+    };
+    let udp = &ip[ip_len..];
+    let src = SocketAddr::new(src, u16::from_be_bytes([udp[0], udp[1]]));
+    let dst = SocketAddr::new(dst, u16::from_be_bytes([udp[2], udp[3]]));
+    Some((ip_len, src, dst))
+}
+
+/// Turns the query in `frame` into a response of `payload` bytes, already
+/// written after the UDP header: swaps the addresses and ports and fixes
+/// lengths and checksums. Returns the length of the packet.
+fn turn_around(frame: &mut [u8], ip_len: usize, payload: usize) -> usize {
+    let (dst, src) = frame.split_at_mut(6);
+    dst.swap_with_slice(&mut src[..6]);
+    let udp = ETH_LEN + ip_len;
+    let udp_len = (UDP_LEN + payload) as u16;
+    let (ports, rest) = frame[udp..].split_at_mut(2);
+    ports.swap_with_slice(&mut rest[..2]);
+    frame[udp + 4..udp + 6].copy_from_slice(&udp_len.to_be_bytes());
+    frame[udp + 6..udp + 8].copy_from_slice(&[0, 0]);
+    let ip = ETH_LEN;
+    if ip_len == IPV4_LEN {
+        let (a, b) = frame[ip + 12..ip + 20].split_at_mut(4);
+        a.swap_with_slice(b);
+        let total = (IPV4_LEN as u16) + udp_len;
+        frame[ip + 2..ip + 4].copy_from_slice(&total.to_be_bytes());
+        frame[ip + 8] = HOP_LIMIT;
+        frame[ip + 10..ip + 12].copy_from_slice(&[0, 0]);
+        let sum = checksum(0, &frame[ip..ip + IPV4_LEN]);
+        frame[ip + 10..ip + 12].copy_from_slice(&sum.to_be_bytes());
+        // A zero UDP checksum over IPv4 means none.
+    } else {
+        let (a, b) = frame[ip + 8..ip + 40].split_at_mut(16);
+        a.swap_with_slice(b);
+        frame[ip + 4..ip + 6].copy_from_slice(&udp_len.to_be_bytes());
+        frame[ip + 7] = HOP_LIMIT;
+        // The pseudo-header: addresses, length and next header.
+        let mut sum = sum_words(0, &frame[ip + 8..ip + 40]);
+        sum += u32::from(udp_len) + u32::from(IPPROTO_UDP);
+        let sum = match checksum(sum, &frame[udp..udp + usize::from(udp_len)]) {
+            0 => 0xffff,
+            sum => sum,
+        };
+        frame[udp + 6..udp + 8].copy_from_slice(&sum.to_be_bytes());
+    }
+    udp + usize::from(udp_len)
+}
+
+fn sum_words(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for word in &mut chunks {
+        sum += u32::from(u16::from_be_bytes([word[0], word[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+/// The Internet checksum (RFC 1071) of `data`, starting from `sum`.
+fn checksum(sum: u32, data: &[u8]) -> u16 {
+    let mut sum = u64::from(sum_words(sum, data));
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::compliance::Compliance;
+    use crate::message::{Edns, Message, Rcode};
+    use crate::resolver::{Resolver, ResolverConfig};
+    use crate::rr::{RData, Record};
+    use crate::server::lifecycle::State;
+    use crate::server::{
+        CachedResponse, Fallthrough, Forwarder, Request, ResponsePolicy, Route, Security,
+    };
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::RwLock;
+    use std::time::Duration;
+
+    const V4: [&str; 2] = ["198.51.100.7:40000", "192.0.2.53:53"];
+    const V6: [&str; 2] = ["[2001:db8::7]:40000", "[2001:db8::53]:53"];
+
+    fn name(s: &str) -> DomainName {
+        s.parse().unwrap()
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn a(owner: &str, ttl: u32) -> Record {
+        Record::new(name(owner), ttl, RData::A([192, 0, 2, 1].into()))
+    }
+
+    fn rrsig(owner: &str) -> Record {
+        Record::new(
+            name(owner),
+            300,
+            RData::Unknown {
+                rtype: RecordType::RRSIG,
+                data: vec![0; 24],
+            },
+        )
+    }
+
+    /// A cached response to an A query for `qname`, as the key of a query
+    /// with DO set to `dnssec_ok` finds it.
+    fn cached(qname: &str, dnssec_ok: bool, build: impl FnOnce(&mut Message)) -> CachedResponse {
+        let mut response = Message::query(name(qname), RecordType::A).response();
+        build(&mut response);
+        CachedResponse {
+            question: Question::new(name(qname), RecordType::A),
+            dnssec_ok,
+            checking_disabled: false,
+            response,
+            security: Security::Unchecked,
+            expires_in: Duration::from_secs(300),
+        }
+    }
+
+    fn route(suffix: &str) -> Route {
+        let upstream = Resolver::new(ResolverConfig {
+            servers: vec![addr("127.0.0.1:9")],
+            ..ResolverConfig::default()
+        });
+        Route::new(name(suffix), upstream)
+    }
+
+    fn fast_path(policy: ResponsePolicy, routes: Vec<Route>) -> (FastPath, Receiver<Punted>) {
+        fast_path_with(policy, Compliance::default(), routes)
+    }
+
+    fn fast_path_with(
+        policy: ResponsePolicy,
+        compliance: Compliance,
+        routes: Vec<Route>,
+    ) -> (FastPath, Receiver<Punted>) {
+        let handler: fn(&Request) -> Option<Message> = |_| None;
+        let frontend = Frontend {
+            state: Arc::new(State::new(Arc::new(handler))),
+            max_transfers: 0,
+            transfers: Arc::new(AtomicUsize::new(0)),
+            policy,
+            compliance,
+            clock: clock::system(),
+        };
+        let (punt, punted) = mpsc::sync_channel(8);
+        let fast = FastPath {
+            frontend: Arc::new(frontend),
+            forwarder: Arc::new(RwLock::new(Arc::new(
+                routes.into_iter().fold(Forwarder::new(), Forwarder::route),
+            ))),
+            addrs: vec![addr(V4[1]), addr(V6[1])],
+            punt,
+            arena: Arena::new(),
+            key: CacheKey {
+                question: Question::new(DomainName::root(), RecordType::A),
+                dnssec_ok: false,
+                checking_disabled: false,
+            },
+            rdata: Vec::new(),
+        };
+        (fast, punted)
+    }
+
+    /// A frame holding `query` sent from the first to the second of
+    /// `addrs`, and the length of the packet.
+    fn frame(query: &Message, addrs: [&str; 2]) -> (Vec<u8>, usize) {
+        let (src, dst) = (addr(addrs[0]), addr(addrs[1]));
+        let payload = query.to_wire().unwrap();
+        let udp_len = (UDP_LEN + payload.len()) as u16;
+        let mut frame = vec![2, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 2];
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => {
+                frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+                frame.extend_from_slice(&[0x45, 0]);
+                frame.extend_from_slice(&(IPV4_LEN as u16 + udp_len).to_be_bytes());
+                frame.extend_from_slice(&[0, 0, 0x40, 0, 64, IPPROTO_UDP, 0, 0]);
+                frame.extend_from_slice(&s.octets());
+                frame.extend_from_slice(&d.octets());
+                let sum = checksum(0, &frame[ETH_LEN..]);
+                frame[ETH_LEN + 10..ETH_LEN + 12].copy_from_slice(&sum.to_be_bytes());
+            }
+            (IpAddr::V6(s), IpAddr::V6(d)) => {
+                frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                frame.extend_from_slice(&[0x60, 0, 0, 0]);
+                frame.extend_from_slice(&udp_len.to_be_bytes());
+                frame.extend_from_slice(&[IPPROTO_UDP, 64]);
+                frame.extend_from_slice(&s.octets());
+                frame.extend_from_slice(&d.octets());
+            }
+            _ => unreachable!(),
+        }
+        frame.extend_from_slice(&src.port().to_be_bytes());
+        frame.extend_from_slice(&dst.port().to_be_bytes());
+        frame.extend_from_slice(&udp_len.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(&payload);
+        let len = frame.len();
+        frame.resize(FRAME_SIZE, 0);
+        (frame, len)
+    }
+
+    /// The response in the first `len` bytes of `frame`, once its headers
+    /// are checked to send it back from the second of `addrs` to the
+    /// first.
+    fn response(frame: &[u8], len: usize, addrs: [&str; 2]) -> Message {
+        assert_eq!(frame[..6], [2, 0, 0, 0, 0, 2]);
+        let (ip_len, src, dst) = parse(&frame[..len]).unwrap();
+        assert_eq!((src, dst), (addr(addrs[1]), addr(addrs[0])));
+        let udp = ETH_LEN + ip_len;
+        let udp_len = usize::from(u16::from_be_bytes([frame[udp + 4], frame[udp + 5]]));
+        assert_eq!(udp + udp_len, len);
+        if ip_len == IPV4_LEN {
+            assert_eq!(checksum(0, &frame[ETH_LEN..udp]), 0);
+        } else {
+            let sum = sum_words(0, &frame[ETH_LEN + 8..udp]);
+            let sum = sum + udp_len as u32 + u32::from(IPPROTO_UDP);
+            assert_eq!(checksum(sum, &frame[udp..len]), 0);
+        }
+        Message::from_wire(&frame[udp + UDP_LEN..len]).unwrap()
+    }
+
+    /// Hands `query` to `fast` from the first to the second of `addrs`,
+    /// returning the response if it was answered in place.
+    fn ask(fast: &mut FastPath, query: &Message, addrs: [&str; 2]) -> Option<Message> {
+        let (mut frame, len) = frame(query, addrs);
+        let answered = fast.handle(&mut frame, len)?;
+        Some(response(&frame, answered, addrs))
+    }
+
+    fn query(qname: &str, build: impl FnOnce(&mut Message)) -> Message {
+        let mut query = Message::query(name(qname), RecordType::A);
+        build(&mut query);
+        query
+    }
+
+    #[test]
+    fn answers_cache_hits_in_place() {
+        let route = route(".");
+        assert!(route.restore(cached("www.example", false, |r| {
+            r.answers.push(a("www.example", 300))
+        })));
+        let (mut fast, punted) = fast_path(ResponsePolicy::default(), vec![route]);
+
+        for addrs in [V4, V6] {
+            let query = query("WWW.example", |_| {});
+            let resp = ask(&mut fast, &query, addrs).unwrap();
+            assert_eq!(resp.header.id, query.header.id);
+            assert!(resp.header.qr && resp.header.ra && resp.header.rd);
+            assert!(!resp.header.tc && !resp.header.ad);
+            assert_eq!(resp.questions, query.questions);
+            assert_eq!(resp.answers.len(), 1);
+            assert!(resp.answers[0].ttl <= 300 && resp.answers[0].ttl >= 299);
+            assert!(resp.edns.is_some());
+        }
+        assert!(punted.try_recv().is_err());
+    }
+
+    #[test]
+    fn punts_what_needs_the_handler() {
+        let first = route(".");
+        let corp = route("corp.example").fallthrough(Fallthrough::OnNxdomain);
+        assert!(first.restore(cached("big.example", false, |r| {
+            for i in 0..40 {
+                r.answers.push(a(&format!("host{}.big.example", i), 300));
+            }
+        })));
+        let mut bogus = cached("bogus.example", false, |r| {
+            r.answers.push(a("bogus.example", 300))
+        });
+        bogus.security = Security::Bogus;
+        assert!(first.restore(bogus.clone()));
+        bogus.checking_disabled = true;
+        assert!(first.restore(bogus));
+        assert!(corp.restore(cached("gone.corp.example", false, |r| {
+            r.header.rcode = Rcode::NXDOMAIN
+        })));
+        let (mut fast, punted) = fast_path(ResponsePolicy::default(), vec![first, corp]);
+
+        let punts = [
+            // Not cached.
+            query("www.example", |_| {}),
+            // Not an address query.
+            query("www.example", |q| q.questions[0].qtype = RecordType::MX),
+            // Bigger than a client without EDNS takes.
+            query("big.example", |q| q.edns = None),
+            // Served only to clients that set CD.
+            query("bogus.example", |_| {}),
+            // A later route may answer.
+            query("gone.corp.example", |_| {}),
+        ];
+        for query in &punts {
+            assert!(ask(&mut fast, query, V4).is_none(), "{:?}", query);
+            let (wire, src, to) = punted.try_recv().unwrap();
+            assert_eq!(wire, query.to_wire().unwrap());
+            assert_eq!((src, to), (addr(V4[0]), 0));
+        }
+
+        let resp = ask(&mut fast, &query("big.example", |_| {}), V6).unwrap();
+        assert_eq!(resp.answers.len(), 40);
+        let cd = query("bogus.example", |q| q.header.cd = true);
+        assert_eq!(ask(&mut fast, &cd, V4).unwrap().answers.len(), 1);
+
+        // Datagrams for other addresses are left alone.
+        let (mut other, len) = frame(&punts[0], ["198.51.100.7:40000", "192.0.2.54:53"]);
+        assert!(fast.handle(&mut other, len).is_none());
+        assert!(punted.try_recv().is_err());
+    }
+
+    #[test]
+    fn keeps_to_dnssec_ok_and_the_response_policy() {
+        let build = |r: &mut Message| {
+            r.header.ad = true;
+            r.answers.push(a("www.example", 300));
+            r.answers.push(rrsig("www.example"));
+            r.authority.push(Record::new(
+                name("example"),
+                300,
+                RData::Ns(name("ns.example")),
+            ));
+            r.additional.push(a("ns.example", 300));
+        };
+        let route = route(".");
+        assert!(route.restore(cached("www.example", false, build)));
+        assert!(route.restore(cached("www.example", true, build)));
+        let (mut fast, _punted) = fast_path(ResponsePolicy::default(), vec![route]);
+
+        let resp = ask(&mut fast, &query("www.example", |_| {}), V4).unwrap();
+        let types: Vec<RecordType> = resp.answers.iter().map(Record::rtype).collect();
+        assert_eq!(types, [RecordType::A]);
+        assert_eq!((resp.authority.len(), resp.additional.len()), (1, 1));
+        assert!(!resp.header.ad);
+
+        let dnssec_ok = query("www.example", |q| {
+            q.edns = Some(Edns {
+                dnssec_ok: true,
+                ..Edns::default()
+            })
+        });
+        let resp = ask(&mut fast, &dnssec_ok, V4).unwrap();
+        assert_eq!(resp.answers.len(), 2);
+        assert!(resp.header.ad && resp.edns.unwrap().dnssec_ok);
+
+        let again = self::route(".");
+        assert!(again.restore(cached("www.example", false, build)));
+        let minimal = ResponsePolicy {
+            minimal_responses: true,
+            ..ResponsePolicy::default()
+        };
+        let (mut fast, _punted) = fast_path(minimal, vec![again]);
+        let resp = ask(&mut fast, &query("www.example", |_| {}), V4).unwrap();
+        assert_eq!(resp.answers.len(), 1);
+        assert!(resp.authority.is_empty() && resp.additional.is_empty());
+    }
+
+    #[test]
+    fn answers_from_a_replaced_forwarder() {
+        let (mut fast, punted) = fast_path(ResponsePolicy::default(), vec![route(".")]);
+        let query = query("www.example", |_| {});
+        assert!(ask(&mut fast, &query, V4).is_none());
+        assert!(punted.try_recv().is_ok());
+
+        let reloaded = route(".");
+        assert!(reloaded.restore(cached("www.example", false, |r| {
+            r.answers.push(a("www.example", 300))
+        })));
+        *fast.forwarder.write().unwrap() = Arc::new(Forwarder::new().route(reloaded));
+        assert_eq!(ask(&mut fast, &query, V4).unwrap().answers.len(), 1);
+        assert!(punted.try_recv().is_err());
+    }
+
+    #[test]
+    fn leaves_what_the_profile_refuses_to_the_handler() {
+        let cached = || {
+            let route = route(".");
+            assert!(route.restore(cached("www.example", false, |r| {
+                r.answers.push(a("www.example", 300))
+            })));
+            vec![route]
+        };
+        let two = query("www.example", |q| q.questions.push(q.questions[0].clone()));
+        let policy = ResponsePolicy::default();
+
+        let (mut strict, punted) = fast_path_with(policy, Compliance::STRICT, cached());
+        assert!(ask(&mut strict, &two, V4).is_none());
+        assert!(punted.try_recv().is_ok());
+        let (mut lenient, punted) = fast_path_with(policy, Compliance::LENIENT, cached());
+        let resp = ask(&mut lenient, &two, V4).unwrap();
+        assert_eq!(resp.answers.len(), 1);
+        assert!(punted.try_recv().is_err());
+
+        // Trailing bytes, which the frame's UDP length takes in.
+        let one = query("www.example", |_| {});
+        let payload = one.to_wire().unwrap().len();
+        for (fast, answered) in [(&mut strict, false), (&mut lenient, true)] {
+            let (mut frame, len) = frame(&one, V4);
+            let udp = ETH_LEN + IPV4_LEN;
+            let udp_len = (UDP_LEN + payload + 1) as u16;
+            frame[udp + 4..udp + 6].copy_from_slice(&udp_len.to_be_bytes());
+            assert_eq!(fast.handle(&mut frame, len + 1).is_some(), answered);
+        }
+    }
+}
@@ -0,0 +1,661 @@
+//! Vectorized loops for the bytes every packet goes through: lowercasing
+//! and comparing names, scanning names in text for bytes that need care,
+//! and the hex, base64 and base32hex codecs of [`crate::encoding`].
+//!
+//! Each function picks, at run time, the widest implementation the CPU
+//! has: AVX2 or SSSE3 on x86-64 (SSE2 is always there), NEON on AArch64.
+//! The vector paths only take whole blocks, and only blocks that need no
+//! special handling; the callers' scalar loops do the rest and agree with
+//! the vector paths byte for byte.
+
+/// Lowercases the ASCII letters of `bytes`.
+pub(crate) fn lowercase(bytes: &mut [u8]) {
+    let done = imp::lowercase(bytes);
+    bytes[done..].make_ascii_lowercase();
+}
+
+/// Whether `a` and `b` are equal ignoring ASCII case.
+pub(crate) fn eq_ignore_case(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    match imp::eq_ignore_case(a, b) {
+        Some(done) => a[done..].eq_ignore_ascii_case(&b[done..]),
+        None => false,
+    }
+}
+
+/// How many bytes `text` starts with that are printable ASCII other than
+/// `.` and `\`: the bytes a name in text can hold without escaping.
+pub(crate) fn plain_prefix(text: &[u8]) -> usize {
+    let done = imp::plain_prefix(text);
+    done + text[done..]
+        .iter()
+        .position(|&c| c == b'.' || c == b'\\' || c <= b' ' || c >= 0x7f)
+        .unwrap_or(text.len() - done)
+}
+
+/// Decodes base64 from the start of `text` into `out`, for as many whole
+/// blocks as hold nothing but alphabet characters. Returns how much of
+/// `text` was decoded, a multiple of four.
+pub(crate) fn base64_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+    imp::base64_decode(text, out)
+}
+
+/// Encodes whole blocks from the start of `data` as base64 into `out`.
+/// Returns how much of `data` was encoded, a multiple of three.
+pub(crate) fn base64_encode(data: &[u8], out: &mut Vec<u8>) -> usize {
+    imp::base64_encode(data, out)
+}
+
+/// Decodes hex from the start of `text`, as [`base64_decode`] does.
+/// Returns how much of `text` was decoded, an even number.
+pub(crate) fn hex_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+    imp::hex_decode(text, out)
+}
+
+/// Encodes whole blocks from the start of `data` as lowercase hex.
+pub(crate) fn hex_encode(data: &[u8], out: &mut Vec<u8>) -> usize {
+    imp::hex_encode(data, out)
+}
+
+/// Decodes base32hex from the start of `text`, in either case, as
+/// [`base64_decode`] does. Returns how much of `text` was decoded, a
+/// multiple of eight.
+pub(crate) fn base32hex_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+    let mut quintets = [0u8; 16];
+    let mut done = 0;
+    while text.len() - done >= 16 && imp::base32hex_values(&text[done..done + 16], &mut quintets) {
+        for group in quintets.chunks(8) {
+            let n = group.iter().fold(0u64, |acc, &v| acc << 5 | u64::from(v));
+            out.extend_from_slice(&n.to_be_bytes()[3..]);
+        }
+        done += 16;
+    }
+    done
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use std::arch::x86_64::*;
+
+    /// Lanes of `v` from `lo` to `hi`. Bytes from 0x80 up compare as
+    /// negative, so they are never in range.
+    #[inline(always)]
+    unsafe fn in_range(v: __m128i, lo: u8, hi: u8) -> __m128i {
+        _mm_and_si128(
+            _mm_cmpgt_epi8(v, _mm_set1_epi8(lo as i8 - 1)),
+            _mm_cmplt_epi8(v, _mm_set1_epi8(hi as i8 + 1)),
+        )
+    }
+
+    #[inline(always)]
+    unsafe fn fold(v: __m128i) -> __m128i {
+        let upper = in_range(v, b'A', b'Z');
+        _mm_or_si128(v, _mm_and_si128(upper, _mm_set1_epi8(0x20)))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn fold256(v: __m256i) -> __m256i {
+        let upper = _mm256_and_si256(
+            _mm256_cmpgt_epi8(v, _mm256_set1_epi8(b'A' as i8 - 1)),
+            _mm256_cmpgt_epi8(_mm256_set1_epi8(b'Z' as i8 + 1), v),
+        );
+        _mm256_or_si256(v, _mm256_and_si256(upper, _mm256_set1_epi8(0x20)))
+    }
+
+    pub(super) fn lowercase(bytes: &mut [u8]) -> usize {
+        unsafe {
+            if is_x86_feature_detected!("avx2") {
+                lowercase_avx2(bytes)
+            } else {
+                lowercase_sse2(bytes, 0)
+            }
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn lowercase_avx2(bytes: &mut [u8]) -> usize {
+        let mut i = 0;
+        while bytes.len() - i >= 32 {
+            let p = bytes.as_mut_ptr().add(i) as *mut __m256i;
+            _mm256_storeu_si256(p, fold256(_mm256_loadu_si256(p)));
+            i += 32;
+        }
+        lowercase_sse2(bytes, i)
+    }
+
+    unsafe fn lowercase_sse2(bytes: &mut [u8], mut i: usize) -> usize {
+        while bytes.len() - i >= 16 {
+            let p = bytes.as_mut_ptr().add(i) as *mut __m128i;
+            _mm_storeu_si128(p, fold(_mm_loadu_si128(p)));
+            i += 16;
+        }
+        i
+    }
+
+    pub(super) fn eq_ignore_case(a: &[u8], b: &[u8]) -> Option<usize> {
+        let mut i = 0;
+        while a.len() - i >= 16 {
+            unsafe {
+                let x = fold(_mm_loadu_si128(a.as_ptr().add(i) as *const __m128i));
+                let y = fold(_mm_loadu_si128(b.as_ptr().add(i) as *const __m128i));
+                if _mm_movemask_epi8(_mm_cmpeq_epi8(x, y)) != 0xffff {
+                    return None;
+                }
+            }
+            i += 16;
+        }
+        Some(i)
+    }
+
+    #[inline(always)]
+    unsafe fn special(v: __m128i) -> i32 {
+        let mask = _mm_or_si128(
+            _mm_or_si128(
+                _mm_cmpeq_epi8(v, _mm_set1_epi8(b'.' as i8)),
+                _mm_cmpeq_epi8(v, _mm_set1_epi8(b'\\' as i8)),
+            ),
+            // Below `!` or at DEL and above, as signed bytes.
+            _mm_or_si128(
+                _mm_cmplt_epi8(v, _mm_set1_epi8(b'!' as i8)),
+                _mm_cmpeq_epi8(v, _mm_set1_epi8(0x7f)),
+            ),
+        );
+        _mm_movemask_epi8(mask)
+    }
+
+    pub(super) fn plain_prefix(text: &[u8]) -> usize {
+        let mut i = 0;
+        while text.len() - i >= 16 {
+            let found = unsafe { special(_mm_loadu_si128(text.as_ptr().add(i) as *const __m128i)) };
+            if found != 0 {
+                return i + found.trailing_zeros() as usize;
+            }
+            i += 16;
+        }
+        i
+    }
+
+    /// The sextets of 16 base64 characters, or `None` if any is not in
+    /// the alphabet.
+    #[inline(always)]
+    unsafe fn base64_values(v: __m128i) -> Option<__m128i> {
+        let upper = in_range(v, b'A', b'Z');
+        let lower = in_range(v, b'a', b'z');
+        let digit = in_range(v, b'0', b'9');
+        let plus = _mm_cmpeq_epi8(v, _mm_set1_epi8(b'+' as i8));
+        let slash = _mm_cmpeq_epi8(v, _mm_set1_epi8(b'/' as i8));
+        let valid = _mm_or_si128(
+            _mm_or_si128(upper, lower),
+            _mm_or_si128(digit, _mm_or_si128(plus, slash)),
+        );
+        if _mm_movemask_epi8(valid) != 0xffff {
+            return None;
+        }
+        let offset = _mm_or_si128(
+            _mm_or_si128(
+                _mm_and_si128(upper, _mm_set1_epi8(-65)),
+                _mm_and_si128(lower, _mm_set1_epi8(-71)),
+            ),
+            _mm_or_si128(
+                _mm_and_si128(digit, _mm_set1_epi8(4)),
+                _mm_or_si128(
+                    _mm_and_si128(plus, _mm_set1_epi8(19)),
+                    _mm_and_si128(slash, _mm_set1_epi8(16)),
+                ),
+            ),
+        );
+        Some(_mm_add_epi8(v, offset))
+    }
+
+    /// Packs 16 sextets into 12 bytes, left in the low lanes.
+    #[inline]
+    #[target_feature(enable = "ssse3")]
+    unsafe fn pack_sextets(v: __m128i) -> __m128i {
+        let pairs = _mm_maddubs_epi16(v, _mm_set1_epi32(0x0140_0140));
+        let quads = _mm_madd_epi16(pairs, _mm_set1_epi32(0x0001_1000));
+        _mm_shuffle_epi8(
+            quads,
+            _mm_setr_epi8(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1),
+        )
+    }
+
+    pub(super) fn base64_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+        if is_x86_feature_detected!("ssse3") {
+            unsafe { base64_decode_ssse3(text, out) }
+        } else {
+            0
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    unsafe fn base64_decode_ssse3(text: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 16];
+        while text.len() - i >= 16 {
+            let v = _mm_loadu_si128(text.as_ptr().add(i) as *const __m128i);
+            let values = match base64_values(v) {
+                Some(values) => values,
+                None => break,
+            };
+            _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, pack_sextets(values));
+            out.extend_from_slice(&block[..12]);
+            i += 16;
+        }
+        i
+    }
+
+    pub(super) fn base64_encode(data: &[u8], out: &mut Vec<u8>) -> usize {
+        if is_x86_feature_detected!("ssse3") {
+            unsafe { base64_encode_ssse3(data, out) }
+        } else {
+            0
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    unsafe fn base64_encode_ssse3(data: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 16];
+        // Each step reads 16 bytes and encodes the first 12.
+        while data.len() - i >= 16 {
+            let v = _mm_loadu_si128(data.as_ptr().add(i) as *const __m128i);
+            let v = _mm_shuffle_epi8(
+                v,
+                _mm_setr_epi8(1, 0, 2, 1, 4, 3, 5, 4, 7, 6, 8, 7, 10, 9, 11, 10),
+            );
+            let hi = _mm_mulhi_epu16(
+                _mm_and_si128(v, _mm_set1_epi32(0x0fc0_fc00)),
+                _mm_set1_epi32(0x0400_0040),
+            );
+            let lo = _mm_mullo_epi16(
+                _mm_and_si128(v, _mm_set1_epi32(0x003f_03f0)),
+                _mm_set1_epi32(0x0100_0010),
+            );
+            let sextets = _mm_or_si128(hi, lo);
+            // 'A' is 65 over 0, 'a' 71 over 26, '0' 4 under 52, '+' 19
+            // under 62 and '/' 16 under 63.
+            let step = |above: i8, by: i8| {
+                _mm_and_si128(
+                    _mm_cmpgt_epi8(sextets, _mm_set1_epi8(above)),
+                    _mm_set1_epi8(by),
+                )
+            };
+            let offset = _mm_add_epi8(
+                _mm_add_epi8(_mm_set1_epi8(65), step(25, 6)),
+                _mm_add_epi8(step(51, -75), _mm_add_epi8(step(61, -15), step(62, 3))),
+            );
+            let chars = _mm_add_epi8(sextets, offset);
+            _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, chars);
+            out.extend_from_slice(&block);
+            i += 12;
+        }
+        i
+    }
+
+    /// The nibbles of 16 hex digits, or `None` if any is not one.
+    #[inline(always)]
+    unsafe fn hex_values(v: __m128i) -> Option<__m128i> {
+        let digit = in_range(v, b'0', b'9');
+        let upper = in_range(v, b'A', b'F');
+        let lower = in_range(v, b'a', b'f');
+        if _mm_movemask_epi8(_mm_or_si128(digit, _mm_or_si128(upper, lower))) != 0xffff {
+            return None;
+        }
+        let offset = _mm_or_si128(
+            _mm_and_si128(digit, _mm_set1_epi8(-48)),
+            _mm_or_si128(
+                _mm_and_si128(upper, _mm_set1_epi8(-55)),
+                _mm_and_si128(lower, _mm_set1_epi8(-87)),
+            ),
+        );
+        Some(_mm_add_epi8(v, offset))
+    }
+
+    pub(super) fn hex_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+        if is_x86_feature_detected!("ssse3") {
+            unsafe { hex_decode_ssse3(text, out) }
+        } else {
+            0
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    unsafe fn hex_decode_ssse3(text: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 16];
+        while text.len() - i >= 32 {
+            let p = text.as_ptr().add(i) as *const __m128i;
+            let (a, b) = match (
+                hex_values(_mm_loadu_si128(p)),
+                hex_values(_mm_loadu_si128(p.add(1))),
+            ) {
+                (Some(a), Some(b)) => (a, b),
+                _ => break,
+            };
+            let weights = _mm_set1_epi16(0x0110);
+            let bytes =
+                _mm_packus_epi16(_mm_maddubs_epi16(a, weights), _mm_maddubs_epi16(b, weights));
+            _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, bytes);
+            out.extend_from_slice(&block);
+            i += 32;
+        }
+        i
+    }
+
+    pub(super) fn hex_encode(data: &[u8], out: &mut Vec<u8>) -> usize {
+        if is_x86_feature_detected!("ssse3") {
+            unsafe { hex_encode_ssse3(data, out) }
+        } else {
+            0
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    unsafe fn hex_encode_ssse3(data: &[u8], out: &mut Vec<u8>) -> usize {
+        let digits = _mm_loadu_si128(b"0123456789abcdef".as_ptr() as *const __m128i);
+        let mask = _mm_set1_epi8(0x0f);
+        let mut i = 0;
+        let mut block = [0u8; 32];
+        while data.len() - i >= 16 {
+            let v = _mm_loadu_si128(data.as_ptr().add(i) as *const __m128i);
+            let hi = _mm_shuffle_epi8(digits, _mm_and_si128(_mm_srli_epi16(v, 4), mask));
+            let lo = _mm_shuffle_epi8(digits, _mm_and_si128(v, mask));
+            let p = block.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(p, _mm_unpacklo_epi8(hi, lo));
+            _mm_storeu_si128(p.add(1), _mm_unpackhi_epi8(hi, lo));
+            out.extend_from_slice(&block);
+            i += 16;
+        }
+        i
+    }
+
+    pub(super) fn base32hex_values(text: &[u8], values: &mut [u8; 16]) -> bool {
+        unsafe {
+            let v = _mm_loadu_si128(text.as_ptr() as *const __m128i);
+            let digit = in_range(v, b'0', b'9');
+            let upper = in_range(v, b'A', b'V');
+            let lower = in_range(v, b'a', b'v');
+            if _mm_movemask_epi8(_mm_or_si128(digit, _mm_or_si128(upper, lower))) != 0xffff {
+                return false;
+            }
+            let offset = _mm_or_si128(
+                _mm_and_si128(digit, _mm_set1_epi8(-48)),
+                _mm_or_si128(
+                    _mm_and_si128(upper, _mm_set1_epi8(-55)),
+                    _mm_and_si128(lower, _mm_set1_epi8(-87)),
+                ),
+            );
+            _mm_storeu_si128(values.as_mut_ptr() as *mut __m128i, _mm_add_epi8(v, offset));
+        }
+        true
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod imp {
+    use std::arch::aarch64::*;
+
+    #[inline(always)]
+    unsafe fn in_range(v: uint8x16_t, lo: u8, hi: u8) -> uint8x16_t {
+        vandq_u8(vcgeq_u8(v, vdupq_n_u8(lo)), vcleq_u8(v, vdupq_n_u8(hi)))
+    }
+
+    #[inline(always)]
+    unsafe fn fold(v: uint8x16_t) -> uint8x16_t {
+        vorrq_u8(v, vandq_u8(in_range(v, b'A', b'Z'), vdupq_n_u8(0x20)))
+    }
+
+    #[inline(always)]
+    unsafe fn all(mask: uint8x16_t) -> bool {
+        vminvq_u8(mask) == 0xff
+    }
+
+    pub(super) fn lowercase(bytes: &mut [u8]) -> usize {
+        let mut i = 0;
+        while bytes.len() - i >= 16 {
+            unsafe {
+                let p = bytes.as_mut_ptr().add(i);
+                vst1q_u8(p, fold(vld1q_u8(p)));
+            }
+            i += 16;
+        }
+        i
+    }
+
+    pub(super) fn eq_ignore_case(a: &[u8], b: &[u8]) -> Option<usize> {
+        let mut i = 0;
+        while a.len() - i >= 16 {
+            unsafe {
+                let x = fold(vld1q_u8(a.as_ptr().add(i)));
+                let y = fold(vld1q_u8(b.as_ptr().add(i)));
+                if !all(vceqq_u8(x, y)) {
+                    return None;
+                }
+            }
+            i += 16;
+        }
+        Some(i)
+    }
+
+    pub(super) fn plain_prefix(text: &[u8]) -> usize {
+        let mut i = 0;
+        while text.len() - i >= 16 {
+            unsafe {
+                let v = vld1q_u8(text.as_ptr().add(i));
+                let plain = vandq_u8(
+                    in_range(v, b'!', 0x7e),
+                    vandq_u8(
+                        vmvnq_u8(vceqq_u8(v, vdupq_n_u8(b'.'))),
+                        vmvnq_u8(vceqq_u8(v, vdupq_n_u8(b'\\'))),
+                    ),
+                );
+                if !all(plain) {
+                    // The scalar loop finds which byte it is.
+                    return i;
+                }
+            }
+            i += 16;
+        }
+        i
+    }
+
+    #[inline(always)]
+    unsafe fn base64_values(v: uint8x16_t) -> Option<uint8x16_t> {
+        let upper = in_range(v, b'A', b'Z');
+        let lower = in_range(v, b'a', b'z');
+        let digit = in_range(v, b'0', b'9');
+        let plus = vceqq_u8(v, vdupq_n_u8(b'+'));
+        let slash = vceqq_u8(v, vdupq_n_u8(b'/'));
+        let valid = vorrq_u8(
+            vorrq_u8(upper, lower),
+            vorrq_u8(digit, vorrq_u8(plus, slash)),
+        );
+        if !all(valid) {
+            return None;
+        }
+        let offset = vorrq_u8(
+            vorrq_u8(
+                vandq_u8(upper, vdupq_n_u8(65u8.wrapping_neg())),
+                vandq_u8(lower, vdupq_n_u8(71u8.wrapping_neg())),
+            ),
+            vorrq_u8(
+                vandq_u8(digit, vdupq_n_u8(4)),
+                vorrq_u8(
+                    vandq_u8(plus, vdupq_n_u8(19)),
+                    vandq_u8(slash, vdupq_n_u8(16)),
+                ),
+            ),
+        );
+        Some(vaddq_u8(v, offset))
+    }
+
+    pub(super) fn base64_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 48];
+        while text.len() - i >= 64 {
+            unsafe {
+                let v = vld4q_u8(text.as_ptr().add(i));
+                let (a, b, c, d) = match (
+                    base64_values(v.0),
+                    base64_values(v.1),
+                    base64_values(v.2),
+                    base64_values(v.3),
+                ) {
+                    (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                    _ => break,
+                };
+                let bytes = uint8x16x3_t(
+                    vorrq_u8(vshlq_n_u8::<2>(a), vshrq_n_u8::<4>(b)),
+                    vorrq_u8(vshlq_n_u8::<4>(b), vshrq_n_u8::<2>(c)),
+                    vorrq_u8(vshlq_n_u8::<6>(c), d),
+                );
+                vst3q_u8(block.as_mut_ptr(), bytes);
+            }
+            out.extend_from_slice(&block);
+            i += 64;
+        }
+        i
+    }
+
+    pub(super) fn base64_encode(data: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 64];
+        while data.len() - i >= 48 {
+            unsafe {
+                let table = vld1q_u8_x4(crate::encoding::BASE64.as_ptr());
+                let v = vld3q_u8(data.as_ptr().add(i));
+                let mask = vdupq_n_u8(0x3f);
+                let sextets = uint8x16x4_t(
+                    vshrq_n_u8::<2>(v.0),
+                    vandq_u8(vorrq_u8(vshlq_n_u8::<4>(v.0), vshrq_n_u8::<4>(v.1)), mask),
+                    vandq_u8(vorrq_u8(vshlq_n_u8::<2>(v.1), vshrq_n_u8::<6>(v.2)), mask),
+                    vandq_u8(v.2, mask),
+                );
+                let chars = uint8x16x4_t(
+                    vqtbl4q_u8(table, sextets.0),
+                    vqtbl4q_u8(table, sextets.1),
+                    vqtbl4q_u8(table, sextets.2),
+                    vqtbl4q_u8(table, sextets.3),
+                );
+                vst4q_u8(block.as_mut_ptr(), chars);
+            }
+            out.extend_from_slice(&block);
+            i += 48;
+        }
+        i
+    }
+
+    #[inline(always)]
+    unsafe fn hex_values(v: uint8x16_t) -> Option<uint8x16_t> {
+        let digit = in_range(v, b'0', b'9');
+        let upper = in_range(v, b'A', b'F');
+        let lower = in_range(v, b'a', b'f');
+        if !all(vorrq_u8(digit, vorrq_u8(upper, lower))) {
+            return None;
+        }
+        let offset = vorrq_u8(
+            vandq_u8(digit, vdupq_n_u8(48u8.wrapping_neg())),
+            vorrq_u8(
+                vandq_u8(upper, vdupq_n_u8(55u8.wrapping_neg())),
+                vandq_u8(lower, vdupq_n_u8(87u8.wrapping_neg())),
+            ),
+        );
+        Some(vaddq_u8(v, offset))
+    }
+
+    pub(super) fn hex_decode(text: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 16];
+        while text.len() - i >= 32 {
+            unsafe {
+                let v = vld2q_u8(text.as_ptr().add(i));
+                let (hi, lo) = match (hex_values(v.0), hex_values(v.1)) {
+                    (Some(hi), Some(lo)) => (hi, lo),
+                    _ => break,
+                };
+                vst1q_u8(block.as_mut_ptr(), vorrq_u8(vshlq_n_u8::<4>(hi), lo));
+            }
+            out.extend_from_slice(&block);
+            i += 32;
+        }
+        i
+    }
+
+    pub(super) fn hex_encode(data: &[u8], out: &mut Vec<u8>) -> usize {
+        let mut i = 0;
+        let mut block = [0u8; 32];
+        while data.len() - i >= 16 {
+            unsafe {
+                let digits = vld1q_u8(b"0123456789abcdef".as_ptr());
+                let v = vld1q_u8(data.as_ptr().add(i));
+                let chars = uint8x16x2_t(
+                    vqtbl1q_u8(digits, vshrq_n_u8::<4>(v)),
+                    vqtbl1q_u8(digits, vandq_u8(v, vdupq_n_u8(0x0f))),
+                );
+                vst2q_u8(block.as_mut_ptr(), chars);
+            }
+            out.extend_from_slice(&block);
+            i += 16;
+        }
+        i
+    }
+
+    pub(super) fn base32hex_values(text: &[u8], values: &mut [u8; 16]) -> bool {
+        unsafe {
+            let v = vld1q_u8(text.as_ptr());
+            let digit = in_range(v, b'0', b'9');
+            let upper = in_range(v, b'A', b'V');
+            let lower = in_range(v, b'a', b'v');
+            if !all(vorrq_u8(digit, vorrq_u8(upper, lower))) {
+                return false;
+            }
+            let offset = vorrq_u8(
+                vandq_u8(digit, vdupq_n_u8(48u8.wrapping_neg())),
+                vorrq_u8(
+                    vandq_u8(upper, vdupq_n_u8(55u8.wrapping_neg())),
+                    vandq_u8(lower, vdupq_n_u8(87u8.wrapping_neg())),
+                ),
+            );
+            vst1q_u8(values.as_mut_ptr(), vaddq_u8(v, offset));
+        }
+        true
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod imp {
+    pub(super) fn lowercase(_: &mut [u8]) -> usize {
+        0
+    }
+
+    pub(super) fn eq_ignore_case(_: &[u8], _: &[u8]) -> Option<usize> {
+        Some(0)
+    }
+
+    pub(super) fn plain_prefix(_: &[u8]) -> usize {
+        0
+    }
+
+    pub(super) fn base64_decode(_: &[u8], _: &mut Vec<u8>) -> usize {
+        0
+    }
+
+    pub(super) fn base64_encode(_: &[u8], _: &mut Vec<u8>) -> usize {
+        0
+    }
+
+    pub(super) fn hex_decode(_: &[u8], _: &mut Vec<u8>) -> usize {
+        0
+    }
+
+    pub(super) fn hex_encode(_: &[u8], _: &mut Vec<u8>) -> usize {
+        0
+    }
+
+    pub(super) fn base32hex_values(_: &[u8], _: &mut [u8; 16]) -> bool {
+        false
+    }
+}
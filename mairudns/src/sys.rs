@@ -0,0 +1,673 @@
+//! Socket options the standard library does not expose.
+//!
+//! Address and port reuse, and `IPV6_V6ONLY` so that IPv4 and IPv6
+//! wildcard sockets can share a port, must be set between `socket()` and
+//! `bind()`, so these helpers create the socket themselves through the C
+//! library. On platforms without an implementation here the options are ignored and
+//! the socket is bound normally.
+//!
+//! Sockets inherited from a service manager are taken over by descriptor,
+//! after checking what kind of socket each is.
+//!
+//! Echo requests are sent through unprivileged ICMP datagram sockets,
+//! which Linux (within `net.ipv4.ping_group_range`) and macOS offer.
+//!
+//! Files are mapped into memory with `mmap()` on Linux and macOS, and
+//! read into it elsewhere.
+//!
+//! On Linux, datagrams are received and sent in batches with `recvmmsg()`
+//! and `sendmmsg()`, and threads can be pinned to a CPU; elsewhere
+//! datagrams go one at a time and pinning does nothing.
+
+use std::fs::File;
+use std::io;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+
+/// Which reuse options to set before binding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Reuse {
+    pub addr: bool,
+    pub port: bool,
+}
+
+/// An inherited socket.
+#[derive(Debug)]
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), allow(dead_code))]
+pub enum Adopted {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+/// Takes over descriptor `fd`, which must be a UDP socket or a listening
+/// TCP socket, and marks it close-on-exec.
+pub fn adopt(fd: i32) -> io::Result<Adopted> {
+    imp::adopt(fd)
+}
+
+/// Receives at least one datagram, and more if they are already queued, up
+/// to one per buffer. `received` gets the length and source of each, in
+/// the order of the buffers.
+pub fn recv_batch(
+    socket: &UdpSocket,
+    bufs: &mut [Vec<u8>],
+    received: &mut Vec<(usize, SocketAddr)>,
+) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return mmsg::recv_batch(socket, bufs, received);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let (n, src) = socket.recv_from(&mut bufs[0])?;
+        received.push((n, src));
+        Ok(())
+    }
+}
+
+/// Sends datagrams to their destinations. As with `send_to()` for a
+/// single one, a datagram that cannot be sent is skipped.
+pub fn send_batch(socket: &UdpSocket, datagrams: &[(&[u8], SocketAddr)]) {
+    #[cfg(target_os = "linux")]
+    mmsg::send_batch(socket, datagrams);
+    #[cfg(not(target_os = "linux"))]
+    for (data, dst) in datagrams {
+        let _ = socket.send_to(data, dst);
+    }
+}
+
+/// Restricts the calling thread to run on CPU `cpu`.
+pub fn pin_thread(cpu: usize) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return mmsg::pin_thread(cpu);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = cpu;
+        Ok(())
+    }
+}
+
+/// Encodes `addr` as a `sockaddr_in` or `sockaddr_in6`, with the length
+/// of the structure.
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub fn encode_sockaddr(addr: &SocketAddr) -> ([u8; 28], u32) {
+    imp::sockaddr(addr)
+}
+
+/// Decodes a `sockaddr_in` or `sockaddr_in6` as the kernel wrote it.
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub fn decode_sockaddr(bytes: &[u8]) -> io::Result<SocketAddr> {
+    mmsg::decode_bytes(bytes)
+}
+
+/// Binds a UDP socket after applying the requested reuse options.
+pub fn bind_udp(addr: SocketAddr, reuse: Reuse) -> io::Result<UdpSocket> {
+    imp::bind_udp(addr, reuse)
+}
+
+/// Binds and listens on a TCP socket after applying the requested reuse
+/// options.
+pub fn bind_tcp(addr: SocketAddr, reuse: Reuse) -> io::Result<TcpListener> {
+    imp::bind_tcp(addr, reuse)
+}
+
+/// An unbound ICMP (or ICMPv6, if `v6`) datagram socket, through which
+/// echo requests can be sent without privileges. The kernel chooses the
+/// identifier of each request.
+pub fn icmp_socket(v6: bool) -> io::Result<UdpSocket> {
+    imp::icmp_socket(v6)
+}
+
+/// The name of the host, if it can be told.
+pub fn hostname() -> Option<String> {
+    imp::hostname()
+}
+
+/// A file mapped into memory read-only, unmapped when dropped. Changing
+/// the file in place while it is mapped changes what is read, or makes
+/// reading it fault if the file shrinks; replace it by renaming instead.
+pub struct MappedFile(imp::Mapping);
+
+impl MappedFile {
+    pub fn as_slice(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+/// Maps the whole of `file` into memory.
+pub fn map_file(file: &File) -> io::Result<MappedFile> {
+    imp::map(file).map(MappedFile)
+}
+
+/// Wakes the threads blocked accepting on `listener`, which then fail.
+/// Unlike connecting to it, this reaches listeners that share their port
+/// through `SO_REUSEPORT`. Only Linux supports it.
+pub fn interrupt_accept(listener: &TcpListener) -> io::Result<()> {
+    imp::interrupt_accept(listener)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod imp {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::ptr;
+
+    use super::{Adopted, Reuse};
+
+    #[cfg(target_os = "linux")]
+    mod consts {
+        use std::os::raw::c_int;
+        pub const AF_INET6: c_int = 10;
+        pub const SOCK_DGRAM: c_int = 2 | 0o2000000;
+        pub const SOCK_STREAM: c_int = 1 | 0o2000000;
+        pub const SOL_SOCKET: c_int = 1;
+        pub const SO_REUSEADDR: c_int = 2;
+        pub const SO_REUSEPORT: c_int = 15;
+        pub const IPPROTO_IPV6: c_int = 41;
+        pub const IPV6_V6ONLY: c_int = 26;
+        pub const SO_TYPE: c_int = 3;
+        pub const SO_ACCEPTCONN: c_int = 30;
+    }
+
+    #[cfg(target_os = "macos")]
+    mod consts {
+        use std::os::raw::c_int;
+        pub const AF_INET6: c_int = 30;
+        pub const SOCK_DGRAM: c_int = 2;
+        pub const SOCK_STREAM: c_int = 1;
+        pub const SOL_SOCKET: c_int = 0xffff;
+        pub const SO_REUSEADDR: c_int = 4;
+        pub const SO_REUSEPORT: c_int = 0x200;
+        pub const IPPROTO_IPV6: c_int = 41;
+        pub const IPV6_V6ONLY: c_int = 27;
+        pub const SO_TYPE: c_int = 0x1008;
+        pub const SO_ACCEPTCONN: c_int = 0x2;
+    }
+
+    use consts::*;
+
+    const AF_INET: c_int = 2;
+
+    /// Socket types as `SO_TYPE` reports them, without flags.
+    const TYPE_STREAM: c_int = 1;
+    const TYPE_DGRAM: c_int = 2;
+
+    const F_SETFD: c_int = 2;
+    const FD_CLOEXEC: c_int = 1;
+
+    /// Connections queued before `accept()`.
+    const BACKLOG: c_int = 128;
+
+    extern "C" {
+        fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+        fn setsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *const c_void,
+            len: u32,
+        ) -> c_int;
+        fn getsockopt(
+            fd: c_int,
+            level: c_int,
+            name: c_int,
+            value: *mut c_void,
+            len: *mut u32,
+        ) -> c_int;
+        fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+        fn bind(fd: c_int, addr: *const u8, len: u32) -> c_int;
+        fn listen(fd: c_int, backlog: c_int) -> c_int;
+        fn close(fd: c_int) -> c_int;
+        fn shutdown(fd: c_int, how: c_int) -> c_int;
+        fn gethostname(name: *mut u8, len: usize) -> c_int;
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    const PROT_READ: c_int = 1;
+    const MAP_PRIVATE: c_int = 2;
+
+    /// Memory mapped with `mmap()`, or the contents of a file that cannot
+    /// be mapped, such as an empty one.
+    pub enum Mapping {
+        Mapped { addr: *const u8, len: usize },
+        Read(Vec<u8>),
+    }
+
+    // The mapping is read-only and never aliased mutably.
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    impl Mapping {
+        pub fn as_slice(&self) -> &[u8] {
+            match self {
+                &Mapping::Mapped { addr, len } => unsafe { std::slice::from_raw_parts(addr, len) },
+                Mapping::Read(buf) => buf,
+            }
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            if let Mapping::Mapped { addr, len } = *self {
+                unsafe { munmap(addr as *mut c_void, len) };
+            }
+        }
+    }
+
+    pub fn map(file: &File) -> io::Result<Mapping> {
+        let len = file.metadata()?.len() as usize;
+        let addr = match len {
+            0 => -1isize as *mut c_void,
+            _ => unsafe {
+                mmap(
+                    ptr::null_mut(),
+                    len,
+                    PROT_READ,
+                    MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            },
+        };
+        if addr as isize == -1 {
+            // Some file systems cannot be mapped; read the file instead.
+            let mut buf = Vec::with_capacity(len);
+            (&*file).read_to_end(&mut buf)?;
+            return Ok(Mapping::Read(buf));
+        }
+        Ok(Mapping::Mapped {
+            addr: addr as *const u8,
+            len,
+        })
+    }
+
+    /// Encodes a `sockaddr_in`/`sockaddr_in6` into a buffer.
+    pub(super) fn sockaddr(addr: &SocketAddr) -> ([u8; 28], u32) {
+        let mut buf = [0u8; 28];
+        let (family, len) = match addr {
+            SocketAddr::V4(_) => (AF_INET, 16),
+            SocketAddr::V6(_) => (AF_INET6, 28),
+        };
+        if cfg!(target_os = "macos") {
+            buf[0] = len as u8;
+            buf[1] = family as u8;
+        } else {
+            buf[..2].copy_from_slice(&(family as u16).to_ne_bytes());
+        }
+        buf[2..4].copy_from_slice(&addr.port().to_be_bytes());
+        match addr {
+            SocketAddr::V4(a) => buf[4..8].copy_from_slice(&a.ip().octets()),
+            SocketAddr::V6(a) => {
+                buf[4..8].copy_from_slice(&a.flowinfo().to_be_bytes());
+                buf[8..24].copy_from_slice(&a.ip().octets());
+                buf[24..28].copy_from_slice(&a.scope_id().to_ne_bytes());
+            }
+        }
+        (buf, len)
+    }
+
+    fn check(ret: c_int) -> io::Result<c_int> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    fn set_flag(fd: c_int, level: c_int, name: c_int) -> io::Result<()> {
+        let one: c_int = 1;
+        let ptr = &one as *const c_int as *const c_void;
+        check(unsafe { setsockopt(fd, level, name, ptr, 4) }).map(drop)
+    }
+
+    /// Creates a socket of type `ty` bound to `addr`, then runs `ready` on
+    /// it; the descriptor is closed if any step fails.
+    fn bind_fd(
+        addr: SocketAddr,
+        ty: c_int,
+        reuse: Reuse,
+        ready: impl FnOnce(c_int) -> io::Result<()>,
+    ) -> io::Result<c_int> {
+        let family = if addr.is_ipv4() { AF_INET } else { AF_INET6 };
+        let fd = check(unsafe { socket(family, ty, 0) })?;
+        let setup = || -> io::Result<()> {
+            if reuse.addr {
+                set_flag(fd, SOL_SOCKET, SO_REUSEADDR)?;
+            }
+            if reuse.port {
+                set_flag(fd, SOL_SOCKET, SO_REUSEPORT)?;
+            }
+            if addr.is_ipv6() {
+                set_flag(fd, IPPROTO_IPV6, IPV6_V6ONLY)?;
+            }
+            let (sa, len) = sockaddr(&addr);
+            check(unsafe { bind(fd, sa.as_ptr(), len) })?;
+            ready(fd)
+        };
+        match setup() {
+            Ok(()) => Ok(fd),
+            Err(e) => {
+                unsafe { close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    pub fn bind_udp(addr: SocketAddr, reuse: Reuse) -> io::Result<UdpSocket> {
+        let fd = bind_fd(addr, SOCK_DGRAM, reuse, |_| Ok(()))?;
+        Ok(unsafe { UdpSocket::from_raw_fd(fd) })
+    }
+
+    pub fn bind_tcp(addr: SocketAddr, reuse: Reuse) -> io::Result<TcpListener> {
+        let fd = bind_fd(addr, SOCK_STREAM, reuse, |fd| {
+            check(unsafe { listen(fd, BACKLOG) }).map(drop)
+        })?;
+        Ok(unsafe { TcpListener::from_raw_fd(fd) })
+    }
+
+    pub fn icmp_socket(v6: bool) -> io::Result<UdpSocket> {
+        const IPPROTO_ICMP: c_int = 1;
+        const IPPROTO_ICMPV6: c_int = 58;
+        let (family, protocol) = if v6 {
+            (AF_INET6, IPPROTO_ICMPV6)
+        } else {
+            (AF_INET, IPPROTO_ICMP)
+        };
+        let fd = check(unsafe { socket(family, SOCK_DGRAM, protocol) })?;
+        Ok(unsafe { UdpSocket::from_raw_fd(fd) })
+    }
+
+    pub fn hostname() -> Option<String> {
+        let mut buf = [0u8; 256];
+        check(unsafe { gethostname(buf.as_mut_ptr(), buf.len()) }).ok()?;
+        let len = buf.iter().position(|&b| b == 0)?;
+        String::from_utf8(buf[..len].to_vec())
+            .ok()
+            .filter(|h| !h.is_empty())
+    }
+
+    pub fn interrupt_accept(listener: &TcpListener) -> io::Result<()> {
+        // Elsewhere, shutting down a listening socket fails with ENOTCONN.
+        const SHUT_RD: c_int = 0;
+        check(unsafe { shutdown(listener.as_raw_fd(), SHUT_RD) }).map(drop)
+    }
+
+    fn get_int(fd: c_int, name: c_int) -> io::Result<c_int> {
+        let mut value: c_int = 0;
+        let mut len = 4;
+        let ptr = &mut value as *mut c_int as *mut c_void;
+        check(unsafe { getsockopt(fd, SOL_SOCKET, name, ptr, &mut len) })?;
+        Ok(value)
+    }
+
+    pub fn adopt(fd: c_int) -> io::Result<Adopted> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "not a UDP or TCP socket");
+        let ty = get_int(fd, SO_TYPE)?;
+        check(unsafe { fcntl(fd, F_SETFD, FD_CLOEXEC) })?;
+        // Only IP sockets have an address the standard library can read.
+        let adopted = match ty {
+            TYPE_DGRAM => {
+                let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+                socket.local_addr()?;
+                Adopted::Udp(socket)
+            }
+            TYPE_STREAM if get_int(fd, SO_ACCEPTCONN)? != 0 => {
+                let listener = unsafe { TcpListener::from_raw_fd(fd) };
+                listener.local_addr()?;
+                Adopted::Tcp(listener)
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(adopted)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod imp {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+
+    use super::{Adopted, Reuse};
+
+    pub struct Mapping(Vec<u8>);
+
+    impl Mapping {
+        pub fn as_slice(&self) -> &[u8] {
+            &self.0
+        }
+    }
+
+    pub fn map(mut file: &File) -> io::Result<Mapping> {
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Ok(Mapping(buf))
+    }
+
+    pub fn adopt(_fd: i32) -> io::Result<Adopted> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "inherited sockets are not supported on this platform",
+        ))
+    }
+
+    pub fn bind_udp(addr: SocketAddr, _reuse: Reuse) -> io::Result<UdpSocket> {
+        UdpSocket::bind(addr)
+    }
+
+    pub fn bind_tcp(addr: SocketAddr, _reuse: Reuse) -> io::Result<TcpListener> {
+        TcpListener::bind(addr)
+    }
+
+    pub fn icmp_socket(_v6: bool) -> io::Result<UdpSocket> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ICMP sockets are not supported on this platform",
+        ))
+    }
+
+    pub fn hostname() -> Option<String> {
+        std::env::var("COMPUTERNAME").ok()
+    }
+
+    pub fn interrupt_accept(_listener: &TcpListener) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod mmsg {
+    use std::io;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+    use std::os::raw::{c_int, c_uint, c_void};
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    const AF_INET: u16 = 2;
+    const AF_INET6: u16 = 10;
+    const MSG_WAITFORONE: c_int = 0x10000;
+
+    /// Room for any `sockaddr`, aligned like `sockaddr_storage`.
+    type SockaddrStorage = [u64; 16];
+
+    #[repr(C)]
+    struct IoVec {
+        base: *mut c_void,
+        len: usize,
+    }
+
+    #[repr(C)]
+    struct MsgHdr {
+        name: *mut c_void,
+        name_len: u32,
+        iov: *mut IoVec,
+        iov_len: usize,
+        control: *mut c_void,
+        control_len: usize,
+        flags: c_int,
+    }
+
+    #[repr(C)]
+    struct MMsgHdr {
+        hdr: MsgHdr,
+        len: c_uint,
+    }
+
+    extern "C" {
+        fn recvmmsg(
+            fd: c_int,
+            msgs: *mut MMsgHdr,
+            len: c_uint,
+            flags: c_int,
+            timeout: *mut c_void,
+        ) -> c_int;
+        fn sendmmsg(fd: c_int, msgs: *mut MMsgHdr, len: c_uint, flags: c_int) -> c_int;
+        fn sched_setaffinity(pid: c_int, size: usize, mask: *const u64) -> c_int;
+    }
+
+    fn header(name: *mut SockaddrStorage, name_len: u32, iov: *mut IoVec) -> MMsgHdr {
+        MMsgHdr {
+            hdr: MsgHdr {
+                name: name as *mut c_void,
+                name_len,
+                iov,
+                iov_len: 1,
+                control: ptr::null_mut(),
+                control_len: 0,
+                flags: 0,
+            },
+            len: 0,
+        }
+    }
+
+    fn decode(name: &SockaddrStorage) -> io::Result<SocketAddr> {
+        let mut bytes = [0u8; 128];
+        for (chunk, word) in bytes.chunks_mut(8).zip(name) {
+            chunk.copy_from_slice(&word.to_ne_bytes());
+        }
+        decode_bytes(&bytes)
+    }
+
+    pub(super) fn decode_bytes(bytes: &[u8]) -> io::Result<SocketAddr> {
+        let family = match bytes {
+            [a, b, ..] => u16::from_ne_bytes([*a, *b]),
+            _ => 0,
+        };
+        match family {
+            AF_INET if bytes.len() >= 8 => {
+                let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+                let ip = Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]);
+                Ok(SocketAddr::from((ip, port)))
+            }
+            AF_INET6 if bytes.len() >= 28 => {
+                let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&bytes[8..24]);
+                let flowinfo = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+                let scope = u32::from_ne_bytes([bytes[24], bytes[25], bytes[26], bytes[27]]);
+                let addr = SocketAddrV6::new(Ipv6Addr::from(octets), port, flowinfo, scope);
+                Ok(SocketAddr::V6(addr))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected address family",
+            )),
+        }
+    }
+
+    fn encode(addr: &SocketAddr) -> (SockaddrStorage, u32) {
+        let (bytes, len) = super::imp::sockaddr(addr);
+        let mut name = [0u64; 16];
+        for (word, chunk) in name.iter_mut().zip(bytes.chunks(8)) {
+            let mut b = [0u8; 8];
+            b[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(b);
+        }
+        (name, len)
+    }
+
+    pub fn recv_batch(
+        socket: &UdpSocket,
+        bufs: &mut [Vec<u8>],
+        received: &mut Vec<(usize, SocketAddr)>,
+    ) -> io::Result<()> {
+        let mut names = vec![[0u64; 16]; bufs.len()];
+        let mut iovs: Vec<IoVec> = bufs
+            .iter_mut()
+            .map(|b| IoVec {
+                base: b.as_mut_ptr() as *mut c_void,
+                len: b.len(),
+            })
+            .collect();
+        let mut hdrs: Vec<MMsgHdr> = names
+            .iter_mut()
+            .zip(iovs.iter_mut())
+            .map(|(name, iov)| header(name, 128, iov))
+            .collect();
+        let n = unsafe {
+            recvmmsg(
+                socket.as_raw_fd(),
+                hdrs.as_mut_ptr(),
+                hdrs.len() as c_uint,
+                MSG_WAITFORONE,
+                ptr::null_mut(),
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for (hdr, name) in hdrs.iter().zip(&names).take(n as usize) {
+            received.push((hdr.len as usize, decode(name)?));
+        }
+        Ok(())
+    }
+
+    pub fn send_batch(socket: &UdpSocket, datagrams: &[(&[u8], SocketAddr)]) {
+        let mut names: Vec<(SockaddrStorage, u32)> =
+            datagrams.iter().map(|(_, dst)| encode(dst)).collect();
+        let mut iovs: Vec<IoVec> = datagrams
+            .iter()
+            .map(|(data, _)| IoVec {
+                base: data.as_ptr() as *mut c_void,
+                len: data.len(),
+            })
+            .collect();
+        let mut hdrs: Vec<MMsgHdr> = names
+            .iter_mut()
+            .zip(iovs.iter_mut())
+            .map(|((name, len), iov)| header(name, *len, iov))
+            .collect();
+        let mut sent = 0;
+        while sent < hdrs.len() {
+            let rest = &mut hdrs[sent..];
+            let n = unsafe {
+                sendmmsg(
+                    socket.as_raw_fd(),
+                    rest.as_mut_ptr(),
+                    rest.len() as c_uint,
+                    0,
+                )
+            };
+            // A negative result means the first datagram failed.
+            sent += if n > 0 { n as usize } else { 1 };
+        }
+    }
+
+    pub fn pin_thread(cpu: usize) -> io::Result<()> {
+        let mut mask = [0u64; 16];
+        mask[cpu / 64 % 16] |= 1 << (cpu % 64);
+        let size = std::mem::size_of_val(&mask);
+        if unsafe { sched_setaffinity(0, size, mask.as_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+//! Mock servers for testing code that talks DNS.
+//!
+//! A [`MockServer`] answers on a loopback port over both UDP and TCP, as
+//! scripted: a queue of [`Action`]s taken one per query, then fixed
+//! answers by question, then a fallback [`Handler`]. Actions can delay,
+//! truncate, mangle or drop responses, so that retries, server rotation
+//! and TCP fallback in a [`Resolver`](crate::resolver::Resolver) or a
+//! forwarder can be tested against servers that misbehave on cue. The
+//! server records every query it receives for the test to inspect.
+//!
+//! The mock speaks through real loopback sockets, so the crate's own
+//! transports are what is tested; nothing leaves the host.
+//!
+//! [`rfc5952`] holds conformance vectors for IPv6 address text, for
+//! checking the crate's handling of it and applications' own.
+
+pub mod rfc5952;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::client::{read_framed, write_framed, Protocol};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::rr::{Record, RecordType};
+use crate::server::{Handler, Request};
+
+/// How often the UDP thread checks whether the server has been dropped.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What a mock server does with one query.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// Sends this response, with the query's ID and question, and its EDNS
+    /// options if the query had EDNS.
+    Respond(Message),
+    /// Sends an empty response with this RCODE, such as FORMERR or
+    /// SERVFAIL.
+    Rcode(Rcode),
+    /// Sends an empty response with the TC bit set, as a server does when
+    /// the answer does not fit in UDP.
+    Truncate,
+    /// Sends a response whose header promises a question that is not
+    /// there.
+    Malformed,
+    /// Sends an empty response with another ID, which clients should
+    /// ignore.
+    WrongId,
+    /// Sends these bytes as they are.
+    Raw(Vec<u8>),
+    /// Sends nothing; over TCP, closes the connection.
+    Drop,
+    /// Waits, then does the other action.
+    Delay(Duration, Box<Action>),
+}
+
+impl Action {
+    /// A response holding `answers`.
+    pub fn answer(answers: Vec<Record>) -> Action {
+        Action::Respond(Message {
+            answers,
+            ..Message::default()
+        })
+    }
+
+    pub fn delay(by: Duration, then: Action) -> Action {
+        Action::Delay(by, Box::new(then))
+    }
+
+    /// The bytes to send in answer to `query`, if any.
+    fn perform(&self, query: &Message) -> Option<Vec<u8>> {
+        let mut resp = query.response();
+        match self {
+            Action::Respond(message) => {
+                resp.header.aa = message.header.aa;
+                resp.header.tc = message.header.tc;
+                resp.header.ra = message.header.ra;
+                resp.header.ad = message.header.ad;
+                resp.header.rcode = message.header.rcode;
+                resp.answers = message.answers.clone();
+                resp.authority = message.authority.clone();
+                resp.additional = message.additional.clone();
+                if let (Some(edns), Some(theirs)) = (&mut resp.edns, &message.edns) {
+                    edns.options = theirs.options.clone();
+                }
+            }
+            Action::Rcode(rcode) => resp.header.rcode = *rcode,
+            Action::Truncate => resp.header.tc = true,
+            Action::Malformed => {
+                let mut wire = resp.to_wire().ok()?;
+                wire.truncate(12);
+                wire[5] = 1;
+                return Some(wire);
+            }
+            Action::WrongId => resp.header.id = query.header.id.wrapping_add(1),
+            Action::Raw(bytes) => return Some(bytes.clone()),
+            Action::Drop => return None,
+            Action::Delay(by, then) => {
+                thread::sleep(*by);
+                return then.perform(query);
+            }
+        }
+        resp.to_wire().ok()
+    }
+}
+
+/// A query a mock server received.
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub message: Message,
+    pub protocol: Protocol,
+    pub src: SocketAddr,
+}
+
+struct Script {
+    queue: Mutex<VecDeque<Action>>,
+    answers: HashMap<(DomainName, RecordType), Action>,
+    handler: Option<Box<dyn Handler>>,
+    received: Mutex<Vec<Received>>,
+    stopped: AtomicBool,
+}
+
+impl Script {
+    /// The action for `query`: the next queued one, the one for its
+    /// question, or the handler's answer.
+    fn action(&self, query: &Message, src: SocketAddr, protocol: Protocol) -> Action {
+        self.received.lock().unwrap().push(Received {
+            message: query.clone(),
+            protocol,
+            src,
+        });
+        if let Some(action) = self.queue.lock().unwrap().pop_front() {
+            return action;
+        }
+        if let Some(action) = query
+            .question()
+            .and_then(|q| self.answers.get(&(q.name.clone(), q.qtype)))
+        {
+            return action.clone();
+        }
+        let request = Request {
+            message: query.clone(),
+            src,
+            protocol,
+            key: None,
+        };
+        match self.handler.as_ref().and_then(|h| h.handle(&request)) {
+            Some(resp) => Action::Respond(resp),
+            None => Action::Rcode(Rcode::REFUSED),
+        }
+    }
+
+    fn reply(&self, buf: &[u8], src: SocketAddr, protocol: Protocol) -> Option<Vec<u8>> {
+        let query = Message::from_wire(buf).ok()?;
+        self.action(&query, src, protocol).perform(&query)
+    }
+}
+
+/// Builds a [`MockServer`].
+#[derive(Default)]
+pub struct MockServerBuilder {
+    queue: VecDeque<Action>,
+    answers: HashMap<(DomainName, RecordType), Action>,
+    handler: Option<Box<dyn Handler>>,
+}
+
+impl MockServerBuilder {
+    /// Queues `action` for the next query not taken by an earlier one,
+    /// whatever it asks.
+    pub fn then(mut self, action: Action) -> Self {
+        self.queue.push_back(action);
+        self
+    }
+
+    /// Does `action` for every query for `name`/`qtype` once the queue is
+    /// empty.
+    pub fn on(mut self, name: DomainName, qtype: RecordType, action: Action) -> Self {
+        self.answers.insert((name, qtype), action);
+        self
+    }
+
+    /// Answers queries for `name`/`qtype` with `answers`.
+    pub fn answer(self, name: DomainName, qtype: RecordType, answers: Vec<Record>) -> Self {
+        self.on(name, qtype, Action::answer(answers))
+    }
+
+    /// Passes queries nothing else takes to `handler`, such as an
+    /// [`Authority`](crate::server::Authority); they are REFUSED without
+    /// one.
+    pub fn handler<H: Handler>(mut self, handler: H) -> Self {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Starts serving on a loopback port, over UDP and TCP.
+    pub fn start(self) -> io::Result<MockServer> {
+        self.start_on(SocketAddr::from(([127, 0, 0, 1], 0)))
+    }
+
+    /// Starts serving on `addr`, over UDP and TCP, for code that only
+    /// talks to the standard port, such as iterative resolution; binding
+    /// port 53 takes privileges.
+    pub fn start_on(self, addr: SocketAddr) -> io::Result<MockServer> {
+        // With port 0, TCP takes the port UDP was given, unless something
+        // holds it over TCP already; then both try another.
+        let mut attempts = if addr.port() == 0 { 8 } else { 1 };
+        let (udp, tcp) = loop {
+            let udp = UdpSocket::bind(addr)?;
+            match TcpListener::bind(udp.local_addr()?) {
+                Ok(tcp) => break (udp, tcp),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempts > 1 => attempts -= 1,
+                Err(e) => return Err(e),
+            }
+        };
+        let addr = udp.local_addr()?;
+        udp.set_read_timeout(Some(POLL_INTERVAL))?;
+        let script = Arc::new(Script {
+            queue: Mutex::new(self.queue),
+            answers: self.answers,
+            handler: self.handler,
+            received: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        });
+        let s = script.clone();
+        thread::spawn(move || serve_udp(&udp, &s));
+        let s = script.clone();
+        thread::spawn(move || serve_tcp(&tcp, &s));
+        Ok(MockServer { addr, script })
+    }
+}
+
+/// A scripted DNS server on a loopback port, stopped when dropped.
+pub struct MockServer {
+    addr: SocketAddr,
+    script: Arc<Script>,
+}
+
+impl MockServer {
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::default()
+    }
+
+    /// The address it serves on, over both UDP and TCP.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Queues `action` for the next query, behind those already queued.
+    pub fn push(&self, action: Action) {
+        self.script.queue.lock().unwrap().push_back(action);
+    }
+
+    /// The queries received so far, in order.
+    pub fn received(&self) -> Vec<Received> {
+        self.script.received.lock().unwrap().clone()
+    }
+
+    /// How many queries arrived over `protocol`.
+    pub fn count(&self, protocol: Protocol) -> usize {
+        let received = self.script.received.lock().unwrap();
+        received.iter().filter(|r| r.protocol == protocol).count()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.script.stopped.store(true, Ordering::SeqCst);
+        // Wakes the TCP listener, which then sees the flag.
+        let _ = TcpStream::connect_timeout(&self.addr, POLL_INTERVAL);
+    }
+}
+
+fn serve_udp(socket: &UdpSocket, script: &Arc<Script>) {
+    let mut buf = vec![0u8; 65535];
+    while !script.stopped.load(Ordering::SeqCst) {
+        let (n, src) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(_) => return,
+        };
+        let buf = buf[..n].to_vec();
+        let (socket, script) = match socket.try_clone() {
+            Ok(socket) => (socket, script.clone()),
+            Err(_) => return,
+        };
+        // Each query on its own, so that a delayed one holds up no other.
+        thread::spawn(move || {
+            if let Some(wire) = script.reply(&buf, src, Protocol::Udp) {
+                let _ = socket.send_to(&wire, src);
+            }
+        });
+    }
+}
+
+fn serve_tcp(listener: &TcpListener, script: &Arc<Script>) {
+    for stream in listener.incoming() {
+        if script.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let (mut stream, script) = match stream {
+            Ok(stream) => (stream, script.clone()),
+            Err(_) => continue,
+        };
+        thread::spawn(move || {
+            let src = match stream.peer_addr() {
+                Ok(src) => src,
+                Err(_) => return,
+            };
+            while let Ok(buf) = read_framed(&mut stream) {
+                match script.reply(&buf, src, Protocol::Tcp) {
+                    Some(wire) if write_framed(&mut stream, &wire).is_ok() => {}
+                    _ => return,
+                }
+            }
+        });
+    }
+}
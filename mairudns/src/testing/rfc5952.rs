@@ -0,0 +1,506 @@
+//! Conformance vectors for IPv6 address text (RFC 4291 §2.2, RFC 5952).
+//!
+//! The crate reads and writes IPv6 addresses through [`Ipv6Addr`], in
+//! zone files, configuration, the C ABI and record data shown as text.
+//! These vectors pin down what that text must look like, so that a
+//! change of implementation, or an application's own wrapper around
+//! addresses, can be checked against the RFC rather than against itself:
+//!
+//! - [`CASES`]: hand-picked inputs and their canonical forms, one rule of
+//!   RFC 5952 each, the examples of its §4 among them.
+//! - [`INVALID`]: text that is not an IPv6 address and must be refused.
+//! - [`vectors`]: the cases, with several hundred more generated from
+//!   every placement of up to two runs of zero fields among fields with
+//!   and without leading zeros, each written in several forms.
+//!
+//! [`check`] runs them all against a parser and a formatter, and
+//! [`canonical`] is the reference the generated ones are checked against.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+
+/// The rule of RFC 5952 a vector exercises.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Leading zeros in a field are left out (§4.1).
+    LeadingZeros,
+    /// `::` stands for the longest run of zero fields it can (§4.2.1).
+    Longest,
+    /// A single zero field is not shortened to `::` (§4.2.2).
+    SingleZero,
+    /// Of runs of equal length, the first is shortened (§4.2.3).
+    FirstOfEqual,
+    /// Hexadecimal digits are written in lowercase (§4.3).
+    Lowercase,
+    /// IPv4-mapped addresses end in dotted decimal (§5); other addresses
+    /// read in mixed notation are written in hexadecimal.
+    Mixed,
+    /// Fields with leading zeros next to the run `::` stands for, where
+    /// leaving the zeros out must not move or lengthen the run.
+    NextToRun,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rule::LeadingZeros => "leading zeros (§4.1)",
+            Rule::Longest => "longest run (§4.2.1)",
+            Rule::SingleZero => "single zero field (§4.2.2)",
+            Rule::FirstOfEqual => "first of equal runs (§4.2.3)",
+            Rule::Lowercase => "lowercase (§4.3)",
+            Rule::Mixed => "mixed notation (§5)",
+            Rule::NextToRun => "leading zeros next to the run",
+        })
+    }
+}
+
+/// Inputs and the canonical text of the address they are, by the rule
+/// each mainly exercises.
+pub const CASES: &[(&str, &str, Rule)] = &[
+    // §4.1
+    (
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:0001",
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:1",
+        Rule::LeadingZeros,
+    ),
+    (
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:001",
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:1",
+        Rule::LeadingZeros,
+    ),
+    (
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:01",
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:1",
+        Rule::LeadingZeros,
+    ),
+    (
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:1",
+        "2001:db8:aaaa:bbbb:cccc:dddd:eeee:1",
+        Rule::LeadingZeros,
+    ),
+    (
+        "2001:0db8:0000:0001:0001:0001:0001:0001",
+        "2001:db8:0:1:1:1:1:1",
+        Rule::LeadingZeros,
+    ),
+    ("000a::00b0", "a::b0", Rule::LeadingZeros),
+    ("0a00:0:0:0:0:0:0:00a0", "a00::a0", Rule::LeadingZeros),
+    (
+        "0000:0000:0000:0000:0000:0000:0000:0000",
+        "::",
+        Rule::LeadingZeros,
+    ),
+    ("0:0:0:0:0:0:0:0", "::", Rule::LeadingZeros),
+    ("0:0:0:0:0:0:0:1", "::1", Rule::LeadingZeros),
+    ("::0:1", "::1", Rule::LeadingZeros),
+    // §4.2.1
+    ("2001:db8:0:0:0:0:2:1", "2001:db8::2:1", Rule::Longest),
+    ("2001:db8:0:0:0::1", "2001:db8::1", Rule::Longest),
+    ("2001:db8:0:0::1", "2001:db8::1", Rule::Longest),
+    ("2001:db8:0::1", "2001:db8::1", Rule::Longest),
+    ("2001:db8::1", "2001:db8::1", Rule::Longest),
+    ("2001:0:0:1:0:0:0:1", "2001:0:0:1::1", Rule::Longest),
+    ("2001::1:0:0:0:1", "2001:0:0:1::1", Rule::Longest),
+    ("1:0:0:2:0:0:0:3", "1:0:0:2::3", Rule::Longest),
+    ("1:0:0:0:2:0:0:3", "1::2:0:0:3", Rule::Longest),
+    ("2001:db8:0:0:1:0:0:0", "2001:db8:0:0:1::", Rule::Longest),
+    ("1:0:0:0:0:0:0:0", "1::", Rule::Longest),
+    (
+        "fe80:0000:0000:0000:0204:61ff:fe9d:f156",
+        "fe80::204:61ff:fe9d:f156",
+        Rule::Longest,
+    ),
+    ("ff02:0:0:0:0:0:0:fb", "ff02::fb", Rule::Longest),
+    // §4.2.2
+    (
+        "2001:db8:aaaa:bbbb:cccc:dddd::1",
+        "2001:db8:aaaa:bbbb:cccc:dddd:0:1",
+        Rule::SingleZero,
+    ),
+    (
+        "2001:db8:0:1:1:1:1:1",
+        "2001:db8:0:1:1:1:1:1",
+        Rule::SingleZero,
+    ),
+    ("1:2:3:4:5:6:7::", "1:2:3:4:5:6:7:0", Rule::SingleZero),
+    ("::2:3:4:5:6:7:8", "0:2:3:4:5:6:7:8", Rule::SingleZero),
+    ("1:2:3::5:6:7:8", "1:2:3:0:5:6:7:8", Rule::SingleZero),
+    ("0:1:0:1:0:1:0:1", "0:1:0:1:0:1:0:1", Rule::SingleZero),
+    // §4.2.3
+    (
+        "2001:db8:0:0:1:0:0:1",
+        "2001:db8::1:0:0:1",
+        Rule::FirstOfEqual,
+    ),
+    (
+        "2001:0db8:0:0:1:0:0:1",
+        "2001:db8::1:0:0:1",
+        Rule::FirstOfEqual,
+    ),
+    (
+        "2001:db8::0:1:0:0:1",
+        "2001:db8::1:0:0:1",
+        Rule::FirstOfEqual,
+    ),
+    ("2001:db8:0:0:1::1", "2001:db8::1:0:0:1", Rule::FirstOfEqual),
+    (
+        "2001:db8:0:0:aaaa::1",
+        "2001:db8::aaaa:0:0:1",
+        Rule::FirstOfEqual,
+    ),
+    ("0:0:1:0:0:1:0:0", "::1:0:0:1:0:0", Rule::FirstOfEqual),
+    ("1:0:0:1:0:0:1:1", "1::1:0:0:1:1", Rule::FirstOfEqual),
+    ("1:0:0:0:1:0:0:0", "1::1:0:0:0", Rule::FirstOfEqual),
+    // §4.3
+    ("2001:DB8:0:0:1::1", "2001:db8::1:0:0:1", Rule::Lowercase),
+    (
+        "2001:Db8::ABCD:Ef01",
+        "2001:db8::abcd:ef01",
+        Rule::Lowercase,
+    ),
+    ("FE80::1", "fe80::1", Rule::Lowercase),
+    ("::FFFF:C000:201", "::ffff:192.0.2.1", Rule::Lowercase),
+    // §5
+    ("::ffff:192.0.2.1", "::ffff:192.0.2.1", Rule::Mixed),
+    ("0:0:0:0:0:ffff:c000:0201", "::ffff:192.0.2.1", Rule::Mixed),
+    ("::ffff:0.0.0.0", "::ffff:0.0.0.0", Rule::Mixed),
+    (
+        "::ffff:255.255.255.255",
+        "::ffff:255.255.255.255",
+        Rule::Mixed,
+    ),
+    ("64:ff9b::192.0.2.33", "64:ff9b::c000:221", Rule::Mixed),
+    ("::192.0.2.1", "::c000:201", Rule::Mixed),
+    ("1:2:3:4:5:6:1.2.3.4", "1:2:3:4:5:6:102:304", Rule::Mixed),
+    ("::ffff:0:192.0.2.1", "::ffff:0:c000:201", Rule::Mixed),
+    // Leading zeros next to the run.
+    (
+        "2001:0db8:0000:0000:0000:0000:0001:0001",
+        "2001:db8::1:1",
+        Rule::NextToRun,
+    ),
+    (
+        "0001:0000:0000:0000:0000:0000:0000:0001",
+        "1::1",
+        Rule::NextToRun,
+    ),
+    (
+        "0000:0001:0000:0000:0000:0000:0000:0000",
+        "0:1::",
+        Rule::NextToRun,
+    ),
+    (
+        "0000:0000:0000:0000:0000:0000:0001:0000",
+        "::1:0",
+        Rule::NextToRun,
+    ),
+    (
+        "2001:0db8:0000:0000:00aa:0000:0000:0000",
+        "2001:db8:0:0:aa::",
+        Rule::NextToRun,
+    ),
+    (
+        "2001:0db8:0000:00a0:0000:0000:0000:0001",
+        "2001:db8:0:a0::1",
+        Rule::NextToRun,
+    ),
+    (
+        "2001:0db8:00a0:0000:0000:0a00:0000:0001",
+        "2001:db8:a0::a00:0:1",
+        Rule::NextToRun,
+    ),
+    (
+        "0010:0000:0000:0100:0000:0000:0000:1000",
+        "10:0:0:100::1000",
+        Rule::NextToRun,
+    ),
+    (
+        "2001:0db8::000a:0000:0000:0000",
+        "2001:db8:0:0:a::",
+        Rule::NextToRun,
+    ),
+    ("0:0:0a:0:0:0:0:0b", "0:0:a::b", Rule::NextToRun),
+];
+
+/// Text that is not an IPv6 address.
+pub const INVALID: &[&str] = &[
+    "",
+    ":",
+    ":::",
+    "::::",
+    "1:::2",
+    "1::2::3",
+    "::1::",
+    "1:2:3:4:5:6:7",
+    "1:2:3:4:5:6:7:8:9",
+    "1:2:3:4:5:6:7:8::",
+    "::1:2:3:4:5:6:7:8",
+    ":1:2:3:4:5:6:7:8",
+    "1:2:3:4:5:6:7:8:",
+    ":1::2",
+    "1::2:",
+    "12345::",
+    "1:2:3:4:5:6:7:10000",
+    "g::",
+    "::g",
+    "2001:db8::x",
+    " ::1",
+    "::1 ",
+    "[::1]",
+    "fe80::1%eth0",
+    "::1/128",
+    "1.2.3.4",
+    "::ffff:1.2.3",
+    "::ffff:1.2.3.4.5",
+    "::ffff:256.1.1.1",
+    "::ffff:1.2.3.04",
+    "::1.2.3.4:1",
+    "1:2:3:4:5:6:7:1.2.3.4",
+    "1:2:3:4:5:6:7:8:1.2.3.4",
+    "0x1::",
+    "-1::",
+    "+1::",
+];
+
+/// One vector: text that must parse, and the text the address must be
+/// written as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vector {
+    pub input: String,
+    pub canonical: String,
+    pub rule: Rule,
+}
+
+/// Where an implementation departs from a vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Valid text was refused.
+    Refused(Vector),
+    /// Valid text was read as another address than the canonical text.
+    Misread(Vector, Ipv6Addr),
+    /// The address was written as other text.
+    Miswritten(Vector, String),
+    /// Text that is not an address was accepted.
+    Accepted(&'static str),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Refused(v) => write!(f, "{}: refused {:?}", v.rule, v.input),
+            Failure::Misread(v, addr) => write!(
+                f,
+                "{}: read {:?} as {}, not {}",
+                v.rule, v.input, addr, v.canonical
+            ),
+            Failure::Miswritten(v, text) => write!(
+                f,
+                "{}: wrote {:?} as {:?}, not {:?}",
+                v.rule, v.input, text, v.canonical
+            ),
+            Failure::Accepted(text) => write!(f, "accepted {:?}", text),
+        }
+    }
+}
+
+/// The canonical text of `addr` as RFC 5952 gives it, written out rule by
+/// rule as the reference for the generated vectors.
+pub fn canonical(addr: Ipv6Addr) -> String {
+    let fields = addr.segments();
+    if fields[..6] == [0, 0, 0, 0, 0, 0xffff] {
+        let [a, b] = fields[6].to_be_bytes();
+        let [c, d] = fields[7].to_be_bytes();
+        return format!("::ffff:{}", Ipv4Addr::new(a, b, c, d));
+    }
+    // The first of the longest runs of two or more zero fields.
+    let mut best = 0..0;
+    for run in zero_runs(&fields) {
+        if run.len() >= 2 && run.len() > best.len() {
+            best = run;
+        }
+    }
+    let hex = |fields: &[u16]| {
+        fields
+            .iter()
+            .map(|f| format!("{:x}", f))
+            .collect::<Vec<_>>()
+            .join(":")
+    };
+    if best.is_empty() {
+        return hex(&fields);
+    }
+    format!(
+        "{}::{}",
+        hex(&fields[..best.start]),
+        hex(&fields[best.end..])
+    )
+}
+
+/// Fields that fill what the runs of zeros leave, with and without
+/// leading zeros.
+const FILLERS: [u16; 8] = [0x1, 0xa, 0x10, 0xab, 0x100, 0xabc, 0x1000, 0xabcd];
+
+/// Every vector: [`CASES`], and those generated from every placement of
+/// one run of zero fields, and of two, each address written fully
+/// expanded with leading zeros, in uppercase, canonically, and with `::`
+/// standing for a run other than the one it should.
+pub fn vectors() -> Vec<Vector> {
+    let mut out: Vec<Vector> = CASES
+        .iter()
+        .map(|&(input, canonical, rule)| Vector {
+            input: input.to_string(),
+            canonical: canonical.to_string(),
+            rule,
+        })
+        .collect();
+    let mut addrs = Vec::new();
+    for start in 0..8 {
+        for len in 1..=8 - start {
+            addrs.push(fill(&[(start, start + len)], start));
+        }
+    }
+    for a in 0..8 {
+        for a_len in 1..=3 {
+            for b in a + a_len + 1..8 {
+                for b_len in 1..=(8 - b).min(3) {
+                    addrs.push(fill(&[(a, a + a_len), (b, b + b_len)], a + b));
+                }
+            }
+        }
+    }
+    for fields in addrs {
+        let addr = Ipv6Addr::from(fields);
+        let canonical = canonical(addr);
+        let rule = rule_of(&fields);
+        let expanded: Vec<String> = fields.iter().map(|f| format!("{:04x}", f)).collect();
+        let mut inputs: Vec<String> = Vec::new();
+        for input in vec![
+            expanded.join(":"),
+            expanded.join(":").to_uppercase(),
+            canonical.clone(),
+        ]
+        .into_iter()
+        .chain(other_compressions(&fields))
+        {
+            if !inputs.contains(&input) {
+                inputs.push(input);
+            }
+        }
+        for input in inputs {
+            let rule = if input.contains(|c: char| c.is_ascii_uppercase()) {
+                Rule::Lowercase
+            } else {
+                rule
+            };
+            out.push(Vector {
+                input,
+                canonical: canonical.clone(),
+                rule,
+            });
+        }
+    }
+    out
+}
+
+/// Eight fields, zero over `runs`, each a start and an end, and filled
+/// elsewhere from [`FILLERS`], starting at `seed`.
+fn fill(runs: &[(usize, usize)], seed: usize) -> [u16; 8] {
+    let mut fields = [0u16; 8];
+    for (i, field) in fields.iter_mut().enumerate() {
+        if !runs.iter().any(|&(start, end)| (start..end).contains(&i)) {
+            *field = FILLERS[(seed + i) % FILLERS.len()];
+        }
+    }
+    fields
+}
+
+/// The rule canonical text of `fields` turns most on.
+fn rule_of(fields: &[u16; 8]) -> Rule {
+    let runs = zero_runs(fields);
+    let longest = match runs.iter().map(|r| r.len()).max() {
+        Some(len) if len >= 2 => len,
+        Some(_) => return Rule::SingleZero,
+        None => return Rule::LeadingZeros,
+    };
+    let run = runs.iter().find(|r| r.len() == longest).unwrap();
+    let padded = |i: usize| fields.get(i).is_some_and(|&f| f != 0 && f < 0x1000);
+    if runs.iter().filter(|r| r.len() == longest).count() > 1 {
+        Rule::FirstOfEqual
+    } else if runs.len() > 1 {
+        Rule::Longest
+    } else if padded(run.end) || run.start.checked_sub(1).is_some_and(padded) {
+        Rule::NextToRun
+    } else {
+        Rule::Longest
+    }
+}
+
+/// The runs of zero fields in `fields`.
+fn zero_runs(fields: &[u16]) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (i, &field) in fields.iter().enumerate() {
+        if field != 0 {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if run.end == i => run.end += 1,
+            _ => runs.push(i..i + 1),
+        }
+    }
+    runs
+}
+
+/// `fields` written with `::` for each run of zeros in turn, with leading
+/// zeros kept in the other fields.
+fn other_compressions(fields: &[u16; 8]) -> Vec<String> {
+    let padded = |fields: &[u16]| {
+        fields
+            .iter()
+            .map(|f| format!("{:02x}", f))
+            .collect::<Vec<_>>()
+            .join(":")
+    };
+    zero_runs(fields)
+        .into_iter()
+        .map(|r| {
+            format!(
+                "{}::{}",
+                padded(&fields[..r.start]),
+                padded(&fields[r.end..])
+            )
+        })
+        .collect()
+}
+
+/// Runs every vector and [`INVALID`] text against `parse` and `format`,
+/// returning where they depart from them. What each input should read
+/// as is its canonical text read by [`Ipv6Addr`].
+pub fn check(
+    parse: impl Fn(&str) -> Option<Ipv6Addr>,
+    format: impl Fn(Ipv6Addr) -> String,
+) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for vector in vectors() {
+        let expected: Ipv6Addr = match vector.canonical.parse() {
+            Ok(addr) => addr,
+            Err(_) => panic!("bad vector {:?}", vector),
+        };
+        match parse(&vector.input) {
+            None => failures.push(Failure::Refused(vector)),
+            Some(addr) if addr != expected => failures.push(Failure::Misread(vector, addr)),
+            Some(addr) => {
+                let text = format(addr);
+                if text != vector.canonical {
+                    failures.push(Failure::Miswritten(vector, text));
+                }
+            }
+        }
+    }
+    for &text in INVALID {
+        if parse(text).is_some() {
+            failures.push(Failure::Accepted(text));
+        }
+    }
+    failures
+}
@@ -0,0 +1,254 @@
+//! Finding the names under a domain by querying a list of candidates.
+//!
+//! [`BruteForce`] fans the queries out over a pool of threads, spreads
+//! them across the configured servers and paces each server to a query
+//! rate. Before it starts it asks for a few random labels: a domain with a
+//! wildcard answers those too, and findings whose answers match the
+//! wildcard's are flagged rather than reported as names of their own.
+
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{self, Protocol};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::rr::{Record, RecordType};
+
+/// How candidate names are queried.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BruteForce {
+    /// The servers asked, in turn; recursive resolvers or the domain's
+    /// authoritative servers.
+    pub servers: Vec<SocketAddr>,
+    /// The types asked for each name. CNAME records come back in the
+    /// answers to the others, dangling ones included.
+    pub types: Vec<RecordType>,
+    /// Queries outstanding at once at most.
+    pub concurrency: usize,
+    /// Queries a second sent to each server at most, or as fast as they
+    /// answer if `None`.
+    pub rate: Option<f64>,
+    pub timeout: Duration,
+    /// Tries for each query, moving to the next server after a failure.
+    pub attempts: usize,
+    /// Random labels asked first to find a wildcard; 0 skips the check.
+    pub wildcard_probes: usize,
+}
+
+/// A name that answered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub name: DomainName,
+    /// The answer records for every type asked, CNAME chains included.
+    pub records: Vec<Record>,
+    /// Every answer matched the wildcard's, so the name may not exist in
+    /// its own right.
+    pub wildcard: bool,
+}
+
+/// The outcome of [`BruteForce::run`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BruteReport {
+    /// The names that answered, in canonical order.
+    pub found: Vec<Finding>,
+    /// The records the random labels got, if the domain has a wildcard.
+    pub wildcard: Vec<Record>,
+    pub queries: usize,
+    /// Queries that got no response from any server.
+    pub failed: Vec<(DomainName, RecordType)>,
+    /// Entries of the list that are not valid names.
+    pub invalid: Vec<String>,
+    pub elapsed: Duration,
+}
+
+impl BruteReport {
+    pub fn queries_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.queries as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Spaces the queries sent to one server.
+struct Pacer {
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    /// Waits for the next slot at `rate` queries a second.
+    fn wait(&self, rate: Option<f64>) {
+        let interval = match rate.filter(|r| *r > 0.0) {
+            Some(rate) => Duration::from_secs_f64(1.0 / rate),
+            None => return,
+        };
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + interval;
+            slot
+        };
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+impl BruteForce {
+    pub fn new(servers: Vec<SocketAddr>) -> BruteForce {
+        BruteForce {
+            servers,
+            types: vec![RecordType::A, RecordType::AAAA],
+            concurrency: 32,
+            rate: None,
+            timeout: Duration::from_secs(2),
+            attempts: 2,
+            wildcard_probes: 3,
+        }
+    }
+
+    /// Queries each of `words`, labels or names relative to `domain`, for
+    /// each of [`BruteForce::types`].
+    pub fn run<I, S>(&self, domain: &DomainName, words: I) -> BruteReport
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let start = Instant::now();
+        let mut report = BruteReport::default();
+        let mut names = Vec::new();
+        for word in words {
+            let word = word.as_ref().trim();
+            if word.is_empty() {
+                continue;
+            }
+            match DomainName::parse_relative(word, domain) {
+                Ok(name) if name.is_subdomain_of(domain) => names.push(name),
+                _ => report.invalid.push(word.to_string()),
+            }
+        }
+        names.sort();
+        names.dedup();
+
+        let pacers: Vec<Pacer> = self
+            .servers
+            .iter()
+            .map(|_| Pacer {
+                next: Mutex::new(Instant::now()),
+            })
+            .collect();
+        let queries = AtomicUsize::new(0);
+        let ask = |i: usize, name: &DomainName, qtype: RecordType| {
+            self.ask(&pacers, i, name, qtype, &queries)
+        };
+
+        let probes: Vec<(DomainName, RecordType)> = (0..self.wildcard_probes)
+            .filter_map(|_| {
+                domain
+                    .prepend(format!("{:016x}", random::u64()).as_bytes())
+                    .ok()
+            })
+            .flat_map(|name| self.types.iter().map(move |&t| (name.clone(), t)))
+            .collect();
+        let mut wildcard = Vec::new();
+        for (i, (name, qtype)) in probes.iter().enumerate() {
+            if let Some(resp) = ask(i, name, *qtype) {
+                wildcard.extend(resp.answers);
+            }
+        }
+        let signature: HashSet<(RecordType, String)> = wildcard.iter().map(rdata_key).collect();
+
+        let jobs: Vec<(&DomainName, RecordType)> = names
+            .iter()
+            .flat_map(|name| self.types.iter().map(move |&t| (name, t)))
+            .collect();
+        let next = AtomicUsize::new(0);
+        let answers: Mutex<BTreeMap<DomainName, Vec<Record>>> = Mutex::new(BTreeMap::new());
+        let failed = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.max(1) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let (name, qtype) = match jobs.get(i) {
+                        Some(&job) => job,
+                        None => return,
+                    };
+                    match ask(i, name, qtype) {
+                        Some(resp) if !resp.answers.is_empty() => {
+                            let mut answers = answers.lock().unwrap();
+                            let records = answers.entry(name.clone()).or_default();
+                            for rr in resp.answers {
+                                if !records.contains(&rr) {
+                                    records.push(rr);
+                                }
+                            }
+                        }
+                        Some(_) => {}
+                        None => failed.lock().unwrap().push((name.clone(), qtype)),
+                    }
+                });
+            }
+        });
+
+        report.found = answers
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|(name, records)| Finding {
+                wildcard: !signature.is_empty()
+                    && records.iter().all(|rr| signature.contains(&rdata_key(rr))),
+                name,
+                records,
+            })
+            .collect();
+        report.wildcard = wildcard;
+        report.queries = queries.into_inner();
+        report.failed = failed.into_inner().unwrap();
+        report.failed.sort();
+        report.elapsed = start.elapsed();
+        report
+    }
+
+    /// Sends one query, starting with server `i` of the list and moving
+    /// round it on failures. Responses other than NOERROR and NXDOMAIN
+    /// count as failures.
+    fn ask(
+        &self,
+        pacers: &[Pacer],
+        i: usize,
+        name: &DomainName,
+        qtype: RecordType,
+        queries: &AtomicUsize,
+    ) -> Option<Message> {
+        if self.servers.is_empty() {
+            return None;
+        }
+        let query = Message::query(name.clone(), qtype);
+        for attempt in 0..self.attempts.max(1) {
+            let n = (i + attempt) % self.servers.len();
+            pacers[n].wait(self.rate);
+            queries.fetch_add(1, Ordering::Relaxed);
+            let server = self.servers[n];
+            let mut result = client::exchange(server, Protocol::Udp, &query, self.timeout);
+            if result.as_ref().is_ok_and(|resp| resp.header.tc) {
+                result = client::exchange(server, Protocol::Tcp, &query, self.timeout);
+            }
+            match result {
+                Ok(resp) if matches!(resp.header.rcode, Rcode::NOERROR | Rcode::NXDOMAIN) => {
+                    return Some(resp)
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// What wildcard answers are compared on: the type and data, as the
+/// owner differs with every name asked and the TTL counts down.
+fn rdata_key(rr: &Record) -> (RecordType, String) {
+    (rr.rtype(), rr.rdata.to_string())
+}
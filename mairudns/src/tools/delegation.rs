@@ -0,0 +1,484 @@
+//! Checking that a zone is delegated consistently and served properly.
+//!
+//! [`check_delegation`] asks the parent zone's servers for the delegation,
+//! then asks every name server either side lists: whether it answers for
+//! the zone authoritatively, what NS set and SOA serial it serves, and
+//! whether it answers recursive queries for names outside the zone too.
+//! The glue the parent publishes is compared with the addresses the zone
+//! itself gives its name servers.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::client::{self, Protocol};
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::resolver::{self, Resolver};
+use crate::rr::{RData, RecordType};
+
+/// Errors that stop a check before any server is asked.
+#[derive(Debug)]
+pub enum CheckError {
+    /// The root has no parent to be delegated from.
+    Root,
+    /// The parent zone or its servers could not be found.
+    Resolver(resolver::Error),
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::Root => f.write_str("the root zone has no delegation"),
+            CheckError::Resolver(e) => write!(f, "finding the parent zone: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+impl From<resolver::Error> for CheckError {
+    fn from(e: resolver::Error) -> CheckError {
+        CheckError::Resolver(e)
+    }
+}
+
+/// How one name server address answered for the zone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerStatus {
+    /// It answered with the zone's SOA, authoritatively.
+    Authoritative { serial: u32 },
+    /// It answered, but not as a server for the zone: with this RCODE, or
+    /// without the AA bit or the SOA.
+    Lame(Rcode),
+    /// It did not answer.
+    Unreachable(String),
+}
+
+/// One address of a name server, as checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerCheck {
+    pub name: DomainName,
+    pub addr: IpAddr,
+    pub status: ServerStatus,
+    /// The NS set it serves for the zone, if it is authoritative.
+    pub ns: BTreeSet<DomainName>,
+    /// It answered a recursive query for a name outside the zone.
+    pub open_resolver: bool,
+}
+
+/// Something wrong with the delegation or its servers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// The NS sets of the parent and the zone differ.
+    NsMismatch {
+        only_parent: Vec<DomainName>,
+        only_child: Vec<DomainName>,
+    },
+    /// The zone's own servers serve different NS sets.
+    ChildNsDisagree,
+    /// The zone's servers serve different SOA serials.
+    SerialMismatch(Vec<u32>),
+    /// A listed name server has no address.
+    NoAddress(DomainName),
+    Lame {
+        name: DomainName,
+        addr: IpAddr,
+    },
+    Unreachable {
+        name: DomainName,
+        addr: IpAddr,
+    },
+    OpenResolver {
+        name: DomainName,
+        addr: IpAddr,
+    },
+    /// A name server inside the zone has no glue at the parent.
+    MissingGlue(DomainName),
+    /// The parent's glue for a name server differs from the addresses the
+    /// zone gives it.
+    GlueMismatch {
+        name: DomainName,
+        parent: Vec<IpAddr>,
+        child: Vec<IpAddr>,
+    },
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NsMismatch {
+                only_parent,
+                only_child,
+            } => {
+                f.write_str("NS sets differ; only at the parent: ")?;
+                write_list(f, only_parent)?;
+                f.write_str("; only in the zone: ")?;
+                write_list(f, only_child)
+            }
+            Problem::ChildNsDisagree => f.write_str("the zone's servers serve different NS sets"),
+            Problem::SerialMismatch(serials) => {
+                f.write_str("the zone's servers serve different serials: ")?;
+                write_list(f, serials)
+            }
+            Problem::NoAddress(name) => write!(f, "{} has no address", name),
+            Problem::Lame { name, addr } => write!(f, "{} ({}) is lame", name, addr),
+            Problem::Unreachable { name, addr } => {
+                write!(f, "{} ({}) does not answer", name, addr)
+            }
+            Problem::OpenResolver { name, addr } => {
+                write!(f, "{} ({}) is an open resolver", name, addr)
+            }
+            Problem::MissingGlue(name) => write!(f, "no glue at the parent for {}", name),
+            Problem::GlueMismatch {
+                name,
+                parent,
+                child,
+            } => {
+                write!(f, "glue for {} at the parent is ", name)?;
+                write_list(f, parent)?;
+                f.write_str(" but the zone has ")?;
+                write_list(f, child)
+            }
+        }
+    }
+}
+
+/// What [`check_delegation`] found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegationReport {
+    pub zone: DomainName,
+    pub parent: DomainName,
+    /// The NS set the parent delegates to, across its servers.
+    pub parent_ns: BTreeSet<DomainName>,
+    /// The NS set the zone's authoritative servers serve, across them.
+    pub child_ns: BTreeSet<DomainName>,
+    /// The parent's glue addresses.
+    pub glue: BTreeMap<DomainName, BTreeSet<IpAddr>>,
+    pub servers: Vec<ServerCheck>,
+    pub problems: Vec<Problem>,
+}
+
+impl DelegationReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// How a delegation is checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegationCheck {
+    /// The port name servers are asked on.
+    pub port: u16,
+    pub timeout: Duration,
+    /// The name asked for with recursion desired, to find open resolvers.
+    /// It should lie outside any zone the servers are meant to serve.
+    pub recursion_probe: DomainName,
+}
+
+impl Default for DelegationCheck {
+    fn default() -> DelegationCheck {
+        DelegationCheck {
+            port: 53,
+            timeout: Duration::from_secs(3),
+            recursion_probe: "a.root-servers.net.".parse().unwrap(),
+        }
+    }
+}
+
+/// Checks the delegation of `zone` with the default settings, finding the
+/// parent's servers and the name servers' addresses through `resolver`.
+pub fn check_delegation(
+    resolver: &Resolver,
+    zone: &DomainName,
+) -> Result<DelegationReport, CheckError> {
+    DelegationCheck::default().run(resolver, zone)
+}
+
+/// The addresses of `name`, through `resolver`.
+fn addresses(resolver: &Resolver, name: &DomainName) -> BTreeSet<IpAddr> {
+    [RecordType::A, RecordType::AAAA]
+        .iter()
+        .flat_map(|&t| resolver.lookup(name, t).unwrap_or_default())
+        .filter_map(|rr| match rr.rdata {
+            RData::A(a) => Some(IpAddr::V4(a)),
+            RData::Aaaa(a) => Some(IpAddr::V6(a)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The name servers and glue of a referral or answer for `zone`.
+fn delegation(
+    resp: &Message,
+    zone: &DomainName,
+) -> (BTreeSet<DomainName>, Vec<(DomainName, IpAddr)>) {
+    let ns: BTreeSet<DomainName> = resp
+        .answers
+        .iter()
+        .chain(&resp.authority)
+        .filter(|rr| rr.name == *zone)
+        .filter_map(|rr| match &rr.rdata {
+            RData::Ns(ns) => Some(ns.to_lowercase()),
+            _ => None,
+        })
+        .collect();
+    let glue = resp
+        .additional
+        .iter()
+        .filter(|rr| ns.contains(&rr.name.to_lowercase()))
+        .filter_map(|rr| match rr.rdata {
+            RData::A(a) => Some((rr.name.to_lowercase(), IpAddr::V4(a))),
+            RData::Aaaa(a) => Some((rr.name.to_lowercase(), IpAddr::V6(a))),
+            _ => None,
+        })
+        .collect();
+    (ns, glue)
+}
+
+impl DelegationCheck {
+    /// Sends one query to `addr`, over TCP if the UDP response is
+    /// truncated.
+    fn ask(
+        &self,
+        addr: IpAddr,
+        name: &DomainName,
+        qtype: RecordType,
+        rd: bool,
+    ) -> Result<Message, client::Error> {
+        let server = SocketAddr::new(addr, self.port);
+        let mut query = Message::query(name.clone(), qtype);
+        query.header.rd = rd;
+        let resp = client::exchange(server, Protocol::Udp, &query, self.timeout)?;
+        if resp.header.tc {
+            return client::exchange(server, Protocol::Tcp, &query, self.timeout);
+        }
+        Ok(resp)
+    }
+
+    /// The zone that `name` lies in, as its SOA shows.
+    fn enclosing_zone(
+        &self,
+        resolver: &Resolver,
+        name: &DomainName,
+    ) -> Result<DomainName, CheckError> {
+        let resp = resolver.query(name, RecordType::SOA)?;
+        Ok(resp
+            .answers
+            .iter()
+            .chain(&resp.authority)
+            .find(|rr| rr.rtype() == RecordType::SOA && name.is_subdomain_of(&rr.name))
+            .map_or_else(|| name.clone(), |rr| rr.name.clone()))
+    }
+
+    /// The addresses `server` gives `name`.
+    fn addresses_at(&self, server: IpAddr, name: &DomainName) -> BTreeSet<IpAddr> {
+        [RecordType::A, RecordType::AAAA]
+            .iter()
+            .filter_map(|&t| self.ask(server, name, t, false).ok())
+            .flat_map(|resp| resp.answers)
+            .filter_map(|rr| match rr.rdata {
+                RData::A(a) if rr.name == *name => Some(IpAddr::V4(a)),
+                RData::Aaaa(a) if rr.name == *name => Some(IpAddr::V6(a)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn check_server(&self, zone: &DomainName, name: &DomainName, addr: IpAddr) -> ServerCheck {
+        let mut check = ServerCheck {
+            name: name.clone(),
+            addr,
+            status: ServerStatus::Unreachable(String::new()),
+            ns: BTreeSet::new(),
+            open_resolver: false,
+        };
+        let resp = match self.ask(addr, zone, RecordType::SOA, false) {
+            Ok(resp) => resp,
+            Err(e) => {
+                check.status = ServerStatus::Unreachable(e.to_string());
+                return check;
+            }
+        };
+        let serial = resp.answers.iter().find_map(|rr| match &rr.rdata {
+            RData::Soa(soa) if rr.name == *zone => Some(soa.serial),
+            _ => None,
+        });
+        check.status = match serial {
+            Some(serial) if resp.header.aa && resp.header.rcode == Rcode::NOERROR => {
+                ServerStatus::Authoritative { serial }
+            }
+            _ => ServerStatus::Lame(resp.header.rcode),
+        };
+        if let ServerStatus::Authoritative { .. } = check.status {
+            if let Ok(resp) = self.ask(addr, zone, RecordType::NS, false) {
+                check.ns = delegation(&resp, zone).0;
+            }
+        }
+        if let Ok(resp) = self.ask(addr, &self.recursion_probe, RecordType::A, true) {
+            check.open_resolver = resp.header.ra
+                && resp.header.rcode == Rcode::NOERROR
+                && !resp.header.aa
+                && !resp.answers.is_empty();
+        }
+        check
+    }
+
+    /// Checks the delegation of `zone`, finding the parent's servers and
+    /// the addresses of name servers without glue through `resolver`.
+    pub fn run(
+        &self,
+        resolver: &Resolver,
+        zone: &DomainName,
+    ) -> Result<DelegationReport, CheckError> {
+        let parent = self.enclosing_zone(resolver, &zone.parent().ok_or(CheckError::Root)?)?;
+        let parent_servers: BTreeSet<IpAddr> = resolver
+            .lookup(&parent, RecordType::NS)?
+            .iter()
+            .filter_map(|rr| match &rr.rdata {
+                RData::Ns(ns) => Some(addresses(resolver, ns)),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+
+        let mut report = DelegationReport {
+            zone: zone.clone(),
+            parent,
+            parent_ns: BTreeSet::new(),
+            child_ns: BTreeSet::new(),
+            glue: BTreeMap::new(),
+            servers: Vec::new(),
+            problems: Vec::new(),
+        };
+        for &addr in &parent_servers {
+            if let Ok(resp) = self.ask(addr, zone, RecordType::NS, false) {
+                let (ns, glue) = delegation(&resp, zone);
+                report.parent_ns.extend(ns);
+                for (name, addr) in glue {
+                    report.glue.entry(name).or_default().insert(addr);
+                }
+            }
+        }
+
+        // Name servers the zone lists that the parent does not are checked
+        // too, as they are found.
+        let mut checked = BTreeSet::new();
+        let mut pending: Vec<DomainName> = report.parent_ns.iter().cloned().collect();
+        while let Some(name) = pending.pop() {
+            if !checked.insert(name.clone()) {
+                continue;
+            }
+            // Name servers inside the zone without glue can only be found
+            // by asking the zone's own servers.
+            let authoritative = report
+                .servers
+                .iter()
+                .find(|c| matches!(c.status, ServerStatus::Authoritative { .. }));
+            let addrs = match (report.glue.get(&name), authoritative) {
+                (Some(glue), _) => glue.clone(),
+                (None, Some(server)) if name.is_subdomain_of(zone) => {
+                    self.addresses_at(server.addr, &name)
+                }
+                (None, _) => addresses(resolver, &name),
+            };
+            if addrs.is_empty() {
+                report.problems.push(Problem::NoAddress(name.clone()));
+            }
+            for addr in addrs {
+                let check = self.check_server(zone, &name, addr);
+                pending.extend(check.ns.iter().filter(|n| !checked.contains(*n)).cloned());
+                report.servers.push(check);
+            }
+        }
+
+        self.find_problems(&mut report);
+        Ok(report)
+    }
+
+    fn find_problems(&self, report: &mut DelegationReport) {
+        let zone = &report.zone;
+        let mut problems = Vec::new();
+        let mut serials = BTreeSet::new();
+        let mut ns_sets = BTreeSet::new();
+        for check in &report.servers {
+            let (name, addr) = (check.name.clone(), check.addr);
+            match check.status {
+                ServerStatus::Authoritative { serial } => {
+                    serials.insert(serial);
+                    ns_sets.insert(check.ns.clone());
+                    report.child_ns.extend(check.ns.iter().cloned());
+                }
+                ServerStatus::Lame(_) => problems.push(Problem::Lame { name, addr }),
+                ServerStatus::Unreachable(_) => problems.push(Problem::Unreachable { name, addr }),
+            }
+            if check.open_resolver {
+                problems.push(Problem::OpenResolver {
+                    name: check.name.clone(),
+                    addr,
+                });
+            }
+        }
+        if ns_sets.len() > 1 {
+            problems.push(Problem::ChildNsDisagree);
+        }
+        if serials.len() > 1 {
+            problems.push(Problem::SerialMismatch(serials.into_iter().collect()));
+        }
+        if !report.child_ns.is_empty() && report.child_ns != report.parent_ns {
+            problems.push(Problem::NsMismatch {
+                only_parent: report
+                    .parent_ns
+                    .difference(&report.child_ns)
+                    .cloned()
+                    .collect(),
+                only_child: report
+                    .child_ns
+                    .difference(&report.parent_ns)
+                    .cloned()
+                    .collect(),
+            });
+        }
+
+        // Glue is only needed, and only checked, for name servers inside
+        // the zone; the zone's own answer for them is taken from the first
+        // authoritative server.
+        let authoritative = report
+            .servers
+            .iter()
+            .find(|c| matches!(c.status, ServerStatus::Authoritative { .. }));
+        for name in report.parent_ns.iter().filter(|n| n.is_subdomain_of(zone)) {
+            let parent: Vec<IpAddr> = match report.glue.get(name) {
+                Some(glue) => glue.iter().copied().collect(),
+                None => {
+                    problems.push(Problem::MissingGlue(name.clone()));
+                    continue;
+                }
+            };
+            let server = match authoritative {
+                Some(server) => server.addr,
+                None => continue,
+            };
+            let child: Vec<IpAddr> = self.addresses_at(server, name).into_iter().collect();
+            if child != parent {
+                problems.push(Problem::GlueMismatch {
+                    name: name.clone(),
+                    parent,
+                    child,
+                });
+            }
+        }
+        report.problems.extend(problems);
+    }
+}
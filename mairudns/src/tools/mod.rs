@@ -0,0 +1,11 @@
+//! Utilities for working with DNS traffic outside a running server.
+
+pub mod brute;
+pub mod delegation;
+pub mod pcap;
+#[cfg(feature = "dnssec")]
+pub mod walk;
+
+pub use self::delegation::check_delegation;
+#[cfg(feature = "dnssec")]
+pub use self::walk::walk_zone;
@@ -0,0 +1,739 @@
+//! Packet captures: reading DNS messages out of pcap files, writing them,
+//! and replaying the queries of a capture against a server.
+//!
+//! [`Reader`] reads the classic pcap format, not pcapng, in either byte
+//! order and with microsecond or nanosecond timestamps. [`Extractor`]
+//! picks the DNS messages out of its packets, over Ethernet (802.1Q tags
+//! included), raw IP, BSD loopback and Linux cooked captures: UDP
+//! datagrams as they are, and TCP streams reassembled from their segments
+//! and split into messages. IP fragments are skipped.
+//!
+//! A [`Replay`] sends the queries of a capture to a server, at the pace
+//! they were captured or as fast as it can, and reports how the server
+//! answered and where it answered otherwise than in the capture.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{self, Protocol};
+use crate::message::{Message, Rcode};
+use crate::wire;
+
+/// Errors produced when reading a capture.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Not a pcap file; pcapng files are not read either.
+    BadMagic,
+    /// The file ends within a header or a packet.
+    Truncated,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "i/o error: {}", e),
+            Error::BadMagic => f.write_str("not a pcap file"),
+            Error::Truncated => f.write_str("truncated capture"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => Error::Truncated,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+/// The link-layer header type of a capture's packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LinkType(pub u32);
+
+impl LinkType {
+    /// BSD loopback: a 4-byte address family in host byte order.
+    pub const NULL: LinkType = LinkType(0);
+    pub const ETHERNET: LinkType = LinkType(1);
+    /// Bare IPv4 or IPv6 packets.
+    pub const RAW: LinkType = LinkType(101);
+    /// Linux cooked capture, as from `tcpdump -i any`.
+    pub const LINUX_SLL: LinkType = LinkType(113);
+    pub const IPV4: LinkType = LinkType(228);
+    pub const IPV6: LinkType = LinkType(229);
+    /// Linux cooked capture, version 2.
+    pub const LINUX_SLL2: LinkType = LinkType(276);
+}
+
+/// One captured packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    /// When it was captured, since the Unix epoch.
+    pub timestamp: Duration,
+    /// The captured bytes, possibly cut short of the packet's length.
+    pub data: Vec<u8>,
+}
+
+/// The largest packet record read; anything larger means a corrupt file.
+const MAX_RECORD: u32 = 256 * 1024;
+
+/// Reads the packets of a pcap file.
+pub struct Reader<R> {
+    input: R,
+    swapped: bool,
+    nanos: bool,
+    link_type: LinkType,
+}
+
+impl<R: Read> Reader<R> {
+    /// Reads the file header from `input`.
+    pub fn new(mut input: R) -> Result<Reader<R>, Error> {
+        let mut header = [0u8; 24];
+        input.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (swapped, nanos) = match magic {
+            0xa1b2_c3d4 => (false, false),
+            0xa1b2_3c4d => (false, true),
+            0xd4c3_b2a1 => (true, false),
+            0x4d3c_b2a1 => (true, true),
+            _ => return Err(Error::BadMagic),
+        };
+        let mut reader = Reader {
+            input,
+            swapped,
+            nanos,
+            link_type: LinkType(0),
+        };
+        reader.link_type = LinkType(reader.u32(&header[20..24]) & 0x0fff_ffff);
+        Ok(reader)
+    }
+
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.swapped {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+
+    /// The next packet, or `None` at the end of the file.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, Error> {
+        let mut header = [0u8; 16];
+        match self.input.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut header[1..])?,
+        }
+        let secs = self.u32(&header[0..4]);
+        let frac = self.u32(&header[4..8]);
+        let captured = self.u32(&header[8..12]);
+        if captured > MAX_RECORD {
+            return Err(Error::Truncated);
+        }
+        let mut data = vec![0u8; captured as usize];
+        self.input.read_exact(&mut data)?;
+        let frac = if self.nanos {
+            Duration::from_nanos(u64::from(frac))
+        } else {
+            Duration::from_micros(u64::from(frac))
+        };
+        Ok(Some(Packet {
+            timestamp: Duration::from_secs(u64::from(secs)) + frac,
+            data,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        self.next_packet().transpose()
+    }
+}
+
+/// Writes packets as a pcap file, little-endian with microsecond
+/// timestamps.
+pub struct Writer<W> {
+    output: W,
+    link_type: LinkType,
+    /// The next TCP sequence number of each stream written to.
+    sequences: HashMap<(SocketAddr, SocketAddr), u32>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes the file header for packets of `link_type`.
+    pub fn new(mut output: W, link_type: LinkType) -> io::Result<Writer<W>> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&MAX_RECORD.to_le_bytes());
+        header.extend_from_slice(&link_type.0.to_le_bytes());
+        output.write_all(&header)?;
+        Ok(Writer {
+            output,
+            link_type,
+            sequences: HashMap::new(),
+        })
+    }
+
+    pub fn write_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        let len = packet.data.len() as u32;
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&(packet.timestamp.as_secs() as u32).to_le_bytes());
+        header.extend_from_slice(&packet.timestamp.subsec_micros().to_le_bytes());
+        header.extend_from_slice(&len.to_le_bytes());
+        header.extend_from_slice(&len.to_le_bytes());
+        self.output.write_all(&header)?;
+        self.output.write_all(&packet.data)
+    }
+
+    /// Writes `message` as one IP packet, which the writer's link type
+    /// must be [`LinkType::RAW`] for. A TCP message is written as a
+    /// segment of its own carrying its length prefix, following the
+    /// messages written before it between the same addresses; the first
+    /// of them is preceded by a SYN. TLS messages cannot be written.
+    pub fn write_message(&mut self, message: &DnsMessage) -> io::Result<()> {
+        if self.link_type != LinkType::RAW {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "messages are written to raw IP captures only",
+            ));
+        }
+        let (protocol, transport) = match message.protocol {
+            Protocol::Udp => {
+                let mut datagram = Vec::with_capacity(8 + message.wire.len());
+                datagram.extend_from_slice(&message.src.port().to_be_bytes());
+                datagram.extend_from_slice(&message.dst.port().to_be_bytes());
+                datagram.extend_from_slice(&((8 + message.wire.len()) as u16).to_be_bytes());
+                datagram.extend_from_slice(&[0, 0]);
+                datagram.extend_from_slice(&message.wire);
+                (17, datagram)
+            }
+            Protocol::Tcp => {
+                let key = (message.src, message.dst);
+                if !self.sequences.contains_key(&key) {
+                    let syn = Packet {
+                        timestamp: message.timestamp,
+                        data: ip_packet(message, 6, &tcp_segment(message, 0, 0x02, &[]))?,
+                    };
+                    self.write_packet(&syn)?;
+                }
+                let next = self.sequences.entry(key).or_insert(1);
+                let seq = *next;
+                *next = next.wrapping_add(2 + message.wire.len() as u32);
+                let mut framed = Vec::with_capacity(2 + message.wire.len());
+                framed.extend_from_slice(&(message.wire.len() as u16).to_be_bytes());
+                framed.extend_from_slice(&message.wire);
+                // PSH and ACK.
+                (6, tcp_segment(message, seq, 0x18, &framed))
+            }
+            Protocol::Tls => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "messages over TLS are encrypted on the wire",
+                ))
+            }
+        };
+        let packet = Packet {
+            timestamp: message.timestamp,
+            data: ip_packet(message, protocol, &transport)?,
+        };
+        self.write_packet(&packet)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+}
+
+/// A TCP segment between the ports of `message`; checksums are left zero.
+fn tcp_segment(message: &DnsMessage, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&message.src.port().to_be_bytes());
+    segment.extend_from_slice(&message.dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&[0; 4]);
+    segment.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    segment
+}
+
+/// `transport` in an IPv4 or IPv6 packet between the addresses of
+/// `message`.
+fn ip_packet(message: &DnsMessage, protocol: u8, transport: &[u8]) -> io::Result<Vec<u8>> {
+    let mut packet = Vec::new();
+    match (message.src.ip(), message.dst.ip()) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let len = 20 + transport.len();
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&(len as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(transport.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[protocol, 64]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "source and destination of different address families",
+            ))
+        }
+    }
+    packet.extend_from_slice(transport);
+    Ok(packet)
+}
+
+/// A DNS message found in a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub timestamp: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub protocol: Protocol,
+    /// The message as sent, without the TCP length prefix.
+    pub wire: Vec<u8>,
+}
+
+impl DnsMessage {
+    pub fn decode(&self) -> Result<Message, wire::Error> {
+        Message::from_wire(&self.wire)
+    }
+}
+
+/// Out-of-order segments held back per TCP stream, before the stream is
+/// given up on.
+const MAX_PENDING_SEGMENTS: usize = 64;
+
+/// What is known of one direction of a TCP connection.
+#[derive(Default)]
+struct Stream {
+    /// The sequence number of the next byte expected.
+    next: Option<u32>,
+    /// Bytes received in order but not yet part of a whole message.
+    buf: Vec<u8>,
+    /// Segments that arrived ahead of a gap, by sequence number.
+    pending: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Stream {
+    /// Adds the segment at `seq`; false if the stream is beyond repair.
+    fn add(&mut self, seq: u32, data: &[u8]) -> bool {
+        let next = *self.next.get_or_insert(seq);
+        let ahead = seq.wrapping_sub(next) as i32;
+        if ahead > 0 {
+            self.pending.insert(seq, data.to_vec());
+            return self.pending.len() <= MAX_PENDING_SEGMENTS;
+        }
+        // A retransmission may overlap what is already there.
+        let behind = ahead.unsigned_abs() as usize;
+        if behind < data.len() {
+            self.buf.extend_from_slice(&data[behind..]);
+            self.next = Some(seq.wrapping_add(data.len() as u32));
+        }
+        while let Some((&seq, _)) = self.pending.iter().next() {
+            if seq.wrapping_sub(self.next.unwrap()) as i32 > 0 {
+                break;
+            }
+            let data = self.pending.remove(&seq).unwrap();
+            self.add(seq, &data);
+        }
+        true
+    }
+
+    /// The whole messages received, taken out of the buffer.
+    fn messages(&mut self) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut at = 0;
+        while self.buf.len() >= at + 2 {
+            let len = usize::from(u16::from_be_bytes([self.buf[at], self.buf[at + 1]]));
+            if self.buf.len() < at + 2 + len {
+                break;
+            }
+            out.push(self.buf[at + 2..at + 2 + len].to_vec());
+            at += 2 + len;
+        }
+        self.buf.drain(..at);
+        out
+    }
+}
+
+/// Picks DNS messages out of captured packets.
+pub struct Extractor {
+    link_type: LinkType,
+    ports: Vec<u16>,
+    streams: HashMap<(SocketAddr, SocketAddr), Stream>,
+}
+
+impl Extractor {
+    /// An extractor for packets of `link_type`, taking traffic to or from
+    /// port 53.
+    pub fn new(link_type: LinkType) -> Extractor {
+        Extractor {
+            link_type,
+            ports: vec![53],
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Takes traffic to or from any of `ports` instead.
+    pub fn ports(mut self, ports: &[u16]) -> Self {
+        self.ports = ports.to_vec();
+        self
+    }
+
+    /// The DNS messages `packet` completes: none, one, or for TCP several.
+    pub fn push(&mut self, packet: &Packet) -> Vec<DnsMessage> {
+        let ip = match link_payload(self.link_type, &packet.data) {
+            Some(ip) => ip,
+            None => return Vec::new(),
+        };
+        let (src, dst, protocol, segment) = match ip_payload(ip) {
+            Some(parsed) => parsed,
+            None => return Vec::new(),
+        };
+        let message = |src_port, dst_port, wire| DnsMessage {
+            timestamp: packet.timestamp,
+            src: SocketAddr::new(src, src_port),
+            dst: SocketAddr::new(dst, dst_port),
+            protocol: if protocol == 17 {
+                Protocol::Udp
+            } else {
+                Protocol::Tcp
+            },
+            wire,
+        };
+        if segment.len() < 8 {
+            return Vec::new();
+        }
+        let src_port = u16::from_be_bytes([segment[0], segment[1]]);
+        let dst_port = u16::from_be_bytes([segment[2], segment[3]]);
+        if !self.ports.contains(&src_port) && !self.ports.contains(&dst_port) {
+            return Vec::new();
+        }
+        if protocol == 17 {
+            return vec![message(src_port, dst_port, segment[8..].to_vec())];
+        }
+        if segment.len() < 20 {
+            return Vec::new();
+        }
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let offset = usize::from(segment[12] >> 4) * 4;
+        let flags = segment[13];
+        let data = match segment.get(offset..) {
+            Some(data) => data,
+            None => return Vec::new(),
+        };
+        let key = (
+            SocketAddr::new(src, src_port),
+            SocketAddr::new(dst, dst_port),
+        );
+        const FIN: u8 = 0x01;
+        const SYN: u8 = 0x02;
+        const RST: u8 = 0x04;
+        if flags & SYN != 0 {
+            self.streams.insert(
+                key,
+                Stream {
+                    next: Some(seq.wrapping_add(1)),
+                    ..Stream::default()
+                },
+            );
+        }
+        let mut out = Vec::new();
+        if !data.is_empty() {
+            let stream = self.streams.entry(key).or_default();
+            if stream.add(seq, data) {
+                out = stream.messages();
+            } else {
+                self.streams.remove(&key);
+            }
+        }
+        if flags & (FIN | RST) != 0 {
+            self.streams.remove(&key);
+        }
+        out.into_iter()
+            .map(|wire| message(src_port, dst_port, wire))
+            .collect()
+    }
+}
+
+/// The IP packet in a frame of `link_type`.
+fn link_payload(link_type: LinkType, frame: &[u8]) -> Option<&[u8]> {
+    match link_type {
+        LinkType::RAW | LinkType::IPV4 | LinkType::IPV6 => Some(frame),
+        LinkType::NULL => frame.get(4..),
+        LinkType::LINUX_SLL => ethertype_payload(
+            u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]),
+            frame.get(16..)?,
+        ),
+        LinkType::LINUX_SLL2 => ethertype_payload(
+            u16::from_be_bytes([*frame.first()?, *frame.get(1)?]),
+            frame.get(20..)?,
+        ),
+        LinkType::ETHERNET => {
+            let mut at = 12;
+            loop {
+                let ethertype = u16::from_be_bytes([*frame.get(at)?, *frame.get(at + 1)?]);
+                if ethertype == 0x8100 || ethertype == 0x88a8 {
+                    at += 4;
+                    continue;
+                }
+                return ethertype_payload(ethertype, frame.get(at + 2..)?);
+            }
+        }
+        _ => None,
+    }
+}
+
+fn ethertype_payload(ethertype: u16, payload: &[u8]) -> Option<&[u8]> {
+    match ethertype {
+        0x0800 | 0x86dd => Some(payload),
+        _ => None,
+    }
+}
+
+/// The addresses, transport protocol and transport segment of an IP
+/// packet carrying UDP or TCP, unless it is a fragment.
+fn ip_payload(packet: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 => {
+            let header = usize::from(packet[0] & 0x0f) * 4;
+            let total = usize::from(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]));
+            let fragment = u16::from_be_bytes([*packet.get(6)?, *packet.get(7)?]);
+            // More fragments, or an offset.
+            if fragment & 0x3fff != 0 {
+                return None;
+            }
+            let protocol = *packet.get(9)?;
+            let src = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+            let dst = Ipv4Addr::new(
+                *packet.get(16)?,
+                *packet.get(17)?,
+                *packet.get(18)?,
+                *packet.get(19)?,
+            );
+            // Ethernet pads short frames.
+            let end = total.min(packet.len());
+            let payload = packet.get(header..end)?;
+            transport(src.into(), dst.into(), protocol, payload)
+        }
+        6 => {
+            let len = usize::from(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]));
+            let mut next = *packet.get(6)?;
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(packet.get(8..24)?);
+            dst.copy_from_slice(packet.get(24..40)?);
+            let end = (40 + len).min(packet.len());
+            let mut payload = packet.get(40..end)?;
+            // Hop-by-hop, routing and destination options come before the
+            // transport header; a fragment header means a fragment.
+            while matches!(next, 0 | 43 | 60) {
+                let ext = (usize::from(*payload.get(1)?) + 1) * 8;
+                next = *payload.first()?;
+                payload = payload.get(ext..)?;
+            }
+            transport(
+                Ipv6Addr::from(src).into(),
+                Ipv6Addr::from(dst).into(),
+                next,
+                payload,
+            )
+        }
+        _ => None,
+    }
+}
+
+fn transport(
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    payload: &[u8],
+) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    match protocol {
+        6 | 17 => Some((src, dst, protocol, payload)),
+        _ => None,
+    }
+}
+
+/// Every DNS message to or from port 53 in the capture `input`.
+pub fn read_messages<R: Read>(input: R) -> Result<Vec<DnsMessage>, Error> {
+    let mut reader = Reader::new(input)?;
+    let mut extractor = Extractor::new(reader.link_type());
+    let mut messages = Vec::new();
+    while let Some(packet) = reader.next_packet()? {
+        messages.extend(extractor.push(&packet));
+    }
+    Ok(messages)
+}
+
+/// How the queries of a capture are sent to a server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub server: SocketAddr,
+    /// Sends queries this many times faster than they were captured, or
+    /// as fast as possible if `None`.
+    pub speed: Option<f64>,
+    /// Queries outstanding at once at most.
+    pub concurrency: usize,
+    pub timeout: Duration,
+}
+
+/// How a server answered a replayed capture.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    pub sent: usize,
+    pub answered: usize,
+    pub timed_out: usize,
+    /// Queries that failed otherwise, e.g. with a malformed response.
+    pub failed: usize,
+    pub rcodes: BTreeMap<Rcode, usize>,
+    /// Answers whose RCODE or answer records, TTLs aside, differ from the
+    /// response in the capture. Queries captured without their response
+    /// are not compared.
+    pub differing: usize,
+    /// The time each answered query took, shortest first.
+    pub latencies: Vec<Duration>,
+    pub elapsed: Duration,
+}
+
+impl ReplayReport {
+    /// The latency that the fraction `p` of answered queries came within.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let i = ((self.latencies.len() as f64 * p).ceil() as usize).clamp(1, self.latencies.len());
+        Some(self.latencies[i - 1])
+    }
+
+    pub fn queries_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.sent as f64 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Identifies a response in a capture by the query it answers.
+type ExchangeKey = (SocketAddr, u16, String);
+
+fn exchange_key(client: SocketAddr, message: &Message) -> ExchangeKey {
+    let question = message
+        .question()
+        .map(|q| q.to_string().to_lowercase())
+        .unwrap_or_default();
+    (client, message.header.id, question)
+}
+
+/// What two responses are compared on.
+fn outcome(message: &Message) -> (Rcode, Vec<String>) {
+    let mut answers: Vec<String> = message
+        .answers
+        .iter()
+        .map(|rr| {
+            let mut rr = rr.clone();
+            rr.ttl = 0;
+            rr.to_string()
+        })
+        .collect();
+    answers.sort();
+    (message.header.rcode, answers)
+}
+
+impl Replay {
+    pub fn new(server: SocketAddr) -> Replay {
+        Replay {
+            server,
+            speed: None,
+            concurrency: 16,
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Sends the queries among `messages` to the server, each over the
+    /// transport it was captured on, and compares the answers with the
+    /// captured responses.
+    pub fn run(&self, messages: &[DnsMessage]) -> ReplayReport {
+        let mut queries = Vec::new();
+        let mut captured = HashMap::new();
+        for m in messages {
+            let decoded = match m.decode() {
+                Ok(decoded) => decoded,
+                Err(_) => continue,
+            };
+            if decoded.header.qr {
+                captured
+                    .entry(exchange_key(m.dst, &decoded))
+                    .or_insert_with(|| outcome(&decoded));
+            } else {
+                queries.push((m, decoded));
+            }
+        }
+        let first = queries
+            .first()
+            .map_or(Duration::from_secs(0), |(m, _)| m.timestamp);
+        let next = AtomicUsize::new(0);
+        let report = Mutex::new(ReplayReport::default());
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..self.concurrency.max(1) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let (m, query) = match queries.get(i) {
+                        Some(q) => q,
+                        None => return,
+                    };
+                    if let Some(speed) = self.speed.filter(|s| *s > 0.0) {
+                        let at = m.timestamp.saturating_sub(first).div_f64(speed);
+                        thread::sleep(at.saturating_sub(start.elapsed()));
+                    }
+                    let sent = Instant::now();
+                    let result = client::exchange(self.server, m.protocol, query, self.timeout);
+                    let took = sent.elapsed();
+                    let mut report = report.lock().unwrap();
+                    report.sent += 1;
+                    match result {
+                        Ok(resp) => {
+                            report.answered += 1;
+                            report.latencies.push(took);
+                            *report.rcodes.entry(resp.header.rcode).or_default() += 1;
+                            let key = exchange_key(m.src, query);
+                            if captured.get(&key).is_some_and(|o| *o != outcome(&resp)) {
+                                report.differing += 1;
+                            }
+                        }
+                        Err(client::Error::Timeout) => report.timed_out += 1,
+                        Err(_) => report.failed += 1,
+                    }
+                });
+            }
+        });
+        let mut report = report.into_inner().unwrap();
+        report.latencies.sort();
+        report.elapsed = start.elapsed();
+        report
+    }
+}
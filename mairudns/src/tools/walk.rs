@@ -0,0 +1,311 @@
+//! Enumerating a signed zone through its authenticated denial of
+//! existence.
+//!
+//! An NSEC chain names every owner in the zone in canonical order, so
+//! following it from the apex lists the zone. An NSEC3 chain names only
+//! hashes: the walk collects the chain by asking for names whose hashes
+//! fall in spans not yet seen, then hashes the candidate labels of a
+//! wordlist to recover the names behind it, as a dictionary attack would.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::dnssec::{nsec3_hash, Nsec, Nsec3};
+use crate::encoding;
+use crate::message::Message;
+use crate::name::DomainName;
+use crate::random;
+use crate::resolver::{self, QueryOptions, Resolver};
+use crate::rr::RecordType;
+
+/// Errors that stop a walk before it finds anything.
+#[derive(Debug)]
+pub enum WalkError {
+    Resolver(resolver::Error),
+    /// The zone answers with neither NSEC nor NSEC3 records.
+    Unsigned,
+    /// No NSEC record leads on from this name: the chain is broken, or
+    /// a server along the way hides it.
+    Broken(DomainName),
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalkError::Resolver(e) => write!(f, "{}", e),
+            WalkError::Unsigned => f.write_str("no NSEC or NSEC3 records in the responses"),
+            WalkError::Broken(name) => write!(f, "no NSEC record leads on from {}", name),
+        }
+    }
+}
+
+impl std::error::Error for WalkError {}
+
+impl From<resolver::Error> for WalkError {
+    fn from(e: resolver::Error) -> WalkError {
+        WalkError::Resolver(e)
+    }
+}
+
+/// Settings for [`walk_zone`].
+#[derive(Clone, Debug)]
+pub struct WalkOptions {
+    /// Stops the walk after this many queries, with what it has found.
+    pub max_queries: usize,
+    /// Labels, or names relative to the zone, to try against the hashes of
+    /// an NSEC3 chain. Unused for NSEC.
+    pub wordlist: Vec<String>,
+    /// How the queries are sent. The DO bit is always set.
+    pub query: QueryOptions,
+}
+
+impl Default for WalkOptions {
+    fn default() -> WalkOptions {
+        WalkOptions {
+            max_queries: 10_000,
+            wordlist: Vec::new(),
+            query: QueryOptions::default(),
+        }
+    }
+}
+
+/// The parameters of an NSEC3 chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Nsec3Chain {
+    pub salt: Vec<u8>,
+    pub iterations: u16,
+    /// Hashes no wordlist entry matched, with the types at each.
+    pub uncracked: BTreeMap<Vec<u8>, Vec<RecordType>>,
+}
+
+/// What a walk found.
+#[derive(Clone, Debug)]
+pub struct ZoneWalk {
+    pub zone: DomainName,
+    /// The names found, with the types their NSEC or NSEC3 records list.
+    pub names: BTreeMap<DomainName, Vec<RecordType>>,
+    /// Set when the zone is signed with NSEC3.
+    pub nsec3: Option<Nsec3Chain>,
+    pub queries: usize,
+    /// Whether the chain was followed all the way round, rather than the
+    /// walk stopping at [`WalkOptions::max_queries`].
+    pub complete: bool,
+}
+
+/// Enumerates `zone` by walking its NSEC chain, or by collecting its NSEC3
+/// chain and cracking the hashes with [`WalkOptions::wordlist`].
+///
+/// The queries go to `resolver`'s servers: the zone's authoritative
+/// servers give the most reliable walk, though a validating recursive
+/// resolver passes the records through as well.
+pub fn walk_zone(
+    resolver: &Resolver,
+    zone: &DomainName,
+    options: &WalkOptions,
+) -> Result<ZoneWalk, WalkError> {
+    let mut walker = Walker {
+        resolver,
+        zone,
+        options,
+        query: QueryOptions {
+            dnssec_ok: true,
+            ..options.query.clone()
+        },
+        walk: ZoneWalk {
+            zone: zone.clone(),
+            names: BTreeMap::new(),
+            nsec3: None,
+            queries: 0,
+            complete: false,
+        },
+    };
+    walker.walk_nsec()?;
+    Ok(walker.walk)
+}
+
+/// A span of an NSEC3 chain: the hash after an owner, and its types.
+type Spans = BTreeMap<Vec<u8>, (Vec<u8>, Vec<RecordType>)>;
+
+struct Walker<'a> {
+    resolver: &'a Resolver,
+    zone: &'a DomainName,
+    options: &'a WalkOptions,
+    query: QueryOptions,
+    walk: ZoneWalk,
+}
+
+impl Walker<'_> {
+    fn ask(&mut self, name: &DomainName, qtype: RecordType) -> Result<Message, WalkError> {
+        self.walk.queries += 1;
+        Ok(self.resolver.query_with(name, qtype, &self.query)?)
+    }
+
+    fn exhausted(&self) -> bool {
+        self.walk.queries >= self.options.max_queries
+    }
+
+    /// Follows the NSEC chain from the apex, switching to the NSEC3 walk
+    /// if the first response holds NSEC3 records instead.
+    fn walk_nsec(&mut self) -> Result<(), WalkError> {
+        let mut current = self.zone.clone();
+        while !self.exhausted() {
+            let resp = self.ask(&current, RecordType::NSEC)?;
+            let mut nsec = nsec_records(&resp)
+                .into_iter()
+                .find(|(owner, _)| *owner == current);
+            if nsec.is_none() {
+                if self.exhausted() {
+                    break;
+                }
+                // A delegation, whose NSEC record the parent only shows
+                // in denials: ask for the first name past its subtree.
+                let probe = successor(&current, self.zone)
+                    .ok_or_else(|| WalkError::Broken(current.clone()))?;
+                let resp = self.ask(&probe, RecordType::A)?;
+                nsec = nsec_records(&resp)
+                    .into_iter()
+                    .find(|(owner, nsec)| *owner >= current && covers(owner, &nsec.next, &probe));
+                if nsec.is_none() && current == *self.zone && has_nsec3(&resp) {
+                    return self.walk_nsec3(resp);
+                }
+            }
+            let (owner, nsec) = match nsec {
+                Some(found) => found,
+                None if self.walk.names.is_empty() => return Err(WalkError::Unsigned),
+                None => return Err(WalkError::Broken(current)),
+            };
+            self.walk.names.insert(owner.clone(), nsec.types);
+            if nsec.next <= owner || !nsec.next.is_subdomain_of(self.zone) {
+                self.walk.complete = true;
+                break;
+            }
+            current = nsec.next;
+        }
+        Ok(())
+    }
+
+    /// Collects the NSEC3 chain, starting with the records in `first`,
+    /// then cracks what it can of it.
+    fn walk_nsec3(&mut self, first: Message) -> Result<(), WalkError> {
+        let mut spans = Spans::new();
+        let (salt, iterations) = match self.absorb(&first, &mut spans) {
+            Some(params) => params,
+            None => return Err(WalkError::Unsigned),
+        };
+        // Names whose hashes fall in known spans would only return records
+        // already held, so they are skipped unqueried; the cap on tries
+        // keeps a chain that never closes from spinning here.
+        let mut tries = 0usize;
+        while !closed(&spans) && !self.exhausted() {
+            tries += 1;
+            if tries > self.options.max_queries.saturating_mul(1000) {
+                break;
+            }
+            let label = format!("{:016x}", random::u64());
+            let name = match self.zone.prepend(label.as_bytes()) {
+                Ok(name) => name,
+                Err(_) => break,
+            };
+            if known(&spans, &nsec3_hash(&name, &salt, iterations)) {
+                continue;
+            }
+            let resp = self.ask(&name, RecordType::A)?;
+            self.absorb(&resp, &mut spans);
+        }
+        self.walk.complete = closed(&spans);
+
+        let mut candidates = vec![self.zone.clone()];
+        candidates.extend(
+            self.options
+                .wordlist
+                .iter()
+                .filter_map(|word| DomainName::parse_relative(word.trim(), self.zone).ok())
+                .filter(|name| name.is_subdomain_of(self.zone)),
+        );
+        for name in candidates {
+            let hash = nsec3_hash(&name, &salt, iterations);
+            if let Some((_, types)) = spans.remove(&hash) {
+                self.walk.names.insert(name, types);
+            }
+        }
+        self.walk.nsec3 = Some(Nsec3Chain {
+            salt,
+            iterations,
+            uncracked: spans
+                .into_iter()
+                .map(|(hash, (_, types))| (hash, types))
+                .collect(),
+        });
+        Ok(())
+    }
+
+    /// Adds the zone's NSEC3 records in `resp` to `spans`, returning the
+    /// chain's salt and iterations if there were any.
+    fn absorb(&self, resp: &Message, spans: &mut Spans) -> Option<(Vec<u8>, u16)> {
+        let mut params = None;
+        for rr in resp.answers.iter().chain(&resp.authority) {
+            let nsec3 = match Nsec3::from_rdata(&rr.rdata) {
+                Some(nsec3) if nsec3.hash_algorithm == Nsec3::SHA1 => nsec3,
+                _ => continue,
+            };
+            if rr.name.parent().as_ref() != Some(self.zone) {
+                continue;
+            }
+            let label = String::from_utf8_lossy(&rr.name.labels()[0]).into_owned();
+            if let Ok(hash) = encoding::base32hex_decode(&label) {
+                params = Some((nsec3.salt.clone(), nsec3.iterations));
+                spans.insert(hash, (nsec3.next_hashed, nsec3.types));
+            }
+        }
+        params
+    }
+}
+
+/// The NSEC records in the answer and authority sections of `resp`.
+fn nsec_records(resp: &Message) -> Vec<(DomainName, Nsec)> {
+    resp.answers
+        .iter()
+        .chain(&resp.authority)
+        .filter_map(|rr| Some((rr.name.clone(), Nsec::from_rdata(&rr.rdata)?)))
+        .collect()
+}
+
+fn has_nsec3(resp: &Message) -> bool {
+    resp.authority
+        .iter()
+        .any(|rr| rr.rtype() == RecordType::NSEC3)
+}
+
+/// The first name in canonical order after `name` and every name beneath
+/// it: its first label with a zero byte added. The apex has no such name
+/// inside the zone, so the first name beneath it stands in.
+fn successor(name: &DomainName, zone: &DomainName) -> Option<DomainName> {
+    if name == zone {
+        return zone.prepend(&[0]).ok();
+    }
+    let mut label = name.labels()[0].clone();
+    label.push(0);
+    name.parent()?.prepend(&label).ok()
+}
+
+/// Whether `name` falls at or after `owner` and before `next`, the last
+/// record of a chain wrapping round to the first.
+fn covers<T: Ord + ?Sized>(owner: &T, next: &T, name: &T) -> bool {
+    if owner < next {
+        owner <= name && name < next
+    } else {
+        owner <= name || name < next
+    }
+}
+
+/// Whether `hash` is an owner of `spans` or falls inside one of them.
+fn known(spans: &Spans, hash: &[u8]) -> bool {
+    let before = spans.range(..=hash.to_vec()).next_back();
+    let owner = before.or_else(|| spans.iter().next_back());
+    owner.is_some_and(|(owner, (next, _))| covers(&owner[..], &next[..], hash))
+}
+
+/// Whether every span of the chain leads to another one held.
+fn closed(spans: &Spans) -> bool {
+    !spans.is_empty() && spans.values().all(|(next, _)| spans.contains_key(next))
+}
@@ -0,0 +1,632 @@
+//! Transaction signatures (TSIG, RFC 8945).
+//!
+//! A TSIG record is appended to a message after encoding, so signing and
+//! verifying work on wire bytes: [`sign`] turns a [`Message`] into signed
+//! wire data, and [`Signed::parse`] splits the TSIG record off received
+//! data so that [`Signed::verify`] can check it. Multi-message responses
+//! such as zone transfers chain each MAC to the previous one; use
+//! [`StreamSigner`] and [`StreamVerifier`] for those.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::crypto::{self, Sha256};
+use crate::encoding;
+use crate::message::{Message, Rcode};
+use crate::name::DomainName;
+use crate::rr::{RData, RecordClass, RecordType};
+use crate::wire::{self, Decoder, Encoder};
+
+/// The permitted clock skew used when signing (RFC 8945 §10).
+pub const FUDGE: u16 = 300;
+
+/// A signed message in a stream must follow at most this many unsigned
+/// ones (RFC 8945 §5.3.1).
+pub const MAX_UNSIGNED: usize = 99;
+
+/// Errors produced when checking a signature.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    Wire(wire::Error),
+    /// The message carries no TSIG record.
+    Unsigned,
+    /// The TSIG record is not the last additional record, or its data is
+    /// malformed.
+    Malformed,
+    /// The key name or algorithm is not the one expected.
+    BadKey,
+    /// The MAC does not verify.
+    BadSig,
+    /// The signing time is outside the fudge window.
+    BadTime,
+    /// The other side reported a TSIG error.
+    Reported(Rcode),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Wire(e) => write!(f, "malformed message: {}", e),
+            Error::Unsigned => f.write_str("message is not signed"),
+            Error::Malformed => f.write_str("malformed TSIG record"),
+            Error::BadKey => f.write_str("unknown key or algorithm"),
+            Error::BadSig => f.write_str("signature does not verify"),
+            Error::BadTime => f.write_str("signature time outside the allowed window"),
+            Error::Reported(rcode) => write!(f, "peer reported TSIG error {}", rcode),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<wire::Error> for Error {
+    fn from(e: wire::Error) -> Error {
+        Error::Wire(e)
+    }
+}
+
+impl Error {
+    /// The TSIG error code to report back for this failure (RFC 8945
+    /// §5.2), if any.
+    pub fn rcode(&self) -> Option<Rcode> {
+        match self {
+            Error::BadKey => Some(Rcode::BADKEY),
+            Error::BadSig => Some(Rcode::BADSIG),
+            Error::BadTime => Some(Rcode::BADTIME),
+            _ => None,
+        }
+    }
+}
+
+/// A MAC algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    HmacSha256,
+}
+
+impl Algorithm {
+    /// The algorithm's name as carried in TSIG records.
+    pub fn name(self) -> DomainName {
+        match self {
+            Algorithm::HmacSha256 => DomainName::from_labels(["hmac-sha256"]).unwrap(),
+        }
+    }
+
+    pub fn from_name(name: &DomainName) -> Option<Algorithm> {
+        if *name == Algorithm::HmacSha256.name() {
+            Some(Algorithm::HmacSha256)
+        } else {
+            None
+        }
+    }
+
+    /// Length of the MACs this algorithm produces.
+    pub fn mac_len(self) -> usize {
+        match self {
+            Algorithm::HmacSha256 => 32,
+        }
+    }
+
+    fn mac(self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::HmacSha256 => crypto::hmac::<Sha256>(secret, data),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.name().to_string();
+        f.write_str(name.trim_end_matches('.'))
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Algorithm, ()> {
+        let name: DomainName = s.parse().map_err(drop)?;
+        Algorithm::from_name(&name).ok_or(())
+    }
+}
+
+/// A shared secret and the name both sides know it by.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    pub name: DomainName,
+    pub algorithm: Algorithm,
+    pub secret: Vec<u8>,
+}
+
+impl Key {
+    pub fn new(name: DomainName, algorithm: Algorithm, secret: Vec<u8>) -> Key {
+        Key {
+            name,
+            algorithm,
+            secret,
+        }
+    }
+
+    /// A key with a base64 secret, as found in `named.conf` and `tsig-keygen`
+    /// output.
+    pub fn from_base64(
+        name: DomainName,
+        algorithm: Algorithm,
+        secret: &str,
+    ) -> Result<Key, encoding::Error> {
+        Ok(Key::new(name, algorithm, encoding::base64_decode(secret)?))
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The keys a server accepts, by name.
+#[derive(Clone, Debug, Default)]
+pub struct Keyring {
+    keys: HashMap<DomainName, Key>,
+}
+
+impl Keyring {
+    pub fn new() -> Keyring {
+        Keyring::default()
+    }
+
+    /// Adds `key`, replacing any key with the same name.
+    pub fn insert(&mut self, key: Key) {
+        self.keys.insert(key.name.clone(), key);
+    }
+
+    pub fn remove(&mut self, name: &DomainName) -> Option<Key> {
+        self.keys.remove(name)
+    }
+
+    pub fn get(&self, name: &DomainName) -> Option<&Key> {
+        self.keys.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Seconds since the Unix epoch, as used for the time signed.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The data of a TSIG record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tsig {
+    pub algorithm: DomainName,
+    /// Seconds since the epoch; only the low 48 bits are encoded.
+    pub time_signed: u64,
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    pub original_id: u16,
+    pub error: Rcode,
+    pub other: Vec<u8>,
+}
+
+impl Tsig {
+    fn encode(&self, enc: &mut Encoder) {
+        enc.name(&self.algorithm, false);
+        enc.u16((self.time_signed >> 32) as u16);
+        enc.u32(self.time_signed as u32);
+        enc.u16(self.fudge);
+        enc.u16(self.mac.len() as u16);
+        enc.bytes(&self.mac);
+        enc.u16(self.original_id);
+        enc.u16(self.error.0);
+        enc.u16(self.other.len() as u16);
+        enc.bytes(&self.other);
+    }
+
+    fn decode(data: &[u8]) -> Result<Tsig, wire::Error> {
+        let mut dec = Decoder::new(data);
+        let algorithm = dec.name()?;
+        let time_signed = u64::from(dec.u16()?) << 32 | u64::from(dec.u32()?);
+        let fudge = dec.u16()?;
+        let mac_len = dec.u16()? as usize;
+        let mac = dec.bytes(mac_len)?.to_vec();
+        let original_id = dec.u16()?;
+        let error = Rcode(dec.u16()?);
+        let other_len = dec.u16()? as usize;
+        let other = dec.bytes(other_len)?.to_vec();
+        if dec.remaining() != 0 {
+            return Err(dec.bad_rdata());
+        }
+        Ok(Tsig {
+            algorithm,
+            time_signed,
+            fudge,
+            mac,
+            original_id,
+            error,
+            other,
+        })
+    }
+
+    /// The timer fields, which are all a subsequent message in a stream
+    /// covers besides its own data.
+    fn encode_timers(&self, enc: &mut Encoder) {
+        enc.u16((self.time_signed >> 32) as u16);
+        enc.u32(self.time_signed as u32);
+        enc.u16(self.fudge);
+    }
+
+    /// The TSIG variables covered by the MAC (RFC 8945 §4.3.3).
+    fn encode_variables(&self, key_name: &DomainName, enc: &mut Encoder) {
+        enc.canonical_name(key_name);
+        enc.u16(RecordClass::ANY.0);
+        enc.u32(0);
+        enc.canonical_name(&self.algorithm);
+        self.encode_timers(enc);
+        enc.u16(self.error.0);
+        enc.u16(self.other.len() as u16);
+        enc.bytes(&self.other);
+    }
+
+    fn check_time(&self, now: u64) -> Result<(), Error> {
+        if now.abs_diff(self.time_signed) > u64::from(self.fudge) {
+            return Err(Error::BadTime);
+        }
+        Ok(())
+    }
+}
+
+/// Appends a TSIG record with `tsig` to encoded message data.
+fn append(wire: &mut Vec<u8>, key_name: &DomainName, tsig: &Tsig) -> Result<(), wire::Error> {
+    let mut enc = Encoder::uncompressed();
+    enc.name(key_name, false);
+    enc.u16(RecordType::TSIG.0);
+    enc.u16(RecordClass::ANY.0);
+    enc.u32(0);
+    let len_pos = enc.len();
+    enc.u16(0);
+    tsig.encode(&mut enc);
+    let len = enc.len() - len_pos - 2;
+    enc.set_u16(len_pos, len as u16);
+    let arcount = u16::from_be_bytes([wire[10], wire[11]]);
+    let arcount = arcount
+        .checked_add(1)
+        .ok_or(wire::Error::TooLong { offset: 10 })?;
+    wire[10..12].copy_from_slice(&arcount.to_be_bytes());
+    wire.extend_from_slice(enc.as_bytes());
+    if wire.len() > usize::from(u16::MAX) {
+        return Err(wire::Error::TooLong { offset: wire.len() });
+    }
+    Ok(())
+}
+
+/// Builds the MAC input: the prior MAC, the message data and either the
+/// full variables or only the timers.
+fn mac_input(prior: Option<&[u8]>, data: &[u8], variables: &Encoder) -> Vec<u8> {
+    let mut input = Vec::with_capacity(data.len() + variables.len() + 66);
+    if let Some(prior) = prior {
+        input.extend_from_slice(&(prior.len() as u16).to_be_bytes());
+        input.extend_from_slice(prior);
+    }
+    input.extend_from_slice(data);
+    input.extend_from_slice(variables.as_bytes());
+    input
+}
+
+/// Signs `msg` with `key`, returning the signed wire data and the MAC. A
+/// response passes the MAC of the request it answers.
+pub fn sign(
+    msg: &Message,
+    key: &Key,
+    request_mac: Option<&[u8]>,
+    now: u64,
+) -> Result<(Vec<u8>, Vec<u8>), wire::Error> {
+    sign_wire(msg.to_wire()?, key, request_mac, now, Rcode::NOERROR)
+}
+
+/// Signs an already encoded message, e.g. one truncated to fit a UDP
+/// response, optionally reporting a TSIG error such as BADTIME in the
+/// record.
+pub fn sign_wire(
+    mut wire: Vec<u8>,
+    key: &Key,
+    request_mac: Option<&[u8]>,
+    now: u64,
+    error: Rcode,
+) -> Result<(Vec<u8>, Vec<u8>), wire::Error> {
+    if wire.len() < 12 {
+        return Err(wire::Error::Truncated { offset: 0 });
+    }
+    let mut tsig = Tsig {
+        algorithm: key.algorithm.name(),
+        time_signed: now,
+        fudge: FUDGE,
+        mac: Vec::new(),
+        original_id: u16::from_be_bytes([wire[0], wire[1]]),
+        error,
+        other: Vec::new(),
+    };
+    if error == Rcode::BADTIME {
+        // The server's time, so the client can see the skew (§5.2.3).
+        tsig.other = (now & 0xffff_ffff_ffff).to_be_bytes()[2..].to_vec();
+    }
+    let mut vars = Encoder::uncompressed();
+    tsig.encode_variables(&key.name, &mut vars);
+    tsig.mac = key
+        .algorithm
+        .mac(&key.secret, &mac_input(request_mac, &wire, &vars));
+    append(&mut wire, &key.name, &tsig)?;
+    Ok((wire, tsig.mac))
+}
+
+/// The space a TSIG record made with `key` takes, to be set aside when a
+/// signed message must fit a size limit.
+pub fn overhead(key: &Key) -> usize {
+    // Owner, type, class, TTL and length; then the fixed fields around
+    // the algorithm name and a 48-bit time in the other data.
+    key.name.wire_len() + 10 + key.algorithm.name().wire_len() + 16 + 6 + key.algorithm.mac_len()
+}
+
+/// Encodes an unsigned error response carrying a TSIG record with an
+/// empty MAC, for requests whose key or signature could not be verified
+/// (RFC 8945 §5.3.2).
+pub fn error_response(
+    resp: &Message,
+    key_name: &DomainName,
+    algorithm: &DomainName,
+    error: Rcode,
+    now: u64,
+) -> Result<Vec<u8>, wire::Error> {
+    let mut resp = resp.clone();
+    resp.header.rcode = Rcode::NOTAUTH;
+    let mut wire = resp.to_wire()?;
+    let tsig = Tsig {
+        algorithm: algorithm.clone(),
+        time_signed: now,
+        fudge: FUDGE,
+        mac: Vec::new(),
+        original_id: resp.header.id,
+        error,
+        other: Vec::new(),
+    };
+    append(&mut wire, key_name, &tsig)?;
+    Ok(wire)
+}
+
+/// Received wire data with its TSIG record split off.
+#[derive(Clone, Debug)]
+pub struct Signed {
+    /// The decoded message, without the TSIG record.
+    pub message: Message,
+    pub key_name: DomainName,
+    pub tsig: Tsig,
+    /// The message data the MAC covers: TSIG removed, ARCOUNT adjusted and
+    /// the original ID restored.
+    data: Vec<u8>,
+}
+
+impl Signed {
+    /// Decodes `wire`, returning `None` for an unsigned message.
+    pub fn parse(wire: &[u8]) -> Result<Option<Signed>, Error> {
+        let mut message = Message::from_wire(wire)?;
+        let tsig_pos = match message
+            .additional
+            .iter()
+            .position(|rr| rr.rtype() == RecordType::TSIG)
+        {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        if tsig_pos + 1 != message.additional.len() {
+            return Err(Error::Malformed);
+        }
+        let rr = message.additional.pop().unwrap();
+        let tsig = match &rr.rdata {
+            RData::Unknown { data, .. } if rr.class == RecordClass::ANY => {
+                Tsig::decode(data).map_err(|_| Error::Malformed)?
+            }
+            _ => return Err(Error::Malformed),
+        };
+        let start = last_record_offset(wire)?;
+        let mut data = wire[..start].to_vec();
+        data[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+        let arcount = u16::from_be_bytes([data[10], data[11]]) - 1;
+        data[10..12].copy_from_slice(&arcount.to_be_bytes());
+        message.header.id = tsig.original_id;
+        Ok(Some(Signed {
+            message,
+            key_name: rr.name,
+            tsig,
+            data,
+        }))
+    }
+
+    /// Checks the signature with `key`. A response passes the MAC of the
+    /// request it answers.
+    pub fn verify(&self, key: &Key, request_mac: Option<&[u8]>, now: u64) -> Result<(), Error> {
+        self.check_key(key)?;
+        let mut vars = Encoder::uncompressed();
+        self.tsig.encode_variables(&self.key_name, &mut vars);
+        self.check_mac(key, request_mac, &self.data, &vars)?;
+        if self.tsig.error != Rcode::NOERROR {
+            return Err(Error::Reported(self.tsig.error));
+        }
+        self.tsig.check_time(now)
+    }
+
+    fn check_key(&self, key: &Key) -> Result<(), Error> {
+        if self.key_name != key.name || self.tsig.algorithm != key.algorithm.name() {
+            return Err(Error::BadKey);
+        }
+        Ok(())
+    }
+
+    fn check_mac(
+        &self,
+        key: &Key,
+        prior: Option<&[u8]>,
+        data: &[u8],
+        vars: &Encoder,
+    ) -> Result<(), Error> {
+        if self.tsig.mac.is_empty() && self.tsig.error != Rcode::NOERROR {
+            // Unsigned errors from a peer that could not verify us.
+            return Err(Error::Reported(self.tsig.error));
+        }
+        let expected = key
+            .algorithm
+            .mac(&key.secret, &mac_input(prior, data, vars));
+        if !crypto::verify_mac(&expected, &self.tsig.mac) {
+            return Err(Error::BadSig);
+        }
+        Ok(())
+    }
+}
+
+/// Offset of the last record in `wire`, which must have at least one
+/// additional record.
+fn last_record_offset(wire: &[u8]) -> Result<usize, wire::Error> {
+    let mut dec = Decoder::new(wire);
+    dec.seek(4)?;
+    let qdcount = dec.u16()?;
+    let rrcount: usize = (0..3)
+        .map(|_| dec.u16().map(usize::from))
+        .sum::<Result<_, _>>()?;
+    for _ in 0..qdcount {
+        dec.name()?;
+        dec.bytes(4)?;
+    }
+    let mut start = dec.pos();
+    for _ in 0..rrcount {
+        start = dec.pos();
+        dec.name()?;
+        dec.bytes(8)?;
+        let len = dec.u16()?;
+        dec.bytes(usize::from(len))?;
+    }
+    Ok(start)
+}
+
+/// Signs the messages of a multi-message response, each covering the one
+/// before (RFC 8945 §5.3.1).
+#[derive(Debug)]
+pub struct StreamSigner {
+    key: Key,
+    prior_mac: Vec<u8>,
+    first: bool,
+}
+
+impl StreamSigner {
+    /// Starts a stream answering a request with MAC `request_mac`.
+    pub fn new(key: Key, request_mac: Vec<u8>) -> StreamSigner {
+        StreamSigner {
+            key,
+            prior_mac: request_mac,
+            first: true,
+        }
+    }
+
+    /// Signs the next message. This signer signs every message.
+    pub fn sign(&mut self, msg: &Message, now: u64) -> Result<Vec<u8>, wire::Error> {
+        if self.first {
+            self.first = false;
+            let (wire, mac) = sign(msg, &self.key, Some(&self.prior_mac), now)?;
+            self.prior_mac = mac;
+            return Ok(wire);
+        }
+        let mut wire = msg.to_wire()?;
+        let mut tsig = Tsig {
+            algorithm: self.key.algorithm.name(),
+            time_signed: now,
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: msg.header.id,
+            error: Rcode::NOERROR,
+            other: Vec::new(),
+        };
+        let mut timers = Encoder::uncompressed();
+        tsig.encode_timers(&mut timers);
+        tsig.mac = self.key.algorithm.mac(
+            &self.key.secret,
+            &mac_input(Some(&self.prior_mac), &wire, &timers),
+        );
+        append(&mut wire, &self.key.name, &tsig)?;
+        self.prior_mac = tsig.mac;
+        Ok(wire)
+    }
+}
+
+/// Verifies the messages of a multi-message response. Unsigned messages
+/// are allowed between signed ones, up to [`MAX_UNSIGNED`] in a row; the
+/// first and last must be signed.
+#[derive(Debug)]
+pub struct StreamVerifier {
+    key: Key,
+    prior_mac: Vec<u8>,
+    first: bool,
+    /// Data of unsigned messages since the last signed one.
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+}
+
+impl StreamVerifier {
+    /// Starts verifying the response to a request signed with MAC
+    /// `request_mac`.
+    pub fn new(key: Key, request_mac: Vec<u8>) -> StreamVerifier {
+        StreamVerifier {
+            key,
+            prior_mac: request_mac,
+            first: true,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+        }
+    }
+
+    /// Checks the next message and decodes it.
+    pub fn verify(&mut self, wire: &[u8], now: u64) -> Result<Message, Error> {
+        let signed = match Signed::parse(wire)? {
+            Some(signed) => signed,
+            None if self.first || self.unsigned_count >= MAX_UNSIGNED => {
+                return Err(Error::Unsigned)
+            }
+            None => {
+                self.unsigned.extend_from_slice(wire);
+                self.unsigned_count += 1;
+                return Ok(Message::from_wire(wire)?);
+            }
+        };
+        if self.first {
+            signed.verify(&self.key, Some(&self.prior_mac), now)?;
+            self.first = false;
+        } else {
+            signed.check_key(&self.key)?;
+            let mut timers = Encoder::uncompressed();
+            signed.tsig.encode_timers(&mut timers);
+            let mut data = std::mem::take(&mut self.unsigned);
+            data.extend_from_slice(&signed.data);
+            signed.check_mac(&self.key, Some(&self.prior_mac), &data, &timers)?;
+            signed.tsig.check_time(now)?;
+        }
+        self.unsigned_count = 0;
+        self.prior_mac = signed.tsig.mac;
+        Ok(signed.message)
+    }
+
+    /// Whether the last message seen was signed, as the final message of
+    /// a stream must be.
+    pub fn is_complete(&self) -> bool {
+        !self.first && self.unsigned_count == 0
+    }
+}
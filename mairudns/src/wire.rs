@@ -0,0 +1,351 @@
+//! Low-level wire format encoding and decoding.
+//!
+//! [`Encoder`] and [`Decoder`] handle the primitive pieces of a DNS message:
+//! big-endian integers, length-prefixed strings and (compressed) names. The
+//! structured types in [`crate::rr`] and [`crate::message`] are built on top
+//! of them.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::compliance::Compliance;
+use crate::name::{self, DomainName};
+use crate::simd;
+
+/// Errors produced when decoding wire data, with the byte offset into
+/// the message they are at, or for encoding, the length the message had
+/// reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The input ended before a complete item starting at `offset` was
+    /// read.
+    Truncated { offset: usize },
+    /// A compression pointer points forward or loops.
+    BadPointer { offset: usize },
+    /// A label type other than a normal label or pointer.
+    BadLabelType { offset: usize },
+    /// A decoded name, starting at `offset`, violates length limits.
+    BadName { offset: usize, error: name::Error },
+    /// Record data does not match its declared length or format.
+    BadRdata { offset: usize },
+    /// Bytes remain after the last section, from `offset` on.
+    TrailingData { offset: usize },
+    /// The encoded message would exceed 65535 bytes.
+    TooLong { offset: usize },
+}
+
+impl Error {
+    /// Where in the message the error is.
+    pub fn offset(&self) -> usize {
+        match *self {
+            Error::Truncated { offset }
+            | Error::BadPointer { offset }
+            | Error::BadLabelType { offset }
+            | Error::BadName { offset, .. }
+            | Error::BadRdata { offset }
+            | Error::TrailingData { offset }
+            | Error::TooLong { offset } => offset,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Truncated { offset } => {
+                write!(f, "unexpected end of data at byte {}", offset)
+            }
+            Error::BadPointer { offset } => {
+                write!(f, "invalid compression pointer at byte {}", offset)
+            }
+            Error::BadLabelType { offset } => {
+                write!(f, "unsupported label type at byte {}", offset)
+            }
+            Error::BadName { offset, error } => {
+                write!(f, "invalid name at byte {}: {}", offset, error)
+            }
+            Error::BadRdata { offset } => write!(f, "malformed record data at byte {}", offset),
+            Error::TrailingData { offset } => {
+                write!(f, "trailing data after message at byte {}", offset)
+            }
+            Error::TooLong { offset } => write!(f, "message too long at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Builds a wire-format buffer, compressing names when asked to.
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+    names: HashMap<DomainName, u16>,
+    uncompressed: bool,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder::default()
+    }
+
+    /// An encoder that never emits compression pointers, for record data
+    /// that must stand on its own outside a message.
+    pub fn uncompressed() -> Encoder {
+        Encoder {
+            uncompressed: true,
+            ..Encoder::default()
+        }
+    }
+
+    /// Current length of the buffer.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    /// Writes a `<character-string>`: a length octet followed by data.
+    pub fn character_string(&mut self, v: &[u8]) -> Result<(), Error> {
+        if v.len() > 255 {
+            return Err(Error::BadRdata {
+                offset: self.buf.len(),
+            });
+        }
+        self.u8(v.len() as u8);
+        self.bytes(v);
+        Ok(())
+    }
+
+    /// Overwrites two bytes at `pos`, used to back-fill lengths and counts.
+    pub fn set_u16(&mut self, pos: usize, v: u16) {
+        self.buf[pos..pos + 2].copy_from_slice(&v.to_be_bytes());
+    }
+
+    /// Writes a name, replacing any previously written suffix with a
+    /// pointer when `compress` is set.
+    pub fn name(&mut self, name: &DomainName, compress: bool) {
+        let labels = name.labels();
+        for i in 0..labels.len() {
+            let suffix = name.suffix(labels.len() - i);
+            if compress && !self.uncompressed {
+                if let Some(&off) = self.names.get(&suffix) {
+                    self.u16(0xc000 | off);
+                    return;
+                }
+            }
+            if self.buf.len() < 0x4000 {
+                self.names.entry(suffix).or_insert(self.buf.len() as u16);
+            }
+            self.u8(labels[i].len() as u8);
+            self.bytes(&labels[i]);
+        }
+        self.u8(0);
+    }
+
+    /// Writes a name in canonical form: uncompressed and lowercased.
+    pub fn canonical_name(&mut self, name: &DomainName) {
+        for label in name.labels() {
+            self.u8(label.len() as u8);
+            let start = self.buf.len();
+            self.bytes(label);
+            simd::lowercase(&mut self.buf[start..]);
+        }
+        self.u8(0);
+    }
+
+    /// Drops everything after `len`, forgetting compression targets there.
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+        self.names.retain(|_, off| (*off as usize) < len);
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads wire-format data from a complete message buffer.
+///
+/// The decoder always sees the whole message so that compression pointers
+/// can be followed, and tracks its own read position. What it puts up with
+/// that the RFCs forbid follows its [`Compliance`] profile.
+#[derive(Clone)]
+pub struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    compliance: Compliance,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Decoder<'a> {
+        Decoder::with_compliance(buf, Compliance::default())
+    }
+
+    pub fn with_compliance(buf: &'a [u8], compliance: Compliance) -> Decoder<'a> {
+        Decoder {
+            buf,
+            pos: 0,
+            compliance,
+        }
+    }
+
+    pub fn compliance(&self) -> &Compliance {
+        &self.compliance
+    }
+
+    /// The full underlying buffer.
+    pub fn buffer(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        let v = *self.buf.get(self.pos).ok_or(self.truncated())?;
+        self.pos += 1;
+        Ok(v)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.remaining() < n {
+            return Err(self.truncated());
+        }
+        let v = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(v)
+    }
+
+    /// Reads a `<character-string>`.
+    pub fn character_string(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    /// Reads a possibly compressed name.
+    pub fn name(&mut self) -> Result<DomainName, Error> {
+        let mut labels: Vec<&[u8]> = Vec::new();
+        let start = self.pos;
+        let mut pos = self.pos;
+        let mut end = None;
+        let mut len = 1;
+        loop {
+            let b = *self.buf.get(pos).ok_or(Error::Truncated { offset: pos })?;
+            match b & 0xc0 {
+                0x00 if b == 0 => {
+                    pos += 1;
+                    break;
+                }
+                0x00 => {
+                    let n = b as usize;
+                    let label = self
+                        .buf
+                        .get(pos + 1..pos + 1 + n)
+                        .ok_or(Error::Truncated { offset: pos })?;
+                    len += n + 1;
+                    if len > name::MAX_NAME_LEN {
+                        return Err(Error::BadName {
+                            offset: start,
+                            error: name::Error::NameTooLong { index: pos },
+                        });
+                    }
+                    labels.push(label);
+                    pos += n + 1;
+                }
+                0xc0 => {
+                    let lo = *self
+                        .buf
+                        .get(pos + 1)
+                        .ok_or(Error::Truncated { offset: pos })?;
+                    let target = (usize::from(b & 0x3f) << 8) | usize::from(lo);
+                    // Pointers must go strictly backwards, which rules out
+                    // loops without needing a hop counter.
+                    if target >= pos {
+                        return Err(Error::BadPointer { offset: pos });
+                    }
+                    if end.is_none() {
+                        end = Some(pos + 2);
+                    }
+                    pos = target;
+                }
+                _ => return Err(Error::BadLabelType { offset: pos }),
+            }
+        }
+        self.pos = end.unwrap_or(pos);
+        DomainName::from_labels(labels).map_err(|error| Error::BadName {
+            offset: start,
+            error,
+        })
+    }
+
+    /// Reads a name that must be written uncompressed, failing with
+    /// [`Error::BadPointer`] on a compression pointer unless the profile
+    /// puts up with them.
+    pub fn uncompressed_name(&mut self) -> Result<DomainName, Error> {
+        let start = self.pos;
+        let name = self.name()?;
+        let len: usize = name.labels().iter().map(|l| l.len() + 1).sum::<usize>() + 1;
+        if self.pos - start != len && !self.compliance.compressed_rdata {
+            return Err(Error::BadPointer {
+                offset: self.pos - 2,
+            });
+        }
+        Ok(name)
+    }
+
+    /// Moves the read position to `pos`, which must lie within the buffer.
+    pub fn seek(&mut self, pos: usize) -> Result<(), Error> {
+        if pos > self.buf.len() {
+            return Err(Error::Truncated { offset: self.pos });
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    /// [`Error::Truncated`] at the read position.
+    pub fn truncated(&self) -> Error {
+        Error::Truncated { offset: self.pos }
+    }
+
+    /// [`Error::BadRdata`] at the read position.
+    pub fn bad_rdata(&self) -> Error {
+        Error::BadRdata { offset: self.pos }
+    }
+}
@@ -0,0 +1,423 @@
+//! Zones kept outside the process, in a database, and read a name at a
+//! time as queries arrive.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::name::DomainName;
+use crate::rr::{Record, RecordClass, RecordType};
+
+use super::lookup::{self, Tree};
+use super::{parse_records, Answer, Diff, Error, Zone};
+
+/// Errors of zone backends.
+#[derive(Debug)]
+pub enum BackendError {
+    Io(io::Error),
+    /// The database refused a request.
+    Storage(String),
+    /// A stored record that does not parse.
+    Data(String),
+    /// The backend holds no zone with this origin.
+    NoZone(DomainName),
+    /// A difference that does not apply to the zone.
+    Zone(Error),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Io(e) => write!(f, "{}", e),
+            BackendError::Storage(e) => write!(f, "storage error: {}", e),
+            BackendError::Data(e) => write!(f, "bad stored record: {}", e),
+            BackendError::NoZone(origin) => write!(f, "no zone {}", origin),
+            BackendError::Zone(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+impl From<io::Error> for BackendError {
+    fn from(e: io::Error) -> BackendError {
+        BackendError::Io(e)
+    }
+}
+
+impl From<Error> for BackendError {
+    fn from(e: Error) -> BackendError {
+        BackendError::Zone(e)
+    }
+}
+
+/// Storage for zones other than the in-memory [`Zone`]. Every method
+/// takes the origin of the zone it concerns, so one backend can hold many
+/// zones.
+///
+/// The lookup itself is the same as for in-memory zones and is built on
+/// [`rrset`](Backend::rrset) and [`name_exists`](Backend::name_exists),
+/// which a query may call several times; wrap slow backends in a
+/// [`CachedBackend`].
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Origins of the zones held.
+    fn zones(&self) -> Result<Vec<DomainName>, BackendError>;
+
+    /// The RRset of `rtype` at `name`, or every record at `name` for ANY;
+    /// empty if there is none.
+    fn rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError>;
+
+    /// Whether `name` owns records or has descendants that do.
+    fn name_exists(&self, zone: &DomainName, name: &DomainName) -> Result<bool, BackendError>;
+
+    /// Every record of the zone.
+    fn records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError>;
+
+    /// Applies a difference whose old SOA is the zone's current one, all
+    /// or nothing.
+    fn apply(&self, zone: &DomainName, diff: &Diff) -> Result<(), BackendError>;
+
+    /// Replaces the zone, or adds it, with the records of `zone`.
+    fn store(&self, zone: &Zone) -> Result<(), BackendError>;
+
+    /// The SOA serial, if the zone has an SOA.
+    fn serial(&self, zone: &DomainName) -> Result<Option<u32>, BackendError> {
+        Ok(self
+            .rrset(zone, zone, RecordType::SOA)?
+            .first()
+            .and_then(super::journal::serial))
+    }
+
+    /// Looks up `qname`/`qtype` in the zone as [`Zone::lookup`] does.
+    fn lookup(
+        &self,
+        zone: &DomainName,
+        qname: &DomainName,
+        qtype: RecordType,
+    ) -> Result<Answer, BackendError> {
+        let tree = BackendTree::new(self, zone);
+        let answer = lookup::lookup(&tree, qname, qtype);
+        tree.finish(answer)
+    }
+
+    /// The owner of the wildcard that answers for `qname`, if `qname` does
+    /// not exist itself.
+    fn wildcard_owner(
+        &self,
+        zone: &DomainName,
+        qname: &DomainName,
+    ) -> Result<Option<DomainName>, BackendError> {
+        let tree = BackendTree::new(self, zone);
+        let owner = lookup::wildcard_owner(&tree, qname);
+        tree.finish(owner)
+    }
+
+    /// The zone as an AXFR response lists it; empty without an SOA.
+    fn transfer_records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError> {
+        let mut records = self.records(zone)?;
+        let soa = match records.iter().position(|rr| rr.rtype() == RecordType::SOA) {
+            Some(i) => records.remove(i),
+            None => return Ok(Vec::new()),
+        };
+        records.retain(|rr| rr.rtype() != RecordType::SOA);
+        records.insert(0, soa.clone());
+        records.push(soa);
+        Ok(records)
+    }
+}
+
+/// A backend seen as the [`Tree`] of one zone. The lookup cannot fail, so
+/// the first error is kept aside and reported once it is done.
+struct BackendTree<'a, B: ?Sized> {
+    backend: &'a B,
+    origin: &'a DomainName,
+    error: RefCell<Option<BackendError>>,
+}
+
+impl<'a, B: Backend + ?Sized> BackendTree<'a, B> {
+    fn new(backend: &'a B, origin: &'a DomainName) -> Self {
+        BackendTree {
+            backend,
+            origin,
+            error: RefCell::new(None),
+        }
+    }
+
+    fn keep<T: Default>(&self, result: Result<T, BackendError>) -> T {
+        result.unwrap_or_else(|e| {
+            self.error.borrow_mut().get_or_insert(e);
+            T::default()
+        })
+    }
+
+    fn finish<T>(self, value: T) -> Result<T, BackendError> {
+        match self.error.into_inner() {
+            Some(e) => Err(e),
+            None => Ok(value),
+        }
+    }
+}
+
+impl<B: Backend + ?Sized> Tree for BackendTree<'_, B> {
+    fn origin(&self) -> &DomainName {
+        self.origin
+    }
+
+    fn rrset(&self, name: &DomainName, rtype: RecordType) -> Cow<'_, [Record]> {
+        Cow::Owned(self.keep(self.backend.rrset(self.origin, name, rtype)))
+    }
+
+    fn owns_records(&self, name: &DomainName) -> bool {
+        !self.rrset(name, RecordType::ANY).is_empty()
+    }
+
+    fn name_exists(&self, name: &DomainName) -> bool {
+        self.keep(self.backend.name_exists(self.origin, name))
+    }
+}
+
+/// Checks that `diff` applies to a zone at `origin` whose SOA is `soa`.
+pub(super) fn check_diff(
+    origin: &DomainName,
+    soa: Option<&Record>,
+    diff: &Diff,
+) -> Result<(), BackendError> {
+    let serial = soa.and_then(super::journal::serial);
+    if serial.is_none() || serial != diff.old_serial() || diff.new_serial().is_none() {
+        return Err(Error::SerialMismatch.into());
+    }
+    if diff.new_soa.name != *origin {
+        return Err(Error::OutOfZone.into());
+    }
+    if diff.added.iter().any(|rr| !rr.name.is_subdomain_of(origin)) {
+        return Err(Error::OutOfZone.into());
+    }
+    Ok(())
+}
+
+/// The RRsets `diff` changes, the SOA among them.
+pub(super) fn changed_rrsets(diff: &Diff) -> BTreeSet<(DomainName, RecordType)> {
+    diff.deleted
+        .iter()
+        .chain(&diff.added)
+        .chain(std::iter::once(&diff.new_soa))
+        .map(|rr| (rr.name.clone(), rr.rtype()))
+        .collect()
+}
+
+/// The RRset of `rtype` at `name` once `diff` is applied to `current`.
+pub(super) fn changed_rrset(
+    current: Vec<Record>,
+    diff: &Diff,
+    name: &DomainName,
+    rtype: RecordType,
+) -> Vec<Record> {
+    if rtype == RecordType::SOA && *name == diff.new_soa.name {
+        return vec![diff.new_soa.clone()];
+    }
+    let matches = |rr: &&Record| rr.name == *name && rr.rtype() == rtype;
+    let mut rrset = current;
+    for gone in diff.deleted.iter().filter(matches) {
+        rrset.retain(|rr| rr.rdata != gone.rdata);
+    }
+    for new in diff.added.iter().filter(matches) {
+        match rrset.iter_mut().find(|rr| rr.rdata == new.rdata) {
+            Some(existing) => existing.ttl = new.ttl,
+            None => rrset.push(new.clone()),
+        }
+    }
+    rrset
+}
+
+/// Reads a stored record from its parts, `data` as in a zone file.
+pub(super) fn parse_record(
+    name: &str,
+    ttl: u32,
+    class: RecordClass,
+    rtype: &str,
+    data: &str,
+) -> Result<Record, BackendError> {
+    let line = format!("{} {} {} {} {}", name, ttl, class, rtype, data);
+    let bad = || BackendError::Data(line.clone());
+    let mut records = parse_records(&line, &DomainName::root()).map_err(|_| bad())?;
+    match (records.pop(), records.is_empty()) {
+        (Some(rr), true) => Ok(rr),
+        _ => Err(bad()),
+    }
+}
+
+/// Limits of a [`CachedBackend`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackendCache {
+    /// Most answers kept; the least recently stored go first.
+    pub capacity: usize,
+    /// How long an answer is kept. Changes made through the cache drop
+    /// the zone's answers at once; changes made to the database directly
+    /// show after this long.
+    pub ttl: Duration,
+}
+
+impl Default for BackendCache {
+    fn default() -> BackendCache {
+        BackendCache {
+            capacity: 10_000,
+            ttl: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Rrset(DomainName, DomainName, RecordType),
+    Exists(DomainName, DomainName),
+}
+
+impl CacheKey {
+    fn zone(&self) -> &DomainName {
+        match self {
+            CacheKey::Rrset(zone, _, _) | CacheKey::Exists(zone, _) => zone,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Cached {
+    Rrset(Vec<Record>),
+    Exists(bool),
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<CacheKey, (Cached, Instant)>,
+    /// Keys in the order they were stored, oldest first.
+    order: VecDeque<CacheKey>,
+}
+
+/// Keeps the answers of another backend for hot names, so that the
+/// several reads of one lookup, and the same query asked again, reach
+/// the database once.
+#[derive(Debug)]
+pub struct CachedBackend<B> {
+    inner: B,
+    limits: BackendCache,
+    entries: Mutex<Entries>,
+}
+
+impl<B: Backend> CachedBackend<B> {
+    pub fn new(inner: B, limits: BackendCache) -> CachedBackend<B> {
+        CachedBackend {
+            inner,
+            limits,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Drops every cached answer.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.clear();
+        entries.order.clear();
+    }
+
+    fn forget_zone(&self, zone: &DomainName) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.retain(|key, _| key.zone() != zone);
+        let Entries { map, order } = &mut *entries;
+        order.retain(|key| map.contains_key(key));
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Cached> {
+        let entries = self.entries.lock().unwrap();
+        match entries.map.get(key) {
+            Some((Cached::Rrset(rrset), at)) if at.elapsed() < self.limits.ttl => {
+                Some(Cached::Rrset(rrset.clone()))
+            }
+            Some((Cached::Exists(exists), at)) if at.elapsed() < self.limits.ttl => {
+                Some(Cached::Exists(*exists))
+            }
+            _ => None,
+        }
+    }
+
+    fn put(&self, key: CacheKey, value: Cached) {
+        if self.limits.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        while entries.map.len() >= self.limits.capacity {
+            match entries.order.pop_front() {
+                Some(old) => {
+                    entries.map.remove(&old);
+                }
+                None => break,
+            }
+        }
+        if entries
+            .map
+            .insert(key.clone(), (value, Instant::now()))
+            .is_none()
+        {
+            entries.order.push_back(key);
+        }
+    }
+}
+
+impl<B: Backend> Backend for CachedBackend<B> {
+    fn zones(&self) -> Result<Vec<DomainName>, BackendError> {
+        self.inner.zones()
+    }
+
+    fn rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        let key = CacheKey::Rrset(zone.clone(), name.clone(), rtype);
+        if let Some(Cached::Rrset(rrset)) = self.get(&key) {
+            return Ok(rrset);
+        }
+        let rrset = self.inner.rrset(zone, name, rtype)?;
+        self.put(key, Cached::Rrset(rrset.clone()));
+        Ok(rrset)
+    }
+
+    fn name_exists(&self, zone: &DomainName, name: &DomainName) -> Result<bool, BackendError> {
+        let key = CacheKey::Exists(zone.clone(), name.clone());
+        if let Some(Cached::Exists(exists)) = self.get(&key) {
+            return Ok(exists);
+        }
+        let exists = self.inner.name_exists(zone, name)?;
+        self.put(key, Cached::Exists(exists));
+        Ok(exists)
+    }
+
+    fn records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError> {
+        self.inner.records(zone)
+    }
+
+    fn apply(&self, zone: &DomainName, diff: &Diff) -> Result<(), BackendError> {
+        let result = self.inner.apply(zone, diff);
+        self.forget_zone(zone);
+        result
+    }
+
+    fn store(&self, zone: &Zone) -> Result<(), BackendError> {
+        let result = self.inner.store(zone);
+        self.forget_zone(zone.origin());
+        result
+    }
+}
@@ -0,0 +1,122 @@
+//! The history of a zone's changes, from which IXFR responses are built.
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+
+use crate::rr::{RData, Record};
+
+use super::serial_cmp;
+
+/// Default number of differences a journal keeps.
+pub const JOURNAL_LIMIT: usize = 100;
+
+/// The change from one version of a zone to the next (RFC 1995 §4): the
+/// old SOA and the records deleted, then the new SOA and the records added.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diff {
+    pub old_soa: Record,
+    pub deleted: Vec<Record>,
+    pub new_soa: Record,
+    pub added: Vec<Record>,
+}
+
+pub(super) fn serial(soa: &Record) -> Option<u32> {
+    match &soa.rdata {
+        RData::Soa(soa) => Some(soa.serial),
+        _ => None,
+    }
+}
+
+impl Diff {
+    pub fn old_serial(&self) -> Option<u32> {
+        serial(&self.old_soa)
+    }
+
+    pub fn new_serial(&self) -> Option<u32> {
+        serial(&self.new_soa)
+    }
+
+    /// The difference in IXFR order: old SOA, deletions, new SOA,
+    /// additions.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        std::iter::once(&self.old_soa)
+            .chain(&self.deleted)
+            .chain(std::iter::once(&self.new_soa))
+            .chain(&self.added)
+    }
+}
+
+/// The most recent differences of a zone, oldest first.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    diffs: VecDeque<Diff>,
+    limit: usize,
+}
+
+impl Default for Journal {
+    fn default() -> Journal {
+        Journal {
+            diffs: VecDeque::new(),
+            limit: JOURNAL_LIMIT,
+        }
+    }
+}
+
+impl Journal {
+    /// Records a difference, dropping the oldest beyond the limit.
+    pub fn push(&mut self, diff: Diff) {
+        self.diffs.push_back(diff);
+        while self.diffs.len() > self.limit {
+            self.diffs.pop_front();
+        }
+    }
+
+    /// Number of differences kept; zero disables the journal.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        while self.diffs.len() > limit {
+            self.diffs.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.diffs.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.diffs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diffs.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diff> {
+        self.diffs.iter()
+    }
+
+    /// The unbroken chain of differences leading from `serial` to the
+    /// latest version, or `None` if the journal does not reach back that
+    /// far.
+    pub fn since(&self, serial: u32) -> Option<Vec<&Diff>> {
+        let start = self
+            .diffs
+            .iter()
+            .position(|d| d.old_serial() == Some(serial))?;
+        let chain: Vec<&Diff> = self.diffs.iter().skip(start).collect();
+        let linked = chain
+            .windows(2)
+            .all(|w| w[0].new_serial() == w[1].old_serial());
+        let forward = chain
+            .iter()
+            .all(|d| match (d.old_serial(), d.new_serial()) {
+                (Some(old), Some(new)) => serial_cmp(new, old) == Some(Ordering::Greater),
+                _ => false,
+            });
+        if linked && forward {
+            Some(chain)
+        } else {
+            None
+        }
+    }
+}
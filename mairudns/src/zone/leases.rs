@@ -0,0 +1,806 @@
+//! Address and PTR records synthesized from a DHCP server's leases.
+//!
+//! A [`LeaseSync`] reads the lease file of ISC dhcpd (`dhcpd.leases`) or
+//! Kea (its memfile CSV, or the JSON of `lease4-get-all` and
+//! `lease6-get-all`) and keeps one A or AAAA record per named client in a
+//! designated zone, with the matching PTR record in whichever reverse zone
+//! the [`Authority`] holds for the address. Records come and go with the
+//! leases, each change a journaled difference with a new serial, so that
+//! secondaries follow by IXFR. TTLs are half the lease lifetime, as ISC
+//! dhcpd's own DDNS updates give them (RFC 4702 §5 leaves it open), and
+//! at most a configured maximum.
+//!
+//! Names holding records the sync did not add, such as static hosts from
+//! the zone's master file, are left alone.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::addr::reverse_name;
+use crate::clock::{self, Clock};
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType};
+use crate::server::{Authority, SharedZone};
+
+use super::{Diff, Zone};
+
+/// One lease as the DHCP server recorded it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lease {
+    pub address: IpAddr,
+    /// The name the client asked for, if any.
+    pub hostname: Option<String>,
+    /// When the lease began, in seconds since the epoch.
+    pub starts: Option<u64>,
+    /// When the lease ends, in seconds since the epoch; `None` for never.
+    pub ends: Option<u64>,
+    /// Whether the address is bound to the client, rather than free,
+    /// expired, released or declined.
+    pub active: bool,
+}
+
+impl Lease {
+    /// Whether the lease holds at `now`.
+    pub fn is_current(&self, now: u64) -> bool {
+        self.active && self.ends.is_none_or(|ends| ends > now)
+    }
+
+    /// How long the lease runs in all, if it ends.
+    pub fn lifetime(&self) -> Option<u64> {
+        Some(self.ends?.saturating_sub(self.starts?))
+    }
+}
+
+/// The format of a lease file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaseFormat {
+    /// ISC dhcpd's `dhcpd.leases` or `dhcpd6.leases`.
+    IscDhcpd,
+    /// The CSV lease file of Kea's memfile backend, for either family.
+    KeaCsv,
+    /// The JSON Kea answers `lease4-get-all` and `lease6-get-all` with,
+    /// the response as a whole or just its list of leases. Needs the
+    /// `kea` feature.
+    KeaJson,
+}
+
+/// Errors reading leases or applying them.
+#[derive(Debug)]
+pub enum LeaseError {
+    Io(io::Error),
+    /// The lease file is malformed at `line`, counted from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// The zone would not take the records.
+    Zone(super::Error),
+    /// The designated zone is not held in memory.
+    NoZone(DomainName),
+}
+
+impl fmt::Display for LeaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeaseError::Io(e) => write!(f, "cannot read leases: {}", e),
+            LeaseError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            LeaseError::Zone(e) => write!(f, "cannot update the zone: {}", e),
+            LeaseError::NoZone(origin) => write!(f, "no zone {} in memory", origin),
+        }
+    }
+}
+
+impl std::error::Error for LeaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LeaseError::Io(e) => Some(e),
+            LeaseError::Zone(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LeaseError {
+    fn from(e: io::Error) -> LeaseError {
+        LeaseError::Io(e)
+    }
+}
+
+impl From<super::Error> for LeaseError {
+    fn from(e: super::Error) -> LeaseError {
+        LeaseError::Zone(e)
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> LeaseError {
+    LeaseError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// The leases in `text`, in the order the file has them. Later entries
+/// for an address supersede earlier ones.
+pub fn parse_leases(text: &str, format: LeaseFormat) -> Result<Vec<Lease>, LeaseError> {
+    match format {
+        LeaseFormat::IscDhcpd => parse_isc(text),
+        LeaseFormat::KeaCsv => parse_kea_csv(text),
+        LeaseFormat::KeaJson => parse_kea_json(text),
+    }
+}
+
+/// A token of an ISC dhcpd lease file, with the line it is on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Open,
+    Close,
+    Semi,
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, LeaseError> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            ';' => tokens.push((Token::Semi, line)),
+            '"' => {
+                let start = line;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => s.push(c),
+                            None => return Err(parse_error(start, "unterminated string")),
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            s.push(c);
+                        }
+                        None => return Err(parse_error(start, "unterminated string")),
+                    }
+                }
+                tokens.push((Token::Str(s), start));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars
+                    .next_if(|&c| !c.is_whitespace() && !matches!(c, '{' | '}' | ';' | '"' | '#'))
+                {
+                    word.push(c);
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A statement of an ISC dhcpd lease file, or a block with the words
+/// before its braces.
+enum Item {
+    Statement(Vec<Token>, usize),
+    Block(Vec<Token>, Vec<Item>, usize),
+}
+
+type Tokens = std::vec::IntoIter<(Token, usize)>;
+
+/// The items up to the end of the file, or up to the brace closing the
+/// block opened on line `open` if there is one.
+fn items(tokens: &mut Tokens, open: Option<usize>) -> Result<Vec<Item>, LeaseError> {
+    let mut found = Vec::new();
+    let mut words = Vec::new();
+    let mut start = 0;
+    while let Some((token, line)) = tokens.next() {
+        if words.is_empty() {
+            start = line;
+        }
+        match token {
+            Token::Semi => {
+                if !words.is_empty() {
+                    found.push(Item::Statement(std::mem::take(&mut words), start));
+                }
+            }
+            Token::Open => {
+                let body = items(tokens, Some(line))?;
+                found.push(Item::Block(std::mem::take(&mut words), body, start));
+            }
+            Token::Close if open.is_some() => return Ok(found),
+            Token::Close => return Err(parse_error(line, "unbalanced '}'")),
+            token => words.push(token),
+        }
+    }
+    match open {
+        Some(line) => Err(parse_error(line, "unterminated block")),
+        None => Ok(found),
+    }
+}
+
+fn word(token: Option<&Token>) -> Option<&str> {
+    match token? {
+        Token::Word(w) => Some(w),
+        _ => None,
+    }
+}
+
+fn string(token: Option<&Token>) -> Option<&str> {
+    match token? {
+        Token::Word(w) | Token::Str(w) => Some(w),
+        _ => None,
+    }
+}
+
+/// The leases of an ISC dhcpd lease file: `lease` blocks for IPv4 and
+/// the `iaaddr` blocks within `ia-na` and `ia-ta` blocks for IPv6.
+fn parse_isc(text: &str) -> Result<Vec<Lease>, LeaseError> {
+    let mut tokens = tokenize(text)?.into_iter();
+    let mut leases = Vec::new();
+    for item in items(&mut tokens, None)? {
+        let (head, body, line) = match item {
+            Item::Block(head, body, line) => (head, body, line),
+            Item::Statement(..) => continue,
+        };
+        match word(head.first()) {
+            Some("lease") => {
+                let address = address(head.get(1), line)?;
+                leases.push(isc_lease(address, &body)?);
+            }
+            Some("ia-na") | Some("ia-ta") => {
+                let hostname = hostname_statement(&body);
+                // Times in `iaaddr` blocks count from the client's last
+                // transaction with the server.
+                let cltt = body.iter().find_map(|item| match item {
+                    Item::Statement(words, line) if word(words.first()) == Some("cltt") => {
+                        Some(isc_time(&words[1..], *line))
+                    }
+                    _ => None,
+                });
+                let cltt = cltt.transpose()?.flatten();
+                for item in &body {
+                    if let Item::Block(head, inner, line) = item {
+                        if word(head.first()) == Some("iaaddr") {
+                            let address = address(head.get(1), *line)?;
+                            let mut lease = isc_lease(address, inner)?;
+                            lease.starts = lease.starts.or(cltt);
+                            lease.hostname = lease.hostname.or_else(|| hostname.clone());
+                            leases.push(lease);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(leases)
+}
+
+fn address(token: Option<&Token>, line: usize) -> Result<IpAddr, LeaseError> {
+    word(token)
+        .and_then(|w| w.parse().ok())
+        .ok_or_else(|| parse_error(line, "expected an address"))
+}
+
+fn isc_lease(address: IpAddr, body: &[Item]) -> Result<Lease, LeaseError> {
+    let mut lease = Lease {
+        address,
+        hostname: None,
+        starts: None,
+        ends: None,
+        active: false,
+    };
+    for item in body {
+        let (words, line) = match item {
+            Item::Statement(words, line) => (words, *line),
+            Item::Block(..) => continue,
+        };
+        match word(words.first()) {
+            Some("starts") => lease.starts = isc_time(&words[1..], line)?,
+            Some("ends") => lease.ends = isc_time(&words[1..], line)?,
+            Some("binding") if word(words.get(1)) == Some("state") => {
+                lease.active = word(words.get(2)) == Some("active");
+            }
+            Some("client-hostname") => {
+                lease.hostname = lease
+                    .hostname
+                    .or_else(|| string(words.get(1)).map(str::to_string));
+            }
+            _ => {}
+        }
+    }
+    // A name given to the client's DDNS update is the one it goes by.
+    if let Some(name) = hostname_statement(body) {
+        lease.hostname = Some(name);
+    }
+    Ok(lease)
+}
+
+/// The name of a `set ddns-fwd-name = "...";` statement.
+fn hostname_statement(body: &[Item]) -> Option<String> {
+    body.iter().find_map(|item| match item {
+        Item::Statement(words, _)
+            if word(words.first()) == Some("set")
+                && word(words.get(1)) == Some("ddns-fwd-name") =>
+        {
+            string(words.get(3)).map(str::to_string)
+        }
+        _ => None,
+    })
+}
+
+/// A time of an ISC dhcpd lease file: `never`, `epoch SECONDS`, or a
+/// weekday followed by `YYYY/MM/DD HH:MM:SS` in UTC.
+fn isc_time(words: &[Token], line: usize) -> Result<Option<u64>, LeaseError> {
+    let bad = || parse_error(line, "malformed time");
+    match word(words.first()) {
+        Some("never") => return Ok(None),
+        Some("epoch") => {
+            let secs = word(words.get(1)).and_then(|w| w.parse().ok());
+            return secs.map(Some).ok_or_else(bad);
+        }
+        _ => {}
+    }
+    let date = word(words.get(1)).ok_or_else(bad)?;
+    let time = word(words.get(2)).ok_or_else(bad)?;
+    let numbers =
+        |s: &str, sep: char| -> Option<Vec<u64>> { s.split(sep).map(|n| n.parse().ok()).collect() };
+    let (date, time) = match (numbers(date, '/'), numbers(time, ':')) {
+        (Some(d), Some(t)) if d.len() == 3 && t.len() == 3 => (d, t),
+        _ => return Err(bad()),
+    };
+    if !(1..=12).contains(&date[1]) || !(1..=31).contains(&date[2]) || date[0] < 1970 {
+        return Err(bad());
+    }
+    let days = days_from_civil(date[0], date[1], date[2]);
+    Ok(Some(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2]))
+}
+
+/// Days from 1970-01-01 to a date on or after it in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The leases of a Kea memfile CSV file, whose header says where each
+/// column is. A row with a valid lifetime of zero is a released lease.
+fn parse_kea_csv(text: &str) -> Result<Vec<Lease>, LeaseError> {
+    let mut lines = text.lines().enumerate();
+    let header: Vec<&str> = match lines.next() {
+        Some((_, header)) => header.split(',').map(str::trim).collect(),
+        None => return Ok(Vec::new()),
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|c| *c == name)
+            .ok_or_else(|| parse_error(1, format!("no {} column", name)))
+    };
+    let (address, lifetime, expire) = (
+        column("address")?,
+        column("valid_lifetime")?,
+        column("expire")?,
+    );
+    let hostname = column("hostname").ok();
+    let state = column("state").ok();
+    let mut leases = Vec::new();
+    for (i, row) in lines {
+        if row.trim().is_empty() {
+            continue;
+        }
+        let line = i + 1;
+        let fields: Vec<&str> = row.split(',').collect();
+        let field = |i: usize| fields.get(i).copied().unwrap_or("");
+        let number = |i: usize, what: &str| {
+            field(i)
+                .parse::<u64>()
+                .map_err(|_| parse_error(line, format!("malformed {}", what)))
+        };
+        let lifetime = number(lifetime, "valid_lifetime")?;
+        let ends = number(expire, "expire")?;
+        // Commas in names are written as "&#x2c"; names with them are of
+        // no use as labels anyway.
+        let name = hostname.map(field).filter(|h| !h.is_empty());
+        leases.push(Lease {
+            address: field(address)
+                .parse()
+                .map_err(|_| parse_error(line, "malformed address"))?,
+            hostname: name.map(str::to_string),
+            starts: Some(ends.saturating_sub(lifetime)),
+            ends: Some(ends),
+            // State 0 is the default state; 1 declined, 2 reclaimed.
+            active: lifetime > 0 && state.is_none_or(|s| matches!(field(s), "" | "0")),
+        });
+    }
+    Ok(leases)
+}
+
+/// The leases of Kea's `lease4-get-all` or `lease6-get-all` JSON.
+#[cfg(feature = "kea")]
+fn parse_kea_json(text: &str) -> Result<Vec<Lease>, LeaseError> {
+    use serde_json::Value;
+
+    let json_error = |e: serde_json::Error| parse_error(e.line(), e.to_string());
+    let value: Value = serde_json::from_str(text).map_err(json_error)?;
+    // The control agent answers with a list of responses, one per
+    // service.
+    let value = match &value {
+        Value::Array(items) if items.first().is_some_and(|i| i.get("arguments").is_some()) => {
+            &items[0]
+        }
+        value => value,
+    };
+    let list = value
+        .get("arguments")
+        .unwrap_or(value)
+        .get("leases")
+        .or_else(|| value.is_array().then_some(value))
+        .and_then(Value::as_array)
+        .ok_or_else(|| parse_error(1, "no list of leases"))?;
+    let mut leases = Vec::new();
+    for (i, item) in list.iter().enumerate() {
+        let bad = |what: &str| parse_error(1, format!("leases[{}]: malformed {}", i, what));
+        let address = item
+            .get("ip-address")
+            .and_then(Value::as_str)
+            .and_then(|a| a.parse().ok())
+            .ok_or_else(|| bad("ip-address"))?;
+        let lifetime = item
+            .get("valid-lft")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| bad("valid-lft"))?;
+        let cltt = item
+            .get("cltt")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| bad("cltt"))?;
+        let state = item.get("state").and_then(Value::as_u64).unwrap_or(0);
+        leases.push(Lease {
+            address,
+            hostname: item
+                .get("hostname")
+                .and_then(Value::as_str)
+                .filter(|h| !h.is_empty())
+                .map(str::to_string),
+            starts: Some(cltt),
+            ends: Some(cltt + lifetime),
+            active: lifetime > 0 && state == 0,
+        });
+    }
+    Ok(leases)
+}
+
+#[cfg(not(feature = "kea"))]
+fn parse_kea_json(_: &str) -> Result<Vec<Lease>, LeaseError> {
+    Err(parse_error(
+        1,
+        "this build cannot read Kea JSON: it lacks the kea feature",
+    ))
+}
+
+/// The label a client's hostname gives in the zone: its first label,
+/// lowercased, if it is a valid host name label (RFC 952, RFC 1123 §2.1).
+pub fn host_label(hostname: &str) -> Option<String> {
+    let label = hostname.split('.').next()?.to_ascii_lowercase();
+    let valid = !label.is_empty()
+        && label.len() <= 63
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+    valid.then_some(label)
+}
+
+/// Whether the name of `record` holds records of its type, or a CNAME,
+/// other than those in `managed`.
+fn is_taken(zone: &Zone, record: &Record, managed: &[Record]) -> bool {
+    [record.rtype(), RecordType::CNAME].iter().any(|&rtype| {
+        zone.rrset(&record.name, rtype)
+            .unwrap_or(&[])
+            .iter()
+            .any(|rr| {
+                !managed
+                    .iter()
+                    .any(|m| m.name == rr.name && m.rdata == rr.rdata)
+            })
+    })
+}
+
+/// Keeps the records of a DHCP server's leases in a zone.
+///
+/// Each named client with a current lease gets an A or AAAA record in
+/// the zone and, unless [`reverse`](LeaseSync::reverse) turns it off, a
+/// PTR record in the reverse zone the [`Authority`] holds for its address. Every change is
+/// journaled with a new serial, so that secondaries follow by IXFR, and
+/// names holding records the sync did not add are left alone.
+pub struct LeaseSync {
+    authority: Arc<Authority>,
+    zone: DomainName,
+    path: PathBuf,
+    format: LeaseFormat,
+    reverse: bool,
+    max_ttl: u32,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+    state: Mutex<SyncState>,
+    stopped: AtomicBool,
+}
+
+#[derive(Default)]
+struct SyncState {
+    /// The records the sync added and has not removed since.
+    managed: Vec<Record>,
+    /// When the lease file was last read as modified.
+    modified: Option<SystemTime>,
+    /// When the next of the applied leases ends.
+    next_expiry: Option<u64>,
+    last_error: Option<String>,
+}
+
+impl LeaseSync {
+    /// A sync of the leases in `path` into the zone `zone` of
+    /// `authority`, with PTR records in its reverse zones.
+    pub fn new(
+        authority: Arc<Authority>,
+        zone: DomainName,
+        path: impl Into<PathBuf>,
+        format: LeaseFormat,
+    ) -> LeaseSync {
+        LeaseSync {
+            authority,
+            zone,
+            path: path.into(),
+            format,
+            reverse: true,
+            max_ttl: 300,
+            interval: Duration::from_secs(30),
+            clock: clock::system(),
+            state: Mutex::new(SyncState::default()),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether PTR records are kept as well.
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    /// The largest TTL the records are given.
+    pub fn max_ttl(mut self, ttl: u32) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    /// How often [`run`](LeaseSync::run) checks the lease file for
+    /// changes.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn zone(&self) -> &DomainName {
+        &self.zone
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The records currently kept from leases.
+    pub fn records(&self) -> Vec<Record> {
+        self.state.lock().unwrap().managed.clone()
+    }
+
+    /// What went wrong with the last sync, if it failed.
+    pub fn last_error(&self) -> Option<String> {
+        self.state.lock().unwrap().last_error.clone()
+    }
+
+    /// Reads the lease file and applies its leases, returning how many
+    /// records were added or removed.
+    pub fn sync(&self) -> Result<usize, LeaseError> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let text = fs::read_to_string(&self.path)?;
+        let leases = parse_leases(&text, self.format)?;
+        let changed = self.apply(&leases)?;
+        self.state.lock().unwrap().modified = modified;
+        Ok(changed)
+    }
+
+    /// Makes the records match `leases`, returning how many records were
+    /// added or removed. Leases no longer current lose their records.
+    pub fn apply(&self, leases: &[Lease]) -> Result<usize, LeaseError> {
+        let now = self.clock.unix_time();
+        // The last entry for an address is its lease; of the current
+        // leases for one name and family, the latest to start is the
+        // one the name points to.
+        let mut by_address: HashMap<IpAddr, &Lease> = HashMap::new();
+        for lease in leases {
+            by_address.insert(lease.address, lease);
+        }
+        let mut by_name: HashMap<(String, bool), &Lease> = HashMap::new();
+        for lease in by_address.values().filter(|l| l.is_current(now)) {
+            let label = match lease.hostname.as_deref().and_then(host_label) {
+                Some(label) => label,
+                None => continue,
+            };
+            let key = (label, lease.address.is_ipv6());
+            match by_name.get(&key) {
+                Some(held) if (held.starts, held.address) >= (lease.starts, lease.address) => {}
+                _ => {
+                    by_name.insert(key, lease);
+                }
+            }
+        }
+
+        let forward = self
+            .authority
+            .zone(&self.zone)
+            .ok_or_else(|| LeaseError::NoZone(self.zone.clone()))?;
+        let mut state = self.state.lock().unwrap();
+        let mut desired = Vec::new();
+        let mut next_expiry = None;
+        for ((label, _), lease) in &by_name {
+            let name = match self.zone.prepend(label.as_bytes()) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            let ttl = lease.lifetime().map_or(self.max_ttl, |t| {
+                (t / 2).min(u64::from(self.max_ttl)) as u32
+            });
+            let rdata = match lease.address {
+                IpAddr::V4(a) => RData::A(a),
+                IpAddr::V6(a) => RData::Aaaa(a),
+            };
+            let record = Record::new(name.clone(), ttl, rdata);
+            // Static hosts keep their reverse mapping too.
+            if is_taken(&forward.read().unwrap(), &record, &state.managed) {
+                continue;
+            }
+            desired.push(record);
+            if self.reverse {
+                desired.push(Record::new(
+                    reverse_name(lease.address),
+                    ttl,
+                    RData::Ptr(name),
+                ));
+            }
+            if let Some(ends) = lease.ends {
+                next_expiry = Some(next_expiry.map_or(ends, |n: u64| n.min(ends)));
+            }
+        }
+
+        state.next_expiry = next_expiry;
+        let removed: Vec<Record> = state
+            .managed
+            .iter()
+            .filter(|rr| !desired.contains(rr))
+            .cloned()
+            .collect();
+        let added: Vec<Record> = desired
+            .into_iter()
+            .filter(|rr| !state.managed.contains(rr))
+            .collect();
+
+        // One difference per zone touched.
+        let mut changes: Vec<(SharedZone, Vec<Record>, Vec<Record>)> = Vec::new();
+        for (rr, adding) in removed
+            .iter()
+            .map(|rr| (rr, false))
+            .chain(added.iter().map(|rr| (rr, true)))
+        {
+            let zone = if rr.name.is_subdomain_of(&self.zone) {
+                forward.clone()
+            } else {
+                // Addresses without a reverse zone here get no PTR.
+                match self.authority.find(&rr.name) {
+                    Some(zone) => zone,
+                    None => continue,
+                }
+            };
+            let i = match changes.iter().position(|(z, ..)| Arc::ptr_eq(z, &zone)) {
+                Some(i) => i,
+                None => {
+                    changes.push((zone, Vec::new(), Vec::new()));
+                    changes.len() - 1
+                }
+            };
+            if adding {
+                changes[i].2.push(rr.clone());
+            } else {
+                changes[i].1.push(rr.clone());
+            }
+        }
+
+        let mut count = 0;
+        for (zone, deleted, added) in changes {
+            let mut zone = zone.write().unwrap();
+            // Names with records of their own are not taken over.
+            let added: Vec<Record> = added
+                .into_iter()
+                .filter(|rr| !is_taken(&zone, rr, &state.managed))
+                .collect();
+            if deleted.is_empty() && added.is_empty() {
+                continue;
+            }
+            let old_soa = zone
+                .soa()
+                .cloned()
+                .ok_or(LeaseError::Zone(super::Error::SerialMismatch))?;
+            let mut new_soa = old_soa.clone();
+            if let RData::Soa(soa) = &mut new_soa.rdata {
+                soa.serial = soa.serial.wrapping_add(1);
+            }
+            zone.apply(Diff {
+                old_soa,
+                deleted: deleted.clone(),
+                new_soa,
+                added: added.clone(),
+            })?;
+            count += deleted.len() + added.len();
+            state.managed.retain(|rr| !deleted.contains(rr));
+            state.managed.extend(added);
+        }
+        Ok(count)
+    }
+
+    /// Has [`run`](LeaseSync::run) return when it next wakes. The records
+    /// it added stay.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Syncs whenever the lease file changes, a lease ends, or the
+    /// interval passes, until stopped. Run it on a thread of its own.
+    pub fn run(&self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            let (modified, next_expiry) = {
+                let state = self.state.lock().unwrap();
+                (state.modified, state.next_expiry)
+            };
+            let now = self.clock.unix_time();
+            let current = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+            let expired = next_expiry.is_some_and(|e| e <= now);
+            if modified.is_none() || current != modified || expired {
+                let result = self.sync();
+                self.state.lock().unwrap().last_error = result.err().map(|e| e.to_string());
+            }
+            let now = self.clock.unix_time();
+            let until_expiry = self
+                .state
+                .lock()
+                .unwrap()
+                .next_expiry
+                .map(|e| Duration::from_secs(e.saturating_sub(now).max(1)));
+            thread::sleep(until_expiry.map_or(self.interval, |d| d.min(self.interval)));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! The RFC 1034 §4.3.2 lookup, over zone data wherever it is kept: the
+//! in-memory [`Zone`](super::Zone) or a [`Backend`](super::Backend).
+
+use std::borrow::Cow;
+
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordType, Soa};
+
+use super::{Answer, MAX_CNAMES};
+
+/// Read access to the records of one zone.
+pub(super) trait Tree {
+    fn origin(&self) -> &DomainName;
+
+    /// The RRset of `rtype` at `name`, or every record at `name` for ANY;
+    /// empty if there is none.
+    fn rrset(&self, name: &DomainName, rtype: RecordType) -> Cow<'_, [Record]>;
+
+    /// Whether `name` owns records.
+    fn owns_records(&self, name: &DomainName) -> bool;
+
+    /// Whether `name` owns records or has descendants that do (an empty
+    /// non-terminal).
+    fn name_exists(&self, name: &DomainName) -> bool;
+}
+
+pub(super) fn soa<T: Tree + ?Sized>(tree: &T) -> Option<Record> {
+    tree.rrset(tree.origin(), RecordType::SOA).first().cloned()
+}
+
+/// The topmost delegation point below the origin at or above `name`, if
+/// any.
+pub(super) fn find_cut<T: Tree + ?Sized>(tree: &T, name: &DomainName) -> Option<DomainName> {
+    (tree.origin().label_count() + 1..=name.label_count())
+        .map(|n| name.suffix(n))
+        .find(|n| !tree.rrset(n, RecordType::NS).is_empty())
+}
+
+/// The owner of the wildcard that answers for `qname`, if `qname` does not
+/// exist itself.
+pub(super) fn wildcard_owner<T: Tree + ?Sized>(tree: &T, qname: &DomainName) -> Option<DomainName> {
+    if !qname.is_subdomain_of(tree.origin()) || tree.name_exists(qname) {
+        return None;
+    }
+    let mut encloser = qname.parent()?;
+    while !tree.name_exists(&encloser) {
+        encloser = encloser.parent()?;
+    }
+    let owner = encloser.prepend(b"*").ok()?;
+    if tree.owns_records(&owner) {
+        Some(owner)
+    } else {
+        None
+    }
+}
+
+/// Looks up `qname`/`qtype`: exact matches, CNAME restarts within the
+/// zone, referrals from the topmost cut with glue, wildcard synthesis, and
+/// NODATA vs NXDOMAIN, with A/AAAA for NS, MX and SRV targets added to the
+/// additional section.
+pub(super) fn lookup<T: Tree + ?Sized>(tree: &T, qname: &DomainName, qtype: RecordType) -> Answer {
+    let mut answer = Answer {
+        authoritative: true,
+        ..Answer::default()
+    };
+    let mut qname = qname.clone();
+    for _ in 0..=MAX_CNAMES {
+        if !qname.is_subdomain_of(tree.origin()) {
+            // A CNAME left the zone; the client follows it.
+            break;
+        }
+        if let Some(cut) = referral_cut(tree, &qname, qtype) {
+            if answer.answers.is_empty() {
+                answer.authoritative = false;
+            }
+            answer
+                .authority
+                .extend_from_slice(&tree.rrset(&cut, RecordType::NS));
+            add_glue(tree, &mut answer);
+            return answer;
+        }
+        // The name whose records answer, and the name to give them when
+        // they come from a wildcard.
+        let (node, owner) = if tree.owns_records(&qname) {
+            (qname.clone(), None)
+        } else if tree.name_exists(&qname) {
+            add_negative(tree, &mut answer);
+            return answer;
+        } else {
+            match wildcard_owner(tree, &qname) {
+                Some(wildcard) => (wildcard, Some(qname.clone())),
+                None => {
+                    answer.rcode = Rcode::NXDOMAIN;
+                    add_negative(tree, &mut answer);
+                    return answer;
+                }
+            }
+        };
+        let synthesize = |rrset: &[Record]| -> Vec<Record> {
+            rrset
+                .iter()
+                .map(|rr| match &owner {
+                    Some(name) => Record {
+                        name: name.clone(),
+                        ..rr.clone()
+                    },
+                    None => rr.clone(),
+                })
+                .collect()
+        };
+        if qtype != RecordType::CNAME {
+            let cname = tree.rrset(&node, RecordType::CNAME);
+            if !cname.is_empty() {
+                answer.answers.extend(synthesize(&cname));
+                match cname.first().map(|r| &r.rdata) {
+                    Some(RData::Cname(target)) => {
+                        qname = target.clone();
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        let rrset = tree.rrset(&node, qtype);
+        if rrset.is_empty() {
+            add_negative(tree, &mut answer);
+            return answer;
+        }
+        answer.answers.extend(synthesize(&rrset));
+        break;
+    }
+    add_additional(tree, &mut answer);
+    answer
+}
+
+/// The cut to refer a query for `qname` to. The parent side answers DS
+/// queries at the cut itself.
+fn referral_cut<T: Tree + ?Sized>(
+    tree: &T,
+    qname: &DomainName,
+    qtype: RecordType,
+) -> Option<DomainName> {
+    let cut = find_cut(tree, qname)?;
+    if cut == *qname && qtype == RecordType::DS {
+        return find_cut(tree, &qname.parent()?);
+    }
+    Some(cut)
+}
+
+/// Adds the SOA for a negative answer, with its TTL capped by the minimum
+/// field (RFC 2308 §3).
+fn add_negative<T: Tree + ?Sized>(tree: &T, answer: &mut Answer) {
+    if let Some(mut soa) = soa(tree) {
+        if let RData::Soa(Soa { minimum, .. }) = soa.rdata {
+            soa.ttl = soa.ttl.min(minimum);
+        }
+        answer.authority.push(soa);
+    }
+}
+
+/// Adds glue for the NS records in the authority section.
+fn add_glue<T: Tree + ?Sized>(tree: &T, answer: &mut Answer) {
+    let targets: Vec<DomainName> = answer
+        .authority
+        .iter()
+        .filter_map(|rr| match &rr.rdata {
+            RData::Ns(ns) => Some(ns.clone()),
+            _ => None,
+        })
+        .collect();
+    add_addresses(tree, answer, &targets);
+}
+
+/// Adds addresses for the NS, MX and SRV targets in the answer and
+/// authority sections.
+fn add_additional<T: Tree + ?Sized>(tree: &T, answer: &mut Answer) {
+    let targets: Vec<DomainName> = answer
+        .answers
+        .iter()
+        .chain(&answer.authority)
+        .filter_map(|rr| match &rr.rdata {
+            RData::Ns(n) | RData::Mx { exchange: n, .. } | RData::Srv { target: n, .. } => {
+                Some(n.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    add_addresses(tree, answer, &targets);
+}
+
+fn add_addresses<T: Tree + ?Sized>(tree: &T, answer: &mut Answer, targets: &[DomainName]) {
+    for target in targets {
+        if !target.is_subdomain_of(tree.origin()) {
+            continue;
+        }
+        for rtype in [RecordType::A, RecordType::AAAA].iter() {
+            for rr in tree.rrset(target, *rtype).iter() {
+                if !answer.additional.contains(rr) {
+                    answer.additional.push(rr.clone());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,599 @@
+//! Zones compiled into a compact read-only file that is mapped into
+//! memory and served where it lies, so that a zone of millions of
+//! records, a TLD's among them, takes no more heap than the names a query
+//! touches.
+//!
+//! A compiled zone is, with numbers in network byte order:
+//!
+//! - a header: the magic `MAIRUZN\0`, the format version (1), the class
+//!   of the zone and its origin in wire form, in lower case;
+//! - the nodes, one per owner name in canonical order (RFC 4034 §6.1):
+//!   the length of the owner's labels below the origin, those labels
+//!   rightmost first, each after its length and in lower case, then the
+//!   number of records and, for each, its type, TTL, data length and data,
+//!   with the names in the data uncompressed;
+//! - the index: the offset of each node, in 64 bits;
+//! - a trailer: the number of nodes and the offset of the index, in 64
+//!   bits each.
+//!
+//! Owners are kept relative to the origin with their labels reversed, so
+//! a lookup is a binary search over the index comparing labels in place,
+//! and only the records of the node it lands on are decoded.
+//!
+//! Files are written whole, to a temporary file then renamed over the old
+//! one, so that whoever has the old one mapped keeps reading it until
+//! they map the new one. [`compile`] writes one from a [`Zone`]; a
+//! [`MappedBackend`] applies a [`Diff`] by writing the nodes it changes
+//! and copying the others as they are, without decoding them.
+//!
+//! Records are checked against the [`Compliance`] profile the backend was
+//! opened with as they are read, as those of a zone file are when it is
+//! loaded: a file compiled under a lenient profile may hold what a strict
+//! one refuses.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::compliance::Compliance;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+use crate::sys::{self, MappedFile};
+use crate::wire::{self, Decoder, Encoder};
+
+use super::backend::{changed_rrset, changed_rrsets, check_diff};
+use super::{Backend, BackendError, Diff, Zone};
+
+const MAGIC: &[u8; 8] = b"MAIRUZN\0";
+
+const VERSION: u16 = 1;
+
+/// The length of the node count and index offset that end the file.
+const TRAILER_LEN: usize = 16;
+
+/// The labels of a stored owner, rightmost first. A malformed label ends
+/// them.
+struct Labels<'a>(&'a [u8]);
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let (&len, rest) = self.0.split_first()?;
+        match (rest.get(..usize::from(len)), rest.get(usize::from(len)..)) {
+            (Some(label), Some(rest)) => {
+                self.0 = rest;
+                Some(label)
+            }
+            _ => {
+                self.0 = &[];
+                None
+            }
+        }
+    }
+}
+
+/// Compares a stored owner with the labels of a name below the origin,
+/// given leftmost first, in canonical order.
+fn cmp_owner(stored: &[u8], relative: &[Vec<u8>]) -> Ordering {
+    let mut stored = Labels(stored);
+    for label in relative.iter().rev() {
+        let have = match stored.next() {
+            Some(have) => have,
+            None => return Ordering::Less,
+        };
+        match have
+            .iter()
+            .copied()
+            .cmp(label.iter().map(u8::to_ascii_lowercase))
+        {
+            Ordering::Equal => {}
+            ord => return ord,
+        }
+    }
+    match stored.next() {
+        Some(_) => Ordering::Greater,
+        None => Ordering::Equal,
+    }
+}
+
+/// Whether a stored owner is at or below the name `relative` leaves of
+/// the origin.
+fn owner_within(stored: &[u8], relative: &[Vec<u8>]) -> bool {
+    let mut stored = Labels(stored);
+    relative.iter().rev().all(|label| {
+        stored
+            .next()
+            .is_some_and(|have| label.eq_ignore_ascii_case(have))
+    })
+}
+
+/// Encodes the node of `owner`, a name at or below `origin`.
+fn encode_node(
+    origin: &DomainName,
+    owner: &DomainName,
+    records: &[Record],
+) -> Result<Vec<u8>, BackendError> {
+    let relative = &owner.labels()[..owner.label_count() - origin.label_count()];
+    let mut enc = Encoder::uncompressed();
+    enc.u8(relative.iter().map(|l| l.len() + 1).sum::<usize>() as u8);
+    for label in relative.iter().rev() {
+        enc.u8(label.len() as u8);
+        enc.bytes(&label.to_ascii_lowercase());
+    }
+    enc.u32(records.len() as u32);
+    for rr in records {
+        enc.u16(rr.rtype().0);
+        enc.u32(rr.ttl);
+        let at = enc.len();
+        enc.u16(0);
+        let bad = |e: wire::Error| BackendError::Data(format!("{}: {}", rr, e));
+        rr.rdata.encode(&mut enc).map_err(bad)?;
+        let len = enc.len() - at - 2;
+        if len > usize::from(u16::MAX) {
+            return Err(bad(wire::Error::TooLong { offset: enc.len() }));
+        }
+        enc.set_u16(at, len as u16);
+    }
+    Ok(enc.into_bytes())
+}
+
+/// Writes a compiled zone, node by node in canonical order.
+struct Writer<W: Write> {
+    out: W,
+    pos: u64,
+    index: Vec<u64>,
+}
+
+impl<W: Write> Writer<W> {
+    fn new(mut out: W, origin: &DomainName, class: RecordClass) -> io::Result<Writer<W>> {
+        let mut enc = Encoder::uncompressed();
+        enc.bytes(MAGIC);
+        enc.u16(VERSION);
+        enc.u16(class.0);
+        enc.canonical_name(origin);
+        out.write_all(enc.as_bytes())?;
+        Ok(Writer {
+            out,
+            pos: enc.len() as u64,
+            index: Vec::new(),
+        })
+    }
+
+    /// Adds a node as [`encode_node`] or an existing file has it.
+    fn node(&mut self, node: &[u8]) -> io::Result<()> {
+        self.index.push(self.pos);
+        self.out.write_all(node)?;
+        self.pos += node.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let index = self.pos;
+        let mut buf = Vec::with_capacity(self.index.len() * 8 + TRAILER_LEN);
+        for offset in &self.index {
+            buf.extend_from_slice(&offset.to_be_bytes());
+        }
+        buf.extend_from_slice(&(self.index.len() as u64).to_be_bytes());
+        buf.extend_from_slice(&index.to_be_bytes());
+        self.out.write_all(&buf)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Writes a compiled zone to a temporary file beside `path`, with the
+/// nodes `fill` writes, and renames it over `path`.
+fn replace(
+    path: &Path,
+    origin: &DomainName,
+    class: RecordClass,
+    fill: impl FnOnce(&mut Writer<BufWriter<File>>) -> Result<(), BackendError>,
+) -> Result<(), BackendError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let write = || -> Result<(), BackendError> {
+        let file = BufWriter::new(File::create(&temporary)?);
+        let mut writer = Writer::new(file, origin, class)?;
+        fill(&mut writer)?;
+        let file = writer.finish()?;
+        file.get_ref().sync_all()?;
+        Ok(())
+    };
+    let result = write().and_then(|()| Ok(fs::rename(&temporary, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// The records of `zone` in the order a node keeps them: by type.
+fn node_records(zone: &Zone, name: &DomainName) -> Vec<Record> {
+    let mut types = zone.types(name);
+    types.sort_by_key(|t| t.0);
+    types
+        .into_iter()
+        .flat_map(|t| zone.rrset(name, t).unwrap_or(&[]).iter().cloned())
+        .collect()
+}
+
+/// Compiles `zone` into the file at `path`, replacing whatever is there.
+pub fn compile<P: AsRef<Path>>(zone: &Zone, path: P) -> Result<(), BackendError> {
+    let origin = zone.origin();
+    replace(path.as_ref(), origin, zone.class(), |writer| {
+        for name in zone.names() {
+            let records = node_records(zone, name);
+            writer.node(&encode_node(origin, name, &records)?)?;
+        }
+        Ok(())
+    })
+}
+
+/// A compiled zone, mapped.
+struct Image {
+    map: MappedFile,
+    origin: DomainName,
+    class: RecordClass,
+    nodes: usize,
+    index: usize,
+    compliance: Compliance,
+}
+
+/// A node of an [`Image`]: its owner as stored and where its records
+/// start.
+struct Node<'a> {
+    owner: &'a [u8],
+    records: usize,
+}
+
+impl Image {
+    fn open(path: &Path, compliance: Compliance) -> Result<Image, BackendError> {
+        let map = sys::map_file(&File::open(path)?)?;
+        let bad = |what: &str| BackendError::Data(format!("{}: {}", path.display(), what));
+        let data = map.as_slice();
+        if data.len() < MAGIC.len() + 4 + TRAILER_LEN || &data[..MAGIC.len()] != MAGIC {
+            return Err(bad("not a compiled zone"));
+        }
+        let mut dec = Decoder::new(&data[..data.len() - TRAILER_LEN]);
+        dec.seek(MAGIC.len()).map_err(|_| bad("truncated"))?;
+        let header = (|| Ok::<_, wire::Error>((dec.u16()?, dec.u16()?, dec.name()?)))();
+        let (version, class, origin) = header.map_err(|_| bad("truncated header"))?;
+        if version != VERSION {
+            return Err(bad(&format!("unsupported format version {}", version)));
+        }
+        let trailer = &data[data.len() - TRAILER_LEN..];
+        let number = |bytes: &[u8]| {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(bytes);
+            usize::try_from(u64::from_be_bytes(buf)).ok()
+        };
+        let (nodes, index) = match (number(&trailer[..8]), number(&trailer[8..])) {
+            (Some(nodes), Some(index)) => (nodes, index),
+            _ => return Err(bad("truncated")),
+        };
+        let end = nodes.checked_mul(8).and_then(|len| len.checked_add(index));
+        if index < dec.pos() || end != Some(data.len() - TRAILER_LEN) {
+            return Err(bad("truncated"));
+        }
+        Ok(Image {
+            map,
+            origin,
+            class: RecordClass(class),
+            nodes,
+            index,
+            compliance,
+        })
+    }
+
+    fn data(&self) -> &[u8] {
+        self.map.as_slice()
+    }
+
+    fn corrupt(&self, what: impl fmt::Display) -> BackendError {
+        BackendError::Data(format!("zone {}: {}", self.origin, what))
+    }
+
+    /// Where node `i` starts and ends.
+    fn span(&self, i: usize) -> Result<(usize, usize), BackendError> {
+        let offset = |i: usize| {
+            let at = self.index + i * 8;
+            let mut buf = [0; 8];
+            buf.copy_from_slice(&self.data()[at..at + 8]);
+            u64::from_be_bytes(buf) as usize
+        };
+        let start = offset(i);
+        let end = if i + 1 < self.nodes {
+            offset(i + 1)
+        } else {
+            self.index
+        };
+        if start >= end || end > self.index {
+            return Err(self.corrupt(format!("bad offset of node {}", i)));
+        }
+        Ok((start, end))
+    }
+
+    fn node(&self, i: usize) -> Result<Node<'_>, BackendError> {
+        let (start, end) = self.span(i)?;
+        let data = &self.data()[start..end];
+        let len = usize::from(data[0]);
+        match data.get(1..1 + len) {
+            Some(owner) => Ok(Node {
+                owner,
+                records: start + 1 + len,
+            }),
+            None => Err(self.corrupt(format!("bad owner of node {}", i))),
+        }
+    }
+
+    /// The owner of a node.
+    fn owner(&self, node: &Node<'_>) -> Result<DomainName, BackendError> {
+        let mut labels: Vec<&[u8]> = Labels(node.owner).collect();
+        labels.reverse();
+        let origin = self.origin.labels().iter().map(Vec::as_slice);
+        DomainName::from_labels(labels.into_iter().chain(origin)).map_err(|e| self.corrupt(e))
+    }
+
+    /// The labels of `name` below the origin, leftmost first, if it is in
+    /// the zone.
+    fn relative<'n>(&self, name: &'n DomainName) -> Option<&'n [Vec<u8>]> {
+        if !name.is_subdomain_of(&self.origin) {
+            return None;
+        }
+        Some(&name.labels()[..name.label_count() - self.origin.label_count()])
+    }
+
+    /// Finds the node of the name `relative` leaves of the origin, or
+    /// where it would be.
+    fn search(&self, relative: &[Vec<u8>]) -> Result<Result<usize, usize>, BackendError> {
+        let (mut low, mut high) = (0, self.nodes);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match cmp_owner(self.node(mid)?.owner, relative) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(Ok(mid)),
+            }
+        }
+        Ok(Err(low))
+    }
+
+    /// The records of `node`, owned by `owner`, of type `rtype` or of
+    /// every type for ANY.
+    fn records(
+        &self,
+        node: &Node<'_>,
+        owner: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        let mut dec = Decoder::with_compliance(&self.data()[..self.index], self.compliance);
+        let mut records = Vec::new();
+        let mut read = || -> Result<(), wire::Error> {
+            dec.seek(node.records)?;
+            for _ in 0..dec.u32()? {
+                let found = RecordType(dec.u16()?);
+                let ttl = dec.u32()?;
+                let len = usize::from(dec.u16()?);
+                if rtype != RecordType::ANY && found != rtype {
+                    dec.bytes(len)?;
+                    continue;
+                }
+                records.push(Record {
+                    name: owner.clone(),
+                    class: self.class,
+                    ttl,
+                    rdata: RData::decode(found, &mut dec, len)?,
+                });
+            }
+            Ok(())
+        };
+        read().map_err(|e| self.corrupt(format!("records of {}: {}", owner, e)))?;
+        for record in &records {
+            self.compliance
+                .check_record(record)
+                .map_err(|e| self.corrupt(format!("records of {}: {}", owner, e)))?;
+        }
+        Ok(records)
+    }
+
+    fn rrset(&self, name: &DomainName, rtype: RecordType) -> Result<Vec<Record>, BackendError> {
+        let found = match self.relative(name) {
+            Some(relative) => self.search(relative)?,
+            None => return Ok(Vec::new()),
+        };
+        match found {
+            Ok(i) => self.records(&self.node(i)?, &name.to_lowercase(), rtype),
+            Err(_) => Ok(Vec::new()),
+        }
+    }
+}
+
+/// A zone compiled with [`compile`], mapped into memory and served from
+/// there. The backend holds the one zone the file does.
+///
+/// Changes are written as a new file that is then mapped in place of the
+/// old, so they cost a pass over the file; the format suits zones that
+/// are large and change in batches rather than by frequent updates.
+pub struct MappedBackend {
+    path: PathBuf,
+    compliance: Compliance,
+    image: RwLock<Arc<Image>>,
+    /// Held while a new file is written, so that changes apply one at a
+    /// time.
+    writing: Mutex<()>,
+}
+
+impl fmt::Debug for MappedBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let image = self.image();
+        f.debug_struct("MappedBackend")
+            .field("path", &self.path)
+            .field("origin", &image.origin)
+            .field("nodes", &image.nodes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MappedBackend {
+    /// Maps the compiled zone at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedBackend, BackendError> {
+        MappedBackend::open_with(path, &Compliance::default())
+    }
+
+    /// Maps the compiled zone at `path`, refusing to serve records that
+    /// `compliance` does not put up with.
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        compliance: &Compliance,
+    ) -> Result<MappedBackend, BackendError> {
+        let path = path.as_ref().to_path_buf();
+        let image = Image::open(&path, *compliance)?;
+        Ok(MappedBackend {
+            path,
+            compliance: *compliance,
+            image: RwLock::new(Arc::new(image)),
+            writing: Mutex::new(()),
+        })
+    }
+
+    /// The origin of the zone.
+    pub fn origin(&self) -> DomainName {
+        self.image().origin.clone()
+    }
+
+    /// Maps the file again, once it was compiled anew. Queries under way
+    /// finish with the old one.
+    pub fn reload(&self) -> Result<(), BackendError> {
+        let image = Image::open(&self.path, self.compliance)?;
+        *self.image.write().unwrap() = Arc::new(image);
+        Ok(())
+    }
+
+    fn image(&self) -> Arc<Image> {
+        self.image.read().unwrap().clone()
+    }
+
+    /// The image, if it holds `zone`.
+    fn zone(&self, zone: &DomainName) -> Result<Arc<Image>, BackendError> {
+        let image = self.image();
+        if image.origin != *zone {
+            return Err(BackendError::NoZone(zone.clone()));
+        }
+        Ok(image)
+    }
+}
+
+impl Backend for MappedBackend {
+    fn zones(&self) -> Result<Vec<DomainName>, BackendError> {
+        Ok(vec![self.origin()])
+    }
+
+    fn rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        self.zone(zone)?.rrset(name, rtype)
+    }
+
+    fn name_exists(&self, zone: &DomainName, name: &DomainName) -> Result<bool, BackendError> {
+        let image = self.zone(zone)?;
+        let relative = match image.relative(name) {
+            Some(relative) => relative,
+            None => return Ok(false),
+        };
+        // A name's descendants directly follow where it is or would be.
+        match image.search(relative)? {
+            Ok(_) => Ok(true),
+            Err(i) if i < image.nodes => Ok(owner_within(image.node(i)?.owner, relative)),
+            Err(_) => Ok(false),
+        }
+    }
+
+    fn records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError> {
+        let image = self.zone(zone)?;
+        let mut records = Vec::new();
+        for i in 0..image.nodes {
+            let node = image.node(i)?;
+            let owner = image.owner(&node)?;
+            records.extend(image.records(&node, &owner, RecordType::ANY)?);
+        }
+        Ok(records)
+    }
+
+    fn apply(&self, zone: &DomainName, diff: &Diff) -> Result<(), BackendError> {
+        let _writing = self.writing.lock().unwrap();
+        let image = self.zone(zone)?;
+        let soa = image.rrset(zone, RecordType::SOA)?;
+        check_diff(zone, soa.first(), diff)?;
+
+        // The changed nodes as they will be, in canonical order.
+        let mut changed: BTreeMap<DomainName, Vec<Record>> = BTreeMap::new();
+        for (name, rtype) in changed_rrsets(diff) {
+            if image.relative(&name).is_none() {
+                continue;
+            }
+            if !changed.contains_key(&name) {
+                let current = image.rrset(&name, RecordType::ANY)?;
+                changed.insert(name.clone(), current);
+            }
+            let node = changed.get_mut(&name).unwrap();
+            let (current, mut rest): (Vec<Record>, Vec<Record>) =
+                node.drain(..).partition(|rr| rr.rtype() == rtype);
+            rest.extend(changed_rrset(current, diff, &name, rtype));
+            rest.sort_by_key(|rr| rr.rtype().0);
+            *node = rest;
+        }
+
+        let origin = &image.origin;
+        replace(&self.path, origin, image.class, |writer| {
+            let mut changed = changed.into_iter().peekable();
+            let write_changed =
+                |writer: &mut Writer<BufWriter<File>>, name: DomainName, records: Vec<Record>| {
+                    if records.is_empty() {
+                        return Ok(());
+                    }
+                    writer.node(&encode_node(origin, &name, &records)?)?;
+                    Ok::<_, BackendError>(())
+                };
+            for i in 0..image.nodes {
+                let owner = image.node(i)?.owner;
+                let mut replaced = false;
+                while let Some((name, _)) = changed.peek() {
+                    let relative = image.relative(name).unwrap_or(&[]);
+                    match cmp_owner(owner, relative) {
+                        Ordering::Less => break,
+                        ord => {
+                            replaced |= ord == Ordering::Equal;
+                            let (name, records) = changed.next().unwrap();
+                            write_changed(writer, name, records)?;
+                        }
+                    }
+                }
+                if !replaced {
+                    let (start, end) = image.span(i)?;
+                    writer.node(&image.data()[start..end])?;
+                }
+            }
+            for (name, records) in changed {
+                write_changed(writer, name, records)?;
+            }
+            Ok(())
+        })?;
+        self.reload()
+    }
+
+    fn store(&self, zone: &Zone) -> Result<(), BackendError> {
+        let _writing = self.writing.lock().unwrap();
+        self.zone(zone.origin())?;
+        compile(zone, &self.path)?;
+        self.reload()
+    }
+}
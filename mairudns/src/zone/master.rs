@@ -0,0 +1,525 @@
+//! Zone files in the master file format of RFC 1035 §5.
+//!
+//! Supported are `$ORIGIN` and `$TTL` (RFC 2308), relative owner names and
+//! `@`, owners inherited from the previous record, TTL and class in either
+//! order, parentheses, comments, and the generic `\# len hex` data of RFC
+//! 3597 for any type. Types without a dedicated [`RData`] variant can only
+//! be written generically. `$INCLUDE` is not supported.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::compliance::{Compliance, Violation};
+use crate::encoding;
+use crate::name::{self, DomainName};
+use crate::rr::{RData, Record, RecordClass, RecordType, Soa};
+use crate::wire::Decoder;
+
+/// What is wrong with an entry of a zone file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    Name(name::Error),
+    BadTtl,
+    UnknownType,
+    /// Record data that does not fit its type.
+    BadRdata,
+    /// A type whose data can only be given in the generic `\#` form.
+    UnsupportedType(RecordType),
+    /// No TTL given and no `$TTL` or earlier record to take it from.
+    MissingTtl,
+    /// An indented first entry, which has no owner to inherit.
+    MissingOwner,
+    UnbalancedParens,
+    UnterminatedString,
+    BadDirective,
+    /// A record the zone cannot hold.
+    Zone(super::Error),
+    /// A record the compliance profile refuses.
+    Compliance(Violation),
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Name(e) => write!(f, "bad name: {}", e),
+            ParseErrorKind::BadTtl => f.write_str("bad TTL"),
+            ParseErrorKind::UnknownType => f.write_str("unknown record type"),
+            ParseErrorKind::BadRdata => f.write_str("bad record data"),
+            ParseErrorKind::UnsupportedType(t) => {
+                write!(f, "{} data must be given in generic form", t)
+            }
+            ParseErrorKind::MissingTtl => f.write_str("no TTL given"),
+            ParseErrorKind::MissingOwner => f.write_str("no owner name to inherit"),
+            ParseErrorKind::UnbalancedParens => f.write_str("unbalanced parentheses"),
+            ParseErrorKind::UnterminatedString => f.write_str("unterminated string"),
+            ParseErrorKind::BadDirective => f.write_str("bad or unsupported directive"),
+            ParseErrorKind::Zone(e) => write!(f, "{}", e),
+            ParseErrorKind::Compliance(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An error in a zone file. Errors in a token point at it, or into it for
+/// bad names, by line and column; others at the line where their entry
+/// starts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    /// The column, counted in characters from 1, if the error is at one.
+    pub column: Option<usize>,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.kind),
+            None => write!(f, "line {}: {}", self.line, self.kind),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Token {
+    text: String,
+    quoted: bool,
+    /// Where the token starts, its opening quote if quoted.
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: Some(self.column),
+            kind,
+        }
+    }
+
+    /// An error for the name the token holds, at the character it is in.
+    fn name_error(&self, e: name::Error) -> ParseError {
+        let index = e.index();
+        let chars = self.text.get(..index).map_or(index, |t| t.chars().count());
+        ParseError {
+            line: self.line,
+            column: Some(self.column + usize::from(self.quoted) + chars),
+            kind: ParseErrorKind::Name(e),
+        }
+    }
+}
+
+/// The column of byte `at` of `text`, on a line starting at byte `start`.
+fn column(text: &str, start: usize, at: usize) -> usize {
+    text[start..at].chars().count() + 1
+}
+
+/// One logical entry: a line, or several joined by parentheses.
+struct Entry {
+    line: usize,
+    /// Whether the entry starts with blanks and so inherits its owner.
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+fn entries(text: &str) -> Result<Vec<Entry>, ParseError> {
+    let mut entries = Vec::new();
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut depth = 0;
+    // Where the outermost open parenthesis is.
+    let mut opened = (0, 0);
+    let mut entry = Entry {
+        line,
+        indented: false,
+        tokens: Vec::new(),
+    };
+    let mut at_start = true;
+    let mut chars = text.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        if at_start {
+            entry.indented = c == ' ' || c == '\t';
+            at_start = false;
+        }
+        match c {
+            '\n' => {
+                line += 1;
+                line_start = at + 1;
+                if depth == 0 {
+                    if !entry.tokens.is_empty() {
+                        entries.push(entry);
+                    }
+                    entry = Entry {
+                        line,
+                        indented: false,
+                        tokens: Vec::new(),
+                    };
+                    at_start = true;
+                }
+            }
+            ' ' | '\t' | '\r' => {}
+            ';' => {
+                while chars.peek().is_some_and(|&(_, c)| c != '\n') {
+                    chars.next();
+                }
+            }
+            '(' => {
+                if depth == 0 {
+                    opened = (line, column(text, line_start, at));
+                }
+                depth += 1;
+            }
+            ')' => {
+                if depth == 0 {
+                    return Err(ParseError {
+                        line,
+                        column: Some(column(text, line_start, at)),
+                        kind: ParseErrorKind::UnbalancedParens,
+                    });
+                }
+                depth -= 1;
+            }
+            '"' => {
+                let (start_line, start_column) = (line, column(text, line_start, at));
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => {
+                            text.push('\\');
+                            text.extend(chars.next().map(|(_, c)| c));
+                        }
+                        Some((at, c)) => {
+                            if c == '\n' {
+                                line += 1;
+                                line_start = at + 1;
+                            }
+                            text.push(c);
+                        }
+                        None => {
+                            return Err(ParseError {
+                                line: start_line,
+                                column: Some(start_column),
+                                kind: ParseErrorKind::UnterminatedString,
+                            })
+                        }
+                    }
+                }
+                entry.tokens.push(Token {
+                    text,
+                    quoted: true,
+                    line: start_line,
+                    column: start_column,
+                });
+            }
+            c => {
+                let column = column(text, line_start, at);
+                let mut text = c.to_string();
+                if c == '\\' {
+                    text.extend(chars.next().map(|(_, c)| c));
+                }
+                while let Some(&(_, c)) = chars.peek() {
+                    if matches!(c, ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')' | '"') {
+                        break;
+                    }
+                    chars.next();
+                    text.push(c);
+                    if c == '\\' {
+                        text.extend(chars.next().map(|(_, c)| c));
+                    }
+                }
+                entry.tokens.push(Token {
+                    text,
+                    quoted: false,
+                    line,
+                    column,
+                });
+            }
+        }
+    }
+    if depth != 0 {
+        return Err(ParseError {
+            line: opened.0,
+            column: Some(opened.1),
+            kind: ParseErrorKind::UnbalancedParens,
+        });
+    }
+    if !entry.tokens.is_empty() {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Parses a TTL given in seconds or with BIND's unit suffixes, e.g. `1h30m`.
+pub fn parse_ttl(s: &str) -> Option<u32> {
+    if s.is_empty() {
+        return None;
+    }
+    if let Ok(secs) = s.parse() {
+        return Some(secs);
+    }
+    let mut total = 0u32;
+    let mut n: Option<u32> = None;
+    for c in s.chars() {
+        if let Some(d) = c.to_digit(10) {
+            n = Some(n.unwrap_or(0).checked_mul(10)?.checked_add(d)?);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            'w' => 604_800,
+            'd' => 86400,
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        total = total.checked_add(n.take()?.checked_mul(unit)?)?;
+    }
+    if n.is_some() {
+        return None;
+    }
+    Some(total)
+}
+
+/// Decodes the escapes of a character string: `\X` and `\DDD`.
+fn character_string(s: &str) -> Option<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let rest = &bytes[i + 1..];
+        match rest.first() {
+            Some(d) if d.is_ascii_digit() => {
+                let digits = rest.get(..3).filter(|d| d.iter().all(u8::is_ascii_digit))?;
+                let v = digits
+                    .iter()
+                    .fold(0u32, |acc, d| acc * 10 + u32::from(d - b'0'));
+                out.push(u8::try_from(v).ok()?);
+                i += 4;
+            }
+            Some(&c) => {
+                out.push(c);
+                i += 2;
+            }
+            None => return None,
+        }
+    }
+    if out.len() > 255 {
+        return None;
+    }
+    Some(out)
+}
+
+/// The data of a record of type `rtype` from `tokens`, with errors not at
+/// any one token put at `line`.
+fn parse_rdata(
+    rtype: RecordType,
+    tokens: &[Token],
+    origin: &DomainName,
+    line: usize,
+) -> Result<RData, ParseError> {
+    let at_entry = |kind| ParseError {
+        line,
+        column: None,
+        kind,
+    };
+    let bad = |t: &Token| t.error(ParseErrorKind::BadRdata);
+    let name = |t: &Token| DomainName::parse_relative(&t.text, origin).map_err(|e| t.name_error(e));
+    let num = |t: &Token| t.text.parse::<u16>().map_err(|_| bad(t));
+    let ttl = |t: &Token| parse_ttl(&t.text).ok_or_else(|| bad(t));
+    if let Some(generic) = tokens.first().filter(|t| t.text == "\\#" && !t.quoted) {
+        let len: usize = match tokens.get(1) {
+            Some(t) => t.text.parse().map_err(|_| bad(t))?,
+            None => return Err(bad(generic)),
+        };
+        let text: String = tokens[2..].iter().map(|t| t.text.as_str()).collect();
+        let data = encoding::hex_decode(&text).map_err(|_| bad(&tokens[1]))?;
+        if data.len() != len {
+            return Err(bad(&tokens[1]));
+        }
+        return RData::decode(rtype, &mut Decoder::new(&data), len).map_err(|_| bad(generic));
+    }
+    let rdata = match (rtype, tokens) {
+        (RecordType::A, [a]) => RData::A(a.text.parse::<Ipv4Addr>().map_err(|_| bad(a))?),
+        (RecordType::AAAA, [a]) => RData::Aaaa(a.text.parse::<Ipv6Addr>().map_err(|_| bad(a))?),
+        (RecordType::NS, [n]) => RData::Ns(name(n)?),
+        (RecordType::CNAME, [n]) => RData::Cname(name(n)?),
+        (RecordType::PTR, [n]) => RData::Ptr(name(n)?),
+        (RecordType::DNAME, [n]) => RData::Dname(name(n)?),
+        (RecordType::SOA, [mname, rname, serial, refresh, retry, expire, minimum]) => {
+            RData::Soa(Soa {
+                mname: name(mname)?,
+                rname: name(rname)?,
+                serial: serial.text.parse().map_err(|_| bad(serial))?,
+                refresh: ttl(refresh)?,
+                retry: ttl(retry)?,
+                expire: ttl(expire)?,
+                minimum: ttl(minimum)?,
+            })
+        }
+        (RecordType::MX, [preference, exchange]) => RData::Mx {
+            preference: num(preference)?,
+            exchange: name(exchange)?,
+        },
+        (RecordType::TXT, strings) if !strings.is_empty() => RData::Txt(
+            strings
+                .iter()
+                .map(|s| character_string(&s.text).ok_or_else(|| bad(s)))
+                .collect::<Result<_, _>>()?,
+        ),
+        (RecordType::SRV, [priority, weight, port, target]) => RData::Srv {
+            priority: num(priority)?,
+            weight: num(weight)?,
+            port: num(port)?,
+            target: name(target)?,
+        },
+        (
+            RecordType::A
+            | RecordType::AAAA
+            | RecordType::NS
+            | RecordType::CNAME
+            | RecordType::PTR
+            | RecordType::DNAME
+            | RecordType::SOA
+            | RecordType::MX
+            | RecordType::TXT
+            | RecordType::SRV,
+            _,
+        ) => {
+            // Past the fields the type has, the first one too many is at
+            // fault; short of them, the entry.
+            let fields = match rtype {
+                RecordType::SOA => 7,
+                RecordType::SRV => 4,
+                RecordType::MX => 2,
+                _ => 1,
+            };
+            let extra = tokens.get(fields);
+            return Err(extra.map_or_else(|| at_entry(ParseErrorKind::BadRdata), bad));
+        }
+        _ => return Err(at_entry(ParseErrorKind::UnsupportedType(rtype))),
+    };
+    Ok(rdata)
+}
+
+/// Parses the records of a zone file. Relative names are taken relative
+/// to `origin` until a `$ORIGIN` directive changes it.
+pub fn parse_records(text: &str, origin: &DomainName) -> Result<Vec<Record>, ParseError> {
+    parse_records_with(text, origin, &Compliance::default())
+}
+
+/// Like [`parse_records`], refusing the records `compliance` does.
+pub fn parse_records_with(
+    text: &str,
+    origin: &DomainName,
+    compliance: &Compliance,
+) -> Result<Vec<Record>, ParseError> {
+    Ok(parse_lines(text, origin, compliance)?
+        .into_iter()
+        .map(|(_, rr)| rr)
+        .collect())
+}
+
+/// Like [`parse_records_with`], with the line each record starts on.
+pub(super) fn parse_lines(
+    text: &str,
+    origin: &DomainName,
+    compliance: &Compliance,
+) -> Result<Vec<(usize, Record)>, ParseError> {
+    let mut origin = origin.clone();
+    let mut default_ttl = None;
+    let mut last_ttl = None;
+    let mut last_owner: Option<DomainName> = None;
+    let mut last_class = RecordClass::IN;
+    let mut records = Vec::new();
+    for entry in entries(text)? {
+        let line = entry.line;
+        let err = |kind| ParseError {
+            line,
+            column: None,
+            kind,
+        };
+        let tokens = &entry.tokens;
+        let first = &tokens[0];
+        if !entry.indented && !first.quoted && first.text.starts_with('$') {
+            match (first.text.to_ascii_uppercase().as_str(), &tokens[1..]) {
+                ("$ORIGIN", [name]) => {
+                    origin = DomainName::parse_relative(&name.text, &origin)
+                        .map_err(|e| name.name_error(e))?;
+                }
+                ("$TTL", [ttl]) => {
+                    default_ttl = Some(
+                        parse_ttl(&ttl.text).ok_or_else(|| ttl.error(ParseErrorKind::BadTtl))?,
+                    );
+                }
+                _ => return Err(first.error(ParseErrorKind::BadDirective)),
+            }
+            continue;
+        }
+
+        let mut rest = &tokens[..];
+        let owner = if entry.indented {
+            last_owner
+                .clone()
+                .ok_or(err(ParseErrorKind::MissingOwner))?
+        } else {
+            rest = &rest[1..];
+            DomainName::parse_relative(&first.text, &origin).map_err(|e| first.name_error(e))?
+        };
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = rest.first() {
+            if ttl.is_none() {
+                if let Some(t) = parse_ttl(&token.text) {
+                    ttl = Some(t);
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            if class.is_none() {
+                if let Ok(c) = token.text.parse::<RecordClass>() {
+                    class = Some(c);
+                    rest = &rest[1..];
+                    continue;
+                }
+            }
+            break;
+        }
+        let rtype: RecordType = match rest.first() {
+            Some(t) => t
+                .text
+                .parse()
+                .map_err(|_| t.error(ParseErrorKind::UnknownType))?,
+            None => return Err(err(ParseErrorKind::UnknownType)),
+        };
+        let rdata = parse_rdata(rtype, &rest[1..], &origin, line)?;
+        if ttl.is_some() {
+            last_ttl = ttl;
+        }
+        let ttl = ttl
+            .or(default_ttl)
+            .or(last_ttl)
+            .ok_or(err(ParseErrorKind::MissingTtl))?;
+        let class = class.unwrap_or(last_class);
+        last_class = class;
+        last_owner = Some(owner.clone());
+        let record = Record {
+            name: owner,
+            class,
+            ttl,
+            rdata,
+        };
+        compliance
+            .check_record(&record)
+            .map_err(|e| err(ParseErrorKind::Compliance(e)))?;
+        records.push((line, record));
+    }
+    Ok(records)
+}
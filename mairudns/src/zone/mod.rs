@@ -0,0 +1,464 @@
+//! Authoritative zone data and the RFC 1034 §4.3.2 lookup over it.
+//!
+//! Zones are held in memory as [`Zone`]s, or served through a
+//! [`Backend`]: from Redis, from SQLite with the `sqlite` feature, or from
+//! a compiled file mapped into memory.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::compliance::Compliance;
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+
+mod backend;
+mod journal;
+mod leases;
+mod lookup;
+mod mapped;
+mod master;
+mod redis;
+mod secondary;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod template;
+mod update;
+mod zonemd;
+
+pub use self::backend::{Backend, BackendCache, BackendError, CachedBackend};
+pub use self::journal::{Diff, Journal, JOURNAL_LIMIT};
+pub use self::leases::{host_label, parse_leases, Lease, LeaseError, LeaseFormat, LeaseSync};
+pub use self::mapped::{compile, MappedBackend};
+pub use self::master::{parse_records, parse_records_with, parse_ttl, ParseError, ParseErrorKind};
+pub use self::redis::RedisBackend;
+pub(crate) use self::secondary::{request as transfer_request, Connection, TransferEnd};
+pub use self::secondary::{Refresh, Secondary, Status, TransferError};
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteBackend;
+pub use self::template::{Template, TemplateError, Value};
+pub use self::update::{NameMatch, UpdatePolicy, UpdateRule};
+pub use self::zonemd::{Zonemd, ZonemdError};
+
+use self::lookup::Tree;
+
+/// Upper bound on CNAME restarts within one lookup.
+const MAX_CNAMES: usize = 8;
+
+/// Errors produced when changing a zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The record's owner is not at or below the zone origin.
+    OutOfZone,
+    /// The record's class differs from the zone's.
+    WrongClass,
+    /// A difference does not start from the zone's current SOA.
+    SerialMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::OutOfZone => "record is outside the zone",
+            Error::WrongClass => "record class differs from the zone class",
+            Error::SerialMismatch => "difference does not apply to the current serial",
+        })
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Compares SOA serials in RFC 1982 sequence space. Serials exactly half
+/// the space apart are incomparable.
+pub fn serial_cmp(a: u32, b: u32) -> Option<Ordering> {
+    match a.wrapping_sub(b) {
+        0 => Some(Ordering::Equal),
+        d if d < 1 << 31 => Some(Ordering::Greater),
+        d if d > 1 << 31 => Some(Ordering::Less),
+        _ => None,
+    }
+}
+
+/// The records at one owner name, by type.
+type Node = HashMap<RecordType, Vec<Record>>;
+
+/// The data of one zone: every record at or below its origin, including
+/// delegations and glue.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    origin: DomainName,
+    class: RecordClass,
+    /// Kept in canonical order, so that a name's descendants directly
+    /// follow it.
+    nodes: BTreeMap<DomainName, Node>,
+    journal: Journal,
+}
+
+/// The outcome of a lookup, ready to be copied into a response.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Answer {
+    pub rcode: Rcode,
+    /// Whether the zone is authoritative for the answer; false for
+    /// referrals.
+    pub authoritative: bool,
+    pub answers: Vec<Record>,
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+impl Zone {
+    pub fn new(origin: DomainName) -> Zone {
+        Zone {
+            origin,
+            class: RecordClass::IN,
+            nodes: BTreeMap::new(),
+            journal: Journal::default(),
+        }
+    }
+
+    pub fn with_class(origin: DomainName, class: RecordClass) -> Zone {
+        Zone {
+            class,
+            ..Zone::new(origin)
+        }
+    }
+
+    /// Loads a zone from a zone file; see [`parse_records`]. The zone takes
+    /// the class of its first record.
+    pub fn from_master(origin: DomainName, text: &str) -> Result<Zone, ParseError> {
+        Zone::from_master_with(origin, text, &Compliance::default())
+    }
+
+    /// Like [`from_master`](Zone::from_master), refusing the records
+    /// `compliance` does.
+    pub fn from_master_with(
+        origin: DomainName,
+        text: &str,
+        compliance: &Compliance,
+    ) -> Result<Zone, ParseError> {
+        let records = master::parse_lines(text, &origin, compliance)?;
+        let class = records.first().map_or(RecordClass::IN, |(_, rr)| rr.class);
+        let mut zone = Zone::with_class(origin, class);
+        for (line, rr) in records {
+            zone.insert(rr).map_err(|e| ParseError {
+                line,
+                column: None,
+                kind: ParseErrorKind::Zone(e),
+            })?;
+        }
+        Ok(zone)
+    }
+
+    pub fn origin(&self) -> &DomainName {
+        &self.origin
+    }
+
+    pub fn class(&self) -> RecordClass {
+        self.class
+    }
+
+    fn check(&self, record: &Record) -> Result<(), Error> {
+        if !record.name.is_subdomain_of(&self.origin) {
+            return Err(Error::OutOfZone);
+        }
+        if record.class != self.class {
+            return Err(Error::WrongClass);
+        }
+        Ok(())
+    }
+
+    /// Adds a record. Adding a record already present only updates its TTL.
+    pub fn insert(&mut self, record: Record) -> Result<(), Error> {
+        self.check(&record)?;
+        let rrset = self
+            .nodes
+            .entry(record.name.clone())
+            .or_default()
+            .entry(record.rtype())
+            .or_default();
+        match rrset.iter_mut().find(|r| r.rdata == record.rdata) {
+            Some(existing) => existing.ttl = record.ttl,
+            None => rrset.push(record),
+        }
+        Ok(())
+    }
+
+    /// Removes one record, returning whether it was present.
+    pub fn remove(&mut self, record: &Record) -> bool {
+        let node = match self.nodes.get_mut(&record.name) {
+            Some(node) => node,
+            None => return false,
+        };
+        let rrset = match node.get_mut(&record.rtype()) {
+            Some(rrset) => rrset,
+            None => return false,
+        };
+        let before = rrset.len();
+        rrset.retain(|r| r.rdata != record.rdata);
+        let removed = rrset.len() != before;
+        if rrset.is_empty() {
+            node.remove(&record.rtype());
+        }
+        if node.is_empty() {
+            self.nodes.remove(&record.name);
+        }
+        removed
+    }
+
+    /// Removes and returns the RRset of `rtype` at `name`.
+    pub fn remove_rrset(&mut self, name: &DomainName, rtype: RecordType) -> Vec<Record> {
+        let node = match self.nodes.get_mut(name) {
+            Some(node) => node,
+            None => return Vec::new(),
+        };
+        let rrset = node.remove(&rtype).unwrap_or_default();
+        if node.is_empty() {
+            self.nodes.remove(name);
+        }
+        rrset
+    }
+
+    /// Applies a difference whose old SOA is the current one and records
+    /// it in the journal. Nothing changes if any added record is invalid.
+    pub fn apply(&mut self, diff: Diff) -> Result<(), Error> {
+        if self.serial().is_none()
+            || self.serial() != diff.old_serial()
+            || diff.new_serial().is_none()
+        {
+            return Err(Error::SerialMismatch);
+        }
+        self.check(&diff.new_soa)?;
+        for rr in &diff.added {
+            self.check(rr)?;
+        }
+        for rr in &diff.deleted {
+            self.remove(rr);
+        }
+        self.remove_rrset(&self.origin.clone(), RecordType::SOA);
+        self.insert(diff.new_soa.clone())?;
+        for rr in &diff.added {
+            self.insert(rr.clone())?;
+        }
+        self.journal.push(diff);
+        Ok(())
+    }
+
+    /// Replaces the records of `rtype` at `name` with `records`, or
+    /// removes them if `records` is empty, as one change: the serial is
+    /// bumped and the difference journaled, so that secondaries can follow
+    /// it with IXFR. Returns whether anything changed.
+    pub fn replace_rrset(
+        &mut self,
+        name: &DomainName,
+        rtype: RecordType,
+        records: Vec<Record>,
+    ) -> Result<bool, Error> {
+        let old_soa = self.soa().cloned().ok_or(Error::SerialMismatch)?;
+        let current = self.rrset(name, rtype).unwrap_or(&[]);
+        let deleted: Vec<Record> = current
+            .iter()
+            .filter(|rr| !records.contains(rr))
+            .cloned()
+            .collect();
+        let added: Vec<Record> = records
+            .into_iter()
+            .filter(|rr| !current.contains(rr))
+            .collect();
+        if deleted.is_empty() && added.is_empty() {
+            return Ok(false);
+        }
+        let mut new_soa = old_soa.clone();
+        if let RData::Soa(soa) = &mut new_soa.rdata {
+            soa.serial = soa.serial.wrapping_add(1);
+        }
+        self.apply(Diff {
+            old_soa,
+            deleted,
+            new_soa,
+            added,
+        })?;
+        Ok(true)
+    }
+
+    /// The differences that led to the current version.
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn journal_mut(&mut self) -> &mut Journal {
+        &mut self.journal
+    }
+
+    /// The zone as an AXFR response lists it: the SOA, every other
+    /// record, and the SOA again.
+    pub fn transfer_records(&self) -> Vec<Record> {
+        let soa = match self.soa() {
+            Some(soa) => soa.clone(),
+            None => return Vec::new(),
+        };
+        let mut records = Vec::with_capacity(self.len() + 1);
+        records.push(soa.clone());
+        records.extend(
+            self.records()
+                .filter(|rr| rr.rtype() != RecordType::SOA)
+                .cloned(),
+        );
+        records.push(soa);
+        records
+    }
+
+    /// The records of an incremental transfer from `serial` (RFC 1995
+    /// §4), or `None` if the journal cannot provide one.
+    pub fn incremental_records(&self, serial: u32) -> Option<Vec<Record>> {
+        let soa = self.soa()?;
+        let diffs = self.journal.since(serial)?;
+        if diffs.last()?.new_soa != *soa {
+            return None;
+        }
+        let mut records = vec![soa.clone()];
+        for diff in diffs {
+            records.extend(diff.records().cloned());
+        }
+        records.push(soa.clone());
+        Some(records)
+    }
+
+    /// The RRset of `rtype` at `name`, if any.
+    pub fn rrset(&self, name: &DomainName, rtype: RecordType) -> Option<&[Record]> {
+        self.nodes
+            .get(name)
+            .and_then(|n| n.get(&rtype))
+            .map(Vec::as_slice)
+    }
+
+    /// The types of the RRsets at `name`.
+    pub fn types(&self, name: &DomainName) -> Vec<RecordType> {
+        self.nodes
+            .get(name)
+            .map_or_else(Vec::new, |node| node.keys().copied().collect())
+    }
+
+    /// The SOA record at the origin.
+    pub fn soa(&self) -> Option<&Record> {
+        self.rrset(&self.origin, RecordType::SOA)
+            .and_then(|r| r.first())
+    }
+
+    /// The SOA serial, if the zone has an SOA.
+    pub fn serial(&self) -> Option<u32> {
+        match self.soa().map(|r| &r.rdata) {
+            Some(RData::Soa(soa)) => Some(soa.serial),
+            _ => None,
+        }
+    }
+
+    /// Every owner name, in canonical order.
+    pub fn names(&self) -> impl Iterator<Item = &DomainName> {
+        self.nodes.keys()
+    }
+
+    /// Every record, grouped by owner name in canonical order.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.nodes.values().flat_map(|n| n.values().flatten())
+    }
+
+    /// Number of records in the zone.
+    pub fn len(&self) -> usize {
+        self.nodes
+            .values()
+            .map(|n| n.values().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Whether `name` owns records or has descendants that do (an empty
+    /// non-terminal).
+    pub fn name_exists(&self, name: &DomainName) -> bool {
+        self.nodes
+            .range(name.clone()..)
+            .next()
+            .is_some_and(|(n, _)| n.is_subdomain_of(name))
+    }
+
+    /// The last name before `name` in canonical order that owns records of
+    /// `rtype`, wrapping round to the last in the zone: the owner of the
+    /// NSEC or NSEC3 record covering `name`.
+    pub fn preceding(&self, name: &DomainName, rtype: RecordType) -> Option<&DomainName> {
+        self.nodes
+            .range(..name.clone())
+            .rev()
+            .chain(self.nodes.iter().rev())
+            .find(|(_, node)| node.contains_key(&rtype))
+            .map(|(name, _)| name)
+    }
+
+    /// The topmost delegation point below the origin at or above `name`,
+    /// if any.
+    pub fn find_cut(&self, name: &DomainName) -> Option<DomainName> {
+        lookup::find_cut(self, name)
+    }
+
+    /// Looks up `qname`/`qtype` following RFC 1034 §4.3.2: exact matches,
+    /// CNAME restarts within the zone, referrals from the topmost cut with
+    /// glue, wildcard synthesis, and NODATA vs NXDOMAIN, with A/AAAA for
+    /// NS, MX and SRV targets added to the additional section.
+    pub fn lookup(&self, qname: &DomainName, qtype: RecordType) -> Answer {
+        lookup::lookup(self, qname, qtype)
+    }
+
+    /// The owner of the wildcard that answers for `qname`, if `qname` does
+    /// not exist itself.
+    pub fn wildcard_owner(&self, qname: &DomainName) -> Option<DomainName> {
+        lookup::wildcard_owner(self, qname)
+    }
+
+    /// The digest of the zone by ZONEMD's SIMPLE scheme with
+    /// `hash_algorithm`, `None` if that is not supported.
+    pub fn digest(&self, hash_algorithm: u8) -> Option<Vec<u8>> {
+        zonemd::digest(self, hash_algorithm)
+    }
+
+    /// Checks the zone against its ZONEMD records: whether one matched,
+    /// or `false` if there are none this crate can check.
+    pub fn verify_zonemd(&self) -> Result<bool, ZonemdError> {
+        zonemd::verify(self)
+    }
+
+    /// Replaces the ZONEMD records at the origin with digests of the zone
+    /// as it is, one for each of `hash_algorithms`. Signatures over them
+    /// are left to the signer.
+    pub fn set_zonemd(&mut self, hash_algorithms: &[u8]) -> Result<(), ZonemdError> {
+        zonemd::update(self, hash_algorithms)
+    }
+}
+
+impl Tree for Zone {
+    fn origin(&self) -> &DomainName {
+        &self.origin
+    }
+
+    fn rrset(&self, name: &DomainName, rtype: RecordType) -> Cow<'_, [Record]> {
+        match self.nodes.get(name) {
+            Some(node) if rtype == RecordType::ANY => {
+                Cow::Owned(node.values().flatten().cloned().collect())
+            }
+            Some(node) => node
+                .get(&rtype)
+                .map_or(Cow::Borrowed(&[]), |r| Cow::Borrowed(r)),
+            None => Cow::Borrowed(&[]),
+        }
+    }
+
+    fn owns_records(&self, name: &DomainName) -> bool {
+        self.nodes.contains_key(name)
+    }
+
+    fn name_exists(&self, name: &DomainName) -> bool {
+        Zone::name_exists(self, name)
+    }
+}
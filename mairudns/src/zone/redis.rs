@@ -0,0 +1,545 @@
+//! Zones in Redis, spoken to over its RESP protocol.
+//!
+//! Under a key prefix, by default `mairu`, a zone at `example.com.` is
+//! kept as:
+//!
+//! - `mairu:zones`: a set of every zone's origin;
+//! - `mairu:names:example.com.`: a sorted set of the zone's owner names,
+//!   labels reversed (`com.example.www.`) so that a name's descendants
+//!   sort right after it;
+//! - `mairu:node:example.com.:www.example.com.`: a hash from record type
+//!   to the RRset, one `ttl class data` line per record, the data as in a
+//!   zone file.
+//!
+//! Names are stored in lower case. Changes are made in `MULTI`
+//! transactions, watching the zone's SOA, so that two servers changing
+//! the same zone cannot interleave.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::name::DomainName;
+use crate::rr::{Record, RecordType};
+
+use super::backend::{changed_rrset, changed_rrsets, check_diff, parse_record};
+use super::{Backend, BackendError, Diff, Zone};
+
+/// How long to wait for Redis.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A RESP reply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn into_bulk(self) -> Option<Vec<u8>> {
+        match self {
+            Reply::Bulk(bytes) => bytes,
+            _ => None,
+        }
+    }
+
+    fn into_array(self) -> Vec<Reply> {
+        match self {
+            Reply::Array(Some(items)) => items,
+            _ => Vec::new(),
+        }
+    }
+
+    fn into_strings(self) -> Vec<String> {
+        self.into_array()
+            .into_iter()
+            .filter_map(Reply::into_bulk)
+            .map(|b| String::from_utf8_lossy(&b).into_owned())
+            .collect()
+    }
+}
+
+fn protocol(what: &str) -> BackendError {
+    BackendError::Storage(format!("malformed reply: {}", what))
+}
+
+/// One connection to Redis.
+struct Connection {
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn open(addr: SocketAddr, password: Option<&str>) -> Result<Connection, BackendError> {
+        let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let mut conn = Connection {
+            reader: BufReader::new(stream),
+        };
+        if let Some(password) = password {
+            conn.call(&[b"AUTH", password.as_bytes()])?;
+        }
+        Ok(conn)
+    }
+
+    /// Sends several commands at once and reads their replies.
+    fn pipeline(&mut self, commands: &[Vec<Vec<u8>>]) -> Result<Vec<Reply>, BackendError> {
+        let mut buf = Vec::new();
+        for args in commands {
+            buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+            for arg in args {
+                buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+                buf.extend_from_slice(arg);
+                buf.extend_from_slice(b"\r\n");
+            }
+        }
+        self.reader.get_mut().write_all(&buf)?;
+        let mut replies = Vec::with_capacity(commands.len());
+        let mut failure = None;
+        for _ in commands {
+            // Read every reply even after an error, to keep the
+            // connection in step.
+            match self.read()? {
+                Ok(reply) => replies.push(reply),
+                Err(e) => {
+                    failure.get_or_insert(e);
+                    replies.push(Reply::Bulk(None));
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(BackendError::Storage(e)),
+            None => Ok(replies),
+        }
+    }
+
+    fn call(&mut self, args: &[&[u8]]) -> Result<Reply, BackendError> {
+        let command = args.iter().map(|a| a.to_vec()).collect();
+        Ok(self.pipeline(&[command])?.remove(0))
+    }
+
+    fn line(&mut self) -> Result<String, BackendError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        match line.strip_suffix("\r\n") {
+            Some(line) => Ok(line.to_string()),
+            None => Err(protocol("unterminated line")),
+        }
+    }
+
+    /// Reads one reply, or the error Redis answered with.
+    fn read(&mut self) -> Result<Result<Reply, String>, BackendError> {
+        let line = self.line()?;
+        let (kind, rest) = line.split_at(line.len().min(1));
+        let number = || rest.parse::<i64>().map_err(|_| protocol(&line));
+        Ok(Ok(match kind {
+            "+" => Reply::Status(rest.to_string()),
+            "-" => return Ok(Err(rest.to_string())),
+            ":" => Reply::Integer(number()?),
+            "$" => match number()? {
+                len if len < 0 => Reply::Bulk(None),
+                len => {
+                    let mut data = vec![0; len as usize + 2];
+                    io::Read::read_exact(&mut self.reader, &mut data)?;
+                    data.truncate(len as usize);
+                    Reply::Bulk(Some(data))
+                }
+            },
+            "*" => match number()? {
+                len if len < 0 => Reply::Array(None),
+                len => {
+                    let mut items = Vec::with_capacity(len as usize);
+                    for _ in 0..len {
+                        match self.read()? {
+                            Ok(item) => items.push(item),
+                            Err(e) => return Ok(Err(e)),
+                        }
+                    }
+                    Reply::Array(Some(items))
+                }
+            },
+            _ => return Err(protocol(&line)),
+        }))
+    }
+}
+
+/// The labels of `name` in reverse, so that names sort by their
+/// hierarchy: `www.example.com.` becomes `com.example.www.`.
+fn reversed(name: &DomainName) -> String {
+    if name.is_root() {
+        return String::new();
+    }
+    let text = name.to_lowercase().to_string();
+    let mut labels = Vec::new();
+    let mut label = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                label.push(c);
+                label.extend(chars.next());
+            }
+            '.' => labels.push(std::mem::take(&mut label)),
+            _ => label.push(c),
+        }
+    }
+    labels.iter().rev().map(|l| format!("{}.", l)).collect()
+}
+
+/// The inverse of [`reversed`].
+fn unreversed(text: &str) -> Result<DomainName, BackendError> {
+    let mut labels: Vec<&str> = Vec::new();
+    let mut start = 0;
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'.' => {
+                labels.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if labels.is_empty() {
+        return Ok(DomainName::root());
+    }
+    let forward: Vec<&str> = labels.into_iter().rev().collect();
+    format!("{}.", forward.join("."))
+        .parse()
+        .map_err(|_| BackendError::Data(format!("name {:?}", text)))
+}
+
+/// Zones served from Redis.
+pub struct RedisBackend {
+    addr: SocketAddr,
+    password: Option<String>,
+    prefix: String,
+    /// Kept open between requests and reopened after an error.
+    conn: Mutex<Option<Connection>>,
+}
+
+impl fmt::Debug for RedisBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisBackend")
+            .field("addr", &self.addr)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisBackend {
+    /// Serves the zones kept in the Redis server at `addr`. Nothing is
+    /// sent until the first request.
+    pub fn new(addr: SocketAddr) -> RedisBackend {
+        RedisBackend {
+            addr,
+            password: None,
+            prefix: "mairu".into(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    /// Authenticates with `password` on connecting.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Puts every key under `prefix` rather than `mairu`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn zones_key(&self) -> Vec<u8> {
+        format!("{}:zones", self.prefix).into_bytes()
+    }
+
+    fn names_key(&self, zone: &DomainName) -> Vec<u8> {
+        format!("{}:names:{}", self.prefix, zone.to_lowercase()).into_bytes()
+    }
+
+    fn node_key(&self, zone: &DomainName, name: &DomainName) -> Vec<u8> {
+        format!(
+            "{}:node:{}:{}",
+            self.prefix,
+            zone.to_lowercase(),
+            name.to_lowercase()
+        )
+        .into_bytes()
+    }
+
+    /// Runs `f` on the connection, opening it if need be and dropping it
+    /// if `f` fails, since the connection may be out of step.
+    fn with<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, BackendError>,
+    ) -> Result<T, BackendError> {
+        let mut slot = self.conn.lock().unwrap();
+        let conn = match slot.as_mut() {
+            Some(conn) => conn,
+            None => slot.insert(Connection::open(self.addr, self.password.as_deref())?),
+        };
+        let result = f(conn);
+        if result.is_err() {
+            *slot = None;
+        }
+        result
+    }
+
+    /// Checks that the zone exists.
+    fn check_zone(&self, conn: &mut Connection, zone: &DomainName) -> Result<(), BackendError> {
+        let origin = zone.to_lowercase().to_string();
+        match conn.call(&[b"SISMEMBER", &self.zones_key(), origin.as_bytes()])? {
+            Reply::Integer(1) => Ok(()),
+            _ => Err(BackendError::NoZone(zone.clone())),
+        }
+    }
+
+    /// The records of one node, from the fields and values of its hash.
+    fn node(&self, name: &DomainName, fields: Vec<String>) -> Result<Vec<Record>, BackendError> {
+        let mut records = Vec::new();
+        for pair in fields.chunks(2) {
+            if let [rtype, lines] = pair {
+                records.extend(self.rrset_lines(name, rtype, lines)?);
+            }
+        }
+        Ok(records)
+    }
+
+    fn rrset_lines(
+        &self,
+        name: &DomainName,
+        rtype: &str,
+        lines: &str,
+    ) -> Result<Vec<Record>, BackendError> {
+        // Owners take the case they are stored in, as with the other
+        // backends.
+        let owner = name.to_lowercase().to_string();
+        lines
+            .lines()
+            .map(|line| {
+                let mut parts = line.splitn(3, ' ');
+                let ttl = parts.next().and_then(|t| t.parse().ok());
+                let class = parts.next().and_then(|c| c.parse().ok());
+                match (ttl, class, parts.next()) {
+                    (Some(ttl), Some(class), Some(data)) => {
+                        parse_record(&owner, ttl, class, rtype, data)
+                    }
+                    _ => Err(BackendError::Data(line.to_string())),
+                }
+            })
+            .collect()
+    }
+
+    fn read_rrset(
+        &self,
+        conn: &mut Connection,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        let key = self.node_key(zone, name);
+        if rtype == RecordType::ANY {
+            let fields = conn.call(&[b"HGETALL", &key])?.into_strings();
+            return self.node(name, fields);
+        }
+        let rtype_text = rtype.to_string();
+        match conn
+            .call(&[b"HGET", &key, rtype_text.as_bytes()])?
+            .into_bulk()
+        {
+            Some(lines) => self.rrset_lines(name, &rtype_text, &String::from_utf8_lossy(&lines)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The commands that make the RRset of `rtype` at `name` hold
+    /// `records`, given whether the node keeps other types.
+    fn write_rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+        records: &[Record],
+        others: bool,
+    ) -> Vec<Vec<Vec<u8>>> {
+        let key = self.node_key(zone, name);
+        let field = rtype.to_string().into_bytes();
+        let member = reversed(name).into_bytes();
+        if records.is_empty() {
+            let mut commands = vec![vec![b"HDEL".to_vec(), key, field]];
+            if !others {
+                commands.push(vec![b"ZREM".to_vec(), self.names_key(zone), member]);
+            }
+            return commands;
+        }
+        let lines: Vec<String> = records
+            .iter()
+            .map(|rr| format!("{} {} {}", rr.ttl, rr.class, rr.rdata))
+            .collect();
+        vec![
+            vec![b"HSET".to_vec(), key, field, lines.join("\n").into_bytes()],
+            vec![
+                b"ZADD".to_vec(),
+                self.names_key(zone),
+                b"0".to_vec(),
+                member,
+            ],
+        ]
+    }
+
+    /// Runs `commands` in a transaction, failing if a watched key changed.
+    fn transaction(conn: &mut Connection, commands: Vec<Vec<Vec<u8>>>) -> Result<(), BackendError> {
+        let mut all = vec![vec![b"MULTI".to_vec()]];
+        all.extend(commands);
+        all.push(vec![b"EXEC".to_vec()]);
+        match conn.pipeline(&all)?.pop() {
+            Some(Reply::Array(Some(_))) => Ok(()),
+            Some(Reply::Array(None)) => Err(BackendError::Storage(
+                "the zone changed while being written".into(),
+            )),
+            _ => Err(protocol("EXEC")),
+        }
+    }
+}
+
+impl Backend for RedisBackend {
+    fn zones(&self) -> Result<Vec<DomainName>, BackendError> {
+        let origins = self.with(|conn| conn.call(&[b"SMEMBERS", &self.zones_key()]))?;
+        let mut zones = origins
+            .into_strings()
+            .iter()
+            .map(|o| {
+                o.parse()
+                    .map_err(|_| BackendError::Data(format!("zone name {:?}", o)))
+            })
+            .collect::<Result<Vec<DomainName>, _>>()?;
+        zones.sort();
+        Ok(zones)
+    }
+
+    fn rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        self.with(|conn| {
+            let records = self.read_rrset(conn, zone, name, rtype)?;
+            if records.is_empty() && name == zone {
+                // Tell a missing zone from an empty apex.
+                self.check_zone(conn, zone)?;
+            }
+            Ok(records)
+        })
+    }
+
+    fn name_exists(&self, zone: &DomainName, name: &DomainName) -> Result<bool, BackendError> {
+        let start = reversed(name);
+        let mut end = start.clone().into_bytes();
+        end.push(0xff);
+        let mut min = b"[".to_vec();
+        min.extend_from_slice(start.as_bytes());
+        let mut max = b"(".to_vec();
+        max.extend_from_slice(&end);
+        let found = self.with(|conn| {
+            conn.call(&[
+                b"ZRANGEBYLEX",
+                &self.names_key(zone),
+                &min,
+                &max,
+                b"LIMIT",
+                b"0",
+                b"1",
+            ])
+        })?;
+        Ok(!found.into_array().is_empty())
+    }
+
+    fn records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError> {
+        self.with(|conn| {
+            self.check_zone(conn, zone)?;
+            let names = conn
+                .call(&[b"ZRANGE", &self.names_key(zone), b"0", b"-1"])?
+                .into_strings()
+                .iter()
+                .map(|n| unreversed(n))
+                .collect::<Result<Vec<_>, _>>()?;
+            let commands: Vec<Vec<Vec<u8>>> = names
+                .iter()
+                .map(|n| vec![b"HGETALL".to_vec(), self.node_key(zone, n)])
+                .collect();
+            let mut records = Vec::new();
+            for (name, fields) in names.iter().zip(conn.pipeline(&commands)?) {
+                records.extend(self.node(name, fields.into_strings())?);
+            }
+            Ok(records)
+        })
+    }
+
+    fn apply(&self, zone: &DomainName, diff: &Diff) -> Result<(), BackendError> {
+        self.with(|conn| {
+            conn.call(&[b"WATCH", &self.node_key(zone, zone)])?;
+            let soa = self.read_rrset(conn, zone, zone, RecordType::SOA)?;
+            if let Err(e) = check_diff(zone, soa.first(), diff) {
+                conn.call(&[b"UNWATCH"])?;
+                return Err(e);
+            }
+            let mut commands = Vec::new();
+            for (name, rtype) in changed_rrsets(diff) {
+                let node = self.read_rrset(conn, zone, &name, RecordType::ANY)?;
+                let others = node.iter().any(|rr| rr.rtype() != rtype);
+                let current = node.into_iter().filter(|rr| rr.rtype() == rtype).collect();
+                let new = changed_rrset(current, diff, &name, rtype);
+                commands.extend(self.write_rrset(zone, &name, rtype, &new, others));
+            }
+            RedisBackend::transaction(conn, commands)
+        })
+    }
+
+    fn store(&self, zone: &Zone) -> Result<(), BackendError> {
+        let origin = zone.origin();
+        self.with(|conn| {
+            let old = conn
+                .call(&[b"ZRANGE", &self.names_key(origin), b"0", b"-1"])?
+                .into_strings();
+            let mut commands = Vec::new();
+            for name in &old {
+                commands.push(vec![
+                    b"DEL".to_vec(),
+                    self.node_key(origin, &unreversed(name)?),
+                ]);
+            }
+            commands.push(vec![b"DEL".to_vec(), self.names_key(origin)]);
+            for name in zone.names() {
+                let mut rtypes: Vec<RecordType> = Vec::new();
+                for rr in zone.records_at(name) {
+                    if !rtypes.contains(&rr.rtype()) {
+                        rtypes.push(rr.rtype());
+                    }
+                }
+                for rtype in rtypes {
+                    let rrset = zone.rrset(name, rtype).unwrap_or_default();
+                    commands.extend(self.write_rrset(origin, name, rtype, rrset, true));
+                }
+            }
+            commands.push(vec![
+                b"SADD".to_vec(),
+                self.zones_key(),
+                origin.to_lowercase().to_string().into_bytes(),
+            ]);
+            RedisBackend::transaction(conn, commands)
+        })
+    }
+}
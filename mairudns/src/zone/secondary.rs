@@ -0,0 +1,565 @@
+//! Keeping a copy of a zone up to date from its primaries (RFC 1034 §4.3.5,
+//! RFC 1995).
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::client::{self, read_framed, write_framed};
+use crate::message::{Message, Question, Rcode};
+use crate::name::DomainName;
+use crate::random;
+use crate::resolver::Transfer;
+use crate::rr::{RData, Record, RecordType};
+use crate::server::SharedZone;
+use crate::tsig::{self, StreamVerifier};
+use crate::wire;
+
+use super::{serial_cmp, Diff, Error as ZoneError, Zone, ZonemdError};
+
+/// Refresh interval used until the zone's SOA is known.
+const INITIAL_RETRY: Duration = Duration::from_secs(60);
+
+/// Errors from one refresh attempt.
+#[derive(Debug)]
+pub enum TransferError {
+    Client(client::Error),
+    Tsig(tsig::Error),
+    /// The primary answered with an error rcode.
+    Rcode(Rcode),
+    /// The transfer did not have the shape of an AXFR or IXFR response.
+    Malformed(&'static str),
+    /// A transferred record does not belong in the zone.
+    Zone(ZoneError),
+    /// The transferred zone does not match its ZONEMD records.
+    Zonemd(ZonemdError),
+    /// No primaries are configured.
+    NoPrimaries,
+    /// The transfer ran past the limit on its records.
+    TooManyRecords(usize),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Client(e) => write!(f, "transfer failed: {}", e),
+            TransferError::Tsig(e) => write!(f, "TSIG: {}", e),
+            TransferError::Rcode(rcode) => write!(f, "primary responded {}", rcode),
+            TransferError::Malformed(what) => write!(f, "malformed transfer: {}", what),
+            TransferError::Zone(e) => write!(f, "bad record in transfer: {}", e),
+            TransferError::Zonemd(e) => write!(f, "transferred zone rejected: {}", e),
+            TransferError::NoPrimaries => f.write_str("no primaries configured"),
+            TransferError::TooManyRecords(max) => write!(f, "more than {} records", max),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+impl From<client::Error> for TransferError {
+    fn from(e: client::Error) -> TransferError {
+        TransferError::Client(e)
+    }
+}
+
+impl From<io::Error> for TransferError {
+    fn from(e: io::Error) -> TransferError {
+        TransferError::Client(e.into())
+    }
+}
+
+impl From<wire::Error> for TransferError {
+    fn from(e: wire::Error) -> TransferError {
+        TransferError::Client(e.into())
+    }
+}
+
+impl From<tsig::Error> for TransferError {
+    fn from(e: tsig::Error) -> TransferError {
+        TransferError::Tsig(e)
+    }
+}
+
+impl From<ZoneError> for TransferError {
+    fn from(e: ZoneError) -> TransferError {
+        TransferError::Zone(e)
+    }
+}
+
+/// The outcome of a successful refresh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Refresh {
+    /// The primary has nothing newer.
+    UpToDate { serial: u32 },
+    /// A new version was transferred and is now being served.
+    Transferred { serial: u32, incremental: bool },
+}
+
+/// Where a secondary zone stands.
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    /// Serial of the version being served.
+    pub serial: Option<u32>,
+    /// When the primaries were last reached successfully.
+    pub last_refresh: Option<Instant>,
+    /// When the next refresh is due.
+    pub next_refresh: Option<Instant>,
+    /// When the zone stops being served if no primary can be reached.
+    pub expires: Option<Instant>,
+    /// Whether the zone expired and is no longer served.
+    pub expired: bool,
+    pub last_error: Option<String>,
+}
+
+/// A secondary zone: refreshes a [`SharedZone`] from its primaries on the
+/// SOA refresh and retry timers, by IXFR when a version is already held
+/// and by AXFR otherwise, and empties it once the expire interval passes
+/// without contact. An empty zone has no SOA and is answered with
+/// SERVFAIL by [`Authority`](crate::server::Authority).
+///
+/// Transfers and SOA checks use TCP, signed with TSIG when a key is set.
+pub struct Secondary {
+    zone: SharedZone,
+    primaries: Vec<SocketAddr>,
+    key: Option<tsig::Key>,
+    timeout: Duration,
+    verify_zonemd: bool,
+    status: Mutex<Status>,
+    /// Set by [`Secondary::notify`] to cut the current wait short.
+    wake: (Mutex<bool>, Condvar),
+    stopped: AtomicBool,
+}
+
+impl Secondary {
+    /// A secondary keeping `zone` up to date from `primaries`, tried in
+    /// order.
+    pub fn new(zone: SharedZone, primaries: Vec<SocketAddr>) -> Secondary {
+        let serial = zone.read().unwrap().serial();
+        Secondary {
+            zone,
+            primaries,
+            key: None,
+            timeout: Duration::from_secs(30),
+            verify_zonemd: false,
+            status: Mutex::new(Status {
+                serial,
+                ..Status::default()
+            }),
+            wake: (Mutex::new(false), Condvar::new()),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Signs every request with `key` and requires signed responses.
+    pub fn set_key(&mut self, key: tsig::Key) {
+        self.key = Some(key);
+    }
+
+    /// Timeout for connecting and for each read from a primary.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Checks each version transferred against its ZONEMD records and
+    /// keeps serving the one before if it does not match.
+    pub fn set_verify_zonemd(&mut self, verify: bool) {
+        self.verify_zonemd = verify;
+    }
+
+    pub fn zone(&self) -> &SharedZone {
+        &self.zone
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Asks for an immediate refresh, as on receiving a NOTIFY.
+    pub fn notify(&self) {
+        let (flag, cvar) = &self.wake;
+        *flag.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+
+    /// Has [`run`](Secondary::run) return, as when the zone is no longer
+    /// served; a transfer under way is finished first.
+    pub fn stop(&self) {
+        self.stopped.store(true, AtomicOrdering::SeqCst);
+        self.notify();
+    }
+
+    /// Refreshes the zone on its timers until stopped. Run it on a thread
+    /// of its own.
+    pub fn run(&self) {
+        while !self.stopped.load(AtomicOrdering::SeqCst) {
+            let now = Instant::now();
+            let due = self.status().next_refresh.is_none_or(|t| t <= now);
+            if due {
+                self.refresh_and_schedule();
+            }
+            self.wait();
+        }
+    }
+
+    /// Sleeps until the next refresh or expiry is due, or until notified.
+    fn wait(&self) {
+        let status = self.status();
+        let deadline = match (status.next_refresh, status.expires) {
+            (Some(r), Some(e)) if !status.expired => r.min(e),
+            (Some(r), _) => r,
+            _ => Instant::now(),
+        };
+        let (flag, cvar) = &self.wake;
+        let mut notified = flag.lock().unwrap();
+        while !*notified {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                break;
+            }
+            notified = cvar.wait_timeout(notified, left).unwrap().0;
+        }
+        if std::mem::take(&mut *notified) {
+            self.status.lock().unwrap().next_refresh = None;
+        }
+        drop(notified);
+        self.check_expiry();
+    }
+
+    /// Runs one refresh and sets the timers from its outcome.
+    fn refresh_and_schedule(&self) {
+        let result = self.refresh();
+        let now = Instant::now();
+        let timers = match self.zone.read().unwrap().soa().map(|rr| &rr.rdata) {
+            Some(RData::Soa(soa)) => Some((soa.refresh, soa.retry, soa.expire)),
+            _ => None,
+        };
+        let secs = |s: u32| Duration::from_secs(u64::from(s));
+        let mut status = self.status.lock().unwrap();
+        match result {
+            Ok(outcome) => {
+                status.serial = Some(match outcome {
+                    Refresh::UpToDate { serial } | Refresh::Transferred { serial, .. } => serial,
+                });
+                status.last_refresh = Some(now);
+                status.last_error = None;
+                status.expired = false;
+                let (refresh, _, expire) = timers.unwrap_or_default();
+                status.next_refresh = Some(now + secs(refresh));
+                status.expires = Some(now + secs(expire));
+            }
+            Err(e) => {
+                status.last_error = Some(e.to_string());
+                status.next_refresh =
+                    Some(now + timers.map_or(INITIAL_RETRY, |(_, retry, _)| secs(retry)));
+            }
+        }
+    }
+
+    /// Stops serving the zone once its expire interval has passed.
+    fn check_expiry(&self) {
+        let mut status = self.status.lock().unwrap();
+        if status.expired || status.expires.is_none_or(|e| e > Instant::now()) {
+            return;
+        }
+        status.expired = true;
+        status.serial = None;
+        let mut zone = self.zone.write().unwrap();
+        *zone = Zone::with_class(zone.origin().clone(), zone.class());
+    }
+
+    /// Checks each primary's SOA serial in turn and transfers from the
+    /// first one that has a newer version, or reports the zone up to date.
+    pub fn refresh(&self) -> Result<Refresh, TransferError> {
+        let mut last_err = TransferError::NoPrimaries;
+        for &primary in &self.primaries {
+            match self.refresh_from(primary) {
+                Ok(outcome) => return Ok(outcome),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err)
+    }
+
+    /// Checks `primary` and transfers from it if it is ahead.
+    pub fn refresh_from(&self, primary: SocketAddr) -> Result<Refresh, TransferError> {
+        let (origin, current) = {
+            let zone = self.zone.read().unwrap();
+            (zone.origin().clone(), zone.soa().cloned())
+        };
+        let mut conn = Connection::open(primary, self.timeout, self.key.clone())?;
+        let soa = conn.query(&origin, RecordType::SOA)?;
+        let serial = soa
+            .answers
+            .iter()
+            .find_map(|rr| match &rr.rdata {
+                RData::Soa(soa) if rr.name == origin => Some(soa.serial),
+                _ => None,
+            })
+            .ok_or(TransferError::Malformed("no SOA in answer"))?;
+        let held = current.as_ref().and_then(|rr| match &rr.rdata {
+            RData::Soa(soa) => Some(soa.serial),
+            _ => None,
+        });
+        if let Some(held) = held {
+            if serial_cmp(serial, held) != Some(Ordering::Greater) {
+                return Ok(Refresh::UpToDate { serial: held });
+            }
+        }
+        let (qtype, authority) = match current {
+            Some(soa) => (RecordType::IXFR, Some(soa)),
+            None => (RecordType::AXFR, None),
+        };
+        let records = conn.transfer(&origin, qtype, authority, held)?;
+        if records.len() == 1 {
+            // The primary had nothing newer after all.
+            return Ok(Refresh::UpToDate {
+                serial: held.unwrap_or(serial),
+            });
+        }
+        let (zone, incremental) = self.apply(&origin, held, records)?;
+        let serial = zone.serial().ok_or(TransferError::Malformed("no SOA"))?;
+        if self.verify_zonemd {
+            zone.verify_zonemd().map_err(TransferError::Zonemd)?;
+        }
+        *self.zone.write().unwrap() = zone;
+        Ok(Refresh::Transferred {
+            serial,
+            incremental,
+        })
+    }
+
+    /// Builds the new version of the zone from transferred records.
+    fn apply(
+        &self,
+        origin: &DomainName,
+        held: Option<u32>,
+        mut records: Vec<Record>,
+    ) -> Result<(Zone, bool), TransferError> {
+        // Both forms end with a copy of the opening SOA.
+        let last = records.pop().and_then(|rr| match rr.rdata {
+            RData::Soa(soa) => Some(soa.serial),
+            _ => None,
+        });
+        let incremental = records.len() > 1 && records[1].rtype() == RecordType::SOA;
+        let zone = if incremental {
+            let mut zone = self.zone.read().unwrap().clone();
+            if zone.serial() != held {
+                return Err(TransferError::Malformed("zone changed during transfer"));
+            }
+            for diff in parse_diffs(records.drain(1..))? {
+                zone.apply(diff)?;
+            }
+            zone
+        } else {
+            let class = self.zone.read().unwrap().class();
+            let mut zone = Zone::with_class(origin.clone(), class);
+            for rr in records {
+                zone.insert(rr)?;
+            }
+            zone
+        };
+        if zone.serial() != last {
+            return Err(TransferError::Malformed("closing SOA does not match"));
+        }
+        Ok((zone, incremental))
+    }
+}
+
+/// Splits the body of an incremental transfer into its differences.
+fn parse_diffs(records: impl Iterator<Item = Record>) -> Result<Vec<Diff>, TransferError> {
+    let mut diffs: Vec<Diff> = Vec::new();
+    let mut adding = true;
+    for rr in records {
+        let is_soa = rr.rtype() == RecordType::SOA;
+        if is_soa {
+            adding = !adding;
+        }
+        match (is_soa, adding, diffs.last_mut()) {
+            (true, false, _) => diffs.push(Diff {
+                old_soa: rr.clone(),
+                deleted: Vec::new(),
+                new_soa: rr,
+                added: Vec::new(),
+            }),
+            (true, true, Some(diff)) => diff.new_soa = rr,
+            (false, false, Some(diff)) => diff.deleted.push(rr),
+            (false, true, Some(diff)) => diff.added.push(rr),
+            _ => return Err(TransferError::Malformed("IXFR difference without SOA")),
+        }
+    }
+    Ok(diffs)
+}
+
+/// A TCP connection to a primary, carrying several exchanges.
+pub(crate) struct Connection {
+    stream: TcpStream,
+    key: Option<tsig::Key>,
+}
+
+impl Connection {
+    pub(crate) fn open(
+        primary: SocketAddr,
+        timeout: Duration,
+        key: Option<tsig::Key>,
+    ) -> Result<Connection, TransferError> {
+        let stream = TcpStream::connect_timeout(&primary, timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        Ok(Connection { stream, key })
+    }
+
+    /// Sends a query, signed if there is a key, and returns the verifier
+    /// for its response.
+    pub(crate) fn send(
+        &mut self,
+        query: &Message,
+    ) -> Result<Option<StreamVerifier>, TransferError> {
+        match &self.key {
+            Some(key) => {
+                let (wire, mac) = tsig::sign(query, key, None, tsig::now())?;
+                write_framed(&mut self.stream, &wire)?;
+                Ok(Some(StreamVerifier::new(key.clone(), mac)))
+            }
+            None => {
+                write_framed(&mut self.stream, &query.to_wire()?)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads the next response message to `query`.
+    pub(crate) fn recv(
+        &mut self,
+        query: &Message,
+        verifier: &mut Option<StreamVerifier>,
+    ) -> Result<Message, TransferError> {
+        loop {
+            let wire = read_framed(&mut self.stream)?;
+            let resp = match verifier {
+                Some(v) => v.verify(&wire, tsig::now())?,
+                None => Message::from_wire(&wire)?,
+            };
+            if resp.header.id != query.header.id || !resp.header.qr {
+                continue;
+            }
+            // Only the first message of a transfer must echo the question.
+            if !resp.questions.is_empty() && resp.questions != query.questions {
+                continue;
+            }
+            if resp.header.rcode != Rcode::NOERROR {
+                return Err(TransferError::Rcode(resp.header.rcode));
+            }
+            return Ok(resp);
+        }
+    }
+
+    fn query(&mut self, origin: &DomainName, qtype: RecordType) -> Result<Message, TransferError> {
+        let query = request(origin, qtype, None);
+        let mut verifier = self.send(&query)?;
+        let resp = self.recv(&query, &mut verifier)?;
+        if verifier.is_some_and(|v| !v.is_complete()) {
+            return Err(TransferError::Tsig(tsig::Error::Unsigned));
+        }
+        Ok(resp)
+    }
+
+    /// Runs an AXFR or IXFR and returns every record of the response,
+    /// from the opening SOA to the closing one. An IXFR response holding
+    /// only an SOA no newer than `held` is returned as is.
+    fn transfer(
+        self,
+        origin: &DomainName,
+        qtype: RecordType,
+        authority: Option<Record>,
+        held: Option<u32>,
+    ) -> Result<Vec<Record>, TransferError> {
+        Transfer::start(self, request(origin, qtype, authority), held, None)?.collect()
+    }
+}
+
+/// A transfer or SOA query for `origin`.
+pub(crate) fn request(
+    origin: &DomainName,
+    qtype: RecordType,
+    authority: Option<Record>,
+) -> Message {
+    let mut query = Message {
+        questions: vec![Question::new(origin.clone(), qtype)],
+        authority: authority.into_iter().collect(),
+        ..Message::default()
+    };
+    query.header.id = random::u16();
+    query
+}
+
+/// Recognizes the closing SOA of an AXFR or IXFR response.
+#[derive(Default)]
+pub(crate) struct TransferEnd {
+    /// Serial of the version already held, for IXFR.
+    held: Option<u32>,
+    /// Serial of the opening SOA.
+    serial: Option<u32>,
+    count: usize,
+    /// For IXFR: whether the current difference is in its additions, and
+    /// the serial it leads to.
+    incremental: bool,
+    adding: bool,
+    diff_serial: u32,
+}
+
+impl TransferEnd {
+    pub(crate) fn new(held: Option<u32>) -> TransferEnd {
+        TransferEnd {
+            held,
+            ..TransferEnd::default()
+        }
+    }
+
+    /// Takes the next record, and tells whether it closes the transfer.
+    pub(crate) fn push(&mut self, rr: &Record) -> Result<bool, TransferError> {
+        self.count += 1;
+        let soa = match &rr.rdata {
+            RData::Soa(soa) => Some(soa.serial),
+            _ => None,
+        };
+        let serial = match self.serial {
+            Some(serial) => serial,
+            None => {
+                let serial = soa.ok_or(TransferError::Malformed("no opening SOA"))?;
+                self.serial = Some(serial);
+                let newer = self
+                    .held
+                    .is_none_or(|held| serial_cmp(serial, held) == Some(Ordering::Greater));
+                return Ok(!newer);
+            }
+        };
+        let soa = match soa {
+            Some(soa) => soa,
+            None => return Ok(false),
+        };
+        if self.count == 2 {
+            if soa == serial {
+                // An AXFR of a zone that holds only its SOA.
+                return Ok(true);
+            }
+            self.incremental = true;
+            return Ok(false);
+        }
+        if !self.incremental {
+            return Ok(soa == serial);
+        }
+        if self.adding && self.diff_serial == serial {
+            return Ok(true);
+        }
+        if !self.adding {
+            self.diff_serial = soa;
+        }
+        self.adding = !self.adding;
+        Ok(false)
+    }
+}
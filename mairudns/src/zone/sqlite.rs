@@ -0,0 +1,282 @@
+//! Zones in an SQLite database laid out as PowerDNS's generic SQL backends
+//! lay them out, so that a database kept for PowerDNS can be served as it
+//! is.
+//!
+//! Zones are rows of `domains` and records rows of `records`, with owner
+//! names in lower case and without the final dot, and the record data in
+//! zone file syntax in `content`. Disabled records and the empty
+//! non-terminal rows PowerDNS adds (those without a type) are ignored.
+//! Every record is taken to be of class IN.
+
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::name::DomainName;
+use crate::rr::{Record, RecordClass, RecordType};
+
+use super::backend::{changed_rrset, changed_rrsets, check_diff, parse_record};
+use super::{Backend, BackendError, Diff, Zone};
+
+/// The tables, as PowerDNS creates them, for a database that has none.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS domains (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR(255) NOT NULL COLLATE NOCASE,
+    master VARCHAR(128) DEFAULT NULL,
+    last_check INTEGER DEFAULT NULL,
+    type VARCHAR(8) NOT NULL,
+    notified_serial INTEGER DEFAULT NULL,
+    account VARCHAR(40) DEFAULT NULL,
+    options VARCHAR(65535) DEFAULT NULL,
+    catalog VARCHAR(255) DEFAULT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS name_index ON domains(name);
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    domain_id INTEGER DEFAULT NULL,
+    name VARCHAR(255) DEFAULT NULL,
+    type VARCHAR(10) DEFAULT NULL,
+    content VARCHAR(65535) DEFAULT NULL,
+    ttl INTEGER DEFAULT NULL,
+    prio INTEGER DEFAULT NULL,
+    disabled BOOLEAN DEFAULT 0,
+    ordername VARCHAR(255),
+    auth BOOL DEFAULT 1,
+    FOREIGN KEY(domain_id) REFERENCES domains(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+CREATE INDEX IF NOT EXISTS records_lookup_idx ON records(name, type);
+CREATE INDEX IF NOT EXISTS records_lookup_id_idx ON records(domain_id, name, type);
+";
+
+impl From<rusqlite::Error> for BackendError {
+    fn from(e: rusqlite::Error) -> BackendError {
+        BackendError::Storage(e.to_string())
+    }
+}
+
+/// A name as the database stores it.
+fn stored(name: &DomainName) -> String {
+    let text = name.to_lowercase().to_string();
+    match text.strip_suffix('.') {
+        Some(text) => text.to_string(),
+        None => text,
+    }
+}
+
+/// A stored name as the zone file parser reads it.
+fn absolute(name: &str) -> String {
+    if name.is_empty() {
+        ".".into()
+    } else {
+        format!("{}.", name)
+    }
+}
+
+/// Zones served from an SQLite database.
+#[derive(Debug)]
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+    /// Opens the database at `path`, creating it and its tables if need
+    /// be.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteBackend, BackendError> {
+        SqliteBackend::with_connection(Connection::open(path)?)
+    }
+
+    /// Serves the database `conn` is open on, creating its tables if need
+    /// be.
+    pub fn with_connection(conn: Connection) -> Result<SqliteBackend, BackendError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteBackend {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn domain_id(conn: &Connection, zone: &DomainName) -> Result<i64, BackendError> {
+    conn.query_row(
+        "SELECT id FROM domains WHERE name = ?1",
+        params![stored(zone)],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or_else(|| BackendError::NoZone(zone.clone()))
+}
+
+fn read_rows(
+    conn: &Connection,
+    sql: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<Record>, BackendError> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query_map(params, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, u32>(3)?,
+        ))
+    })?;
+    let mut records = Vec::new();
+    for row in rows {
+        let (name, rtype, content, ttl) = row?;
+        records.push(parse_record(
+            &absolute(&name),
+            ttl,
+            RecordClass::IN,
+            &rtype,
+            &content,
+        )?);
+    }
+    Ok(records)
+}
+
+fn rrset(
+    conn: &Connection,
+    id: i64,
+    name: &DomainName,
+    rtype: RecordType,
+) -> Result<Vec<Record>, BackendError> {
+    if rtype == RecordType::ANY {
+        read_rows(
+            conn,
+            "SELECT name, type, content, ttl FROM records \
+             WHERE domain_id = ?1 AND name = ?2 AND type IS NOT NULL AND disabled = 0",
+            &[&id, &stored(name)],
+        )
+    } else {
+        read_rows(
+            conn,
+            "SELECT name, type, content, ttl FROM records \
+             WHERE domain_id = ?1 AND name = ?2 AND type = ?3 AND disabled = 0",
+            &[&id, &stored(name), &rtype.to_string()],
+        )
+    }
+}
+
+fn insert(conn: &Connection, id: i64, rr: &Record) -> Result<(), BackendError> {
+    conn.prepare_cached(
+        "INSERT INTO records (domain_id, name, type, content, ttl, prio, disabled, auth) \
+         VALUES (?1, ?2, ?3, ?4, ?5, 0, 0, 1)",
+    )?
+    .execute(params![
+        id,
+        stored(&rr.name),
+        rr.rtype().to_string(),
+        rr.rdata.to_string(),
+        rr.ttl
+    ])?;
+    Ok(())
+}
+
+impl Backend for SqliteBackend {
+    fn zones(&self) -> Result<Vec<DomainName>, BackendError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare_cached("SELECT name FROM domains ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut zones = Vec::new();
+        for name in names {
+            let name = absolute(&name?);
+            zones.push(
+                name.parse()
+                    .map_err(|_| BackendError::Data(format!("zone name {:?}", name)))?,
+            );
+        }
+        Ok(zones)
+    }
+
+    fn rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        let conn = self.conn.lock().unwrap();
+        let id = domain_id(&conn, zone)?;
+        rrset(&conn, id, name, rtype)
+    }
+
+    fn name_exists(&self, zone: &DomainName, name: &DomainName) -> Result<bool, BackendError> {
+        let conn = self.conn.lock().unwrap();
+        let id = domain_id(&conn, zone)?;
+        let exact = stored(name);
+        let below = if exact.is_empty() {
+            "%".to_string()
+        } else {
+            let escaped = exact
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%.{}", escaped)
+        };
+        let found = conn
+            .prepare_cached(
+                "SELECT 1 FROM records \
+                 WHERE domain_id = ?1 AND type IS NOT NULL AND disabled = 0 \
+                 AND (name = ?2 OR name LIKE ?3 ESCAPE '\\') LIMIT 1",
+            )?
+            .query_row(params![id, exact, below], |_| Ok(()))
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    fn records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError> {
+        let conn = self.conn.lock().unwrap();
+        let id = domain_id(&conn, zone)?;
+        read_rows(
+            &conn,
+            "SELECT name, type, content, ttl FROM records \
+             WHERE domain_id = ?1 AND type IS NOT NULL AND disabled = 0 ORDER BY name, type",
+            &[&id],
+        )
+    }
+
+    fn apply(&self, zone: &DomainName, diff: &Diff) -> Result<(), BackendError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let id = domain_id(&tx, zone)?;
+        let soa = rrset(&tx, id, zone, RecordType::SOA)?;
+        check_diff(zone, soa.first(), diff)?;
+        for (name, rtype) in changed_rrsets(diff) {
+            let current = rrset(&tx, id, &name, rtype)?;
+            let new = changed_rrset(current, diff, &name, rtype);
+            tx.prepare_cached(
+                "DELETE FROM records WHERE domain_id = ?1 AND name = ?2 AND type = ?3",
+            )?
+            .execute(params![id, stored(&name), rtype.to_string()])?;
+            for rr in &new {
+                insert(&tx, id, rr)?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn store(&self, zone: &Zone) -> Result<(), BackendError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let id = match domain_id(&tx, zone.origin()) {
+            Ok(id) => {
+                tx.execute("DELETE FROM records WHERE domain_id = ?1", params![id])?;
+                id
+            }
+            Err(BackendError::NoZone(_)) => {
+                tx.execute(
+                    "INSERT INTO domains (name, type) VALUES (?1, 'NATIVE')",
+                    params![stored(zone.origin())],
+                )?;
+                tx.last_insert_rowid()
+            }
+            Err(e) => return Err(e),
+        };
+        for rr in zone.records() {
+            insert(&tx, id, rr)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+//! Record templates: records stamped out over ranges of counters, like
+//! BIND's `$GENERATE` but with named variables, several ranges and
+//! address arithmetic.
+//!
+//! A [`Template`] is an owner name and record data in zone file syntax
+//! with `${...}` expressions in them. An expression adds and subtracts
+//! variables and integers, as in `${i}` or `${base+i-1}`; adding an
+//! integer to an address offsets the address.
+//! After a colon comes the width to zero-pad integers to, and after
+//! another the base: `d`, `x`, `X` or `o`. So `host-${i:3}` gives
+//! `host-007`, and with `net` set to `2001:db8::` `${net+i}` gives
+//! `2001:db8::7`. `$$` is a literal `$`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+
+use super::{parse_records, Error, ParseError, Zone};
+
+/// The most records one template may expand to, against mistakes such as
+/// a range too many digits long.
+const MAX_RECORDS: u64 = 1_000_000;
+
+/// Errors of expanding a template.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// An expression that does not parse.
+    Syntax(String),
+    UnknownVariable(String),
+    /// An operation the values do not support, such as adding two
+    /// addresses.
+    BadOperands(String),
+    /// A result out of range, such as an address past the end of its
+    /// family.
+    Overflow(String),
+    /// More records than a template may expand to.
+    TooMany(u64),
+    /// An expanded record the zone file parser rejects, with the values
+    /// it was expanded for.
+    Record {
+        bindings: String,
+        error: ParseError,
+    },
+    /// An expanded record the zone cannot hold.
+    Zone(Error),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Syntax(expr) => write!(f, "bad expression {:?}", expr),
+            TemplateError::UnknownVariable(name) => write!(f, "unknown variable {:?}", name),
+            TemplateError::BadOperands(expr) => write!(f, "cannot evaluate {:?}", expr),
+            TemplateError::Overflow(expr) => write!(f, "{:?} is out of range", expr),
+            TemplateError::TooMany(n) => write!(
+                f,
+                "template expands to {} records, more than {}",
+                n, MAX_RECORDS
+            ),
+            TemplateError::Record { bindings, error } => write!(f, "with {}: {}", bindings, error),
+            TemplateError::Zone(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// The value of a template variable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Addr(IpAddr),
+    Text(String),
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Value {
+        Value::Int(n)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Value {
+        Value::Int(i64::from(n))
+    }
+}
+
+impl From<IpAddr> for Value {
+    fn from(addr: IpAddr) -> Value {
+        Value::Addr(addr)
+    }
+}
+
+impl From<Ipv4Addr> for Value {
+    fn from(addr: Ipv4Addr) -> Value {
+        Value::Addr(IpAddr::V4(addr))
+    }
+}
+
+impl From<Ipv6Addr> for Value {
+    fn from(addr: Ipv6Addr) -> Value {
+        Value::Addr(IpAddr::V6(addr))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Value {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Value {
+        Value::Text(text)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Addr(a) => write!(f, "{}", a),
+            Value::Text(t) => f.write_str(t),
+        }
+    }
+}
+
+/// A counter a template is expanded over: `start`, then every `step`
+/// up to and including `end`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Range {
+    name: String,
+    start: i64,
+    end: i64,
+    step: i64,
+}
+
+impl Range {
+    fn len(&self) -> u64 {
+        if self.end < self.start {
+            return 0;
+        }
+        ((self.end - self.start) / self.step) as u64 + 1
+    }
+}
+
+/// A parameterized record, expanded into one record for each combination
+/// of its ranges' values, the first range varying slowest.
+///
+/// ```
+/// use mairudns::rr::RecordType;
+/// use mairudns::zone::Template;
+///
+/// let origin = "example.".parse().unwrap();
+/// let records = Template::new("host-${i:3}", RecordType::A, "${net+i}")
+///     .var("net", "192.0.2.0".parse::<std::net::Ipv4Addr>().unwrap())
+///     .range("i", 1, 3)
+///     .ttl(300)
+///     .expand(&origin)
+///     .unwrap();
+/// assert_eq!(records[2].to_string(), "host-003.example.\t300\tIN\tA\t192.0.2.3");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    owner: String,
+    rtype: RecordType,
+    rdata: String,
+    ttl: Option<u32>,
+    class: RecordClass,
+    vars: HashMap<String, Value>,
+    ranges: Vec<Range>,
+}
+
+impl Template {
+    /// A template for records of `rtype` named `owner`, relative to the
+    /// origin unless it ends in a dot, with data `rdata`.
+    pub fn new(owner: &str, rtype: RecordType, rdata: &str) -> Template {
+        Template {
+            owner: owner.to_string(),
+            rtype,
+            rdata: rdata.to_string(),
+            ttl: None,
+            class: RecordClass::IN,
+            vars: HashMap::new(),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// The TTL of the records; without one, they get the zone's minimum
+    /// when applied to a zone and an hour otherwise.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn class(mut self, class: RecordClass) -> Self {
+        self.class = class;
+        self
+    }
+
+    /// Sets a variable that does not vary.
+    pub fn var(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.vars.insert(name.to_string(), value.into());
+        self
+    }
+
+    /// Counts `name` from `start` to `end`, both included.
+    pub fn range(self, name: &str, start: i64, end: i64) -> Self {
+        self.range_step(name, start, end, 1)
+    }
+
+    /// Counts `name` from `start` to `end` by `step`, which must be
+    /// positive.
+    pub fn range_step(mut self, name: &str, start: i64, end: i64, step: i64) -> Self {
+        self.ranges.push(Range {
+            name: name.to_string(),
+            start,
+            end,
+            step: step.max(1),
+        });
+        self
+    }
+
+    /// How many records the template expands to.
+    pub fn count(&self) -> u64 {
+        self.ranges
+            .iter()
+            .try_fold(1u64, |n, r| n.checked_mul(r.len()))
+            .unwrap_or(u64::MAX)
+    }
+
+    /// The records the template expands to, with relative owner names
+    /// under `origin`.
+    pub fn expand(&self, origin: &DomainName) -> Result<Vec<Record>, TemplateError> {
+        self.expand_with_ttl(origin, self.ttl.unwrap_or(3600))
+    }
+
+    fn expand_with_ttl(&self, origin: &DomainName, ttl: u32) -> Result<Vec<Record>, TemplateError> {
+        let count = self.count();
+        if count > MAX_RECORDS {
+            return Err(TemplateError::TooMany(count));
+        }
+        let mut records = Vec::with_capacity(count as usize);
+        let mut bindings = self.vars.clone();
+        let mut counters: Vec<i64> = self.ranges.iter().map(|r| r.start).collect();
+        for _ in 0..count {
+            for (range, &value) in self.ranges.iter().zip(&counters) {
+                bindings.insert(range.name.clone(), Value::Int(value));
+            }
+            let owner = substitute(&self.owner, &bindings)?;
+            let rdata = substitute(&self.rdata, &bindings)?;
+            let line = format!("{} {} {} {} {}", owner, ttl, self.class, self.rtype, rdata);
+            let parsed = parse_records(&line, origin).map_err(|error| TemplateError::Record {
+                bindings: self.describe(&counters),
+                error,
+            })?;
+            records.extend(parsed);
+            // Advances the counters, the last range fastest.
+            for (range, value) in self.ranges.iter().zip(counters.iter_mut()).rev() {
+                if *value + range.step <= range.end {
+                    *value += range.step;
+                    break;
+                }
+                *value = range.start;
+            }
+        }
+        Ok(records)
+    }
+
+    /// Adds the records the template expands to to `zone`, and returns
+    /// how many there were.
+    pub fn apply(&self, zone: &mut Zone) -> Result<usize, TemplateError> {
+        let minimum = zone.soa().and_then(|soa| match &soa.rdata {
+            RData::Soa(soa) => Some(soa.minimum),
+            _ => None,
+        });
+        let ttl = self.ttl.or(minimum).unwrap_or(3600);
+        let records = self.expand_with_ttl(zone.origin(), ttl)?;
+        let count = records.len();
+        for rr in records {
+            zone.insert(rr).map_err(TemplateError::Zone)?;
+        }
+        Ok(count)
+    }
+
+    fn describe(&self, counters: &[i64]) -> String {
+        let values: Vec<String> = self
+            .ranges
+            .iter()
+            .zip(counters)
+            .map(|(r, v)| format!("{}={}", r.name, v))
+            .collect();
+        values.join(", ")
+    }
+}
+
+/// `text` with every `${...}` replaced by its value.
+fn substitute(text: &str, vars: &HashMap<String, Value>) -> Result<String, TemplateError> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let body = rest
+            .strip_prefix("${")
+            .and_then(|r| r.find('}').map(|end| &r[..end]))
+            .ok_or_else(|| TemplateError::Syntax(rest.to_string()))?;
+        out.push_str(&evaluate(body, vars)?);
+        rest = &rest[body.len() + 3..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The text of the expression `expr`: a sum, then an optional width and
+/// base.
+fn evaluate(expr: &str, vars: &HashMap<String, Value>) -> Result<String, TemplateError> {
+    let syntax = || TemplateError::Syntax(expr.to_string());
+    let mut parts = expr.split(':');
+    let sum = parts.next().unwrap_or("").trim();
+    let width: usize = match parts.next() {
+        Some(w) => w.trim().parse().map_err(|_| syntax())?,
+        None => 0,
+    };
+    let base = parts.next().map(str::trim).unwrap_or("d");
+    if parts.next().is_some() || sum.is_empty() {
+        return Err(syntax());
+    }
+
+    let mut value: Option<Value> = None;
+    let mut negate = false;
+    let mut term_start = 0;
+    let bytes = sum.as_bytes();
+    for i in 0..=bytes.len() {
+        let end = i == bytes.len();
+        if !end && bytes[i] != b'+' && bytes[i] != b'-' {
+            continue;
+        }
+        let term = sum[term_start..i].trim();
+        let term = match term.parse::<i64>() {
+            Ok(n) => Value::Int(n),
+            Err(_) if term.is_empty() => return Err(syntax()),
+            Err(_) => vars
+                .get(term)
+                .cloned()
+                .ok_or_else(|| TemplateError::UnknownVariable(term.to_string()))?,
+        };
+        value = Some(match value {
+            None if negate => add(Value::Int(0), term, true, expr)?,
+            None => term,
+            Some(acc) => add(acc, term, negate, expr)?,
+        });
+        if !end {
+            negate = bytes[i] == b'-';
+        }
+        term_start = i + 1;
+    }
+
+    match value.ok_or_else(syntax)? {
+        Value::Int(n) => {
+            let digits = match base {
+                "d" => n.unsigned_abs().to_string(),
+                "x" => format!("{:x}", n.unsigned_abs()),
+                "X" => format!("{:X}", n.unsigned_abs()),
+                "o" => format!("{:o}", n.unsigned_abs()),
+                _ => return Err(syntax()),
+            };
+            let sign = if n < 0 { "-" } else { "" };
+            Ok(format!("{}{:0>width$}", sign, digits, width = width))
+        }
+        other if width == 0 && base == "d" => Ok(other.to_string()),
+        _ => Err(TemplateError::BadOperands(expr.to_string())),
+    }
+}
+
+/// `a` plus or minus `b`.
+fn add(a: Value, b: Value, negate: bool, expr: &str) -> Result<Value, TemplateError> {
+    let overflow = || TemplateError::Overflow(expr.to_string());
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => {
+            let sum = if negate {
+                a.checked_sub(b)
+            } else {
+                a.checked_add(b)
+            };
+            sum.map(Value::Int).ok_or_else(overflow)
+        }
+        // An address may be offset, but not subtracted.
+        (Value::Int(_), Value::Addr(_)) if negate => {
+            Err(TemplateError::BadOperands(expr.to_string()))
+        }
+        (Value::Addr(IpAddr::V4(a)), Value::Int(n))
+        | (Value::Int(n), Value::Addr(IpAddr::V4(a)))
+            if !negate || n >= 0 =>
+        {
+            let n = if negate { -n } else { n };
+            let sum = i64::from(u32::from(a)) + n;
+            if !(0..=i64::from(u32::MAX)).contains(&sum) {
+                return Err(overflow());
+            }
+            Ok(Value::Addr(IpAddr::V4(Ipv4Addr::from(sum as u32))))
+        }
+        (Value::Addr(IpAddr::V6(a)), Value::Int(n))
+        | (Value::Int(n), Value::Addr(IpAddr::V6(a))) => {
+            let n = if negate {
+                -i128::from(n)
+            } else {
+                i128::from(n)
+            };
+            let base = u128::from(a);
+            let sum = if n < 0 {
+                base.checked_sub(n.unsigned_abs())
+            } else {
+                base.checked_add(n as u128)
+            };
+            sum.map(|s| Value::Addr(IpAddr::V6(Ipv6Addr::from(s))))
+                .ok_or_else(overflow)
+        }
+        _ => Err(TemplateError::BadOperands(expr.to_string())),
+    }
+}
@@ -0,0 +1,305 @@
+//! Dynamic update (RFC 2136): prerequisites, update operations and the
+//! policies deciding which key may change what.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::message::Rcode;
+use crate::name::DomainName;
+use crate::rr::{RData, Record, RecordClass, RecordType};
+
+use super::{serial_cmp, Diff, Zone};
+
+/// Which owner names an [`UpdateRule`] covers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameMatch {
+    /// Exactly this name.
+    Name(DomainName),
+    /// This name and everything below it.
+    Subdomain(DomainName),
+    /// Any name in the zone.
+    Zone,
+    /// The name of the key the update was signed with, as for hosts
+    /// updating their own records.
+    KeyName,
+}
+
+/// Allows updates signed with `key` to names matching `names`, for the
+/// listed record types or, if `types` is empty, for any type but SOA.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateRule {
+    pub key: DomainName,
+    pub names: NameMatch,
+    pub types: Vec<RecordType>,
+}
+
+impl UpdateRule {
+    fn allows(&self, key: &DomainName, name: &DomainName, rtype: RecordType) -> bool {
+        let name_ok = match &self.names {
+            NameMatch::Name(n) => name == n,
+            NameMatch::Subdomain(n) => name.is_subdomain_of(n),
+            NameMatch::Zone => true,
+            NameMatch::KeyName => name == key,
+        };
+        let type_ok = if self.types.is_empty() {
+            rtype != RecordType::SOA
+        } else {
+            self.types.contains(&rtype)
+        };
+        self.key == *key && name_ok && type_ok
+    }
+}
+
+/// The rules for one zone. An update is accepted only if every record in
+/// its update section is allowed by some rule; an empty policy refuses
+/// everything. Deleting all RRsets at a name (type ANY) needs a rule that
+/// allows any type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpdatePolicy {
+    pub rules: Vec<UpdateRule>,
+}
+
+impl UpdatePolicy {
+    pub fn new() -> UpdatePolicy {
+        UpdatePolicy::default()
+    }
+
+    /// Adds a rule.
+    pub fn grant(mut self, key: DomainName, names: NameMatch, types: Vec<RecordType>) -> Self {
+        self.rules.push(UpdateRule { key, names, types });
+        self
+    }
+
+    /// Whether `key` may make every change in `updates`.
+    pub fn allows(&self, key: &DomainName, updates: &[Record]) -> bool {
+        updates.iter().all(|rr| {
+            self.rules.iter().any(|rule| {
+                if rr.rtype() == RecordType::ANY {
+                    rule.types.is_empty() && rule.allows(key, &rr.name, RecordType::A)
+                } else {
+                    rule.allows(key, &rr.name, rr.rtype())
+                }
+            })
+        })
+    }
+}
+
+impl Zone {
+    /// The records owned by `name`.
+    pub fn records_at<'a>(&'a self, name: &DomainName) -> impl Iterator<Item = &'a Record> + 'a {
+        self.nodes
+            .get(name)
+            .into_iter()
+            .flat_map(|node| node.values().flatten())
+    }
+
+    /// Checks the prerequisite section of an update (RFC 2136 §3.2).
+    pub fn check_prerequisites(&self, prerequisites: &[Record]) -> Result<(), Rcode> {
+        let mut value_dependent: HashMap<(DomainName, RecordType), Vec<&Record>> = HashMap::new();
+        for rr in prerequisites {
+            if rr.ttl != 0 {
+                return Err(Rcode::FORMERR);
+            }
+            if !rr.name.is_subdomain_of(&self.origin) {
+                return Err(Rcode::NOTZONE);
+            }
+            let in_use = self.nodes.contains_key(&rr.name);
+            let rtype = rr.rtype();
+            let empty = is_empty_rdata(&rr.rdata);
+            match rr.class {
+                RecordClass::ANY if !empty => return Err(Rcode::FORMERR),
+                RecordClass::ANY if rtype == RecordType::ANY => {
+                    if !in_use {
+                        return Err(Rcode::NXDOMAIN);
+                    }
+                }
+                RecordClass::ANY => {
+                    if self.rrset(&rr.name, rtype).is_none() {
+                        return Err(Rcode::NXRRSET);
+                    }
+                }
+                RecordClass::NONE if !empty => return Err(Rcode::FORMERR),
+                RecordClass::NONE if rtype == RecordType::ANY => {
+                    if in_use {
+                        return Err(Rcode::YXDOMAIN);
+                    }
+                }
+                RecordClass::NONE => {
+                    if self.rrset(&rr.name, rtype).is_some() {
+                        return Err(Rcode::YXRRSET);
+                    }
+                }
+                class if class == self.class => value_dependent
+                    .entry((rr.name.clone(), rtype))
+                    .or_default()
+                    .push(rr),
+                _ => return Err(Rcode::FORMERR),
+            }
+        }
+        for ((name, rtype), expected) in value_dependent {
+            // Compared as sets of data, ignoring TTLs.
+            let held = self.rrset(&name, rtype).unwrap_or_default();
+            let same = held
+                .iter()
+                .all(|h| expected.iter().any(|e| e.rdata == h.rdata))
+                && expected
+                    .iter()
+                    .all(|e| held.iter().any(|h| h.rdata == e.rdata));
+            if !same {
+                return Err(Rcode::NXRRSET);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the update section without changing anything (RFC 2136
+    /// §3.4.1).
+    pub fn prescan_update(&self, updates: &[Record]) -> Result<(), Rcode> {
+        for rr in updates {
+            if !rr.name.is_subdomain_of(&self.origin) {
+                return Err(Rcode::NOTZONE);
+            }
+            let rtype = rr.rtype();
+            let ok = match rr.class {
+                c if c == self.class => !rtype.is_meta(),
+                RecordClass::ANY => {
+                    rr.ttl == 0
+                        && is_empty_rdata(&rr.rdata)
+                        && (rtype == RecordType::ANY || !rtype.is_meta())
+                }
+                RecordClass::NONE => rr.ttl == 0 && !rtype.is_meta(),
+                _ => false,
+            };
+            if !ok {
+                return Err(Rcode::FORMERR);
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes an update: checks prerequisites, then applies the update
+    /// section as one change that bumps the SOA serial and goes into the
+    /// journal. Returns whether anything changed; on error nothing did.
+    pub fn update(&mut self, prerequisites: &[Record], updates: &[Record]) -> Result<bool, Rcode> {
+        let old_soa = self.soa().cloned().ok_or(Rcode::SERVFAIL)?;
+        self.check_prerequisites(prerequisites)?;
+        self.prescan_update(updates)?;
+
+        // Work on a copy of just the names being changed.
+        let touched: BTreeSet<DomainName> = updates.iter().map(|rr| rr.name.clone()).collect();
+        let mut work = Zone::with_class(self.origin.clone(), self.class);
+        for name in &touched {
+            for rr in self.records_at(name) {
+                work.insert(rr.clone()).map_err(|_| Rcode::SERVFAIL)?;
+            }
+        }
+        for rr in updates {
+            work.apply_update(rr);
+        }
+
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        for name in &touched {
+            for rr in self.records_at(name) {
+                if rr.rtype() != RecordType::SOA && !work.records_at(name).any(|w| w == rr) {
+                    deleted.push(rr.clone());
+                }
+            }
+            for rr in work.records_at(name) {
+                if rr.rtype() != RecordType::SOA && !self.records_at(name).any(|o| o == rr) {
+                    added.push(rr.clone());
+                }
+            }
+        }
+        let new_soa = work.soa().filter(|soa| *soa != &old_soa).cloned();
+        if deleted.is_empty() && added.is_empty() && new_soa.is_none() {
+            return Ok(false);
+        }
+        let new_soa = new_soa.unwrap_or_else(|| {
+            let mut soa = old_soa.clone();
+            if let RData::Soa(data) = &mut soa.rdata {
+                data.serial = data.serial.wrapping_add(1);
+            }
+            soa
+        });
+        let diff = Diff {
+            old_soa,
+            deleted,
+            new_soa,
+            added,
+        };
+        self.apply(diff).map_err(|_| Rcode::SERVFAIL)?;
+        Ok(true)
+    }
+
+    /// Applies one record of the update section (RFC 2136 §3.4.2).
+    fn apply_update(&mut self, rr: &Record) {
+        let rtype = rr.rtype();
+        let apex = rr.name == self.origin;
+        match rr.class {
+            RecordClass::ANY if rtype == RecordType::ANY => {
+                let types: Vec<RecordType> = self
+                    .records_at(&rr.name)
+                    .map(Record::rtype)
+                    .filter(|t| !(apex && (*t == RecordType::SOA || *t == RecordType::NS)))
+                    .collect();
+                for t in types {
+                    self.remove_rrset(&rr.name, t);
+                }
+            }
+            RecordClass::ANY => {
+                if !(apex && (rtype == RecordType::SOA || rtype == RecordType::NS)) {
+                    self.remove_rrset(&rr.name, rtype);
+                }
+            }
+            RecordClass::NONE => {
+                let last_apex_ns = apex
+                    && rtype == RecordType::NS
+                    && self.rrset(&rr.name, rtype).is_some_and(|ns| ns.len() == 1);
+                if rtype != RecordType::SOA && !last_apex_ns {
+                    let mut target = rr.clone();
+                    target.class = self.class;
+                    self.remove(&target);
+                }
+            }
+            _ => self.add_update(rr),
+        }
+    }
+
+    fn add_update(&mut self, rr: &Record) {
+        let rtype = rr.rtype();
+        if rtype == RecordType::SOA {
+            // Only at the apex, and only moving the serial forward.
+            let newer = match (&rr.rdata, self.serial()) {
+                (RData::Soa(new), Some(old)) => {
+                    serial_cmp(new.serial, old) == Some(Ordering::Greater)
+                }
+                _ => false,
+            };
+            if rr.name == self.origin && newer {
+                self.remove_rrset(&rr.name, RecordType::SOA);
+                let _ = self.insert(rr.clone());
+            }
+            return;
+        }
+        let has_cname = self.rrset(&rr.name, RecordType::CNAME).is_some();
+        let has_other = self
+            .records_at(&rr.name)
+            .any(|r| r.rtype() != RecordType::CNAME);
+        if rtype == RecordType::CNAME {
+            if has_other {
+                return;
+            }
+            // A CNAME replaces the one already there.
+            self.remove_rrset(&rr.name, RecordType::CNAME);
+        } else if has_cname {
+            return;
+        }
+        let _ = self.insert(rr.clone());
+    }
+}
+
+/// Whether record data is the empty data of a class ANY or NONE record.
+fn is_empty_rdata(rdata: &RData) -> bool {
+    matches!(rdata, RData::Unknown { data, .. } if data.is_empty())
+}
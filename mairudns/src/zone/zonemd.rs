@@ -0,0 +1,230 @@
+//! Message digests for DNS zones (ZONEMD, RFC 8976): a digest over every
+//! record of a zone, published at its apex, by which a copy obtained from
+//! anywhere can be checked against what its publisher signed or served.
+
+use std::fmt;
+
+use crate::crypto::{Digest, Sha384, Sha512};
+use crate::encoding;
+use crate::rr::{RData, Record, RecordType};
+use crate::wire::Encoder;
+
+use super::Zone;
+
+/// A ZONEMD record.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Zonemd {
+    /// The SOA serial of the version of the zone the digest is over.
+    pub serial: u32,
+    pub scheme: u8,
+    pub hash_algorithm: u8,
+    pub digest: Vec<u8>,
+}
+
+impl Zonemd {
+    /// The scheme that digests the zone's records in one pass.
+    pub const SIMPLE: u8 = 1;
+    pub const SHA384: u8 = 1;
+    pub const SHA512: u8 = 2;
+
+    pub fn from_rdata(rdata: &RData) -> Option<Zonemd> {
+        let data = match rdata {
+            RData::Unknown { rtype, data } if *rtype == RecordType::ZONEMD => data,
+            _ => return None,
+        };
+        if data.len() < 6 {
+            return None;
+        }
+        Some(Zonemd {
+            serial: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            scheme: data[4],
+            hash_algorithm: data[5],
+            digest: data[6..].to_vec(),
+        })
+    }
+
+    pub fn to_rdata(&self) -> RData {
+        let mut data = Vec::with_capacity(6 + self.digest.len());
+        data.extend_from_slice(&self.serial.to_be_bytes());
+        data.push(self.scheme);
+        data.push(self.hash_algorithm);
+        data.extend_from_slice(&self.digest);
+        RData::Unknown {
+            rtype: RecordType::ZONEMD,
+            data,
+        }
+    }
+}
+
+impl fmt::Display for Zonemd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.serial,
+            self.scheme,
+            self.hash_algorithm,
+            encoding::hex_encode(&self.digest).to_ascii_uppercase()
+        )
+    }
+}
+
+/// Why a zone's ZONEMD records do not check out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ZonemdError {
+    /// The zone has no SOA record at its origin.
+    NoSoa,
+    /// The ZONEMD records are for another version of the zone.
+    Serial { zonemd: u32, soa: u32 },
+    /// Two ZONEMD records have the same scheme and hash algorithm.
+    Duplicate,
+    /// The digest of the zone differs from the one published.
+    Mismatch,
+    /// A digest of this hash algorithm cannot be made.
+    Unsupported(u8),
+}
+
+impl fmt::Display for ZonemdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZonemdError::NoSoa => f.write_str("zone has no SOA record"),
+            ZonemdError::Serial { zonemd, soa } => write!(
+                f,
+                "ZONEMD is for serial {}, the zone has serial {}",
+                zonemd, soa
+            ),
+            ZonemdError::Duplicate => f.write_str("duplicate ZONEMD records"),
+            ZonemdError::Mismatch => f.write_str("zone does not match its ZONEMD digest"),
+            ZonemdError::Unsupported(alg) => {
+                write!(f, "unsupported ZONEMD hash algorithm {}", alg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ZonemdError {}
+
+/// The SIMPLE scheme digest of `zone` (RFC 8976 §3.3): every record in
+/// canonical form and order, less the ZONEMD records at the apex and the
+/// signatures over them.
+pub(super) fn digest(zone: &Zone, hash_algorithm: u8) -> Option<Vec<u8>> {
+    match hash_algorithm {
+        Zonemd::SHA384 => Some(simple::<Sha384>(zone)),
+        Zonemd::SHA512 => Some(simple::<Sha512>(zone)),
+        _ => None,
+    }
+}
+
+fn simple<D: Digest>(zone: &Zone) -> Vec<u8> {
+    let origin = zone.origin();
+    let mut hash = D::default();
+    for name in zone.names() {
+        let mut owner = Encoder::uncompressed();
+        owner.canonical_name(name);
+        let owner = owner.into_bytes();
+        let mut types = zone.types(name);
+        types.sort_unstable();
+        for rtype in types {
+            if name == origin && rtype == RecordType::ZONEMD {
+                continue;
+            }
+            let mut records: Vec<(Vec<u8>, &Record)> = zone
+                .rrset(name, rtype)
+                .unwrap_or(&[])
+                .iter()
+                .map(|rr| (rr.rdata.canonical(), rr))
+                .filter(|(rdata, _)| {
+                    // The type an RRSIG covers leads its data.
+                    let covered = rdata.get(..2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+                    !(name == origin
+                        && rtype == RecordType::RRSIG
+                        && covered == Some(RecordType::ZONEMD.0))
+                })
+                .collect();
+            records.sort_by(|a, b| a.0.cmp(&b.0));
+            records.dedup_by(|a, b| a.0 == b.0);
+            for (rdata, rr) in records {
+                let mut enc = Encoder::uncompressed();
+                enc.bytes(&owner);
+                enc.u16(rtype.0);
+                enc.u16(rr.class.0);
+                enc.u32(rr.ttl);
+                enc.u16(rdata.len() as u16);
+                enc.bytes(&rdata);
+                hash.update(enc.as_bytes());
+            }
+        }
+    }
+    hash.finish()
+}
+
+/// The ZONEMD records at the apex of `zone`.
+fn published(zone: &Zone) -> Vec<Zonemd> {
+    zone.rrset(zone.origin(), RecordType::ZONEMD)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|rr| Zonemd::from_rdata(&rr.rdata))
+        .collect()
+}
+
+/// Checks `zone` against its ZONEMD records (RFC 8976 §4): whether one it
+/// has a digest of matched, or `false` if it has none or only digests of
+/// unknown schemes and hash algorithms.
+pub(super) fn verify(zone: &Zone) -> Result<bool, ZonemdError> {
+    let serial = zone.serial().ok_or(ZonemdError::NoSoa)?;
+    let zonemds = published(zone);
+    for (i, zonemd) in zonemds.iter().enumerate() {
+        if zonemds[..i]
+            .iter()
+            .any(|z| (z.scheme, z.hash_algorithm) == (zonemd.scheme, zonemd.hash_algorithm))
+        {
+            return Err(ZonemdError::Duplicate);
+        }
+    }
+    let mut outcome = Ok(false);
+    for zonemd in zonemds.iter().filter(|z| z.scheme == Zonemd::SIMPLE) {
+        let digest = match digest(zone, zonemd.hash_algorithm) {
+            Some(digest) => digest,
+            None => continue,
+        };
+        if zonemd.serial != serial {
+            outcome = Err(ZonemdError::Serial {
+                zonemd: zonemd.serial,
+                soa: serial,
+            });
+        } else if zonemd.digest == digest {
+            return Ok(true);
+        } else {
+            outcome = Err(ZonemdError::Mismatch);
+        }
+    }
+    outcome
+}
+
+/// Replaces the ZONEMD records at the apex of `zone` with SIMPLE scheme
+/// digests of the current version, one for each of `hash_algorithms`.
+pub(super) fn update(zone: &mut Zone, hash_algorithms: &[u8]) -> Result<(), ZonemdError> {
+    let soa = zone.soa().ok_or(ZonemdError::NoSoa)?;
+    let (ttl, class) = (soa.ttl, soa.class);
+    let serial = zone.serial().ok_or(ZonemdError::NoSoa)?;
+    let origin = zone.origin().clone();
+    let mut records = Vec::new();
+    for &hash_algorithm in hash_algorithms {
+        let zonemd = Zonemd {
+            serial,
+            scheme: Zonemd::SIMPLE,
+            hash_algorithm,
+            digest: digest(zone, hash_algorithm).ok_or(ZonemdError::Unsupported(hash_algorithm))?,
+        };
+        records.push(Record {
+            class,
+            ..Record::new(origin.clone(), ttl, zonemd.to_rdata())
+        });
+    }
+    zone.remove_rrset(&origin, RecordType::ZONEMD);
+    for rr in records {
+        // At the origin and in the zone's class, so always accepted.
+        let _ = zone.insert(rr);
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Address-based access control: longest-prefix rules per kind of
+//! request, each action, and recursion refused by clearing RD.
+
+use mairudns::addr::PrefixMap;
+use mairudns::client::Protocol;
+use mairudns::message::{ExtendedError, Message, Opcode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::RecordType;
+use mairudns::server::{AccessControl, Acl, AclAction, Handler, HandlerExt, Request};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(src: &str, qtype: RecordType, protocol: Protocol) -> Request {
+    Request {
+        message: Message::query(name("example"), qtype),
+        src: format!("{}:5300", src).parse().unwrap(),
+        protocol,
+        key: None,
+    }
+}
+
+/// Answers SERVFAIL to queries that still ask for recursion, NOERROR to
+/// the rest.
+fn inner(request: &Request) -> Option<Message> {
+    let mut resp = request.message.response();
+    if request.message.header.rd {
+        resp.header.rcode = Rcode::SERVFAIL;
+    }
+    Some(resp)
+}
+
+fn handler(config: AccessControl) -> impl Handler {
+    (inner as fn(&Request) -> Option<Message>).with(config)
+}
+
+#[test]
+fn the_longest_matching_prefix_wins() {
+    let mut map = PrefixMap::new();
+    map.insert("10.0.0.0/8".parse().unwrap(), 1);
+    map.insert("10.1.0.0/16".parse().unwrap(), 2);
+    assert_eq!(map.lookup(&"10.1.2.3".parse().unwrap()), Some(&2));
+    assert_eq!(map.lookup(&"::ffff:10.2.2.3".parse().unwrap()), Some(&1));
+    assert_eq!(map.lookup(&"11.2.2.3".parse().unwrap()), None);
+
+    let acl = Acl::new(AclAction::Refuse)
+        .rule("10.0.0.0/8".parse().unwrap(), AclAction::Allow)
+        .rule("10.1.0.0/16".parse().unwrap(), AclAction::Drop);
+    assert_eq!(acl.action(&"10.2.0.1".parse().unwrap()), AclAction::Allow);
+    assert_eq!(acl.action(&"10.1.0.1".parse().unwrap()), AclAction::Drop);
+    assert_eq!(acl.action(&"192.0.2.1".parse().unwrap()), AclAction::Refuse);
+}
+
+#[test]
+fn refuses_with_an_extended_error() {
+    let handler = handler(AccessControl {
+        queries: Acl::new(AclAction::Refuse),
+        refusal_text: Some("not for you".to_string()),
+        ..AccessControl::new()
+    });
+    let resp = handler
+        .handle(&request("192.0.2.1", RecordType::A, Protocol::Udp))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::REFUSED);
+    assert_eq!(
+        resp.extended_errors().collect::<Vec<_>>(),
+        vec![ExtendedError::new(ExtendedError::PROHIBITED, "not for you")]
+    );
+}
+
+#[test]
+fn drops_and_truncates_over_udp_only() {
+    let handler = handler(AccessControl {
+        queries: Acl::new(AclAction::Truncate)
+            .rule("198.51.100.0/24".parse().unwrap(), AclAction::Drop),
+        ..AccessControl::new()
+    });
+    let resp = handler
+        .handle(&request("192.0.2.1", RecordType::A, Protocol::Udp))
+        .unwrap();
+    assert!(resp.header.tc);
+    let resp = handler
+        .handle(&request("192.0.2.1", RecordType::A, Protocol::Tcp))
+        .unwrap();
+    assert!(!resp.header.tc);
+    assert!(handler
+        .handle(&request("198.51.100.1", RecordType::A, Protocol::Udp))
+        .is_none());
+}
+
+#[test]
+fn refused_recursion_clears_rd_but_echoes_it() {
+    let handler = handler(AccessControl {
+        recursion: Acl::new(AclAction::Refuse)
+            .rule("10.0.0.0/8".parse().unwrap(), AclAction::Allow),
+        ..AccessControl::new()
+    });
+    let resp = handler
+        .handle(&request("192.0.2.1", RecordType::A, Protocol::Udp))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.header.rd);
+    let resp = handler
+        .handle(&request("10.0.0.1", RecordType::A, Protocol::Udp))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+}
+
+#[test]
+fn transfers_and_updates_have_their_own_acls() {
+    let config = AccessControl {
+        transfers: Acl::new(AclAction::Refuse),
+        updates: Acl::new(AclAction::Drop),
+        ..AccessControl::new()
+    };
+    let handler = handler(config.clone());
+    let axfr = request("192.0.2.1", RecordType::AXFR, Protocol::Tcp);
+    assert_eq!(config.acl_for(&axfr), &config.transfers);
+    assert_eq!(handler.handle(&axfr).unwrap().header.rcode, Rcode::REFUSED);
+    let mut update = request("192.0.2.1", RecordType::SOA, Protocol::Tcp);
+    update.message.header.opcode = Opcode::UPDATE;
+    assert!(handler.handle(&update).is_none());
+    let query = request("192.0.2.1", RecordType::SOA, Protocol::Tcp);
+    assert!(handler.handle(&query).is_some());
+}
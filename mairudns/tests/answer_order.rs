@@ -0,0 +1,268 @@
+//! Answer ordering: cyclic, random and fixed RRsets chosen by name, the
+//! sortlist moving the addresses that suit a client to the front, other
+//! records keeping their places, and the configuration that builds it.
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+use mairudns::client::Protocol;
+use mairudns::config::{AnswerOrderConfig, Config, OrderConfig};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{
+    AnswerOrder, AnswerOrdering, Authority, Handler, HandlerExt, Request, SortlistEntry,
+};
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 192.0.2.53
+www IN A 10.0.0.1
+www IN A 10.0.0.2
+www IN A 10.0.0.3
+www IN A 10.0.0.4
+fixed.legacy IN A 10.0.1.1
+fixed.legacy IN A 10.0.1.2
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn v4(last: u8) -> Record {
+    Record::new(
+        name("www.example.com."),
+        300,
+        RData::A(Ipv4Addr::new(10, 0, 0, last)),
+    )
+}
+
+fn v6(last: u16) -> Record {
+    Record::new(
+        name("www.example.com."),
+        300,
+        RData::Aaaa(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, last)),
+    )
+}
+
+fn client(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+/// The last octets or groups of the addresses in the answer, in order.
+fn order(resp: &Message) -> Vec<u16> {
+    resp.answers
+        .iter()
+        .filter_map(|rr| match rr.rdata {
+            RData::A(a) => Some(u16::from(a.octets()[3])),
+            RData::Aaaa(a) => Some(a.segments()[7]),
+            _ => None,
+        })
+        .collect()
+}
+
+fn answer(records: Vec<Record>) -> Message {
+    let mut resp = Message::query(name("www.example.com."), RecordType::A).response();
+    resp.answers = records;
+    resp
+}
+
+fn ask(handler: &impl Handler, qname: &str) -> Message {
+    handler
+        .handle(&Request {
+            message: Message::query(name(qname), RecordType::A),
+            src: "192.0.2.1:5300".parse().unwrap(),
+            protocol: Protocol::Udp,
+            key: None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn rrsets_rotate_shuffle_or_stay_by_name() {
+    let ordering = AnswerOrdering::new(AnswerOrder::Cyclic)
+        .name(name("legacy.example.com."), AnswerOrder::Fixed)
+        .name(name("shuffled.legacy.example.com."), AnswerOrder::Random);
+    assert_eq!(
+        ordering.order_for(&name("www.example.com.")),
+        AnswerOrder::Cyclic
+    );
+    assert_eq!(
+        ordering.order_for(&name("a.legacy.example.com.")),
+        AnswerOrder::Fixed
+    );
+    // The most specific name wins, whatever the order they were given in.
+    assert_eq!(
+        ordering.order_for(&name("a.shuffled.legacy.example.com.")),
+        AnswerOrder::Random
+    );
+
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example.com."), ZONE).unwrap());
+    let handler = authority.with(ordering);
+    let rotations: Vec<_> = (0..5)
+        .map(|_| order(&ask(&handler, "www.example.com.")))
+        .collect();
+    assert_eq!(
+        rotations,
+        [
+            [1, 2, 3, 4],
+            [2, 3, 4, 1],
+            [3, 4, 1, 2],
+            [4, 1, 2, 3],
+            [1, 2, 3, 4],
+        ]
+    );
+    // Single records do not turn the rotation.
+    ask(&handler, "ns.example.com.");
+    assert_eq!(order(&ask(&handler, "www.example.com.")), [2, 3, 4, 1]);
+    for _ in 0..3 {
+        assert_eq!(order(&ask(&handler, "fixed.legacy.example.com.")), [1, 2]);
+    }
+    assert_eq!(handler.config().order, AnswerOrder::Cyclic);
+
+    // Over enough responses a shuffle gives every record first place.
+    let random = AnswerOrdering::new(AnswerOrder::Random);
+    let firsts: HashSet<u16> = (0..200)
+        .map(|turn| {
+            let mut resp = answer(vec![v4(1), v4(2), v4(3), v4(4)]);
+            random.apply(&mut resp, &client("192.0.2.1"), turn);
+            let mut sorted = order(&resp);
+            let first = sorted[0];
+            sorted.sort_unstable();
+            assert_eq!(sorted, [1, 2, 3, 4]);
+            first
+        })
+        .collect();
+    assert_eq!(firsts.len(), 4);
+}
+
+#[test]
+fn rrsets_keep_their_places_among_other_records() {
+    let cname = Record::new(
+        name("alias.example.com."),
+        300,
+        RData::Cname(name("www.example.com.")),
+    );
+    let mut resp = answer(vec![cname.clone(), v4(1), v6(1), v4(2), v6(2), v4(3)]);
+    AnswerOrdering::new(AnswerOrder::Cyclic).apply(&mut resp, &client("192.0.2.1"), 1);
+    // Each RRset rotates within the places it had.
+    assert_eq!(resp.answers[0], cname);
+    assert_eq!(order(&resp), [2, 2, 3, 1, 1]);
+    assert_eq!(resp.answers[2].rtype(), RecordType::AAAA);
+}
+
+#[test]
+fn the_sortlist_puts_what_suits_the_client_first() {
+    let ordering = AnswerOrdering::new(AnswerOrder::Cyclic)
+        .sortlist(
+            SortlistEntry::new("192.0.2.0/24".parse().unwrap())
+                .prefer("10.0.0.3/32".parse().unwrap())
+                .prefer("10.0.0.4/32".parse().unwrap())
+                .prefer("2001:db8::2/128".parse().unwrap()),
+        )
+        .sortlist(
+            SortlistEntry::new("0.0.0.0/0".parse().unwrap()).prefer("10.0.0.1/32".parse().unwrap()),
+        );
+    assert!(ordering.sortlist_for(&client("2001:db8::1")).is_none());
+    assert_eq!(
+        ordering
+            .sortlist_for(&client("198.51.100.1"))
+            .unwrap()
+            .preferred
+            .len(),
+        1
+    );
+
+    // The rest keep the order the rotation left them in.
+    let sorted: Vec<_> = (0..4)
+        .map(|turn| {
+            let mut resp = answer(vec![v4(1), v4(2), v4(3), v4(4)]);
+            ordering.apply(&mut resp, &client("192.0.2.7"), turn);
+            order(&resp)
+        })
+        .collect();
+    assert_eq!(
+        sorted,
+        [[3, 4, 1, 2], [3, 4, 2, 1], [3, 4, 1, 2], [3, 4, 1, 2]]
+    );
+    let mut resp = answer(vec![v6(1), v6(2), v6(3)]);
+    ordering.apply(&mut resp, &client("192.0.2.7"), 0);
+    assert_eq!(order(&resp), [2, 1, 3]);
+
+    // The first entry holding the client applies, and only that one.
+    let mut resp = answer(vec![v4(3), v4(2), v4(1)]);
+    AnswerOrdering {
+        order: AnswerOrder::Fixed,
+        ..ordering.clone()
+    }
+    .apply(&mut resp, &client("198.51.100.1"), 0);
+    assert_eq!(order(&resp), [1, 3, 2]);
+}
+
+#[test]
+fn configuration_builds_the_ordering() {
+    let config = AnswerOrderConfig::new(OrderConfig::Cyclic)
+        .name("legacy.example.com", OrderConfig::Fixed)
+        .sortlist(
+            "10.0.0.0/8",
+            vec!["10.1.0.0/16".into(), "2001:db8::/32".into()],
+        );
+    let ordering = config.to_answer_ordering().unwrap();
+    assert_eq!(
+        ordering,
+        AnswerOrdering::new(AnswerOrder::Cyclic)
+            .name(name("legacy.example.com."), AnswerOrder::Fixed)
+            .sortlist(
+                SortlistEntry::new("10.0.0.0/8".parse().unwrap())
+                    .prefer("10.1.0.0/16".parse().unwrap())
+                    .prefer("2001:db8::/32".parse().unwrap())
+            )
+    );
+
+    // Problems are reported against the field that has them.
+    let field = |config: AnswerOrderConfig| {
+        let problems = Config::new().answer_order(config).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    assert_eq!(
+        field(AnswerOrderConfig::default().name("bad..name", OrderConfig::Random)),
+        "answer-order.names[0].name"
+    );
+    assert_eq!(
+        field(AnswerOrderConfig::default().sortlist("10.0.0.0/33", Vec::new())),
+        "answer-order.sortlist[0].clients"
+    );
+    assert_eq!(
+        field(
+            AnswerOrderConfig::default()
+                .sortlist("10.0.0.0/8", Vec::new())
+                .sortlist("10.0.0.0/8", vec!["10.1.0.0/16".into(), "nope".into()])
+        ),
+        "answer-order.sortlist[1].prefer[1]"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn answer_order_reads_from_toml() {
+    let config = Config::from_toml(
+        "[answer-order]\n\
+         order = \"random\"\n\
+         names = [{ name = \"legacy.example.com\", order = \"fixed\" }]\n\
+         sortlist = [{ clients = \"10.0.0.0/8\", prefer = [\"10.1.0.0/16\"] }]\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.answer_order,
+        AnswerOrderConfig::new(OrderConfig::Random)
+            .name("legacy.example.com", OrderConfig::Fixed)
+            .sortlist("10.0.0.0/8", vec!["10.1.0.0/16".into()])
+    );
+    assert!(Config::from_toml("[answer-order]\norder = \"sideways\"\n").is_err());
+}
@@ -0,0 +1,333 @@
+//! The management API: token checks, reading and changing zones and
+//! RRsets, the forwarding cache, the record representation it shares with
+//! serde, and the HTTP listener in front of it.
+
+#![cfg(feature = "api")]
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::api::{Api, Reply};
+use mairudns::server::{Authority, Forwarder, Handler, Request, Route, Server};
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+use serde_json::{json, Value};
+
+const TOKEN: &str = "s3cret";
+const AUTH: Option<&str> = Some("Bearer s3cret");
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn authority() -> Arc<Authority> {
+    let zone = Zone::from_master(
+        name("example"),
+        "$TTL 300\n@ SOA ns hostmaster 7 3600 600 86400 300\n@ NS ns\n\
+         ns A 192.0.2.1\nwww A 192.0.2.80\n",
+    )
+    .unwrap();
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone);
+    authority
+}
+
+fn json(reply: &Reply) -> Value {
+    serde_json::from_str(&reply.body).unwrap()
+}
+
+fn serial(authority: &Authority) -> Option<u32> {
+    authority.zone(&name("example"))?.read().unwrap().serial()
+}
+
+#[test]
+fn refuses_requests_without_the_token() {
+    let api = Api::new(TOKEN, authority());
+    for auth in &[
+        None,
+        Some("Bearer wrong"),
+        Some("s3cret"),
+        Some("Basic s3cret"),
+    ] {
+        let reply = api.handle("GET", "/api/v1/zones", *auth, b"");
+        assert_eq!(reply.status, 401, "{:?}", auth);
+    }
+    assert_eq!(api.handle("GET", "/api/v1/zones", AUTH, b"").status, 200);
+}
+
+#[test]
+fn lists_and_shows_zones() {
+    let api = Api::new(TOKEN, authority());
+    let reply = api.handle("GET", "/api/v1/zones", AUTH, b"");
+    assert_eq!(
+        json(&reply),
+        json!([{
+            "name": "example.",
+            "class": "IN",
+            "serial": 7,
+            "records": 4,
+            "secondary": false,
+        }])
+    );
+    let zone = json(&api.handle("GET", "/api/v1/zones/example.", AUTH, b""));
+    assert_eq!(zone["serial"], 7);
+    assert_eq!(zone["records"].as_array().unwrap().len(), 4);
+
+    let rrset = json(&api.handle(
+        "GET",
+        "/api/v1/zones/example/rrsets/www.example/A",
+        AUTH,
+        b"",
+    ));
+    assert_eq!(
+        rrset,
+        json!([{ "name": "www.example.", "class": "IN", "ttl": 300, "type": "A", "data": { "address": "192.0.2.80" } }])
+    );
+
+    assert_eq!(
+        api.handle("GET", "/api/v1/zones/other", AUTH, b"").status,
+        404
+    );
+    assert_eq!(
+        api.handle(
+            "GET",
+            "/api/v1/zones/example/rrsets/www.example/MX",
+            AUTH,
+            b""
+        )
+        .status,
+        404
+    );
+    assert_eq!(api.handle("GET", "/api/v1/nothing", AUTH, b"").status, 404);
+    assert_eq!(api.handle("GET", "/zones", AUTH, b"").status, 404);
+    assert_eq!(api.handle("POST", "/api/v1/zones", AUTH, b"").status, 405);
+}
+
+#[test]
+fn replaces_and_deletes_rrsets_bumping_the_serial() {
+    let authority = authority();
+    let api = Api::new(TOKEN, authority.clone());
+    let path = "/api/v1/zones/example/rrsets/www.example/A";
+    let body = br#"[
+        {"name": "www.example.", "ttl": 60, "type": "A", "data": "192.0.2.81"},
+        {"name": "www.example.", "ttl": 60, "type": "A", "data": "192.0.2.82"}
+    ]"#;
+    let reply = api.handle("PUT", path, AUTH, body);
+    assert_eq!(json(&reply), json!({ "changed": true, "serial": 8 }));
+    let records = authority
+        .zone(&name("example"))
+        .unwrap()
+        .read()
+        .unwrap()
+        .rrset(&name("www.example"), RecordType::A)
+        .unwrap()
+        .to_vec();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].ttl, 60);
+
+    // The same RRset again changes nothing.
+    let reply = api.handle("PUT", path, AUTH, body);
+    assert_eq!(json(&reply), json!({ "changed": false, "serial": 8 }));
+
+    let reply = api.handle("DELETE", path, AUTH, b"");
+    assert_eq!(json(&reply), json!({ "changed": true, "serial": 9 }));
+    assert_eq!(api.handle("GET", path, AUTH, b"").status, 404);
+    assert_eq!(serial(&authority), Some(9));
+}
+
+#[test]
+fn rejects_records_that_do_not_belong() {
+    let authority = authority();
+    let api = Api::new(TOKEN, authority.clone());
+    let path = "/api/v1/zones/example/rrsets/www.example/A";
+    let other_name = br#"[{"name": "ftp.example.", "ttl": 60, "type": "A", "data": "192.0.2.1"}]"#;
+    assert_eq!(api.handle("PUT", path, AUTH, other_name).status, 400);
+    let other_type =
+        br#"[{"name": "www.example.", "ttl": 60, "type": "AAAA", "data": "2001:db8::1"}]"#;
+    assert_eq!(api.handle("PUT", path, AUTH, other_type).status, 400);
+    assert_eq!(api.handle("PUT", path, AUTH, b"{not json").status, 400);
+    let outside = "/api/v1/zones/example/rrsets/www.other/A";
+    assert_eq!(api.handle("PUT", outside, AUTH, b"[]").status, 400);
+    let bad_type = "/api/v1/zones/example/rrsets/www.example/BOGUS!";
+    assert_eq!(api.handle("PUT", bad_type, AUTH, b"[]").status, 400);
+    let soa = "/api/v1/zones/example/rrsets/example/SOA";
+    assert_eq!(api.handle("DELETE", soa, AUTH, b"").status, 409);
+    assert_eq!(api.handle("POST", path, AUTH, b"[]").status, 405);
+    assert_eq!(serial(&authority), Some(7));
+}
+
+#[test]
+fn transfers_only_secondary_zones() {
+    let api = Api::new(TOKEN, authority());
+    let reply = api.handle("POST", "/api/v1/zones/example/transfer", AUTH, b"");
+    assert_eq!(reply.status, 404);
+    assert_eq!(json(&reply)["error"], "example. is not a secondary zone");
+}
+
+#[test]
+fn inspects_and_flushes_the_cache() {
+    let upstream = MockServer::builder()
+        .answer(
+            name("www.example"),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example"),
+                300,
+                RData::A([192, 0, 2, 80].into()),
+            )],
+        )
+        .answer(
+            name("mail.example"),
+            RecordType::A,
+            vec![Record::new(
+                name("mail.example"),
+                300,
+                RData::A([192, 0, 2, 25].into()),
+            )],
+        )
+        .start()
+        .unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![upstream.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    let forwarder = Arc::new(Forwarder::new().route(Route::new(DomainName::root(), resolver)));
+    for qname in &["www.example", "mail.example"] {
+        let mut query = Message::query(name(qname), RecordType::A);
+        query.header.rd = true;
+        forwarder.handle(&Request {
+            message: query,
+            src: "127.0.0.1:1".parse().unwrap(),
+            protocol: Protocol::Udp,
+            key: None,
+        });
+    }
+    let api = Api::new(TOKEN, authority()).forwarder(forwarder.clone());
+
+    let all = json(&api.handle("GET", "/api/v1/cache", AUTH, b""));
+    assert_eq!(all.as_array().unwrap().len(), 2);
+    let www = json(&api.handle("GET", "/api/v1/cache?name=www.example", AUTH, b""));
+    assert_eq!(www[0]["name"], "www.example.");
+    assert_eq!(www[0]["type"], "A");
+    assert_eq!(www[0]["rcode"], "NOERROR");
+    assert_eq!(www[0]["answers"][0]["data"]["address"], "192.0.2.80");
+    assert!(www[0]["expires_in"].as_u64().unwrap() <= 300);
+
+    let reply = api.handle(
+        "DELETE",
+        "/api/v1/cache?name=%2A.example&type=AAAA",
+        AUTH,
+        b"",
+    );
+    assert_eq!(json(&reply), json!({ "flushed": true, "responses": 0 }));
+    let reply = api.handle("DELETE", "/api/v1/cache?name=www.example", AUTH, b"");
+    assert_eq!(json(&reply), json!({ "flushed": true, "responses": 1 }));
+    let reply = api.handle("DELETE", "/api/v1/cache", AUTH, b"");
+    assert_eq!(json(&reply), json!({ "flushed": true }));
+    assert_eq!(
+        json(&api.handle("GET", "/api/v1/cache", AUTH, b"")),
+        json!([])
+    );
+    assert_eq!(
+        api.handle("GET", "/api/v1/cache?type=BOGUS!", AUTH, b"")
+            .status,
+        400
+    );
+
+    let without = Api::new(TOKEN, authority());
+    assert_eq!(
+        without.handle("GET", "/api/v1/cache", AUTH, b"").status,
+        404
+    );
+}
+
+#[test]
+fn records_share_the_serde_representation() {
+    let zone = Zone::from_master(
+        name("example"),
+        "$TTL 300\n@ SOA ns hm 1 2 3 4 5\n@ NS ns\nns A 192.0.2.1\n@ MX 10 mx\n\
+         @ TXT \"a\\\"b\" \"c\\255\"\n_s._tcp SRV 1 2 3 ns\n@ TYPE999 \\# 2 abcd\n",
+    )
+    .unwrap();
+    let text = serde_json::to_string(&zone).unwrap();
+    let back: Zone = serde_json::from_str(&text).unwrap();
+    let mut before: Vec<Record> = zone.records().cloned().collect();
+    let mut after: Vec<Record> = back.records().cloned().collect();
+    before.sort_by_key(|rr| rr.to_string());
+    after.sort_by_key(|rr| rr.to_string());
+    assert_eq!(before, after);
+
+    let record: Record =
+        serde_json::from_str(r#"{"name":"a.","ttl":1,"type":"A","data":"192.0.2.9"}"#).unwrap();
+    assert_eq!(record.rdata, RData::A([192, 0, 2, 9].into()));
+    assert!(serde_json::from_str::<Record>(
+        r#"{"name":"a.","ttl":1,"type":"MX","data":{"preference":3}}"#
+    )
+    .is_err());
+
+    let api = Api::new(TOKEN, authority());
+    let schema = json(&api.handle("GET", "/api/v1/schema", AUTH, b""));
+    assert!(schema["$defs"]["data-SOA"].is_object());
+    assert!(schema["$defs"]["record"].is_object());
+}
+
+fn http(addr: std::net::SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn serves_http() {
+    let authority = authority();
+    let mut server = Server::new(authority.clone());
+    let addr = server
+        .listen_api(
+            "127.0.0.1:0".parse().unwrap(),
+            Api::new(TOKEN, authority.clone()),
+        )
+        .unwrap();
+    thread::spawn(move || server.run());
+
+    let response = http(
+        addr,
+        "GET /api/v1/zones HTTP/1.1\r\nHost: x\r\nAuthorization: Bearer s3cret\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.contains("Content-Type: application/json\r\n"));
+    assert!(response.ends_with(r#""secondary":false}]"#), "{}", response);
+
+    let body = r#"[{"name":"www.example.","ttl":60,"type":"A","data":"192.0.2.99"}]"#;
+    let response = http(
+        addr,
+        &format!(
+            "PUT /api/v1/zones/example/rrsets/www.example/A HTTP/1.1\r\n\
+             Authorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert_eq!(serial(&authority), Some(8));
+
+    let response = http(addr, "GET /api/v1/zones HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    let response = http(
+        addr,
+        "PUT /api/v1/zones HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\
+         Content-Length: 99999999\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+}
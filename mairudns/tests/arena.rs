@@ -0,0 +1,221 @@
+//! Messages read in place and responses built in an arena: they must
+//! agree with the owned message types, and, once the arena has grown,
+//! answering a query must not allocate.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use mairudns::arena::{Arena, MessageRef};
+use mairudns::message::{EdnsOption, Message, OptionCode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+
+/// Counts the allocations of each thread, so that tests running at the
+/// same time do not count each other's.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(Cell::get)
+}
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// A response with every section filled, an unusual rcode and EDNS
+/// options.
+fn sample() -> Message {
+    let mut msg = Message::query(name("www.Example.com"), RecordType::A);
+    msg.header.qr = true;
+    msg.header.rcode = Rcode(17);
+    msg.answers.push(Record::new(
+        name("www.example.com"),
+        60,
+        RData::A([192, 0, 2, 1].into()),
+    ));
+    msg.authority.push(Record::new(
+        name("example.com"),
+        60,
+        RData::Ns(name("ns.example.com")),
+    ));
+    msg.additional.push(Record::new(
+        name("ns.example.com"),
+        60,
+        RData::A([192, 0, 2, 53].into()),
+    ));
+    let edns = msg.edns.as_mut().unwrap();
+    edns.dnssec_ok = true;
+    edns.options.push(EdnsOption {
+        code: OptionCode::PADDING,
+        data: vec![0; 5],
+    });
+    msg
+}
+
+#[test]
+fn reads_what_the_owned_decoder_reads() {
+    let msg = sample();
+    let wire = msg.to_wire().unwrap();
+    let parsed = MessageRef::parse(&wire).unwrap();
+    assert_eq!(parsed.header(), msg.header);
+    assert_eq!(parsed.as_bytes(), &wire[..]);
+
+    let question = parsed.question().unwrap();
+    assert_eq!(question.to_question(), msg.questions[0]);
+    // Names compare without regard to case, and keep theirs.
+    assert!(question.name == name("WWW.example.COM"));
+    assert_eq!(question.name.to_string(), "www.Example.com.");
+
+    let records = |section: mairudns::arena::Records<'_>| -> Vec<Record> {
+        section.map(|rr| rr.to_record().unwrap()).collect()
+    };
+    assert_eq!(records(parsed.answers()), msg.answers);
+    assert_eq!(records(parsed.authority()), msg.authority);
+    assert_eq!(records(parsed.additional()), msg.additional);
+    let edns = parsed.edns().unwrap();
+    assert!(edns.dnssec_ok);
+    assert_eq!(edns.option(OptionCode::PADDING), Some(&[0u8; 5][..]));
+    assert_eq!(parsed.to_message().unwrap(), msg);
+}
+
+#[test]
+fn refuses_what_the_owned_decoder_refuses() {
+    let wire = sample().to_wire().unwrap();
+    for len in 0..wire.len() {
+        assert_eq!(
+            MessageRef::parse(&wire[..len]).is_ok(),
+            Message::from_wire(&wire[..len]).is_ok(),
+            "cut to {} bytes",
+            len
+        );
+    }
+    let mut trailing = wire.clone();
+    trailing.push(0);
+    assert!(MessageRef::parse(&trailing).is_err());
+
+    // A compression pointer to itself.
+    let mut looped = Message::query(name("a.example"), RecordType::A)
+        .to_wire()
+        .unwrap();
+    looped.truncate(12);
+    looped.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1]);
+    assert!(MessageRef::parse(&looped).is_err());
+}
+
+#[test]
+fn builds_responses_the_owned_decoder_reads() {
+    let query = Message::query(name("www.example.com"), RecordType::A);
+    let wire = query.to_wire().unwrap();
+    let parsed = MessageRef::parse(&wire).unwrap();
+    let qname = parsed.question().unwrap().name;
+    let mut arena = Arena::new();
+
+    let mut resp = arena.response(&parsed, 512);
+    resp.set_rcode(Rcode(17));
+    resp.header_mut().aa = true;
+    assert!(resp.answer(qname, RecordType::A, 60, &[192, 0, 2, 1]));
+    let mail = name("mail.example.com");
+    assert!(resp.authority(&mail, RecordType::A, 60, &[192, 0, 2, 2]));
+    // Sections are written in order.
+    assert!(!resp.answer(qname, RecordType::A, 60, &[192, 0, 2, 1]));
+    assert!(resp.additional(
+        name("x.mail.EXAMPLE.com"),
+        RecordType::A,
+        60,
+        &[192, 0, 2, 3]
+    ));
+    let built = resp.finish().to_vec();
+
+    let msg = Message::from_wire(&built).unwrap();
+    assert_eq!(msg.header.id, query.header.id);
+    assert!(msg.header.qr && msg.header.aa && msg.header.rd);
+    assert_eq!(msg.header.rcode, Rcode(17));
+    assert_eq!(msg.questions, query.questions);
+    assert_eq!(msg.answers.len(), 1);
+    assert_eq!(msg.authority[0].name, mail);
+    assert_eq!(msg.additional[0].name, name("x.mail.example.com"));
+    assert!(msg.edns.is_some());
+    // Names are compressed at least as well as the owned encoder does.
+    assert!(built.len() <= msg.to_wire().unwrap().len());
+}
+
+#[test]
+fn leaves_out_what_does_not_fit() {
+    let query = Message::query(name("www.example.com"), RecordType::A);
+    let wire = query.to_wire().unwrap();
+    let parsed = MessageRef::parse(&wire).unwrap();
+    let qname = parsed.question().unwrap().name;
+    let mut arena = Arena::new();
+
+    let mut resp = arena.response(&parsed, 100);
+    let mut fitted = 0;
+    while resp.answer(qname, RecordType::A, 60, &[192, 0, 2, 1]) {
+        fitted += 1;
+    }
+    let built = resp.finish();
+    assert!(built.len() <= 100);
+    let msg = Message::from_wire(built).unwrap();
+    assert!(msg.header.tc);
+    assert_eq!(msg.answers.len(), fitted);
+    // Room is kept for the OPT record.
+    assert!(msg.edns.is_some());
+}
+
+#[test]
+fn answering_in_a_grown_arena_does_not_allocate() {
+    let wire = Message::query(name("www.example.com"), RecordType::A)
+        .to_wire()
+        .unwrap();
+    let mut arena = Arena::with_capacity(512);
+    let capacity = arena.capacity();
+    let answer = |arena: &mut Arena| {
+        let query = MessageRef::parse(&wire).unwrap();
+        let question = query.question().unwrap();
+        let mut resp = arena.response(&query, 512);
+        resp.answer(question.name, RecordType::A, 300, &[192, 0, 2, 1]);
+        resp.finish().len()
+    };
+    let len = answer(&mut arena);
+
+    let before = allocations();
+    for _ in 0..100 {
+        assert_eq!(answer(&mut arena), len);
+    }
+    assert_eq!(allocations() - before, 0);
+    assert_eq!(arena.capacity(), capacity);
+
+    // The owned types allocate for the same work.
+    let before = allocations();
+    let query = Message::from_wire(&wire).unwrap();
+    let mut resp = query.response();
+    resp.answers.push(Record::new(
+        query.questions[0].name.clone(),
+        300,
+        RData::A([192, 0, 2, 1].into()),
+    ));
+    assert_eq!(resp.to_wire_limited(512).unwrap().len(), len);
+    assert!(allocations() - before > 0);
+}
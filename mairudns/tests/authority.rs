@@ -0,0 +1,171 @@
+//! Authoritative query processing (RFC 1034 §4.3.2) over a zone loaded
+//! from a zone file, directly and through the `Authority` handler.
+
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Opcode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Authority, Handler, Request};
+use mairudns::zone::{Answer, Zone};
+
+const ZONE: &str = "\
+$TTL 300
+@        IN SOA   ns1 hostmaster 1 3600 600 86400 60
+@        IN NS    ns1
+@        IN MX    10 mail
+ns1      IN A     192.0.2.53
+mail     IN A     192.0.2.25
+www      IN CNAME web
+web      IN A     192.0.2.80
+outside  IN CNAME www.example.net.
+*.wild   IN TXT   \"wildcard\"
+sub      IN NS    ns.sub
+ns.sub   IN A     192.0.2.54
+empty.nonterminal.deep IN A 192.0.2.9
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn zone() -> Zone {
+    Zone::from_master(name("example"), ZONE).unwrap()
+}
+
+fn types(records: &[Record]) -> Vec<RecordType> {
+    records.iter().map(Record::rtype).collect()
+}
+
+fn owners(records: &[Record]) -> Vec<DomainName> {
+    records.iter().map(|r| r.name.clone()).collect()
+}
+
+#[test]
+fn answers_exact_matches_with_additional_addresses() {
+    let answer = zone().lookup(&name("example"), RecordType::MX);
+    assert_eq!(answer.rcode, Rcode::NOERROR);
+    assert!(answer.authoritative);
+    assert_eq!(types(&answer.answers), vec![RecordType::MX]);
+    assert_eq!(owners(&answer.additional), vec![name("mail.example")]);
+}
+
+#[test]
+fn tells_nodata_from_nxdomain() {
+    let zone = zone();
+    let nodata: Answer = zone.lookup(&name("web.example"), RecordType::AAAA);
+    assert_eq!(nodata.rcode, Rcode::NOERROR);
+    assert!(nodata.answers.is_empty());
+    assert_eq!(types(&nodata.authority), vec![RecordType::SOA]);
+
+    // An empty non-terminal exists, so it has no data rather than no name.
+    let ent = zone.lookup(&name("nonterminal.deep.example"), RecordType::A);
+    assert_eq!(ent.rcode, Rcode::NOERROR);
+    assert!(ent.answers.is_empty());
+
+    let nx = zone.lookup(&name("missing.example"), RecordType::A);
+    assert_eq!(nx.rcode, Rcode::NXDOMAIN);
+    assert_eq!(types(&nx.authority), vec![RecordType::SOA]);
+}
+
+#[test]
+fn follows_cnames_within_the_zone_only() {
+    let zone = zone();
+    let answer = zone.lookup(&name("www.example"), RecordType::A);
+    assert_eq!(
+        owners(&answer.answers),
+        vec![name("www.example"), name("web.example")]
+    );
+    assert_eq!(
+        types(&answer.answers),
+        vec![RecordType::CNAME, RecordType::A]
+    );
+    let answer = zone.lookup(&name("outside.example"), RecordType::A);
+    assert_eq!(types(&answer.answers), vec![RecordType::CNAME]);
+    assert_eq!(answer.rcode, Rcode::NOERROR);
+    // Asking for the CNAME itself stops there.
+    let answer = zone.lookup(&name("www.example"), RecordType::CNAME);
+    assert_eq!(answer.answers.len(), 1);
+}
+
+#[test]
+fn synthesizes_wildcard_answers() {
+    let zone = zone();
+    let answer = zone.lookup(&name("anything.wild.example"), RecordType::TXT);
+    assert_eq!(answer.answers.len(), 1);
+    assert_eq!(answer.answers[0].name, name("anything.wild.example"));
+    assert_eq!(
+        answer.answers[0].rdata,
+        RData::Txt(vec![b"wildcard".to_vec()])
+    );
+    assert_eq!(
+        zone.wildcard_owner(&name("a.b.wild.example")),
+        Some(name("*.wild.example"))
+    );
+    // The wildcard owns TXT only.
+    let nodata = zone.lookup(&name("anything.wild.example"), RecordType::A);
+    assert_eq!(nodata.rcode, Rcode::NOERROR);
+    assert!(nodata.answers.is_empty());
+}
+
+#[test]
+fn refers_below_cuts_with_glue() {
+    let zone = zone();
+    for qname in &["sub.example", "host.sub.example"] {
+        let answer = zone.lookup(&name(qname), RecordType::A);
+        assert_eq!(answer.rcode, Rcode::NOERROR);
+        assert!(!answer.authoritative);
+        assert!(answer.answers.is_empty());
+        assert_eq!(types(&answer.authority), vec![RecordType::NS]);
+        assert_eq!(owners(&answer.additional), vec![name("ns.sub.example")]);
+    }
+    assert_eq!(
+        zone.find_cut(&name("deep.host.sub.example")),
+        Some(name("sub.example"))
+    );
+}
+
+fn request(message: Message) -> Request {
+    Request {
+        message,
+        src: "192.0.2.1:53000".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+#[test]
+fn the_handler_sets_flags_and_refuses_other_zones() {
+    let authority = Authority::new();
+    authority.insert(zone());
+    let resp = authority
+        .handle(&request(Message::query(name("web.example"), RecordType::A)))
+        .unwrap();
+    assert!(resp.header.aa);
+    assert_eq!(resp.answers.len(), 1);
+
+    let resp = authority
+        .handle(&request(Message::query(
+            name("host.sub.example"),
+            RecordType::A,
+        )))
+        .unwrap();
+    assert!(!resp.header.aa);
+    assert_eq!(types(&resp.authority), vec![RecordType::NS]);
+
+    let resp = authority
+        .handle(&request(Message::query(name("example.net"), RecordType::A)))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::REFUSED);
+
+    let mut status = Message::query(name("example"), RecordType::SOA);
+    status.header.opcode = Opcode::STATUS;
+    let resp = authority.handle(&request(status)).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOTIMP);
+
+    let empty = Authority::new();
+    empty.insert(Zone::new(name("example")));
+    let resp = empty
+        .handle(&request(Message::query(name("example"), RecordType::SOA)))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+}
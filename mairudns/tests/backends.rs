@@ -0,0 +1,693 @@
+//! Zones served from storage backends: each must answer exactly as the
+//! same zone held in memory, before and after a change, and the cache in
+//! front of them must spare the database repeated reads.
+//!
+//! Redis is stood in for by a small server speaking just the RESP
+//! commands the backend uses, with `WATCH` and `MULTI` transactions.
+//! Mapped zones are compiled to temporary files.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Authority, Handler, Request, TransferAcl};
+use mairudns::zone::{
+    compile, Backend, BackendCache, BackendError, CachedBackend, Diff, MappedBackend, RedisBackend,
+    Zone,
+};
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+$TTL 300
+@ IN SOA ns1 admin 1 3600 600 86400 120
+@ IN NS ns1
+@ IN MX 10 mail
+ns1 IN A 192.0.2.1
+mail IN A 192.0.2.2
+mail IN AAAA 2001:db8::2
+www IN CNAME web
+web IN A 192.0.2.3
+web IN TXT \"hello world\"
+a.b.c IN A 192.0.2.4
+*.wild IN A 192.0.2.5
+sub IN NS ns.sub
+ns.sub IN A 192.0.2.6
+out IN CNAME www.example.net.
+_sip._tcp IN SRV 0 5 5060 mail
+";
+
+const QUESTIONS: &[(&str, RecordType)] = &[
+    ("example.com", RecordType::SOA),
+    ("example.com", RecordType::MX),
+    ("example.com", RecordType::ANY),
+    ("www.example.com", RecordType::A),
+    ("www.example.com", RecordType::CNAME),
+    ("web.example.com", RecordType::TXT),
+    ("web.example.com", RecordType::AAAA),
+    ("b.c.example.com", RecordType::A),
+    ("c.example.com", RecordType::A),
+    ("nope.example.com", RecordType::A),
+    ("x.wild.example.com", RecordType::A),
+    ("x.y.wild.example.com", RecordType::A),
+    ("x.wild.example.com", RecordType::MX),
+    ("sub.example.com", RecordType::A),
+    ("deep.sub.example.com", RecordType::A),
+    ("sub.example.com", RecordType::DS),
+    ("out.example.com", RecordType::A),
+    ("_sip._tcp.example.com", RecordType::SRV),
+    ("WWW.EXAMPLE.COM", RecordType::A),
+    ("example.org", RecordType::A),
+];
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(qname: &str, qtype: RecordType) -> Request {
+    Request {
+        message: Message::query(name(qname), qtype),
+        src: "127.0.0.1:5000".parse().unwrap(),
+        protocol: Protocol::Tcp,
+        key: None,
+    }
+}
+
+/// A response with its ID cleared and its sections in a fixed order.
+fn normalized(resp: Option<Message>) -> Message {
+    let mut resp = resp.unwrap();
+    resp.header.id = 0;
+    for section in [&mut resp.answers, &mut resp.authority, &mut resp.additional] {
+        section.sort_by_key(|rr| rr.to_string());
+    }
+    resp
+}
+
+fn assert_same_answers(memory: &Authority, stored: &Authority) {
+    for (qname, qtype) in QUESTIONS {
+        assert_eq!(
+            normalized(memory.handle(&request(qname, *qtype))),
+            normalized(stored.handle(&request(qname, *qtype))),
+            "{} {}",
+            qname,
+            qtype
+        );
+    }
+}
+
+/// The change of `web.example.com. A`, as serial 1 to 2.
+fn change(zone: &Zone) -> (Zone, Diff) {
+    let mut changed = zone.clone();
+    changed
+        .replace_rrset(
+            &name("web.example.com"),
+            RecordType::A,
+            vec![Record::new(
+                name("web.example.com"),
+                60,
+                RData::A([198, 51, 100, 1].into()),
+            )],
+        )
+        .unwrap();
+    let diff = changed.journal().since(1).unwrap()[0].clone();
+    (changed, diff)
+}
+
+/// Stores the zone in `backend` and checks it against the zone in memory,
+/// then applies a change to both and checks again.
+fn answers_like_memory(backend: Arc<dyn Backend>) {
+    let zone = Zone::from_master(name("example.com"), ZONE).unwrap();
+    backend.store(&zone).unwrap();
+    assert_eq!(backend.zones().unwrap(), vec![name("example.com")]);
+    assert_eq!(backend.serial(&name("example.com")).unwrap(), Some(1));
+    assert!(matches!(
+        backend.records(&name("example.org")),
+        Err(BackendError::NoZone(_))
+    ));
+
+    let memory = Authority::new();
+    memory.insert(zone.clone());
+    let stored = Authority::new();
+    stored.insert_backend(name("example.com"), backend.clone());
+    assert_same_answers(&memory, &stored);
+    assert_eq!(
+        memory.wildcard_owner(&name("x.wild.example.com")),
+        stored.wildcard_owner(&name("x.wild.example.com"))
+    );
+
+    stored.set_transfer_acl(
+        name("example.com"),
+        TransferAcl {
+            addresses: vec!["127.0.0.0/8".parse().unwrap()],
+            keys: vec![],
+        },
+    );
+    let axfr = stored
+        .handle(&request("example.com", RecordType::AXFR))
+        .unwrap();
+    assert_eq!(axfr.answers.len(), zone.len() + 1);
+
+    let (changed, diff) = change(&zone);
+    backend.apply(&name("example.com"), &diff).unwrap();
+    assert_eq!(backend.serial(&name("example.com")).unwrap(), Some(2));
+    // The difference no longer starts from the zone's SOA.
+    assert!(backend.apply(&name("example.com"), &diff).is_err());
+    memory.insert(changed);
+    assert_same_answers(&memory, &stored);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_answers_like_memory() {
+    use mairudns::zone::SqliteBackend;
+
+    let path = std::env::temp_dir().join(format!("mairu-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    answers_like_memory(Arc::new(SqliteBackend::open(&path).unwrap()));
+    // Storing the zone again replaces what the database held.
+    answers_like_memory(Arc::new(CachedBackend::new(
+        SqliteBackend::open(&path).unwrap(),
+        BackendCache::default(),
+    )));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn redis_answers_like_memory() {
+    let redis = FakeRedis::start(None);
+    answers_like_memory(Arc::new(RedisBackend::new(redis.addr).prefix("t")));
+    let redis = FakeRedis::start(Some("hunter2"));
+    answers_like_memory(Arc::new(CachedBackend::new(
+        RedisBackend::new(redis.addr).password("hunter2"),
+        BackendCache::default(),
+    )));
+}
+
+#[test]
+fn redis_keys_follow_the_documented_layout() {
+    let redis = FakeRedis::start(None);
+    let backend = RedisBackend::new(redis.addr);
+    backend
+        .store(&Zone::from_master(name("example.com"), ZONE).unwrap())
+        .unwrap();
+    let state = redis.state.lock().unwrap();
+    assert!(state.sets[&b"mairu:zones"[..]].contains(&b"example.com."[..]));
+    assert!(state.sets[&b"mairu:names:example.com."[..]].contains(&b"com.example.www."[..]));
+    let node = &state.hashes[&b"mairu:node:example.com.:mail.example.com."[..]];
+    assert_eq!(node[&b"A"[..]], b"300 IN 192.0.2.2");
+    assert_eq!(node[&b"AAAA"[..]], b"300 IN 2001:db8::2");
+}
+
+#[test]
+fn redis_refuses_a_wrong_password_and_interleaved_changes() {
+    let redis = FakeRedis::start(Some("hunter2"));
+    let backend = RedisBackend::new(redis.addr).password("wrong");
+    assert!(matches!(backend.zones(), Err(BackendError::Storage(_))));
+
+    let backend = RedisBackend::new(redis.addr).password("hunter2");
+    let zone = Zone::from_master(name("example.com"), ZONE).unwrap();
+    backend.store(&zone).unwrap();
+    let (_, diff) = change(&zone);
+    // Another server writes the apex between WATCH and EXEC.
+    redis.interfere.store(true, Ordering::SeqCst);
+    match backend.apply(&name("example.com"), &diff) {
+        Err(BackendError::Storage(e)) => assert_eq!(e, "the zone changed while being written"),
+        other => panic!("{:?}", other),
+    }
+    assert_eq!(backend.serial(&name("example.com")).unwrap(), Some(1));
+    backend.apply(&name("example.com"), &diff).unwrap();
+    assert_eq!(backend.serial(&name("example.com")).unwrap(), Some(2));
+}
+
+#[test]
+fn redis_reconnects_after_the_connection_drops() {
+    let redis = FakeRedis::start(None);
+    let backend = RedisBackend::new(redis.addr);
+    backend
+        .store(&Zone::from_master(name("example.com"), ZONE).unwrap())
+        .unwrap();
+    redis.drop_connections.store(true, Ordering::SeqCst);
+    assert!(backend.zones().is_err());
+    assert_eq!(backend.zones().unwrap(), vec![name("example.com")]);
+}
+
+/// A path for a compiled zone, unique to the test.
+fn mapped_path(test: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("mairu-{}-{}.mzone", test, std::process::id()))
+}
+
+#[test]
+fn mapped_answers_like_memory() {
+    let path = mapped_path("answers");
+    let empty = Zone::from_master(
+        name("example.com"),
+        "@ 300 IN SOA ns1 admin 1 3600 600 86400 120\n",
+    )
+    .unwrap();
+    compile(&empty, &path).unwrap();
+    let backend = MappedBackend::open(&path).unwrap();
+    assert_eq!(backend.origin(), name("example.com"));
+    answers_like_memory(Arc::new(backend));
+    // A zone of another origin cannot be stored in its place.
+    let backend = MappedBackend::open(&path).unwrap();
+    let other = Zone::from_master(name("example.org"), "@ 300 IN SOA ns h 1 1 1 1 1\n").unwrap();
+    assert!(matches!(
+        backend.store(&other),
+        Err(BackendError::NoZone(_))
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn mapped_files_are_read_in_place_and_checked() {
+    let path = mapped_path("format");
+    let zone = Zone::from_master(name("example.com"), ZONE).unwrap();
+    compile(&zone, &path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(&bytes[..10], b"MAIRUZN\0\0\x01");
+
+    // Names below the origin exist if anything is at or under them.
+    let backend = MappedBackend::open(&path).unwrap();
+    let origin = name("example.com");
+    for (exists, qname) in [
+        (true, "b.c.example.com"),
+        (true, "C.example.com"),
+        (false, "d.example.com"),
+        (false, "www.example.org"),
+    ] {
+        assert_eq!(backend.name_exists(&origin, &name(qname)).unwrap(), exists);
+    }
+
+    // A second reader sees a change once it maps the file again.
+    let reader = MappedBackend::open(&path).unwrap();
+    let (_, diff) = change(&zone);
+    backend.apply(&origin, &diff).unwrap();
+    assert_eq!(reader.serial(&origin).unwrap(), Some(1));
+    reader.reload().unwrap();
+    assert_eq!(reader.serial(&origin).unwrap(), Some(2));
+
+    let junk = mapped_path("junk");
+    std::fs::write(&junk, b"junk").unwrap();
+    assert!(matches!(
+        MappedBackend::open(&junk),
+        Err(BackendError::Data(e)) if e.ends_with("not a compiled zone")
+    ));
+    std::fs::write(&junk, &bytes[..bytes.len() - 3]).unwrap();
+    assert!(matches!(
+        MappedBackend::open(&junk),
+        Err(BackendError::Data(_))
+    ));
+    let mut newer = bytes.clone();
+    newer[9] = 2;
+    std::fs::write(&junk, &newer).unwrap();
+    assert!(matches!(
+        MappedBackend::open(&junk),
+        Err(BackendError::Data(e)) if e.ends_with("unsupported format version 2")
+    ));
+    std::fs::remove_file(&junk).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// A backend counting the reads that reach it.
+#[derive(Debug)]
+struct Counting<B> {
+    inner: B,
+    reads: AtomicUsize,
+}
+
+impl<B: Backend> Backend for Counting<B> {
+    fn zones(&self) -> Result<Vec<DomainName>, BackendError> {
+        self.inner.zones()
+    }
+
+    fn rrset(
+        &self,
+        zone: &DomainName,
+        name: &DomainName,
+        rtype: RecordType,
+    ) -> Result<Vec<Record>, BackendError> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.rrset(zone, name, rtype)
+    }
+
+    fn name_exists(&self, zone: &DomainName, name: &DomainName) -> Result<bool, BackendError> {
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        self.inner.name_exists(zone, name)
+    }
+
+    fn records(&self, zone: &DomainName) -> Result<Vec<Record>, BackendError> {
+        self.inner.records(zone)
+    }
+
+    fn apply(&self, zone: &DomainName, diff: &Diff) -> Result<(), BackendError> {
+        self.inner.apply(zone, diff)
+    }
+
+    fn store(&self, zone: &Zone) -> Result<(), BackendError> {
+        self.inner.store(zone)
+    }
+}
+
+#[test]
+fn the_cache_keeps_hot_names_until_changed_or_stale() {
+    let redis = FakeRedis::start(None);
+    let counting = Counting {
+        inner: RedisBackend::new(redis.addr),
+        reads: AtomicUsize::new(0),
+    };
+    let limits = BackendCache {
+        capacity: 100,
+        ttl: Duration::from_millis(300),
+    };
+    let cached = Arc::new(CachedBackend::new(counting, limits));
+    let zone = Zone::from_master(name("example.com"), ZONE).unwrap();
+    cached.store(&zone).unwrap();
+    let authority = Authority::new();
+    authority.insert_backend(name("example.com"), cached.clone());
+    let reads = || cached.inner().reads.load(Ordering::SeqCst);
+
+    let first = authority.handle(&request("www.example.com", RecordType::A));
+    let cold = reads();
+    assert!(cold > 0);
+    let again = authority.handle(&request("www.example.com", RecordType::A));
+    assert_eq!(normalized(first), normalized(again));
+    assert_eq!(reads(), cold);
+
+    // A change made through the cache shows at once.
+    let (_, diff) = change(&zone);
+    cached.apply(&name("example.com"), &diff).unwrap();
+    let resp = authority
+        .handle(&request("web.example.com", RecordType::A))
+        .unwrap();
+    assert_eq!(resp.answers[0].rdata, RData::A([198, 51, 100, 1].into()));
+
+    // One made to the database directly shows once the answer is stale.
+    let (twice_changed, diff) = {
+        let (mut changed, _) = change(&zone);
+        changed
+            .replace_rrset(&name("web.example.com"), RecordType::A, Vec::new())
+            .unwrap();
+        let diff = changed.journal().since(2).unwrap()[0].clone();
+        (changed, diff)
+    };
+    cached
+        .inner()
+        .inner
+        .apply(&name("example.com"), &diff)
+        .unwrap();
+    let resp = authority
+        .handle(&request("web.example.com", RecordType::A))
+        .unwrap();
+    assert_eq!(resp.answers.len(), 1);
+    thread::sleep(Duration::from_millis(400));
+    let resp = authority
+        .handle(&request("web.example.com", RecordType::A))
+        .unwrap();
+    assert!(resp.answers.is_empty());
+    assert_eq!(twice_changed.serial(), Some(3));
+    assert_eq!(cached.serial(&name("example.com")).unwrap(), Some(3));
+}
+
+/// What the fake Redis holds. Sets and sorted sets are kept alike, as
+/// every score the backend writes is zero and sorted sets then order
+/// their members lexically.
+#[derive(Default)]
+struct State {
+    sets: HashMap<Vec<u8>, BTreeSet<Vec<u8>>>,
+    hashes: HashMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+    /// Bumped on every write, for WATCH.
+    versions: HashMap<Vec<u8>, u64>,
+}
+
+impl State {
+    fn touch(&mut self, key: &[u8]) {
+        *self.versions.entry(key.to_vec()).or_default() += 1;
+    }
+
+    fn version(&self, key: &[u8]) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+}
+
+struct FakeRedis {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    /// Makes the next WATCH see its key written by someone else.
+    interfere: Arc<AtomicBool>,
+    /// Makes the server close every connection once, on its next command.
+    drop_connections: Arc<AtomicBool>,
+}
+
+impl FakeRedis {
+    fn start(password: Option<&'static str>) -> FakeRedis {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let redis = FakeRedis {
+            addr: listener.local_addr().unwrap(),
+            state: Arc::default(),
+            interfere: Arc::default(),
+            drop_connections: Arc::default(),
+        };
+        let state = redis.state.clone();
+        let interfere = redis.interfere.clone();
+        let drop_connections = redis.drop_connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let session = Session {
+                    password,
+                    authenticated: password.is_none(),
+                    state: state.clone(),
+                    interfere: interfere.clone(),
+                    drop_connections: drop_connections.clone(),
+                    queued: None,
+                    watched: Vec::new(),
+                };
+                let stream = stream.unwrap();
+                thread::spawn(move || session.serve(stream));
+            }
+        });
+        redis
+    }
+}
+
+struct Session {
+    password: Option<&'static str>,
+    authenticated: bool,
+    state: Arc<Mutex<State>>,
+    interfere: Arc<AtomicBool>,
+    drop_connections: Arc<AtomicBool>,
+    /// Commands queued since MULTI.
+    queued: Option<Vec<Vec<Vec<u8>>>>,
+    watched: Vec<(Vec<u8>, u64)>,
+}
+
+fn bulk(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+fn array<'a>(out: &mut Vec<u8>, items: impl ExactSizeIterator<Item = &'a Vec<u8>>) {
+    out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+    for item in items {
+        bulk(out, item);
+    }
+}
+
+fn integer(out: &mut Vec<u8>, n: usize) {
+    out.extend_from_slice(format!(":{}\r\n", n).as_bytes());
+}
+
+/// Whether `member` is within the ZRANGEBYLEX bound `bound`, taken as a
+/// minimum if `min`.
+fn within(member: &[u8], bound: &[u8], min: bool) -> bool {
+    match bound.split_first() {
+        Some((b'-', _)) => min,
+        Some((b'+', _)) => !min,
+        Some((b'[', b)) if min => member >= b,
+        Some((b'[', b)) => member <= b,
+        Some((b'(', b)) if min => member > b,
+        Some((b'(', b)) => member < b,
+        _ => false,
+    }
+}
+
+impl Session {
+    fn serve(mut self, stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        while let Some(args) = read_command(&mut reader) {
+            if self.drop_connections.swap(false, Ordering::SeqCst) {
+                return;
+            }
+            let mut out = Vec::new();
+            self.command(&args, &mut out);
+            if writer.write_all(&out).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn command(&mut self, args: &[Vec<u8>], out: &mut Vec<u8>) {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        if name == "AUTH" {
+            self.authenticated = Some(&args[1][..]) == self.password.map(str::as_bytes);
+            out.extend_from_slice(if self.authenticated {
+                b"+OK\r\n"
+            } else {
+                b"-WRONGPASS invalid password\r\n"
+            });
+            return;
+        }
+        if !self.authenticated {
+            out.extend_from_slice(b"-NOAUTH Authentication required.\r\n");
+            return;
+        }
+        match (name.as_str(), self.queued.as_mut()) {
+            ("EXEC", Some(_)) => {
+                let queued = self.queued.take().unwrap();
+                let watched = std::mem::take(&mut self.watched);
+                let state = self.state.lock().unwrap();
+                if watched.iter().any(|(key, v)| state.version(key) != *v) {
+                    out.extend_from_slice(b"*-1\r\n");
+                    return;
+                }
+                drop(state);
+                out.extend_from_slice(format!("*{}\r\n", queued.len()).as_bytes());
+                for args in queued {
+                    self.run(&args, out);
+                }
+            }
+            (_, Some(queued)) => {
+                queued.push(args.to_vec());
+                out.extend_from_slice(b"+QUEUED\r\n");
+            }
+            ("MULTI", None) => {
+                self.queued = Some(Vec::new());
+                out.extend_from_slice(b"+OK\r\n");
+            }
+            ("WATCH", None) => {
+                let mut state = self.state.lock().unwrap();
+                let version = state.version(&args[1]);
+                self.watched.push((args[1].clone(), version));
+                if self.interfere.swap(false, Ordering::SeqCst) {
+                    state.touch(&args[1]);
+                }
+                out.extend_from_slice(b"+OK\r\n");
+            }
+            ("UNWATCH", None) => {
+                self.watched.clear();
+                out.extend_from_slice(b"+OK\r\n");
+            }
+            _ => self.run(args, out),
+        }
+    }
+
+    fn run(&self, args: &[Vec<u8>], out: &mut Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let key = &args[1];
+        match name.as_str() {
+            "SMEMBERS" | "ZRANGE" => {
+                let members = state.sets.get(key).cloned().unwrap_or_default();
+                array(out, members.iter().collect::<Vec<_>>().into_iter());
+            }
+            "SISMEMBER" => {
+                let found = state.sets.get(key).is_some_and(|s| s.contains(&args[2]));
+                integer(out, found as usize);
+            }
+            "SADD" | "ZADD" => {
+                // ZADD carries a score before its member.
+                let members = if name == "SADD" {
+                    &args[2..]
+                } else {
+                    &args[3..]
+                };
+                let set = state.sets.entry(key.clone()).or_default();
+                let added = members.iter().filter(|m| set.insert(m.to_vec())).count();
+                state.touch(key);
+                integer(out, added);
+            }
+            "ZREM" => {
+                let set = state.sets.entry(key.clone()).or_default();
+                let removed = args[2..].iter().filter(|m| set.remove(*m)).count();
+                state.touch(key);
+                integer(out, removed);
+            }
+            "ZRANGEBYLEX" => {
+                let limit = match args.get(4).map(|a| a.eq_ignore_ascii_case(b"LIMIT")) {
+                    Some(true) => String::from_utf8_lossy(&args[6]).parse().unwrap(),
+                    _ => usize::MAX,
+                };
+                let found: Vec<&Vec<u8>> = state
+                    .sets
+                    .get(key)
+                    .into_iter()
+                    .flatten()
+                    .filter(|m| within(m, &args[2], true) && within(m, &args[3], false))
+                    .take(limit)
+                    .collect();
+                array(out, found.into_iter());
+            }
+            "HGET" => match state.hashes.get(key).and_then(|h| h.get(&args[2])) {
+                Some(value) => bulk(out, value),
+                None => out.extend_from_slice(b"$-1\r\n"),
+            },
+            "HGETALL" => {
+                let hash = state.hashes.get(key).cloned().unwrap_or_default();
+                let flat: Vec<&Vec<u8>> = hash.iter().flat_map(|(f, v)| vec![f, v]).collect();
+                array(out, flat.into_iter());
+            }
+            "HSET" => {
+                let hash = state.hashes.entry(key.clone()).or_default();
+                let added = hash.insert(args[2].clone(), args[3].clone()).is_none();
+                state.touch(key);
+                integer(out, added as usize);
+            }
+            "HDEL" => {
+                let hash = state.hashes.entry(key.clone()).or_default();
+                let removed = hash.remove(&args[2]).is_some();
+                if hash.is_empty() {
+                    state.hashes.remove(key);
+                }
+                state.touch(key);
+                integer(out, removed as usize);
+            }
+            "DEL" => {
+                let mut removed = 0;
+                for key in &args[1..] {
+                    let set = state.sets.remove(key).is_some();
+                    let hash = state.hashes.remove(key).is_some();
+                    removed += (set || hash) as usize;
+                    state.touch(key);
+                }
+                integer(out, removed);
+            }
+            _ => out.extend_from_slice(format!("-ERR unknown command '{}'\r\n", name).as_bytes()),
+        }
+    }
+}
+
+/// Reads one command, an array of bulk strings.
+fn read_command(reader: &mut BufReader<TcpStream>) -> Option<Vec<Vec<u8>>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Some(args)
+}
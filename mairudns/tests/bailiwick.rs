@@ -0,0 +1,278 @@
+//! Scrubbing responses of what their servers have no authority to say:
+//! answers off the CNAME and DNAME chain, authority records for other
+//! zones, glue outside a delegation and addresses nothing asked for; the
+//! RFC 2181 trust ranking; and a forwarding cache that will not replace
+//! what it holds with something it should believe less.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::clock::MockClock;
+use mairudns::message::{Message, Question};
+use mairudns::name::DomainName;
+use mairudns::resolver::{scrub, Resolver, ResolverConfig, Trust};
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{CachePolicy, CachedResponse, Forwarder, Handler, Request, Route, Security};
+use mairudns::testing::MockServer;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn rr(owner: &str, rdata: RData) -> Record {
+    Record::new(name(owner), 300, rdata)
+}
+
+fn a(owner: &str, last: u8) -> Record {
+    rr(owner, RData::A([192, 0, 2, last].into()))
+}
+
+fn ns(owner: &str, target: &str) -> Record {
+    rr(owner, RData::Ns(name(target)))
+}
+
+fn response(qname: &str, qtype: RecordType) -> Message {
+    Message::query(name(qname), qtype).response()
+}
+
+fn owners(records: &[Record]) -> Vec<String> {
+    records.iter().map(|rr| rr.name.to_string()).collect()
+}
+
+#[test]
+fn referrals_keep_only_glue_inside_the_delegation() {
+    let mut resp = response("www.example.com.", RecordType::A);
+    resp.authority.push(ns("example.com.", "ns1.example.com."));
+    resp.authority.push(ns("example.com.", "ns.other.net."));
+    resp.authority.push(ns("bank.com.", "ns.evil.com."));
+    resp.additional.push(a("ns1.example.com.", 1));
+    resp.additional.push(a("ns.other.net.", 2));
+    resp.additional.push(a("ns.evil.com.", 3));
+    resp.additional.push(a("www.bank.com.", 66));
+    // An answer the question does not lead to.
+    resp.answers.push(a("www.bank.com.", 66));
+    // Asked as a server for net., the whole response would be foreign.
+    assert_eq!(scrub(&mut resp.clone(), &name("net.")), 8);
+
+    assert_eq!(scrub(&mut resp, &name("com.")), 5);
+    assert!(resp.answers.is_empty());
+    assert_eq!(owners(&resp.authority), ["example.com.", "example.com."]);
+    // The out-of-zone name server keeps its NS record but not its
+    // address, which must be looked up from its own zone.
+    assert_eq!(owners(&resp.additional), ["ns1.example.com."]);
+    assert_eq!(Trust::of(&resp), Trust::Authority);
+}
+
+#[test]
+fn answers_follow_the_alias_chain() {
+    let mut resp = response("www.example.com.", RecordType::A);
+    resp.header.aa = true;
+    resp.answers.push(rr(
+        "www.example.com.",
+        RData::Cname(name("web.example.com.")),
+    ));
+    resp.answers.push(rr(
+        "web.example.com.",
+        RData::Cname(name("cdn.example.net.")),
+    ));
+    resp.answers.push(a("cdn.example.net.", 9));
+    resp.answers.push(a("mail.example.com.", 10));
+    resp.authority.push(ns("example.com.", "ns1.example.com."));
+    resp.additional.push(a("ns1.example.com.", 1));
+
+    // A server for example.com. may not speak for example.net.
+    let mut from_zone = resp.clone();
+    assert_eq!(scrub(&mut from_zone, &name("example.com.")), 2);
+    assert_eq!(
+        owners(&from_zone.answers),
+        ["www.example.com.", "web.example.com."]
+    );
+    assert_eq!(Trust::of(&from_zone), Trust::AuthoritativeAnswer);
+
+    // A recursive resolver may, but not for names off the chain; what is
+    // left passes a second time untouched.
+    let mut from_resolver = resp.clone();
+    assert_eq!(scrub(&mut from_resolver, &DomainName::root()), 1);
+    assert_eq!(from_resolver.answers.len(), 3);
+    assert_eq!(scrub(&mut from_resolver, &DomainName::root()), 0);
+
+    // A DNAME is kept when the question lies beneath it, and the CNAME
+    // synthesized from it leads on.
+    let mut resp = response("www.old.example.com.", RecordType::A);
+    resp.answers.push(rr(
+        "old.example.com.",
+        RData::Dname(name("new.example.com.")),
+    ));
+    resp.answers.push(rr(
+        "www.old.example.com.",
+        RData::Cname(name("www.new.example.com.")),
+    ));
+    resp.answers.push(a("www.new.example.com.", 5));
+    resp.answers.push(rr(
+        "other.example.com.",
+        RData::Dname(name("x.example.com.")),
+    ));
+    assert_eq!(scrub(&mut resp, &name("example.com.")), 1);
+    assert_eq!(
+        owners(&resp.answers),
+        [
+            "old.example.com.",
+            "www.old.example.com.",
+            "www.new.example.com."
+        ]
+    );
+}
+
+#[test]
+fn authority_and_additional_sections_are_held_to_the_question() {
+    let mut resp = response("example.com.", RecordType::MX);
+    resp.header.aa = true;
+    resp.answers.push(rr(
+        "example.com.",
+        RData::Mx {
+            preference: 10,
+            exchange: name("mx.example.com."),
+        },
+    ));
+    resp.authority.push(ns("example.com.", "ns1.example.com."));
+    resp.authority
+        .push(ns("sub.example.com.", "ns.sub.example.com."));
+    resp.additional.push(a("mx.example.com.", 25));
+    resp.additional.push(a("ns1.example.com.", 1));
+    resp.additional.push(a("www.example.com.", 80));
+    resp.additional.push(rr(
+        "mx.example.com.",
+        RData::Txt(vec![b"not an address".to_vec()]),
+    ));
+    assert_eq!(scrub(&mut resp, &name("example.com.")), 3);
+    // NS records only for zones above the question.
+    assert_eq!(owners(&resp.authority), ["example.com."]);
+    // Addresses only for the exchange and the name server.
+    assert_eq!(
+        owners(&resp.additional),
+        ["mx.example.com.", "ns1.example.com."]
+    );
+
+    // A negative answer keeps its SOA; one for an unrelated zone goes.
+    let mut resp = response("nope.example.com.", RecordType::A);
+    resp.header.aa = true;
+    let soa = |owner: &str| {
+        rr(
+            owner,
+            RData::Soa(Soa {
+                mname: name("ns1.example.com."),
+                rname: name("hostmaster.example.com."),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 60,
+            }),
+        )
+    };
+    resp.authority.push(soa("example.com."));
+    resp.authority.push(soa("sub.example.com."));
+    assert_eq!(scrub(&mut resp, &name("example.com.")), 1);
+    assert_eq!(owners(&resp.authority), ["example.com."]);
+    assert_eq!(Trust::of(&resp), Trust::AuthoritativeAuthority);
+
+    // Without a question there is nothing to hold records to.
+    let mut bare = Message::default();
+    bare.answers.push(a("www.example.com.", 1));
+    assert_eq!(scrub(&mut bare, &name("example.com.")), 0);
+}
+
+#[test]
+fn trust_ranks_answers_above_authority_and_authoritative_above_relayed() {
+    assert!(Trust::Additional < Trust::Authority);
+    assert!(Trust::Authority < Trust::Answer);
+    assert!(Trust::Answer < Trust::AuthoritativeAuthority);
+    assert!(Trust::AuthoritativeAuthority < Trust::AuthoritativeAnswer);
+
+    let mut resp = response("www.example.com.", RecordType::A);
+    assert_eq!(Trust::of(&resp), Trust::Authority);
+    resp.answers.push(a("www.example.com.", 1));
+    assert_eq!(Trust::of(&resp), Trust::Answer);
+    resp.header.aa = true;
+    assert_eq!(Trust::of(&resp), Trust::AuthoritativeAnswer);
+}
+
+/// A cached A answer for www.example.com. from 192.0.2.`last`, for
+/// `expires_in`, authoritative if `aa`.
+fn cached(last: u8, aa: bool, expires_in: Duration) -> CachedResponse {
+    let mut response = response("www.example.com.", RecordType::A);
+    response.header.aa = aa;
+    response.answers.push(a("www.example.com.", last));
+    CachedResponse {
+        question: Question::new(name("www.example.com."), RecordType::A),
+        dnssec_ok: false,
+        checking_disabled: false,
+        response,
+        security: Security::Unchecked,
+        expires_in,
+    }
+}
+
+fn ask(forwarder: &Forwarder, qname: &str) -> Message {
+    forwarder
+        .handle(&Request {
+            message: Message::query(name(qname), RecordType::A),
+            src: "192.0.2.1:5300".parse().unwrap(),
+            protocol: Protocol::Udp,
+            key: None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn forwarding_caches_keep_the_more_trusted_answer() {
+    // An upstream that slips an unrelated record into every answer.
+    let upstream = MockServer::builder()
+        .handler(|request: &Request| {
+            let q = request.message.question()?;
+            let mut resp = request.message.response();
+            resp.answers.push(Record::new(
+                q.name.clone(),
+                300,
+                RData::A([192, 0, 2, 7].into()),
+            ));
+            resp.answers.push(a("www.bank.com.", 66));
+            resp.additional.push(a("ns.bank.com.", 67));
+            Some(resp)
+        })
+        .start()
+        .unwrap();
+    let clock = MockClock::new(1_700_000_000);
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![upstream.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    let forwarder = Forwarder::new().route(
+        Route::new(DomainName::root(), resolver)
+            .clock(Arc::new(clock.clone()))
+            .cache_policy(CachePolicy::default()),
+    );
+
+    // What is forwarded, and cached, is scrubbed.
+    let resp = ask(&forwarder, "mail.example.com.");
+    assert_eq!(owners(&resp.answers), ["mail.example.com."]);
+    assert!(resp.additional.is_empty());
+
+    let route = &forwarder.routes()[0];
+    let minute = Duration::from_secs(60);
+    assert!(route.restore(cached(1, true, minute)));
+    // A relayed answer does not replace an authoritative one...
+    assert!(!route.restore(cached(2, false, minute)));
+    let answer = |forwarder: &Forwarder| ask(forwarder, "www.example.com.").answers[0].clone();
+    assert_eq!(answer(&forwarder).rdata, RData::A([192, 0, 2, 1].into()));
+    // ...but one as trusted does, and anything does once it has expired.
+    assert!(route.restore(cached(3, true, minute)));
+    assert_eq!(answer(&forwarder).rdata, RData::A([192, 0, 2, 3].into()));
+    clock.advance(minute);
+    assert!(route.restore(cached(4, false, minute)));
+    assert_eq!(answer(&forwarder).rdata, RData::A([192, 0, 2, 4].into()));
+    assert_eq!(upstream.count(Protocol::Udp), 1);
+}
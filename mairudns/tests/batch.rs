@@ -0,0 +1,210 @@
+//! Resolving batches of names against a server run by the crate: every
+//! name's outcome once, read-ahead bounded by the concurrency, pacing,
+//! cancelling, and retries with and without a budget.
+
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::message::Rcode;
+use mairudns::name::DomainName;
+use mairudns::resolver::{BatchOptions, Error, Progress, Resolver, ResolverConfig};
+use mairudns::rr::{RData, RecordType};
+use mairudns::server::{Authority, Server};
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+*.hosts IN A 192.0.2.1
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn resolver(servers: Vec<SocketAddr>) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers,
+        timeout: Duration::from_millis(200),
+        attempts: 1,
+        ..ResolverConfig::default()
+    })
+}
+
+/// Serves example. over UDP and TCP, returning a resolver that asks it.
+fn serve() -> Resolver {
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example."), ZONE).unwrap());
+    let mut server = Server::new(authority);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Udp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    resolver(vec![addr])
+}
+
+fn hosts(n: usize) -> Vec<DomainName> {
+    (0..n)
+        .map(|i| name(&format!("h{}.hosts.example.", i)))
+        .collect()
+}
+
+/// A resolver whose only server refuses every query at the transport.
+fn unreachable() -> Resolver {
+    resolver(vec!["127.0.0.1:9".parse().unwrap()])
+}
+
+#[test]
+fn yields_each_name_once_with_its_outcome() {
+    let resolver = serve();
+    let mut names = hosts(500);
+    names.push(name("nope.example."));
+    names.push(name("ns1.example."));
+    let mut batch = resolver.resolve_many(names.clone(), RecordType::A, &BatchOptions::default());
+    let mut seen = BTreeSet::new();
+    for (name, outcome) in &mut batch {
+        match outcome {
+            Ok(records) if name == self::name("ns1.example.") => {
+                assert_eq!(records[0].rdata, RData::A([192, 0, 2, 53].into()));
+            }
+            Ok(records) => {
+                assert_eq!(records.len(), 1);
+                assert_eq!(records[0].name, name);
+            }
+            Err(e) => {
+                assert_eq!(name, self::name("nope.example."));
+                assert!(matches!(e, Error::Rcode(Rcode::NXDOMAIN)));
+            }
+        }
+        assert!(seen.insert(name));
+    }
+    assert_eq!(seen, names.into_iter().collect());
+    // NXDOMAIN is an answer, not a failure that may pass.
+    assert_eq!(
+        batch.progress(),
+        Progress {
+            started: 502,
+            completed: 502,
+            failed: 1,
+            retries: 0,
+        }
+    );
+}
+
+#[test]
+fn reads_ahead_no_further_than_the_concurrency() {
+    let resolver = serve();
+    let options = BatchOptions {
+        concurrency: 4,
+        ..BatchOptions::default()
+    };
+    let mut batch = resolver.resolve_many(hosts(100), RecordType::A, &options);
+    assert!(batch.next().unwrap().1.is_ok());
+    thread::sleep(Duration::from_millis(300));
+    // One taken, a channel of four outcomes, and four lookups blocked on
+    // it.
+    let progress = batch.progress();
+    assert!(progress.started <= 9, "{:?}", progress);
+    assert_eq!(batch.count(), 99);
+}
+
+#[test]
+fn paces_lookups_and_stops_when_cancelled() {
+    let resolver = serve();
+    let options = BatchOptions {
+        rate: Some(100.0),
+        ..BatchOptions::default()
+    };
+    let start = Instant::now();
+    let mut batch = resolver.resolve_many(hosts(50), RecordType::A, &options);
+    let cancel = batch.cancel_handle();
+    let mut yielded = 0;
+    while batch.next().is_some() {
+        yielded += 1;
+        if yielded == 20 {
+            thread::spawn({
+                let cancel = cancel.clone();
+                move || cancel.cancel()
+            })
+            .join()
+            .unwrap();
+        }
+    }
+    // Twenty lookups at 100 a second take most of 200ms; those under way
+    // when the batch was cancelled still come, and no more.
+    assert!(start.elapsed() >= Duration::from_millis(180));
+    assert!((20..25).contains(&yielded), "{}", yielded);
+    let progress = batch.progress();
+    assert_eq!(progress.started, yielded);
+    assert_eq!(progress.completed, yielded);
+}
+
+#[test]
+fn retries_transient_failures_within_the_budget() {
+    let options = BatchOptions {
+        retries: 3,
+        retry_budget: Some(5),
+        concurrency: 4,
+        ..BatchOptions::default()
+    };
+    let mut batch = unreachable().resolve_many(hosts(10), RecordType::A, &options);
+    let outcomes: Vec<_> = batch.by_ref().collect();
+    assert_eq!(outcomes.len(), 10);
+    assert!(outcomes
+        .iter()
+        .all(|(_, outcome)| matches!(outcome, Err(Error::Client(_)))));
+    assert_eq!(
+        batch.progress(),
+        Progress {
+            started: 10,
+            completed: 10,
+            failed: 10,
+            retries: 5,
+        }
+    );
+
+    // Without a budget, each name gets all its retries.
+    let options = BatchOptions {
+        retry_budget: None,
+        ..options
+    };
+    let mut batch = unreachable().resolve_many(hosts(10), RecordType::A, &options);
+    assert_eq!(batch.by_ref().count(), 10);
+    assert_eq!(batch.progress().retries, 30);
+}
+
+#[test]
+fn dropping_the_batch_stops_reading_the_input() {
+    let resolver = serve();
+    let taken = Arc::new(AtomicUsize::new(0));
+    let names = {
+        let taken = taken.clone();
+        (0..).map(move |i| {
+            taken.fetch_add(1, Ordering::SeqCst);
+            name(&format!("h{}.hosts.example.", i))
+        })
+    };
+    let options = BatchOptions {
+        concurrency: 8,
+        ..BatchOptions::default()
+    };
+    let mut batch = resolver.resolve_many(names, RecordType::A, &options);
+    assert_eq!(batch.by_ref().take(100).count(), 100);
+    drop(batch);
+    thread::sleep(Duration::from_millis(200));
+    let after_drop = taken.load(Ordering::SeqCst);
+    assert!(after_drop < 100 + 2 * 8 + 8, "{}", after_drop);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(taken.load(Ordering::SeqCst), after_drop);
+}
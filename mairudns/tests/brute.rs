@@ -0,0 +1,166 @@
+//! Brute-forcing names under a domain served by the crate: what is found
+//! and how it is reported, wildcard detection, pacing to a query rate,
+//! and servers that do not answer.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, RecordType};
+use mairudns::server::{Authority, Server};
+use mairudns::tools::brute::BruteForce;
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+mail IN AAAA 2001:db8::25
+old IN CNAME gone.example.
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// Serves example. from `text` over UDP and TCP.
+fn serve(text: &str) -> SocketAddr {
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example."), text).unwrap());
+    let mut server = Server::new(authority);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Udp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    addr
+}
+
+#[test]
+fn finds_the_names_that_answer() {
+    let addr = serve(ZONE);
+    let report = BruteForce::new(vec![addr]).run(
+        &name("example."),
+        [
+            "www",
+            "mail",
+            " old ",
+            "nope",
+            "ftp",
+            "www",
+            "bad..label",
+            "",
+            "x.other.",
+        ],
+    );
+    let found: Vec<_> = report.found.iter().map(|f| f.name.to_string()).collect();
+    assert_eq!(found, ["mail.example.", "old.example.", "www.example."]);
+    assert!(report.found.iter().all(|f| !f.wildcard));
+    assert!(report.wildcard.is_empty());
+    assert!(report.failed.is_empty());
+    assert_eq!(report.invalid, ["bad..label", "x.other."]);
+
+    // Each type's answers are gathered under the name, and a dangling
+    // CNAME is reported as the chain it is.
+    let www = &report.found[2];
+    assert_eq!(www.records.len(), 1);
+    assert_eq!(www.records[0].rdata, RData::A([192, 0, 2, 80].into()));
+    assert_eq!(report.found[0].records[0].rtype(), RecordType::AAAA);
+    let old = &report.found[1];
+    assert_eq!(old.records.len(), 1);
+    assert_eq!(old.records[0].rtype(), RecordType::CNAME);
+
+    // Five distinct names, two types each, after three probes of each.
+    assert_eq!(report.queries, 5 * 2 + 3 * 2);
+    assert!(report.queries_per_second() > 0.0);
+}
+
+#[test]
+fn flags_what_only_a_wildcard_answers() {
+    let addr = serve(&format!("{}*.example. IN A 192.0.2.99\n", ZONE));
+    let mut brute = BruteForce::new(vec![addr]);
+    brute.types = vec![RecordType::A];
+    let report = brute.run(&name("example."), ["www", "w1", "w2", "mail"]);
+    assert_eq!(report.wildcard.len(), 3);
+    assert!(report
+        .wildcard
+        .iter()
+        .all(|rr| rr.rdata == RData::A([192, 0, 2, 99].into())));
+    let flags: Vec<_> = report
+        .found
+        .iter()
+        .map(|f| (f.name.to_string(), f.wildcard))
+        .collect();
+    assert_eq!(
+        flags,
+        [
+            ("w1.example.".to_string(), true),
+            ("w2.example.".to_string(), true),
+            ("www.example.".to_string(), false),
+        ]
+    );
+
+    // Without probes nothing is known of the wildcard.
+    brute.wildcard_probes = 0;
+    let report = brute.run(&name("example."), ["w1"]);
+    assert!(report.wildcard.is_empty());
+    assert!(!report.found[0].wildcard);
+    assert_eq!(report.queries, 1);
+}
+
+#[test]
+fn paces_queries_to_the_rate() {
+    let addr = serve(ZONE);
+    let mut brute = BruteForce::new(vec![addr]);
+    brute.rate = Some(50.0);
+    brute.wildcard_probes = 0;
+    let words: Vec<String> = (0..20).map(|i| format!("w{}", i)).collect();
+    let start = Instant::now();
+    let report = brute.run(&name("example."), &words);
+    // 40 queries at 50 a second take most of a second however many
+    // threads send them.
+    assert_eq!(report.queries, 40);
+    assert!(start.elapsed() >= Duration::from_millis(750));
+    assert!(report.elapsed >= Duration::from_millis(750));
+}
+
+#[test]
+fn moves_past_servers_that_do_not_answer() {
+    let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let good = serve(ZONE);
+    let mut brute = BruteForce::new(vec![silent.local_addr().unwrap(), good]);
+    brute.timeout = Duration::from_millis(200);
+    brute.wildcard_probes = 0;
+    brute.types = vec![RecordType::A];
+    let report = brute.run(&name("example."), ["www", "ns1"]);
+    assert_eq!(report.found.len(), 2);
+    assert!(report.failed.is_empty());
+    // The name that went to the silent server first was asked twice.
+    assert_eq!(report.queries, 3);
+
+    // With nowhere else to go, the queries fail and are listed.
+    brute.servers = vec![silent.local_addr().unwrap()];
+    brute.attempts = 1;
+    let report = brute.run(&name("example."), ["www", "ns1"]);
+    assert!(report.found.is_empty());
+    assert_eq!(
+        report.failed,
+        [
+            (name("ns1.example."), RecordType::A),
+            (name("www.example."), RecordType::A),
+        ]
+    );
+
+    brute.servers.clear();
+    let report = brute.run(&name("example."), ["www"]);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.queries, 0);
+}
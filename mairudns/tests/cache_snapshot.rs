@@ -0,0 +1,225 @@
+//! Cache snapshots and warming: a forwarder warmed from a query log and
+//! saved to disk restarts with the same answers, aged by the time the
+//! snapshot spent on disk, and refuses snapshots it cannot trust.
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mairudns::client::Protocol;
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::querylog::popular_questions;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Forwarder, Handler, Request, Route};
+use mairudns::testing::MockServer;
+
+/// Length of the magic string opening a snapshot; the version byte and
+/// the time it was written follow.
+const MAGIC_LEN: usize = 14;
+
+const LOG: &str = "\
+{\"time\":\"2000-02-29T00:00:00Z\",\"qname\":\"www.example.\",\"qtype\":\"A\"}
+{\"time\":\"2000-02-29T00:00:01Z\",\"qname\":\"www.example.\",\"qtype\":\"A\"}
+short.example A
+www.corp.example AAAA
+# comments and blank lines are skipped
+
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn upstream() -> MockServer {
+    MockServer::builder()
+        .answer(
+            name("www.example"),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example"),
+                300,
+                RData::A([192, 0, 2, 1].into()),
+            )],
+        )
+        .answer(
+            name("short.example"),
+            RecordType::A,
+            vec![Record::new(
+                name("short.example"),
+                60,
+                RData::A([192, 0, 2, 2].into()),
+            )],
+        )
+        .answer(
+            name("www.corp.example"),
+            RecordType::AAAA,
+            vec![Record::new(
+                name("www.corp.example"),
+                300,
+                RData::Aaaa("2001:db8::1".parse().unwrap()),
+            )],
+        )
+        .start()
+        .unwrap()
+}
+
+/// A forwarder with a route for `corp.example` and one for the rest,
+/// both sent to `server`.
+fn forwarder(server: &MockServer) -> Forwarder {
+    let resolver = || {
+        Resolver::new(ResolverConfig {
+            servers: vec![server.addr()],
+            timeout: Duration::from_millis(500),
+            attempts: 1,
+            ..ResolverConfig::default()
+        })
+    };
+    Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver()))
+        .route(Route::new(name("corp.example"), resolver()))
+}
+
+fn cached(forwarder: &Forwarder, qname: &str, qtype: RecordType) -> Option<Message> {
+    forwarder.answer_cached(&Request {
+        message: Message::query(name(qname), qtype),
+        src: "192.0.2.53:53000".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    })
+}
+
+fn snapshot_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cache-{}-{}.snapshot", std::process::id(), test))
+}
+
+/// Moves the time `path` says it was written `by` seconds back.
+fn backdate(path: &Path, by: u64) {
+    let mut snapshot = fs::read(path).unwrap();
+    let field = &mut snapshot[MAGIC_LEN + 1..MAGIC_LEN + 9];
+    let written = u64::from_be_bytes((&*field).try_into().unwrap());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    assert!(now.as_secs() - written < 5);
+    field.copy_from_slice(&(written - by).to_be_bytes());
+    fs::write(path, snapshot).unwrap();
+}
+
+#[test]
+fn warms_from_a_query_log() {
+    let server = upstream();
+    let forwarder = forwarder(&server);
+    let questions = popular_questions(LOG.as_bytes(), 10).unwrap();
+    assert_eq!(questions[0], (name("www.example"), RecordType::A));
+    assert_eq!(questions.len(), 3);
+
+    assert_eq!(forwarder.warm(&questions), 3);
+    assert_eq!(server.received().len(), 3);
+    for (qname, qtype) in &questions {
+        assert!(cached(&forwarder, &qname.to_string(), *qtype).is_some());
+    }
+    assert_eq!(forwarder.routes()[0].cache_entries(None).len(), 1);
+    assert_eq!(forwarder.warm(&[]), 0);
+}
+
+#[test]
+fn restarts_with_the_saved_answers_aged() {
+    let server = upstream();
+    let first = forwarder(&server);
+    first.warm(&popular_questions(LOG.as_bytes(), 10).unwrap());
+    let path = snapshot_path("aged");
+    assert_eq!(first.save_cache(&path).unwrap(), 3);
+    let mut partial = path.clone().into_os_string();
+    partial.push(".tmp");
+    assert!(!Path::new(&partial).exists());
+
+    // Restored as saved, the answers need no upstream.
+    let second = forwarder(&server);
+    assert_eq!(second.load_cache(&path).unwrap(), 3);
+    let resp = second
+        .handle(&Request {
+            message: Message::query(name("www.corp.example"), RecordType::AAAA),
+            src: "192.0.2.53:53000".parse().unwrap(),
+            protocol: Protocol::Udp,
+            key: None,
+        })
+        .unwrap();
+    assert_eq!(resp.answers.len(), 1);
+    assert_eq!(server.received().len(), 3);
+
+    // Two minutes on disk age what is left and drop what has expired.
+    backdate(&path, 120);
+    let third = forwarder(&server);
+    assert_eq!(third.load_cache(&path).unwrap(), 2);
+    let resp = cached(&third, "www.example", RecordType::A).unwrap();
+    assert!(resp.answers[0].ttl <= 180, "{}", resp.answers[0].ttl);
+    assert!(cached(&third, "short.example", RecordType::A).is_none());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn skips_routes_that_are_gone() {
+    let server = upstream();
+    let first = forwarder(&server);
+    first.warm(&popular_questions(LOG.as_bytes(), 10).unwrap());
+    let path = snapshot_path("routes");
+    first.save_cache(&path).unwrap();
+
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    let other = Forwarder::new().route(Route::new(name("corp.example"), resolver));
+    assert_eq!(other.load_cache(&path).unwrap(), 1);
+    assert!(cached(&other, "www.corp.example", RecordType::AAAA).is_some());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn refuses_snapshots_it_cannot_read() {
+    let server = upstream();
+    let first = forwarder(&server);
+    first.warm(&[(name("www.example"), RecordType::A)]);
+    let path = snapshot_path("refused");
+    first.save_cache(&path).unwrap();
+    let good = fs::read(&path).unwrap();
+    let load = |bytes: &[u8]| {
+        fs::write(&path, bytes).unwrap();
+        forwarder(&server).load_cache(&path).unwrap_err()
+    };
+
+    let mut bytes = good.clone();
+    bytes[0] = b'X';
+    let err = load(&bytes);
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "bad cache snapshot: not a snapshot");
+
+    let mut bytes = good.clone();
+    bytes[MAGIC_LEN] = 99;
+    assert_eq!(
+        load(&bytes).to_string(),
+        "bad cache snapshot: unknown version"
+    );
+
+    // A response longer than any message is refused before it is read.
+    let mut bytes = good.clone();
+    let entry = MAGIC_LEN + 9;
+    let suffix = usize::from(u16::from_be_bytes([bytes[entry], bytes[entry + 1]]));
+    let size = entry + 2 + suffix + 5;
+    bytes[size..size + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert_eq!(load(&bytes).to_string(), "bad cache snapshot: response");
+    bytes[size..size + 4].copy_from_slice(&65536u32.to_be_bytes());
+    assert_eq!(load(&bytes).to_string(), "bad cache snapshot: response");
+
+    assert_eq!(
+        load(&good[..good.len() - 1]).kind(),
+        ErrorKind::UnexpectedEof
+    );
+    assert_eq!(load(&good[..5]).kind(), ErrorKind::UnexpectedEof);
+
+    fs::remove_file(&path).unwrap();
+    let err = forwarder(&server).load_cache(&path).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
@@ -0,0 +1,263 @@
+//! Classifying requests: where each answer came from and at what cost,
+//! as the forwarder notes it, the samplers that take the classes, the
+//! ring buffer of recent requests, and the telemetry configuration.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::config::{Config, TelemetryConfig};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{
+    Authority, Classify, Forwarder, Handler, HandlerExt, QueryClass, RecentQueries, Request, Route,
+    Sampler, Security, Topology,
+};
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 192.0.2.53
+www IN A 192.0.2.1
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(qname: &str, protocol: Protocol) -> Request {
+    Request {
+        message: Message::query(name(qname), RecordType::A),
+        src: "192.0.2.9:5300".parse::<SocketAddr>().unwrap(),
+        protocol,
+        key: None,
+    }
+}
+
+fn authority() -> Arc<Authority> {
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example.com."), ZONE).unwrap());
+    authority
+}
+
+/// A sampler keeping every class it is given.
+#[derive(Clone, Default)]
+struct Collect(Arc<Mutex<Vec<QueryClass>>>);
+
+impl Sampler for Collect {
+    fn sample(&self, class: &QueryClass) {
+        self.0.lock().unwrap().push(class.clone());
+    }
+}
+
+impl Collect {
+    fn take(&self) -> Vec<QueryClass> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+#[test]
+fn answers_are_classified_by_where_they_came_from() {
+    let upstream = MockServer::builder()
+        .answer(
+            name("www.example.net."),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example.net."),
+                300,
+                RData::A([198, 51, 100, 1].into()),
+            )],
+        )
+        .start()
+        .unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![upstream.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    let forwarder = Forwarder::new().route(Route::new(name("example.net."), resolver));
+    let zones = authority();
+    let collect = Collect::default();
+    let handler = (move |request: &Request| {
+        if request
+            .message
+            .question()?
+            .name
+            .is_subdomain_of(&name("example.com."))
+        {
+            zones.handle(request)
+        } else {
+            forwarder.handle(request)
+        }
+    })
+    .with(Classify::new().sampler(Arc::new(collect.clone())));
+
+    handler.handle(&request("www.example.com.", Protocol::Udp));
+    handler.handle(&request("www.example.net.", Protocol::Tcp));
+    handler.handle(&request("www.example.net.", Protocol::Udp));
+    // No route: refused without a zone or a cache.
+    handler.handle(&request("www.example.org.", Protocol::Udp));
+
+    let classes = collect.take();
+    let topologies: Vec<Topology> = classes.iter().map(|c| c.topology).collect();
+    assert_eq!(
+        topologies,
+        vec![
+            Topology::Authoritative,
+            Topology::CacheMiss,
+            Topology::CacheHit,
+            Topology::Local
+        ]
+    );
+    let depths: Vec<u32> = classes.iter().map(|c| c.recursion_depth).collect();
+    assert_eq!(depths, vec![0, 1, 0, 0]);
+
+    let first = &classes[0];
+    assert_eq!(first.client, "192.0.2.9:5300".parse().unwrap());
+    assert_eq!(first.qname, Some(name("www.example.com.")));
+    assert_eq!(first.qtype, Some(RecordType::A));
+    assert_eq!(first.rcode, Some(Rcode::NOERROR));
+    assert_eq!(first.security, Security::Unchecked);
+    let wire = authority()
+        .handle(&request("www.example.com.", Protocol::Udp))
+        .unwrap()
+        .to_wire()
+        .unwrap();
+    assert_eq!(first.response_size, wire.len());
+    assert_eq!(classes[1].protocol, Protocol::Tcp);
+    assert_eq!(classes[3].rcode, Some(Rcode::REFUSED));
+
+    // The class reads as one line.
+    let line = first.to_string();
+    assert!(
+        line.contains(" 192.0.2.9:5300 udp www.example.com. A NOERROR authoritative upstream=0 "),
+        "{}",
+        line
+    );
+}
+
+#[test]
+fn samplers_see_drops_and_nested_layers() {
+    let collect = Collect::default();
+    let dropping = (|_: &Request| None).with(Classify::new().sampler(Arc::new(collect.clone())));
+    dropping.handle(&request("www.example.com.", Protocol::Udp));
+    let classes = collect.take();
+    assert_eq!(classes[0].rcode, None);
+    assert_eq!(classes[0].response_size, 0);
+    assert_eq!(classes[0].topology, Topology::Local);
+    assert!(classes[0].to_string().contains(" dropped local "));
+
+    // Each of nested layers sees the request, and closures are samplers.
+    let outer = Collect::default();
+    let counted = Arc::new(Mutex::new(0));
+    let count = counted.clone();
+    let handler = authority()
+        .with(Classify::new().sampler(Arc::new(move |_: &QueryClass| {
+            *count.lock().unwrap() += 1;
+        })))
+        .with(Classify::new().sampler(Arc::new(outer.clone())));
+    handler.handle(&request("www.example.com.", Protocol::Udp));
+    assert_eq!(*counted.lock().unwrap(), 1);
+    assert_eq!(outer.take()[0].topology, Topology::Authoritative);
+
+    // Nothing is sampled at a rate of zero, nor without samplers.
+    let none = Collect::default();
+    let handler = authority().with(
+        Classify::new()
+            .sampler(Arc::new(none.clone()))
+            .sample_rate(0.0),
+    );
+    for _ in 0..10 {
+        assert!(handler
+            .handle(&request("www.example.com.", Protocol::Udp))
+            .is_some());
+    }
+    assert!(none.take().is_empty());
+    assert_eq!(handler.config().sample_rate, 0.0);
+}
+
+#[test]
+fn recent_queries_keep_the_last_ones() {
+    let recent = Arc::new(RecentQueries::new(2));
+    assert_eq!(recent.capacity(), 2);
+    let handler = authority().with(Classify::new().sampler(recent.clone()));
+    for qname in ["a.example.com.", "b.example.com.", "www.example.com."] {
+        handler.handle(&request(qname, Protocol::Udp));
+    }
+    let names = |classes: Vec<QueryClass>| -> Vec<DomainName> {
+        classes.into_iter().filter_map(|c| c.qname).collect()
+    };
+    assert_eq!(
+        names(recent.all()),
+        vec![name("b.example.com."), name("www.example.com.")]
+    );
+    assert_eq!(names(recent.last(1)), vec![name("www.example.com.")]);
+    assert_eq!(recent.last(10).len(), 2);
+    assert_eq!(recent.all()[0].rcode, Some(Rcode::NXDOMAIN));
+    recent.clear();
+    assert!(recent.all().is_empty());
+
+    let nothing = RecentQueries::new(0);
+    nothing.sample(&authority_class());
+    assert!(nothing.all().is_empty());
+}
+
+/// The class of one authoritative answer.
+fn authority_class() -> QueryClass {
+    let collect = Collect::default();
+    authority()
+        .with(Classify::new().sampler(Arc::new(collect.clone())))
+        .handle(&request("www.example.com.", Protocol::Udp));
+    collect.take().remove(0)
+}
+
+#[test]
+fn telemetry_comes_from_the_configuration() {
+    let telemetry = TelemetryConfig::default();
+    assert_eq!(telemetry.recent_queries, 1000);
+    assert_eq!(telemetry.sample_rate, 1.0);
+    assert!(Config::new()
+        .telemetry(telemetry.clone())
+        .validate()
+        .is_ok());
+
+    let field = |telemetry: TelemetryConfig| {
+        let problems = Config::new().telemetry(telemetry).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    assert_eq!(
+        field(TelemetryConfig {
+            sample_rate: 1.5,
+            ..telemetry.clone()
+        }),
+        "telemetry.sample-rate"
+    );
+    assert_eq!(
+        field(TelemetryConfig {
+            recent_queries: 0,
+            ..telemetry
+        }),
+        "telemetry.recent-queries"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn telemetry_reads_from_toml() {
+    let config = Config::from_toml("[telemetry]\nsample-rate = 0.25\n").unwrap();
+    assert_eq!(
+        config.telemetry,
+        Some(TelemetryConfig {
+            recent_queries: 1000,
+            sample_rate: 0.25,
+        })
+    );
+}
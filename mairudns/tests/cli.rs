@@ -0,0 +1,551 @@
+//! The `mairu-dns` binary: its exit codes, `serve --check`, `query`
+//! against a mock server, the zone file subcommands and reading packet
+//! captures.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::MockServer;
+use mairudns::tools::pcap::{DnsMessage, LinkType, Writer};
+
+const ZONE: &str = "\
+$ORIGIN example.
+$TTL 300
+@    SOA ns hostmaster 1 3600 600 86400 300
+@    NS  ns
+ns   A   192.0.2.1
+www  A   192.0.2.80
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn mairu(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mairu-dns"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+/// A scratch directory of its own for each test.
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mairu-cli-{}-{}", std::process::id(), test));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write(dir: &Path, file: &str, text: &str) -> String {
+    let path = dir.join(file);
+    fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn exit_codes_follow_the_command() {
+    let output = mairu(&[]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("usage: mairu-dns"));
+
+    let output = mairu(&["help"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("zone check <origin> <file>"));
+
+    let output = mairu(&["--version"]);
+    assert_eq!(
+        stdout(&output),
+        format!("mairu-dns {}\n", env!("CARGO_PKG_VERSION"))
+    );
+
+    let output = mairu(&["frobnicate"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "mairu-dns: unknown command \"frobnicate\"\n"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn serve_check_validates_the_configuration() {
+    let dir = scratch("serve");
+    let good = write(
+        &dir,
+        "good.toml",
+        "[[listeners]]\ntransport = \"dns\"\naddress = \"127.0.0.1:0\"\n",
+    );
+    let output = mairu(&["serve", "--config", &good, "--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output), format!("{}: OK\n", good));
+
+    let bad = write(
+        &dir,
+        "bad.toml",
+        "[[zones]]\nname = \"example\"\nfile = \"example.zone\"\ntransfer-key = \"missing\"\n",
+    );
+    let output = mairu(&["serve", "--config", &bad, "--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("zones[0].transfer-key: no key named \"missing\""),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = mairu(&["serve"]);
+    assert_eq!(stderr(&output), "mairu-dns: serve needs --config <file>\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn query_prints_the_response_like_dig() {
+    let host = name("host.example");
+    let server = MockServer::builder()
+        .answer(
+            host.clone(),
+            RecordType::A,
+            vec![Record::new(host, 300, RData::A([192, 0, 2, 1].into()))],
+        )
+        .start()
+        .unwrap();
+    let at = format!("@{}", server.addr().ip());
+    let port = server.addr().port().to_string();
+    let output = mairu(&["query", "host.example", "A", &at, "-p", &port, "+tcp"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(out.contains("status: NOERROR"), "{}", out);
+    assert!(out.contains("192.0.2.1"), "{}", out);
+    assert!(
+        out.contains(&format!(";; SERVER: {}(TCP)", server.addr())),
+        "{}",
+        out
+    );
+    assert_eq!(server.received().len(), 1);
+
+    let output = mairu(&["query", "host.example", "BOGUS", &at]);
+    assert_eq!(
+        stderr(&output),
+        "mairu-dns: unknown record type \"BOGUS\"\n"
+    );
+}
+
+#[test]
+fn zone_check_reports_problems() {
+    let dir = scratch("check");
+    let good = write(&dir, "good.zone", ZONE);
+    let output = mairu(&["zone", "check", "example", &good]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "zone example./IN: loaded serial 1, 4 records\nOK\n"
+    );
+
+    let glueless = write(&dir, "glueless.zone", &ZONE.replace("ns   A", "mx   A"));
+    let output = mairu(&["zone", "check", "example", &glueless]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("name server ns.example. has no address records"));
+
+    let stale = write(
+        &dir,
+        "stale.zone",
+        &format!("{}@ ZONEMD \\# 54 000000010101{}\n", ZONE, "00".repeat(48)),
+    );
+    let output = mairu(&["zone", "check", "example", &stale]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("zone does not match its ZONEMD digest"));
+
+    let output = mairu(&["zone", "check", "example"]);
+    assert_eq!(
+        stderr(&output),
+        "mairu-dns: usage: mairu-dns zone check <origin> <file> [--strict]\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn zone_convert_writes_canonical_form() {
+    let dir = scratch("convert");
+    let input = write(&dir, "input.zone", ZONE);
+    let output = mairu(&["zone", "convert", "example", &input]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let text = stdout(&output);
+    let mut lines = text.lines();
+    assert_eq!(lines.next(), Some("$ORIGIN example."));
+    assert!(lines.next().unwrap().contains("SOA"));
+    assert_eq!(lines.count(), 3);
+
+    // Converting the canonical form again changes nothing.
+    let canonical = dir.join("canonical.zone");
+    let canonical = canonical.to_str().unwrap();
+    let output = mairu(&["zone", "convert", "example", &input, "-o", canonical]);
+    assert!(output.status.success());
+    let again = mairu(&["zone", "convert", "example", canonical]);
+    assert_eq!(stdout(&again), text);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn zone_diff_shows_changes_and_warns_about_the_serial() {
+    let dir = scratch("diff");
+    let old = write(&dir, "old.zone", ZONE);
+    let new = write(&dir, "new.zone", &ZONE.replace("192.0.2.80", "192.0.2.81"));
+    let output = mairu(&["zone", "diff", "example", &old, &new]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("-www.example.") && lines[0].ends_with("192.0.2.80"));
+    assert!(lines[1].starts_with("+www.example.") && lines[1].ends_with("192.0.2.81"));
+    assert!(stderr(&output).contains("the serial did not increase (1 to 1)"));
+
+    let output = mairu(&["zone", "diff", "example", &old, &old]);
+    assert!(stdout(&output).is_empty() && stderr(&output).is_empty());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn zone_sign_writes_a_signed_zone() {
+    use mairudns::dnssec::{Nsec3, Nsec3Param, Rrsig, SigningKey};
+    use mairudns::zone::Zone;
+
+    let dir = scratch("sign");
+    let input = write(&dir, "example.zone", ZONE);
+    let key = SigningKey::ed25519(257, &[1; 32]).unwrap();
+    write(
+        &dir,
+        "Kexample.key",
+        &format!("example. 3600 IN DNSKEY {}\n", key.dnskey()),
+    );
+    let private = write(&dir, "Kexample.private", &key.to_bind());
+    let signed = dir.join("signed.zone");
+    let signed = signed.to_str().unwrap();
+    let output = mairu(&[
+        "zone",
+        "sign",
+        "example",
+        &input,
+        "-k",
+        &private,
+        "--nsec3",
+        "--opt-out",
+        "--jitter",
+        "0",
+        "--validity",
+        "2d",
+        "--zonemd",
+        "sha384",
+        "-o",
+        signed,
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let zone = Zone::from_master(name("example."), &fs::read_to_string(signed).unwrap()).unwrap();
+    let sigs: Vec<Rrsig> = zone
+        .records()
+        .filter_map(|rr| Rrsig::from_rdata(&rr.rdata))
+        .collect();
+    assert!(sigs
+        .iter()
+        .any(|sig| sig.type_covered == RecordType::DNSKEY));
+    assert!(sigs.iter().any(|sig| sig.type_covered == RecordType::A));
+    assert!(sigs
+        .iter()
+        .all(|sig| sig.key_tag == key.key_tag()
+            && sig.expiration - sig.inception == 2 * 86400 + 3600));
+    let param = zone
+        .rrset(&name("example."), RecordType::NSEC3PARAM)
+        .unwrap();
+    assert!(Nsec3Param::from_rdata(&param[0].rdata).is_some());
+    assert!(zone
+        .records()
+        .filter_map(|rr| Nsec3::from_rdata(&rr.rdata))
+        .all(|nsec3| nsec3.is_opt_out()));
+    assert!(zone.rrset(&name("example."), RecordType::CDS).is_some());
+    assert_eq!(zone.verify_zonemd(), Ok(true));
+
+    // Without a key, or with salt the policy does not allow.
+    let output = mairu(&["zone", "sign", "example", &input]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("usage: mairu-dns zone sign"));
+    let output = mairu(&[
+        "zone", "sign", "example", &input, "-k", &private, "--nsec3", "--salt", "aabb",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("the policy allows no NSEC3 salt"));
+    let output = mairu(&[
+        "zone",
+        "sign",
+        "example",
+        &input,
+        "-k",
+        &private,
+        "--nsec3",
+        "--iterations",
+        "10",
+    ]);
+    assert!(stderr(&output).contains("10 NSEC3 iterations are more than the policy allows"));
+    // Unless the old parameters are asked for.
+    let output = mairu(&[
+        "zone",
+        "sign",
+        "example",
+        &input,
+        "-k",
+        &private,
+        "--nsec3",
+        "--iterations",
+        "10",
+        "--salt",
+        "aabb",
+        "--legacy-nsec3",
+        "-o",
+        signed,
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let zone = Zone::from_master(name("example."), &fs::read_to_string(signed).unwrap()).unwrap();
+    let param = zone
+        .rrset(&name("example."), RecordType::NSEC3PARAM)
+        .unwrap();
+    let param = Nsec3Param::from_rdata(&param[0].rdata).unwrap();
+    assert_eq!((param.iterations, &param.salt[..]), (10, &[0xaa, 0xbb][..]));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn zone_ds_prints_the_parents_records() {
+    use mairudns::dnssec::SigningKey;
+
+    let dir = scratch("ds");
+    let ksk = SigningKey::ed25519(257, &[1; 32]).unwrap();
+    let zsk = SigningKey::ed25519(256, &[2; 32]).unwrap();
+    let ksk_file = write(
+        &dir,
+        "Kexample.key",
+        &format!("example. 3600 IN DNSKEY {}\n", ksk.dnskey()),
+    );
+    let zsk_file = write(
+        &dir,
+        "Kzone.key",
+        &format!("example. 3600 IN DNSKEY {}\n", zsk.dnskey()),
+    );
+    let output = mairu(&["zone", "ds", &ksk_file, "-d", "sha256", "-d", "sha384"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lines: Vec<String> = stdout(&output).lines().map(String::from).collect();
+    let tag = ksk.key_tag();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(&format!("example.\tIN\tDS\t{} 15 2 ", tag)));
+    assert!(lines[1].starts_with(&format!("example.\tIN\tDS\t{} 15 4 ", tag)));
+    // The private key file names the same key.
+    let private = ksk_file.replace(".key", ".private");
+    let output = mairu(&["zone", "ds", &private]);
+    assert_eq!(stdout(&output), format!("{}\n", lines[0]));
+
+    let output = mairu(&["zone", "ds", &zsk_file]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("not a key-signing key"));
+    let output = mairu(&["zone", "ds", &ksk_file, "-d", "sha1"]);
+    assert!(stderr(&output).contains("unsupported digest type \"sha1\""));
+    let output = mairu(&["zone", "ds"]);
+    assert!(stderr(&output).contains("usage: mairu-dns zone ds"));
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn zone_sshfp_prints_what_ssh_keygen_does() {
+    let dir = scratch("sshfp");
+    // A key made by `ssh-keygen`, whose `ssh-keygen -r` records these are.
+    let key = write(
+        &dir,
+        "ssh_host_ed25519_key.pub",
+        "# the host key\n\
+         ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAnFpYB+X7187FWeDHADwXm1Dzw2h358vbG1GIKl2FHf root@host\n",
+    );
+    let output = mairu(&["zone", "sshfp", "host.example.", &key]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "host.example.\tIN\tSSHFP\t4 1 c225ac54bbef174fb2d95efda3af3b07caa907fa\n\
+         host.example.\tIN\tSSHFP\t4 2 6920142ec4b63b9aa61e02cc6ee3fc33184a981ef4cad21c70b0ffc39d56a32d\n"
+    );
+
+    let bad = write(&dir, "bad.pub", "ssh-ed25519 AAAA\n");
+    let output = mairu(&["zone", "sshfp", "host.example.", &bad]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("not an OpenSSH public key"));
+    let output = mairu(&["zone", "sshfp", "host.example."]);
+    assert!(stderr(&output).contains("usage: mairu-dns zone sshfp"));
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn zone_sign_publishes_foreign_keys() {
+    use mairudns::dnssec::{Dnskey, Rrsig, SigningKey};
+    use mairudns::zone::Zone;
+
+    let dir = scratch("foreign");
+    let input = write(&dir, "example.zone", ZONE);
+    let key = SigningKey::ed25519(257, &[1; 32]).unwrap();
+    let private = write(&dir, "Kexample.private", &key.to_bind());
+    write(
+        &dir,
+        "Kexample.key",
+        &format!("example. 3600 IN DNSKEY {}\n", key.dnskey()),
+    );
+    // The other provider's key set as `dig` prints it, ours included.
+    let theirs = SigningKey::ed25519(257, &[2; 32]).unwrap();
+    let keys = write(
+        &dir,
+        "theirs.keys",
+        &format!(
+            "; <<>> DiG <<>> example. DNSKEY\n\
+             example.\t\t3600\tIN\tDNSKEY\t{}\n\
+             example.\t\t3600\tIN\tDNSKEY\t{} ; ours\n",
+            theirs.dnskey(),
+            key.dnskey()
+        ),
+    );
+    let signed = dir.join("signed.zone");
+    let signed = signed.to_str().unwrap();
+    let output = mairu(&[
+        "zone",
+        "sign",
+        "example",
+        &input,
+        "-k",
+        &private,
+        "--foreign",
+        &keys,
+        "-o",
+        signed,
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let zone = Zone::from_master(name("example."), &fs::read_to_string(signed).unwrap()).unwrap();
+    let published: Vec<Dnskey> = zone
+        .rrset(&name("example."), RecordType::DNSKEY)
+        .unwrap()
+        .iter()
+        .filter_map(|rr| Dnskey::from_rdata(&rr.rdata))
+        .collect();
+    assert_eq!(published.len(), 2);
+    assert!(published.contains(theirs.dnskey()));
+    assert!(zone
+        .records()
+        .filter_map(|rr| Rrsig::from_rdata(&rr.rdata))
+        .all(|sig| sig.key_tag == key.key_tag()));
+
+    let empty = write(&dir, "empty.keys", "; nothing\n");
+    let output = mairu(&[
+        "zone",
+        "sign",
+        "example",
+        &input,
+        "-k",
+        &private,
+        "--foreign",
+        &empty,
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("empty.keys: no DNSKEY record"));
+    let bad = write(&dir, "bad.keys", "example. 3600 IN DNSKEY 257 3\n");
+    let output = mairu(&[
+        "zone",
+        "sign",
+        "example",
+        &input,
+        "-k",
+        &private,
+        "--foreign",
+        &bad,
+    ]);
+    assert!(stderr(&output).contains("bad.keys:1: invalid DNSKEY record"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn pcap_prints_and_replays_captured_messages() {
+    let dir = scratch("pcap");
+    let host = name("host.example");
+    let query = Message::query(host.clone(), RecordType::A);
+    let mut captured = query.response();
+    captured.answers.push(Record::new(
+        host.clone(),
+        300,
+        RData::A([192, 0, 2, 9].into()),
+    ));
+    let mut writer = Writer::new(Vec::new(), LinkType::RAW).unwrap();
+    for (t, src, dst, message) in [
+        (1, "192.0.2.1:5000", "192.0.2.53:53", &query),
+        (2, "192.0.2.53:53", "192.0.2.1:5000", &captured),
+    ] {
+        writer
+            .write_message(&DnsMessage {
+                timestamp: Duration::from_micros(1_700_000_000_000_000 + t),
+                src: src.parse().unwrap(),
+                dst: dst.parse().unwrap(),
+                protocol: Protocol::Udp,
+                wire: message.to_wire().unwrap(),
+            })
+            .unwrap();
+    }
+    let path = dir.join("capture.pcap");
+    fs::write(&path, writer.into_inner()).unwrap();
+    let path = path.to_str().unwrap();
+
+    let output = mairu(&["pcap", "print", path]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    assert!(
+        out.contains(";; 1700000000.000001 192.0.2.1:5000 -> 192.0.2.53:53 (UDP)"),
+        "{}",
+        out
+    );
+    assert!(out.contains("192.0.2.9"), "{}", out);
+
+    // The server answers otherwise than in the capture.
+    let server = MockServer::builder()
+        .answer(
+            host.clone(),
+            RecordType::A,
+            vec![Record::new(host, 300, RData::A([192, 0, 2, 1].into()))],
+        )
+        .start()
+        .unwrap();
+    let at = format!("@{}", server.addr().ip());
+    let port = server.addr().port().to_string();
+    let output = mairu(&["pcap", "replay", path, &at, "-p", &port]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = stdout(&output);
+    for line in [
+        "sent:       1",
+        "answered:   1",
+        "differing:  1",
+        "NOERROR:    1",
+    ] {
+        assert!(out.contains(line), "{}", out);
+    }
+    assert_eq!(server.received().len(), 1);
+
+    let output = mairu(&["pcap", "replay", path]);
+    assert_eq!(
+        stderr(&output),
+        "mairu-dns: usage: mairu-dns pcap replay <file> @<server> [...]\n"
+    );
+    let output = mairu(&["pcap", "print", &format!("{}.missing", path)]);
+    assert_eq!(output.status.code(), Some(1));
+    fs::remove_dir_all(dir).unwrap();
+}
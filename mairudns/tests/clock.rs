@@ -0,0 +1,124 @@
+//! Clocks: how a mock clock moves, and the server checking TSIG signing
+//! times against the clock it is given, up to the fudge and no further.
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use mairudns::client::{read_framed, write_framed};
+use mairudns::clock::{self, Clock, MockClock};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::RecordType;
+use mairudns::server::{Request, Server};
+use mairudns::tsig::{self, Keyring, Signed};
+
+const NOW: u64 = 1_700_000_000;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn key() -> tsig::Key {
+    tsig::Key::new(
+        name("xfr"),
+        tsig::Algorithm::HmacSha256,
+        b"0123456789abcdef".to_vec(),
+    )
+}
+
+#[test]
+fn mock_clocks_move_only_when_told() {
+    let mock = MockClock::new(NOW);
+    let shared: Arc<dyn Clock> = Arc::new(mock.clone());
+    let start = shared.now();
+    assert_eq!(shared.unix_time(), NOW);
+    assert_eq!(shared.system_time(), UNIX_EPOCH + Duration::from_secs(NOW));
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(shared.now(), start);
+
+    // Clones share the time, and both clocks advance together.
+    mock.advance(Duration::from_millis(1500));
+    assert_eq!(shared.now() - start, Duration::from_millis(1500));
+    assert_eq!(shared.unix_time(), NOW + 1);
+
+    // Stepping the wall clock leaves monotonic time alone, backwards too.
+    mock.set_unix_time(NOW - 3600);
+    assert_eq!(shared.unix_time(), NOW - 3600);
+    assert_eq!(shared.now() - start, Duration::from_millis(1500));
+    mock.set_unix_time(0);
+    assert_eq!(shared.unix_time(), 0);
+
+    let system = clock::system();
+    let real = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert!(system.unix_time().abs_diff(real) <= 1);
+}
+
+/// A TCP server answering every query with an empty NOERROR response,
+/// checking TSIG by `clock`.
+fn server(clock: &MockClock) -> SocketAddr {
+    let mut server = Server::new(|req: &Request| Some(req.message.response()));
+    let mut keys = Keyring::new();
+    keys.insert(key());
+    server.set_keyring(keys);
+    server.set_clock(Arc::new(clock.clone()));
+    let addr = server.listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// Sends a query signed at `signed_at` and returns the signed response
+/// with the query's MAC.
+fn ask(addr: SocketAddr, signed_at: u64) -> (Signed, Vec<u8>) {
+    let query = Message::query(name("example.com."), RecordType::SOA);
+    let (wire, mac) = tsig::sign(&query, &key(), None, signed_at).unwrap();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write_framed(&mut stream, &wire).unwrap();
+    let resp = read_framed(&mut stream).unwrap();
+    (Signed::parse(&resp).unwrap().unwrap(), mac)
+}
+
+#[test]
+fn servers_check_tsig_times_by_their_clock() {
+    let clock = MockClock::new(NOW);
+    let addr = server(&clock);
+
+    for signed_at in [NOW - 300, NOW, NOW + 300] {
+        let (resp, mac) = ask(addr, signed_at);
+        assert_eq!(resp.message.header.rcode, Rcode::NOERROR);
+        assert_eq!(resp.tsig.error, Rcode::NOERROR);
+        // Signed at the server's time, not the client's.
+        assert_eq!(resp.tsig.time_signed, NOW);
+        resp.verify(&key(), Some(&mac), NOW).unwrap();
+    }
+
+    // Beyond the fudge, the error is signed with the server's time so
+    // that the client can tell how far off it is (RFC 8945 §5.2.3).
+    for signed_at in [NOW - 301, NOW + 301] {
+        let (resp, mac) = ask(addr, signed_at);
+        assert_eq!(resp.message.header.rcode, Rcode::NOTAUTH);
+        assert_eq!(resp.tsig.error, Rcode::BADTIME);
+        assert_eq!(resp.tsig.time_signed, NOW);
+        // Signed, so the error gets past the MAC check.
+        assert!(!resp.tsig.mac.is_empty());
+        assert!(matches!(
+            resp.verify(&key(), Some(&mac), NOW),
+            Err(tsig::Error::Reported(Rcode::BADTIME))
+        ));
+    }
+
+    // The same signing time fails once the server's clock moves on.
+    let (resp, _) = ask(addr, NOW);
+    assert_eq!(resp.tsig.error, Rcode::NOERROR);
+    clock.advance(Duration::from_secs(301));
+    let (resp, _) = ask(addr, NOW);
+    assert_eq!(resp.tsig.error, Rcode::BADTIME);
+    assert_eq!(resp.tsig.time_signed, NOW + 301);
+    clock.set_unix_time(NOW - 300);
+    let (resp, _) = ask(addr, NOW);
+    assert_eq!(resp.tsig.error, Rcode::NOERROR);
+}
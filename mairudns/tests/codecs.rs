@@ -0,0 +1,247 @@
+//! The text codecs and name case folding, whose long inputs go through
+//! vector paths: known vectors, and agreement with plain byte-at-a-time
+//! versions for every length and every place a byte needing care can sit.
+
+use mairudns::encoding::{
+    base32hex_decode, base32hex_encode, base64_decode, base64_encode, hex_decode, hex_encode, Error,
+};
+use mairudns::name::DomainName;
+
+/// A xorshift generator, so that failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Base64 a byte at a time.
+fn plain_base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .chain(&[0, 0])
+            .take(3)
+            .fold(0u32, |n, &b| n << 8 | u32::from(b));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn plain_base64_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::new();
+    let (mut acc, mut bits, mut padding) = (0u32, 0, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            _ => return Err(Error::BadChar),
+        };
+        if padding > 0 {
+            return Err(Error::BadLength);
+        }
+        acc = acc << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 6 || padding > 2 || acc & ((1 << bits) - 1) != 0 {
+        return Err(Error::BadLength);
+    }
+    Ok(out)
+}
+
+fn plain_hex_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let digits = text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|c| match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            _ => Err(Error::BadChar),
+        })
+        .collect::<Result<Vec<u8>, Error>>()?;
+    if digits.len() % 2 != 0 {
+        return Err(Error::BadLength);
+    }
+    Ok(digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect())
+}
+
+/// `text` with `c` put in at `at`.
+fn with(text: &str, at: usize, c: u8) -> String {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.insert(at, c);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[test]
+fn rfc4648_vectors() {
+    let vectors: [(&str, &str, &str, &str); 7] = [
+        ("", "", "", ""),
+        ("f", "Zg==", "66", "co"),
+        ("fo", "Zm8=", "666f", "cpng"),
+        ("foo", "Zm9v", "666f6f", "cpnmu"),
+        ("foob", "Zm9vYg==", "666f6f62", "cpnmuog"),
+        ("fooba", "Zm9vYmE=", "666f6f6261", "cpnmuoj1"),
+        ("foobar", "Zm9vYmFy", "666f6f626172", "cpnmuoj1e8"),
+    ];
+    for (data, base64, hex, base32hex) in vectors {
+        assert_eq!(base64_encode(data.as_bytes()), base64);
+        assert_eq!(base64_decode(base64).unwrap(), data.as_bytes());
+        assert_eq!(hex_encode(data.as_bytes()), hex);
+        assert_eq!(hex_decode(&hex.to_uppercase()).unwrap(), data.as_bytes());
+        assert_eq!(base32hex_encode(data.as_bytes()), base32hex);
+        assert_eq!(
+            base32hex_decode(&base32hex.to_uppercase()).unwrap(),
+            data.as_bytes()
+        );
+    }
+    // Leftover bits must be zero, and a lone character is no byte.
+    assert_eq!(base32hex_decode("cpnmuoj1e9"), Err(Error::BadLength));
+    assert_eq!(base32hex_decode("cpnmuoj1e"), Err(Error::BadLength));
+    assert_eq!(base32hex_decode("cpnmuoj1w8"), Err(Error::BadChar));
+    assert_eq!(base64_decode("Zm9=v"), Err(Error::BadLength));
+    assert_eq!(hex_decode("666"), Err(Error::BadLength));
+}
+
+#[test]
+fn long_inputs_round_trip_and_match_the_plain_codecs() {
+    let mut rng = Rng(0x1234_5678_9abc_def0);
+    for len in 0..300 {
+        for _ in 0..8 {
+            let data = rng.bytes(len);
+            let base64 = base64_encode(&data);
+            assert_eq!(base64, plain_base64_encode(&data));
+            assert_eq!(base64_decode(&base64).unwrap(), data);
+
+            let hex = hex_encode(&data);
+            let plain: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(hex, plain);
+            assert_eq!(hex_decode(&hex).unwrap(), data);
+            assert_eq!(hex_decode(&hex.to_uppercase()).unwrap(), data);
+
+            let base32hex = base32hex_encode(&data);
+            assert_eq!(base32hex_decode(&base32hex).unwrap(), data);
+            assert_eq!(base32hex_decode(&base32hex.to_uppercase()).unwrap(), data);
+        }
+    }
+}
+
+#[test]
+fn bytes_needing_care_are_found_wherever_they_sit() {
+    let mut rng = Rng(42);
+    let data = rng.bytes(120);
+    let base64 = base64_encode(&data);
+    let hex = hex_encode(&data);
+    let base32hex = base32hex_encode(&data);
+    for c in [b' ', b'\n', b'=', b'!', b'G', b'w', b'-', 0x80] {
+        for at in 0..=base64.len() {
+            let text = with(&base64, at, c);
+            assert_eq!(
+                base64_decode(&text),
+                plain_base64_decode(&text),
+                "{:?}",
+                text
+            );
+        }
+        for at in 0..=hex.len() {
+            let text = with(&hex, at, c);
+            assert_eq!(hex_decode(&text), plain_hex_decode(&text), "{:?}", text);
+        }
+        for at in 0..=base32hex.len() {
+            let text = with(&base32hex, at, c);
+            assert!(base32hex_decode(&text).is_err(), "{:?}", text);
+        }
+    }
+}
+
+#[test]
+fn names_fold_ascii_letters_only() {
+    let upper: Vec<u8> = (0..=255).collect();
+    let lower: Vec<u8> = upper.iter().map(u8::to_ascii_lowercase).collect();
+    for len in [1, 15, 16, 17, 31, 32, 33, 63] {
+        for start in [0, 60, 128, 190] {
+            let take = |bytes: &[u8]| -> Vec<u8> {
+                bytes
+                    .iter()
+                    .cycle()
+                    .skip(start)
+                    .take(len)
+                    .copied()
+                    .collect()
+            };
+            let a = DomainName::from_labels([take(&upper), b"example".to_vec()]).unwrap();
+            let b = DomainName::from_labels([take(&lower), b"EXAMPLE".to_vec()]).unwrap();
+            assert_eq!(a, b);
+            assert_eq!(a.to_lowercase().labels()[0], take(&lower));
+
+            // Bytes differing by the case bit but outside A-Z are not
+            // folded, wherever they sit in the label.
+            for at in 0..len {
+                let mut label = take(&lower);
+                if !label[at].is_ascii_alphabetic() {
+                    label[at] ^= 0x20;
+                    let c = DomainName::from_labels([label, b"example".to_vec()]).unwrap();
+                    assert_ne!(a, c, "byte {} of {:?}", at, c);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn names_in_text_are_scanned_for_escapes_wherever_they_sit() {
+    let plain = "abcdefghijklmnopqrstuvwxyz0123456789-_abcdefghijklmnopqrstuvwx";
+    for at in 0..plain.len() {
+        let name: DomainName = format!("{}\\065{}.example", &plain[..at], &plain[at..])
+            .parse()
+            .unwrap();
+        let mut label = plain.as_bytes().to_vec();
+        label.insert(at, b'A');
+        assert_eq!(name.labels()[0], label);
+
+        let name: DomainName = format!("{}\\.{}.example", &plain[..at], &plain[at..])
+            .parse()
+            .unwrap();
+        assert_eq!(name.label_count(), 2);
+        assert_eq!(name.labels()[0][at], b'.');
+
+        // A dot ends a label, and an empty label is refused.
+        let dotted = format!("{}.{}.example", &plain[..at], &plain[at..]).parse::<DomainName>();
+        match dotted {
+            Ok(name) => assert_eq!((at, name.label_count()), (at, 3)),
+            Err(_) => assert_eq!(at, 0),
+        }
+
+        for bad in [" ", "\x7f", "\u{e9}"] {
+            let text = format!("{}{}{}.example", &plain[..at], bad, &plain[at..]);
+            assert!(text.parse::<DomainName>().is_err(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Compliance profiles: what each toggle puts up with in messages, read
+//! whole or in place, in zone files and compiled zones, and in the
+//! queries a server answers, and how the configuration chooses them.
+
+use std::fs;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::arena::MessageRef;
+use mairudns::compliance::{is_hostname, Compliance, Violation};
+use mairudns::config::{ComplianceConfig, ComplianceProfile};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Request, Server};
+use mairudns::wire;
+use mairudns::zone::{compile, parse_records_with, Backend, MappedBackend, Zone};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn response(answer: Record) -> Message {
+    let mut resp = Message::query(name("example"), RecordType::ANY).response();
+    resp.edns = None;
+    resp.answers.push(answer);
+    resp
+}
+
+/// A response whose one answer is `rdata` ending with the name
+/// `example`, with that name compressed to a pointer at the question.
+fn compressed(rdata: RData) -> Vec<u8> {
+    let mut wire = response(Record::new(name("svc.example"), 300, rdata))
+        .to_wire()
+        .unwrap();
+    let len = wire.len();
+    // The data length is the one that reaches the end.
+    let at = (12..len - 2)
+        .rev()
+        .find(|&at| usize::from(u16::from_be_bytes([wire[at], wire[at + 1]])) == len - at - 2)
+        .unwrap();
+    wire.truncate(len - 9);
+    wire.extend_from_slice(&[0xc0, 12]);
+    let rdlength = (wire.len() - at - 2) as u16;
+    wire[at..at + 2].copy_from_slice(&rdlength.to_be_bytes());
+    wire
+}
+
+fn srv() -> RData {
+    RData::Srv {
+        priority: 0,
+        weight: 0,
+        port: 5060,
+        target: name("example"),
+    }
+}
+
+#[test]
+fn profiles_set_each_toggle() {
+    assert_eq!(
+        Compliance::STRICT,
+        Compliance {
+            underscores_in_hostnames: false,
+            large_ttls: false,
+            compressed_rdata: false,
+            trailing_bytes: false,
+            multiple_questions: false,
+            empty_txt: false,
+        }
+    );
+    let default = Compliance::default();
+    assert!(!default.trailing_bytes);
+    assert_eq!(
+        Compliance {
+            trailing_bytes: true,
+            ..default
+        },
+        Compliance::LENIENT
+    );
+
+    assert!(is_hostname(&name("www-1.example")));
+    assert!(is_hostname(&name("*.example")));
+    assert!(!is_hostname(&name("_sip.example")));
+    assert!(!is_hostname(&name("-a.example")));
+    assert!(!is_hostname(&name("a.*.example")));
+}
+
+#[test]
+fn decodes_messages_under_a_profile() {
+    let query = Message::query(name("www.example"), RecordType::A);
+    let mut trailing = query.to_wire().unwrap();
+    trailing.push(0);
+    assert_eq!(
+        Message::from_wire(&trailing),
+        Err(wire::Error::TrailingData {
+            offset: trailing.len() - 1
+        })
+    );
+    let lenient = Message::from_wire_with(&trailing, &Compliance::LENIENT).unwrap();
+    assert_eq!(lenient.questions, query.questions);
+
+    let large = response(Record::new(
+        name("www.example"),
+        0x8000_0000,
+        RData::A([192, 0, 2, 1].into()),
+    ))
+    .to_wire()
+    .unwrap();
+    let strict = Message::from_wire_with(&large, &Compliance::STRICT).unwrap();
+    assert_eq!(strict.answers[0].ttl, 0);
+    let lenient = Message::from_wire_with(&large, &Compliance::LENIENT).unwrap();
+    assert_eq!(lenient.answers[0].ttl, 0x8000_0000);
+
+    for rdata in [srv(), RData::Dname(name("example"))] {
+        let wire = compressed(rdata.clone());
+        assert_eq!(
+            Message::from_wire_with(&wire, &Compliance::STRICT),
+            Err(wire::Error::BadPointer {
+                offset: wire.len() - 2
+            })
+        );
+        let resp = Message::from_wire(&wire).unwrap();
+        assert_eq!(resp.answers[0].rdata, rdata);
+    }
+
+    let empty = response(Record::new(
+        name("www.example"),
+        300,
+        RData::Txt(Vec::new()),
+    ))
+    .to_wire()
+    .unwrap();
+    assert!(Message::from_wire_with(&empty, &Compliance::STRICT).is_err());
+    assert!(Message::from_wire(&empty).is_ok());
+}
+
+#[test]
+fn reads_messages_in_place_under_a_profile() {
+    let query = Message::query(name("www.example"), RecordType::A);
+    let mut trailing = query.to_wire().unwrap();
+    trailing.push(0);
+    assert_eq!(
+        MessageRef::parse(&trailing).unwrap_err(),
+        wire::Error::TrailingData {
+            offset: trailing.len() - 1
+        }
+    );
+    let parsed = MessageRef::parse_with(&trailing, &Compliance::LENIENT).unwrap();
+    assert_eq!(parsed.question().unwrap().to_question(), query.questions[0]);
+    assert_eq!(parsed.to_message().unwrap().questions, query.questions);
+
+    for rdata in [srv(), RData::Dname(name("example"))] {
+        let wire = compressed(rdata.clone());
+        assert_eq!(
+            MessageRef::parse_with(&wire, &Compliance::STRICT).unwrap_err(),
+            wire::Error::BadPointer {
+                offset: wire.len() - 2
+            }
+        );
+        let parsed = MessageRef::parse(&wire).unwrap();
+        let record = parsed.answers().next().unwrap();
+        assert_eq!(record.to_record().unwrap().rdata, rdata);
+    }
+    let plain = response(Record::new(name("svc.example"), 300, srv()))
+        .to_wire()
+        .unwrap();
+    assert!(MessageRef::parse_with(&plain, &Compliance::STRICT).is_ok());
+
+    // TTLs are as sent in place, and as the profile says once decoded.
+    let large = response(Record::new(
+        name("www.example"),
+        0x8000_0000,
+        RData::A([192, 0, 2, 1].into()),
+    ))
+    .to_wire()
+    .unwrap();
+    let parsed = MessageRef::parse_with(&large, &Compliance::STRICT).unwrap();
+    let record = parsed.answers().next().unwrap();
+    assert_eq!(record.ttl, 0x8000_0000);
+    assert_eq!(record.to_record().unwrap().ttl, 0);
+    assert_eq!(parsed.to_message().unwrap().answers[0].ttl, 0);
+    let parsed = MessageRef::parse_with(&large, &Compliance::LENIENT).unwrap();
+    assert_eq!(parsed.to_message().unwrap().answers[0].ttl, 0x8000_0000);
+}
+
+#[test]
+fn checks_zone_files() {
+    let origin = name("example");
+    let check = |text: &str, compliance: &Compliance| {
+        parse_records_with(text, &origin, compliance).map(drop)
+    };
+    let underscore = "_x.example. 300 IN A 192.0.2.1\n";
+    assert!(check(underscore, &Compliance::default()).is_ok());
+    let error = check(underscore, &Compliance::STRICT).unwrap_err();
+    assert!(error.to_string().contains("_x.example"), "{}", error);
+    // Names that are not host names may have underscores.
+    let service = "_sip._tcp.example. 300 IN SRV 0 0 5060 sip.example.\n";
+    assert!(check(service, &Compliance::STRICT).is_ok());
+    let target = "_sip._tcp.example. 300 IN SRV 0 0 5060 _sip.example.\n";
+    assert!(check(target, &Compliance::STRICT).is_err());
+
+    let large = "a 3000000000 IN A 192.0.2.1\n";
+    assert!(Zone::from_master_with(origin.clone(), large, &Compliance::STRICT).is_err());
+    assert!(Zone::from_master_with(origin.clone(), large, &Compliance::LENIENT).is_ok());
+
+    let record = Record::new(name("www.example"), 300, RData::Txt(Vec::new()));
+    assert_eq!(
+        Compliance::STRICT.check_record(&record),
+        Err(Violation::EmptyTxt)
+    );
+    assert_eq!(Compliance::default().check_record(&record), Ok(()));
+    assert_eq!(
+        Violation::LargeTtl(3_000_000_000).to_string(),
+        "TTL 3000000000 is above 2^31 - 1"
+    );
+}
+
+#[test]
+fn checks_compiled_zones_as_they_are_read() {
+    let origin = name("example");
+    let text = "$TTL 300
+@ SOA ns host 1 3600 600 86400 300
+@ NS ns
+ns A 192.0.2.1
+_x A 192.0.2.2
+";
+    let zone = Zone::from_master(origin.clone(), text).unwrap();
+    let dir = std::env::temp_dir().join(format!("mairudns-compliance-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("example.mzone");
+    compile(&zone, &path).unwrap();
+
+    let lenient = MappedBackend::open(&path).unwrap();
+    let found = lenient.rrset(&origin, &name("_x.example"), RecordType::A);
+    assert_eq!(found.unwrap().len(), 1);
+
+    let strict = MappedBackend::open_with(&path, &Compliance::STRICT).unwrap();
+    let ns = strict.rrset(&origin, &name("ns.example"), RecordType::A);
+    assert_eq!(ns.unwrap().len(), 1);
+    let error = strict
+        .rrset(&origin, &name("_x.example"), RecordType::A)
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("not a valid host name"),
+        "{}",
+        error
+    );
+    assert!(strict.records(&origin).is_err());
+    // Reloading keeps the profile.
+    strict.reload().unwrap();
+    assert!(strict
+        .rrset(&origin, &name("_x.example"), RecordType::A)
+        .is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The RCODE a server under `compliance` answers `wire` with.
+fn answered(compliance: Compliance, wire: &[u8]) -> Rcode {
+    let mut server = Server::new(|r: &Request| Some(r.message.response()));
+    server.set_compliance(compliance);
+    let addr = server.listen_udp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    socket.send_to(wire, addr).unwrap();
+    let mut buf = [0; 512];
+    let len = socket.recv(&mut buf).unwrap();
+    control.shutdown();
+    running.join().unwrap().unwrap();
+    Message::from_wire(&buf[..len]).unwrap().header.rcode
+}
+
+#[test]
+fn servers_refuse_what_the_profile_does() {
+    let mut query = Message::query(name("www.example"), RecordType::A);
+    query.questions.push(query.questions[0].clone());
+    let wire = query.to_wire().unwrap();
+    assert_eq!(answered(Compliance::STRICT, &wire), Rcode::FORMERR);
+    assert_eq!(answered(Compliance::LENIENT, &wire), Rcode::NOERROR);
+
+    let mut trailing = Message::query(name("www.example"), RecordType::A)
+        .to_wire()
+        .unwrap();
+    trailing.push(0);
+    assert_eq!(answered(Compliance::default(), &trailing), Rcode::FORMERR);
+    assert_eq!(answered(Compliance::LENIENT, &trailing), Rcode::NOERROR);
+}
+
+#[test]
+fn configurations_override_a_profile() {
+    assert_eq!(
+        ComplianceConfig::default().to_compliance(),
+        Compliance::default()
+    );
+    let config = ComplianceConfig {
+        underscores_in_hostnames: Some(true),
+        ..ComplianceConfig::new(ComplianceProfile::Strict)
+    };
+    assert_eq!(
+        config.to_compliance(),
+        Compliance {
+            underscores_in_hostnames: true,
+            ..Compliance::STRICT
+        }
+    );
+    let config = ComplianceConfig {
+        trailing_bytes: Some(false),
+        ..ComplianceConfig::new(ComplianceProfile::Lenient)
+    };
+    assert_eq!(config.to_compliance(), Compliance::default());
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn reads_the_profile_from_toml() {
+    use mairudns::config::Config;
+
+    let config = Config::from_toml(
+        r#"
+        [compliance]
+        profile = "strict"
+        large-ttls = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.compliance.to_compliance(),
+        Compliance {
+            large_ttls: true,
+            ..Compliance::STRICT
+        }
+    );
+    assert!(Config::from_toml("[compliance]\nprofile = \"loose\"\n").is_err());
+}
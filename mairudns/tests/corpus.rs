@@ -0,0 +1,167 @@
+//! Decodes the messages in `tests/corpus` and checks them against their
+//! golden decodings, and that re-encoding reproduces them byte for byte
+//! where the corpus says it should.
+//!
+//! The `captured-*` messages are taken byte for byte from the tests of
+//! the dns-parser crate (version 0.8.0, MIT or Apache-2.0), where they
+//! stand as responses recorded from public servers, along with a stub's
+//! query; their IDs, TTLs and addresses are those of live answers, but
+//! they were not captured here. They hold addresses, CNAME chains with
+//! authority and glue, MX, SRV, TXT and PTR records, and an NXDOMAIN with
+//! its SOA.
+//!
+//! No capture is at hand yet for root referrals, signed answers and
+//! referrals, NSEC denials, EDNS options, truncated responses or long
+//! compression chains. The `pending-*` messages stand in for them,
+//! constructed as the servers their comments name lay them out, and each
+//! is to be replaced by a real capture of its kind; until then these
+//! kinds are not covered by recorded traffic. The other messages are
+//! constructed on purpose: SRV targets left uncompressed as RFC 2782
+//! requires, encodings that only some servers produce, and malformed
+//! messages that must be rejected.
+//!
+//! Each `<name>.hex` file holds one message in hex, after `;` comment lines
+//! of which two are directives. `source:` says where the message came
+//! from. `reencode: exact` is for messages the encoder must reproduce,
+//! `reencode: differs` for legal encodings it does not produce itself,
+//! such as uncompressed names, and `decode: error` for messages that must
+//! be rejected. `<name>.golden` holds the decoding. Run with
+//! `UPDATE_GOLDEN=1` to rewrite the golden files after a deliberate
+//! change, and review the difference.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use mairudns::message::Message;
+
+#[derive(PartialEq)]
+enum Directive {
+    Exact,
+    Differs,
+    Error,
+}
+
+struct Entry {
+    name: String,
+    directive: Directive,
+    wire: Vec<u8>,
+}
+
+fn load(path: &Path) -> Entry {
+    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+    let text = fs::read_to_string(path).unwrap();
+    let mut directive = None;
+    let mut source = None;
+    let mut hex = String::new();
+    for line in text.lines() {
+        match line.strip_prefix(';') {
+            Some(comment) => match comment.trim() {
+                "reencode: exact" => directive = Some(Directive::Exact),
+                "reencode: differs" => directive = Some(Directive::Differs),
+                "decode: error" => directive = Some(Directive::Error),
+                c => {
+                    if let Some(from) = c.strip_prefix("source:") {
+                        source = Some(from.trim().to_string());
+                    }
+                }
+            },
+            None => hex.extend(line.chars().filter(|c| !c.is_whitespace())),
+        }
+    }
+    let wire = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+    assert!(source.is_some_and(|s| !s.is_empty()), "{}: no source", name);
+    Entry {
+        directive: directive.unwrap_or_else(|| panic!("{}: no directive", name)),
+        name,
+        wire,
+    }
+}
+
+/// The message as `dig` would show it, with the EDNS options it does not.
+fn render(message: &Message) -> String {
+    let mut out = message.to_string();
+    if let Some(edns) = &message.edns {
+        if !edns.options.is_empty() {
+            out.push_str("\n;; EDNS OPTIONS:\n");
+        }
+        for option in &edns.options {
+            write!(out, "; {}:", option.code.0).unwrap();
+            for b in &option.data {
+                write!(out, " {:02x}", b).unwrap();
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// What is wrong with `entry`, if anything.
+fn check(entry: &Entry, golden_path: &Path, update: bool) -> Vec<String> {
+    let mut problems = Vec::new();
+    let decoded = Message::from_wire(&entry.wire);
+    let rendered = match &decoded {
+        Ok(message) => render(message),
+        Err(e) => format!("error: {}\n", e),
+    };
+    match (&entry.directive, &decoded) {
+        (Directive::Error, Ok(_)) => problems.push("decoded, but should not".to_string()),
+        (Directive::Error, Err(_)) => {}
+        (_, Err(e)) => problems.push(format!("failed to decode: {}", e)),
+        (directive, Ok(message)) => {
+            let wire = message.to_wire().unwrap();
+            if *directive == Directive::Exact && wire != entry.wire {
+                problems.push(format!(
+                    "re-encoded differently:\n  corpus  {:02x?}\n  encoded {:02x?}",
+                    entry.wire, wire
+                ));
+            }
+            if *directive == Directive::Differs && wire == entry.wire {
+                problems.push("re-encoded identically; mark it exact".to_string());
+            }
+            match Message::from_wire(&wire) {
+                Ok(again) if again == *message => {}
+                _ => problems.push("re-encoding does not decode to the same message".to_string()),
+            }
+        }
+    }
+    if update {
+        fs::write(golden_path, &rendered).unwrap();
+    } else {
+        match fs::read_to_string(golden_path) {
+            Ok(golden) if golden == rendered => {}
+            Ok(golden) => problems.push(format!(
+                "decoded differently:\n--- golden\n{}--- decoded\n{}",
+                golden, rendered
+            )),
+            Err(e) => problems.push(format!("{}: {}", golden_path.display(), e)),
+        }
+    }
+    problems
+}
+
+#[test]
+fn corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "hex"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no messages in {}", dir.display());
+
+    let mut failures = String::new();
+    for path in &paths {
+        let entry = load(path);
+        for problem in check(&entry, &path.with_extension("golden"), update) {
+            writeln!(failures, "{}: {}", entry.name, problem).unwrap();
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures);
+}
@@ -0,0 +1,211 @@
+//! DANE (RFC 6698, RFC 7671): TLSA records matched against certificate
+//! chains, by usage, selector and matching type, and counted only when
+//! the validator finds them secure.
+//!
+//! `dane/ca.der` is a self-signed CA certificate and `dane/mail.der` a
+//! certificate for `mail.example` it issued, both made with OpenSSL; the
+//! digests below are OpenSSL's too.
+
+#![cfg(feature = "dnssec")]
+
+use std::sync::Arc;
+
+use mairudns::dnssec::{
+    sign_zone, tlsa_owner, verify_tlsa, Dane, SigningKey, SigningPolicy, Status, Tlsa,
+    TrustAnchors, Validated, Validator,
+};
+use mairudns::encoding::hex_decode;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::Authority;
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+const CA: &[u8] = include_bytes!("dane/ca.der");
+const MAIL: &[u8] = include_bytes!("dane/mail.der");
+
+/// SHA-256 of the certificates, and SHA-256 and SHA-512 of their
+/// SubjectPublicKeyInfo.
+const CA_CERT_SHA256: &str = "2cecc76d0941e9c16492dde5483603684b022a2d27918dcfbe3cc305f58c01a4";
+const CA_SPKI_SHA256: &str = "83a5ca206f886e48274c182971b959e853f4393ae5fa741aabb21b3d224fd2a0";
+const MAIL_CERT_SHA256: &str = "22fd49a8445937e2bca7a344c0c162650dafdf675067d2e1a38231fcbe140226";
+const MAIL_SPKI_SHA256: &str = "b9b42f19c873c5fba84f766fabf7e739c925fda7c7fa8413ec591664ff3b50a2";
+const MAIL_SPKI_SHA512: &str = "6380cc2a07590bb8b0daffeb0e8fb1456eae6a8c8a53d852fe83f7c7753ae220\
+                                f794363ffa34ed0f20fa56b517f6a125a04660fa8496b146a589ea5f3e124430";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn tlsa(usage: u8, selector: u8, matching_type: u8, data: &str) -> Tlsa {
+    Tlsa {
+        usage,
+        selector,
+        matching_type,
+        data: hex_decode(data).unwrap(),
+    }
+}
+
+/// A signed `example.` publishing `records` for SMTP on each host, and a
+/// validator that trusts its key.
+fn setup(records: &[(&str, Tlsa)]) -> (MockServer, Validator) {
+    let mut zone = Zone::from_master(
+        name("example."),
+        "$TTL 3600\n@ SOA ns hostmaster 1 3600 600 86400 300\n@ NS ns\nns A 192.0.2.53\n",
+    )
+    .unwrap();
+    for (host, tlsa) in records {
+        let owner = tlsa_owner(&name(host), 25, "tcp").unwrap();
+        zone.insert(Record::new(owner, 3600, tlsa.to_rdata()))
+            .unwrap();
+    }
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let signed = sign_zone(&zone, &[key], &SigningPolicy::default()).unwrap();
+    let anchors =
+        TrustAnchors::from_records(signed.rrset(&name("example."), RecordType::DNSKEY).unwrap());
+    let authority = Arc::new(Authority::new());
+    authority.insert(signed);
+    let server = MockServer::builder().handler(authority).start().unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    (server, Validator::new(resolver).anchors(anchors))
+}
+
+fn query(validator: &Validator, host: &str) -> Validated {
+    let owner = tlsa_owner(&name(host), 25, "tcp").unwrap();
+    validator.query(&owner, RecordType::TLSA).unwrap()
+}
+
+#[test]
+fn records_are_named_and_written_as_published() {
+    assert_eq!(
+        tlsa_owner(&name("mail.example."), 25, "tcp"),
+        Some(name("_25._tcp.mail.example."))
+    );
+    let record = tlsa(3, 1, 1, MAIL_SPKI_SHA256);
+    assert_eq!(
+        record.to_string(),
+        format!("3 1 1 {}", MAIL_SPKI_SHA256.to_ascii_uppercase())
+    );
+    assert_eq!(Tlsa::from_rdata(&record.to_rdata()), Some(record));
+    assert_eq!(Tlsa::from_rdata(&RData::A([192, 0, 2, 1].into())), None);
+
+    assert!(tlsa(2, 0, 1, CA_CERT_SHA256).is_usable());
+    assert!(!tlsa(4, 0, 1, CA_CERT_SHA256).is_usable());
+    assert!(!tlsa(3, 2, 1, CA_CERT_SHA256).is_usable());
+    assert!(!tlsa(3, 1, 3, CA_CERT_SHA256).is_usable());
+}
+
+#[test]
+fn selectors_and_matching_types() {
+    assert!(tlsa(3, 0, 1, MAIL_CERT_SHA256).matches(MAIL));
+    assert!(tlsa(3, 1, 1, MAIL_SPKI_SHA256).matches(MAIL));
+    assert!(tlsa(3, 1, 2, MAIL_SPKI_SHA512).matches(MAIL));
+    let full = Tlsa {
+        data: MAIL.to_vec(),
+        ..tlsa(3, 0, 0, "")
+    };
+    assert!(full.matches(MAIL));
+    assert!(!full.matches(CA));
+
+    // The selector decides what is hashed.
+    assert!(!tlsa(3, 1, 1, MAIL_CERT_SHA256).matches(MAIL));
+    assert!(!tlsa(3, 0, 1, MAIL_SPKI_SHA256).matches(MAIL));
+    assert!(!tlsa(3, 1, 1, MAIL_SPKI_SHA256).matches(CA));
+    // What is not a certificate has no public key to match.
+    assert!(!tlsa(3, 1, 1, MAIL_SPKI_SHA256).matches(&[0x30, 0x03, 0x02, 0x01, 0x00]));
+}
+
+#[test]
+fn usages_decide_which_certificate_must_match() {
+    let (_server, validator) = setup(&[
+        ("ee.example.", tlsa(3, 1, 1, MAIL_SPKI_SHA256)),
+        ("ta.example.", tlsa(2, 0, 1, CA_CERT_SHA256)),
+        ("ca.example.", tlsa(2, 1, 1, CA_SPKI_SHA256)),
+        ("pkix.example.", tlsa(1, 0, 1, MAIL_CERT_SHA256)),
+        ("both.example.", tlsa(1, 0, 1, MAIL_CERT_SHA256)),
+        ("both.example.", tlsa(3, 0, 1, MAIL_CERT_SHA256)),
+    ]);
+    let chain = [MAIL, CA];
+
+    let ee = query(&validator, "ee.example.");
+    assert_eq!(ee.status, Status::Secure);
+    let dane = verify_tlsa(&chain, &ee);
+    assert_eq!(dane, Dane::Matched(tlsa(3, 1, 1, MAIL_SPKI_SHA256)));
+    assert!(dane.is_authenticated());
+
+    // A trust anchor is one of the CAs after the server's certificate,
+    // never the server's own.
+    let ta = query(&validator, "ta.example.");
+    assert!(verify_tlsa(&chain, &ta).is_authenticated());
+    assert_eq!(verify_tlsa(&[MAIL], &ta), Dane::Mismatch);
+    assert_eq!(verify_tlsa(&[CA, MAIL], &ta), Dane::Mismatch);
+    assert!(verify_tlsa(&chain, &query(&validator, "ca.example.")).is_authenticated());
+    // Unless the chain is a self-signed certificate alone.
+    assert!(verify_tlsa(&[CA], &ta).is_authenticated());
+
+    // PKIX usages match, but leave the chain to be validated.
+    let pkix = verify_tlsa(&chain, &query(&validator, "pkix.example."));
+    assert_eq!(pkix, Dane::Matched(tlsa(1, 0, 1, MAIL_CERT_SHA256)));
+    assert!(!pkix.is_authenticated());
+    // DANE usages win when both match.
+    let both = verify_tlsa(&chain, &query(&validator, "both.example."));
+    assert_eq!(both, Dane::Matched(tlsa(3, 0, 1, MAIL_CERT_SHA256)));
+
+    // A secure record that nothing matches fails the server.
+    assert_eq!(verify_tlsa(&[CA, CA], &ee), Dane::Mismatch);
+    assert_eq!(verify_tlsa::<&[u8]>(&[], &ee), Dane::Mismatch);
+}
+
+#[test]
+fn records_count_only_when_secure_and_usable() {
+    let (_server, validator) = setup(&[
+        ("mail.example.", tlsa(3, 1, 1, MAIL_SPKI_SHA256)),
+        ("future.example.", tlsa(4, 1, 1, MAIL_SPKI_SHA256)),
+    ]);
+    let chain = [MAIL, CA];
+    // No records, or none this crate understands: DANE has no say.
+    let none = query(&validator, "none.example.");
+    assert_eq!(none.status, Status::Secure);
+    assert_eq!(verify_tlsa(&chain, &none), Dane::Insecure);
+    let future = query(&validator, "future.example.");
+    assert_eq!(verify_tlsa(&chain, &future), Dane::Insecure);
+
+    // Records that could be forged count for nothing, and records that
+    // were forged fail the server.
+    let secure = query(&validator, "mail.example.");
+    let judged = |status| Validated {
+        status,
+        ..secure.clone()
+    };
+    assert_eq!(
+        verify_tlsa(&chain, &judged(Status::Insecure)),
+        Dane::Insecure
+    );
+    assert_eq!(verify_tlsa(&chain, &judged(Status::Bogus)), Dane::Failed);
+    assert_eq!(
+        verify_tlsa(&chain, &judged(Status::Indeterminate)),
+        Dane::Failed
+    );
+    let mut altered = secure.message.clone();
+    for rr in altered
+        .answers
+        .iter_mut()
+        .filter(|rr| rr.rtype() == RecordType::TLSA)
+    {
+        rr.rdata = tlsa(3, 1, 1, CA_SPKI_SHA256).to_rdata();
+    }
+    assert_eq!(
+        verify_tlsa(&[CA], &validator.validate(&altered)),
+        Dane::Failed
+    );
+
+    // A zone the validator is told not to trust is insecure.
+    validator.add_negative_anchor(name("example."), None);
+    let insecure = query(&validator, "mail.example.");
+    assert_eq!(insecure.status, Status::Insecure);
+    assert_eq!(verify_tlsa(&chain, &insecure), Dane::Insecure);
+}
@@ -0,0 +1,408 @@
+//! Discovery of Designated Resolvers: SVCB records against the RFC 9460
+//! test vectors, the designations they make, and upgrading a stub
+//! resolver to DoT and DoH once a certificate issued by OpenSSL shows the
+//! designated resolver speaks for the one that designated it.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use mairudns::client::{Connected, Protocol, TlsConnector};
+use mairudns::name::DomainName;
+use mairudns::resolver::{
+    Designated, Discovery, DiscoveryError, EncryptedTransport, QueryOptions, Resolver,
+    ResolverConfig, Svcb, DESIGNATION_NAME,
+};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Accepted, Authority, Limits, Server, TlsAcceptor};
+use mairudns::zone::Zone;
+
+/// Issued for dns.example, 127.0.0.1 and 2001:db8::53 by
+/// `openssl req -x509 -addext subjectAltName=...`.
+const RESOLVER: &[u8] = include_bytes!("ddr/resolver.der");
+/// Issued the same way for dns.example and 10.0.0.1 only.
+const OTHER: &[u8] = include_bytes!("ddr/other.der");
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn hex(s: &str) -> Vec<u8> {
+    let s: String = s.split_whitespace().collect();
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn svcb(wire: &str) -> Option<Svcb> {
+    Svcb::from_rdata(&RData::Unknown {
+        rtype: RecordType::SVCB,
+        data: hex(wire),
+    })
+}
+
+fn alpn(ids: &[&[u8]]) -> Vec<u8> {
+    ids.iter()
+        .flat_map(|id| std::iter::once(id.len() as u8).chain(id.iter().copied()))
+        .collect()
+}
+
+/// Hands back the TCP stream as if a handshake had agreed on the
+/// protocol the listener offers.
+struct Plain(&'static [u8]);
+
+impl TlsAcceptor for Plain {
+    fn accept(&self, stream: TcpStream, _alpn: &[&[u8]]) -> io::Result<Accepted> {
+        Ok(Accepted {
+            stream: Box::new(stream),
+            alpn: Some(self.0.to_vec()),
+        })
+    }
+}
+
+/// Completes every handshake as if the server had presented `cert`,
+/// counting them.
+struct Presenting {
+    cert: &'static [u8],
+    handshakes: Arc<AtomicUsize>,
+}
+
+impl TlsConnector for Presenting {
+    fn connect(&self, stream: TcpStream, name: &str, alpn: &[&[u8]]) -> io::Result<Connected> {
+        assert_eq!(name, "dns.example");
+        self.handshakes.fetch_add(1, Ordering::SeqCst);
+        Ok(Connected {
+            stream: Box::new(stream),
+            alpn: Some(alpn[0].to_vec()),
+            certificates: vec![self.cert.to_vec()],
+        })
+    }
+}
+
+fn presenting(cert: &'static [u8]) -> (Discovery, Arc<AtomicUsize>) {
+    let handshakes = Arc::new(AtomicUsize::new(0));
+    let discovery = Discovery::new().tls(Presenting {
+        cert,
+        handshakes: handshakes.clone(),
+    });
+    (discovery, handshakes)
+}
+
+/// A Do53 resolver at 127.0.0.1 publishing `designations`, which answers
+/// www.example. with 192.0.2.2.
+fn designating(designations: Vec<Svcb>) -> SocketAddr {
+    let mut arpa = Zone::from_master(
+        name("resolver.arpa."),
+        "resolver.arpa. 3600 IN SOA ns h 1 1 1 1 1\nresolver.arpa. 3600 IN NS ns\n",
+    )
+    .unwrap();
+    for svcb in designations {
+        arpa.insert(Record::new(name(DESIGNATION_NAME), 300, svcb.to_rdata()))
+            .unwrap();
+    }
+    let example = Zone::from_master(
+        name("example."),
+        "example. 3600 IN SOA ns h 1 1 1 1 1\nexample. 3600 IN NS ns\n\
+         www 3600 IN A 192.0.2.2\ndns 3600 IN A 127.0.0.1\n",
+    )
+    .unwrap();
+    let authority = Arc::new(Authority::new());
+    authority.insert(arpa);
+    authority.insert(example);
+    let mut server = Server::new(authority);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Udp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// The encrypted resolver, on DoT and DoH ports, which answers
+/// www.example. with 192.0.2.99.
+fn encrypted() -> (u16, u16) {
+    let example = Zone::from_master(
+        name("example."),
+        "example. 3600 IN SOA ns h 1 1 1 1 1\nexample. 3600 IN NS ns\n\
+         www 3600 IN A 192.0.2.99\n",
+    )
+    .unwrap();
+    let authority = Arc::new(Authority::new());
+    authority.insert(example);
+    let mut server = Server::new(authority);
+    let local = "127.0.0.1:0".parse().unwrap();
+    let dot = server
+        .listen_tls(local, Plain(b"dot"), Limits::default())
+        .unwrap();
+    let doh = server
+        .listen_https(local, Plain(b"http/1.1"), Limits::default())
+        .unwrap();
+    thread::spawn(move || server.run());
+    (dot.port(), doh.port())
+}
+
+fn resolver(server: SocketAddr) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server],
+        ..ResolverConfig::default()
+    })
+}
+
+#[test]
+fn reads_the_rfc_9460_test_vectors() {
+    // D.1: AliasMode.
+    let alias = svcb("00 00 03 66 6f 6f 07 65 78 61 6d 70 6c 65 03 63 6f 6d 00").unwrap();
+    assert_eq!(alias.priority, 0);
+    assert_eq!(alias.target, name("foo.example.com."));
+    assert!(alias.params.is_empty());
+
+    // D.2: port=53.
+    let port = svcb(
+        "00 10 03 66 6f 6f 07 65 78 61 6d 70 6c 65 03 63 6f 6d 00
+         00 03 00 02 00 35",
+    )
+    .unwrap();
+    assert_eq!(port.priority, 16);
+    assert_eq!(port.port(), Some(53));
+
+    // D.2: ipv6hint=2001:db8::1,2001:db8::53:1.
+    let hints = svcb(
+        "00 01 00 00 06 00 20
+         20 01 0d b8 00 00 00 00 00 00 00 00 00 00 00 01
+         20 01 0d b8 00 00 00 00 00 00 00 00 00 53 00 01",
+    )
+    .unwrap();
+    assert!(hints.target.is_root());
+    assert_eq!(
+        hints.hints(),
+        vec![
+            "2001:db8::1".parse::<IpAddr>().unwrap(),
+            "2001:db8::53:1".parse().unwrap()
+        ]
+    );
+
+    // D.2: alpn=h2,h3-19 mandatory=ipv4hint,alpn ipv4hint=192.0.2.1.
+    let wire = "00 10 03 66 6f 6f 07 65 78 61 6d 70 6c 65 03 6f 72 67 00
+                00 00 00 04 00 01 00 04
+                00 01 00 09 02 68 32 05 68 33 2d 31 39
+                00 04 00 04 c0 00 02 01";
+    let full = svcb(wire).unwrap();
+    assert_eq!(full.alpn(), vec![b"h2".to_vec(), b"h3-19".to_vec()]);
+    assert_eq!(full.param(0), Some(&[0, 1, 0, 4][..]));
+    assert_eq!(full.hints(), vec!["192.0.2.1".parse::<IpAddr>().unwrap()]);
+    assert_eq!(
+        full.to_rdata(),
+        RData::Unknown {
+            rtype: RecordType::SVCB,
+            data: hex(wire),
+        }
+    );
+
+    // D.3: keys out of order, and repeated.
+    assert_eq!(
+        svcb("00 01 00 00 04 00 04 c0 00 02 01 00 01 00 03 02 68 32"),
+        None
+    );
+    assert_eq!(svcb("00 01 00 00 03 00 02 00 35 00 03 00 02 00 36"), None);
+    // Truncated values.
+    assert_eq!(svcb("00 01 00 00 03 00 02 00"), None);
+}
+
+#[test]
+fn designations_follow_alpn_order_and_need_a_dohpath() {
+    let hint: IpAddr = "192.0.2.53".parse().unwrap();
+    let record = Svcb {
+        priority: 1,
+        target: name("dns.example."),
+        params: vec![
+            (Svcb::ALPN, alpn(&[b"h2", b"dot", b"h3", b"doq"])),
+            (Svcb::IPV4HINT, vec![192, 0, 2, 53]),
+            (Svcb::DOHPATH, b"/dns-query{?dns}".to_vec()),
+        ],
+    };
+    let designated = Designated::from_svcb(&record, &record.hints());
+    let found: Vec<_> = designated
+        .iter()
+        .map(|d| (d.transport, d.address, d.path.as_deref()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (
+                EncryptedTransport::Https,
+                SocketAddr::new(hint, 443),
+                Some("/dns-query")
+            ),
+            (EncryptedTransport::Tls, SocketAddr::new(hint, 853), None),
+            (EncryptedTransport::Quic, SocketAddr::new(hint, 853), None),
+        ]
+    );
+    assert!(designated
+        .iter()
+        .all(|d| d.server_name == name("dns.example.") && d.priority == 1));
+
+    // DoH is passed over without a path, and a port applies to all.
+    let no_path = Svcb {
+        params: vec![
+            (Svcb::ALPN, alpn(&[b"h2", b"dot"])),
+            (Svcb::PORT, 8853u16.to_be_bytes().to_vec()),
+        ],
+        ..record.clone()
+    };
+    let designated = Designated::from_svcb(&no_path, &[hint]);
+    assert_eq!(designated.len(), 1);
+    assert_eq!(designated[0].transport, EncryptedTransport::Tls);
+    assert_eq!(designated[0].address, SocketAddr::new(hint, 8853));
+
+    // Aliases and the root target designate nothing.
+    let alias = Svcb {
+        priority: 0,
+        ..record.clone()
+    };
+    assert!(Designated::from_svcb(&alias, &[hint]).is_empty());
+    let root = Svcb {
+        target: DomainName::root(),
+        ..record
+    };
+    assert!(Designated::from_svcb(&root, &[hint]).is_empty());
+}
+
+#[test]
+fn upgrades_to_verified_dot_and_doh() {
+    let (dot, doh) = encrypted();
+    for (id, port, path) in [
+        (&b"dot"[..], dot, None),
+        (&b"http/1.1"[..], doh, Some(b"/dns-query{?dns}".to_vec())),
+    ] {
+        let mut params = vec![
+            (Svcb::ALPN, alpn(&[id])),
+            (Svcb::PORT, port.to_be_bytes().to_vec()),
+        ];
+        params.extend(path.map(|p| (Svcb::DOHPATH, p)));
+        // Without hints, the target's address is looked up at the server.
+        let server = designating(vec![Svcb {
+            priority: 1,
+            target: name("dns.example."),
+            params,
+        }]);
+        let plain = resolver(server);
+        let (discovery, handshakes) = presenting(RESOLVER);
+        let upgraded = discovery.upgrade(&plain);
+        let upgrade = &upgraded.config().upgrades[&server];
+        assert_eq!(
+            upgrade.designated().address,
+            SocketAddr::new("127.0.0.1".parse().unwrap(), port)
+        );
+
+        let before = handshakes.load(Ordering::SeqCst);
+        let answers = upgraded
+            .lookup(&name("www.example."), RecordType::A)
+            .unwrap();
+        assert_eq!(answers[0].rdata, RData::A("192.0.2.99".parse().unwrap()));
+        assert_eq!(handshakes.load(Ordering::SeqCst), before + 1);
+
+        // Queries pinned to a protocol still go to the Do53 server.
+        let pinned = QueryOptions {
+            protocol: Some(Protocol::Udp),
+            ..QueryOptions::default()
+        };
+        let answers = upgraded
+            .lookup_with(&name("www.example."), RecordType::A, &pinned)
+            .unwrap();
+        assert_eq!(answers[0].rdata, RData::A("192.0.2.2".parse().unwrap()));
+
+        // Those pinned to TLS go to a DoT upgrade, and fail without one.
+        let tls = QueryOptions {
+            protocol: Some(Protocol::Tls),
+            ..QueryOptions::default()
+        };
+        let pinned = upgraded.lookup_with(&name("www.example."), RecordType::A, &tls);
+        if upgrade.designated().transport == EncryptedTransport::Tls {
+            assert_eq!(
+                pinned.unwrap()[0].rdata,
+                RData::A("192.0.2.99".parse().unwrap())
+            );
+        } else {
+            assert!(pinned.is_err());
+        }
+        assert!(plain
+            .lookup_with(&name("www.example."), RecordType::A, &tls)
+            .is_err());
+    }
+}
+
+#[test]
+fn certificates_must_name_the_designating_resolver() {
+    let (dot, _) = encrypted();
+    let server = designating(vec![Svcb {
+        priority: 1,
+        target: name("dns.example."),
+        params: vec![
+            (Svcb::ALPN, alpn(&[b"dot"])),
+            (Svcb::PORT, dot.to_be_bytes().to_vec()),
+            (Svcb::IPV4HINT, vec![127, 0, 0, 1]),
+        ],
+    }]);
+    let plain = resolver(server);
+    let (discovery, _) = presenting(OTHER);
+    assert!(matches!(
+        discovery.discover(&plain, server),
+        Err(DiscoveryError::Unverified)
+    ));
+    assert!(discovery.upgrade(&plain).config().upgrades.is_empty());
+
+    // Opportunistically, a private resolver may designate itself.
+    let opportunistic = discovery.opportunistic(true);
+    assert!(opportunistic.discover(&plain, server).is_ok());
+    let elsewhere = Designated {
+        address: "127.0.0.2:853".parse().unwrap(),
+        ..opportunistic.designations(&plain, server).unwrap()[0].clone()
+    };
+    assert!(opportunistic
+        .verify(server.ip(), &elsewhere, plain.config().timeout)
+        .is_err());
+}
+
+#[test]
+fn passes_over_what_it_cannot_use() {
+    let server = designating(vec![
+        Svcb {
+            priority: 2,
+            target: name("dns.example."),
+            params: vec![
+                (Svcb::ALPN, alpn(&[b"dot"])),
+                (Svcb::IPV4HINT, vec![127, 0, 0, 1]),
+            ],
+        },
+        Svcb {
+            priority: 1,
+            target: name("dns.example."),
+            params: vec![
+                (Svcb::ALPN, alpn(&[b"doq"])),
+                (Svcb::IPV4HINT, vec![127, 0, 0, 1]),
+            ],
+        },
+    ]);
+    let plain = resolver(server);
+    let (discovery, _) = presenting(RESOLVER);
+    let designations = discovery.designations(&plain, server).unwrap();
+    assert_eq!(designations.len(), 1);
+    assert_eq!(designations[0].transport, EncryptedTransport::Tls);
+    assert!(matches!(
+        Discovery::new().discover(&plain, server),
+        Err(DiscoveryError::NoDesignation)
+    ));
+
+    // Nor is anything designated when nothing is published.
+    let none = designating(Vec::new());
+    assert!(matches!(
+        discovery.discover(&resolver(none), none),
+        Err(DiscoveryError::NoDesignation)
+    ));
+}
@@ -0,0 +1,254 @@
+//! Checking delegations between zones served by the crate on loopback
+//! addresses sharing one port: a clean delegation, lame and unreachable
+//! servers, NS sets and serials that disagree, glue that does not match
+//! the zone, and name servers that recurse for anyone.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record};
+use mairudns::server::{Authority, Handler, Request, Server};
+use mairudns::testing::MockServer;
+use mairudns::tools::check_delegation;
+use mairudns::tools::delegation::{CheckError, DelegationCheck, Problem, ServerStatus};
+use mairudns::zone::Zone;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ip(last: u8) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, last))
+}
+
+fn authority(origin: &str, text: &str) -> Authority {
+    let authority = Authority::new();
+    authority.insert(Zone::from_master(name(origin), text).unwrap());
+    authority
+}
+
+/// Serves the zone `origin` from `text` on `addr`, returning the port.
+fn serve(addr: SocketAddr, origin: &str, text: &str) -> u16 {
+    let mut server = Server::new(Arc::new(authority(origin, text)));
+    server.listen(addr).unwrap();
+    let port = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Udp)
+        .unwrap()
+        .1
+        .port();
+    thread::spawn(move || server.run());
+    port
+}
+
+/// Serves `parent` as example. on 127.0.0.1, returning a check on its
+/// port and a resolver that asks it.
+fn parent(parent: &str) -> (DelegationCheck, Resolver) {
+    let port = serve("127.0.0.1:0".parse().unwrap(), "example.", parent);
+    let check = DelegationCheck {
+        port,
+        timeout: Duration::from_millis(300),
+        ..DelegationCheck::default()
+    };
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![SocketAddr::new(ip(1), port)],
+        timeout: Duration::from_millis(300),
+        ..ResolverConfig::default()
+    });
+    (check, resolver)
+}
+
+const CHILD: &str = "\
+$TTL 300
+@ IN SOA ns1 hostmaster 7 7200 900 1209600 300
+@ IN NS ns1
+@ IN NS ns2
+ns1 IN A 127.0.0.2
+ns2 IN A 127.0.0.3
+";
+
+#[test]
+fn consistent_delegations_pass() {
+    let (check, resolver) = parent(
+        "$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 127.0.0.1
+sub IN NS ns1.sub
+sub IN NS ns2.sub
+ns1.sub IN A 127.0.0.2
+ns2.sub IN A 127.0.0.3
+",
+    );
+    serve(SocketAddr::new(ip(2), check.port), "sub.example.", CHILD);
+    serve(SocketAddr::new(ip(3), check.port), "sub.example.", CHILD);
+
+    let report = check.run(&resolver, &name("sub.example.")).unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    assert_eq!(report.zone, name("sub.example."));
+    assert_eq!(report.parent, name("example."));
+    let ns: Vec<_> = vec![name("ns1.sub.example."), name("ns2.sub.example.")];
+    assert_eq!(report.parent_ns.iter().cloned().collect::<Vec<_>>(), ns);
+    assert_eq!(report.child_ns, report.parent_ns);
+    assert_eq!(report.glue[&ns[0]].iter().collect::<Vec<_>>(), [&ip(2)]);
+    assert_eq!(report.servers.len(), 2);
+    for server in &report.servers {
+        assert_eq!(server.status, ServerStatus::Authoritative { serial: 7 });
+        assert_eq!(server.ns, report.parent_ns);
+        assert!(!server.open_resolver);
+    }
+}
+
+#[test]
+fn reports_every_problem_it_finds() {
+    let (check, resolver) = parent(
+        "$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 127.0.0.1
+sub IN NS ns1.sub
+sub IN NS ns2.sub
+sub IN NS gone.sub
+sub IN NS lame
+sub IN NS noaddr.elsewhere.
+ns1.sub IN A 127.0.0.2
+ns2.sub IN A 127.0.0.3
+gone.sub IN A 127.0.0.9
+lame IN A 127.0.0.4
+",
+    );
+    let child = CHILD.replace(
+        "ns2 IN A",
+        "ns3 IN A 127.0.0.2\ngone IN A 127.0.0.10\nns2 IN A",
+    ) + "@ IN NS ns3\n@ IN NS gone\n";
+    serve(SocketAddr::new(ip(2), check.port), "sub.example.", &child);
+    serve(
+        SocketAddr::new(ip(3), check.port),
+        "sub.example.",
+        &child.replace(" 7 ", " 8 "),
+    );
+    // A server for some other zone, which refuses this one.
+    serve(
+        SocketAddr::new(ip(4), check.port),
+        "other.",
+        "$TTL 300\n@ IN SOA ns hostmaster 1 7200 900 1209600 300\n@ IN NS ns\n",
+    );
+
+    let report = check.run(&resolver, &name("sub.example.")).unwrap();
+    assert!(!report.is_ok());
+    let expected = [
+        Problem::NoAddress(name("noaddr.elsewhere.")),
+        Problem::Unreachable {
+            name: name("gone.sub.example."),
+            addr: ip(9),
+        },
+        Problem::Lame {
+            name: name("lame.example."),
+            addr: ip(4),
+        },
+        Problem::SerialMismatch(vec![7, 8]),
+        Problem::NsMismatch {
+            only_parent: vec![name("noaddr.elsewhere."), name("lame.example.")],
+            only_child: vec![name("ns3.sub.example.")],
+        },
+        Problem::GlueMismatch {
+            name: name("gone.sub.example."),
+            parent: vec![ip(9)],
+            child: vec![ip(10)],
+        },
+    ];
+    for problem in &expected {
+        assert!(report.problems.contains(problem), "{}", problem);
+    }
+    assert_eq!(
+        report.problems.len(),
+        expected.len(),
+        "{:?}",
+        report.problems
+    );
+
+    // The name server only the zone lists is found and checked too.
+    let ns3 = report
+        .servers
+        .iter()
+        .find(|s| s.name == name("ns3.sub.example."))
+        .unwrap();
+    assert_eq!(ns3.addr, ip(2));
+    assert_eq!(ns3.status, ServerStatus::Authoritative { serial: 7 });
+    let lame = report.servers.iter().find(|s| s.addr == ip(4)).unwrap();
+    assert!(matches!(lame.status, ServerStatus::Lame(_)));
+
+    assert_eq!(
+        expected[4].to_string(),
+        "NS sets differ; only at the parent: noaddr.elsewhere., lame.example.; \
+         only in the zone: ns3.sub.example."
+    );
+}
+
+#[test]
+fn finds_open_resolvers() {
+    let (check, resolver) = parent(
+        "$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 127.0.0.1
+open IN NS ns.open
+ns.open IN A 127.0.0.5
+",
+    );
+    // Authoritative for its zone, and recursing for anything else.
+    let zone = authority(
+        "open.example.",
+        "$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 127.0.0.5
+",
+    );
+    let _open = MockServer::builder()
+        .handler(move |request: &Request| {
+            let q = request.message.question()?;
+            if request.message.header.rd && !q.name.is_subdomain_of(&name("open.example.")) {
+                let mut resp = request.message.response();
+                resp.header.ra = true;
+                resp.answers.push(Record::new(
+                    q.name.clone(),
+                    60,
+                    RData::A([198, 41, 0, 4].into()),
+                ));
+                return Some(resp);
+            }
+            zone.handle(request)
+        })
+        .start_on(SocketAddr::new(ip(5), check.port))
+        .unwrap();
+
+    let report = check.run(&resolver, &name("open.example.")).unwrap();
+    assert_eq!(
+        report.problems,
+        [Problem::OpenResolver {
+            name: name("ns.open.example."),
+            addr: ip(5),
+        }]
+    );
+    assert!(report.servers[0].open_resolver);
+    assert_eq!(
+        report.problems[0].to_string(),
+        "ns.open.example. (127.0.0.5) is an open resolver"
+    );
+}
+
+#[test]
+fn the_root_has_no_delegation_to_check() {
+    let (_, resolver) = parent("$TTL 300\n@ IN SOA ns hostmaster 1 7200 900 1209600 300\n");
+    assert!(matches!(
+        check_delegation(&resolver, &DomainName::root()),
+        Err(CheckError::Root)
+    ));
+}
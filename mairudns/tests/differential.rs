@@ -0,0 +1,285 @@
+//! Sends the same generated queries to a reference resolver directly and
+//! through a forwarder built on the crate, and reports where the two
+//! disagree: in the RCODE, in the answer, or in echoing the question as
+//! it was asked.
+//!
+//! The queries mix record types, names that exist and names that do
+//! not, and randomise the case of every letter in the name as resolvers
+//! using DNS 0x20 do, which the forwarder must preserve in its response.
+//! Some carry two questions, which the forwarder must answer with FORMERR
+//! (RFC 9619) whatever the reference makes of them, since resolvers
+//! deployed before it disagree.
+//!
+//! It needs a resolver to compare against, so it only runs when
+//! `MAIRU_DIFFERENTIAL` is set, to the address of a reference resolver
+//! such as a local unbound (`127.0.0.1:53`), or to `system` for the
+//! first `nameserver` of `/etc/resolv.conf`. `MAIRU_DIFFERENTIAL_SEED`
+//! replays the queries of an earlier run, whose seed a failure prints,
+//! and `MAIRU_DIFFERENTIAL_QUERIES` sets how many to send.
+
+use std::env;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use mairudns::client::{read_framed, write_framed};
+use mairudns::message::{Message, Question, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::RecordType;
+use mairudns::server::{CachePolicy, Forwarder, Route, Server};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Names whose records seldom change between two queries a moment apart.
+const NAMES: &[&str] = &[
+    "example.com.",
+    "example.org.",
+    "iana.org.",
+    "ietf.org.",
+    "rfc-editor.org.",
+    "root-servers.net.",
+    "a.root-servers.net.",
+    "nic.cz.",
+    "isc.org.",
+    "nlnetlabs.nl.",
+];
+
+const TYPES: &[RecordType] = &[
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::NS,
+    RecordType::SOA,
+    RecordType::MX,
+    RecordType::TXT,
+    RecordType::CNAME,
+    RecordType::DS,
+    RecordType::CAA,
+];
+
+/// A xorshift generator, so that a seed replays a run.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// `name` with the case of each letter flipped at random.
+fn randomize_case(name: &str, rng: &mut Rng) -> DomainName {
+    let mixed: String = name
+        .chars()
+        .map(|c| {
+            if rng.next() & 1 == 1 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    mixed.parse().unwrap()
+}
+
+fn generate(rng: &mut Rng) -> Message {
+    let base = NAMES[rng.below(NAMES.len())];
+    let name = match rng.below(5) {
+        // A name that should not exist.
+        0 => format!("mairu-{:x}.{}", rng.next(), base),
+        _ => base.to_string(),
+    };
+    let mut query = Message::query(randomize_case(&name, rng), TYPES[rng.below(TYPES.len())]);
+    if rng.below(10) == 0 {
+        let other = NAMES[rng.below(NAMES.len())];
+        query
+            .questions
+            .push(Question::new(randomize_case(other, rng), RecordType::A));
+    }
+    query
+}
+
+/// Sends `query` over UDP, and over TCP if the response is truncated.
+/// Responses are matched by ID only, so that those to a query with two
+/// questions, which may carry none, are taken too.
+fn ask(server: SocketAddr, query: &Message) -> Result<Message, String> {
+    let wire = query.to_wire().map_err(|e| e.to_string())?;
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| e.to_string())?;
+    socket.connect(server).map_err(|e| e.to_string())?;
+    socket.send(&wire).map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + TIMEOUT;
+    let mut buf = vec![0u8; 65535];
+    let resp = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err("timed out".into());
+        }
+        socket.set_read_timeout(Some(left)).unwrap();
+        let n = match socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err("timed out".into())
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        match Message::from_wire(&buf[..n]) {
+            Ok(resp) if resp.header.id == query.header.id => break resp,
+            _ => continue,
+        }
+    };
+    if !resp.header.tc {
+        return Ok(resp);
+    }
+    let mut stream = TcpStream::connect_timeout(&server, TIMEOUT).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    write_framed(&mut stream, &wire).map_err(|e| e.to_string())?;
+    let framed = read_framed(&mut stream).map_err(|e| e.to_string())?;
+    Message::from_wire(&framed).map_err(|e| e.to_string())
+}
+
+/// The answer section, without TTLs and in a fixed order.
+fn answers(resp: &Message) -> Vec<String> {
+    let mut answers: Vec<String> = resp
+        .answers
+        .iter()
+        .map(|rr| {
+            let mut rr = rr.clone();
+            rr.ttl = 0;
+            rr.name = rr.name.to_lowercase();
+            rr.to_string()
+        })
+        .collect();
+    answers.sort();
+    answers
+}
+
+/// How `ours` differs from `theirs`, if it does.
+fn compare(query: &Message, ours: &Message, theirs: &Message) -> Option<String> {
+    if query.questions.len() > 1 {
+        return match ours.header.rcode {
+            Rcode::FORMERR => None,
+            rcode => Some(format!("rcode {} to two questions", rcode)),
+        };
+    }
+    if ours.header.rcode != theirs.header.rcode {
+        return Some(format!(
+            "rcode {} where the reference gave {}",
+            ours.header.rcode, theirs.header.rcode
+        ));
+    }
+    let asked = query.questions[0].name.to_string();
+    match ours.question() {
+        Some(q) if q.name.to_string() == asked => {}
+        Some(q) => return Some(format!("question echoed as {}", q.name)),
+        None => return Some("question not echoed".into()),
+    }
+    let (ours, theirs) = (answers(ours), answers(theirs));
+    if ours != theirs {
+        return Some(format!(
+            "answer {:?} where the reference gave {:?}",
+            ours, theirs
+        ));
+    }
+    None
+}
+
+/// How the response of `ours` to `query` differs from that of
+/// `reference`, if it does, or if the reference answers and `ours` does
+/// not.
+fn differ(ours: SocketAddr, reference: SocketAddr, query: &Message) -> Option<String> {
+    // Without a response from the reference there is nothing to compare.
+    let theirs = ask(reference, query).ok()?;
+    match ask(ours, query) {
+        Ok(resp) => compare(query, &resp, &theirs),
+        Err(e) => Some(format!("no response: {}", e)),
+    }
+}
+
+fn describe(query: &Message) -> String {
+    let questions: Vec<String> = query
+        .questions
+        .iter()
+        .map(|q| format!("{} {}", q.name, q.qtype))
+        .collect();
+    questions.join(" + ")
+}
+
+fn reference(setting: &str) -> SocketAddr {
+    if setting != "system" {
+        return setting
+            .parse()
+            .unwrap_or_else(|_| panic!("MAIRU_DIFFERENTIAL: not an address: {}", setting));
+    }
+    *ResolverConfig::system()
+        .expect("cannot read /etc/resolv.conf")
+        .servers
+        .first()
+        .expect("no nameserver in /etc/resolv.conf")
+}
+
+#[test]
+fn differential() {
+    let setting = match env::var("MAIRU_DIFFERENTIAL") {
+        Ok(setting) => setting,
+        Err(_) => return,
+    };
+    let reference = reference(&setting);
+    let seed = env::var("MAIRU_DIFFERENTIAL_SEED")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64
+        })
+        // Xorshift never leaves zero.
+        | 1;
+    let count = env::var("MAIRU_DIFFERENTIAL_QUERIES")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(200);
+
+    let config = ResolverConfig {
+        servers: vec![reference],
+        timeout: TIMEOUT,
+        ..ResolverConfig::default()
+    };
+    // Without a cache every query goes through, as it does to the
+    // reference.
+    let forwarder = Forwarder::new().route(
+        Route::new(DomainName::root(), Resolver::new(config)).cache_policy(CachePolicy::disabled()),
+    );
+    let mut server = Server::new(forwarder);
+    let ours = server.listen_udp("127.0.0.1:0".parse().unwrap()).unwrap();
+    server.listen_tcp(ours).unwrap();
+    thread::spawn(move || server.run());
+
+    let mut rng = Rng(seed);
+    let mut failures = Vec::new();
+    for _ in 0..count {
+        let query = generate(&mut rng);
+        // Answers change and packets are lost; only a difference seen
+        // twice in a row is the crate's.
+        let diff = differ(ours, reference, &query).and_then(|_| differ(ours, reference, &query));
+        if let Some(diff) = diff {
+            failures.push(format!("{}: {}", describe(&query), diff));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} queries differ from {} (MAIRU_DIFFERENTIAL_SEED={}):\n{}",
+        failures.len(),
+        count,
+        reference,
+        seed,
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,231 @@
+//! The mDNS discovery proxy: unicast queries under a link's domain
+//! answered from what a responder on the link multicasts, with names
+//! moved out of `local.`, TTLs capped and link-local addresses left out,
+//! and the configuration of its links.
+//!
+//! The proxy and the responder meet in the IPv4 mDNS group on the default
+//! interface, which the host must allow joining.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::config::{Config, DiscoveryProxyConfig, ProxyLinkConfig};
+use mairudns::mdns::{DiscoveryProxy, LinkInterface, ProxyLink, Responder, Socket};
+use mairudns::message::{Message, Question, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordClass, RecordType};
+use mairudns::server::{Handler, HandlerExt, Request};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(qname: &str, qtype: RecordType) -> Request {
+    Request {
+        message: Message::query(name(qname), qtype),
+        src: "192.0.2.9:5300".parse::<SocketAddr>().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+fn link(domain: &str) -> ProxyLink {
+    ProxyLink::bind(name(domain), LinkInterface::V4(Ipv4Addr::UNSPECIFIED)).unwrap()
+}
+
+/// A responder on the link for a printer, with a routable and a
+/// link-local address and an IPP service.
+fn printer() -> Responder {
+    let mut responder = Responder::new(Socket::v4(Ipv4Addr::UNSPECIFIED).unwrap());
+    let a = |addr: &str| {
+        Record::new(
+            name("proxied-printer.local."),
+            120,
+            RData::A(addr.parse().unwrap()),
+        )
+    };
+    responder.add(a("10.1.2.3"), true);
+    responder.add(a("169.254.1.1"), true);
+    responder.add(
+        Record::new(
+            name("_ipp._tcp.local."),
+            4500,
+            RData::Ptr(name("Office._ipp._tcp.local.")),
+        ),
+        false,
+    );
+    responder.add(
+        Record::new(
+            name("Office._ipp._tcp.local."),
+            120,
+            RData::Srv {
+                priority: 0,
+                weight: 0,
+                port: 631,
+                target: name("proxied-printer.local."),
+            },
+        ),
+        true,
+    );
+    responder
+}
+
+#[test]
+fn links_answer_unicast_queries_from_mdns() {
+    let mut responder = printer();
+    thread::spawn(move || responder.serve());
+    let proxy = DiscoveryProxy::new()
+        .link(link("lab.home.arpa."))
+        .link(link("home.arpa."))
+        .wait(Duration::from_millis(800));
+    let listening = proxy.clone();
+    thread::spawn(move || listening.run());
+    let handler = (|request: &Request| {
+        let mut resp = request.message.response();
+        resp.header.rcode = Rcode::REFUSED;
+        Some(resp)
+    })
+    .with(proxy);
+
+    // Only the routable address, at most ten seconds, under the link's
+    // domain.
+    let resp = handler
+        .handle(&request("proxied-printer.lab.home.arpa.", RecordType::A))
+        .unwrap();
+    assert!(resp.header.aa);
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("proxied-printer.lab.home.arpa."),
+            10,
+            RData::A([10, 1, 2, 3].into()),
+        )]
+    );
+
+    // Browsing gives the instances, with their SRV records and the
+    // addresses of their targets.
+    let resp = handler
+        .handle(&request("_ipp._tcp.lab.home.arpa.", RecordType::PTR))
+        .unwrap();
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("_ipp._tcp.lab.home.arpa."),
+            10,
+            RData::Ptr(name("Office._ipp._tcp.lab.home.arpa.")),
+        )]
+    );
+    let srv = resp
+        .additional
+        .iter()
+        .find(|rr| rr.rtype() == RecordType::SRV)
+        .unwrap();
+    assert_eq!(srv.name, name("Office._ipp._tcp.lab.home.arpa."));
+    assert!(matches!(&srv.rdata, RData::Srv { port: 631, target, .. }
+        if *target == name("proxied-printer.lab.home.arpa.")));
+    assert!(
+        resp.additional
+            .iter()
+            .any(|rr| rr.name == name("proxied-printer.lab.home.arpa.")
+                && rr.rtype() == RecordType::A)
+    );
+
+    // The apex has an SOA, and names with nothing to show get it in the
+    // authority section, since mDNS cannot prove a name absent.
+    let resp = handler
+        .handle(&request("lab.home.arpa.", RecordType::SOA))
+        .unwrap();
+    assert_eq!(resp.answers.len(), 1);
+    assert_eq!(resp.answers[0].ttl, 10);
+    let resp = handler
+        .handle(&request("proxied-printer.lab.home.arpa.", RecordType::TXT))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+    assert_eq!(resp.authority[0].name, name("lab.home.arpa."));
+
+    // The most specific link answers: the outer one has its own apex.
+    let resp = handler
+        .handle(&request("home.arpa.", RecordType::SOA))
+        .unwrap();
+    assert_eq!(resp.answers[0].name, name("home.arpa."));
+
+    // Other classes are refused, and names outside the links passed on.
+    let mut chaos = request("proxied-printer.lab.home.arpa.", RecordType::A);
+    chaos.message.questions = vec![Question {
+        qclass: RecordClass::CH,
+        ..chaos.message.questions[0].clone()
+    }];
+    assert_eq!(handler.handle(&chaos).unwrap().header.rcode, Rcode::REFUSED);
+    let resp = handler
+        .handle(&request("www.example.", RecordType::A))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::REFUSED);
+    assert!(!resp.header.aa);
+}
+
+#[test]
+fn proxy_links_come_from_the_configuration() {
+    let config = ProxyLinkConfig {
+        domain: "lab.home.arpa.".into(),
+        ..ProxyLinkConfig::default()
+    };
+    assert_eq!(
+        config.to_link().unwrap(),
+        (
+            name("lab.home.arpa."),
+            LinkInterface::V4(Ipv4Addr::UNSPECIFIED)
+        )
+    );
+    let v4 = ProxyLinkConfig {
+        address: Some("192.168.1.1".into()),
+        ..config.clone()
+    };
+    assert_eq!(
+        v4.to_link().unwrap().1,
+        LinkInterface::V4(Ipv4Addr::new(192, 168, 1, 1))
+    );
+    let v6 = ProxyLinkConfig {
+        ipv6_interface: Some(2),
+        ..config.clone()
+    };
+    assert_eq!(v6.to_link().unwrap().1, LinkInterface::V6(2));
+
+    // Problems are reported against the field that has them.
+    let field = |proxy: DiscoveryProxyConfig| {
+        let problems = Config::new().discovery_proxy(proxy).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    let with_link = |link: ProxyLinkConfig| DiscoveryProxyConfig {
+        links: vec![link],
+        ..DiscoveryProxyConfig::default()
+    };
+    assert_eq!(
+        field(DiscoveryProxyConfig::default()),
+        "discovery-proxy.links"
+    );
+    assert_eq!(
+        field(with_link(ProxyLinkConfig {
+            domain: "lab.local.".into(),
+            ..config.clone()
+        })),
+        "discovery-proxy.links[0].domain"
+    );
+    assert_eq!(
+        field(with_link(ProxyLinkConfig {
+            address: Some("fe80::1".into()),
+            ..config.clone()
+        })),
+        "discovery-proxy.links[0].address"
+    );
+    assert_eq!(
+        field(with_link(ProxyLinkConfig {
+            ipv6_interface: Some(2),
+            ..v4
+        })),
+        "discovery-proxy.links[0].address"
+    );
+}
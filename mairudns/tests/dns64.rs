@@ -0,0 +1,318 @@
+//! DNS64 synthesis in the resolver, against a mock server that knows some
+//! names only by their A records, and discovery of the NAT64 prefix of a
+//! DNS64 through `ipv4only.arpa.`.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use mairudns::addr::Nat64Prefix;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{
+    discover_nat64, Dns64, QueryOptions, Resolver, ResolverConfig, IPV4ONLY_ADDRS, IPV4ONLY_ARPA,
+};
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn well_known() -> Nat64Prefix {
+    Nat64Prefix::new("64:ff9b::".parse().unwrap(), 96).unwrap()
+}
+
+fn a(owner: &str, addr: [u8; 4], ttl: u32) -> Record {
+    Record::new(name(owner), ttl, RData::A(addr.into()))
+}
+
+fn aaaa(owner: &str, addr: &str) -> Record {
+    Record::new(name(owner), 300, RData::Aaaa(addr.parse().unwrap()))
+}
+
+fn nodata(ttl: u32, minimum: u32) -> Action {
+    let soa = Soa {
+        mname: name("ns.example"),
+        rname: name("hostmaster.example"),
+        serial: 1,
+        refresh: 3600,
+        retry: 600,
+        expire: 86400,
+        minimum,
+    };
+    Action::Respond(Message {
+        authority: vec![Record::new(name("example"), ttl, RData::Soa(soa))],
+        ..Message::default()
+    })
+}
+
+fn resolver(server: &MockServer, dns64: Dns64) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        dns64: Some(dns64),
+        ..ResolverConfig::default()
+    })
+}
+
+fn addresses(resp: &Message) -> Vec<Ipv6Addr> {
+    resp.answers
+        .iter()
+        .filter_map(|rr| match rr.rdata {
+            RData::Aaaa(a) => Some(a),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn synthesizes_aaaa_from_a_with_the_negative_ttl_as_a_cap() {
+    let server = MockServer::builder()
+        .on(name("v4.example"), RecordType::AAAA, nodata(600, 60))
+        .answer(
+            name("v4.example"),
+            RecordType::A,
+            vec![a("v4.example", [192, 0, 2, 33], 300)],
+        )
+        .start()
+        .unwrap();
+    let resp = resolver(&server, Dns64::new(well_known()))
+        .query(&name("v4.example"), RecordType::AAAA)
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(!resp.header.ad);
+    assert_eq!(
+        addresses(&resp),
+        vec!["64:ff9b::c000:221".parse::<Ipv6Addr>().unwrap()]
+    );
+    assert_eq!(resp.answers[0].ttl, 60);
+    assert!(resp.authority.is_empty());
+}
+
+#[test]
+fn keeps_real_aaaa_records() {
+    let server = MockServer::builder()
+        .answer(
+            name("dual.example"),
+            RecordType::AAAA,
+            vec![aaaa("dual.example", "2001:db8::1")],
+        )
+        .answer(
+            name("dual.example"),
+            RecordType::A,
+            vec![a("dual.example", [192, 0, 2, 1], 300)],
+        )
+        .start()
+        .unwrap();
+    let resp = resolver(&server, Dns64::new(well_known()))
+        .query(&name("dual.example"), RecordType::AAAA)
+        .unwrap();
+    assert_eq!(
+        addresses(&resp),
+        vec!["2001:db8::1".parse::<Ipv6Addr>().unwrap()]
+    );
+    // No A query was needed.
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn treats_excluded_aaaa_records_as_absent() {
+    let server = MockServer::builder()
+        .answer(
+            name("mapped.example"),
+            RecordType::AAAA,
+            vec![aaaa("mapped.example", "::ffff:192.0.2.9")],
+        )
+        .answer(
+            name("mapped.example"),
+            RecordType::A,
+            vec![a("mapped.example", [192, 0, 2, 9], 300)],
+        )
+        .start()
+        .unwrap();
+    let resp = resolver(&server, Dns64::new(well_known()))
+        .query(&name("mapped.example"), RecordType::AAAA)
+        .unwrap();
+    assert_eq!(
+        addresses(&resp),
+        vec!["64:ff9b::c000:209".parse::<Ipv6Addr>().unwrap()]
+    );
+}
+
+#[test]
+fn skips_excluded_a_records() {
+    let server = MockServer::builder()
+        .on(name("private.example"), RecordType::AAAA, nodata(600, 600))
+        .answer(
+            name("private.example"),
+            RecordType::A,
+            vec![
+                a("private.example", [10, 0, 0, 1], 300),
+                a("private.example", [192, 0, 2, 1], 300),
+            ],
+        )
+        .start()
+        .unwrap();
+    let mut dns64 = Dns64::new(well_known());
+    dns64.exclude_v4.push("10.0.0.0/8".parse().unwrap());
+    let resp = resolver(&server, dns64)
+        .query(&name("private.example"), RecordType::AAAA)
+        .unwrap();
+    assert_eq!(
+        addresses(&resp),
+        vec!["64:ff9b::c000:201".parse::<Ipv6Addr>().unwrap()]
+    );
+}
+
+#[test]
+fn leaves_nxdomain_alone() {
+    let server = MockServer::builder()
+        .on(
+            name("gone.example"),
+            RecordType::AAAA,
+            Action::Rcode(Rcode::NXDOMAIN),
+        )
+        .start()
+        .unwrap();
+    let resp = resolver(&server, Dns64::new(well_known()))
+        .query(&name("gone.example"), RecordType::AAAA)
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    assert_eq!(server.received().len(), 1);
+}
+
+#[test]
+fn leaves_validating_clients_alone() {
+    let server = MockServer::builder()
+        .on(name("v4.example"), RecordType::AAAA, nodata(600, 600))
+        .answer(
+            name("v4.example"),
+            RecordType::A,
+            vec![a("v4.example", [192, 0, 2, 33], 300)],
+        )
+        .start()
+        .unwrap();
+    let options = QueryOptions {
+        dnssec_ok: true,
+        checking_disabled: true,
+        ..QueryOptions::default()
+    };
+    let resp = resolver(&server, Dns64::new(well_known()))
+        .query_with(&name("v4.example"), RecordType::AAAA, &options)
+        .unwrap();
+    assert!(resp.answers.is_empty());
+}
+
+#[test]
+fn redirects_reverse_lookups_inside_the_prefix() {
+    let v4_ptr = name("33.2.0.192.in-addr.arpa");
+    let server = MockServer::builder()
+        .answer(
+            v4_ptr.clone(),
+            RecordType::PTR,
+            vec![Record::new(
+                v4_ptr.clone(),
+                120,
+                RData::Ptr(name("v4.example")),
+            )],
+        )
+        .start()
+        .unwrap();
+    let v6: Ipv6Addr = well_known().embed(Ipv4Addr::new(192, 0, 2, 33));
+    let ptr = mairudns::addr::reverse_name(v6.into());
+    let resp = resolver(&server, Dns64::new(well_known()))
+        .query(&ptr, RecordType::PTR)
+        .unwrap();
+    assert_eq!(resp.questions[0].name, ptr);
+    assert_eq!(resp.answers[0], Record::new(ptr, 120, RData::Cname(v4_ptr)));
+    assert_eq!(resp.answers[1].rdata, RData::Ptr(name("v4.example")));
+}
+
+/// A DNS64 answering for `ipv4only.arpa.` with `addrs`, the first with a
+/// TTL of 100 and each next one a second longer, and a resolver asking it.
+fn synthesizing(addrs: &[Ipv6Addr]) -> (MockServer, Resolver) {
+    let owner = name(IPV4ONLY_ARPA);
+    let records = addrs
+        .iter()
+        .zip(100..)
+        .map(|(&addr, ttl)| Record::new(owner.clone(), ttl, RData::Aaaa(addr)))
+        .collect();
+    let server = MockServer::builder()
+        .answer(owner, RecordType::AAAA, records)
+        .start()
+        .unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    (server, resolver)
+}
+
+/// The well-known addresses embedded in `prefix`.
+fn embedded(prefix: Nat64Prefix) -> Vec<Ipv6Addr> {
+    IPV4ONLY_ADDRS.iter().map(|&v4| prefix.embed(v4)).collect()
+}
+
+#[test]
+fn discovers_the_nat64_prefix_at_each_length() {
+    for (addr, len) in [
+        ("64:ff9b::", 96),
+        ("2001:db8::", 32),
+        ("2001:db8:100::", 40),
+        ("2001:db8:122::", 48),
+        ("2001:db8:122:300::", 56),
+        ("2001:db8:122:344::", 64),
+    ] {
+        let prefix = Nat64Prefix::new(addr.parse().unwrap(), len).unwrap();
+        let (_server, resolver) = synthesizing(&embedded(prefix));
+        let found = discover_nat64(&resolver).unwrap().unwrap();
+        assert_eq!(found.prefixes, [prefix]);
+        assert_eq!(found.ttl, 100);
+        assert_eq!(found.dns64(), Some(Dns64::new(prefix)));
+    }
+
+    // Several prefixes are kept in the order given, the first to be used.
+    let other = Nat64Prefix::new("2001:db8:64::".parse().unwrap(), 96).unwrap();
+    let mut addrs = embedded(other);
+    addrs.extend(embedded(well_known()));
+    let (_server, resolver) = synthesizing(&addrs);
+    let found = discover_nat64(&resolver).unwrap().unwrap();
+    assert_eq!(found.prefixes, [other, well_known()]);
+    assert_eq!(found.ttl, 100);
+}
+
+#[test]
+fn finds_no_prefix_without_synthesis() {
+    let (_server, resolver) = synthesizing(&[]);
+    assert_eq!(discover_nat64(&resolver).unwrap(), None);
+    let (_server, resolver) = synthesizing(&["2001:db8::1".parse().unwrap()]);
+    assert_eq!(discover_nat64(&resolver).unwrap(), None);
+    let server = MockServer::builder()
+        .then(Action::Rcode(Rcode::NXDOMAIN))
+        .start()
+        .unwrap();
+    let plain = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    assert_eq!(Dns64::discover(&plain).unwrap(), None);
+}
+
+#[test]
+fn finds_each_address_at_the_length_it_was_embedded_at() {
+    // Prefix bits that spell 192.0.0.170 do not make it fit a /32 too.
+    let prefix = Nat64Prefix::new("2001:db8:c000:aa::".parse().unwrap(), 64).unwrap();
+    let first = prefix.embed(IPV4ONLY_ADDRS[0]);
+    assert_eq!(Nat64Prefix::embedding(first, IPV4ONLY_ADDRS[0]), [prefix]);
+    assert_eq!(Nat64Prefix::embedding(first, IPV4ONLY_ADDRS[1]), []);
+
+    // Either address is enough.
+    let (_server, resolver) = synthesizing(&[first]);
+    assert_eq!(
+        Dns64::discover(&resolver).unwrap(),
+        Some(Dns64::new(prefix))
+    );
+}
@@ -0,0 +1,402 @@
+//! The DNSCrypt client against a resolver built on `crypto_box`, a
+//! separate implementation of X25519-XSalsa20Poly1305: stamps, choosing
+//! among signed certificates, padding, and falling back to TCP when UDP
+//! answers are truncated.
+
+#![cfg(feature = "dnscrypt")]
+
+use std::net::{TcpListener, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crypto_box::aead::Aead;
+use crypto_box::{PublicKey, SalsaBox, SecretKey};
+use ed25519_dalek::{Signer, SigningKey};
+
+use mairudns::client::{read_framed, write_framed, Protocol};
+use mairudns::dnscrypt::{
+    Certificate, DnsCrypt, Error, Provider, CERT_MAGIC, MIN_QUERY_LEN, RESOLVER_MAGIC,
+};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::{QueryOptions, Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+const PROVIDER_NAME: &str = "2.dnscrypt-cert.example.";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+fn provider_key() -> SigningKey {
+    SigningKey::from_bytes(&[7; 32])
+}
+
+/// A certificate for `resolver_key`, signed by `signer`.
+fn certificate(
+    signer: &SigningKey,
+    resolver_key: &PublicKey,
+    magic: [u8; 8],
+    serial: u32,
+    valid: (u32, u32),
+) -> Vec<u8> {
+    let mut signed = resolver_key.as_bytes().to_vec();
+    signed.extend_from_slice(&magic);
+    signed.extend_from_slice(&serial.to_be_bytes());
+    signed.extend_from_slice(&valid.0.to_be_bytes());
+    signed.extend_from_slice(&valid.1.to_be_bytes());
+    let mut cert = CERT_MAGIC.to_vec();
+    cert.extend_from_slice(&[0, 1, 0, 0]);
+    cert.extend_from_slice(&signer.sign(&signed).to_bytes());
+    cert.extend_from_slice(&signed);
+    cert
+}
+
+/// What the resolver saw of the queries it was sent.
+#[derive(Default)]
+struct Seen {
+    /// The length of each boxed query's padded plaintext, by protocol.
+    padded: Vec<(Protocol, usize)>,
+    /// Distinct client public keys.
+    clients: Vec<[u8; 32]>,
+}
+
+/// A DNSCrypt resolver on UDP and TCP at one port. It serves `certs` in
+/// the clear and answers boxed A queries with 192.0.2.1, truncating UDP
+/// answers for names under big.example.
+struct FakeResolver {
+    secret: SecretKey,
+    magic: [u8; 8],
+    certs: Vec<Vec<u8>>,
+    seen: Mutex<Seen>,
+}
+
+impl FakeResolver {
+    fn answer(&self, packet: &[u8], protocol: Protocol) -> Option<Vec<u8>> {
+        if !packet.starts_with(&self.magic) {
+            let query = Message::from_wire(packet).ok()?;
+            let mut resp = query.response();
+            for cert in &self.certs {
+                resp.answers.push(Record::new(
+                    name(PROVIDER_NAME),
+                    3600,
+                    RData::Txt(vec![cert.clone()]),
+                ));
+            }
+            return resp.to_wire().ok();
+        }
+        let mut client = [0u8; 32];
+        client.copy_from_slice(&packet[8..40]);
+        let shared = SalsaBox::new(&PublicKey::from(client), &self.secret);
+        let mut nonce = [0u8; 24];
+        nonce[..12].copy_from_slice(&packet[40..52]);
+        let mut padded = shared.decrypt(&nonce.into(), &packet[52..]).ok()?;
+        {
+            let mut seen = self.seen.lock().unwrap();
+            seen.padded.push((protocol, padded.len()));
+            if !seen.clients.contains(&client) {
+                seen.clients.push(client);
+            }
+        }
+        let end = padded.iter().rposition(|&b| b != 0)?;
+        assert_eq!(padded[end], 0x80);
+        padded.truncate(end);
+
+        let query = Message::from_wire(&padded).unwrap();
+        let q = query.question().unwrap().clone();
+        let mut resp = query.response();
+        if protocol == Protocol::Udp && q.name.is_subdomain_of(&name("big.example.")) {
+            resp.header.tc = true;
+        } else {
+            resp.answers.push(Record::new(
+                q.name,
+                300,
+                RData::A("192.0.2.1".parse().unwrap()),
+            ));
+        }
+        let mut wire = resp.to_wire().unwrap();
+        wire.push(0x80);
+        wire.resize(wire.len().div_ceil(64) * 64, 0);
+        nonce[12..].copy_from_slice(&[5; 12]);
+        let mut out = RESOLVER_MAGIC.to_vec();
+        out.extend_from_slice(&nonce);
+        out.extend(shared.encrypt(&nonce.into(), &wire[..]).unwrap());
+        Some(out)
+    }
+}
+
+/// Starts `resolver` and returns the provider that describes it.
+fn start(resolver: FakeResolver) -> (Provider, Arc<FakeResolver>) {
+    let (udp, tcp) = loop {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+            break (udp, tcp);
+        }
+    };
+    let provider = Provider {
+        address: udp.local_addr().unwrap(),
+        name: name(PROVIDER_NAME),
+        public_key: provider_key().verifying_key().to_bytes(),
+    };
+    let resolver = Arc::new(resolver);
+    let on_udp = resolver.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        while let Ok((n, peer)) = udp.recv_from(&mut buf) {
+            if let Some(out) = on_udp.answer(&buf[..n], Protocol::Udp) {
+                udp.send_to(&out, peer).unwrap();
+            }
+        }
+    });
+    let on_tcp = resolver.clone();
+    thread::spawn(move || {
+        for mut stream in tcp.incoming().flatten() {
+            let packet = read_framed(&mut stream).unwrap();
+            if let Some(out) = on_tcp.answer(&packet, Protocol::Tcp) {
+                write_framed(&mut stream, &out).unwrap();
+            }
+        }
+    });
+    (provider, resolver)
+}
+
+fn resolver_with_cert(serial: u32) -> FakeResolver {
+    let secret = SecretKey::from([9; 32]);
+    let magic = *b"mairuQ01";
+    let now = now();
+    let certs = vec![certificate(
+        &provider_key(),
+        &secret.public_key(),
+        magic,
+        serial,
+        (now - 60, now + 3600),
+    )];
+    FakeResolver {
+        secret,
+        magic,
+        certs,
+        seen: Mutex::default(),
+    }
+}
+
+#[test]
+fn reads_stamps() {
+    // The scaleway-fr entry of the public resolver list.
+    let provider = Provider::from_stamp(
+        "sdns://AQcAAAAAAAAADjIxMi40Ny4yMjguMTM2IOgBuE6mBr-wusDOQ0RbsV66ZLAvo8SqMa4QY2oHkDJNHz\
+         IuZG5zY3J5cHQtY2VydC5mci5kbnNjcnlwdC5vcmc",
+    )
+    .unwrap();
+    assert_eq!(provider.address, "212.47.228.136:443".parse().unwrap());
+    assert_eq!(provider.name, name("2.dnscrypt-cert.fr.dnscrypt.org."));
+    assert_eq!(
+        provider.public_key,
+        [
+            0xe8, 0x01, 0xb8, 0x4e, 0xa6, 0x06, 0xbf, 0xb0, 0xba, 0xc0, 0xce, 0x43, 0x44, 0x5b,
+            0xb1, 0x5e, 0xba, 0x64, 0xb0, 0x2f, 0xa3, 0xc4, 0xaa, 0x31, 0xae, 0x10, 0x63, 0x6a,
+            0x07, 0x90, 0x32, 0x4d
+        ]
+    );
+    assert_eq!(
+        Provider::from_stamp(&provider.to_stamp()),
+        Ok(provider.clone())
+    );
+
+    // Ports other than 443 are kept, and IPv6 addresses bracketed.
+    for address in ["192.0.2.1:8443", "[2001:db8::1]:443", "[2001:db8::1]:5353"] {
+        let other = Provider {
+            address: address.parse().unwrap(),
+            ..provider.clone()
+        };
+        assert_eq!(Provider::from_stamp(&other.to_stamp()), Ok(other));
+    }
+
+    for (stamp, why) in [
+        ("https://example.com/", "not an sdns:// URL"),
+        ("sdns://!!", "bad base64"),
+        // A DoH stamp.
+        ("sdns://AgAAAAAAAAAA", "not a DNSCrypt stamp"),
+        ("sdns://AQAAAAAAAAAA", "truncated"),
+        ("sdns://AQAAAAAAAAAAA2Zvbw", "bad address"),
+    ] {
+        assert_eq!(
+            Provider::from_stamp(stamp),
+            Err(Error::BadStamp(why)),
+            "{}",
+            stamp
+        );
+    }
+}
+
+#[test]
+fn verifies_certificates() {
+    let secret = SecretKey::from([9; 32]);
+    let signer = provider_key();
+    let key = signer.verifying_key().to_bytes();
+    let cert = certificate(&signer, &secret.public_key(), *b"mairuQ01", 7, (100, 200));
+    let parsed = Certificate::parse(&cert, &key).unwrap();
+    assert_eq!(parsed.es_version, 1);
+    assert_eq!(parsed.resolver_key, *secret.public_key().as_bytes());
+    assert_eq!(parsed.client_magic, *b"mairuQ01");
+    assert_eq!(parsed.serial, 7);
+    assert!(!parsed.is_valid_at(99));
+    assert!(parsed.is_valid_at(100) && parsed.is_valid_at(200));
+    assert!(!parsed.is_valid_at(201));
+
+    let other = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes();
+    assert_eq!(Certificate::parse(&cert, &other), Err(Error::BadSignature));
+    let mut tampered = cert.clone();
+    tampered[80] ^= 1;
+    assert_eq!(
+        Certificate::parse(&tampered, &key),
+        Err(Error::BadSignature)
+    );
+    let mut chacha = cert.clone();
+    chacha[5] = 2;
+    assert_eq!(
+        Certificate::parse(&chacha, &key),
+        Err(Error::BadCertificate("unsupported encryption system"))
+    );
+    assert_eq!(
+        Certificate::parse(&cert[..123], &key),
+        Err(Error::BadCertificate("truncated"))
+    );
+}
+
+#[test]
+fn uses_the_newest_valid_certificate() {
+    let secret = SecretKey::from([9; 32]);
+    let stale = SecretKey::from([10; 32]);
+    let signer = provider_key();
+    let now = now();
+    let mut resolver = resolver_with_cert(5);
+    resolver.certs.extend([
+        // Newer, but expired or not yet valid.
+        certificate(
+            &signer,
+            &stale.public_key(),
+            *b"mairuQ02",
+            9,
+            (now - 7200, now - 3600),
+        ),
+        certificate(
+            &signer,
+            &stale.public_key(),
+            *b"mairuQ03",
+            8,
+            (now + 3600, now + 7200),
+        ),
+        // Newer still, but not the provider's.
+        certificate(
+            &SigningKey::from_bytes(&[8; 32]),
+            &stale.public_key(),
+            *b"mairuQ04",
+            10,
+            (now - 60, now + 3600),
+        ),
+        // Valid, but older.
+        certificate(
+            &signer,
+            &stale.public_key(),
+            *b"mairuQ05",
+            4,
+            (now - 60, now + 3600),
+        ),
+    ]);
+    let (provider, _) = start(resolver);
+    let client = DnsCrypt::new(provider);
+    assert_eq!(client.fetch_certificates(TIMEOUT).unwrap().len(), 4);
+    let cert = client.certificate(TIMEOUT).unwrap();
+    assert_eq!(cert.serial, 5);
+    assert_eq!(cert.resolver_key, *secret.public_key().as_bytes());
+
+    let (provider, _) = start(FakeResolver {
+        certs: Vec::new(),
+        ..resolver_with_cert(1)
+    });
+    let query = Message::query(name("www.example."), RecordType::A);
+    assert!(DnsCrypt::new(provider)
+        .exchange(&query, None, TIMEOUT)
+        .is_err());
+}
+
+#[test]
+fn pads_and_boxes_each_query_from_a_fresh_key() {
+    let (provider, resolver) = start(resolver_with_cert(1));
+    let client = DnsCrypt::new(provider);
+    for protocol in [
+        Some(Protocol::Udp),
+        Some(Protocol::Tcp),
+        Some(Protocol::Udp),
+    ] {
+        let query = Message::query(name("www.example."), RecordType::A);
+        let resp = client.exchange(&query, protocol, TIMEOUT).unwrap();
+        assert_eq!(resp.header.id, query.header.id);
+        assert_eq!(
+            resp.answers[0].rdata,
+            RData::A("192.0.2.1".parse().unwrap())
+        );
+    }
+    let seen = resolver.seen.lock().unwrap();
+    assert_eq!(
+        seen.padded,
+        vec![
+            (Protocol::Udp, MIN_QUERY_LEN),
+            (Protocol::Tcp, 64),
+            (Protocol::Udp, MIN_QUERY_LEN)
+        ]
+    );
+    assert_eq!(seen.clients.len(), 3);
+}
+
+#[test]
+fn falls_back_to_tcp_and_pads_udp_more() {
+    let (provider, resolver) = start(resolver_with_cert(1));
+    let client = DnsCrypt::new(provider.clone());
+    let query = Message::query(name("a.big.example."), RecordType::A);
+    let resp = client.exchange(&query, None, TIMEOUT).unwrap();
+    assert!(!resp.header.tc);
+    assert_eq!(resp.answers.len(), 1);
+    // Pinned to UDP, the truncated answer is returned as it is.
+    let resp = client
+        .exchange(&query, Some(Protocol::Udp), TIMEOUT)
+        .unwrap();
+    assert!(resp.header.tc);
+    assert_eq!(
+        resolver.seen.lock().unwrap().padded,
+        vec![
+            (Protocol::Udp, MIN_QUERY_LEN),
+            (Protocol::Tcp, 64),
+            (Protocol::Udp, MIN_QUERY_LEN + 64)
+        ]
+    );
+
+    // The stub resolver sends queries to DNSCrypt servers the same way.
+    let mut config = ResolverConfig {
+        servers: Vec::new(),
+        ..ResolverConfig::default()
+    };
+    config.add_dnscrypt(provider);
+    let stub = Resolver::new(config);
+    let answers = stub.lookup(&name("b.big.example."), RecordType::A).unwrap();
+    assert_eq!(answers[0].rdata, RData::A("192.0.2.1".parse().unwrap()));
+    let pinned = QueryOptions {
+        protocol: Some(Protocol::Tcp),
+        ..QueryOptions::default()
+    };
+    stub.lookup_with(&name("www.example."), RecordType::A, &pinned)
+        .unwrap();
+    assert_eq!(
+        resolver.seen.lock().unwrap().padded.last(),
+        Some(&(Protocol::Tcp, 64))
+    );
+}
@@ -0,0 +1,227 @@
+//! DNS Stateful Operations: DSO messages on the wire, sessions set up
+//! with the server's timeouts and carrying queries, the server's answers
+//! to DSO requests it does not take, and the settings that configure it.
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::{read_framed, write_framed, DsoSession, Protocol};
+use mairudns::config::{Config, ServerSettings};
+use mairudns::dso::{self, DsoMessage, DsoType, Keepalive, Tlv};
+use mairudns::message::{Message, Opcode, Rcode};
+use mairudns::rr::RecordType;
+use mairudns::server::{Request, Server};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn secs(s: u64) -> Option<Duration> {
+    Some(Duration::from_secs(s))
+}
+
+/// A server answering every query with an empty response, offering DSO
+/// sessions with `dso`, and the address of its TCP listener.
+fn start(dso: Option<Keepalive>) -> SocketAddr {
+    let mut server = Server::new(|r: &Request| Some(r.message.response()));
+    server.set_dso(dso);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Tcp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    addr
+}
+
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream
+}
+
+/// Sends `msg` on `stream` and reads the DSO message that comes back.
+fn exchange(stream: &mut TcpStream, msg: &DsoMessage) -> DsoMessage {
+    write_framed(stream, &msg.to_wire().unwrap()).unwrap();
+    DsoMessage::from_wire(&read_framed(stream).unwrap()).unwrap()
+}
+
+/// Whether the server has closed `stream`.
+fn closed(stream: &mut TcpStream) -> bool {
+    match read_framed(stream) {
+        Err(e) => !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+        Ok(_) => false,
+    }
+}
+
+#[test]
+fn dso_messages_round_trip() {
+    let keepalive = DsoMessage::keepalive(&Keepalive {
+        inactivity_timeout: None,
+        keepalive_interval: secs(20),
+    });
+    assert_ne!(keepalive.header.id, 0);
+    assert!(!keepalive.is_unidirectional());
+    let wire = keepalive.to_wire().unwrap();
+    // The DSO opcode, four zero counts, then the TLV: all ones for
+    // infinity, and milliseconds.
+    assert!(dso::is_dso(&wire));
+    assert_eq!(&wire[4..12], &[0; 8]);
+    assert_eq!(
+        &wire[12..],
+        &[0, 1, 0, 8, 0xff, 0xff, 0xff, 0xff, 0, 0, 0x4e, 0x20]
+    );
+    let back = DsoMessage::from_wire(&wire).unwrap();
+    assert_eq!(back, keepalive);
+    assert_eq!(back.header.opcode, Opcode::DSO);
+    assert_eq!(
+        back.keepalive_values(),
+        Some(Keepalive {
+            inactivity_timeout: None,
+            keepalive_interval: secs(20),
+        })
+    );
+
+    let retry = DsoMessage::retry_delay(Duration::from_millis(1500));
+    let back = DsoMessage::from_wire(&retry.to_wire().unwrap()).unwrap();
+    assert!(back.is_unidirectional());
+    assert_eq!(back.primary().unwrap().dtype, DsoType::RETRY_DELAY);
+    assert_eq!(back.retry_delay_value(), Some(Duration::from_millis(1500)));
+    assert_eq!(back.keepalive_values(), None);
+
+    // Ordinary messages are not DSO, and DSO messages with records are
+    // malformed.
+    let query = Message::query("example.".parse().unwrap(), RecordType::A)
+        .to_wire()
+        .unwrap();
+    assert!(!dso::is_dso(&query));
+    assert!(DsoMessage::from_wire(&query).is_err());
+    let mut counted = wire.clone();
+    counted[5] = 1;
+    assert!(DsoMessage::from_wire(&counted).is_err());
+    assert!(DsoMessage::from_wire(&wire[..wire.len() - 1]).is_err());
+    assert!(Keepalive::from_tlv(&Tlv::new(DsoType::KEEPALIVE, vec![0; 4])).is_err());
+    assert_eq!(DsoType(0xf900).to_string(), "TYPE63744");
+    assert_eq!(DsoType::KEEPALIVE.to_string(), "KEEPALIVE");
+}
+
+#[test]
+fn sessions_take_the_servers_timeouts_and_carry_queries() {
+    let addr = start(Some(Keepalive {
+        inactivity_timeout: secs(30),
+        keepalive_interval: secs(3),
+    }));
+    let mut session = DsoSession::connect(addr, None, &Keepalive::default(), TIMEOUT).unwrap();
+    // The server's own timeouts, with the interval raised to the least
+    // the RFC allows.
+    assert_eq!(
+        session.timeouts(),
+        Keepalive {
+            inactivity_timeout: secs(30),
+            keepalive_interval: Some(dso::MIN_KEEPALIVE_INTERVAL),
+        }
+    );
+    assert!(!session.keepalive_due());
+    assert!(!session.inactivity_expired());
+    assert_eq!(session.retry_delay(), None);
+
+    // Queries and keepalives share the one connection.
+    for _ in 0..3 {
+        let query = Message::query("www.example.".parse().unwrap(), RecordType::A);
+        let resp = session.query(&query, TIMEOUT).unwrap();
+        assert_eq!(resp.header.id, query.header.id);
+        assert_eq!(resp.header.rcode, Rcode::NOERROR);
+        session.keepalive(TIMEOUT).unwrap();
+    }
+
+    // Infinite timeouts are kept as such.
+    let addr = start(Some(Keepalive {
+        inactivity_timeout: None,
+        keepalive_interval: None,
+    }));
+    let session = DsoSession::connect(addr, None, &Keepalive::default(), TIMEOUT).unwrap();
+    assert_eq!(session.timeouts().inactivity_timeout, None);
+    assert_eq!(session.timeouts().keepalive_interval, None);
+    assert!(!session.keepalive_due());
+}
+
+#[test]
+fn servers_refuse_what_they_do_not_take() {
+    // Without DSO, a session cannot be set up, but the connection still
+    // answers queries.
+    let addr = start(None);
+    assert!(DsoSession::connect(addr, None, &Keepalive::default(), TIMEOUT).is_err());
+    let mut stream = connect(addr);
+    let keepalive = DsoMessage::keepalive(&Keepalive::default());
+    let resp = exchange(&mut stream, &keepalive);
+    assert_eq!(resp.header.rcode, Rcode::NOTIMP);
+    assert_eq!(resp.header.id, keepalive.header.id);
+    assert!(resp.header.qr);
+    let query = Message::query("www.example.".parse().unwrap(), RecordType::A);
+    write_framed(&mut stream, &query.to_wire().unwrap()).unwrap();
+    let resp = Message::from_wire(&read_framed(&mut stream).unwrap()).unwrap();
+    assert_eq!(resp.header.id, query.header.id);
+
+    // An unknown TLV type is answered DSOTYPENI and the connection kept.
+    let addr = start(Some(Keepalive::default()));
+    let mut stream = connect(addr);
+    let unknown = DsoMessage::request(Tlv::new(DsoType(0xf900), vec![1, 2]));
+    let resp = exchange(&mut stream, &unknown);
+    assert_eq!(resp.header.rcode, Rcode::DSOTYPENI);
+    assert_eq!(resp.header.id, unknown.header.id);
+    let resp = exchange(&mut stream, &keepalive);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert_eq!(resp.keepalive_values(), Some(Keepalive::default()));
+
+    // A client sending what only servers may is answered FORMERR and
+    // cut off.
+    let retry = DsoMessage::request(dso::retry_delay_tlv(Duration::from_secs(1)));
+    let resp = exchange(&mut stream, &retry);
+    assert_eq!(resp.header.rcode, Rcode::FORMERR);
+    assert!(closed(&mut stream));
+
+    // As is a malformed Keepalive.
+    let mut stream = connect(addr);
+    let short = DsoMessage::request(Tlv::new(DsoType::KEEPALIVE, vec![0; 4]));
+    assert_eq!(exchange(&mut stream, &short).header.rcode, Rcode::FORMERR);
+    assert!(closed(&mut stream));
+
+    // Unidirectional messages from a client end the connection unanswered.
+    let mut stream = connect(addr);
+    let one_way = DsoMessage::unidirectional(Keepalive::default().to_tlv());
+    write_framed(&mut stream, &one_way.to_wire().unwrap()).unwrap();
+    assert!(closed(&mut stream));
+}
+
+#[test]
+fn dso_settings_come_from_the_configuration() {
+    let settings = ServerSettings::default();
+    assert_eq!(settings.to_dso(), Some(Keepalive::default()));
+    let settings = ServerSettings {
+        dso_inactivity_timeout_secs: 0,
+        dso_keepalive_interval_secs: 60,
+        ..ServerSettings::default()
+    };
+    assert_eq!(
+        settings.to_dso(),
+        Some(Keepalive {
+            inactivity_timeout: None,
+            keepalive_interval: secs(60),
+        })
+    );
+    let off = ServerSettings {
+        dso: false,
+        ..ServerSettings::default()
+    };
+    assert_eq!(off.to_dso(), None);
+
+    let mut config = Config::new();
+    config.server.dso_keepalive_interval_secs = 5;
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].field, "server.dso-keepalive-interval-secs");
+    config.server.dso_keepalive_interval_secs = 0;
+    assert!(config.validate().is_ok());
+}
@@ -0,0 +1,235 @@
+//! DNS error reporting (RFC 9567): report query names, the Report-Channel
+//! option authoritative zones advertise, the limits on the reports sent,
+//! and a validator reporting the failures of a zone to its agent.
+
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::clock::MockClock;
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::{ErrorReport, ErrorReporter, ReportPolicy, Resolver, ResolverConfig};
+use mairudns::rr::RecordType;
+use mairudns::server::Authority;
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn zone(origin: &str, body: &str) -> Zone {
+    let text = format!(
+        "$TTL 3600\n@ SOA ns hostmaster 1 3600 600 86400 300\n@ NS ns\nns A 192.0.2.53\n{}",
+        body
+    );
+    Zone::from_master(name(origin), &text).unwrap()
+}
+
+/// A server for `agent.test.`, answering every report.
+fn agent() -> MockServer {
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone("agent.test.", "*._er IN TXT \"thanks\"\n"));
+    MockServer::builder().handler(authority).start().unwrap()
+}
+
+fn resolver(server: &MockServer) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    })
+}
+
+/// The reports `server` has received for `agent.test.`, once `count` of
+/// them have arrived or a while has passed; they are sent in the
+/// background.
+fn reports(server: &MockServer, count: usize) -> Vec<ErrorReport> {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        let reports: Vec<ErrorReport> = server
+            .received()
+            .iter()
+            .filter_map(|r| r.message.question())
+            .filter_map(|q| ErrorReport::from_name(&q.name, &name("agent.test.")))
+            .collect();
+        if reports.len() >= count || Instant::now() > deadline {
+            return reports;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn report_names_encode_the_failure() {
+    // RFC 9567 §6.1.1's example.
+    let agent = name("a01.agent-domain.example.");
+    let report = ErrorReport::new(name("broken.test."), RecordType::A, 7);
+    let query = report.to_name(&agent).unwrap();
+    assert_eq!(
+        query,
+        name("_er.1.broken.test.7._er.a01.agent-domain.example.")
+    );
+    assert_eq!(ErrorReport::from_name(&query, &agent), Some(report));
+    let shouting = name("_ER.1.broken.test.7._ER.a01.agent-domain.example.");
+    assert!(ErrorReport::from_name(&shouting, &agent).is_some());
+
+    // A failure at the root names no labels of its own.
+    let root = ErrorReport::new(DomainName::root(), RecordType::NS, 9);
+    let query = root.to_name(&name("agent.")).unwrap();
+    assert_eq!(query, name("_er.2.9._er.agent."));
+    assert_eq!(ErrorReport::from_name(&query, &name("agent.")), Some(root));
+
+    for other in ["www.agent.", "_er.1.x.7.agent.", "_er.A.x.7._er.agent."] {
+        assert_eq!(ErrorReport::from_name(&name(other), &name("agent.")), None);
+    }
+    assert_eq!(ErrorReport::from_name(&query, &name("other.")), None);
+    // A report that would not fit in a name is not sent.
+    let long = format!("{0}.{0}.{0}.{0}.", "a".repeat(60));
+    let long = ErrorReport::new(name(&long), RecordType::A, 7);
+    assert_eq!(long.to_name(&name("agent.example.")), None);
+}
+
+#[test]
+fn zones_advertise_their_agent() {
+    let mut message = Message::query(name("www.example."), RecordType::A);
+    assert_eq!(message.report_channel(), None);
+    message.set_report_channel(&name("agent.test."));
+    message.set_report_channel(&name("other.test."));
+    assert_eq!(message.report_channel(), Some(name("other.test.")));
+    let wire = message.to_wire().unwrap();
+    assert_eq!(
+        Message::from_wire(&wire).unwrap().report_channel(),
+        Some(name("other.test."))
+    );
+    // The root cannot be an agent.
+    message.set_report_channel(&DomainName::root());
+    assert_eq!(message.report_channel(), Some(name("other.test.")));
+
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone("example.", "www A 192.0.2.1\n"));
+    authority.insert(zone("other.", "www A 192.0.2.2\n"));
+    authority.set_report_channel(name("example."), Some(name("agent.test.")));
+    let server = MockServer::builder()
+        .handler(authority.clone())
+        .start()
+        .unwrap();
+    let resolver = resolver(&server);
+    let ask = |qname: &str| {
+        resolver
+            .query(&name(qname), RecordType::A)
+            .unwrap()
+            .report_channel()
+    };
+    assert_eq!(ask("www.example."), Some(name("agent.test.")));
+    assert_eq!(ask("nope.example."), Some(name("agent.test.")));
+    assert_eq!(ask("www.other."), None);
+    authority.set_report_channel(name("example."), None);
+    assert_eq!(ask("www.example."), None);
+}
+
+#[test]
+fn reports_are_limited() {
+    let server = agent();
+    let clock = MockClock::new(1_700_000_000);
+    let policy = ReportPolicy {
+        reports_per_second: 2,
+        repeat_after: Duration::from_secs(60),
+        max_entries: 3,
+    };
+    let reporter = ErrorReporter::new(resolver(&server), policy).clock(Arc::new(clock.clone()));
+    let agent = name("agent.test.");
+    let report = |qname: &str| {
+        let report = ErrorReport::new(name(qname), RecordType::A, 6);
+        reporter.report(&report, &agent)
+    };
+    assert!(report("a.example."));
+    // The same report waits for `repeat_after`.
+    assert!(!report("a.example."));
+    assert!(report("b.example."));
+    // Two a second.
+    assert!(!report("c.example."));
+    clock.advance(Duration::from_secs(1));
+    assert!(report("c.example."));
+    // Three remembered, none of them old enough to forget.
+    assert!(!report("d.example."));
+    clock.advance(Duration::from_secs(60));
+    assert!(report("d.example."));
+    assert!(report("a.example."));
+    // Nothing is reported about a report.
+    assert!(!report("_er.1.x.7._er.agent.test."));
+
+    let mut sent: Vec<DomainName> = reports(&server, 5).into_iter().map(|r| r.qname).collect();
+    sent.sort();
+    let expected = [
+        "a.example.",
+        "a.example.",
+        "b.example.",
+        "c.example.",
+        "d.example.",
+    ];
+    assert_eq!(sent, expected.map(name));
+
+    // A response without an agent has nowhere to go.
+    clock.advance(Duration::from_secs(1));
+    let response = Message::query(name("www.example."), RecordType::A).response();
+    assert!(!reporter.report_response(&response, 6));
+    let mut response = response;
+    response.set_report_channel(&agent);
+    assert!(reporter.report_response(&response, 6));
+    assert!(!ErrorReporter::new(
+        resolver(&server),
+        ReportPolicy {
+            reports_per_second: 0,
+            ..ReportPolicy::default()
+        }
+    )
+    .report_response(&response, 6));
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn validators_report_failures_once() {
+    use mairudns::dnssec::{sign_zone, SigningKey, SigningPolicy, Status, TrustAnchors, Validator};
+    use mairudns::message::ExtendedError;
+
+    // `example.` is signed by a key other than the one trusted.
+    let signed = |seed| {
+        let key = SigningKey::ed25519(257, &[seed; 32]).unwrap();
+        let zone = zone("example.", "www A 192.0.2.1\n");
+        sign_zone(&zone, &[key], &SigningPolicy::default()).unwrap()
+    };
+    let served = signed(7);
+    let anchors = TrustAnchors::from_records(
+        signed(8)
+            .rrset(&name("example."), RecordType::DNSKEY)
+            .unwrap(),
+    );
+    let authority = Arc::new(Authority::new());
+    authority.insert(served);
+    authority.insert(zone("agent.test.", "*._er IN TXT \"thanks\"\n"));
+    authority.set_report_channel(name("example."), Some(name("agent.test.")));
+    let server = MockServer::builder().handler(authority).start().unwrap();
+
+    let validator = Validator::new(resolver(&server))
+        .anchors(anchors)
+        .report_errors(ReportPolicy::default());
+    for _ in 0..3 {
+        let validated = validator
+            .query(&name("www.example."), RecordType::A)
+            .unwrap();
+        assert_eq!(validated.status, Status::Bogus);
+    }
+    let sent = reports(&server, 1);
+    assert_eq!(
+        sent,
+        [ErrorReport::new(
+            name("www.example."),
+            RecordType::A,
+            ExtendedError::DNSKEY_MISSING
+        )]
+    );
+    // Not even once more, after a pause.
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(reports(&server, 0).len(), 1);
+}
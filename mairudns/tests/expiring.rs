@@ -0,0 +1,116 @@
+//! Expiring values and TTLs counted down: records handed on with what is
+//! left of their TTLs at any instant, whole messages aged, and nothing
+//! stored changed along the way.
+
+use std::time::{Duration, Instant};
+
+use mairudns::cache::{self, Expiring, RemainingTtl};
+use mairudns::message::Message;
+use mairudns::rr::{RData, Record, RecordType};
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+fn a(owner: &str, ttl: u32) -> Record {
+    Record::new(owner.parse().unwrap(), ttl, RData::A([192, 0, 2, 1].into()))
+}
+
+#[test]
+fn remaining_ttls_count_whole_seconds_down_to_zero() {
+    let stored = Instant::now();
+    assert_eq!(cache::remaining_ttl(300, stored, stored), 300);
+    // Part of a second held is not yet a second.
+    assert_eq!(
+        cache::remaining_ttl(300, stored, stored + Duration::from_millis(1999)),
+        299
+    );
+    assert_eq!(cache::remaining_ttl(300, stored, stored + secs(300)), 0);
+    assert_eq!(cache::remaining_ttl(300, stored, stored + secs(1000)), 0);
+    // An instant before the value was stored takes nothing off.
+    assert_eq!(cache::remaining_ttl(300, stored + secs(5), stored), 300);
+}
+
+#[test]
+fn expiring_values_know_their_age() {
+    let stored = Instant::now();
+    let value = Expiring::kept_for("v", stored, secs(10));
+    assert_eq!(value.stored(), stored);
+    assert_eq!(value.expires(), stored + secs(10));
+    assert_eq!(value.lifetime(), secs(10));
+    assert_eq!(value.age(stored + secs(4)), secs(4));
+    assert_eq!(value.remaining(stored + secs(4)), secs(6));
+    assert_eq!(value.get(stored + secs(9)), Some(&"v"));
+    assert!(value.is_expired(stored + secs(10)));
+    assert_eq!(value.get(stored + secs(10)), None);
+    assert_eq!(value.remaining(stored + secs(20)), Duration::ZERO);
+    // Still there to look at, expired or not.
+    assert_eq!(*value.value(), "v");
+    assert_eq!(value.into_value(), "v");
+}
+
+#[test]
+fn cached_records_are_handed_on_counted_down() {
+    let stored = Instant::now();
+    let cached = [
+        Expiring::for_ttl(a("short.example.", 10), stored),
+        Expiring::for_ttl(a("long.example.", 100), stored),
+    ];
+    assert_eq!(
+        cached[0].record(stored + secs(3)).unwrap().ttl,
+        7,
+        "counted from when it was stored"
+    );
+    assert_eq!(cached[0].record(stored + secs(10)), None);
+
+    // Expired records are skipped.
+    let records: Vec<Record> = cached
+        .iter()
+        .with_remaining_ttl(stored + secs(20))
+        .collect();
+    assert_eq!(records, vec![a("long.example.", 80)]);
+    assert_eq!(
+        cached.iter().with_remaining_ttl(stored).size_hint(),
+        (0, Some(2))
+    );
+    // What is stored is left as it was.
+    assert_eq!(cached[1].value().ttl, 100);
+
+    // An RRset kept for as long as its shortest TTL.
+    let rrset = Expiring::kept_for(
+        vec![a("www.example.", 10), a("www.example.", 30)],
+        stored,
+        secs(10),
+    );
+    let ttls: Vec<u32> = rrset
+        .records(stored + Duration::from_millis(3500))
+        .map(|rr| rr.ttl)
+        .collect();
+    assert_eq!(ttls, vec![7, 27]);
+    assert_eq!(rrset.records(stored + secs(10)).count(), 0);
+}
+
+#[test]
+fn messages_age_in_every_section() {
+    let mut msg = Message::query("www.example.".parse().unwrap(), RecordType::A).response();
+    msg.answers.push(a("www.example.", 300));
+    msg.authority.push(a("ns.example.", 5));
+    msg.additional.push(a("extra.example.", 60));
+    let ttls = |msg: &Message| -> Vec<u32> {
+        msg.answers
+            .iter()
+            .chain(&msg.authority)
+            .chain(&msg.additional)
+            .map(|rr| rr.ttl)
+            .collect()
+    };
+
+    let stored = Instant::now();
+    let cached = Expiring::kept_for(msg.clone(), stored, secs(5));
+    // Aged even once expired, for serving stale answers.
+    assert_eq!(ttls(&cached.aged(stored + secs(10))), vec![290, 0, 50]);
+    assert_eq!(ttls(cached.value()), vec![300, 5, 60]);
+
+    cache::age(&mut msg, 100);
+    assert_eq!(ttls(&msg), vec![200, 0, 0]);
+}
@@ -0,0 +1,486 @@
+//! The C ABI: names, addresses and messages across the boundary, the
+//! buffer protocol and status codes, resolution through the opaque
+//! handle, and `include/mairudns.h` agreeing with the Rust declarations,
+//! checked by compiling it with the system's C compiler.
+
+#![cfg(feature = "ffi")]
+
+use std::ffi::{CStr, CString};
+use std::mem::{offset_of, size_of};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::Command;
+use std::ptr;
+use std::slice;
+
+use mairudns::ffi::*;
+use mairudns::message::{Edns, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn c(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+fn text(p: *const c_char) -> String {
+    unsafe { CStr::from_ptr(p) }.to_str().unwrap().to_string()
+}
+
+/// Calls a text-writing function with a buffer of `len` bytes, returning
+/// the status, the size it said it needed and the text.
+fn call(len: usize, f: impl FnOnce(*mut c_char, usize, *mut usize) -> i32) -> (i32, usize, String) {
+    let mut buf = vec![0x55 as c_char; len];
+    let mut needed = 0;
+    let status = f(buf.as_mut_ptr(), len, &mut needed);
+    let out = match status {
+        MAIRU_OK => text(buf.as_ptr()),
+        _ => String::new(),
+    };
+    (status, needed, out)
+}
+
+#[test]
+fn status_codes_are_described() {
+    let described: Vec<_> = [0, -1, -2, -3, -4, -5, -99, 7]
+        .iter()
+        .map(|&code| text(mairu_strerror(code)))
+        .collect();
+    assert_eq!(
+        described,
+        [
+            "success",
+            "invalid argument",
+            "parse error",
+            "buffer too small",
+            "timed out",
+            "resolution failed",
+            "unknown error",
+            "unknown error"
+        ]
+    );
+}
+
+#[test]
+fn names_are_formatted_and_encoded() {
+    let input = c("WWW.Example.COM");
+    let (status, needed, out) = call(64, |o, l, n| unsafe {
+        mairu_name_format(input.as_ptr(), o, l, n)
+    });
+    assert_eq!((status, out.as_str()), (MAIRU_OK, "WWW.Example.COM."));
+    assert_eq!(needed, "WWW.Example.COM.".len() + 1);
+
+    // Exactly the size needed fits; one less does not, and says so.
+    let (status, _, out) = call(needed, |o, l, n| unsafe {
+        mairu_name_format(input.as_ptr(), o, l, n)
+    });
+    assert_eq!((status, out.as_str()), (MAIRU_OK, "WWW.Example.COM."));
+    let (status, told, _) = call(needed - 1, |o, l, n| unsafe {
+        mairu_name_format(input.as_ptr(), o, l, n)
+    });
+    assert_eq!((status, told), (MAIRU_ERR_BUFFER, needed));
+    // A null buffer asks only for the size, and `needed` may be null.
+    let mut size = 0;
+    let status = unsafe { mairu_name_format(input.as_ptr(), ptr::null_mut(), 0, &mut size) };
+    assert_eq!((status, size), (MAIRU_ERR_BUFFER, needed));
+    let mut buf = [0 as c_char; 64];
+    let status =
+        unsafe { mairu_name_format(input.as_ptr(), buf.as_mut_ptr(), 64, ptr::null_mut()) };
+    assert_eq!(status, MAIRU_OK);
+
+    // Escapes survive, in both directions.
+    let escaped = c(r"a\.b\032c.example");
+    let (status, _, out) = call(64, |o, l, n| unsafe {
+        mairu_name_format(escaped.as_ptr(), o, l, n)
+    });
+    assert_eq!((status, out.as_str()), (MAIRU_OK, r"a\.b\032c.example."));
+
+    let mut wire = [0u8; 64];
+    let mut written = 0;
+    let status = unsafe {
+        mairu_name_to_wire(
+            c("www.example").as_ptr(),
+            wire.as_mut_ptr(),
+            64,
+            &mut written,
+        )
+    };
+    assert_eq!(status, MAIRU_OK);
+    assert_eq!(&wire[..written], b"\x03www\x07example\x00");
+    let status = unsafe {
+        mairu_name_to_wire(
+            c("www.example").as_ptr(),
+            wire.as_mut_ptr(),
+            12,
+            &mut written,
+        )
+    };
+    assert_eq!((status, written), (MAIRU_ERR_BUFFER, 13));
+
+    let from = b"\x03www\x07example\x00";
+    let (status, _, out) = call(64, |o, l, n| unsafe {
+        mairu_name_from_wire(from.as_ptr(), from.len(), o, l, n)
+    });
+    assert_eq!((status, out.as_str()), (MAIRU_OK, "www.example."));
+    // Trailing bytes, a pointer with nothing to point to, a cut label.
+    for bad in [&b"\x03www\x00\x00"[..], b"\xc0\x00", b"\x05ab"] {
+        let (status, _, _) = call(64, |o, l, n| unsafe {
+            mairu_name_from_wire(bad.as_ptr(), bad.len(), o, l, n)
+        });
+        assert_eq!(status, MAIRU_ERR_PARSE, "{:02x?}", bad);
+    }
+
+    let too_long = c(&"a".repeat(64));
+    let (status, _, _) = call(128, |o, l, n| unsafe {
+        mairu_name_format(too_long.as_ptr(), o, l, n)
+    });
+    assert_eq!(status, MAIRU_ERR_PARSE);
+    let (status, _, _) = call(64, |o, l, n| unsafe {
+        mairu_name_format(ptr::null(), o, l, n)
+    });
+    assert_eq!(status, MAIRU_ERR_ARGUMENT);
+    let not_utf8 = [0xffu8 as c_char, 0];
+    let (status, _, _) = call(64, |o, l, n| unsafe {
+        mairu_name_format(not_utf8.as_ptr(), o, l, n)
+    });
+    assert_eq!(status, MAIRU_ERR_ARGUMENT);
+}
+
+#[test]
+fn addresses_are_parsed_formatted_and_reversed() {
+    let mut addr = MairuAddr::default();
+    let status = unsafe { mairu_addr_parse(c("2001:DB8:0:0:0:0:2:1").as_ptr(), &mut addr) };
+    assert_eq!(status, MAIRU_OK);
+    assert_eq!(addr.family, 6);
+    assert_eq!(
+        addr.bytes,
+        [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1]
+    );
+    let (status, _, out) = call(64, |o, l, n| unsafe { mairu_addr_format(&addr, o, l, n) });
+    assert_eq!((status, out.as_str()), (MAIRU_OK, "2001:db8::2:1"));
+    let (status, _, out) = call(128, |o, l, n| unsafe {
+        mairu_addr_reverse_name(&addr, o, l, n)
+    });
+    assert_eq!(
+        (status, out.as_str()),
+        (
+            MAIRU_OK,
+            "1.0.0.0.2.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa."
+        )
+    );
+
+    let status = unsafe { mairu_addr_parse(c("192.0.2.1").as_ptr(), &mut addr) };
+    assert_eq!(status, MAIRU_OK);
+    assert_eq!((addr.family, &addr.bytes[..4]), (4, &[192, 0, 2, 1][..]));
+    let (_, _, out) = call(64, |o, l, n| unsafe {
+        mairu_addr_reverse_name(&addr, o, l, n)
+    });
+    assert_eq!(out, "1.2.0.192.in-addr.arpa.");
+
+    for bad in ["192.0.2", "2001:db8::1::2", "192.0.2.1/24", ""] {
+        let status = unsafe { mairu_addr_parse(c(bad).as_ptr(), &mut addr) };
+        assert_eq!(status, MAIRU_ERR_PARSE, "{:?}", bad);
+    }
+    let status = unsafe { mairu_addr_parse(c("192.0.2.1").as_ptr(), ptr::null_mut()) };
+    assert_eq!(status, MAIRU_ERR_ARGUMENT);
+    let unknown = MairuAddr {
+        family: 5,
+        bytes: [0; 16],
+    };
+    let (status, _, _) = call(64, |o, l, n| unsafe {
+        mairu_addr_format(&unknown, o, l, n)
+    });
+    assert_eq!(status, MAIRU_ERR_ARGUMENT);
+}
+
+/// The records of a decoded section.
+fn section<'a>(records: *const MairuRecord, count: usize) -> &'a [MairuRecord] {
+    match count {
+        0 => &[],
+        _ => unsafe { slice::from_raw_parts(records, count) },
+    }
+}
+
+#[test]
+fn messages_decode_into_c_structs() {
+    let mut msg = Message::query(name("www.example."), RecordType::MX).response();
+    msg.header.id = 0xbeef;
+    msg.header.aa = true;
+    msg.header.rcode = Rcode::BADCOOKIE;
+    msg.edns = Some(Edns {
+        udp_size: 1232,
+        dnssec_ok: true,
+        ..Edns::default()
+    });
+    msg.answers.push(Record::new(
+        name("www.example."),
+        300,
+        RData::Mx {
+            preference: 10,
+            exchange: name("mail.example."),
+        },
+    ));
+    msg.authority.push(Record::new(
+        name("example."),
+        3600,
+        RData::Txt(vec![b"a \"quoted\" string".to_vec()]),
+    ));
+    let wire = msg.to_wire().unwrap();
+
+    let mut out = ptr::null_mut();
+    let status = unsafe { mairu_message_decode(wire.as_ptr(), wire.len(), &mut out) };
+    assert_eq!(status, MAIRU_OK);
+    let m = unsafe { &*out };
+    assert_eq!(m.id, 0xbeef);
+    // QR, AA and RD, with the low bits of RCODE 23 in the header.
+    assert_eq!(m.flags, 0x8000 | 0x0400 | 0x0100 | (23 & 0xf));
+    assert_eq!((m.opcode, m.rcode), (0, 23));
+    assert_eq!((m.has_edns, m.edns_version, m.dnssec_ok), (1, 0, 1));
+    assert_eq!(m.udp_size, 1232);
+
+    let questions = unsafe { slice::from_raw_parts(m.questions, m.question_count) };
+    assert_eq!(questions.len(), 1);
+    assert_eq!(text(questions[0].name), "www.example.");
+    assert_eq!((questions[0].qtype, questions[0].qclass), (15, 1));
+
+    let answers = section(m.answers, m.answer_count);
+    assert_eq!(answers.len(), 1);
+    let mx = &answers[0];
+    assert_eq!(text(mx.name), "www.example.");
+    assert_eq!((mx.rtype, mx.rclass, mx.ttl), (15, 1, 300));
+    assert_eq!(text(mx.rdata_text), "10 mail.example.");
+    // Uncompressed, though the exchange was compressed on the wire.
+    let rdata = unsafe { slice::from_raw_parts(mx.rdata, mx.rdata_len) };
+    assert_eq!(rdata, b"\x00\x0a\x04mail\x07example\x00");
+
+    let authority = section(m.authority, m.authority_count);
+    assert_eq!(text(authority[0].rdata_text), r#""a \"quoted\" string""#);
+    // The OPT record is in the EDNS fields, not among the records.
+    assert_eq!(m.additional_count, 0);
+    unsafe { mairu_message_free(out) };
+
+    let cut = &wire[..wire.len() - 3];
+    let mut out = ptr::null_mut();
+    let status = unsafe { mairu_message_decode(cut.as_ptr(), cut.len(), &mut out) };
+    assert_eq!((status, out), (MAIRU_ERR_PARSE, ptr::null_mut()));
+    let status = unsafe { mairu_message_decode(ptr::null(), 0, &mut out) };
+    assert_eq!(status, MAIRU_ERR_ARGUMENT);
+    // Freeing null does nothing.
+    unsafe { mairu_message_free(ptr::null_mut()) };
+}
+
+#[test]
+fn resolvers_query_through_the_handle() {
+    let server = MockServer::builder()
+        .answer(
+            name("www.example."),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example."),
+                60,
+                RData::A([192, 0, 2, 1].into()),
+            )],
+        )
+        .on(
+            name("gone.example."),
+            RecordType::A,
+            Action::Rcode(Rcode::NXDOMAIN),
+        )
+        .on(name("slow.example."), RecordType::A, Action::Drop)
+        .start()
+        .unwrap();
+    // With and without a port.
+    let with_port = c(&server.addr().to_string());
+    let servers = [with_port.as_ptr()];
+    let resolver = unsafe { mairu_resolver_new(servers.as_ptr(), 1, 200) };
+    assert!(!resolver.is_null());
+
+    let mut out = ptr::null_mut();
+    let status = unsafe { mairu_resolve(resolver, c("www.example").as_ptr(), 1, &mut out) };
+    assert_eq!(status, MAIRU_OK);
+    let m = unsafe { &*out };
+    let answers = section(m.answers, m.answer_count);
+    assert_eq!(text(answers[0].rdata_text), "192.0.2.1");
+    unsafe { mairu_message_free(out) };
+
+    // NXDOMAIN is an answer, with its RCODE.
+    let status = unsafe { mairu_resolve(resolver, c("gone.example").as_ptr(), 1, &mut out) };
+    assert_eq!(status, MAIRU_OK);
+    assert_eq!(unsafe { (*out).rcode }, 3);
+    unsafe { mairu_message_free(out) };
+
+    let status = unsafe { mairu_resolve(resolver, c("slow.example").as_ptr(), 1, &mut out) };
+    assert_eq!(status, MAIRU_ERR_TIMEOUT);
+    let status = unsafe { mairu_resolve(resolver, c("a..b").as_ptr(), 1, &mut out) };
+    assert_eq!(status, MAIRU_ERR_PARSE);
+    let status = unsafe { mairu_resolve(ptr::null(), c("www.example").as_ptr(), 1, &mut out) };
+    assert_eq!(status, MAIRU_ERR_ARGUMENT);
+    unsafe { mairu_resolver_free(resolver) };
+
+    let bare = c("127.0.0.1");
+    let resolver = unsafe { mairu_resolver_new([bare.as_ptr()].as_ptr(), 1, 0) };
+    assert!(!resolver.is_null());
+    unsafe { mairu_resolver_free(resolver) };
+    for bad in ["not an address", "192.0.2.1:99999"] {
+        let bad = c(bad);
+        let resolver = unsafe { mairu_resolver_new([bad.as_ptr()].as_ptr(), 1, 0) };
+        assert!(resolver.is_null());
+    }
+    assert!(unsafe { mairu_resolver_new(ptr::null(), 1, 0) }.is_null());
+    unsafe { mairu_resolver_free(ptr::null_mut()) };
+}
+
+/// A C program printing the header's constants and the sizes and field
+/// offsets of its structs, one `name value` per line.
+const LAYOUT: &str = r#"
+#include <stddef.h>
+#include <stdio.h>
+#include "mairudns.h"
+
+#define SIZE(t) printf(#t " %zu\n", sizeof(t))
+#define FIELD(t, f) printf(#t "." #f " %zu\n", offsetof(t, f))
+
+int main(void) {
+    printf("MAIRU_OK %d\n", MAIRU_OK);
+    printf("MAIRU_ERR_ARGUMENT %d\n", MAIRU_ERR_ARGUMENT);
+    printf("MAIRU_ERR_PARSE %d\n", MAIRU_ERR_PARSE);
+    printf("MAIRU_ERR_BUFFER %d\n", MAIRU_ERR_BUFFER);
+    printf("MAIRU_ERR_TIMEOUT %d\n", MAIRU_ERR_TIMEOUT);
+    printf("MAIRU_ERR_RESOLVE %d\n", MAIRU_ERR_RESOLVE);
+    SIZE(mairu_addr);
+    FIELD(mairu_addr, bytes);
+    SIZE(mairu_question);
+    FIELD(mairu_question, qtype);
+    FIELD(mairu_question, qclass);
+    SIZE(mairu_record);
+    FIELD(mairu_record, rtype);
+    FIELD(mairu_record, rclass);
+    FIELD(mairu_record, ttl);
+    FIELD(mairu_record, rdata);
+    FIELD(mairu_record, rdata_len);
+    FIELD(mairu_record, rdata_text);
+    SIZE(mairu_message);
+    FIELD(mairu_message, flags);
+    FIELD(mairu_message, opcode);
+    FIELD(mairu_message, rcode);
+    FIELD(mairu_message, has_edns);
+    FIELD(mairu_message, edns_version);
+    FIELD(mairu_message, dnssec_ok);
+    FIELD(mairu_message, udp_size);
+    FIELD(mairu_message, questions);
+    FIELD(mairu_message, question_count);
+    FIELD(mairu_message, answers);
+    FIELD(mairu_message, answer_count);
+    FIELD(mairu_message, authority);
+    FIELD(mairu_message, authority_count);
+    FIELD(mairu_message, additional);
+    FIELD(mairu_message, additional_count);
+    return 0;
+}
+"#;
+
+#[test]
+fn the_header_matches_the_rust_declarations() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = std::env::temp_dir().join(format!("mairu-ffi-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("layout.c");
+    let program = dir.join("layout");
+    std::fs::write(&source, LAYOUT).unwrap();
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let built = Command::new(cc)
+        .args(["-std=c99", "-Wall", "-Werror", "-o"])
+        .arg(&program)
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg(&source)
+        .output()
+        .unwrap();
+    assert!(
+        built.status.success(),
+        "{}",
+        String::from_utf8_lossy(&built.stderr)
+    );
+    let run = Command::new(&program).output().unwrap();
+    let printed = String::from_utf8(run.stdout).unwrap();
+
+    let expected = [
+        ("MAIRU_OK", MAIRU_OK as usize),
+        ("MAIRU_ERR_ARGUMENT", MAIRU_ERR_ARGUMENT as usize),
+        ("MAIRU_ERR_PARSE", MAIRU_ERR_PARSE as usize),
+        ("MAIRU_ERR_BUFFER", MAIRU_ERR_BUFFER as usize),
+        ("MAIRU_ERR_TIMEOUT", MAIRU_ERR_TIMEOUT as usize),
+        ("MAIRU_ERR_RESOLVE", MAIRU_ERR_RESOLVE as usize),
+        ("mairu_addr", size_of::<MairuAddr>()),
+        ("mairu_addr.bytes", offset_of!(MairuAddr, bytes)),
+        ("mairu_question", size_of::<MairuQuestion>()),
+        ("mairu_question.qtype", offset_of!(MairuQuestion, qtype)),
+        ("mairu_question.qclass", offset_of!(MairuQuestion, qclass)),
+        ("mairu_record", size_of::<MairuRecord>()),
+        ("mairu_record.rtype", offset_of!(MairuRecord, rtype)),
+        ("mairu_record.rclass", offset_of!(MairuRecord, rclass)),
+        ("mairu_record.ttl", offset_of!(MairuRecord, ttl)),
+        ("mairu_record.rdata", offset_of!(MairuRecord, rdata)),
+        ("mairu_record.rdata_len", offset_of!(MairuRecord, rdata_len)),
+        (
+            "mairu_record.rdata_text",
+            offset_of!(MairuRecord, rdata_text),
+        ),
+        ("mairu_message", size_of::<MairuMessage>()),
+        ("mairu_message.flags", offset_of!(MairuMessage, flags)),
+        ("mairu_message.opcode", offset_of!(MairuMessage, opcode)),
+        ("mairu_message.rcode", offset_of!(MairuMessage, rcode)),
+        ("mairu_message.has_edns", offset_of!(MairuMessage, has_edns)),
+        (
+            "mairu_message.edns_version",
+            offset_of!(MairuMessage, edns_version),
+        ),
+        (
+            "mairu_message.dnssec_ok",
+            offset_of!(MairuMessage, dnssec_ok),
+        ),
+        ("mairu_message.udp_size", offset_of!(MairuMessage, udp_size)),
+        (
+            "mairu_message.questions",
+            offset_of!(MairuMessage, questions),
+        ),
+        (
+            "mairu_message.question_count",
+            offset_of!(MairuMessage, question_count),
+        ),
+        ("mairu_message.answers", offset_of!(MairuMessage, answers)),
+        (
+            "mairu_message.answer_count",
+            offset_of!(MairuMessage, answer_count),
+        ),
+        (
+            "mairu_message.authority",
+            offset_of!(MairuMessage, authority),
+        ),
+        (
+            "mairu_message.authority_count",
+            offset_of!(MairuMessage, authority_count),
+        ),
+        (
+            "mairu_message.additional",
+            offset_of!(MairuMessage, additional),
+        ),
+        (
+            "mairu_message.additional_count",
+            offset_of!(MairuMessage, additional_count),
+        ),
+    ];
+    let expected: String = expected
+        .iter()
+        .map(|(name, value)| match name.starts_with("MAIRU_") {
+            true => format!("{} {}\n", name, *value as i32),
+            false => format!("{} {}\n", name, value),
+        })
+        .collect();
+    assert_eq!(printed, expected);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,190 @@
+//! Blocklist filtering: loading hosts files, domain lists and Response
+//! Policy Zones, how rules match, and the response for each action.
+
+use std::sync::{Arc, Mutex};
+
+use mairudns::client::Protocol;
+use mairudns::message::{ExtendedError, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::policy::{Action, Filter, MatchKind};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Handler, HandlerExt, Request};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+const HOSTS: &str = "\
+# blocked
+0.0.0.0 ads.test tracker.test localhost
+127.0.0.1 also-blocked.test
+192.168.1.5 nas.lan
+192.168.1.6 nas.lan
+::1 localhost ip6-loopback
+not-an-address skipped.test
+";
+
+const DOMAINS: &str = "\
+! adblock comment
+||bad.test^
+*.wild.test
+exact.test
+http://not-a-name
+";
+
+const RPZ: &str = "\
+$TTL 60
+@            SOA   ns hostmaster 1 3600 600 86400 60
+@            NS    ns
+nx.test      CNAME .
+*.nd.test    CNAME *.
+ok.bad.test  CNAME rpz-passthru.
+drop.test    CNAME rpz-drop.
+loc.test     A     10.0.0.1
+loc.test     A     10.0.0.2
+alias.test   CNAME elsewhere.example.
+32.1.0.0.127.rpz-ip CNAME .
+";
+
+fn filter() -> Filter {
+    let mut filter = Filter::new();
+    assert_eq!(filter.load_hosts(HOSTS, &Action::Nxdomain, "hosts"), 4);
+    assert_eq!(filter.load_domains(DOMAINS, &Action::sinkhole(), "list"), 3);
+    assert_eq!(filter.load_rpz(RPZ, &name("rpz.local"), "rpz").unwrap(), 6);
+    filter
+}
+
+#[test]
+fn hosts_files_block_or_redirect() {
+    let filter = filter();
+    assert_eq!(
+        filter.lookup(&name("ads.test")).unwrap().rule.action,
+        Action::Nxdomain
+    );
+    assert!(filter.lookup(&name("also-blocked.test")).is_some());
+    assert!(filter.lookup(&name("sub.ads.test")).is_none());
+    assert!(filter.lookup(&name("localhost")).is_none());
+    assert_eq!(
+        filter.lookup(&name("nas.lan")).unwrap().rule.action,
+        Action::Redirect(vec![
+            RData::A([192, 168, 1, 5].into()),
+            RData::A([192, 168, 1, 6].into())
+        ])
+    );
+}
+
+#[test]
+fn lists_match_exactly_by_suffix_or_by_wildcard() {
+    let filter = filter();
+    assert!(filter.lookup(&name("exact.test")).is_some());
+    assert!(filter.lookup(&name("www.exact.test")).is_none());
+    assert!(filter.lookup(&name("bad.test")).is_some());
+    let hit = filter.lookup(&name("deep.sub.bad.test")).unwrap();
+    assert_eq!(hit.kind, MatchKind::Suffix);
+    assert_eq!(hit.trigger, &name("bad.test"));
+    assert_eq!(&*hit.rule.source, "list");
+    assert!(filter.lookup(&name("wild.test")).is_none());
+    assert_eq!(
+        filter.lookup(&name("a.wild.test")).unwrap().kind,
+        MatchKind::Wildcard
+    );
+    // The closer rule wins over the one covering it.
+    assert_eq!(
+        filter.lookup(&name("ok.bad.test")).unwrap().rule.action,
+        Action::Passthru
+    );
+}
+
+#[test]
+fn response_policy_zones_map_to_actions() {
+    let filter = filter();
+    let action = |n: &str| filter.lookup(&name(n)).map(|h| h.rule.action.clone());
+    assert_eq!(action("nx.test"), Some(Action::Nxdomain));
+    assert_eq!(action("a.nd.test"), Some(Action::Nodata));
+    assert_eq!(action("drop.test"), Some(Action::Drop));
+    assert_eq!(
+        action("loc.test"),
+        Some(Action::Redirect(vec![
+            RData::A([10, 0, 0, 1].into()),
+            RData::A([10, 0, 0, 2].into())
+        ]))
+    );
+    assert_eq!(
+        action("alias.test"),
+        Some(Action::Redirect(vec![RData::Cname(name(
+            "elsewhere.example"
+        ))]))
+    );
+}
+
+fn upstream(request: &Request) -> Option<Message> {
+    let q = request.message.question()?;
+    let mut resp = request.message.response();
+    resp.answers.push(Record::new(
+        q.name.clone(),
+        300,
+        RData::A([198, 51, 100, 1].into()),
+    ));
+    Some(resp)
+}
+
+fn ask<H: Handler>(handler: &H, qname: &str, qtype: RecordType) -> Option<Message> {
+    handler.handle(&Request {
+        message: Message::query(name(qname), qtype),
+        src: "192.0.2.1:5300".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    })
+}
+
+#[test]
+fn answers_for_each_action_and_logs_hits() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut filter = filter();
+    filter.set_ttl(30);
+    let sink = log.clone();
+    filter.set_logger(move |req, hit| {
+        sink.lock().unwrap().push((
+            req.message.questions[0].name.clone(),
+            hit.rule.source.to_string(),
+        ))
+    });
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(Arc::new(filter));
+
+    let resp = ask(&handler, "ads.test", RecordType::A).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    assert_eq!(
+        resp.extended_errors().next().unwrap().code,
+        ExtendedError::BLOCKED
+    );
+    let resp = ask(&handler, "a.nd.test", RecordType::A).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+
+    let resp = ask(&handler, "x.bad.test", RecordType::AAAA).unwrap();
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("x.bad.test"),
+            30,
+            RData::Aaaa("::".parse().unwrap())
+        )]
+    );
+    assert_eq!(
+        resp.extended_errors().next().unwrap().code,
+        ExtendedError::FORGED_ANSWER
+    );
+    let resp = ask(&handler, "alias.test", RecordType::A).unwrap();
+    assert_eq!(resp.answers[0].rtype(), RecordType::CNAME);
+
+    assert!(ask(&handler, "drop.test", RecordType::A).is_none());
+    let resp = ask(&handler, "ok.bad.test", RecordType::A).unwrap();
+    assert_eq!(resp.answers[0].rdata, RData::A([198, 51, 100, 1].into()));
+    let resp = ask(&handler, "clean.example", RecordType::A).unwrap();
+    assert_eq!(resp.answers[0].ttl, 300);
+
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 6);
+    assert_eq!(log[0], (name("ads.test"), "hosts".to_string()));
+    assert_eq!(log[5], (name("ok.bad.test"), "rpz".to_string()));
+}
@@ -0,0 +1,428 @@
+//! The forwarding handler: routing by longest suffix, fallbacks and
+//! fallthrough between routes, no upstream asked twice and no query
+//! forwarded round in a loop, and the per-route response cache: which
+//! negative answers it keeps, and inspecting and flushing it by name
+//! pattern, with mock servers as upstreams.
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::cache::NamePattern;
+use mairudns::client::Protocol;
+use mairudns::clock::MockClock;
+use mairudns::message::{Message, Question, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{
+    CachePolicy, CachedResponse, Fallthrough, Forwarder, Handler, NegativeCaching, Request, Route,
+    Security, Server,
+};
+use mairudns::testing::MockServer;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn a(owner: &str, addr: [u8; 4], ttl: u32) -> Record {
+    Record::new(name(owner), ttl, RData::A(addr.into()))
+}
+
+/// An upstream answering `www.corp.example` with `addr`.
+fn upstream(addr: [u8; 4]) -> MockServer {
+    MockServer::builder()
+        .answer(
+            name("www.corp.example"),
+            RecordType::A,
+            vec![a("www.corp.example", addr, 300)],
+        )
+        .answer(
+            name("www.example"),
+            RecordType::A,
+            vec![a("www.example", addr, 300)],
+        )
+        .start()
+        .unwrap()
+}
+
+/// An upstream answering everything with `rcode`.
+fn failing(rcode: Rcode) -> MockServer {
+    MockServer::builder()
+        .handler(move |req: &Request| {
+            let mut resp = req.message.response();
+            resp.header.rcode = rcode;
+            Some(resp)
+        })
+        .start()
+        .unwrap()
+}
+
+/// An upstream denying every name, with the SOA that says for how long.
+fn denying() -> MockServer {
+    MockServer::builder()
+        .handler(|req: &Request| {
+            let mut resp = req.message.response();
+            resp.header.rcode = Rcode::NXDOMAIN;
+            resp.authority.push(Record::new(
+                name("corp.example"),
+                3600,
+                RData::Soa(Soa {
+                    mname: name("ns.corp.example"),
+                    rname: name("hostmaster.corp.example"),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 300,
+                }),
+            ));
+            Some(resp)
+        })
+        .start()
+        .unwrap()
+}
+
+fn resolver(server: &MockServer) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    })
+}
+
+fn ask(forwarder: &Forwarder, qname: &str) -> Message {
+    let request = Request {
+        message: Message::query(name(qname), RecordType::A),
+        src: "192.0.2.1:53000".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    };
+    forwarder.handle(&request).unwrap()
+}
+
+fn address(resp: &Message) -> Option<RData> {
+    resp.answers.first().map(|rr| rr.rdata.clone())
+}
+
+#[test]
+fn routes_by_the_longest_matching_suffix() {
+    let public = upstream([198, 51, 100, 1]);
+    let corp = upstream([10, 0, 0, 1]);
+    let forwarder = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver(&public)))
+        .route(Route::new(name("corp.example"), resolver(&corp)));
+    let resp = ask(&forwarder, "www.corp.example");
+    assert!(resp.header.ra);
+    assert_eq!(address(&resp), Some(RData::A([10, 0, 0, 1].into())));
+    let resp = ask(&forwarder, "www.example");
+    assert_eq!(address(&resp), Some(RData::A([198, 51, 100, 1].into())));
+    assert_eq!(corp.received().len(), 1);
+    assert_eq!(public.received().len(), 1);
+}
+
+#[test]
+fn falls_through_to_the_next_route_only_when_told_to() {
+    let public = upstream([198, 51, 100, 1]);
+    let corp = failing(Rcode::NXDOMAIN);
+    let final_route = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver(&public)))
+        .route(Route::new(name("corp.example"), resolver(&corp)));
+    assert_eq!(
+        ask(&final_route, "www.corp.example").header.rcode,
+        Rcode::NXDOMAIN
+    );
+    assert!(public.received().is_empty());
+
+    let on_failure = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver(&public)))
+        .route(
+            Route::new(name("corp.example"), resolver(&corp)).fallthrough(Fallthrough::OnFailure),
+        );
+    assert_eq!(
+        ask(&on_failure, "www.corp.example").header.rcode,
+        Rcode::NXDOMAIN
+    );
+
+    let on_nxdomain = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver(&public)))
+        .route(
+            Route::new(name("corp.example"), resolver(&corp)).fallthrough(Fallthrough::OnNxdomain),
+        );
+    let resp = ask(&on_nxdomain, "www.corp.example");
+    assert_eq!(address(&resp), Some(RData::A([198, 51, 100, 1].into())));
+}
+
+#[test]
+fn tries_fallbacks_within_a_route() {
+    let failing = failing(Rcode::SERVFAIL);
+    let backup = upstream([10, 0, 0, 2]);
+    let forwarder = Forwarder::new().route(
+        Route::new(name("corp.example"), resolver(&failing))
+            .fallback(resolver(&backup))
+            .fallback_when(Fallthrough::OnFailure),
+    );
+    let resp = ask(&forwarder, "www.corp.example");
+    assert_eq!(address(&resp), Some(RData::A([10, 0, 0, 2].into())));
+    assert_eq!(failing.received().len(), 1);
+}
+
+#[test]
+fn refuses_names_no_route_covers() {
+    let corp = upstream([10, 0, 0, 1]);
+    let forwarder = Forwarder::new().route(Route::new(name("corp.example"), resolver(&corp)));
+    assert_eq!(ask(&forwarder, "www.example").header.rcode, Rcode::REFUSED);
+    assert!(corp.received().is_empty());
+}
+
+#[test]
+fn caches_within_the_ttl_bounds_of_the_route() {
+    let server = upstream([10, 0, 0, 1]);
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    let policy = CachePolicy {
+        max_ttl: 60,
+        ..CachePolicy::default()
+    };
+    let forwarder = Forwarder::new().route(
+        Route::new(name("corp.example"), resolver(&server))
+            .cache_policy(policy)
+            .clock(clock.clone()),
+    );
+    assert_eq!(ask(&forwarder, "www.corp.example").answers[0].ttl, 300);
+    clock.advance(Duration::from_secs(20));
+    let cached = ask(&forwarder, "www.corp.example");
+    // Served from the cache, aged by the time spent there.
+    assert_eq!(cached.answers[0].ttl, 280);
+    assert_eq!(server.received().len(), 1);
+    // Gone once the route's own limit passes.
+    clock.advance(Duration::from_secs(41));
+    ask(&forwarder, "www.corp.example");
+    assert_eq!(server.received().len(), 2);
+
+    forwarder.flush_name(&name("www.corp.example"));
+    ask(&forwarder, "www.corp.example");
+    assert_eq!(server.received().len(), 3);
+
+    let uncached = Forwarder::new().route(
+        Route::new(name("corp.example"), resolver(&server)).cache_policy(CachePolicy::disabled()),
+    );
+    ask(&uncached, "www.corp.example");
+    ask(&uncached, "www.corp.example");
+    assert_eq!(server.received().len(), 5);
+}
+
+#[test]
+fn fallbacks_follow_nxdomain_only_when_told_to() {
+    let internal = failing(Rcode::NXDOMAIN);
+    let public = upstream([198, 51, 100, 1]);
+    let split = Forwarder::new().route(
+        Route::new(name("corp.example"), resolver(&internal))
+            .fallback(resolver(&public))
+            .fallback_when(Fallthrough::OnNxdomain),
+    );
+    let resp = ask(&split, "www.corp.example");
+    assert_eq!(address(&resp), Some(RData::A([198, 51, 100, 1].into())));
+    // The final answer is the one cached.
+    ask(&split, "www.corp.example");
+    assert_eq!(internal.received().len(), 1);
+    assert_eq!(public.received().len(), 1);
+
+    // By default only failures move on, and an NXDOMAIN is final.
+    let on_failure = Forwarder::new()
+        .route(Route::new(name("corp.example"), resolver(&internal)).fallback(resolver(&public)));
+    assert_eq!(
+        ask(&on_failure, "www.corp.example").header.rcode,
+        Rcode::NXDOMAIN
+    );
+    assert_eq!(public.received().len(), 1);
+    assert_eq!(on_failure.routes()[0].fallbacks().len(), 1);
+    assert_eq!(on_failure.routes()[0].upstreams().count(), 2);
+
+    // The last answer stands when every fallback fails too.
+    let refused = failing(Rcode::REFUSED);
+    let all_failing = Forwarder::new().route(
+        Route::new(name("corp.example"), resolver(&internal))
+            .fallback(resolver(&refused))
+            .fallback_when(Fallthrough::OnNxdomain),
+    );
+    assert_eq!(
+        ask(&all_failing, "www.corp.example").header.rcode,
+        Rcode::REFUSED
+    );
+}
+
+#[test]
+fn no_upstream_is_asked_twice_for_one_query() {
+    let internal = failing(Rcode::NXDOMAIN);
+    let forwarder = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver(&internal)))
+        .route(
+            Route::new(name("corp.example"), resolver(&internal))
+                .fallback(resolver(&internal))
+                .fallback_when(Fallthrough::OnNxdomain)
+                .fallthrough(Fallthrough::OnNxdomain)
+                .cache_policy(CachePolicy::disabled()),
+        );
+    assert_eq!(
+        ask(&forwarder, "www.corp.example").header.rcode,
+        Rcode::NXDOMAIN
+    );
+    assert_eq!(internal.received().len(), 1);
+}
+
+#[test]
+fn negative_answers_are_cached_as_the_route_says() {
+    let public = upstream([198, 51, 100, 1]);
+    for (caching, fallthrough, asked) in [
+        (NegativeCaching::Always, Fallthrough::OnNxdomain, 1),
+        (NegativeCaching::FinalOnly, Fallthrough::OnNxdomain, 2),
+        (NegativeCaching::FinalOnly, Fallthrough::Never, 1),
+        (NegativeCaching::Never, Fallthrough::Never, 2),
+    ] {
+        let internal = denying();
+        let forwarder = Forwarder::new()
+            .route(
+                Route::new(DomainName::root(), resolver(&public))
+                    .cache_policy(CachePolicy::disabled()),
+            )
+            .route(
+                Route::new(name("corp.example"), resolver(&internal))
+                    .negative_caching(caching)
+                    .fallthrough(fallthrough),
+            );
+        ask(&forwarder, "gone.corp.example");
+        ask(&forwarder, "gone.corp.example");
+        assert_eq!(
+            internal.received().len(),
+            asked,
+            "{:?} {:?}",
+            caching,
+            fallthrough
+        );
+    }
+}
+
+#[test]
+fn forwarding_loops_are_cut_short() {
+    // Two forwarders, each the other's upstream.
+    let free = || -> SocketAddr {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    };
+    let (a, b) = (free(), free());
+    let upstream = |addr| {
+        Resolver::new(ResolverConfig {
+            servers: vec![addr],
+            timeout: Duration::from_secs(2),
+            attempts: 1,
+            ..ResolverConfig::default()
+        })
+    };
+    let mut controls = Vec::new();
+    let mut threads = Vec::new();
+    for (listen, to) in [(a, b), (b, a)] {
+        let forwarder = Forwarder::new().route(
+            Route::new(DomainName::root(), upstream(to)).cache_policy(CachePolicy::disabled()),
+        );
+        let mut server = Server::new(forwarder);
+        server.listen_udp(listen).unwrap();
+        controls.push(server.control());
+        threads.push(thread::spawn(move || server.run()));
+    }
+
+    // The forwarder that sees the query come back answers SERVFAIL, and
+    // that is passed back along.
+    let started = Instant::now();
+    let resp = upstream(a)
+        .query(&name("loop.example"), RecordType::A)
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+    // Answered at once, not when the upstreams time out.
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    for control in controls {
+        control.shutdown();
+    }
+    for thread in threads {
+        thread.join().unwrap().unwrap();
+    }
+}
+
+#[test]
+fn name_patterns_pick_a_name_or_a_subtree() {
+    let exact: NamePattern = "www.example".parse().unwrap();
+    assert_eq!(exact, NamePattern::exact(name("www.example")));
+    assert!(exact.matches(&name("WWW.example")));
+    assert!(!exact.matches(&name("a.www.example")));
+    let subtree: NamePattern = "*.example".parse().unwrap();
+    assert_eq!(subtree, NamePattern::subtree(name("example")));
+    assert!(subtree.matches(&name("example")) && subtree.matches(&name("a.b.example")));
+    assert!(!subtree.matches(&name("example.net")));
+    let any: NamePattern = "*".parse().unwrap();
+    assert_eq!(any, NamePattern::any());
+    assert!(any.matches(&DomainName::root()));
+
+    for text in ["www.example.", "*.example.", "*"] {
+        assert_eq!(text.parse::<NamePattern>().unwrap().to_string(), text);
+    }
+    assert!("*.bad..name".parse::<NamePattern>().is_err());
+}
+
+#[test]
+fn inspects_and_flushes_cached_names() {
+    let clock = Arc::new(MockClock::new(1_700_000_000));
+    let route = |suffix: &str| {
+        let server = upstream([10, 0, 0, 1]);
+        Route::new(name(suffix), resolver(&server)).clock(clock.clone())
+    };
+    let cached = |qname: &str, qtype: RecordType, ttl: u64| {
+        let question = Question::new(name(qname), qtype);
+        let mut response = Message::query(name(qname), qtype).response();
+        response.answers.push(a(qname, [192, 0, 2, 1], ttl as u32));
+        CachedResponse {
+            question,
+            dnssec_ok: false,
+            checking_disabled: false,
+            response,
+            security: Security::Unchecked,
+            expires_in: Duration::from_secs(ttl),
+        }
+    };
+    let (corp, public) = (route("corp.example"), route("."));
+    assert!(corp.restore(cached("www.corp.example", RecordType::A, 300)));
+    assert!(corp.restore(cached("www.corp.example", RecordType::AAAA, 300)));
+    assert!(corp.restore(cached("db.corp.example", RecordType::A, 60)));
+    assert!(public.restore(cached("www.example", RecordType::A, 300)));
+    let forwarder = Forwarder::new().route(public).route(corp);
+
+    let all = forwarder.inspect_cache(&NamePattern::any());
+    assert_eq!(all.len(), 4);
+    let suffixes: Vec<String> = all.iter().map(|(s, _)| s.to_string()).collect();
+    assert_eq!(suffixes.iter().filter(|s| *s == "corp.example.").count(), 3);
+    let corp_names = forwarder.inspect_cache(&"*.corp.example".parse().unwrap());
+    assert_eq!(corp_names.len(), 3);
+    clock.advance(Duration::from_secs(20));
+    let db = forwarder.inspect_cache(&"db.corp.example".parse().unwrap());
+    assert_eq!(db.len(), 1);
+    assert_eq!(db[0].1.expires_in, Duration::from_secs(40));
+    assert_eq!(db[0].1.response.answers[0].ttl, 40);
+    // Expired responses are not shown, though they are still dropped.
+    clock.advance(Duration::from_secs(40));
+    assert!(forwarder
+        .inspect_cache(&"db.corp.example".parse().unwrap())
+        .is_empty());
+
+    let www = "www.corp.example".parse().unwrap();
+    assert_eq!(forwarder.flush_matching(&www, Some(RecordType::AAAA)), 1);
+    assert_eq!(forwarder.inspect_cache(&www).len(), 1);
+    assert_eq!(
+        forwarder.flush_matching(&"*.example".parse().unwrap(), None),
+        3
+    );
+    assert!(forwarder.inspect_cache(&NamePattern::any()).is_empty());
+    assert_eq!(forwarder.flush_matching(&NamePattern::any(), None), 0);
+}
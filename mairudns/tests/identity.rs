@@ -0,0 +1,186 @@
+//! Identity answers: CHAOS TXT queries for the server's version, host
+//! name and ID, refusal of hidden values, and the NSID option.
+
+use mairudns::client::Protocol;
+use mairudns::message::{EdnsOption, Message, OptionCode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, RecordClass, RecordType};
+use mairudns::server::{Handler, HandlerExt, Request, ServerIdentity};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(message: Message) -> Request {
+    Request {
+        message,
+        src: "192.0.2.1:5353".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+fn chaos(owner: &str, qtype: RecordType) -> Message {
+    let mut query = Message::query(name(owner), qtype);
+    query.questions[0].qclass = RecordClass::CH;
+    query
+}
+
+fn asking_nsid(mut query: Message) -> Message {
+    query.edns.as_mut().unwrap().options.push(EdnsOption {
+        code: OptionCode::NSID,
+        data: Vec::new(),
+    });
+    query
+}
+
+fn upstream(request: &Request) -> Option<Message> {
+    let mut resp = request.message.response();
+    resp.header.rcode = Rcode::NXDOMAIN;
+    Some(resp)
+}
+
+fn identity() -> ServerIdentity {
+    ServerIdentity {
+        version: Some("test 1.0".to_string()),
+        hostname: Some("ns1.example".to_string()),
+        id: None,
+        nsid: Some(b"ns1".to_vec()),
+    }
+}
+
+fn txt(resp: &Message) -> Vec<Vec<u8>> {
+    match &resp.answers[0].rdata {
+        RData::Txt(strings) => strings.clone(),
+        other => panic!("not TXT: {:?}", other),
+    }
+}
+
+#[test]
+fn answers_chaos_names_case_insensitively() {
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(identity());
+    for owner in &["version.bind", "VERSION.BIND", "version.server"] {
+        let resp = handler
+            .handle(&request(chaos(owner, RecordType::TXT)))
+            .unwrap();
+        assert_eq!(resp.header.rcode, Rcode::NOERROR);
+        assert!(resp.header.aa);
+        assert_eq!(resp.answers[0].class, RecordClass::CH);
+        assert_eq!(resp.answers[0].ttl, 0);
+        assert_eq!(txt(&resp), vec![b"test 1.0".to_vec()]);
+    }
+    let resp = handler
+        .handle(&request(chaos("hostname.bind", RecordType::ANY)))
+        .unwrap();
+    assert_eq!(txt(&resp), vec![b"ns1.example".to_vec()]);
+    // Other types get no data.
+    let resp = handler
+        .handle(&request(chaos("hostname.bind", RecordType::A)))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+}
+
+#[test]
+fn refuses_hidden_values() {
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(identity());
+    let resp = handler
+        .handle(&request(chaos("id.server", RecordType::TXT)))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::REFUSED);
+    assert!(resp.answers.is_empty());
+
+    let hidden = (upstream as fn(&Request) -> Option<Message>).with(ServerIdentity::hidden());
+    for owner in &["version.bind", "hostname.bind", "id.server"] {
+        let resp = hidden
+            .handle(&request(asking_nsid(chaos(owner, RecordType::TXT))))
+            .unwrap();
+        assert_eq!(resp.header.rcode, Rcode::REFUSED);
+        assert!(resp.edns.unwrap().option(OptionCode::NSID).is_none());
+    }
+}
+
+#[test]
+fn passes_other_queries_on() {
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(identity());
+    // Other CHAOS names, the same names in class IN, and two questions.
+    let resp = handler
+        .handle(&request(chaos("authors.bind", RecordType::TXT)))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    let resp = handler
+        .handle(&request(Message::query(
+            name("version.bind"),
+            RecordType::TXT,
+        )))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    let mut two = chaos("version.bind", RecordType::TXT);
+    two.questions.push(two.questions[0].clone());
+    assert_eq!(
+        handler.handle(&request(two)).unwrap().header.rcode,
+        Rcode::NXDOMAIN
+    );
+}
+
+#[test]
+fn splits_long_values_into_txt_strings() {
+    let config = ServerIdentity {
+        version: Some("v".repeat(300)),
+        ..identity()
+    };
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(config);
+    let resp = handler
+        .handle(&request(chaos("version.bind", RecordType::TXT)))
+        .unwrap();
+    let strings = txt(&resp);
+    assert_eq!(
+        strings.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![255, 45]
+    );
+}
+
+#[test]
+fn adds_nsid_only_when_asked() {
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(identity());
+    let query = Message::query(name("www.example"), RecordType::A);
+    let resp = handler
+        .handle(&request(asking_nsid(query.clone())))
+        .unwrap();
+    let option = resp.edns.unwrap();
+    assert_eq!(
+        option.option(OptionCode::NSID).unwrap().data,
+        b"ns1".to_vec()
+    );
+
+    let resp = handler.handle(&request(query.clone())).unwrap();
+    assert!(resp.edns.unwrap().option(OptionCode::NSID).is_none());
+
+    // An NSID option with data is not a request for one.
+    let mut odd = query.clone();
+    odd.edns.as_mut().unwrap().options.push(EdnsOption {
+        code: OptionCode::NSID,
+        data: b"x".to_vec(),
+    });
+    let resp = handler.handle(&request(odd)).unwrap();
+    assert!(resp.edns.unwrap().option(OptionCode::NSID).is_none());
+
+    // Without EDNS in the query there is nowhere to put it.
+    let mut plain = asking_nsid(query);
+    plain.edns = None;
+    assert!(handler.handle(&request(plain)).unwrap().edns.is_none());
+}
+
+#[test]
+fn defaults_to_the_crate_version() {
+    let config = ServerIdentity::new();
+    assert_eq!(
+        config.lookup(&name("version.bind")),
+        Some(Some(concat!("mairudns ", env!("CARGO_PKG_VERSION"))))
+    );
+    assert_eq!(config.lookup(&name("www.example")), None);
+    assert_eq!(
+        ServerIdentity::hidden().lookup(&name("id.server")),
+        Some(None)
+    );
+}
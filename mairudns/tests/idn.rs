@@ -0,0 +1,91 @@
+//! Internationalized names for display: Punycode decoding, A-labels
+//! shown as U-labels unless they would mislead, and how risky a name is
+//! to show, by mixed scripts and lookalike characters.
+
+use mairudns::idn::{punycode_decode, skeleton, to_unicode, to_unicode_checked, DisplayRisk};
+use mairudns::name::DomainName;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn risk(s: &str) -> DisplayRisk {
+    to_unicode_checked(&name(s)).risk
+}
+
+#[test]
+fn punycode_is_decoded() {
+    // RFC 3492 §7.1 (A) and (B), and a basic code point part.
+    assert_eq!(
+        punycode_decode("egbpdaj6bu4bxfgehfvwxn").as_deref(),
+        Some("ليهمابتكلموشعربي؟")
+    );
+    assert_eq!(
+        punycode_decode("ihqwcrb4cv8a8dqg056pqjye").as_deref(),
+        Some("他们为什么不说中文")
+    );
+    assert_eq!(punycode_decode("bcher-kva").as_deref(), Some("bücher"));
+    assert_eq!(punycode_decode("tda").as_deref(), Some("ü"));
+    // Basic code points keep their case; the digits have none.
+    assert_eq!(punycode_decode("MNCHEN-3YA").as_deref(), Some("MüNCHEN"));
+    assert_eq!(punycode_decode("").as_deref(), Some(""));
+
+    // Not ASCII, a digit out of range, cut short, or too large.
+    assert_eq!(punycode_decode("bücher-kva"), None);
+    assert_eq!(punycode_decode("bcher-kv!"), None);
+    assert_eq!(punycode_decode("bcher-k"), None);
+    assert_eq!(punycode_decode("99999999999"), None);
+}
+
+#[test]
+fn a_labels_are_shown_as_u_labels() {
+    assert_eq!(
+        to_unicode(&name("xn--bcher-kva.example.")),
+        "bücher.example."
+    );
+    assert_eq!(to_unicode(&name("XN--mnchen-3YA.de.")), "münchen.de.");
+    assert_eq!(to_unicode(&name("xn--e1afmkfd.xn--p1ai.")), "пример.рф.");
+    assert_eq!(to_unicode(&name("Example.COM.")), "Example.COM.");
+    assert_eq!(to_unicode(&DomainName::root()), ".");
+
+    // Labels that do not decode, decode to ASCII or to something that
+    // shows as nothing are left as they are.
+    assert_eq!(to_unicode(&name("xn--zz!.com.")), "xn--zz!.com.");
+    assert_eq!(to_unicode(&name("xn--abc-.com.")), "xn--abc-.com.");
+    assert_eq!(to_unicode(&name("xn--ab-g1t.com.")), "xn--ab-g1t.com.");
+    assert_eq!(to_unicode(&name("a\\.b.com.")), "a\\.b.com.");
+
+    let shown = to_unicode_checked(&name("xn--bcher-kva.example."));
+    assert_eq!(shown.to_string(), "bücher.example.");
+    assert_eq!(shown.risk, DisplayRisk::Safe);
+}
+
+#[test]
+fn risky_names_are_rated() {
+    // One script, or Latin with those of Chinese, Japanese or Korean.
+    assert_eq!(risk("xn--e1afmkfd.xn--p1ai."), DisplayRisk::Safe);
+    assert_eq!(risk("xn--abc-x68do18h.example."), DisplayRisk::Safe);
+    assert_eq!(risk("xn--nckya0bk5909dcvb2w6i.jp."), DisplayRisk::Safe);
+    assert_eq!(risk("xn--hello-e30tq28s.kr."), DisplayRisk::Safe);
+
+    // Latin with Cyrillic, or Cyrillic with Greek.
+    assert_eq!(risk("xn--a-ltb.example."), DisplayRisk::MixedScript);
+    assert_eq!(risk("xn--wxa9v.example."), DisplayRisk::MixedScript);
+
+    // Lookalikes of ASCII labels, in one script or several; the riskiest
+    // label rates the name.
+    assert_eq!(risk("xn--pple-43d.com."), DisplayRisk::Confusable);
+    assert_eq!(risk("xn--l-7sba6dbr.com."), DisplayRisk::Confusable);
+    assert_eq!(risk("xn--mi7chab1aes7c.com."), DisplayRisk::Confusable);
+    assert_eq!(risk("xn--a-ltb.xn--pple-43d.com."), DisplayRisk::Confusable);
+
+    assert_eq!(skeleton("раураl"), "paypal");
+    assert_eq!(skeleton("ＥＸＡＭＰＬＥ０１"), "example01");
+    assert_eq!(skeleton("Bücher"), "bücher");
+
+    assert!(DisplayRisk::Safe < DisplayRisk::MixedScript);
+    assert!(DisplayRisk::MixedScript < DisplayRisk::Confusable);
+    assert_eq!(DisplayRisk::default(), DisplayRisk::Safe);
+    assert_eq!(DisplayRisk::MixedScript.to_string(), "mixed-script");
+    assert_eq!(DisplayRisk::Confusable.to_string(), "confusable");
+}
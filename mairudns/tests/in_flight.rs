@@ -0,0 +1,201 @@
+//! The in-flight table of a resolver: queries noted while under way,
+//! servers at their cap of pending queries passed over and callers told
+//! once all are, retries held to a shared budget, and the route
+//! configuration that sets the cap.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::config::{Config, RouteConfig};
+use mairudns::message::Question;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Error, Resolver, ResolverConfig, RetryBudget, SendLimits};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// A server answering `www.example.` A.
+fn upstream() -> MockServer {
+    MockServer::builder()
+        .answer(
+            name("www.example."),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example."),
+                300,
+                RData::A([192, 0, 2, 1].into()),
+            )],
+        )
+        .start()
+        .unwrap()
+}
+
+fn resolver(servers: &[&MockServer], attempts: usize, limits: SendLimits) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: servers.iter().map(|s| s.addr()).collect(),
+        timeout: Duration::from_millis(200),
+        attempts,
+        limits,
+        ..ResolverConfig::default()
+    })
+}
+
+fn capped(max_pending_per_server: usize) -> SendLimits {
+    SendLimits {
+        max_pending_per_server,
+        ..SendLimits::default()
+    }
+}
+
+/// Waits until `resolver` has `n` queries under way.
+fn wait_for(resolver: &Resolver, n: usize) {
+    let start = Instant::now();
+    while resolver.in_flight().len() != n {
+        assert!(start.elapsed() < TIMEOUT);
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn servers_at_their_cap_turn_queries_away() {
+    let server = upstream();
+    server.push(Action::delay(Duration::from_millis(150), Action::Drop));
+    let resolver = resolver(&[&server], 1, capped(1));
+    assert!(!resolver.is_saturated());
+    let held = resolver.clone();
+    let query = thread::spawn(move || held.query(&name("www.example."), RecordType::A));
+    wait_for(&resolver, 1);
+
+    // The query held is listed, and the server is at its cap.
+    let outstanding = resolver.in_flight().outstanding();
+    assert_eq!(outstanding.len(), 1);
+    assert_eq!(outstanding[0].server, server.addr());
+    assert_eq!(
+        outstanding[0].question,
+        Some(Question::new(name("www.example."), RecordType::A))
+    );
+    assert!(!outstanding[0].retry);
+    assert_eq!(resolver.in_flight().pending(server.addr()), 1);
+    assert!(resolver.is_saturated());
+    let err = resolver
+        .query(&name("www.example."), RecordType::A)
+        .unwrap_err();
+    assert!(matches!(err, Error::Busy), "{:?}", err);
+    assert_eq!(
+        err.to_string(),
+        "every name server has too many queries pending"
+    );
+
+    // Once the query is done, the table is empty again.
+    assert!(query.join().unwrap().is_err());
+    assert!(resolver.in_flight().is_empty());
+    assert_eq!(resolver.in_flight().pending(server.addr()), 0);
+    assert!(!resolver.is_saturated());
+    assert!(resolver.query(&name("www.example."), RecordType::A).is_ok());
+}
+
+#[test]
+fn queries_pass_over_servers_at_their_cap() {
+    let first = upstream();
+    let second = upstream();
+    first.push(Action::delay(Duration::from_millis(300), Action::Drop));
+    let resolver = resolver(&[&first, &second], 1, capped(1));
+    let held = resolver.clone();
+    let query = thread::spawn(move || held.query(&name("www.example."), RecordType::A));
+    wait_for(&resolver, 1);
+
+    // The first server is busy, so the second answers; it is not a
+    // retry, so the budget plays no part.
+    assert!(!resolver.is_saturated());
+    let resp = resolver
+        .query(&name("www.example."), RecordType::A)
+        .unwrap();
+    assert_eq!(resp.answers.len(), 1);
+    assert_eq!(first.count(Protocol::Udp), 1);
+    assert_eq!(second.count(Protocol::Udp), 1);
+    assert_eq!(resolver.in_flight().retries_refused(), 0);
+    // The held query times out at the first server and is retried at the
+    // second.
+    assert!(query.join().unwrap().is_ok());
+    assert_eq!(second.count(Protocol::Udp), 2);
+}
+
+#[test]
+fn retries_are_held_to_the_budget() {
+    let silent = MockServer::builder().start().unwrap();
+    for _ in 0..10 {
+        silent.push(Action::Drop);
+    }
+    let limits = SendLimits {
+        retry_budget: RetryBudget {
+            ratio: 0.0,
+            min_per_sec: 0.0,
+            burst: 1.0,
+        },
+        ..SendLimits::default()
+    };
+    let budgeted = resolver(&[&silent], 3, limits);
+
+    // A first try and the one retry the budget holds; the next retry is
+    // refused, and the query fails as the last try did.
+    let err = budgeted
+        .query(&name("www.example."), RecordType::A)
+        .unwrap_err();
+    assert!(matches!(err, Error::Client(_)), "{:?}", err);
+    assert_eq!(silent.count(Protocol::Udp), 2);
+    assert_eq!(budgeted.in_flight().retries_refused(), 1);
+    // First tries are always sent; retries have to wait for the budget.
+    assert!(budgeted
+        .query(&name("www.example."), RecordType::A)
+        .is_err());
+    assert_eq!(silent.count(Protocol::Udp), 3);
+    assert_eq!(budgeted.in_flight().retries_refused(), 2);
+
+    // Clones share the table and the budget.
+    let clone = budgeted.clone();
+    assert!(clone.query(&name("www.example."), RecordType::A).is_err());
+    assert_eq!(budgeted.in_flight().retries_refused(), 3);
+
+    // Without a budget, every attempt is made.
+    let silent = MockServer::builder().start().unwrap();
+    for _ in 0..3 {
+        silent.push(Action::Drop);
+    }
+    let limits = SendLimits {
+        retry_budget: RetryBudget::unlimited(),
+        ..SendLimits::default()
+    };
+    let resolver = self::resolver(&[&silent], 3, limits);
+    assert!(resolver
+        .query(&name("www.example."), RecordType::A)
+        .is_err());
+    assert_eq!(silent.count(Protocol::Udp), 3);
+    assert_eq!(resolver.in_flight().retries_refused(), 0);
+}
+
+#[test]
+fn pending_caps_come_from_the_configuration() {
+    let route = RouteConfig {
+        max_pending: 8,
+        ..RouteConfig::new(".", vec!["192.0.2.53:53".parse().unwrap()])
+    };
+    let limits = route.to_route().unwrap().upstream().config().limits;
+    assert_eq!(limits.max_pending_per_server, 8);
+    assert_eq!(limits.retry_budget, RetryBudget::default());
+
+    let problems = Config::new()
+        .forwarder(RouteConfig {
+            max_pending: 0,
+            ..route
+        })
+        .validate()
+        .unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].field, "forwarders[0].max-pending");
+}
@@ -0,0 +1,177 @@
+//! The recursor's infrastructure cache: smoothed round-trip times and
+//! the timeouts they lead to, timeout history forgiven over time, EDNS
+//! and TCP capabilities, lameness by zone, the order servers are tried
+//! in, and what is forgotten and when.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use mairudns::name::DomainName;
+use mairudns::recursor::{Capability, InfraCache, InfraOptions, UNKNOWN_RTT};
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+fn ip(last: u8) -> IpAddr {
+    IpAddr::from([192, 0, 2, last])
+}
+
+fn zone() -> DomainName {
+    "example.".parse().unwrap()
+}
+
+#[test]
+fn round_trips_are_smoothed_into_timeouts() {
+    let cache = InfraCache::default();
+    let now = Instant::now();
+    // Never asked: twice the assumed round trip.
+    assert_eq!(cache.get(ip(1), now), None);
+    assert_eq!(cache.timeout(ip(1), now), UNKNOWN_RTT * 2);
+
+    // The first sample sets the RTT and half of it as the variation;
+    // later ones move them by an eighth and a quarter (RFC 6298).
+    cache.answered(ip(1), ms(100), now);
+    let info = cache.get(ip(1), now).unwrap();
+    assert_eq!(info.srtt, Some(ms(100)));
+    assert_eq!(info.rttvar, ms(50));
+    assert_eq!(info.timeout(), ms(300));
+    cache.answered(ip(1), ms(200), now);
+    let info = cache.get(ip(1), now).unwrap();
+    assert_eq!(info.srtt, Some(Duration::from_micros(112_500)));
+    assert_eq!(info.rttvar, Duration::from_micros(62_500));
+
+    // Each timeout in a row doubles the wait, up to twelve seconds; an
+    // answer resets it.
+    cache.timed_out(ip(1), now);
+    assert_eq!(cache.timeout(ip(1), now), ms(725));
+    cache.timed_out(ip(1), now);
+    assert_eq!(cache.timeout(ip(1), now), ms(1450));
+    for _ in 0..10 {
+        cache.timed_out(ip(1), now);
+    }
+    assert_eq!(cache.timeout(ip(1), now), secs(12));
+    cache.answered(ip(1), ms(100), now);
+    assert_eq!(cache.get(ip(1), now).unwrap().timeouts_in_row, 0);
+    assert!(cache.timeout(ip(1), now) < secs(1));
+
+    // Quick servers are still given a moment.
+    cache.answered(ip(2), Duration::from_micros(100), now);
+    assert_eq!(cache.timeout(ip(2), now), ms(50));
+}
+
+#[test]
+fn timeouts_are_forgiven_over_time() {
+    let cache = InfraCache::default();
+    let now = Instant::now();
+    cache.answered(ip(1), ms(100), now);
+    cache.timed_out(ip(1), now);
+    cache.timed_out(ip(1), now);
+    let info = cache.get(ip(1), now).unwrap();
+    assert_eq!(info.timeouts, 2.0);
+    assert_eq!(info.timeouts_in_row, 2);
+    // Three times the RTT, for the two timeouts.
+    assert_eq!(info.expected_rtt(), ms(300));
+
+    // A minute on, by half; another minute on, by half again.
+    let info = cache.get(ip(1), now + secs(60)).unwrap();
+    assert!((info.timeouts - 1.0).abs() < 1e-9);
+    let info = cache.get(ip(1), now + secs(120)).unwrap();
+    assert!((info.timeouts - 0.5).abs() < 1e-9);
+    // Only looking does not change what is stored.
+    let info = cache.get(ip(1), now).unwrap();
+    assert_eq!(info.timeouts, 2.0);
+}
+
+#[test]
+fn capabilities_and_lameness_are_kept() {
+    let options = InfraOptions {
+        lame_ttl: secs(60),
+        ..InfraOptions::default()
+    };
+    let cache = InfraCache::new(options);
+    assert_eq!(*cache.options(), options);
+    let now = Instant::now();
+    cache.edns(ip(1), false, now);
+    cache.tcp(ip(1), true, now);
+    let info = cache.get(ip(1), now).unwrap();
+    assert_eq!(info.edns, Capability::Unsupported);
+    assert_eq!(info.tcp, Capability::Supported);
+    assert_eq!(info.srtt, None);
+
+    // Lame for one zone only, and only for a while.
+    let other: DomainName = "example.net.".parse().unwrap();
+    cache.lame(ip(1), &zone(), now);
+    assert!(cache.is_lame(ip(1), &zone(), now));
+    assert!(!cache.is_lame(ip(1), &other, now));
+    assert!(!cache.is_lame(ip(2), &zone(), now));
+    assert!(!cache.is_lame(ip(1), &zone(), now + secs(60)));
+}
+
+#[test]
+fn servers_are_tried_quickest_first_and_lame_last() {
+    let cache = InfraCache::default();
+    let now = Instant::now();
+    cache.answered(ip(1), ms(20), now);
+    cache.answered(ip(2), ms(30), now);
+    cache.answered(ip(3), ms(5000), now);
+    for _ in 0..3 {
+        cache.timed_out(ip(4), now);
+    }
+    let servers = [ip(4), ip(3), ip(2), ip(1)];
+
+    // The two quick ones share the first place; the one that keeps
+    // timing out, then the slow one, come after.
+    let mut firsts = HashSet::new();
+    for _ in 0..200 {
+        let order = cache.select(&servers, &zone(), now);
+        assert_eq!(&order[2..], &[ip(4), ip(3)]);
+        firsts.insert(order[0]);
+    }
+    assert_eq!(firsts, [ip(1), ip(2)].iter().copied().collect());
+
+    // A server never asked is within the band of a quick one.
+    assert!(cache.select(&[ip(3), ip(9)], &zone(), now)[0] == ip(9));
+
+    // Lame servers go last, whatever their RTT, but only for their zone.
+    cache.lame(ip(1), &zone(), now);
+    cache.lame(ip(2), &zone(), now);
+    let order = cache.select(&servers, &zone(), now);
+    assert_eq!(&order[..2], &[ip(4), ip(3)]);
+    let other: DomainName = "example.net.".parse().unwrap();
+    let order = cache.select(&servers, &other, now);
+    assert_eq!(&order[2..], &[ip(4), ip(3)]);
+}
+
+#[test]
+fn servers_are_forgotten_when_stale_or_crowded_out() {
+    let cache = InfraCache::new(InfraOptions {
+        ttl: secs(100),
+        capacity: 2,
+        ..InfraOptions::default()
+    });
+    let now = Instant::now();
+    cache.answered(ip(1), ms(20), now);
+    cache.answered(ip(2), ms(20), now + secs(1));
+    // The least recently heard of makes room.
+    cache.answered(ip(3), ms(20), now + secs(2));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(ip(1), now + secs(2)), None);
+    assert!(cache.get(ip(2), now + secs(2)).is_some());
+
+    // Nothing learned for the TTL, and what was is gone.
+    assert_eq!(cache.get(ip(2), now + secs(101)), None);
+    cache.timed_out(ip(2), now + secs(101));
+    let info = cache.get(ip(2), now + secs(101)).unwrap();
+    assert_eq!(info.srtt, None);
+    assert_eq!(info.timeouts_in_row, 1);
+    cache.expire(now + secs(102));
+    assert_eq!(cache.len(), 1);
+    cache.clear();
+    assert!(cache.is_empty());
+}
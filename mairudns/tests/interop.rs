@@ -0,0 +1,130 @@
+//! Conversions to and from hickory-proto and `domain`, each run only with
+//! its feature enabled.
+
+#![cfg(any(feature = "hickory", feature = "domain"))]
+
+use std::convert::{TryFrom, TryInto};
+
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn records() -> Vec<Record> {
+    vec![
+        Record::new(name("host.example"), 300, RData::A([192, 0, 2, 1].into())),
+        Record::new(
+            name("example"),
+            3600,
+            RData::Mx {
+                preference: 10,
+                exchange: name("mail.example"),
+            },
+        ),
+        Record::new(
+            name("example"),
+            60,
+            RData::Txt(vec![b"v=spf1 -all".to_vec(), Vec::new()]),
+        ),
+    ]
+}
+
+fn response() -> Message {
+    let mut msg = Message::query(name("example"), RecordType::MX);
+    msg.header.id = 0x4d2;
+    msg.header.qr = true;
+    msg.header.aa = true;
+    msg.header.rcode = Rcode::NOERROR;
+    msg.answers = records();
+    msg
+}
+
+#[cfg(feature = "hickory")]
+mod with_hickory {
+    use super::*;
+    use hickory_proto::op;
+    use hickory_proto::rr::{self, Name};
+
+    #[test]
+    fn names_keep_their_labels_and_case() {
+        let ours = name("Mixed\\.Dot.Example");
+        let theirs = Name::from(&ours);
+        assert_eq!(theirs.num_labels(), 2);
+        assert_eq!(theirs.iter().next().unwrap(), b"Mixed.Dot");
+        assert!(theirs.is_fqdn());
+        assert_eq!(DomainName::try_from(&theirs).unwrap(), ours);
+        assert_eq!(
+            DomainName::try_from(Name::root()).unwrap(),
+            DomainName::root()
+        );
+    }
+
+    #[test]
+    fn relative_names_are_taken_from_the_root() {
+        let relative = Name::from_ascii("www.example").unwrap();
+        assert!(!relative.is_fqdn());
+        assert_eq!(DomainName::try_from(relative).unwrap(), name("www.example"));
+    }
+
+    #[test]
+    fn records_round_trip() {
+        for record in records() {
+            let theirs: rr::Record = (&record).try_into().unwrap();
+            assert_eq!(theirs.ttl(), record.ttl);
+            assert_eq!(u16::from(theirs.record_type()), record.rtype().0);
+            let back: Record = theirs.try_into().unwrap();
+            assert_eq!(back, record);
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let ours = response();
+        let theirs = op::Message::try_from(&ours).unwrap();
+        assert_eq!(theirs.id(), 0x4d2);
+        assert!(theirs.authoritative());
+        assert_eq!(theirs.answers().len(), 3);
+        assert_eq!(Message::try_from(theirs).unwrap(), ours);
+    }
+}
+
+#[cfg(feature = "domain")]
+mod with_domain {
+    use super::*;
+    use ::domain::base::{self, Name};
+    use mairudns::interop::domain::DomainRecord;
+
+    #[test]
+    fn names_keep_their_labels_and_case() {
+        let ours = name("Mixed\\.Dot.Example");
+        let theirs = Name::<Vec<u8>>::from(&ours);
+        assert_eq!(theirs.label_count(), 3);
+        assert_eq!(theirs.as_slice(), b"\x09Mixed.Dot\x07Example\x00");
+        assert_eq!(DomainName::try_from(&theirs).unwrap(), ours);
+        let root = Name::<Vec<u8>>::from(DomainName::root());
+        assert_eq!(DomainName::try_from(&root).unwrap(), DomainName::root());
+    }
+
+    #[test]
+    fn records_round_trip() {
+        for record in records() {
+            let theirs = DomainRecord::try_from(&record).unwrap();
+            assert_eq!(theirs.ttl().as_secs(), record.ttl);
+            assert_eq!(theirs.rtype().to_int(), record.rtype().0);
+            assert_eq!(Record::try_from(theirs).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let ours = response();
+        let theirs = base::Message::<Vec<u8>>::try_from(&ours).unwrap();
+        assert_eq!(theirs.header().id(), 0x4d2);
+        assert!(theirs.header().aa());
+        assert_eq!(theirs.header_counts().ancount(), 3);
+        assert_eq!(Message::try_from(theirs).unwrap(), ours);
+    }
+}
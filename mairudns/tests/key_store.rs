@@ -0,0 +1,314 @@
+//! The key store: the first keys of a zone, zone-signing keys rolled by
+//! pre-publication and key-signing and combined keys by double
+//! signature, keys kept in BIND-style files, encrypted under a
+//! passphrase or held in an external backend.
+
+#![cfg(feature = "dnssec")]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use mairudns::dnssec::{
+    Algorithm, KeyBackend, KeyError, KeyEvent, KeyPolicy, KeyScheme, KeyStore, Rrsig, SigningKey,
+    SigningPolicy, StoreError,
+};
+use mairudns::name::DomainName;
+use mairudns::rr::RecordType;
+use mairudns::zone::Zone;
+
+const DAY: u32 = 86400;
+const T0: u32 = 1_700_000_000;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mairu-keys-{}-{}", std::process::id(), test));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A backend that keeps keys in memory, by label.
+#[derive(Default)]
+struct Memory(Mutex<HashMap<String, SigningKey>>);
+
+impl KeyBackend for Memory {
+    fn generate(&self, label: &str, algorithm: Algorithm) -> Result<Vec<u8>, KeyError> {
+        let key = SigningKey::generate(256, algorithm)?;
+        let public = key.dnskey().public_key.clone();
+        self.0.lock().unwrap().insert(label.into(), key);
+        Ok(public)
+    }
+
+    fn public_key(&self, label: &str) -> Result<Option<Vec<u8>>, KeyError> {
+        let keys = self.0.lock().unwrap();
+        Ok(keys.get(label).map(|k| k.dnskey().public_key.clone()))
+    }
+
+    fn sign(&self, label: &str, _: Algorithm, data: &[u8]) -> Result<Vec<u8>, KeyError> {
+        let keys = self.0.lock().unwrap();
+        keys.get(label).ok_or(KeyError::Invalid)?.sign(data)
+    }
+
+    fn destroy(&self, label: &str) -> Result<(), KeyError> {
+        self.0.lock().unwrap().remove(label);
+        Ok(())
+    }
+}
+
+fn tags(keys: &[SigningKey]) -> Vec<u16> {
+    tags_of(&keys.iter().map(SigningKey::key_tag).collect::<Vec<_>>())
+}
+
+fn tags_of(tags: &[u16]) -> Vec<u16> {
+    let mut tags = tags.to_vec();
+    tags.sort_unstable();
+    tags
+}
+
+/// The tags of the keys that signed `www.example.` and of those in the
+/// key set of `signed`.
+fn signers_and_keys(signed: &Zone) -> (Vec<u16>, usize) {
+    let signers = signed
+        .rrset(&name("www.example."), RecordType::RRSIG)
+        .unwrap()
+        .iter()
+        .filter_map(|rr| Rrsig::from_rdata(&rr.rdata))
+        .filter(|sig| sig.type_covered == RecordType::A)
+        .map(|sig| sig.key_tag)
+        .collect();
+    let keys = signed
+        .rrset(&name("example."), RecordType::DNSKEY)
+        .unwrap()
+        .len();
+    (signers, keys)
+}
+
+#[test]
+fn zone_signing_keys_are_published_before_they_sign() {
+    let dir = scratch("zsk");
+    let policy = KeyPolicy::default();
+    let mut store = KeyStore::new(name("Example."), &dir);
+    let events = store.maintain(&policy, T0).unwrap();
+    let (ksk, zsk) = match events[..] {
+        [KeyEvent::Generated(ksk, T0), KeyEvent::Generated(zsk, T0)] => (ksk, zsk),
+        _ => panic!("{:?}", events),
+    };
+    assert_eq!(tags(&store.signing_keys(T0)), tags_of(&[ksk, zsk]));
+    assert!(store.maintain(&policy, T0 + DAY).unwrap().is_empty());
+
+    // A day before the zone-signing key's 90 days are up, its successor
+    // is published, to sign once they are.
+    let events = store.maintain(&policy, T0 + 89 * DAY).unwrap();
+    let next = match events[..] {
+        [KeyEvent::Generated(next, start), KeyEvent::Retired(old, end)] => {
+            assert_eq!((old, start, end), (zsk, T0 + 90 * DAY, T0 + 90 * DAY));
+            next
+        }
+        _ => panic!("{:?}", events),
+    };
+    let zone = Zone::from_master(name("example."), ZONE).unwrap();
+    let sign = |store: &KeyStore, now: u32| {
+        let policy = SigningPolicy {
+            now: Some(now),
+            ..SigningPolicy::default()
+        };
+        signers_and_keys(&store.sign(&zone, &policy).unwrap())
+    };
+    assert_eq!(sign(&store, T0 + 89 * DAY + 10), (vec![zsk], 3));
+    // The old key stays published until its signatures expire.
+    assert_eq!(sign(&store, T0 + 90 * DAY + 10), (vec![next], 3));
+    assert!(store.maintain(&policy, T0 + 90 * DAY).unwrap().is_empty());
+    assert_eq!(
+        store.maintain(&policy, T0 + 92 * DAY).unwrap(),
+        [KeyEvent::Removed(zsk)]
+    );
+    assert_eq!(sign(&store, T0 + 92 * DAY), (vec![next], 2));
+    assert_eq!(
+        tags(&store.signing_keys(T0 + 92 * DAY)),
+        tags_of(&[ksk, next])
+    );
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn key_signing_keys_sign_together_until_the_ds_changes() {
+    let dir = scratch("ksk");
+    let policy = KeyPolicy {
+        zsk_lifetime: None,
+        ..KeyPolicy::default()
+    };
+    let mut store = KeyStore::new(name("example."), &dir);
+    store.maintain(&policy, T0).unwrap();
+    let ksk = store
+        .keys()
+        .iter()
+        .find(|k| k.key().is_ksk())
+        .unwrap()
+        .key()
+        .key_tag();
+    assert!(store.maintain(&policy, T0 + 364 * DAY).unwrap().is_empty());
+    let events = store.maintain(&policy, T0 + 365 * DAY).unwrap();
+    let next = match events[..] {
+        [KeyEvent::Generated(next, start), KeyEvent::Retired(old, end)] => {
+            assert_eq!((old, start, end), (ksk, T0 + 365 * DAY, T0 + 372 * DAY));
+            next
+        }
+        _ => panic!("{:?}", events),
+    };
+    assert_eq!(store.signing_keys(T0 + 366 * DAY).len(), 3);
+    assert_eq!(
+        store.maintain(&policy, T0 + 372 * DAY).unwrap(),
+        [KeyEvent::Removed(ksk)]
+    );
+    let left: Vec<u16> = store.keys().iter().map(|k| k.key().key_tag()).collect();
+    assert!(left.contains(&next) && !left.contains(&ksk));
+
+    // Waiting for the parent keeps the old key signing.
+    let policy = KeyPolicy {
+        wait_for_ds: true,
+        ..policy
+    };
+    let events = store.maintain(&policy, T0 + 730 * DAY).unwrap();
+    assert!(
+        matches!(events[..], [KeyEvent::Generated(_, _)]),
+        "{:?}",
+        events
+    );
+    assert_eq!(store.signing_keys(T0 + 800 * DAY).len(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn private_keys_are_encrypted_under_the_passphrase() {
+    let dir = scratch("passphrase");
+    let policy = KeyPolicy::default();
+    let mut store = KeyStore::new(name("example."), &dir).passphrase("secret");
+    store.maintain(&policy, T0).unwrap();
+    store.maintain(&policy, T0 + 89 * DAY).unwrap();
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let file = path.file_name().unwrap().to_str().unwrap().to_owned();
+        assert!(file.starts_with("Kexample.+013+"), "{}", file);
+        if file.ends_with(".private") {
+            let text = fs::read_to_string(&path).unwrap();
+            assert!(text.contains("Encryption: PBKDF2-SHA256 ChaCha20-Poly1305\n"));
+            assert!(!text.contains("PrivateKey:"), "{}", text);
+            assert!(text.contains("Activate: "));
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(&path).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+            }
+        }
+    }
+
+    // The keys and their timing come back with the passphrase only.
+    let mut again = KeyStore::new(name("example."), &dir).passphrase("secret");
+    again.load().unwrap();
+    assert_eq!(again.keys().len(), 3);
+    for (read, written) in again.keys().iter().zip(store.keys()) {
+        assert_eq!(read.key().dnskey(), written.key().dnskey());
+        assert_eq!(read.timing(), written.timing());
+    }
+    let mut wrong = KeyStore::new(name("example."), &dir).passphrase("guess");
+    assert!(matches!(wrong.load(), Err(StoreError::Locked(_))));
+    let mut none = KeyStore::new(name("example."), &dir);
+    assert!(matches!(none.load(), Err(StoreError::Locked(_))));
+    // Another zone's store sees none of them.
+    let mut other = KeyStore::new(name("other.example."), &dir);
+    other.load().unwrap();
+    assert!(other.keys().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_backend_holds_combined_keys() {
+    let dir = scratch("backend");
+    let memory = Arc::new(Memory::default());
+    let policy = KeyPolicy {
+        algorithm: Algorithm::ED25519,
+        scheme: KeyScheme::Combined,
+        ..KeyPolicy::default()
+    };
+    let mut store = KeyStore::new(name("example."), &dir).backend(memory.clone());
+    assert!(matches!(
+        store.maintain(&policy, T0).unwrap()[..],
+        [KeyEvent::Generated(_, T0)]
+    ));
+    let csk = store.keys()[0].key().key_tag();
+    assert!(store.keys()[0].key().is_ksk());
+    let private = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().unwrap() == "private")
+        .unwrap();
+    assert!(fs::read_to_string(private).unwrap().contains("Label: "));
+
+    // A combined key rolls like a key-signing key, and stays published
+    // while the signatures it made elsewhere expire.
+    let events = store.maintain(&policy, T0 + 365 * DAY).unwrap();
+    assert!(
+        matches!(events[..], [KeyEvent::Generated(_, _), KeyEvent::Retired(old, _)] if old == csk)
+    );
+    assert_eq!(memory.0.lock().unwrap().len(), 2);
+    assert!(store.maintain(&policy, T0 + 372 * DAY).unwrap().is_empty());
+    assert_eq!(store.published_keys(T0 + 372 * DAY).len(), 1);
+    assert_eq!(
+        store.maintain(&policy, T0 + 374 * DAY).unwrap(),
+        [KeyEvent::Removed(csk)]
+    );
+    assert_eq!(memory.0.lock().unwrap().len(), 1);
+
+    let mut without = KeyStore::new(name("example."), &dir);
+    assert!(matches!(without.load(), Err(StoreError::NoBackend(_))));
+    let mut again = KeyStore::new(name("example."), &dir).backend(memory);
+    again.load().unwrap();
+    let zone = Zone::from_master(name("example."), ZONE).unwrap();
+    let signed = again
+        .sign(
+            &zone,
+            &SigningPolicy {
+                now: Some(T0 + 380 * DAY),
+                ..SigningPolicy::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        signers_and_keys(&signed),
+        (vec![again.keys()[0].key().key_tag()], 1)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keys_round_trip_through_bind_files() {
+    let rsa = SigningKey::generate_rsa(257, Algorithm::RSASHA256, 1024).unwrap();
+    let again = SigningKey::from_bind(257, &rsa.to_bind()).unwrap();
+    assert_eq!(again.dnskey(), rsa.dnskey());
+    assert_eq!(again.sign(b"data").unwrap(), rsa.sign(b"data").unwrap());
+    for (algorithm, size) in [
+        (Algorithm::ECDSAP256SHA256, 64),
+        (Algorithm::ECDSAP384SHA384, 96),
+        (Algorithm::ED25519, 64),
+    ] {
+        let key = SigningKey::generate(256, algorithm).unwrap();
+        assert_eq!(key.sign(b"data").unwrap().len(), size);
+        let again = SigningKey::from_bind(256, &key.to_bind()).unwrap();
+        assert_eq!(again.dnskey(), key.dnskey());
+    }
+}
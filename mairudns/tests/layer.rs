@@ -0,0 +1,108 @@
+//! Composing middleware around a handler: the order layers see requests
+//! in, short-circuiting, and rewriting requests and responses.
+
+use std::sync::{Arc, Mutex};
+
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::RecordType;
+use mairudns::server::{from_fn, Builder, Handler, HandlerExt, Identity, Request};
+
+fn request(name: &str) -> Request {
+    Request {
+        message: Message::query(name.parse::<DomainName>().unwrap(), RecordType::A),
+        src: "192.0.2.1:5353".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+fn echo(request: &Request) -> Option<Message> {
+    Some(request.message.response())
+}
+
+/// A layer noting `tag` before and after the handler it wraps.
+fn tracing(
+    log: &Arc<Mutex<Vec<String>>>,
+    tag: &'static str,
+) -> impl Fn(&Request, &dyn Handler) -> Option<Message> + Send + Sync + 'static {
+    let log = log.clone();
+    move |req, next| {
+        log.lock().unwrap().push(format!("{} in", tag));
+        let resp = next.handle(req);
+        log.lock().unwrap().push(format!("{} out", tag));
+        resp
+    }
+}
+
+#[test]
+fn layers_added_first_see_requests_first() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let handler = Builder::new()
+        .layer(from_fn(tracing(&log, "outer")))
+        .layer(from_fn(tracing(&log, "inner")))
+        .handler(echo);
+    assert!(handler.handle(&request("a.example")).is_some());
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec!["outer in", "inner in", "inner out", "outer out"]
+    );
+}
+
+#[test]
+fn layers_can_answer_without_the_inner_handler() {
+    let called = Arc::new(Mutex::new(0));
+    let counted = called.clone();
+    let inner = move |req: &Request| {
+        *counted.lock().unwrap() += 1;
+        echo(req)
+    };
+    let handler = inner.with(from_fn(|req: &Request, next: &dyn Handler| {
+        if req.message.questions[0].name == "blocked.example".parse().unwrap() {
+            let mut resp = req.message.response();
+            resp.header.rcode = Rcode::REFUSED;
+            return Some(resp);
+        }
+        next.handle(req)
+    }));
+    let resp = handler.handle(&request("blocked.example")).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::REFUSED);
+    assert_eq!(*called.lock().unwrap(), 0);
+    let resp = handler.handle(&request("open.example")).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert_eq!(*called.lock().unwrap(), 1);
+}
+
+#[test]
+fn layers_can_rewrite_requests_and_responses() {
+    let handler = Builder::new()
+        .layer(from_fn(|req: &Request, next: &dyn Handler| {
+            let mut resp = next.handle(req)?;
+            resp.header.aa = true;
+            Some(resp)
+        }))
+        .layer(from_fn(|req: &Request, next: &dyn Handler| {
+            let mut req = req.clone();
+            req.message.questions[0].qtype = RecordType::AAAA;
+            next.handle(&req)
+        }))
+        .handler(echo);
+    let resp = handler.handle(&request("a.example")).unwrap();
+    assert!(resp.header.aa);
+    assert_eq!(resp.questions[0].qtype, RecordType::AAAA);
+}
+
+#[test]
+fn boxed_shared_and_identity_handlers_pass_through() {
+    let shared: Arc<dyn Handler> = Arc::new(echo);
+    let boxed: Box<dyn Handler> = Box::new(shared.clone());
+    let handler = Builder::new().layer(Identity).handler(boxed);
+    let req = request("a.example");
+    let resp = handler.handle(&req).unwrap();
+    assert_eq!(resp.header.id, req.message.header.id);
+    assert!(Builder::default()
+        .handler(|_: &Request| None)
+        .handle(&request("a.example"))
+        .is_none());
+}
@@ -0,0 +1,369 @@
+//! Records from DHCP leases: lease files in ISC dhcpd's and Kea's formats,
+//! the A, AAAA and PTR records their named clients get and lose as leases
+//! end, syncs from the file run until stopped, and the configuration that
+//! sets the sync up.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::clock::MockClock;
+use mairudns::config::{Config, LeaseFormatConfig, LeasesConfig, ZoneConfig};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, RecordType};
+use mairudns::server::Authority;
+use mairudns::zone::{host_label, parse_leases, LeaseFormat, LeaseSync, Zone};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Twenty minutes after the leases below start.
+const NOW: u64 = 1_672_921_200;
+
+const ISC: &str = r#"
+# The format of this file is documented in the dhcpd.leases(5) manual page.
+authoring-byte-order little-endian;
+server-duid "\000\001\000\001";
+
+lease 192.0.2.10 {
+  starts 4 2023/01/05 12:00:00;
+  ends 4 2023/01/05 14:00:00;
+  cltt 4 2023/01/05 12:00:00;
+  binding state active;
+  next binding state free;
+  hardware ethernet 00:11:22:33:44:55;
+  uid "\001\000\021\"3DU";
+  client-hostname "Laptop.example";
+}
+lease 192.0.2.11 {
+  starts epoch 1672920000; # Thu Jan 05 12:00:00 2023
+  ends never;
+  binding state free;
+  client-hostname "gone";
+}
+lease 192.0.2.12 {
+  starts 4 2023/01/05 12:00:00;
+  ends 4 2023/01/05 14:00:00;
+  binding state active;
+  client-hostname "www";
+}
+ia-na "\001\000\000\000\000\001" {
+  cltt 4 2023/01/05 12:00:00;
+  iaaddr 2001:db8::5 {
+    binding state active;
+    preferred-life 1800;
+    max-life 3600;
+    ends 4 2023/01/05 13:00:00;
+  }
+  set ddns-fwd-name = "printer.lan.example";
+}
+"#;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// Serves `lan.example.`, with a static `www`, and its reverse zone.
+fn authority() -> Arc<Authority> {
+    let authority = Arc::new(Authority::new());
+    authority.insert(
+        Zone::from_master(
+            name("lan.example."),
+            "$TTL 3600\n@ SOA ns hm 1 2 3 4 5\n@ NS ns\nns A 192.0.2.1\nwww A 192.0.2.99\n",
+        )
+        .unwrap(),
+    );
+    authority.insert(
+        Zone::from_master(
+            name("2.0.192.in-addr.arpa."),
+            "$TTL 3600\n@ SOA ns.lan.example. hm 1 2 3 4 5\n@ NS ns.lan.example.\n",
+        )
+        .unwrap(),
+    );
+    authority
+}
+
+/// The records `authority` has for `owner` and `rtype`.
+fn rrset(authority: &Authority, zone: &str, owner: &str, rtype: RecordType) -> Vec<RData> {
+    let zone = authority.zone(&name(zone)).unwrap();
+    let zone = zone.read().unwrap();
+    zone.rrset(&name(owner), rtype)
+        .map(|rrs| rrs.iter().map(|rr| rr.rdata.clone()).collect())
+        .unwrap_or_default()
+}
+
+fn serial(authority: &Authority, zone: &str) -> Option<u32> {
+    authority
+        .zone(&name(zone))
+        .unwrap()
+        .read()
+        .unwrap()
+        .serial()
+}
+
+fn lease_file(test: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("leases-{}-{}", std::process::id(), test));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn isc_leases_give_records_until_they_end() {
+    let leases = parse_leases(ISC, LeaseFormat::IscDhcpd).unwrap();
+    assert_eq!(leases.len(), 4);
+    assert_eq!(leases[0].starts, Some(1_672_920_000));
+    assert_eq!(leases[0].ends, Some(1_672_927_200));
+    assert_eq!(leases[0].hostname.as_deref(), Some("Laptop.example"));
+    assert!(!leases[1].active);
+    // An IPv6 lease starts at its client's last transaction.
+    assert!(leases[3].active);
+    assert_eq!(leases[3].hostname.as_deref(), Some("printer.lan.example"));
+    assert_eq!(leases[3].starts, Some(1_672_920_000));
+
+    let authority = authority();
+    let clock = MockClock::new(NOW);
+    let sync = LeaseSync::new(
+        authority.clone(),
+        name("lan.example."),
+        "/nonexistent",
+        LeaseFormat::IscDhcpd,
+    )
+    .clock(Arc::new(clock.clone()));
+
+    // The laptop's A and PTR and the printer's AAAA; the free lease
+    // gets nothing, and the static www is left alone.
+    assert_eq!(sync.apply(&leases).unwrap(), 3);
+    assert_eq!(
+        rrset(
+            &authority,
+            "lan.example.",
+            "laptop.lan.example.",
+            RecordType::A
+        ),
+        vec![RData::A([192, 0, 2, 10].into())]
+    );
+    assert_eq!(
+        rrset(
+            &authority,
+            "lan.example.",
+            "www.lan.example.",
+            RecordType::A
+        ),
+        vec![RData::A([192, 0, 2, 99].into())]
+    );
+    assert!(rrset(
+        &authority,
+        "lan.example.",
+        "gone.lan.example.",
+        RecordType::A
+    )
+    .is_empty());
+    assert_eq!(
+        rrset(
+            &authority,
+            "2.0.192.in-addr.arpa.",
+            "10.2.0.192.in-addr.arpa.",
+            RecordType::PTR
+        ),
+        vec![RData::Ptr(name("laptop.lan.example."))]
+    );
+    // The TTLs are half the lease lifetime, at most the largest allowed.
+    let records = sync.records();
+    assert!(records.iter().all(|rr| rr.ttl == 300));
+    assert_eq!(serial(&authority, "lan.example."), Some(2));
+    assert_eq!(serial(&authority, "2.0.192.in-addr.arpa."), Some(2));
+
+    // Nothing changes on a second apply.
+    assert_eq!(sync.apply(&leases).unwrap(), 0);
+    assert_eq!(serial(&authority, "lan.example."), Some(2));
+
+    // The IPv6 lease ends first, then the laptop's.
+    clock.set_unix_time(1_672_923_601);
+    assert_eq!(sync.apply(&leases).unwrap(), 1);
+    assert!(rrset(
+        &authority,
+        "lan.example.",
+        "printer.lan.example.",
+        RecordType::AAAA
+    )
+    .is_empty());
+    clock.set_unix_time(1_672_927_201);
+    assert_eq!(sync.apply(&leases).unwrap(), 2);
+    assert!(sync.records().is_empty());
+    assert_eq!(serial(&authority, "lan.example."), Some(4));
+    // Each change is kept for incremental transfers.
+    let zone = authority.zone(&name("lan.example.")).unwrap();
+    assert!(zone.read().unwrap().incremental_records(1).is_some());
+}
+
+#[test]
+fn kea_leases_give_records() {
+    let text = "\
+address,hwaddr,client_id,valid_lifetime,expire,subnet_id,fqdn_fwd,fqdn_rev,hostname,state,user_context,pool_id
+192.0.2.20,00:00:00:00:00:01,,3600,1672923600,1,0,0,alpha.lan.example.,0,,0
+192.0.2.21,00:00:00:00:00:02,,3600,1672923600,1,0,0,beta,1,,0
+192.0.2.20,00:00:00:00:00:01,,0,1672923600,1,0,0,alpha.lan.example.,0,,0
+192.0.2.22,00:00:00:00:00:03,,3600,1672923600,1,0,0,gamma,0,,0
+";
+    let leases = parse_leases(text, LeaseFormat::KeaCsv).unwrap();
+    assert_eq!(leases.len(), 4);
+
+    // The last line for an address is its lease, so alpha's was
+    // released; beta's is declined.
+    let authority = authority();
+    let sync = LeaseSync::new(
+        authority.clone(),
+        name("lan.example."),
+        "/nonexistent",
+        LeaseFormat::KeaCsv,
+    )
+    .clock(Arc::new(MockClock::new(NOW)))
+    .reverse(false);
+    assert_eq!(sync.apply(&leases).unwrap(), 1);
+    assert_eq!(sync.records()[0].name, name("gamma.lan.example."));
+    assert!(rrset(
+        &authority,
+        "2.0.192.in-addr.arpa.",
+        "22.2.0.192.in-addr.arpa.",
+        RecordType::PTR
+    )
+    .is_empty());
+}
+
+#[cfg(feature = "kea")]
+#[test]
+fn kea_json_leases_give_records() {
+    let text = r#"[{"arguments":{"leases":[{"ip-address":"192.0.2.30","hostname":"delta.lan.example.","valid-lft":600,"cltt":1672920000,"state":0,"subnet-id":1}]},"result":0,"text":"1 found"}]"#;
+    let leases = parse_leases(text, LeaseFormat::KeaJson).unwrap();
+    assert_eq!(leases[0].ends, Some(1_672_920_600));
+    // Just the list of leases will do, and an empty hostname is none.
+    let bare = parse_leases(
+        r#"{"leases":[{"ip-address":"2001:db8::9","valid-lft":600,"cltt":1,"hostname":""}]}"#,
+        LeaseFormat::KeaJson,
+    )
+    .unwrap();
+    assert_eq!(bare[0].hostname, None);
+
+    let sync = LeaseSync::new(
+        authority(),
+        name("lan.example."),
+        "/nonexistent",
+        LeaseFormat::KeaJson,
+    )
+    .clock(Arc::new(MockClock::new(1_672_920_100)));
+    assert_eq!(sync.apply(&leases).unwrap(), 2);
+    assert_eq!(sync.records()[0].ttl, 300);
+}
+
+#[test]
+fn malformed_leases_are_reported_by_line() {
+    let err = parse_leases(
+        "lease 192.0.2.1 {\n  starts 4 2023/13/01 00:00:00;\n}\n",
+        LeaseFormat::IscDhcpd,
+    )
+    .unwrap_err();
+    assert!(err.to_string().starts_with("line 2: "), "{}", err);
+    let err = parse_leases("lease 192.0.2.1 {\n\n", LeaseFormat::IscDhcpd).unwrap_err();
+    assert!(err.to_string().starts_with("line 1: "), "{}", err);
+    assert!(parse_leases("x,y\n", LeaseFormat::KeaCsv).is_err());
+
+    // Hostnames become a single lowercase label, if they can.
+    assert_eq!(host_label("Host-1.foo"), Some("host-1".into()));
+    assert_eq!(host_label("My_Host"), None);
+}
+
+#[test]
+fn syncs_run_from_the_file_until_stopped() {
+    let path = lease_file("run", "lease 192.0.2.10 {\n");
+    let authority = authority();
+    let sync = Arc::new(
+        LeaseSync::new(
+            authority.clone(),
+            name("lan.example."),
+            &path,
+            LeaseFormat::IscDhcpd,
+        )
+        .clock(Arc::new(MockClock::new(NOW)))
+        .interval(Duration::from_millis(20)),
+    );
+    let running = sync.clone();
+    let runner = thread::spawn(move || running.run());
+    let wait = |done: &dyn Fn() -> bool| {
+        let start = Instant::now();
+        while !done() {
+            assert!(start.elapsed() < TIMEOUT);
+            thread::sleep(Duration::from_millis(10));
+        }
+    };
+
+    // A broken file is kept as the last error, and tried again.
+    wait(&|| sync.last_error().is_some());
+    assert!(sync.last_error().unwrap().starts_with("line 1: "));
+    assert!(sync.records().is_empty());
+
+    fs::write(&path, ISC).unwrap();
+    wait(&|| sync.records().len() == 3);
+    assert_eq!(sync.last_error(), None);
+    assert_eq!(
+        rrset(
+            &authority,
+            "lan.example.",
+            "laptop.lan.example.",
+            RecordType::A
+        )
+        .len(),
+        1
+    );
+
+    // Stopping leaves the records.
+    sync.stop();
+    runner.join().unwrap();
+    assert_eq!(sync.records().len(), 3);
+    assert_eq!(sync.sync().unwrap(), 0);
+    fs::remove_file(&path).unwrap();
+    assert!(sync.sync().is_err());
+}
+
+#[test]
+fn lease_syncs_come_from_the_configuration() {
+    let path = lease_file("config", ISC);
+    let config = LeasesConfig {
+        reverse: false,
+        max_ttl: 60,
+        ..LeasesConfig::new(&path, LeaseFormatConfig::IscDhcpd)
+    };
+    let authority = authority();
+    let sync = config.to_sync(authority.clone(), name("lan.example."));
+    assert_eq!(sync.zone(), &name("lan.example."));
+    assert_eq!(sync.path(), path.as_path());
+    let sync = sync.clock(Arc::new(MockClock::new(NOW)));
+    assert_eq!(sync.sync().unwrap(), 2);
+    assert!(sync.records().iter().all(|rr| rr.ttl == 60));
+    assert_eq!(serial(&authority, "2.0.192.in-addr.arpa."), Some(1));
+    fs::remove_file(&path).unwrap();
+
+    // Problems are reported against the field that has them.
+    let field = |zone: ZoneConfig| {
+        let problems = Config::new().zone(zone).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    let leases = LeasesConfig::new("/var/lib/dhcp/dhcpd.leases", LeaseFormatConfig::IscDhcpd);
+    assert_eq!(
+        field(
+            ZoneConfig::secondary("lan.example.", vec!["192.0.2.1:53".parse().unwrap()])
+                .leases(leases.clone())
+        ),
+        "zones[0].leases"
+    );
+    assert_eq!(
+        field(
+            ZoneConfig::primary("lan.example.", "lan.zone").leases(LeasesConfig {
+                interval_secs: 0,
+                ..leases
+            })
+        ),
+        "zones[0].leases.interval-secs"
+    );
+}
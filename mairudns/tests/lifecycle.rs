@@ -0,0 +1,175 @@
+//! Server lifecycle: draining requests under way on shutdown, the drain
+//! timeout, swapping the handler and listeners while the server runs,
+//! pre-bound sockets and systemd socket activation.
+
+use std::env;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::{exchange, Protocol};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::RecordType;
+use mairudns::server::{activated_sockets, Request, Server};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn local() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+fn query() -> Message {
+    Message::query(name("a.test"), RecordType::A)
+}
+
+fn ask(addr: SocketAddr, protocol: Protocol) -> Option<Message> {
+    exchange(addr, protocol, &query(), Duration::from_millis(500)).ok()
+}
+
+fn answer(request: &Request) -> Option<Message> {
+    Some(request.message.response())
+}
+
+fn slow(delay: Duration) -> impl Fn(&Request) -> Option<Message> + Send + Sync + 'static {
+    move |request: &Request| {
+        thread::sleep(delay);
+        Some(request.message.response())
+    }
+}
+
+#[test]
+fn shutdown_answers_requests_under_way() {
+    let mut server = Server::new(slow(Duration::from_millis(300)));
+    let udp = server.listen_udp(local()).unwrap();
+    let tcp = server.listen_tcp(local()).unwrap();
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+    let client =
+        thread::spawn(move || exchange(tcp, Protocol::Tcp, &query(), Duration::from_secs(2)));
+    thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    control.shutdown();
+    assert!(control.is_shutting_down());
+    assert!(client.join().unwrap().is_ok());
+    running.join().unwrap().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // Nothing listens any more.
+    assert!(ask(udp, Protocol::Udp).is_none());
+    assert!(ask(tcp, Protocol::Tcp).is_none());
+}
+
+#[test]
+fn shutdown_gives_up_after_the_drain_timeout() {
+    let mut server = Server::new(slow(Duration::from_secs(3)));
+    let tcp = server.listen_tcp(local()).unwrap();
+    server.set_drain_timeout(Duration::from_millis(200));
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+    let client =
+        thread::spawn(move || exchange(tcp, Protocol::Tcp, &query(), Duration::from_secs(5)));
+    thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    control.shutdown();
+    running.join().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+    // The connection was closed without an answer.
+    assert!(client.join().unwrap().is_err());
+}
+
+#[test]
+fn replaces_the_handler_on_the_same_sockets() {
+    let mut server = Server::new(answer as fn(&Request) -> Option<Message>);
+    let udp = server.listen_udp(local()).unwrap();
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+    assert_eq!(
+        ask(udp, Protocol::Udp).unwrap().header.rcode,
+        Rcode::NOERROR
+    );
+    control.set_handler(|request: &Request| {
+        let mut resp = request.message.response();
+        resp.header.rcode = Rcode::REFUSED;
+        Some(resp)
+    });
+    assert_eq!(
+        ask(udp, Protocol::Udp).unwrap().header.rcode,
+        Rcode::REFUSED
+    );
+    control.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn opens_and_closes_listeners_while_running() {
+    let mut server = Server::new(answer as fn(&Request) -> Option<Message>);
+    let first = server.listen_udp(local()).unwrap();
+    let control = server.control();
+    // Before run() there is nothing to add listeners to.
+    assert_eq!(
+        control.listen_udp(local()).unwrap_err().kind(),
+        std::io::ErrorKind::NotConnected
+    );
+    let running = thread::spawn(move || server.run());
+    thread::sleep(Duration::from_millis(50));
+
+    let udp = control.listen_udp(local()).unwrap();
+    let tcp = control.listen_tcp(local()).unwrap();
+    assert!(ask(udp, Protocol::Udp).is_some());
+    assert!(ask(tcp, Protocol::Tcp).is_some());
+    let listening = control.listening();
+    assert!(listening.contains(&(Protocol::Udp, first)));
+    assert!(listening.contains(&(Protocol::Udp, udp)));
+    assert!(listening.contains(&(Protocol::Tcp, tcp)));
+
+    assert_eq!(control.close(udp), 1);
+    assert_eq!(control.close(tcp), 1);
+    thread::sleep(Duration::from_millis(600));
+    assert!(ask(udp, Protocol::Udp).is_none());
+    assert!(ask(tcp, Protocol::Tcp).is_none());
+    assert!(ask(first, Protocol::Udp).is_some());
+    assert!(!control.listening().contains(&(Protocol::Udp, udp)));
+    control.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn serves_pre_bound_sockets() {
+    let udp = UdpSocket::bind(local()).unwrap();
+    let tcp = TcpListener::bind(local()).unwrap();
+    let (udp_addr, tcp_addr) = (udp.local_addr().unwrap(), tcp.local_addr().unwrap());
+    let mut server = Server::new(answer as fn(&Request) -> Option<Message>);
+    server.adopt_udp(udp);
+    server.adopt_tcp(tcp);
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+    assert!(ask(udp_addr, Protocol::Udp).is_some());
+    assert!(ask(tcp_addr, Protocol::Tcp).is_some());
+    control.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn activation_ignores_variables_meant_for_others() {
+    // Without the variables there is nothing to take over.
+    env::remove_var("LISTEN_PID");
+    assert!(activated_sockets().unwrap().is_empty());
+
+    // Another process's sockets are left alone, and the variables are
+    // removed all the same.
+    env::set_var("LISTEN_PID", (std::process::id() + 1).to_string());
+    env::set_var("LISTEN_FDS", "2");
+    env::set_var("LISTEN_FDNAMES", "dns:dot");
+    assert!(activated_sockets().unwrap().is_empty());
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        assert!(env::var_os(var).is_none(), "{}", var);
+    }
+
+    env::set_var("LISTEN_PID", std::process::id().to_string());
+    env::set_var("LISTEN_FDS", "0");
+    assert!(activated_sockets().unwrap().is_empty());
+    assert!(env::var_os("LISTEN_FDS").is_none());
+}
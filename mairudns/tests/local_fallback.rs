@@ -0,0 +1,95 @@
+//! LLMNR and NetBIOS fallback for single-label names: the queries they
+//! send and when the resolver falls back to them.
+
+use std::time::{Duration, Instant};
+
+use mairudns::llmnr;
+use mairudns::message::Rcode;
+use mairudns::name::DomainName;
+use mairudns::netbios;
+use mairudns::resolver::{Error, LocalFallback, Resolver, ResolverConfig};
+use mairudns::rr::RecordType;
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn resolver(server: &MockServer, fallback: LocalFallback) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        fallback,
+        ..ResolverConfig::default()
+    })
+}
+
+fn nxdomain() -> MockServer {
+    MockServer::builder()
+        .then(Action::Rcode(Rcode::NXDOMAIN))
+        .then(Action::Rcode(Rcode::NXDOMAIN))
+        .start()
+        .unwrap()
+}
+
+#[test]
+fn netbios_names_use_first_level_encoding() {
+    // RFC 1001 §14.1, with the workstation suffix in place of a space.
+    assert_eq!(
+        netbios::encode_name("fred", 0x00).unwrap(),
+        name("EGFCEFEECACACACACACACACACACACAAA")
+    );
+    assert_eq!(
+        netbios::encode_name("FRED", 0x20).unwrap(),
+        name("EGFCEFEECACACACACACACACACACACACA")
+    );
+    assert!(netbios::encode_name("", 0x00).is_none());
+    assert!(netbios::encode_name("sixteen-letters!", 0x00).is_none());
+}
+
+#[test]
+fn llmnr_queries_carry_no_rd_bit_or_edns() {
+    let query = llmnr::query_message(&name("printer"), RecordType::A);
+    assert!(!query.header.rd);
+    assert!(query.edns.is_none());
+    assert_eq!(query.questions.len(), 1);
+    assert_eq!(query.questions[0].name, name("printer"));
+}
+
+#[test]
+fn does_not_fall_back_when_disabled() {
+    let server = nxdomain();
+    let start = Instant::now();
+    let result =
+        resolver(&server, LocalFallback::default()).lookup(&name("printer"), RecordType::A);
+    assert!(matches!(result, Err(Error::Rcode(Rcode::NXDOMAIN))));
+    assert!(start.elapsed() < llmnr::TIMEOUT);
+}
+
+#[test]
+fn does_not_fall_back_for_names_with_several_labels() {
+    let server = nxdomain();
+    let fallback = LocalFallback {
+        llmnr: true,
+        netbios: true,
+    };
+    let start = Instant::now();
+    let result = resolver(&server, fallback).lookup(&name("printer.example"), RecordType::A);
+    assert!(matches!(result, Err(Error::Rcode(Rcode::NXDOMAIN))));
+    assert!(start.elapsed() < llmnr::TIMEOUT);
+}
+
+#[test]
+fn keeps_the_dns_error_when_nothing_answers_locally() {
+    let server = nxdomain();
+    let fallback = LocalFallback {
+        llmnr: true,
+        netbios: false,
+    };
+    let start = Instant::now();
+    let result = resolver(&server, fallback).lookup(&name("no-such-host-here"), RecordType::A);
+    assert!(matches!(result, Err(Error::Rcode(Rcode::NXDOMAIN))));
+    // The LLMNR wait was sat through.
+    assert!(start.elapsed() >= llmnr::TIMEOUT);
+}
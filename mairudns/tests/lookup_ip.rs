@@ -0,0 +1,186 @@
+//! Happy Eyeballs address lookup against a mock server: the order
+//! addresses come out in, the wait for AAAA after A, and what happens when
+//! one or both queries fail.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{precedence, sort_by_precedence, Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn records(owner: &DomainName, addrs: &[&str]) -> Vec<Record> {
+    addrs
+        .iter()
+        .map(|a| {
+            let rdata = match ip(a) {
+                IpAddr::V4(a) => RData::A(a),
+                IpAddr::V6(a) => RData::Aaaa(a),
+            };
+            Record::new(owner.clone(), 300, rdata)
+        })
+        .collect()
+}
+
+fn resolver(server: &MockServer) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    })
+}
+
+#[test]
+fn interleaves_families_starting_with_ipv6() {
+    let host = name("host.example");
+    let server = MockServer::builder()
+        .answer(
+            host.clone(),
+            RecordType::AAAA,
+            records(&host, &["2001:db8::1", "2001:db8::2"]),
+        )
+        .answer(
+            host.clone(),
+            RecordType::A,
+            records(&host, &["192.0.2.1", "192.0.2.2", "192.0.2.3"]),
+        )
+        .start()
+        .unwrap();
+    let lookup = resolver(&server).lookup_ip(&host);
+    // With both answers in, neither family waits for the other.
+    std::thread::sleep(Duration::from_millis(200));
+    let addrs: Vec<IpAddr> = lookup.collect();
+    assert_eq!(
+        addrs,
+        vec![
+            ip("2001:db8::1"),
+            ip("192.0.2.1"),
+            ip("2001:db8::2"),
+            ip("192.0.2.2"),
+            ip("192.0.2.3"),
+        ]
+    );
+}
+
+#[test]
+fn orders_each_family_by_precedence() {
+    let host = name("host.example");
+    let server = MockServer::builder()
+        .answer(
+            host.clone(),
+            RecordType::AAAA,
+            records(&host, &["2001:0::1", "fd00::1", "2001:db8::1"]),
+        )
+        .answer(host.clone(), RecordType::A, Vec::new())
+        .start()
+        .unwrap();
+    let addrs: Vec<IpAddr> = resolver(&server).lookup_ip(&host).collect();
+    // Global unicast first, then Teredo, then ULA.
+    assert_eq!(addrs, vec![ip("2001:db8::1"), ip("2001::1"), ip("fd00::1")]);
+}
+
+#[test]
+fn waits_for_a_late_aaaa_answer_only_briefly() {
+    let host = name("host.example");
+    let late = Action::delay(
+        Duration::from_millis(400),
+        Action::answer(records(&host, &["2001:db8::1"])),
+    );
+    let server = MockServer::builder()
+        .on(host.clone(), RecordType::AAAA, late)
+        .answer(host.clone(), RecordType::A, records(&host, &["192.0.2.1"]))
+        .start()
+        .unwrap();
+    let start = Instant::now();
+    let mut addrs = resolver(&server).lookup_ip(&host);
+    assert_eq!(addrs.next(), Some(ip("192.0.2.1")));
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert_eq!(addrs.next(), Some(ip("2001:db8::1")));
+    assert_eq!(addrs.next(), None);
+}
+
+#[test]
+fn uses_one_family_when_the_other_fails() {
+    let host = name("host.example");
+    let server = MockServer::builder()
+        .on(
+            host.clone(),
+            RecordType::AAAA,
+            Action::Rcode(Rcode::SERVFAIL),
+        )
+        .answer(host.clone(), RecordType::A, records(&host, &["192.0.2.1"]))
+        .start()
+        .unwrap();
+    let mut addrs = resolver(&server).lookup_ip(&host);
+    assert_eq!(addrs.by_ref().collect::<Vec<_>>(), vec![ip("192.0.2.1")]);
+    assert_eq!(addrs.errors().len(), 1);
+}
+
+#[test]
+fn reports_errors_when_nothing_resolves() {
+    let host = name("missing.example");
+    let server = MockServer::builder()
+        .on(
+            host.clone(),
+            RecordType::AAAA,
+            Action::Rcode(Rcode::NXDOMAIN),
+        )
+        .on(host.clone(), RecordType::A, Action::Rcode(Rcode::NXDOMAIN))
+        .start()
+        .unwrap();
+    let mut addrs = resolver(&server).lookup_ip(&host);
+    assert_eq!(addrs.next(), None);
+    assert_eq!(addrs.errors().len(), 2);
+}
+
+#[test]
+fn precedence_follows_the_rfc_6724_policy_table() {
+    assert_eq!(precedence(&ip("::1")), 50);
+    assert_eq!(precedence(&ip("2001:db8::1")), 40);
+    assert_eq!(precedence(&ip("192.0.2.1")), 35);
+    assert_eq!(precedence(&ip("2002:c000:0201::1")), 30);
+    assert_eq!(precedence(&ip("2001:0::1")), 5);
+    assert_eq!(precedence(&ip("fd00::1")), 3);
+    assert_eq!(precedence(&ip("fec0::1")), 1);
+    let mut addrs = vec![ip("fd00::1"), ip("192.0.2.1"), ip("::1"), ip("fd00::2")];
+    sort_by_precedence(&mut addrs);
+    assert_eq!(
+        addrs,
+        vec![ip("::1"), ip("192.0.2.1"), ip("fd00::1"), ip("fd00::2")]
+    );
+}
+
+#[test]
+fn reads_resolv_conf() {
+    let config = ResolverConfig::from_resolv_conf(
+        "# local\nnameserver 192.0.2.53\nnameserver 2001:db8::53 ; second\n\
+         nameserver not-an-address\nsearch example\noptions timeout:3 attempts:4 ndots:2\n",
+    );
+    assert_eq!(
+        config.servers,
+        vec![
+            "192.0.2.53:53".parse().unwrap(),
+            "[2001:db8::53]:53".parse().unwrap()
+        ]
+    );
+    assert_eq!(config.timeout, Duration::from_secs(3));
+    assert_eq!(config.attempts, 4);
+}
+
+#[test]
+fn queries_round_trip_through_the_wire_format() {
+    let query = Message::query(name("host.example"), RecordType::AAAA);
+    let decoded = Message::from_wire(&query.to_wire().unwrap()).unwrap();
+    assert_eq!(decoded, query);
+}
@@ -0,0 +1,400 @@
+//! Mail policies published in the DNS: SPF records parsed and checked
+//! across their includes against the lookup limits, DKIM keys, DMARC
+//! policies found up the tree, and the two halves of MTA-STS.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::mail::{
+    check_spf, lookup_dkim, lookup_dmarc, lookup_mta_sts, policy_url, Alignment, Directive,
+    Disposition, DkimKey, DmarcPolicy, MailError, Mechanism, MtaStsMode, MtaStsPolicy,
+    MtaStsRecord, Qualifier, SpfProblem, SpfRecord, MAX_SPF_LOOKUPS,
+};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::server::{Authority, Server};
+use mairudns::zone::Zone;
+
+const ZONE: &str = r#"$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 192.0.2.53
+@ IN TXT "v=spf1 ip4:192.0.2.0/24 include:_spf.example.com. " "a mx:mail.example.com/24//64 -all"
+@ IN TXT "google-site-verification=abc"
+@ IN TXT "v=spf10 not spf at all"
+_spf IN TXT "v=spf1 include:_spf2.example.com. exists:%{i}.x.example.com. ~all"
+_spf2 IN TXT "v=spf1 redirect=_spf.example.com."
+void IN TXT "v=spf1 include:a.missing.example.com. include:b.missing.example.com. include:c.missing.example.com. -all"
+many IN TXT "v=spf1 a:1.example.com a:2.example.com a:3.example.com a:4.example.com a:5.example.com a:6.example.com include:more.example.com -all"
+more IN TXT "v=spf1 mx ptr exists:x.example.com redirect=void.example.com"
+odd IN TXT "v=spf1 include:%{d}.example.com include:two.example.com include:broken.example.com ?all"
+two IN TXT "v=spf1 -all"
+two IN TXT "v=spf1 +all"
+broken IN TXT "v=spf1 frob:x"
+sel._domainkey IN TXT "v=DKIM1; k=rsa; t=y:s; h=sha256; p=AAEC" "AwQ="
+old._domainkey IN TXT "v=DKIM1; p="
+_dmarc IN TXT "v=DMARC1; p=reject; rua=mailto:a@example.com,mailto:b@example.com; pct=50; adkim=s"
+_dmarc.own IN TXT "v=DMARC1; p=none"
+_mta-sts IN TXT "v=STSv1; id=20240101T000000"
+"#;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// Serves example.com. over UDP and TCP, returning a resolver that asks
+/// it.
+fn serve() -> Resolver {
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example.com."), ZONE).unwrap());
+    let mut server = Server::new(authority);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Udp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    Resolver::new(ResolverConfig {
+        servers: vec![addr],
+        ..ResolverConfig::default()
+    })
+}
+
+fn syntax_error<T: std::fmt::Debug>(result: Result<T, MailError>) -> String {
+    match result {
+        Err(MailError::Syntax(why)) => why,
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn spf_records_parse_and_print_back() {
+    let text = "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.1 ip6:2001:db8::/32 a a:x.example/24 \
+                mx//64 mx:m.example/28//96 ?ptr ptr:p.example include:i.example \
+                ~exists:%{i}.e.example redirect=r.example exp=why.example ra=postmaster -all";
+    let record: SpfRecord = text.parse().unwrap();
+    use Mechanism::*;
+    let mechanisms: Vec<&Mechanism> = record.directives.iter().map(|d| &d.mechanism).collect();
+    assert_eq!(
+        mechanisms,
+        [
+            &Ip4 {
+                addr: Ipv4Addr::new(192, 0, 2, 0),
+                prefix: 24
+            },
+            &Ip4 {
+                addr: Ipv4Addr::new(198, 51, 100, 1),
+                prefix: 32
+            },
+            &Ip6 {
+                addr: "2001:db8::".parse::<Ipv6Addr>().unwrap(),
+                prefix: 32
+            },
+            &A {
+                domain: None,
+                v4_prefix: None,
+                v6_prefix: None
+            },
+            &A {
+                domain: Some("x.example".into()),
+                v4_prefix: Some(24),
+                v6_prefix: None
+            },
+            &Mx {
+                domain: None,
+                v4_prefix: None,
+                v6_prefix: Some(64)
+            },
+            &Mx {
+                domain: Some("m.example".into()),
+                v4_prefix: Some(28),
+                v6_prefix: Some(96)
+            },
+            &Ptr(None),
+            &Ptr(Some("p.example".into())),
+            &Include("i.example".into()),
+            &Exists("%{i}.e.example".into()),
+            &All,
+        ]
+    );
+    assert_eq!(record.directives[7].qualifier, Qualifier::Neutral);
+    assert_eq!(
+        record.directives[10],
+        Directive {
+            qualifier: Qualifier::SoftFail,
+            mechanism: Exists("%{i}.e.example".into()),
+        }
+    );
+    assert_eq!(record.directives[11].qualifier, Qualifier::Fail);
+    assert_eq!(record.redirect.as_deref(), Some("r.example"));
+    assert_eq!(record.explanation.as_deref(), Some("why.example"));
+    assert_eq!(
+        record.other_modifiers,
+        [("ra".to_string(), "postmaster".to_string())]
+    );
+    // The redirect is never reached past `all`, so it costs nothing.
+    assert_eq!(record.effective_redirect(), None);
+    assert_eq!(record.lookups(), 8);
+
+    // Printed in a canonical order that parses to the same record.
+    let printed = record.to_string();
+    assert!(printed.starts_with("v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.1/32 "));
+    assert!(printed.ends_with(" -all redirect=r.example exp=why.example ra=postmaster"));
+    assert_eq!(printed.parse::<SpfRecord>().unwrap(), record);
+
+    let redirect: SpfRecord = "V=SPF1 redirect=r.example".parse().unwrap();
+    assert_eq!(redirect.effective_redirect(), Some("r.example"));
+    assert_eq!(redirect.lookups(), 1);
+}
+
+#[test]
+fn malformed_spf_records_say_why() {
+    let why = |text: &str| syntax_error(text.parse::<SpfRecord>());
+    assert_eq!(why("v=spf2 -all"), "not an SPF record");
+    assert_eq!(why(""), "not an SPF record");
+    assert_eq!(why("v=spf1 frob"), "unknown mechanism \"frob\"");
+    assert_eq!(why("v=spf1 all:x"), "unknown mechanism \"all:x\"");
+    assert_eq!(why("v=spf1 include"), "include needs a domain");
+    assert_eq!(why("v=spf1 include:"), "include needs a domain");
+    assert_eq!(
+        why("v=spf1 ip4:192.0.2.0/33"),
+        "invalid network \"192.0.2.0/33\""
+    );
+    assert_eq!(why("v=spf1 ip6:nope"), "invalid address \"nope\"");
+    assert_eq!(why("v=spf1 a/40"), "invalid prefix length \"40\"");
+    assert_eq!(why("v=spf1 mx//129"), "invalid prefix length \"129\"");
+    assert_eq!(why("v=spf1 a:"), "empty domain");
+    assert_eq!(
+        why("v=spf1 redirect=a.example redirect=b.example"),
+        "redirect appears twice"
+    );
+    assert_eq!(why("v=spf1 exp="), "exp needs a domain");
+}
+
+#[test]
+fn spf_checks_follow_includes_and_redirects() {
+    let resolver = serve();
+    let check = check_spf(&resolver, &name("example.com.")).unwrap();
+    let domains: Vec<_> = check.records.iter().map(|(d, _)| d.to_string()).collect();
+    assert_eq!(
+        domains,
+        ["example.com.", "_spf.example.com.", "_spf2.example.com."]
+    );
+    // include, a and mx; include and exists; the redirect.
+    assert_eq!(check.lookups, 6);
+    assert_eq!(check.void_lookups, 0);
+    assert_eq!(
+        check.problems,
+        [SpfProblem::Loop(name("_spf.example.com."))]
+    );
+    assert!(check.within_limits());
+    assert_eq!(
+        check.records[0].1.directives[3].mechanism,
+        Mechanism::Mx {
+            domain: Some("mail.example.com".into()),
+            v4_prefix: Some(24),
+            v6_prefix: Some(64),
+        }
+    );
+
+    // Six a, the include, then mx, ptr, exists and the redirect beneath it.
+    let check = check_spf(&resolver, &name("many.example.com.")).unwrap();
+    assert_eq!(check.lookups, 11 + 3);
+    assert!(check.lookups > MAX_SPF_LOOKUPS);
+    assert!(!check.within_limits());
+    assert_eq!(check.void_lookups, 3);
+
+    let check = check_spf(&resolver, &name("void.example.com.")).unwrap();
+    assert_eq!(check.lookups, 3);
+    assert_eq!(check.void_lookups, 3);
+    assert!(!check.within_limits());
+    assert_eq!(
+        check.problems,
+        [
+            SpfProblem::Missing(name("a.missing.example.com.")),
+            SpfProblem::Missing(name("b.missing.example.com.")),
+            SpfProblem::Missing(name("c.missing.example.com.")),
+        ]
+    );
+
+    let check = check_spf(&resolver, &name("odd.example.com.")).unwrap();
+    assert_eq!(
+        check.problems,
+        [
+            SpfProblem::Macro("%{d}.example.com".into()),
+            SpfProblem::Multiple(name("two.example.com.")),
+            SpfProblem::Invalid(
+                name("broken.example.com."),
+                "unknown mechanism \"frob:x\"".into()
+            ),
+        ]
+    );
+    assert_eq!(
+        check.problems[0].to_string(),
+        "%{d}.example.com depends on the message"
+    );
+
+    // Only the domain's own record is an error.
+    assert!(matches!(
+        check_spf(&resolver, &name("two.example.com.")),
+        Err(MailError::Multiple)
+    ));
+    assert!(matches!(
+        check_spf(&resolver, &name("nowhere.example.com.")),
+        Err(MailError::Missing)
+    ));
+    assert!(matches!(
+        check_spf(&resolver, &name("broken.example.com.")),
+        Err(MailError::Syntax(_))
+    ));
+}
+
+#[test]
+fn dkim_keys_parse_and_are_found_by_selector() {
+    let key: DkimKey = "v=DKIM1; k=ed25519; n=notes here; s=email:*; t=y; \
+                        p=AAEC\n AwQ=; x=ignored"
+        .parse()
+        .unwrap();
+    assert_eq!(key.key_type, "ed25519");
+    assert_eq!(key.notes.as_deref(), Some("notes here"));
+    assert_eq!(key.service_types, ["email", "*"]);
+    assert_eq!(key.public_key, [0, 1, 2, 3, 4]);
+    assert!(key.is_testing());
+    assert!(key.allows_subdomains());
+    assert!(!key.is_revoked());
+
+    // Defaults, with v= optional.
+    let key: DkimKey = "p=AAEC".parse().unwrap();
+    assert_eq!(key.key_type, "rsa");
+    assert_eq!(key.service_types, ["*"]);
+    assert!(key.hash_algorithms.is_empty());
+
+    let why = |text: &str| syntax_error(text.parse::<DkimKey>());
+    assert_eq!(why("v=DKIM1; k=rsa"), "no p= tag");
+    assert_eq!(why("k=rsa; v=DKIM1; p="), "v= must come first");
+    assert_eq!(why("v=DKIM2; p="), "unknown version \"DKIM2\"");
+    assert_eq!(why("p=AAEC; p=AAEC"), "tag p appears twice");
+    assert_eq!(why("p"), "\"p\" is not tag=value");
+    assert!(why("p=!!!!").starts_with("public key: "));
+
+    let resolver = serve();
+    let key = lookup_dkim(&resolver, "sel", &name("example.com.")).unwrap();
+    assert_eq!(key.public_key, [0, 1, 2, 3, 4]);
+    assert_eq!(key.hash_algorithms, ["sha256"]);
+    assert!(key.is_testing() && !key.allows_subdomains());
+    assert!(lookup_dkim(&resolver, "old", &name("example.com."))
+        .unwrap()
+        .is_revoked());
+    assert!(matches!(
+        lookup_dkim(&resolver, "none", &name("example.com.")),
+        Err(MailError::Missing)
+    ));
+}
+
+#[test]
+fn dmarc_policies_parse_and_are_found_up_the_tree() {
+    let policy: DmarcPolicy = "v=DMARC1; p=quarantine; sp=none; pct=25; \
+                               rua=mailto:a@example.com, mailto:b@example.com; \
+                               ruf=mailto:f@example.com; adkim=s; aspf=r; fo=0:1:d; \
+                               ri=3600; rf=afrf; x=1"
+        .parse()
+        .unwrap();
+    assert_eq!(policy.policy, Disposition::Quarantine);
+    assert_eq!(policy.subdomain_policy, Disposition::None);
+    assert_eq!(policy.percent, 25);
+    assert_eq!(policy.rua, ["mailto:a@example.com", "mailto:b@example.com"]);
+    assert_eq!(policy.ruf, ["mailto:f@example.com"]);
+    assert_eq!(policy.adkim, Alignment::Strict);
+    assert_eq!(policy.aspf, Alignment::Relaxed);
+    assert_eq!(policy.failure_options, ["0", "1", "d"]);
+    assert_eq!(policy.report_interval, 3600);
+    assert_eq!(policy.policy.to_string(), "quarantine");
+
+    let defaults: DmarcPolicy = "v=DMARC1; p=REJECT".parse().unwrap();
+    assert_eq!(defaults.subdomain_policy, Disposition::Reject);
+    assert_eq!(defaults.percent, 100);
+    assert_eq!(defaults.adkim, Alignment::Relaxed);
+    assert_eq!(defaults.report_interval, 86400);
+    assert_eq!(defaults.report_formats, ["afrf"]);
+
+    let why = |text: &str| syntax_error(text.parse::<DmarcPolicy>());
+    assert_eq!(why("p=none; v=DMARC1"), "v=DMARC1 must come first");
+    assert_eq!(why("v=DMARC1; sp=none"), "no p= tag");
+    assert_eq!(why("v=DMARC1; p=maybe"), "unknown policy \"maybe\"");
+    assert_eq!(why("v=DMARC1; p=none; pct=101"), "pct=101 is not 0-100");
+    assert_eq!(why("v=DMARC1; p=none; adkim=x"), "unknown alignment \"x\"");
+    assert_eq!(why("v=DMARC1; p=none; ri=soon"), "ri=soon is not a number");
+
+    let resolver = serve();
+    let (at, policy) = lookup_dmarc(&resolver, &name("a.b.example.com.")).unwrap();
+    assert_eq!(at, name("example.com."));
+    assert_eq!(policy.policy, Disposition::Reject);
+    assert_eq!(policy.percent, 50);
+    let (at, policy) = lookup_dmarc(&resolver, &name("x.own.example.com.")).unwrap();
+    assert_eq!(at, name("own.example.com."));
+    assert_eq!(policy.policy, Disposition::None);
+    assert!(matches!(
+        lookup_dmarc(&resolver, &name("com.")),
+        Err(MailError::Missing)
+    ));
+}
+
+#[test]
+fn mta_sts_records_and_policies() {
+    let record: MtaStsRecord = "v=STSv1; id=20240101T000000;".parse().unwrap();
+    assert_eq!(record.id, "20240101T000000");
+    let why = |text: &str| syntax_error(text.parse::<MtaStsRecord>());
+    assert_eq!(why("id=1; v=STSv1"), "v=STSv1 must come first");
+    assert_eq!(why("v=STSv1"), "no id= tag");
+    assert_eq!(
+        why("v=STSv1; id=not-ok"),
+        "id \"not-ok\" is not 1-32 letters and digits"
+    );
+
+    let resolver = serve();
+    assert_eq!(
+        lookup_mta_sts(&resolver, &name("example.com.")).unwrap(),
+        record
+    );
+    assert!(matches!(
+        lookup_mta_sts(&resolver, &name("own.example.com.")),
+        Err(MailError::Missing)
+    ));
+    assert_eq!(
+        policy_url(&name("example.com.")),
+        "https://mta-sts.example.com/.well-known/mta-sts.txt"
+    );
+
+    let policy: MtaStsPolicy = "version: STSv1\r\nmode: enforce\r\nmx: mail.example.com\r\n\
+                                mx: *.example.net\r\nmax_age: 86400\r\nextra: ignored\r\n"
+        .parse()
+        .unwrap();
+    assert_eq!(policy.mode, MtaStsMode::Enforce);
+    assert_eq!(policy.max_age, Duration::from_secs(86400));
+    assert!(policy.matches_mx("mail.example.com."));
+    assert!(policy.matches_mx("MAIL.Example.COM"));
+    assert!(policy.matches_mx("mx1.example.net"));
+    // A wildcard stands for exactly one label.
+    assert!(!policy.matches_mx("a.b.example.net"));
+    assert!(!policy.matches_mx("example.net"));
+    assert!(!policy.matches_mx("other.example.com"));
+
+    let why = |text: &str| syntax_error(text.parse::<MtaStsPolicy>());
+    assert_eq!(why("mode: enforce\nmx: a\nmax_age: 1"), "no version: STSv1");
+    assert_eq!(why("version: STSv1\nmx: a\nmax_age: 1"), "no mode");
+    assert_eq!(why("version: STSv1\nmode: enforce\nmax_age: 1"), "no mx");
+    assert_eq!(
+        why("version: STSv1\nmode: testing\nmx: a\nmax_age: 31557601"),
+        "max_age \"31557601\" is out of range"
+    );
+    assert_eq!(why("version: STSv1\nmode: off"), "unknown mode \"off\"");
+    assert_eq!(why("version STSv1"), "\"version STSv1\" is not key: value");
+    let none: MtaStsPolicy = "version: STSv1\nmode: none\nmax_age: 0".parse().unwrap();
+    assert_eq!(none.mode, MtaStsMode::None);
+    assert!(none.mx.is_empty());
+}
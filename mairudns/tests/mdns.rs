@@ -0,0 +1,223 @@
+//! The mDNS cache and responder and DNS-SD registration, without touching
+//! the multicast group: the cache is fed records directly and the
+//! responder is asked for the response it would send.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use mairudns::mdns::sd::{parse_txt, register_service, service_name, ServiceInfo};
+use mairudns::mdns::{Cache, Responder, Socket, CACHE_FLUSH, UNICAST_RESPONSE};
+use mairudns::message::{Message, Question};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordClass, RecordType};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn a(owner: &str, addr: [u8; 4], ttl: u32) -> Record {
+    Record::new(name(owner), ttl, RData::A(addr.into()))
+}
+
+fn flushing(mut record: Record) -> Record {
+    record.class = RecordClass(record.class.0 | CACHE_FLUSH);
+    record
+}
+
+fn multicast_peer() -> SocketAddr {
+    "192.0.2.7:5353".parse().unwrap()
+}
+
+fn query(owner: &str, qtype: RecordType, qclass: RecordClass) -> Message {
+    let mut query = Message::default();
+    query.questions.push(Question {
+        name: name(owner),
+        qtype,
+        qclass,
+    });
+    query
+}
+
+#[test]
+fn cache_flush_replaces_records_older_than_a_second() {
+    let now = Instant::now();
+    let mut cache = Cache::new();
+    cache.insert(a("host.local", [10, 0, 0, 1], 120), now);
+    cache.insert(a("host.local", [10, 0, 0, 2], 120), now);
+    let later = now + Duration::from_secs(5);
+    cache.insert(flushing(a("host.local", [10, 0, 0, 3], 120)), later);
+    let found = cache.lookup(&name("host.local"), RecordType::A, later);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].rdata, RData::A([10, 0, 0, 3].into()));
+    assert_eq!(found[0].class, RecordClass::IN);
+}
+
+#[test]
+fn cache_flush_keeps_records_from_the_same_burst() {
+    let now = Instant::now();
+    let mut cache = Cache::new();
+    cache.insert(flushing(a("host.local", [10, 0, 0, 1], 120)), now);
+    let soon = now + Duration::from_millis(200);
+    cache.insert(flushing(a("host.local", [10, 0, 0, 2], 120)), soon);
+    assert_eq!(
+        cache.lookup(&name("host.local"), RecordType::A, soon).len(),
+        2
+    );
+}
+
+#[test]
+fn goodbye_records_expire_after_a_second() {
+    let now = Instant::now();
+    let mut cache = Cache::new();
+    cache.insert(a("host.local", [10, 0, 0, 1], 120), now);
+    cache.insert(a("host.local", [10, 0, 0, 1], 0), now);
+    let host = name("host.local");
+    assert_eq!(cache.lookup(&host, RecordType::A, now).len(), 1);
+    let later = now + Duration::from_secs(2);
+    assert!(cache.lookup(&host, RecordType::A, later).is_empty());
+    cache.purge(later);
+    assert!(cache.records(later).is_empty());
+}
+
+#[test]
+fn known_answers_need_half_their_ttl_left() {
+    let now = Instant::now();
+    let mut cache = Cache::new();
+    cache.insert(a("host.local", [10, 0, 0, 1], 100), now);
+    let host = name("host.local");
+    assert_eq!(
+        cache
+            .known_answers(&host, RecordType::A, now + Duration::from_secs(40))
+            .len(),
+        1
+    );
+    assert!(cache
+        .known_answers(&host, RecordType::A, now + Duration::from_secs(60))
+        .is_empty());
+}
+
+fn responder() -> Responder {
+    let mut responder = Responder::new(Socket::oneshot().unwrap());
+    responder.add(a("host.local", [10, 0, 0, 1], 120), true);
+    responder
+}
+
+#[test]
+fn responder_multicasts_answers_with_the_cache_flush_bit() {
+    let (resp, to) = responder()
+        .respond(
+            &query("host.local", RecordType::A, RecordClass::IN),
+            multicast_peer(),
+        )
+        .unwrap();
+    assert_eq!(to, None);
+    assert_eq!(resp.header.id, 0);
+    assert!(resp.header.aa);
+    assert!(resp.questions.is_empty());
+    assert_eq!(resp.answers.len(), 1);
+    assert_eq!(resp.answers[0].class.0, RecordClass::IN.0 | CACHE_FLUSH);
+}
+
+#[test]
+fn responder_unicasts_to_qu_questions_and_legacy_queriers() {
+    let responder = responder();
+    let qu = RecordClass(RecordClass::IN.0 | UNICAST_RESPONSE);
+    let (_, to) = responder
+        .respond(&query("host.local", RecordType::A, qu), multicast_peer())
+        .unwrap();
+    assert_eq!(to, Some(multicast_peer()));
+
+    let legacy: SocketAddr = "192.0.2.7:40000".parse().unwrap();
+    let mut q = query("host.local", RecordType::A, RecordClass::IN);
+    q.header.id = 0x1234;
+    let (resp, to) = responder.respond(&q, legacy).unwrap();
+    assert_eq!(to, Some(legacy));
+    assert_eq!(resp.header.id, 0x1234);
+    assert_eq!(resp.questions, q.questions);
+    assert_eq!(resp.answers[0].ttl, 10);
+    assert_eq!(resp.answers[0].class, RecordClass::IN);
+}
+
+#[test]
+fn responder_suppresses_known_answers() {
+    let mut q = query("host.local", RecordType::A, RecordClass::IN);
+    q.answers.push(a("host.local", [10, 0, 0, 1], 100));
+    assert!(responder().respond(&q, multicast_peer()).is_none());
+    q.answers[0].ttl = 30;
+    assert!(responder().respond(&q, multicast_peer()).is_some());
+}
+
+#[test]
+fn responder_ignores_responses_and_other_names() {
+    let responder = responder();
+    let mut q = query("host.local", RecordType::A, RecordClass::IN);
+    q.header.qr = true;
+    assert!(responder.respond(&q, multicast_peer()).is_none());
+    let q = query("other.local", RecordType::A, RecordClass::IN);
+    assert!(responder.respond(&q, multicast_peer()).is_none());
+}
+
+#[test]
+fn registered_services_answer_browses_with_their_records() {
+    let mut responder = Responder::new(Socket::oneshot().unwrap());
+    let info = ServiceInfo {
+        instance: "My Printer".to_string(),
+        service: "_ipp._tcp".to_string(),
+        host: name("printer.local"),
+        port: 631,
+        txt: vec!["rp=ipp/print".to_string(), "color".to_string()],
+        addrs: vec!["10.0.0.9".parse().unwrap()],
+    };
+    let instance = register_service(&mut responder, &info).unwrap();
+    assert_eq!(instance.labels()[0], b"My Printer");
+    assert_eq!(instance.suffix(3), name("_ipp._tcp.local"));
+
+    let q = query("_ipp._tcp.local", RecordType::PTR, RecordClass::IN);
+    let (resp, _) = responder.respond(&q, multicast_peer()).unwrap();
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("_ipp._tcp.local"),
+            4500,
+            RData::Ptr(instance.clone()),
+        )]
+    );
+    let mut extra: Vec<RecordType> = resp.additional.iter().map(Record::rtype).collect();
+    extra.sort_by_key(|t| t.0);
+    assert_eq!(extra, vec![RecordType::A, RecordType::TXT, RecordType::SRV]);
+
+    let q = query(
+        "_services._dns-sd._udp.local",
+        RecordType::PTR,
+        RecordClass::IN,
+    );
+    let (resp, _) = responder.respond(&q, multicast_peer()).unwrap();
+    assert_eq!(resp.answers[0].rdata, RData::Ptr(name("_ipp._tcp.local")));
+}
+
+#[test]
+fn parses_txt_attributes() {
+    let strings = vec![
+        b"rp=ipp/print".to_vec(),
+        b"color".to_vec(),
+        b"empty=".to_vec(),
+        b"=nokey".to_vec(),
+        Vec::new(),
+    ];
+    assert_eq!(
+        parse_txt(&strings),
+        vec![
+            ("rp".to_string(), Some(b"ipp/print".to_vec())),
+            ("color".to_string(), None),
+            ("empty".to_string(), Some(Vec::new())),
+        ]
+    );
+    assert_eq!(
+        service_name("_http._tcp").unwrap(),
+        name("_http._tcp.local")
+    );
+    assert_eq!(
+        service_name("_http._tcp.local.").unwrap(),
+        name("_http._tcp.local")
+    );
+}
@@ -0,0 +1,249 @@
+//! The sharded message cache of a forwarding route: eviction in the
+//! background down to the entry and byte limits, the clock hand sparing
+//! entries in use, sweeps of what has expired, and consistent accounting
+//! under concurrent use, whatever the number of shards.
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::clock::MockClock;
+use mairudns::message::{Message, Question};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{
+    CachePolicy, CacheUsage, CachedResponse, Forwarder, Handler, Request, Route, Security,
+};
+use mairudns::testing::MockServer;
+
+fn name(prefix: &str, i: usize) -> DomainName {
+    format!("{}{}.example.com.", prefix, i).parse().unwrap()
+}
+
+/// An upstream answering every A query, for 60 seconds if the name starts
+/// with `short` and 300 otherwise.
+fn upstream() -> MockServer {
+    MockServer::builder()
+        .handler(|request: &Request| {
+            let question = request.message.question()?;
+            let ttl = if question.name.to_string().starts_with("short") {
+                60
+            } else {
+                300
+            };
+            let mut resp = request.message.response();
+            resp.answers.push(Record::new(
+                question.name.clone(),
+                ttl,
+                RData::A([192, 0, 2, 1].into()),
+            ));
+            Some(resp)
+        })
+        .start()
+        .unwrap()
+}
+
+fn forwarder(server: &MockServer, policy: CachePolicy) -> Forwarder {
+    let upstream = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    Forwarder::new().route(Route::new(DomainName::root(), upstream).cache_policy(policy))
+}
+
+fn policy(capacity: usize, shards: usize) -> CachePolicy {
+    CachePolicy {
+        capacity,
+        shards,
+        ..CachePolicy::default()
+    }
+}
+
+fn request(qname: DomainName) -> Request {
+    Request {
+        message: Message::query(qname, RecordType::A),
+        src: "192.0.2.1:5300".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+/// Asks `forwarder` for the names `prefix` followed by each of `range`.
+fn fill(forwarder: &Forwarder, prefix: &str, range: std::ops::Range<usize>) {
+    for i in range {
+        forwarder.handle(&request(name(prefix, i))).unwrap();
+    }
+}
+
+fn is_cached(forwarder: &Forwarder, prefix: &str, i: usize) -> bool {
+    forwarder.answer_cached(&request(name(prefix, i))).is_some()
+}
+
+/// Waits for the evictor to bring the usage of the route of `forwarder`
+/// to what `done` accepts.
+fn settle(forwarder: &Forwarder, done: impl Fn(&CacheUsage) -> bool) -> CacheUsage {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let usage = forwarder.routes()[0].cache_usage();
+        if done(&usage) {
+            return usage;
+        }
+        assert!(Instant::now() < deadline, "{:?}", usage);
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn evicts_in_the_background_down_to_the_capacity() {
+    let server = upstream();
+    for shards in [1, 4, 16] {
+        let forwarder = forwarder(&server, policy(100, shards));
+        fill(&forwarder, "host", 0..300);
+        let usage = settle(&forwarder, |u| u.entries <= 100);
+        assert!(usage.evictions >= 200, "{:?}", usage);
+        assert_eq!(forwarder.cache_entries(None).len(), usage.entries);
+        // The latest answers are among those kept.
+        assert!(is_cached(&forwarder, "host", 299));
+    }
+}
+
+#[test]
+fn the_clock_hand_spares_entries_in_use() {
+    let server = upstream();
+    let forwarder = forwarder(&server, policy(64, 1));
+    fill(&forwarder, "host", 0..64);
+    // Entries asked for since the hand last passed them are kept over
+    // those that were not.
+    for i in 64..96 {
+        for hot in 0..8 {
+            assert!(is_cached(&forwarder, "host", hot), "{}", hot);
+        }
+        fill(&forwarder, "host", i..i + 1);
+    }
+    let usage = settle(&forwarder, |u| u.entries <= 64);
+    assert!(usage.evictions >= 32, "{:?}", usage);
+    assert!((0..8).all(|hot| is_cached(&forwarder, "host", hot)));
+}
+
+#[test]
+fn drops_expired_entries_before_evicting() {
+    let server = upstream();
+    let clock = Arc::new(MockClock::new(951_782_400));
+    let upstream = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    let forwarder = Forwarder::new().route(
+        Route::new(DomainName::root(), upstream)
+            .cache_policy(policy(20, 4))
+            .clock(clock.clone()),
+    );
+    fill(&forwarder, "short", 0..15);
+    clock.advance(Duration::from_secs(61));
+    assert_eq!(forwarder.routes()[0].cache_usage().entries, 15);
+    assert!(forwarder.cache_entries(None).is_empty());
+
+    // Going over the capacity wakes the evictor, which finds room enough
+    // in what has expired.
+    fill(&forwarder, "host", 0..10);
+    let usage = settle(&forwarder, |u| u.entries == 10);
+    assert_eq!(usage.evictions, 0);
+    assert!((0..10).all(|i| is_cached(&forwarder, "host", i)));
+}
+
+#[test]
+fn keeps_to_the_byte_limit() {
+    let server = upstream();
+    let one = {
+        let forwarder = forwarder(&server, policy(10, 1));
+        fill(&forwarder, "host", 0..1);
+        forwarder.routes()[0].cache_usage().bytes
+    };
+    assert!(one > 0);
+    let forwarder = forwarder(
+        &server,
+        CachePolicy {
+            max_bytes: one * 50,
+            ..policy(1000, 8)
+        },
+    );
+    fill(&forwarder, "host", 0..200);
+    let usage = settle(&forwarder, |u| u.bytes <= one * 50);
+    assert!(usage.entries <= 50 && usage.entries > 0, "{:?}", usage);
+    assert!(usage.evictions >= 150, "{:?}", usage);
+}
+
+#[test]
+fn accounting_holds_under_concurrent_use() {
+    let server = upstream();
+    for shards in [1, 16] {
+        let forwarder = Arc::new(forwarder(&server, policy(100, shards)));
+        let workers: Vec<_> = (0..4)
+            .map(|w| {
+                let forwarder = forwarder.clone();
+                thread::spawn(move || {
+                    let prefix = format!("w{}-", w);
+                    for i in 0..100 {
+                        fill(&forwarder, &prefix, i..i + 1);
+                        if i % 3 == 0 {
+                            forwarder.flush_name(&name(&prefix, i / 2));
+                        }
+                        is_cached(&forwarder, &prefix, i / 4);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let usage = settle(&forwarder, |u| u.entries <= 100);
+        assert_eq!(forwarder.cache_entries(None).len(), usage.entries);
+        forwarder.flush();
+        assert_eq!(
+            forwarder.routes()[0].cache_usage(),
+            CacheUsage {
+                evictions: usage.evictions,
+                ..CacheUsage::default()
+            }
+        );
+    }
+}
+
+/// A cached answer for `host<i>.example.com`, as a snapshot holds it.
+fn entry(i: usize) -> CachedResponse {
+    let qname = name("host", i);
+    let mut response = Message::query(qname.clone(), RecordType::A).response();
+    response.answers.push(Record::new(
+        qname.clone(),
+        300,
+        RData::A(Ipv4Addr::from(0xc633_0000 | i as u32)),
+    ));
+    CachedResponse {
+        question: Question::new(qname, RecordType::A),
+        dnssec_ok: false,
+        checking_disabled: false,
+        response,
+        security: Security::Unchecked,
+        expires_in: Duration::from_secs(300),
+    }
+}
+
+#[test]
+fn restoring_stops_at_the_capacity() {
+    let server = upstream();
+    let forwarder = forwarder(&server, policy(10, 0));
+    let route = &forwarder.routes()[0];
+    let restored = (0..20).filter(|&i| route.restore(entry(i))).count();
+    assert_eq!(restored, 10);
+    assert_eq!(route.cache_usage().evictions, 0);
+    assert!(is_cached(&forwarder, "host", 0));
+
+    let forwarder = self::forwarder(&server, CachePolicy::disabled());
+    assert!(!forwarder.routes()[0].restore(entry(0)));
+    assert_eq!(forwarder.routes()[0].cache_usage(), CacheUsage::default());
+}
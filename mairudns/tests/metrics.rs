@@ -0,0 +1,300 @@
+//! Metrics: what the instrumented handler, the forwarder and rate
+//! limiting record, the client limit, the Prometheus rendering and its
+//! HTTP endpoint.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::addr::Prefix;
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::metrics::{Metrics, QueryKey, RTT_BUCKETS};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{
+    Authority, Forwarder, Handler, HandlerExt, Instrument, RateLimit, Request, Route, Server,
+    TransferAcl,
+};
+use mairudns::testing::{Action, MockServer};
+use mairudns::zone::Zone;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(owner: &str, qtype: RecordType, src: &str, protocol: Protocol) -> Request {
+    Request {
+        message: Message::query(name(owner), qtype),
+        src: format!("{}:5300", src).parse().unwrap(),
+        protocol,
+        key: None,
+    }
+}
+
+fn nxdomain(request: &Request) -> Option<Message> {
+    let mut resp = request.message.response();
+    resp.header.rcode = Rcode::NXDOMAIN;
+    Some(resp)
+}
+
+fn silent(_: &Request) -> Option<Message> {
+    None
+}
+
+fn key(protocol: Protocol, qtype: RecordType, rcode: Option<Rcode>) -> QueryKey {
+    QueryKey {
+        protocol,
+        qtype,
+        rcode,
+    }
+}
+
+#[test]
+fn counts_requests_by_key_and_client() {
+    let metrics = Arc::new(Metrics::new());
+    let handler =
+        (nxdomain as fn(&Request) -> Option<Message>).with(Instrument::new(metrics.clone()));
+    let dropping =
+        (silent as fn(&Request) -> Option<Message>).with(Instrument::new(metrics.clone()));
+    for _ in 0..3 {
+        handler.handle(&request(
+            "a.test",
+            RecordType::AAAA,
+            "192.0.2.1",
+            Protocol::Udp,
+        ));
+    }
+    handler.handle(&request(
+        "a.test",
+        RecordType::A,
+        "192.0.2.2",
+        Protocol::Tcp,
+    ));
+    dropping.handle(&request(
+        "a.test",
+        RecordType::A,
+        "192.0.2.2",
+        Protocol::Udp,
+    ));
+
+    let snapshot = metrics.snapshot();
+    let aaaa = key(Protocol::Udp, RecordType::AAAA, Some(Rcode::NXDOMAIN));
+    assert_eq!(snapshot.queries[&aaaa], 3);
+    let tcp = key(Protocol::Tcp, RecordType::A, Some(Rcode::NXDOMAIN));
+    assert_eq!(snapshot.queries[&tcp], 1);
+    assert_eq!(
+        snapshot.queries[&key(Protocol::Udp, RecordType::A, None)],
+        1
+    );
+    let client = |s: &str| s.parse::<IpAddr>().unwrap();
+    assert_eq!(snapshot.clients[&client("192.0.2.1")], 3);
+    assert_eq!(snapshot.clients[&client("192.0.2.2")], 2);
+    assert!(snapshot.zones.is_empty());
+
+    metrics.reset();
+    assert_eq!(metrics.snapshot(), Default::default());
+}
+
+#[test]
+fn counts_clients_beyond_the_limit_together() {
+    let metrics = Metrics::with_max_clients(2);
+    let k = key(Protocol::Udp, RecordType::A, Some(Rcode::NOERROR));
+    for src in &[
+        "192.0.2.1",
+        "192.0.2.2",
+        "192.0.2.3",
+        "192.0.2.4",
+        "192.0.2.1",
+    ] {
+        metrics.record_query(k, src.parse().unwrap(), None);
+    }
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.clients.len(), 2);
+    assert_eq!(snapshot.clients[&"192.0.2.1".parse::<IpAddr>().unwrap()], 2);
+    assert_eq!(snapshot.other_clients, 2);
+    assert!(metrics
+        .to_prometheus()
+        .contains("dns_client_queries_total{client=\"other\"} 2\n"));
+}
+
+#[test]
+fn counts_requests_per_zone_and_transfers() {
+    let zone = Zone::from_master(
+        name("example"),
+        "$TTL 300\n@ SOA ns hostmaster 1 3600 600 86400 60\n@ NS ns\nns A 192.0.2.53\n",
+    )
+    .unwrap();
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone);
+    authority.set_transfer_acl(
+        name("example"),
+        TransferAcl {
+            addresses: vec!["192.0.2.0/24".parse::<Prefix>().unwrap()],
+            keys: Vec::new(),
+        },
+    );
+    let metrics = Arc::new(Metrics::new());
+    let handler = authority.clone().with(Instrument {
+        metrics: metrics.clone(),
+        authority: Some(authority),
+    });
+    handler.handle(&request(
+        "ns.example",
+        RecordType::A,
+        "192.0.2.1",
+        Protocol::Udp,
+    ));
+    handler.handle(&request(
+        "www.other",
+        RecordType::A,
+        "192.0.2.1",
+        Protocol::Udp,
+    ));
+    handler.handle(&request(
+        "example",
+        RecordType::AXFR,
+        "192.0.2.1",
+        Protocol::Tcp,
+    ));
+    // Refused transfers are not counted as transfers.
+    let refused = request("example", RecordType::AXFR, "198.51.100.1", Protocol::Tcp);
+    assert_eq!(
+        handler.handle(&refused).unwrap().header.rcode,
+        Rcode::REFUSED
+    );
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.zones[&name("example")], 3);
+    assert_eq!(snapshot.zones.len(), 1);
+    assert_eq!(snapshot.transfers[&(name("example"), RecordType::AXFR)], 1);
+}
+
+#[test]
+fn the_forwarder_records_cache_lookups_and_round_trips() {
+    let upstream = MockServer::builder()
+        .answer(
+            name("www.example"),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example"),
+                300,
+                RData::A([192, 0, 2, 80].into()),
+            )],
+        )
+        .start()
+        .unwrap();
+    let dead = MockServer::builder().then(Action::Drop).start().unwrap();
+    let resolver = |server: &MockServer| {
+        Resolver::new(ResolverConfig {
+            servers: vec![server.addr()],
+            timeout: Duration::from_millis(200),
+            attempts: 1,
+            ..ResolverConfig::default()
+        })
+    };
+    let metrics = Arc::new(Metrics::new());
+    let forwarder = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver(&upstream)))
+        .route(Route::new(name("down.example"), resolver(&dead)))
+        .metrics(metrics.clone());
+    for _ in 0..3 {
+        forwarder.handle(&request(
+            "www.example",
+            RecordType::A,
+            "192.0.2.1",
+            Protocol::Udp,
+        ));
+    }
+    forwarder.handle(&request(
+        "x.down.example",
+        RecordType::A,
+        "192.0.2.1",
+        Protocol::Udp,
+    ));
+
+    let snapshot = metrics.snapshot();
+    // The failed query missed the cache too.
+    assert_eq!((snapshot.cache_hits, snapshot.cache_misses), (2, 2));
+    assert_eq!(snapshot.cache_hit_ratio(), Some(0.5));
+    let rtt = &snapshot.upstream_rtt[&DomainName::root()];
+    assert_eq!(rtt.count(), 1);
+    assert_eq!(rtt.counts.len(), RTT_BUCKETS.len() + 1);
+    assert_eq!(snapshot.upstream_failures[&name("down.example")], 1);
+}
+
+#[test]
+fn rate_limiting_records_drops_and_slips() {
+    let metrics = Arc::new(Metrics::new());
+    let config = RateLimit {
+        slip: 2,
+        metrics: Some(metrics.clone()),
+        ..RateLimit::new(1)
+    };
+    let handler = (nxdomain as fn(&Request) -> Option<Message>).with(config);
+    for _ in 0..5 {
+        handler.handle(&request(
+            "a.test",
+            RecordType::A,
+            "192.0.2.1",
+            Protocol::Udp,
+        ));
+    }
+    let snapshot = metrics.snapshot();
+    assert_eq!(
+        (snapshot.rate_limit_slips, snapshot.rate_limit_drops),
+        (2, 2)
+    );
+}
+
+#[test]
+fn renders_the_prometheus_text_format() {
+    let metrics = Metrics::new();
+    let k = key(Protocol::Tcp, RecordType::MX, None);
+    metrics.record_query(k, "192.0.2.1".parse().unwrap(), Some(&name("ex\"ample")));
+    metrics.record_upstream(&DomainName::root(), Some(Duration::from_millis(3)));
+    metrics.record_upstream(&DomainName::root(), Some(Duration::from_secs(9)));
+    metrics.record_quota_exceeded(true);
+    let text = metrics.to_prometheus();
+    for line in &[
+        "# TYPE dns_queries_total counter",
+        "dns_queries_total{protocol=\"tcp\",qtype=\"MX\",rcode=\"none\"} 1",
+        r#"dns_zone_queries_total{zone="ex\\\"ample."} 1"#,
+        "# TYPE dns_upstream_rtt_seconds histogram",
+        "dns_upstream_rtt_seconds_bucket{route=\".\",le=\"0.0025\"} 0",
+        "dns_upstream_rtt_seconds_bucket{route=\".\",le=\"0.005\"} 1",
+        "dns_upstream_rtt_seconds_bucket{route=\".\",le=\"5\"} 1",
+        "dns_upstream_rtt_seconds_bucket{route=\".\",le=\"+Inf\"} 2",
+        "dns_upstream_rtt_seconds_count{route=\".\"} 2",
+        "dns_cache_lookups_total{result=\"hit\"} 0",
+        "dns_client_quota_exceeded_total{limit=\"concurrency\"} 1",
+    ] {
+        assert!(text.lines().any(|l| l == *line), "missing {}", line);
+    }
+}
+
+#[test]
+fn serves_metrics_over_http() {
+    let metrics = Arc::new(Metrics::new());
+    metrics.record_cache_lookup(true);
+    let mut server = Server::new(nxdomain as fn(&Request) -> Option<Message>);
+    let addr = server
+        .listen_metrics("127.0.0.1:0".parse().unwrap(), metrics)
+        .unwrap();
+    thread::spawn(move || server.run());
+    let get = |request: &[u8]| {
+        let mut c = TcpStream::connect(addr).unwrap();
+        c.write_all(request).unwrap();
+        let mut out = String::new();
+        c.read_to_string(&mut out).unwrap();
+        out
+    };
+    let out = get(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n");
+    assert!(out.starts_with("HTTP/1.1 200"));
+    assert!(out.contains("text/plain"));
+    assert!(out.contains("dns_cache_lookups_total{result=\"hit\"} 1\n"));
+    assert!(get(b"GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+}
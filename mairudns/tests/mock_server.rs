@@ -0,0 +1,229 @@
+//! The scripted mock server: what each action puts on the wire, the order
+//! in which queued actions, fixed answers and the handler are consulted,
+//! and the resolver's retries, rotation and TCP fallback against servers
+//! that misbehave on cue.
+
+#![cfg(feature = "testing")]
+
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::{read_framed, write_framed, Protocol};
+use mairudns::message::{EdnsOption, Message, OptionCode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::Request;
+use mairudns::testing::{Action, MockServer};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn www() -> Vec<Record> {
+    vec![Record::new(
+        name("www.example."),
+        60,
+        RData::A([192, 0, 2, 1].into()),
+    )]
+}
+
+/// Sends `query` over UDP and returns the raw reply, if one comes.
+fn udp(addr: SocketAddr, query: &Message) -> Option<Vec<u8>> {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    socket.send_to(&query.to_wire().unwrap(), addr).unwrap();
+    let mut buf = [0u8; 4096];
+    let n = socket.recv(&mut buf).ok()?;
+    Some(buf[..n].to_vec())
+}
+
+fn query(qname: &str) -> Message {
+    let mut q = Message::query(name(qname), RecordType::A);
+    q.header.id = 0x1234;
+    q
+}
+
+#[test]
+fn actions_put_what_they_say_on_the_wire() {
+    let mut scripted = Message::default();
+    scripted.header.aa = true;
+    scripted.header.rcode = Rcode::NXDOMAIN;
+    scripted.answers = www();
+    scripted.edns = Some(Default::default());
+    scripted.edns.as_mut().unwrap().options = vec![EdnsOption {
+        code: OptionCode::TCP_KEEPALIVE,
+        data: vec![0, 10],
+    }];
+    let server = MockServer::builder()
+        .then(Action::Respond(scripted))
+        .then(Action::Rcode(Rcode::SERVFAIL))
+        .then(Action::Truncate)
+        .then(Action::Malformed)
+        .then(Action::WrongId)
+        .then(Action::Raw(vec![1, 2, 3]))
+        .then(Action::Drop)
+        .then(Action::delay(
+            Duration::from_millis(200),
+            Action::answer(www()),
+        ))
+        .start()
+        .unwrap();
+    let q = query("www.example.");
+    let decoded = |wire: Vec<u8>| Message::from_wire(&wire).unwrap();
+
+    // The query's ID and question, the script's flags and records, and
+    // its EDNS options since the query had EDNS.
+    let resp = decoded(udp(server.addr(), &q).unwrap());
+    assert_eq!(resp.header.id, 0x1234);
+    assert!(resp.header.qr && resp.header.aa);
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    assert_eq!(resp.questions, q.questions);
+    assert_eq!(resp.answers, www());
+    assert_eq!(
+        resp.tcp_keepalive(),
+        Some(Some(Duration::from_millis(1000)))
+    );
+
+    let resp = decoded(udp(server.addr(), &q).unwrap());
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+    assert!(resp.answers.is_empty());
+    let resp = decoded(udp(server.addr(), &q).unwrap());
+    assert!(resp.header.tc);
+    let wire = udp(server.addr(), &q).unwrap();
+    assert_eq!(wire.len(), 12);
+    assert!(Message::from_wire(&wire).is_err());
+    let resp = decoded(udp(server.addr(), &q).unwrap());
+    assert_eq!(resp.header.id, 0x1235);
+    assert_eq!(udp(server.addr(), &q).unwrap(), vec![1, 2, 3]);
+    assert_eq!(udp(server.addr(), &q), None);
+    let start = Instant::now();
+    let resp = decoded(udp(server.addr(), &q).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(resp.answers, www());
+
+    // Every query was recorded, in order, with how it came.
+    let received = server.received();
+    assert_eq!(received.len(), 8);
+    assert!(received.iter().all(|r| r.protocol == Protocol::Udp
+        && r.message.header.id == 0x1234
+        && r.src.ip() == server.addr().ip()));
+    assert_eq!(server.count(Protocol::Tcp), 0);
+}
+
+#[test]
+fn queued_actions_come_before_answers_then_the_handler() {
+    let server = MockServer::builder()
+        .answer(name("www.example."), RecordType::A, www())
+        .then(Action::Rcode(Rcode::SERVFAIL))
+        .handler(|req: &Request| {
+            let mut resp = req.message.response();
+            resp.header.rcode = Rcode::NXDOMAIN;
+            Some(resp)
+        })
+        .start()
+        .unwrap();
+    let rcode = |qname: &str| {
+        let wire = udp(server.addr(), &query(qname)).unwrap();
+        Message::from_wire(&wire).unwrap().header.rcode
+    };
+    assert_eq!(rcode("www.example."), Rcode::SERVFAIL);
+    assert_eq!(rcode("www.example."), Rcode::NOERROR);
+    assert_eq!(rcode("other.example."), Rcode::NXDOMAIN);
+    server.push(Action::Rcode(Rcode::NOTIMP));
+    assert_eq!(rcode("other.example."), Rcode::NOTIMP);
+    assert_eq!(rcode("www.example."), Rcode::NOERROR);
+
+    // Without a handler, what nothing takes is refused.
+    let bare = MockServer::builder().start().unwrap();
+    let wire = udp(bare.addr(), &query("www.example.")).unwrap();
+    assert_eq!(
+        Message::from_wire(&wire).unwrap().header.rcode,
+        Rcode::REFUSED
+    );
+}
+
+#[test]
+fn tcp_shares_the_script_and_drop_closes_the_connection() {
+    let server = MockServer::builder()
+        .then(Action::answer(www()))
+        .then(Action::Drop)
+        .start()
+        .unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let q = query("www.example.");
+    write_framed(&mut stream, &q.to_wire().unwrap()).unwrap();
+    let resp = Message::from_wire(&read_framed(&mut stream).unwrap()).unwrap();
+    assert_eq!(resp.answers, www());
+    write_framed(&mut stream, &q.to_wire().unwrap()).unwrap();
+    assert!(read_framed(&mut stream).is_err());
+    assert_eq!(server.count(Protocol::Tcp), 2);
+
+    // Dropping the server stops it on both transports.
+    let addr = server.addr();
+    drop(server);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(udp(addr, &q), None);
+    let refused = TcpStream::connect(addr).map(|mut s| {
+        write_framed(&mut s, &q.to_wire().unwrap()).ok();
+        s.set_read_timeout(Some(Duration::from_millis(300)))
+            .unwrap();
+        read_framed(&mut s)
+    });
+    assert!(!matches!(refused, Ok(Ok(_))));
+}
+
+#[test]
+fn resolvers_move_past_servers_that_misbehave() {
+    let bad = MockServer::builder()
+        .then(Action::Drop)
+        .then(Action::Malformed)
+        .then(Action::WrongId)
+        .then(Action::Rcode(Rcode::FORMERR))
+        .start()
+        .unwrap();
+    let good = MockServer::builder()
+        .then(Action::Truncate)
+        .answer(name("www.example."), RecordType::A, www())
+        .start()
+        .unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![bad.addr(), good.addr()],
+        timeout: Duration::from_millis(200),
+        ..ResolverConfig::default()
+    });
+
+    // The first server times out; the second truncates, so the answer
+    // comes over TCP.
+    let answers = resolver
+        .lookup(&name("www.example."), RecordType::A)
+        .unwrap();
+    assert_eq!(answers, www());
+    assert_eq!(bad.count(Protocol::Udp), 1);
+    assert_eq!(good.count(Protocol::Udp), 1);
+    assert_eq!(good.count(Protocol::Tcp), 1);
+
+    // The other failures each cost the bad server one query.
+    for _ in 0..3 {
+        let _ = resolver.query(&name("www.example."), RecordType::A);
+    }
+    assert_eq!(bad.count(Protocol::Udp), 4);
+
+    // A slow answer is waited for within the timeout.
+    bad.push(Action::delay(
+        Duration::from_millis(100),
+        Action::answer(www()),
+    ));
+    let start = Instant::now();
+    let resp = resolver
+        .query(&name("www.example."), RecordType::A)
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(resp.answers, www());
+}
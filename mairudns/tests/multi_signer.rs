@@ -0,0 +1,319 @@
+//! Zones signed by several providers (RFC 8901), which publish each
+//! other's keys but sign only with their own, and algorithm rollovers,
+//! which sign with the new algorithm before publishing its keys.
+
+#![cfg(feature = "dnssec")]
+
+use std::fs;
+use std::time::Duration;
+
+use mairudns::dnssec::{
+    cds_records, foreign_keys, sign_zone, Algorithm, DigestType, Dnskey, Ds, KeyEvent, KeyPolicy,
+    KeyStore, OnlineSigner, ParentDs, Rrsig, SignError, SigningKey, SigningPolicy,
+};
+use mairudns::name::DomainName;
+use mairudns::rr::{Record, RecordType};
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn zone() -> Zone {
+    Zone::from_master(name("example."), ZONE).unwrap()
+}
+
+/// One provider's key-signing and zone-signing keys.
+fn provider(seed: u8) -> Vec<SigningKey> {
+    vec![
+        SigningKey::ed25519(257, &[seed; 32]).unwrap(),
+        SigningKey::ed25519(256, &[seed + 1; 32]).unwrap(),
+    ]
+}
+
+fn dnskeys(zone: &Zone) -> Vec<Dnskey> {
+    zone.rrset(&name("example."), RecordType::DNSKEY)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|rr| Dnskey::from_rdata(&rr.rdata))
+        .collect()
+}
+
+/// The key tags and algorithms signing `rtype` at `owner`.
+fn signers(zone: &Zone, owner: &str, rtype: RecordType) -> Vec<(u16, Algorithm)> {
+    let mut signers: Vec<(u16, Algorithm)> = zone
+        .rrset(&name(owner), RecordType::RRSIG)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|rr| Rrsig::from_rdata(&rr.rdata))
+        .filter(|sig| sig.type_covered == rtype)
+        .map(|sig| (sig.key_tag, sig.algorithm))
+        .collect();
+    signers.sort();
+    signers
+}
+
+fn tags(keys: &[SigningKey]) -> Vec<(u16, Algorithm)> {
+    let mut tags: Vec<(u16, Algorithm)> =
+        keys.iter().map(|k| (k.key_tag(), k.algorithm())).collect();
+    tags.sort();
+    tags
+}
+
+/// The other provider's key set, as it publishes it: its keys, and ours.
+fn their_key_set(theirs: &[SigningKey], ours: &[SigningKey]) -> Vec<Record> {
+    theirs
+        .iter()
+        .chain(ours)
+        .map(|k| Record::new(name("example."), 3600, k.dnskey().to_rdata()))
+        .collect()
+}
+
+#[test]
+fn foreign_keys_are_the_other_signers_own() {
+    let (ours, theirs) = (provider(1), provider(3));
+    let local: Vec<Dnskey> = ours.iter().map(|k| k.dnskey().clone()).collect();
+    let mut records = their_key_set(&theirs, &ours);
+    // Duplicates and keys that are not zone keys are left out.
+    records.push(records[0].clone());
+    let mut not_a_zone_key = theirs[0].dnskey().clone();
+    not_a_zone_key.flags = 0;
+    records.push(Record::new(
+        name("example."),
+        3600,
+        not_a_zone_key.to_rdata(),
+    ));
+    let foreign = foreign_keys(&records, &local);
+    let expected: Vec<Dnskey> = theirs.iter().map(|k| k.dnskey().clone()).collect();
+    assert_eq!(foreign, expected);
+}
+
+#[test]
+fn each_provider_publishes_all_keys_and_signs_with_its_own() {
+    let (ours, theirs) = (provider(1), provider(3));
+    let local: Vec<Dnskey> = ours.iter().map(|k| k.dnskey().clone()).collect();
+    let policy = SigningPolicy {
+        foreign: foreign_keys(&their_key_set(&theirs, &ours), &local),
+        ..SigningPolicy::default()
+    };
+    let signed = sign_zone(&zone(), &ours, &policy).unwrap();
+
+    let mut published = dnskeys(&signed);
+    published.sort_by_key(Dnskey::key_tag);
+    let mut all: Vec<Dnskey> = ours
+        .iter()
+        .chain(&theirs)
+        .map(|k| k.dnskey().clone())
+        .collect();
+    all.sort_by_key(Dnskey::key_tag);
+    assert_eq!(published, all);
+    // Only our keys sign, the key set included.
+    assert_eq!(
+        signers(&signed, "www.example.", RecordType::A),
+        tags(&ours[1..])
+    );
+    assert_eq!(
+        signers(&signed, "example.", RecordType::DNSKEY),
+        tags(&ours[..1])
+    );
+    // The parent is asked to list both providers' key-signing keys.
+    let cds: Vec<Ds> = signed
+        .rrset(&name("example."), RecordType::CDS)
+        .unwrap()
+        .iter()
+        .filter_map(|rr| Ds::from_rdata(&rr.rdata))
+        .collect();
+    let expected: Vec<Ds> = cds_records(
+        &name("example."),
+        &[ours[0].dnskey().clone(), theirs[0].dnskey().clone()],
+        &[DigestType::SHA256],
+        3600,
+    )
+    .iter()
+    .filter_map(|rr| Ds::from_rdata(&rr.rdata))
+    .collect();
+    assert_eq!(cds.len(), 2);
+    assert!(expected.iter().all(|ds| cds.contains(ds)));
+
+    // The other provider's copy, signed the same way, has the same key set.
+    let local: Vec<Dnskey> = theirs.iter().map(|k| k.dnskey().clone()).collect();
+    let policy = SigningPolicy {
+        foreign: foreign_keys(&their_key_set(&ours, &theirs), &local),
+        ..SigningPolicy::default()
+    };
+    let other = sign_zone(&zone(), &theirs, &policy).unwrap();
+    let mut their_published = dnskeys(&other);
+    their_published.sort_by_key(Dnskey::key_tag);
+    assert_eq!(their_published, published);
+}
+
+#[test]
+fn foreign_algorithms_must_be_signed_locally() {
+    let ours = provider(1);
+    let theirs = SigningKey::ecdsa(257, Algorithm::ECDSAP256SHA256, &[5; 32]).unwrap();
+    let policy = SigningPolicy {
+        foreign: vec![theirs.dnskey().clone()],
+        ..SigningPolicy::default()
+    };
+    let err = sign_zone(&zone(), &ours, &policy).unwrap_err();
+    assert!(matches!(
+        err,
+        SignError::UnsignedAlgorithm(Algorithm::ECDSAP256SHA256)
+    ));
+    assert_eq!(
+        err.to_string(),
+        "no local key signs with the foreign keys' algorithm ECDSAP256SHA256"
+    );
+    let mut zone = zone();
+    let online = OnlineSigner::new(ours).policy(policy);
+    assert!(matches!(
+        online.publish(&mut zone),
+        Err(SignError::UnsignedAlgorithm(_))
+    ));
+}
+
+/// A key store for `example.` in a directory of its own.
+fn store(test: &str) -> (KeyStore, std::path::PathBuf) {
+    let dir = std::env::temp_dir().join(format!("mairu-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    (KeyStore::new(name("example."), &dir), dir)
+}
+
+fn signed_at(store: &KeyStore, now: u32) -> Zone {
+    let policy = SigningPolicy {
+        now: Some(now),
+        jitter: Duration::ZERO,
+        ..SigningPolicy::default()
+    };
+    store.sign(&zone(), &policy).unwrap()
+}
+
+fn algorithms(signers: &[(u16, Algorithm)]) -> Vec<Algorithm> {
+    let mut algorithms: Vec<Algorithm> = signers.iter().map(|&(_, a)| a).collect();
+    algorithms.sort();
+    algorithms.dedup();
+    algorithms
+}
+
+const DAY: u32 = 86400;
+
+#[test]
+fn algorithm_rollovers_sign_before_they_publish() {
+    let (mut store, dir) = store("algorithm-roll");
+    let old = KeyPolicy {
+        algorithm: Algorithm::ED25519,
+        ksk_lifetime: None,
+        zsk_lifetime: None,
+        ..KeyPolicy::default()
+    };
+    let new = KeyPolicy {
+        algorithm: Algorithm::ECDSAP256SHA256,
+        ..old.clone()
+    };
+    let t0 = 1_000_000;
+    assert_eq!(store.maintain(&old, t0).unwrap().len(), 2);
+
+    // The new algorithm's keys sign everything at once...
+    let t1 = t0 + DAY;
+    let events = store.maintain(&new, t1).unwrap();
+    assert!(
+        matches!(
+            events[..],
+            [KeyEvent::Generated(_, t), KeyEvent::Generated(_, u)] if t == t1 && u == t1
+        ),
+        "{:?}",
+        events
+    );
+    let zone = signed_at(&store, t1);
+    let both = [Algorithm::ECDSAP256SHA256, Algorithm::ED25519];
+    assert_eq!(
+        algorithms(&signers(&zone, "www.example.", RecordType::A)),
+        both
+    );
+    // ...but are published only once those signatures are in caches.
+    assert!(dnskeys(&zone)
+        .iter()
+        .all(|k| k.algorithm == Algorithm::ED25519));
+    assert_eq!(store.withheld_keys(t1).len(), 2);
+    assert!(store.maintain(&new, t1 + DAY - 1).unwrap().is_empty());
+
+    // Then the old keys go, together, after `ds_safety`.
+    let t2 = t1 + DAY;
+    let zone = signed_at(&store, t2);
+    assert_eq!(dnskeys(&zone).len(), 4);
+    let events = store.maintain(&new, t2).unwrap();
+    let retire = t2 + 7 * DAY;
+    assert_eq!(events.len(), 2);
+    assert!(events
+        .iter()
+        .all(|e| matches!(e, KeyEvent::Retired(_, t) if *t == retire)));
+    let zone = signed_at(&store, retire - 1);
+    assert_eq!(dnskeys(&zone).len(), 4);
+
+    let events = store.maintain(&new, retire).unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(events.iter().all(|e| matches!(e, KeyEvent::Removed(_))));
+    let zone = signed_at(&store, retire);
+    assert!(dnskeys(&zone)
+        .iter()
+        .all(|k| k.algorithm == Algorithm::ECDSAP256SHA256));
+    assert_eq!(
+        algorithms(&signers(&zone, "www.example.", RecordType::A)),
+        [Algorithm::ECDSAP256SHA256]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn algorithm_rollovers_can_wait_for_the_parent() {
+    let (mut store, dir) = store("algorithm-ds");
+    let old = KeyPolicy {
+        algorithm: Algorithm::ED25519,
+        ksk_lifetime: None,
+        zsk_lifetime: None,
+        wait_for_ds: true,
+        ..KeyPolicy::default()
+    };
+    let new = KeyPolicy {
+        algorithm: Algorithm::ECDSAP256SHA256,
+        ..old.clone()
+    };
+    let t0 = 1_000_000;
+    store.maintain(&old, t0).unwrap();
+    store.maintain(&new, t0).unwrap();
+    let t1 = t0 + DAY;
+    // Published, but the parent still lists the old key.
+    assert!(store.maintain(&new, t1).unwrap().is_empty());
+    let ksk = |algorithm: Algorithm| {
+        store
+            .keys()
+            .iter()
+            .map(|k| k.key().dnskey().clone())
+            .find(|k| k.flags == 257 && k.algorithm == algorithm)
+            .unwrap()
+    };
+    let parent = |key: Dnskey| {
+        ParentDs::Agreed(vec![key.ds(&name("example."), DigestType::SHA256).unwrap()])
+    };
+    let (old_ksk, new_ksk) = (ksk(Algorithm::ED25519), ksk(Algorithm::ECDSAP256SHA256));
+    assert!(store
+        .confirm_ds(&parent(old_ksk), &new, t1)
+        .unwrap()
+        .is_empty());
+    // Once it lists only the new one, every old key goes.
+    let events = store.confirm_ds(&parent(new_ksk), &new, t1).unwrap();
+    assert_eq!(events.len(), 2, "{:?}", events);
+    assert!(events
+        .iter()
+        .all(|e| matches!(e, KeyEvent::Retired(_, t) if *t == t1 + 7 * DAY)));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,273 @@
+//! Signing answers as they are given: signatures for those who ask with
+//! the DO bit, NSEC white lies that cover only the name asked about,
+//! changes to the zone signed as soon as they are made, and answers a
+//! validator accepts.
+
+#![cfg(feature = "dnssec")]
+
+use std::sync::Arc;
+
+use mairudns::client::Protocol;
+use mairudns::dnssec::{
+    Algorithm, AnchorKey, Dnskey, Nsec, OnlineSigner, Rrsig, SigningKey, Status, TrustAnchors,
+    Validator,
+};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Authority, Handler, Request, SharedZone};
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+a.b.c IN TXT \"deep\"
+*.wild IN A 192.0.2.99
+sub IN NS ns.sub
+ns.sub IN A 192.0.2.54
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// A key-signing key and a zone-signing key, whose ECDSA signatures
+/// differ each time they are made.
+fn keys() -> Vec<SigningKey> {
+    vec![
+        SigningKey::ecdsa(257, Algorithm::ECDSAP256SHA256, &[7; 32]).unwrap(),
+        SigningKey::ecdsa(256, Algorithm::ECDSAP256SHA256, &[9; 32]).unwrap(),
+    ]
+}
+
+fn authority(keys: Vec<SigningKey>) -> (Arc<Authority>, SharedZone) {
+    let authority = Arc::new(Authority::new());
+    authority
+        .set_signer(name("example."), Arc::new(OnlineSigner::new(keys)))
+        .unwrap();
+    let zone = authority.insert(Zone::from_master(name("example."), ZONE).unwrap());
+    (authority, zone)
+}
+
+fn ask(authority: &Authority, qname: &str, qtype: RecordType, dnssec_ok: bool) -> Message {
+    let mut message = Message::query(name(qname), qtype);
+    message.edns.as_mut().unwrap().dnssec_ok = dnssec_ok;
+    let request = Request {
+        message,
+        src: "192.0.2.1:5300".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    };
+    authority.handle(&request).unwrap()
+}
+
+fn sigs(section: &[Record]) -> Vec<(DomainName, Rrsig)> {
+    section
+        .iter()
+        .filter_map(|rr| Some((rr.name.clone(), Rrsig::from_rdata(&rr.rdata)?)))
+        .collect()
+}
+
+fn nsecs(section: &[Record]) -> Vec<(DomainName, Nsec)> {
+    section
+        .iter()
+        .filter_map(|rr| Some((rr.name.clone(), Nsec::from_rdata(&rr.rdata)?)))
+        .collect()
+}
+
+/// Whether `name` lies strictly between `owner` and `next` in canonical
+/// order.
+fn covers(owner: &DomainName, next: &DomainName, name: &DomainName) -> bool {
+    owner < name && name < next
+}
+
+#[test]
+fn signs_only_for_those_who_ask() {
+    let keys = keys();
+    let (authority, zone) = authority(keys.clone());
+    // The keys are published in the zone.
+    let published: Vec<Dnskey> = zone
+        .read()
+        .unwrap()
+        .rrset(&name("example."), RecordType::DNSKEY)
+        .unwrap()
+        .iter()
+        .filter_map(|rr| Dnskey::from_rdata(&rr.rdata))
+        .collect();
+    assert!(keys.iter().all(|k| published.contains(k.dnskey())));
+
+    let plain = ask(&authority, "www.example.", RecordType::A, false);
+    assert!(sigs(&plain.answers).is_empty());
+    let signed = ask(&authority, "www.example.", RecordType::A, true);
+    let answer_sigs = sigs(&signed.answers);
+    assert_eq!(answer_sigs.len(), 1);
+    let (owner, sig) = &answer_sigs[0];
+    assert_eq!(*owner, name("www.example."));
+    assert_eq!(
+        (sig.type_covered, sig.key_tag, sig.labels),
+        (RecordType::A, keys[1].key_tag(), 2)
+    );
+    // The key set is signed by the key-signing key.
+    let dnskey = ask(&authority, "example.", RecordType::DNSKEY, true);
+    assert!(sigs(&dnskey.answers)
+        .iter()
+        .all(|(_, sig)| sig.key_tag == keys[0].key_tag()));
+
+    // Signatures are kept and given again rather than made anew.
+    let again = ask(&authority, "www.example.", RecordType::A, true);
+    assert_eq!(sigs(&again.answers), answer_sigs);
+}
+
+#[test]
+fn white_lies_cover_only_the_names_denied() {
+    let (authority, zone) = authority(keys());
+    let names: Vec<DomainName> = zone
+        .read()
+        .unwrap()
+        .records()
+        .map(|rr| rr.name.clone())
+        .collect();
+    for qname in [
+        "nope.example.",
+        "a.nope.example.",
+        "www0.example.",
+        "-.example.",
+    ] {
+        let resp = ask(&authority, qname, RecordType::A, true);
+        assert_eq!(resp.header.rcode, Rcode::NXDOMAIN, "{}", qname);
+        let denial = nsecs(&resp.authority);
+        assert!(!denial.is_empty());
+        // The name asked about is covered, and nothing in the zone is.
+        assert!(denial
+            .iter()
+            .any(|(owner, nsec)| covers(owner, &nsec.next, &name(qname))));
+        for (owner, nsec) in &denial {
+            assert!(
+                names.iter().all(|n| !covers(owner, &nsec.next, n)),
+                "{} {} {}",
+                qname,
+                owner,
+                nsec
+            );
+        }
+        // Every NSEC is signed.
+        let signed = sigs(&resp.authority);
+        assert!(denial.iter().all(|(owner, _)| signed
+            .iter()
+            .any(|(at, sig)| at == owner && sig.type_covered == RecordType::NSEC)));
+    }
+}
+
+#[test]
+fn no_data_lists_the_types_that_are_there() {
+    let (authority, _) = authority(keys());
+    let resp = ask(&authority, "www.example.", RecordType::TXT, true);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+    let denial = nsecs(&resp.authority);
+    let (owner, nsec) = denial
+        .iter()
+        .find(|(owner, _)| *owner == name("www.example."))
+        .unwrap();
+    assert!(nsec.has(RecordType::A) && !nsec.has(RecordType::TXT));
+    // It covers nothing below the name either.
+    assert_eq!(nsec.next, name("\\000.www.example."));
+    assert!(!covers(owner, &nsec.next, &name("a.www.example.")));
+
+    // An empty non-terminal has no types at all.
+    let resp = ask(&authority, "b.c.example.", RecordType::A, true);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    let denial = nsecs(&resp.authority);
+    let (_, nsec) = denial
+        .iter()
+        .find(|(owner, _)| *owner == name("b.c.example."))
+        .unwrap();
+    assert!(!nsec.has(RecordType::A) && !nsec.has(RecordType::TXT));
+
+    // The parent side of an unsigned delegation.
+    let resp = ask(&authority, "sub.example.", RecordType::DS, true);
+    let denial = nsecs(&resp.authority);
+    let (_, nsec) = denial
+        .iter()
+        .find(|(owner, _)| *owner == name("sub.example."))
+        .unwrap();
+    assert!(nsec.has(RecordType::NS) && !nsec.has(RecordType::DS));
+}
+
+#[test]
+fn wildcard_expansions_are_signed_as_the_wildcard() {
+    let (authority, _) = authority(keys());
+    let resp = ask(&authority, "x.y.wild.example.", RecordType::A, true);
+    assert_eq!(resp.answers[0].name, name("x.y.wild.example."));
+    let (_, sig) = &sigs(&resp.answers)[0];
+    assert_eq!(sig.labels, 2);
+    // With proof that the name itself does not exist.
+    assert!(nsecs(&resp.authority).iter().any(|(owner, nsec)| covers(
+        owner,
+        &nsec.next,
+        &name("y.wild.example.")
+    )));
+}
+
+#[test]
+fn a_validator_accepts_the_answers_and_changes_to_the_zone() {
+    let keys = keys();
+    let anchor = keys[0].dnskey().clone();
+    let (authority, zone) = authority(keys);
+    let server = MockServer::builder()
+        .handler(authority.clone())
+        .start()
+        .unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    let mut anchors = TrustAnchors::new();
+    anchors.add(name("example."), AnchorKey::Dnskey(anchor));
+    let validator = Validator::new(resolver).anchors(anchors);
+    let check = |qname: &str, qtype: RecordType| {
+        let v = validator.query(&name(qname), qtype).unwrap();
+        assert_eq!(
+            (v.status, v.reason),
+            (Status::Secure, None),
+            "{} {}",
+            qname,
+            qtype
+        );
+    };
+    for (qname, qtype) in [
+        ("www.example.", RecordType::A),
+        ("example.", RecordType::DNSKEY),
+        ("example.", RecordType::CDS),
+        ("a.b.c.example.", RecordType::TXT),
+        ("sub.example.", RecordType::DS),
+        ("nope.example.", RecordType::A),
+        ("a.zzz.example.", RecordType::A),
+        ("www.example.", RecordType::AAAA),
+        ("b.c.example.", RecordType::A),
+        ("x.wild.example.", RecordType::A),
+        ("x.wild.example.", RecordType::TXT),
+        ("wild.example.", RecordType::A),
+        ("WwW[.example.", RecordType::A),
+        ("\\000.www.example.", RecordType::A),
+    ] {
+        check(qname, qtype);
+    }
+
+    // A record added as an update or the API would add it.
+    zone.write()
+        .unwrap()
+        .insert(Record::new(
+            name("new.example."),
+            60,
+            RData::A([192, 0, 2, 1].into()),
+        ))
+        .unwrap();
+    check("new.example.", RecordType::A);
+    check("new.example.", RecordType::TXT);
+}
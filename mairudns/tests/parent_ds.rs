@@ -0,0 +1,216 @@
+//! Keeping the parent's DS records in step: DS, CDS and CDNSKEY records
+//! from a zone's keys, the records that ask for the DS records' removal,
+//! a check of what the parent's servers publish, and key-signing key
+//! rollovers that wait for it.
+
+#![cfg(feature = "dnssec")]
+
+use std::fs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mairudns::dnssec::{
+    cdnskey_records, cds_records, delete_records, ds_records, Algorithm, DigestType, Dnskey, Ds,
+    DsChecker, KeyEvent, KeyPolicy, KeyStore, ParentDs, SigningKey,
+};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{Record, RecordType};
+use mairudns::server::Authority;
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// The root zone's key-signing key of 2017, and its DS record as IANA
+/// publishes it.
+const ROOT_KSK: &str = "257 3 8 AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=";
+const ROOT_DS: &str = "20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D";
+
+fn keys() -> (SigningKey, SigningKey) {
+    (
+        SigningKey::ed25519(257, &[7; 32]).unwrap(),
+        SigningKey::ed25519(256, &[8; 32]).unwrap(),
+    )
+}
+
+fn ds_of(records: &[Record]) -> Vec<Ds> {
+    records
+        .iter()
+        .filter_map(|rr| Ds::from_rdata(&rr.rdata))
+        .collect()
+}
+
+#[test]
+fn ds_records_are_made_from_key_signing_keys() {
+    let root: Dnskey = ROOT_KSK.parse().unwrap();
+    assert_eq!(root.key_tag(), 20326);
+    let ds = ds_records(
+        &name("."),
+        std::slice::from_ref(&root),
+        &[DigestType::SHA256],
+        86400,
+    );
+    assert_eq!(ds.len(), 1);
+    assert_eq!(ds_of(&ds)[0].to_string(), ROOT_DS);
+    assert!(ds_of(&ds)[0].matches(&name("."), &root));
+    assert!(!ds_of(&ds)[0].matches(&name("example."), &root));
+
+    let child = name("child.example.");
+    let (ksk, zsk) = keys();
+    let keys = vec![ksk.dnskey().clone(), zsk.dnskey().clone()];
+    let ds = ds_records(
+        &child,
+        &keys,
+        &[DigestType::SHA256, DigestType::SHA384],
+        3600,
+    );
+    // One of each digest type, for the key-signing key only.
+    let digests: Vec<(u16, u8, usize)> = ds_of(&ds)
+        .iter()
+        .map(|ds| (ds.key_tag, ds.digest_type.0, ds.digest.len()))
+        .collect();
+    assert_eq!(digests, [(ksk.key_tag(), 2, 32), (ksk.key_tag(), 4, 48)]);
+    assert!(ds.iter().all(|rr| rr.name == child && rr.ttl == 3600));
+
+    // A revoked key has no DS record.
+    let mut revoked = ksk.dnskey().clone();
+    revoked.flags |= 0x80;
+    assert!(ds_records(&child, &[revoked], &[DigestType::SHA256], 0).is_empty());
+
+    let cds = cds_records(&child, &keys, &[DigestType::SHA256], 3600);
+    assert_eq!(cds.len(), 1);
+    assert_eq!(cds[0].rtype(), RecordType::CDS);
+    assert_eq!(ds_of(&cds), ds_of(&ds[..1]));
+    let cdnskey = cdnskey_records(&child, &keys, 3600);
+    assert_eq!(cdnskey.len(), 1);
+    assert_eq!(cdnskey[0].rtype(), RecordType::CDNSKEY);
+    assert_eq!(
+        Dnskey::from_rdata(&cdnskey[0].rdata).as_ref(),
+        Some(ksk.dnskey())
+    );
+}
+
+// RFC 8078 §4: "CDS 0 0 0 00" and "CDNSKEY 0 3 0 AA==".
+#[test]
+fn delete_records_ask_for_no_ds_at_all() {
+    let records = delete_records(&name("child.example."), 0);
+    assert_eq!(records[0].rtype(), RecordType::CDS);
+    let cds = Ds::from_rdata(&records[0].rdata).unwrap();
+    assert_eq!(cds.to_string(), "0 0 0 00");
+    assert_eq!(records[1].rtype(), RecordType::CDNSKEY);
+    let cdnskey = Dnskey::from_rdata(&records[1].rdata).unwrap();
+    assert_eq!(cdnskey.to_string(), "0 3 0 AA==");
+    assert_eq!(records.len(), 2);
+}
+
+/// A server for `example.` whose delegation of `child` carries `ds`.
+fn parent_server(ds: &[Record]) -> (MockServer, SocketAddr) {
+    let mut text = String::from(
+        "$TTL 300\n@ SOA ns hostmaster 1 3600 600 86400 300\n@ NS ns\nns A 192.0.2.53\n\
+         child NS ns.child\nns.child A 192.0.2.54\n",
+    );
+    for rr in ds {
+        text.push_str(&format!("child DS {}\n", rr.rdata));
+    }
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example."), &text).unwrap());
+    let server = MockServer::builder().handler(authority).start().unwrap();
+    let addr = server.addr();
+    (server, addr)
+}
+
+#[test]
+fn the_checker_asks_every_parent_server() {
+    let child = name("child.example.");
+    let (ksk, zsk) = keys();
+    let keys = vec![ksk.dnskey().clone(), zsk.dnskey().clone()];
+    let ds = ds_records(&child, &keys, &[DigestType::SHA256], 3600);
+    let (_a, a) = parent_server(&ds);
+    let (_b, b) = parent_server(&ds);
+    let (_c, c) = parent_server(&[]);
+    let checker =
+        |servers| DsChecker::new(Resolver::new(ResolverConfig::default())).servers(servers);
+
+    let agreed = checker(vec![a, b]).check(&child).unwrap();
+    assert_eq!(agreed, ParentDs::Agreed(ds_of(&ds)));
+    assert!(agreed.matches(&child, &keys));
+    let other = SigningKey::ed25519(257, &[9; 32]).unwrap();
+    assert!(!agreed.matches(&child, &[other.dnskey().clone()]));
+    // Every key-signing key needs its DS record, and no other may have one.
+    assert!(!agreed.matches(&child, &[keys[0].clone(), other.dnskey().clone()]));
+
+    let missing = checker(vec![c]).check(&child).unwrap();
+    assert_eq!(missing, ParentDs::Agreed(Vec::new()));
+    assert!(!missing.matches(&child, &keys));
+    let split = checker(vec![a, c]).check(&child).unwrap();
+    assert_eq!(split, ParentDs::Disagreed);
+    assert!(!split.matches(&child, &keys));
+}
+
+#[test]
+fn a_rollover_waits_for_the_parent() {
+    let child = name("child.example.");
+    let dir = std::env::temp_dir().join(format!("mairu-parent-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let mut store = KeyStore::new(child.clone(), &dir);
+    let policy = KeyPolicy {
+        algorithm: Algorithm::ED25519,
+        wait_for_ds: true,
+        ksk_lifetime: Some(Duration::from_secs(1000)),
+        zsk_lifetime: None,
+        ..KeyPolicy::default()
+    };
+    store.maintain(&policy, 1000).unwrap();
+    // The successor signs alongside the old key, which is not retired.
+    let events = store.maintain(&policy, 2500).unwrap();
+    assert!(
+        matches!(events[..], [KeyEvent::Generated(_, 2500)]),
+        "{:?}",
+        events
+    );
+    let ksks: Vec<Dnskey> = store
+        .keys()
+        .iter()
+        .filter(|k| k.key().is_ksk())
+        .map(|k| k.key().dnskey().clone())
+        .collect();
+    assert_eq!(ksks.len(), 2);
+    let (old, new) = (&ksks[0], &ksks[1]);
+    let parent = |key: &Dnskey| {
+        ParentDs::Agreed(ds_of(&ds_records(
+            &child,
+            std::slice::from_ref(key),
+            &[DigestType::SHA256],
+            0,
+        )))
+    };
+
+    // Nothing happens while the parent has the old DS record, or both.
+    assert!(store
+        .confirm_ds(&parent(old), &policy, 3000)
+        .unwrap()
+        .is_empty());
+    let both = ParentDs::Agreed(ds_of(&ds_records(&child, &ksks, &[DigestType::SHA256], 0)));
+    assert!(store.confirm_ds(&both, &policy, 3000).unwrap().is_empty());
+    assert!(store.maintain(&policy, 3100).unwrap().is_empty());
+
+    // Once it has only the new one, the old key goes after `ds_safety`.
+    let events = store.confirm_ds(&parent(new), &policy, 3000).unwrap();
+    assert_eq!(events, [KeyEvent::Retired(old.key_tag(), 3000 + 7 * 86400)]);
+    let mut again = KeyStore::new(child.clone(), &dir);
+    again.load().unwrap();
+    let timing = again
+        .keys()
+        .iter()
+        .find(|k| k.key().dnskey() == old)
+        .unwrap()
+        .timing();
+    assert_eq!(timing.inactive, Some(3000 + 7 * 86400));
+    assert!(store.maintain(&policy, 3100).unwrap().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,217 @@
+//! Where parse errors say they are: byte indexes into names and
+//! addresses, lines and columns in zone files, and byte offsets into
+//! messages, read whole or in place.
+
+use mairudns::addr::{self, IpSet, Prefix};
+use mairudns::arena::MessageRef;
+use mairudns::message::{EdnsOption, Message, OptionCode};
+use mairudns::name::{self, DomainName};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::wire::Error;
+use mairudns::zone::{parse_records, ParseErrorKind};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn parse_name(s: &str) -> name::Error {
+    s.parse::<DomainName>().unwrap_err()
+}
+
+#[test]
+fn names_point_at_the_byte_at_fault() {
+    assert_eq!(
+        parse_name("ab.c d.e"),
+        name::Error::IllegalChar { index: 4, ch: ' ' }
+    );
+    assert_eq!(
+        parse_name("é.example"),
+        name::Error::IllegalChar { index: 0, ch: 'é' }
+    );
+    assert_eq!(parse_name("a..b"), name::Error::EmptyLabel { index: 2 });
+    assert_eq!(parse_name("a.\\25"), name::Error::BadEscape { index: 2 });
+
+    let long = "x".repeat(63);
+    assert_eq!(
+        parse_name(&format!("a.{}x.b", long)),
+        name::Error::LabelTooLong { index: 2, len: 64 }
+    );
+    let error = parse_name(&format!("{0}.{0}.{0}.{0}.c", long));
+    assert_eq!(error, name::Error::NameTooLong { index: 192 });
+    assert_eq!(error.index(), 192);
+    assert_eq!(
+        error.to_string(),
+        "name longer than 255 bytes from byte 192"
+    );
+
+    // The origin's labels are where the text ends.
+    let origin = name(&format!("{0}.{0}.{0}", long));
+    let text = format!("ab.{}", long);
+    assert_eq!(
+        DomainName::parse_relative(&text, &origin).unwrap_err(),
+        name::Error::NameTooLong { index: text.len() }
+    );
+
+    // Names built from labels count from where each label would start.
+    assert_eq!(
+        DomainName::from_labels(["www", "", "example"]).unwrap_err(),
+        name::Error::EmptyLabel { index: 4 }
+    );
+}
+
+#[test]
+fn addresses_point_at_the_character_at_fault() {
+    let error = "10.0.0.x/8".parse::<Prefix>().unwrap_err();
+    assert_eq!(error, addr::Error::IllegalChar { index: 7, ch: 'x' });
+    // Entries of a set count from the start of the whole text.
+    assert_eq!(
+        "10.0.0.0/8, !1.2.g.4".parse::<IpSet>().unwrap_err(),
+        addr::Error::IllegalChar { index: 17, ch: 'g' }
+    );
+}
+
+#[test]
+fn zone_files_point_at_the_token_or_into_it() {
+    let origin = name("example");
+    let at = |text: &str| {
+        let error = parse_records(text, &origin).unwrap_err();
+        (error.line, error.column, error.kind)
+    };
+
+    // Into names, by character rather than byte.
+    let (line, column, kind) = at("$TTL 300\nwww  IN  CNAME  xé\u{7}y\n");
+    assert_eq!((line, column), (2, Some(18)));
+    assert_eq!(
+        kind,
+        ParseErrorKind::Name(name::Error::IllegalChar { index: 1, ch: 'é' })
+    );
+    // At fields that do not parse, or are one too many.
+    assert_eq!(
+        at("$TTL 300\nwww IN MX (\n  1x0 mail )\n"),
+        (3, Some(3), ParseErrorKind::BadRdata)
+    );
+    assert_eq!(
+        at("$TTL 300\nwww IN CNAME a.example. b\n"),
+        (2, Some(25), ParseErrorKind::BadRdata)
+    );
+    assert_eq!(
+        at("$TTL 300\nwww IN BOGUS x\n"),
+        (2, Some(8), ParseErrorKind::UnknownType)
+    );
+    assert_eq!(
+        at("$TTL 300\nwww IN TXT \"abc\n"),
+        (2, Some(12), ParseErrorKind::UnterminatedString)
+    );
+    // At the line an entry starts on when no one field is at fault.
+    assert_eq!(
+        at("$TTL 300\nwww IN MX (\n  10 )\n"),
+        (2, None, ParseErrorKind::BadRdata)
+    );
+    let error = parse_records("$TTL 300\nwww IN MX (\n  1x0 mail )\n", &origin).unwrap_err();
+    assert_eq!(error.to_string(), "line 3, column 3: bad record data");
+}
+
+/// Sets the 16-bit field at `at` of `wire`.
+fn set_u16(wire: &mut [u8], at: usize, value: u16) {
+    wire[at..at + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// The error `wire` fails with, checking that it is the same in place.
+fn error(wire: &[u8]) -> Error {
+    let error = Message::from_wire(wire).unwrap_err();
+    if let Err(in_place) = MessageRef::parse(wire) {
+        assert_eq!(in_place, error);
+    }
+    error
+}
+
+#[test]
+fn messages_point_at_the_byte_at_fault() {
+    // A header, then 3www7example0 at 12 and the type and class at 25.
+    let query = Message::query(name("www.example"), RecordType::A);
+    let mut wire = query.to_wire().unwrap();
+    wire.truncate(29);
+    set_u16(&mut wire, 10, 0);
+    assert!(Message::from_wire(&wire).is_ok());
+
+    assert_eq!(error(&wire[..14]), Error::Truncated { offset: 12 });
+    assert_eq!(error(&wire[..20]), Error::Truncated { offset: 16 });
+    assert_eq!(error(&wire[..26]), Error::Truncated { offset: 25 });
+    let mut trailing = wire.clone();
+    trailing.extend_from_slice(&[0, 0]);
+    assert_eq!(error(&trailing), Error::TrailingData { offset: 29 });
+
+    let mut pointer = wire.clone();
+    pointer[16..18].copy_from_slice(&[0xc0, 16]);
+    assert_eq!(error(&pointer), Error::BadPointer { offset: 16 });
+    let mut label = wire.clone();
+    label[16] = 0x40;
+    assert_eq!(error(&label), Error::BadLabelType { offset: 16 });
+    let found = error(&label);
+    assert_eq!(found.offset(), 16);
+    assert_eq!(found.to_string(), "unsupported label type at byte 16");
+
+    let mut long = wire[..12].to_vec();
+    for _ in 0..4 {
+        long.push(63);
+        long.extend_from_slice(&[b'x'; 63]);
+    }
+    long.extend_from_slice(&[0, 0, 1, 0, 1]);
+    assert_eq!(
+        Message::from_wire(&long).unwrap_err(),
+        Error::BadName {
+            offset: 12,
+            error: name::Error::NameTooLong { index: 204 },
+        }
+    );
+}
+
+#[test]
+fn record_data_errors_point_at_the_data() {
+    let mut resp = Message::query(name("www.example"), RecordType::A).response();
+    resp.edns = None;
+    resp.answers.push(Record::new(
+        name("www.example"),
+        300,
+        RData::A([192, 0, 2, 1].into()),
+    ));
+    let mut wire = resp.to_wire().unwrap();
+    // Three bytes of address, which start where the last four did.
+    let len = wire.len();
+    set_u16(&mut wire, len - 6, 3);
+    wire.pop();
+    let error = Message::from_wire(&wire).unwrap_err();
+    assert_eq!(error, Error::BadRdata { offset: len - 4 });
+    assert_eq!(
+        error.to_string(),
+        format!("malformed record data at byte {}", len - 4)
+    );
+
+    // EDNS options are read where they are in the message.
+    let mut query = Message::query(name("www.example"), RecordType::A);
+    query.edns.as_mut().unwrap().options.push(EdnsOption {
+        code: OptionCode(65001),
+        data: vec![1, 2, 3, 4],
+    });
+    let mut wire = query.to_wire().unwrap();
+    let len = wire.len();
+    set_u16(&mut wire, len - 6, 5);
+    assert_eq!(
+        Message::from_wire(&wire).unwrap_err(),
+        Error::Truncated { offset: len - 4 }
+    );
+}
+
+#[test]
+fn encoding_says_how_long_the_message_got() {
+    let mut resp = Message::query(name("www.example"), RecordType::TXT).response();
+    resp.answers.push(Record::new(
+        name("www.example"),
+        300,
+        RData::Txt(vec![vec![b'x'; 255]; 300]),
+    ));
+    match resp.to_wire() {
+        Err(Error::TooLong { offset }) => assert!(offset > 65535, "{}", offset),
+        other => panic!("{:?}", other),
+    }
+}
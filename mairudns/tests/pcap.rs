@@ -0,0 +1,470 @@
+//! Packet captures: files in either byte order and timestamp resolution,
+//! DNS messages found behind each link type and reassembled from TCP
+//! segments, captures written back out, and captured queries replayed.
+//! The frames here are put together byte by byte after the formats'
+//! specifications, not by the writer under test.
+
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Request, Server};
+use mairudns::tools::pcap::{
+    read_messages, DnsMessage, Error, Extractor, LinkType, Packet, Reader, Replay, ReplayReport,
+    Writer,
+};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn query(qname: &str) -> Vec<u8> {
+    let mut query = Message::query(name(qname), RecordType::A);
+    query.header.id = 0x0053;
+    query.to_wire().unwrap()
+}
+
+fn qname(message: &DnsMessage) -> String {
+    message.decode().unwrap().questions[0].name.to_string()
+}
+
+fn udp(sport: u16, dport: u16, payload: &[u8]) -> Vec<u8> {
+    let mut d = Vec::new();
+    d.extend_from_slice(&sport.to_be_bytes());
+    d.extend_from_slice(&dport.to_be_bytes());
+    d.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+    d.extend_from_slice(&[0, 0]);
+    d.extend_from_slice(payload);
+    d
+}
+
+const SYN: u8 = 0x02;
+const FIN: u8 = 0x01;
+const PSH_ACK: u8 = 0x18;
+
+fn tcp(sport: u16, dport: u16, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut s = Vec::new();
+    s.extend_from_slice(&sport.to_be_bytes());
+    s.extend_from_slice(&dport.to_be_bytes());
+    s.extend_from_slice(&seq.to_be_bytes());
+    s.extend_from_slice(&[0; 4]);
+    // A 24-byte header: one option word, a maximum segment size.
+    s.extend_from_slice(&[0x60, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    s.extend_from_slice(&[2, 4, 0x05, 0xb4]);
+    s.extend_from_slice(payload);
+    s
+}
+
+/// An IPv4 packet from 192.0.2.1 to 192.0.2.53 with `fragment` as its
+/// flags and offset.
+fn ipv4(protocol: u8, fragment: u16, transport: &[u8]) -> Vec<u8> {
+    let mut p = vec![0x45, 0];
+    p.extend_from_slice(&(20 + transport.len() as u16).to_be_bytes());
+    p.extend_from_slice(&[0, 1]);
+    p.extend_from_slice(&fragment.to_be_bytes());
+    p.extend_from_slice(&[64, protocol, 0, 0, 192, 0, 2, 1, 192, 0, 2, 53]);
+    p.extend_from_slice(transport);
+    p
+}
+
+/// An IPv6 packet from 2001:db8::1 to 2001:db8::53 with extension
+/// headers, each given as its type and eight bytes.
+fn ipv6(protocol: u8, extensions: &[u8], transport: &[u8]) -> Vec<u8> {
+    let mut p = vec![0x60, 0, 0, 0];
+    let len = extensions.len() * 8 + transport.len();
+    p.extend_from_slice(&(len as u16).to_be_bytes());
+    p.push(*extensions.first().unwrap_or(&protocol));
+    p.push(64);
+    p.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+    p.extend_from_slice(&[0; 11]);
+    p.push(1);
+    p.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+    p.extend_from_slice(&[0; 11]);
+    p.push(0x53);
+    for (i, _) in extensions.iter().enumerate() {
+        let next = *extensions.get(i + 1).unwrap_or(&protocol);
+        p.extend_from_slice(&[next, 0, 0, 0, 0, 0, 0, 0]);
+    }
+    p.extend_from_slice(transport);
+    p
+}
+
+fn ethernet(tags: &[u16], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut f = vec![0x02, 0, 0, 0, 0, 0x53, 0x02, 0, 0, 0, 0, 0x01];
+    for tag in tags {
+        f.extend_from_slice(&tag.to_be_bytes());
+        f.extend_from_slice(&[0, 42]);
+    }
+    f.extend_from_slice(&ethertype.to_be_bytes());
+    f.extend_from_slice(payload);
+    f
+}
+
+/// A pcap file of `packets`, each with its seconds, fraction and data.
+fn file(big_endian: bool, nanos: bool, link: u32, packets: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+    let u32b = |v: u32| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let u16b = |v: u16| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let mut f = Vec::new();
+    f.extend_from_slice(&u32b(if nanos { 0xa1b2_3c4d } else { 0xa1b2_c3d4 }));
+    f.extend_from_slice(&u16b(2));
+    f.extend_from_slice(&u16b(4));
+    f.extend_from_slice(&[0; 8]);
+    f.extend_from_slice(&u32b(65535));
+    f.extend_from_slice(&u32b(link));
+    for (secs, frac, data) in packets {
+        f.extend_from_slice(&u32b(*secs));
+        f.extend_from_slice(&u32b(*frac));
+        f.extend_from_slice(&u32b(data.len() as u32));
+        f.extend_from_slice(&u32b(data.len() as u32));
+        f.extend_from_slice(data);
+    }
+    f
+}
+
+fn extract(link: LinkType, frames: &[Vec<u8>]) -> Vec<DnsMessage> {
+    let mut extractor = Extractor::new(link);
+    frames
+        .iter()
+        .flat_map(|data| {
+            extractor.push(&Packet {
+                timestamp: Duration::ZERO,
+                data: data.clone(),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn reads_either_byte_order_and_timestamp_resolution() {
+    let packet = ipv4(17, 0, &udp(5353, 53, &query("a.example.")));
+    for (big_endian, nanos) in [(false, false), (true, false), (false, true), (true, true)] {
+        let frac = if nanos { 123_456_789 } else { 123_456 };
+        let f = file(
+            big_endian,
+            nanos,
+            101,
+            &[(1_700_000_000, frac, packet.clone())],
+        );
+        let mut reader = Reader::new(&f[..]).unwrap();
+        assert_eq!(reader.link_type(), LinkType::RAW);
+        let read = reader.next_packet().unwrap().unwrap();
+        let expected = Duration::from_secs(1_700_000_000)
+            + match nanos {
+                true => Duration::from_nanos(123_456_789),
+                false => Duration::from_micros(123_456),
+            };
+        assert_eq!(read.timestamp, expected);
+        assert_eq!(read.data, packet);
+        assert!(reader.next_packet().unwrap().is_none());
+
+        let messages = read_messages(&f[..]).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].src, "192.0.2.1:5353".parse().unwrap());
+        assert_eq!(messages[0].dst, "192.0.2.53:53".parse().unwrap());
+        assert_eq!(messages[0].protocol, Protocol::Udp);
+        assert_eq!(messages[0].timestamp, expected);
+    }
+
+    // The upper bits of the link type hold the FCS length, not the type.
+    let f = file(false, false, 0x1000_0000 | 1, &[]);
+    assert_eq!(Reader::new(&f[..]).unwrap().link_type(), LinkType::ETHERNET);
+
+    // pcapng, and files cut short in either header or in a packet.
+    let mut pcapng = vec![
+        0x0a, 0x0d, 0x0d, 0x0a, 0x1c, 0, 0, 0, 0x4d, 0x3c, 0x2b, 0x1a,
+    ];
+    pcapng.resize(28, 0);
+    assert!(matches!(
+        Reader::new(&pcapng[..]).err(),
+        Some(Error::BadMagic)
+    ));
+    let f = file(false, false, 101, &[(0, 0, packet)]);
+    for cut in [10, 24 + 8, f.len() - 1] {
+        let result = read_messages(&f[..cut]);
+        assert!(matches!(result, Err(Error::Truncated)), "cut at {}", cut);
+    }
+    // A record longer than any packet means a corrupt file.
+    let mut huge = file(false, false, 101, &[]);
+    huge.extend_from_slice(&[0; 8]);
+    huge.extend_from_slice(&(1u32 << 20).to_le_bytes());
+    huge.extend_from_slice(&(1u32 << 20).to_le_bytes());
+    assert!(matches!(read_messages(&huge[..]), Err(Error::Truncated)));
+}
+
+#[test]
+fn finds_messages_behind_each_link_type() {
+    let v4 = ipv4(17, 0, &udp(5353, 53, &query("a.example.")));
+    let v6 = ipv6(17, &[], &udp(5353, 53, &query("a.example.")));
+    let mut null = 2u32.to_le_bytes().to_vec();
+    null.extend_from_slice(&v4);
+    let mut sll = vec![0, 0, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0];
+    sll.extend_from_slice(&0x86ddu16.to_be_bytes());
+    sll.extend_from_slice(&v6);
+    let mut sll2 = 0x0800u16.to_be_bytes().to_vec();
+    sll2.extend_from_slice(&[0; 18]);
+    sll2.extend_from_slice(&v4);
+    // Ethernet pads short frames; the IP length says where data ends.
+    let mut padded = ethernet(&[], 0x0800, &v4);
+    padded.extend_from_slice(&[0; 16]);
+    let cases = [
+        (LinkType::RAW, v4.clone()),
+        (LinkType::RAW, v6.clone()),
+        (LinkType::IPV4, v4.clone()),
+        (LinkType::IPV6, v6.clone()),
+        (LinkType::NULL, null),
+        (LinkType::LINUX_SLL, sll),
+        (LinkType::LINUX_SLL2, sll2),
+        (LinkType::ETHERNET, padded),
+        (LinkType::ETHERNET, ethernet(&[0x8100], 0x86dd, &v6)),
+        (LinkType::ETHERNET, ethernet(&[0x88a8, 0x8100], 0x0800, &v4)),
+        // Hop-by-hop and destination options before the UDP header.
+        (
+            LinkType::RAW,
+            ipv6(17, &[0, 60], &udp(5353, 53, &query("a.example."))),
+        ),
+    ];
+    for (i, (link, frame)) in cases.iter().enumerate() {
+        let messages = extract(*link, std::slice::from_ref(frame));
+        assert_eq!(messages.len(), 1, "case {}", i);
+        assert_eq!(qname(&messages[0]), "a.example.");
+        assert_eq!(messages[0].wire, query("a.example."));
+    }
+
+    // Not IP, not UDP or TCP, fragments of either IP version, other
+    // ports, link types not read, and frames cut short.
+    let skipped = [
+        (LinkType::ETHERNET, ethernet(&[], 0x0806, &v4)),
+        (LinkType::RAW, ipv4(1, 0, &udp(5353, 53, &[0; 8]))),
+        (LinkType::RAW, ipv4(17, 0x2000, &udp(5353, 53, &[0; 8]))),
+        (LinkType::RAW, ipv4(17, 0x0010, &[0; 16])),
+        (LinkType::RAW, ipv6(17, &[44], &udp(5353, 53, &[0; 8]))),
+        (LinkType::RAW, ipv4(17, 0, &udp(5353, 5354, &[0; 8]))),
+        (LinkType(105), v4.clone()),
+        (LinkType::RAW, v4[..24].to_vec()),
+        (LinkType::ETHERNET, vec![0; 13]),
+    ];
+    for (i, (link, frame)) in skipped.iter().enumerate() {
+        assert!(
+            extract(*link, std::slice::from_ref(frame)).is_empty(),
+            "case {}",
+            i
+        );
+    }
+
+    // Other ports when asked for.
+    let frame = ipv4(17, 0, &udp(5353, 853, &query("a.example.")));
+    let mut extractor = Extractor::new(LinkType::RAW).ports(&[853]);
+    let packet = Packet {
+        timestamp: Duration::ZERO,
+        data: frame,
+    };
+    assert_eq!(extractor.push(&packet).len(), 1);
+}
+
+fn framed(wire: &[u8]) -> Vec<u8> {
+    let mut framed = (wire.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(wire);
+    framed
+}
+
+#[test]
+fn reassembles_tcp_streams() {
+    let (a, b, c) = (
+        framed(&query("a.example.")),
+        framed(&query("b.example.")),
+        framed(&query("c.example.")),
+    );
+    let seg =
+        |seq: u32, flags: u8, payload: &[u8]| ipv4(6, 0, &tcp(40000, 53, seq, flags, payload));
+    // After the SYN at 1000 data starts at 1001: the first message split
+    // in two with the halves arriving in reverse, a retransmission that
+    // overlaps what came before, then two messages in one segment.
+    let (a1, a2) = a.split_at(5);
+    let mut bc = b.clone();
+    bc.extend_from_slice(&c);
+    let frames = [
+        seg(1000, SYN, &[]),
+        seg(1001 + 5, PSH_ACK, a2),
+        seg(1001, PSH_ACK, a1),
+        seg(1001, PSH_ACK, &a),
+        seg(1001 + a.len() as u32, PSH_ACK, &bc),
+    ];
+    let messages = extract(LinkType::RAW, &frames);
+    let names: Vec<_> = messages.iter().map(qname).collect();
+    assert_eq!(names, ["a.example.", "b.example.", "c.example."]);
+    assert!(messages
+        .iter()
+        .all(|m| m.protocol == Protocol::Tcp && m.src == "192.0.2.1:40000".parse().unwrap()));
+
+    // Streams whose start was not captured begin where the capture does,
+    // and sequence numbers wrap.
+    let start = u32::MAX - 3;
+    let (b1, b2) = b.split_at(8);
+    let frames = [
+        seg(start, PSH_ACK, &a),
+        seg(start.wrapping_add(a.len() as u32), PSH_ACK, b1),
+        seg(start.wrapping_add(a.len() as u32 + 8), PSH_ACK, b2),
+    ];
+    let names: Vec<_> = extract(LinkType::RAW, &frames).iter().map(qname).collect();
+    assert_eq!(names, ["a.example.", "b.example."]);
+
+    // A FIN ends the stream: what was left of a message is dropped, and a
+    // new stream between the same ports starts afresh.
+    let frames = [
+        seg(1000, SYN, &[]),
+        seg(1001, PSH_ACK | FIN, a1),
+        seg(5000, SYN, &[]),
+        seg(5001, PSH_ACK, &c),
+    ];
+    let names: Vec<_> = extract(LinkType::RAW, &frames).iter().map(qname).collect();
+    assert_eq!(names, ["c.example."]);
+
+    // A stream held up by a gap for too long is given up on, and picked
+    // up again at the next segment as one whose start was not captured;
+    // filling the gap then brings nothing.
+    let mut frames = vec![seg(1000, SYN, &[])];
+    for i in 0..66 {
+        frames.push(seg(2000 + i * 100, PSH_ACK, &a));
+    }
+    frames.push(seg(1001, PSH_ACK, &a));
+    let messages = extract(LinkType::RAW, &frames);
+    assert_eq!(messages.len(), 1);
+}
+
+fn captured(t: u64, src: &str, dst: &str, protocol: Protocol, m: &Message) -> DnsMessage {
+    DnsMessage {
+        timestamp: Duration::from_millis(t),
+        src: src.parse().unwrap(),
+        dst: dst.parse().unwrap(),
+        protocol,
+        wire: m.to_wire().unwrap(),
+    }
+}
+
+#[test]
+fn writes_captures_and_replays_their_queries() {
+    let mut server = Server::new(|req: &Request| {
+        let mut resp = req.message.response();
+        resp.answers.push(Record::new(
+            req.message.questions[0].name.clone(),
+            60,
+            RData::A([192, 0, 2, 1].into()),
+        ));
+        Some(resp)
+    });
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr: SocketAddr = server.local_addrs()[0].1;
+    thread::spawn(move || server.run());
+
+    let answered = |q: &Message, addr: [u8; 4]| {
+        let mut r = q.response();
+        let qname = q.questions[0].name.clone();
+        r.answers
+            .push(Record::new(qname, 300, RData::A(addr.into())));
+        r
+    };
+    let qa = Message::query(name("a.example."), RecordType::A);
+    let qb = Message::query(name("b.example."), RecordType::A);
+    let qc = Message::query(name("c.example."), RecordType::A);
+    let (client4, server4) = ("192.0.2.1:5000", "192.0.2.53:53");
+    let (client6, server6) = ("[2001:db8::1]:6000", "[2001:db8::53]:53");
+    let messages = vec![
+        captured(0, client4, server4, Protocol::Udp, &qa),
+        captured(
+            1,
+            server4,
+            client4,
+            Protocol::Udp,
+            &answered(&qa, [192, 0, 2, 1]),
+        ),
+        captured(2, client6, server6, Protocol::Tcp, &qb),
+        captured(3, client6, server6, Protocol::Tcp, &qc),
+        captured(
+            4,
+            server6,
+            client6,
+            Protocol::Tcp,
+            &answered(&qb, [198, 51, 100, 1]),
+        ),
+    ];
+    let mut writer = Writer::new(Vec::new(), LinkType::RAW).unwrap();
+    for m in &messages {
+        writer.write_message(m).unwrap();
+    }
+    let f = writer.into_inner();
+    assert_eq!(read_messages(&f[..]).unwrap(), messages);
+    // A SYN ahead of each TCP direction's first message.
+    assert_eq!(Reader::new(&f[..]).unwrap().count(), 7);
+
+    // Messages are written to raw IP captures only, between addresses of
+    // one family.
+    let mut ethernet = Writer::new(Vec::new(), LinkType::ETHERNET).unwrap();
+    assert!(ethernet.write_message(&messages[0]).is_err());
+    let mut raw = Writer::new(Vec::new(), LinkType::RAW).unwrap();
+    let mixed = captured(0, client4, server6, Protocol::Udp, &qa);
+    assert!(raw.write_message(&mixed).is_err());
+
+    // Each query goes over the transport it was captured on, and answers
+    // are compared with the captured responses, TTLs aside.
+    let report = Replay::new(addr).run(&messages);
+    assert_eq!(report.sent, 3);
+    assert_eq!(report.answered, 3);
+    assert_eq!(report.rcodes.get(&Rcode::NOERROR), Some(&3));
+    assert_eq!(report.differing, 1);
+    assert_eq!(report.latencies.len(), 3);
+    assert!(report.percentile(0.5).is_some());
+
+    // At captured pace, the replay takes as long as the capture did.
+    let paced = Replay {
+        speed: Some(1.0),
+        concurrency: 1,
+        ..Replay::new(addr)
+    };
+    let spread = vec![
+        captured(0, client4, server4, Protocol::Udp, &qa),
+        captured(300, client4, server4, Protocol::Udp, &qb),
+    ];
+    assert!(paced.run(&spread).elapsed >= Duration::from_millis(300));
+
+    // Nobody listening.
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let report = Replay {
+        timeout: Duration::from_millis(200),
+        ..Replay::new(silent.local_addr().unwrap())
+    }
+    .run(&messages[..1]);
+    assert_eq!((report.sent, report.timed_out), (1, 1));
+    assert_eq!(report.percentile(0.5), None);
+}
+
+#[test]
+fn percentiles_round_up_to_an_answered_query() {
+    let report = ReplayReport {
+        latencies: (1..=10).map(Duration::from_millis).collect(),
+        sent: 20,
+        elapsed: Duration::from_secs(4),
+        ..ReplayReport::default()
+    };
+    assert_eq!(report.percentile(0.0), Some(Duration::from_millis(1)));
+    assert_eq!(report.percentile(0.5), Some(Duration::from_millis(5)));
+    assert_eq!(report.percentile(0.91), Some(Duration::from_millis(10)));
+    assert_eq!(report.percentile(1.0), Some(Duration::from_millis(10)));
+    assert_eq!(report.queries_per_second(), 5.0);
+    assert_eq!(ReplayReport::default().queries_per_second(), 0.0);
+}
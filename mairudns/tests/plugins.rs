@@ -0,0 +1,323 @@
+//! Plugins at their three hook points, around a plain handler and inside
+//! the forwarder, and, with the `script` feature, plugins written in
+//! Rhai.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Forwarder, Handler, HandlerExt, Plugin, Plugins, Request, Route};
+use mairudns::testing::MockServer;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn a(owner: &str, addr: [u8; 4]) -> Record {
+    Record::new(name(owner), 300, RData::A(addr.into()))
+}
+
+fn request(qname: &str, qtype: RecordType) -> Request {
+    Request {
+        message: Message::query(name(qname), qtype),
+        src: "192.0.2.1:53000".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+/// Echoes the question with no answers.
+fn empty(request: &Request) -> Option<Message> {
+    Some(request.message.response())
+}
+
+/// A plugin recording the hooks it sees, answering `owner` before the
+/// cache and tagging every response with a TXT record of its `tag`.
+struct Recording {
+    tag: &'static str,
+    owner: Option<&'static str>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl Recording {
+    fn new(tag: &'static str, owner: Option<&'static str>) -> Arc<Recording> {
+        Arc::new(Recording {
+            tag,
+            owner,
+            calls: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl Plugin for Recording {
+    fn pre_cache(&self, request: &Request) -> Option<Message> {
+        self.calls.lock().unwrap().push("pre-cache".into());
+        let question = request.message.question()?;
+        if Some(question.name.clone()) != self.owner.map(name) {
+            return None;
+        }
+        let mut resp = request.message.response();
+        resp.answers.push(a(self.owner.unwrap(), [192, 0, 2, 99]));
+        Some(resp)
+    }
+
+    fn post_answer(&self, _request: &Request, response: &mut Message) {
+        self.calls.lock().unwrap().push("post-answer".into());
+        response.additional.push(Record::new(
+            name("tag.invalid"),
+            0,
+            RData::Txt(vec![self.tag.as_bytes().to_vec()]),
+        ));
+    }
+}
+
+fn tags(resp: &Message) -> Vec<String> {
+    resp.additional
+        .iter()
+        .map(|rr| match &rr.rdata {
+            RData::Txt(strings) => String::from_utf8(strings[0].clone()).unwrap(),
+            other => panic!("{:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn the_first_plugin_to_answer_wins_and_all_see_the_answer() {
+    let first = Recording::new("first", Some("one.example"));
+    let second = Recording::new("second", Some("two.example"));
+    let plugins = Plugins::new().plugin(first.clone()).plugin(second.clone());
+    assert_eq!(plugins.len(), 2);
+    let handler = (empty as fn(&Request) -> Option<Message>).with(plugins);
+
+    let resp = handler
+        .handle(&request("one.example", RecordType::A))
+        .unwrap();
+    assert_eq!(resp.answers, vec![a("one.example", [192, 0, 2, 99])]);
+    assert_eq!(tags(&resp), vec!["first", "second"]);
+    // The second plugin is not asked once the first has answered.
+    assert_eq!(first.calls(), vec!["pre-cache", "post-answer"]);
+    assert_eq!(second.calls(), vec!["post-answer"]);
+
+    let resp = handler
+        .handle(&request("two.example", RecordType::A))
+        .unwrap();
+    assert_eq!(resp.answers, vec![a("two.example", [192, 0, 2, 99])]);
+
+    let resp = handler
+        .handle(&request("three.example", RecordType::A))
+        .unwrap();
+    assert!(resp.answers.is_empty());
+    assert_eq!(tags(&resp), vec!["first", "second"]);
+}
+
+#[test]
+fn nothing_runs_after_a_handler_that_drops_the_request() {
+    let plugin = Recording::new("only", None);
+    let handler = (|_: &Request| None).with(Plugins::new().plugin(plugin.clone()));
+    assert!(handler
+        .handle(&request("www.example", RecordType::A))
+        .is_none());
+    assert_eq!(plugin.calls(), vec!["pre-cache"]);
+}
+
+/// A plugin at the pre-upstream hook: it answers `local.example` itself
+/// and asks for AAAA in place of the A records of other names.
+#[derive(Default)]
+struct Upstream {
+    calls: AtomicUsize,
+}
+
+impl Plugin for Upstream {
+    fn pre_upstream(&self, request: &Request, query: &mut Message) -> Option<Message> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if query.question()?.name == name("local.example") {
+            let mut resp = request.message.response();
+            resp.answers.push(a("local.example", [127, 0, 0, 1]));
+            return Some(resp);
+        }
+        query.questions[0].qtype = RecordType::AAAA;
+        None
+    }
+}
+
+#[test]
+fn the_forwarder_calls_plugins_before_going_upstream() {
+    let server = MockServer::builder()
+        .answer(
+            name("www.example"),
+            RecordType::AAAA,
+            vec![Record::new(
+                name("www.example"),
+                300,
+                RData::Aaaa("2001:db8::1".parse().unwrap()),
+            )],
+        )
+        .start()
+        .unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    let plugin = Arc::new(Upstream::default());
+    let forwarder = Forwarder::new()
+        .route(Route::new(DomainName::root(), resolver))
+        .plugins(Plugins::new().plugin(plugin.clone()));
+
+    // The changed query is what goes upstream, and its answer is cached.
+    for _ in 0..2 {
+        let resp = forwarder
+            .handle(&request("www.example", RecordType::A))
+            .unwrap();
+        assert_eq!(resp.header.rcode, Rcode::NOERROR);
+        assert_eq!(resp.answers.len(), 1);
+    }
+    let received = server.received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(
+        received[0].message.question().unwrap().qtype,
+        RecordType::AAAA
+    );
+    assert_eq!(plugin.calls.load(Ordering::SeqCst), 1);
+
+    // An answer from the plugin goes nowhere and is never cached.
+    for _ in 0..2 {
+        let resp = forwarder
+            .handle(&request("local.example", RecordType::A))
+            .unwrap();
+        assert_eq!(resp.answers, vec![a("local.example", [127, 0, 0, 1])]);
+    }
+    assert_eq!(server.received().len(), 1);
+    assert_eq!(plugin.calls.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(feature = "script")]
+mod script {
+    use super::*;
+    use mairudns::server::ScriptPlugin;
+
+    fn handler(plugin: &Arc<ScriptPlugin>) -> impl Handler {
+        (empty as fn(&Request) -> Option<Message>).with(Plugins::new().plugin(plugin.clone()))
+    }
+
+    #[test]
+    fn scripts_answer_and_rewrite_responses() {
+        let plugin = Arc::new(
+            ScriptPlugin::new(
+                r#"
+fn pre_cache(request) {
+    if request.name == "www.example.com." && request.type == "A" {
+        let addr = if random(2) == 0 { "192.0.2.1" } else { "192.0.2.2" };
+        return #{ answers: [`www.example.com. 30 IN A ${addr}`] };
+    }
+    if request.name == "nx.example.com." {
+        return #{ rcode: "NXDOMAIN", authority: ["example.com. 60 IN SOA ns admin 1 2 3 4 5"] };
+    }
+}
+
+fn post_answer(request, response) {
+    if request.protocol == "udp" && hour_utc() < 24 && weekday_utc() < 7 {
+        response.additional = [`${request.client}.seen.invalid. 0 IN TXT "${request.type}"`];
+        return response;
+    }
+}
+"#,
+            )
+            .unwrap(),
+        );
+        let handler = handler(&plugin);
+
+        let mut seen = Vec::new();
+        for _ in 0..64 {
+            let resp = handler
+                .handle(&request("www.example.com", RecordType::A))
+                .unwrap();
+            assert_eq!(resp.answers.len(), 1);
+            assert_eq!(resp.answers[0].ttl, 30);
+            seen.push(resp.answers[0].rdata.clone());
+        }
+        assert!(seen.contains(&RData::A([192, 0, 2, 1].into())));
+        assert!(seen.contains(&RData::A([192, 0, 2, 2].into())));
+
+        let resp = handler
+            .handle(&request("nx.example.com", RecordType::MX))
+            .unwrap();
+        assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+        assert_eq!(resp.authority[0].rtype(), RecordType::SOA);
+        assert_eq!(resp.additional[0].name, name("192.0.2.1.seen.invalid"));
+        assert_eq!(resp.additional[0].rdata, RData::Txt(vec![b"MX".to_vec()]));
+        assert_eq!(plugin.errors(), 0);
+    }
+
+    #[test]
+    fn failing_hooks_are_skipped_and_counted() {
+        let plugin = Arc::new(
+            ScriptPlugin::new(
+                r#"
+fn pre_cache(request) {
+    if request.name == "number.example." { return 5; }
+    if request.name == "loop.example." { loop {} }
+    if request.name == "field.example." { return #{ bogus: 1 }; }
+    if request.name == "record.example." { return #{ answers: ["not a record"] }; }
+    if request.name == "rcode.example." { return #{ rcode: "SOMETIMES" }; }
+    if request.name == "throw.example." { throw "no"; }
+}
+"#,
+            )
+            .unwrap(),
+        );
+        let handler = handler(&plugin);
+        let names = [
+            "number.example",
+            "loop.example",
+            "field.example",
+            "record.example",
+            "rcode.example",
+            "throw.example",
+        ];
+        for (i, qname) in names.iter().enumerate() {
+            // The handler answers in the failed hook's place.
+            let resp = handler.handle(&request(qname, RecordType::A)).unwrap();
+            assert!(resp.answers.is_empty());
+            assert_eq!(resp.header.rcode, Rcode::NOERROR);
+            assert_eq!(plugin.errors(), i as u64 + 1, "{}", qname);
+        }
+        handler
+            .handle(&request("fine.example", RecordType::A))
+            .unwrap();
+        assert_eq!(plugin.errors(), names.len() as u64);
+    }
+
+    #[test]
+    fn scripts_that_do_not_compile_or_load_are_refused() {
+        assert!(ScriptPlugin::new("fn pre_cache( {").is_err());
+        let missing = std::env::temp_dir().join("no-such-script.rhai");
+        let err = ScriptPlugin::load(&missing).unwrap_err();
+        assert!(err.0.starts_with(&missing.display().to_string()), "{}", err);
+
+        let path = std::env::temp_dir().join(format!("plugin-{}.rhai", std::process::id()));
+        std::fs::write(
+            &path,
+            "fn pre_upstream(request) { #{ rcode: \"REFUSED\" } }",
+        )
+        .unwrap();
+        let plugin = ScriptPlugin::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let mut query = Message::query(name("www.example"), RecordType::A);
+        let resp = plugin
+            .unwrap()
+            .pre_upstream(&request("www.example", RecordType::A), &mut query)
+            .unwrap();
+        assert_eq!(resp.header.rcode, Rcode::REFUSED);
+    }
+}
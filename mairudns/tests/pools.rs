@@ -0,0 +1,660 @@
+//! Health-checked answer pools: the probes against local listeners, the
+//! damping of state changes, the addresses kept in answers, weights and
+//! failover tiers, selection by client subnet, answers the pools make up
+//! themselves, and the pools as a layer.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{IpAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::config::{Config, PoolConfig, PoolTargetConfig, SelectionKind};
+use mairudns::message::{Edns, EdnsOption, Message, OptionCode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{Handler, HandlerExt, Pool, Pools, Probe, Request, Selection, Target};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn request(qname: &str, qtype: RecordType) -> Request {
+    Request {
+        message: Message::query(name(qname), qtype),
+        src: "203.0.113.7:53000".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+/// A response for `owner` holding an address record for each of `addrs`.
+fn response(owner: &str, addrs: &[&str]) -> Message {
+    let mut resp = Message::query(name(owner), RecordType::A).response();
+    for addr in addrs {
+        let rdata = match ip(addr) {
+            IpAddr::V4(a) => RData::A(a),
+            IpAddr::V6(a) => RData::Aaaa(a),
+        };
+        resp.answers.push(Record::new(name(owner), 60, rdata));
+    }
+    resp
+}
+
+fn addresses(resp: &Message) -> Vec<String> {
+    resp.answers
+        .iter()
+        .filter_map(|rr| match rr.rdata {
+            RData::A(a) => Some(a.to_string()),
+            RData::Aaaa(a) => Some(a.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A pool for `www.example` of three IPv4 targets and one IPv6 one,
+/// changing state on the first probe to disagree.
+fn pool() -> Pool {
+    let mut pool = Pool::new(
+        name("www.example"),
+        [
+            ip("192.0.2.1"),
+            ip("192.0.2.2"),
+            ip("192.0.2.3"),
+            ip("2001:db8::1"),
+        ],
+        Probe::Tcp { port: 80 },
+    );
+    pool.rise = 1;
+    pool.fall = 1;
+    pool
+}
+
+/// A port on the loopback address nothing listens on.
+fn closed_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// An HTTP server answering each request with `status_line`, returning
+/// its port and what it was sent.
+fn http_server(status_line: &'static str) -> (u16, thread::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 512];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        stream.write_all(status_line.as_bytes()).unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    (port, server)
+}
+
+#[test]
+fn tcp_probes_need_an_accepted_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    assert!(Probe::Tcp { port }.check(ip("127.0.0.1"), TIMEOUT));
+    drop(listener);
+    assert!(!Probe::Tcp {
+        port: closed_port()
+    }
+    .check(ip("127.0.0.1"), TIMEOUT));
+}
+
+#[test]
+fn http_probes_need_a_success_or_redirect_status() {
+    for (status_line, healthy) in [
+        ("HTTP/1.1 200 OK\r\n\r\n", true),
+        ("HTTP/1.0 302 Found\r\nLocation: /\r\n\r\n", true),
+        ("HTTP/1.1 500 Internal Server Error\r\n\r\n", false),
+        ("SSH-2.0-OpenSSH_9.6\r\n", false),
+    ] {
+        let (port, server) = http_server(status_line);
+        let probe = Probe::Http {
+            port,
+            path: "/health".into(),
+            host: Some("www.example".into()),
+        };
+        assert_eq!(
+            probe.check(ip("127.0.0.1"), TIMEOUT),
+            healthy,
+            "{}",
+            status_line
+        );
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("GET /health HTTP/1.0\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("\r\nHost: www.example\r\n"), "{}", request);
+    }
+
+    // Without a host name the address is sent in its place.
+    let (port, server) = http_server("HTTP/1.1 204 No Content\r\n\r\n");
+    let probe = Probe::Http {
+        port,
+        path: "/".into(),
+        host: None,
+    };
+    assert!(probe.check(ip("127.0.0.1"), TIMEOUT));
+    assert!(server.join().unwrap().contains("\r\nHost: 127.0.0.1\r\n"));
+
+    // A server that never answers fails the probe once the timeout ends.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let probe = Probe::Http {
+        port: listener.local_addr().unwrap().port(),
+        path: "/".into(),
+        host: None,
+    };
+    let started = Instant::now();
+    assert!(!probe.check(ip("127.0.0.1"), Duration::from_millis(200)));
+    assert!(started.elapsed() < TIMEOUT);
+}
+
+#[test]
+fn targets_change_state_only_after_a_streak() {
+    let mut pool = pool();
+    pool.rise = 2;
+    pool.fall = 3;
+    let pools = Pools::new().pool(pool);
+    let www = name("www.example");
+    let a = ip("192.0.2.1");
+    let status = |pools: &Pools| pools.status(&www).unwrap()[0].clone();
+
+    // Targets are healthy until probed.
+    assert!(status(&pools).healthy);
+    assert!(status(&pools).last_checked.is_none());
+    pools.report(&www, a, false);
+    pools.report(&www, a, false);
+    assert!(status(&pools).healthy);
+    assert_eq!(status(&pools).streak, 2);
+    assert!(status(&pools).last_checked.is_some());
+    // A success in between starts the count again.
+    pools.report(&www, a, true);
+    assert_eq!(status(&pools).streak, 0);
+    for _ in 0..3 {
+        pools.report(&www, a, false);
+    }
+    assert!(!status(&pools).healthy);
+
+    pools.report(&www, a, true);
+    assert!(!status(&pools).healthy);
+    pools.report(&www, a, true);
+    assert!(status(&pools).healthy);
+    assert_eq!(status(&pools).streak, 0);
+
+    // Reports about other names and addresses change nothing.
+    pools.report(&name("other.example"), a, false);
+    pools.report(&www, ip("192.0.2.9"), false);
+    assert!(pools.status(&www).unwrap().iter().all(|t| t.healthy));
+    assert!(pools.status(&name("other.example")).is_none());
+}
+
+#[test]
+fn unhealthy_targets_leave_the_answers() {
+    let pools = Pools::new().pool(pool());
+    let www = name("www.example");
+    let all = ["192.0.2.1", "192.0.2.2", "192.0.2.3", "2001:db8::1"];
+
+    let mut resp = response("www.example", &all);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), all);
+
+    pools.report(&www, ip("192.0.2.2"), false);
+    let mut resp = response("www.example", &all);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), ["192.0.2.1", "192.0.2.3", "2001:db8::1"]);
+
+    // Addresses outside the pool, and other names, are left alone.
+    let mut resp = response("www.example", &["192.0.2.2", "198.51.100.1"]);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), ["198.51.100.1"]);
+    let mut resp = response("mail.example", &["192.0.2.2"]);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), ["192.0.2.2"]);
+}
+
+#[test]
+fn answers_keep_their_minimum_of_addresses() {
+    let mut pool = pool();
+    pool.min_answers = 2;
+    let pools = Pools::new().pool(pool);
+    let www = name("www.example");
+    for addr in ["192.0.2.1", "192.0.2.2", "2001:db8::1"] {
+        pools.report(&www, ip(addr), false);
+    }
+
+    // One healthy IPv4 target is made up to two with an unhealthy one,
+    // and the IPv6 target, down and alone, is kept all the same.
+    let mut resp = response(
+        "www.example",
+        &["192.0.2.1", "192.0.2.2", "192.0.2.3", "2001:db8::1"],
+    );
+    pools.filter(&mut resp);
+    let kept = addresses(&resp);
+    assert_eq!(kept.len(), 3, "{:?}", kept);
+    assert!(kept.contains(&"192.0.2.3".to_string()));
+    assert!(kept.contains(&"2001:db8::1".to_string()));
+
+    // Addresses outside the pool count toward the minimum.
+    let mut resp = response("www.example", &["192.0.2.1", "198.51.100.1", "192.0.2.3"]);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), ["198.51.100.1", "192.0.2.3"]);
+}
+
+#[test]
+fn the_layer_filters_answers_and_errors_pass_through() {
+    let pools = Pools::new().pool(pool());
+    pools.report(&name("www.example"), ip("192.0.2.1"), false);
+    let handler = (|request: &Request| {
+        let mut resp = response("www.example", &["192.0.2.1", "192.0.2.2"]);
+        resp.header.id = request.message.header.id;
+        if request.message.question()?.name == name("fail.example") {
+            resp.header.rcode = Rcode::SERVFAIL;
+        }
+        Some(resp)
+    })
+    .with(pools);
+    assert_eq!(
+        handler.pools().status(&name("www.example")).unwrap().len(),
+        4
+    );
+
+    let resp = handler
+        .handle(&request("www.example", RecordType::A))
+        .unwrap();
+    assert_eq!(addresses(&resp), ["192.0.2.2"]);
+    let resp = handler
+        .handle(&request("fail.example", RecordType::A))
+        .unwrap();
+    assert_eq!(addresses(&resp), ["192.0.2.1", "192.0.2.2"]);
+}
+
+#[test]
+fn running_pools_probe_until_stopped() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let up = listener.local_addr().unwrap().port();
+    let mut pool = Pool::new(
+        name("www.example"),
+        [ip("127.0.0.1")],
+        Probe::Tcp { port: up },
+    );
+    pool.interval = Duration::from_millis(20);
+    pool.timeout = Duration::from_millis(500);
+    pool.rise = 1;
+    pool.fall = 1;
+    let mut down = pool.clone();
+    down.name = name("down.example");
+    down.probe = Probe::Tcp {
+        port: closed_port(),
+    };
+    let pools = Pools::new().pool(pool).pool(down);
+
+    pools.probe_all();
+    let status = pools.status(&name("www.example")).unwrap();
+    assert!(status[0].healthy && status[0].last_checked.is_some());
+    assert!(!pools.status(&name("down.example")).unwrap()[0].healthy);
+
+    drop(listener);
+    let running = pools.clone();
+    let runner = thread::spawn(move || running.run());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while pools.status(&name("www.example")).unwrap()[0].healthy {
+        assert!(
+            Instant::now() < deadline,
+            "the closed listener was never probed"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    pools.stop();
+    runner.join().unwrap();
+}
+
+/// A pool for `www.example` giving out one of three IPv4 targets, the
+/// last in a second tier.
+fn weighted_pool() -> Pool {
+    let mut pool = Pool::new(
+        name("www.example"),
+        [
+            Target::new(ip("192.0.2.1")).weight(3),
+            Target::new(ip("192.0.2.2")),
+            Target::new(ip("198.51.100.1")).tier(1),
+        ],
+        Probe::Icmp,
+    );
+    pool.answers = 1;
+    pool.rise = 1;
+    pool.fall = 1;
+    pool
+}
+
+const WEIGHTED: [&str; 3] = ["192.0.2.1", "192.0.2.2", "198.51.100.1"];
+
+/// A query from `src` with a Client Subnet option of `source` bits of
+/// `addr`, if any.
+fn subnet_request(src: &str, ecs: Option<(&str, u8)>) -> Request {
+    let mut request = request("www.example", RecordType::A);
+    request.src = src.parse().unwrap();
+    if let Some((addr, source)) = ecs {
+        let mut data = vec![0, 1, source, 0];
+        let octets = match ip(addr) {
+            IpAddr::V4(a) => a.octets(),
+            IpAddr::V6(_) => unreachable!(),
+        };
+        data.extend_from_slice(&octets[..usize::from(source).div_ceil(8)]);
+        request.message.edns = Some(Edns {
+            options: vec![EdnsOption {
+                code: OptionCode::CLIENT_SUBNET,
+                data,
+            }],
+            ..Edns::default()
+        });
+    }
+    request
+}
+
+/// What `pools` give `request` out of the weighted targets, and the
+/// scope prefix length of the Client Subnet option of the response.
+fn steer(pools: &Pools, request: &Request) -> (Vec<String>, Option<u8>) {
+    let mut resp = request.message.response();
+    for addr in WEIGHTED {
+        resp.answers.push(Record::new(
+            name("www.example"),
+            60,
+            RData::A(addr.parse().unwrap()),
+        ));
+    }
+    pools.steer(request, &mut resp);
+    let scope = resp
+        .edns
+        .as_ref()
+        .and_then(|e| e.option(OptionCode::CLIENT_SUBNET))
+        .map(|o| o.data[3]);
+    (addresses(&resp), scope)
+}
+
+fn soa() -> Record {
+    Record::new(
+        name("example"),
+        300,
+        RData::Soa(Soa {
+            mname: name("ns.example"),
+            rname: name("hostmaster.example"),
+            serial: 1,
+            refresh: 7200,
+            retry: 900,
+            expire: 1_209_600,
+            minimum: 300,
+        }),
+    )
+}
+
+#[test]
+fn targets_are_given_out_by_weight_within_the_first_healthy_tier() {
+    let pools = Pools::new().pool(weighted_pool());
+    let www = name("www.example");
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for _ in 0..4000 {
+        let mut resp = response("www.example", &WEIGHTED);
+        pools.filter(&mut resp);
+        let given = addresses(&resp);
+        assert_eq!(given.len(), 1);
+        *counts.entry(given[0].clone()).or_default() += 1;
+    }
+    // Three to one, and nothing from the second tier.
+    let heavy = counts["192.0.2.1"];
+    assert!((2700..=3300).contains(&heavy), "{:?}", counts);
+    assert_eq!(heavy + counts["192.0.2.2"], 4000);
+
+    pools.report(&www, ip("192.0.2.1"), false);
+    pools.report(&www, ip("192.0.2.2"), false);
+    let mut resp = response("www.example", &WEIGHTED);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), ["198.51.100.1"]);
+
+    // With every target down, the first tier still makes up the answer.
+    pools.report(&www, ip("198.51.100.1"), false);
+    let mut resp = response("www.example", &WEIGHTED);
+    pools.filter(&mut resp);
+    assert_eq!(addresses(&resp), ["192.0.2.1"]);
+
+    // A target of weight 0 is only a last resort.
+    let mut pool = Pool::new(
+        www.clone(),
+        [
+            Target::new(ip("192.0.2.1")).weight(0),
+            Target::new(ip("192.0.2.2")),
+        ],
+        Probe::Icmp,
+    );
+    pool.answers = 1;
+    let pools = Pools::new().pool(pool);
+    for _ in 0..100 {
+        let mut resp = response("www.example", &["192.0.2.1", "192.0.2.2"]);
+        pools.filter(&mut resp);
+        assert_eq!(addresses(&resp), ["192.0.2.2"]);
+    }
+}
+
+#[test]
+fn client_subnets_keep_their_targets_and_are_scoped() {
+    let mut pool = weighted_pool();
+    pool.targets[2].tier = 0;
+    pool.selection = Selection::ClientSubnet {
+        v4_prefix: 24,
+        v6_prefix: 56,
+    };
+    let pools = Pools::new().pool(pool);
+
+    // The same for a whole /24, mapped addresses included.
+    let (first, scope) = steer(&pools, &subnet_request("203.0.113.1:5353", None));
+    assert_eq!(first.len(), 1);
+    assert_eq!(scope, None);
+    for src in ["203.0.113.200:53", "[::ffff:203.0.113.9]:53"] {
+        assert_eq!(steer(&pools, &subnet_request(src, None)).0, first);
+    }
+    let seen: HashSet<Vec<String>> = (0..50)
+        .map(|i| steer(&pools, &subnet_request(&format!("10.{}.0.1:53", i), None)).0)
+        .collect();
+    assert!(seen.len() > 1, "{:?}", seen);
+
+    // The Client Subnet stands for the source, and the answer is scoped
+    // to the prefix it was chosen by.
+    let ecs = subnet_request("192.0.2.53:53", Some(("203.0.113.0", 24)));
+    assert_eq!(steer(&pools, &ecs), (first.clone(), Some(24)));
+    let longer = subnet_request("192.0.2.53:53", Some(("203.0.113.128", 25)));
+    assert_eq!(steer(&pools, &longer), (first, Some(24)));
+    let shorter = subnet_request("192.0.2.53:53", Some(("203.0.0.0", 16)));
+    assert_eq!(steer(&pools, &shorter).1, Some(16));
+
+    // Answers not chosen by subnet are not scoped.
+    let mut all = weighted_pool();
+    all.answers = 0;
+    all.selection = Selection::ClientSubnet {
+        v4_prefix: 24,
+        v6_prefix: 56,
+    };
+    let (given, scope) = steer(&Pools::new().pool(all), &ecs);
+    assert_eq!(given.len(), 2);
+    assert_eq!(scope, None);
+    let mut random = weighted_pool();
+    random.targets[2].tier = 0;
+    assert_eq!(steer(&Pools::new().pool(random), &ecs).1, None);
+}
+
+#[test]
+fn pools_with_a_ttl_answer_for_names_the_zone_has() {
+    let mut pool = pool();
+    pool.ttl = Some(30);
+    pool.min_answers = 0;
+    let pools = Pools::new().pool(pool);
+    let www = name("www.example");
+    pools.report(&www, ip("192.0.2.2"), false);
+
+    // A no-data answer becomes one with the healthy targets.
+    let query = request("www.example", RecordType::A);
+    let mut resp = query.message.response();
+    resp.authority.push(soa());
+    pools.steer(&query, &mut resp);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert_eq!(addresses(&resp), ["192.0.2.1", "192.0.2.3"]);
+    assert!(resp.answers.iter().all(|rr| rr.ttl == 30));
+    assert!(resp.authority.is_empty());
+
+    // What the zone has for the name gives way to the targets.
+    let query = request("www.example", RecordType::AAAA);
+    let mut resp = response("www.example", &["2001:db8::99", "192.0.2.9"]);
+    pools.steer(&query, &mut resp);
+    assert_eq!(addresses(&resp), ["192.0.2.9", "2001:db8::1"]);
+
+    // A name the zone does not have stays missing, with its proof.
+    let query = request("www.example", RecordType::A);
+    let mut resp = query.message.response();
+    resp.header.rcode = Rcode::NXDOMAIN;
+    resp.authority.push(soa());
+    let denied = resp.clone();
+    pools.steer(&query, &mut resp);
+    assert_eq!(resp, denied);
+
+    // Other types and names are left alone.
+    for (qname, qtype) in [
+        ("www.example", RecordType::MX),
+        ("mail.example", RecordType::A),
+    ] {
+        let query = request(qname, qtype);
+        let mut resp = query.message.response();
+        resp.authority.push(soa());
+        let before = resp.clone();
+        pools.steer(&query, &mut resp);
+        assert_eq!(resp, before);
+    }
+
+    // Through the layer, too.
+    let handler = (|request: &Request| {
+        let mut resp = request.message.response();
+        if request.message.question()?.name != name("www.example") {
+            resp.header.rcode = Rcode::NXDOMAIN;
+        }
+        resp.authority.push(soa());
+        Some(resp)
+    })
+    .with(pools);
+    let resp = handler
+        .handle(&request("www.example", RecordType::A))
+        .unwrap();
+    assert_eq!(addresses(&resp), ["192.0.2.1", "192.0.2.3"]);
+    let resp = handler
+        .handle(&request("gone.example", RecordType::A))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    assert!(resp.answers.is_empty());
+    assert_eq!(resp.authority, [soa()]);
+}
+
+#[test]
+fn pool_configurations_become_pools() {
+    let config = PoolConfig {
+        name: "www.example.".into(),
+        targets: vec![ip("192.0.2.1")],
+        port: Some(80),
+        weighted_targets: vec![PoolTargetConfig {
+            address: ip("192.0.2.2"),
+            weight: 5,
+            tier: 1,
+        }],
+        answers: 1,
+        selection: SelectionKind::ClientSubnet,
+        subnet_prefix_v4: 20,
+        ttl: Some(30),
+        ..PoolConfig::default()
+    };
+    let pool = config.to_pool().unwrap();
+    assert_eq!(
+        pool.targets,
+        [
+            Target::new(ip("192.0.2.1")),
+            Target::new(ip("192.0.2.2")).weight(5).tier(1),
+        ]
+    );
+    assert_eq!(
+        pool.selection,
+        Selection::ClientSubnet {
+            v4_prefix: 20,
+            v6_prefix: 56,
+        }
+    );
+    assert_eq!((pool.answers, pool.ttl), (1, Some(30)));
+
+    let field = |pool: PoolConfig| {
+        let problems = Config::new().pool(pool).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    assert_eq!(
+        field(PoolConfig {
+            subnet_prefix_v4: 33,
+            ..config.clone()
+        }),
+        "pools[0].subnet-prefix-v4"
+    );
+    assert_eq!(
+        field(PoolConfig {
+            subnet_prefix_v6: 129,
+            ..config.clone()
+        }),
+        "pools[0].subnet-prefix-v6"
+    );
+    assert_eq!(
+        field(PoolConfig {
+            targets: Vec::new(),
+            weighted_targets: Vec::new(),
+            ..config
+        }),
+        "pools[0].targets"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn pool_configurations_are_read_from_toml() {
+    let config = Config::from_toml(
+        r#"
+        [[pools]]
+        name = "www.example."
+        targets = ["192.0.2.1"]
+        port = 443
+        weighted-targets = [{ address = "192.0.2.2", weight = 3 }, { address = "198.51.100.1", tier = 1 }]
+        answers = 1
+        selection = "client-subnet"
+        subnet-prefix-v6 = 48
+        ttl = 30
+        "#,
+    )
+    .unwrap();
+    let pool = &config.pools[0];
+    assert_eq!(pool.weighted_targets[0].tier, 0);
+    assert_eq!(pool.weighted_targets[1].weight, 1);
+    assert_eq!(pool.selection, SelectionKind::ClientSubnet);
+    assert_eq!(pool.to_pool().unwrap().targets.len(), 3);
+    assert_eq!(config.validate(), Ok(()));
+}
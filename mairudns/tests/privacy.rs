@@ -0,0 +1,312 @@
+//! Outbound query privacy: what each Client Subnet policy sends, reverse
+//! lookups minimised one ancestor at a time, policies overridden for some
+//! servers, what the forwarder passes on and caches, and how routes are
+//! configured.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::config::{Config, PrivacyConfig, RouteConfig, UpstreamPrivacyConfig};
+use mairudns::message::{EdnsOption, Message, OptionCode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{ClientSubnet, Privacy, QueryOptions, Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Forwarder, Handler, Request, Route};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// A Client Subnet option for `addr`/`prefix`, of IPv4.
+fn ecs(addr: &[u8], prefix: u8) -> EdnsOption {
+    let mut data = vec![0, 1, prefix, 0];
+    data.extend_from_slice(addr);
+    EdnsOption {
+        code: OptionCode::CLIENT_SUBNET,
+        data,
+    }
+}
+
+fn subnet(message: &Message) -> Option<Vec<u8>> {
+    let option = message.edns.as_ref()?.option(OptionCode::CLIENT_SUBNET)?;
+    Some(option.data.clone())
+}
+
+fn resolver(servers: &[&MockServer], privacy: Privacy) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: servers.iter().map(|s| s.addr()).collect(),
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        privacy,
+        ..ResolverConfig::default()
+    })
+}
+
+/// The names and types `server` was asked for, in order.
+fn asked(server: &MockServer) -> Vec<(DomainName, RecordType)> {
+    let received = server.received();
+    let questions = received.iter().map(|r| &r.message.questions[0]);
+    questions.map(|q| (q.name.clone(), q.qtype)).collect()
+}
+
+#[test]
+fn treats_client_subnet_options_as_the_policy_says() {
+    let mut query = Message::query(name("www.example"), RecordType::A);
+    let edns = query.edns.as_mut().unwrap();
+    edns.options.push(ecs(&[192, 0, 2, 77], 32));
+    let policy = |client_subnet| Privacy {
+        client_subnet,
+        minimize_reverse: false,
+    };
+
+    assert!(matches!(
+        policy(ClientSubnet::Forward).outgoing(&query),
+        Cow::Borrowed(_)
+    ));
+    assert_eq!(subnet(&policy(ClientSubnet::Strip).outgoing(&query)), None);
+    assert_eq!(
+        subnet(&policy(ClientSubnet::Suppress).outgoing(&query)),
+        Some(vec![0, 1, 0, 0])
+    );
+    let coarsen = ClientSubnet::Coarsen {
+        v4_prefix: 20,
+        v6_prefix: 56,
+    };
+    assert_eq!(
+        subnet(&policy(coarsen).outgoing(&query)),
+        Some(vec![0, 1, 20, 0, 192, 0, 0])
+    );
+
+    // Queries without one are left be, unless the server is to be told
+    // not to add one.
+    let plain = Message::query(name("www.example"), RecordType::A);
+    assert!(matches!(
+        policy(ClientSubnet::Strip).outgoing(&plain),
+        Cow::Borrowed(_)
+    ));
+    assert_eq!(
+        subnet(&policy(ClientSubnet::Suppress).outgoing(&plain)),
+        Some(vec![0, 1, 0, 0])
+    );
+    assert_eq!(Privacy::default().client_subnet, ClientSubnet::Strip);
+}
+
+#[test]
+fn minimizes_reverse_lookups() {
+    let server = MockServer::builder()
+        .on(
+            name("9.10.in-addr.arpa"),
+            RecordType::NS,
+            Action::Rcode(Rcode::NXDOMAIN),
+        )
+        .start()
+        .unwrap();
+    let resolver = resolver(&[&server], Privacy::STRICT);
+
+    // The address is never sent once an ancestor does not exist.
+    let resp = resolver
+        .query(&name("4.3.9.10.in-addr.arpa"), RecordType::PTR)
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    assert_eq!(resp.questions[0].name, name("4.3.9.10.in-addr.arpa"));
+    assert!(resp.answers.is_empty());
+    assert_eq!(
+        asked(&server),
+        [
+            (name("10.in-addr.arpa"), RecordType::NS),
+            (name("9.10.in-addr.arpa"), RecordType::NS),
+        ]
+    );
+
+    resolver
+        .query(&name("4.3.2.1.in-addr.arpa"), RecordType::PTR)
+        .unwrap();
+    assert_eq!(
+        asked(&server)[2..],
+        [
+            (name("1.in-addr.arpa"), RecordType::NS),
+            (name("2.1.in-addr.arpa"), RecordType::NS),
+            (name("3.2.1.in-addr.arpa"), RecordType::NS),
+            (name("4.3.2.1.in-addr.arpa"), RecordType::PTR),
+        ]
+    );
+
+    // Other names are asked for at once.
+    resolver.query(&name("www.example"), RecordType::A).unwrap();
+    assert_eq!(asked(&server).len(), 7);
+}
+
+/// An upstream answering with the Client Subnet option it was sent, with
+/// a scope covering all of it for names under `scoped.example`.
+fn echoing() -> MockServer {
+    MockServer::builder()
+        .handler(|req: &Request| {
+            let q = &req.message.questions[0];
+            let mut resp = req.message.response();
+            resp.answers.push(Record::new(
+                q.name.clone(),
+                300,
+                RData::A([192, 0, 2, 1].into()),
+            ));
+            if let (Some(edns), Some(mut data)) = (&mut resp.edns, subnet(&req.message)) {
+                if q.name.is_subdomain_of(&name("scoped.example")) {
+                    data[3] = data[2];
+                }
+                edns.options.push(EdnsOption {
+                    code: OptionCode::CLIENT_SUBNET,
+                    data,
+                });
+            }
+            Some(resp)
+        })
+        .start()
+        .unwrap()
+}
+
+#[test]
+fn overrides_the_policy_for_some_servers() {
+    let open = MockServer::builder().then(Action::Drop).start().unwrap();
+    let strict = echoing();
+    let mut config = ResolverConfig {
+        servers: vec![open.addr(), strict.addr()],
+        timeout: Duration::from_millis(200),
+        attempts: 1,
+        privacy: Privacy::OPEN,
+        ..ResolverConfig::default()
+    };
+    config.server_privacy.insert(strict.addr(), Privacy::STRICT);
+    assert_eq!(config.privacy_for(open.addr()), Privacy::OPEN);
+    assert_eq!(config.privacy_for(strict.addr()), Privacy::STRICT);
+
+    let options = QueryOptions {
+        edns_options: vec![ecs(&[192, 0, 2, 77], 32)],
+        ..QueryOptions::default()
+    };
+    Resolver::new(config)
+        .query_with(&name("www.example"), RecordType::A, &options)
+        .unwrap();
+    let sent = |server: &MockServer| subnet(&server.received()[0].message);
+    assert_eq!(sent(&open), Some(vec![0, 1, 32, 0, 192, 0, 2, 77]));
+    assert_eq!(sent(&strict), Some(vec![0, 1, 0, 0]));
+}
+
+#[test]
+fn forwards_client_subnets_and_caches_only_answers_for_all() {
+    let upstream = echoing();
+    let coarsen = Privacy {
+        client_subnet: ClientSubnet::Coarsen {
+            v4_prefix: 24,
+            v6_prefix: 56,
+        },
+        minimize_reverse: false,
+    };
+    let forwarder = Forwarder::new().route(Route::new(name("."), resolver(&[&upstream], coarsen)));
+    let ask = |qname: &str| {
+        let mut message = Message::query(name(qname), RecordType::A);
+        let edns = message.edns.as_mut().unwrap();
+        edns.options.push(ecs(&[192, 0, 2, 77], 32));
+        let request = Request {
+            message,
+            src: "192.0.2.77:53000".parse().unwrap(),
+            protocol: Protocol::Udp,
+            key: None,
+        };
+        forwarder.handle(&request).unwrap()
+    };
+
+    ask("www.example");
+    ask("www.example");
+    assert_eq!(upstream.received().len(), 1);
+    assert_eq!(
+        subnet(&upstream.received()[0].message),
+        Some(vec![0, 1, 24, 0, 192, 0, 2])
+    );
+
+    // Answers for one subnet are asked for again each time.
+    ask("www.scoped.example");
+    ask("www.scoped.example");
+    assert_eq!(upstream.received().len(), 3);
+}
+
+#[test]
+fn routes_configure_privacy_by_upstream() {
+    let (first, second) = (
+        "192.0.2.1:53".parse().unwrap(),
+        "192.0.2.2:53".parse().unwrap(),
+    );
+    let route = RouteConfig {
+        privacy: PrivacyConfig {
+            minimize_reverse: true,
+            upstreams: vec![UpstreamPrivacyConfig {
+                address: second,
+                client_subnet: None,
+                minimize_reverse: Some(false),
+            }],
+            ..PrivacyConfig::default()
+        },
+        ..RouteConfig::new(".", vec![first, second])
+    };
+    let config = route.to_route().unwrap().upstream().config().clone();
+    assert_eq!(
+        config.privacy_for(first),
+        Privacy {
+            client_subnet: ClientSubnet::Strip,
+            minimize_reverse: true,
+        }
+    );
+    assert_eq!(config.privacy_for(second), Privacy::default());
+
+    let mut bad = route.clone();
+    bad.privacy.v4_prefix = 33;
+    bad.privacy.upstreams[0].address = "192.0.2.9:53".parse().unwrap();
+    let problems = Config::new().forwarder(bad).validate().unwrap_err();
+    let fields: Vec<_> = problems.iter().map(|p| p.field.as_str()).collect();
+    assert_eq!(fields, ["forwarders[0].privacy.v4-prefix"]);
+
+    let mut unknown = route;
+    unknown.privacy.upstreams[0].address = "192.0.2.9:53".parse().unwrap();
+    let problem = unknown.to_route().unwrap_err();
+    assert_eq!(
+        problem.to_string(),
+        "privacy.upstreams[0].address: not an upstream of the route"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn reads_privacy_from_toml() {
+    let config = Config::from_toml(
+        r#"
+        [[forwarders]]
+        suffix = "."
+        upstreams = ["192.0.2.1:53", "192.0.2.2:53"]
+        [forwarders.privacy]
+        client-subnet = "coarsen"
+        v4-prefix = 20
+        [[forwarders.privacy.upstreams]]
+        address = "192.0.2.2:53"
+        client-subnet = "suppress"
+        "#,
+    )
+    .unwrap();
+    let route = config.forwarders[0].to_route().unwrap();
+    let upstream = route.upstream().config();
+    assert_eq!(
+        upstream.privacy_for("192.0.2.1:53".parse().unwrap()),
+        Privacy {
+            client_subnet: ClientSubnet::Coarsen {
+                v4_prefix: 20,
+                v6_prefix: 56,
+            },
+            minimize_reverse: false,
+        }
+    );
+    assert_eq!(
+        upstream
+            .privacy_for("192.0.2.2:53".parse().unwrap())
+            .client_subnet,
+        ClientSubnet::Suppress
+    );
+}
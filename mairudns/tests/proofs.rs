@@ -0,0 +1,261 @@
+//! Denial proofs from zones signed in advance: which NSEC or NSEC3
+//! records prove a name error, a missing type, an empty non-terminal, a
+//! wildcard expansion or an unsigned delegation, and the cap on NSEC3
+//! iterations and salts.
+
+#![cfg(feature = "dnssec")]
+
+use std::time::Duration;
+
+use mairudns::dnssec::{
+    add_proofs, denial_records, nsec3_hash, sign_zone, Denial, Nsec, Nsec3, Rrsig, SignError,
+    SigningKey, SigningPolicy, MAX_NSEC3_ITERATIONS,
+};
+use mairudns::encoding::base32hex_decode;
+use mairudns::message::Rcode;
+use mairudns::name::DomainName;
+use mairudns::rr::{Record, RecordType};
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+a.b.c IN TXT \"deep\"
+*.wild IN A 192.0.2.99
+sub IN NS ns.sub
+ns.sub IN A 192.0.2.54
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn signed(denial: Denial) -> Zone {
+    let zone = Zone::from_master(name("example."), ZONE).unwrap();
+    let policy = SigningPolicy {
+        now: Some(1_700_000_000),
+        jitter: Duration::ZERO,
+        denial,
+        max_nsec3_iterations: u16::MAX,
+        nsec3_salt: true,
+        ..SigningPolicy::default()
+    };
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    sign_zone(&zone, &[key], &policy).unwrap()
+}
+
+fn nsec3(iterations: u16, salt: &[u8]) -> Denial {
+    Denial::Nsec3 {
+        iterations,
+        salt: salt.to_vec(),
+        opt_out: false,
+    }
+}
+
+/// The denial records `zone` proves its answer to `qname`/`qtype` with.
+fn proof(zone: &Zone, qname: &str, qtype: RecordType) -> Vec<Record> {
+    let answer = zone.lookup(&name(qname), qtype);
+    denial_records(zone, &name(qname), qtype, &answer)
+}
+
+/// Whether an NSEC in `proof` is at `name`, or covers it when `at` is
+/// false.
+fn nsec_proves(proof: &[Record], name: &DomainName, at: bool) -> bool {
+    proof.iter().any(|rr| match Nsec::from_rdata(&rr.rdata) {
+        Some(_) if at => rr.name == *name,
+        Some(nsec) => rr.name < *name && (*name < nsec.next || nsec.next <= rr.name),
+        None => false,
+    })
+}
+
+/// Whether an NSEC3 in `proof` matches the hash of `name`, or covers it
+/// when `at` is false.
+fn nsec3_proves(proof: &[Record], name: &DomainName, at: bool) -> bool {
+    proof.iter().any(|rr| {
+        let nsec3 = match Nsec3::from_rdata(&rr.rdata) {
+            Some(nsec3) => nsec3,
+            None => return false,
+        };
+        let label = std::str::from_utf8(&rr.name.labels()[0]).unwrap();
+        let owner = base32hex_decode(label).unwrap();
+        let hash = nsec3_hash(name, &nsec3.salt, nsec3.iterations);
+        let next = &nsec3.next_hashed;
+        if at {
+            owner == hash
+        } else if owner < *next {
+            owner < hash && hash < *next
+        } else {
+            owner < hash || hash < *next
+        }
+    })
+}
+
+#[test]
+fn nsec_proofs_cover_what_the_answer_relies_on() {
+    let zone = signed(Denial::Nsec);
+    // A name error: the name and the wildcard at its closest encloser.
+    let nx = proof(&zone, "nope.example.", RecordType::A);
+    assert!(nsec_proves(&nx, &name("nope.example."), false));
+    assert!(nsec_proves(&nx, &name("*.example."), false));
+    assert!(nx.len() <= 2);
+    let deep = proof(&zone, "x.nope.b.c.example.", RecordType::A);
+    assert!(nsec_proves(&deep, &name("nope.b.c.example."), false));
+    assert!(nsec_proves(&deep, &name("*.b.c.example."), false));
+
+    // A missing type at a name: its own NSEC, listing what is there.
+    let nodata = proof(&zone, "www.example.", RecordType::TXT);
+    assert_eq!(nodata.len(), 1);
+    let nsec = Nsec::from_rdata(&nodata[0].rdata).unwrap();
+    assert_eq!(nodata[0].name, name("www.example."));
+    assert!(nsec.has(RecordType::A) && !nsec.has(RecordType::TXT));
+
+    // An empty non-terminal: the NSEC before it, whose next name is below.
+    let empty = proof(&zone, "b.c.example.", RecordType::A);
+    assert!(nsec_proves(&empty, &name("b.c.example."), false));
+    assert!(nsec_proves(&empty, &name("c.example."), false));
+
+    // A wildcard: the expanded name does not exist, and when the
+    // wildcard lacks the type, neither does that.
+    let expanded = proof(&zone, "x.wild.example.", RecordType::A);
+    assert_eq!(expanded.len(), 1);
+    assert!(nsec_proves(&expanded, &name("x.wild.example."), false));
+    let missing = proof(&zone, "x.wild.example.", RecordType::TXT);
+    assert!(nsec_proves(&missing, &name("x.wild.example."), false));
+    assert!(nsec_proves(&missing, &name("*.wild.example."), true));
+
+    // An unsigned delegation: the NSEC at the cut, without DS.
+    let referral = proof(&zone, "www.sub.example.", RecordType::A);
+    assert_eq!(referral.len(), 1);
+    assert_eq!(referral[0].name, name("sub.example."));
+    assert!(!Nsec::from_rdata(&referral[0].rdata)
+        .unwrap()
+        .has(RecordType::DS));
+
+    // An answer relies on nothing.
+    assert!(proof(&zone, "www.example.", RecordType::A).is_empty());
+}
+
+#[test]
+fn nsec3_proofs_give_the_closest_encloser() {
+    for denial in [nsec3(0, b""), nsec3(5, b"\xab\xcd")] {
+        let zone = signed(denial);
+        // RFC 5155 §7.2.2: the closest encloser, the next closer name and
+        // the wildcard at the closest encloser.
+        let nx = proof(&zone, "x.nope.b.c.example.", RecordType::A);
+        assert!(nsec3_proves(&nx, &name("b.c.example."), true));
+        assert!(nsec3_proves(&nx, &name("nope.b.c.example."), false));
+        assert!(nsec3_proves(&nx, &name("*.b.c.example."), false));
+        assert!(nx.len() <= 3);
+
+        // §7.2.3 and §7.2.4: the NSEC3 matching the name, empty
+        // non-terminals included.
+        let nodata = proof(&zone, "www.example.", RecordType::TXT);
+        assert_eq!(nodata.len(), 1);
+        assert!(nsec3_proves(&nodata, &name("www.example."), true));
+        let nsec3 = Nsec3::from_rdata(&nodata[0].rdata).unwrap();
+        assert!(nsec3.has(RecordType::A) && !nsec3.has(RecordType::TXT));
+        let empty = proof(&zone, "b.c.example.", RecordType::A);
+        assert!(nsec3_proves(&empty, &name("b.c.example."), true));
+
+        // §7.2.6: a wildcard expansion proves the next closer name absent.
+        let expanded = proof(&zone, "x.y.wild.example.", RecordType::A);
+        assert!(nsec3_proves(&expanded, &name("y.wild.example."), false));
+        // §7.2.5: a wildcard without the type also matches the wildcard.
+        let missing = proof(&zone, "x.wild.example.", RecordType::TXT);
+        assert!(nsec3_proves(&missing, &name("wild.example."), true));
+        assert!(nsec3_proves(&missing, &name("x.wild.example."), false));
+        assert!(nsec3_proves(&missing, &name("*.wild.example."), true));
+
+        // The unsigned delegation's NSEC3 lists NS and no DS.
+        let referral = proof(&zone, "www.sub.example.", RecordType::A);
+        assert!(nsec3_proves(&referral, &name("sub.example."), true));
+        assert!(!Nsec3::from_rdata(&referral[0].rdata)
+            .unwrap()
+            .has(RecordType::DS));
+    }
+}
+
+#[test]
+fn a_chain_hashed_too_often_gives_one_record() {
+    // Validators treat an NSEC3 record with more iterations than they
+    // accept as insecure (RFC 9276 §3.2); any one will do.
+    let zone = signed(nsec3(MAX_NSEC3_ITERATIONS + 1, b""));
+    for (qname, qtype) in [
+        ("nope.example.", RecordType::A),
+        ("www.example.", RecordType::TXT),
+        ("x.wild.example.", RecordType::A),
+    ] {
+        let proof = proof(&zone, qname, qtype);
+        assert_eq!(proof.len(), 1, "{}", qname);
+        assert_eq!(proof[0].rtype(), RecordType::NSEC3);
+    }
+    assert!(proof(&zone, "www.example.", RecordType::A).is_empty());
+}
+
+#[test]
+fn proofs_and_signatures_complete_answers() {
+    let zone = signed(Denial::Nsec);
+    let signatures = |records: &[Record], rtype: RecordType| {
+        records
+            .iter()
+            .filter_map(|rr| Some((rr.name.clone(), Rrsig::from_rdata(&rr.rdata)?)))
+            .filter(|(_, sig)| sig.type_covered == rtype)
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>()
+    };
+
+    let mut answer = zone.lookup(&name("nope.example."), RecordType::A);
+    add_proofs(&zone, &name("nope.example."), RecordType::A, &mut answer);
+    assert_eq!(answer.rcode, Rcode::NXDOMAIN);
+    assert_eq!(signatures(&answer.authority, RecordType::SOA).len(), 1);
+    assert_eq!(
+        signatures(&answer.authority, RecordType::NSEC).len(),
+        answer
+            .authority
+            .iter()
+            .filter(|rr| rr.rtype() == RecordType::NSEC)
+            .count()
+    );
+
+    // A wildcard's signature is given under the name it was expanded for.
+    let mut answer = zone.lookup(&name("x.wild.example."), RecordType::A);
+    add_proofs(&zone, &name("x.wild.example."), RecordType::A, &mut answer);
+    assert_eq!(
+        signatures(&answer.answers, RecordType::A),
+        [name("x.wild.example.")]
+    );
+
+    // A referral's NS records and glue are not signed, but the proof
+    // that it has no DS records is.
+    let mut answer = zone.lookup(&name("www.sub.example."), RecordType::A);
+    add_proofs(&zone, &name("www.sub.example."), RecordType::A, &mut answer);
+    assert!(!answer.authoritative);
+    assert!(signatures(&answer.authority, RecordType::NS).is_empty());
+    assert_eq!(
+        signatures(&answer.authority, RecordType::NSEC),
+        [name("sub.example.")]
+    );
+    assert!(signatures(&answer.additional, RecordType::A).is_empty());
+}
+
+#[test]
+fn signing_refuses_nsec3_iterations_and_salt_by_default() {
+    let zone = Zone::from_master(name("example."), ZONE).unwrap();
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let sign = |denial| {
+        let policy = SigningPolicy {
+            denial,
+            ..SigningPolicy::default()
+        };
+        sign_zone(&zone, std::slice::from_ref(&key), &policy)
+    };
+    assert!(sign(nsec3(0, b"")).is_ok());
+    assert!(matches!(
+        sign(nsec3(1, b"")),
+        Err(SignError::Nsec3Iterations(1))
+    ));
+    assert!(matches!(sign(nsec3(0, b"\xab")), Err(SignError::Nsec3Salt)));
+}
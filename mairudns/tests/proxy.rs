@@ -0,0 +1,354 @@
+//! Proxies at both ends: queries sent through SOCKS5 and HTTP CONNECT
+//! proxies, proxy URLs, and listeners reading the client's address from
+//! PROXY v2 headers.
+
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::{
+    self, read_framed, write_framed, PoolPolicy, Protocol, Proxy, ProxyKind, TcpPool,
+};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::{QueryOptions, Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Limits, Request, Server};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// A TCP server answering each query with the client's address, as an A
+/// record for IPv4 clients and an AAAA record for IPv6 ones.
+fn echo_client(limits: Limits) -> SocketAddr {
+    let handler = |req: &Request| -> Option<Message> {
+        let mut resp = req.message.response();
+        let rdata = match req.src.ip() {
+            IpAddr::V4(ip) => RData::A(ip),
+            IpAddr::V6(ip) => RData::Aaaa(ip),
+        };
+        resp.answers.push(Record::new(
+            req.message.questions[0].name.clone(),
+            60,
+            rdata,
+        ));
+        Some(resp)
+    };
+    let mut server = Server::new(handler);
+    let addr = server
+        .listen_tcp_with("127.0.0.1:0".parse().unwrap(), limits)
+        .unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+/// Copies between `a` and `b` until either closes.
+fn relay(a: TcpStream, b: TcpStream) {
+    let (mut a_in, mut b_out) = (a.try_clone().unwrap(), b.try_clone().unwrap());
+    thread::spawn(move || {
+        let _ = std::io::copy(&mut a_in, &mut b_out);
+    });
+    let (mut a, mut b) = (a, b);
+    let _ = std::io::copy(&mut b, &mut a);
+}
+
+/// A SOCKS5 proxy that wants user and secret, recording the targets it
+/// connects to. Targets on port 9 it refuses with `reply`.
+fn socks5(seen: Arc<Mutex<Vec<SocketAddr>>>, reply: u8) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut s = stream.unwrap();
+            let seen = seen.clone();
+            thread::spawn(move || {
+                let mut greeting = [0u8; 3];
+                s.read_exact(&mut greeting).unwrap();
+                if greeting != [5, 1, 2] {
+                    // No acceptable method.
+                    s.write_all(&[5, 0xff]).unwrap();
+                    return;
+                }
+                s.write_all(&[5, 2]).unwrap();
+                let mut len = [0u8; 2];
+                s.read_exact(&mut len).unwrap();
+                assert_eq!(len[0], 1);
+                let mut username = vec![0u8; usize::from(len[1])];
+                s.read_exact(&mut username).unwrap();
+                s.read_exact(&mut len[..1]).unwrap();
+                let mut password = vec![0u8; usize::from(len[0])];
+                s.read_exact(&mut password).unwrap();
+                let ok = username == b"user" && password == b"secret";
+                s.write_all(&[1, if ok { 0 } else { 1 }]).unwrap();
+                if !ok {
+                    return;
+                }
+                let mut request = [0u8; 10];
+                s.read_exact(&mut request).unwrap();
+                assert_eq!(request[..4], [5, 1, 0, 1]);
+                let ip = [request[4], request[5], request[6], request[7]];
+                let target = SocketAddr::from((ip, u16::from_be_bytes([request[8], request[9]])));
+                if target.port() == 9 {
+                    s.write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+                    return;
+                }
+                seen.lock().unwrap().push(target);
+                let upstream = TcpStream::connect(target).unwrap();
+                // Bound to a name, which the client skips.
+                s.write_all(&[5, 0, 0, 3, 4, b'p', b'r', b'x', b'y', 0, 0])
+                    .unwrap();
+                relay(s, upstream);
+            });
+        }
+    });
+    addr
+}
+
+/// An HTTP proxy that wants u and p, and refuses targets on port 9.
+fn http_proxy() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut s = stream.unwrap();
+            thread::spawn(move || {
+                let mut head = Vec::new();
+                let mut byte = [0u8];
+                while !head.ends_with(b"\r\n\r\n") {
+                    s.read_exact(&mut byte).unwrap();
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8(head).unwrap();
+                let target: SocketAddr = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+                assert!(head.starts_with(&format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target)));
+                if !head.contains("Proxy-Authorization: Basic dTpw\r\n") {
+                    s.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                        .unwrap();
+                    return;
+                }
+                if target.port() == 9 {
+                    s.write_all(b"HTTP/1.1 502 Bad Gateway\r\n\r\n").unwrap();
+                    return;
+                }
+                let upstream = TcpStream::connect(target).unwrap();
+                s.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                    .unwrap();
+                relay(s, upstream);
+            });
+        }
+    });
+    addr
+}
+
+fn error_of(result: Result<Message, client::Error>) -> String {
+    result.unwrap_err().to_string()
+}
+
+#[test]
+fn reads_and_writes_proxy_urls() {
+    let proxy: Proxy = "socks5://192.0.2.1:1080".parse().unwrap();
+    assert_eq!(proxy, Proxy::socks5("192.0.2.1:1080".parse().unwrap()));
+    assert_eq!(proxy.to_string(), "socks5://192.0.2.1:1080");
+
+    let proxy: Proxy = "http://user:p@ss:word@[2001:db8::1]:3128/".parse().unwrap();
+    assert_eq!(proxy.kind, ProxyKind::Http);
+    assert_eq!(proxy.address, "[2001:db8::1]:3128".parse().unwrap());
+    assert_eq!(
+        proxy.credentials,
+        Some(("user".to_string(), "p@ss:word".to_string()))
+    );
+    // The password is left out wherever the proxy is shown.
+    assert_eq!(proxy.to_string(), "http://user@[2001:db8::1]:3128");
+    assert!(!format!("{:?}", proxy).contains("word"));
+
+    for (url, error) in [
+        ("192.0.2.1:1080", "expected scheme://address"),
+        (
+            "socks4://192.0.2.1:1080",
+            "unsupported proxy scheme \"socks4\"",
+        ),
+        ("http://proxy.example:3128", "invalid proxy address"),
+        ("http://192.0.2.1", "invalid proxy address"),
+    ] {
+        let problem = url.parse::<Proxy>().unwrap_err();
+        assert!(problem.contains(error), "{}: {}", url, problem);
+    }
+}
+
+#[test]
+fn queries_through_socks5() {
+    let server = echo_client(Limits::default());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let address = socks5(seen.clone(), 5);
+    let proxy = Proxy::socks5(address).credentials("user", "secret");
+    let q = Message::query(name("a.example."), RecordType::A);
+    let resp = client::exchange_tcp_via(server, Some(&proxy), &q, TIMEOUT).unwrap();
+    // The server sees the proxy's connection.
+    assert_eq!(resp.answers[0].rdata, RData::A([127, 0, 0, 1].into()));
+    assert_eq!(*seen.lock().unwrap(), vec![server]);
+
+    let wrong = Proxy::socks5(address).credentials("user", "wrong");
+    let error = error_of(client::exchange_tcp_via(server, Some(&wrong), &q, TIMEOUT));
+    assert!(error.contains("rejected the credentials"), "{}", error);
+    let refused = SocketAddr::new(server.ip(), 9);
+    let error = error_of(client::exchange_tcp_via(refused, Some(&proxy), &q, TIMEOUT));
+    assert!(
+        error.contains("connection refused through the SOCKS5 proxy"),
+        "{}",
+        error
+    );
+    let error = error_of(client::exchange_tcp_via(
+        server,
+        Some(&Proxy::socks5(address)),
+        &q,
+        TIMEOUT,
+    ));
+    assert!(
+        error.contains("accepts no offered authentication"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn queries_through_http_connect() {
+    let server = echo_client(Limits::default());
+    let address = http_proxy();
+    let proxy: Proxy = format!("http://u:p@{}", address).parse().unwrap();
+    let q = Message::query(name("a.example."), RecordType::A);
+    let resp = client::exchange_tcp_via(server, Some(&proxy), &q, TIMEOUT).unwrap();
+    assert_eq!(resp.answers.len(), 1);
+
+    let error = error_of(client::exchange_tcp_via(
+        server,
+        Some(&Proxy::http(address)),
+        &q,
+        TIMEOUT,
+    ));
+    assert!(error.contains("HTTP proxy responded 407"), "{}", error);
+    let refused = SocketAddr::new(server.ip(), 9);
+    let error = error_of(client::exchange_tcp_via(refused, Some(&proxy), &q, TIMEOUT));
+    assert!(error.contains("HTTP proxy responded 502"), "{}", error);
+}
+
+#[test]
+fn resolvers_and_pools_go_through_the_proxy() {
+    let server = echo_client(Limits::default());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let proxy = Proxy::socks5(socks5(seen.clone(), 5)).credentials("user", "secret");
+
+    // Proxies carry no UDP, so queries go over TCP, pinned to UDP or not.
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server],
+        proxy: Some(proxy.clone()),
+        ..ResolverConfig::default()
+    });
+    let udp = QueryOptions {
+        protocol: Some(Protocol::Udp),
+        ..QueryOptions::default()
+    };
+    resolver
+        .lookup_with(&name("b.example."), RecordType::A, &udp)
+        .unwrap();
+    resolver.lookup(&name("c.example."), RecordType::A).unwrap();
+    assert_eq!(seen.lock().unwrap().len(), 2);
+
+    // A pool keeps one connection through the proxy for several queries.
+    let pool = TcpPool::with_proxy(PoolPolicy::default(), Some(proxy));
+    let q = Message::query(name("d.example."), RecordType::A);
+    for _ in 0..3 {
+        pool.exchange(server, &q, TIMEOUT).unwrap();
+    }
+    assert_eq!(seen.lock().unwrap().len(), 3);
+}
+
+/// A PROXY v2 PROXY header for a TCP connection from `src`, with a TLV
+/// the listener skips.
+fn proxy_header(src: SocketAddr) -> Vec<u8> {
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    let (family, addresses) = match src.ip() {
+        IpAddr::V4(ip) => (0x11, [&ip.octets()[..], &[127, 0, 0, 1]].concat()),
+        IpAddr::V6(ip) => (
+            0x21,
+            [&ip.octets()[..], &std::net::Ipv6Addr::LOCALHOST.octets()].concat(),
+        ),
+    };
+    let len = addresses.len() + 4 + 3;
+    header.extend_from_slice(&[0x21, family]);
+    header.extend_from_slice(&(len as u16).to_be_bytes());
+    header.extend_from_slice(&addresses);
+    header.extend_from_slice(&src.port().to_be_bytes());
+    header.extend_from_slice(&53u16.to_be_bytes());
+    header.extend_from_slice(&[0xee, 0, 0]);
+    header
+}
+
+/// Sends `header`, then a query, and returns the address the server saw,
+/// or `None` if it closed the connection.
+fn seen_through(server: SocketAddr, header: &[u8]) -> Option<RData> {
+    let q = Message::query(name("a.example."), RecordType::A);
+    let mut stream = TcpStream::connect(server).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut packet = header.to_vec();
+    write_framed(&mut packet, &q.to_wire().unwrap()).unwrap();
+    // The server may close before reading everything.
+    let _ = stream.write_all(&packet);
+    let wire = read_framed(&mut stream).ok()?;
+    Some(Message::from_wire(&wire).unwrap().answers[0].rdata.clone())
+}
+
+#[test]
+fn listeners_take_the_client_from_proxy_headers() {
+    let server = echo_client(Limits {
+        proxy_protocol: true,
+        ..Limits::default()
+    });
+    assert_eq!(
+        seen_through(server, &proxy_header("198.51.100.7:4444".parse().unwrap())),
+        Some(RData::A([198, 51, 100, 7].into()))
+    );
+    assert_eq!(
+        seen_through(server, &proxy_header("[2001:db8::7]:4444".parse().unwrap())),
+        Some(RData::Aaaa("2001:db8::7".parse().unwrap()))
+    );
+    // LOCAL, from the load balancer itself: the peer address stands.
+    assert_eq!(
+        seen_through(server, b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00"),
+        Some(RData::A([127, 0, 0, 1].into()))
+    );
+    // UNSPEC: so does it.
+    assert_eq!(
+        seen_through(server, b"\r\n\r\n\0\r\nQUIT\n\x21\x00\x00\x00"),
+        Some(RData::A([127, 0, 0, 1].into()))
+    );
+
+    // Connections without a well-formed header are closed.
+    let mut truncated = proxy_header("198.51.100.7:4444".parse().unwrap());
+    truncated[15] = 8;
+    truncated.truncate(24);
+    let mut version1 = proxy_header("198.51.100.7:4444".parse().unwrap());
+    version1[12] = 0x11;
+    let mut command = proxy_header("198.51.100.7:4444".parse().unwrap());
+    command[12] = 0x22;
+    for header in [
+        &b""[..],
+        b"PROXY TCP4 198.51.100.7 127.0.0.1 4444 53\r\n",
+        &truncated,
+        &version1,
+        &command,
+    ] {
+        assert_eq!(seen_through(server, header), None, "{:?}", header);
+    }
+
+    // Listeners that do not expect a header see the proxy.
+    let plain = echo_client(Limits::default());
+    assert_eq!(
+        seen_through(plain, b""),
+        Some(RData::A([127, 0, 0, 1].into()))
+    );
+}
@@ -0,0 +1,260 @@
+//! DNS Push Notifications: the SUBSCRIBE, PUSH, UNSUBSCRIBE and RECONFIRM
+//! TLVs on the wire, subscriptions to a served zone that are told of its
+//! changes, the limits on what a session may subscribe to, and the
+//! settings that turn push on.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::{DsoSession, Protocol};
+use mairudns::config::{Config, ServerSettings};
+use mairudns::dso::{DsoType, Keepalive, Tlv};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::push::{self, Change, Subscription};
+use mairudns::rr::{RData, Record, RecordClass, RecordType};
+use mairudns::server::{Authority, PushPolicy, Server, SharedZone};
+use mairudns::zone::Zone;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+const ZONE: &str = "\
+$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 300
+@ IN NS ns
+ns IN A 192.0.2.53
+svc IN A 192.0.2.1
+svc IN AAAA 2001:db8::1
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn svc(last: u8) -> Record {
+    Record::new(
+        name("svc.example.com."),
+        300,
+        RData::A([192, 0, 2, last].into()),
+    )
+}
+
+/// Serves the zone with push on, polling for changes every 50 ms, and
+/// returns the zone and the address of the TCP listener.
+fn start(max_subscriptions: usize) -> (SharedZone, SocketAddr) {
+    let authority = Arc::new(Authority::new());
+    let zone = authority.insert(Zone::from_master(name("example.com."), ZONE).unwrap());
+    let mut server = Server::new(authority);
+    server.set_push(Some(PushPolicy {
+        poll_interval: Duration::from_millis(50),
+        max_subscriptions,
+    }));
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Tcp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    (zone, addr)
+}
+
+fn session(addr: SocketAddr) -> DsoSession {
+    DsoSession::connect(addr, None, &Keepalive::default(), TIMEOUT).unwrap()
+}
+
+#[test]
+fn push_tlvs_round_trip() {
+    let added = svc(1);
+    let changes = vec![
+        Change::Add(added.clone()),
+        Change::Remove(svc(2)),
+        Change::RemoveAll {
+            name: name("svc.example.com."),
+            rtype: RecordType::ANY,
+            class: RecordClass::IN,
+        },
+    ];
+    let tlvs = push::push_tlvs(&changes, 1000).unwrap();
+    assert_eq!(tlvs.len(), 1);
+    assert_eq!(tlvs[0].dtype, DsoType::PUSH);
+    // A removal's TTL is not kept: it stands for the removal on the wire.
+    let back = push::parse_push(&tlvs[0]).unwrap();
+    assert_eq!(back[0], changes[0]);
+    assert!(matches!(&back[1], Change::Remove(r) if r.rdata == svc(2).rdata));
+    assert_eq!(back[2], changes[2]);
+
+    // Small TLVs carry one change each, but never none.
+    let tlvs = push::push_tlvs(&changes, 10).unwrap();
+    assert_eq!(tlvs.len(), 3);
+    let back: Vec<Change> = tlvs
+        .iter()
+        .flat_map(|t| push::parse_push(t).unwrap())
+        .collect();
+    assert_eq!(back.len(), 3);
+    assert!(push::parse_push(&Tlv::new(DsoType::PUSH, Vec::new())).is_err());
+    // TTLs above 2^31 but for the two that mean removal are reserved.
+    let mut reserved = push::push_tlvs(&changes[..1], 1000).unwrap().remove(0);
+    let ttl_at = reserved.data.len() - 4 - 2 - 4;
+    reserved.data[ttl_at..ttl_at + 4].copy_from_slice(&0x8000_0000u32.to_be_bytes());
+    assert!(push::parse_push(&reserved).is_err());
+
+    let subscription = Subscription::new(name("svc.example.com."), RecordType::A);
+    assert_eq!(
+        Subscription::from_tlv(&subscription.to_tlv()).unwrap(),
+        subscription
+    );
+    assert!(subscription.matches(&added));
+    assert!(!subscription.matches(&Record::new(
+        name("svc.example.com."),
+        300,
+        RData::Aaaa("2001:db8::1".parse().unwrap()),
+    )));
+    let mut trailing = subscription.to_tlv();
+    trailing.data.push(0);
+    assert!(Subscription::from_tlv(&trailing).is_err());
+    let any = Subscription::new(name("svc.example.com."), RecordType::ANY);
+    assert!(any.matches(&added));
+
+    assert_eq!(
+        push::parse_unsubscribe(&push::unsubscribe_tlv(77)).unwrap(),
+        77
+    );
+    let reconfirm = push::reconfirm_tlv(&added).unwrap();
+    let back = push::parse_reconfirm(&reconfirm).unwrap();
+    assert_eq!(back.ttl, 0);
+    assert_eq!(back.rdata, added.rdata);
+    assert!(push::parse_reconfirm(&subscription.to_tlv()).is_err());
+}
+
+#[test]
+fn subscribers_are_told_of_changes_until_they_unsubscribe() {
+    let (zone, addr) = start(64);
+    let mut session = session(addr);
+    let subscription = Subscription::new(name("svc.example.com."), RecordType::A);
+    let id = session.subscribe(&subscription, TIMEOUT).unwrap();
+
+    // What is there now comes first.
+    assert_eq!(
+        session.next_push(TIMEOUT).unwrap(),
+        vec![Change::Add(svc(1))]
+    );
+    // Subscribed sessions are never inactive.
+    assert!(!session.inactivity_expired());
+
+    // A change of address: the old one removed, the new one added. The
+    // AAAA record is not subscribed to.
+    {
+        let mut zone = zone.write().unwrap();
+        assert!(zone.remove(&svc(1)));
+        zone.insert(svc(2)).unwrap();
+        zone.insert(Record::new(
+            name("svc.example.com."),
+            300,
+            RData::Aaaa("2001:db8::2".parse().unwrap()),
+        ))
+        .unwrap();
+    }
+    let mut changes = session.next_push(TIMEOUT).unwrap();
+    if changes.len() < 2 {
+        changes.extend(session.next_push(TIMEOUT).unwrap());
+    }
+    assert_eq!(changes.len(), 2);
+    assert!(matches!(&changes[0], Change::Remove(r) if r.rdata == svc(1).rdata));
+    assert_eq!(changes[1], Change::Add(svc(2)));
+
+    // Queries still work on the session.
+    let query = Message::query(name("ns.example.com."), RecordType::A);
+    assert_eq!(session.query(&query, TIMEOUT).unwrap().answers.len(), 1);
+
+    // Once unsubscribed, no more.
+    session.unsubscribe(id).unwrap();
+    zone.write().unwrap().insert(svc(3)).unwrap();
+    assert!(session.next_push(Duration::from_millis(300)).is_err());
+}
+
+#[test]
+fn reconfirmed_records_that_are_gone_are_removed() {
+    let (zone, addr) = start(64);
+    let mut session = session(addr);
+    let subscription = Subscription::new(name("svc.example.com."), RecordType::ANY);
+    session.subscribe(&subscription, TIMEOUT).unwrap();
+    let first = session.next_push(TIMEOUT).unwrap();
+    assert_eq!(first.len(), 2);
+
+    zone.write().unwrap().remove(&svc(1));
+    session.reconfirm(&svc(1)).unwrap();
+    let changes = session.next_push(TIMEOUT).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(matches!(&changes[0], Change::Remove(r) if r.rdata == svc(1).rdata));
+}
+
+#[test]
+fn sessions_are_held_to_their_subscriptions() {
+    let (_zone, addr) = start(2);
+    let mut subscriber = session(addr);
+    let sub = |qname: &str, rtype| Subscription::new(name(qname), rtype);
+    subscriber
+        .subscribe(&sub("svc.example.com.", RecordType::A), TIMEOUT)
+        .unwrap();
+    // The same subscription twice, and meta types other than ANY, are
+    // malformed; names that do not exist can be waited on.
+    assert!(subscriber
+        .subscribe(&sub("svc.example.com.", RecordType::A), TIMEOUT)
+        .is_err());
+    assert!(subscriber
+        .subscribe(&sub("svc.example.com.", RecordType::AXFR), TIMEOUT)
+        .is_err());
+    subscriber
+        .subscribe(&sub("later.example.com.", RecordType::A), TIMEOUT)
+        .unwrap();
+    // Beyond the limit, refused.
+    assert!(subscriber
+        .subscribe(&sub("svc.example.com.", RecordType::AAAA), TIMEOUT)
+        .is_err());
+
+    // Servers without push answer DSOTYPENI.
+    let mut server = Server::new(Arc::new(Authority::new()));
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Tcp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    let err = session(addr)
+        .subscribe(&sub("svc.example.com.", RecordType::A), TIMEOUT)
+        .unwrap_err();
+    assert!(err.to_string().contains("DSOTYPENI"), "{}", err);
+}
+
+#[test]
+fn push_settings_come_from_the_configuration() {
+    assert_eq!(ServerSettings::default().to_push(), None);
+    let settings = ServerSettings {
+        push: true,
+        push_poll_interval_ms: 250,
+        push_max_subscriptions: 8,
+        ..ServerSettings::default()
+    };
+    assert_eq!(
+        settings.to_push(),
+        Some(PushPolicy {
+            poll_interval: Duration::from_millis(250),
+            max_subscriptions: 8,
+        })
+    );
+
+    let mut config = Config::new();
+    config.server = ServerSettings {
+        push_poll_interval_ms: 0,
+        ..settings
+    };
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].field, "server.push-poll-interval-ms");
+}
@@ -0,0 +1,129 @@
+//! Per-query options: the header bits and EDNS fields they set, and the
+//! timeout and transport they pin, as a mock server sees them.
+
+use std::time::{Duration, Instant};
+
+use mairudns::client::{self, Protocol};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Error, QueryOptions, Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn server() -> MockServer {
+    let host = name("host.example");
+    MockServer::builder()
+        .answer(
+            host.clone(),
+            RecordType::A,
+            vec![Record::new(host, 300, RData::A([192, 0, 2, 1].into()))],
+        )
+        .start()
+        .unwrap()
+}
+
+fn resolver(server: &MockServer, timeout: Duration) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout,
+        attempts: 1,
+        ..ResolverConfig::default()
+    })
+}
+
+fn last_query(server: &MockServer) -> Message {
+    server.received().last().unwrap().message.clone()
+}
+
+#[test]
+fn defaults_ask_for_recursion_only() {
+    let query = QueryOptions::default().message(name("host.example"), RecordType::A);
+    assert!(query.header.rd);
+    assert!(!query.header.cd);
+    assert!(!query.edns.unwrap().dnssec_ok);
+}
+
+#[test]
+fn sets_the_do_and_cd_bits_and_the_payload_size() {
+    let server = server();
+    let options = QueryOptions {
+        dnssec_ok: true,
+        checking_disabled: true,
+        recursion_desired: false,
+        udp_size: Some(1232),
+        ..QueryOptions::default()
+    };
+    resolver(&server, Duration::from_secs(1))
+        .query_with(&name("host.example"), RecordType::A, &options)
+        .unwrap();
+    let query = last_query(&server);
+    assert!(query.header.cd);
+    assert!(!query.header.rd);
+    let edns = query.edns.unwrap();
+    assert!(edns.dnssec_ok);
+    assert_eq!(edns.udp_size, 1232);
+}
+
+#[test]
+fn pins_the_transport() {
+    let server = server();
+    let options = QueryOptions {
+        protocol: Some(Protocol::Tcp),
+        ..QueryOptions::default()
+    };
+    let records = resolver(&server, Duration::from_secs(1))
+        .lookup_with(&name("host.example"), RecordType::A, &options)
+        .unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(server.count(Protocol::Tcp), 1);
+    assert_eq!(server.count(Protocol::Udp), 0);
+}
+
+#[test]
+fn pins_tls_only_to_an_upgrade() {
+    let server = server();
+    let options = QueryOptions {
+        protocol: Some(Protocol::Tls),
+        ..QueryOptions::default()
+    };
+    let result = resolver(&server, Duration::from_secs(1)).lookup_with(
+        &name("host.example"),
+        RecordType::A,
+        &options,
+    );
+    assert!(result.is_err());
+    assert!(server.received().is_empty());
+}
+
+#[test]
+fn falls_back_to_tcp_on_truncation_unless_pinned() {
+    let server = server();
+    server.push(Action::Truncate);
+    resolver(&server, Duration::from_secs(1))
+        .query(&name("host.example"), RecordType::A)
+        .unwrap();
+    assert_eq!(server.count(Protocol::Udp), 1);
+    assert_eq!(server.count(Protocol::Tcp), 1);
+}
+
+#[test]
+fn overrides_the_timeout() {
+    let server = server();
+    server.push(Action::Drop);
+    let options = QueryOptions {
+        timeout: Some(Duration::from_millis(100)),
+        ..QueryOptions::default()
+    };
+    let start = Instant::now();
+    let result = resolver(&server, Duration::from_secs(10)).query_with(
+        &name("host.example"),
+        RecordType::A,
+        &options,
+    );
+    assert!(matches!(result, Err(Error::Client(client::Error::Timeout))));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
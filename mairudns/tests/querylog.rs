@@ -0,0 +1,349 @@
+//! Query logging: the JSON lines and dnstap streams a logger writes, the
+//! redaction and sampling of the server layer, and reading a log back for
+//! the popular questions.
+
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::querylog::{self, Entry, Format, Logger, Redaction};
+use mairudns::rr::RecordType;
+use mairudns::server::{Handler, HandlerExt, QueryLog, Request};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// A writer whose output can be read back while the logger holds it.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Shared {
+    fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn entry(client: &str) -> Entry {
+    let mut query = Message::query(name("host.example"), RecordType::AAAA);
+    query.header.id = 0;
+    Entry {
+        time: UNIX_EPOCH + Duration::from_secs(951_782_400) + Duration::from_micros(1500),
+        duration: Duration::from_micros(250),
+        client: client.parse().unwrap(),
+        protocol: Protocol::Udp,
+        response: Some(query.response()),
+        query,
+    }
+}
+
+fn request(qname: &str, src: &str) -> Request {
+    Request {
+        message: Message::query(name(qname), RecordType::A),
+        src: src.parse().unwrap(),
+        protocol: Protocol::Tcp,
+        key: None,
+    }
+}
+
+fn answer(request: &Request) -> Option<Message> {
+    Some(request.message.response())
+}
+
+#[test]
+fn json_lines_carry_the_question_and_the_outcome() {
+    let line = querylog::json_line(&entry("192.0.2.7:5300"));
+    assert_eq!(
+        line,
+        "{\"time\":\"2000-02-29T00:00:00.001500Z\",\"client\":\"192.0.2.7\",\
+         \"port\":5300,\"protocol\":\"udp\",\"id\":0,\"opcode\":\"QUERY\",\
+         \"qname\":\"host.example.\",\"qtype\":\"AAAA\",\"qclass\":\"IN\",\
+         \"rcode\":\"NOERROR\",\"answers\":0,\"truncated\":false,\
+         \"duration_us\":250}\n"
+    );
+}
+
+#[test]
+fn json_lines_escape_names_and_mark_unanswered_queries() {
+    let mut entry = entry("[2001:db8::1]:0");
+    entry.query = Message::query(name("a\"b\\.example"), RecordType::A);
+    entry.response = None;
+    let line = querylog::json_line(&entry);
+    assert!(line.contains("\"client\":\"2001:db8::1\",\"protocol\""));
+    assert!(!line.contains("\"port\""));
+    assert!(line.contains("\"rcode\":null"));
+    // The name is escaped once in its presentation form and again for JSON.
+    assert!(line.contains(r#""qname":"a\\\"b\\.example.""#), "{}", line);
+    let popular = querylog::popular_questions(line.as_bytes(), 1).unwrap();
+    assert_eq!(
+        popular,
+        vec![(entry.query.questions[0].name.clone(), RecordType::A)]
+    );
+}
+
+#[test]
+fn redaction_keeps_only_the_network() {
+    let anonymize = Redaction::anonymize();
+    assert_eq!(
+        anonymize.apply("192.0.2.77:5353".parse().unwrap()),
+        "192.0.2.0:0".parse().unwrap()
+    );
+    assert_eq!(
+        anonymize.apply("[2001:db8:1:2::9]:53".parse().unwrap()),
+        "[2001:db8:1::]:0".parse().unwrap()
+    );
+    let whole = Redaction::default();
+    assert_eq!(
+        whole.apply("192.0.2.77:5353".parse().unwrap()),
+        "192.0.2.77:5353".parse().unwrap()
+    );
+}
+
+#[test]
+fn the_layer_logs_redacted_clients_and_passes_responses_through() {
+    let out = Shared::default();
+    let logger = Arc::new(Logger::new(out.clone(), Format::JsonLines).unwrap());
+    let mut config = QueryLog::new(logger.clone());
+    config.redaction = Redaction::anonymize();
+    let handler = (answer as fn(&Request) -> Option<Message>).with(config);
+    let resp = handler
+        .handle(&request("host.example", "192.0.2.77:5353"))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    drop(handler);
+    drop(logger);
+    let log = String::from_utf8(out.contents()).unwrap();
+    assert_eq!(log.lines().count(), 1);
+    assert!(log.contains("\"client\":\"192.0.2.0\",\"protocol\":\"tcp\""));
+}
+
+#[test]
+fn the_layer_samples_requests() {
+    let out = Shared::default();
+    let logger = Arc::new(Logger::new(out.clone(), Format::JsonLines).unwrap());
+    let mut config = QueryLog::new(logger.clone());
+    config.sample_rate = 0.0;
+    let handler = (answer as fn(&Request) -> Option<Message>).with(config);
+    for _ in 0..50 {
+        assert!(handler
+            .handle(&request("host.example", "192.0.2.1:53"))
+            .is_some());
+    }
+    drop(handler);
+    drop(logger);
+    assert!(out.contents().is_empty());
+}
+
+/// A writer that holds up the logger's thread until released.
+struct Stalled {
+    release: mpsc::Receiver<()>,
+    out: Shared,
+}
+
+impl Write for Stalled {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = self.release.recv();
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn drops_entries_when_the_writer_falls_behind() {
+    let (release, stalled) = mpsc::channel();
+    let out = Shared::default();
+    let writer = Stalled {
+        release: stalled,
+        out: out.clone(),
+    };
+    let logger = Logger::new(writer, Format::JsonLines).unwrap();
+    let total = 5000;
+    for _ in 0..total {
+        logger.log(entry("192.0.2.1:53"));
+    }
+    let dropped = logger.dropped();
+    assert!(dropped > 0);
+    drop(release);
+    drop(logger);
+    let written = String::from_utf8(out.contents()).unwrap().lines().count() as u64;
+    assert_eq!(written + dropped, total);
+}
+
+/// Splits a Frame Streams stream into its frames, with control frames as
+/// `Err(type)`.
+fn frames(mut stream: &[u8]) -> Vec<Result<Vec<u8>, u32>> {
+    let mut frames = Vec::new();
+    while !stream.is_empty() {
+        let len = u32::from_be_bytes(stream[..4].try_into().unwrap()) as usize;
+        if len == 0 {
+            let len = u32::from_be_bytes(stream[4..8].try_into().unwrap()) as usize;
+            let kind = u32::from_be_bytes(stream[8..12].try_into().unwrap());
+            frames.push(Err(kind));
+            stream = &stream[8 + len..];
+        } else {
+            frames.push(Ok(stream[4..4 + len].to_vec()));
+            stream = &stream[4 + len..];
+        }
+    }
+    frames
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn dnstap_files_are_unidirectional_streams() {
+    let path = std::env::temp_dir().join(format!("querylog-{}.dnstap", std::process::id()));
+    let logger = Logger::create(&path, Format::Dnstap).unwrap();
+    logger.log(entry("192.0.2.7:5300"));
+    drop(logger);
+    let stream = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let frames = frames(&stream);
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[0], Err(2));
+    assert_eq!(frames[3], Err(3));
+    assert!(contains(&stream[..42], b"protobuf:dnstap.Dnstap"));
+    let query = frames[1].as_ref().unwrap();
+    let response = frames[2].as_ref().unwrap();
+    assert_eq!(
+        querylog::dnstap_frames(&entry("192.0.2.7:5300")),
+        vec![query.clone(), response.clone()]
+    );
+    // Both carry the client address; only the response carries its wire
+    // form as well as the query's.
+    assert!(contains(query, &[192, 0, 2, 7]));
+    let wire = entry("192.0.2.7:5300").response.unwrap().to_wire().unwrap();
+    assert!(!contains(query, &wire));
+    assert!(contains(response, &wire));
+}
+
+#[test]
+fn dnstap_frames_skip_the_response_of_unanswered_queries() {
+    let mut entry = entry("192.0.2.7:5300");
+    entry.response = None;
+    assert_eq!(querylog::dnstap_frames(&entry).len(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn dnstap_sockets_do_the_bidirectional_handshake() {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("querylog-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let collector = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        loop {
+            let mut word = [0u8; 4];
+            stream.read_exact(&mut word).unwrap();
+            let mut len = u32::from_be_bytes(word) as usize;
+            let control = len == 0;
+            if control {
+                stream.read_exact(&mut word).unwrap();
+                len = u32::from_be_bytes(word) as usize;
+            }
+            let mut frame = vec![0u8; len];
+            stream.read_exact(&mut frame).unwrap();
+            if !control {
+                received.push(Ok(frame));
+                continue;
+            }
+            let kind = u32::from_be_bytes(frame[..4].try_into().unwrap());
+            received.push(Err(kind));
+            match kind {
+                // READY is answered with ACCEPT, STOP with FINISH.
+                4 => stream
+                    .write_all(&[0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 1])
+                    .unwrap(),
+                3 => {
+                    stream
+                        .write_all(&[0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 5])
+                        .unwrap();
+                    return received;
+                }
+                _ => {}
+            }
+        }
+    });
+    let logger = Logger::connect_dnstap(&path).unwrap();
+    logger.log(entry("192.0.2.7:5300"));
+    drop(logger);
+    let received = collector.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(received.len(), 5);
+    assert_eq!(received[0], Err(4));
+    assert_eq!(received[1], Err(2));
+    assert!(received[2].is_ok() && received[3].is_ok());
+    assert_eq!(received[4], Err(3));
+}
+
+#[cfg(unix)]
+#[test]
+fn dnstap_sockets_need_a_collector_that_accepts() {
+    use std::os::unix::net::UnixListener;
+
+    let path = std::env::temp_dir().join(format!("querylog-{}-reject.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let collector = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut ready = [0u8; 12];
+        stream.read_exact(&mut ready).unwrap();
+        // FINISH in place of ACCEPT.
+        stream
+            .write_all(&[0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 5])
+            .unwrap();
+    });
+    let result = Logger::connect_dnstap(&path);
+    collector.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn popular_questions_rank_logs_and_lists() {
+    let mut log = String::new();
+    for (qname, count) in &[("b.example", 3), ("a.example", 3), ("c.example", 1)] {
+        let mut entry = entry("192.0.2.1:53");
+        entry.query = Message::query(name(qname), RecordType::A);
+        for _ in 0..*count {
+            log.push_str(&querylog::json_line(&entry));
+        }
+    }
+    log.push_str("# hand-written\n\nC.Example\nc.example MX\nhost.example bogus\n");
+    let popular = querylog::popular_questions(log.as_bytes(), 3).unwrap();
+    assert_eq!(
+        popular,
+        vec![
+            (name("a.example"), RecordType::A),
+            (name("b.example"), RecordType::A),
+            (name("c.example"), RecordType::A),
+        ]
+    );
+    let all = querylog::popular_questions(log.as_bytes(), 10).unwrap();
+    assert_eq!(all.len(), 4);
+    assert_eq!(all[3], (name("c.example"), RecordType::MX));
+}
@@ -0,0 +1,239 @@
+//! Client quotas: what a query over quota is answered with, which queries
+//! count and against which network, queries in progress held to their
+//! limit, the metrics of refused queries, and the configuration that
+//! builds the quota.
+
+use std::net::SocketAddr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::addr::IpSet;
+use mairudns::client::Protocol;
+use mairudns::config::{Config, QuotaActionConfig, QuotaConfig};
+use mairudns::message::{ExtendedError, Message, Rcode};
+use mairudns::metrics::Metrics;
+use mairudns::rr::RecordType;
+use mairudns::server::{ClientQuota, Handler, HandlerExt, QuotaAction, Request};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn request(src: &str, rd: bool, protocol: Protocol) -> Request {
+    let mut message = Message::query("www.example.".parse().unwrap(), RecordType::A);
+    message.header.rd = rd;
+    Request {
+        message,
+        src: src.parse::<SocketAddr>().unwrap(),
+        protocol,
+        key: None,
+    }
+}
+
+fn recursive(src: &str) -> Request {
+    request(src, true, Protocol::Udp)
+}
+
+fn echo(request: &Request) -> Option<Message> {
+    Some(request.message.response())
+}
+
+/// Whether a query from `src` gets through, rather than being refused.
+fn admitted(handler: &impl Handler, src: &str) -> bool {
+    handler.handle(&recursive(src)).unwrap().header.rcode == Rcode::NOERROR
+}
+
+#[test]
+fn queries_over_quota_get_the_configured_action() {
+    let handler = echo.with(ClientQuota::new(1));
+    assert!(admitted(&handler, "192.0.2.1:5300"));
+    let resp = handler.handle(&recursive("192.0.2.1:5300")).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+    let errors: Vec<_> = resp.extended_errors().collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, ExtendedError::OTHER);
+    assert_eq!(errors[0].text, "client query rate exceeded");
+
+    // Truncated over UDP, so the client comes back over TCP; there, it
+    // has nowhere further to go and gets SERVFAIL.
+    let handler = echo.with(ClientQuota::new(1).action(QuotaAction::Truncate));
+    assert!(admitted(&handler, "192.0.2.1:5300"));
+    let resp = handler.handle(&recursive("192.0.2.1:5300")).unwrap();
+    assert!(resp.header.tc);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+    let resp = handler
+        .handle(&request("192.0.2.1:5300", true, Protocol::Tcp))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+
+    let handler = echo.with(ClientQuota::new(1).action(QuotaAction::Drop));
+    assert!(admitted(&handler, "192.0.2.1:5300"));
+    assert!(handler.handle(&recursive("192.0.2.1:5300")).is_none());
+}
+
+#[test]
+fn quotas_are_kept_per_network_for_recursive_queries() {
+    let mut quota = ClientQuota::new(1);
+    quota.ipv4_prefix_len = 24;
+    quota.exempt = IpSet::new();
+    quota.exempt.insert("198.51.100.0/24".parse().unwrap());
+    let handler = echo.with(quota);
+
+    assert!(admitted(&handler, "192.0.2.1:5300"));
+    // The same /24, from another address and as a mapped IPv6 address.
+    assert!(!admitted(&handler, "192.0.2.2:5300"));
+    assert!(!admitted(&handler, "[::ffff:192.0.2.3]:5300"));
+    // Another network has a quota of its own.
+    assert!(admitted(&handler, "203.0.113.1:5300"));
+    // IPv6 clients are grouped by /64.
+    assert!(admitted(&handler, "[2001:db8:0:1::1]:5300"));
+    assert!(!admitted(&handler, "[2001:db8:0:1::2]:5300"));
+    assert!(admitted(&handler, "[2001:db8:0:2::1]:5300"));
+    // Exempt clients are never limited.
+    for _ in 0..10 {
+        assert!(admitted(&handler, "198.51.100.7:5300"));
+    }
+
+    // Queries not asking for recursion are left to response rate
+    // limiting, unless all queries are counted.
+    let no_rd = request("192.0.2.1:5300", false, Protocol::Udp);
+    assert_eq!(handler.handle(&no_rd).unwrap().header.rcode, Rcode::NOERROR);
+    let mut quota = ClientQuota::new(1);
+    quota.recursion_only = false;
+    let handler = echo.with(quota);
+    assert_eq!(handler.handle(&no_rd).unwrap().header.rcode, Rcode::NOERROR);
+    assert_eq!(
+        handler.handle(&no_rd).unwrap().header.rcode,
+        Rcode::SERVFAIL
+    );
+}
+
+#[test]
+fn queries_in_progress_are_held_to_the_limit() {
+    // The handler holds each query until told to answer it.
+    let (started, starts) = mpsc::channel();
+    let (go, wait) = mpsc::channel::<()>();
+    let wait = Arc::new(Mutex::new(wait));
+    let slow = move |request: &Request| {
+        started.send(()).unwrap();
+        wait.lock().unwrap().recv().unwrap();
+        Some(request.message.response())
+    };
+    let metrics = Arc::new(Metrics::new());
+    let mut quota = ClientQuota::new(0).max_concurrent(2);
+    quota.metrics = Some(metrics.clone());
+    let handler = Arc::new(slow.with(quota));
+
+    let queries: Vec<_> = (0..2)
+        .map(|_| {
+            let handler = handler.clone();
+            thread::spawn(move || handler.handle(&recursive("192.0.2.1:5300")).unwrap())
+        })
+        .collect();
+    for _ in 0..2 {
+        starts.recv_timeout(TIMEOUT).unwrap();
+    }
+
+    // A third is refused at once, saying why; another client is not.
+    let resp = handler.handle(&recursive("192.0.2.1:5300")).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+    assert_eq!(
+        resp.extended_errors().next().unwrap().text,
+        "too many client queries in progress"
+    );
+    let other = {
+        let handler = handler.clone();
+        thread::spawn(move || handler.handle(&recursive("203.0.113.1:5300")).unwrap())
+    };
+    starts.recv_timeout(TIMEOUT).unwrap();
+
+    for _ in 0..3 {
+        go.send(()).unwrap();
+    }
+    for query in queries {
+        assert_eq!(query.join().unwrap().header.rcode, Rcode::NOERROR);
+    }
+    assert_eq!(other.join().unwrap().header.rcode, Rcode::NOERROR);
+
+    // Finished queries no longer count.
+    go.send(()).unwrap();
+    assert!(admitted(&*handler, "192.0.2.1:5300"));
+
+    let snapshot = metrics.snapshot();
+    assert_eq!(snapshot.quota_concurrency_exceeded, 1);
+    assert_eq!(snapshot.quota_rate_exceeded, 0);
+    assert!(metrics
+        .to_prometheus()
+        .contains("dns_client_quota_exceeded_total{limit=\"concurrency\"} 1"));
+}
+
+#[test]
+fn quotas_come_from_the_configuration() {
+    let config = QuotaConfig {
+        queries_per_second: 20,
+        max_concurrent: 4,
+        action: QuotaActionConfig::Truncate,
+        all_queries: true,
+        ipv4_prefix_len: 24,
+        exempt: vec!["10.0.0.0/8".into()],
+        ..QuotaConfig::default()
+    };
+    let quota = config.to_quota().unwrap();
+    assert_eq!(quota.queries_per_second, 20);
+    assert_eq!(quota.burst, 20);
+    assert_eq!(quota.max_concurrent, 4);
+    assert_eq!(quota.action, QuotaAction::Truncate);
+    assert!(!quota.recursion_only);
+    assert_eq!(quota.ipv4_prefix_len, 24);
+    assert!(quota.exempt.contains(&"10.1.2.3".parse().unwrap()));
+    let quota = QuotaConfig {
+        burst: Some(50),
+        ..config
+    }
+    .to_quota()
+    .unwrap();
+    assert_eq!(quota.burst, 50);
+
+    // Problems are reported against the field that has them.
+    let field = |quota: QuotaConfig| {
+        let problems = Config::new().quota(quota).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    let bad = |f: fn(&mut QuotaConfig)| {
+        let mut quota = QuotaConfig::default();
+        f(&mut quota);
+        field(quota)
+    };
+    assert_eq!(bad(|q| q.ipv4_prefix_len = 33), "quota.ipv4-prefix-len");
+    assert_eq!(bad(|q| q.ipv6_prefix_len = 129), "quota.ipv6-prefix-len");
+    assert_eq!(bad(|q| q.burst = Some(0)), "quota.burst");
+    assert_eq!(
+        bad(|q| q.exempt = vec!["10.0.0.0/8".into(), "nope".into()]),
+        "quota.exempt[1]"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn quotas_read_from_toml() {
+    let config = Config::from_toml(
+        "[quota]\n\
+         queries-per-second = 100\n\
+         burst = 200\n\
+         action = \"drop\"\n\
+         exempt = [\"192.0.2.0/24\"]\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.quota,
+        Some(QuotaConfig {
+            queries_per_second: 100,
+            burst: Some(200),
+            action: QuotaActionConfig::Drop,
+            exempt: vec!["192.0.2.0/24".into()],
+            ..QuotaConfig::default()
+        })
+    );
+    assert!(Config::from_toml("[quota]\naction = \"ignore\"\n").is_err());
+}
@@ -0,0 +1,231 @@
+//! The serde form of records, RRsets and zones: typed data objects, the
+//! zone file syntax still read as data, errors for data that does not
+//! fit its type, and the JSON Schema describing it all.
+
+#![cfg(feature = "api")]
+
+use mairudns::interop::json_schema;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, RRset, Record, RecordClass, RecordType, Soa};
+use mairudns::zone::Zone;
+use serde_json::{json, Value};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn parse<T: serde::de::DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+const ZONE: &str = "$TTL 300
+@ SOA ns hostmaster 1 7200 900 1209600 300
+@ NS ns
+ns A 192.0.2.1
+ns AAAA 2001:db8::1
+@ MX 10 mail
+@ TXT \"v=spf1 \\\"-all\\\"\" \"\\255\"
+www CNAME @
+_sip._tcp SRV 1 2 5060 ns
+@ TYPE999 \\# 2 abcd
+";
+
+#[test]
+fn record_data_is_typed() {
+    let zone = Zone::from_master(name("example."), ZONE).unwrap();
+    let data = |rtype: RecordType| {
+        let rr = zone.records().find(|rr| rr.rtype() == rtype).unwrap();
+        let value = serde_json::to_value(rr).unwrap();
+        assert_eq!(value["class"], "IN");
+        assert_eq!(value["type"], rtype.to_string());
+        assert_eq!(parse::<Record>(value.clone()).unwrap(), *rr);
+        value["data"].clone()
+    };
+    assert_eq!(data(RecordType::A), json!({"address": "192.0.2.1"}));
+    assert_eq!(data(RecordType::AAAA), json!({"address": "2001:db8::1"}));
+    assert_eq!(data(RecordType::CNAME), json!({"target": "example."}));
+    assert_eq!(
+        data(RecordType::SOA),
+        json!({"mname": "ns.example.", "rname": "hostmaster.example.",
+               "serial": 1, "refresh": 7200, "retry": 900,
+               "expire": 1209600, "minimum": 300})
+    );
+    assert_eq!(
+        data(RecordType::MX),
+        json!({"preference": 10, "exchange": "mail.example."})
+    );
+    assert_eq!(
+        data(RecordType::TXT),
+        json!({"strings": ["v=spf1 \\\"-all\\\"", "\\255"]})
+    );
+    assert_eq!(
+        data(RecordType::SRV),
+        json!({"priority": 1, "weight": 2, "port": 5060, "target": "ns.example."})
+    );
+    assert_eq!(data("TYPE999".parse().unwrap()), json!({"hex": "abcd"}));
+
+    let rr = Record::new(name("example."), 60, RData::A([192, 0, 2, 9].into()));
+    assert_eq!(
+        serde_json::to_value(&rr).unwrap(),
+        json!({"name": "example.", "ttl": 60, "class": "IN", "type": "A",
+               "data": {"address": "192.0.2.9"}})
+    );
+}
+
+#[test]
+fn record_data_is_checked_against_its_type() {
+    // The class defaults to IN, and data may be zone file text.
+    let rr: Record = parse(json!({"name": "a.example.", "ttl": 1, "type": "MX",
+                                  "data": "10 mail.example."}))
+    .unwrap();
+    assert_eq!(rr.class, RecordClass::IN);
+    assert_eq!(
+        rr.rdata,
+        RData::Mx {
+            preference: 10,
+            exchange: name("mail.example.")
+        }
+    );
+    let rr: Record = parse(json!({"name": "a.example.", "ttl": 1, "class": "CH",
+                                  "type": "TXT", "data": {"strings": ["a\\\"b", "\\010"]}}))
+    .unwrap();
+    assert_eq!(rr.class, RecordClass::CH);
+    assert_eq!(rr.rdata, RData::Txt(vec![b"a\"b".to_vec(), vec![10]]));
+
+    let error = |data: Value, rtype: &str| {
+        parse::<Record>(json!({"name": "a.example.", "ttl": 1, "type": rtype, "data": data}))
+            .unwrap_err()
+    };
+    assert!(error(json!({"preference": 10}), "MX").contains("MX data needs field `exchange`"));
+    assert!(error(json!({"address": "192.0.2.1", "target": "a."}), "A")
+        .contains("A data has no field `target`"));
+    assert!(error(json!({"address": "2001:db8::1"}), "A").contains("is not an IPv4 address"));
+    assert!(error(json!({"address": "192.0.2.1"}), "AAAA").contains("is not an IPv6 address"));
+    assert!(error(json!({"address": "192.0.2.1", "port": 3}), "A").contains("no field `port`"));
+    assert!(error(json!({"colour": "red"}), "A").contains("unknown field `colour`"));
+    assert!(error(json!({"hex": "abc"}), "TYPE999").contains("hex"));
+    assert!(error(json!({"strings": ["\\256"]}), "TXT").contains("bad escape"));
+    assert!(error(json!({"strings": ["x\\"]}), "TXT").contains("dangling backslash"));
+    assert!(error(json!("not an address"), "A").contains("bad record data"));
+    assert!(error(json!(7), "A").contains("an object or a string"));
+    assert!(error(json!({"target": "a..b."}), "NS").contains("invalid name"));
+    assert!(parse::<Record>(
+        json!({"name": "a.", "ttl": 1, "type": "A", "data": "192.0.2.1",
+                               "weight": 3})
+    )
+    .unwrap_err()
+    .contains("unknown field `weight`"));
+}
+
+#[test]
+fn rrsets_group_records() {
+    let a = |ttl, last| Record::new(name("ns.example."), ttl, RData::A([192, 0, 2, last].into()));
+    let mx = Record::new(
+        name("example."),
+        60,
+        RData::Mx {
+            preference: 10,
+            exchange: name("mail.example."),
+        },
+    );
+    let sets = RRset::group(&[a(300, 1), mx.clone(), a(60, 2)]);
+    assert_eq!(sets.len(), 2);
+    assert_eq!(sets[0].rtype, RecordType::A);
+    assert_eq!(sets[0].ttl, 60);
+    assert_eq!(sets[0].records().collect::<Vec<_>>(), [a(60, 1), a(60, 2)]);
+    assert_eq!(sets[1].records().collect::<Vec<_>>(), [mx]);
+
+    let value = serde_json::to_value(&sets[0]).unwrap();
+    assert_eq!(
+        value,
+        json!({"name": "ns.example.", "ttl": 60, "class": "IN", "type": "A",
+               "data": [{"address": "192.0.2.1"}, {"address": "192.0.2.2"}]})
+    );
+    assert_eq!(parse::<RRset>(value).unwrap(), sets[0]);
+    let mixed = parse::<RRset>(json!({"name": "a.", "ttl": 1, "type": "A",
+                                      "data": ["192.0.2.1", {"address": "192.0.2.2"}]}))
+    .unwrap();
+    assert_eq!(mixed.rdata.len(), 2);
+}
+
+#[test]
+fn zones_round_trip_in_a_stable_order() {
+    let zone = Zone::from_master(name("example."), ZONE).unwrap();
+    let text = serde_json::to_string(&zone).unwrap();
+    let back: Zone = serde_json::from_str(&text).unwrap();
+    let sorted = |zone: &Zone| {
+        let mut records: Vec<Record> = zone.records().cloned().collect();
+        records.sort_by_key(|rr| rr.to_string());
+        records
+    };
+    assert_eq!(sorted(&back), sorted(&zone));
+    assert_eq!(serde_json::to_string(&back).unwrap(), text);
+
+    let value: Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(value["origin"], "example.");
+    assert_eq!(value["class"], "IN");
+    assert_eq!(value["rrsets"].as_array().unwrap().len(), 9);
+
+    // Records that do not fit the zone are refused with the record named.
+    let soa = RData::Soa(Soa {
+        mname: name("ns.example."),
+        rname: name("hostmaster.example."),
+        serial: 1,
+        refresh: 1,
+        retry: 1,
+        expire: 1,
+        minimum: 1,
+    });
+    let outside = json!({"origin": "example.", "rrsets": [
+        {"name": "example.", "ttl": 1, "type": "SOA",
+         "data": [serde_json::to_value(Record::new(name("example."), 1, soa)).unwrap()["data"]]},
+        {"name": "example.org.", "ttl": 1, "type": "A", "data": ["192.0.2.1"]},
+    ]});
+    assert!(parse::<Zone>(outside).unwrap_err().contains("example.org."));
+}
+
+#[test]
+fn the_schema_describes_every_typed_form() {
+    let schema: Value = serde_json::from_str(&json_schema()).unwrap();
+    assert_eq!(
+        schema["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+    assert_eq!(schema["$ref"], "#/$defs/zone");
+    let defs = &schema["$defs"];
+    for (rtype, fields) in [
+        ("A", &["address"][..]),
+        ("AAAA", &["address"]),
+        ("NS", &["target"]),
+        ("CNAME", &["target"]),
+        ("PTR", &["target"]),
+        ("DNAME", &["target"]),
+        (
+            "SOA",
+            &[
+                "mname", "rname", "serial", "refresh", "retry", "expire", "minimum",
+            ],
+        ),
+        ("MX", &["preference", "exchange"]),
+        ("TXT", &["strings"]),
+        ("SRV", &["priority", "weight", "port", "target"]),
+        ("other", &["hex"]),
+    ] {
+        let def = &defs[format!("data-{}", rtype)];
+        assert_eq!(def["required"], json!(fields), "{}", rtype);
+        assert_eq!(def["additionalProperties"], false, "{}", rtype);
+    }
+    // One choice of data for each type with fields, and one for the rest.
+    assert_eq!(defs["record"]["allOf"].as_array().unwrap().len(), 11);
+    assert_eq!(
+        defs["rrset"]["allOf"][0],
+        json!({"if": {"properties": {"type": {"const": "A"}}},
+               "then": {"properties": {"data": {"type": "array",
+                                                "items": {"$ref": "#/$defs/data-A"}}}}})
+    );
+    assert_eq!(
+        defs["record"]["required"],
+        json!(["name", "ttl", "type", "data"])
+    );
+    assert_eq!(defs["zone"]["required"], json!(["origin", "rrsets"]));
+}
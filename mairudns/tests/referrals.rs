@@ -0,0 +1,217 @@
+//! The recursor's referral cache: delegations read from referrals with
+//! the records the parent has no say over left out, kept by zone cut for
+//! their TTL, and the closest cut to a name found with the root hints to
+//! fall back on.
+
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::recursor::{find_zone_cut, Delegation, ReferralCache, ReferralOptions, RootHints};
+use mairudns::rr::{RData, Record, RecordType};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ns(zone: &str, ttl: u32, server: &str) -> Record {
+    Record::new(name(zone), ttl, RData::Ns(name(server)))
+}
+
+fn a(owner: &str, addr: &str) -> Record {
+    Record::new(name(owner), 172_800, RData::A(addr.parse().unwrap()))
+}
+
+/// A referral for `qname` to the servers of `zone`.
+fn referral(qname: &str, zone: &str, ttl: u32) -> Message {
+    let mut resp = Message::query(name(qname), RecordType::A);
+    resp.header.qr = true;
+    resp.authority
+        .push(ns(zone, ttl, &format!("a.ns.{}", zone)));
+    resp.additional
+        .push(a(&format!("a.ns.{}", zone), "192.0.2.1"));
+    resp
+}
+
+#[test]
+fn delegations_are_read_from_referrals() {
+    let mut resp = Message::query(name("www.example.com."), RecordType::A);
+    resp.header.qr = true;
+    resp.authority.push(ns("com.", 172_800, "a.nic.com."));
+    resp.authority.push(ns("com.", 86_400, "b.nic.com."));
+    resp.authority.push(ns("com.", 172_800, "a.nic.com."));
+    // A second cut, one the question is not in, and one outside the zone
+    // the servers answer for.
+    resp.authority
+        .push(ns("example.com.", 172_800, "ns.example.com."));
+    resp.authority
+        .push(ns("org.", 172_800, "a0.org.afilias-nst.info."));
+    resp.additional.push(a("a.nic.com.", "192.5.6.30"));
+    resp.additional.push(a("ns.example.com.", "192.0.2.53"));
+    resp.additional.push(Record::new(
+        name("a.nic.com."),
+        172_800,
+        RData::Txt(vec![b"not an address".to_vec()]),
+    ));
+
+    let delegation = Delegation::from_referral(&resp, &DomainName::root()).unwrap();
+    assert_eq!(delegation.zone, name("com."));
+    assert_eq!(delegation.ns, [name("a.nic.com."), name("b.nic.com.")]);
+    assert_eq!(delegation.glue, [a("a.nic.com.", "192.5.6.30")]);
+    assert_eq!(delegation.ttl, 86_400);
+    assert_eq!(
+        delegation.addresses(),
+        ["192.5.6.30".parse::<IpAddr>().unwrap()]
+    );
+    assert_eq!(delegation.unresolved(), [&name("b.nic.com.")]);
+    assert_eq!(
+        delegation.to_records(),
+        [
+            ns("com.", 86_400, "a.nic.com."),
+            ns("com.", 86_400, "b.nic.com."),
+            a("a.nic.com.", "192.5.6.30"),
+        ]
+    );
+
+    // The servers of a zone delegate only below it, and glue is kept
+    // only for servers inside the zone delegated.
+    let resp = referral("www.sub.example.com.", "sub.example.com.", 3600);
+    let delegation = Delegation::from_referral(&resp, &name("example.com.")).unwrap();
+    assert_eq!(delegation.zone, name("sub.example.com."));
+    assert_eq!(delegation.addresses().len(), 1);
+    let mut resp = referral("www.example.com.", "example.com.", 3600);
+    resp.additional[0] = a("a.ns.example.com.", "192.0.2.1");
+    assert_eq!(
+        Delegation::from_referral(&resp, &name("example.com.")),
+        None
+    );
+    assert_eq!(Delegation::from_referral(&resp, &name("org.")), None);
+    let mut resp = referral("www.example.com.", "example.com.", 3600);
+    resp.authority[0] = ns("example.com.", 3600, "ns.example.net.");
+    resp.additional[0] = a("ns.example.net.", "192.0.2.1");
+    let delegation = Delegation::from_referral(&resp, &name("com.")).unwrap();
+    assert!(delegation.glue.is_empty());
+    assert_eq!(delegation.unresolved(), [&name("ns.example.net.")]);
+
+    // Answers and errors are not referrals.
+    let mut resp = referral("www.example.com.", "example.com.", 3600);
+    resp.answers.push(a("www.example.com.", "192.0.2.80"));
+    assert_eq!(Delegation::from_referral(&resp, &name("com.")), None);
+    let mut resp = referral("www.example.com.", "example.com.", 3600);
+    resp.header.rcode = Rcode::NXDOMAIN;
+    assert_eq!(Delegation::from_referral(&resp, &name("com.")), None);
+    let mut resp = referral("www.example.com.", "example.com.", 3600);
+    resp.authority.clear();
+    assert_eq!(Delegation::from_referral(&resp, &name("com.")), None);
+}
+
+#[test]
+fn delegations_are_kept_for_their_ttl() {
+    let cache = ReferralCache::new(ReferralOptions {
+        max_ttl: 7200,
+        ..ReferralOptions::default()
+    });
+    assert_eq!(cache.options().capacity, 100_000);
+    let now = Instant::now();
+    let learned = cache.learn(
+        &referral("www.example.com.", "com.", 172_800),
+        &DomainName::root(),
+        now,
+    );
+    assert_eq!(learned, Some(name("com.")));
+    let learned = cache.learn(
+        &referral("www.example.com.", "example.com.", 600),
+        &name("com."),
+        now,
+    );
+    assert_eq!(learned, Some(name("example.com.")));
+    // Not a referral: nothing learned.
+    let mut answer = referral("www.example.com.", "example.com.", 600);
+    answer.answers.push(a("www.example.com.", "192.0.2.80"));
+    assert_eq!(cache.learn(&answer, &name("com."), now), None);
+    assert_eq!(cache.len(), 2);
+
+    // Capped, and counted down.
+    let later = now + Duration::from_secs(100);
+    assert_eq!(cache.get(&name("com."), later).unwrap().ttl, 7100);
+    assert_eq!(cache.get(&name("example.com."), later).unwrap().ttl, 500);
+    assert_eq!(cache.get(&name("net."), later), None);
+
+    // The closest cut at or above a name.
+    let closest = |n: &str, at| cache.closest(&name(n), at).map(|d| d.zone);
+    assert_eq!(closest("www.example.com.", now), Some(name("example.com.")));
+    assert_eq!(closest("example.com.", now), Some(name("example.com.")));
+    assert_eq!(closest("www.example.org.", now), None);
+    let expired = now + Duration::from_secs(600);
+    assert_eq!(closest("www.example.com.", expired), Some(name("com.")));
+
+    cache.expire(expired);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.remove(&name("com.")).unwrap().zone, name("com."));
+    assert_eq!(cache.remove(&name("com.")), None);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn delegations_closest_to_expiring_make_room() {
+    let cache = ReferralCache::new(ReferralOptions {
+        capacity: 2,
+        ..ReferralOptions::default()
+    });
+    let now = Instant::now();
+    let root = DomainName::root();
+    cache.learn(&referral("www.example.com.", "com.", 3600), &root, now);
+    cache.learn(&referral("www.example.net.", "net.", 600), &root, now);
+    // Replacing a zone already held takes no room.
+    cache.learn(&referral("www.example.com.", "com.", 7200), &root, now);
+    assert_eq!(cache.len(), 2);
+    cache.learn(&referral("www.example.org.", "org.", 1800), &root, now);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&name("net."), now).is_none());
+    assert_eq!(cache.get(&name("com."), now).unwrap().ttl, 7200);
+
+    // Expired ones go before any that are still good.
+    let later = now + Duration::from_secs(1800);
+    cache.learn(&referral("www.example.net.", "net.", 600), &root, later);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&name("com."), later).is_some());
+    assert!(cache.get(&name("net."), later).is_some());
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn zone_cuts_fall_back_to_the_root() {
+    let cache = ReferralCache::default();
+    let hints = RootHints::iana();
+    let now = Instant::now();
+    cache.learn(
+        &referral("www.example.com.", "com.", 172_800),
+        &DomainName::root(),
+        now,
+    );
+    cache.learn(
+        &referral("www.example.com.", "example.com.", 3600),
+        &name("com."),
+        now,
+    );
+
+    let cut = find_zone_cut(&cache, &hints, &name("www.example.com."), now);
+    assert_eq!(cut.zone, name("example.com."));
+    assert_eq!(cut.ns, [name("a.ns.example.com.")]);
+
+    // The DS of example.com. is served from the com. side of the cut.
+    let parent = name("example.com.").parent().unwrap();
+    assert_eq!(
+        find_zone_cut(&cache, &hints, &parent, now).zone,
+        name("com.")
+    );
+
+    let root = find_zone_cut(&cache, &hints, &name("example.org."), now);
+    assert_eq!(root, Delegation::root(&hints));
+    assert!(root.zone.is_root());
+    assert_eq!(root.ns.len(), 13);
+    assert_eq!(root.addresses().len(), 26);
+    assert!(root.unresolved().is_empty());
+}
@@ -0,0 +1,151 @@
+//! The control channel: command syntax, the socket's permissions, and the
+//! challenge and MAC that authenticate each command.
+
+#![cfg(unix)]
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use mairudns::server::remote::{self, Command, Error, RemoteControl};
+
+const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+fn socket(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("remote-{}-{}.sock", std::process::id(), test));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// Serves on `path`, recording the commands that get through.
+fn start(path: &PathBuf) -> Arc<Mutex<Vec<Command>>> {
+    let control = RemoteControl::bind(path, KEY).unwrap();
+    let executed = Arc::new(Mutex::new(Vec::new()));
+    let log = executed.clone();
+    thread::spawn(move || {
+        control.run(move |command: &Command| match command {
+            Command::Drain => Err("not now".to_string()),
+            command => {
+                log.lock().unwrap().push(command.clone());
+                Ok(format!("did {}", command))
+            }
+        })
+    });
+    executed
+}
+
+fn read_message(stream: &mut UnixStream) -> Vec<u8> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut msg).unwrap();
+    msg
+}
+
+fn write_message(stream: &mut UnixStream, msg: &[u8]) {
+    stream.write_all(&(msg.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(msg).unwrap();
+}
+
+#[test]
+fn commands_round_trip_through_their_text() {
+    for text in &[
+        "status",
+        "reload",
+        "reload example.",
+        "flush",
+        "flush *.example.",
+        "flush www.example. AAAA",
+        "cache",
+        "cache *",
+        "stats",
+        "recent 5",
+        "debug on",
+        "debug off",
+        "drain",
+        "reconfigure",
+    ] {
+        let command: Command = text.parse().unwrap();
+        assert_eq!(command.to_string(), *text);
+    }
+    assert!("flush www.example. BOGUS!".parse::<Command>().is_err());
+    assert!("recent many".parse::<Command>().is_err());
+    assert!("restart".parse::<Command>().is_err());
+}
+
+#[test]
+fn runs_authenticated_commands() {
+    let path = socket("run");
+    let executed = start(&path);
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let reply = remote::send(&path, KEY, &Command::Debug(true)).unwrap();
+    assert_eq!(reply, "did debug on");
+    assert!(matches!(
+        remote::send(&path, KEY, &Command::Drain),
+        Err(Error::Failed(text)) if text == "not now"
+    ));
+    assert!(matches!(
+        remote::send(&path, b"wrong", &Command::Status),
+        Err(Error::Failed(text)) if text == "bad key"
+    ));
+    assert_eq!(*executed.lock().unwrap(), vec![Command::Debug(true)]);
+}
+
+#[test]
+fn challenges_are_fresh_and_requests_cannot_be_replayed() {
+    let path = socket("replay");
+    let executed = start(&path);
+
+    let mut challenges = HashSet::new();
+    for _ in 0..16 {
+        let mut stream = UnixStream::connect(&path).unwrap();
+        let challenge = read_message(&mut stream);
+        assert_eq!(challenge.len(), 32);
+        assert!(challenges.insert(challenge));
+    }
+
+    // A request seen on the wire, here by a server posing as the real
+    // one, is refused under another challenge.
+    let decoy = socket("decoy");
+    let listener = std::os::unix::net::UnixListener::bind(&decoy).unwrap();
+    let sniffer = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        write_message(&mut stream, &[7; 32]);
+        let request = read_message(&mut stream);
+        write_message(&mut stream, b"\0ok");
+        request
+    });
+    assert_eq!(remote::send(&decoy, KEY, &Command::Stats).unwrap(), "ok");
+    let request = sniffer.join().unwrap();
+    std::fs::remove_file(&decoy).unwrap();
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    read_message(&mut stream);
+    write_message(&mut stream, &request);
+    assert_eq!(read_message(&mut stream), b"\x01bad key");
+    assert!(executed.lock().unwrap().is_empty());
+}
+
+#[test]
+fn binds_over_stale_sockets_only() {
+    let path = socket("stale");
+    drop(RemoteControl::bind(&path, KEY).unwrap());
+    // Dropping removes the socket; one left by a crash is replaced.
+    assert!(!path.exists());
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    drop(listener);
+    let control = RemoteControl::bind(&path, KEY).unwrap();
+    assert_eq!(control.path(), path.as_path());
+    drop(control);
+
+    std::fs::write(&path, b"not a socket").unwrap();
+    let err = RemoteControl::bind(&path, KEY).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,265 @@
+//! Response sizing on a running server: the UDP cap, what is dropped
+//! before a response is truncated, minimal responses, and EDNS padding
+//! on encrypted transports only, with TLS stood in for by plain TCP.
+
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::{exchange_tcp, exchange_udp};
+use mairudns::message::{Edns, EdnsOption, Message, OptionCode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{
+    Accepted, Limits, Request, ResponsePolicy, Server, TlsAcceptor, MAX_UDP_SIZE, PADDING_BLOCK,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+struct Plain;
+
+impl TlsAcceptor for Plain {
+    fn accept(&self, stream: TcpStream, _alpn: &[&[u8]]) -> io::Result<Accepted> {
+        Ok(Accepted {
+            stream: Box::new(stream),
+            alpn: None,
+        })
+    }
+}
+
+/// Answers by the first label of the question: `small` with two
+/// addresses, `mid` with 70 and a large authority record, `big` with 100,
+/// all with a name server and ten additional records; `nx` with NXDOMAIN and a SOA, and
+/// `wild` with a wildcard answer and its proof.
+fn handler(request: &Request) -> Option<Message> {
+    let mut resp = request.message.response();
+    let qname = request.message.question()?.name.clone();
+    let label = qname.to_string();
+    let label = label.split('.').next().unwrap().to_string();
+    resp.edns = request.message.edns.as_ref().map(|_| Edns::default());
+    if label == "nx" {
+        resp.header.rcode = Rcode::NXDOMAIN;
+        resp.authority.push(Record::new(
+            name("example"),
+            300,
+            RData::Soa(Soa {
+                mname: name("ns.example"),
+                rname: name("hostmaster.example"),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                minimum: 300,
+            }),
+        ));
+        return Some(resp);
+    }
+    let count = match label.as_str() {
+        "mid" => 70,
+        "big" => 100,
+        _ => 2,
+    };
+    for i in 0..count {
+        resp.answers.push(Record::new(
+            qname.clone(),
+            60,
+            RData::A([10, 0, 0, i].into()),
+        ));
+    }
+    if label == "wild" {
+        // The NSEC record from a.example to z.example, for A only.
+        resp.authority.push(Record::new(
+            name("a.example"),
+            60,
+            RData::Unknown {
+                rtype: RecordType::NSEC,
+                data: b"\x01z\x07example\x00\x00\x01\x40".to_vec(),
+            },
+        ));
+    }
+    if label == "mid" {
+        resp.authority.push(Record::new(
+            name("example"),
+            60,
+            RData::Txt(vec![vec![b'y'; 250]]),
+        ));
+    }
+    resp.authority.push(Record::new(
+        name("example"),
+        60,
+        RData::Ns(name("ns.example")),
+    ));
+    for i in 0..10 {
+        resp.additional.push(Record::new(
+            name("ns.example"),
+            60,
+            RData::Txt(vec![vec![b'x'; 20 + i]]),
+        ));
+    }
+    Some(resp)
+}
+
+/// A query offering `udp_size`, asking for padding if `padded`.
+fn query(qname: &str, udp_size: u16, padded: bool) -> Message {
+    let mut query = Message::query(name(qname), RecordType::A);
+    let mut edns = Edns {
+        udp_size,
+        ..Edns::default()
+    };
+    if padded {
+        edns.options.push(EdnsOption {
+            code: OptionCode::PADDING,
+            data: vec![0; 10],
+        });
+    }
+    query.edns = Some(edns);
+    query
+}
+
+/// Starts a server with `policy`, returning its DNS and TLS addresses.
+fn start(policy: ResponsePolicy) -> (SocketAddr, SocketAddr) {
+    let mut server = Server::new(handler as fn(&Request) -> Option<Message>);
+    server.set_response_policy(policy);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let dns = server.local_addrs()[0].1;
+    let tls = server
+        .listen_tls("127.0.0.1:0".parse().unwrap(), Plain, Limits::default())
+        .unwrap();
+    thread::spawn(move || server.run());
+    (dns, tls)
+}
+
+fn size(resp: &Message) -> usize {
+    resp.to_wire().unwrap().len()
+}
+
+fn padding(resp: &Message) -> Option<usize> {
+    resp.edns
+        .as_ref()
+        .unwrap()
+        .option(OptionCode::PADDING)
+        .map(|o| o.data.len())
+}
+
+#[test]
+fn udp_responses_stay_under_the_cap() {
+    let (dns, _) = start(ResponsePolicy::default());
+
+    let resp = exchange_udp(dns, &query("small.example", 4096, false), TIMEOUT).unwrap();
+    assert!(!resp.header.tc);
+    assert_eq!(resp.additional.len(), 10);
+    assert!(size(&resp) <= MAX_UDP_SIZE);
+
+    // Too big with its extra sections, the answer goes out without them.
+    let resp = exchange_udp(dns, &query("mid.example", 4096, false), TIMEOUT).unwrap();
+    assert!(!resp.header.tc);
+    assert_eq!(resp.answers.len(), 70);
+    assert!(resp.authority.is_empty() && resp.additional.is_empty());
+
+    // Too big even so, it is truncated.
+    let resp = exchange_udp(dns, &query("big.example", 4096, false), TIMEOUT).unwrap();
+    assert!(resp.header.tc);
+    assert!(size(&resp) <= MAX_UDP_SIZE);
+    let resp = exchange_tcp(dns, &query("big.example", 4096, false), TIMEOUT).unwrap();
+    assert!(!resp.header.tc);
+    assert_eq!(resp.answers.len(), 100);
+    assert_eq!(resp.additional.len(), 10);
+
+    // A client offering less gets less.
+    let resp = exchange_udp(dns, &query("mid.example", 512, false), TIMEOUT).unwrap();
+    assert!(resp.header.tc);
+    assert!(size(&resp) <= 512);
+}
+
+#[test]
+fn the_cap_never_goes_below_512_bytes() {
+    let (dns, _) = start(ResponsePolicy {
+        max_udp_size: 100,
+        ..ResponsePolicy::default()
+    });
+    let resp = exchange_udp(dns, &query("small.example", 4096, false), TIMEOUT).unwrap();
+    assert_eq!(resp.answers.len(), 2);
+    let len = size(&resp);
+    assert!(len > 100 && len <= 512, "{}", len);
+}
+
+#[test]
+fn overflowing_responses_can_be_truncated_as_they_are_or_emptied() {
+    let (dns, _) = start(ResponsePolicy {
+        minimal_on_overflow: false,
+        ..ResponsePolicy::default()
+    });
+    let resp = exchange_udp(dns, &query("mid.example", 4096, false), TIMEOUT).unwrap();
+    assert!(resp.header.tc);
+    assert!(!resp.answers.is_empty());
+
+    let (dns, _) = start(ResponsePolicy {
+        empty_truncated: true,
+        ..ResponsePolicy::default()
+    });
+    let resp = exchange_udp(dns, &query("big.example", 4096, false), TIMEOUT).unwrap();
+    assert!(resp.header.tc);
+    assert_eq!(resp.questions.len(), 1);
+    assert!(resp.answers.is_empty() && resp.authority.is_empty() && resp.additional.is_empty());
+    // Responses that fit are not touched.
+    let resp = exchange_udp(dns, &query("small.example", 4096, false), TIMEOUT).unwrap();
+    assert!(!resp.header.tc);
+    assert_eq!(resp.additional.len(), 10);
+}
+
+#[test]
+fn minimal_responses_keep_what_answers_need() {
+    let (dns, _) = start(ResponsePolicy {
+        minimal_responses: true,
+        ..ResponsePolicy::default()
+    });
+    let resp = exchange_tcp(dns, &query("small.example", 4096, false), TIMEOUT).unwrap();
+    assert_eq!(resp.answers.len(), 2);
+    assert!(resp.authority.is_empty() && resp.additional.is_empty());
+
+    // The proof of a wildcard answer stays, and so does a negative
+    // answer's SOA.
+    let resp = exchange_tcp(dns, &query("wild.example", 4096, false), TIMEOUT).unwrap();
+    let kept: Vec<RecordType> = resp.authority.iter().map(|rr| rr.rtype()).collect();
+    assert_eq!(kept, vec![RecordType::NSEC]);
+    let resp = exchange_tcp(dns, &query("nx.example", 4096, false), TIMEOUT).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NXDOMAIN);
+    assert_eq!(resp.authority[0].rtype(), RecordType::SOA);
+}
+
+#[test]
+fn only_padded_queries_over_encrypted_transports_are_padded() {
+    let (dns, tls) = start(ResponsePolicy::default());
+
+    let resp = exchange_tcp(tls, &query("small.example", 4096, true), TIMEOUT).unwrap();
+    assert!(padding(&resp).is_some());
+    assert_eq!(size(&resp) % PADDING_BLOCK, 0);
+    let resp = exchange_tcp(tls, &query("big.example", 4096, true), TIMEOUT).unwrap();
+    assert_eq!(size(&resp) % PADDING_BLOCK, 0);
+
+    let resp = exchange_tcp(tls, &query("small.example", 4096, false), TIMEOUT).unwrap();
+    assert_eq!(padding(&resp), None);
+    let resp = exchange_tcp(dns, &query("small.example", 4096, true), TIMEOUT).unwrap();
+    assert_eq!(padding(&resp), None);
+    let resp = exchange_udp(dns, &query("small.example", 4096, true), TIMEOUT).unwrap();
+    assert_eq!(padding(&resp), None);
+
+    let (_, tls) = start(ResponsePolicy {
+        padding_block: 128,
+        ..ResponsePolicy::default()
+    });
+    let resp = exchange_tcp(tls, &query("nx.example", 4096, true), TIMEOUT).unwrap();
+    assert_eq!(size(&resp), 128);
+
+    let (_, tls) = start(ResponsePolicy {
+        padding_block: 0,
+        ..ResponsePolicy::default()
+    });
+    let resp = exchange_tcp(tls, &query("small.example", 4096, true), TIMEOUT).unwrap();
+    assert_eq!(padding(&resp), None);
+}
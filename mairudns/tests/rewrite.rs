@@ -0,0 +1,263 @@
+//! Answer rewriting: static host overrides, CNAME flattening, address
+//! substitution for hairpin NAT and per-client suppression, on their own
+//! and in front of an upstream handler.
+
+use std::net::IpAddr;
+
+use mairudns::addr::Prefix;
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Question, Rcode};
+use mairudns::name::DomainName;
+use mairudns::policy::Rewrite;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Handler, HandlerExt, Request};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn prefix(addr: &str, len: u8) -> Prefix {
+    Prefix::new(addr.parse().unwrap(), len).unwrap()
+}
+
+fn request(owner: &str, qtype: RecordType, src: &str) -> Request {
+    Request {
+        message: Message::query(name(owner), qtype),
+        src: src.parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    }
+}
+
+fn question(owner: &str, qtype: RecordType) -> Question {
+    Message::query(name(owner), qtype).questions[0].clone()
+}
+
+/// An upstream that answers every query with a CNAME to `edge.cdn.net`
+/// and its address.
+fn upstream(request: &Request) -> Option<Message> {
+    let q = request.message.question()?;
+    let mut resp = request.message.response();
+    resp.answers.push(Record::new(
+        q.name.clone(),
+        300,
+        RData::Cname(name("edge.cdn.net")),
+    ));
+    resp.answers.push(Record::new(
+        name("edge.cdn.net"),
+        20,
+        RData::A([203, 0, 113, 7].into()),
+    ));
+    Some(resp)
+}
+
+#[test]
+fn host_overrides_answer_each_family_with_the_configured_ttl() {
+    let v4: IpAddr = "192.168.1.5".parse().unwrap();
+    let v6: IpAddr = "fd00::5".parse().unwrap();
+    let rewrite = Rewrite::new()
+        .ttl(30)
+        .host(name("nas.lan"), vec![v4, v6])
+        .host(name("printer.lan"), vec![v4]);
+
+    let resp = rewrite
+        .answer(&request("nas.lan", RecordType::A, "10.0.0.1:5353"))
+        .unwrap();
+    assert!(resp.header.aa);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("nas.lan"),
+            30,
+            RData::A([192, 168, 1, 5].into())
+        )]
+    );
+
+    let resp = rewrite
+        .answer(&request("nas.lan", RecordType::AAAA, "10.0.0.1:5353"))
+        .unwrap();
+    assert_eq!(resp.answers.len(), 1);
+    assert_eq!(
+        resp.answers[0].rdata,
+        RData::Aaaa("fd00::5".parse().unwrap())
+    );
+
+    let resp = rewrite
+        .answer(&request("nas.lan", RecordType::ANY, "10.0.0.1:5353"))
+        .unwrap();
+    assert_eq!(resp.answers.len(), 2);
+
+    // A family without addresses is answered with no data.
+    let resp = rewrite
+        .answer(&request("printer.lan", RecordType::AAAA, "10.0.0.1:5353"))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+}
+
+#[test]
+fn host_overrides_leave_other_names_and_types_alone() {
+    let rewrite = Rewrite::new().host(name("nas.lan"), vec!["192.168.1.5".parse().unwrap()]);
+    assert!(rewrite
+        .answer(&request("nas.lan", RecordType::MX, "10.0.0.1:5353"))
+        .is_none());
+    assert!(rewrite
+        .answer(&request("www.nas.lan", RecordType::A, "10.0.0.1:5353"))
+        .is_none());
+}
+
+#[test]
+fn flattens_cname_chains_below_the_suffix() {
+    let rewrite = Rewrite::new().flatten_cnames(name("cdn.test"));
+    let req = request("www.cdn.test", RecordType::A, "10.0.0.1:5353");
+    let mut resp = upstream(&req).unwrap();
+    rewrite.apply(&question("www.cdn.test", RecordType::A), &mut resp);
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("www.cdn.test"),
+            20,
+            RData::A([203, 0, 113, 7].into())
+        )]
+    );
+
+    // Outside the suffix, and for CNAME queries, the chain stays.
+    let req = request("www.other.test", RecordType::A, "10.0.0.1:5353");
+    let mut resp = upstream(&req).unwrap();
+    rewrite.apply(&question("www.other.test", RecordType::A), &mut resp);
+    assert_eq!(resp.answers.len(), 2);
+    let req = request("www.cdn.test", RecordType::CNAME, "10.0.0.1:5353");
+    let mut resp = upstream(&req).unwrap();
+    rewrite.apply(&question("www.cdn.test", RecordType::CNAME), &mut resp);
+    assert_eq!(resp.answers.len(), 2);
+}
+
+#[test]
+fn flattening_keeps_chains_that_lead_nowhere() {
+    let rewrite = Rewrite::new().flatten_cnames(name("cdn.test"));
+    let mut resp = Message::default();
+    resp.answers.push(Record::new(
+        name("www.cdn.test"),
+        300,
+        RData::Cname(name("edge.cdn.net")),
+    ));
+    let before = resp.answers.clone();
+    rewrite.apply(&question("www.cdn.test", RecordType::AAAA), &mut resp);
+    assert_eq!(resp.answers, before);
+}
+
+#[test]
+fn maps_addresses_keeping_the_host_part() {
+    let rewrite = Rewrite::new()
+        .map_addresses(prefix("203.0.113.0", 24), prefix("10.1.2.0", 24))
+        .map_addresses(prefix("2001:db8::", 32), prefix("fd00:1::", 32));
+    let mut resp = Message::default();
+    resp.answers.push(Record::new(
+        name("a.test"),
+        60,
+        RData::A([203, 0, 113, 7].into()),
+    ));
+    resp.answers.push(Record::new(
+        name("a.test"),
+        60,
+        RData::A([198, 51, 100, 7].into()),
+    ));
+    resp.additional.push(Record::new(
+        name("a.test"),
+        60,
+        RData::Aaaa("2001:db8:0:1::9".parse().unwrap()),
+    ));
+    rewrite.apply(&question("a.test", RecordType::A), &mut resp);
+    assert_eq!(resp.answers[0].rdata, RData::A([10, 1, 2, 7].into()));
+    assert_eq!(resp.answers[1].rdata, RData::A([198, 51, 100, 7].into()));
+    assert_eq!(
+        resp.additional[0].rdata,
+        RData::Aaaa("fd00:1:0:1::9".parse().unwrap())
+    );
+}
+
+#[test]
+fn ignores_address_maps_of_mismatched_prefixes() {
+    let rewrite = Rewrite::new()
+        .map_addresses(prefix("203.0.113.0", 24), prefix("10.1.0.0", 16))
+        .map_addresses(prefix("203.0.113.0", 24), prefix("fd00::", 24));
+    assert_eq!(rewrite, Rewrite::new());
+}
+
+#[test]
+fn leaves_error_responses_alone() {
+    let rewrite = Rewrite::new().map_addresses(prefix("203.0.113.0", 24), prefix("10.1.2.0", 24));
+    let mut resp = Message::default();
+    resp.header.rcode = Rcode::SERVFAIL;
+    resp.answers.push(Record::new(
+        name("a.test"),
+        60,
+        RData::A([203, 0, 113, 7].into()),
+    ));
+    rewrite.apply(&question("a.test", RecordType::A), &mut resp);
+    assert_eq!(resp.answers[0].rdata, RData::A([203, 0, 113, 7].into()));
+}
+
+#[test]
+fn suppresses_a_type_for_listed_clients_only() {
+    let rewrite = Rewrite::new().suppress(
+        name("example"),
+        RecordType::AAAA,
+        "10.9.0.0/16".parse().unwrap(),
+    );
+    let resp = rewrite
+        .answer(&request("www.example", RecordType::AAAA, "10.9.3.1:5353"))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert!(resp.answers.is_empty());
+    assert!(!resp.header.aa);
+    assert!(rewrite
+        .answer(&request("www.example", RecordType::AAAA, "10.8.3.1:5353"))
+        .is_none());
+    assert!(rewrite
+        .answer(&request("www.example", RecordType::A, "10.9.3.1:5353"))
+        .is_none());
+    assert!(rewrite
+        .answer(&request("www.other", RecordType::AAAA, "10.9.3.1:5353"))
+        .is_none());
+}
+
+#[test]
+fn layer_answers_overrides_and_rewrites_upstream_answers() {
+    let rewrite = Rewrite::new()
+        .host(name("nas.lan"), vec!["192.168.1.5".parse().unwrap()])
+        .flatten_cnames(name("cdn.test"))
+        .map_addresses(prefix("203.0.113.0", 24), prefix("10.1.2.0", 24))
+        .suppress(name("."), RecordType::AAAA, "10.9.0.0/16".parse().unwrap());
+    let handler = (upstream as fn(&Request) -> Option<Message>).with(rewrite.clone());
+    assert_eq!(handler.rewrite(), &rewrite);
+
+    let resp = handler
+        .handle(&request("nas.lan", RecordType::A, "10.0.0.1:5353"))
+        .unwrap();
+    assert_eq!(resp.answers[0].rdata, RData::A([192, 168, 1, 5].into()));
+
+    let resp = handler
+        .handle(&request("www.cdn.test", RecordType::A, "10.0.0.1:5353"))
+        .unwrap();
+    assert_eq!(
+        resp.answers,
+        vec![Record::new(
+            name("www.cdn.test"),
+            20,
+            RData::A([10, 1, 2, 7].into())
+        )]
+    );
+
+    let resp = handler
+        .handle(&request("www.other", RecordType::A, "10.0.0.1:5353"))
+        .unwrap();
+    assert_eq!(resp.answers.len(), 2);
+    assert_eq!(resp.answers[1].rdata, RData::A([10, 1, 2, 7].into()));
+
+    let resp = handler
+        .handle(&request("www.other", RecordType::AAAA, "10.9.3.1:5353"))
+        .unwrap();
+    assert!(resp.answers.is_empty());
+}
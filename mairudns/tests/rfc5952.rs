@@ -0,0 +1,92 @@
+//! Runs the IPv6 text vectors of `mairudns::testing::rfc5952` against
+//! every way the crate reads and writes addresses: bare addresses,
+//! prefixes as ACLs and views take them, and AAAA records in zone files.
+//! Needs the `testing` feature.
+
+#![cfg(feature = "testing")]
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use mairudns::addr::Prefix;
+use mairudns::name::DomainName;
+use mairudns::rr::RData;
+use mairudns::testing::rfc5952::{self, Failure};
+use mairudns::zone::parse_records;
+
+fn assert_conforms(what: &str, failures: Vec<Failure>) {
+    if failures.is_empty() {
+        return;
+    }
+    let report: Vec<String> = failures.iter().map(Failure::to_string).collect();
+    panic!(
+        "{}: {} of the vectors fail:\n{}",
+        what,
+        failures.len(),
+        report.join("\n")
+    );
+}
+
+#[test]
+fn vectors_are_consistent() {
+    let vectors = rfc5952::vectors();
+    assert!(vectors.len() > 500, "only {} vectors", vectors.len());
+    for vector in vectors {
+        let addr: Ipv6Addr = vector.canonical.parse().unwrap();
+        assert_eq!(rfc5952::canonical(addr), vector.canonical, "{:?}", vector);
+    }
+}
+
+#[test]
+fn addresses() {
+    assert_conforms(
+        "Ipv6Addr",
+        rfc5952::check(|s| s.parse().ok(), |a| a.to_string()),
+    );
+    assert_conforms(
+        "IpAddr",
+        rfc5952::check(
+            |s| match s.parse() {
+                Ok(IpAddr::V6(a)) => Some(a),
+                _ => None,
+            },
+            |a| IpAddr::V6(a).to_string(),
+        ),
+    );
+}
+
+#[test]
+fn prefixes() {
+    // A bare address is a host prefix.
+    let parse = |s: &str| match s.parse::<Prefix>() {
+        Ok(p) if !s.contains('/') => match p.addr() {
+            IpAddr::V6(a) => Some(a),
+            IpAddr::V4(_) => None,
+        },
+        _ => None,
+    };
+    let format = |a: Ipv6Addr| {
+        let text = Prefix::host(IpAddr::V6(a)).to_string();
+        text.strip_suffix("/128").unwrap_or(&text).to_string()
+    };
+    assert_conforms("Prefix", rfc5952::check(parse, format));
+}
+
+#[test]
+fn zone_files() {
+    let origin: DomainName = "example.".parse().unwrap();
+    let parse = |s: &str| {
+        if s.is_empty() || s.contains(char::is_whitespace) {
+            return None;
+        }
+        let records = parse_records(&format!("host 300 IN AAAA {}\n", s), &origin).ok()?;
+        match records.as_slice() {
+            [record] => match record.rdata {
+                RData::Aaaa(a) => Some(a),
+                _ => None,
+            },
+            _ => None,
+        }
+    };
+    let format = |a: Ipv6Addr| RData::Aaaa(a).to_string();
+    assert_conforms("zone files", rfc5952::check(parse, format));
+}
@@ -0,0 +1,209 @@
+//! Root hints and priming: the IANA hints built in, `named.root` files,
+//! the priming query and what is made of its answer, and a primer that
+//! primes again when due by its clock until stopped.
+//!
+//! Root servers are always reached on port 53, so the mock root listens
+//! on a loopback address other than 127.0.0.1 at that port. Where binding
+//! it is not allowed the tests that need it return early.
+
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::clock::MockClock;
+use mairudns::message::{Header, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::recursor::{self, HintsError, PrimeError, RootHints, RootPrimer};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+const NAMED_ROOT: &str = "\
+;       This file holds the information on root name servers needed to
+;       initialize cache of Internet domain name servers
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+.                        3600000      NS    B.ROOT-SERVERS.NET.
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ns(host: &str, ttl: u32) -> Record {
+    Record::new(DomainName::root(), ttl, RData::Ns(name(host)))
+}
+
+fn a(owner: &str, addr: [u8; 4]) -> Record {
+    Record::new(name(owner), 3600, RData::A(addr.into()))
+}
+
+/// A priming response naming `a.root.test` at `addr`, for `ttl` seconds.
+fn priming(aa: bool, ttl: u32, addr: [u8; 4]) -> Action {
+    Action::Respond(Message {
+        header: Header {
+            aa,
+            ..Header::default()
+        },
+        answers: vec![ns("a.root.test.", ttl)],
+        additional: vec![a("a.root.test.", addr)],
+        ..Message::default()
+    })
+}
+
+/// A mock root server on port 53 of `ip` answering the priming query
+/// with itself, or `None` without the privilege.
+fn root(ip: [u8; 4], ttl: u32) -> Option<MockServer> {
+    let builder =
+        MockServer::builder().on(DomainName::root(), RecordType::NS, priming(true, ttl, ip));
+    match builder.start_on(SocketAddr::from((ip, 53))) {
+        Ok(server) => Some(server),
+        Err(e) if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::AddrInUse) => None,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Hints naming `a.root.test` at `ip`.
+fn hints(ip: [u8; 4]) -> RootHints {
+    RootHints::from_records(&[ns("a.root.test.", 3_600_000), a("a.root.test.", ip)]).unwrap()
+}
+
+fn wait_until(done: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !done() {
+        assert!(start.elapsed() < TIMEOUT);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn hints_come_built_in_or_from_named_root() {
+    let iana = RootHints::iana();
+    assert_eq!(iana, RootHints::default());
+    assert_eq!(iana.servers().len(), 13);
+    assert_eq!(iana.addresses().len(), 26);
+    assert_eq!(iana.ttl(), 3_600_000);
+    assert_eq!(
+        iana.addresses()[0],
+        "198.41.0.4:53".parse::<SocketAddr>().unwrap()
+    );
+    assert_eq!(RootHints::from_records(&iana.to_records()).unwrap(), iana);
+
+    // Servers without addresses are left out.
+    let parsed = RootHints::parse(NAMED_ROOT).unwrap();
+    assert_eq!(parsed.servers().len(), 1);
+    assert_eq!(parsed.servers()[0].name, name("a.root-servers.net."));
+    assert_eq!(
+        parsed.servers()[0].addresses,
+        vec![
+            "198.41.0.4".parse::<IpAddr>().unwrap(),
+            "2001:503:ba3e::2:30".parse().unwrap()
+        ]
+    );
+
+    let path = std::env::temp_dir().join(format!("named-{}.root", std::process::id()));
+    std::fs::write(&path, NAMED_ROOT).unwrap();
+    assert_eq!(RootHints::load(&path).unwrap(), parsed);
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(RootHints::load(&path), Err(HintsError::Io(_))));
+    assert!(matches!(
+        RootHints::parse(".  NS"),
+        Err(HintsError::Parse(_))
+    ));
+    let err = RootHints::parse(". 3600000 NS b.root-servers.net.\n").unwrap_err();
+    assert!(matches!(err, HintsError::NoServers));
+    assert_eq!(err.to_string(), "no root server with an address");
+}
+
+#[test]
+fn priming_takes_the_root_servers_from_the_answer() {
+    let server = match root([127, 0, 53, 1], 86400) {
+        Some(server) => server,
+        None => return,
+    };
+    let primed = recursor::prime(&hints([127, 0, 53, 1]), TIMEOUT).unwrap();
+    assert_eq!(primed.ttl(), 86400);
+    assert_eq!(
+        primed.addresses(),
+        vec!["127.0.53.1:53".parse::<SocketAddr>().unwrap()]
+    );
+    let query = &server.received()[0].message;
+    assert!(!query.header.rd);
+
+    // Servers named without addresses keep those of the hints.
+    server.push(Action::Respond(Message {
+        header: Header {
+            aa: true,
+            ..Header::default()
+        },
+        answers: vec![ns("a.root.test.", 86400)],
+        ..Message::default()
+    }));
+    let primed = recursor::prime(&hints([127, 0, 53, 1]), TIMEOUT).unwrap();
+    assert_eq!(primed.servers()[0].addresses.len(), 1);
+
+    // Answers that are not authoritative, are errors, or do not name the
+    // root's servers are refused.
+    for action in [
+        priming(false, 86400, [127, 0, 53, 1]),
+        Action::Rcode(Rcode::SERVFAIL),
+        Action::answer(vec![a("a.root.test.", [127, 0, 53, 1])]),
+    ] {
+        server.push(action);
+        let err = recursor::prime(&hints([127, 0, 53, 1]), TIMEOUT).unwrap_err();
+        assert!(matches!(err, PrimeError::BadResponse(_)), "{:?}", err);
+    }
+}
+
+#[test]
+fn primers_prime_again_when_due_until_stopped() {
+    let server = match root([127, 0, 53, 2], 3600) {
+        Some(server) => server,
+        None => return,
+    };
+    // The first priming fails.
+    server.push(Action::Rcode(Rcode::SERVFAIL));
+    let clock = MockClock::new(1_700_000_000);
+    let primer = Arc::new(
+        RootPrimer::new(hints([127, 0, 53, 2]))
+            .timeout(Duration::from_millis(500))
+            .clock(Arc::new(clock.clone())),
+    );
+    assert_eq!(primer.current().ttl(), 3_600_000);
+    let running = primer.clone();
+    let runner = thread::spawn(move || running.run());
+
+    wait_until(|| primer.last_error().is_some());
+    assert_eq!(
+        primer.last_error().unwrap(),
+        "bad priming response: server responded SERVFAIL"
+    );
+    assert_eq!(primer.primed_at(), None);
+    assert_eq!(*primer.current(), hints([127, 0, 53, 2]));
+
+    // A minute on by the clock, it tries again.
+    clock.advance(Duration::from_secs(60));
+    wait_until(|| primer.primed_at().is_some());
+    assert_eq!(primer.primed_at(), Some(1_700_000_060));
+    assert_eq!(primer.current().ttl(), 3600);
+    wait_until(|| primer.last_error().is_none());
+
+    // And again once the TTL of the answer runs out, not before.
+    clock.advance(Duration::from_secs(3599));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(server.count(Protocol::Udp), 2);
+    clock.advance(Duration::from_secs(1));
+    wait_until(|| server.count(Protocol::Udp) == 3);
+
+    // Stopping cuts the wait short.
+    primer.stop();
+    runner.join().unwrap();
+    clock.advance(Duration::from_secs(3600));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(server.count(Protocol::Udp), 3);
+}
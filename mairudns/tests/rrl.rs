@@ -0,0 +1,220 @@
+//! Response rate limiting: the slip and leak ratio of limited responses,
+//! what each bucket is keyed by, and a full bucket table, which must keep
+//! limiting new clients rather than wave their responses through.
+
+use std::sync::Arc;
+
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{
+    Authority, Handler, HandlerExt, RateLimit, RateLimited, Request, ResponseKind,
+};
+use mairudns::zone::Zone;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn soa(zone: &str) -> Record {
+    Record::new(
+        name(zone),
+        3600,
+        RData::Soa(Soa {
+            mname: name("ns.example"),
+            rname: name("hostmaster.example"),
+            serial: 1,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+        }),
+    )
+}
+
+/// Answers every query with one A record, NXDOMAIN below `nx.example`.
+fn answer(request: &Request) -> Option<Message> {
+    let q = request.message.question()?.clone();
+    let mut resp = request.message.response();
+    resp.header.aa = true;
+    if q.name.is_subdomain_of(&name("nx.example")) {
+        resp.header.rcode = Rcode::NXDOMAIN;
+        resp.authority.push(soa("nx.example"));
+    } else {
+        resp.answers
+            .push(Record::new(q.name, 60, RData::A([192, 0, 2, 1].into())));
+    }
+    Some(resp)
+}
+
+fn limited(config: RateLimit) -> RateLimited<fn(&Request) -> Option<Message>> {
+    (answer as fn(&Request) -> Option<Message>).with(config)
+}
+
+fn request(src: &str, qname: &str, protocol: Protocol) -> Request {
+    Request {
+        message: Message::query(name(qname), RecordType::A),
+        src: format!("{}:5300", src).parse().unwrap(),
+        protocol,
+        key: None,
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Outcomes {
+    sent: usize,
+    slipped: usize,
+    dropped: usize,
+}
+
+fn send<H: Handler>(handler: &H, src: &str, qname: &str, count: usize) -> Outcomes {
+    let mut outcomes = Outcomes::default();
+    for _ in 0..count {
+        match handler.handle(&request(src, qname, Protocol::Udp)) {
+            Some(resp) if resp.header.tc => {
+                assert!(resp.answers.is_empty());
+                outcomes.slipped += 1
+            }
+            Some(_) => outcomes.sent += 1,
+            None => outcomes.dropped += 1,
+        }
+    }
+    outcomes
+}
+
+fn config(per_second: u32, slip: u32) -> RateLimit {
+    RateLimit {
+        slip,
+        ..RateLimit::new(per_second)
+    }
+}
+
+#[test]
+fn slips_every_nth_limited_response() {
+    let handler = limited(config(5, 2));
+    assert_eq!(
+        send(&handler, "192.0.2.1", "www.example", 25),
+        Outcomes {
+            sent: 5,
+            slipped: 10,
+            dropped: 10
+        }
+    );
+}
+
+#[test]
+fn the_slip_sets_the_leak_ratio() {
+    for &(slip, slipped) in &[(0, 0), (1, 30), (3, 10), (10, 3)] {
+        let handler = limited(config(1, slip));
+        let outcomes = send(&handler, "192.0.2.1", "www.example", 31);
+        assert_eq!(outcomes.sent, 1);
+        assert_eq!(outcomes.slipped, slipped, "slip {}", slip);
+        assert_eq!(outcomes.dropped, 30 - slipped, "slip {}", slip);
+    }
+}
+
+#[test]
+fn buckets_are_per_network_name_and_kind() {
+    let handler = limited(config(2, 0));
+    assert_eq!(send(&handler, "192.0.2.1", "www.example", 4).sent, 2);
+    // The same /24.
+    assert_eq!(send(&handler, "192.0.2.200", "www.example", 4).sent, 0);
+    assert_eq!(send(&handler, "198.51.100.1", "www.example", 4).sent, 2);
+    assert_eq!(send(&handler, "192.0.2.1", "mail.example", 4).sent, 2);
+    // Random names in one zone share its NXDOMAIN bucket.
+    let nx: usize = (0..4)
+        .map(|i| send(&handler, "192.0.2.1", &format!("r{}.nx.example", i), 1).sent)
+        .sum();
+    assert_eq!(nx, 2);
+}
+
+#[test]
+fn leaves_tcp_and_exempt_clients_alone() {
+    let mut config = config(1, 0);
+    config.exempt = "192.0.2.0/24".parse().unwrap();
+    let handler = limited(config);
+    assert_eq!(send(&handler, "192.0.2.1", "www.example", 5).sent, 5);
+    for _ in 0..5 {
+        assert!(handler
+            .handle(&request("198.51.100.1", "www.example", Protocol::Tcp))
+            .is_some());
+    }
+    assert_eq!(handler.buckets(), 0);
+}
+
+#[test]
+fn a_full_table_still_limits_new_clients() {
+    let mut config = config(1, 0);
+    config.max_entries = 8;
+    let handler = limited(config);
+    for i in 0..100 {
+        send(&handler, &format!("10.0.{}.1", i), "www.example", 1);
+    }
+    assert!(handler.buckets() <= 8);
+    // A client arriving now is accounted like any other.
+    assert_eq!(
+        send(&handler, "192.0.2.1", "www.example", 10),
+        Outcomes {
+            sent: 1,
+            slipped: 0,
+            dropped: 9
+        }
+    );
+}
+
+#[test]
+fn a_busy_bucket_outlives_a_flood_of_new_ones() {
+    let mut config = config(1, 0);
+    config.max_entries = 32;
+    let handler = limited(config);
+    assert_eq!(send(&handler, "192.0.2.1", "www.example", 2).sent, 1);
+    for i in 0..1000 {
+        send(
+            &handler,
+            &format!("10.{}.{}.1", i / 256, i % 256),
+            "www.example",
+            1,
+        );
+        // The victim's bucket keeps being hit, so the hand spares it.
+        assert_eq!(send(&handler, "192.0.2.1", "www.example", 1).sent, 0);
+    }
+    assert!(handler.buckets() <= 32);
+}
+
+#[test]
+fn classifies_responses() {
+    let mut zone = Zone::new(name("example"));
+    zone.insert(soa("example")).unwrap();
+    zone.insert(Record::new(
+        name("*.wild.example"),
+        60,
+        RData::A([192, 0, 2, 1].into()),
+    ))
+    .unwrap();
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone);
+    let config = RateLimit {
+        authority: Some(authority.clone()),
+        ..RateLimit::new(1)
+    };
+    let classify = |qname: &str| {
+        let resp = authority
+            .handle(&request("192.0.2.1", qname, Protocol::Udp))
+            .unwrap();
+        config.classify(&resp)
+    };
+    assert_eq!(
+        classify("a.wild.example"),
+        (ResponseKind::Wildcard, name("*.wild.example"))
+    );
+    assert_eq!(
+        classify("missing.example"),
+        (ResponseKind::Nxdomain, name("example"))
+    );
+    assert_eq!(classify("example"), (ResponseKind::Nodata, name("example")));
+    assert_eq!(
+        classify("example.net"),
+        (ResponseKind::Error, DomainName::root())
+    );
+}
@@ -0,0 +1,252 @@
+//! Secondary zones pulling from a primary served by the crate: AXFR into
+//! an empty zone, IXFR after an update, TSIG, refusals, ZONEMD checks,
+//! and the refresh loop with NOTIFY and stop.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::message::Rcode;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{Authority, Control, Server, SharedZone, TransferAcl};
+use mairudns::tsig::{self, Keyring};
+use mairudns::zone::{Diff, Refresh, Secondary, TransferError, Zone, Zonemd, ZonemdError};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn soa(serial: u32) -> Record {
+    Record::new(
+        name("example.com"),
+        3600,
+        RData::Soa(Soa {
+            mname: name("ns.example.com"),
+            rname: name("hostmaster.example.com"),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+        }),
+    )
+}
+
+fn host(i: u32) -> Record {
+    Record::new(
+        name(&format!("h{}.example.com", i)),
+        60,
+        RData::A([10, 0, (i / 256) as u8, (i % 256) as u8].into()),
+    )
+}
+
+fn key() -> tsig::Key {
+    tsig::Key::new(
+        name("transfer"),
+        tsig::Algorithm::HmacSha256,
+        b"0123456789abcdef".to_vec(),
+    )
+}
+
+struct Primary {
+    addr: SocketAddr,
+    zone: SharedZone,
+    control: Control,
+}
+
+impl Drop for Primary {
+    fn drop(&mut self) {
+        self.control.shutdown();
+    }
+}
+
+/// Serves example.com with `hosts` A records, transferring only to holders
+/// of [`key`].
+fn primary(hosts: u32) -> Primary {
+    let mut zone = Zone::new(name("example.com"));
+    zone.insert(soa(1)).unwrap();
+    for i in 0..hosts {
+        zone.insert(host(i)).unwrap();
+    }
+    let authority = Arc::new(Authority::new());
+    let shared = authority.insert(zone);
+    authority.set_transfer_acl(
+        name("example.com"),
+        TransferAcl {
+            addresses: Vec::new(),
+            keys: vec![name("transfer")],
+        },
+    );
+    let mut server = Server::new(authority);
+    let mut keys = Keyring::new();
+    keys.insert(key());
+    server.set_keyring(keys);
+    let addr = server.listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let control = server.control();
+    thread::spawn(move || server.run());
+    Primary {
+        addr,
+        zone: shared,
+        control,
+    }
+}
+
+fn secondary(primaries: Vec<SocketAddr>) -> Secondary {
+    let mut secondary = Secondary::new(
+        Arc::new(Authority::new()).insert(Zone::new(name("example.com"))),
+        primaries,
+    );
+    secondary.set_timeout(Duration::from_secs(2));
+    secondary
+}
+
+#[test]
+fn transfers_the_whole_zone_then_increments() {
+    let primary = primary(1000);
+    let mut secondary = secondary(vec![primary.addr]);
+    secondary.set_key(key());
+    assert_eq!(
+        secondary.refresh().unwrap(),
+        Refresh::Transferred {
+            serial: 1,
+            incremental: false
+        }
+    );
+    assert_eq!(secondary.zone().read().unwrap().len(), 1001);
+    assert_eq!(
+        secondary.refresh().unwrap(),
+        Refresh::UpToDate { serial: 1 }
+    );
+
+    primary
+        .zone
+        .write()
+        .unwrap()
+        .apply(Diff {
+            old_soa: soa(1),
+            deleted: vec![host(0)],
+            new_soa: soa(2),
+            added: vec![host(5000)],
+        })
+        .unwrap();
+    assert_eq!(
+        secondary.refresh().unwrap(),
+        Refresh::Transferred {
+            serial: 2,
+            incremental: true
+        }
+    );
+    let zone = secondary.zone().read().unwrap();
+    assert!(zone.rrset(&host(0).name, RecordType::A).is_none());
+    assert!(zone.rrset(&host(5000).name, RecordType::A).is_some());
+    assert_eq!(zone.serial(), Some(2));
+}
+
+#[test]
+fn refuses_unsigned_and_wrongly_signed_transfers() {
+    let primary = primary(10);
+    let mut secondary = secondary(vec![primary.addr]);
+    assert!(matches!(
+        secondary.refresh(),
+        Err(TransferError::Rcode(Rcode::REFUSED))
+    ));
+    secondary.set_key(tsig::Key::new(
+        name("transfer"),
+        tsig::Algorithm::HmacSha256,
+        b"not the right secret".to_vec(),
+    ));
+    assert!(secondary.refresh().is_err());
+    assert!(secondary.zone().read().unwrap().soa().is_none());
+}
+
+#[test]
+fn keeps_the_old_version_when_zonemd_does_not_match() {
+    let primary = primary(10);
+    // A digest made before the last change no longer matches.
+    {
+        let mut zone = primary.zone.write().unwrap();
+        zone.set_zonemd(&[Zonemd::SHA384]).unwrap();
+        zone.insert(host(10)).unwrap();
+    }
+    let mut secondary = secondary(vec![primary.addr]);
+    secondary.set_key(key());
+    secondary.set_verify_zonemd(true);
+    assert!(matches!(
+        secondary.refresh(),
+        Err(TransferError::Zonemd(ZonemdError::Mismatch))
+    ));
+    assert!(secondary.zone().read().unwrap().soa().is_none());
+
+    primary
+        .zone
+        .write()
+        .unwrap()
+        .set_zonemd(&[Zonemd::SHA384])
+        .unwrap();
+    assert_eq!(
+        secondary.refresh().unwrap(),
+        Refresh::Transferred {
+            serial: 1,
+            incremental: false
+        }
+    );
+    assert_eq!(secondary.zone().read().unwrap().verify_zonemd(), Ok(true));
+}
+
+#[test]
+fn fails_without_primaries_or_when_none_answer() {
+    assert!(matches!(
+        secondary(Vec::new()).refresh(),
+        Err(TransferError::NoPrimaries)
+    ));
+    // Nothing listens on a port just released.
+    let closed = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+    assert!(matches!(
+        secondary(vec![closed]).refresh_from(closed),
+        Err(TransferError::Client(_))
+    ));
+}
+
+#[test]
+fn refreshes_on_notify_until_stopped() {
+    let primary = primary(10);
+    let mut secondary = secondary(vec![primary.addr]);
+    secondary.set_key(key());
+    let secondary = Arc::new(secondary);
+    let runner = secondary.clone();
+    let thread = thread::spawn(move || runner.run());
+
+    let wait_for = |serial: u32| {
+        let start = Instant::now();
+        while secondary.status().serial != Some(serial) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(20));
+        }
+    };
+    wait_for(1);
+    primary
+        .zone
+        .write()
+        .unwrap()
+        .apply(Diff {
+            old_soa: soa(1),
+            deleted: Vec::new(),
+            new_soa: soa(2),
+            added: vec![host(99)],
+        })
+        .unwrap();
+    // The refresh timer is an hour away; NOTIFY brings it forward.
+    secondary.notify();
+    wait_for(2);
+    let status = secondary.status();
+    assert!(status.last_refresh.is_some());
+    assert!(status.last_error.is_none());
+    assert!(status.next_refresh.unwrap() > Instant::now() + Duration::from_secs(3000));
+    secondary.stop();
+    thread.join().unwrap();
+}
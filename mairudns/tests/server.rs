@@ -0,0 +1,191 @@
+//! The UDP and TCP frontend: dispatch to the handler, truncation of
+//! responses too large for UDP, RFC 7766 framing and idle timeouts, and
+//! what malformed queries get back.
+
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use mairudns::client::{exchange, read_framed, write_framed, Protocol};
+use mairudns::message::{Edns, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Control, Handler, Request, Server};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// Answers `many.example` with 100 TXT records, anything else with one A
+/// record.
+fn handler(request: &Request) -> Option<Message> {
+    let q = request.message.question()?.clone();
+    let mut resp = request.message.response();
+    resp.header.aa = true;
+    if q.name == name("many.example") {
+        for i in 0..100 {
+            let text = format!("record number {:03} of a long answer", i);
+            resp.answers.push(Record::new(
+                q.name.clone(),
+                60,
+                RData::Txt(vec![text.into_bytes()]),
+            ));
+        }
+    } else {
+        resp.answers
+            .push(Record::new(q.name, 60, RData::A([192, 0, 2, 1].into())));
+    }
+    Some(resp)
+}
+
+struct Running {
+    udp: SocketAddr,
+    tcp: SocketAddr,
+    control: Control,
+    thread: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.control.shutdown();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap().unwrap();
+        }
+    }
+}
+
+fn start<H: Handler>(handler: H, idle: Duration) -> Running {
+    let mut server = Server::new(handler);
+    server.set_tcp_idle_timeout(idle);
+    let lo: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let udp = server.listen_udp(lo).unwrap();
+    let tcp = server.listen_tcp(lo).unwrap();
+    let control = server.control();
+    let thread = Some(thread::spawn(move || server.run()));
+    Running {
+        udp,
+        tcp,
+        control,
+        thread,
+    }
+}
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[test]
+fn answers_over_udp_and_tcp() {
+    let server = start(handler, Duration::from_secs(5));
+    let query = Message::query(name("host.example"), RecordType::A);
+    for (addr, protocol) in [(server.udp, Protocol::Udp), (server.tcp, Protocol::Tcp)] {
+        let resp = exchange(addr, protocol, &query, TIMEOUT).unwrap();
+        assert_eq!(resp.header.id, query.header.id);
+        assert!(resp.header.qr);
+        assert!(resp.header.aa);
+        assert_eq!(resp.answers.len(), 1);
+    }
+}
+
+#[test]
+fn truncates_large_udp_responses() {
+    let server = start(handler, Duration::from_secs(5));
+    let mut query = Message::query(name("many.example"), RecordType::TXT);
+    query.edns = None;
+    let resp = exchange(server.udp, Protocol::Udp, &query, TIMEOUT).unwrap();
+    assert!(resp.header.tc);
+    assert!(resp.to_wire().unwrap().len() <= 512);
+
+    // A larger advertised payload moves the limit but still falls short.
+    query.edns = Some(Edns {
+        udp_size: 1232,
+        ..Edns::default()
+    });
+    let resp = exchange(server.udp, Protocol::Udp, &query, TIMEOUT).unwrap();
+    assert!(resp.header.tc);
+    assert!(resp.to_wire().unwrap().len() <= 1232);
+
+    let resp = exchange(server.tcp, Protocol::Tcp, &query, TIMEOUT).unwrap();
+    assert!(!resp.header.tc);
+    assert_eq!(resp.answers.len(), 100);
+}
+
+#[test]
+fn answers_pipelined_queries_on_one_connection() {
+    let server = start(handler, Duration::from_secs(5));
+    let mut stream = TcpStream::connect(server.tcp).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let queries: Vec<Message> = (0..3)
+        .map(|i| {
+            let mut q = Message::query(name(&format!("h{}.example", i)), RecordType::A);
+            q.header.id = 100 + i;
+            q
+        })
+        .collect();
+    // All three go out in one write before any response is read.
+    let mut out = Vec::new();
+    for q in &queries {
+        write_framed(&mut out, &q.to_wire().unwrap()).unwrap();
+    }
+    std::io::Write::write_all(&mut stream, &out).unwrap();
+    let mut ids: Vec<u16> = (0..3)
+        .map(|_| {
+            Message::from_wire(&read_framed(&mut stream).unwrap())
+                .unwrap()
+                .header
+                .id
+        })
+        .collect();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![100, 101, 102]);
+}
+
+#[test]
+fn closes_idle_connections() {
+    let server = start(handler, Duration::from_millis(200));
+    let mut stream = TcpStream::connect(server.tcp).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let start = Instant::now();
+    let mut buf = [0; 1];
+    assert_eq!(stream.read(&mut buf).unwrap_or(0), 0);
+    assert!(start.elapsed() < TIMEOUT);
+}
+
+#[test]
+fn answers_malformed_queries_with_formerr() {
+    let server = start(handler, Duration::from_secs(5));
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+    // A header claiming one question, and no question.
+    let header = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    socket.send_to(&header, server.udp).unwrap();
+    let mut buf = [0; 512];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    let resp = Message::from_wire(&buf[..len]).unwrap();
+    assert_eq!(resp.header.id, 0x1234);
+    assert_eq!(resp.header.rcode, Rcode::FORMERR);
+
+    // Responses and runts get nothing back.
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    socket
+        .send_to(&[0x12, 0x34, 0x81, 0x80], server.udp)
+        .unwrap();
+    let mut response = header;
+    response[2] |= 0x80;
+    socket.send_to(&response, server.udp).unwrap();
+    assert!(socket.recv_from(&mut buf).is_err());
+}
+
+#[test]
+fn stays_silent_when_the_handler_declines() {
+    let server = start(|_: &Request| None, Duration::from_secs(5));
+    let query = Message::query(name("host.example"), RecordType::A);
+    assert!(exchange(
+        server.udp,
+        Protocol::Udp,
+        &query,
+        Duration::from_millis(200)
+    )
+    .is_err());
+}
@@ -0,0 +1,438 @@
+//! Offline zone signing: what gets signed and by which key, validity
+//! windows with jitter, the key set with its CDS and CDNSKEY records, the
+//! NSEC and NSEC3 chains with opt-out, and zones a validator accepts.
+
+#![cfg(feature = "dnssec")]
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mairudns::clock::MockClock;
+use mairudns::dnssec::{
+    nsec3_hash, sign_zone, AnchorKey, Denial, DigestType, Dnskey, Nsec, Nsec3, Nsec3Param, Rrsig,
+    SignError, SigningKey, SigningPolicy, Status, TrustAnchors, Validator,
+};
+use mairudns::encoding::base32hex_encode;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{Record, RecordType};
+use mairudns::server::Authority;
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+www IN AAAA 2001:db8::80
+a.b.c IN TXT \"deep\"
+*.wild IN A 192.0.2.99
+sub IN NS ns.sub
+ns.sub IN A 192.0.2.54
+sec IN NS ns.sec
+ns.sec IN A 192.0.2.55
+sec IN DS \\# 36 30390D023AA5AB37EFCE57F737FC1627013FEE07BDF241BD10F3B1964AB55C78E79A304B
+";
+
+const NOW: u32 = 1_700_000_000;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn zone() -> Zone {
+    Zone::from_master(name("example."), ZONE).unwrap()
+}
+
+/// A key-signing key and a zone-signing key.
+fn keys() -> Vec<SigningKey> {
+    vec![
+        SigningKey::ed25519(257, &[1; 32]).unwrap(),
+        SigningKey::ed25519(256, &[2; 32]).unwrap(),
+    ]
+}
+
+fn policy(denial: Denial) -> SigningPolicy {
+    SigningPolicy {
+        now: Some(NOW),
+        denial,
+        ..SigningPolicy::default()
+    }
+}
+
+fn nsec3(opt_out: bool) -> Denial {
+    Denial::Nsec3 {
+        iterations: 0,
+        salt: Vec::new(),
+        opt_out,
+    }
+}
+
+fn sigs(zone: &Zone) -> Vec<(DomainName, Rrsig)> {
+    zone.records()
+        .filter_map(|rr| Some((rr.name.clone(), Rrsig::from_rdata(&rr.rdata)?)))
+        .collect()
+}
+
+fn of(zone: &Zone, rtype: RecordType) -> Vec<&Record> {
+    zone.records().filter(|rr| rr.rtype() == rtype).collect()
+}
+
+#[test]
+fn signs_each_authoritative_rrset_within_its_validity() {
+    let keys = keys();
+    let signed = sign_zone(&zone(), &keys, &policy(Denial::Nsec)).unwrap();
+    let signatures = sigs(&signed);
+    let rrsets: BTreeSet<(DomainName, RecordType)> = signed
+        .records()
+        .filter(|rr| rr.rtype() != RecordType::RRSIG)
+        .map(|rr| (rr.name.clone(), rr.rtype()))
+        .collect();
+    // Delegations and glue belong to the child.
+    let unsigned = [
+        (name("sub.example."), RecordType::NS),
+        (name("ns.sub.example."), RecordType::A),
+        (name("sec.example."), RecordType::NS),
+        (name("ns.sec.example."), RecordType::A),
+    ];
+    for (owner, rtype) in &rrsets {
+        let by: Vec<u16> = signatures
+            .iter()
+            .filter(|(at, sig)| at == owner && sig.type_covered == *rtype)
+            .map(|(_, sig)| sig.key_tag)
+            .collect();
+        if unsigned.contains(&(owner.clone(), *rtype)) {
+            assert!(by.is_empty(), "{} {}", owner, rtype);
+            continue;
+        }
+        let key = match *rtype {
+            RecordType::DNSKEY | RecordType::CDS | RecordType::CDNSKEY => &keys[0],
+            _ => &keys[1],
+        };
+        assert_eq!(by, [key.key_tag()], "{} {}", owner, rtype);
+    }
+
+    let policy = SigningPolicy::default();
+    let (backdate, validity, jitter) = (
+        policy.backdate.as_secs() as u32,
+        policy.validity.as_secs() as u32,
+        policy.jitter.as_secs() as u32,
+    );
+    let mut expirations = BTreeSet::new();
+    for (owner, sig) in &signatures {
+        assert_eq!(sig.signer, name("example."));
+        assert_eq!(sig.inception, NOW - backdate);
+        assert!(
+            (NOW + validity - jitter..=NOW + validity).contains(&sig.expiration),
+            "{} {}",
+            owner,
+            sig
+        );
+        let labels = owner.label_count() - usize::from(owner.is_wildcard());
+        assert_eq!(usize::from(sig.labels), labels);
+        expirations.insert(sig.expiration);
+    }
+    // The jitter spreads the expirations out.
+    assert!(expirations.len() > 1);
+
+    let steady = SigningPolicy {
+        jitter: Duration::ZERO,
+        validity: Duration::from_secs(86400),
+        ..self::policy(Denial::Nsec)
+    };
+    let signed = sign_zone(&zone(), &keys, &steady).unwrap();
+    assert!(sigs(&signed)
+        .iter()
+        .all(|(_, sig)| sig.expiration == NOW + 86400));
+}
+
+#[test]
+fn wildcards_are_signed_with_the_labels_of_their_expansions() {
+    let signed = sign_zone(&zone(), &keys(), &policy(Denial::Nsec)).unwrap();
+    let wild = name("*.wild.example.");
+    let (_, sig) = sigs(&signed)
+        .into_iter()
+        .find(|(at, sig)| *at == wild && sig.type_covered == RecordType::A)
+        .unwrap();
+    assert_eq!(sig.labels, 2);
+}
+
+#[test]
+fn publishes_the_keys_with_cds_and_cdnskey_for_the_ksk() {
+    let keys = keys();
+    let signed = sign_zone(&zone(), &keys, &policy(Denial::Nsec)).unwrap();
+    let origin = name("example.");
+    let dnskeys: Vec<Dnskey> = signed
+        .rrset(&origin, RecordType::DNSKEY)
+        .unwrap()
+        .iter()
+        .filter_map(|rr| Dnskey::from_rdata(&rr.rdata))
+        .collect();
+    assert_eq!(dnskeys.len(), 2);
+    assert!(keys.iter().all(|k| dnskeys.contains(k.dnskey())));
+
+    let cds = signed.rrset(&origin, RecordType::CDS).unwrap();
+    let ds = keys[0]
+        .dnskey()
+        .ds(&origin, DigestType::SHA256)
+        .unwrap()
+        .to_rdata();
+    assert_eq!(cds.len(), 1);
+    assert_eq!(cds[0].rdata.to_string(), ds.to_string());
+    let cdnskey = signed.rrset(&origin, RecordType::CDNSKEY).unwrap();
+    assert_eq!(cdnskey.len(), 1);
+    assert_eq!(
+        Dnskey::from_rdata(&cdnskey[0].rdata).as_ref(),
+        Some(keys[0].dnskey())
+    );
+
+    let quiet = SigningPolicy {
+        cds: Vec::new(),
+        cdnskey: false,
+        ..policy(Denial::Nsec)
+    };
+    let signed = sign_zone(&zone(), &keys, &quiet).unwrap();
+    assert!(signed.rrset(&origin, RecordType::CDS).is_none());
+    assert!(signed.rrset(&origin, RecordType::CDNSKEY).is_none());
+}
+
+#[test]
+fn the_nsec_chain_links_every_name_in_order() {
+    let signed = sign_zone(&zone(), &keys(), &policy(Denial::Nsec)).unwrap();
+    let mut chain: Vec<(DomainName, Nsec)> = of(&signed, RecordType::NSEC)
+        .into_iter()
+        .map(|rr| (rr.name.clone(), Nsec::from_rdata(&rr.rdata).unwrap()))
+        .collect();
+    chain.sort_by(|a, b| a.0.cmp(&b.0));
+    // In canonical order, glue and empty non-terminals left out.
+    let owners = [
+        "example.",
+        "a.b.c.example.",
+        "ns1.example.",
+        "sec.example.",
+        "sub.example.",
+        "*.wild.example.",
+        "www.example.",
+    ];
+    assert_eq!(
+        chain
+            .iter()
+            .map(|(owner, _)| owner.clone())
+            .collect::<Vec<_>>(),
+        owners.iter().map(|n| name(n)).collect::<Vec<_>>()
+    );
+    for (i, (_, nsec)) in chain.iter().enumerate() {
+        assert_eq!(nsec.next, name(owners[(i + 1) % owners.len()]));
+    }
+
+    let types =
+        |owner: &str| -> &Nsec { &chain.iter().find(|(o, _)| *o == name(owner)).unwrap().1 };
+    let apex = types("example.");
+    for rtype in [
+        RecordType::SOA,
+        RecordType::NS,
+        RecordType::DNSKEY,
+        RecordType::NSEC,
+    ] {
+        assert!(apex.has(rtype), "{}", rtype);
+    }
+    assert!(types("www.example.").has(RecordType::AAAA));
+    assert!(!types("www.example.").has(RecordType::TXT));
+    // Only the parent's side of a delegation.
+    assert!(types("sub.example.").has(RecordType::NS));
+    assert!(!types("sub.example.").has(RecordType::DS));
+    assert!(types("sec.example.").has(RecordType::DS));
+    assert!(!types("sec.example.").has(RecordType::A));
+}
+
+#[test]
+fn the_nsec3_chain_hashes_every_name_and_can_opt_out() {
+    for opt_out in [false, true] {
+        let signed = sign_zone(&zone(), &keys(), &policy(nsec3(opt_out))).unwrap();
+        assert!(of(&signed, RecordType::NSEC).is_empty());
+        let param = signed
+            .rrset(&name("example."), RecordType::NSEC3PARAM)
+            .unwrap();
+        let param = Nsec3Param::from_rdata(&param[0].rdata).unwrap();
+        assert_eq!((param.iterations, param.salt.len()), (0, 0));
+
+        let mut chain: Vec<(String, Nsec3)> = of(&signed, RecordType::NSEC3)
+            .into_iter()
+            .map(|rr| {
+                let label = String::from_utf8(rr.name.labels()[0].to_vec()).unwrap();
+                (label.to_lowercase(), Nsec3::from_rdata(&rr.rdata).unwrap())
+            })
+            .collect();
+        chain.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Every name with data, the empty non-terminals above a.b.c, and
+        // the delegations unless they are unsigned and opted out.
+        let mut names = vec![
+            "example.",
+            "c.example.",
+            "b.c.example.",
+            "a.b.c.example.",
+            "ns1.example.",
+            "www.example.",
+            "wild.example.",
+            "*.wild.example.",
+            "sec.example.",
+        ];
+        if !opt_out {
+            names.push("sub.example.");
+        }
+        let mut hashes: Vec<String> = names
+            .iter()
+            .map(|n| base32hex_encode(&nsec3_hash(&name(n), &[], 0)).to_lowercase())
+            .collect();
+        hashes.sort();
+        let owners: Vec<&String> = chain.iter().map(|(owner, _)| owner).collect();
+        assert_eq!(
+            owners,
+            hashes.iter().collect::<Vec<_>>(),
+            "opt-out {}",
+            opt_out
+        );
+
+        for (i, (_, nsec3)) in chain.iter().enumerate() {
+            let next = &chain[(i + 1) % chain.len()].0;
+            assert_eq!(base32hex_encode(&nsec3.next_hashed).to_lowercase(), *next);
+            assert_eq!(nsec3.is_opt_out(), opt_out);
+        }
+    }
+}
+
+#[test]
+fn signing_again_replaces_the_old_chain_and_signatures() {
+    let keys = keys();
+    let once = sign_zone(&zone(), &keys, &policy(Denial::Nsec)).unwrap();
+    let later = SigningPolicy {
+        now: Some(NOW + 86400),
+        ..policy(nsec3(false))
+    };
+    let twice = sign_zone(&once, &keys, &later).unwrap();
+    assert!(of(&twice, RecordType::NSEC).is_empty());
+    assert!(!of(&twice, RecordType::NSEC3).is_empty());
+    assert!(sigs(&twice)
+        .iter()
+        .all(|(_, sig)| sig.inception == NOW + 86400 - 3600));
+    // The key set is not published twice.
+    assert_eq!(
+        twice
+            .rrset(&name("example."), RecordType::DNSKEY)
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[test]
+fn refuses_what_it_cannot_sign() {
+    let keys = keys();
+    let policy = policy(Denial::Nsec);
+    assert_eq!(
+        sign_zone(&Zone::new(name("example.")), &keys, &policy).unwrap_err(),
+        SignError::NoSoa
+    );
+    assert_eq!(
+        sign_zone(&zone(), &[], &policy).unwrap_err(),
+        SignError::NoKeys
+    );
+    // Neither a key without the zone flag nor a revoked one that would
+    // sign the zone's data will do.
+    for flags in [0, 256 | Dnskey::REVOKE] {
+        let bad = SigningKey::ed25519(flags, &[3; 32]).unwrap();
+        let tag = bad.key_tag();
+        assert_eq!(
+            sign_zone(&zone(), &[keys[0].clone(), bad], &policy).unwrap_err(),
+            SignError::BadKey(tag)
+        );
+    }
+
+    let iterated = Denial::Nsec3 {
+        iterations: 10,
+        salt: Vec::new(),
+        opt_out: false,
+    };
+    assert_eq!(
+        sign_zone(&zone(), &keys, &self::policy(iterated.clone())).unwrap_err(),
+        SignError::Nsec3Iterations(10)
+    );
+    let salted = Denial::Nsec3 {
+        iterations: 0,
+        salt: vec![0xaa],
+        opt_out: false,
+    };
+    assert_eq!(
+        sign_zone(&zone(), &keys, &self::policy(salted)).unwrap_err(),
+        SignError::Nsec3Salt
+    );
+    let legacy = SigningPolicy {
+        max_nsec3_iterations: 10,
+        ..self::policy(iterated)
+    };
+    assert!(sign_zone(&zone(), &keys, &legacy).is_ok());
+}
+
+#[test]
+fn a_validator_anchored_at_the_ksk_accepts_the_signed_zone() {
+    for denial in [Denial::Nsec, nsec3(false), nsec3(true)] {
+        let keys = keys();
+        let authority = Authority::new();
+        authority.insert(sign_zone(&zone(), &keys, &policy(denial.clone())).unwrap());
+        let server = MockServer::builder().handler(authority).start().unwrap();
+        let resolver = Resolver::new(ResolverConfig {
+            servers: vec![server.addr()],
+            ..ResolverConfig::default()
+        });
+        let mut anchors = TrustAnchors::new();
+        anchors.add(
+            name("example."),
+            AnchorKey::Dnskey(keys[0].dnskey().clone()),
+        );
+        let validator = Validator::new(resolver)
+            .anchors(anchors)
+            .clock(Arc::new(MockClock::new(u64::from(NOW))));
+        // An opted-out span of the NSEC3 chain may hide unsigned
+        // delegations, so a name it covers is only insecurely denied
+        // (RFC 5155 §6).
+        let opt_out = matches!(denial, Denial::Nsec3 { opt_out: true, .. });
+        for (qname, qtype, covered) in [
+            ("www.example.", RecordType::A, false),
+            ("example.", RecordType::CDS, false),
+            ("a.b.c.example.", RecordType::TXT, false),
+            ("sec.example.", RecordType::DS, false),
+            ("nope.example.", RecordType::A, true),
+            ("b.c.example.", RecordType::A, false),
+            ("www.example.", RecordType::TXT, false),
+            ("x.wild.example.", RecordType::A, false),
+        ] {
+            let expected = match opt_out && covered {
+                true => Status::Insecure,
+                false => Status::Secure,
+            };
+            let v = validator.query(&name(qname), qtype).unwrap();
+            assert_eq!(
+                (v.status, v.reason),
+                (expected, None),
+                "{} {} {:?}",
+                qname,
+                qtype,
+                denial
+            );
+        }
+        // Nothing proves the unsigned delegation has no DS records when it
+        // is opted out of the NSEC3 chain.
+        let v = validator
+            .query(&name("sub.example."), RecordType::DS)
+            .unwrap();
+        let expected = match opt_out {
+            true => Status::Insecure,
+            false => Status::Secure,
+        };
+        assert_eq!(v.status, expected, "{:?}", denial);
+    }
+}
@@ -0,0 +1,181 @@
+//! SSH host key fingerprints (RFC 4255, RFC 6594): the SSHFP records of
+//! OpenSSH public keys, checked against what `ssh-keygen -r` prints for
+//! them, and host keys verified against secure records only.
+
+#![cfg(feature = "dnssec")]
+
+use std::sync::Arc;
+
+use mairudns::dnssec::{
+    parse_openssh_key, sign_zone, sshfp_records, verify_sshfp, HostKeyCheck, SigningKey,
+    SigningPolicy, Sshfp, Status, TrustAnchors, Validated, Validator,
+};
+use mairudns::encoding::hex_decode;
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{Record, RecordType};
+use mairudns::server::Authority;
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+/// Host keys made by `ssh-keygen`, and the records `ssh-keygen -r` gives
+/// for them.
+const ED25519: &str =
+    "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAnFpYB+X7187FWeDHADwXm1Dzw2h358vbG1GIKl2FHf root@host";
+const ED25519_SSHFP: [&str; 2] = [
+    "4 1 c225ac54bbef174fb2d95efda3af3b07caa907fa",
+    "4 2 6920142ec4b63b9aa61e02cc6ee3fc33184a981ef4cad21c70b0ffc39d56a32d",
+];
+const ECDSA: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBKlmm0/L7gzEQHhwB2WFqYM753YpFh/8Lo+8CsO5IJ0b6sYrQeDSsgQru9sGs0CDcg6geAepyXTIDTKpKlDVglU= root@host";
+const ECDSA_SSHFP: [&str; 2] = [
+    "3 1 a7a845adb641ea9c5d4263c89cb42c27d38965dc",
+    "3 2 0cdd07e20741c81e1a00c8d31086898a926cddb5d5acc141837006dd3532a8ee",
+];
+const RSA: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQDcyOI7LAzELt6ao0c8Q8gsEm5wVxs4ibejOVGGBZmJNDOfKPFkMhG3YkVJEFTXdpknhUQKyZtKEO+kVM0B4Uu4kdtK8iq7W737CR0u5EQKtvOKFQRE6GXxDQ6FoBDlvtyp81efYakCnOAietn99ifZ7EflxJjHWFRee3f4gSEk5w== root@host";
+const RSA_SSHFP: [&str; 2] = [
+    "1 1 37c8d71b6cf6477c5b3353b25bc5e392eac6480a",
+    "1 2 3cc6ae8371747c2f82ec5019390553898c526f636bad527e7b03d20579578ce8",
+];
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn sshfp(text: &str) -> Sshfp {
+    let fields: Vec<&str> = text.split(' ').collect();
+    Sshfp {
+        algorithm: fields[0].parse().unwrap(),
+        fingerprint_type: fields[1].parse().unwrap(),
+        fingerprint: hex_decode(fields[2]).unwrap(),
+    }
+}
+
+#[test]
+fn records_are_those_ssh_keygen_gives() {
+    for (key, expected) in [
+        (ED25519, ED25519_SSHFP),
+        (ECDSA, ECDSA_SSHFP),
+        (RSA, RSA_SSHFP),
+    ] {
+        let records: Vec<String> = sshfp_records(key)
+            .unwrap()
+            .iter()
+            .map(Sshfp::to_string)
+            .collect();
+        assert_eq!(records, expected);
+        let record = sshfp(expected[1]);
+        assert_eq!(Sshfp::from_rdata(&record.to_rdata()), Some(record.clone()));
+        assert!(record.matches(&parse_openssh_key(key).unwrap()));
+    }
+    let key = parse_openssh_key(ED25519).unwrap();
+    assert_eq!(Sshfp::new(&key, 3), None);
+    assert!(!sshfp(ED25519_SSHFP[1]).matches(&parse_openssh_key(RSA).unwrap()));
+}
+
+#[test]
+fn keys_must_be_what_they_claim() {
+    // The comment is optional.
+    let bare = ED25519.rsplit_once(' ').unwrap().0;
+    assert_eq!(parse_openssh_key(bare), parse_openssh_key(ED25519));
+    // The type outside the key must match the one inside.
+    let relabelled = ED25519.replacen("ssh-ed25519", "ssh-rsa", 1);
+    assert_eq!(parse_openssh_key(&relabelled), None);
+    assert_eq!(parse_openssh_key("ssh-ed25519 !!!"), None);
+    assert_eq!(parse_openssh_key("ssh-ed25519"), None);
+    assert_eq!(parse_openssh_key(""), None);
+    // A type without an SSHFP algorithm.
+    let mut unknown = vec![0, 0, 0, 7];
+    unknown.extend_from_slice(b"ssh-foo");
+    let line = format!("ssh-foo {}", mairudns::encoding::base64_encode(&unknown));
+    assert_eq!(parse_openssh_key(&line), None);
+    assert_eq!(sshfp_records(&line), None);
+}
+
+/// A signed `example.` with `records` at `host.example.`, and a validator
+/// that trusts it.
+fn setup(records: &[&str]) -> (MockServer, Validator) {
+    let mut zone = Zone::from_master(
+        name("example."),
+        "$TTL 3600\n@ SOA ns hostmaster 1 3600 600 86400 300\n@ NS ns\nns A 192.0.2.53\n",
+    )
+    .unwrap();
+    for text in records {
+        let record = Record::new(name("host.example."), 3600, sshfp(text).to_rdata());
+        zone.insert(record).unwrap();
+    }
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let signed = sign_zone(&zone, &[key], &SigningPolicy::default()).unwrap();
+    let anchors =
+        TrustAnchors::from_records(signed.rrset(&name("example."), RecordType::DNSKEY).unwrap());
+    let authority = Arc::new(Authority::new());
+    authority.insert(signed);
+    let server = MockServer::builder().handler(authority).start().unwrap();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    (server, Validator::new(resolver).anchors(anchors))
+}
+
+fn check(records: &[&str], key: &str) -> HostKeyCheck {
+    let (_server, validator) = setup(records);
+    let validated = validator
+        .query(&name("host.example."), RecordType::SSHFP)
+        .unwrap();
+    assert_eq!(validated.status, Status::Secure);
+    verify_sshfp(&parse_openssh_key(key).unwrap(), &validated)
+}
+
+#[test]
+fn host_keys_are_verified_by_their_algorithms_records() {
+    let all = [ED25519_SSHFP, ECDSA_SSHFP, RSA_SSHFP].concat();
+    for key in [ED25519, ECDSA, RSA] {
+        assert_eq!(check(&all, key), HostKeyCheck::Verified);
+    }
+    // Records for the key's algorithm that are not its fingerprint.
+    let other = "4 2 0000000000000000000000000000000000000000000000000000000000000000";
+    assert_eq!(check(&[other], ED25519), HostKeyCheck::Mismatch);
+    // None for its algorithm at all.
+    assert_eq!(check(&ECDSA_SSHFP, ED25519), HostKeyCheck::Insecure);
+    assert_eq!(check(&[], ED25519), HostKeyCheck::Insecure);
+
+    // SHA-1 counts only without SHA-256 (RFC 6594 §4.1).
+    assert_eq!(check(&ED25519_SSHFP[..1], ED25519), HostKeyCheck::Verified);
+    assert_eq!(
+        check(&[ED25519_SSHFP[0], other], ED25519),
+        HostKeyCheck::Mismatch
+    );
+    // Fingerprint types this crate does not know are left out.
+    let unknown = "4 9 00";
+    assert_eq!(check(&[unknown], ED25519), HostKeyCheck::Insecure);
+    assert_eq!(
+        check(&[unknown, ED25519_SSHFP[1]], ED25519),
+        HostKeyCheck::Verified
+    );
+}
+
+#[test]
+fn records_count_only_when_secure() {
+    let (_server, validator) = setup(&ED25519_SSHFP);
+    let key = parse_openssh_key(ED25519).unwrap();
+    let secure = validator
+        .query(&name("host.example."), RecordType::SSHFP)
+        .unwrap();
+    let judged = |status| Validated {
+        status,
+        ..secure.clone()
+    };
+    assert_eq!(verify_sshfp(&key, &secure), HostKeyCheck::Verified);
+    assert_eq!(
+        verify_sshfp(&key, &judged(Status::Insecure)),
+        HostKeyCheck::Insecure
+    );
+    assert_eq!(
+        verify_sshfp(&key, &judged(Status::Bogus)),
+        HostKeyCheck::Failed
+    );
+    assert_eq!(
+        verify_sshfp(&key, &judged(Status::Indeterminate)),
+        HostKeyCheck::Failed
+    );
+}
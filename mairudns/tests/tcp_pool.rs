@@ -0,0 +1,254 @@
+//! Persistent TCP connections: the edns-tcp-keepalive option, pipelined
+//! queries answered out of order, the limits on connections, and closing
+//! connections when idle or when the server asks.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::{read_framed, write_framed, PoolPolicy, Protocol, TcpPool};
+use mairudns::message::{EdnsOption, Message, OptionCode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{QueryOptions, Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// How a test server answers on each connection.
+#[derive(Clone, Copy)]
+struct Behavior {
+    /// Queries read before any is answered; they are answered in reverse.
+    batch: usize,
+    /// The keepalive timeout given in responses, in units of 100 ms.
+    keepalive: Option<u16>,
+    /// How long each answer takes.
+    delay: Duration,
+}
+
+/// A server answering qN.example. with 192.0.2.N, counting connections.
+fn server(behavior: Behavior) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counted = connections.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            counted.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || loop {
+                let mut queries = Vec::new();
+                for _ in 0..behavior.batch {
+                    match read_framed(&mut stream) {
+                        Ok(wire) => queries.push(Message::from_wire(&wire).unwrap()),
+                        Err(_) => return,
+                    }
+                }
+                for query in queries.into_iter().rev() {
+                    assert_eq!(query.tcp_keepalive(), Some(None));
+                    thread::sleep(behavior.delay);
+                    let mut resp = query.response();
+                    let qname = query.questions[0].name.clone();
+                    let n = qname.labels()[0][1] - b'0';
+                    resp.answers
+                        .push(Record::new(qname, 60, RData::A([192, 0, 2, n].into())));
+                    if let Some(t) = behavior.keepalive {
+                        resp.set_tcp_keepalive(Some(Duration::from_millis(u64::from(t) * 100)));
+                    }
+                    if write_framed(&mut stream, &resp.to_wire().unwrap()).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    (addr, connections)
+}
+
+fn answering(keepalive: Option<u16>) -> Behavior {
+    Behavior {
+        batch: 1,
+        keepalive,
+        delay: Duration::from_millis(0),
+    }
+}
+
+fn query(n: u8) -> Message {
+    Message::query(name(&format!("q{}.example.", n)), RecordType::A)
+}
+
+/// Sends `query(n)` for each of `ns` at once and checks the answers.
+fn concurrently(pool: &TcpPool, addr: SocketAddr, ns: &[u8]) {
+    let handles: Vec<_> = ns
+        .iter()
+        .map(|&n| {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                let mut q = query(n);
+                q.header.id = 1000 + u16::from(n);
+                let resp = pool.exchange(addr, &q, TIMEOUT).unwrap();
+                assert_eq!(resp.header.id, q.header.id);
+                assert_eq!(resp.answers[0].rdata, RData::A([192, 0, 2, n].into()));
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn keepalive_option_is_in_units_of_100_ms() {
+    let mut q = query(1);
+    assert_eq!(q.tcp_keepalive(), None);
+    q.set_tcp_keepalive(None);
+    assert_eq!(q.tcp_keepalive(), Some(None));
+    let edns = q.edns.as_ref().unwrap();
+    assert_eq!(edns.options.len(), 1);
+    assert_eq!(edns.options[0].code, OptionCode::TCP_KEEPALIVE);
+    assert!(edns.options[0].data.is_empty());
+
+    let mut resp = q.response();
+    resp.set_tcp_keepalive(Some(Duration::from_millis(12_345)));
+    assert_eq!(resp.edns.as_ref().unwrap().options[0].data, vec![0, 123]);
+    assert_eq!(
+        resp.tcp_keepalive(),
+        Some(Some(Duration::from_millis(12_300)))
+    );
+    // Replaced, not repeated, and capped at what 16 bits hold.
+    resp.set_tcp_keepalive(Some(Duration::from_secs(1 << 20)));
+    assert_eq!(resp.edns.as_ref().unwrap().options.len(), 1);
+    assert_eq!(
+        resp.tcp_keepalive(),
+        Some(Some(Duration::from_millis(6_553_500)))
+    );
+    let wire = resp.to_wire().unwrap();
+    assert_eq!(
+        Message::from_wire(&wire).unwrap().tcp_keepalive(),
+        Some(Some(Duration::from_millis(6_553_500)))
+    );
+
+    // Malformed options are ignored, and messages without EDNS get none.
+    resp.edns.as_mut().unwrap().options = vec![EdnsOption {
+        code: OptionCode::TCP_KEEPALIVE,
+        data: vec![1],
+    }];
+    assert_eq!(resp.tcp_keepalive(), None);
+    let mut plain = query(1);
+    plain.edns = None;
+    plain.set_tcp_keepalive(None);
+    assert_eq!(plain.tcp_keepalive(), None);
+}
+
+#[test]
+fn pipelines_queries_answered_out_of_order() {
+    let (addr, connections) = server(Behavior {
+        batch: 4,
+        ..answering(Some(50))
+    });
+    let pool = TcpPool::new(PoolPolicy {
+        max_connections: 1,
+        ..PoolPolicy::default()
+    });
+    concurrently(&pool, addr, &[1, 2, 3, 4]);
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.connections(addr), 1);
+}
+
+#[test]
+fn opens_connections_for_busy_ones_up_to_the_limit() {
+    let (addr, connections) = server(Behavior {
+        delay: Duration::from_millis(300),
+        ..answering(Some(50))
+    });
+    let pool = TcpPool::new(PoolPolicy {
+        max_connections: 2,
+        max_outstanding: 1,
+        ..PoolPolicy::default()
+    });
+    concurrently(&pool, addr, &[1, 2, 3, 4]);
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    assert_eq!(pool.connections(addr), 2);
+}
+
+#[test]
+fn closes_idle_connections_when_the_server_says() {
+    // The server allows half a second of idleness.
+    let (addr, connections) = server(answering(Some(5)));
+    let pool = TcpPool::new(PoolPolicy::default());
+    pool.exchange(addr, &query(1), TIMEOUT).unwrap();
+    pool.exchange(addr, &query(2), TIMEOUT).unwrap();
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    thread::sleep(Duration::from_millis(1500));
+    assert_eq!(pool.connections(addr), 0);
+    pool.exchange(addr, &query(3), TIMEOUT).unwrap();
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+
+    // Without a timeout from the server, the pool's own applies; a server
+    // allowing longer is held to the pool's maximum.
+    for keepalive in [None, Some(600)] {
+        let (addr, _) = server(answering(keepalive));
+        let pool = TcpPool::new(PoolPolicy {
+            idle_timeout: Duration::from_millis(500),
+            max_idle_timeout: Duration::from_millis(500),
+            ..PoolPolicy::default()
+        });
+        pool.exchange(addr, &query(1), TIMEOUT).unwrap();
+        assert_eq!(pool.connections(addr), 1);
+        thread::sleep(Duration::from_millis(1500));
+        assert_eq!(pool.connections(addr), 0);
+    }
+
+    // A timeout of zero closes the connection at once.
+    let (addr, connections) = server(answering(Some(0)));
+    let pool = TcpPool::new(PoolPolicy::default());
+    pool.exchange(addr, &query(1), TIMEOUT).unwrap();
+    pool.exchange(addr, &query(2), TIMEOUT).unwrap();
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn retries_on_a_fresh_connection_when_a_reused_one_is_closed() {
+    // The server closes each connection after one response.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let q = Message::from_wire(&read_framed(&mut stream).unwrap()).unwrap();
+            write_framed(&mut stream, &q.response().to_wire().unwrap()).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+    let pool = TcpPool::new(PoolPolicy::default());
+    for n in 1..=3 {
+        pool.exchange(addr, &query(n), TIMEOUT).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn resolvers_share_the_pool_for_tcp_queries() {
+    let (addr, connections) = server(answering(Some(50)));
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![addr],
+        tcp_pool: Some(TcpPool::new(PoolPolicy::default())),
+        ..ResolverConfig::default()
+    });
+    let tcp = QueryOptions {
+        protocol: Some(Protocol::Tcp),
+        ..QueryOptions::default()
+    };
+    for n in 1..=3 {
+        let answers = resolver
+            .lookup_with(&name(&format!("q{}.example.", n)), RecordType::A, &tcp)
+            .unwrap();
+        assert_eq!(answers[0].rdata, RData::A([192, 0, 2, n].into()));
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,193 @@
+//! Record templates: expansion over several ranges with padded counters
+//! in any base, address offsets, literal dollars, TTLs from the zone,
+//! and the errors of expressions and records that do not work out.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, RecordClass, RecordType};
+use mairudns::zone::{Error, Template, TemplateError, Value, Zone};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn lines(template: &Template) -> Vec<String> {
+    template
+        .expand(&name("example."))
+        .unwrap()
+        .iter()
+        .map(|rr| rr.to_string())
+        .collect()
+}
+
+#[test]
+fn ranges_are_expanded_first_slowest() {
+    let template = Template::new("h${i:3}-${j:2:x}", RecordType::AAAA, "${net+i-1}")
+        .var("net", "2001:db8::ff".parse::<Ipv6Addr>().unwrap())
+        .range("i", 1, 3)
+        .range_step("j", 10, 30, 10)
+        .ttl(60);
+    assert_eq!(template.count(), 9);
+    let expanded = lines(&template);
+    assert_eq!(expanded.len(), 9);
+    assert_eq!(expanded[0], "h001-0a.example.\t60\tIN\tAAAA\t2001:db8::ff");
+    assert_eq!(expanded[1], "h001-14.example.\t60\tIN\tAAAA\t2001:db8::ff");
+    assert_eq!(expanded[3], "h002-0a.example.\t60\tIN\tAAAA\t2001:db8::100");
+    assert_eq!(expanded[8], "h003-1e.example.\t60\tIN\tAAAA\t2001:db8::101");
+
+    // Bases, widths, negative counters, steps that overshoot the end.
+    let template = Template::new("n${i:4:X}.${i:0:o}.${i:3}", RecordType::A, "192.0.2.1")
+        .range_step("i", -9, 20, 13);
+    assert_eq!(
+        lines(&template),
+        [
+            "n-0009.-11.-009.example.\t3600\tIN\tA\t192.0.2.1",
+            "n0004.4.004.example.\t3600\tIN\tA\t192.0.2.1",
+            "n0011.21.017.example.\t3600\tIN\tA\t192.0.2.1",
+        ]
+    );
+
+    // No ranges: one record. An empty range: none. Steps are at least 1.
+    assert_eq!(Template::new("a", RecordType::A, "192.0.2.1").count(), 1);
+    assert_eq!(
+        Template::new("a${i}", RecordType::A, "192.0.2.1")
+            .range("i", 2, 1)
+            .count(),
+        0
+    );
+    assert_eq!(
+        Template::new("a${i}", RecordType::A, "192.0.2.1")
+            .range_step("i", 1, 3, 0)
+            .count(),
+        3
+    );
+}
+
+#[test]
+fn variables_are_substituted() {
+    let template = Template::new("x${i}", RecordType::TXT, "\"cost $$${i}\" ${name}")
+        .var("name", "plain")
+        .range("i", -1, 0);
+    assert_eq!(
+        lines(&template),
+        [
+            "x-1.example.\t3600\tIN\tTXT\t\"cost $-1\" \"plain\"",
+            "x0.example.\t3600\tIN\tTXT\t\"cost $0\" \"plain\"",
+        ]
+    );
+
+    // Absolute owners, other classes, and variables of every kind.
+    let template = Template::new("${host}${n}.example.net.", RecordType::A, "${base - 2 + n}")
+        .var("host", String::from("web"))
+        .var("n", 7u32)
+        .var("base", Ipv4Addr::new(192, 0, 2, 0))
+        .class(RecordClass::CH);
+    let records = template.expand(&name("example.")).unwrap();
+    assert_eq!(records[0].name, name("web7.example.net."));
+    assert_eq!(records[0].class, RecordClass::CH);
+    assert_eq!(records[0].rdata, RData::A(Ipv4Addr::new(192, 0, 2, 5)));
+    assert_eq!(
+        Value::from("192.0.2.1".parse::<std::net::IpAddr>().unwrap()).to_string(),
+        "192.0.2.1"
+    );
+    assert_eq!(Value::from(-3i64), Value::Int(-3));
+}
+
+#[test]
+fn bad_templates_are_reported() {
+    let origin = name("example.");
+    let error = |template: Template| template.range("i", 0, 1).expand(&origin).unwrap_err();
+    let a = |rdata: &str| Template::new("x${i}", RecordType::A, rdata);
+    let v4 = |rdata: &str| a(rdata).var("n", Ipv4Addr::new(255, 255, 255, 255));
+
+    assert_eq!(
+        error(Template::new("x${q}", RecordType::A, "192.0.2.1")),
+        TemplateError::UnknownVariable("q".into())
+    );
+    assert_eq!(error(a("${}")), TemplateError::Syntax("".into()));
+    assert_eq!(error(a("${i+}")), TemplateError::Syntax("i+".into()));
+    assert_eq!(error(a("${-i}")), TemplateError::Syntax("-i".into()));
+    assert_eq!(error(a("${i:w}")), TemplateError::Syntax("i:w".into()));
+    assert_eq!(error(a("${i:2:b}")), TemplateError::Syntax("i:2:b".into()));
+    assert_eq!(
+        error(a("${i:2:x:y}")),
+        TemplateError::Syntax("i:2:x:y".into())
+    );
+    assert_eq!(error(a("${i")), TemplateError::Syntax("${i".into()));
+    assert_eq!(error(a("$i")), TemplateError::Syntax("$i".into()));
+    assert_eq!(error(v4("${n+i}")), TemplateError::Overflow("n+i".into()));
+    assert_eq!(
+        error(v4("${n+n}")),
+        TemplateError::BadOperands("n+n".into())
+    );
+    assert_eq!(
+        error(v4("${i-n}")),
+        TemplateError::BadOperands("i-n".into())
+    );
+    assert_eq!(
+        error(v4("${0-n}")),
+        TemplateError::BadOperands("0-n".into())
+    );
+    assert_eq!(
+        error(v4("${n:3}")),
+        TemplateError::BadOperands("n:3".into())
+    );
+    assert_eq!(
+        error(a("${b+1}").var("b", i64::MAX)),
+        TemplateError::Overflow("b+1".into())
+    );
+
+    // The record is reported with the values it was expanded for.
+    let bad = error(a("not.an.address"));
+    match &bad {
+        TemplateError::Record { bindings, .. } => assert_eq!(bindings, "i=0"),
+        other => panic!("{:?}", other),
+    }
+    assert!(bad.to_string().starts_with("with i=0: "));
+
+    let many = Template::new("x${i}-${j}", RecordType::A, "192.0.2.1")
+        .range("i", 1, 10_000)
+        .range("j", 1, 1_000);
+    assert_eq!(many.count(), 10_000_000);
+    assert_eq!(
+        many.expand(&origin).unwrap_err(),
+        TemplateError::TooMany(10_000_000)
+    );
+    assert_eq!(
+        TemplateError::TooMany(10_000_000).to_string(),
+        "template expands to 10000000 records, more than 1000000"
+    );
+}
+
+#[test]
+fn templates_are_applied_to_zones() {
+    let mut zone = Zone::from_master(
+        name("2.0.192.in-addr.arpa."),
+        "@ 3600 SOA ns.example. hostmaster.example. 1 7200 900 1209600 120\n",
+    )
+    .unwrap();
+    let added = Template::new("${i}", RecordType::PTR, "host${i:3}.example.")
+        .range("i", 1, 254)
+        .apply(&mut zone)
+        .unwrap();
+    assert_eq!(added, 254);
+    assert_eq!(zone.records().count(), 255);
+    let ptr = zone
+        .records()
+        .find(|rr| rr.name == name("7.2.0.192.in-addr.arpa."))
+        .unwrap();
+    // Without a TTL of their own, records get the zone's minimum.
+    assert_eq!(ptr.ttl, 120);
+    assert_eq!(ptr.rdata, RData::Ptr(name("host007.example.")));
+
+    let outside = Template::new("a.example.org.", RecordType::A, "192.0.2.1")
+        .ttl(5)
+        .apply(&mut zone);
+    assert_eq!(outside, Err(TemplateError::Zone(Error::OutOfZone)));
+    let mut empty = Zone::new(name("example."));
+    Template::new("a", RecordType::A, "192.0.2.1")
+        .apply(&mut empty)
+        .unwrap();
+    assert_eq!(empty.records().next().unwrap().ttl, 3600);
+}
@@ -0,0 +1,193 @@
+//! Iterative resolution traced through mock root and zone servers.
+//!
+//! Referred servers are always reached on port 53, so the mocks listen on
+//! loopback addresses other than 127.0.0.1 at that port. Where binding it
+//! is not allowed the tests return early.
+
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use mairudns::message::{Header, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig, StepKind};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer, MockServerBuilder};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn a(owner: &str, addr: [u8; 4]) -> Record {
+    Record::new(name(owner), 3600, RData::A(addr.into()))
+}
+
+fn ns(zone: &str, host: &str) -> Record {
+    Record::new(name(zone), 3600, RData::Ns(name(host)))
+}
+
+fn respond(
+    aa: bool,
+    answers: Vec<Record>,
+    authority: Vec<Record>,
+    additional: Vec<Record>,
+) -> Action {
+    Action::Respond(Message {
+        header: Header {
+            aa,
+            ..Header::default()
+        },
+        answers,
+        authority,
+        additional,
+        ..Message::default()
+    })
+}
+
+/// Starts `builder` on port 53 of `ip`, or `None` without the privilege.
+fn on_port_53(builder: MockServerBuilder, ip: [u8; 4]) -> Option<MockServer> {
+    match builder.start_on(SocketAddr::from((ip, 53))) {
+        Ok(server) => Some(server),
+        Err(e) if matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::AddrInUse) => None,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+fn stub(root_ip: [u8; 4]) -> MockServer {
+    MockServer::builder()
+        .answer(
+            DomainName::root(),
+            RecordType::NS,
+            vec![ns(".", "a.root.test")],
+        )
+        .answer(
+            name("a.root.test"),
+            RecordType::A,
+            vec![a("a.root.test", root_ip)],
+        )
+        .start()
+        .unwrap()
+}
+
+fn resolver(stub: &MockServer) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![stub.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    })
+}
+
+#[test]
+fn follows_referrals_and_cnames() {
+    let referral = || {
+        respond(
+            false,
+            Vec::new(),
+            vec![ns("example", "ns.example")],
+            vec![a("ns.example", [127, 0, 0, 3])],
+        )
+    };
+    let root = MockServer::builder()
+        .on(name("www.example"), RecordType::A, referral())
+        .on(name("host.example"), RecordType::A, referral());
+    let root = match on_port_53(root, [127, 0, 0, 2]) {
+        Some(root) => root,
+        None => return,
+    };
+    let zone = MockServer::builder()
+        .on(
+            name("www.example"),
+            RecordType::A,
+            respond(
+                true,
+                vec![Record::new(
+                    name("www.example"),
+                    300,
+                    RData::Cname(name("host.example")),
+                )],
+                Vec::new(),
+                // Nothing the servers of example. may speak for.
+                vec![a("elsewhere.test", [192, 0, 2, 99])],
+            ),
+        )
+        .on(
+            name("host.example"),
+            RecordType::A,
+            respond(
+                true,
+                vec![a("host.example", [192, 0, 2, 1])],
+                Vec::new(),
+                Vec::new(),
+            ),
+        );
+    let zone = on_port_53(zone, [127, 0, 0, 3]).unwrap();
+    let stub = stub([127, 0, 0, 2]);
+
+    let trace = resolver(&stub)
+        .trace(&name("www.example"), RecordType::A)
+        .unwrap();
+    let kinds: Vec<StepKind> = trace.steps.iter().map(|s| s.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            StepKind::Priming,
+            StepKind::Referral {
+                zone: name("example")
+            },
+            StepKind::Cname {
+                target: name("host.example")
+            },
+            StepKind::Referral {
+                zone: name("example")
+            },
+            StepKind::Answer,
+        ]
+    );
+    assert_eq!(trace.steps[2].scrubbed, 1);
+    assert_eq!(trace.steps[1].server_name, Some(name("a.root.test")));
+    assert_eq!(trace.steps[2].server_name, Some(name("ns.example")));
+    let answer = trace.answer().unwrap();
+    assert_eq!(answer.answers, vec![a("host.example", [192, 0, 2, 1])]);
+    let servers: Vec<SocketAddr> = trace.servers().collect();
+    assert_eq!(
+        servers,
+        vec![
+            stub.addr(),
+            root.addr(),
+            zone.addr(),
+            root.addr(),
+            zone.addr()
+        ]
+    );
+    // Iterative queries do not ask for recursion.
+    assert!(zone.received().iter().all(|r| !r.message.header.rd));
+    let text = trace.to_string();
+    assert!(text.contains("host.example."));
+    assert!(text.contains(";; Received"));
+    assert!(text.contains(";; dropped 1 out-of-bailiwick records"));
+}
+
+#[test]
+fn records_a_failure_when_no_server_answers_usefully() {
+    let root = MockServer::builder().on(
+        name("broken.example"),
+        RecordType::A,
+        Action::Rcode(Rcode::SERVFAIL),
+    );
+    let _root = match on_port_53(root, [127, 0, 0, 4]) {
+        Some(root) => root,
+        None => return,
+    };
+    let stub = stub([127, 0, 0, 4]);
+    let trace = resolver(&stub)
+        .trace(&name("broken.example"), RecordType::A)
+        .unwrap();
+    let last = trace.steps.last().unwrap();
+    assert_eq!(last.kind, StepKind::Failure);
+    assert!(last.response.is_none());
+    assert!(trace.answer().is_none());
+    assert!(trace
+        .to_string()
+        .contains(";; no usable response for zone ."));
+}
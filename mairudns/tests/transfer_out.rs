@@ -0,0 +1,217 @@
+//! Serving zone transfers: AXFR streamed over several TCP messages, IXFR
+//! from the journal with a fallback to the whole zone, transfer ACLs and
+//! the limit on concurrent transfers.
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::addr::Prefix;
+use mairudns::client::{read_framed, write_framed, Protocol};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{Authority, Control, Handler, Request, Server, TransferAcl};
+use mairudns::zone::{Diff, Zone};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn soa(serial: u32) -> Record {
+    Record::new(
+        name("example.com"),
+        3600,
+        RData::Soa(Soa {
+            mname: name("ns.example.com"),
+            rname: name("hostmaster.example.com"),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+        }),
+    )
+}
+
+fn host(i: u32) -> Record {
+    Record::new(
+        name(&format!("h{}.example.com", i)),
+        60,
+        RData::A([10, 0, (i / 256) as u8, (i % 256) as u8].into()),
+    )
+}
+
+fn authority(hosts: u32, allowed: &str) -> Arc<Authority> {
+    let mut zone = Zone::new(name("example.com"));
+    zone.insert(soa(1)).unwrap();
+    for i in 0..hosts {
+        zone.insert(host(i)).unwrap();
+    }
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone);
+    authority.set_transfer_acl(
+        name("example.com"),
+        TransferAcl {
+            addresses: vec![allowed.parse::<Prefix>().unwrap()],
+            keys: Vec::new(),
+        },
+    );
+    authority
+}
+
+/// Bumps the serial to 2, replacing `h0` with `h9999`.
+fn update(authority: &Authority) {
+    authority
+        .zone(&name("example.com"))
+        .unwrap()
+        .write()
+        .unwrap()
+        .apply(Diff {
+            old_soa: soa(1),
+            deleted: vec![host(0)],
+            new_soa: soa(2),
+            added: vec![host(9999)],
+        })
+        .unwrap();
+}
+
+fn ixfr(serial: u32) -> Message {
+    let mut query = Message::query(name("example.com"), RecordType::IXFR);
+    query.authority.push(soa(serial));
+    query
+}
+
+fn request(message: Message, protocol: Protocol) -> Request {
+    Request {
+        message,
+        src: "127.0.0.1:53000".parse().unwrap(),
+        protocol,
+        key: None,
+    }
+}
+
+struct Running {
+    addr: SocketAddr,
+    control: Control,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.control.shutdown();
+    }
+}
+
+fn serve(authority: Arc<Authority>, max_transfers: usize) -> Running {
+    let mut server = Server::new(authority);
+    server.set_max_transfers(max_transfers);
+    let addr = server.listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let control = server.control();
+    thread::spawn(move || server.run());
+    Running { addr, control }
+}
+
+/// Sends `query` over TCP and reads messages until the closing SOA or an
+/// error rcode.
+fn stream(addr: SocketAddr, query: &Message) -> Vec<Message> {
+    let mut conn = TcpStream::connect(addr).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    write_framed(&mut conn, &query.to_wire().unwrap()).unwrap();
+    let mut messages = Vec::new();
+    let mut soas = 0;
+    loop {
+        let msg = Message::from_wire(&read_framed(&mut conn).unwrap()).unwrap();
+        soas += msg
+            .answers
+            .iter()
+            .filter(|rr| rr.rtype() == RecordType::SOA)
+            .count();
+        let done = msg.header.rcode != Rcode::NOERROR
+            || soas >= 2
+            || (soas == 1 && msg.answers.len() == 1 && messages.is_empty());
+        messages.push(msg);
+        if done {
+            return messages;
+        }
+    }
+}
+
+fn answers(messages: &[Message]) -> Vec<Record> {
+    messages.iter().flat_map(|m| m.answers.clone()).collect()
+}
+
+#[test]
+fn streams_axfr_over_several_messages() {
+    let server = serve(authority(2000, "127.0.0.0/8"), 10);
+    let query = Message::query(name("example.com"), RecordType::AXFR);
+    let messages = stream(server.addr, &query);
+    assert!(messages.len() > 1);
+    assert_eq!(messages[0].questions, query.questions);
+    assert!(messages[1..].iter().all(|m| m.questions.is_empty()));
+    assert!(messages.iter().all(|m| m.header.id == query.header.id));
+    let records = answers(&messages);
+    assert_eq!(records.len(), 2002);
+    assert_eq!(records.first(), Some(&soa(1)));
+    assert_eq!(records.last(), Some(&soa(1)));
+}
+
+#[test]
+fn refuses_clients_outside_the_acl_and_beyond_the_limit() {
+    let server = serve(authority(10, "192.0.2.0/24"), 10);
+    let query = Message::query(name("example.com"), RecordType::AXFR);
+    let messages = stream(server.addr, &query);
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].header.rcode, Rcode::REFUSED);
+
+    let server = serve(authority(10, "127.0.0.0/8"), 0);
+    let messages = stream(server.addr, &query);
+    assert_eq!(messages[0].header.rcode, Rcode::REFUSED);
+
+    // Nobody at all without an ACL.
+    assert!(!TransferAcl::default().allows("127.0.0.1".parse().unwrap(), None));
+}
+
+#[test]
+fn serves_ixfr_from_the_journal() {
+    let authority = authority(10, "127.0.0.0/8");
+    update(&authority);
+    let server = serve(authority, 10);
+    let records = answers(&stream(server.addr, &ixfr(1)));
+    assert_eq!(
+        records,
+        vec![soa(2), soa(1), host(0), soa(2), host(9999), soa(2)]
+    );
+}
+
+#[test]
+fn falls_back_to_axfr_when_the_journal_does_not_reach() {
+    let authority = authority(10, "127.0.0.0/8");
+    update(&authority);
+    let server = serve(authority, 10);
+    let records = answers(&stream(server.addr, &ixfr(0)));
+    assert_eq!(records.len(), 12);
+    assert_eq!(records[0], soa(2));
+    assert_eq!(records[1].rtype(), RecordType::A);
+}
+
+#[test]
+fn answers_current_or_udp_ixfr_with_the_soa_alone() {
+    let authority = authority(10, "127.0.0.0/8");
+    update(&authority);
+    let resp = authority.handle(&request(ixfr(2), Protocol::Tcp)).unwrap();
+    assert_eq!(resp.answers, vec![soa(2)]);
+    let resp = authority.handle(&request(ixfr(1), Protocol::Udp)).unwrap();
+    assert_eq!(resp.answers, vec![soa(2)]);
+
+    let axfr = Message::query(name("example.com"), RecordType::AXFR);
+    let resp = authority.handle(&request(axfr, Protocol::Udp)).unwrap();
+    assert_eq!(resp.header.rcode, Rcode::FORMERR);
+    let resp = authority
+        .handle(&request(
+            Message::query(name("example.com"), RecordType::IXFR),
+            Protocol::Tcp,
+        ))
+        .unwrap();
+    assert_eq!(resp.header.rcode, Rcode::FORMERR);
+}
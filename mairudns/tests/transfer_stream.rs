@@ -0,0 +1,333 @@
+//! Zone transfers read as iterators: AXFR and IXFR from a primary served
+//! by the crate, the record limit, TSIG, records handed out before the
+//! transfer has finished, and connections that end too soon.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mairudns::client::{read_framed, write_framed};
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{transfer, Resolver, ResolverConfig, TransferOptions};
+use mairudns::rr::{RData, Record, RecordType, Soa};
+use mairudns::server::{Authority, Control, Server, SharedZone, TransferAcl};
+use mairudns::tsig::{self, Keyring};
+use mairudns::zone::{Diff, TransferError, Zone};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn soa(serial: u32) -> Record {
+    Record::new(
+        name("example.com"),
+        3600,
+        RData::Soa(Soa {
+            mname: name("ns.example.com"),
+            rname: name("hostmaster.example.com"),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+        }),
+    )
+}
+
+fn host(i: u32) -> Record {
+    Record::new(
+        name(&format!("h{}.example.com", i)),
+        60,
+        RData::A([10, 0, (i / 256) as u8, (i % 256) as u8].into()),
+    )
+}
+
+fn key() -> tsig::Key {
+    tsig::Key::new(
+        name("transfer"),
+        tsig::Algorithm::HmacSha256,
+        b"0123456789abcdef".to_vec(),
+    )
+}
+
+fn signed() -> TransferOptions {
+    TransferOptions {
+        timeout: TIMEOUT,
+        key: Some(key()),
+        ..TransferOptions::default()
+    }
+}
+
+struct Primary {
+    addr: SocketAddr,
+    zone: SharedZone,
+    control: Control,
+}
+
+impl Drop for Primary {
+    fn drop(&mut self) {
+        self.control.shutdown();
+    }
+}
+
+/// Serves example.com at serial 1 with `hosts` A records, transferring
+/// only to holders of [`key`].
+fn primary(hosts: u32) -> Primary {
+    let mut zone = Zone::new(name("example.com"));
+    zone.insert(soa(1)).unwrap();
+    for i in 0..hosts {
+        zone.insert(host(i)).unwrap();
+    }
+    let authority = Arc::new(Authority::new());
+    let shared = authority.insert(zone);
+    authority.set_transfer_acl(
+        name("example.com"),
+        TransferAcl {
+            addresses: Vec::new(),
+            keys: vec![name("transfer")],
+        },
+    );
+    let mut server = Server::new(authority);
+    let mut keys = Keyring::new();
+    keys.insert(key());
+    server.set_keyring(keys);
+    let addr = server.listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let control = server.control();
+    thread::spawn(move || server.run());
+    Primary {
+        addr,
+        zone: shared,
+        control,
+    }
+}
+
+/// An address nothing listens on.
+fn closed_port() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// A primary that answers the first transfer request it gets with
+/// `messages`, one TCP message each, waiting for a word on the returned
+/// channel before each message after the first. It closes the connection
+/// once `messages` runs out.
+fn scripted(messages: Vec<Vec<Record>>) -> (SocketAddr, mpsc::Sender<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (go, wait) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let query = Message::from_wire(&read_framed(&mut stream).unwrap()).unwrap();
+        for (i, answers) in messages.into_iter().enumerate() {
+            if i > 0 && wait.recv().is_err() {
+                return;
+            }
+            let mut resp = query.response();
+            resp.header.aa = true;
+            if i > 0 {
+                resp.questions.clear();
+            }
+            resp.answers = answers;
+            write_framed(&mut stream, &resp.to_wire().unwrap()).unwrap();
+        }
+    });
+    (addr, go)
+}
+
+#[test]
+fn axfr_yields_the_zone_between_its_soas() {
+    let primary = primary(20000);
+    let mut xfr = transfer(primary.addr, &name("example.com"), &signed()).unwrap();
+    assert_eq!(xfr.next().unwrap().unwrap(), soa(1));
+    assert_eq!(xfr.yielded(), 1);
+    let rest = xfr.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(rest.len(), 20001);
+    assert_eq!(rest.last(), Some(&soa(1)));
+    assert_eq!(
+        rest.iter().filter(|rr| rr.rtype() == RecordType::A).count(),
+        20000
+    );
+    assert_eq!(xfr.yielded(), 20002);
+    assert!(xfr.next().is_none());
+}
+
+#[test]
+fn ixfr_yields_the_changes_since_the_held_serial() {
+    let primary = primary(10);
+    primary
+        .zone
+        .write()
+        .unwrap()
+        .apply(Diff {
+            old_soa: soa(1),
+            deleted: vec![host(0)],
+            new_soa: soa(2),
+            added: vec![host(5000)],
+        })
+        .unwrap();
+    let since = |serial| TransferOptions {
+        since: Some(soa(serial)),
+        ..signed()
+    };
+
+    let records = transfer(primary.addr, &name("example.com"), &since(1))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(
+        records,
+        vec![soa(2), soa(1), host(0), soa(2), host(5000), soa(2)]
+    );
+
+    // Already up to date: the current SOA alone.
+    let records = transfer(primary.addr, &name("example.com"), &since(2))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records, vec![soa(2)]);
+}
+
+#[test]
+fn transfers_stop_at_the_record_limit() {
+    let primary = primary(5000);
+    let limited = TransferOptions {
+        max_records: Some(1000),
+        ..signed()
+    };
+    let mut xfr = transfer(primary.addr, &name("example.com"), &limited).unwrap();
+    let items: Vec<_> = xfr.by_ref().collect();
+    assert_eq!(items.len(), 1001);
+    assert!(items[..1000].iter().all(Result::is_ok));
+    assert!(matches!(
+        items[1000],
+        Err(TransferError::TooManyRecords(1000))
+    ));
+    assert_eq!(xfr.yielded(), 1000);
+    assert!(xfr.next().is_none());
+
+    // A limit the zone fits in changes nothing.
+    let roomy = TransferOptions {
+        max_records: Some(5002),
+        ..signed()
+    };
+    let xfr = transfer(primary.addr, &name("example.com"), &roomy).unwrap();
+    assert_eq!(xfr.map(Result::unwrap).count(), 5002);
+}
+
+#[test]
+fn refused_and_wrongly_signed_transfers_fail_once() {
+    let primary = primary(10);
+    let unsigned = TransferOptions {
+        timeout: TIMEOUT,
+        ..TransferOptions::default()
+    };
+    let items: Vec<_> = transfer(primary.addr, &name("example.com"), &unsigned)
+        .unwrap()
+        .collect();
+    assert_eq!(items.len(), 1);
+    assert!(matches!(
+        items[0],
+        Err(TransferError::Rcode(Rcode::REFUSED))
+    ));
+
+    let wrong = TransferOptions {
+        key: Some(tsig::Key::new(
+            name("transfer"),
+            tsig::Algorithm::HmacSha256,
+            b"not the right secret".to_vec(),
+        )),
+        ..signed()
+    };
+    let items: Vec<_> = transfer(primary.addr, &name("example.com"), &wrong)
+        .unwrap()
+        .collect();
+    assert_eq!(items.len(), 1);
+    assert!(items[0].is_err());
+
+    // A signed request answered without signatures is not trusted.
+    let (addr, _go) = scripted(vec![vec![soa(1), host(0), soa(1)]]);
+    let items: Vec<_> = transfer(addr, &name("example.com"), &signed())
+        .unwrap()
+        .collect();
+    assert_eq!(items.len(), 1);
+    assert!(matches!(items[0], Err(TransferError::Tsig(_))));
+}
+
+#[test]
+fn records_come_before_the_transfer_ends() {
+    let (addr, go) = scripted(vec![vec![soa(1), host(0), host(1)], vec![host(2), soa(1)]]);
+    let unsigned = TransferOptions {
+        timeout: TIMEOUT,
+        ..TransferOptions::default()
+    };
+    let mut xfr = transfer(addr, &name("example.com"), &unsigned).unwrap();
+    // The second message has not been sent yet.
+    assert_eq!(xfr.next().unwrap().unwrap(), soa(1));
+    assert_eq!(xfr.next().unwrap().unwrap(), host(0));
+    assert_eq!(xfr.next().unwrap().unwrap(), host(1));
+    go.send(()).unwrap();
+    assert_eq!(xfr.next().unwrap().unwrap(), host(2));
+    assert_eq!(xfr.next().unwrap().unwrap(), soa(1));
+    assert!(xfr.next().is_none());
+}
+
+#[test]
+fn a_connection_closed_mid_transfer_is_one_error() {
+    let (addr, go) = scripted(vec![vec![soa(1), host(0)]]);
+    let unsigned = TransferOptions {
+        timeout: TIMEOUT,
+        ..TransferOptions::default()
+    };
+    let mut xfr = transfer(addr, &name("example.com"), &unsigned).unwrap();
+    assert_eq!(xfr.next().unwrap().unwrap(), soa(1));
+    assert_eq!(xfr.next().unwrap().unwrap(), host(0));
+    drop(go);
+    assert!(matches!(xfr.next(), Some(Err(TransferError::Client(_)))));
+    assert!(xfr.next().is_none());
+    assert_eq!(xfr.yielded(), 2);
+
+    // Records that do not open with an SOA are not a transfer at all.
+    let (addr, _go) = scripted(vec![vec![host(0), soa(1)]]);
+    let mut xfr = transfer(addr, &name("example.com"), &unsigned).unwrap();
+    assert!(matches!(xfr.next(), Some(Err(TransferError::Malformed(_)))));
+    assert!(xfr.next().is_none());
+}
+
+#[test]
+fn resolvers_transfer_from_the_first_server_that_connects() {
+    let primary = primary(10);
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![closed_port(), primary.addr],
+        ..ResolverConfig::default()
+    });
+    let records = resolver
+        .transfer(&name("example.com"), &signed())
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(records.len(), 12);
+
+    let nowhere = Resolver::new(ResolverConfig {
+        servers: vec![closed_port()],
+        ..ResolverConfig::default()
+    });
+    assert!(matches!(
+        nowhere.transfer(&name("example.com"), &signed()),
+        Err(TransferError::Client(_))
+    ));
+    let none = Resolver::new(ResolverConfig {
+        servers: Vec::new(),
+        ..ResolverConfig::default()
+    });
+    assert!(matches!(
+        none.transfer(&name("example.com"), &signed()),
+        Err(TransferError::NoPrimaries)
+    ));
+}
@@ -0,0 +1,202 @@
+//! Trust anchor files: IANA's `root-anchors.xml` and the trust anchor
+//! statements of BIND configuration, read and written back, anchors from
+//! DS and DNSKEY records, and negative trust anchors.
+
+#![cfg(feature = "dnssec")]
+
+use mairudns::dnssec::{AnchorKey, DigestType, SigningKey, TrustAnchors};
+use mairudns::name::DomainName;
+use mairudns::rr::Record;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// IANA's root anchors as published in 2024: the retired key of 2010,
+/// the key of 2017, and the one of 2024.
+const ROOT_ANCHORS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrustAnchor id="38845BA1-D7C9-4DAE-AD5E-4A5B7C7F0B6F" source="http://data.iana.org/root-anchors/root-anchors.xml">
+<Zone>.</Zone>
+<KeyDigest id="Kjqmt7v" validFrom="2010-07-15T00:00:00+00:00" validUntil="2019-01-11T00:00:00+00:00">
+<KeyTag>19036</KeyTag>
+<Algorithm>8</Algorithm>
+<DigestType>2</DigestType>
+<Digest>49AAC11D7B6F6446702E54A1607371607A1A41855200FD2CE1CDDE32F24E8FB5</Digest>
+</KeyDigest>
+<KeyDigest id="Klajeyz" validFrom="2017-02-02T00:00:00+00:00">
+<KeyTag>20326</KeyTag>
+<Algorithm>8</Algorithm>
+<DigestType>2</DigestType>
+<Digest>E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D</Digest>
+</KeyDigest>
+<KeyDigest id="Kmyv6jo" validFrom="2024-07-18T00:00:00+00:00">
+<KeyTag>38696</KeyTag>
+<Algorithm>8</Algorithm>
+<DigestType>2</DigestType>
+<Digest>683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16</Digest>
+<PublicKey>AwEAAa96</PublicKey>
+<Flags>257</Flags>
+</KeyDigest>
+</TrustAnchor>
+"#;
+
+/// 2025-10-09, and 2014-05-13.
+const NOW: u64 = 1_760_000_000;
+const BEFORE_2017: u64 = 1_400_000_000;
+
+const BIND: &str = r#"
+options { directory "/var/named"; listen-on { any; }; };
+# The root, managed.
+trust-anchors {
+    /* from root-anchors.xml */
+    . initial-ds 20326 8 2 "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D";
+    "example.com." static-key 257 3 15 "l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=";
+};
+// The older statements.
+managed-keys { "org" initial-key 257 3 15 "l02Woi0iS8Aa25FQ
+   kUd9RMzZHJpBoRQwAQEX1SxZJA4="; };
+trusted-keys { net. 257 3 15 "l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4="; };
+"#;
+
+fn key_tags(anchors: &TrustAnchors, zone: &DomainName) -> Vec<u16> {
+    anchors
+        .get(zone)
+        .unwrap_or(&[])
+        .iter()
+        .map(|anchor| match &anchor.key {
+            AnchorKey::Ds(ds) => ds.key_tag,
+            AnchorKey::Dnskey(key) => key.key_tag(),
+        })
+        .collect()
+}
+
+#[test]
+fn iana_anchors_are_read_for_when_they_are_valid() {
+    let root = DomainName::root();
+    let anchors = TrustAnchors::from_iana_xml(ROOT_ANCHORS, NOW).unwrap();
+    assert_eq!(key_tags(&anchors, &root), [20326, 38696]);
+    assert!(anchors.get(&root).unwrap().iter().all(|a| a.managed));
+    // They are the ones built in.
+    let keys = |anchors: &TrustAnchors| -> Vec<AnchorKey> {
+        anchors
+            .get(&root)
+            .unwrap()
+            .iter()
+            .map(|a| a.key.clone())
+            .collect()
+    };
+    assert_eq!(keys(&anchors), keys(&TrustAnchors::root()));
+    // The key of 2017 entered its state when it became valid.
+    assert_eq!(anchors.get(&root).unwrap()[0].since, 1_485_993_600);
+
+    let earlier = TrustAnchors::from_iana_xml(ROOT_ANCHORS, BEFORE_2017).unwrap();
+    assert_eq!(key_tags(&earlier, &root), [19036]);
+
+    let xml = anchors.to_iana_xml(&root);
+    assert!(xml.contains("<KeyDigest id=\"20326\" validFrom=\"2017-02-02T00:00:00+00:00\">"));
+    assert_eq!(TrustAnchors::from_iana_xml(&xml, NOW).unwrap(), anchors);
+}
+
+#[test]
+fn keys_are_written_to_xml_with_their_digests() {
+    let zone = name("example.");
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let mut anchors = TrustAnchors::new();
+    anchors.add(zone.clone(), AnchorKey::Dnskey(key.dnskey().clone()));
+    let xml = anchors.to_iana_xml(&zone);
+    assert!(xml.contains("<Flags>257</Flags>"));
+    let read = TrustAnchors::from_iana_xml(&xml, NOW).unwrap();
+    let ds = key.dnskey().ds(&zone, DigestType::SHA256);
+    assert_eq!(read.get(&zone).unwrap()[0].key, AnchorKey::Ds(ds.unwrap()));
+}
+
+#[test]
+fn bind_statements_are_read_and_written() {
+    let anchors = TrustAnchors::from_bind(BIND).unwrap();
+    assert_eq!(anchors.iter().count(), 4);
+    assert_eq!(key_tags(&anchors, &DomainName::root()), [20326]);
+    // `initial-` anchors follow rollovers; `static-` and `trusted-keys`
+    // ones stay put.
+    let managed = |zone: &str| anchors.get(&name(zone)).unwrap()[0].managed;
+    assert!(managed("."));
+    assert!(managed("org."));
+    assert!(!managed("example.com."));
+    assert!(!managed("net."));
+    // A key split over lines is one key.
+    assert_eq!(
+        anchors.get(&name("org.")).unwrap()[0].key,
+        anchors.get(&name("net.")).unwrap()[0].key
+    );
+
+    let text = anchors.to_bind();
+    assert!(text.starts_with("trust-anchors {\n"));
+    assert!(text.contains("\t\"org.\" initial-key 257 3 15 \"l02Woi0iS8Aa25FQ"));
+    assert!(text.contains("\t\"net.\" static-key 257 3 15 "));
+    assert_eq!(TrustAnchors::from_bind(&text).unwrap(), anchors);
+    assert_eq!(TrustAnchors::from_bind("").unwrap(), TrustAnchors::new());
+}
+
+#[test]
+fn bind_errors_give_the_line() {
+    for (text, error) in [
+        (
+            "trust-anchors { . initial-ds 1 2 3; };",
+            "line 1: trust anchors have four fields",
+        ),
+        (
+            "trust-anchors { . foo 1 2 3 \"00\"; };",
+            "line 1: unknown anchor type \"foo\"",
+        ),
+        (
+            "trust-anchors {\n . initial-key 1 2 3 \"%%\"; };",
+            "line 2: bad base64 key",
+        ),
+        ("trust-anchors { ", "line 1: unbalanced braces"),
+        ("trust-anchors \"x", "line 1: unterminated string"),
+    ] {
+        let err = TrustAnchors::from_bind(text).unwrap_err();
+        assert_eq!(err.to_string(), error, "{}", text);
+    }
+    let err = TrustAnchors::from_iana_xml("<Zone>.</Zone>", NOW).unwrap_err();
+    assert_eq!(err.to_string(), "line 1: no TrustAnchor element");
+}
+
+#[test]
+fn anchors_from_records_are_static() {
+    let zone = name("example.");
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let ds = key.dnskey().ds(&zone, DigestType::SHA256).unwrap();
+    let records = [
+        Record::new(zone.clone(), 3600, key.dnskey().to_rdata()),
+        Record::new(name("other."), 3600, ds.to_rdata()),
+    ];
+    let anchors = TrustAnchors::from_records(&records);
+    let example = anchors.get(&zone).unwrap();
+    assert_eq!(example[0].key, AnchorKey::Dnskey(key.dnskey().clone()));
+    assert!(example[0].is_trusted() && !example[0].managed);
+    assert_eq!(
+        anchors.get(&name("other.")).unwrap()[0].key,
+        AnchorKey::Ds(ds)
+    );
+}
+
+#[test]
+fn negative_anchors_cover_zones_until_they_lapse() {
+    let mut anchors = TrustAnchors::root();
+    anchors.add_negative(name("broken.example."), Some(NOW + 60));
+    anchors.add_negative(name("forever.example."), None);
+    let negative = |anchors: &TrustAnchors, zone: &str, now| anchors.is_negative(&name(zone), now);
+    assert!(negative(&anchors, "broken.example.", NOW));
+    assert!(negative(&anchors, "www.broken.example.", NOW + 59));
+    assert!(!negative(&anchors, "www.broken.example.", NOW + 60));
+    assert!(!negative(&anchors, "example.", NOW));
+    assert!(negative(&anchors, "forever.example.", u64::MAX));
+
+    assert_eq!(anchors.expire_negative(NOW), []);
+    assert_eq!(anchors.expire_negative(NOW + 60), [name("broken.example.")]);
+    assert_eq!(anchors.negative().count(), 1);
+    assert!(anchors.remove_negative(&name("forever.example.")));
+    assert!(!anchors.remove_negative(&name("forever.example.")));
+    // They are separate from the anchors themselves.
+    assert_eq!(anchors, TrustAnchors::root());
+}
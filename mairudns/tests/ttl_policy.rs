@@ -0,0 +1,321 @@
+//! TTL rules on served answers: the most specific rule winning, RRSIGs
+//! left alone and signed RRsets kept within their signatures, negative
+//! answers following their own bounds, and the configuration that builds
+//! the rules.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::clock::MockClock;
+use mairudns::config::{Config, TtlRuleConfig};
+use mairudns::message::{Message, Question, Rcode};
+use mairudns::name::DomainName;
+use mairudns::policy::{TtlPolicy, TtlRule};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Authority, Handler, HandlerExt, Request};
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 300
+@ IN SOA ns hostmaster 1 7200 900 1209600 30
+@ IN NS ns
+ns IN A 192.0.2.53
+short 5 IN A 192.0.2.1
+m.sub IN A 192.0.2.2
+m.sub IN TXT \"hello\"
+";
+
+const NOW: u64 = 1_700_000_000;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ask(handler: &impl Handler, qname: &str, qtype: RecordType) -> Message {
+    handler
+        .handle(&Request {
+            message: Message::query(name(qname), qtype),
+            src: "192.0.2.1:5300".parse().unwrap(),
+            protocol: Protocol::Udp,
+            key: None,
+        })
+        .unwrap()
+}
+
+fn ttls(records: &[Record]) -> Vec<u32> {
+    records.iter().map(|rr| rr.ttl).collect()
+}
+
+fn authority() -> Arc<Authority> {
+    let authority = Arc::new(Authority::new());
+    authority.insert(Zone::from_master(name("example.com."), ZONE).unwrap());
+    authority
+}
+
+/// An RRSIG over `covered` at `owner`, with just the fields the policy
+/// reads filled in.
+fn rrsig(owner: &str, ttl: u32, covered: RecordType, original_ttl: u32, expiration: u32) -> Record {
+    let mut data = Vec::new();
+    data.extend_from_slice(&covered.0.to_be_bytes());
+    data.extend_from_slice(&[15, 2]);
+    data.extend_from_slice(&original_ttl.to_be_bytes());
+    data.extend_from_slice(&expiration.to_be_bytes());
+    data.extend_from_slice(&(NOW as u32 - 86400).to_be_bytes());
+    data.extend_from_slice(&[0x12, 0x34, 0]);
+    data.extend_from_slice(&[0; 64]);
+    Record::new(
+        name(owner),
+        ttl,
+        RData::Unknown {
+            rtype: RecordType::RRSIG,
+            data,
+        },
+    )
+}
+
+fn a(owner: &str, ttl: u32) -> Record {
+    Record::new(name(owner), ttl, RData::A([192, 0, 2, 1].into()))
+}
+
+#[test]
+fn the_most_specific_rule_rewrites_the_answer() {
+    let policy = TtlPolicy::new()
+        .rule(TtlRule::new(DomainName::root()).floor(60))
+        .rule(
+            TtlRule::new(name("sub.example.com."))
+                .multiplier(0.5)
+                .ceiling(100),
+        )
+        .rule(
+            TtlRule::new(name("sub.example.com."))
+                .types(vec![RecordType::TXT])
+                .ceiling(10),
+        );
+    let handler = authority().with(policy);
+
+    let resp = ask(&handler, "short.example.com.", RecordType::A);
+    assert_eq!(ttls(&resp.answers), vec![60]);
+    // The floor raises nothing already above it, in any section.
+    let resp = ask(&handler, "example.com.", RecordType::NS);
+    assert_eq!(ttls(&resp.answers), vec![300]);
+    assert_eq!(ttls(&resp.additional), vec![300]);
+    // Halved to 150, then lowered to the ceiling.
+    let resp = ask(&handler, "m.sub.example.com.", RecordType::A);
+    assert_eq!(ttls(&resp.answers), vec![100]);
+    // The typed rule wins over the one for all types.
+    let resp = ask(&handler, "m.sub.example.com.", RecordType::TXT);
+    assert_eq!(ttls(&resp.answers), vec![10]);
+
+    assert_eq!(TtlRule::new(DomainName::root()).rewrite(42), 42);
+    assert_eq!(
+        TtlRule::new(DomainName::root())
+            .floor(500)
+            .ceiling(100)
+            .rewrite(42),
+        100
+    );
+}
+
+#[test]
+fn rrsigs_are_left_alone_and_bound_their_rrsets() {
+    let clock = MockClock::new(NOW);
+    let policy = TtlPolicy::new()
+        .rule(TtlRule::new(DomainName::root()).floor(3600))
+        .clock(Arc::new(clock.clone()));
+    let question = Question::new(name("www.example.com."), RecordType::A);
+    let signed = || {
+        let mut resp = Message::query(name("www.example.com."), RecordType::A).response();
+        resp.answers = vec![
+            a("www.example.com.", 60),
+            rrsig(
+                "www.example.com.",
+                60,
+                RecordType::A,
+                1800,
+                NOW as u32 + 86400,
+            ),
+            a("other.example.com.", 60),
+        ];
+        resp
+    };
+
+    // Raised to the floor, but no further than the original TTL the
+    // signature carries; the RRSIG itself and the unsigned RRset are
+    // rewritten as they would be without signatures.
+    let mut resp = signed();
+    policy.apply(&question, &mut resp);
+    assert_eq!(ttls(&resp.answers), vec![1800, 60, 3600]);
+
+    // Nor past the signature's expiration.
+    clock.set_unix_time(NOW + 86400 - 100);
+    let mut resp = signed();
+    policy.apply(&question, &mut resp);
+    assert_eq!(ttls(&resp.answers), vec![100, 60, 3600]);
+
+    clock.advance(Duration::from_secs(1000));
+    let mut resp = signed();
+    policy.apply(&question, &mut resp);
+    assert_eq!(ttls(&resp.answers), vec![0, 60, 3600]);
+
+    // Of several signatures, the most restrictive.
+    clock.set_unix_time(NOW);
+    let mut resp = signed();
+    resp.answers.push(rrsig(
+        "www.example.com.",
+        60,
+        RecordType::A,
+        7200,
+        NOW as u32 + 600,
+    ));
+    policy.apply(&question, &mut resp);
+    assert_eq!(ttls(&resp.answers), vec![600, 60, 3600, 60]);
+
+    // A signature over another type or owner bounds nothing here.
+    let mut resp = signed();
+    resp.answers[1] = rrsig(
+        "www.example.com.",
+        60,
+        RecordType::AAAA,
+        10,
+        NOW as u32 + 10,
+    );
+    resp.answers.push(rrsig(
+        "elsewhere.example.com.",
+        60,
+        RecordType::A,
+        10,
+        NOW as u32 + 10,
+    ));
+    policy.apply(&question, &mut resp);
+    assert_eq!(ttls(&resp.answers), vec![3600, 60, 3600, 60]);
+}
+
+#[test]
+fn negative_answers_follow_their_own_bounds() {
+    let policy = TtlPolicy::new().rule(
+        TtlRule::new(DomainName::root())
+            .floor(600)
+            .negative_floor(120)
+            .negative_ceiling(900),
+    );
+    let handler = authority().with(policy);
+    let soa_minimum = |resp: &Message| match &resp.authority[0].rdata {
+        RData::Soa(soa) => soa.minimum,
+        other => panic!("not an SOA: {:?}", other),
+    };
+
+    // The SOA's minimum of 30 is below the negative floor, and both the
+    // SOA's TTL and its minimum move to it; the positive floor plays no
+    // part.
+    for (qname, qtype, rcode) in [
+        ("nowhere.example.com.", RecordType::A, Rcode::NXDOMAIN),
+        ("short.example.com.", RecordType::MX, Rcode::NOERROR),
+    ] {
+        let resp = ask(&handler, qname, qtype);
+        assert_eq!(resp.header.rcode, rcode);
+        assert!(resp.answers.is_empty());
+        assert_eq!(ttls(&resp.authority), vec![120]);
+        assert_eq!(soa_minimum(&resp), 120);
+    }
+
+    // Without negative bounds the negative TTL is the lesser of the
+    // SOA's TTL and its minimum, as resolvers would take it.
+    let handler =
+        authority().with(TtlPolicy::new().rule(TtlRule::new(DomainName::root()).floor(600)));
+    let resp = ask(&handler, "nowhere.example.com.", RecordType::A);
+    assert_eq!(ttls(&resp.authority), vec![30]);
+    assert_eq!(soa_minimum(&resp), 30);
+
+    // A signed SOA keeps its minimum, and its signature bounds the TTL.
+    let clock = MockClock::new(NOW);
+    let policy = TtlPolicy::new()
+        .rule(TtlRule::new(DomainName::root()).negative_floor(7200))
+        .clock(Arc::new(clock));
+    let mut resp = ask(&authority(), "nowhere.example.com.", RecordType::A);
+    resp.authority.push(rrsig(
+        "example.com.",
+        300,
+        RecordType::SOA,
+        300,
+        NOW as u32 + 86400,
+    ));
+    policy.apply(
+        &Question::new(name("nowhere.example.com."), RecordType::A),
+        &mut resp,
+    );
+    assert_eq!(ttls(&resp.authority), vec![300, 300]);
+    assert_eq!(soa_minimum(&resp), 30);
+}
+
+#[test]
+fn ttl_rules_come_from_the_configuration() {
+    let config = Config::new()
+        .ttl_rule(TtlRuleConfig::new(".").floor(30))
+        .ttl_rule(
+            TtlRuleConfig::new("m.example.")
+                .multiplier(2.0)
+                .negative_floor(60)
+                .negative_ceiling(300),
+        );
+    assert_eq!(
+        config.to_ttl_policy().unwrap(),
+        TtlPolicy::new()
+            .rule(TtlRule::new(DomainName::root()).floor(30))
+            .rule(
+                TtlRule::new(name("m.example."))
+                    .multiplier(2.0)
+                    .negative_floor(60)
+                    .negative_ceiling(300)
+            )
+    );
+
+    // Problems are reported against the field that has them.
+    let field = |rule: TtlRuleConfig| {
+        let problems = Config::new().ttl_rule(rule).validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        problems[0].field.clone()
+    };
+    assert_eq!(field(TtlRuleConfig::new("bad..name")), "ttl-rules[0].name");
+    assert_eq!(
+        field(TtlRuleConfig::new(".").floor(60).ceiling(10)),
+        "ttl-rules[0].floor"
+    );
+    assert_eq!(
+        field(
+            TtlRuleConfig::new(".")
+                .negative_floor(60)
+                .negative_ceiling(10)
+        ),
+        "ttl-rules[0].negative-floor"
+    );
+    assert_eq!(
+        field(TtlRuleConfig::new(".").multiplier(-1.0)),
+        "ttl-rules[0].multiplier"
+    );
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn ttl_rules_read_from_toml() {
+    let config = Config::from_toml(
+        "[[ttl-rules]]\n\
+         floor = 30\n\
+         [[ttl-rules]]\n\
+         name = \"m.example.\"\n\
+         types = [\"A\"]\n\
+         negative-ceiling = 60\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.ttl_rules,
+        vec![
+            TtlRuleConfig::new(".").floor(30),
+            TtlRuleConfig {
+                types: vec!["A".into()],
+                ..TtlRuleConfig::new("m.example.").negative_ceiling(60)
+            },
+        ]
+    );
+    assert!(Config::from_toml("[[ttl-rules]]\nfloor = \"soon\"\n").is_err());
+}
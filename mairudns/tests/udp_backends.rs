@@ -0,0 +1,134 @@
+//! The UDP backends: each answers many clients over IPv4 and IPv6, on
+//! plain and SO_REUSEPORT sockets, sends responses as large as a
+//! datagram allows, and stops with the server. io_uring falls back to
+//! threads where the kernel or the build lacks it, so the same checks
+//! hold whether it is available or not.
+
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::{exchange, Protocol};
+use mairudns::message::{Edns, Message};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Request, ResponsePolicy, Server, UdpBackend};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// Echoes the question, with a TXT answer of as many bytes as the first
+/// label asks for.
+fn handler(request: &Request) -> Option<Message> {
+    let mut resp = request.message.response();
+    let qname = request.message.question()?.name.clone();
+    let size: usize = std::str::from_utf8(&qname.labels()[0])
+        .ok()
+        .and_then(|l| l.strip_prefix("txt"))
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    let strings = (0..size)
+        .step_by(255)
+        .map(|start| vec![b'x'; (size - start).min(255)])
+        .collect();
+    if size > 0 {
+        resp.answers
+            .push(Record::new(qname, 60, RData::Txt(strings)));
+    }
+    Some(resp)
+}
+
+fn ask(addr: SocketAddr, qname: &str) -> Message {
+    let mut query = Message::query(name(qname), RecordType::TXT);
+    query.edns = Some(Edns {
+        udp_size: 4096,
+        ..Edns::default()
+    });
+    let resp = exchange(addr, Protocol::Udp, &query, TIMEOUT).unwrap();
+    assert_eq!(resp.header.id, query.header.id);
+    assert_eq!(resp.questions, query.questions);
+    resp
+}
+
+/// Runs a server with `backend`, sending up to 4096 bytes, on a plain
+/// socket and two workers sharing another, both on `lo`, and has 16
+/// clients ask each in turn.
+fn serve_many(backend: UdpBackend, lo: &str) {
+    let mut server = Server::new(handler as fn(&Request) -> Option<Message>);
+    server.set_udp_backend(backend);
+    server.set_response_policy(ResponsePolicy {
+        max_udp_size: 4096,
+        ..ResponsePolicy::default()
+    });
+    let lo: SocketAddr = lo.parse().unwrap();
+    let workers = server.listen_workers(lo, 2).unwrap();
+    server.listen(lo).unwrap();
+    let plain = server
+        .local_addrs()
+        .into_iter()
+        .find(|(protocol, addr)| *protocol == Protocol::Udp && *addr != workers)
+        .unwrap()
+        .1;
+    let control = server.control();
+    let running = thread::spawn(move || server.run());
+
+    let clients: Vec<_> = (0..16)
+        .map(|i| {
+            thread::spawn(move || {
+                for j in 0..100 {
+                    let addr = if j % 2 == 0 { workers } else { plain };
+                    ask(addr, &format!("q{}-{}.example", i, j));
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    // Responses up to the size the client offered come whole.
+    let resp = ask(plain, "txt3000.example");
+    assert!(!resp.header.tc);
+    match &resp.answers[0].rdata {
+        RData::Txt(strings) => assert_eq!(strings.iter().map(Vec::len).sum::<usize>(), 3000),
+        other => panic!("{:?}", other),
+    }
+    assert!(ask(workers, "txt8000.example").header.tc);
+
+    let stopping = Instant::now();
+    control.shutdown();
+    running.join().unwrap().unwrap();
+    assert!(stopping.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn threads_serve_ipv4_and_ipv6() {
+    assert!(UdpBackend::Threads.is_available());
+    serve_many(UdpBackend::Threads, "127.0.0.1:0");
+    serve_many(UdpBackend::Threads, "[::1]:0");
+}
+
+#[test]
+fn io_uring_serves_ipv4_and_ipv6_or_falls_back() {
+    if cfg!(not(all(feature = "uring", target_os = "linux"))) {
+        assert!(!UdpBackend::IoUring.is_available());
+    }
+    serve_many(UdpBackend::IoUring, "127.0.0.1:0");
+    serve_many(UdpBackend::IoUring, "[::1]:0");
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn the_backend_is_chosen_in_the_configuration() {
+    use mairudns::config::{self, Config};
+
+    let config = Config::from_toml("[server]\nudp-backend = \"io-uring\"\n").unwrap();
+    assert_eq!(config.server.udp_backend, config::UdpBackend::IoUring);
+    assert_eq!(config.server.to_udp_backend(), UdpBackend::IoUring);
+    let config = Config::from_toml("").unwrap();
+    assert_eq!(config.server.to_udp_backend(), UdpBackend::Threads);
+    assert!(Config::from_toml("[server]\nudp-backend = \"epoll\"\n").is_err());
+}
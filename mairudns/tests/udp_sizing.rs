@@ -0,0 +1,183 @@
+//! Adaptive UDP payload sizes: servers asked at the DNS Flag Day size,
+//! lowered to the smallest when large responses go missing and raised
+//! again later, moved to TCP when even that is not enough, and sizes the
+//! caller pins left alone.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use mairudns::client::Protocol;
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::resolver::{
+    QueryOptions, Resolver, ResolverConfig, UdpSizing, FLAG_DAY_UDP_SIZE, MIN_UDP_SIZE,
+};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn server() -> SocketAddr {
+    "192.0.2.1:53".parse().unwrap()
+}
+
+fn truncated() -> Message {
+    let mut resp = Message::query(name("www.example."), RecordType::A).response();
+    resp.header.tc = true;
+    resp
+}
+
+fn resolver(server: &MockServer, attempts: usize) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(200),
+        attempts,
+        ..ResolverConfig::default()
+    })
+}
+
+/// A server answering `www.example.` A.
+fn upstream() -> MockServer {
+    MockServer::builder()
+        .answer(
+            name("www.example."),
+            RecordType::A,
+            vec![Record::new(
+                name("www.example."),
+                300,
+                RData::A([192, 0, 2, 1].into()),
+            )],
+        )
+        .start()
+        .unwrap()
+}
+
+/// The payload sizes advertised in the UDP queries `server` received.
+fn udp_sizes(server: &MockServer) -> Vec<u16> {
+    server
+        .received()
+        .iter()
+        .filter(|r| r.protocol == Protocol::Udp)
+        .map(|r| r.message.edns.as_ref().unwrap().udp_size)
+        .collect()
+}
+
+#[test]
+fn lost_responses_lower_the_size_for_a_while() {
+    let sizing = UdpSizing::new();
+    let now = Instant::now();
+    assert_eq!(sizing.udp_size(server(), now), FLAG_DAY_UDP_SIZE);
+    assert_eq!(sizing.status(server()).udp_size, FLAG_DAY_UDP_SIZE);
+
+    sizing.timed_out(server(), FLAG_DAY_UDP_SIZE, now);
+    assert_eq!(sizing.udp_size(server(), now), MIN_UDP_SIZE);
+    let status = sizing.status(server());
+    assert_eq!(status.timeouts, 1);
+    assert_eq!(status.lowered, Some(now));
+    // Timeouts at the smallest size say nothing of fragmentation.
+    sizing.timed_out(server(), MIN_UDP_SIZE, now);
+    assert_eq!(sizing.status(server()).timeouts, 1);
+
+    // Answers are noted, and a response to a larger size clears the
+    // timeouts.
+    let resp = Message::query(name("www.example."), RecordType::A).response();
+    sizing.answered(server(), FLAG_DAY_UDP_SIZE, &resp, now);
+    let status = sizing.status(server());
+    assert_eq!(status.timeouts, 0);
+    assert_eq!(status.largest_response, resp.to_wire().unwrap().len());
+
+    // Ten minutes on, the larger size is tried again.
+    let later = now + Duration::from_secs(599);
+    assert_eq!(sizing.udp_size(server(), later), MIN_UDP_SIZE);
+    let later = now + Duration::from_secs(600);
+    assert_eq!(sizing.udp_size(server(), later), FLAG_DAY_UDP_SIZE);
+    assert_eq!(sizing.status(server()).lowered, None);
+
+    // Other servers are not affected.
+    let other = "192.0.2.2:53".parse().unwrap();
+    assert_eq!(sizing.udp_size(other, now), FLAG_DAY_UDP_SIZE);
+}
+
+#[test]
+fn truncation_at_the_smallest_size_moves_to_tcp() {
+    let sizing = UdpSizing::new();
+    let now = Instant::now();
+    // Truncation at a larger size is the ordinary fallback to TCP.
+    for _ in 0..5 {
+        sizing.answered(server(), FLAG_DAY_UDP_SIZE, &truncated(), now);
+    }
+    assert!(!sizing.prefers_tcp(server(), now));
+
+    // Three in a row at the smallest, and TCP comes first for a while.
+    sizing.timed_out(server(), FLAG_DAY_UDP_SIZE, now);
+    sizing.answered(server(), MIN_UDP_SIZE, &truncated(), now);
+    sizing.answered(server(), MIN_UDP_SIZE, &truncated(), now);
+    assert!(!sizing.prefers_tcp(server(), now));
+    sizing.answered(server(), MIN_UDP_SIZE, &truncated(), now);
+    assert!(sizing.prefers_tcp(server(), now));
+    assert!(sizing.prefers_tcp(server(), now + Duration::from_secs(299)));
+    assert!(!sizing.prefers_tcp(server(), now + Duration::from_secs(300)));
+
+    // Unless TCP fails.
+    sizing.answered(server(), MIN_UDP_SIZE, &truncated(), now);
+    sizing.answered(server(), MIN_UDP_SIZE, &truncated(), now);
+    sizing.answered(server(), MIN_UDP_SIZE, &truncated(), now);
+    assert!(sizing.prefers_tcp(server(), now));
+    sizing.tcp_failed(server());
+    assert!(!sizing.prefers_tcp(server(), now));
+}
+
+#[test]
+fn resolvers_adapt_to_what_the_path_delivers() {
+    // The first query at the larger size goes missing; the retry is at
+    // the smallest and answered.
+    let server = upstream();
+    server.push(Action::Drop);
+    let resolver = resolver(&server, 2);
+    let resp = resolver
+        .query(&name("www.example."), RecordType::A)
+        .unwrap();
+    assert_eq!(resp.answers.len(), 1);
+    assert_eq!(udp_sizes(&server), vec![FLAG_DAY_UDP_SIZE, MIN_UDP_SIZE]);
+    let status = resolver.udp_sizing().status(server.addr());
+    assert_eq!(status.udp_size, MIN_UDP_SIZE);
+    assert!(status.largest_response > 0);
+
+    // Truncated three times at the smallest size, each time answered over
+    // TCP; after that TCP comes first.
+    for _ in 0..3 {
+        server.push(Action::Truncate);
+        resolver
+            .query(&name("www.example."), RecordType::A)
+            .unwrap();
+    }
+    assert_eq!(server.count(Protocol::Tcp), 3);
+    let udp = server.count(Protocol::Udp);
+    resolver
+        .query(&name("www.example."), RecordType::A)
+        .unwrap();
+    assert_eq!(server.count(Protocol::Udp), udp);
+    assert_eq!(server.count(Protocol::Tcp), 4);
+}
+
+#[test]
+fn pinned_sizes_are_sent_as_they_are() {
+    let server = upstream();
+    server.push(Action::Drop);
+    let resolver = resolver(&server, 2);
+    let options = QueryOptions {
+        udp_size: Some(4096),
+        ..QueryOptions::default()
+    };
+    resolver
+        .query_with(&name("www.example."), RecordType::A, &options)
+        .unwrap();
+    assert_eq!(udp_sizes(&server), vec![4096, 4096]);
+    // Nothing is learned from them.
+    assert_eq!(
+        resolver.udp_sizing().status(server.addr()).udp_size,
+        FLAG_DAY_UDP_SIZE
+    );
+}
@@ -0,0 +1,334 @@
+//! RFC 2136 dynamic updates: TSIG-signed updates over the wire, update
+//! policies, prerequisites, and updates applied all or not at all.
+
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use mairudns::client::{read_framed, write_framed, Protocol};
+use mairudns::message::{Message, Opcode, Rcode};
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordClass, RecordType, Soa};
+use mairudns::server::{Authority, Handler, Request, Server};
+use mairudns::tsig::{self, Keyring};
+use mairudns::zone::{NameMatch, UpdatePolicy, Zone};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn soa(serial: u32) -> Record {
+    Record::new(
+        name("example.com"),
+        3600,
+        RData::Soa(Soa {
+            mname: name("ns.example.com"),
+            rname: name("hostmaster.example.com"),
+            serial,
+            refresh: 3600,
+            retry: 600,
+            expire: 86400,
+            minimum: 60,
+        }),
+    )
+}
+
+fn txt(owner: &str, text: &str) -> Record {
+    Record::new(name(owner), 60, RData::Txt(vec![text.as_bytes().to_vec()]))
+}
+
+fn a(owner: &str, addr: [u8; 4]) -> Record {
+    Record::new(name(owner), 60, RData::A(addr.into()))
+}
+
+/// A prerequisite or deletion of class `class` for `rtype` at `owner`,
+/// with no data.
+fn empty(owner: &str, rtype: RecordType, class: RecordClass) -> Record {
+    let mut rr = Record::new(
+        name(owner),
+        0,
+        RData::Unknown {
+            rtype,
+            data: Vec::new(),
+        },
+    );
+    rr.class = class;
+    rr
+}
+
+/// `rr` as a value-dependent prerequisite.
+fn exactly(mut rr: Record) -> Record {
+    rr.ttl = 0;
+    rr
+}
+
+/// The deletion of exactly `rr`.
+fn deletion(mut rr: Record) -> Record {
+    rr.class = RecordClass::NONE;
+    rr.ttl = 0;
+    rr
+}
+
+const CHALLENGE: &str = "_acme-challenge.example.com";
+
+fn authority() -> Arc<Authority> {
+    let mut zone = Zone::new(name("example.com"));
+    zone.insert(soa(1)).unwrap();
+    zone.insert(a("www.example.com", [192, 0, 2, 1])).unwrap();
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone);
+    authority.set_update_policy(
+        name("example.com"),
+        UpdatePolicy::new()
+            .grant(
+                name("acme"),
+                NameMatch::Subdomain(name(CHALLENGE)),
+                vec![RecordType::TXT],
+            )
+            .grant(name("admin"), NameMatch::Zone, Vec::new()),
+    );
+    authority
+}
+
+fn update_message(prerequisites: Vec<Record>, updates: Vec<Record>) -> Message {
+    let mut msg = Message::query(name("example.com"), RecordType::SOA);
+    msg.header.opcode = Opcode::UPDATE;
+    msg.header.rd = false;
+    msg.answers = prerequisites;
+    msg.authority = updates;
+    msg
+}
+
+/// Applies an update as if signed with `key`.
+fn update(
+    authority: &Authority,
+    key: &str,
+    prerequisites: Vec<Record>,
+    updates: Vec<Record>,
+) -> Rcode {
+    let request = Request {
+        message: update_message(prerequisites, updates),
+        src: "192.0.2.1:53000".parse().unwrap(),
+        protocol: Protocol::Tcp,
+        key: Some(name(key)),
+    };
+    authority.handle(&request).unwrap().header.rcode
+}
+
+fn serial(authority: &Authority) -> Option<u32> {
+    authority
+        .zone(&name("example.com"))
+        .unwrap()
+        .read()
+        .unwrap()
+        .serial()
+}
+
+fn acme_key() -> tsig::Key {
+    tsig::Key::new(
+        name("acme"),
+        tsig::Algorithm::HmacSha256,
+        b"0123456789abcdef".to_vec(),
+    )
+}
+
+fn send(addr: SocketAddr, key: Option<&tsig::Key>, msg: &Message) -> Rcode {
+    let wire = match key {
+        Some(key) => tsig::sign(msg, key, None, tsig::now()).unwrap().0,
+        None => msg.to_wire().unwrap(),
+    };
+    let mut conn = TcpStream::connect(addr).unwrap();
+    write_framed(&mut conn, &wire).unwrap();
+    Message::from_wire(&read_framed(&mut conn).unwrap())
+        .unwrap()
+        .header
+        .rcode
+}
+
+#[test]
+fn accepts_only_tsig_signed_updates_over_the_wire() {
+    let authority = authority();
+    let mut server = Server::new(authority.clone());
+    let mut keys = Keyring::new();
+    keys.insert(acme_key());
+    server.set_keyring(keys);
+    let addr = server.listen_tcp("127.0.0.1:0".parse().unwrap()).unwrap();
+    let control = server.control();
+    thread::spawn(move || server.run());
+
+    let msg = update_message(Vec::new(), vec![txt(CHALLENGE, "token")]);
+    assert_eq!(send(addr, None, &msg), Rcode::REFUSED);
+    assert_eq!(serial(&authority), Some(1));
+    assert_eq!(send(addr, Some(&acme_key()), &msg), Rcode::NOERROR);
+    assert_eq!(serial(&authority), Some(2));
+    let zone = authority.zone(&name("example.com")).unwrap();
+    assert_eq!(zone.read().unwrap().journal().len(), 1);
+    control.shutdown();
+}
+
+#[test]
+fn enforces_the_update_policy() {
+    let authority = authority();
+    // The ACME key may touch TXT records under its challenge name only.
+    assert_eq!(
+        update(
+            &authority,
+            "acme",
+            Vec::new(),
+            vec![a(CHALLENGE, [192, 0, 2, 9])]
+        ),
+        Rcode::REFUSED
+    );
+    assert_eq!(
+        update(
+            &authority,
+            "acme",
+            Vec::new(),
+            vec![txt("www.example.com", "x")]
+        ),
+        Rcode::REFUSED
+    );
+    assert_eq!(
+        update(
+            &authority,
+            "stranger",
+            Vec::new(),
+            vec![txt(CHALLENGE, "x")]
+        ),
+        Rcode::REFUSED
+    );
+    // Even a rule for any type leaves the SOA alone.
+    assert!(!UpdatePolicy::new()
+        .grant(name("admin"), NameMatch::Zone, Vec::new())
+        .allows(&name("admin"), &[soa(5)]));
+    assert!(UpdatePolicy::new()
+        .grant(name("host.example.com"), NameMatch::KeyName, Vec::new())
+        .allows(
+            &name("host.example.com"),
+            &[a("host.example.com", [192, 0, 2, 7])]
+        ));
+    assert_eq!(serial(&authority), Some(1));
+}
+
+#[test]
+fn adds_and_deletes_records() {
+    let authority = authority();
+    let token = txt(CHALLENGE, "token");
+    assert_eq!(
+        update(&authority, "acme", Vec::new(), vec![token.clone()]),
+        Rcode::NOERROR
+    );
+    assert_eq!(
+        update(&authority, "acme", Vec::new(), vec![deletion(token)]),
+        Rcode::NOERROR
+    );
+    assert_eq!(serial(&authority), Some(3));
+    let zone = authority.zone(&name("example.com")).unwrap();
+    assert!(zone
+        .read()
+        .unwrap()
+        .rrset(&name(CHALLENGE), RecordType::TXT)
+        .is_none());
+
+    // Deleting every RRset at a name.
+    assert_eq!(
+        update(
+            &authority,
+            "admin",
+            Vec::new(),
+            vec![empty("www.example.com", RecordType::ANY, RecordClass::ANY)]
+        ),
+        Rcode::NOERROR
+    );
+    assert!(!zone.read().unwrap().name_exists(&name("www.example.com")));
+}
+
+#[test]
+fn checks_prerequisites_before_changing_anything() {
+    let authority = authority();
+    let in_use = empty("www.example.com", RecordType::ANY, RecordClass::ANY);
+    let not_in_use = empty("www.example.com", RecordType::ANY, RecordClass::NONE);
+    let rrset_exists = empty("www.example.com", RecordType::A, RecordClass::ANY);
+    let rrset_absent = empty("www.example.com", RecordType::A, RecordClass::NONE);
+    let change = || vec![a("new.example.com", [192, 0, 2, 2])];
+
+    assert_eq!(
+        update(&authority, "admin", vec![not_in_use], change()),
+        Rcode::YXDOMAIN
+    );
+    assert_eq!(
+        update(&authority, "admin", vec![rrset_absent], change()),
+        Rcode::YXRRSET
+    );
+    let mut missing = empty("gone.example.com", RecordType::ANY, RecordClass::ANY);
+    assert_eq!(
+        update(&authority, "admin", vec![missing.clone()], change()),
+        Rcode::NXDOMAIN
+    );
+    missing.rdata = RData::Unknown {
+        rtype: RecordType::A,
+        data: Vec::new(),
+    };
+    assert_eq!(
+        update(&authority, "admin", vec![missing], change()),
+        Rcode::NXRRSET
+    );
+    // Value-dependent: the RRset must be exactly this.
+    assert_eq!(
+        update(
+            &authority,
+            "admin",
+            vec![exactly(a("www.example.com", [192, 0, 2, 99]))],
+            change()
+        ),
+        Rcode::NXRRSET
+    );
+    assert_eq!(serial(&authority), Some(1));
+
+    assert_eq!(
+        update(
+            &authority,
+            "admin",
+            vec![
+                in_use,
+                rrset_exists,
+                exactly(a("www.example.com", [192, 0, 2, 1]))
+            ],
+            change()
+        ),
+        Rcode::NOERROR
+    );
+    assert_eq!(serial(&authority), Some(2));
+}
+
+#[test]
+fn rejects_updates_outside_the_zone_whole() {
+    let authority = authority();
+    assert_eq!(
+        update(
+            &authority,
+            "admin",
+            Vec::new(),
+            vec![
+                a("fine.example.com", [192, 0, 2, 3]),
+                a("host.example.net", [192, 0, 2, 4])
+            ]
+        ),
+        Rcode::NOTZONE
+    );
+    let zone = authority.zone(&name("example.com")).unwrap();
+    assert!(!zone.read().unwrap().name_exists(&name("fine.example.com")));
+
+    let mut other = update_message(Vec::new(), Vec::new());
+    other.questions[0].name = name("example.net");
+    let request = Request {
+        message: other,
+        src: "192.0.2.1:53000".parse().unwrap(),
+        protocol: Protocol::Tcp,
+        key: Some(name("admin")),
+    };
+    assert_eq!(
+        authority.handle(&request).unwrap().header.rcode,
+        Rcode::NOTAUTH
+    );
+}
@@ -0,0 +1,198 @@
+//! Per-upstream statistics: the RFC 6298 round-trip time estimator, how
+//! each kind of response, timeout and error is counted, and a resolver
+//! made with an estimator of its own.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{
+    Resolver, ResolverConfig, RttEstimator, ServerStats, SmoothedRtt, UpstreamStats,
+};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::testing::{Action, MockServer};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+fn server(actions: impl IntoIterator<Item = Action>) -> MockServer {
+    let host = name("host.example");
+    actions
+        .into_iter()
+        .fold(MockServer::builder(), |builder, action| {
+            builder.then(action)
+        })
+        .answer(
+            host.clone(),
+            RecordType::A,
+            vec![Record::new(host, 300, RData::A([192, 0, 2, 1].into()))],
+        )
+        .start()
+        .unwrap()
+}
+
+fn resolver(servers: &[&MockServer]) -> Resolver {
+    Resolver::new(ResolverConfig {
+        servers: servers.iter().map(|s| s.addr()).collect(),
+        timeout: ms(200),
+        attempts: 1,
+        ..ResolverConfig::default()
+    })
+}
+
+/// An estimator that has seen nothing, whatever it is told.
+#[derive(Debug)]
+struct Fixed(Duration);
+
+impl RttEstimator for Fixed {
+    fn fresh(&self) -> Box<dyn RttEstimator> {
+        Box::new(Fixed(self.0))
+    }
+
+    fn sample(&mut self, _: Duration) {}
+
+    fn srtt(&self) -> Option<Duration> {
+        Some(self.0)
+    }
+
+    fn rttvar(&self) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+}
+
+#[test]
+fn smooths_samples_and_backs_off_on_timeouts() {
+    let mut rtt = SmoothedRtt::new();
+    assert_eq!((rtt.srtt(), rtt.rttvar(), rtt.rto()), (None, None, None));
+    rtt.timed_out();
+    assert_eq!(rtt.rto(), None);
+
+    rtt.sample(ms(100));
+    assert_eq!((rtt.srtt(), rtt.rttvar()), (Some(ms(100)), Some(ms(50))));
+    assert_eq!(rtt.rto(), Some(ms(300)));
+    rtt.sample(ms(20));
+    assert_eq!(rtt.srtt(), Some(ms(90)));
+    assert_eq!(rtt.rttvar(), Some(Duration::from_micros(57_500)));
+    assert_eq!(rtt.rto(), Some(ms(320)));
+
+    // Each timeout in a row doubles the timeout, until the next sample.
+    rtt.timed_out();
+    assert_eq!(rtt.rto(), Some(ms(640)));
+    rtt.timed_out();
+    assert_eq!(rtt.rto(), Some(ms(1280)));
+    for _ in 0..20 {
+        rtt.timed_out();
+    }
+    assert_eq!(rtt.rto(), Some(Duration::from_secs(60)));
+    rtt.sample(ms(90));
+    assert!(rtt.rto() < Some(ms(320)));
+
+    // Fast servers still get the smallest timeout.
+    let mut fast = SmoothedRtt::new();
+    fast.sample(Duration::from_micros(100));
+    assert_eq!(fast.rto(), Some(ms(50)));
+    assert_eq!(fast.fresh().srtt(), None);
+}
+
+#[test]
+fn counts_each_outcome() {
+    let stats = ServerStats::default();
+    let server = "192.0.2.53:53".parse().unwrap();
+    assert_eq!(stats.get(server), UpstreamStats::default());
+    assert_eq!(stats.get(server).success_rate(), None);
+
+    let query = Message::query(name("host.example"), RecordType::A);
+    let now = Instant::now();
+    let answer = |rcode: Rcode, edns: bool| {
+        let mut resp = query.response();
+        resp.header.rcode = rcode;
+        if !edns {
+            resp.edns = None;
+        }
+        stats.answered(server, &query, &resp, ms(10), now);
+    };
+    answer(Rcode::NOERROR, true);
+    answer(Rcode::NXDOMAIN, true);
+    answer(Rcode::SERVFAIL, true);
+    stats.timed_out(server);
+    stats.failed(server);
+    let seen = stats.get(server);
+    assert_eq!((seen.queries, seen.successes, seen.failures), (5, 2, 1));
+    assert_eq!((seen.timeouts, seen.errors), (1, 1));
+    assert_eq!((seen.consecutive_failures, seen.edns_failures), (3, 0));
+    assert_eq!(seen.last_success, Some(now));
+    assert_eq!(seen.success_rate(), Some(0.4));
+    assert_eq!(seen.srtt, Some(ms(10)));
+
+    // Responses that do not understand EDNS, failing or not.
+    answer(Rcode::NOERROR, false);
+    answer(Rcode::FORMERR, true);
+    answer(Rcode::NOTIMP, true);
+    let seen = stats.get(server);
+    assert_eq!(seen.edns_failures, 3);
+    assert_eq!((seen.successes, seen.failures), (4, 2));
+    assert_eq!(seen.consecutive_failures, 1);
+
+    stats.clear();
+    assert!(stats.all().is_empty());
+}
+
+#[test]
+fn resolvers_keep_stats_for_each_server() {
+    let dead = server([Action::Drop]);
+    let failing = server([Action::Rcode(Rcode::SERVFAIL)]);
+    let malformed = server([Action::Malformed]);
+    let good = server([]);
+    let resolver = resolver(&[&dead, &failing, &malformed, &good]);
+    resolver
+        .query(&name("host.example"), RecordType::A)
+        .unwrap();
+
+    // Clones of a resolver share its statistics.
+    let clone = resolver.clone();
+    let stats = clone.stats();
+    let timed_out = stats.get(dead.addr());
+    assert_eq!((timed_out.queries, timed_out.timeouts), (1, 1));
+    assert_eq!((timed_out.srtt, timed_out.last_success), (None, None));
+    assert_eq!(stats.get(failing.addr()).failures, 1);
+    // Malformed responses are ignored, as spoofed ones would be.
+    let ignored = stats.get(malformed.addr());
+    assert_eq!((ignored.timeouts, ignored.errors), (1, 0));
+    let answered = stats.get(good.addr());
+    assert_eq!((answered.queries, answered.successes), (1, 1));
+    assert!(answered.srtt.is_some() && answered.rto >= Some(ms(50)));
+    assert_eq!(answered.success_rate(), Some(1.0));
+
+    let mut addrs: Vec<_> = [&dead, &failing, &malformed, &good]
+        .iter()
+        .map(|s| s.addr())
+        .collect();
+    addrs.sort();
+    let all: Vec<_> = stats.all().into_iter().map(|(addr, _)| addr).collect();
+    assert_eq!(all, addrs);
+}
+
+#[test]
+fn takes_an_estimator_of_its_own() {
+    let good = server([]);
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![good.addr()],
+        rtt_estimator: Arc::new(Fixed(ms(42))),
+        ..ResolverConfig::default()
+    });
+    resolver
+        .query(&name("host.example"), RecordType::A)
+        .unwrap();
+    let answered = resolver.stats().get(good.addr());
+    assert_eq!(
+        (answered.srtt, answered.rttvar),
+        (Some(ms(42)), Some(Duration::ZERO))
+    );
+    assert_eq!(answered.rto, Some(ms(50)));
+}
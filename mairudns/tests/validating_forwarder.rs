@@ -0,0 +1,202 @@
+//! Forwarding routes that validate: answers cached once with what
+//! validation made of them, bogus answers kept from clients that did not
+//! set CD, signatures kept from clients that did not set DO, and cached
+//! answers that last no longer than their signatures.
+
+#![cfg(feature = "dnssec")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mairudns::client::Protocol;
+use mairudns::clock::MockClock;
+use mairudns::dnssec::{sign_zone, SigningKey, SigningPolicy, TrustAnchors};
+use mairudns::message::{ExtendedError, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, RecordType};
+use mairudns::server::{Authority, CachedResponse, Forwarder, Handler, Request, Route, Security};
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+/// When the zone is signed, and where the clock starts.
+const NOW: u32 = 1_700_000_000;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// An upstream serving `example.` signed at `NOW` for `validity`, with
+/// the A record of `bad.example.` altered after signing; and the anchors
+/// to validate it with.
+fn upstream(validity: Duration) -> (MockServer, TrustAnchors) {
+    let zone = Zone::from_master(
+        name("example."),
+        "$TTL 3600\n@ SOA ns hostmaster 1 3600 600 86400 300\n@ NS ns\n\
+         ns A 192.0.2.53\nwww A 192.0.2.1\nbad A 192.0.2.2\n",
+    )
+    .unwrap();
+    let policy = SigningPolicy {
+        now: Some(NOW),
+        validity,
+        jitter: Duration::ZERO,
+        ..SigningPolicy::default()
+    };
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let mut signed = sign_zone(&zone, &[key], &policy).unwrap();
+    let anchors =
+        TrustAnchors::from_records(signed.rrset(&name("example."), RecordType::DNSKEY).unwrap());
+    let mut bad = signed
+        .rrset(&name("bad.example."), RecordType::A)
+        .unwrap()
+        .to_vec();
+    signed.remove_rrset(&name("bad.example."), RecordType::A);
+    for rr in &mut bad {
+        rr.rdata = RData::A([192, 0, 2, 66].into());
+        signed.insert(rr.clone()).unwrap();
+    }
+    let authority = Arc::new(Authority::new());
+    authority.insert(signed);
+    let server = MockServer::builder().handler(authority).start().unwrap();
+    (server, anchors)
+}
+
+fn forwarder(server: &MockServer, anchors: TrustAnchors, clock: &MockClock) -> Forwarder {
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    let route = Route::new(DomainName::root(), resolver)
+        .validate(anchors)
+        .clock(Arc::new(clock.clone()));
+    Forwarder::new().route(route)
+}
+
+fn ask(forwarder: &Forwarder, qname: &str, dnssec_ok: bool, cd: bool) -> Message {
+    let mut message = Message::query(name(qname), RecordType::A);
+    message.header.cd = cd;
+    if let Some(edns) = &mut message.edns {
+        edns.dnssec_ok = dnssec_ok;
+    }
+    let request = Request {
+        message,
+        src: "192.0.2.1:53000".parse().unwrap(),
+        protocol: Protocol::Udp,
+        key: None,
+    };
+    forwarder.handle(&request).unwrap()
+}
+
+/// How many A queries for `qname` the upstream has had.
+fn asked(server: &MockServer, qname: &str) -> usize {
+    server
+        .received()
+        .iter()
+        .filter(|r| r.message.question().is_some_and(|q| q.name == name(qname)))
+        .count()
+}
+
+fn has_rrsig(resp: &Message) -> bool {
+    resp.answers
+        .iter()
+        .any(|rr| rr.rtype() == RecordType::RRSIG)
+}
+
+#[test]
+fn secure_answers_are_validated_once() {
+    let (server, anchors) = upstream(Duration::from_secs(30 * 86400));
+    let clock = MockClock::new(u64::from(NOW));
+    let forwarder = forwarder(&server, anchors, &clock);
+
+    let plain = ask(&forwarder, "www.example.", false, false);
+    assert_eq!(plain.header.rcode, Rcode::NOERROR);
+    assert_eq!(plain.answers.len(), 1);
+    assert_eq!(plain.answers[0].rdata, RData::A([192, 0, 2, 1].into()));
+    assert!(!plain.header.ad);
+    // The upstream was asked for the signatures all the same.
+    let upstream_query = &server.received()[0].message;
+    assert!(upstream_query.header.cd);
+    assert!(upstream_query.edns.as_ref().unwrap().dnssec_ok);
+
+    // From the cache, with the signatures and AD for clients that ask.
+    let signed = ask(&forwarder, "www.example.", true, false);
+    assert!(signed.header.ad);
+    assert!(has_rrsig(&signed));
+    let unchecked = ask(&forwarder, "www.example.", true, true);
+    assert!(has_rrsig(&unchecked));
+    assert_eq!(asked(&server, "www.example."), 1);
+
+    let www = forwarder.routes()[0]
+        .cache_entries(Some(&name("www.example.")))
+        .remove(0);
+    assert_eq!(www.security, Security::Secure);
+}
+
+#[test]
+fn bogus_answers_go_only_to_clients_that_set_cd() {
+    let (server, anchors) = upstream(Duration::from_secs(30 * 86400));
+    let clock = MockClock::new(u64::from(NOW));
+    let forwarder = forwarder(&server, anchors, &clock);
+
+    let resp = ask(&forwarder, "bad.example.", true, false);
+    assert_eq!(resp.header.rcode, Rcode::SERVFAIL);
+    assert!(resp.answers.is_empty());
+    let error = resp.extended_errors().next().unwrap();
+    assert_eq!(error.code, ExtendedError::DNSSEC_BOGUS);
+
+    // As it came, from the cache.
+    let resp = ask(&forwarder, "bad.example.", true, true);
+    assert_eq!(resp.header.rcode, Rcode::NOERROR);
+    assert_eq!(resp.answers[0].rdata, RData::A([192, 0, 2, 66].into()));
+    assert!(!resp.header.ad);
+    assert_eq!(asked(&server, "bad.example."), 1);
+
+    // But not for long, in case the zone is fixed.
+    clock.advance(Duration::from_secs(59));
+    ask(&forwarder, "bad.example.", true, false);
+    assert_eq!(asked(&server, "bad.example."), 1);
+    clock.advance(Duration::from_secs(2));
+    ask(&forwarder, "bad.example.", true, false);
+    assert_eq!(asked(&server, "bad.example."), 2);
+}
+
+#[test]
+fn answers_are_cached_no_longer_than_their_signatures() {
+    // Signatures that lapse ten minutes after `NOW`, over records with an
+    // hour to live.
+    let (server, anchors) = upstream(Duration::from_secs(600));
+    let clock = MockClock::new(u64::from(NOW));
+    let forwarder = forwarder(&server, anchors, &clock);
+    assert!(ask(&forwarder, "www.example.", true, false).header.ad);
+    let www = forwarder.routes()[0]
+        .cache_entries(Some(&name("www.example.")))
+        .remove(0);
+    assert_eq!(www.expires_in, Duration::from_secs(600));
+
+    clock.advance(Duration::from_secs(599));
+    ask(&forwarder, "www.example.", true, false);
+    assert_eq!(asked(&server, "www.example."), 1);
+    clock.advance(Duration::from_secs(2));
+    ask(&forwarder, "www.example.", true, false);
+    assert_eq!(asked(&server, "www.example."), 2);
+}
+
+#[test]
+fn validating_routes_restore_only_validated_answers() {
+    let (server, anchors) = upstream(Duration::from_secs(30 * 86400));
+    let clock = MockClock::new(u64::from(NOW));
+    let forwarder = forwarder(&server, anchors, &clock);
+    ask(&forwarder, "www.example.", true, false);
+    let route = &forwarder.routes()[0];
+    assert!(route.is_validating());
+    let entry: CachedResponse = route.cache_entries(None).remove(0);
+    route.flush();
+
+    let unchecked = CachedResponse {
+        security: Security::Unchecked,
+        ..entry.clone()
+    };
+    assert!(!route.restore(unchecked));
+    assert!(route.restore(entry));
+    assert_eq!(route.cache_entries(None)[0].security, Security::Secure);
+}
@@ -0,0 +1,432 @@
+//! The DNSSEC validator against zones signed here and served by an
+//! authority: secure, insecure and bogus chains, denial of existence by
+//! NSEC and NSEC3, wildcard expansions, and signatures outside their
+//! validity.
+
+#![cfg(feature = "dnssec")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mairudns::clock::MockClock;
+use mairudns::dnssec::{
+    sign_zone, AnchorKey, Denial, DigestType, Reason, Rrsig, SigningKey, SigningPolicy, Status,
+    TrustAnchors, Validated, Validator,
+};
+use mairudns::message::{ExtendedError, Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Authority, Handler, Request};
+use mairudns::testing::MockServer;
+use mairudns::zone::Zone;
+
+/// When the zones are signed, and where the validator's clock starts.
+const NOW: u32 = 1_700_000_000;
+
+const DAY: u32 = 86400;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn key(seed: u8) -> SigningKey {
+    SigningKey::ed25519(257, &[seed; 32]).unwrap()
+}
+
+fn zone(origin: &str, body: &str) -> Zone {
+    let text = format!(
+        "$TTL 3600\n@ IN SOA ns1 hostmaster 1 7200 900 1209600 300\n@ IN NS ns1\n{}",
+        body
+    );
+    Zone::from_master(name(origin), &text).unwrap()
+}
+
+/// `zone` signed by `key` at `now` with `denial`.
+fn signed(zone: &Zone, key: &SigningKey, now: u32, denial: Denial) -> Zone {
+    let policy = SigningPolicy {
+        now: Some(now),
+        jitter: Duration::ZERO,
+        denial,
+        ..SigningPolicy::default()
+    };
+    sign_zone(zone, std::slice::from_ref(key), &policy).unwrap()
+}
+
+/// A DS record at `origin` for `key`.
+fn ds(origin: &str, key: &SigningKey) -> Record {
+    let ds = key.dnskey().ds(&name(origin), DigestType::SHA256).unwrap();
+    Record::new(name(origin), 3600, ds.to_rdata())
+}
+
+fn nsec3() -> Denial {
+    Denial::Nsec3 {
+        iterations: 0,
+        salt: Vec::new(),
+        opt_out: false,
+    }
+}
+
+/// Answers DS queries for zone apexes from the parent zones alone, as a
+/// server that is not also authoritative for the child would, and
+/// everything else from the closest zone. A lame one answers DS queries
+/// with nothing at all.
+struct Servers {
+    parents: Authority,
+    all: Authority,
+    lame: bool,
+}
+
+impl Handler for Servers {
+    fn handle(&self, request: &Request) -> Option<Message> {
+        let q = request.message.question()?;
+        if q.qtype != RecordType::DS {
+            return self.all.handle(request);
+        }
+        if self.lame {
+            return Some(request.message.response());
+        }
+        match self.all.origins().contains(&q.name) {
+            true => self.parents.handle(request),
+            false => self.all.handle(request),
+        }
+    }
+}
+
+/// The signed root, with below it:
+///
+/// - `example.`, signed with NSEC and holding a wildcard;
+/// - `hashed.`, signed with NSEC3 and holding a wildcard;
+/// - `unsigned.`, a delegation without DS records;
+/// - `stale.`, whose signatures expired before `NOW`;
+/// - `early.`, whose signatures become valid after `NOW`;
+/// - `orphan.`, whose DS matches no key it publishes.
+fn servers(lame: bool) -> (MockServer, SigningKey) {
+    let root_key = key(1);
+    let children = [
+        ("example.", key(2), NOW, Denial::Nsec),
+        ("hashed.", key(3), NOW, nsec3()),
+        ("stale.", key(4), NOW - 40 * DAY, Denial::Nsec),
+        ("early.", key(5), NOW + DAY, Denial::Nsec),
+        ("orphan.", key(6), NOW, Denial::Nsec),
+    ];
+    let mut root = zone(
+        ".",
+        "ns1 IN A 192.0.2.53\n\
+         unsigned IN NS ns1.unsigned.\n\
+         ns1.unsigned IN A 192.0.2.54\n",
+    );
+    let body = "www IN A 192.0.2.1\n\
+                *.wild IN A 192.0.2.7\n\
+                ns1 IN A 192.0.2.53\n";
+    let all = Authority::new();
+    for (origin, key, at, denial) in children {
+        root.insert(Record::new(name(origin), 3600, RData::Ns(name("ns1."))))
+            .unwrap();
+        let listed = if origin == "orphan." {
+            self::key(7)
+        } else {
+            key.clone()
+        };
+        root.insert(ds(origin, &listed)).unwrap();
+        all.insert(signed(&zone(origin, body), &key, at, denial));
+    }
+    all.insert(zone(
+        "unsigned.",
+        "host IN A 192.0.2.9\nns1 IN A 192.0.2.54\n",
+    ));
+    let root = signed(&root, &root_key, NOW, Denial::Nsec);
+    all.insert(root.clone());
+    let parents = Authority::new();
+    parents.insert(root);
+    let server = MockServer::builder()
+        .handler(Servers { parents, all, lame })
+        .start()
+        .unwrap();
+    (server, root_key)
+}
+
+fn validator(server: &MockServer, anchor: &SigningKey, clock: &Arc<MockClock>) -> Validator {
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    let mut anchors = TrustAnchors::new();
+    anchors.add(
+        DomainName::root(),
+        AnchorKey::Dnskey(anchor.dnskey().clone()),
+    );
+    Validator::new(resolver)
+        .anchors(anchors)
+        .clock(clock.clone())
+}
+
+fn setup() -> (MockServer, Validator, Arc<MockClock>) {
+    let (server, root_key) = servers(false);
+    let clock = Arc::new(MockClock::new(u64::from(NOW)));
+    let validator = validator(&server, &root_key, &clock);
+    (server, validator, clock)
+}
+
+fn query(validator: &Validator, qname: &str, qtype: RecordType) -> Validated {
+    validator.query(&name(qname), qtype).unwrap()
+}
+
+fn status(validated: &Validated) -> (Status, Option<Reason>) {
+    (validated.status, validated.reason)
+}
+
+fn has(section: &[Record], rtype: RecordType) -> bool {
+    section.iter().any(|rr| rr.rtype() == rtype)
+}
+
+#[test]
+fn a_chain_from_the_anchor_is_secure() {
+    let (_server, validator, _) = setup();
+    for zone in ["example.", "hashed."] {
+        let www = format!("www.{}", zone);
+        let v = query(&validator, &www, RecordType::A);
+        assert_eq!(status(&v), (Status::Secure, None), "{}", www);
+        assert!(v.message.header.ad);
+        assert_eq!(v.message.answers[0].rdata, RData::A([192, 0, 2, 1].into()));
+    }
+    // The zone apexes' own key sets.
+    let v = query(&validator, "example.", RecordType::DNSKEY);
+    assert_eq!(status(&v), (Status::Secure, None));
+}
+
+#[test]
+fn a_delegation_proven_unsigned_is_insecure() {
+    let (_server, validator, _) = setup();
+    let v = query(&validator, "host.unsigned.", RecordType::A);
+    assert_eq!(status(&v), (Status::Insecure, None));
+    assert!(!v.message.header.ad);
+    assert_eq!(v.message.answers[0].rdata, RData::A([192, 0, 2, 9].into()));
+}
+
+#[test]
+fn without_an_anchor_everything_is_insecure() {
+    let (server, _, clock) = setup();
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        ..ResolverConfig::default()
+    });
+    let validator = Validator::new(resolver)
+        .anchors(TrustAnchors::new())
+        .clock(clock);
+    let v = query(&validator, "www.example.", RecordType::A);
+    assert_eq!(status(&v), (Status::Insecure, None));
+}
+
+#[test]
+fn a_chain_that_breaks_is_bogus() {
+    let (server, validator, clock) = setup();
+    // The parent lists a key the child does not publish.
+    let v = query(&validator, "www.orphan.", RecordType::A);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::DnskeyMissing)));
+    assert!(!v.message.header.ad);
+
+    // An anchor for a key the root does not publish.
+    let validator = self::validator(&server, &key(9), &clock);
+    let v = query(&validator, "www.example.", RecordType::A);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::DnskeyMissing)));
+    assert_eq!(
+        v.extended_error().unwrap().code,
+        ExtendedError::DNSKEY_MISSING
+    );
+}
+
+#[test]
+fn altered_or_stripped_answers_are_bogus() {
+    let (_server, validator, _) = setup();
+    let secure = query(&validator, "www.example.", RecordType::A).message;
+    assert_eq!(validator.validate(&secure).status, Status::Secure);
+
+    let mut altered = secure.clone();
+    altered.answers[0].rdata = RData::A([192, 0, 2, 66].into());
+    let v = validator.validate(&altered);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::BadSignature)));
+    assert!(!v.message.header.ad);
+
+    let mut stripped = secure;
+    stripped
+        .answers
+        .retain(|rr| rr.rtype() != RecordType::RRSIG);
+    let v = validator.validate(&stripped);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::RrsigsMissing)));
+    assert_eq!(
+        v.extended_error().unwrap().code,
+        ExtendedError::RRSIGS_MISSING
+    );
+}
+
+#[test]
+fn denial_is_proven_by_nsec_and_nsec3() {
+    let (_server, validator, _) = setup();
+    for (zone, denial) in [
+        ("example.", RecordType::NSEC),
+        ("hashed.", RecordType::NSEC3),
+    ] {
+        // A name that does not exist.
+        let v = query(&validator, &format!("nowhere.{}", zone), RecordType::A);
+        assert_eq!(v.message.header.rcode, Rcode::NXDOMAIN);
+        assert!(has(&v.message.authority, denial), "{}", zone);
+        assert_eq!(status(&v), (Status::Secure, None), "{}", zone);
+        assert!(v.message.header.ad);
+
+        // A type the name does not have.
+        let v = query(&validator, &format!("www.{}", zone), RecordType::AAAA);
+        assert_eq!(v.message.header.rcode, Rcode::NOERROR);
+        assert!(v.message.answers.is_empty());
+        assert!(has(&v.message.authority, denial), "{}", zone);
+        assert_eq!(status(&v), (Status::Secure, None), "{}", zone);
+
+        // Either without its proof.
+        for (qname, qtype) in [("nowhere", RecordType::A), ("www", RecordType::AAAA)] {
+            let mut unproven = query(&validator, &format!("{}.{}", qname, zone), qtype).message;
+            unproven.authority.retain(|rr| !covers(rr, denial));
+            let v = validator.validate(&unproven);
+            assert_eq!(
+                status(&v),
+                (Status::Bogus, Some(Reason::NsecMissing)),
+                "{} {}",
+                qname,
+                zone
+            );
+        }
+    }
+}
+
+/// Whether `rr` is of `rtype` or an RRSIG over one.
+fn covers(rr: &Record, rtype: RecordType) -> bool {
+    Rrsig::from_rdata(&rr.rdata).map_or(rr.rtype(), |sig| sig.type_covered) == rtype
+}
+
+#[test]
+fn wildcard_expansions_need_proof_that_the_name_does_not_exist() {
+    let (_server, validator, _) = setup();
+    for (zone, denial) in [
+        ("example.", RecordType::NSEC),
+        ("hashed.", RecordType::NSEC3),
+    ] {
+        let qname = format!("a.b.wild.{}", zone);
+        let v = query(&validator, &qname, RecordType::A);
+        assert_eq!(status(&v), (Status::Secure, None), "{}", qname);
+        assert_eq!(v.message.answers[0].name, name(&qname));
+        assert_eq!(v.message.answers[0].rdata, RData::A([192, 0, 2, 7].into()));
+        assert!(has(&v.message.authority, denial));
+
+        // Without the proof, the expansion could hide a name that exists.
+        let mut unproven = v.message.clone();
+        unproven.authority.clear();
+        let v = validator.validate(&unproven);
+        assert_eq!(status(&v), (Status::Bogus, Some(Reason::NsecMissing)));
+        assert_eq!(
+            v.extended_error().unwrap().code,
+            ExtendedError::NSEC_MISSING
+        );
+    }
+}
+
+#[test]
+fn signatures_outside_their_validity_are_bogus() {
+    let (_server, validator, clock) = setup();
+    let v = query(&validator, "www.stale.", RecordType::A);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::SignatureExpired)));
+    assert_eq!(
+        v.extended_error().unwrap().code,
+        ExtendedError::SIGNATURE_EXPIRED
+    );
+    let v = query(&validator, "www.early.", RecordType::A);
+    assert_eq!(
+        status(&v),
+        (Status::Bogus, Some(Reason::SignatureNotYetValid))
+    );
+    assert_eq!(
+        v.extended_error().unwrap().code,
+        ExtendedError::SIGNATURE_NOT_YET_VALID
+    );
+
+    // Time moves on: what was early becomes valid, and what was valid
+    // expires along with every key learned from it.
+    clock.advance(Duration::from_secs(u64::from(DAY)));
+    let v = query(&validator, "www.early.", RecordType::A);
+    assert_eq!(status(&v), (Status::Secure, None));
+    clock.advance(Duration::from_secs(u64::from(40 * DAY)));
+    let v = query(&validator, "www.example.", RecordType::A);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::SignatureExpired)));
+}
+
+#[test]
+fn validity_includes_both_ends_by_the_wall_clock() {
+    // The root and example. are signed at the same time, so share their
+    // validity.
+    let (server, root_key) = servers(false);
+    let example = signed(&zone("example.", ""), &key(2), NOW, Denial::Nsec);
+    let sig = example
+        .records()
+        .find_map(|rr| Rrsig::from_rdata(&rr.rdata))
+        .unwrap();
+    let (inception, expiration) = (u64::from(sig.inception), u64::from(sig.expiration));
+    let cases = [
+        (inception - 1, Some(Reason::SignatureNotYetValid)),
+        (inception, None),
+        (expiration, None),
+        (expiration + 1, Some(Reason::SignatureExpired)),
+    ];
+    for (at, reason) in cases {
+        // Only the wall clock moves; nothing learned earlier is reused.
+        let clock = Arc::new(MockClock::new(u64::from(NOW)));
+        clock.set_unix_time(at);
+        let validator = validator(&server, &root_key, &clock);
+        let v = query(&validator, "www.example.", RecordType::A);
+        assert_eq!(v.reason, reason, "at {}", at);
+    }
+}
+
+#[test]
+fn unsigned_answers_for_ds_records_are_judged_by_the_parent() {
+    let (server, root_key) = servers(true);
+    let clock = Arc::new(MockClock::new(u64::from(NOW)));
+    let validator = validator(&server, &root_key, &clock);
+    // The root is secure, so it cannot leave the DS records of
+    // `example.` unproven.
+    let v = query(&validator, "www.example.", RecordType::A);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::NsecMissing)));
+    let v = query(&validator, "example.", RecordType::DS);
+    assert_eq!(status(&v), (Status::Bogus, Some(Reason::NsecMissing)));
+}
+
+#[test]
+fn negative_anchors_make_zones_insecure_for_a_while() {
+    let (_server, validator, clock) = setup();
+    let v = query(&validator, "www.orphan.", RecordType::A);
+    assert_eq!(v.status, Status::Bogus);
+
+    // RFC 7646: an operator vouches for a broken zone, and only that zone.
+    validator.add_negative_anchor(name("orphan."), Some(Duration::from_secs(60)));
+    let v = query(&validator, "www.orphan.", RecordType::A);
+    assert_eq!(status(&v), (Status::Insecure, None));
+    assert_eq!(v.message.answers[0].rdata, RData::A([192, 0, 2, 1].into()));
+    assert_eq!(
+        query(&validator, "www.example.", RecordType::A).status,
+        Status::Secure
+    );
+    let anchors = validator.trust_anchors();
+    let negative: Vec<_> = anchors.negative().collect();
+    assert_eq!(negative, [(&name("orphan."), Some(u64::from(NOW) + 60))]);
+
+    // It lapses on its own.
+    clock.advance(Duration::from_secs(61));
+    let v = query(&validator, "www.orphan.", RecordType::A);
+    assert_eq!(v.status, Status::Bogus);
+
+    // Or is taken away.
+    validator.add_negative_anchor(name("orphan."), None);
+    let v = query(&validator, "www.orphan.", RecordType::A);
+    assert_eq!(v.status, Status::Insecure);
+    assert!(validator.remove_negative_anchor(&name("orphan.")));
+    assert!(!validator.remove_negative_anchor(&name("orphan.")));
+    let v = query(&validator, "www.orphan.", RecordType::A);
+    assert_eq!(v.status, Status::Bogus);
+}
@@ -0,0 +1,140 @@
+//! Split-horizon views: which view a client lands in, by address and
+//! TSIG key, and caches that stay within their view.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use mairudns::addr::IpSet;
+use mairudns::client::Protocol;
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::server::{Forwarder, Handler, Request, Route, View, Views};
+use mairudns::testing::MockServer;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn request(src: &str, key: Option<&str>) -> Request {
+    Request {
+        message: Message::query(name("www.example"), RecordType::A),
+        src: src.parse::<SocketAddr>().unwrap(),
+        protocol: Protocol::Udp,
+        key: key.map(name),
+    }
+}
+
+/// A handler answering with `rcode`, to tell views apart.
+fn answering(rcode: Rcode) -> impl Handler {
+    move |req: &Request| {
+        let mut resp = req.message.response();
+        resp.header.rcode = rcode;
+        Some(resp)
+    }
+}
+
+#[test]
+fn client_sets_take_exclusions_and_mapped_addresses() {
+    let set: IpSet = "!10.1.0.0/16, 10.0.0.0/8 ::1".parse().unwrap();
+    assert!(set.contains(&"10.2.3.4".parse().unwrap()));
+    assert!(!set.contains(&"10.1.3.4".parse().unwrap()));
+    assert!(set.contains(&"::ffff:10.2.3.4".parse().unwrap()));
+    assert!(set.contains(&"::1".parse().unwrap()));
+    assert!(!set.contains(&"192.0.2.1".parse().unwrap()));
+    assert!(IpSet::any().contains(&"2001:db8::1".parse().unwrap()));
+}
+
+#[test]
+fn sends_each_client_to_the_first_matching_view() {
+    let views = Views::new()
+        .view(View::new(
+            "internal",
+            "10.0.0.0/8".parse().unwrap(),
+            answering(Rcode::NXDOMAIN),
+        ))
+        .view(View::new(
+            "everyone",
+            IpSet::any(),
+            answering(Rcode::NOERROR),
+        ));
+    let internal = request("10.1.2.3:5300", None);
+    assert_eq!(views.select(&internal).unwrap().name(), "internal");
+    assert_eq!(
+        views.handle(&internal).unwrap().header.rcode,
+        Rcode::NXDOMAIN
+    );
+    let external = request("192.0.2.1:5300", None);
+    assert_eq!(views.select(&external).unwrap().name(), "everyone");
+    assert_eq!(
+        views.handle(&external).unwrap().header.rcode,
+        Rcode::NOERROR
+    );
+}
+
+#[test]
+fn keyed_views_need_a_signed_request() {
+    let views = Views::new()
+        .view(View::new("ops", IpSet::any(), answering(Rcode::NOERROR)).key(name("ops-key")));
+    assert!(views.select(&request("10.1.2.3:5300", None)).is_none());
+    assert!(views
+        .select(&request("10.1.2.3:5300", Some("other-key")))
+        .is_none());
+    assert!(views
+        .select(&request("10.1.2.3:5300", Some("ops-key")))
+        .is_some());
+    // Nothing matched: refused.
+    assert_eq!(
+        views
+            .handle(&request("10.1.2.3:5300", None))
+            .unwrap()
+            .header
+            .rcode,
+        Rcode::REFUSED
+    );
+}
+
+fn upstream(addr: [u8; 4]) -> MockServer {
+    let host = name("www.example");
+    MockServer::builder()
+        .answer(
+            host.clone(),
+            RecordType::A,
+            vec![Record::new(host, 300, RData::A(addr.into()))],
+        )
+        .start()
+        .unwrap()
+}
+
+fn forwarder(server: &MockServer) -> Forwarder {
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![server.addr()],
+        timeout: Duration::from_millis(500),
+        attempts: 1,
+        ..ResolverConfig::default()
+    });
+    Forwarder::new().route(Route::new(DomainName::root(), resolver))
+}
+
+#[test]
+fn caches_do_not_leak_between_views() {
+    let inside = upstream([10, 0, 0, 1]);
+    let outside = upstream([198, 51, 100, 1]);
+    let views = Views::new()
+        .view(View::new(
+            "internal",
+            "10.0.0.0/8".parse().unwrap(),
+            forwarder(&inside),
+        ))
+        .view(View::new("external", IpSet::any(), forwarder(&outside)));
+    for _ in 0..2 {
+        let resp = views.handle(&request("10.1.2.3:5300", None)).unwrap();
+        assert_eq!(resp.answers[0].rdata, RData::A([10, 0, 0, 1].into()));
+        let resp = views.handle(&request("192.0.2.1:5300", None)).unwrap();
+        assert_eq!(resp.answers[0].rdata, RData::A([198, 51, 100, 1].into()));
+    }
+    // The second round came from each view's own cache.
+    assert_eq!(inside.received().len(), 1);
+    assert_eq!(outside.received().len(), 1);
+}
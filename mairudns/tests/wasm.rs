@@ -0,0 +1,81 @@
+//! The crate on WebAssembly: the codec, names, addresses and zone text
+//! working as they do natively, transaction IDs still random, and the
+//! socket transports failing cleanly where the host has no sockets.
+//!
+//! These run on WASI only, with a runner for the target, e.g.
+//! `CARGO_TARGET_WASM32_WASIP1_RUNNER=wasmtime cargo test --target
+//! wasm32-wasip1 --test wasm`. On wasm32-unknown-unknown the crate builds
+//! with the `wasm` feature, but tests need a JavaScript host to run in.
+
+#![cfg(target_family = "wasm")]
+
+use std::collections::HashSet;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use mairudns::message::{Message, Rcode};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::zone::Zone;
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+#[test]
+fn messages_round_trip_as_they_do_natively() {
+    let mut query = Message::query(name("www.example."), RecordType::AAAA);
+    query.header.id = 0x1234;
+    let wire = query.to_wire().unwrap();
+    // The same bytes as on any other target: ID, RD, one question, and
+    // the OPT record queries carry.
+    assert_eq!(&wire[..12], &[0x12, 0x34, 0x01, 0, 0, 1, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(Message::from_wire(&wire).unwrap(), query);
+
+    let mut resp = query.response();
+    resp.header.rcode = Rcode::NXDOMAIN;
+    let v6: Ipv6Addr = "2001:db8::1".parse().unwrap();
+    resp.answers
+        .push(Record::new(name("www.example."), 300, RData::Aaaa(v6)));
+    let back = Message::from_wire(&resp.to_wire().unwrap()).unwrap();
+    assert_eq!(back, resp);
+    assert_eq!(
+        back.answers[0].to_string(),
+        "www.example.\t300\tIN\tAAAA\t2001:db8::1"
+    );
+}
+
+#[test]
+fn zones_parse_from_text() {
+    let zone = Zone::from_master(
+        name("example."),
+        "$TTL 300\n\
+         @ IN SOA ns hostmaster 1 3600 600 86400 300\n\
+         @ IN NS ns\n\
+         ns IN A 192.0.2.1\n\
+         www IN CNAME ns\n",
+    )
+    .unwrap();
+    assert_eq!(zone.records().count(), 4);
+    let ns = RData::A([192, 0, 2, 1].into());
+    assert!(zone.records().any(|rr| rr.rdata == ns));
+}
+
+#[test]
+fn transaction_ids_are_random() {
+    let ids: HashSet<u16> = (0..64)
+        .map(|_| Message::query(name("example."), RecordType::A).header.id)
+        .collect();
+    assert!(ids.len() > 60, "{} distinct IDs of 64", ids.len());
+}
+
+#[test]
+fn resolvers_fail_without_sockets() {
+    let resolver = Resolver::new(ResolverConfig {
+        servers: vec![SocketAddr::from(([192, 0, 2, 53], 53))],
+        timeout: Duration::from_millis(100),
+        ..ResolverConfig::default()
+    });
+    assert!(resolver.lookup(&name("example."), RecordType::A).is_err());
+}
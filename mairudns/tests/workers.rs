@@ -0,0 +1,108 @@
+//! SO_REUSEPORT workers: every UDP socket and TCP listener bound to the
+//! shared address answers, bursts that arrive faster than they are
+//! answered are received and sent in batches without loss, and the
+//! workers stop with the server.
+
+use std::collections::HashSet;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mairudns::client::{exchange, Protocol};
+use mairudns::message::Message;
+use mairudns::name::DomainName;
+use mairudns::rr::RecordType;
+use mairudns::server::{Control, Request, Server};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn echo(request: &Request) -> Option<Message> {
+    Some(request.message.response())
+}
+
+fn start(workers: usize) -> (SocketAddr, Control, thread::JoinHandle<std::io::Result<()>>) {
+    let mut server = Server::new(echo as fn(&Request) -> Option<Message>);
+    let addr = server
+        .listen_workers("127.0.0.1:0".parse().unwrap(), workers)
+        .unwrap();
+    server.set_pin_workers(true);
+    let control = server.control();
+    (addr, control, thread::spawn(move || server.run()))
+}
+
+#[test]
+fn answers_over_udp_and_tcp_from_many_clients() {
+    let (addr, control, running) = start(4);
+    let clients: Vec<_> = (0..16)
+        .map(|i| {
+            thread::spawn(move || {
+                let protocol = if i % 2 == 0 {
+                    Protocol::Udp
+                } else {
+                    Protocol::Tcp
+                };
+                for j in 0..20 {
+                    let query = Message::query(name(&format!("c{}-{}.test", i, j)), RecordType::A);
+                    let resp = exchange(addr, protocol, &query, Duration::from_secs(2)).unwrap();
+                    assert_eq!(resp.questions, query.questions);
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+    let start = Instant::now();
+    control.shutdown();
+    running.join().unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn answers_every_query_of_a_burst() {
+    let (addr, control, running) = start(2);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    // Sent back to back from one socket, so that they land on one worker
+    // and queue up for batched receives.
+    let mut sent = HashSet::new();
+    for id in 0..200u16 {
+        let mut query = Message::query(name(&format!("q{}.test", id)), RecordType::A);
+        query.header.id = id;
+        socket.send_to(&query.to_wire().unwrap(), addr).unwrap();
+        sent.insert(id);
+    }
+    let mut buf = [0u8; 512];
+    while !sent.is_empty() {
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        let resp = Message::from_wire(&buf[..len]).unwrap();
+        let id = resp.header.id;
+        assert_eq!(resp.questions[0].name, name(&format!("q{}.test", id)));
+        assert!(sent.remove(&id), "duplicate answer {}", id);
+    }
+    control.shutdown();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn one_worker_still_serves_both_protocols() {
+    let (addr, control, running) = start(0);
+    let query = Message::query(name("a.test"), RecordType::A);
+    for &protocol in &[Protocol::Udp, Protocol::Tcp] {
+        assert!(exchange(addr, protocol, &query, Duration::from_secs(1)).is_ok());
+    }
+    assert_eq!(
+        control
+            .listening()
+            .iter()
+            .filter(|&&(_, a)| a == addr)
+            .count(),
+        2
+    );
+    control.shutdown();
+    running.join().unwrap().unwrap();
+}
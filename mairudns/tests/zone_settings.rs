@@ -0,0 +1,214 @@
+//! Zone settings inherited from the defaults of the whole configuration
+//! and of a zone's view: what overrides what, lists and keys cleared by
+//! empty ones, TTL rules narrowed to the zone, and the problems found in
+//! defaults and in the zones using them.
+
+use mairudns::config::{Config, KeyConfig, TtlRuleConfig, ViewConfig, ZoneConfig, ZoneSettings};
+
+fn config() -> Config {
+    Config::new()
+        .key(KeyConfig::new("xfr.", "c2VjcmV0"))
+        .zone_defaults(
+            ZoneSettings::new()
+                .allow_transfer(vec!["10.0.0.0/8".into()])
+                .transfer_key("xfr.")
+                .verify_zonemd(true)
+                .ttl_rules(vec![TtlRuleConfig::new(".").ceiling(300)]),
+        )
+        .view(
+            ViewConfig::new("internal")
+                .client("192.168.0.0/16")
+                .zone_defaults(
+                    ZoneSettings::new()
+                        .allow_transfer(Vec::new())
+                        .report_channel("agent.example."),
+                ),
+        )
+        .zone(ZoneConfig::primary("example.", "a.zone"))
+        .zone(
+            ZoneConfig::primary("example.", "b.zone")
+                .view("internal")
+                .transfer_key("")
+                .ttl_rule(TtlRuleConfig::new("www.example.").floor(60)),
+        )
+        .zone(ZoneConfig::primary("other.", "c.zone").view("internal"))
+}
+
+#[test]
+fn zones_inherit_what_they_leave_unset() {
+    let config = config();
+    assert_eq!(config.validate(), Ok(()));
+
+    // Outside views, the defaults of the whole configuration, with the
+    // TTL rule above the zone applying to it as a whole.
+    let zone = config.effective_zone("example", None).unwrap();
+    assert_eq!(zone.allow_transfer, Some(vec!["10.0.0.0/8".to_string()]));
+    assert_eq!(zone.transfer_key.as_deref(), Some("xfr."));
+    assert_eq!(zone.verify_zonemd, Some(true));
+    assert_eq!(zone.report_channel, None);
+    assert_eq!(
+        zone.ttl_rules,
+        Some(vec![TtlRuleConfig::new("example.").ceiling(300)])
+    );
+
+    // In the view, its empty list overrides the global one, the zone's
+    // empty key clears the inherited key, and the zone's own rules
+    // replace the inherited ones.
+    let zone = config.effective_zone("EXAMPLE.", Some("internal")).unwrap();
+    assert_eq!(zone.file, Some("b.zone".into()));
+    assert_eq!(zone.allow_transfer, Some(Vec::new()));
+    assert_eq!(zone.transfer_key, None);
+    assert_eq!(zone.report_channel.as_deref(), Some("agent.example."));
+    assert_eq!(zone.verify_zonemd, Some(true));
+    assert_eq!(
+        zone.ttl_rules,
+        Some(vec![TtlRuleConfig::new("www.example.").floor(60)])
+    );
+    assert_eq!(zone.transfer_acl().unwrap().addresses.len(), 0);
+
+    // The zone's own setting wins over both.
+    let config = config.zone(
+        ZoneConfig {
+            verify_zonemd: Some(false),
+            ..ZoneConfig::primary("third.", "d.zone")
+        }
+        .view("internal"),
+    );
+    let zone = config.effective_zone("third.", Some("internal")).unwrap();
+    assert_eq!(zone.verify_zonemd, Some(false));
+    assert!(config.effective_zone("third.", None).is_none());
+    assert_eq!(config.effective_zones().len(), 4);
+
+    // Rules below another zone do not apply to this one.
+    let config = Config::new()
+        .zone_defaults(
+            ZoneSettings::new().ttl_rules(vec![TtlRuleConfig::new("a.example.").floor(60)]),
+        )
+        .zone(ZoneConfig::primary("b.example.", "b.zone"));
+    let zone = config.effective_zone("b.example.", None).unwrap();
+    assert_eq!(zone.ttl_rules, Some(Vec::new()));
+    assert!(zone.ttl_rules().unwrap().is_empty());
+}
+
+#[test]
+fn settings_override_as_a_whole() {
+    let parent = ZoneSettings::new()
+        .allow_transfer(vec!["10.0.0.0/8".into(), "192.0.2.0/24".into()])
+        .primary_key("a.");
+    let own = ZoneSettings::new().allow_transfer(vec!["198.51.100.0/24".into()]);
+    let merged = own.inherit(&parent);
+    assert_eq!(
+        merged.allow_transfer,
+        Some(vec!["198.51.100.0/24".to_string()])
+    );
+    assert_eq!(merged.primary_key.as_deref(), Some("a."));
+    assert_eq!(ZoneSettings::new().inherit(&parent), parent);
+
+    // A zone's settings come back out as they went in.
+    let zone = ZoneConfig::primary("example.", "example.zone").with_settings(merged.clone());
+    assert_eq!(zone.settings(), merged);
+}
+
+#[test]
+fn defaults_and_views_are_checked() {
+    let fields = |config: Config| -> Vec<String> {
+        config
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|p| p.field)
+            .collect()
+    };
+    assert_eq!(
+        fields(config().zone_defaults(ZoneSettings::new().transfer_key("nokey."))),
+        vec!["zone-defaults.transfer-key"]
+    );
+    assert_eq!(
+        fields(
+            config().view(
+                ViewConfig::new("internal")
+                    .zone_defaults(ZoneSettings::new().allow_transfer(vec!["nope".into()]))
+            )
+        ),
+        vec!["views[1].name", "views[1].zone-defaults.allow-transfer[0]"]
+    );
+    assert_eq!(
+        fields(config().zone(ZoneConfig::primary("other.", "d.zone").view("nope"))),
+        vec!["zones[3].view"]
+    );
+    // A zone may be in several views, but once in each.
+    assert_eq!(
+        fields(config().zone(ZoneConfig::primary("Other", "d.zone").view("internal"))),
+        vec!["zones[3].name"]
+    );
+    assert_eq!(
+        fields(config().zone(
+            ZoneConfig::primary("third.", "d.zone").ttl_rule(TtlRuleConfig::new("elsewhere."))
+        )),
+        vec!["zones[3].ttl-rules[0].name"]
+    );
+    // Signing keys inherited by zones with no use for them are ignored.
+    let config = Config::new()
+        .zone_defaults(ZoneSettings::new().signing_keys(vec!["Kexample.+013+12345".into()]))
+        .zone(ZoneConfig::secondary(
+            "example.",
+            vec!["192.0.2.1:53".parse().unwrap()],
+        ));
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn views_become_handlers_for_their_clients() {
+    let view = ViewConfig::new("internal")
+        .client("192.168.0.0/16")
+        .to_view(|_: &_| None)
+        .unwrap();
+    assert_eq!(view.name(), "internal");
+    assert!(ViewConfig::new("bad")
+        .client("nope")
+        .to_view(|_: &_| None)
+        .is_err());
+}
+
+#[cfg(feature = "toml")]
+#[test]
+fn zone_defaults_read_from_toml() {
+    let config = Config::from_toml(
+        "[[keys]]\n\
+         name = \"xfr.\"\n\
+         secret = \"c2VjcmV0\"\n\
+         [zone-defaults]\n\
+         allow-transfer = [\"10.0.0.0/8\"]\n\
+         transfer-key = \"xfr.\"\n\
+         verify-zonemd = true\n\
+         ttl-rules = [{ name = \".\", ceiling = 300 }]\n\
+         [[views]]\n\
+         name = \"internal\"\n\
+         clients = [\"192.168.0.0/16\"]\n\
+         [views.zone-defaults]\n\
+         allow-transfer = []\n\
+         report-channel = \"agent.example.\"\n\
+         [[zones]]\n\
+         name = \"example.\"\n\
+         file = \"a.zone\"\n\
+         [[zones]]\n\
+         name = \"example.\"\n\
+         view = \"internal\"\n\
+         file = \"b.zone\"\n\
+         transfer-key = \"\"\n\
+         ttl-rules = [{ name = \"www.example.\", floor = 60 }]\n\
+         [[zones]]\n\
+         name = \"other.\"\n\
+         view = \"internal\"\n\
+         file = \"c.zone\"\n",
+    )
+    .unwrap();
+    assert_eq!(config, self::config());
+    let back = Config::from_toml(&config.to_toml().unwrap()).unwrap();
+    assert_eq!(back, config);
+
+    // Problems are found in the text too.
+    let err = Config::from_toml("[[zones]]\nname = \"a.\"\nfile = \"a.zone\"\nview = \"v\"\n")
+        .unwrap_err();
+    assert!(err.to_string().contains("zones[0].view"), "{}", err);
+}
@@ -0,0 +1,187 @@
+//! Walking signed zones served by the crate: every name of an NSEC chain,
+//! an NSEC3 chain collected and cracked with a wordlist, the query limit,
+//! and zones with nothing to walk.
+
+#![cfg(feature = "dnssec")]
+
+use std::sync::Arc;
+use std::thread;
+
+use mairudns::client::Protocol;
+use mairudns::dnssec::{nsec3_hash, sign_zone, Denial, SigningKey, SigningPolicy};
+use mairudns::name::DomainName;
+use mairudns::resolver::{Resolver, ResolverConfig};
+use mairudns::rr::RecordType;
+use mairudns::server::{Authority, Server};
+use mairudns::tools::walk::{WalkError, WalkOptions};
+use mairudns::tools::walk_zone;
+use mairudns::zone::Zone;
+
+const ZONE: &str = "\
+$TTL 3600
+@ IN SOA ns1 hostmaster 1 7200 900 1209600 300
+@ IN NS ns1
+ns1 IN A 192.0.2.53
+www IN A 192.0.2.80
+www IN AAAA 2001:db8::80
+a.b.c IN TXT \"deep\"
+*.wild IN A 192.0.2.99
+sub IN NS ns.sub
+ns.sub IN A 192.0.2.54
+";
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+fn zone() -> Zone {
+    Zone::from_master(name("example."), ZONE).unwrap()
+}
+
+fn signed(denial: Denial) -> Zone {
+    let keys = [
+        SigningKey::ed25519(257, &[1; 32]).unwrap(),
+        SigningKey::ed25519(256, &[2; 32]).unwrap(),
+    ];
+    let policy = SigningPolicy {
+        denial,
+        nsec3_salt: true,
+        max_nsec3_iterations: 5,
+        ..SigningPolicy::default()
+    };
+    sign_zone(&zone(), &keys, &policy).unwrap()
+}
+
+/// Serves `zone` over UDP and TCP, returning a resolver that asks it.
+fn serve(zone: Zone) -> Resolver {
+    let authority = Arc::new(Authority::new());
+    authority.insert(zone);
+    let mut server = Server::new(authority);
+    server.listen("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = server
+        .local_addrs()
+        .into_iter()
+        .find(|&(p, _)| p == Protocol::Udp)
+        .unwrap()
+        .1;
+    thread::spawn(move || server.run());
+    Resolver::new(ResolverConfig {
+        servers: vec![addr],
+        ..ResolverConfig::default()
+    })
+}
+
+fn words(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn nsec_walks_list_every_name() {
+    let resolver = serve(signed(Denial::Nsec));
+    let walk = walk_zone(&resolver, &name("example."), &WalkOptions::default()).unwrap();
+    assert!(walk.complete);
+    assert!(walk.nsec3.is_none());
+    assert_eq!(walk.zone, name("example."));
+    // Everything with records, delegations included, but not the glue
+    // beneath them, which the chain leaves out.
+    let names: Vec<_> = walk.names.keys().map(|n| n.to_string()).collect();
+    assert_eq!(
+        names,
+        [
+            "example.",
+            "a.b.c.example.",
+            "ns1.example.",
+            "sub.example.",
+            "*.wild.example.",
+            "www.example.",
+        ]
+    );
+    assert_eq!(
+        walk.names[&name("www.example.")],
+        [
+            RecordType::A,
+            RecordType::AAAA,
+            RecordType::RRSIG,
+            RecordType::NSEC
+        ]
+    );
+    assert!(walk.names[&name("example.")].contains(&RecordType::DNSKEY));
+    // One query for each name of the chain.
+    assert_eq!(walk.queries, 6);
+}
+
+#[test]
+fn walks_stop_at_the_query_limit() {
+    let resolver = serve(signed(Denial::Nsec));
+    let options = WalkOptions {
+        max_queries: 2,
+        ..WalkOptions::default()
+    };
+    let walk = walk_zone(&resolver, &name("example."), &options).unwrap();
+    assert!(!walk.complete);
+    assert_eq!(walk.queries, 2);
+    assert_eq!(
+        walk.names.keys().cloned().collect::<Vec<_>>(),
+        [name("example."), name("a.b.c.example.")]
+    );
+}
+
+#[test]
+fn nsec3_walks_crack_what_the_wordlist_holds() {
+    let salt = vec![0xab, 0xcd];
+    let resolver = serve(signed(Denial::Nsec3 {
+        iterations: 1,
+        salt: salt.clone(),
+        opt_out: false,
+    }));
+    let options = WalkOptions {
+        wordlist: words(&["www", " ns1 ", "b.c", "nothere", "mail"]),
+        ..WalkOptions::default()
+    };
+    let walk = walk_zone(&resolver, &name("example."), &options).unwrap();
+    assert!(walk.complete);
+    assert_eq!(
+        walk.names.keys().cloned().collect::<Vec<_>>(),
+        [
+            name("example."),
+            name("b.c.example."),
+            name("ns1.example."),
+            name("www.example."),
+        ]
+    );
+    // An empty non-terminal has a hash but no types.
+    assert!(walk.names[&name("b.c.example.")].is_empty());
+    assert_eq!(
+        walk.names[&name("www.example.")],
+        [RecordType::A, RecordType::AAAA, RecordType::RRSIG]
+    );
+
+    // The rest of the chain stays as hashes: the delegation, the deep
+    // name, the wildcard and the non-terminals above them.
+    let chain = walk.nsec3.unwrap();
+    assert_eq!(chain.salt, salt);
+    assert_eq!(chain.iterations, 1);
+    assert_eq!(chain.uncracked.len(), 5);
+    for rest in ["sub", "a.b.c", "*.wild", "wild", "c"] {
+        let hash = nsec3_hash(&name(&format!("{}.example.", rest)), &salt, 1);
+        assert!(chain.uncracked.contains_key(&hash), "{}", rest);
+    }
+    assert_eq!(
+        chain.uncracked[&nsec3_hash(&name("sub.example."), &salt, 1)],
+        [RecordType::NS]
+    );
+
+    // Without a wordlist only the apex, whose name is known, is cracked.
+    let walk = walk_zone(&resolver, &name("example."), &WalkOptions::default()).unwrap();
+    assert_eq!(walk.names.len(), 1);
+    assert_eq!(walk.nsec3.unwrap().uncracked.len(), 8);
+}
+
+#[test]
+fn unsigned_zones_cannot_be_walked() {
+    let resolver = serve(zone());
+    assert!(matches!(
+        walk_zone(&resolver, &name("example."), &WalkOptions::default()),
+        Err(WalkError::Unsigned)
+    ));
+}
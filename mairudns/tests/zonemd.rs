@@ -0,0 +1,183 @@
+//! Zone message digests (RFC 8976): the digest of the specification's
+//! example zone, checking zones against their ZONEMD records, keeping
+//! them up to date, and signing them.
+
+use mairudns::encoding::hex_decode;
+use mairudns::name::DomainName;
+use mairudns::rr::{RData, Record, RecordType};
+use mairudns::zone::{Zone, Zonemd, ZonemdError};
+
+fn name(s: &str) -> DomainName {
+    s.parse().unwrap()
+}
+
+/// The simple example zone of RFC 8976 Appendix A.1, less its ZONEMD
+/// record.
+const EXAMPLE: &str = "\
+example.      86400  IN  SOA     ns1 admin 2018031900 1800 900 604800 86400
+              86400  IN  NS      ns1
+              86400  IN  NS      ns2
+ns1           3600   IN  A       203.0.113.63
+ns2           3600   IN  AAAA    2001:db8::63
+";
+
+/// Its SHA-384 digest, as the appendix publishes it.
+const EXAMPLE_DIGEST: &str = "c68090d90a7aed716bc459f9340e3d7c1370d4d24b7e2fc3\
+                              a1ddc0b9a87153b9a9713b3c9ae5cc27777f98b8e730044c";
+
+fn zonemd(serial: u32, hash_algorithm: u8, digest: Vec<u8>) -> Record {
+    let zonemd = Zonemd {
+        serial,
+        scheme: Zonemd::SIMPLE,
+        hash_algorithm,
+        digest,
+    };
+    Record::new(name("example."), 86400, zonemd.to_rdata())
+}
+
+fn example() -> Zone {
+    Zone::from_master(name("example."), EXAMPLE).unwrap()
+}
+
+fn published(zone: &Zone) -> Vec<Zonemd> {
+    zone.rrset(&name("example."), RecordType::ZONEMD)
+        .unwrap_or(&[])
+        .iter()
+        .filter_map(|rr| Zonemd::from_rdata(&rr.rdata))
+        .collect()
+}
+
+#[test]
+fn digests_the_example_of_the_specification() {
+    let expected = hex_decode(EXAMPLE_DIGEST).unwrap();
+    let mut zone = example();
+    assert_eq!(zone.digest(Zonemd::SHA384).unwrap(), expected);
+    assert_eq!(zone.digest(Zonemd::SHA512).unwrap().len(), 64);
+    assert_eq!(zone.digest(3), None);
+
+    // The published record is left out of the digest it carries.
+    zone.insert(zonemd(2018031900, Zonemd::SHA384, expected.clone()))
+        .unwrap();
+    assert_eq!(zone.digest(Zonemd::SHA384).unwrap(), expected);
+    assert_eq!(zone.verify_zonemd(), Ok(true));
+    assert_eq!(
+        published(&zone)[0].to_string(),
+        format!("2018031900 1 1 {}", EXAMPLE_DIGEST.to_ascii_uppercase())
+    );
+
+    // Names are digested in canonical form, whatever their case.
+    let shouting = Zone::from_master(name("EXAMPLE."), &EXAMPLE.replace("ns1", "NS1")).unwrap();
+    assert_eq!(shouting.digest(Zonemd::SHA384).unwrap(), expected);
+}
+
+#[test]
+fn checks_zones_against_their_zonemd_records() {
+    let digest = hex_decode(EXAMPLE_DIGEST).unwrap();
+    let check = |records: Vec<Record>| {
+        let mut zone = example();
+        for rr in records {
+            zone.insert(rr).unwrap();
+        }
+        zone.verify_zonemd()
+    };
+    // Nothing to check against, or nothing this crate can check.
+    assert_eq!(check(Vec::new()), Ok(false));
+    assert_eq!(check(vec![zonemd(2018031900, 240, vec![0; 48])]), Ok(false));
+    // One that matches is enough.
+    assert_eq!(
+        check(vec![
+            zonemd(2018031900, Zonemd::SHA384, digest.clone()),
+            zonemd(2018031900, Zonemd::SHA512, vec![0; 64]),
+        ]),
+        Ok(true)
+    );
+
+    let mut wrong = digest.clone();
+    wrong[0] ^= 1;
+    assert_eq!(
+        check(vec![zonemd(2018031900, Zonemd::SHA384, wrong)]),
+        Err(ZonemdError::Mismatch)
+    );
+    assert_eq!(
+        check(vec![zonemd(2018031899, Zonemd::SHA384, digest.clone())]),
+        Err(ZonemdError::Serial {
+            zonemd: 2018031899,
+            soa: 2018031900
+        })
+    );
+    assert_eq!(
+        check(vec![
+            zonemd(2018031900, Zonemd::SHA384, digest.clone()),
+            zonemd(2018031900, Zonemd::SHA384, vec![1; 48]),
+        ]),
+        Err(ZonemdError::Duplicate)
+    );
+    // A change to any record shows.
+    let mut zone = example();
+    zone.insert(zonemd(2018031900, Zonemd::SHA384, digest))
+        .unwrap();
+    zone.insert(Record::new(
+        name("ns1.example."),
+        3600,
+        RData::A([203, 0, 113, 64].into()),
+    ))
+    .unwrap();
+    assert_eq!(zone.verify_zonemd(), Err(ZonemdError::Mismatch));
+    assert_eq!(
+        Zone::new(name("example.")).verify_zonemd(),
+        Err(ZonemdError::NoSoa)
+    );
+}
+
+#[test]
+fn set_zonemd_replaces_the_digests() {
+    let mut zone = example();
+    zone.set_zonemd(&[Zonemd::SHA384, Zonemd::SHA512]).unwrap();
+    let zonemds = published(&zone);
+    assert_eq!(zonemds.len(), 2);
+    assert!(zonemds.iter().all(|z| z.serial == 2018031900));
+    assert_eq!(zone.verify_zonemd(), Ok(true));
+
+    zone.set_zonemd(&[Zonemd::SHA512]).unwrap();
+    assert_eq!(published(&zone).len(), 1);
+    assert_eq!(published(&zone)[0].hash_algorithm, Zonemd::SHA512);
+    assert_eq!(zone.set_zonemd(&[7]), Err(ZonemdError::Unsupported(7)));
+    assert_eq!(
+        Zone::new(name("example.")).set_zonemd(&[Zonemd::SHA384]),
+        Err(ZonemdError::NoSoa)
+    );
+}
+
+#[cfg(feature = "dnssec")]
+#[test]
+fn signed_zones_carry_a_signed_digest_of_everything() {
+    use mairudns::dnssec::{sign_zone, Nsec, Rrsig, SigningKey, SigningPolicy};
+
+    let key = SigningKey::ed25519(257, &[7; 32]).unwrap();
+    let policy = SigningPolicy {
+        zonemd: vec![Zonemd::SHA384],
+        ..SigningPolicy::default()
+    };
+    let signed = sign_zone(&example(), std::slice::from_ref(&key), &policy).unwrap();
+    assert_eq!(signed.verify_zonemd(), Ok(true));
+    let covered: Vec<RecordType> = signed
+        .rrset(&name("example."), RecordType::RRSIG)
+        .unwrap()
+        .iter()
+        .filter_map(|rr| Rrsig::from_rdata(&rr.rdata))
+        .map(|sig| sig.type_covered)
+        .collect();
+    assert!(covered.contains(&RecordType::ZONEMD));
+    let nsec = signed.rrset(&name("example."), RecordType::NSEC).unwrap();
+    assert!(Nsec::from_rdata(&nsec[0].rdata)
+        .unwrap()
+        .has(RecordType::ZONEMD));
+
+    // A zone that has ZONEMD records keeps them up to date when signed
+    // again, without being asked.
+    let mut zone = example();
+    zone.set_zonemd(&[Zonemd::SHA512]).unwrap();
+    let signed = sign_zone(&zone, &[key], &SigningPolicy::default()).unwrap();
+    assert_eq!(signed.verify_zonemd(), Ok(true));
+    assert_eq!(published(&signed)[0].hash_algorithm, Zonemd::SHA512);
+}